@@ -0,0 +1,42 @@
+//! Canonicalization of transaction graphs: which transactions and outputs are in the best chain
+
+use bdk_bench::gen::{self, GraphParams};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn canonicalize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("canonicalize");
+    group.sample_size(10);
+    for txs in [1_000, 10_000] {
+        for conflict_rate in [0.0, 0.1] {
+            let id = format!("txs={}/conflicts={}", txs, conflict_rate);
+            let params = GraphParams {
+                txs,
+                conflict_rate,
+                ..Default::default()
+            };
+            let graph = gen::graph(&params, gen::SEED);
+            let tip = graph.tip();
+            group.bench_function(&id, |b| {
+                b.iter(|| graph.graph.list_chain_txs(&graph.chain, tip).count())
+            });
+
+            let outpoints = graph
+                .graph
+                .all_txouts()
+                .map(|(outpoint, _)| ((), outpoint))
+                .collect::<Vec<_>>();
+            group.bench_function(format!("{}/unspent", id), |b| {
+                b.iter(|| {
+                    graph
+                        .graph
+                        .filter_chain_unspents(&graph.chain, tip, outpoints.iter().cloned())
+                        .count()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, canonicalize);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Aggregation of changesets, as done when loading a wallet
+
+use bdk_bench::gen::{self, BlockParams};
+use bdk_chain::Append;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+fn aggregate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("changeset");
+    for count in [100, 1_000] {
+        let params = BlockParams {
+            blocks: count,
+            ..Default::default()
+        };
+        let changesets = gen::wallet_changesets(&params, gen::SEED);
+        group.bench_function(format!("aggregate/changesets={}", count), |b| {
+            b.iter_batched(
+                || changesets.clone(),
+                |changesets| {
+                    let mut aggregate = bdk_wallet::wallet::ChangeSet::default();
+                    for changeset in changesets {
+                        aggregate.append(changeset);
+                    }
+                    aggregate
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    // each keychain of an index added by its own changeset
+    for keychains in [10, 100] {
+        let changesets = gen::keychain_index(keychains, 0)
+            .keychains()
+            .map(|(keychain, descriptor)| {
+                let mut changeset = bdk_chain::keychain::ChangeSet::default();
+                changeset
+                    .keychains_added
+                    .insert(*keychain, descriptor.clone());
+                changeset
+            })
+            .collect::<Vec<_>>();
+        group.bench_function(format!("keychains_added/keychains={}", keychains), |b| {
+            b.iter_batched(
+                || changesets.clone(),
+                |changesets| {
+                    let mut aggregate = bdk_chain::keychain::ChangeSet::default();
+                    for changeset in changesets {
+                        aggregate.append(changeset);
+                    }
+                    aggregate
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, aggregate);
+criterion_main!(benches);
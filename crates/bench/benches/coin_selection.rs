@@ -0,0 +1,54 @@
+//! Coin selection on large sets of candidates
+
+use bdk_bench::gen;
+use bdk_chain::bitcoin::hashes::Hash;
+use bdk_chain::bitcoin::{Amount, FeeRate, ScriptBuf, WPubkeyHash};
+use bdk_wallet::wallet::coin_selection::{
+    BranchAndBoundCoinSelection, CoinSelectionAlgorithm, LargestFirstCoinSelection,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+fn coin_selection(c: &mut Criterion) {
+    let drain_script = ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros());
+    let fee_rate = FeeRate::from_sat_per_vb_u32(5);
+    let mut group = c.benchmark_group("coin_selection");
+    for candidates in [100, 1_000, 5_000] {
+        let utxos = gen::weighted_utxos(candidates, gen::SEED);
+        // a target that the candidates can reach in many ways
+        let target = Amount::from_sat(
+            utxos
+                .iter()
+                .map(|utxo| utxo.utxo.txout().value.to_sat())
+                .sum::<u64>()
+                / 10,
+        );
+        group.bench_function(format!("bnb/candidates={}", candidates), |b| {
+            b.iter_batched(
+                || (utxos.clone(), StdRng::seed_from_u64(gen::SEED)),
+                |(utxos, mut rng)| {
+                    BranchAndBoundCoinSelection::default()
+                        .coin_select(vec![], utxos, fee_rate, target, &drain_script, &mut rng)
+                        .expect("enough funds")
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function(format!("largest_first/candidates={}", candidates), |b| {
+            b.iter_batched(
+                || (utxos.clone(), StdRng::seed_from_u64(gen::SEED)),
+                |(utxos, mut rng)| {
+                    LargestFirstCoinSelection
+                        .coin_select(vec![], utxos, fee_rate, target, &drain_script, &mut rng)
+                        .expect("enough funds")
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, coin_selection);
+criterion_main!(benches);
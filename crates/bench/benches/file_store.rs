@@ -0,0 +1,99 @@
+//! Writing and loading the changesets of a wallet with a file store
+
+use bdk_bench::gen::{self, BlockParams};
+use bdk_file_store::{DurabilityPolicy, Store};
+use bdk_wallet::wallet::ChangeSet;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+const MAGIC: &[u8] = b"bdk-bench";
+
+fn file_store(c: &mut Criterion) {
+    let dir = tempfile::tempdir().expect("must create a temporary directory");
+    let mut group = c.benchmark_group("file_store");
+    group.sample_size(10);
+    for count in [100, 1_000] {
+        let params = BlockParams {
+            blocks: count,
+            ..Default::default()
+        };
+        let changesets = gen::wallet_changesets(&params, gen::SEED);
+
+        let mut file = 0;
+        group.bench_function(format!("append/changesets={}", count), |b| {
+            b.iter_batched(
+                || {
+                    file += 1;
+                    dir.path().join(format!("append-{}-{}.db", count, file))
+                },
+                |path| {
+                    let mut store =
+                        Store::<ChangeSet>::create_new(MAGIC, &path).expect("must create");
+                    for changeset in &changesets {
+                        store.append_changeset(changeset).expect("must append");
+                    }
+                    drop(store);
+                    std::fs::remove_file(path).expect("must remove");
+                },
+                BatchSize::PerIteration,
+            )
+        });
+
+        let path = dir.path().join(format!("load-{}.db", count));
+        let mut store = Store::<ChangeSet>::create_new(MAGIC, &path).expect("must create");
+        for changeset in &changesets {
+            store.append_changeset(changeset).expect("must append");
+        }
+        drop(store);
+        group.bench_function(format!("load/changesets={}", count), |b| {
+            b.iter(|| {
+                Store::<ChangeSet>::open(MAGIC, &path)
+                    .expect("must open")
+                    .aggregate_changesets()
+                    .expect("must load")
+            })
+        });
+    }
+
+    // the cost of syncing every append to disk, which depends on the disk more than on the store
+    let changesets = gen::wallet_changesets(
+        &BlockParams {
+            blocks: 100,
+            ..Default::default()
+        },
+        gen::SEED,
+    );
+    for policy in [
+        DurabilityPolicy::None,
+        DurabilityPolicy::FsyncData,
+        DurabilityPolicy::FsyncAll,
+    ] {
+        let mut file = 0;
+        group.bench_function(format!("append/durability={:?}", policy), |b| {
+            b.iter_batched(
+                || {
+                    file += 1;
+                    let path = dir
+                        .path()
+                        .join(format!("durability-{:?}-{}.db", policy, file));
+                    let store = Store::<ChangeSet>::create_new(MAGIC, &path)
+                        .expect("must create")
+                        .with_durability(policy)
+                        .expect("must sync");
+                    (path, store)
+                },
+                |(path, mut store)| {
+                    for changeset in &changesets {
+                        store.append_changeset(changeset).expect("must append");
+                    }
+                    drop(store);
+                    std::fs::remove_file(path).expect("must remove");
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, file_store);
+criterion_main!(benches);
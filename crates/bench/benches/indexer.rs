@@ -0,0 +1,119 @@
+//! Derivation and indexing of script pubkeys, and the application of blocks to an indexed graph
+//! and to a wallet
+
+use bdk_bench::gen::{self, BlockParams};
+use bdk_chain::indexed_tx_graph::IndexedTxGraph;
+use bdk_chain::{BlockId, ConfirmationHeightAnchor};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+fn derive(c: &mut Criterion) {
+    let mut group = c.benchmark_group("indexer");
+    for keychains in [1, 10] {
+        group.bench_function(format!("derive/keychains={}/spks=200", keychains), |b| {
+            b.iter_batched(
+                || gen::keychain_index(keychains, 0),
+                |mut index| {
+                    for keychain in 0..keychains {
+                        let _ = index.reveal_to_target(&keychain, 199);
+                    }
+                    index
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn index_txs(c: &mut Criterion) {
+    // the owned script pubkeys are derived before, like the lookahead of a wallet
+    let index = gen::keychain_index(1, 1_000);
+    let spks = (0..1_000)
+        .map(|i| index.spk_at_index(0, i).expect("derived").to_owned())
+        .collect::<Vec<_>>();
+    let params = BlockParams {
+        blocks: 100,
+        txs_per_block: 100,
+        relevant_rate: 0.1,
+    };
+    let blocks = gen::blocks(&spks, &params, gen::SEED);
+    let mut group = c.benchmark_group("indexer");
+    group.sample_size(10);
+    group.bench_function("index_txs/txs=10000", |b| {
+        b.iter_batched(
+            || IndexedTxGraph::<ConfirmationHeightAnchor, _>::new(index.clone()),
+            |mut graph| {
+                let _ = graph.batch_insert_relevant(
+                    blocks
+                        .iter()
+                        .flat_map(|(_, block)| &block.txdata)
+                        .map(|tx| (tx, core::iter::empty())),
+                );
+                graph
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn apply_blocks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("indexer");
+    group.sample_size(10);
+    for count in [100, 1_000] {
+        let params = BlockParams {
+            blocks: count,
+            ..Default::default()
+        };
+        let wallet = gen::wallet();
+        let spks = gen::wallet_spks(&wallet, count as u32);
+        let blocks = gen::blocks(&spks, &params, gen::SEED);
+
+        group.bench_function(format!("apply_blocks/blocks={}", count), |b| {
+            b.iter_batched(
+                || IndexedTxGraph::<ConfirmationHeightAnchor, _>::new(gen::keychain_index(1, 25)),
+                |mut graph| {
+                    for (height, block) in &blocks {
+                        let _ = graph.apply_block_relevant(block, *height);
+                    }
+                    graph
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_function(format!("wallet/apply_block/blocks={}", count), |b| {
+            b.iter_batched(
+                gen::wallet,
+                |mut wallet| {
+                    for (height, block) in &blocks {
+                        wallet.apply_block(block, *height).expect("must connect");
+                    }
+                    wallet
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_function(format!("wallet/apply_blocks/blocks={}", count), |b| {
+            b.iter_batched(
+                gen::wallet,
+                |mut wallet| {
+                    let connected_to = BlockId {
+                        height: 0,
+                        hash: gen::block_hash(0),
+                    };
+                    wallet
+                        .apply_blocks(blocks.iter().map(|(h, b)| (*h, b)), connected_to)
+                        .expect("must connect");
+                    wallet
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, derive, index_txs, apply_blocks);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+//! Building transactions which pay many recipients, as payment processors do
+
+use bdk_bench::gen::{self, BlockParams};
+use bdk_chain::bitcoin::FeeRate;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+fn pay_recipients(c: &mut Criterion) {
+    let mut wallet = gen::funded_wallet(&BlockParams::default(), gen::SEED);
+    let fee_rate = FeeRate::from_sat_per_vb_u32(5);
+    let mut group = c.benchmark_group("tx_builder");
+    for outputs in [1, 50, 200] {
+        let recipients = gen::recipients(outputs, gen::SEED);
+        group.bench_with_input(
+            BenchmarkId::new("add_recipients/outputs", outputs),
+            &recipients,
+            |b, recipients| {
+                b.iter_batched(
+                    || recipients.clone(),
+                    |recipients| {
+                        let mut builder = wallet.build_tx();
+                        builder
+                            .add_recipients(recipients)
+                            .expect("recipients must be valid")
+                            .fee_rate(fee_rate);
+                        builder.finish().expect("wallet must afford the payments")
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+        // the same payments one recipient at a time, without the validation of the batch
+        group.bench_with_input(
+            BenchmarkId::new("add_recipient/outputs", outputs),
+            &recipients,
+            |b, recipients| {
+                b.iter(|| {
+                    let mut builder = wallet.build_tx();
+                    for (address, amount) in recipients {
+                        builder.add_recipient(
+                            address.clone().assume_checked().script_pubkey(),
+                            *amount,
+                        );
+                    }
+                    builder.fee_rate(fee_rate);
+                    builder.finish().expect("wallet must afford the payments")
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pay_recipients);
+criterion_main!(benches);
@@ -0,0 +1,350 @@
+//! Deterministic generators of synthetic data
+//!
+//! The generators take their parameters and a seed, the same ones always giving the same data, so
+//! that the benchmarks measure the same work from one run to the next.
+
+use bdk_chain::bitcoin::address::NetworkUnchecked;
+use bdk_chain::bitcoin::hashes::Hash;
+use bdk_chain::bitcoin::{
+    absolute, block, constants, transaction, Address, Amount, Block, BlockHash, CompactTarget,
+    Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid,
+    WPubkeyHash, Witness,
+};
+use bdk_chain::keychain::KeychainTxOutIndex;
+use bdk_chain::local_chain::LocalChain;
+use bdk_chain::miniscript::{Descriptor, DescriptorPublicKey};
+use bdk_chain::{BlockId, ConfirmationHeightAnchor, ConfirmationTime, TxGraph};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::wallet::ChangeSet;
+use bdk_wallet::{KeychainKind, LocalOutput, Utxo, Wallet, WeightedUtxo};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The seed of the benchmarks
+pub const SEED: u64 = 0x0bdc_bec4;
+
+/// The master key of the descriptors
+pub const TPRV: &str = "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS";
+
+/// The satisfaction weight of a P2WPKH output
+const P2WPKH_SATISFACTION_WEIGHT: usize = 4 + 1 + 73 + 34;
+
+/// The BIP-84 descriptor of the keychain `change` of `account`
+pub fn descriptor(account: u32, change: u32) -> String {
+    format!("wpkh({}/84'/1'/{}'/{}/*)", TPRV, account, change)
+}
+
+/// An index of `keychains` external keychains, one per account, with `lookahead`
+pub fn keychain_index(keychains: u32, lookahead: u32) -> KeychainTxOutIndex<u32> {
+    let secp = Secp256k1::new();
+    let mut index = KeychainTxOutIndex::new(lookahead);
+    for keychain in 0..keychains {
+        let (descriptor, _) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &descriptor(keychain, 0))
+                .expect("descriptor must parse");
+        let _ = index
+            .insert_descriptor(keychain, descriptor)
+            .expect("keychain must be new");
+    }
+    index
+}
+
+/// A regtest wallet of the first account
+pub fn wallet() -> Wallet {
+    Wallet::new(&descriptor(0, 0), &descriptor(0, 1), Network::Regtest)
+        .expect("descriptors must be valid")
+}
+
+/// The hash of the synthetic block at `height`, the genesis block of regtest at 0
+pub fn block_hash(height: u32) -> BlockHash {
+    match height {
+        0 => constants::genesis_block(Network::Regtest).block_hash(),
+        height => BlockHash::hash(&height.to_le_bytes()),
+    }
+}
+
+/// A chain of the synthetic blocks up to `tip_height`
+pub fn chain(tip_height: u32) -> LocalChain {
+    LocalChain::from_blocks((0..=tip_height).map(|h| (h, block_hash(h))).collect())
+        .expect("chain has the genesis block")
+}
+
+/// A script pubkey nobody benchmarked owns
+fn random_spk(rng: &mut StdRng) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(rng.gen()))
+}
+
+/// An input spending an output of a transaction which isn't generated
+fn random_input(rng: &mut StdRng) -> TxIn {
+    TxIn {
+        previous_output: OutPoint::new(Txid::from_byte_array(rng.gen()), 0),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness: Witness::new(),
+    }
+}
+
+/// The parameters of [`graph`]
+#[derive(Debug, Clone, Copy)]
+pub struct GraphParams {
+    /// The number of transactions
+    pub txs: usize,
+    /// The probability that an input of an unconfirmed transaction double-spends an output which
+    /// is already spent
+    pub conflict_rate: f64,
+    /// The share of the transactions which are confirmed, the others are unconfirmed
+    pub confirmed_rate: f64,
+    /// The number of confirmed transactions per block
+    pub txs_per_block: usize,
+}
+
+impl Default for GraphParams {
+    fn default() -> Self {
+        Self {
+            txs: 1_000,
+            conflict_rate: 0.0,
+            confirmed_rate: 0.9,
+            txs_per_block: 10,
+        }
+    }
+}
+
+/// A transaction graph and the chain its transactions are anchored in
+#[derive(Debug)]
+pub struct SyntheticGraph {
+    /// The chain
+    pub chain: LocalChain,
+    /// The transactions
+    pub graph: TxGraph<ConfirmationHeightAnchor>,
+}
+
+impl SyntheticGraph {
+    /// The tip of the chain
+    pub fn tip(&self) -> BlockId {
+        self.chain.tip().block_id()
+    }
+}
+
+/// A graph of transactions spending the outputs of the previous ones.
+///
+/// The first transactions are confirmed, in the order of the blocks. The others are unconfirmed,
+/// with random last-seen times, and some of their inputs double-spend outputs which are already
+/// spent, by a confirmed transaction or an unconfirmed one, see [`GraphParams`].
+pub fn graph(params: &GraphParams, seed: u64) -> SyntheticGraph {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let confirmed = (params.txs as f64 * params.confirmed_rate) as usize;
+    let tip_height = (confirmed / params.txs_per_block.max(1)) as u32 + 1;
+    let mut graph = TxGraph::default();
+    let mut unspent = Vec::<OutPoint>::new();
+    let mut spent = Vec::<OutPoint>::new();
+    for i in 0..params.txs {
+        let is_confirmed = i < confirmed;
+        let mut input = Vec::<TxIn>::new();
+        for _ in 0..rng.gen_range(1..=2) {
+            let previous_output =
+                if !is_confirmed && !spent.is_empty() && rng.gen_bool(params.conflict_rate) {
+                    spent[rng.gen_range(0..spent.len())]
+                } else if !unspent.is_empty() && rng.gen_bool(0.9) {
+                    let outpoint = unspent.swap_remove(rng.gen_range(0..unspent.len()));
+                    spent.push(outpoint);
+                    outpoint
+                } else {
+                    random_input(&mut rng).previous_output
+                };
+            if input
+                .iter()
+                .all(|txin| txin.previous_output != previous_output)
+            {
+                input.push(TxIn {
+                    previous_output,
+                    ..random_input(&mut rng)
+                });
+            }
+        }
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::from_consensus(i as u32),
+            input,
+            output: (0..2)
+                .map(|_| TxOut {
+                    value: Amount::from_sat(rng.gen_range(1_000..1_000_000)),
+                    script_pubkey: random_spk(&mut rng),
+                })
+                .collect(),
+        };
+        let txid = tx.compute_txid();
+        unspent.extend((0..2).map(|vout| OutPoint::new(txid, vout)));
+        let _ = graph.insert_tx(tx);
+        if is_confirmed {
+            let height = 1 + (i / params.txs_per_block.max(1)) as u32;
+            let anchor_block = BlockId {
+                height,
+                hash: block_hash(height),
+            };
+            let _ = graph.insert_anchor(
+                txid,
+                ConfirmationHeightAnchor {
+                    confirmation_height: height,
+                    anchor_block,
+                },
+            );
+        } else {
+            let _ = graph.insert_seen_at(txid, rng.gen_range(0..params.txs as u64));
+        }
+    }
+    SyntheticGraph {
+        chain: chain(tip_height),
+        graph,
+    }
+}
+
+/// The parameters of [`blocks`]
+#[derive(Debug, Clone, Copy)]
+pub struct BlockParams {
+    /// The number of blocks
+    pub blocks: usize,
+    /// The number of transactions per block
+    pub txs_per_block: usize,
+    /// The probability that a transaction pays the next of the owned script pubkeys
+    pub relevant_rate: f64,
+}
+
+impl Default for BlockParams {
+    fn default() -> Self {
+        Self {
+            blocks: 100,
+            txs_per_block: 50,
+            relevant_rate: 0.1,
+        }
+    }
+}
+
+/// Blocks built on the genesis block of regtest, whose transactions pay `spks` in order, each
+/// once, and random script pubkeys, see [`BlockParams`].
+///
+/// The blocks are given with their height, starting at 1.
+pub fn blocks(spks: &[ScriptBuf], params: &BlockParams, seed: u64) -> Vec<(u32, Block)> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut spks = spks.iter();
+    let mut prev_blockhash = block_hash(0);
+    (1..=params.blocks as u32)
+        .map(|height| {
+            let txdata = (0..params.txs_per_block)
+                .map(|_| {
+                    let mut output = vec![TxOut {
+                        value: Amount::from_sat(rng.gen_range(1_000..1_000_000)),
+                        script_pubkey: random_spk(&mut rng),
+                    }];
+                    if rng.gen_bool(params.relevant_rate) {
+                        if let Some(spk) = spks.next() {
+                            output.push(TxOut {
+                                value: Amount::from_sat(rng.gen_range(1_000..1_000_000)),
+                                script_pubkey: spk.clone(),
+                            });
+                        }
+                    }
+                    Transaction {
+                        version: transaction::Version::TWO,
+                        lock_time: absolute::LockTime::ZERO,
+                        input: vec![random_input(&mut rng)],
+                        output,
+                    }
+                })
+                .collect();
+            let block = Block {
+                header: block::Header {
+                    version: block::Version::TWO,
+                    prev_blockhash,
+                    merkle_root: TxMerkleNode::all_zeros(),
+                    time: 1_296_688_602 + height * 600,
+                    bits: CompactTarget::from_consensus(0x207fffff),
+                    nonce: height,
+                },
+                txdata,
+            };
+            prev_blockhash = block.block_hash();
+            (height, block)
+        })
+        .collect()
+}
+
+/// The first `count` external script pubkeys of `wallet`
+pub fn wallet_spks(wallet: &Wallet, count: u32) -> Vec<ScriptBuf> {
+    (0..count)
+        .map(|index| {
+            wallet
+                .peek_address(KeychainKind::External, index)
+                .script_pubkey()
+        })
+        .collect()
+}
+
+/// The changesets staged by a [`wallet`] applying [`blocks`] one at a time, one per block, after
+/// the one of its creation.
+pub fn wallet_changesets(params: &BlockParams, seed: u64) -> Vec<ChangeSet> {
+    let mut wallet = wallet();
+    let spks = wallet_spks(&wallet, params.blocks as u32);
+    let mut changesets = vec![wallet.take_staged().expect("new wallet has changes")];
+    for (height, block) in blocks(&spks, params, seed) {
+        wallet
+            .apply_block(&block, height)
+            .expect("blocks must connect");
+        changesets.extend(wallet.take_staged());
+    }
+    changesets
+}
+
+/// A [`wallet`] which applied [`blocks`], so that it owns an output of each of its relevant
+/// transactions.
+pub fn funded_wallet(params: &BlockParams, seed: u64) -> Wallet {
+    let mut wallet = wallet();
+    let spks = wallet_spks(&wallet, params.blocks as u32);
+    for (height, block) in blocks(&spks, params, seed) {
+        wallet
+            .apply_block(&block, height)
+            .expect("blocks must connect");
+    }
+    wallet
+}
+
+/// `count` regtest recipients of random P2WPKH addresses and values above the dust limit, as
+/// given to `TxBuilder::add_recipients`
+pub fn recipients(count: usize, seed: u64) -> Vec<(Address<NetworkUnchecked>, Amount)> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| {
+            let address = Address::from_script(&random_spk(&mut rng), Network::Regtest)
+                .expect("P2WPKH has an address");
+            (
+                address.into_unchecked(),
+                Amount::from_sat(rng.gen_range(1_000..10_000)),
+            )
+        })
+        .collect()
+}
+
+/// `count` confirmed P2WPKH outputs of a wallet, with random values
+pub fn weighted_utxos(count: usize, seed: u64) -> Vec<WeightedUtxo> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count)
+        .map(|i| WeightedUtxo {
+            satisfaction_weight: P2WPKH_SATISFACTION_WEIGHT,
+            utxo: Utxo::Local(LocalOutput {
+                outpoint: OutPoint::new(Txid::from_byte_array(rng.gen()), 0),
+                txout: TxOut {
+                    // spread over three orders of magnitude like real wallets
+                    value: Amount::from_sat(10f64.powf(rng.gen_range(3.0..6.0)) as u64),
+                    script_pubkey: random_spk(&mut rng),
+                },
+                keychain: KeychainKind::External,
+                is_spent: false,
+                derivation_index: i as u32,
+                confirmation_time: ConfirmationTime::Confirmed {
+                    height: 1,
+                    time: 1_296_688_602,
+                },
+                is_coinbase: false,
+            }),
+        })
+        .collect()
+}
@@ -0,0 +1,18 @@
+//! Benchmarks of the hot paths of [`bdk_chain`] and [`bdk_wallet`].
+//!
+//! The benchmarks are in the `benches` directory, one [criterion] target per area:
+//! `canonicalize`, `indexer`, `changeset`, `coin_selection`, `file_store` and `tx_builder`. Their
+//! data comes from the deterministic generators of [`gen`], so that two runs measure the same
+//! work. Run them with:
+//!
+//! ```text
+//! cargo bench -p bdk-bench                              # everything
+//! cargo bench -p bdk-bench -- canonicalize              # the benchmarks whose id matches it
+//! cargo bench -p bdk-bench -- --save-baseline before    # then `-- --baseline before`
+//! ```
+//!
+//! See the README for comparing a change to a baseline.
+//!
+//! [criterion]: https://docs.rs/criterion
+
+pub mod gen;
@@ -0,0 +1,816 @@
+//! Emit blockchain data with an async client, see [`AsyncEmitter`].
+//!
+//! The [`AsyncEmitter`] emits the same blocks and mempool changes as the [`Emitter`], the
+//! checkpoints and reorgs are handled the same way: both run the same emission logic, only the
+//! RPC calls are made differently. It is generic over an [`AsyncRpcApi`], such as the
+//! [`AsyncClient`] which calls `bitcoind` over HTTP.
+//!
+//! The futures of the emitter are cancellation safe. The state of the emitter is only modified
+//! once the replies a change depends on are received, so a future dropped while a call is in
+//! flight, for example because of a timeout, leaves the emitter as if the call failed: polling it
+//! again continues the emission without skipping or re-emitting blocks.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use bdk_bitcoind_rpc::async_emitter::{AsyncClient, AsyncEmitter};
+//! use bdk_bitcoind_rpc::bitcoincore_rpc::Auth;
+//! use bdk_chain::{bitcoin::BlockHash, local_chain::LocalChain};
+//!
+//! # let genesis_hash: BlockHash = unimplemented!();
+//! let client = AsyncClient::new(
+//!     "http://127.0.0.1:18443",
+//!     Auth::UserPass("user".into(), "pass".into()),
+//! )?;
+//! let (mut chain, _) = LocalChain::from_genesis_hash(genesis_hash);
+//! let mut emitter = AsyncEmitter::new(&client, chain.tip(), 0);
+//! while let Some(emission) = emitter.next_block().await? {
+//!     chain.apply_update(emission.checkpoint)?;
+//! }
+//! let mempool = emitter.mempool().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Emitter`]: crate::Emitter
+
+use crate::emission::{Call, EmitterCore, Poll, Reply, Step};
+use crate::{
+    pruned_block_error, BitcoindRpcErrorExt, BlockEvent, EmitterError, EmitterState, MempoolEvent,
+};
+use async_trait::async_trait;
+use bdk_chain::{local_chain::CheckPoint, BlockId};
+use bitcoin::{block::Header, consensus::encode, Block, BlockHash, Transaction, Txid};
+use bitcoincore_rpc::bitcoincore_rpc_json::{
+    GetBlockHeaderResult, GetBlockResult, GetMempoolEntryResult,
+};
+use bitcoincore_rpc::jsonrpc::{self, serde, serde_json};
+use bitcoincore_rpc::Auth;
+use core::future::Future;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// An async client of the `bitcoind` RPC interface, the async counterpart of
+/// [`bitcoincore_rpc::RpcApi`].
+///
+/// Only [`call`](Self::call) must be implemented, the other methods are the calls made by the
+/// [`AsyncEmitter`].
+#[async_trait]
+pub trait AsyncRpcApi: Sync {
+    /// Call the RPC method `cmd` with `args`
+    async fn call<T: for<'a> serde::Deserialize<'a>>(
+        &self,
+        cmd: &str,
+        args: &[Value],
+    ) -> Result<T, bitcoincore_rpc::Error>;
+
+    /// Get the hash of the block at `height` of the best chain
+    async fn get_block_hash(&self, height: u64) -> Result<BlockHash, bitcoincore_rpc::Error> {
+        self.call("getblockhash", &[height.into()]).await
+    }
+
+    /// Get the hash of the tip of the best chain
+    async fn get_best_block_hash(&self) -> Result<BlockHash, bitcoincore_rpc::Error> {
+        self.call("getbestblockhash", &[]).await
+    }
+
+    /// Get the block of `hash`
+    async fn get_block(&self, hash: &BlockHash) -> Result<Block, bitcoincore_rpc::Error> {
+        let hex: String = self
+            .call("getblock", &[serde_json::to_value(hash)?, 0.into()])
+            .await?;
+        Ok(encode::deserialize_hex(&hex)?)
+    }
+
+    /// Get the description of the block of `hash`, including its txids
+    async fn get_block_info(
+        &self,
+        hash: &BlockHash,
+    ) -> Result<GetBlockResult, bitcoincore_rpc::Error> {
+        self.call("getblock", &[serde_json::to_value(hash)?, 1.into()])
+            .await
+    }
+
+    /// Get the header of the block of `hash`
+    async fn get_block_header(&self, hash: &BlockHash) -> Result<Header, bitcoincore_rpc::Error> {
+        let hex: String = self
+            .call(
+                "getblockheader",
+                &[serde_json::to_value(hash)?, false.into()],
+            )
+            .await?;
+        Ok(encode::deserialize_hex(&hex)?)
+    }
+
+    /// Get the description of the header of the block of `hash`
+    async fn get_block_header_info(
+        &self,
+        hash: &BlockHash,
+    ) -> Result<GetBlockHeaderResult, bitcoincore_rpc::Error> {
+        self.call(
+            "getblockheader",
+            &[serde_json::to_value(hash)?, true.into()],
+        )
+        .await
+    }
+
+    /// Get the entries of the mempool
+    async fn get_raw_mempool_verbose(
+        &self,
+    ) -> Result<HashMap<Txid, GetMempoolEntryResult>, bitcoincore_rpc::Error> {
+        self.call("getrawmempool", &[true.into()]).await
+    }
+
+    /// Get the transaction of `txid`, which must be in the mempool or in the block of `block_hash`
+    /// unless the node has `-txindex=1`
+    async fn get_raw_transaction(
+        &self,
+        txid: &Txid,
+        block_hash: Option<&BlockHash>,
+    ) -> Result<Transaction, bitcoincore_rpc::Error> {
+        let mut args = vec![serde_json::to_value(txid)?, false.into()];
+        if let Some(block_hash) = block_hash {
+            args.push(serde_json::to_value(block_hash)?);
+        }
+        let hex: String = self.call("getrawtransaction", &args).await?;
+        Ok(encode::deserialize_hex(&hex)?)
+    }
+}
+
+/// An [`AsyncRpcApi`] calling `bitcoind` over HTTP, with [`reqwest`]
+#[derive(Debug)]
+pub struct AsyncClient {
+    http: reqwest::Client,
+    url: String,
+    user: Option<String>,
+    pass: Option<String>,
+    nonce: AtomicUsize,
+}
+
+impl AsyncClient {
+    /// Create a client of the node at `url`, authenticated with `auth`.
+    ///
+    /// The cookie file of an [`Auth::CookieFile`] is read once, by this method.
+    pub fn new(url: &str, auth: Auth) -> Result<Self, bitcoincore_rpc::Error> {
+        Self::from_reqwest(reqwest::Client::new(), url, auth)
+    }
+
+    /// Create a client like [`new`](Self::new) which makes the requests with `http`, for example
+    /// a client with timeouts.
+    pub fn from_reqwest(
+        http: reqwest::Client,
+        url: &str,
+        auth: Auth,
+    ) -> Result<Self, bitcoincore_rpc::Error> {
+        let (user, pass) = auth.get_user_pass()?;
+        Ok(Self {
+            http,
+            url: url.to_string(),
+            user,
+            pass,
+            nonce: AtomicUsize::new(1),
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncRpcApi for AsyncClient {
+    async fn call<T: for<'a> serde::Deserialize<'a>>(
+        &self,
+        cmd: &str,
+        args: &[Value],
+    ) -> Result<T, bitcoincore_rpc::Error> {
+        let params = jsonrpc::try_arg(args)?;
+        let id = Value::from(self.nonce.fetch_add(1, Ordering::Relaxed));
+        let request = jsonrpc::Request {
+            method: cmd,
+            params: Some(&params),
+            id: id.clone(),
+            jsonrpc: Some("2.0"),
+        };
+        let mut builder = self.http.post(&self.url).json(&request);
+        if let Some(user) = &self.user {
+            builder = builder.basic_auth(user, self.pass.as_ref());
+        }
+        let response = builder.send().await.map_err(transport_error)?;
+
+        // bitcoind replies to a failed call with an error status and the error in the body
+        let status_error = response.error_for_status_ref().err();
+        let body = response.bytes().await.map_err(transport_error)?;
+        let response: jsonrpc::Response =
+            serde_json::from_slice(&body).map_err(|err| match status_error {
+                Some(status_error) => transport_error(status_error),
+                None => jsonrpc::Error::Json(err).into(),
+            })?;
+        if response.id != id {
+            return Err(jsonrpc::Error::NonceMismatch.into());
+        }
+        Ok(response.result()?)
+    }
+}
+
+fn transport_error(err: reqwest::Error) -> bitcoincore_rpc::Error {
+    bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Transport(Box::new(err)))
+}
+
+/// The [`AsyncEmitter`] emits the data of an [`AsyncRpcApi`] client, see the
+/// [module-level documentation].
+///
+/// [module-level documentation]: self
+pub struct AsyncEmitter<'c, C> {
+    client: &'c C,
+    core: EmitterCore,
+}
+
+impl<'c, C: AsyncRpcApi> AsyncEmitter<'c, C> {
+    /// Construct a new [`AsyncEmitter`], see [`Emitter::new`].
+    ///
+    /// [`Emitter::new`]: crate::Emitter::new
+    pub fn new(client: &'c C, last_cp: CheckPoint, start_height: u32) -> Self {
+        Self {
+            client,
+            core: EmitterCore::new(last_cp, start_height),
+        }
+    }
+
+    /// Construct a new [`AsyncEmitter`] starting at the prune height of the node if `start_height`
+    /// is below it, see [`Emitter::new_clamped`].
+    ///
+    /// [`Emitter::new_clamped`]: crate::Emitter::new_clamped
+    pub async fn new_clamped(
+        client: &'c C,
+        last_cp: CheckPoint,
+        start_height: u32,
+    ) -> Result<Self, EmitterError> {
+        let mut emitter = Self::new(client, last_cp, start_height);
+        emitter.core.clamp_start_height(prune_height(client).await?);
+        Ok(emitter)
+    }
+
+    /// Restore an [`AsyncEmitter`] from the `state` of a previous emitter, see
+    /// [`Emitter::from_state`].
+    ///
+    /// The state of an [`Emitter`] can be restored into an [`AsyncEmitter`] and vice versa.
+    ///
+    /// [`Emitter`]: crate::Emitter
+    /// [`Emitter::from_state`]: crate::Emitter::from_state
+    pub fn from_state(client: &'c C, state: EmitterState) -> Option<Self> {
+        Some(Self {
+            client,
+            core: EmitterCore::from_state(state)?,
+        })
+    }
+
+    /// The state of the emitter, to restore it with [`from_state`](Self::from_state) after a
+    /// restart.
+    pub fn state(&self) -> EmitterState {
+        self.core.state()
+    }
+
+    /// The heights of the blocks which can't be scanned because [`new_clamped`](Self::new_clamped)
+    /// started the emission at the prune height of the node, if any
+    pub fn pruned_heights(&self) -> Option<Range<u32>> {
+        self.core.pruned_heights.clone()
+    }
+
+    /// Emit the changes of the mempool since the last call, see [`Emitter::mempool`].
+    ///
+    /// [`Emitter::mempool`]: crate::Emitter::mempool
+    pub async fn mempool(&mut self) -> Result<MempoolEvent, EmitterError> {
+        let client = self.client;
+        let mempool = client.get_raw_mempool_verbose().await?;
+        let at_tip = client.get_best_block_hash().await? == self.core.last_cp.hash();
+        let mut txs = HashMap::new();
+        for txid in self.core.mempool_txs_to_fetch(&mempool) {
+            match client.get_raw_transaction(&txid, None).await {
+                Ok(tx) => {
+                    txs.insert(txid, tx);
+                }
+                // the tx is confirmed or evicted since `get_raw_mempool_verbose`
+                Err(err) if err.is_not_found_error() => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(self.core.mempool(mempool, at_tip, txs))
+    }
+
+    /// Emit the next block height and header (if any), see [`Emitter::next_header`].
+    ///
+    /// [`Emitter::next_header`]: crate::Emitter::next_header
+    pub async fn next_header(&mut self) -> Result<Option<BlockEvent<Header>>, EmitterError> {
+        let client = self.client;
+        let is_tracking_mempool = self.core.is_tracking_mempool();
+        self.poll(|hash| async move {
+            let header = client.get_block_header(&hash).await?;
+            let txids = if is_tracking_mempool {
+                Some(client.get_block_info(&hash).await?.tx)
+            } else {
+                None
+            };
+            Ok((header, txids))
+        })
+        .await
+    }
+
+    /// Emit the next block height and block (if any), see [`Emitter::next_block`].
+    ///
+    /// [`Emitter::next_block`]: crate::Emitter::next_block
+    pub async fn next_block(&mut self) -> Result<Option<BlockEvent<Block>>, EmitterError> {
+        let client = self.client;
+        let is_tracking_mempool = self.core.is_tracking_mempool();
+        self.poll(|hash| async move {
+            let block = client.get_block(&hash).await?;
+            let txids = if is_tracking_mempool {
+                Some(block.txdata.iter().map(|tx| tx.compute_txid()).collect())
+            } else {
+                None
+            };
+            Ok((block, txids))
+        })
+        .await
+    }
+
+    /// Fetch the block of `block_id`, such as the block of a header emitted by
+    /// [`next_header`](Self::next_header).
+    ///
+    /// Returns [`EmitterError::BlockPruned`] if the block is pruned by the node.
+    pub async fn fetch_block(&self, block_id: BlockId) -> Result<Block, EmitterError> {
+        match self.client.get_block(&block_id.hash).await {
+            Ok(block) => Ok(block),
+            Err(err) => Err(block_error(self.client, block_id.height, err).await),
+        }
+    }
+
+    /// Emit the next block with the item fetched by `get_item`, alongside the txids of the block
+    /// if they are needed to update the mempool snapshot.
+    async fn poll<V, F, Fut>(&mut self, get_item: F) -> Result<Option<BlockEvent<V>>, EmitterError>
+    where
+        F: FnOnce(BlockHash) -> Fut,
+        Fut: Future<Output = Result<(V, Option<Vec<Txid>>), bitcoincore_rpc::Error>>,
+    {
+        let mut poll = Poll::default();
+        let mut reply = None;
+        loop {
+            match self.core.poll(&mut poll, reply.take()) {
+                Step::Call(call) => reply = Some(call_async(self.client, call).await?),
+                Step::Fetch(block_id) => {
+                    let (block, txids) = match get_item(block_id.hash).await {
+                        Ok(item) => item,
+                        Err(err) => {
+                            return Err(block_error(self.client, block_id.height, err).await)
+                        }
+                    };
+                    let checkpoint = self.core.emit(poll, txids);
+                    return Ok(Some(BlockEvent { block, checkpoint }));
+                }
+                Step::Tip => return Ok(None),
+                Step::GenesisMismatch { expected, got } => {
+                    return Err(EmitterError::GenesisMismatch { expected, got })
+                }
+            }
+        }
+    }
+}
+
+/// Make the `call` of the emission logic with an async client
+async fn call_async<C: AsyncRpcApi>(
+    client: &C,
+    call: Call,
+) -> Result<Reply, bitcoincore_rpc::Error> {
+    match call {
+        Call::BlockHash(height) => Ok(Reply::BlockHash(client.get_block_hash(height as _).await?)),
+        Call::HeaderInfo { hash, not_found_ok } => {
+            match client.get_block_header_info(&hash).await {
+                Ok(res) => Ok(Reply::HeaderInfo(Some(Box::new(res)))),
+                Err(err) if not_found_ok && err.is_not_found_error() => Ok(Reply::HeaderInfo(None)),
+                Err(err) => Err(err),
+            }
+        }
+    }
+}
+
+/// The prune height of the node, the height of the earliest block it stores, if it is pruned.
+async fn prune_height<C: AsyncRpcApi>(client: &C) -> Result<Option<u32>, bitcoincore_rpc::Error> {
+    let info: Value = client.call("getblockchaininfo", &[]).await?;
+    Ok(info["pruneheight"]
+        .as_u64()
+        .filter(|_| info["pruned"].as_bool() == Some(true))
+        .map(|prune_height| prune_height as u32))
+}
+
+/// Turn the error `err` of fetching the block at `height` into [`EmitterError::BlockPruned`] if
+/// the block is pruned by the node.
+async fn block_error<C: AsyncRpcApi>(
+    client: &C,
+    height: u32,
+    err: bitcoincore_rpc::Error,
+) -> EmitterError {
+    pruned_block_error(height, prune_height(client).await, err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk_chain::local_chain::LocalChain;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{
+        absolute, block, transaction, Amount, CompactTarget, OutPoint, ScriptBuf, TxIn,
+        TxMerkleNode, TxOut,
+    };
+    use bitcoincore_rpc::bitcoincore_rpc_json::GetMempoolEntryResultFees;
+    use futures::FutureExt;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    /// A node whose calls can be made to hang, so that the futures of the emitter can be dropped
+    /// mid-call
+    #[derive(Default)]
+    struct MockNode(Mutex<NodeState>);
+
+    #[derive(Default)]
+    struct NodeState {
+        /// The blocks by hash, alongside their heights
+        blocks: HashMap<BlockHash, (usize, Block)>,
+        /// The best chain
+        chain: Vec<BlockHash>,
+        /// The mempool transactions, alongside their first-seen timestamps
+        mempool: Vec<(Transaction, u64)>,
+        /// The number of calls before the one which hangs
+        calls_before_hang: Option<usize>,
+    }
+
+    fn rpc_error(code: i32, message: &str) -> bitcoincore_rpc::Error {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }))
+    }
+
+    impl MockNode {
+        /// A node with the genesis block and `n` blocks above it
+        fn new(n: usize) -> Self {
+            let node = Self::default();
+            node.mine(n + 1, 0);
+            node
+        }
+
+        fn mine(&self, n: usize, tag: u32) {
+            let mut state = self.0.lock().unwrap();
+            for _ in 0..n {
+                let height = state.chain.len();
+                let block = Block {
+                    header: block::Header {
+                        version: block::Version::ONE,
+                        prev_blockhash: state
+                            .chain
+                            .last()
+                            .copied()
+                            .unwrap_or_else(BlockHash::all_zeros),
+                        merkle_root: TxMerkleNode::all_zeros(),
+                        time: height as u32,
+                        bits: CompactTarget::from_consensus(0x207fffff),
+                        nonce: tag,
+                    },
+                    txdata: core::mem::take(&mut state.mempool)
+                        .into_iter()
+                        .map(|(tx, _)| tx)
+                        .collect(),
+                };
+                let hash = block.block_hash();
+                state.blocks.insert(hash, (height, block));
+                state.chain.push(hash);
+            }
+        }
+
+        /// Replace the `depth` blocks at the tip with `depth + 1` other blocks
+        fn reorg(&self, depth: usize, tag: u32) {
+            {
+                let mut state = self.0.lock().unwrap();
+                let height = state.chain.len() - depth;
+                state.chain.truncate(height);
+            }
+            self.mine(depth + 1, tag);
+        }
+
+        fn add_to_mempool(&self, vout: u32, first_seen: u64) -> Transaction {
+            let tx = Transaction {
+                version: transaction::Version::TWO,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(Txid::all_zeros(), vout),
+                    ..Default::default()
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(1_000),
+                    script_pubkey: ScriptBuf::new(),
+                }],
+            };
+            let mut state = self.0.lock().unwrap();
+            state.mempool.push((tx.clone(), first_seen));
+            tx
+        }
+
+        fn evict(&self, txid: Txid) {
+            let mut state = self.0.lock().unwrap();
+            state.mempool.retain(|(tx, _)| tx.compute_txid() != txid);
+        }
+
+        /// Make the call after the next `calls` ones hang, or none if `None`
+        fn hang_after(&self, calls: Option<usize>) {
+            self.0.lock().unwrap().calls_before_hang = calls;
+        }
+    }
+
+    impl NodeState {
+        fn reply(&self, cmd: &str, args: &[Value]) -> Result<Value, bitcoincore_rpc::Error> {
+            let block = |arg: &Value| {
+                let hash: BlockHash = serde_json::from_value(arg.clone()).unwrap();
+                self.blocks
+                    .get(&hash)
+                    .ok_or_else(|| rpc_error(-5, "Block not found"))
+            };
+            let value = match cmd {
+                "getblockhash" => {
+                    let height = args[0].as_u64().unwrap() as usize;
+                    let hash = self
+                        .chain
+                        .get(height)
+                        .ok_or_else(|| rpc_error(-8, "Block height out of range"))?;
+                    serde_json::to_value(hash)?
+                }
+                "getbestblockhash" => serde_json::to_value(self.chain.last())?,
+                "getblock" => {
+                    assert_eq!(args[1], 0, "only raw blocks are mocked");
+                    let (_, block) = block(&args[0])?;
+                    encode::serialize_hex(block).into()
+                }
+                "getblockheader" if args[1] == false => {
+                    let (_, block) = block(&args[0])?;
+                    encode::serialize_hex(&block.header).into()
+                }
+                "getblockheader" => {
+                    let (height, block) = block(&args[0])?;
+                    let hash = block.block_hash();
+                    let in_chain = self.chain.get(*height) == Some(&hash);
+                    serde_json::to_value(GetBlockHeaderResult {
+                        hash,
+                        confirmations: if in_chain {
+                            (self.chain.len() - height) as i32
+                        } else {
+                            -1
+                        },
+                        height: *height,
+                        version: block.header.version,
+                        version_hex: Some(
+                            block.header.version.to_consensus().to_be_bytes().to_vec(),
+                        ),
+                        merkle_root: block.header.merkle_root,
+                        time: block.header.time as usize,
+                        median_time: None,
+                        nonce: block.header.nonce,
+                        bits: String::new(),
+                        difficulty: 0.0,
+                        chainwork: Vec::new(),
+                        n_tx: block.txdata.len(),
+                        previous_block_hash: Some(block.header.prev_blockhash)
+                            .filter(|_| *height > 0),
+                        next_block_hash: self.chain.get(height + 1).copied().filter(|_| in_chain),
+                    })?
+                }
+                "getrawmempool" => {
+                    let entries = self
+                        .mempool
+                        .iter()
+                        .map(|(tx, first_seen)| {
+                            let txid = tx.compute_txid();
+                            let entry = GetMempoolEntryResult {
+                                vsize: 100,
+                                weight: None,
+                                time: *first_seen,
+                                height: self.chain.len() as u64 - 1,
+                                descendant_count: 1,
+                                descendant_size: 100,
+                                ancestor_count: 1,
+                                ancestor_size: 100,
+                                wtxid: txid,
+                                fees: GetMempoolEntryResultFees {
+                                    base: Amount::ZERO,
+                                    modified: Amount::ZERO,
+                                    ancestor: Amount::ZERO,
+                                    descendant: Amount::ZERO,
+                                },
+                                depends: Vec::new(),
+                                spent_by: Vec::new(),
+                                bip125_replaceable: false,
+                                unbroadcast: None,
+                            };
+                            (txid, entry)
+                        })
+                        .collect::<HashMap<_, _>>();
+                    serde_json::to_value(entries)?
+                }
+                "getrawtransaction" => {
+                    let txid: Txid = serde_json::from_value(args[0].clone()).unwrap();
+                    let (tx, _) = self
+                        .mempool
+                        .iter()
+                        .find(|(tx, _)| tx.compute_txid() == txid)
+                        .ok_or_else(|| rpc_error(-5, "No such mempool transaction"))?;
+                    encode::serialize_hex(tx).into()
+                }
+                "getblockchaininfo" => serde_json::json!({ "pruned": false }),
+                _ => unimplemented!("{} is not mocked", cmd),
+            };
+            Ok(value)
+        }
+    }
+
+    #[async_trait]
+    impl AsyncRpcApi for MockNode {
+        async fn call<T: for<'a> serde::Deserialize<'a>>(
+            &self,
+            cmd: &str,
+            args: &[Value],
+        ) -> Result<T, bitcoincore_rpc::Error> {
+            let reply = {
+                let mut state = self.0.lock().unwrap();
+                match state.calls_before_hang {
+                    Some(0) => None,
+                    calls => {
+                        state.calls_before_hang = calls.map(|calls| calls - 1);
+                        Some(state.reply(cmd, args))
+                    }
+                }
+            };
+            match reply {
+                Some(value) => Ok(serde_json::from_value(value?)?),
+                None => {
+                    futures::future::pending::<()>().await;
+                    unreachable!()
+                }
+            }
+        }
+    }
+
+    /// Run the future of `$emit` to completion, dropping it at its first call, then at its second
+    /// one, and so on until it completes without hanging. Returns the outcome, and the number of
+    /// times the future was dropped.
+    macro_rules! complete_after_drops {
+        ($node:expr, $emit:expr) => {{
+            let mut drops = 0;
+            loop {
+                $node.hang_after(Some(drops));
+                if let Some(res) = $emit.now_or_never() {
+                    $node.hang_after(None);
+                    break (res.expect("must emit"), drops);
+                }
+                drops += 1;
+            }
+        }};
+    }
+
+    fn next_block_ids<'n>(
+        node: &'n MockNode,
+        emitter: &mut AsyncEmitter<'n, MockNode>,
+        drop_mid_call: bool,
+    ) -> Vec<BlockId> {
+        let mut block_ids = Vec::new();
+        loop {
+            let emission = if drop_mid_call {
+                complete_after_drops!(node, emitter.next_block()).0
+            } else {
+                emitter.next_block().now_or_never().unwrap().unwrap()
+            };
+            match emission {
+                Some(emission) => block_ids.push(emission.checkpoint.block_id()),
+                None => return block_ids,
+            }
+        }
+    }
+
+    #[test]
+    fn next_block_resumes_after_drop() {
+        let node = MockNode::new(5);
+        let genesis_hash = node.0.lock().unwrap().chain[0];
+        let (mut chain, _) = LocalChain::from_genesis_hash(genesis_hash);
+        let mut emitter = AsyncEmitter::new(&node, chain.tip(), 0);
+        let mut expected = AsyncEmitter::new(&node, chain.tip(), 0);
+
+        let block_ids = next_block_ids(&node, &mut emitter, true);
+        assert_eq!(block_ids, next_block_ids(&node, &mut expected, false));
+        assert_eq!(block_ids.len(), 5);
+
+        // the blocks emitted after a reorg connect to the emitted ones
+        node.reorg(2, 1);
+        let block_ids = next_block_ids(&node, &mut emitter, true);
+        assert_eq!(block_ids, next_block_ids(&node, &mut expected, false));
+        assert_eq!(
+            block_ids.iter().map(|b| b.height).collect::<Vec<_>>(),
+            [4, 5, 6]
+        );
+        assert_eq!(emitter.state(), expected.state());
+
+        // a restored emitter whose blocks are all replaced finds the point of agreement
+        let mut restored = AsyncEmitter::from_state(&node, emitter.state()).unwrap();
+        node.reorg(4, 2);
+        let block_ids = next_block_ids(&node, &mut restored, true);
+        assert_eq!(block_ids, next_block_ids(&node, &mut expected, false));
+        assert_eq!(block_ids.first().map(|b| b.height), Some(3));
+        for block_id in &block_ids {
+            let cp = restored.state().blocks;
+            assert_eq!(cp.get(&block_id.height), Some(&block_id.hash));
+        }
+
+        // the emitted checkpoints apply to a local chain
+        let mut emitter = AsyncEmitter::new(&node, chain.tip(), 0);
+        while let (Some(emission), _) = complete_after_drops!(node, emitter.next_block()) {
+            chain.apply_update(emission.checkpoint).unwrap();
+        }
+        assert_eq!(chain.tip().height(), 7);
+    }
+
+    #[test]
+    fn mempool_resumes_after_drop() {
+        let node = MockNode::new(3);
+        let genesis_hash = node.0.lock().unwrap().chain[0];
+        let mut emitter = AsyncEmitter::new(
+            &node,
+            CheckPoint::new(BlockId {
+                height: 0,
+                hash: genesis_hash,
+            }),
+            0,
+        );
+        next_block_ids(&node, &mut emitter, false);
+
+        let txids = (0..3)
+            .map(|vout| node.add_to_mempool(vout, 100 + vout as u64).compute_txid())
+            .collect::<Vec<_>>();
+        let (event, drops) = complete_after_drops!(node, emitter.mempool());
+        // dropped while fetching the mempool, the tip and each of the transactions
+        assert_eq!(drops, 2 + txids.len());
+        let new_txids = event
+            .new_txs
+            .iter()
+            .map(|(tx, _)| tx.compute_txid())
+            .collect::<Vec<_>>();
+        assert_eq!(new_txids, txids);
+        assert_eq!(event.latest_update_time, 102);
+
+        // the eviction isn't lost when the emission is dropped after the mempool is fetched
+        node.evict(txids[0]);
+        let new_txid = node.add_to_mempool(3, 103).compute_txid();
+        let (event, _) = complete_after_drops!(node, emitter.mempool());
+        assert_eq!(event.evicted_txids, HashSet::from([txids[0]]));
+        assert_eq!(
+            event
+                .new_txs
+                .iter()
+                .map(|(tx, _)| tx.compute_txid())
+                .collect::<Vec<_>>(),
+            [new_txid]
+        );
+
+        // the confirmed transactions are not evicted
+        node.mine(1, 0);
+        assert_eq!(next_block_ids(&node, &mut emitter, true).len(), 1);
+        let (event, _) = complete_after_drops!(node, emitter.mempool());
+        assert!(event.new_txs.is_empty());
+        assert!(event.evicted_txids.is_empty());
+        assert!(emitter.state().mempool_txs.is_empty());
+    }
+
+    #[test]
+    fn genesis_mismatch_is_an_error() {
+        let node = MockNode::new(3);
+        let genesis_hash = node.0.lock().unwrap().chain[0];
+        let other_genesis = BlockHash::from_byte_array([1; 32]);
+        let (chain, _) = LocalChain::from_genesis_hash(other_genesis);
+        let mut emitter = AsyncEmitter::new(&node, chain.tip(), 0);
+        match emitter.next_block().now_or_never().unwrap() {
+            Err(EmitterError::GenesisMismatch { expected, got }) => {
+                assert_eq!(expected, other_genesis);
+                assert_eq!(got, genesis_hash);
+            }
+            _ => panic!("the emission must fail"),
+        }
+    }
+
+    #[test]
+    fn futures_are_send() {
+        fn assert_send<T: Send>(_: T) {}
+        let node = MockNode::new(0);
+        let genesis_hash = node.0.lock().unwrap().chain[0];
+        let cp = CheckPoint::new(BlockId {
+            height: 0,
+            hash: genesis_hash,
+        });
+        let mut emitter = AsyncEmitter::new(&node, cp.clone(), 0);
+        assert_send(emitter.next_block());
+        assert_send(emitter.next_header());
+        assert_send(emitter.mempool());
+        assert_send(AsyncEmitter::new_clamped(&node, cp, 0));
+    }
+}
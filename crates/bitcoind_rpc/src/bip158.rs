@@ -0,0 +1,222 @@
+//! Scan the chain with the compact block filters of `bitcoind`, see [`FilterIter`].
+//!
+//! [BIP157] and [BIP158] define the compact block filters, which the node builds with the
+//! `-blockfilterindex=1` option.
+//!
+//! [BIP157]: https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki
+//! [BIP158]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+
+use crate::{block_error, BitcoindRpcErrorExt, BlockEvent, EmitterError};
+use bdk_chain::{local_chain::CheckPoint, BlockId};
+use bitcoin::{bip158::BlockFilter, Block, ScriptBuf};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Emits the blocks which match the compact block filters of the node against a set of script
+/// pubkeys.
+///
+/// Unlike the [`Emitter`], which fetches every block, [`FilterIter`] fetches the filter of every
+/// block and only fetches the blocks whose filter matches one of the script pubkeys. A filter may
+/// match a block which doesn't contain a script pubkey (a false positive), the block is emitted
+/// all the same and nothing relevant is found in it.
+///
+/// The checkpoint of each [`BlockEvent`] connects to the checkpoints of the previous emissions
+/// and of the chain we started with, skipping the blocks which don't match. Once the emission is
+/// done, [`checkpoint`](Self::checkpoint) provides the tip of the scanned chain.
+///
+/// The script pubkeys to match should cover the revealed script pubkeys and the lookahead of the
+/// wallet, such as `KeychainTxOutIndex::inner().all_spks()`. More can be added between the
+/// emissions with [`add_spks`](Self::add_spks), for example once a match reveals more of them.
+///
+/// [`Emitter`]: crate::Emitter
+pub struct FilterIter<'c, C> {
+    client: &'c C,
+    start_height: u32,
+    spks: BTreeSet<ScriptBuf>,
+
+    /// The checkpoint of the last-emitted block that is in the best chain.
+    last_cp: CheckPoint,
+
+    /// The last block of the best chain whose filter is checked, the emission continues from its
+    /// child. This is `None` until the point of agreement with the node is found, or if it is
+    /// later found that the block is no longer in the best chain.
+    last_scanned: Option<BlockId>,
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> FilterIter<'c, C> {
+    /// Construct a new [`FilterIter`], see [`Emitter::new`] for `last_cp` and `start_height`.
+    ///
+    /// The filters of the blocks below `start_height`, such as the [`birthday_height`] of the
+    /// wallet, are not checked.
+    ///
+    /// [`Emitter::new`]: crate::Emitter::new
+    /// [`birthday_height`]: crate::birthday_height
+    pub fn new(client: &'c C, last_cp: CheckPoint, start_height: u32) -> Self {
+        Self {
+            client,
+            start_height,
+            spks: BTreeSet::new(),
+            last_cp,
+            last_scanned: None,
+        }
+    }
+
+    /// Add a script pubkey to match the filters against.
+    pub fn add_spk(&mut self, spk: ScriptBuf) {
+        self.spks.insert(spk);
+    }
+
+    /// Add script pubkeys to match the filters against.
+    pub fn add_spks(&mut self, spks: impl IntoIterator<Item = ScriptBuf>) {
+        self.spks.extend(spks);
+    }
+
+    /// The checkpoint of the last-emitted block, extended with the last block whose filter is
+    /// checked.
+    ///
+    /// Apply it once the emission is done so that the local chain reaches the scanned tip, even if
+    /// the blocks above the last-emitted block are not relevant.
+    pub fn checkpoint(&self) -> CheckPoint {
+        match self.last_scanned {
+            Some(block_id) if block_id.height > self.last_cp.height() => self
+                .last_cp
+                .clone()
+                .push(block_id)
+                .expect("must push a higher block"),
+            _ => self.last_cp.clone(),
+        }
+    }
+
+    /// Emit the next block whose filter matches (if any).
+    pub fn next_block(&mut self) -> Result<Option<BlockEvent<Block>>, Error> {
+        let client = self.client;
+        loop {
+            let last = match self.last_scanned {
+                Some(last) => last,
+                None => {
+                    self.find_agreement()?;
+                    continue;
+                }
+            };
+
+            let header = client.get_block_header_info(&last.hash)?;
+            if header.confirmations < 0 {
+                // the last scanned block is reorged out
+                self.last_scanned = None;
+                continue;
+            }
+            let hash = match header.next_block_hash {
+                Some(hash) => hash,
+                None => return Ok(None),
+            };
+            let height = last.height + 1;
+
+            let filter = BlockFilter::new(&client.get_block_filter(&hash)?.filter);
+            let is_match = filter.match_any(&hash, self.spks.iter().map(|spk| spk.as_bytes()))?;
+            self.last_scanned = Some(BlockId { height, hash });
+            if !is_match {
+                continue;
+            }
+
+            let block = client
+                .get_block(&hash)
+                .map_err(|err| block_error(client, height, err))?;
+            let checkpoint = self
+                .last_cp
+                .clone()
+                .push(BlockId { height, hash })
+                .expect("must push");
+            self.last_cp = checkpoint.clone();
+            return Ok(Some(BlockEvent { block, checkpoint }));
+        }
+    }
+
+    /// Find the last checkpoint which is in the best chain, and continue the scan from its child
+    /// or from the start height.
+    fn find_agreement(&mut self) -> Result<(), Error> {
+        let client = self.client;
+        let mut agreement = None;
+        for cp in self.last_cp.iter() {
+            match client.get_block_header_info(&cp.hash()) {
+                // block not in best chain
+                Ok(res) if res.confirmations < 0 => continue,
+                Ok(_) => {
+                    agreement = Some(cp);
+                    break;
+                }
+                Err(e) if e.is_not_found_error() && cp.height() > 0 => continue,
+                // if we can't find genesis block, we can't create an update that connects
+                Err(e) if e.is_not_found_error() => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let agreement = match agreement {
+            Some(cp) => cp,
+            None => {
+                let genesis_hash = client.get_block_hash(0)?;
+                if let Some(genesis) = self.last_cp.get(0) {
+                    if genesis.hash() != genesis_hash {
+                        return Err(Error::Emitter(EmitterError::GenesisMismatch {
+                            expected: genesis.hash(),
+                            got: genesis_hash,
+                        }));
+                    }
+                }
+                CheckPoint::new(BlockId {
+                    height: 0,
+                    hash: genesis_hash,
+                })
+            }
+        };
+
+        let mut last_scanned = agreement.block_id();
+        if last_scanned.height + 1 < self.start_height {
+            let height = self.start_height - 1;
+            last_scanned = BlockId {
+                height,
+                hash: client.get_block_hash(height as _)?,
+            };
+        }
+        self.last_cp = agreement;
+        self.last_scanned = Some(last_scanned);
+        Ok(())
+    }
+}
+
+/// An error of a [`FilterIter`]
+#[derive(Debug)]
+pub enum Error {
+    /// The emission of a block failed
+    Emitter(EmitterError),
+    /// A block filter can't be decoded
+    Filter(bitcoin::bip158::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Emitter(err) => write!(f, "emitter error: {}", err),
+            Self::Filter(err) => write!(f, "block filter error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<EmitterError> for Error {
+    fn from(err: EmitterError) -> Self {
+        Self::Emitter(err)
+    }
+}
+
+impl From<bitcoincore_rpc::Error> for Error {
+    fn from(err: bitcoincore_rpc::Error) -> Self {
+        Self::Emitter(EmitterError::Rpc(err))
+    }
+}
+
+impl From<bitcoin::bip158::Error> for Error {
+    fn from(err: bitcoin::bip158::Error) -> Self {
+        Self::Filter(err)
+    }
+}
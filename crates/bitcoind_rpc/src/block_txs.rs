@@ -0,0 +1,47 @@
+//! Fetch the blocks of `bitcoind` for a [`TieredFetcher`], as a [`BlockTxSource`].
+//!
+//! [`TieredFetcher`]: bdk_chain::tiered_fetch::TieredFetcher
+
+use bdk_chain::collections::BTreeSet;
+use bdk_chain::spk_client::BackendError;
+use bdk_chain::tiered_fetch::{BlockTxSource, BlockTxs};
+use bdk_chain::BlockId;
+use bitcoin::ScriptBuf;
+
+/// A [`BlockTxSource`] fetching the blocks with `getblock`
+///
+/// All the transactions of the block are returned. A pruned node can't serve the blocks below its
+/// prune height, so a [`TieredFetcher`] falls back to its next source for them.
+///
+/// [`TieredFetcher`]: bdk_chain::tiered_fetch::TieredFetcher
+#[derive(Debug)]
+pub struct RpcBlockTxs<'c, C> {
+    client: &'c C,
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> RpcBlockTxs<'c, C> {
+    /// Fetch the blocks with `client`
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> BlockTxSource for RpcBlockTxs<'c, C> {
+    fn name(&self) -> &str {
+        "bitcoind"
+    }
+
+    fn block_txs(
+        &self,
+        height: u32,
+        _spks: &BTreeSet<ScriptBuf>,
+    ) -> Result<BlockTxs, BackendError> {
+        let hash = self.client.get_block_hash(height as _)?;
+        let block = self.client.get_block(&hash)?;
+        Ok(BlockTxs {
+            block: BlockId { height, hash },
+            time: block.header.time as u64,
+            txs: block.txdata,
+        })
+    }
+}
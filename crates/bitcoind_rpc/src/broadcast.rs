@@ -0,0 +1,231 @@
+//! Broadcast transactions after checking them with `testmempoolaccept`, see
+//! [`BitcoindRpcBroadcastExt`].
+
+#[cfg(feature = "std")]
+use crate::BitcoindRpcErrorExt;
+use bdk_chain::spk_client::BroadcastError;
+#[cfg(feature = "std")]
+use bdk_chain::spk_client::{
+    broadcast_with_retry, BackendError, BroadcastBackend, BroadcastRetryError, BroadcastRetryPolicy,
+};
+use bitcoin::{consensus::encode::serialize_hex, FeeRate, Transaction, Txid};
+use bitcoincore_rpc::jsonrpc::serde_json::{self, Value};
+
+/// The max number of transactions of a package accepted by `testmempoolaccept`.
+pub const MAX_PACKAGE_LEN: usize = 25;
+
+/// Broadcasts transactions with typed rejection reasons.
+///
+/// `sendrawtransaction` fails with an opaque error. These methods first check the transactions
+/// with `testmempoolaccept`, as a package of up to [`MAX_PACKAGE_LEN`] transactions ordered parents
+/// before children, and map the reject reasons to [`BroadcastError`]s. A transaction already in
+/// the mempool is reported as broadcast.
+///
+/// `max_fee_rate` is the max fee rate of the transactions, `None` uses the default of the node
+/// (0.10 BTC/kvB), which rejects high-fee transactions such as recovery transactions.
+/// [`FeeRate::ZERO`] accepts any fee rate.
+pub trait BitcoindRpcBroadcastExt: bitcoincore_rpc::RpcApi {
+    /// Check whether `txs` would be accepted into the mempool of the node, without broadcasting
+    /// them.
+    ///
+    /// Returns a result per transaction, in the order of `txs`. The transactions which are not
+    /// checked because another transaction of the package is rejected are checked again without
+    /// the rejected transactions, a child of a rejected transaction is then reported with
+    /// [`BroadcastError::MissingInputs`]. Returns an error if a request fails, such as when there
+    /// are more than [`MAX_PACKAGE_LEN`] transactions.
+    fn test_broadcast(
+        &self,
+        txs: &[Transaction],
+        max_fee_rate: Option<FeeRate>,
+    ) -> Result<Vec<Result<Txid, BroadcastError<bitcoincore_rpc::Error>>>, bitcoincore_rpc::Error>
+    {
+        let mut results = txs.iter().map(|_| None).collect::<Vec<_>>();
+        // the txs which are not validated because another tx of the package is rejected are
+        // checked again without the rejected txs
+        loop {
+            let pending = (0..txs.len())
+                .filter(|&i| results[i].is_none())
+                .collect::<Vec<_>>();
+            if pending.is_empty() {
+                break;
+            }
+            let hexes = pending
+                .iter()
+                .map(|&i| serialize_hex(&txs[i]))
+                .collect::<Vec<_>>();
+            let mut args = vec![serde_json::to_value(hexes)?];
+            if let Some(max_fee_rate) = max_fee_rate {
+                args.push(btc_per_kvb(max_fee_rate));
+            }
+            let accept_results = self.call::<Vec<Value>>("testmempoolaccept", &args)?;
+
+            let mut unvalidated = Vec::new();
+            for &i in &pending {
+                let txid = txs[i].compute_txid();
+                let result = accept_results
+                    .iter()
+                    .find(|result| result["txid"].as_str() == Some(&txid.to_string()));
+                match result {
+                    Some(result) if result["allowed"].as_bool() == Some(true) => {
+                        results[i] = Some(Ok(txid));
+                    }
+                    Some(result) => match result["reject-reason"].as_str() {
+                        Some(reason) => results[i] = Some(rejection(txid, reason)),
+                        None => unvalidated.push((
+                            i,
+                            result["package-error"].as_str().unwrap_or("not validated"),
+                        )),
+                    },
+                    None => unvalidated.push((i, "missing result")),
+                }
+            }
+            if unvalidated.len() == pending.len() {
+                // nothing is validated, the package itself is rejected
+                for (i, reason) in unvalidated {
+                    results[i] = Some(rejection(txs[i].compute_txid(), reason));
+                }
+            }
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| result.expect("must have a result"))
+            .collect())
+    }
+
+    /// Broadcast the transactions of `txs` which [`test_broadcast`](Self::test_broadcast)
+    /// accepts, in order.
+    ///
+    /// Returns a result per transaction, in the order of `txs`. Returns an error if the
+    /// `testmempoolaccept` request fails, no transaction is broadcast then.
+    fn broadcast_all(
+        &self,
+        txs: &[Transaction],
+        max_fee_rate: Option<FeeRate>,
+    ) -> Result<Vec<Result<Txid, BroadcastError<bitcoincore_rpc::Error>>>, bitcoincore_rpc::Error>
+    {
+        let results = self.test_broadcast(txs, max_fee_rate)?;
+        Ok(txs
+            .iter()
+            .zip(results)
+            .map(|(tx, result)| {
+                let txid = result?;
+                let mut args = vec![Value::String(serialize_hex(tx))];
+                if let Some(max_fee_rate) = max_fee_rate {
+                    args.push(btc_per_kvb(max_fee_rate));
+                }
+                match self.call::<Txid>("sendrawtransaction", &args) {
+                    Ok(txid) => Ok(txid),
+                    Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(
+                        err,
+                    ))) => rejection(txid, &err.message),
+                    Err(err) => Err(BroadcastError::Request(err)),
+                }
+            })
+            .collect())
+    }
+
+    /// Submit `txs` to the mempool of the node as a package, ordered parents before children,
+    /// with the `submitpackage` RPC of Bitcoin Core 26 and later.
+    ///
+    /// Unlike [`broadcast_all`](Self::broadcast_all), a child can pay for a parent whose fee rate
+    /// is below the min fee rate of the mempool. Returns a result per transaction, in the order of
+    /// `txs`. Returns an error if the request fails, such as when the node doesn't support the
+    /// RPC.
+    fn submit_package(
+        &self,
+        txs: &[Transaction],
+        max_fee_rate: Option<FeeRate>,
+    ) -> Result<Vec<Result<Txid, BroadcastError<bitcoincore_rpc::Error>>>, bitcoincore_rpc::Error>
+    {
+        let hexes = txs.iter().map(serialize_hex).collect::<Vec<_>>();
+        let mut args = vec![serde_json::to_value(hexes)?];
+        if let Some(max_fee_rate) = max_fee_rate {
+            args.push(btc_per_kvb(max_fee_rate));
+        }
+        let result = self.call::<Value>("submitpackage", &args)?;
+        let package_msg = result["package_msg"].as_str().unwrap_or("not submitted");
+        Ok(txs
+            .iter()
+            .map(|tx| {
+                let txid = tx.compute_txid();
+                // the results are keyed by wtxid
+                let tx_result = &result["tx-results"][tx.compute_wtxid().to_string()];
+                match tx_result["error"].as_str() {
+                    Some(reason) => rejection(txid, reason),
+                    None if tx_result.is_object() => Ok(txid),
+                    None => rejection(txid, package_msg),
+                }
+            })
+            .collect())
+    }
+
+    /// Broadcast `tx` like [`broadcast_all`](Self::broadcast_all), retrying while the requests
+    /// fail, such as when the work queue of the node is full, with [`broadcast_with_retry`].
+    ///
+    /// Before each retry, the transaction is looked up with `getrawtransaction`, which finds the
+    /// transactions of the mempool, and the confirmed ones if the node runs with `-txindex`.
+    #[cfg(feature = "std")]
+    fn broadcast_with_retry(
+        &self,
+        tx: &Transaction,
+        max_fee_rate: Option<FeeRate>,
+        policy: &BroadcastRetryPolicy,
+    ) -> Result<Txid, BroadcastRetryError> {
+        let backend = RetryBackend {
+            client: self,
+            max_fee_rate,
+        };
+        broadcast_with_retry(&backend, tx, policy)
+    }
+}
+
+impl<C: bitcoincore_rpc::RpcApi> BitcoindRpcBroadcastExt for C {}
+
+/// The [`BroadcastBackend`] of [`BitcoindRpcBroadcastExt::broadcast_with_retry`]
+#[cfg(feature = "std")]
+struct RetryBackend<'a, C> {
+    client: &'a C,
+    max_fee_rate: Option<FeeRate>,
+}
+
+#[cfg(feature = "std")]
+impl<C: BitcoindRpcBroadcastExt> BroadcastBackend for RetryBackend<'_, C> {
+    fn name(&self) -> &str {
+        "bitcoind"
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, BroadcastError<BackendError>> {
+        match self
+            .client
+            .broadcast_all(core::slice::from_ref(tx), self.max_fee_rate)
+        {
+            Ok(mut results) => results
+                .pop()
+                .expect("one result per transaction")
+                .map_err(|err| err.map_request(|err| err.into())),
+            Err(err) => Err(BroadcastError::Request(err.into())),
+        }
+    }
+
+    fn has_tx(&self, txid: Txid) -> Result<bool, BackendError> {
+        match self.client.get_raw_transaction(&txid, None) {
+            Ok(_) => Ok(true),
+            Err(err) if err.is_not_found_error() => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// The fee rate in BTC/kvB, the unit of the `maxfeerate` argument.
+fn btc_per_kvb(fee_rate: FeeRate) -> Value {
+    Value::from(fee_rate.to_sat_per_kwu() as f64 * 4.0 / 100_000_000.0)
+}
+
+/// The result of a transaction rejected for `reason`, which is a success if the transaction is
+/// already in the mempool.
+fn rejection(txid: Txid, reason: &str) -> Result<Txid, BroadcastError<bitcoincore_rpc::Error>> {
+    match BroadcastError::from_reject_reason(reason) {
+        Some(err) => Err(err),
+        None => Ok(txid),
+    }
+}
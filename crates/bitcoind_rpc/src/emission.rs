@@ -0,0 +1,525 @@
+//! The emission logic shared by the [`Emitter`] and the async emitter.
+//!
+//! The [`EmitterCore`] doesn't make the RPC calls itself: [`EmitterCore::poll`] returns the call
+//! it needs the reply of, and the emitters make it with their client and pass the reply back. The
+//! state is only modified once the replies a change depends on are known, never while a call is
+//! in flight, so an emitter whose call fails, or whose future is dropped mid-call, can be polled
+//! again.
+//!
+//! [`Emitter`]: crate::Emitter
+
+use crate::{BitcoindRpcErrorExt, EmitterState, MempoolEvent};
+use bdk_chain::{local_chain::CheckPoint, BlockId};
+use bitcoin::{BlockHash, Transaction, Txid};
+use bitcoincore_rpc::bitcoincore_rpc_json::{GetBlockHeaderResult, GetMempoolEntryResult};
+use core::ops::Range;
+use std::collections::{HashMap, HashSet};
+
+/// The state of an emitter, independent of its client
+pub(crate) struct EmitterCore {
+    pub(crate) start_height: u32,
+
+    /// The checkpoint of the last-emitted block that is in the best chain. If it is later found
+    /// that the block is no longer in the best chain, it will be popped off from here.
+    pub(crate) last_cp: CheckPoint,
+
+    /// The block result returned from rpc of the last-emitted block. As this result contains the
+    /// next block's block hash (which we use to fetch the next block), we set this to `None`
+    /// whenever there are no more blocks, or the next block is no longer in the best chain. This
+    /// gives us an opportunity to re-fetch this result.
+    pub(crate) last_block: Option<LastBlock>,
+
+    /// The heights below the prune height of the node which were skipped by `new_clamped`.
+    pub(crate) pruned_heights: Option<Range<u32>>,
+
+    /// The latest first-seen epoch of emitted mempool transactions. This is used to determine
+    /// whether a mempool transaction is already emitted.
+    pub(crate) last_mempool_time: usize,
+
+    /// The last emitted block during our last mempool emission. This is used to determine whether
+    /// there has been a reorg since our last mempool emission.
+    pub(crate) last_mempool_tip: Option<u32>,
+
+    /// The emitted mempool transactions which are not known to be confirmed or evicted. This is
+    /// used to determine the evicted transactions, and to avoid re-fetching transactions.
+    pub(crate) mempool_snapshot: HashMap<Txid, EmittedTx>,
+}
+
+/// A mempool transaction in the snapshot of the [`EmitterCore`]
+pub(crate) struct EmittedTx {
+    /// The first-seen unix timestamp of the transaction
+    first_seen: u64,
+    /// The transaction, which is `None` if the emitter is restored from an [`EmitterState`] until
+    /// the transaction is fetched again
+    tx: Option<Transaction>,
+}
+
+/// The height, hash and next block hash of the last-emitted block, or of the point of agreement.
+///
+/// Only the headers are fetched to find the blocks, the blocks may be pruned by the node.
+pub(crate) struct LastBlock {
+    pub(crate) height: u32,
+    pub(crate) hash: BlockHash,
+    pub(crate) next_hash: Option<BlockHash>,
+}
+
+/// An RPC call the [`EmitterCore`] needs the reply of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Call {
+    /// `getblockhash` at the height, replied with [`Reply::BlockHash`]
+    BlockHash(u32),
+    /// `getblockheader` of the hash with `verbose=true`, replied with [`Reply::HeaderInfo`]
+    ///
+    /// If `not_found_ok` a "not found" error is replied with `None`, otherwise it is returned.
+    HeaderInfo { hash: BlockHash, not_found_ok: bool },
+}
+
+/// The reply to a [`Call`]
+pub(crate) enum Reply {
+    BlockHash(BlockHash),
+    HeaderInfo(Option<Box<GetBlockHeaderResult>>),
+}
+
+impl Call {
+    /// Make the call with a blocking client
+    pub(crate) fn call_blocking<C: bitcoincore_rpc::RpcApi>(
+        self,
+        client: &C,
+    ) -> Result<Reply, bitcoincore_rpc::Error> {
+        match self {
+            Call::BlockHash(height) => Ok(Reply::BlockHash(client.get_block_hash(height as _)?)),
+            Call::HeaderInfo { hash, not_found_ok } => match client.get_block_header_info(&hash) {
+                Ok(res) => Ok(Reply::HeaderInfo(Some(Box::new(res)))),
+                Err(err) if not_found_ok && err.is_not_found_error() => Ok(Reply::HeaderInfo(None)),
+                Err(err) => Err(err),
+            },
+        }
+    }
+}
+
+/// What the emitter must do next to emit a block, see [`EmitterCore::poll`]
+pub(crate) enum Step {
+    /// Make the call and pass its reply to [`EmitterCore::poll`]
+    Call(Call),
+    /// Fetch the block, then pass it to [`EmitterCore::emit`]
+    Fetch(BlockId),
+    /// There are no more blocks, the emitter is at the tip of the node
+    Tip,
+    /// The genesis block of the node isn't the one of the last checkpoint
+    GenesisMismatch { expected: BlockHash, got: BlockHash },
+}
+
+/// The progress of [`EmitterCore::poll`] towards the next block to emit, local to an emission
+#[derive(Default)]
+pub(crate) struct Poll(Stage);
+
+#[derive(Default)]
+enum Stage {
+    /// Nothing is requested yet
+    #[default]
+    Start,
+    /// The hash at the start height is requested, to enforce the start height
+    StartHash { last: BlockId },
+    /// The hash at the height of the last-emitted block is requested, to check it's still in the
+    /// best chain
+    LastHash {
+        last: BlockId,
+        start_hash: BlockHash,
+    },
+    /// The header of the next block, at this height, is requested
+    NextHeader(u32),
+    /// The header of this checkpoint is requested, to find the point of agreement
+    Agreement(CheckPoint),
+    /// The genesis hash is requested, no checkpoint is in the best chain
+    Genesis,
+    /// The next block is found, it must be fetched
+    Found(LastBlock),
+}
+
+impl EmitterCore {
+    pub(crate) fn new(last_cp: CheckPoint, start_height: u32) -> Self {
+        Self {
+            start_height,
+            last_cp,
+            last_block: None,
+            pruned_heights: None,
+            last_mempool_time: 0,
+            last_mempool_tip: None,
+            mempool_snapshot: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn from_state(state: EmitterState) -> Option<Self> {
+        let last_cp = CheckPoint::from_block_ids(
+            state
+                .blocks
+                .into_iter()
+                .map(|(height, hash)| BlockId { height, hash }),
+        )
+        .ok()?;
+        Some(Self {
+            start_height: state.start_height,
+            last_cp,
+            last_block: None,
+            pruned_heights: state.pruned_heights,
+            last_mempool_time: state.last_mempool_time as usize,
+            last_mempool_tip: state.last_mempool_tip,
+            mempool_snapshot: state
+                .mempool_txs
+                .into_iter()
+                .map(|(txid, first_seen)| {
+                    (
+                        txid,
+                        EmittedTx {
+                            first_seen,
+                            tx: None,
+                        },
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    pub(crate) fn state(&self) -> EmitterState {
+        EmitterState {
+            blocks: self
+                .last_cp
+                .iter()
+                .map(|cp| (cp.height(), cp.hash()))
+                .collect(),
+            start_height: self.start_height,
+            pruned_heights: self.pruned_heights.clone(),
+            mempool_txs: self
+                .mempool_snapshot
+                .iter()
+                .map(|(&txid, emitted_tx)| (txid, emitted_tx.first_seen))
+                .collect(),
+            last_mempool_time: self.last_mempool_time as u64,
+            last_mempool_tip: self.last_mempool_tip,
+        }
+    }
+
+    /// Start the emission at the prune height of the node if the start height is below it
+    pub(crate) fn clamp_start_height(&mut self, prune_height: Option<u32>) {
+        if let Some(prune_height) = prune_height {
+            if self.start_height < prune_height {
+                self.pruned_heights = Some(self.start_height..prune_height);
+                self.start_height = prune_height;
+            }
+        }
+    }
+
+    /// Whether the txids of the emitted blocks are needed to update the mempool snapshot
+    pub(crate) fn is_tracking_mempool(&self) -> bool {
+        !self.mempool_snapshot.is_empty()
+    }
+
+    /// Advance `poll` with the `reply` to the last call it returned, if any.
+    pub(crate) fn poll(&mut self, poll: &mut Poll, mut reply: Option<Reply>) -> Step {
+        loop {
+            let stage = core::mem::take(&mut poll.0);
+            match (stage, reply.take()) {
+                (Stage::Start, _) => match &self.last_block {
+                    Some(last) if last.height < self.start_height => {
+                        poll.0 = Stage::StartHash {
+                            last: BlockId {
+                                height: last.height,
+                                hash: last.hash,
+                            },
+                        };
+                        return Step::Call(Call::BlockHash(self.start_height));
+                    }
+                    Some(last) => match last.next_hash {
+                        Some(next_hash) => {
+                            poll.0 = Stage::NextHeader(last.height + 1);
+                            return Step::Call(Call::HeaderInfo {
+                                hash: next_hash,
+                                not_found_ok: false,
+                            });
+                        }
+                        None => {
+                            self.last_block = None;
+                            return Step::Tip;
+                        }
+                    },
+                    None => {
+                        let cp = self.last_cp.clone();
+                        let hash = cp.hash();
+                        poll.0 = Stage::Agreement(cp);
+                        return Step::Call(Call::HeaderInfo {
+                            hash,
+                            not_found_ok: true,
+                        });
+                    }
+                },
+                (Stage::StartHash { last }, Some(Reply::BlockHash(start_hash))) => {
+                    // make sure last emission is still in best chain
+                    poll.0 = Stage::LastHash { last, start_hash };
+                    return Step::Call(Call::BlockHash(last.height));
+                }
+                (Stage::LastHash { last, start_hash }, Some(Reply::BlockHash(hash))) => {
+                    if hash != last.hash {
+                        self.last_block = None;
+                        continue;
+                    }
+                    poll.0 = Stage::NextHeader(self.start_height);
+                    return Step::Call(Call::HeaderInfo {
+                        hash: start_hash,
+                        not_found_ok: false,
+                    });
+                }
+                (Stage::NextHeader(height), Some(Reply::HeaderInfo(res))) => match res {
+                    Some(res) if res.confirmations >= 0 => {
+                        let hash = res.hash;
+                        poll.0 = Stage::Found(LastBlock {
+                            height,
+                            hash,
+                            next_hash: res.next_block_hash,
+                        });
+                        return Step::Fetch(BlockId { height, hash });
+                    }
+                    // the next block is not in the best chain
+                    _ => {
+                        self.last_block = None;
+                        continue;
+                    }
+                },
+                (Stage::Agreement(cp), Some(Reply::HeaderInfo(res))) => match res {
+                    Some(res) if res.confirmations >= 0 => {
+                        let agreement_h = res.height as u32;
+
+                        // The tip during the last mempool emission needs to in the best chain, we
+                        // reduce it if it is not.
+                        if let Some(h) = self.last_mempool_tip.as_mut() {
+                            if *h > agreement_h {
+                                *h = agreement_h;
+                            }
+                        }
+
+                        // get rid of evicted blocks
+                        self.last_cp = cp;
+                        self.last_block = Some(LastBlock {
+                            height: agreement_h,
+                            hash: res.hash,
+                            next_hash: res.next_block_hash,
+                        });
+                        continue;
+                    }
+                    // the block is not in the best chain or not found, try the previous one. If
+                    // we can't find the genesis block, we can't create an update that connects
+                    _ => match cp.prev() {
+                        Some(prev) if cp.height() > 0 => {
+                            let hash = prev.hash();
+                            poll.0 = Stage::Agreement(prev);
+                            return Step::Call(Call::HeaderInfo {
+                                hash,
+                                not_found_ok: true,
+                            });
+                        }
+                        _ => {
+                            poll.0 = Stage::Genesis;
+                            return Step::Call(Call::BlockHash(0));
+                        }
+                    },
+                },
+                (Stage::Genesis, Some(Reply::BlockHash(genesis_hash))) => {
+                    // a checkpoint chain which starts at another genesis block is of another
+                    // network, there's no update that connects to it
+                    if let Some(genesis) = self.last_cp.get(0) {
+                        if genesis.hash() != genesis_hash {
+                            return Step::GenesisMismatch {
+                                expected: genesis.hash(),
+                                got: genesis_hash,
+                            };
+                        }
+                    }
+                    // Force the genesis checkpoint down the receiver's throat.
+                    self.last_cp = CheckPoint::new(BlockId {
+                        height: 0,
+                        hash: genesis_hash,
+                    });
+                    self.last_block = None;
+                    continue;
+                }
+                (Stage::Found(_), _) => unreachable!("the found block must be emitted"),
+                _ => unreachable!("the reply must match the call"),
+            }
+        }
+    }
+
+    /// Emit the block found by `poll`, with the txids of the block if the emitter
+    /// [`is_tracking_mempool`](Self::is_tracking_mempool).
+    pub(crate) fn emit(&mut self, poll: Poll, txids: Option<Vec<Txid>>) -> CheckPoint {
+        match poll.0 {
+            Stage::Found(last) => self.push_block(last, txids.into_iter().flatten()),
+            _ => unreachable!("the emitted block must be found"),
+        }
+    }
+
+    /// Push `block` on top of the last-emitted block, it must connect to it.
+    pub(crate) fn push_block(
+        &mut self,
+        block: LastBlock,
+        txids: impl IntoIterator<Item = Txid>,
+    ) -> CheckPoint {
+        // the txs of an emitted block are confirmed, not evicted
+        for txid in txids {
+            self.mempool_snapshot.remove(&txid);
+        }
+
+        let new_cp = self
+            .last_cp
+            .clone()
+            .push(BlockId {
+                height: block.height,
+                hash: block.hash,
+            })
+            .expect("must push");
+        self.last_cp = new_cp.clone();
+        self.last_block = Some(block);
+        new_cp
+    }
+
+    /// This is the emitted tip height during the last mempool emission.
+    fn prev_mempool_tip(&self) -> u32 {
+        self.last_mempool_tip
+            // We use `start_height - 1` as we cannot guarantee that the block at
+            // `start_height` has been emitted.
+            .unwrap_or(self.start_height.saturating_sub(1))
+    }
+
+    /// Whether the transaction must be emitted, see [`Emitter::mempool`].
+    ///
+    /// [`Emitter::mempool`]: crate::Emitter::mempool
+    fn needs_emission(&self, txid: &Txid, tx_entry: &GetMempoolEntryResult) -> bool {
+        // Avoid emitting transactions that are already emitted if we can guarantee blocks
+        // containing ancestors are already emitted. The bitcoind rpc interface provides us
+        // with the block height that the tx is introduced to the mempool. If we have already
+        // emitted the block of height, we can assume that all ancestor txs have been processed
+        // by the receiver.
+        let is_within_height = tx_entry.height <= self.prev_mempool_tip() as _;
+        !(self.mempool_snapshot.contains_key(txid) && is_within_height)
+    }
+
+    /// The transactions of `mempool` which must be fetched, to pass them to
+    /// [`mempool`](Self::mempool)
+    pub(crate) fn mempool_txs_to_fetch(
+        &self,
+        mempool: &HashMap<Txid, GetMempoolEntryResult>,
+    ) -> Vec<Txid> {
+        mempool
+            .iter()
+            .filter(|(txid, tx_entry)| self.needs_emission(txid, tx_entry))
+            .filter(|(txid, _)| {
+                self.mempool_snapshot
+                    .get(*txid)
+                    .map_or(true, |emitted_tx| emitted_tx.tx.is_none())
+            })
+            .map(|(&txid, _)| txid)
+            .collect()
+    }
+
+    /// Emit the changes of `mempool`, the verbose `getrawmempool`, with the transactions `fetched`
+    /// as requested by [`mempool_txs_to_fetch`](Self::mempool_txs_to_fetch).
+    ///
+    /// `at_tip` is whether the best block of the node, fetched after the mempool, is the
+    /// last-emitted block. The transactions which are not fetched are confirmed or evicted since
+    /// `mempool` was fetched, they are skipped.
+    pub(crate) fn mempool(
+        &mut self,
+        mempool: HashMap<Txid, GetMempoolEntryResult>,
+        at_tip: bool,
+        mut fetched: HashMap<Txid, Transaction>,
+    ) -> MempoolEvent {
+        // Mempool txs come with a timestamp of when the tx is introduced to the mempool. We keep
+        // track of the latest mempool tx's timestamp, which is when the evicted txs were found
+        // missing.
+        let mut latest_time = self.last_mempool_time;
+
+        // The best block is fetched after the mempool, so that a tx confirmed in the meantime is
+        // not mistaken for an evicted tx.
+        let evicted_txids = if at_tip {
+            self.mempool_snapshot
+                .keys()
+                .filter(|&txid| !mempool.contains_key(txid))
+                .copied()
+                .collect::<HashSet<_>>()
+        } else {
+            HashSet::new()
+        };
+
+        let mut new_txs = HashMap::<Txid, (Transaction, u64)>::new();
+        for (&txid, tx_entry) in &mempool {
+            let tx_time = tx_entry.time as usize;
+            if tx_time > latest_time {
+                latest_time = tx_time;
+            }
+            if !self.needs_emission(&txid, tx_entry) {
+                continue;
+            }
+            let cached_tx = self
+                .mempool_snapshot
+                .get(&txid)
+                .and_then(|emitted_tx| emitted_tx.tx.clone());
+            // the tx is confirmed or evicted since `getrawmempool` if it couldn't be fetched
+            if let Some(tx) = cached_tx.or_else(|| fetched.remove(&txid)) {
+                new_txs.insert(txid, (tx, tx_time as u64));
+            }
+        }
+
+        for txid in &evicted_txids {
+            self.mempool_snapshot.remove(txid);
+        }
+        for (&txid, (tx, first_seen)) in &new_txs {
+            self.mempool_snapshot.insert(
+                txid,
+                EmittedTx {
+                    first_seen: *first_seen,
+                    tx: Some(tx.clone()),
+                },
+            );
+        }
+
+        // order the txs parents before children, the txs entering and leaving the mempool in the
+        // meantime may leave a parent unemitted, but no child is emitted before its parent
+        let mut txids = new_txs.keys().copied().collect::<Vec<_>>();
+        txids.sort_by_key(|txid| (new_txs[txid].1, *txid));
+        let mut ordered_txids = Vec::with_capacity(txids.len());
+        let mut visited = HashSet::new();
+        for txid in txids {
+            visit_parents_first(txid, &mempool, &new_txs, &mut visited, &mut ordered_txids);
+        }
+        let new_txs = ordered_txids
+            .into_iter()
+            .filter_map(|txid| new_txs.remove(&txid))
+            .collect();
+
+        self.last_mempool_time = latest_time;
+        self.last_mempool_tip = Some(self.last_cp.height());
+
+        MempoolEvent {
+            new_txs,
+            evicted_txids,
+            latest_update_time: latest_time as u64,
+        }
+    }
+}
+
+/// Push the emitted ancestors of `txid` which are not visited yet to `ordered_txids`, then `txid`.
+fn visit_parents_first(
+    txid: Txid,
+    mempool: &HashMap<Txid, GetMempoolEntryResult>,
+    new_txs: &HashMap<Txid, (Transaction, u64)>,
+    visited: &mut HashSet<Txid>,
+    ordered_txids: &mut Vec<Txid>,
+) {
+    if !new_txs.contains_key(&txid) || !visited.insert(txid) {
+        return;
+    }
+    if let Some(tx_entry) = mempool.get(&txid) {
+        for &parent_txid in &tx_entry.depends {
+            visit_parents_first(parent_txid, mempool, new_txs, visited, ordered_txids);
+        }
+    }
+    ordered_txids.push(txid);
+}
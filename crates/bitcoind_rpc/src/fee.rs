@@ -0,0 +1,138 @@
+//! Fee estimation with the RPC interface of `bitcoind`, so that a wallet synced with the
+//! [`Emitter`](crate::Emitter) doesn't need a second data source.
+
+use bitcoin::{Amount, FeeRate};
+use bitcoincore_rpc::bitcoincore_rpc_json::EstimateMode;
+use std::fmt;
+
+/// Estimate the fee rate for a transaction to confirm within `target_blocks` blocks with
+/// `estimatesmartfee`, in the estimation `mode`.
+///
+/// The estimate of the node in BTC/kvB is rounded up to the next sat/kwu, so that the fee rate
+/// is never below the estimate. Returns [`FeeEstimateError::NoEstimate`] if the node doesn't have
+/// enough data to estimate, such as on a fresh regtest chain.
+pub fn fee_estimate<C: bitcoincore_rpc::RpcApi>(
+    client: &C,
+    target_blocks: u16,
+    mode: EstimateMode,
+) -> Result<FeeRate, FeeEstimateError> {
+    let res = client.estimate_smart_fee(target_blocks, Some(mode))?;
+    match res.fee_rate {
+        Some(fee_rate) => Ok(fee_rate_from_btc_per_kvb(fee_rate)),
+        None => Err(FeeEstimateError::NoEstimate {
+            target: target_blocks,
+        }),
+    }
+}
+
+/// The min fee rate of the mempool of the node from `getmempoolinfo`, which is the max of the min
+/// relay fee rate and the fee rate below which the mempool is full.
+///
+/// A transaction, such as a replacement, pays at least this fee rate to enter the mempool.
+pub fn mempool_min_fee<C: bitcoincore_rpc::RpcApi>(
+    client: &C,
+) -> Result<FeeRate, FeeEstimateError> {
+    let info = client.get_mempool_info()?;
+    Ok(fee_rate_from_btc_per_kvb(info.mempool_min_fee))
+}
+
+/// The fee rate of `amount` per kvB, rounded up to the next sat/kwu.
+fn fee_rate_from_btc_per_kvb(amount: Amount) -> FeeRate {
+    // 1 kvB is 4 kwu
+    FeeRate::from_sat_per_kwu((amount.to_sat() + 3) / 4)
+}
+
+/// An error of [`fee_estimate`] or [`mempool_min_fee`]
+#[derive(Debug)]
+pub enum FeeEstimateError {
+    /// An RPC call failed
+    Rpc(bitcoincore_rpc::Error),
+    /// The node doesn't have enough data to estimate the fee rate for the `target` of blocks
+    NoEstimate {
+        /// The confirmation target in blocks
+        target: u16,
+    },
+}
+
+impl fmt::Display for FeeEstimateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rpc(err) => write!(f, "rpc error: {}", err),
+            Self::NoEstimate { target } => write!(
+                f,
+                "insufficient data to estimate the fee rate for a target of {} blocks",
+                target
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FeeEstimateError {}
+
+impl From<bitcoincore_rpc::Error> for FeeEstimateError {
+    fn from(err: bitcoincore_rpc::Error) -> Self {
+        Self::Rpc(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoincore_rpc::jsonrpc::serde_json::{self, json, Value};
+
+    /// A client which answers each RPC method with a canned response.
+    struct MockClient(Vec<(&'static str, Value)>);
+
+    impl bitcoincore_rpc::RpcApi for MockClient {
+        fn call<T: for<'a> bitcoincore_rpc::jsonrpc::serde::de::Deserialize<'a>>(
+            &self,
+            cmd: &str,
+            _args: &[Value],
+        ) -> bitcoincore_rpc::Result<T> {
+            let (_, res) = self
+                .0
+                .iter()
+                .find(|(method, _)| *method == cmd)
+                .expect("must mock the method");
+            Ok(serde_json::from_value(res.clone())?)
+        }
+    }
+
+    #[test]
+    fn test_fee_estimate() {
+        // 0.00012345 BTC/kvB is 12345 sat/kvB, or 3086.25 sat/kwu
+        let client = MockClient(vec![(
+            "estimatesmartfee",
+            json!({ "feerate": 0.00012345, "blocks": 2 }),
+        )]);
+        let fee_rate = fee_estimate(&client, 2, EstimateMode::Economical).unwrap();
+        assert_eq!(fee_rate, FeeRate::from_sat_per_kwu(3087));
+
+        let client = MockClient(vec![(
+            "estimatesmartfee",
+            json!({ "errors": ["Insufficient data or no feerate found"], "blocks": 0 }),
+        )]);
+        assert!(matches!(
+            fee_estimate(&client, 6, EstimateMode::Conservative),
+            Err(FeeEstimateError::NoEstimate { target: 6 })
+        ));
+    }
+
+    #[test]
+    fn test_mempool_min_fee() {
+        let client = MockClient(vec![(
+            "getmempoolinfo",
+            json!({
+                "loaded": true,
+                "size": 0,
+                "bytes": 0,
+                "usage": 0,
+                "maxmempool": 300000000,
+                "mempoolminfee": 0.00001,
+                "minrelaytxfee": 0.00001,
+            }),
+        )]);
+        let fee_rate = mempool_min_fee(&client).unwrap();
+        assert_eq!(fee_rate, FeeRate::from_sat_per_kwu(250));
+    }
+}
@@ -1,18 +1,67 @@
 //! This crate is used for emitting blockchain data from the `bitcoind` RPC interface. It does not
-//! use the wallet RPC API, so this crate can be used with wallet-disabled Bitcoin Core nodes.
+//! use the wallet RPC API unless the `node-wallet` feature is enabled, so this crate can be used
+//! with wallet-disabled Bitcoin Core nodes.
 //!
 //! [`Emitter`] is the main structure which sources blockchain data from [`bitcoincore_rpc::Client`].
 //!
 //! To only get block updates (exclude mempool transactions), the caller can use
 //! [`Emitter::next_block`] or/and [`Emitter::next_header`] until it returns `Ok(None)` (which means
-//! the chain tip is reached). A separate method, [`Emitter::mempool`] can be used to emit the whole
-//! mempool.
+//! the chain tip is reached). A separate method, [`Emitter::mempool`] can be used to emit the
+//! changes of the mempool since its last call.
+//!
+//! For the initial sync of a wallet, [`Emitter::into_parallel`] prefetches the upcoming blocks
+//! with worker threads.
+//!
+//! [`BitcoindRpcBroadcastExt`] broadcasts transactions after checking them with
+//! `testmempoolaccept`, with typed rejection reasons.
+//!
+//! [`fee_estimate`] and [`mempool_min_fee`] estimate the fee rates with the node.
+//!
+//! With `-blockfilterindex=1`, the [`bip158::FilterIter`] only fetches the blocks whose compact
+//! block filter matches the script pubkeys of a wallet. [`RpcBlockTxs`] fetches the matched blocks
+//! for a [`TieredFetcher`], which falls back to other chain sources if the node is pruned.
+//!
+//! With the `node-wallet` feature, [`node_wallet::import_wallet_descriptors`] imports the
+//! descriptors of a wallet into a watch-only wallet of the node, and the
+//! [`node_wallet::NodeWalletEmitter`] emits the transactions which the node finds relevant.
+//!
+//! With the `zmq` feature, the [`zmq::NotifiedEmitter`] waits for the ZMQ notifications of
+//! `bitcoind` instead of polling it.
+//!
+//! With the `async` feature, the [`async_emitter::AsyncEmitter`] emits the same data with an async
+//! client, such as the [`async_emitter::AsyncClient`] over HTTP.
+//!
+//! [`TieredFetcher`]: bdk_chain::tiered_fetch::TieredFetcher
 #![warn(missing_docs)]
 
-use bdk_chain::{local_chain::CheckPoint, BlockId};
-use bitcoin::{block::Header, Block, BlockHash, Transaction};
+use bdk_chain::{local_chain::CheckPoint, BlockId, BlockTimeOrHeight};
+use bitcoin::{block::Header, Block, BlockHash, Transaction, Txid};
 pub use bitcoincore_rpc;
-use bitcoincore_rpc::bitcoincore_rpc_json;
+use core::fmt;
+use core::ops::Range;
+use emission::{EmitterCore, Poll, Step};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[cfg(feature = "async")]
+pub mod async_emitter;
+pub mod bip158;
+#[cfg(feature = "std")]
+mod block_txs;
+#[cfg(feature = "std")]
+pub use block_txs::RpcBlockTxs;
+mod broadcast;
+pub use broadcast::{BitcoindRpcBroadcastExt, MAX_PACKAGE_LEN};
+mod emission;
+mod fee;
+pub use fee::{fee_estimate, mempool_min_fee, FeeEstimateError};
+#[cfg(feature = "node-wallet")]
+pub mod node_wallet;
+mod parallel;
+pub use parallel::ParallelEmitter;
+mod prevout;
+pub use prevout::RpcPrevouts;
+#[cfg(feature = "zmq")]
+pub mod zmq;
 
 /// The [`Emitter`] is used to emit data sourced from [`bitcoincore_rpc::Client`].
 ///
@@ -21,25 +70,7 @@ use bitcoincore_rpc::bitcoincore_rpc_json;
 /// [module-level documentation]: crate
 pub struct Emitter<'c, C> {
     client: &'c C,
-    start_height: u32,
-
-    /// The checkpoint of the last-emitted block that is in the best chain. If it is later found
-    /// that the block is no longer in the best chain, it will be popped off from here.
-    last_cp: CheckPoint,
-
-    /// The block result returned from rpc of the last-emitted block. As this result contains the
-    /// next block's block hash (which we use to fetch the next block), we set this to `None`
-    /// whenever there are no more blocks, or the next block is no longer in the best chain. This
-    /// gives us an opportunity to re-fetch this result.
-    last_block: Option<bitcoincore_rpc_json::GetBlockResult>,
-
-    /// The latest first-seen epoch of emitted mempool transactions. This is used to determine
-    /// whether a mempool transaction is already emitted.
-    last_mempool_time: usize,
-
-    /// The last emitted block during our last mempool emission. This is used to determine whether
-    /// there has been a reorg since our last mempool emission.
-    last_mempool_tip: Option<u32>,
+    core: EmitterCore,
 }
 
 impl<'c, C: bitcoincore_rpc::RpcApi> Emitter<'c, C> {
@@ -49,19 +80,60 @@ impl<'c, C: bitcoincore_rpc::RpcApi> Emitter<'c, C> {
     /// can start emission from a block that connects to the original chain.
     ///
     /// `start_height` starts emission from a given height (if there are no conflicts with the
-    /// original chain).
+    /// original chain), such as the [`birthday_height`] of the wallet.
     pub fn new(client: &'c C, last_cp: CheckPoint, start_height: u32) -> Self {
         Self {
             client,
-            start_height,
-            last_cp,
-            last_block: None,
-            last_mempool_time: 0,
-            last_mempool_tip: None,
+            core: EmitterCore::new(last_cp, start_height),
         }
     }
 
-    /// Emit mempool transactions, alongside their first-seen unix timestamps.
+    /// Construct a new [`Emitter`] like [`new`](Self::new), starting at the prune height of the
+    /// node if `start_height` is below it.
+    ///
+    /// The blocks below the prune height can't be fetched, so the transactions they contain can't
+    /// be scanned. The skipped heights are reported by [`pruned_heights`](Self::pruned_heights),
+    /// for example to warn that funds received in them may be missed.
+    pub fn new_clamped(
+        client: &'c C,
+        last_cp: CheckPoint,
+        start_height: u32,
+    ) -> Result<Self, EmitterError> {
+        let mut emitter = Self::new(client, last_cp, start_height);
+        emitter.core.clamp_start_height(prune_height(client)?);
+        Ok(emitter)
+    }
+
+    /// Restore an [`Emitter`] from the `state` of a previous emitter, see [`state`](Self::state).
+    ///
+    /// The restored emitter continues the emission of blocks after the last-emitted block, and
+    /// doesn't emit again the mempool transactions which were emitted, but reports them as evicted
+    /// if they left the mempool in the meantime.
+    ///
+    /// Returns `None` if `state` has no blocks or they are not ordered by height, which is not the
+    /// case for a state returned by [`state`](Self::state).
+    pub fn from_state(client: &'c C, state: EmitterState) -> Option<Self> {
+        Some(Self {
+            client,
+            core: EmitterCore::from_state(state)?,
+        })
+    }
+
+    /// The state of the emitter, to restore it with [`from_state`](Self::from_state) after a
+    /// restart.
+    ///
+    /// Persist it alongside the changes of the wallet, once the emissions are applied.
+    pub fn state(&self) -> EmitterState {
+        self.core.state()
+    }
+
+    /// The heights of the blocks which can't be scanned because [`new_clamped`](Self::new_clamped)
+    /// started the emission at the prune height of the node, if any
+    pub fn pruned_heights(&self) -> Option<Range<u32>> {
+        self.core.pruned_heights.clone()
+    }
+
+    /// Emit the changes of the mempool since the last call, see [`MempoolEvent`].
     ///
     /// This method emits each transaction only once, unless we cannot guarantee the transaction's
     /// ancestors are already emitted.
@@ -70,74 +142,159 @@ impl<'c, C: bitcoincore_rpc::RpcApi> Emitter<'c, C> {
     /// alters the UTXO set of tracked script pubkeys. If an emitted mempool transaction spends a
     /// tracked UTXO which is confirmed at height `h`, but the receiver has only seen up to block
     /// of height `h-1`, we want to re-emit this transaction until the receiver has seen the block
-    /// at height `h`.
-    pub fn mempool(&mut self) -> Result<Vec<(Transaction, u64)>, bitcoincore_rpc::Error> {
+    /// at height `h`. The transactions are only fetched the first time they are emitted.
+    ///
+    /// A transaction which left the mempool is reported as evicted once the emitter has emitted
+    /// the chain tip of the node, and the transaction isn't in one of the emitted blocks. Until
+    /// then, it may have been confirmed in a block which is not emitted yet.
+    pub fn mempool(&mut self) -> Result<MempoolEvent, EmitterError> {
         let client = self.client;
-
-        // This is the emitted tip height during the last mempool emission.
-        let prev_mempool_tip = self
-            .last_mempool_tip
-            // We use `start_height - 1` as we cannot guarantee that the block at
-            // `start_height` has been emitted.
-            .unwrap_or(self.start_height.saturating_sub(1));
-
-        // Mempool txs come with a timestamp of when the tx is introduced to the mempool. We keep
-        // track of the latest mempool tx's timestamp to determine whether we have seen a tx
-        // before. `prev_mempool_time` is the previous timestamp and `last_time` records what will
-        // be the new latest timestamp.
-        let prev_mempool_time = self.last_mempool_time;
-        let mut latest_time = prev_mempool_time;
-
-        let txs_to_emit = client
-            .get_raw_mempool_verbose()?
-            .into_iter()
-            .filter_map({
-                let latest_time = &mut latest_time;
-                move |(txid, tx_entry)| -> Option<Result<_, bitcoincore_rpc::Error>> {
-                    let tx_time = tx_entry.time as usize;
-                    if tx_time > *latest_time {
-                        *latest_time = tx_time;
-                    }
-
-                    // Avoid emitting transactions that are already emitted if we can guarantee
-                    // blocks containing ancestors are already emitted. The bitcoind rpc interface
-                    // provides us with the block height that the tx is introduced to the mempool.
-                    // If we have already emitted the block of height, we can assume that all
-                    // ancestor txs have been processed by the receiver.
-                    let is_already_emitted = tx_time <= prev_mempool_time;
-                    let is_within_height = tx_entry.height <= prev_mempool_tip as _;
-                    if is_already_emitted && is_within_height {
-                        return None;
-                    }
-
-                    let tx = match client.get_raw_transaction(&txid, None) {
-                        Ok(tx) => tx,
-                        // the tx is confirmed or evicted since `get_raw_mempool_verbose`
-                        Err(err) if err.is_not_found_error() => return None,
-                        Err(err) => return Some(Err(err)),
-                    };
-
-                    Some(Ok((tx, tx_time as u64)))
+        let mempool = client.get_raw_mempool_verbose()?;
+        let at_tip = client.get_best_block_hash()? == self.core.last_cp.hash();
+        let mut txs = HashMap::new();
+        for txid in self.core.mempool_txs_to_fetch(&mempool) {
+            match client.get_raw_transaction(&txid, None) {
+                Ok(tx) => {
+                    txs.insert(txid, tx);
                 }
-            })
-            .collect::<Result<Vec<_>, _>>()?;
-
-        self.last_mempool_time = latest_time;
-        self.last_mempool_tip = Some(self.last_cp.height());
-
-        Ok(txs_to_emit)
+                // the tx is confirmed or evicted since `get_raw_mempool_verbose`
+                Err(err) if err.is_not_found_error() => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(self.core.mempool(mempool, at_tip, txs))
     }
 
     /// Emit the next block height and header (if any).
-    pub fn next_header(&mut self) -> Result<Option<BlockEvent<Header>>, bitcoincore_rpc::Error> {
-        Ok(poll(self, |hash| self.client.get_block_header(hash))?
-            .map(|(checkpoint, block)| BlockEvent { block, checkpoint }))
+    ///
+    /// Only the headers are fetched, with `getblockheader`, which keeps a [`LocalChain`] current
+    /// without downloading the blocks. The reorgs are handled as with [`next_block`], and both can
+    /// be called on the same emitter, for example to fetch the blocks of the heights a filter flags
+    /// relevant. The block of an emitted header can also be fetched with
+    /// [`fetch_block`](Self::fetch_block).
+    ///
+    /// While emitted mempool transactions are not known to be confirmed, the txids of each block
+    /// are fetched as well, so that the confirmed transactions are not reported as evicted.
+    ///
+    /// [`LocalChain`]: bdk_chain::local_chain::LocalChain
+    /// [`next_block`]: Self::next_block
+    pub fn next_header(&mut self) -> Result<Option<BlockEvent<Header>>, EmitterError> {
+        let client = self.client;
+        let is_tracking_mempool = self.core.is_tracking_mempool();
+        self.poll(|hash| {
+            let header = client.get_block_header(hash)?;
+            let txids = if is_tracking_mempool {
+                Some(client.get_block_info(hash)?.tx)
+            } else {
+                None
+            };
+            Ok((header, txids))
+        })
     }
 
     /// Emit the next block height and block (if any).
-    pub fn next_block(&mut self) -> Result<Option<BlockEvent<Block>>, bitcoincore_rpc::Error> {
-        Ok(poll(self, |hash| self.client.get_block(hash))?
-            .map(|(checkpoint, block)| BlockEvent { block, checkpoint }))
+    ///
+    /// Returns [`EmitterError::BlockPruned`] if the next block is pruned by the node, see
+    /// [`new_clamped`](Self::new_clamped).
+    pub fn next_block(&mut self) -> Result<Option<BlockEvent<Block>>, EmitterError> {
+        let is_tracking_mempool = self.core.is_tracking_mempool();
+        let client = self.client;
+        self.poll(|hash| {
+            let block = client.get_block(hash)?;
+            let txids = if is_tracking_mempool {
+                Some(block.txdata.iter().map(|tx| tx.compute_txid()).collect())
+            } else {
+                None
+            };
+            Ok((block, txids))
+        })
+    }
+
+    /// Fetch the block of `block_id`, such as the block of a header emitted by
+    /// [`next_header`](Self::next_header).
+    ///
+    /// Returns [`EmitterError::BlockPruned`] if the block is pruned by the node.
+    pub fn fetch_block(&self, block_id: BlockId) -> Result<Block, EmitterError> {
+        self.client
+            .get_block(&block_id.hash)
+            .map_err(|err| block_error(self.client, block_id.height, err))
+    }
+
+    /// Emit the next block with the item fetched by `get_item`, alongside the txids of the block
+    /// if they are needed to update the mempool snapshot.
+    fn poll<V, F>(&mut self, get_item: F) -> Result<Option<BlockEvent<V>>, EmitterError>
+    where
+        F: FnOnce(&BlockHash) -> Result<(V, Option<Vec<Txid>>), bitcoincore_rpc::Error>,
+    {
+        let mut poll = Poll::default();
+        let mut reply = None;
+        loop {
+            match self.core.poll(&mut poll, reply.take()) {
+                Step::Call(call) => reply = Some(call.call_blocking(self.client)?),
+                Step::Fetch(block_id) => {
+                    let (block, txids) = get_item(&block_id.hash)
+                        .map_err(|err| block_error(self.client, block_id.height, err))?;
+                    let checkpoint = self.core.emit(poll, txids);
+                    return Ok(Some(BlockEvent { block, checkpoint }));
+                }
+                Step::Tip => return Ok(None),
+                Step::GenesisMismatch { expected, got } => {
+                    return Err(EmitterError::GenesisMismatch { expected, got })
+                }
+            }
+        }
+    }
+}
+
+/// The state of an [`Emitter`] to restore it after a restart, see [`Emitter::state`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(bdk_chain::serde::Deserialize, bdk_chain::serde::Serialize),
+    serde(crate = "bdk_chain::serde")
+)]
+pub struct EmitterState {
+    /// The blocks of the checkpoint of the last-emitted block, by height
+    pub blocks: BTreeMap<u32, BlockHash>,
+    /// The height the emission starts from
+    pub start_height: u32,
+    /// The heights skipped because they are pruned, see [`Emitter::pruned_heights`]
+    pub pruned_heights: Option<Range<u32>>,
+    /// The emitted mempool transactions which are not known to be confirmed or evicted, alongside
+    /// their first-seen unix timestamps
+    pub mempool_txs: BTreeMap<Txid, u64>,
+    /// The latest first-seen unix timestamp of the emitted mempool transactions
+    pub last_mempool_time: u64,
+    /// The height of the last-emitted block during the last mempool emission
+    pub last_mempool_tip: Option<u32>,
+}
+
+/// A mempool emission of [`Emitter::mempool`]
+#[derive(Debug, Default)]
+pub struct MempoolEvent {
+    /// The transactions which entered the mempool since the last emission, alongside their
+    /// first-seen unix timestamps, ordered parents before children.
+    pub new_txs: Vec<(Transaction, u64)>,
+
+    /// The transactions of previous emissions which left the mempool without being confirmed, for
+    /// example because they were replaced or expired.
+    pub evicted_txids: HashSet<Txid>,
+
+    /// The latest first-seen unix timestamp of the mempool transactions, which is used as the time
+    /// the evicted transactions were found missing.
+    pub latest_update_time: u64,
+}
+
+impl MempoolEvent {
+    /// The evicted transactions alongside their evicted-at timestamps, as expected by
+    /// [`TxGraph::insert_evicted_at`].
+    ///
+    /// [`TxGraph::insert_evicted_at`]: bdk_chain::tx_graph::TxGraph::insert_evicted_at
+    pub fn evicted_ats(&self) -> impl ExactSizeIterator<Item = (Txid, u64)> + '_ {
+        let evicted_at = self.latest_update_time;
+        self.evicted_txids
+            .iter()
+            .map(move |&txid| (txid, evicted_at))
     }
 }
 
@@ -184,134 +341,136 @@ impl<B> BlockEvent<B> {
     }
 }
 
-enum PollResponse {
-    Block(bitcoincore_rpc_json::GetBlockResult),
-    NoMoreBlocks,
-    /// Fetched block is not in the best chain.
-    BlockNotInBestChain,
-    AgreementFound(bitcoincore_rpc_json::GetBlockResult, CheckPoint),
-    /// Force the genesis checkpoint down the receiver's throat.
-    AgreementPointNotFound(BlockHash),
+/// The prune height of the node, the height of the earliest block it stores, if it is pruned.
+fn prune_height<C: bitcoincore_rpc::RpcApi>(
+    client: &C,
+) -> Result<Option<u32>, bitcoincore_rpc::Error> {
+    let info = client.get_blockchain_info()?;
+    Ok(info
+        .prune_height
+        .filter(|_| info.pruned)
+        .map(|prune_height| prune_height as u32))
 }
 
-fn poll_once<C>(emitter: &Emitter<C>) -> Result<PollResponse, bitcoincore_rpc::Error>
-where
-    C: bitcoincore_rpc::RpcApi,
-{
-    let client = emitter.client;
-
-    if let Some(last_res) = &emitter.last_block {
-        let next_hash = if last_res.height < emitter.start_height as _ {
-            // enforce start height
-            let next_hash = client.get_block_hash(emitter.start_height as _)?;
-            // make sure last emission is still in best chain
-            if client.get_block_hash(last_res.height as _)? != last_res.hash {
-                return Ok(PollResponse::BlockNotInBestChain);
-            }
-            next_hash
-        } else {
-            match last_res.nextblockhash {
-                None => return Ok(PollResponse::NoMoreBlocks),
-                Some(next_hash) => next_hash,
-            }
-        };
+/// How much earlier than a wallet birthday given as a time [`birthday_height`] starts, in seconds.
+///
+/// Block timestamps can be off by up to two hours, this is the window Bitcoin Core's
+/// `rescanblockchain` uses.
+const TIMESTAMP_WINDOW: u64 = 2 * 60 * 60;
 
-        let res = client.get_block_info(&next_hash)?;
-        if res.confirmations < 0 {
-            return Ok(PollResponse::BlockNotInBestChain);
+/// The height to start the emission of a wallet with `birthday` from, see
+/// [`Emitter::new`] and [`bip158::FilterIter::new`].
+///
+/// A birthday given as a height is that height. A birthday given as a time is the first block of
+/// the best chain of the node with a median time past at or after [`TIMESTAMP_WINDOW`] (two
+/// hours) before the birthday, or the height above the tip if there is none yet.
+pub fn birthday_height<C: bitcoincore_rpc::RpcApi>(
+    client: &C,
+    birthday: BlockTimeOrHeight,
+) -> Result<u32, bitcoincore_rpc::Error> {
+    let time = match birthday {
+        BlockTimeOrHeight::Height(height) => return Ok(height),
+        BlockTimeOrHeight::Time(time) => time.saturating_sub(TIMESTAMP_WINDOW),
+    };
+    // the median time past never decreases along the chain, the first height at or after `time`
+    // is found by bisection
+    let (mut low, mut high) = (0_u32, client.get_block_count()? as u32 + 1);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let hash = client.get_block_hash(mid as u64)?;
+        let median_time = client.get_block_header_info(&hash)?.median_time;
+        if median_time.map_or(0, |median_time| median_time as u64) < time {
+            low = mid + 1;
+        } else {
+            high = mid;
         }
-
-        return Ok(PollResponse::Block(res));
     }
+    Ok(low)
+}
 
-    for cp in emitter.last_cp.iter() {
-        let res = match client.get_block_info(&cp.hash()) {
-            // block not in best chain
-            Ok(res) if res.confirmations < 0 => continue,
-            Ok(res) => res,
-            Err(e) if e.is_not_found_error() => {
-                if cp.height() > 0 {
-                    continue;
-                }
-                // if we can't find genesis block, we can't create an update that connects
-                break;
-            }
-            Err(e) => return Err(e),
-        };
+/// Turn the error `err` of fetching the block at `height` into [`EmitterError::BlockPruned`] if
+/// the block is pruned by the node.
+fn block_error<C: bitcoincore_rpc::RpcApi>(
+    client: &C,
+    height: u32,
+    err: bitcoincore_rpc::Error,
+) -> EmitterError {
+    pruned_block_error(height, prune_height(client), err)
+}
 
-        // agreement point found
-        return Ok(PollResponse::AgreementFound(res, cp));
+/// Turn the error `err` of fetching the block at `height` into [`EmitterError::BlockPruned`] if
+/// the block is below `prune_height`.
+fn pruned_block_error(
+    height: u32,
+    prune_height: Result<Option<u32>, bitcoincore_rpc::Error>,
+    err: bitcoincore_rpc::Error,
+) -> EmitterError {
+    match prune_height {
+        Ok(Some(prune_height)) if height < prune_height => EmitterError::BlockPruned {
+            requested: height,
+            prune_height,
+        },
+        _ => EmitterError::Rpc(err),
     }
-
-    let genesis_hash = client.get_block_hash(0)?;
-    Ok(PollResponse::AgreementPointNotFound(genesis_hash))
 }
 
-fn poll<C, V, F>(
-    emitter: &mut Emitter<C>,
-    get_item: F,
-) -> Result<Option<(CheckPoint, V)>, bitcoincore_rpc::Error>
-where
-    C: bitcoincore_rpc::RpcApi,
-    F: Fn(&BlockHash) -> Result<V, bitcoincore_rpc::Error>,
-{
-    loop {
-        match poll_once(emitter)? {
-            PollResponse::Block(res) => {
-                let height = res.height as u32;
-                let hash = res.hash;
-                let item = get_item(&hash)?;
-
-                let new_cp = emitter
-                    .last_cp
-                    .clone()
-                    .push(BlockId { height, hash })
-                    .expect("must push");
-                emitter.last_cp = new_cp.clone();
-                emitter.last_block = Some(res);
-                return Ok(Some((new_cp, item)));
-            }
-            PollResponse::NoMoreBlocks => {
-                emitter.last_block = None;
-                return Ok(None);
-            }
-            PollResponse::BlockNotInBestChain => {
-                emitter.last_block = None;
-                continue;
-            }
-            PollResponse::AgreementFound(res, cp) => {
-                let agreement_h = res.height as u32;
-
-                // The tip during the last mempool emission needs to in the best chain, we reduce
-                // it if it is not.
-                if let Some(h) = emitter.last_mempool_tip.as_mut() {
-                    if *h > agreement_h {
-                        *h = agreement_h;
-                    }
-                }
+/// An error of the [`Emitter`]
+#[derive(Debug)]
+pub enum EmitterError {
+    /// An RPC call failed
+    Rpc(bitcoincore_rpc::Error),
+    /// The block at the `requested` height is pruned by the node, the earliest block it stores is
+    /// at `prune_height`
+    BlockPruned {
+        /// The height of the requested block
+        requested: u32,
+        /// The height of the earliest block stored by the node
+        prune_height: u32,
+    },
+    /// The genesis block of the node isn't the one of the last checkpoint, the node is on another
+    /// network
+    GenesisMismatch {
+        /// The genesis block hash of the last checkpoint
+        expected: BlockHash,
+        /// The genesis block hash of the node
+        got: BlockHash,
+    },
+}
 
-                // get rid of evicted blocks
-                emitter.last_cp = cp;
-                emitter.last_block = Some(res);
-                continue;
-            }
-            PollResponse::AgreementPointNotFound(genesis_hash) => {
-                emitter.last_cp = CheckPoint::new(BlockId {
-                    height: 0,
-                    hash: genesis_hash,
-                });
-                emitter.last_block = None;
-                continue;
-            }
+impl fmt::Display for EmitterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rpc(err) => write!(f, "rpc error: {}", err),
+            Self::BlockPruned {
+                requested,
+                prune_height,
+            } => write!(
+                f,
+                "the block at height {} is pruned, the earliest block available is at height {}",
+                requested, prune_height
+            ),
+            Self::GenesisMismatch { expected, got } => write!(
+                f,
+                "the genesis block of the node is {}, expected {}",
+                got, expected
+            ),
         }
     }
 }
 
+impl std::error::Error for EmitterError {}
+
+impl From<bitcoincore_rpc::Error> for EmitterError {
+    fn from(err: bitcoincore_rpc::Error) -> Self {
+        Self::Rpc(err)
+    }
+}
+
 /// Extends [`bitcoincore_rpc::Error`].
 pub trait BitcoindRpcErrorExt {
     /// Returns whether the error is a "not found" error.
     ///
-    /// This is useful since [`Emitter`] emits [`Result<_, bitcoincore_rpc::Error>`]s as
+    /// This is useful since [`Emitter`] emits [`Result<_, EmitterError>`]s as
     /// [`Iterator::Item`].
     fn is_not_found_error(&self) -> bool;
 }
@@ -326,3 +485,12 @@ impl BitcoindRpcErrorExt for bitcoincore_rpc::Error {
         }
     }
 }
+
+impl BitcoindRpcErrorExt for EmitterError {
+    fn is_not_found_error(&self) -> bool {
+        match self {
+            Self::Rpc(err) => err.is_not_found_error(),
+            Self::BlockPruned { .. } | Self::GenesisMismatch { .. } => false,
+        }
+    }
+}
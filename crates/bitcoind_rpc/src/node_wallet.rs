@@ -0,0 +1,242 @@
+//! Let a watch-only wallet of the node find the relevant transactions, see
+//! [`import_wallet_descriptors`] and [`NodeWalletEmitter`].
+//!
+//! For a large wallet, scanning every block is slower than letting `bitcoind` filter the
+//! transactions. The public descriptors of the wallet are imported with `importdescriptors` into a
+//! watch-only wallet of the node, which then reports the relevant transactions with
+//! `listsinceblock`. This needs a node with wallets enabled.
+//!
+//! The wallet RPC methods are called on the wallet of the client, so the client must be connected
+//! to the wallet endpoint of the node, `<url>/wallet/<wallet_name>`.
+
+use crate::{Emitter, EmitterError};
+use bdk_chain::{local_chain::CheckPoint, BlockId};
+use bitcoin::{BlockHash, Transaction, Txid};
+use bitcoincore_rpc::jsonrpc::serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fmt;
+
+/// The code of the RPC error of `loadwallet` for a wallet which doesn't exist.
+const RPC_WALLET_NOT_FOUND: i32 = -18;
+
+/// A descriptor of the wallet to import into the node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportDescriptor {
+    /// The public descriptor, with or without checksum
+    pub descriptor: String,
+    /// The last derivation index to import, if the descriptor is ranged, which should cover the
+    /// revealed script pubkeys and the lookahead of the wallet
+    pub range_end: u32,
+}
+
+/// Import `descriptors` into the watch-only wallet `wallet_name` of the node, creating or loading
+/// the wallet if needed, and rescan the blocks from `start_time`.
+///
+/// `start_time` is a unix timestamp, the node rescans the blocks with a timestamp from two hours
+/// earlier, which blocks until the rescan is done. Import a descriptor again with a larger
+/// `range_end` to watch more of its script pubkeys, the blocks are rescanned from `start_time` for
+/// the new script pubkeys.
+///
+/// `client` must be connected to the endpoint of the wallet, see the [module-level
+/// documentation](self).
+pub fn import_wallet_descriptors<C: bitcoincore_rpc::RpcApi>(
+    client: &C,
+    wallet_name: &str,
+    descriptors: &[ImportDescriptor],
+    start_time: u64,
+) -> Result<(), NodeWalletError> {
+    if !client
+        .list_wallets()?
+        .iter()
+        .any(|name| name == wallet_name)
+    {
+        match client.load_wallet(wallet_name) {
+            Ok(_) => {}
+            Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err)))
+                if err.code == RPC_WALLET_NOT_FOUND =>
+            {
+                client.create_wallet(wallet_name, Some(true), Some(true), None, None)?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let mut requests = Vec::with_capacity(descriptors.len());
+    for import in descriptors {
+        let info = client.get_descriptor_info(&import.descriptor)?;
+        let descriptor = match info.checksum {
+            Some(checksum) => {
+                let descriptor = import.descriptor.split('#').next().unwrap_or_default();
+                format!("{}#{}", descriptor, checksum)
+            }
+            // the normalized descriptor of older nodes has the checksum
+            None => info.descriptor,
+        };
+        let mut request = json!({ "desc": descriptor, "timestamp": start_time });
+        if info.is_range {
+            request["range"] = json!([0, import.range_end]);
+        }
+        requests.push(request);
+    }
+
+    let results = client.call::<Vec<Value>>("importdescriptors", &[Value::Array(requests)])?;
+    for (import, result) in descriptors.iter().zip(results) {
+        if result["success"].as_bool() != Some(true) {
+            return Err(NodeWalletError::ImportFailed {
+                descriptor: import.descriptor.clone(),
+                message: result["error"]["message"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The transactions of the node wallet since the last update, see
+/// [`NodeWalletEmitter::next_update`]
+#[derive(Debug)]
+pub struct NodeWalletUpdate {
+    /// The checkpoint of the chain tip, which connects to the chain we started with and to
+    /// which the anchors of the confirmed transactions connect
+    pub checkpoint: CheckPoint,
+    /// The transactions confirmed in a block of `checkpoint`, alongside the block
+    pub confirmed_txs: Vec<(Transaction, BlockId)>,
+    /// The unconfirmed transactions, alongside the unix timestamps they were received at
+    pub unconfirmed_txs: Vec<(Transaction, u64)>,
+}
+
+/// Emits the transactions which the watch-only wallet of the node finds relevant, see the
+/// [module-level documentation](self).
+///
+/// The checkpoints are emitted from the block headers with an [`Emitter`], so that the anchors of
+/// the confirmed transactions connect to the local chain, without fetching the blocks.
+pub struct NodeWalletEmitter<'c, C> {
+    emitter: Emitter<'c, C>,
+    /// The last block of the previous update, the next update lists the transactions since
+    last_update: Option<BlockHash>,
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> NodeWalletEmitter<'c, C> {
+    /// Construct a new [`NodeWalletEmitter`], see [`Emitter::new`].
+    ///
+    /// `client` must be connected to the endpoint of the wallet, see the [module-level
+    /// documentation](self).
+    pub fn new(client: &'c C, last_cp: CheckPoint, start_height: u32) -> Self {
+        Self {
+            emitter: Emitter::new(client, last_cp, start_height),
+            last_update: None,
+        }
+    }
+
+    /// The inner [`Emitter`] of the headers.
+    pub fn emitter(&self) -> &Emitter<'c, C> {
+        &self.emitter
+    }
+
+    /// Import `descriptors` with [`import_wallet_descriptors`], the next update then emits all the
+    /// transactions of the node wallet again, including those found by the rescan.
+    pub fn import_wallet_descriptors(
+        &mut self,
+        wallet_name: &str,
+        descriptors: &[ImportDescriptor],
+        start_time: u64,
+    ) -> Result<(), NodeWalletError> {
+        import_wallet_descriptors(self.emitter.client, wallet_name, descriptors, start_time)?;
+        self.last_update = None;
+        Ok(())
+    }
+
+    /// Emit the chain tip and the transactions of the node wallet since the last update, or all
+    /// of them for the first update.
+    ///
+    /// A transaction confirmed in a block which is not in the emitted chain, because of a reorg
+    /// since the transactions are listed, is emitted as unconfirmed. It is emitted again with its
+    /// block in the next update.
+    pub fn next_update(&mut self) -> Result<NodeWalletUpdate, EmitterError> {
+        let client = self.emitter.client;
+        let res = client.list_since_block(self.last_update.as_ref(), None, Some(true), None)?;
+        while self.emitter.next_header()?.is_some() {}
+        let checkpoint = self.emitter.core.last_cp.clone();
+
+        let mut confirmed_txs = Vec::new();
+        let mut unconfirmed_txs = Vec::new();
+        let mut is_complete = true;
+        let mut seen = HashSet::<Txid>::new();
+        for tx_res in res.transactions {
+            let info = tx_res.info;
+            // a tx with several relevant outputs is listed once per output
+            if !seen.insert(info.txid) {
+                continue;
+            }
+            let tx = client
+                .get_transaction(&info.txid, Some(true))?
+                .transaction()
+                // the node sends the tx it stores, so it always decodes
+                .map_err(|_| bitcoincore_rpc::Error::UnexpectedStructure)?;
+            let block_id = match (info.blockheight, info.blockhash) {
+                (Some(height), Some(hash)) if info.confirmations > 0 => {
+                    Some(BlockId { height, hash })
+                }
+                _ => None,
+            };
+            match block_id {
+                Some(block_id)
+                    if checkpoint.get(block_id.height).map(|cp| cp.hash())
+                        == Some(block_id.hash) =>
+                {
+                    confirmed_txs.push((tx, block_id))
+                }
+                Some(_) => {
+                    is_complete = false;
+                    unconfirmed_txs.push((tx, info.timereceived));
+                }
+                None => unconfirmed_txs.push((tx, info.timereceived)),
+            }
+        }
+        if is_complete {
+            self.last_update = Some(res.lastblock);
+        }
+
+        Ok(NodeWalletUpdate {
+            checkpoint,
+            confirmed_txs,
+            unconfirmed_txs,
+        })
+    }
+}
+
+/// An error of [`import_wallet_descriptors`]
+#[derive(Debug)]
+pub enum NodeWalletError {
+    /// An RPC call failed
+    Rpc(bitcoincore_rpc::Error),
+    /// The node failed to import the `descriptor`
+    ImportFailed {
+        /// The descriptor which failed to import
+        descriptor: String,
+        /// The error message of the node
+        message: String,
+    },
+}
+
+impl fmt::Display for NodeWalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rpc(err) => write!(f, "rpc error: {}", err),
+            Self::ImportFailed {
+                descriptor,
+                message,
+            } => write!(f, "failed to import {}: {}", descriptor, message),
+        }
+    }
+}
+
+impl std::error::Error for NodeWalletError {}
+
+impl From<bitcoincore_rpc::Error> for NodeWalletError {
+    fn from(err: bitcoincore_rpc::Error) -> Self {
+        Self::Rpc(err)
+    }
+}
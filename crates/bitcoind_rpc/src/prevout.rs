@@ -0,0 +1,50 @@
+//! Look up the outputs spent by transactions with the RPC interface of `bitcoind`, as a
+//! [`PrevoutSource`].
+
+use bdk_chain::collections::BTreeMap;
+use bdk_chain::prevout::PrevoutSource;
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+
+/// A [`PrevoutSource`] fetching the transactions of the outputs with `getrawtransaction`
+///
+/// The node only knows the transactions of its mempool and wallet, unless it runs with
+/// `-txindex`. An output is `None` if its transaction can't be fetched.
+#[derive(Debug)]
+pub struct RpcPrevouts<'c, C> {
+    client: &'c C,
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> RpcPrevouts<'c, C> {
+    /// Look up outputs with `client`
+    pub fn new(client: &'c C) -> Self {
+        Self { client }
+    }
+
+    fn get_tx(&self, txid: &Txid) -> Option<Transaction> {
+        self.client.get_raw_transaction(txid, None).ok()
+    }
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> PrevoutSource for RpcPrevouts<'c, C> {
+    fn get_txout(&self, outpoint: OutPoint) -> Option<TxOut> {
+        self.get_tx(&outpoint.txid)?
+            .output
+            .get(outpoint.vout as usize)
+            .cloned()
+    }
+
+    fn get_txouts(&self, outpoints: &[OutPoint]) -> Vec<Option<TxOut>> {
+        let mut txs = BTreeMap::<Txid, Option<Transaction>>::new();
+        outpoints
+            .iter()
+            .map(|outpoint| {
+                txs.entry(outpoint.txid)
+                    .or_insert_with(|| self.get_tx(&outpoint.txid))
+                    .as_ref()?
+                    .output
+                    .get(outpoint.vout as usize)
+                    .cloned()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,382 @@
+//! Notifications of `bitcoind` over ZMQ, to emit new blocks and transactions without polling.
+//!
+//! `bitcoind` publishes the hashes of the blocks connected to its best chain and the new
+//! transactions on the endpoints of its `-zmqpubhashblock` and `-zmqpubrawtx` options.
+//! [`ZmqSubscriber`] subscribes to them with the [`zeromq`] implementation of ZeroMQ, and
+//! [`NotifiedEmitter`] waits for them before running the logic of [`Emitter`].
+//!
+//! The notifications only wake the emitter up, the RPC interface stays the source of truth. A
+//! notification which is missed or received late only delays an emission.
+
+use crate::{BlockEvent, Emitter, EmitterError, MempoolEvent};
+use bitcoin::{block::Header, consensus::deserialize, hashes::Hash, Block, BlockHash, Transaction};
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use zeromq::{Socket, SocketRecv, SubSocket, ZmqError, ZmqMessage};
+
+/// How long to wait for an endpoint to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The topics published by `bitcoind` which are subscribed to
+const TOPICS: [&str; 2] = ["hashblock", "rawtx"];
+
+/// A notification of `bitcoind`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// A block is connected to the best chain, from `-zmqpubhashblock`
+    Block(BlockHash),
+    /// A transaction entered the mempool or is in a connected block, from `-zmqpubrawtx`
+    Tx(Transaction),
+}
+
+/// An error of a [`NotifiedEmitter`]
+#[derive(Debug)]
+pub enum Error {
+    /// The emitter failed
+    Emitter(EmitterError),
+    /// A ZMQ subscription failed, the emitter can still be polled
+    Zmq(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Emitter(err) => write!(f, "emitter error: {}", err),
+            Self::Zmq(err) => write!(f, "zmq error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<EmitterError> for Error {
+    fn from(err: EmitterError) -> Self {
+        Self::Emitter(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Zmq(err)
+    }
+}
+
+/// A subscriber of the ZMQ notifications of `bitcoind`
+///
+/// Each endpoint is read by a thread, which stops when the subscriber is dropped.
+#[derive(Debug)]
+pub struct ZmqSubscriber {
+    /// Dropped to stop the threads
+    _shutdown: Vec<oneshot::Sender<()>>,
+    notifications: Receiver<io::Result<Notification>>,
+}
+
+impl ZmqSubscriber {
+    /// Subscribe to the blocks and transactions published on `endpoints`, such as
+    /// `tcp://127.0.0.1:28332`.
+    ///
+    /// `bitcoind` may publish both topics on the same endpoint, or each on its own. Fails if an
+    /// endpoint doesn't accept the connection within 10 seconds.
+    pub fn connect<S: AsRef<str>>(endpoints: impl IntoIterator<Item = S>) -> io::Result<Self> {
+        let (sender, notifications) = mpsc::channel();
+        let mut shutdown = Vec::new();
+        for endpoint in endpoints {
+            shutdown.push(spawn_subscription(
+                endpoint.as_ref().to_string(),
+                sender.clone(),
+            )?);
+        }
+        Ok(Self {
+            _shutdown: shutdown,
+            notifications,
+        })
+    }
+
+    /// Block until a notification is received, or `timeout` elapses and `Ok(None)` is returned.
+    ///
+    /// An error is returned once for each endpoint whose subscription fails.
+    pub fn wait(&self, timeout: Duration) -> io::Result<Option<Notification>> {
+        match self.notifications.recv_timeout(timeout) {
+            Ok(notification) => notification.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "all the zmq subscriptions failed",
+            )),
+        }
+    }
+}
+
+/// An [`Emitter`] which waits for the ZMQ notifications of `bitcoind` to emit, instead of polling
+///
+/// The state of the emitter, such as its last checkpoint and start height, is kept: the emitter
+/// can be polled directly with [`emitter_mut`](Self::emitter_mut), or taken back with
+/// [`into_emitter`](Self::into_emitter).
+pub struct NotifiedEmitter<'c, C> {
+    emitter: Emitter<'c, C>,
+    subscriber: ZmqSubscriber,
+}
+
+impl<'c, C: bitcoincore_rpc::RpcApi> NotifiedEmitter<'c, C> {
+    /// Wrap `emitter`, subscribing to the ZMQ `endpoints` of its `bitcoind`, see
+    /// [`ZmqSubscriber::connect`].
+    pub fn new<S: AsRef<str>>(
+        emitter: Emitter<'c, C>,
+        endpoints: impl IntoIterator<Item = S>,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            emitter,
+            subscriber: ZmqSubscriber::connect(endpoints)?,
+        })
+    }
+
+    /// The wrapped [`Emitter`]
+    pub fn emitter(&self) -> &Emitter<'c, C> {
+        &self.emitter
+    }
+
+    /// The wrapped [`Emitter`], to poll it without waiting
+    pub fn emitter_mut(&mut self) -> &mut Emitter<'c, C> {
+        &mut self.emitter
+    }
+
+    /// Unwrap the [`Emitter`], dropping the ZMQ subscriptions
+    pub fn into_emitter(self) -> Emitter<'c, C> {
+        self.emitter
+    }
+
+    /// Block until `bitcoind` notifies a block or a transaction, or `timeout` elapses and
+    /// `Ok(None)` is returned.
+    pub fn wait_for_activity(&mut self, timeout: Duration) -> io::Result<Option<Notification>> {
+        self.subscriber.wait(timeout)
+    }
+
+    /// Emit the next block height and header, waiting up to `timeout` for a new block if the
+    /// emitter is at the chain tip, see [`Emitter::next_header`].
+    pub fn next_header(&mut self, timeout: Duration) -> Result<Option<BlockEvent<Header>>, Error> {
+        self.wait_until(timeout, Emitter::next_header)
+    }
+
+    /// Emit the next block height and block, waiting up to `timeout` for a new block if the
+    /// emitter is at the chain tip, see [`Emitter::next_block`].
+    pub fn next_block(&mut self, timeout: Duration) -> Result<Option<BlockEvent<Block>>, Error> {
+        self.wait_until(timeout, Emitter::next_block)
+    }
+
+    /// Wait up to `timeout` for a notification, then emit the changes of the mempool, see
+    /// [`Emitter::mempool`].
+    ///
+    /// A notification received since the last call returns right away.
+    pub fn mempool(&mut self, timeout: Duration) -> Result<MempoolEvent, Error> {
+        self.wait_for_activity(timeout)?;
+        Ok(self.emitter.mempool()?)
+    }
+
+    /// Call `emit` until it emits, waiting for a notification between two calls.
+    fn wait_until<T>(
+        &mut self,
+        timeout: Duration,
+        mut emit: impl FnMut(&mut Emitter<'c, C>) -> Result<Option<T>, EmitterError>,
+    ) -> Result<Option<T>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(item) = emit(&mut self.emitter)? {
+                return Ok(Some(item));
+            }
+            let now = Instant::now();
+            if now >= deadline || self.wait_for_activity(deadline - now)?.is_none() {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Subscribe to the [`TOPICS`] of the publisher at `endpoint` in a new thread, which sends the
+/// notifications to `sender` until the returned sender is dropped or the subscription fails.
+fn spawn_subscription(
+    endpoint: String,
+    sender: Sender<io::Result<Notification>>,
+) -> io::Result<oneshot::Sender<()>> {
+    let (connected_sender, connected) = mpsc::channel();
+    let (shutdown_sender, shutdown) = oneshot::channel::<()>();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    std::thread::spawn(move || {
+        runtime.block_on(async move {
+            let mut socket = match subscribe(&endpoint).await {
+                Ok(socket) => socket,
+                Err(err) => {
+                    let _ = connected_sender.send(Err(err));
+                    return;
+                }
+            };
+            let _ = connected_sender.send(Ok(()));
+            // the reader is dropped with the runtime once `shutdown` resolves
+            tokio::spawn(async move {
+                loop {
+                    let notification = match socket.recv().await.map_err(zmq_error) {
+                        Ok(message) => match parse(&message) {
+                            Ok(Some(notification)) => Ok(notification),
+                            Ok(None) => continue,
+                            Err(err) => Err(err),
+                        },
+                        Err(err) => Err(err),
+                    };
+                    let is_err = notification.is_err();
+                    if sender.send(notification).is_err() || is_err {
+                        return;
+                    }
+                }
+            });
+            let _ = shutdown.await;
+        })
+    });
+    connected.recv().map_err(|_| {
+        io::Error::new(io::ErrorKind::Other, "the zmq subscription thread panicked")
+    })??;
+    Ok(shutdown_sender)
+}
+
+/// Connect to the publisher at `endpoint` and subscribe to the [`TOPICS`].
+async fn subscribe(endpoint: &str) -> io::Result<SubSocket> {
+    let mut socket = SubSocket::new();
+    // a refused connection is retried forever
+    match tokio::time::timeout(CONNECT_TIMEOUT, socket.connect(endpoint)).await {
+        Ok(result) => result.map_err(zmq_error)?,
+        Err(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} didn't accept the connection", endpoint),
+            ))
+        }
+    }
+    for topic in TOPICS {
+        socket.subscribe(topic).await.map_err(zmq_error)?;
+    }
+    Ok(socket)
+}
+
+/// Parse a message of `bitcoind`, made of the topic, the body and a sequence number. Returns
+/// `None` for the topics which are not subscribed to.
+fn parse(message: &ZmqMessage) -> io::Result<Option<Notification>> {
+    let (topic, body) = match (message.get(0), message.get(1)) {
+        (Some(topic), Some(body)) => (topic.as_ref(), body.as_ref()),
+        _ => return Ok(None),
+    };
+    match topic {
+        b"hashblock" => {
+            // the hash is in the byte order of the RPC interface
+            let mut hash = <[u8; 32]>::try_from(body)
+                .map_err(|_| invalid_data("the block hash must be 32 bytes"))?;
+            hash.reverse();
+            Ok(Some(Notification::Block(BlockHash::from_byte_array(hash))))
+        }
+        b"rawtx" => {
+            let tx = deserialize(body).map_err(|err| invalid_data(&err.to_string()))?;
+            Ok(Some(Notification::Tx(tx)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn zmq_error(err: ZmqError) -> io::Error {
+    match err {
+        ZmqError::Network(err) => err,
+        ZmqError::Endpoint(err) => io::Error::new(io::ErrorKind::InvalidInput, err.to_string()),
+        err => io::Error::new(io::ErrorKind::Other, err.to_string()),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zeromq::{PubSocket, SocketSend};
+
+    /// Bind a `PUB` socket, and publish `messages` every 50ms for 10 seconds: a subscriber only
+    /// receives the messages published after its subscription.
+    fn spawn_publisher(messages: Vec<ZmqMessage>) -> io::Result<String> {
+        let (endpoint_sender, endpoint) = mpsc::channel();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        std::thread::spawn(move || {
+            runtime.block_on(async move {
+                let mut socket = PubSocket::new();
+                let endpoint = socket.bind("tcp://127.0.0.1:0").await.expect("must bind");
+                endpoint_sender.send(endpoint.to_string()).unwrap();
+                for _ in 0..200 {
+                    for message in &messages {
+                        socket.send(message.clone()).await.expect("must publish");
+                    }
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+        });
+        Ok(endpoint.recv().expect("must bind"))
+    }
+
+    fn message(frames: Vec<Vec<u8>>) -> ZmqMessage {
+        let mut frames = frames.into_iter().map(Into::into);
+        let mut message = ZmqMessage::from(frames.next().expect("must have a frame"));
+        frames.for_each(|frame| message.push_back(frame));
+        message
+    }
+
+    #[test]
+    fn test_subscriber_receives_notifications() -> io::Result<()> {
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![bitcoin::TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: bitcoin::ScriptBuf::from_bytes(vec![0x6a; 300]),
+            }],
+        };
+        let hash = BlockHash::from_byte_array([7; 32]);
+        let mut rpc_hash = hash.to_byte_array();
+        rpc_hash.reverse();
+        let seq = 0_u32.to_le_bytes().to_vec();
+        let endpoint = spawn_publisher(vec![
+            message(vec![b"sequence".to_vec(), vec![0; 33], seq.clone()]),
+            message(vec![b"hashblock".to_vec(), rpc_hash.to_vec(), seq.clone()]),
+            message(vec![
+                b"rawtx".to_vec(),
+                bitcoin::consensus::serialize(&tx),
+                seq,
+            ]),
+        ])?;
+
+        let subscriber = ZmqSubscriber::connect([endpoint])?;
+        let mut notifications = Vec::new();
+        while notifications.len() < 2 {
+            let notification = subscriber
+                .wait(Duration::from_secs(5))?
+                .expect("must be notified");
+            if !notifications.contains(&notification) {
+                notifications.push(notification);
+            }
+        }
+        assert!(notifications.contains(&Notification::Block(hash)));
+        assert!(notifications.contains(&Notification::Tx(tx)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_subscriber_rejects_invalid_endpoints() {
+        assert_eq!(
+            ZmqSubscriber::connect(["udp://127.0.0.1:28332"])
+                .expect_err("must reject udp")
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
@@ -0,0 +1,120 @@
+#![cfg(feature = "async")]
+
+use std::collections::BTreeSet;
+
+use bdk_bitcoind_rpc::async_emitter::{AsyncClient, AsyncEmitter};
+use bdk_bitcoind_rpc::Emitter;
+use bdk_chain::{
+    bitcoin::{Address, Amount, Txid},
+    local_chain::LocalChain,
+    BlockId,
+};
+use bdk_testenv::{anyhow, TestEnv};
+use bitcoincore_rpc::{Auth, RpcApi};
+
+fn async_client(env: &TestEnv) -> anyhow::Result<AsyncClient> {
+    Ok(AsyncClient::new(
+        &env.bitcoind.rpc_url(),
+        Auth::CookieFile(env.bitcoind.params.cookie_file.clone()),
+    )?)
+}
+
+/// The [`AsyncEmitter`] emits the same blocks as the [`Emitter`], before and after a reorg.
+#[tokio::test]
+pub async fn test_async_emitter_matches_emitter() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let client = async_client(&env)?;
+    let (mut local_chain, _) = LocalChain::from_genesis_hash(env.rpc_client().get_block_hash(0)?);
+    let mut emitter = Emitter::new(env.rpc_client(), local_chain.tip(), 0);
+    let mut async_emitter = AsyncEmitter::new(&client, local_chain.tip(), 0);
+
+    env.mine_blocks(101, None)?;
+    for _ in 0..2 {
+        let mut exp_blocks = Vec::new();
+        while let Some(emission) = emitter.next_block()? {
+            exp_blocks.push(emission.checkpoint.block_id());
+        }
+        let mut blocks = Vec::new();
+        while let Some(emission) = async_emitter.next_block().await? {
+            blocks.push(emission.checkpoint.block_id());
+            local_chain.apply_update(emission.checkpoint)?;
+        }
+        assert_eq!(blocks, exp_blocks, "emitted blocks are unexpected");
+        assert_eq!(
+            local_chain.tip().block_id(),
+            BlockId {
+                height: env.rpc_client().get_block_count()? as u32,
+                hash: env.rpc_client().get_best_block_hash()?,
+            },
+            "local chain must be at the tip of the node",
+        );
+
+        env.reorg(6)?;
+    }
+    Ok(())
+}
+
+/// The [`AsyncEmitter`] emits the mempool transactions, and does not emit them again.
+#[tokio::test]
+pub async fn test_async_emitter_mempool() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let client = async_client(&env)?;
+    let (local_chain, _) = LocalChain::from_genesis_hash(env.rpc_client().get_block_hash(0)?);
+    let mut emitter = AsyncEmitter::new(&client, local_chain.tip(), 0);
+
+    env.mine_blocks(101, None)?;
+    while emitter.next_block().await?.is_some() {}
+
+    let addr = env
+        .rpc_client()
+        .get_new_address(None, None)?
+        .assume_checked();
+    let exp_txids = send_txs(&env, &addr, 3)?;
+
+    let event = emitter.mempool().await?;
+    assert_eq!(
+        event
+            .new_txs
+            .iter()
+            .map(|(tx, _)| tx.compute_txid())
+            .collect::<BTreeSet<_>>(),
+        exp_txids,
+    );
+    assert!(event.evicted_txids.is_empty());
+    assert!(emitter.mempool().await?.new_txs.is_empty());
+
+    // once confirmed, the transactions are not evicted
+    env.mine_blocks(1, None)?;
+    let emission = emitter
+        .next_block()
+        .await?
+        .expect("must emit the new block");
+    let confirmed = emission
+        .block
+        .txdata
+        .iter()
+        .map(|tx| tx.compute_txid())
+        .collect::<BTreeSet<_>>();
+    assert!(confirmed.is_superset(&exp_txids));
+    let event = emitter.mempool().await?;
+    assert!(event.new_txs.is_empty());
+    assert!(event.evicted_txids.is_empty());
+    Ok(())
+}
+
+fn send_txs(env: &TestEnv, addr: &Address, count: usize) -> anyhow::Result<BTreeSet<Txid>> {
+    let mut txids = BTreeSet::new();
+    for _ in 0..count {
+        txids.insert(env.rpc_client().send_to_address(
+            addr,
+            Amount::from_sat(10_000),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?);
+    }
+    Ok(txids)
+}
@@ -0,0 +1,290 @@
+use bdk_bitcoind_rpc::BitcoindRpcBroadcastExt;
+use bdk_chain::{
+    bitcoin::{
+        absolute, transaction, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+        TxOut, Witness,
+    },
+    spk_client::{BroadcastError, BroadcastRetryError, BroadcastRetryPolicy},
+};
+use bdk_testenv::{anyhow, TestEnv};
+use bitcoincore_rpc::{
+    bitcoincore_rpc_json::{ListUnspentResultEntry, SignRawTransactionInput},
+    RpcApi,
+};
+
+/// Spend `utxo` to `outputs`, signed by the wallet of the node.
+fn spend(
+    env: &TestEnv,
+    utxo: &ListUnspentResultEntry,
+    outputs: Vec<TxOut>,
+) -> anyhow::Result<Transaction> {
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(utxo.txid, utxo.vout),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+            witness: Witness::new(),
+        }],
+        output: outputs,
+    };
+    let signed = env
+        .rpc_client()
+        .sign_raw_transaction_with_wallet(&tx, None, None)?;
+    assert!(signed.complete, "the wallet must sign the tx");
+    Ok(signed.transaction()?)
+}
+
+fn wallet_spk(env: &TestEnv) -> anyhow::Result<ScriptBuf> {
+    Ok(env
+        .rpc_client()
+        .get_new_address(None, None)?
+        .assume_checked()
+        .script_pubkey())
+}
+
+fn utxos(env: &TestEnv) -> anyhow::Result<Vec<ListUnspentResultEntry>> {
+    Ok(env
+        .rpc_client()
+        .list_unspent(Some(1), None, None, None, None)?)
+}
+
+/// Ensure that the rejected transactions are reported with typed reasons, and not broadcast.
+#[test]
+fn broadcast_all_reports_typed_rejections() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(110, None)?;
+    let utxos = utxos(&env)?;
+    assert!(utxos.len() >= 5, "must have mature coinbase outputs");
+    let client = env.rpc_client();
+
+    // no fee
+    let no_fee = spend(
+        &env,
+        &utxos[0],
+        vec![TxOut {
+            value: utxos[0].amount,
+            script_pubkey: wallet_spk(&env)?,
+        }],
+    )?;
+    // dust output
+    let dust = spend(
+        &env,
+        &utxos[1],
+        vec![
+            TxOut {
+                value: Amount::from_sat(1),
+                script_pubkey: wallet_spk(&env)?,
+            },
+            TxOut {
+                value: utxos[1].amount - Amount::from_sat(10_000),
+                script_pubkey: wallet_spk(&env)?,
+            },
+        ],
+    )?;
+    // pays most of the coinbase output as fee
+    let high_fee = spend(
+        &env,
+        &utxos[2],
+        vec![TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: wallet_spk(&env)?,
+        }],
+    )?;
+    // conflicts with a non-replaceable mempool tx
+    let original = spend(
+        &env,
+        &utxos[3],
+        vec![TxOut {
+            value: utxos[3].amount - Amount::from_sat(10_000),
+            script_pubkey: wallet_spk(&env)?,
+        }],
+    )?;
+    client.send_raw_transaction(&original)?;
+    let conflict = spend(
+        &env,
+        &utxos[3],
+        vec![TxOut {
+            value: utxos[3].amount - Amount::from_sat(20_000),
+            script_pubkey: wallet_spk(&env)?,
+        }],
+    )?;
+
+    let txs = [no_fee, dust, high_fee.clone(), conflict];
+    let results = client.broadcast_all(&txs, None)?;
+    assert_eq!(results.len(), txs.len());
+    assert!(
+        matches!(results[0], Err(BroadcastError::MinRelayFeeNotMet)),
+        "{:?}",
+        results[0]
+    );
+    assert!(
+        matches!(&results[1], Err(BroadcastError::NonStandard(reason)) if reason == "dust"),
+        "{:?}",
+        results[1]
+    );
+    assert!(
+        matches!(results[2], Err(BroadcastError::MaxFeeRateExceeded)),
+        "{:?}",
+        results[2]
+    );
+    assert!(
+        matches!(results[3], Err(BroadcastError::MempoolConflict)),
+        "{:?}",
+        results[3]
+    );
+    let mempool = client.get_raw_mempool()?;
+    assert_eq!(mempool, vec![original.compute_txid()]);
+
+    // a max fee rate of zero accepts any fee rate
+    let results = client.broadcast_all(core::slice::from_ref(&high_fee), Some(FeeRate::ZERO))?;
+    assert_eq!(results[0].as_ref().ok(), Some(&high_fee.compute_txid()));
+    assert!(client.get_raw_mempool()?.contains(&high_fee.compute_txid()));
+
+    Ok(())
+}
+
+/// Ensure that a package of a parent and its child is checked and broadcast together, and that
+/// a transaction already in the mempool is reported as broadcast.
+#[test]
+fn broadcast_all_submits_package() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(110, None)?;
+    let utxos = utxos(&env)?;
+    let client = env.rpc_client();
+
+    let parent = spend(
+        &env,
+        &utxos[0],
+        vec![TxOut {
+            value: utxos[0].amount - Amount::from_sat(10_000),
+            script_pubkey: wallet_spk(&env)?,
+        }],
+    )?;
+    // the parent is not in the mempool, so the wallet is told about the output it spends
+    let child = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(parent.compute_txid(), 0),
+            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: parent.output[0].value - Amount::from_sat(10_000),
+            script_pubkey: wallet_spk(&env)?,
+        }],
+        ..parent.clone()
+    };
+    let prevout = SignRawTransactionInput {
+        txid: parent.compute_txid(),
+        vout: 0,
+        script_pub_key: parent.output[0].script_pubkey.clone(),
+        redeem_script: None,
+        amount: Some(parent.output[0].value),
+    };
+    let child = client
+        .sign_raw_transaction_with_wallet(&child, Some(&[prevout]), None)?
+        .transaction()?;
+
+    let txs = [parent.clone(), child.clone()];
+    let results = client.test_broadcast(&txs, None)?;
+    assert!(results.iter().all(|result| result.is_ok()), "{:?}", results);
+    assert!(client.get_raw_mempool()?.is_empty(), "must not broadcast");
+
+    let results = client.broadcast_all(&txs, None)?;
+    assert_eq!(
+        results.into_iter().collect::<Result<Vec<_>, _>>()?,
+        vec![parent.compute_txid(), child.compute_txid()]
+    );
+    let mut mempool = client.get_raw_mempool()?;
+    mempool.sort();
+    let mut exp_mempool = vec![parent.compute_txid(), child.compute_txid()];
+    exp_mempool.sort();
+    assert_eq!(mempool, exp_mempool);
+
+    // already broadcast
+    let results = client.broadcast_all(core::slice::from_ref(&parent), None)?;
+    assert_eq!(results[0].as_ref().ok(), Some(&parent.compute_txid()));
+
+    Ok(())
+}
+
+/// Ensure that `submitpackage` accepts a parent and its child, and reports the child of a
+/// rejected parent.
+#[test]
+fn submit_package_reports_each_tx() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(110, None)?;
+    let client = env.rpc_client();
+    let address = client.get_new_address(None, None)?.assume_checked();
+
+    let (parent, child) = env.create_unbroadcast_package(
+        &address,
+        Amount::from_sat(100_000),
+        Amount::from_sat(2_000),
+    )?;
+    let results = client.submit_package(&[parent.clone(), child.clone()], None)?;
+    assert_eq!(
+        results.into_iter().collect::<Result<Vec<_>, _>>()?,
+        vec![parent.compute_txid(), child.compute_txid()]
+    );
+    let mut mempool = client.get_raw_mempool()?;
+    mempool.sort();
+    let mut exp_mempool = vec![parent.compute_txid(), child.compute_txid()];
+    exp_mempool.sort();
+    assert_eq!(mempool, exp_mempool);
+
+    // the child pays more than its value: the package is rejected
+    let (parent, child) = env.create_unbroadcast_package(
+        &address,
+        Amount::from_sat(100_000),
+        Amount::from_sat(2_000),
+    )?;
+    let mut invalid_child = child.clone();
+    invalid_child.output[0].value = Amount::from_sat(200_000);
+    let results = client.submit_package(&[parent.clone(), invalid_child], None)?;
+    assert!(results[1].is_err(), "{:?}", results);
+    assert!(!client.get_raw_mempool()?.contains(&child.compute_txid()));
+
+    Ok(())
+}
+
+/// Ensure that a broadcast with retries is rejected without retrying, and that a transaction
+/// already in the mempool is reported as broadcast.
+#[test]
+fn broadcast_with_retry() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(110, None)?;
+    let utxos = utxos(&env)?;
+    let client = env.rpc_client();
+    let policy = BroadcastRetryPolicy::default();
+
+    let no_fee = spend(
+        &env,
+        &utxos[0],
+        vec![TxOut {
+            value: utxos[0].amount,
+            script_pubkey: wallet_spk(&env)?,
+        }],
+    )?;
+    assert!(matches!(
+        client.broadcast_with_retry(&no_fee, None, &policy),
+        Err(BroadcastRetryError::Rejected(
+            BroadcastError::MinRelayFeeNotMet
+        ))
+    ));
+
+    let tx = spend(
+        &env,
+        &utxos[1],
+        vec![TxOut {
+            value: utxos[1].amount - Amount::from_sat(10_000),
+            script_pubkey: wallet_spk(&env)?,
+        }],
+    )?;
+    let txid = tx.compute_txid();
+    assert_eq!(client.broadcast_with_retry(&tx, None, &policy)?, txid);
+    assert_eq!(client.broadcast_with_retry(&tx, None, &policy)?, txid);
+    assert!(client.get_raw_mempool()?.contains(&txid));
+    Ok(())
+}
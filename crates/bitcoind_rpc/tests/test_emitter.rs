@@ -1,6 +1,6 @@
 use std::collections::{BTreeMap, BTreeSet};
 
-use bdk_bitcoind_rpc::Emitter;
+use bdk_bitcoind_rpc::{Emitter, EmitterError, EmitterState, MempoolEvent};
 use bdk_chain::{
     bitcoin::{Address, Amount, Txid},
     keychain::Balance,
@@ -9,7 +9,7 @@ use bdk_chain::{
 };
 use bdk_testenv::{anyhow, TestEnv};
 use bitcoin::{hashes::Hash, Block, OutPoint, ScriptBuf, WScriptHash};
-use bitcoincore_rpc::RpcApi;
+use bitcoincore_rpc::{jsonrpc::serde_json::json, RpcApi};
 
 /// Ensure that blocks are emitted in order even after reorg.
 ///
@@ -191,7 +191,7 @@ fn test_into_tx_graph() -> anyhow::Result<()> {
         assert!(emitter.next_block()?.is_none());
 
         let mempool_txs = emitter.mempool()?;
-        let indexed_additions = indexed_tx_graph.batch_insert_unconfirmed(mempool_txs);
+        let indexed_additions = indexed_tx_graph.batch_insert_unconfirmed(mempool_txs.new_txs);
         assert_eq!(
             indexed_additions
                 .graph
@@ -403,6 +403,80 @@ fn tx_can_become_unconfirmed_after_reorg() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Ensure that a tx reorged out in favor of a double spend of it no longer counts in the balance.
+///
+/// 1. Mine 101 blocks, then confirm a tx sending to a tracked address.
+/// 2. Replace the block confirming the tx with a block confirming a double spend of it, which
+///    sends to an address that isn't tracked.
+/// 3. Check the balance after syncing the emitter each time.
+#[test]
+fn tx_is_replaced_by_double_spend_after_reorg() -> anyhow::Result<()> {
+    const SEND_AMOUNT: Amount = Amount::from_sat(10_000);
+
+    let env = TestEnv::new()?;
+    let mut emitter = Emitter::new(
+        env.rpc_client(),
+        CheckPoint::new(BlockId {
+            height: 0,
+            hash: env.rpc_client().get_block_hash(0)?,
+        }),
+        0,
+    );
+
+    // setup addresses
+    let spk_to_track = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+    let addr_to_track = Address::from_script(&spk_to_track, bitcoin::Network::Regtest)?;
+    let addr_double_spend = env
+        .rpc_client()
+        .get_new_address(None, None)?
+        .assume_checked();
+
+    // setup receiver
+    let (mut recv_chain, _) = LocalChain::from_genesis_hash(env.rpc_client().get_block_hash(0)?);
+    let mut recv_graph = IndexedTxGraph::<BlockId, _>::new({
+        let mut recv_index = SpkTxOutIndex::default();
+        recv_index.insert_spk((), spk_to_track.clone());
+        recv_index
+    });
+
+    // confirm a tx sending to the tracked address
+    env.mine_blocks(101, None)?;
+    let txid = env.send(&addr_to_track, SEND_AMOUNT)?;
+    env.mine_blocks(1, None)?;
+    sync_from_emitter(&mut recv_chain, &mut recv_graph, &mut emitter)?;
+    assert_eq!(
+        get_balance(&recv_chain, &recv_graph)?,
+        Balance {
+            confirmed: SEND_AMOUNT,
+            ..Balance::default()
+        },
+        "initial balance must be correct",
+    );
+
+    // reorg the tx out, confirming a double spend of it instead
+    env.invalidate_blocks(1)?;
+    let double_spend_txid = env.double_spend(txid, &addr_double_spend)?;
+    let block_hash = env.mine_blocks(1, None)?[0];
+    sync_from_emitter(&mut recv_chain, &mut recv_graph, &mut emitter)?;
+    assert_eq!(recv_chain.tip().hash(), block_hash);
+    assert_eq!(
+        get_balance(&recv_chain, &recv_graph)?,
+        Balance::default(),
+        "the double spent tx must not count in the balance",
+    );
+    let chain_tip = recv_chain.tip().block_id();
+    assert!(recv_graph
+        .graph()
+        .get_chain_position(&recv_chain, chain_tip, double_spend_txid)
+        .map_or(false, |position| position.is_confirmed()));
+    assert!(recv_graph
+        .graph()
+        .get_chain_position(&recv_chain, chain_tip, txid)
+        .is_none());
+
+    Ok(())
+}
+
 /// Ensure avoid-re-emission-logic is sound when [`Emitter`] is synced to tip.
 ///
 /// The receiver (bdk_chain structures) is synced to the chain tip, and there is txs in the mempool.
@@ -439,6 +513,7 @@ fn mempool_avoids_re_emission() -> anyhow::Result<()> {
     // the first emission should include all transactions
     let emitted_txids = emitter
         .mempool()?
+        .new_txs
         .into_iter()
         .map(|(tx, _)| tx.compute_txid())
         .collect::<BTreeSet<Txid>>();
@@ -449,7 +524,7 @@ fn mempool_avoids_re_emission() -> anyhow::Result<()> {
 
     // second emission should be empty
     assert!(
-        emitter.mempool()?.is_empty(),
+        emitter.mempool()?.new_txs.is_empty(),
         "second emission should be empty"
     );
 
@@ -459,7 +534,7 @@ fn mempool_avoids_re_emission() -> anyhow::Result<()> {
     }
     while emitter.next_header()?.is_some() {}
     assert!(
-        emitter.mempool()?.is_empty(),
+        emitter.mempool()?.new_txs.is_empty(),
         "third emission, after chain tip is extended, should also be empty"
     );
 
@@ -508,6 +583,7 @@ fn mempool_re_emits_if_tx_introduction_height_not_reached() -> anyhow::Result<()
     assert_eq!(
         emitter
             .mempool()?
+            .new_txs
             .into_iter()
             .map(|(tx, _)| tx.compute_txid())
             .collect::<BTreeSet<_>>(),
@@ -517,6 +593,7 @@ fn mempool_re_emits_if_tx_introduction_height_not_reached() -> anyhow::Result<()
     assert_eq!(
         emitter
             .mempool()?
+            .new_txs
             .into_iter()
             .map(|(tx, _)| tx.compute_txid())
             .collect::<BTreeSet<_>>(),
@@ -537,6 +614,7 @@ fn mempool_re_emits_if_tx_introduction_height_not_reached() -> anyhow::Result<()
                 .collect::<BTreeSet<_>>();
             let emitted_txids = emitter
                 .mempool()?
+                .new_txs
                 .into_iter()
                 .map(|(tx, _)| tx.compute_txid())
                 .collect::<BTreeSet<_>>();
@@ -595,6 +673,7 @@ fn mempool_during_reorg() -> anyhow::Result<()> {
     assert_eq!(
         emitter
             .mempool()?
+            .new_txs
             .into_iter()
             .map(|(tx, _)| tx.compute_txid())
             .collect::<BTreeSet<_>>(),
@@ -632,6 +711,7 @@ fn mempool_during_reorg() -> anyhow::Result<()> {
             // include mempool txs introduced at reorg height or greater
             let mempool = emitter
                 .mempool()?
+                .new_txs
                 .into_iter()
                 .map(|(tx, _)| tx.compute_txid())
                 .collect::<BTreeSet<_>>();
@@ -647,6 +727,7 @@ fn mempool_during_reorg() -> anyhow::Result<()> {
 
             let mempool = emitter
                 .mempool()?
+                .new_txs
                 .into_iter()
                 .map(|(tx, _)| tx.compute_txid())
                 .collect::<BTreeSet<_>>();
@@ -735,3 +816,529 @@ fn no_agreement_point() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Spend the output `vout` of `prev_tx` to `address` with a fee of `fee`, signed by the wallet of
+/// `bitcoind` but not broadcast. The spending tx signals replaceability.
+fn spend(
+    env: &TestEnv,
+    prev_tx: &bitcoin::Transaction,
+    vout: u32,
+    address: &Address,
+    fee: Amount,
+) -> anyhow::Result<bitcoin::Transaction> {
+    use bitcoin::{absolute, transaction, Sequence, TxIn, TxOut};
+    use bitcoincore_rpc::bitcoincore_rpc_json::SignRawTransactionInput;
+
+    let prevout = &prev_tx.output[vout as usize];
+    let tx = bitcoin::Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(prev_tx.compute_txid(), vout),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: prevout.value - fee,
+            script_pubkey: address.script_pubkey(),
+        }],
+    };
+    let input = SignRawTransactionInput {
+        txid: prev_tx.compute_txid(),
+        vout,
+        script_pub_key: prevout.script_pubkey.clone(),
+        redeem_script: None,
+        amount: Some(prevout.value),
+    };
+    Ok(env
+        .rpc_client()
+        .sign_raw_transaction_with_wallet(&tx, Some(&[input]), None)?
+        .transaction()?)
+}
+
+/// Ensure that `mempool` only emits the changes of the mempool, with the new txs ordered parents
+/// before children and the txs which left the mempool without confirming reported as evicted.
+///
+/// 1. Broadcast a chain of 3 txs: `a`, its child `b` and its grandchild `c`.
+/// 2. Replace `b` with `b2`, which evicts `b` and `c`.
+/// 3. Mine a block confirming `a` and `b2`, which are not evicted.
+#[test]
+fn mempool_emits_deltas_and_evictions() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let mut emitter = Emitter::new(
+        env.rpc_client(),
+        CheckPoint::new(BlockId {
+            height: 0,
+            hash: env.rpc_client().get_block_hash(0)?,
+        }),
+        0,
+    );
+    let addr = env
+        .rpc_client()
+        .get_new_address(None, None)?
+        .assume_checked();
+    env.mine_blocks(101, Some(addr.clone()))?;
+    while emitter.next_header()?.is_some() {}
+
+    let (tx_a, _) =
+        env.create_unbroadcast_package(&addr, Amount::from_sat(100_000), Amount::ZERO)?;
+    let vout_a = tx_a
+        .output
+        .iter()
+        .position(|txout| txout.script_pubkey == addr.script_pubkey())
+        .expect("must pay to the address") as u32;
+    let tx_b = spend(&env, &tx_a, vout_a, &addr, Amount::from_sat(1_000))?;
+    let tx_c = spend(&env, &tx_b, 0, &addr, Amount::from_sat(1_000))?;
+    let tx_b2 = spend(&env, &tx_a, vout_a, &addr, Amount::from_sat(10_000))?;
+    for tx in [&tx_a, &tx_b, &tx_c] {
+        env.rpc_client().send_raw_transaction(tx)?;
+    }
+
+    // the chain is emitted parents before children
+    let event = emitter.mempool()?;
+    assert_eq!(
+        event
+            .new_txs
+            .iter()
+            .map(|(tx, _)| tx.compute_txid())
+            .collect::<Vec<_>>(),
+        [&tx_a, &tx_b, &tx_c].map(|tx| tx.compute_txid()).to_vec(),
+    );
+    assert!(event.evicted_txids.is_empty());
+    let event = emitter.mempool()?;
+    assert!(event.new_txs.is_empty() && event.evicted_txids.is_empty());
+
+    // the replacement evicts `b` and its child `c`
+    env.rpc_client().send_raw_transaction(&tx_b2)?;
+    let event = emitter.mempool()?;
+    assert_eq!(
+        event
+            .new_txs
+            .iter()
+            .map(|(tx, _)| tx.compute_txid())
+            .collect::<Vec<_>>(),
+        vec![tx_b2.compute_txid()],
+    );
+    assert_eq!(
+        event.evicted_txids,
+        [tx_b.compute_txid(), tx_c.compute_txid()].into(),
+    );
+    assert!(event
+        .evicted_ats()
+        .all(|(_, evicted_at)| evicted_at == event.latest_update_time));
+
+    // the confirmed txs left the mempool, but are not evicted once their block is emitted
+    env.mine_blocks(1, None)?;
+    let event = emitter.mempool()?;
+    assert!(event.new_txs.is_empty() && event.evicted_txids.is_empty());
+    while emitter.next_header()?.is_some() {}
+    let event = emitter.mempool()?;
+    assert!(event.new_txs.is_empty() && event.evicted_txids.is_empty());
+
+    Ok(())
+}
+
+/// Ensure that the local chain is kept correct through a reorg with header emissions only, and
+/// that the header and block emissions can be mixed.
+///
+/// 1. Mine 101 blocks and emit their headers.
+/// 2. Reorg the highest 3 blocks and emit the headers of the new blocks.
+/// 3. Mine a block and emit it as a full block, then fetch the block of the last header.
+#[test]
+fn header_emission_handles_reorg() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let network_tip = env.rpc_client().get_block_count()?;
+    env.mine_blocks(101 - network_tip as usize, None)?;
+
+    let (mut local_chain, _) = LocalChain::from_genesis_hash(env.rpc_client().get_block_hash(0)?);
+    let mut emitter = Emitter::new(env.rpc_client(), local_chain.tip(), 0);
+    while let Some(emission) = emitter.next_header()? {
+        assert_eq!(emission.block.block_hash(), emission.block_hash());
+        local_chain.apply_update(emission.checkpoint)?;
+    }
+    assert_eq!(local_chain.tip().height(), 101);
+
+    let reorged_blocks = env.reorg(3)?;
+    let mut emitted = Vec::new();
+    while let Some(emission) = emitter.next_header()? {
+        assert_eq!(
+            emission.block.prev_blockhash,
+            emission.connected_to().hash,
+            "header must connect to the previous checkpoint"
+        );
+        emitted.push(emission.block_hash());
+        local_chain.apply_update(emission.checkpoint)?;
+    }
+    assert_eq!(emitted, reorged_blocks);
+
+    let hash = env.mine_blocks(1, None)?[0];
+    let emission = emitter.next_block()?.expect("must emit the new block");
+    assert_eq!(emission.block.block_hash(), hash);
+    local_chain.apply_update(emission.checkpoint)?;
+    let block = emitter.fetch_block(local_chain.tip().block_id())?;
+    assert_eq!(block.block_hash(), hash);
+
+    assert_eq!(
+        local_chain
+            .iter_checkpoints()
+            .map(|cp| (cp.height(), cp.hash()))
+            .collect::<BTreeSet<_>>(),
+        best_chain(&env)?.into_iter().collect::<BTreeSet<_>>(),
+        "final local_chain state is unexpected",
+    );
+
+    Ok(())
+}
+
+/// Emit with an [`Emitter`] restored from `state`, and update `state` afterwards, as an
+/// application which restarts between the emissions would.
+fn emit_restored<'c, C: RpcApi, T>(
+    client: &'c C,
+    state: &mut EmitterState,
+    emit: impl FnOnce(&mut Emitter<'c, C>) -> Result<T, EmitterError>,
+) -> anyhow::Result<T> {
+    #[cfg(feature = "serde")]
+    {
+        use bitcoincore_rpc::jsonrpc::serde_json;
+        *state = serde_json::from_str(&serde_json::to_string(state)?)?;
+    }
+    let mut emitter = Emitter::from_state(client, state.clone()).expect("state must be valid");
+    let res = emit(&mut emitter)?;
+    *state = emitter.state();
+    Ok(res)
+}
+
+/// The new txids and the evicted txids of a mempool emission.
+fn mempool_changes(event: &MempoolEvent) -> (Vec<Txid>, BTreeSet<Txid>) {
+    (
+        event
+            .new_txs
+            .iter()
+            .map(|(tx, _)| tx.compute_txid())
+            .collect(),
+        event.evicted_txids.iter().copied().collect(),
+    )
+}
+
+/// Ensure that an [`Emitter`] restored from its state after every emission emits the same blocks
+/// and mempool changes as an emitter which is never restarted, and doesn't emit a mempool tx twice.
+///
+/// 1. Mine 101 blocks and emit them, restarting between the blocks.
+/// 2. Broadcast `a` and its child `b`, which are emitted once.
+/// 3. Replace `b` with `b2`, the restored emitter reports `b` as evicted.
+/// 4. Mine a block confirming `a` and `b2`, which are not evicted.
+#[test]
+fn emitter_resumes_from_state() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let client = env.rpc_client();
+    let genesis_cp = CheckPoint::new(BlockId {
+        height: 0,
+        hash: client.get_block_hash(0)?,
+    });
+    let mut emitter = Emitter::new(client, genesis_cp.clone(), 0);
+    let mut state = Emitter::new(client, genesis_cp, 0).state();
+
+    let addr = client.get_new_address(None, None)?.assume_checked();
+    env.mine_blocks(101, Some(addr.clone()))?;
+    loop {
+        let emission = emitter.next_block()?.map(|e| e.block_hash());
+        let restored_emission =
+            emit_restored(client, &mut state, |e| e.next_block())?.map(|e| e.block_hash());
+        assert_eq!(emission, restored_emission);
+        if emission.is_none() {
+            break;
+        }
+    }
+
+    let (tx_a, _) =
+        env.create_unbroadcast_package(&addr, Amount::from_sat(100_000), Amount::ZERO)?;
+    let vout_a = tx_a
+        .output
+        .iter()
+        .position(|txout| txout.script_pubkey == addr.script_pubkey())
+        .expect("must pay to the address") as u32;
+    let tx_b = spend(&env, &tx_a, vout_a, &addr, Amount::from_sat(1_000))?;
+    let tx_b2 = spend(&env, &tx_a, vout_a, &addr, Amount::from_sat(10_000))?;
+    client.send_raw_transaction(&tx_a)?;
+    client.send_raw_transaction(&tx_b)?;
+
+    let exp_changes = [
+        (
+            vec![tx_a.compute_txid(), tx_b.compute_txid()],
+            BTreeSet::new(),
+        ),
+        // no duplicate emissions
+        (vec![], BTreeSet::new()),
+    ];
+    for exp in exp_changes {
+        assert_eq!(mempool_changes(&emitter.mempool()?), exp);
+        assert_eq!(
+            mempool_changes(&emit_restored(client, &mut state, |e| e.mempool())?),
+            exp
+        );
+    }
+
+    client.send_raw_transaction(&tx_b2)?;
+    let exp = (
+        vec![tx_b2.compute_txid()],
+        BTreeSet::from([tx_b.compute_txid()]),
+    );
+    assert_eq!(mempool_changes(&emitter.mempool()?), exp);
+    assert_eq!(
+        mempool_changes(&emit_restored(client, &mut state, |e| e.mempool())?),
+        exp
+    );
+
+    let hash = env.mine_blocks(1, None)?[0];
+    assert_eq!(emitter.next_block()?.map(|e| e.block_hash()), Some(hash));
+    assert_eq!(
+        emit_restored(client, &mut state, |e| e.next_block())?.map(|e| e.block_hash()),
+        Some(hash)
+    );
+    assert_eq!(
+        mempool_changes(&emitter.mempool()?),
+        (vec![], BTreeSet::new())
+    );
+    assert_eq!(
+        mempool_changes(&emit_restored(client, &mut state, |e| e.mempool())?),
+        (vec![], BTreeSet::new())
+    );
+
+    assert_eq!(emitter.state(), state);
+    assert!(state.mempool_txs.is_empty());
+
+    Ok(())
+}
+
+/// Ensure that the [`Emitter`] reports the blocks pruned by the node.
+///
+/// 1. Mine 600 blocks on a pruned node and prune the blocks below height 300.
+/// 2. Emitting from height 0 fails with [`EmitterError::BlockPruned`].
+/// 3. [`Emitter::new_clamped`] emits from the prune height and reports the skipped heights.
+///
+/// The esplora server of [`TestEnv`] refuses pruned nodes, so `bitcoind` is started alone.
+#[test]
+fn emitter_handles_pruned_node() -> anyhow::Result<()> {
+    let mut conf = bdk_testenv::bitcoind::Conf::default();
+    conf.args.push("-prune=1");
+    conf.args.push("-fastprune");
+    let bitcoind =
+        bdk_testenv::bitcoind::BitcoinD::with_conf(bdk_testenv::bitcoind::exe_path()?, &conf)?;
+    let client = &bitcoind.client;
+
+    let addr = client.get_new_address(None, None)?.assume_checked();
+    client.generate_to_address(600, &addr)?;
+    client.call::<u64>("pruneblockchain", &[json!(300)])?;
+    let prune_height = client
+        .get_blockchain_info()?
+        .prune_height
+        .expect("node must be pruned") as u32;
+    assert!(prune_height > 0, "blocks must be pruned");
+
+    let genesis = client.get_block_hash(0)?;
+    let (mut local_chain, _) = LocalChain::from_genesis_hash(genesis);
+
+    let mut emitter = Emitter::new(client, local_chain.tip(), 0);
+    match emitter.next_block() {
+        Err(EmitterError::BlockPruned {
+            requested,
+            prune_height: err_prune_height,
+        }) => {
+            assert!(requested < prune_height);
+            assert_eq!(err_prune_height, prune_height);
+        }
+        res => panic!("expected a pruned block error, got {:?}", res.map(|_| ())),
+    }
+
+    let mut emitter = Emitter::new_clamped(client, local_chain.tip(), 0)?;
+    assert_eq!(emitter.pruned_heights(), Some(0..prune_height));
+    let mut heights = Vec::new();
+    while let Some(emission) = emitter.next_block()? {
+        heights.push(emission.block_height());
+        local_chain.apply_update(emission.checkpoint)?;
+    }
+    assert_eq!(heights, (prune_height..=600).collect::<Vec<_>>());
+    assert_eq!(local_chain.tip().height(), 600);
+
+    // a start height above the prune height is left as is
+    let emitter = Emitter::new_clamped(client, local_chain.tip(), prune_height + 1)?;
+    assert_eq!(emitter.pruned_heights(), None);
+
+    Ok(())
+}
+
+/// The block hashes of the best chain of the node, by height.
+fn best_chain(env: &TestEnv) -> anyhow::Result<Vec<(u32, bitcoin::BlockHash)>> {
+    let tip_height = env.rpc_client().get_block_count()?;
+    (0..=tip_height)
+        .map(|height| Ok((height as u32, env.rpc_client().get_block_hash(height)?)))
+        .collect()
+}
+
+/// Ensure that the [`ParallelEmitter`] emits the blocks in order, and recovers from a reorg of
+/// the prefetched blocks.
+///
+/// 1. Mine 101 blocks, emit blocks up to height 25 with a buffer of 10 blocks.
+/// 2. Reorg the highest 90 blocks, so that the prefetched blocks are stale.
+/// 3. Emit the remaining blocks, the [`LocalChain`] ends up matching the node.
+///
+/// [`ParallelEmitter`]: bdk_bitcoind_rpc::ParallelEmitter
+#[test]
+fn parallel_emitter_handles_reorg() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let network_tip = env.rpc_client().get_block_count()?;
+    env.mine_blocks(101 - network_tip as usize, None)?;
+
+    let (mut local_chain, _) = LocalChain::from_genesis_hash(env.rpc_client().get_block_hash(0)?);
+    let mut emitter = Emitter::new(env.rpc_client(), local_chain.tip(), 0).into_parallel(4, 10);
+
+    let mut exp_height = 1;
+    while exp_height <= 25 {
+        let emission = emitter.next_block()?.expect("must emit block");
+        assert_eq!(emission.block_height(), exp_height);
+        assert_eq!(emission.block.block_hash(), emission.block_hash());
+        local_chain.apply_update(emission.checkpoint)?;
+        exp_height += 1;
+    }
+
+    env.reorg(90)?;
+    while let Some(emission) = emitter.next_block()? {
+        local_chain.apply_update(emission.checkpoint)?;
+    }
+
+    assert_eq!(
+        local_chain
+            .iter_checkpoints()
+            .map(|cp| (cp.height(), cp.hash()))
+            .collect::<BTreeSet<_>>(),
+        best_chain(&env)?.into_iter().collect::<BTreeSet<_>>(),
+        "final local_chain state is unexpected",
+    );
+
+    Ok(())
+}
+
+/// Compare the wall-clock time of the initial sync of 5,000 blocks with the [`Emitter`] and the
+/// [`ParallelEmitter`].
+///
+/// [`ParallelEmitter`]: bdk_bitcoind_rpc::ParallelEmitter
+#[test]
+fn parallel_emitter_benchmark() -> anyhow::Result<()> {
+    use std::time::Instant;
+
+    let env = TestEnv::new()?;
+    let network_tip = env.rpc_client().get_block_count()?;
+    env.mine_blocks(5_000 - network_tip as usize, None)?;
+    let exp_chain = best_chain(&env)?;
+    let genesis_hash = exp_chain[0].1;
+
+    let start = Instant::now();
+    let mut emitter = Emitter::new(
+        env.rpc_client(),
+        CheckPoint::new(BlockId {
+            height: 0,
+            hash: genesis_hash,
+        }),
+        0,
+    );
+    let mut serial_chain = vec![(0, genesis_hash)];
+    while let Some(emission) = emitter.next_block()? {
+        serial_chain.push((emission.block_height(), emission.block_hash()));
+    }
+    let serial_elapsed = start.elapsed();
+
+    let start = Instant::now();
+    let mut emitter = Emitter::new(
+        env.rpc_client(),
+        CheckPoint::new(BlockId {
+            height: 0,
+            hash: genesis_hash,
+        }),
+        0,
+    )
+    .into_parallel(4, 64);
+    let mut parallel_chain = vec![(0, genesis_hash)];
+    while let Some(emission) = emitter.next_block()? {
+        parallel_chain.push((emission.block_height(), emission.block_hash()));
+    }
+    let parallel_elapsed = start.elapsed();
+
+    println!(
+        "emitted {} blocks: serial {:?}, parallel {:?}",
+        exp_chain.len(),
+        serial_elapsed,
+        parallel_elapsed
+    );
+    assert_eq!(serial_chain, exp_chain);
+    assert_eq!(parallel_chain, exp_chain);
+    assert!(
+        parallel_elapsed < serial_elapsed,
+        "parallel emission must be faster"
+    );
+
+    Ok(())
+}
+
+/// Ensure that the [`NotifiedEmitter`] picks up a new block within a second of it being mined,
+/// and a new mempool tx, with the ZMQ notifications of `bitcoind`.
+///
+/// [`NotifiedEmitter`]: bdk_bitcoind_rpc::zmq::NotifiedEmitter
+#[cfg(feature = "zmq")]
+#[test]
+fn notified_emitter_picks_up_new_blocks() -> anyhow::Result<()> {
+    use bdk_bitcoind_rpc::zmq::NotifiedEmitter;
+    use std::time::{Duration, Instant};
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let endpoint = format!("tcp://127.0.0.1:{}", port);
+    let env = TestEnv::new_with_bitcoind_args(&[
+        &format!("-zmqpubhashblock={}", endpoint),
+        &format!("-zmqpubrawtx={}", endpoint),
+    ])?;
+    let emitter = Emitter::new(
+        env.rpc_client(),
+        CheckPoint::new(BlockId {
+            height: 0,
+            hash: env.rpc_client().get_block_hash(0)?,
+        }),
+        0,
+    );
+    let mut emitter = NotifiedEmitter::new(emitter, [&endpoint])?;
+    let addr = env
+        .rpc_client()
+        .get_new_address(None, None)?
+        .assume_checked();
+    env.mine_blocks(101, Some(addr.clone()))?;
+    while emitter.emitter_mut().next_header()?.is_some() {}
+
+    // nothing happens
+    assert!(emitter.next_header(Duration::from_millis(500))?.is_none());
+
+    std::thread::scope(|scope| -> anyhow::Result<()> {
+        let miner = scope.spawn(|| -> anyhow::Result<_> {
+            std::thread::sleep(Duration::from_secs(1));
+            let hash = env.mine_blocks(1, None)?[0];
+            Ok((Instant::now(), hash))
+        });
+        let emission = emitter
+            .next_header(Duration::from_secs(30))?
+            .expect("must emit the mined block");
+        let emitted_at = Instant::now();
+        let (mined_at, hash) = miner.join().expect("must mine")?;
+        assert_eq!(emission.block_hash(), hash);
+        assert!(emitted_at.saturating_duration_since(mined_at) < Duration::from_secs(1));
+        Ok(())
+    })?;
+
+    // the mempool emission wakes up for the new tx
+    let txid = env.send(&addr, Amount::from_sat(10_000))?;
+    let event = emitter.mempool(Duration::from_secs(30))?;
+    assert!(event
+        .new_txs
+        .iter()
+        .any(|(tx, _)| tx.compute_txid() == txid));
+
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use bdk_bitcoind_rpc::{fee_estimate, mempool_min_fee, FeeEstimateError};
+use bdk_chain::bitcoin::FeeRate;
+use bdk_testenv::{anyhow, TestEnv};
+use bitcoincore_rpc::bitcoincore_rpc_json::EstimateMode;
+
+/// Ensure that a fresh regtest node reports no fee estimate, and the default min relay fee rate
+/// as the min fee rate of its mempool.
+#[test]
+fn fee_estimate_without_data() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let client = env.rpc_client();
+
+    for mode in [
+        EstimateMode::Unset,
+        EstimateMode::Economical,
+        EstimateMode::Conservative,
+    ] {
+        assert!(matches!(
+            fee_estimate(client, 6, mode),
+            Err(FeeEstimateError::NoEstimate { target: 6 })
+        ));
+    }
+    assert_eq!(mempool_min_fee(client)?, FeeRate::from_sat_per_kwu(250));
+
+    Ok(())
+}
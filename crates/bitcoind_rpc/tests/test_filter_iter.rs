@@ -0,0 +1,86 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use bdk_bitcoind_rpc::bip158::FilterIter;
+use bdk_chain::{
+    bitcoin::{Address, Amount, Network},
+    local_chain::LocalChain,
+};
+use bdk_testenv::{anyhow, TestEnv};
+use bitcoin::{hashes::Hash, ScriptBuf, WScriptHash};
+use bitcoincore_rpc::RpcApi;
+
+/// Ensure that the [`FilterIter`] only fetches the blocks which are relevant to the script
+/// pubkeys, and that the local chain stays connected across the skipped blocks.
+///
+/// 1. Mine 1,000 blocks, 5 of which pay to a tracked script pubkey.
+/// 2. Emit blocks from [`FilterIter`], roughly 5 blocks are fetched.
+/// 3. Apply the final checkpoint, the [`LocalChain`] reaches the tip of the node.
+#[test]
+fn filter_iter_fetches_matching_blocks() -> anyhow::Result<()> {
+    let env = TestEnv::new_with_bitcoind_args(&["-blockfilterindex=1"])?;
+    let client = env.rpc_client();
+    let spk = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+    let addr = Address::from_script(&spk, Network::Regtest)?;
+
+    let network_tip = client.get_block_count()?;
+    env.mine_blocks(101 - network_tip as usize, None)?;
+    let mut exp_heights = BTreeSet::new();
+    for _ in 0..5 {
+        env.mine_blocks(150, None)?;
+        env.send(&addr, Amount::from_sat(10_000))?;
+        env.mine_blocks(1, None)?;
+        exp_heights.insert(client.get_block_count()? as u32);
+    }
+    let tip_height = client.get_block_count()?;
+    env.mine_blocks(1_000 - tip_height as usize, None)?;
+    let tip_hash = client.get_best_block_hash()?;
+
+    // wait for the filter index to catch up
+    let start = Instant::now();
+    while client.get_block_filter(&tip_hash).is_err() {
+        assert!(
+            start.elapsed() < Duration::from_secs(30),
+            "filters must be indexed"
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let (mut local_chain, _) = LocalChain::from_genesis_hash(client.get_block_hash(0)?);
+    let mut iter = FilterIter::new(client, local_chain.tip(), 0);
+    iter.add_spk(spk.clone());
+
+    let mut emitted_heights = BTreeSet::new();
+    while let Some(emission) = iter.next_block()? {
+        let height = emission.block_height();
+        if exp_heights.contains(&height) {
+            assert!(
+                emission
+                    .block
+                    .txdata
+                    .iter()
+                    .any(|tx| tx.output.iter().any(|txout| txout.script_pubkey == spk)),
+                "matching block must pay to the tracked spk"
+            );
+        }
+        local_chain.apply_update(emission.checkpoint)?;
+        emitted_heights.insert(height);
+    }
+
+    assert!(exp_heights.is_subset(&emitted_heights));
+    // allow for a few false positives
+    assert!(
+        emitted_heights.len() < 10,
+        "{} blocks fetched",
+        emitted_heights.len()
+    );
+
+    local_chain.apply_update(iter.checkpoint())?;
+    assert_eq!(local_chain.tip().height(), 1_000);
+    assert_eq!(local_chain.tip().hash(), tip_hash);
+
+    // nothing more to emit
+    assert!(iter.next_block()?.is_none());
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+#![cfg(feature = "node-wallet")]
+
+use bdk_bitcoind_rpc::node_wallet::{
+    import_wallet_descriptors, ImportDescriptor, NodeWalletEmitter,
+};
+use bdk_chain::{
+    bitcoin::{Address, Amount, Txid},
+    local_chain::LocalChain,
+};
+use bdk_testenv::{anyhow, TestEnv};
+use bitcoincore_rpc::{jsonrpc::serde_json::Value, Auth, Client, RpcApi};
+
+/// The public receive descriptor of the default wallet of the node.
+fn receive_descriptor(env: &TestEnv) -> anyhow::Result<String> {
+    let res = env.rpc_client().call::<Value>("listdescriptors", &[])?;
+    let descriptors = res["descriptors"]
+        .as_array()
+        .expect("must list descriptors");
+    let descriptor = descriptors
+        .iter()
+        .find(|d| {
+            d["internal"].as_bool() == Some(false)
+                && d["desc"]
+                    .as_str()
+                    .map_or(false, |desc| desc.starts_with("tr("))
+        })
+        .expect("must have a taproot receive descriptor");
+    Ok(descriptor["desc"]
+        .as_str()
+        .expect("must be a string")
+        .to_string())
+}
+
+fn derive_address(env: &TestEnv, descriptor: &str, index: u32) -> anyhow::Result<Address> {
+    let addrs = env
+        .rpc_client()
+        .derive_addresses(descriptor, Some([index, index]))?;
+    Ok(addrs[0].clone().assume_checked())
+}
+
+fn sorted_txids(txs: impl IntoIterator<Item = Txid>) -> Vec<Txid> {
+    let mut txids = txs.into_iter().collect::<Vec<_>>();
+    txids.sort();
+    txids
+}
+
+/// Ensure that the transactions of the imported descriptors are found by the rescan of the node,
+/// and that the updates are incremental and anchored in the emitted checkpoints.
+///
+/// 1. Send to two addresses of a descriptor, then import it into a watch-only wallet.
+/// 2. The first update has both txs, confirmed in blocks of the emitted chain.
+/// 3. The next updates have the new unconfirmed tx, then the same tx confirmed.
+/// 4. Import the descriptor again with a larger range, the tx to a later index is found.
+#[test]
+fn node_wallet_emitter_finds_imported_txs() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    env.mine_blocks(101, None)?;
+    let descriptor = receive_descriptor(&env)?;
+
+    let txid_0 = env.send(
+        &derive_address(&env, &descriptor, 0)?,
+        Amount::from_sat(10_000),
+    )?;
+    env.mine_blocks(1, None)?;
+    let txid_1 = env.send(
+        &derive_address(&env, &descriptor, 1)?,
+        Amount::from_sat(20_000),
+    )?;
+    env.mine_blocks(1, None)?;
+    let txid_15 = env.send(
+        &derive_address(&env, &descriptor, 15)?,
+        Amount::from_sat(30_000),
+    )?;
+    env.mine_blocks(1, None)?;
+
+    let client = Client::new(
+        &env.bitcoind.rpc_url_with_wallet("watch"),
+        Auth::CookieFile(env.bitcoind.params.cookie_file.clone()),
+    )?;
+    let imports = [ImportDescriptor {
+        descriptor: descriptor.clone(),
+        range_end: 9,
+    }];
+    import_wallet_descriptors(&client, "watch", &imports, 0)?;
+    // importing again into the loaded wallet is fine
+    import_wallet_descriptors(&client, "watch", &imports, 0)?;
+
+    let (mut local_chain, _) = LocalChain::from_genesis_hash(client.get_block_hash(0)?);
+    let mut emitter = NodeWalletEmitter::new(&client, local_chain.tip(), 0);
+
+    let update = emitter.next_update()?;
+    local_chain.apply_update(update.checkpoint)?;
+    assert_eq!(local_chain.tip().hash(), client.get_best_block_hash()?);
+    assert!(update.unconfirmed_txs.is_empty());
+    assert_eq!(
+        sorted_txids(update.confirmed_txs.iter().map(|(tx, _)| tx.compute_txid())),
+        sorted_txids([txid_0, txid_1])
+    );
+    for (_, block_id) in &update.confirmed_txs {
+        assert_eq!(
+            local_chain.get(block_id.height).map(|cp| cp.block_id()),
+            Some(*block_id)
+        );
+    }
+
+    // nothing new
+    let update = emitter.next_update()?;
+    assert!(update.confirmed_txs.is_empty());
+    assert!(update.unconfirmed_txs.is_empty());
+
+    let txid_2 = env.send(
+        &derive_address(&env, &descriptor, 2)?,
+        Amount::from_sat(40_000),
+    )?;
+    let update = emitter.next_update()?;
+    assert!(update.confirmed_txs.is_empty());
+    assert_eq!(
+        update
+            .unconfirmed_txs
+            .iter()
+            .map(|(tx, _)| tx.compute_txid())
+            .collect::<Vec<_>>(),
+        vec![txid_2]
+    );
+
+    env.mine_blocks(1, None)?;
+    let update = emitter.next_update()?;
+    local_chain.apply_update(update.checkpoint)?;
+    assert!(update.unconfirmed_txs.is_empty());
+    assert_eq!(update.confirmed_txs.len(), 1);
+    let (tx, block_id) = &update.confirmed_txs[0];
+    assert_eq!(tx.compute_txid(), txid_2);
+    assert_eq!(local_chain.tip().block_id(), *block_id);
+
+    // the larger range is rescanned from the start time
+    let imports = [ImportDescriptor {
+        descriptor,
+        range_end: 19,
+    }];
+    emitter.import_wallet_descriptors("watch", &imports, 0)?;
+    let update = emitter.next_update()?;
+    assert!(update.unconfirmed_txs.is_empty());
+    assert_eq!(
+        sorted_txids(update.confirmed_txs.iter().map(|(tx, _)| tx.compute_txid())),
+        sorted_txids([txid_0, txid_1, txid_2, txid_15])
+    );
+
+    Ok(())
+}
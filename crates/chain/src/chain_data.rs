@@ -86,6 +86,22 @@ impl From<ChainPosition<ConfirmationTimeHeightAnchor>> for ConfirmationTime {
     }
 }
 
+/// A point of the chain, as a block height or a time.
+///
+/// This is how the birthday of a wallet, the point before which it has no history, is expressed.
+#[derive(Debug, Clone, PartialEq, Eq, Copy, PartialOrd, Ord, core::hash::Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(crate = "serde_crate")
+)]
+pub enum BlockTimeOrHeight {
+    /// A block height.
+    Height(u32),
+    /// A time in unix seconds.
+    Time(u64),
+}
+
 /// A reference to a block in the canonical chain.
 ///
 /// `BlockId` implements [`Anchor`]. When a transaction is anchored to `BlockId`, the confirmation
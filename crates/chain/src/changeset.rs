@@ -1,3 +1,107 @@
+/// What a label is attached to, following the label types of
+/// [BIP-329](https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(crate::serde::Deserialize, crate::serde::Serialize),
+    serde(crate = "crate::serde")
+)]
+pub enum LabelRef {
+    /// A transaction.
+    Tx(bitcoin::Txid),
+    /// An address, identified by its script pubkey.
+    Addr(bitcoin::ScriptBuf),
+    /// A transaction output.
+    Output(bitcoin::OutPoint),
+    /// A transaction input, identified by the spending transaction's id and the input's index.
+    Input(bitcoin::OutPoint),
+}
+
+/// A broadcast attempt of a transaction, as recorded in the broadcast journal of a wallet.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(crate::serde::Deserialize, crate::serde::Serialize),
+    serde(crate = "crate::serde")
+)]
+pub struct BroadcastRecord {
+    /// The transaction broadcast.
+    pub txid: bitcoin::Txid,
+    /// The name of the chain source the transaction was broadcast through.
+    pub backend: alloc::string::String,
+    /// What the chain source answered.
+    pub outcome: BroadcastOutcome,
+    /// When the transaction was broadcast, in unix epoch seconds.
+    pub timestamp: u64,
+}
+
+/// The answer of a chain source to a broadcast, see [`BroadcastRecord`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(crate::serde::Deserialize, crate::serde::Serialize),
+    serde(crate = "crate::serde")
+)]
+pub enum BroadcastOutcome {
+    /// The transaction was accepted.
+    Accepted,
+    /// The broadcast failed, with the reason given by the chain source.
+    Rejected(alloc::string::String),
+}
+
+/// How a wallet picks the internal address of the change outputs of its transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(crate::serde::Deserialize, crate::serde::Serialize),
+    serde(crate = "crate::serde")
+)]
+pub enum ChangeAddressPolicy {
+    /// Use the first unused internal address, revealing a new one if needed.
+    #[default]
+    FreshAlways,
+    /// Cycle through the first `n` internal addresses, in order.
+    RotateWithin(u32),
+    /// Always use the internal address at this index.
+    Fixed(u32),
+}
+
+/// An invoice of a wallet: an address and the amount expected on it, until an expiry.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(crate::serde::Deserialize, crate::serde::Serialize),
+    serde(crate = "crate::serde")
+)]
+pub struct InvoiceRecord {
+    /// The script pubkey of the address of the invoice.
+    pub script_pubkey: bitcoin::ScriptBuf,
+    /// The amount expected.
+    pub amount: bitcoin::Amount,
+    /// The block height or time at which the invoice expires.
+    pub expiry: crate::BlockTimeOrHeight,
+    /// The number of confirmations the payments need for the invoice to be settled.
+    pub min_confirmations: u32,
+}
+
+/// A revision of the descriptor of a keychain, the descriptor it had from a block height on.
+///
+/// The revisions of a keychain are numbered from 0, for the descriptor it was created with.
+#[cfg(feature = "miniscript")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(crate::serde::Deserialize, crate::serde::Serialize),
+    serde(crate = "crate::serde")
+)]
+pub struct DescriptorRevisionRecord {
+    /// The id of the descriptor of the revision.
+    pub descriptor_id: crate::DescriptorId,
+    /// The height of the tip of the chain of the wallet when the revision became active, `None`
+    /// for the revision 0 of a keychain persisted before revisions were recorded.
+    pub activation_height: Option<u32>,
+}
+
 /// A changeset containing [`crate`] structures typically persisted together.
 #[cfg(feature = "miniscript")]
 #[derive(Debug, Clone, PartialEq)]
@@ -19,6 +123,35 @@ pub struct CombinedChangeSet<K, A> {
     pub indexed_tx_graph: crate::indexed_tx_graph::ChangeSet<A, crate::keychain::ChangeSet<K>>,
     /// Stores the network type of the transaction data.
     pub network: Option<bitcoin::Network>,
+    /// Labels set (`Some`) or removed (`None`).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub labels: crate::collections::BTreeMap<LabelRef, Option<alloc::string::String>>,
+    /// Broadcast attempts, in the order they were recorded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub broadcasts: alloc::vec::Vec<BroadcastRecord>,
+    /// The change address policy, if it changed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub change_address_policy: Option<ChangeAddressPolicy>,
+    /// The position of the next change address in the cycle of
+    /// [`ChangeAddressPolicy::RotateWithin`], if it changed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub change_rotation: Option<u32>,
+    /// The birthday of the wallet, if it changed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub birthday: Option<crate::BlockTimeOrHeight>,
+    /// When each keychain was last synced, in unix seconds, see
+    /// [`SyncScheduler`](crate::spk_client::SyncScheduler).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub last_synced: crate::collections::BTreeMap<K, u64>,
+    /// The invoices created, by id.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub invoices: crate::collections::BTreeMap<u32, InvoiceRecord>,
+    /// The revisions of the descriptors of the keychains recorded, by keychain and revision.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub descriptor_revisions: crate::collections::BTreeMap<
+        K,
+        crate::collections::BTreeMap<u32, DescriptorRevisionRecord>,
+    >,
 }
 
 #[cfg(feature = "miniscript")]
@@ -28,6 +161,14 @@ impl<K, A> core::default::Default for CombinedChangeSet<K, A> {
             chain: core::default::Default::default(),
             indexed_tx_graph: core::default::Default::default(),
             network: None,
+            labels: core::default::Default::default(),
+            broadcasts: core::default::Default::default(),
+            change_address_policy: None,
+            change_rotation: None,
+            birthday: None,
+            last_synced: core::default::Default::default(),
+            invoices: core::default::Default::default(),
+            descriptor_revisions: core::default::Default::default(),
         }
     }
 }
@@ -44,10 +185,43 @@ impl<K: Ord, A: crate::Anchor> crate::Append for CombinedChangeSet<K, A> {
             );
             self.network = other.network;
         }
+        self.labels.extend(other.labels);
+        self.broadcasts.extend(other.broadcasts);
+        if other.change_address_policy.is_some() {
+            self.change_address_policy = other.change_address_policy;
+        }
+        if other.change_rotation.is_some() {
+            self.change_rotation = other.change_rotation;
+        }
+        if other.birthday.is_some() {
+            self.birthday = other.birthday;
+        }
+        for (keychain, last_synced) in other.last_synced {
+            let entry = self.last_synced.entry(keychain).or_insert(last_synced);
+            *entry = (*entry).max(last_synced);
+        }
+        self.invoices.extend(other.invoices);
+        // a revision never changes once recorded
+        for (keychain, revisions) in other.descriptor_revisions {
+            let recorded = self.descriptor_revisions.entry(keychain).or_default();
+            for (revision, record) in revisions {
+                recorded.entry(revision).or_insert(record);
+            }
+        }
     }
 
     fn is_empty(&self) -> bool {
-        self.chain.is_empty() && self.indexed_tx_graph.is_empty() && self.network.is_none()
+        self.chain.is_empty()
+            && self.indexed_tx_graph.is_empty()
+            && self.network.is_none()
+            && self.labels.is_empty()
+            && self.broadcasts.is_empty()
+            && self.change_address_policy.is_none()
+            && self.change_rotation.is_none()
+            && self.birthday.is_none()
+            && self.last_synced.is_empty()
+            && self.invoices.is_empty()
+            && self.descriptor_revisions.is_empty()
     }
 }
 
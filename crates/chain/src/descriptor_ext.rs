@@ -3,6 +3,7 @@ use crate::{
     miniscript::{Descriptor, DescriptorPublicKey},
 };
 use bitcoin::hashes::{hash_newtype, sha256, Hash};
+use bitcoin::Amount;
 
 hash_newtype! {
     /// Represents the ID of a descriptor, defined as the sha256 hash of
@@ -17,9 +18,16 @@ hash_newtype! {
 
 /// A trait to extend the functionality of a miniscript descriptor.
 pub trait DescriptorExt {
-    /// Returns the minimum value (in satoshis) at which an output is broadcastable.
+    /// Returns the minimum value at which an output is broadcastable.
     /// Panics if the descriptor wildcard is hardened.
-    fn dust_value(&self) -> u64;
+    fn dust_value(&self) -> Amount;
+
+    /// Returns the minimum value at which an output is broadcastable, in satoshis.
+    /// Panics if the descriptor wildcard is hardened.
+    #[deprecated(since = "0.16.0", note = "use `dust_value`, which returns an `Amount`")]
+    fn dust_value_sat(&self) -> u64 {
+        self.dust_value().to_sat()
+    }
 
     /// Returns the descriptor id, calculated as the sha256 of the descriptor, checksum not
     /// included.
@@ -27,12 +35,11 @@ pub trait DescriptorExt {
 }
 
 impl DescriptorExt for Descriptor<DescriptorPublicKey> {
-    fn dust_value(&self) -> u64 {
+    fn dust_value(&self) -> Amount {
         self.at_derivation_index(0)
             .expect("descriptor can't have hardened derivation")
             .script_pubkey()
             .minimal_non_dust()
-            .to_sat()
     }
 
     fn descriptor_id(&self) -> DescriptorId {
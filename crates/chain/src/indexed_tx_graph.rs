@@ -83,6 +83,37 @@ where
         changeset
     }
 
+    /// Index again all the transactions and floating txouts of the graph.
+    ///
+    /// The index only looks at the data inserted after the scripts it tracks, this is needed for
+    /// the data already in the graph to be indexed when new scripts are tracked, e.g. after
+    /// inserting a descriptor in a [`KeychainTxOutIndex`] or a script in a [`SpkTxOutIndex`].
+    /// Nothing is fetched and the transactions aren't canonicalized, reindexing twice leaves the
+    /// index unchanged.
+    ///
+    /// A pass over the graph may reveal scripts that were beyond the lookahead of a
+    /// [`KeychainTxOutIndex`] when an earlier output was scanned, so the graph is scanned again
+    /// for as long as a pass changes the index.
+    ///
+    /// [`KeychainTxOutIndex`]: crate::keychain::KeychainTxOutIndex
+    /// [`SpkTxOutIndex`]: crate::SpkTxOutIndex
+    pub fn reindex(&mut self) -> I::ChangeSet {
+        let mut changeset = I::ChangeSet::default();
+        loop {
+            let mut pass = I::ChangeSet::default();
+            for tx_node in self.graph.full_txs() {
+                pass.append(self.index.index_tx(&tx_node.tx));
+            }
+            for (outpoint, txout) in self.graph.floating_txouts() {
+                pass.append(self.index.index_txout(outpoint, txout));
+            }
+            if pass.is_empty() {
+                return changeset;
+            }
+            changeset.append(pass);
+        }
+    }
+
     /// Apply an `update` directly.
     ///
     /// `update` is a [`TxGraph<A>`] and the resultant changes is returned as [`ChangeSet`].
@@ -119,6 +150,12 @@ where
         self.graph.insert_seen_at(txid, seen_at).into()
     }
 
+    /// Insert a unix timestamp of when a transaction is found missing from the mempool, see
+    /// [`TxGraph::insert_evicted_at`].
+    pub fn insert_evicted_at(&mut self, txid: Txid, evicted_at: u64) -> ChangeSet<A, I::ChangeSet> {
+        self.graph.insert_evicted_at(txid, evicted_at).into()
+    }
+
     /// Batch insert transactions, filtering out those that are irrelevant.
     ///
     /// Relevancy is determined by the [`Indexer::is_tx_relevant`] implementation of `I`. Irrelevant
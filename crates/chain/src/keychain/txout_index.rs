@@ -6,7 +6,7 @@ use crate::{
     DescriptorExt, DescriptorId, SpkIterator, SpkTxOutIndex,
 };
 use alloc::{borrow::ToOwned, vec::Vec};
-use bitcoin::{Amount, OutPoint, Script, SignedAmount, Transaction, TxOut, Txid};
+use bitcoin::{Amount, OutPoint, Script, ScriptBuf, SignedAmount, Transaction, TxOut, Txid};
 use core::{
     fmt::Debug,
     ops::{Bound, RangeBounds},
@@ -168,6 +168,7 @@ impl<K: Clone + Ord + Debug> Indexer for KeychainTxOutIndex<K> {
                 .map(|(k, v)| (k.clone(), v.clone()))
                 .collect(),
             last_revealed: self.last_revealed.clone().into_iter().collect(),
+            marked_used: self.marked_used(),
         }
     }
 
@@ -307,6 +308,30 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         self.inner.unmark_used(&(keychain, index))
     }
 
+    /// The indices marked with [`mark_used`] which still have no indexed output, as they are
+    /// recorded in [`ChangeSet::marked_used`].
+    ///
+    /// [`mark_used`]: Self::mark_used
+    fn marked_used(&self) -> BTreeMap<DescriptorId, BTreeMap<u32, bool>> {
+        let mut marked_used = BTreeMap::<DescriptorId, BTreeMap<u32, bool>>::new();
+        for (keychain, index) in self.inner.all_spks().keys() {
+            let spk_index = (keychain.clone(), *index);
+            if !self.inner.is_used(&spk_index)
+                || self
+                    .inner
+                    .outputs_in_range(spk_index.clone()..=spk_index)
+                    .next()
+                    .is_some()
+            {
+                continue;
+            }
+            if let Some(did) = self.keychain_to_descriptor_id.get(keychain) {
+                marked_used.entry(*did).or_default().insert(*index, true);
+            }
+        }
+        marked_used
+    }
+
     /// Computes the total value transfer effect `tx` has on the script pubkeys belonging to the
     /// keychains in `range`. Value is *sent* when a script pubkey in the `range` is on an input and
     /// *received* when it is on an output. For `sent` to be computed correctly, the output being
@@ -618,6 +643,12 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         self.last_revealed.get(descriptor_id).cloned()
     }
 
+    /// Get the last derivation index revealed for the descriptor of `descriptor_id`, whether or not
+    /// it is still assigned to a keychain.
+    pub fn last_revealed_index_of_descriptor(&self, descriptor_id: DescriptorId) -> Option<u32> {
+        self.last_revealed.get(&descriptor_id).cloned()
+    }
+
     /// Convenience method to call [`Self::reveal_to_target`] on multiple keychains.
     pub fn reveal_to_target_multi(&mut self, keychains: &BTreeMap<K, u32>) -> ChangeSet<K> {
         let mut changeset = ChangeSet::default();
@@ -776,10 +807,17 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
     /// Keychains added by the `keychains_added` field of `ChangeSet<K>` respect the one-to-one
     /// keychain <-> descriptor invariant by silently ignoring attempts to violate it (but will
     /// panic if `debug_assertions` are enabled).
+    ///
+    /// The indices in `marked_used` are marked with [`mark_used`] or [`unmark_used`] once the
+    /// script pubkeys up to `last_revealed` have been derived.
+    ///
+    /// [`mark_used`]: Self::mark_used
+    /// [`unmark_used`]: Self::unmark_used
     pub fn apply_changeset(&mut self, changeset: ChangeSet<K>) {
         let ChangeSet {
             keychains_added,
             last_revealed,
+            marked_used,
         } = changeset;
         for (keychain, descriptor) in keychains_added {
             let _ignore_invariant_violation = self.insert_descriptor(keychain, descriptor);
@@ -793,6 +831,20 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         for did in last_revealed.keys() {
             self.replenish_inner_index_did(*did, self.lookahead);
         }
+
+        for (did, marks) in marked_used {
+            let keychain = match self.descriptor_id_to_keychain.get(&did) {
+                Some(keychain) => keychain.clone(),
+                None => continue,
+            };
+            for (index, used) in marks {
+                let _ = if used {
+                    self.mark_used(keychain.clone(), index)
+                } else {
+                    self.unmark_used(keychain.clone(), index)
+                };
+            }
+        }
     }
 }
 
@@ -851,6 +903,7 @@ impl<K: core::fmt::Debug> std::error::Error for InsertDescriptorError<K> {}
 /// The `last_revealed` field is monotone in that [`append`] will never decrease it.
 /// `keychains_added` is *not* monotone, once it is set any attempt to change it is subject to the
 /// same *one-to-one* keychain <-> descriptor mapping invariant as [`KeychainTxOutIndex`] itself.
+/// For `marked_used`, the latest mark of each derivation index wins.
 ///
 /// [`KeychainTxOutIndex`]: crate::keychain::KeychainTxOutIndex
 /// [`apply_changeset`]: crate::keychain::KeychainTxOutIndex::apply_changeset
@@ -873,6 +926,12 @@ pub struct ChangeSet<K> {
     pub keychains_added: BTreeMap<K, Descriptor<DescriptorPublicKey>>,
     /// Contains for each descriptor_id the last revealed index of derivation
     pub last_revealed: BTreeMap<DescriptorId, u32>,
+    /// Contains for each descriptor_id the derivation indices that were manually marked as used
+    /// (`true`) or unused (`false`)
+    ///
+    /// See [`KeychainTxOutIndex::mark_used`] and [`KeychainTxOutIndex::unmark_used`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub marked_used: BTreeMap<DescriptorId, BTreeMap<u32, bool>>,
 }
 
 impl<K: Ord> Append for ChangeSet<K> {
@@ -911,11 +970,18 @@ impl<K: Ord> Append for ChangeSet<K> {
                 }
             }
         }
+
+        // for `marked_used`, the latest mark of each index takes precedence
+        for (desc_id, marks) in other.marked_used {
+            self.marked_used.entry(desc_id).or_default().extend(marks);
+        }
     }
 
     /// Returns whether the changeset are empty.
     fn is_empty(&self) -> bool {
-        self.last_revealed.is_empty() && self.keychains_added.is_empty()
+        self.last_revealed.is_empty()
+            && self.keychains_added.is_empty()
+            && self.marked_used.is_empty()
     }
 }
 
@@ -924,6 +990,7 @@ impl<K> Default for ChangeSet<K> {
         Self {
             last_revealed: BTreeMap::default(),
             keychains_added: BTreeMap::default(),
+            marked_used: BTreeMap::default(),
         }
     }
 }
@@ -15,7 +15,18 @@
 //! 2. Data persistence agnostic -- `bdk_chain` does not care where you cache on-chain data, what you
 //!    cache or how you retrieve it from persistent storage.
 //!
+//! # Deterministic ordering
+//!
+//! The changesets and the iterators of the structures of this crate don't depend on the platform,
+//! the build or the order the data was inserted in: equal states produce equal changesets, which
+//! serialize to the same bytes. The collections of the changesets, and the ones the iterators of
+//! [`TxGraph`], [`KeychainTxOutIndex`] and [`LocalChain`] walk, are `BTreeMap`s and `BTreeSet`s.
+//! Their serde serializations are sorted by key: transactions by txid, outputs by outpoint,
+//! blocks by height, keychains by the `Ord` of the keychain type.
+//!
 //! [Bitcoin Dev Kit]: https://bitcoindevkit.org/
+//! [`KeychainTxOutIndex`]: crate::keychain::KeychainTxOutIndex
+//! [`LocalChain`]: crate::local_chain::LocalChain
 
 #![no_std]
 #![warn(missing_docs)]
@@ -30,6 +41,7 @@ pub use indexed_tx_graph::IndexedTxGraph;
 pub mod keychain;
 pub use keychain::{Indexed, KeychainIndexed};
 pub mod local_chain;
+pub mod prevout;
 mod tx_data_traits;
 pub mod tx_graph;
 pub use tx_data_traits::*;
@@ -53,6 +65,8 @@ pub use spk_iter::*;
 mod changeset;
 pub use changeset::*;
 pub mod spk_client;
+#[cfg(feature = "std")]
+pub mod tiered_fetch;
 
 #[allow(unused_imports)]
 #[macro_use]
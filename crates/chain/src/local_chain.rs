@@ -22,17 +22,64 @@ pub type ChangeSet = BTreeMap<u32, Option<BlockHash>>;
 ///
 /// Internally, checkpoints are nodes of a reference-counted linked-list. This allows the caller to
 /// cheaply clone a [`CheckPoint`] without copying the whole list and to view the entire chain
-/// without holding a lock on [`LocalChain`].
+/// without holding a lock on [`LocalChain`]. Each node also points to an earlier one, like the
+/// skip list of the block index of Bitcoin Core, so that the checkpoint at a height is found in a
+/// logarithmic number of steps.
 #[derive(Debug, Clone)]
 pub struct CheckPoint(Arc<CPInner>);
 
 /// The internal contents of [`CheckPoint`].
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 struct CPInner {
     /// Block id (hash and height).
     block: BlockId,
     /// Previous checkpoint (if any).
     prev: Option<Arc<CPInner>>,
+    /// The highest earlier checkpoint at or below the `skip_height` of this height (if any).
+    skip: Option<Arc<CPInner>>,
+}
+
+// The skips point into `prev`, printing them would repeat the chain.
+impl core::fmt::Debug for CPInner {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CPInner")
+            .field("block", &self.block)
+            .field("prev", &self.prev)
+            .finish()
+    }
+}
+
+/// The height the checkpoint at `height` skips to, the one of Bitcoin Core's block index
+///
+/// Clearing the lowest set bits of the height makes the skips of consecutive checkpoints go back
+/// by varied distances, which a search can combine to reach any height quickly.
+fn skip_height(height: u32) -> u32 {
+    fn clear_lowest_bit(n: u32) -> u32 {
+        n & n.wrapping_sub(1)
+    }
+    if height < 2 {
+        return 0;
+    }
+    if height & 1 == 1 {
+        clear_lowest_bit(clear_lowest_bit(height - 1)) + 1
+    } else {
+        clear_lowest_bit(height)
+    }
+}
+
+impl CPInner {
+    /// The highest checkpoint of this chain at or below `height` (if any)
+    fn floor(self: &Arc<Self>, height: u32) -> Option<&Arc<CPInner>> {
+        let mut node = self;
+        while node.block.height > height {
+            node = match &node.skip {
+                // the skip doesn't pass any checkpoint at or below `height`
+                Some(skip) if skip.block.height >= height => skip,
+                _ => node.prev.as_ref()?,
+            };
+        }
+        Some(node)
+    }
 }
 
 impl PartialEq for CheckPoint {
@@ -46,7 +93,11 @@ impl PartialEq for CheckPoint {
 impl CheckPoint {
     /// Construct a new base block at the front of a linked list.
     pub fn new(block: BlockId) -> Self {
-        Self(Arc::new(CPInner { block, prev: None }))
+        Self(Arc::new(CPInner {
+            block,
+            prev: None,
+            skip: None,
+        }))
     }
 
     /// Construct a checkpoint from a list of [`BlockId`]s in ascending height order.
@@ -102,9 +153,11 @@ impl CheckPoint {
     /// are pushing on to.
     pub fn push(self, block: BlockId) -> Result<Self, Self> {
         if self.height() < block.height {
+            let skip = self.0.floor(skip_height(block.height)).cloned();
             Ok(Self(Arc::new(CPInner {
                 block,
                 prev: Some(self.0),
+                skip,
             })))
         } else {
             Err(self)
@@ -152,7 +205,11 @@ impl CheckPoint {
     ///
     /// Returns `None` if checkpoint at `height` does not exist`.
     pub fn get(&self, height: u32) -> Option<Self> {
-        self.range(height..=height).next()
+        self.0
+            .floor(height)
+            .filter(|cp| cp.block.height == height)
+            .cloned()
+            .map(Self)
     }
 
     /// Iterate checkpoints over a height range.
@@ -164,18 +221,21 @@ impl CheckPoint {
         R: RangeBounds<u32>,
     {
         let start_bound = range.start_bound().cloned();
-        let end_bound = range.end_bound().cloned();
-        self.iter()
-            .skip_while(move |cp| match end_bound {
-                core::ops::Bound::Included(inc_bound) => cp.height() > inc_bound,
-                core::ops::Bound::Excluded(exc_bound) => cp.height() >= exc_bound,
-                core::ops::Bound::Unbounded => false,
-            })
-            .take_while(move |cp| match start_bound {
-                core::ops::Bound::Included(inc_bound) => cp.height() >= inc_bound,
-                core::ops::Bound::Excluded(exc_bound) => cp.height() > exc_bound,
-                core::ops::Bound::Unbounded => true,
-            })
+        let last = match range.end_bound().cloned() {
+            core::ops::Bound::Included(inc_bound) => self.0.floor(inc_bound),
+            core::ops::Bound::Excluded(exc_bound) => exc_bound
+                .checked_sub(1)
+                .and_then(|inc_bound| self.0.floor(inc_bound)),
+            core::ops::Bound::Unbounded => Some(&self.0),
+        };
+        CheckPointIter {
+            current: last.cloned(),
+        }
+        .take_while(move |cp| match start_bound {
+            core::ops::Bound::Included(inc_bound) => cp.height() >= inc_bound,
+            core::ops::Bound::Excluded(exc_bound) => cp.height() > exc_bound,
+            core::ops::Bound::Unbounded => true,
+        })
     }
 
     /// Inserts `block_id` at its height within the chain.
@@ -403,7 +463,7 @@ impl LocalChain {
     pub fn apply_update(&mut self, update: CheckPoint) -> Result<ChangeSet, CannotConnectError> {
         let (new_tip, changeset) = merge_chains(self.tip.clone(), update)?;
         self.tip = new_tip;
-        self._check_changeset_is_applied(&changeset);
+        debug_assert!(self._check_changeset_is_applied(&changeset));
         Ok(changeset)
     }
 
@@ -0,0 +1,277 @@
+//! Look up the outputs spent by transactions.
+//!
+//! The fee of a transaction can only be computed when all the outputs it spends are known, which
+//! depends on what the chain source fetched. A [`PrevoutSource`] is anything that can look up an
+//! output by its outpoint: the [`TxGraph`] itself, or the clients of the chain source crates. A
+//! [`PrevoutResolver`] chains several of them, local ones first, and caches what they return.
+//!
+//! `bdk_chain` never accesses the network by itself: remote sources only get queried through a
+//! resolver the caller builds and passes explicitly.
+//!
+//! ```
+//! # use bdk_chain::bitcoin::{hashes::Hash, Amount, OutPoint, ScriptBuf, TxOut, Txid};
+//! # use bdk_chain::collections::BTreeMap;
+//! # use bdk_chain::prevout::{PrevoutResolver, PrevoutSource};
+//! # use bdk_chain::{BlockId, TxGraph};
+//! let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+//! let txout = TxOut {
+//!     value: Amount::from_sat(1_000),
+//!     script_pubkey: ScriptBuf::new(),
+//! };
+//! let graph = TxGraph::<BlockId>::default();
+//! // a remote source, such as an Electrum client
+//! let remote = BTreeMap::from([(outpoint, txout.clone())]);
+//!
+//! let mut resolver = PrevoutResolver::new().with_source(&graph).with_source(&remote);
+//! assert_eq!(resolver.get_txout(outpoint), Some(txout));
+//! // the next lookups hit the cache
+//! assert_eq!(resolver.cache().len(), 1);
+//! ```
+//!
+//! [`TxGraph`]: crate::TxGraph
+
+use crate::collections::BTreeMap;
+use crate::tx_graph::TxGraph;
+use alloc::vec::Vec;
+use bitcoin::{OutPoint, TxOut};
+
+/// A source of the outputs spent by transactions
+pub trait PrevoutSource {
+    /// Get the output at `outpoint`, `None` if the source doesn't know it or failed to fetch it
+    fn get_txout(&self, outpoint: OutPoint) -> Option<TxOut>;
+
+    /// Get the outputs at `outpoints`, in the same order
+    ///
+    /// The default implementation calls [`get_txout`](Self::get_txout) for each outpoint, remote
+    /// sources can override it to batch their requests.
+    fn get_txouts(&self, outpoints: &[OutPoint]) -> Vec<Option<TxOut>> {
+        outpoints
+            .iter()
+            .map(|&outpoint| self.get_txout(outpoint))
+            .collect()
+    }
+}
+
+impl<A> PrevoutSource for TxGraph<A> {
+    fn get_txout(&self, outpoint: OutPoint) -> Option<TxOut> {
+        TxGraph::get_txout(self, outpoint).cloned()
+    }
+}
+
+impl PrevoutSource for BTreeMap<OutPoint, TxOut> {
+    fn get_txout(&self, outpoint: OutPoint) -> Option<TxOut> {
+        self.get(&outpoint).cloned()
+    }
+}
+
+impl<S: PrevoutSource + ?Sized> PrevoutSource for &S {
+    fn get_txout(&self, outpoint: OutPoint) -> Option<TxOut> {
+        (**self).get_txout(outpoint)
+    }
+
+    fn get_txouts(&self, outpoints: &[OutPoint]) -> Vec<Option<TxOut>> {
+        (**self).get_txouts(outpoints)
+    }
+}
+
+/// A cache of the outputs found by a [`PrevoutResolver`]
+pub trait PrevoutCache {
+    /// Get the cached output at `outpoint`
+    fn get(&self, outpoint: OutPoint) -> Option<TxOut>;
+
+    /// Cache the output at `outpoint`
+    fn insert(&mut self, outpoint: OutPoint, txout: TxOut);
+}
+
+impl PrevoutCache for BTreeMap<OutPoint, TxOut> {
+    fn get(&self, outpoint: OutPoint) -> Option<TxOut> {
+        BTreeMap::get(self, &outpoint).cloned()
+    }
+
+    fn insert(&mut self, outpoint: OutPoint, txout: TxOut) {
+        BTreeMap::insert(self, outpoint, txout);
+    }
+}
+
+impl<C: PrevoutCache + ?Sized> PrevoutCache for &mut C {
+    fn get(&self, outpoint: OutPoint) -> Option<TxOut> {
+        (**self).get(outpoint)
+    }
+
+    fn insert(&mut self, outpoint: OutPoint, txout: TxOut) {
+        (**self).insert(outpoint, txout)
+    }
+}
+
+/// Look up outputs in a chain of [`PrevoutSource`]s, caching the results
+///
+/// The sources are queried in the order they were added, each one only for the outputs the
+/// previous ones didn't know. The cache is checked first, and a `&mut` to a long lived cache can
+/// be passed with [`with_cache`](Self::with_cache) to share it between resolvers.
+pub struct PrevoutResolver<'a, C = BTreeMap<OutPoint, TxOut>> {
+    sources: Vec<&'a dyn PrevoutSource>,
+    cache: C,
+}
+
+impl<'a, C: core::fmt::Debug> core::fmt::Debug for PrevoutResolver<'a, C> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PrevoutResolver")
+            .field("sources", &self.sources.len())
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+impl<'a> Default for PrevoutResolver<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> PrevoutResolver<'a> {
+    /// A resolver without sources and with an empty in-memory cache
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            cache: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'a, C: PrevoutCache> PrevoutResolver<'a, C> {
+    /// Replace the cache of the resolver
+    pub fn with_cache<C2: PrevoutCache>(self, cache: C2) -> PrevoutResolver<'a, C2> {
+        PrevoutResolver {
+            sources: self.sources,
+            cache,
+        }
+    }
+
+    /// Add a source, queried after the ones already added
+    pub fn with_source(mut self, source: &'a dyn PrevoutSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// The cache of the resolver
+    pub fn cache(&self) -> &C {
+        &self.cache
+    }
+
+    /// Get back the cache of the resolver
+    pub fn into_cache(self) -> C {
+        self.cache
+    }
+
+    /// Get the output at `outpoint` from the cache or the first source which knows it
+    pub fn get_txout(&mut self, outpoint: OutPoint) -> Option<TxOut> {
+        self.get_txouts(&[outpoint]).pop().flatten()
+    }
+
+    /// Get the outputs at `outpoints`, in the same order
+    ///
+    /// Each source is queried once, with a batch of the outpoints still missing.
+    pub fn get_txouts(&mut self, outpoints: &[OutPoint]) -> Vec<Option<TxOut>> {
+        let mut txouts = outpoints
+            .iter()
+            .map(|&outpoint| self.cache.get(outpoint))
+            .collect::<Vec<_>>();
+        for source in &self.sources {
+            let (missing_positions, missing): (Vec<usize>, Vec<OutPoint>) = txouts
+                .iter()
+                .zip(outpoints)
+                .enumerate()
+                .filter(|(_, (txout, _))| txout.is_none())
+                .map(|(i, (_, &outpoint))| (i, outpoint))
+                .unzip();
+            if missing.is_empty() {
+                break;
+            }
+            for (i, txout) in missing_positions
+                .into_iter()
+                .zip(source.get_txouts(&missing))
+            {
+                if let Some(txout) = txout {
+                    self.cache.insert(outpoints[i], txout.clone());
+                    txouts[i] = Some(txout);
+                }
+            }
+        }
+        txouts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BlockId;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, ScriptBuf, Txid};
+    use core::cell::Cell;
+
+    fn txout(value: u64) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::new(),
+        }
+    }
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint::new(Txid::all_zeros(), vout)
+    }
+
+    /// A source counting the outpoints it is asked for
+    struct Counting(BTreeMap<OutPoint, TxOut>, Cell<usize>);
+
+    impl PrevoutSource for Counting {
+        fn get_txout(&self, outpoint: OutPoint) -> Option<TxOut> {
+            self.1.set(self.1.get() + 1);
+            self.0.get(&outpoint).cloned()
+        }
+    }
+
+    #[test]
+    fn test_resolver_chains_sources() {
+        let mut graph = TxGraph::<BlockId>::default();
+        let _ = graph.insert_txout(outpoint(0), txout(1));
+        let remote = Counting(
+            [(outpoint(0), txout(100)), (outpoint(1), txout(2))].into(),
+            Cell::new(0),
+        );
+
+        let mut resolver = PrevoutResolver::new()
+            .with_source(&graph)
+            .with_source(&remote);
+        assert_eq!(
+            resolver.get_txouts(&[outpoint(0), outpoint(1), outpoint(2)]),
+            [Some(txout(1)), Some(txout(2)), None]
+        );
+        // the local graph knew the first output
+        assert_eq!(remote.1.get(), 2);
+
+        // the found outputs are cached, the missing one is asked again
+        assert_eq!(resolver.get_txout(outpoint(1)), Some(txout(2)));
+        assert_eq!(resolver.get_txout(outpoint(2)), None);
+        assert_eq!(remote.1.get(), 3);
+        assert_eq!(resolver.cache().len(), 2);
+    }
+
+    #[test]
+    fn test_resolver_shared_cache() {
+        let remote = Counting([(outpoint(0), txout(1))].into(), Cell::new(0));
+        let mut cache = BTreeMap::new();
+
+        let mut resolver = PrevoutResolver::new()
+            .with_cache(&mut cache)
+            .with_source(&remote);
+        assert_eq!(resolver.get_txout(outpoint(0)), Some(txout(1)));
+        drop(resolver);
+
+        let mut resolver = PrevoutResolver::new()
+            .with_cache(&mut cache)
+            .with_source(&remote);
+        assert_eq!(resolver.get_txout(outpoint(0)), Some(txout(1)));
+        assert_eq!(remote.1.get(), 1);
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -4,9 +4,10 @@ use crate::{
     collections::BTreeMap, keychain::Indexed, local_chain::CheckPoint,
     ConfirmationTimeHeightAnchor, TxGraph,
 };
-use alloc::boxed::Box;
-use bitcoin::{OutPoint, Script, ScriptBuf, Txid};
+use alloc::{boxed::Box, collections::VecDeque, string::String, vec::Vec};
+use bitcoin::{Amount, FeeRate, OutPoint, Script, ScriptBuf, Txid, Weight};
 use core::marker::PhantomData;
+use core::time::Duration;
 
 /// Data required to perform a spk-based blockchain client sync.
 ///
@@ -198,6 +199,11 @@ pub struct FullScanRequest<K> {
     pub chain_tip: CheckPoint,
     /// Iterators of script pubkeys indexed by the keychain index.
     pub spks_by_keychain: BTreeMap<K, Box<dyn Iterator<Item = Indexed<ScriptBuf>> + Send>>,
+    /// Stop gaps of the keychains which don't use the stop gap of the full scan.
+    pub stop_gaps: BTreeMap<K, usize>,
+    /// Called with the progress of the full scan after each batch, see
+    /// [`on_progress`](Self::on_progress).
+    pub progress: Option<ProgressCallback<K>>,
 }
 
 impl<K: Ord + Clone> FullScanRequest<K> {
@@ -207,6 +213,8 @@ impl<K: Ord + Clone> FullScanRequest<K> {
         Self {
             chain_tip,
             spks_by_keychain: BTreeMap::new(),
+            stop_gaps: BTreeMap::new(),
+            progress: None,
         }
     }
 
@@ -247,6 +255,75 @@ impl<K: Ord + Clone> FullScanRequest<K> {
         self
     }
 
+    /// Set the stop gap of a given `keychain`, overriding the stop gap passed to the full scan.
+    ///
+    /// This consumes the [`FullScanRequest`] and returns the updated one.
+    #[must_use]
+    pub fn set_stop_gap_for_keychain(mut self, keychain: K, stop_gap: usize) -> Self {
+        self.stop_gaps.insert(keychain, stop_gap);
+        self
+    }
+
+    /// The stop gap of `keychain`, `default` unless it was set with
+    /// [`set_stop_gap_for_keychain`](Self::set_stop_gap_for_keychain).
+    pub fn stop_gap_for_keychain(&self, keychain: &K, default: usize) -> usize {
+        self.stop_gaps.get(keychain).copied().unwrap_or(default)
+    }
+
+    /// Set a closure that will be called with the [`ScanProgress`] of the full scan after each
+    /// batch of script pubkeys is checked, and once more when the scan of every keychain is done.
+    ///
+    /// The closure runs on the task of the full scan, so it should return quickly: send the
+    /// progress to a channel to handle it elsewhere.
+    ///
+    /// This consumes the [`FullScanRequest`] and returns the updated one.
+    #[must_use]
+    pub fn on_progress(mut self, progress: impl FnMut(&ScanProgress<K>) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Take the progress closure of the request into a [`ScanProgressTracker`] of its keychains,
+    /// with `stop_gap` for the keychains without their own stop gap.
+    ///
+    /// This is for the chain sources: call it before taking the script pubkeys out of the
+    /// request, since the planned totals come from their size hints.
+    pub fn progress_tracker(&mut self, stop_gap: usize) -> ScanProgressTracker<K> {
+        let (plans, keychains): (BTreeMap<_, _>, BTreeMap<_, _>) = self
+            .spks_by_keychain
+            .iter()
+            .map(|(keychain, spks)| {
+                let stop_gap = Ord::max(self.stop_gap_for_keychain(keychain, stop_gap), 1);
+                let max_spks = spks.size_hint().1.map(|n| n.min(u32::MAX as usize) as u32);
+                let planned = KeychainPlan {
+                    stop_gap: stop_gap.min(u32::MAX as usize) as u32,
+                    max_spks,
+                    last_active: 0,
+                };
+                let progress = KeychainScanProgress {
+                    spks_checked: 0,
+                    spks_total: planned.total(0),
+                    txs_fetched: 0,
+                    done: false,
+                };
+                ((keychain.clone(), planned), (keychain.clone(), progress))
+            })
+            .unzip();
+        ScanProgressTracker {
+            callback: self.progress.take(),
+            plans,
+            progress: ScanProgress {
+                total_is_estimate: !keychains.is_empty(),
+                keychains,
+                txs_fetched: 0,
+                batch: 0,
+                elapsed: Duration::ZERO,
+                eta: None,
+            },
+            samples: VecDeque::with_capacity(THROUGHPUT_SAMPLES),
+        }
+    }
+
     /// Chain on additional [`Script`]s that will be synced against.
     ///
     /// This consumes the [`FullScanRequest`] and returns the updated one.
@@ -325,6 +402,1001 @@ pub struct FullScanResult<K, A = ConfirmationTimeHeightAnchor> {
     pub chain_update: CheckPoint,
     /// Last active indices for the corresponding keychains (`K`).
     pub last_active_indices: BTreeMap<K, u32>,
+    /// Statistics of the scan of each keychain, empty if the chain source doesn't collect them.
+    pub scan_stats: BTreeMap<K, ScanStats>,
+}
+
+/// Statistics of the full scan of a keychain
+///
+/// A scan which found activity close to its stop gap may have missed script pubkeys used after
+/// a longer gap, and can be run again with a larger stop gap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// The number of script pubkeys checked against the stop gap
+    pub spks_checked: u32,
+    /// The highest index of a script pubkey with transactions
+    pub last_active_index: Option<u32>,
+    /// The number of transactions fetched, once per script pubkey they are relevant to
+    pub txs_fetched: usize,
+    /// The time spent scanning the keychain
+    pub elapsed: Duration,
+}
+
+/// The progress of a full scan, reported to the closure set with
+/// [`FullScanRequest::on_progress`]
+///
+/// The total of script pubkeys of a keychain which is still scanned assumes that no more of them
+/// have transactions: each one found with transactions pushes the stop gap, and the total, further.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanProgress<K> {
+    /// The progress of each keychain
+    pub keychains: BTreeMap<K, KeychainScanProgress>,
+    /// The number of transactions fetched for all the keychains, once per script pubkey they are
+    /// relevant to
+    pub txs_fetched: usize,
+    /// The number of batches of script pubkeys checked so far
+    pub batch: usize,
+    /// The time spent scanning, zero where the chain source has no clock
+    pub elapsed: Duration,
+    /// The time left to check the planned script pubkeys, estimated from the throughput of the
+    /// last batches
+    pub eta: Option<Duration>,
+    /// Whether the totals of the keychains can still grow, until the scan of every keychain is
+    /// done
+    pub total_is_estimate: bool,
+}
+
+impl<K> ScanProgress<K> {
+    /// The number of script pubkeys checked for all the keychains
+    pub fn spks_checked(&self) -> u64 {
+        self.keychains
+            .values()
+            .map(|keychain| keychain.spks_checked as u64)
+            .sum()
+    }
+
+    /// The number of script pubkeys planned for all the keychains
+    pub fn spks_total(&self) -> u64 {
+        self.keychains
+            .values()
+            .map(|keychain| keychain.spks_total as u64)
+            .sum()
+    }
+}
+
+/// The progress of the full scan of a keychain, see [`ScanProgress`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeychainScanProgress {
+    /// The number of script pubkeys checked against the stop gap
+    pub spks_checked: u32,
+    /// The number of script pubkeys the scan plans to check, the stop gap after the last one with
+    /// transactions, and no more than the script pubkeys of the request
+    pub spks_total: u32,
+    /// The number of transactions fetched, once per script pubkey they are relevant to
+    pub txs_fetched: usize,
+    /// Whether the scan of the keychain is done
+    pub done: bool,
+}
+
+/// The closure set with [`FullScanRequest::on_progress`]
+pub type ProgressCallback<K> = Box<dyn FnMut(&ScanProgress<K>) + Send>;
+
+/// The number of batches the throughput of [`ScanProgress::eta`] is computed over
+const THROUGHPUT_SAMPLES: usize = 8;
+
+/// How many script pubkeys of a keychain the scan plans to check
+#[derive(Debug, Clone, Copy)]
+struct KeychainPlan {
+    stop_gap: u32,
+    max_spks: Option<u32>,
+    /// The number of script pubkeys checked up to the last one with transactions
+    last_active: u32,
+}
+
+impl KeychainPlan {
+    fn total(&self, spks_checked: u32) -> u32 {
+        let total = self.last_active.saturating_add(self.stop_gap);
+        let total = match self.max_spks {
+            Some(max_spks) => total.min(max_spks),
+            None => total,
+        };
+        total.max(spks_checked)
+    }
+}
+
+/// Tracks the [`ScanProgress`] of a full scan for a chain source, see
+/// [`FullScanRequest::progress_tracker`]
+///
+/// The chain source reports each script pubkey it checks against the stop gap with
+/// [`spk_checked`](Self::spk_checked), the end of the scan of each keychain with
+/// [`keychain_done`](Self::keychain_done), calls [`batch_done`](Self::batch_done) after each
+/// batch to report the progress and [`finish`](Self::finish) at the end of the scan.
+pub struct ScanProgressTracker<K> {
+    callback: Option<ProgressCallback<K>>,
+    plans: BTreeMap<K, KeychainPlan>,
+    progress: ScanProgress<K>,
+    /// The elapsed time and the number of script pubkeys checked after the last batches
+    samples: VecDeque<(Duration, u64)>,
+}
+
+impl<K: Ord> ScanProgressTracker<K> {
+    /// The progress so far
+    pub fn progress(&self) -> &ScanProgress<K> {
+        &self.progress
+    }
+
+    /// Record that a script pubkey of `keychain` was checked against the stop gap, with `txs`
+    /// transactions.
+    pub fn spk_checked(&mut self, keychain: &K, txs: usize) {
+        let (plan, progress) = match (
+            self.plans.get_mut(keychain),
+            self.progress.keychains.get_mut(keychain),
+        ) {
+            (Some(plan), Some(progress)) => (plan, progress),
+            _ => return,
+        };
+        progress.spks_checked = progress.spks_checked.saturating_add(1);
+        progress.txs_fetched += txs;
+        if txs > 0 {
+            plan.last_active = progress.spks_checked;
+        }
+        progress.spks_total = plan.total(progress.spks_checked);
+        self.progress.txs_fetched += txs;
+    }
+
+    /// Record that the scan of `keychain` is done, its total becomes the number of script pubkeys
+    /// checked.
+    pub fn keychain_done(&mut self, keychain: &K) {
+        if let Some(progress) = self.progress.keychains.get_mut(keychain) {
+            progress.done = true;
+            progress.spks_total = progress.spks_checked;
+        }
+        self.progress.total_is_estimate = self.progress.keychains.values().any(|k| !k.done);
+    }
+
+    /// Record that a batch is done after `elapsed` since the start of the scan, and call the
+    /// progress closure of the request.
+    pub fn batch_done(&mut self, elapsed: Duration) {
+        self.progress.batch += 1;
+        self.report(elapsed);
+    }
+
+    /// Record that the scan of every keychain is done after `elapsed`, and call the progress
+    /// closure of the request a last time.
+    pub fn finish(&mut self, elapsed: Duration) {
+        let keychains = self.progress.keychains.values_mut();
+        for progress in keychains.filter(|progress| !progress.done) {
+            progress.done = true;
+            progress.spks_total = progress.spks_checked;
+        }
+        self.progress.total_is_estimate = false;
+        self.report(elapsed);
+    }
+
+    fn report(&mut self, elapsed: Duration) {
+        self.progress.elapsed = elapsed;
+
+        let checked = self.progress.spks_checked();
+        if self.samples.len() == THROUGHPUT_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((elapsed, checked));
+        let remaining = self.progress.spks_total().saturating_sub(checked);
+        self.progress.eta = match (self.samples.front(), self.samples.back()) {
+            _ if remaining == 0 => Some(Duration::ZERO),
+            (Some(&(start, start_checked)), Some(&(end, end_checked)))
+                if end > start && end_checked > start_checked =>
+            {
+                let nanos_per_spk =
+                    (end - start).as_nanos() / u128::from(end_checked - start_checked);
+                let nanos = nanos_per_spk.saturating_mul(u128::from(remaining));
+                Some(Duration::from_nanos(nanos.min(u64::MAX as u128) as u64))
+            }
+            _ => None,
+        };
+
+        if let Some(callback) = &mut self.callback {
+            callback(&self.progress);
+        }
+    }
+}
+
+/// A tracker of no keychains, which reports to no closure
+impl<K> Default for ScanProgressTracker<K> {
+    fn default() -> Self {
+        Self {
+            callback: None,
+            plans: BTreeMap::new(),
+            progress: ScanProgress {
+                keychains: BTreeMap::new(),
+                txs_fetched: 0,
+                batch: 0,
+                elapsed: Duration::ZERO,
+                eta: None,
+                total_is_estimate: false,
+            },
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl<K> core::fmt::Debug for ScanProgressTracker<K>
+where
+    K: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ScanProgressTracker")
+            .field("progress", &self.progress)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why a spk-based blockchain client didn't broadcast a transaction
+///
+/// The reject reasons of bitcoind, which the Electrum and Esplora servers relay, are mapped to the
+/// variants with [`from_reject_reason`](Self::from_reject_reason). `E` is the error type of the
+/// client, for requests which failed before the server could accept or reject the transaction.
+#[derive(Debug)]
+pub enum BroadcastError<E> {
+    /// An input spends an output which is unknown or already spent by a confirmed transaction,
+    /// such as when a child is broadcast before its parent
+    MissingInputs,
+    /// An input is spent by a transaction of the mempool which the transaction doesn't replace
+    MempoolConflict,
+    /// The fee rate is below the min relay fee rate or the min fee rate of the mempool
+    MinRelayFeeNotMet,
+    /// The fee or the fee rate doesn't pay for the replaced transactions
+    InsufficientFee,
+    /// The fee rate exceeds the max fee rate of the broadcast, which protects against paying a fee
+    /// which is too high by mistake
+    MaxFeeRateExceeded,
+    /// The transaction is valid but not standard, so it isn't relayed, such as when an output is
+    /// dust or a script is not standard
+    NonStandard(String),
+    /// The transaction breaks the topology or size rules of a version 3 (TRUC) transaction
+    TrucViolation(String),
+    /// The transaction is already confirmed
+    AlreadyConfirmed,
+    /// The server rejected the transaction for another reason
+    Rejected(String),
+    /// The request failed
+    Request(E),
+}
+
+impl<E> BroadcastError<E> {
+    /// Map the reject `reason` of a broadcast, as reported by the server, to a broadcast error.
+    ///
+    /// Returns `None` if the reason says the transaction is already in the mempool, which means
+    /// the broadcast succeeded earlier.
+    pub fn from_reject_reason(reason: &str) -> Option<Self> {
+        const ALREADY_IN_MEMPOOL: &[&str] = &["txn-already-in-mempool", "txn-already-known"];
+        const MISSING_INPUTS: &[&str] = &["missing-inputs", "missingorspent"];
+        const MIN_RELAY_FEE: &[&str] = &["min relay fee not met", "mempool min fee not met"];
+        const MAX_FEE_RATE: &[&str] = &["max-fee-exceeded", "Fee exceeds maximum"];
+        const NON_STANDARD: &[&str] = &[
+            "dust",
+            "scriptpubkey",
+            "bare-multisig",
+            "multi-op-return",
+            "scriptsig-size",
+            "scriptsig-not-pushonly",
+            "tx-size",
+            "non-mandatory-script-verify-flag",
+            "bad-txns-nonstandard-inputs",
+            "bad-witness-nonstandard",
+        ];
+        const TRUC_VIOLATION: &[&str] = &["TRUC-violation", "v3-rule-violation"];
+        let matches = |patterns: &[&str]| patterns.iter().any(|p| reason.contains(p));
+        Some(if matches(ALREADY_IN_MEMPOOL) {
+            return None;
+        } else if matches(MISSING_INPUTS) {
+            Self::MissingInputs
+        } else if reason.contains("txn-mempool-conflict") {
+            Self::MempoolConflict
+        } else if matches(MIN_RELAY_FEE) {
+            Self::MinRelayFeeNotMet
+        } else if reason.contains("insufficient fee") {
+            Self::InsufficientFee
+        } else if matches(MAX_FEE_RATE) {
+            Self::MaxFeeRateExceeded
+        } else if matches(TRUC_VIOLATION) {
+            Self::TrucViolation(reason.into())
+        } else if matches(NON_STANDARD) {
+            Self::NonStandard(reason.into())
+        } else if reason.contains("already in block chain")
+            || reason.contains("txn-already-in-chain")
+        {
+            Self::AlreadyConfirmed
+        } else {
+            Self::Rejected(reason.into())
+        })
+    }
+
+    /// Map the error of a failed request with `f`, leaving the rejections untouched.
+    pub fn map_request<F>(self, f: impl FnOnce(E) -> F) -> BroadcastError<F> {
+        match self {
+            Self::MissingInputs => BroadcastError::MissingInputs,
+            Self::MempoolConflict => BroadcastError::MempoolConflict,
+            Self::MinRelayFeeNotMet => BroadcastError::MinRelayFeeNotMet,
+            Self::InsufficientFee => BroadcastError::InsufficientFee,
+            Self::MaxFeeRateExceeded => BroadcastError::MaxFeeRateExceeded,
+            Self::NonStandard(reason) => BroadcastError::NonStandard(reason),
+            Self::TrucViolation(reason) => BroadcastError::TrucViolation(reason),
+            Self::AlreadyConfirmed => BroadcastError::AlreadyConfirmed,
+            Self::Rejected(reason) => BroadcastError::Rejected(reason),
+            Self::Request(err) => BroadcastError::Request(f(err)),
+        }
+    }
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for BroadcastError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::MissingInputs => write!(f, "the transaction spends missing or spent outputs"),
+            Self::MempoolConflict => write!(f, "the transaction conflicts with the mempool"),
+            Self::MinRelayFeeNotMet => write!(f, "the fee of the transaction is too low"),
+            Self::InsufficientFee => {
+                write!(f, "the fee doesn't pay for the replaced transactions")
+            }
+            Self::MaxFeeRateExceeded => write!(f, "the fee rate exceeds the max fee rate"),
+            Self::NonStandard(reason) => write!(f, "the transaction is not standard: {}", reason),
+            Self::TrucViolation(reason) => {
+                write!(f, "the transaction breaks TRUC rules: {}", reason)
+            }
+            Self::AlreadyConfirmed => write!(f, "the transaction is already confirmed"),
+            Self::Rejected(reason) => write!(f, "the transaction was rejected: {}", reason),
+            Self::Request(err) => write!(f, "the broadcast request failed: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: core::fmt::Debug + core::fmt::Display> std::error::Error for BroadcastError<E> {}
+
+/// An unconfirmed transaction of the mempool of the chain source, related to another one, see
+/// [`CpfpInfo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolTx {
+    /// The transaction
+    pub txid: Txid,
+    /// The fee of the transaction
+    pub fee: Amount,
+    /// The weight of the transaction
+    pub weight: Weight,
+}
+
+/// The unconfirmed ancestors and descendants of a transaction in the mempool of the chain
+/// source, which tell the fee rate the transaction is mined at
+///
+/// The fees of the ancestors of an incoming payment are usually unknown to the wallet, since it
+/// doesn't have the previous outputs of the sender, so they're taken from the chain source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CpfpInfo {
+    /// The unconfirmed transactions the transaction spends the outputs of, directly or not
+    pub ancestors: Vec<MempoolTx>,
+    /// The unconfirmed transactions spending the outputs of the transaction, directly or not
+    pub descendants: Vec<MempoolTx>,
+    /// The fee rate of the transaction with its ancestors and descendants, as computed by the
+    /// chain source, if it reports one
+    pub effective_fee_rate: Option<FeeRate>,
+}
+
+impl CpfpInfo {
+    /// The fee of the ancestors
+    pub fn ancestors_fee(&self) -> Amount {
+        self.ancestors.iter().map(|tx| tx.fee).sum()
+    }
+
+    /// The weight of the ancestors
+    pub fn ancestors_weight(&self) -> Weight {
+        self.ancestors
+            .iter()
+            .map(|tx| tx.weight)
+            .fold(Weight::ZERO, |total, weight| total + weight)
+    }
+}
+
+/// Options of the requests of a [`SyncBackend`] or an [`AsyncSyncBackend`], common to all the
+/// chain sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendOptions {
+    /// The number of consecutive unused script pubkeys after which the full scan of a keychain
+    /// stops, unless the [`FullScanRequest`] sets a stop gap for the keychain
+    pub stop_gap: usize,
+    /// The max number of requests made at once, which is the batch size of Electrum and the
+    /// number of parallel requests of Esplora
+    pub batch_size: usize,
+    /// Whether to fetch the previous `TxOut`s of the transactions, for fee calculation
+    ///
+    /// Esplora returns them with the transactions, so this only makes a difference for Electrum.
+    pub fetch_prev_txouts: bool,
+}
+
+impl Default for BackendOptions {
+    fn default() -> Self {
+        Self {
+            stop_gap: 20,
+            batch_size: 5,
+            fetch_prev_txouts: true,
+        }
+    }
+}
+
+/// The error of a [`SyncBackend`] or an [`AsyncSyncBackend`]
+///
+/// The error of the chain source is boxed, so that backends with different error types can be
+/// used behind the same trait object.
+#[cfg(feature = "std")]
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A chain source which can full scan and sync the requests of this module
+///
+/// This lets an application switch between chain sources at runtime, holding a
+/// `Box<dyn SyncBackend<K>>`. It's implemented by the clients of `bdk_electrum` and
+/// `bdk_esplora`, the results have the anchors of a [`FullScanResult`] and a [`SyncResult`] by
+/// default.
+#[cfg(feature = "std")]
+pub trait SyncBackend<K> {
+    /// Scan the keychains of `request` for transactions, see [`FullScanRequest`]
+    fn full_scan(
+        &self,
+        request: FullScanRequest<K>,
+        options: BackendOptions,
+    ) -> Result<FullScanResult<K>, BackendError>;
+
+    /// Sync the script pubkeys, txids and outpoints of `request`, see [`SyncRequest`]
+    ///
+    /// [`BackendOptions::stop_gap`] is ignored.
+    fn sync(
+        &self,
+        request: SyncRequest,
+        options: BackendOptions,
+    ) -> Result<SyncResult, BackendError>;
+}
+
+/// The future returned by the methods of an [`AsyncSyncBackend`]
+#[cfg(feature = "std")]
+pub type BackendFuture<'a, T> =
+    core::pin::Pin<Box<dyn core::future::Future<Output = Result<T, BackendError>> + Send + 'a>>;
+
+/// The async version of [`SyncBackend`]
+///
+/// The methods return boxed futures so that the trait can be used as a trait object, holding a
+/// `Box<dyn AsyncSyncBackend<K>>`.
+#[cfg(feature = "std")]
+pub trait AsyncSyncBackend<K> {
+    /// Scan the keychains of `request` for transactions, see [`SyncBackend::full_scan`]
+    fn full_scan<'a>(
+        &'a self,
+        request: FullScanRequest<K>,
+        options: BackendOptions,
+    ) -> BackendFuture<'a, FullScanResult<K>>
+    where
+        K: 'a;
+
+    /// Sync the script pubkeys, txids and outpoints of `request`, see [`SyncBackend::sync`]
+    fn sync<'a>(
+        &'a self,
+        request: SyncRequest,
+        options: BackendOptions,
+    ) -> BackendFuture<'a, SyncResult>;
+}
+
+/// A chain source which can broadcast transactions
+///
+/// It's implemented by the clients of `bdk_electrum` and the blocking client of `bdk_esplora`.
+/// `Wallet::broadcast_with` of `bdk_wallet` records the outcome of the broadcasts made through
+/// it under its [`name`](Self::name).
+#[cfg(feature = "std")]
+pub trait BroadcastBackend {
+    /// The name of the chain source
+    fn name(&self) -> &str;
+
+    /// Broadcast `tx`, returning its txid
+    ///
+    /// A transaction already in the mempool of the chain source is reported as broadcast.
+    fn broadcast(&self, tx: &bitcoin::Transaction) -> Result<Txid, BroadcastError<BackendError>>;
+
+    /// Broadcast a package of transactions, ordered parents first, returning a result per
+    /// broadcast transaction
+    ///
+    /// By default the transactions are broadcast one by one with [`broadcast`](Self::broadcast),
+    /// until one fails: the transactions after it aren't broadcast and have no result. A chain
+    /// source accepting packages, such as the `submitpackage` RPC of Bitcoin Core, can submit
+    /// them at once and return a result for each one.
+    fn broadcast_package(
+        &self,
+        txs: &[bitcoin::Transaction],
+    ) -> Vec<Result<Txid, BroadcastError<BackendError>>> {
+        let mut results = Vec::with_capacity(txs.len());
+        for tx in txs {
+            let result = self.broadcast(tx);
+            let failed = result.is_err();
+            results.push(result);
+            if failed {
+                break;
+            }
+        }
+        results
+    }
+
+    /// Whether the chain source knows the transaction `txid`, in its mempool or in a block
+    ///
+    /// [`broadcast_with_retry`] looks the transaction up before retrying a failed broadcast, so
+    /// that a transaction which made it isn't submitted again. By default the transactions can't
+    /// be looked up and `false` is returned.
+    fn has_tx(&self, txid: Txid) -> Result<bool, BackendError> {
+        let _ = txid;
+        Ok(false)
+    }
+}
+
+/// The error of a request which the chain source asks to retry after `delay`, such as with the
+/// `Retry-After` header of an HTTP response
+///
+/// A [`BroadcastBackend`] returns it, or an error whose [`source`](std::error::Error::source) is
+/// it, for [`broadcast_with_retry`] to wait at least `delay` before the next attempt.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct RetryAfter {
+    /// The delay the chain source asks for
+    pub delay: Duration,
+    /// The error of the request
+    pub error: BackendError,
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} (retry after {:?})", self.error, self.delay)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RetryAfter {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+/// How [`broadcast_with_retry`] retries a broadcast which failed with a transient error
+///
+/// The delay before the `n`th retry is `base_delay * 2^(n - 1)`, capped at `max_delay`, or the
+/// [`RetryAfter`] delay of the error if it's longer. The broadcast is given up when the chain
+/// source asks for a delay longer than `max_delay`.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BroadcastRetryPolicy {
+    /// The maximum number of times the broadcast is retried, 0 to never retry
+    pub max_retries: u32,
+    /// The delay before the first retry
+    pub base_delay: Duration,
+    /// The maximum delay between two attempts
+    pub max_delay: Duration,
+}
+
+#[cfg(feature = "std")]
+impl Default for BroadcastRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl BroadcastRetryPolicy {
+    /// The delay before the retry after `retries` retries which failed with `error`, `None` if
+    /// the broadcast must be given up.
+    fn retry_delay(&self, retries: u32, error: &BackendError) -> Option<Duration> {
+        if retries >= self.max_retries {
+            return None;
+        }
+        let backoff = self
+            .base_delay
+            .checked_mul(1 << retries.min(31))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        match retry_after(error) {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay.max(backoff)),
+            None => Some(backoff),
+        }
+    }
+}
+
+/// The [`RetryAfter`] delay of `error` or of one of its sources
+#[cfg(feature = "std")]
+fn retry_after(error: &BackendError) -> Option<Duration> {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error.as_ref());
+    while let Some(error) = source {
+        if let Some(retry_after) = error.downcast_ref::<RetryAfter>() {
+            return Some(retry_after.delay);
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// Why [`broadcast_with_retry`] didn't broadcast a transaction
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum BroadcastRetryError {
+    /// The chain source rejected the transaction, it's not broadcast
+    ///
+    /// This is never a [`BroadcastError::Request`].
+    Rejected(BroadcastError<BackendError>),
+    /// The requests kept failing until the retries were exhausted: the transaction may or may not
+    /// have been broadcast
+    ///
+    /// The transaction must be looked up again before it's replaced by one which doesn't conflict
+    /// with it, or it may be paid twice.
+    Exhausted {
+        /// The number of retries made after the first broadcast
+        retries: u32,
+        /// The error of the last request, a broadcast or a lookup of the transaction
+        error: BackendError,
+    },
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Display for BroadcastRetryError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Rejected(error) => write!(f, "{}", error),
+            Self::Exhausted { retries, error } => write!(
+                f,
+                "the broadcast failed after {} retries, the transaction may have been broadcast: {}",
+                retries, error
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BroadcastRetryError {}
+
+/// Broadcast `tx` through `backend`, retrying the broadcast while it fails with a transient
+/// error according to `policy`
+///
+/// A broadcast fails with a transient error when the request fails, such as when an Esplora
+/// server answers with a 502, an Electrum request times out or the work queue of bitcoind is full,
+/// since the chain source may still have received the transaction. Before each retry, the
+/// transaction is looked up with [`BroadcastBackend::has_tx`] and isn't submitted again if the
+/// chain source knows it. A transaction reported as already confirmed by a retry was broadcast by
+/// an earlier attempt.
+///
+/// The retries block the current thread. The chain sources may retry their own requests too, so
+/// they should be configured not to when they're wrapped here.
+#[cfg(feature = "std")]
+pub fn broadcast_with_retry<B>(
+    backend: &B,
+    tx: &bitcoin::Transaction,
+    policy: &BroadcastRetryPolicy,
+) -> Result<Txid, BroadcastRetryError>
+where
+    B: BroadcastBackend + ?Sized,
+{
+    let txid = tx.compute_txid();
+    let mut error = match backend.broadcast(tx) {
+        Ok(txid) => return Ok(txid),
+        Err(BroadcastError::Request(error)) => error,
+        Err(error) => return Err(BroadcastRetryError::Rejected(error)),
+    };
+    let mut retries = 0;
+    loop {
+        let delay = match policy.retry_delay(retries, &error) {
+            Some(delay) => delay,
+            None => return Err(BroadcastRetryError::Exhausted { retries, error }),
+        };
+        std::thread::sleep(delay);
+        retries += 1;
+        match backend.has_tx(txid) {
+            Ok(true) => return Ok(txid),
+            Ok(false) => {}
+            Err(lookup_error) => {
+                error = lookup_error;
+                continue;
+            }
+        }
+        error = match backend.broadcast(tx) {
+            Ok(_) | Err(BroadcastError::AlreadyConfirmed) => return Ok(txid),
+            Err(BroadcastError::Request(error)) => error,
+            Err(error) => return Err(BroadcastRetryError::Rejected(error)),
+        };
+    }
+}
+
+/// How many requests a [`SyncScheduler`] can make in a window of time, to stay under the rate
+/// limit of a chain source
+///
+/// Each script pubkey and txid of a [`SyncRequest`] costs one request, which is what it costs with
+/// Esplora, unless a script pubkey has more than a page of transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestBudget {
+    /// The max number of requests of a window, at least one
+    pub max_requests: usize,
+    /// The duration of a window, in seconds
+    pub window_secs: u64,
+}
+
+/// The script pubkeys and txids of a keychain to sync, see [`SyncScheduler::add`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeychainRequest<K> {
+    /// The keychain
+    pub keychain: K,
+    /// The script pubkeys of the keychain to sync, typically the revealed ones
+    pub spks: Vec<ScriptBuf>,
+    /// The txids to sync with the keychain, typically its unconfirmed transactions
+    pub txids: Vec<Txid>,
+    /// When the keychain was last synced, in unix seconds, `None` if it never was
+    pub last_synced: Option<u64>,
+}
+
+/// A part of the sync of a keychain handed out by [`SyncScheduler::next_request`]
+///
+/// It's passed back to the scheduler with [`SyncScheduler::complete`] once the chain source
+/// returned the result of the request, or with [`SyncScheduler::requeue`] if it failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncTicket<K> {
+    /// The keychain
+    pub keychain: K,
+    /// The script pubkeys of the request
+    pub spks: Vec<ScriptBuf>,
+    /// The txids of the request
+    pub txids: Vec<Txid>,
+}
+
+impl<K> SyncTicket<K> {
+    /// The number of requests counted against the [`RequestBudget`]
+    pub fn cost(&self) -> usize {
+        self.spks.len() + self.txids.len()
+    }
+}
+
+/// What [`SyncScheduler::next_request`] hands out
+pub enum Scheduled<K> {
+    /// The next request to send to the chain source
+    Request(SyncTicket<K>, SyncRequest),
+    /// The budget of the window is spent, the next request can be made at this time, in unix
+    /// seconds
+    Wait(u64),
+    /// Every keychain was handed out
+    Done,
+}
+
+/// Splits the sync of many keychains into [`SyncRequest`]s which fit a [`RequestBudget`], by
+/// priority
+///
+/// The keychain with the highest priority is synced first, the stalest one first between equal
+/// priorities. A keychain larger than what's left of the budget of the window is split, its
+/// remaining script pubkeys are synced in the next windows. The priority is meant to be the time
+/// of the last activity of the keychain, so that the recently active keychains are synced first:
+/// when a request finds transactions, the rest of its keychain gets the priority `now`.
+///
+/// The scheduler doesn't depend on the chain source: it hands out the requests and is told about
+/// their results, see [`next_request`](Self::next_request) and [`complete`](Self::complete), or
+/// [`sync_next`](Self::sync_next) for a [`SyncBackend`]. The time each keychain finished syncing
+/// is returned by [`last_synced`](Self::last_synced), to be persisted so that the next run knows
+/// which keychains are the most out of date. A run which was interrupted can be resumed this way:
+/// the keychains which didn't finish keep their older time and are synced first again.
+#[derive(Debug, Clone)]
+pub struct SyncScheduler<K> {
+    budget: RequestBudget,
+    queue: BTreeMap<K, PendingKeychain>,
+    in_flight: BTreeMap<K, InFlight>,
+    window_start: Option<u64>,
+    spent: usize,
+    last_synced: BTreeMap<K, u64>,
+}
+
+#[derive(Debug, Clone)]
+struct PendingKeychain {
+    spks: VecDeque<ScriptBuf>,
+    txids: VecDeque<Txid>,
+    priority: u64,
+    last_synced: Option<u64>,
+}
+
+/// The requests of a keychain which were handed out and not completed yet, with what's needed to
+/// requeue them
+#[derive(Debug, Clone)]
+struct InFlight {
+    count: usize,
+    priority: u64,
+    last_synced: Option<u64>,
+}
+
+impl<K: Ord + Clone> SyncScheduler<K> {
+    /// A scheduler of no keychains, spending at most `budget`
+    pub fn new(budget: RequestBudget) -> Self {
+        Self {
+            budget,
+            queue: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+            window_start: None,
+            spent: 0,
+            last_synced: BTreeMap::new(),
+        }
+    }
+
+    /// Add the keychain of `request` with `priority`, replacing it if it was added already
+    #[must_use]
+    pub fn add(mut self, request: KeychainRequest<K>, priority: u64) -> Self {
+        self.queue.insert(
+            request.keychain,
+            PendingKeychain {
+                spks: request.spks.into(),
+                txids: request.txids.into(),
+                priority,
+                last_synced: request.last_synced,
+            },
+        );
+        self
+    }
+
+    /// The keychains left to hand out, in the order they will be
+    pub fn pending(&self) -> Vec<K> {
+        let mut pending = self
+            .queue
+            .iter()
+            .map(|(keychain, pending)| (keychain, pending.priority, pending.last_synced))
+            .collect::<Vec<_>>();
+        pending.sort_by(|(ka, pa, la), (kb, pb, lb)| pb.cmp(pa).then(la.cmp(lb)).then(ka.cmp(kb)));
+        pending.into_iter().map(|(k, _, _)| k.clone()).collect()
+    }
+
+    /// Whether every request was handed out and completed
+    pub fn is_done(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// The time the keychains synced by the scheduler finished syncing, in unix seconds
+    pub fn last_synced(&self) -> &BTreeMap<K, u64> {
+        &self.last_synced
+    }
+
+    /// Hand out the next request at the time `now`, in unix seconds, starting from `chain_tip`
+    pub fn next_request(&mut self, chain_tip: CheckPoint, now: u64) -> Scheduled<K> {
+        let keychain = match self.pending().into_iter().next() {
+            Some(keychain) => keychain,
+            None => return Scheduled::Done,
+        };
+        let max_requests = self.budget.max_requests.max(1);
+        match self.window_start {
+            Some(start) if now < start.saturating_add(self.budget.window_secs) => {
+                if self.spent >= max_requests {
+                    return Scheduled::Wait(start.saturating_add(self.budget.window_secs));
+                }
+            }
+            _ => {
+                self.window_start = Some(now);
+                self.spent = 0;
+            }
+        }
+
+        let pending = self.queue.get_mut(&keychain).expect("pending keychain");
+        let mut left = max_requests - self.spent;
+        let spks = pending
+            .spks
+            .drain(..left.min(pending.spks.len()))
+            .collect::<Vec<_>>();
+        left -= spks.len();
+        let txids = pending
+            .txids
+            .drain(..left.min(pending.txids.len()))
+            .collect::<Vec<_>>();
+        let (priority, last_synced) = (pending.priority, pending.last_synced);
+        if pending.spks.is_empty() && pending.txids.is_empty() {
+            self.queue.remove(&keychain);
+        }
+        self.in_flight
+            .entry(keychain.clone())
+            .or_insert(InFlight {
+                count: 0,
+                priority,
+                last_synced,
+            })
+            .count += 1;
+
+        let ticket = SyncTicket {
+            keychain,
+            spks,
+            txids,
+        };
+        self.spent += ticket.cost();
+        let request = SyncRequest::from_chain_tip(chain_tip)
+            .set_spks(ticket.spks.clone())
+            .set_txids(ticket.txids.clone());
+        Scheduled::Request(ticket, request)
+    }
+
+    /// Report that the request of `ticket` completed at the time `now` with `result`
+    ///
+    /// If the result has transactions, the rest of the keychain gets the priority `now`. Once all
+    /// the requests of the keychain completed, `now` is its [`last_synced`](Self::last_synced)
+    /// time.
+    pub fn complete<A>(&mut self, ticket: &SyncTicket<K>, result: &SyncResult<A>, now: u64) {
+        if result.graph_update.full_txs().next().is_some() {
+            if let Some(pending) = self.queue.get_mut(&ticket.keychain) {
+                pending.priority = pending.priority.max(now);
+            }
+            if let Some(in_flight) = self.in_flight.get_mut(&ticket.keychain) {
+                in_flight.priority = in_flight.priority.max(now);
+            }
+        }
+        self.finish_request(&ticket.keychain);
+        if !self.queue.contains_key(&ticket.keychain)
+            && !self.in_flight.contains_key(&ticket.keychain)
+        {
+            self.last_synced.insert(ticket.keychain.clone(), now);
+        }
+    }
+
+    /// Give back the request of `ticket`, which failed, to hand it out again
+    ///
+    /// The budget it spent isn't refunded, the chain source may have counted the requests.
+    pub fn requeue(&mut self, ticket: SyncTicket<K>) {
+        let (priority, last_synced) = match self.in_flight.get(&ticket.keychain) {
+            Some(in_flight) => (in_flight.priority, in_flight.last_synced),
+            None => (0, None),
+        };
+        self.finish_request(&ticket.keychain);
+        let pending = self
+            .queue
+            .entry(ticket.keychain)
+            .or_insert_with(|| PendingKeychain {
+                spks: VecDeque::new(),
+                txids: VecDeque::new(),
+                priority,
+                last_synced,
+            });
+        for spk in ticket.spks.into_iter().rev() {
+            pending.spks.push_front(spk);
+        }
+        for txid in ticket.txids.into_iter().rev() {
+            pending.txids.push_front(txid);
+        }
+    }
+
+    fn finish_request(&mut self, keychain: &K) {
+        if let Some(in_flight) = self.in_flight.get_mut(keychain) {
+            in_flight.count -= 1;
+            if in_flight.count == 0 {
+                self.in_flight.remove(keychain);
+            }
+        }
+    }
+
+    /// Sync the next request with `backend` at the time `now`, see
+    /// [`next_request`](Self::next_request)
+    ///
+    /// Returns the keychain and the result of the request, or `None` if the budget of the window
+    /// is spent or every keychain was synced. A failed request is requeued.
+    #[cfg(feature = "std")]
+    pub fn sync_next<B: SyncBackend<K> + ?Sized>(
+        &mut self,
+        backend: &B,
+        chain_tip: CheckPoint,
+        options: BackendOptions,
+        now: u64,
+    ) -> Result<Option<(K, SyncResult)>, BackendError> {
+        let (ticket, request) = match self.next_request(chain_tip, now) {
+            Scheduled::Request(ticket, request) => (ticket, request),
+            Scheduled::Wait(_) | Scheduled::Done => return Ok(None),
+        };
+        match backend.sync(request, options) {
+            Ok(result) => {
+                self.complete(&ticket, &result, now);
+                Ok(Some((ticket.keychain, result)))
+            }
+            Err(e) => {
+                self.requeue(ticket);
+                Err(e)
+            }
+        }
+    }
 }
 
 /// A version of [`core::iter::Chain`] which can combine two [`ExactSizeIterator`]s to form a new
@@ -387,3 +1459,377 @@ where
         a_len + b_len
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scan_progress_tracker() {
+        let spks = |n: u32| (0..n).map(|i| (i, ScriptBuf::from_bytes(vec![i as u8])));
+        use bitcoin::hashes::Hash;
+        let (chain, _) =
+            crate::local_chain::LocalChain::from_genesis_hash(bitcoin::BlockHash::all_zeros());
+        let mut request = FullScanRequest::from_chain_tip(chain.tip())
+            .set_spks_for_keychain(0, spks(100))
+            .set_spks_for_keychain(1, spks(3))
+            .set_stop_gap_for_keychain(0, 10);
+        let mut tracker = request.progress_tracker(5);
+        assert!(request.progress.is_none());
+        // the stop gap, no more than the script pubkeys of the request
+        assert_eq!(tracker.progress().keychains[&0].spks_total, 10);
+        assert_eq!(tracker.progress().keychains[&1].spks_total, 3);
+
+        tracker.spk_checked(&0, 0);
+        tracker.spk_checked(&0, 2);
+        tracker.batch_done(Duration::from_secs(1));
+        assert_eq!(tracker.progress().keychains[&0].spks_total, 12);
+        assert_eq!(tracker.progress().txs_fetched, 2);
+        assert_eq!(tracker.progress().eta, None);
+
+        // 2 script pubkeys per second, 8 of keychain 0 and 3 of keychain 1 left
+        tracker.spk_checked(&0, 0);
+        tracker.spk_checked(&0, 0);
+        tracker.batch_done(Duration::from_secs(2));
+        assert_eq!(tracker.progress().batch, 2);
+        assert_eq!(tracker.progress().eta, Some(Duration::from_millis(5500)));
+        assert!(tracker.progress().total_is_estimate);
+
+        tracker.keychain_done(&0);
+        assert_eq!(tracker.progress().keychains[&0].spks_total, 4);
+        assert!(tracker.progress().total_is_estimate);
+        tracker.finish(Duration::from_secs(3));
+        assert!(!tracker.progress().total_is_estimate);
+        assert_eq!(tracker.progress().spks_total(), 4);
+        assert_eq!(tracker.progress().eta, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_sync_scheduler() {
+        use bitcoin::hashes::Hash;
+        let (chain, _) =
+            crate::local_chain::LocalChain::from_genesis_hash(bitcoin::BlockHash::all_zeros());
+        let request = |keychain: u8, spks: u8, last_synced: Option<u64>| KeychainRequest {
+            keychain,
+            spks: (0..spks)
+                .map(|i| ScriptBuf::from_bytes(vec![keychain, i]))
+                .collect(),
+            txids: vec![],
+            last_synced,
+        };
+        let result = |has_tx: bool| {
+            let mut graph_update = TxGraph::<crate::BlockId>::default();
+            if has_tx {
+                let _ = graph_update.insert_tx(bitcoin::Transaction {
+                    version: bitcoin::transaction::Version::TWO,
+                    lock_time: bitcoin::absolute::LockTime::ZERO,
+                    input: vec![],
+                    output: vec![],
+                });
+            }
+            SyncResult {
+                graph_update,
+                chain_update: chain.tip(),
+            }
+        };
+        let next = |scheduler: &mut SyncScheduler<u8>, now: u64| match scheduler
+            .next_request(chain.tip(), now)
+        {
+            Scheduled::Request(ticket, request) => {
+                assert_eq!(request.spks.len(), ticket.spks.len());
+                ticket
+            }
+            _ => panic!("must hand out a request"),
+        };
+        let mut scheduler = SyncScheduler::new(RequestBudget {
+            max_requests: 4,
+            window_secs: 60,
+        })
+        .add(request(0, 2, Some(100)), 0)
+        .add(request(1, 2, None), 0)
+        .add(request(2, 6, Some(50)), 10)
+        .add(request(3, 1, Some(10)), 5);
+        // by priority, then the stalest first
+        assert_eq!(scheduler.pending(), vec![2, 3, 1, 0]);
+
+        // keychain 2 is split to fit the budget of the window
+        let ticket = next(&mut scheduler, 1000);
+        assert_eq!((ticket.keychain, ticket.cost()), (2, 4));
+        assert!(matches!(
+            scheduler.next_request(chain.tip(), 1030),
+            Scheduled::Wait(1060)
+        ));
+        scheduler.complete(&ticket, &result(true), 1030);
+        assert!(!scheduler.last_synced().contains_key(&2));
+
+        // its activity puts it before a keychain added in the meantime
+        scheduler = scheduler.add(request(4, 1, None), 1000);
+        assert_eq!(scheduler.pending(), vec![2, 4, 3, 1, 0]);
+
+        // a new window, the rest of keychain 2 fails and is requeued
+        let ticket = next(&mut scheduler, 1060);
+        assert_eq!((ticket.keychain, ticket.cost()), (2, 2));
+        scheduler.requeue(ticket);
+        assert_eq!(scheduler.pending(), vec![2, 4, 3, 1, 0]);
+        // the failed request isn't refunded
+        let ticket = next(&mut scheduler, 1061);
+        assert_eq!((ticket.keychain, ticket.cost()), (2, 2));
+        scheduler.complete(&ticket, &result(false), 1061);
+        assert_eq!(scheduler.last_synced().get(&2), Some(&1061));
+        assert!(matches!(
+            scheduler.next_request(chain.tip(), 1062),
+            Scheduled::Wait(1120)
+        ));
+
+        // a request can be in flight while the next one is handed out
+        let ticket_4 = next(&mut scheduler, 1120);
+        let ticket_3 = next(&mut scheduler, 1120);
+        let ticket_1 = next(&mut scheduler, 1120);
+        assert_eq!(
+            [ticket_4.cost(), ticket_3.cost(), ticket_1.cost()],
+            [1, 1, 2]
+        );
+        scheduler.complete(&ticket_1, &result(false), 1121);
+        scheduler.complete(&ticket_3, &result(false), 1122);
+        assert_eq!(scheduler.pending(), vec![0]);
+        let ticket_0 = next(&mut scheduler, 1180);
+        assert!(matches!(
+            scheduler.next_request(chain.tip(), 1180),
+            Scheduled::Done
+        ));
+        scheduler.complete(&ticket_0, &result(false), 1181);
+        assert!(!scheduler.is_done());
+        scheduler.complete(&ticket_4, &result(false), 1182);
+        assert!(scheduler.is_done());
+        assert_eq!(
+            scheduler
+                .last_synced()
+                .clone()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![(0, 1181), (1, 1121), (2, 1061), (3, 1122), (4, 1182)]
+        );
+    }
+
+    #[test]
+    fn test_broadcast_error_from_reject_reason() {
+        type Error = BroadcastError<core::convert::Infallible>;
+        let reason = |reason: &str| Error::from_reject_reason(reason);
+        assert!(matches!(
+            reason("sendrawtransaction RPC error: {\"code\":-25,\"message\":\"bad-txns-inputs-missingorspent\"}"),
+            Some(BroadcastError::MissingInputs)
+        ));
+        assert!(matches!(
+            reason("missing-inputs"),
+            Some(BroadcastError::MissingInputs)
+        ));
+        assert!(matches!(
+            reason("txn-mempool-conflict"),
+            Some(BroadcastError::MempoolConflict)
+        ));
+        assert!(matches!(
+            reason("min relay fee not met, 100 < 110"),
+            Some(BroadcastError::MinRelayFeeNotMet)
+        ));
+        assert!(matches!(
+            reason("insufficient fee, rejecting replacement"),
+            Some(BroadcastError::InsufficientFee)
+        ));
+        assert!(matches!(
+            reason("max-fee-exceeded"),
+            Some(BroadcastError::MaxFeeRateExceeded)
+        ));
+        assert!(matches!(
+            reason("dust"),
+            Some(BroadcastError::NonStandard(r)) if r == "dust"
+        ));
+        assert!(matches!(
+            reason("TRUC-violation, tx is too big"),
+            Some(BroadcastError::TrucViolation(_))
+        ));
+        assert!(matches!(
+            reason("Transaction already in block chain"),
+            Some(BroadcastError::AlreadyConfirmed)
+        ));
+        assert!(reason("txn-already-in-mempool").is_none());
+        assert!(matches!(
+            reason("non-final"),
+            Some(BroadcastError::Rejected(r)) if r == "non-final"
+        ));
+    }
+
+    /// A chain source whose first `failures` requests fail, the failed broadcasts reaching it if
+    /// `lost_responses`
+    #[cfg(feature = "std")]
+    #[derive(Default)]
+    struct FlakyBackend {
+        failures: core::cell::Cell<u32>,
+        lost_responses: bool,
+        retry_after: Option<Duration>,
+        rejection: core::cell::Cell<Option<BroadcastError<BackendError>>>,
+        received: core::cell::Cell<bool>,
+        broadcasts: core::cell::Cell<u32>,
+        lookups: core::cell::Cell<u32>,
+    }
+
+    #[cfg(feature = "std")]
+    impl FlakyBackend {
+        fn fail(&self) -> Option<BackendError> {
+            let failures = self.failures.get();
+            if failures == 0 {
+                return None;
+            }
+            self.failures.set(failures - 1);
+            let error = BackendError::from("502 Bad Gateway");
+            Some(match self.retry_after {
+                Some(delay) => Box::new(RetryAfter { delay, error }),
+                None => error,
+            })
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl BroadcastBackend for FlakyBackend {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn broadcast(
+            &self,
+            tx: &bitcoin::Transaction,
+        ) -> Result<Txid, BroadcastError<BackendError>> {
+            self.broadcasts.set(self.broadcasts.get() + 1);
+            if let Some(error) = self.fail() {
+                self.received.set(self.lost_responses);
+                return Err(BroadcastError::Request(error));
+            }
+            match self.rejection.take() {
+                Some(error) => Err(error),
+                None => {
+                    self.received.set(true);
+                    Ok(tx.compute_txid())
+                }
+            }
+        }
+
+        fn has_tx(&self, _txid: Txid) -> Result<bool, BackendError> {
+            self.lookups.set(self.lookups.get() + 1);
+            match self.fail() {
+                Some(error) => Err(error),
+                None => Ok(self.received.get()),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_broadcast_with_retry() {
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+        let txid = tx.compute_txid();
+        let policy = BroadcastRetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::from_secs(1),
+        };
+
+        // the server receives the tx but the response is lost, then the first lookup fails too:
+        // the second lookup finds the tx, which isn't submitted again
+        let backend = FlakyBackend {
+            failures: 2.into(),
+            lost_responses: true,
+            ..Default::default()
+        };
+        assert_eq!(broadcast_with_retry(&backend, &tx, &policy).unwrap(), txid);
+        assert_eq!(backend.broadcasts.get(), 1);
+        assert_eq!(backend.lookups.get(), 2);
+
+        // the tx didn't make it, it's submitted again
+        let backend = FlakyBackend {
+            failures: 1.into(),
+            ..Default::default()
+        };
+        assert_eq!(broadcast_with_retry(&backend, &tx, &policy).unwrap(), txid);
+        assert_eq!(backend.broadcasts.get(), 2);
+        assert_eq!(backend.lookups.get(), 1);
+
+        // a retry reporting the tx as confirmed means an earlier attempt broadcast it
+        let backend = FlakyBackend {
+            failures: 1.into(),
+            rejection: Some(BroadcastError::AlreadyConfirmed).into(),
+            ..Default::default()
+        };
+        assert_eq!(broadcast_with_retry(&backend, &tx, &policy).unwrap(), txid);
+
+        // a rejection is definitive
+        let backend = FlakyBackend {
+            rejection: Some(BroadcastError::MinRelayFeeNotMet).into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            broadcast_with_retry(&backend, &tx, &policy),
+            Err(BroadcastRetryError::Rejected(
+                BroadcastError::MinRelayFeeNotMet
+            ))
+        ));
+        assert_eq!(backend.broadcasts.get(), 1);
+        assert_eq!(backend.lookups.get(), 0);
+
+        // the status of the tx is unknown once the retries are exhausted
+        let backend = FlakyBackend {
+            failures: u32::MAX.into(),
+            ..Default::default()
+        };
+        let err = broadcast_with_retry(&backend, &tx, &policy).unwrap_err();
+        assert!(matches!(
+            err,
+            BroadcastRetryError::Exhausted { retries: 3, .. }
+        ));
+        assert!(alloc::string::ToString::to_string(&err).contains("502 Bad Gateway"));
+        assert_eq!(backend.broadcasts.get(), 1);
+        assert_eq!(backend.lookups.get(), 3);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_broadcast_with_retry_after() {
+        let tx = bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+        let policy = BroadcastRetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::from_millis(100),
+        };
+
+        // the delay asked by the chain source is longer than the backoff
+        let backend = FlakyBackend {
+            failures: 1.into(),
+            retry_after: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        assert!(broadcast_with_retry(&backend, &tx, &policy).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(backend.broadcasts.get(), 2);
+
+        // the broadcast is given up rather than retried sooner than asked
+        let backend = FlakyBackend {
+            failures: 1.into(),
+            retry_after: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            broadcast_with_retry(&backend, &tx, &policy),
+            Err(BroadcastRetryError::Exhausted { retries: 0, .. })
+        ));
+        assert_eq!(backend.lookups.get(), 0);
+    }
+}
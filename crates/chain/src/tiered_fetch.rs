@@ -0,0 +1,388 @@
+//! Fetch the relevant transactions of the blocks matched by compact block filters, falling back
+//! from one chain source to the next, see [`TieredFetcher`].
+//!
+//! A wallet can detect its transactions with the [BIP158] filters of its own node, and still not
+//! be able to fetch the matched blocks from it, if the node is pruned below their height. A
+//! [`TieredFetcher`] tries an ordered list of [`BlockTxSource`]s for each matched height, such as
+//! the node, then an Esplora server, then an Electrum server, and records which one served each
+//! block.
+//!
+//! [BIP158]: https://github.com/bitcoin/bips/blob/master/bip-0158.mediawiki
+
+use crate::collections::{BTreeMap, BTreeSet};
+use crate::spk_client::BackendError;
+use crate::tx_graph::TxGraph;
+use crate::{BlockId, ConfirmationTimeHeightAnchor};
+use alloc::{string::String, vec::Vec};
+use bitcoin::{BlockHash, OutPoint, ScriptBuf, Transaction};
+use core::fmt;
+
+/// A block fetched by a [`BlockTxSource`], with its relevant transactions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTxs {
+    /// The block
+    pub block: BlockId,
+    /// The time of the block header
+    pub time: u64,
+    /// The transactions of the block, at least the relevant ones
+    pub txs: Vec<Transaction>,
+}
+
+/// A chain source which can fetch the transactions of a block relevant to script pubkeys
+///
+/// It's implemented by the clients of `bdk_electrum`, the blocking client of `bdk_esplora` and
+/// the `RpcBlockTxs` of `bdk_bitcoind_rpc`.
+pub trait BlockTxSource {
+    /// The name of the chain source, recorded in [`TieredUpdate::served_by`]
+    fn name(&self) -> &str;
+
+    /// Fetch the block at `height` of the best chain, with its transactions which pay to or spend
+    /// from one of `spks`
+    ///
+    /// The source may return more transactions, such as all the transactions of the block, the
+    /// [`TieredFetcher`] only keeps the relevant ones.
+    fn block_txs(&self, height: u32, spks: &BTreeSet<ScriptBuf>) -> Result<BlockTxs, BackendError>;
+}
+
+impl<S: BlockTxSource + ?Sized> BlockTxSource for &S {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn block_txs(&self, height: u32, spks: &BTreeSet<ScriptBuf>) -> Result<BlockTxs, BackendError> {
+        (**self).block_txs(height, spks)
+    }
+}
+
+/// A [`BlockTxSource`] which failed to serve a block, and was skipped for the next one
+#[derive(Debug)]
+pub struct TierFailure {
+    /// The height of the block
+    pub height: u32,
+    /// The name of the source
+    pub tier: String,
+    /// Why the source failed
+    pub error: BackendError,
+}
+
+/// The result of [`TieredFetcher::fetch`]
+#[derive(Debug, Default)]
+pub struct TieredUpdate {
+    /// The relevant transactions, anchored in their block
+    pub graph_update: TxGraph<ConfirmationTimeHeightAnchor>,
+    /// The fetched blocks, by height
+    pub blocks: BTreeMap<u32, BlockHash>,
+    /// The name of the source which served each block, by height
+    pub served_by: BTreeMap<u32, String>,
+    /// The sources which failed, in the order they were tried
+    pub failures: Vec<TierFailure>,
+}
+
+/// Fetch the relevant transactions of blocks from the first of several [`BlockTxSource`]s which
+/// can serve them
+///
+/// The sources are tried in the order they were added, a source which fails for a block is
+/// recorded in [`TieredUpdate::failures`] and the next one is tried. The result is the same
+/// whichever source serves a block: a transaction is kept if it pays to one of the script pubkeys
+/// of the fetcher, or spends one of its outpoints. The outputs of the kept transactions which pay
+/// to the script pubkeys are added to the outpoints, so the blocks are fetched by increasing
+/// height.
+pub struct TieredFetcher<'a> {
+    tiers: Vec<&'a dyn BlockTxSource>,
+    spks: BTreeSet<ScriptBuf>,
+    outpoints: BTreeSet<OutPoint>,
+}
+
+impl<'a> fmt::Debug for TieredFetcher<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TieredFetcher")
+            .field(
+                "tiers",
+                &self
+                    .tiers
+                    .iter()
+                    .map(|tier| tier.name())
+                    .collect::<Vec<_>>(),
+            )
+            .field("spks", &self.spks.len())
+            .field("outpoints", &self.outpoints.len())
+            .finish()
+    }
+}
+
+impl<'a> Default for TieredFetcher<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> TieredFetcher<'a> {
+    /// A fetcher without sources, script pubkeys and outpoints
+    pub fn new() -> Self {
+        Self {
+            tiers: Vec::new(),
+            spks: BTreeSet::new(),
+            outpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Add a source, tried after the ones already added
+    pub fn with_tier(mut self, tier: &'a dyn BlockTxSource) -> Self {
+        self.tiers.push(tier);
+        self
+    }
+
+    /// Add script pubkeys whose transactions are relevant
+    pub fn add_spks(&mut self, spks: impl IntoIterator<Item = ScriptBuf>) {
+        self.spks.extend(spks);
+    }
+
+    /// Add outpoints whose spending transactions are relevant, such as the outputs of the wallet
+    /// confirmed below the fetched blocks
+    pub fn add_outpoints(&mut self, outpoints: impl IntoIterator<Item = OutPoint>) {
+        self.outpoints.extend(outpoints);
+    }
+
+    /// Fetch the blocks at `heights`, by increasing height
+    ///
+    /// # Errors
+    ///
+    /// Fails if no source can serve one of the blocks, with the failures of all the sources.
+    pub fn fetch(
+        &mut self,
+        heights: impl IntoIterator<Item = u32>,
+    ) -> Result<TieredUpdate, TieredFetchError> {
+        let heights = heights.into_iter().collect::<BTreeSet<u32>>();
+        let mut update = TieredUpdate::default();
+        for height in heights {
+            let mut failures = Vec::new();
+            let mut served = None;
+            for tier in &self.tiers {
+                match tier.block_txs(height, &self.spks) {
+                    Ok(block_txs) if block_txs.block.height != height => {
+                        failures.push(TierFailure {
+                            height,
+                            tier: String::from(tier.name()),
+                            error: format!(
+                                "served the block at height {} instead of {}",
+                                block_txs.block.height, height
+                            )
+                            .into(),
+                        })
+                    }
+                    Ok(block_txs) => {
+                        served = Some((String::from(tier.name()), block_txs));
+                        break;
+                    }
+                    Err(error) => failures.push(TierFailure {
+                        height,
+                        tier: String::from(tier.name()),
+                        error,
+                    }),
+                }
+            }
+            let (tier, block_txs) = match served {
+                Some(served) => served,
+                None => return Err(TieredFetchError { height, failures }),
+            };
+            update.failures.extend(failures);
+            update.blocks.insert(height, block_txs.block.hash);
+            update.served_by.insert(height, tier);
+            self.insert_block_txs(&mut update.graph_update, block_txs);
+        }
+        Ok(update)
+    }
+
+    /// Insert the relevant transactions of `block_txs` into `graph`, and track their outputs.
+    fn insert_block_txs(
+        &mut self,
+        graph: &mut TxGraph<ConfirmationTimeHeightAnchor>,
+        block_txs: BlockTxs,
+    ) {
+        let anchor = ConfirmationTimeHeightAnchor {
+            confirmation_height: block_txs.block.height,
+            confirmation_time: block_txs.time,
+            anchor_block: block_txs.block,
+        };
+        // the transactions of a block can spend the outputs of the previous ones, which the
+        // sources don't necessarily return in the block order
+        let txs = block_txs
+            .txs
+            .into_iter()
+            .map(|tx| (tx.compute_txid(), tx))
+            .collect::<Vec<_>>();
+        for (txid, tx) in &txs {
+            for (vout, txout) in tx.output.iter().enumerate() {
+                if self.spks.contains(&txout.script_pubkey) {
+                    self.outpoints.insert(OutPoint::new(*txid, vout as u32));
+                }
+            }
+        }
+        for (txid, tx) in txs {
+            let is_relevant = tx
+                .output
+                .iter()
+                .any(|txout| self.spks.contains(&txout.script_pubkey))
+                || tx
+                    .input
+                    .iter()
+                    .any(|txin| self.outpoints.contains(&txin.previous_output));
+            if is_relevant {
+                let _ = graph.insert_anchor(txid, anchor);
+                let _ = graph.insert_tx(tx);
+            }
+        }
+    }
+}
+
+/// The error of [`TieredFetcher::fetch`]: no source could serve a block
+#[derive(Debug)]
+pub struct TieredFetchError {
+    /// The height of the block
+    pub height: u32,
+    /// The failures of the sources, in the order they were tried
+    pub failures: Vec<TierFailure>,
+}
+
+impl fmt::Display for TieredFetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "no source could serve the block at height {}",
+            self.height
+        )?;
+        for failure in &self.failures {
+            write!(f, ", {}: {}", failure.tier, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TieredFetchError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::{absolute, hashes::Hash, transaction, Amount, TxIn, TxOut, Txid};
+
+    struct MockTier {
+        name: &'static str,
+        /// The lowest height it can serve
+        pruned_below: u32,
+        blocks: BTreeMap<u32, BlockTxs>,
+    }
+
+    impl BlockTxSource for MockTier {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn block_txs(
+            &self,
+            height: u32,
+            _spks: &BTreeSet<ScriptBuf>,
+        ) -> Result<BlockTxs, BackendError> {
+            if height < self.pruned_below {
+                return Err(format!("block {} is pruned", height).into());
+            }
+            self.blocks
+                .get(&height)
+                .cloned()
+                .ok_or_else(|| "unknown block".into())
+        }
+    }
+
+    fn tx(previous_output: OutPoint, script_pubkey: ScriptBuf) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_tiered_fetcher() {
+        let spk = ScriptBuf::from_bytes(vec![0x51]);
+        let other_spk = ScriptBuf::from_bytes(vec![0x52]);
+        let receive = tx(OutPoint::new(Txid::all_zeros(), 0), spk.clone());
+        let unrelated = tx(OutPoint::new(Txid::all_zeros(), 1), other_spk.clone());
+        // doesn't pay to `spk`, but spends from it
+        let spend = tx(OutPoint::new(receive.compute_txid(), 0), other_spk);
+        let blocks = BTreeMap::from([
+            (
+                10,
+                BlockTxs {
+                    block: BlockId {
+                        height: 10,
+                        hash: BlockHash::hash(b"10"),
+                    },
+                    time: 1_000,
+                    txs: vec![receive.clone(), unrelated],
+                },
+            ),
+            (
+                20,
+                BlockTxs {
+                    block: BlockId {
+                        height: 20,
+                        hash: BlockHash::hash(b"20"),
+                    },
+                    time: 2_000,
+                    txs: vec![spend.clone()],
+                },
+            ),
+        ]);
+        let node = MockTier {
+            name: "bitcoind",
+            pruned_below: 15,
+            blocks: blocks.clone(),
+        };
+        let esplora = MockTier {
+            name: "esplora",
+            pruned_below: 0,
+            blocks,
+        };
+
+        let mut fetcher = TieredFetcher::new().with_tier(&node).with_tier(&esplora);
+        fetcher.add_spks([spk.clone()]);
+        let update = fetcher.fetch([20, 10]).unwrap();
+        assert_eq!(
+            update.served_by,
+            BTreeMap::from([(10, "esplora".into()), (20, "bitcoind".into())])
+        );
+        assert_eq!(update.failures.len(), 1);
+        assert_eq!(update.failures[0].height, 10);
+        assert_eq!(update.failures[0].tier, "bitcoind");
+        let txids = update
+            .graph_update
+            .full_txs()
+            .map(|tx| tx.txid)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            txids,
+            BTreeSet::from([receive.compute_txid(), spend.compute_txid()])
+        );
+        assert_eq!(update.graph_update.all_anchors().len(), 2);
+
+        // the same result from a single tier
+        let mut fetcher = TieredFetcher::new().with_tier(&esplora);
+        fetcher.add_spks([spk]);
+        let esplora_update = fetcher.fetch([10, 20]).unwrap();
+        assert_eq!(esplora_update.graph_update, update.graph_update);
+        assert_eq!(esplora_update.blocks, update.blocks);
+        assert!(esplora_update.failures.is_empty());
+
+        // no tier serves the block
+        let mut fetcher = TieredFetcher::new().with_tier(&node);
+        let err = fetcher.fetch([10]).unwrap_err();
+        assert_eq!(err.height, 10);
+        assert_eq!(err.failures.len(), 1);
+    }
+}
@@ -19,9 +19,10 @@
 //! Conflicting transactions are allowed to coexist within a [`TxGraph`]. This is useful for
 //! identifying and traversing conflicts and descendants of a given transaction. Some [`TxGraph`]
 //! methods only consider transactions that are "canonical" (i.e., in the best chain or in mempool).
-//! We decide which transactions are canonical based on the transaction's anchors and the
-//! `last_seen` (as unconfirmed) timestamp; see the [`try_get_chain_position`] documentation for
-//! more details.
+//! We decide which transactions are canonical based on the transaction's anchors, the
+//! `last_seen` (as unconfirmed) timestamp and the `last_evicted` timestamp of when the
+//! transaction was found missing from the mempool; see the [`try_get_chain_position`]
+//! documentation for more details.
 //!
 //! The [`ChangeSet`] reports changes made to a [`TxGraph`]; it can be used to either save to
 //! persistent storage, or to be applied to another [`TxGraph`].
@@ -95,8 +96,9 @@ use crate::{
 use alloc::collections::vec_deque::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use bitcoin::{Amount, OutPoint, Script, SignedAmount, Transaction, TxOut, Txid};
+use bitcoin::{Amount, OutPoint, Script, ScriptBuf, SignedAmount, Transaction, TxIn, TxOut, Txid};
 use core::fmt::{self, Formatter};
+use core::mem::size_of;
 use core::{
     convert::Infallible,
     ops::{Deref, RangeInclusive},
@@ -110,13 +112,18 @@ use core::{
 #[derive(Clone, Debug, PartialEq)]
 pub struct TxGraph<A = ()> {
     // all transactions that the graph is aware of in format: `(tx_node, tx_anchors, tx_last_seen)`
-    txs: HashMap<Txid, (TxNodeInternal, BTreeSet<A>, u64)>,
-    spends: BTreeMap<OutPoint, HashSet<Txid>>,
+    // the collections are ordered so that the iteration order doesn't depend on the platform, see
+    // the "Deterministic ordering" section of the crate documentation
+    txs: BTreeMap<Txid, (TxNodeInternal, BTreeSet<A>, u64)>,
+    spends: BTreeMap<OutPoint, BTreeSet<Txid>>,
+    txids_by_spk: BTreeMap<ScriptBuf, BTreeSet<Txid>>,
     anchors: BTreeSet<(A, Txid)>,
+    last_evicted: BTreeMap<Txid, u64>,
+    store: SharedStore,
 
     // This atrocity exists so that `TxGraph::outspends()` can return a reference.
-    // FIXME: This can be removed once `HashSet::new` is a const fn.
-    empty_outspends: HashSet<Txid>,
+    // FIXME: This can be removed once `BTreeSet::new` is a const fn (Rust 1.66).
+    empty_outspends: BTreeSet<Txid>,
 }
 
 impl<A> Default for TxGraph<A> {
@@ -124,12 +131,140 @@ impl<A> Default for TxGraph<A> {
         Self {
             txs: Default::default(),
             spends: Default::default(),
+            txids_by_spk: Default::default(),
             anchors: Default::default(),
+            last_evicted: Default::default(),
+            store: Default::default(),
             empty_outspends: Default::default(),
         }
     }
 }
 
+/// Transactions shared by the [`TxGraph`]s created with [`TxGraph::new_with_store`], so that a
+/// transaction inserted into several of them is only stored once.
+///
+/// The store only holds weak references to the transactions: a transaction is freed once the
+/// graphs holding it are dropped.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct TxStore {
+    txs: std::sync::Mutex<StoredTxs>,
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+struct StoredTxs {
+    by_txid: HashMap<Txid, alloc::sync::Weak<Transaction>>,
+    // the entries of the freed transactions are dropped when there are this many entries
+    prune_at: usize,
+}
+
+#[cfg(feature = "std")]
+impl TxStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the stored transaction equal to `tx`, or store `tx` if there is none.
+    pub fn intern(&self, tx: Arc<Transaction>) -> Arc<Transaction> {
+        self.intern_with_txid(tx.compute_txid(), tx)
+    }
+
+    fn intern_with_txid(&self, txid: Txid, tx: Arc<Transaction>) -> Arc<Transaction> {
+        let mut txs = self.lock();
+        if let Some(stored) = txs.by_txid.get(&txid).and_then(alloc::sync::Weak::upgrade) {
+            return stored;
+        }
+        if txs.by_txid.len() >= txs.prune_at {
+            txs.by_txid.retain(|_, stored| stored.strong_count() > 0);
+            txs.prune_at = (2 * txs.by_txid.len()).max(64);
+        }
+        txs.by_txid.insert(txid, Arc::downgrade(&tx));
+        tx
+    }
+
+    /// The number of transactions in the store which are still held by a graph.
+    pub fn len(&self) -> usize {
+        self.lock()
+            .by_txid
+            .values()
+            .filter(|stored| stored.strong_count() > 0)
+            .count()
+    }
+
+    /// Whether no transaction of the store is held by a graph.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StoredTxs> {
+        // the map is valid even if a thread panicked while holding the lock
+        self.txs
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// The [`TxStore`] of a [`TxGraph`], which takes no part in the comparison of graphs.
+#[derive(Clone, Debug, Default)]
+struct SharedStore {
+    #[cfg(feature = "std")]
+    store: Option<Arc<TxStore>>,
+}
+
+impl PartialEq for SharedStore {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl SharedStore {
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn intern(&self, txid: Txid, tx: Arc<Transaction>) -> Arc<Transaction> {
+        #[cfg(feature = "std")]
+        if let Some(store) = &self.store {
+            return store.intern_with_txid(txid, tx);
+        }
+        tx
+    }
+}
+
+/// The approximate memory used by a [`TxGraph`], in bytes, see [`TxGraph::memory_footprint`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    /// The full transactions. A transaction shared with other graphs, for example through a
+    /// [`TxStore`], is divided between them.
+    pub txs: usize,
+    /// The floating txouts.
+    pub txouts: usize,
+    /// The anchors.
+    pub anchors: usize,
+    /// The indexes of the transactions, spends, script pubkeys and last evictions.
+    pub indexes: usize,
+}
+
+impl MemoryFootprint {
+    /// The total of the footprint.
+    pub fn total(&self) -> usize {
+        self.txs + self.txouts + self.anchors + self.indexes
+    }
+}
+
+fn tx_footprint(tx: &Transaction) -> usize {
+    let inputs = tx
+        .input
+        .iter()
+        .map(|txin| size_of::<TxIn>() + txin.script_sig.len() + txin.witness.size())
+        .sum::<usize>();
+    let outputs = tx.output.iter().map(txout_footprint).sum::<usize>();
+    size_of::<Transaction>() + inputs + outputs
+}
+
+fn txout_footprint(txout: &TxOut) -> usize {
+    size_of::<TxOut>() + txout.script_pubkey.len()
+}
+
 /// A transaction node in the [`TxGraph`].
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TxNode<'a, T, A> {
@@ -206,9 +341,69 @@ impl fmt::Display for CalculateFeeError {
 impl std::error::Error for CalculateFeeError {}
 
 impl<A> TxGraph<A> {
+    /// Create an empty graph which stores its transactions in `store`, sharing them with the other
+    /// graphs of the store.
+    ///
+    /// The graph behaves like a graph created with [`TxGraph::default`], the store only changes
+    /// where a transaction inserted into several graphs is allocated.
+    #[cfg(feature = "std")]
+    pub fn new_with_store(store: Arc<TxStore>) -> Self {
+        Self {
+            store: SharedStore { store: Some(store) },
+            ..Default::default()
+        }
+    }
+
+    /// The approximate memory used by the graph, in bytes.
+    ///
+    /// This counts the transactions, txouts, anchors and indexes held by the graph, but not the
+    /// overhead of the allocator, and counts an anchor as the size of `A`.
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let mut footprint = MemoryFootprint::default();
+        for (tx_node, _, _) in self.txs.values() {
+            match tx_node {
+                TxNodeInternal::Whole(tx) => {
+                    footprint.txs += tx_footprint(tx) / Arc::strong_count(tx);
+                }
+                TxNodeInternal::Partial(txouts) => {
+                    footprint.txouts += txouts
+                        .values()
+                        .map(|txout| size_of::<u32>() + txout_footprint(txout))
+                        .sum::<usize>();
+                }
+            }
+        }
+        // each anchor is in the anchors of the graph and in the anchors of its transaction
+        footprint.anchors = self.anchors.len() * (size_of::<(A, Txid)>() + size_of::<A>());
+        footprint.indexes = self.txs.len()
+            * (size_of::<Txid>() + size_of::<(TxNodeInternal, BTreeSet<A>, u64)>())
+            + self
+                .spends
+                .values()
+                .map(|txids| {
+                    size_of::<OutPoint>()
+                        + size_of::<BTreeSet<Txid>>()
+                        + txids.len() * size_of::<Txid>()
+                })
+                .sum::<usize>()
+            + self
+                .txids_by_spk
+                .iter()
+                .map(|(spk, txids)| {
+                    size_of::<ScriptBuf>()
+                        + spk.len()
+                        + size_of::<BTreeSet<Txid>>()
+                        + txids.len() * size_of::<Txid>()
+                })
+                .sum::<usize>()
+            + self.last_evicted.len() * (size_of::<Txid>() + size_of::<u64>());
+        footprint
+    }
+
     /// Iterate over all tx outputs known by [`TxGraph`].
     ///
-    /// This includes txouts of both full transactions as well as floating transactions.
+    /// This includes txouts of both full transactions as well as floating transactions, in the order
+    /// of their outpoints.
     pub fn all_txouts(&self) -> impl Iterator<Item = (OutPoint, &TxOut)> {
         self.txs.iter().flat_map(|(txid, (tx, _, _))| match tx {
             TxNodeInternal::Whole(tx) => tx
@@ -228,7 +423,7 @@ impl<A> TxGraph<A> {
     /// Iterate over floating txouts known by [`TxGraph`].
     ///
     /// Floating txouts are txouts that do not have the residing full transaction contained in the
-    /// graph.
+    /// graph. They are returned in the order of their outpoints.
     pub fn floating_txouts(&self) -> impl Iterator<Item = (OutPoint, &TxOut)> {
         self.txs
             .iter()
@@ -243,7 +438,7 @@ impl<A> TxGraph<A> {
             .flatten()
     }
 
-    /// Iterate over all full transactions in the graph.
+    /// Iterate over all full transactions in the graph, in txid order.
     pub fn full_txs(&self) -> impl Iterator<Item = TxNode<'_, Arc<Transaction>, A>> {
         self.txs
             .iter()
@@ -355,10 +550,17 @@ impl<A> TxGraph<A> {
     ///
     /// [`TxGraph`] allows conflicting transactions within the graph. Obviously the transactions in
     /// the returned set will never be in the same active-chain.
-    pub fn outspends(&self, outpoint: OutPoint) -> &HashSet<Txid> {
+    pub fn outspends(&self, outpoint: OutPoint) -> &BTreeSet<Txid> {
         self.spends.get(&outpoint).unwrap_or(&self.empty_outspends)
     }
 
+    /// The full transactions with an output paying to `spk`.
+    ///
+    /// Like [`outspends`](Self::outspends), the set may contain conflicting transactions.
+    pub fn txids_paying_to(&self, spk: &Script) -> &BTreeSet<Txid> {
+        self.txids_by_spk.get(spk).unwrap_or(&self.empty_outspends)
+    }
+
     /// Iterates over the transactions spending from `txid`.
     ///
     /// The iterator item is a union of `(vout, txid-set)` where:
@@ -368,7 +570,7 @@ impl<A> TxGraph<A> {
     pub fn tx_spends(
         &self,
         txid: Txid,
-    ) -> impl DoubleEndedIterator<Item = (u32, &HashSet<Txid>)> + '_ {
+    ) -> impl DoubleEndedIterator<Item = (u32, &BTreeSet<Txid>)> + '_ {
         let start = OutPoint::new(txid, 0);
         let end = OutPoint::new(txid, u32::MAX);
         self.spends
@@ -454,11 +656,24 @@ impl<A> TxGraph<A> {
             .filter(move |(_, conflicting_txid)| *conflicting_txid != txid)
     }
 
+    /// The txids of the transactions spending the outputs of `txid`.
+    fn spending_txids(&self, txid: Txid) -> impl Iterator<Item = Txid> + '_ {
+        self.spends
+            .range(tx_outpoint_range(txid))
+            .flat_map(|(_, spends)| spends.iter().copied())
+    }
+
     /// Get all transaction anchors known by [`TxGraph`].
     pub fn all_anchors(&self) -> &BTreeSet<(A, Txid)> {
         &self.anchors
     }
 
+    /// Get the last time the transaction of `txid` was found missing from the mempool, see
+    /// [`insert_evicted_at`](Self::insert_evicted_at).
+    pub fn last_evicted(&self, txid: Txid) -> Option<u64> {
+        self.last_evicted.get(&txid).copied()
+    }
+
     /// Whether the graph has any transactions or outputs in it.
     pub fn is_empty(&self) -> bool {
         self.txs.is_empty()
@@ -474,7 +689,10 @@ impl<A: Clone + Ord> TxGraph<A> {
     where
         F: FnMut(A) -> A2,
     {
-        let mut new_graph = TxGraph::<A2>::default();
+        let mut new_graph = TxGraph::<A2> {
+            store: self.store.clone(),
+            ..Default::default()
+        };
         new_graph.apply_changeset(self.initial_changeset().map_anchors(f));
         new_graph
     }
@@ -564,6 +782,20 @@ impl<A: Clone + Ord> TxGraph<A> {
         self.apply_update(update)
     }
 
+    /// Inserts the given `evicted_at` for `txid` into [`TxGraph`].
+    ///
+    /// `evicted_at` is the unix timestamp of when the unconfirmed transaction was found to be
+    /// missing from the mempool, because it was replaced or expired. The transaction, and its
+    /// descendants, stop being canonical unless it is seen again after `evicted_at`, see
+    /// [`try_get_chain_position`]. Like `seen_at`, only the latest `evicted_at` is kept.
+    ///
+    /// [`try_get_chain_position`]: Self::try_get_chain_position
+    pub fn insert_evicted_at(&mut self, txid: Txid, evicted_at: u64) -> ChangeSet<A> {
+        let mut update = Self::default();
+        update.last_evicted.insert(txid, evicted_at);
+        self.apply_update(update)
+    }
+
     /// Update the last seen time for all unconfirmed transactions.
     ///
     /// This method updates the last seen unconfirmed time for this [`TxGraph`] by inserting
@@ -655,9 +887,18 @@ impl<A: Clone + Ord> TxGraph<A> {
                     self.spends.entry(outpoint).or_default().insert(txid);
                 });
 
+            if !matches!(self.txs.get(&txid), Some((TxNodeInternal::Whole(_), _, _))) {
+                for txout in &tx.output {
+                    self.txids_by_spk
+                        .entry(txout.script_pubkey.clone())
+                        .or_default()
+                        .insert(txid);
+                }
+            }
+
             match self.txs.get_mut(&txid) {
                 Some((tx_node @ TxNodeInternal::Partial(_), _, _)) => {
-                    *tx_node = TxNodeInternal::Whole(wrapped_tx.clone());
+                    *tx_node = TxNodeInternal::Whole(self.store.intern(txid, wrapped_tx));
                 }
                 Some((TxNodeInternal::Whole(tx), _, _)) => {
                     debug_assert_eq!(
@@ -667,10 +908,9 @@ impl<A: Clone + Ord> TxGraph<A> {
                     );
                 }
                 None => {
-                    self.txs.insert(
-                        txid,
-                        (TxNodeInternal::Whole(wrapped_tx), BTreeSet::new(), 0),
-                    );
+                    let tx = self.store.intern(txid, wrapped_tx);
+                    self.txs
+                        .insert(txid, (TxNodeInternal::Whole(tx), BTreeSet::new(), 0));
                 }
             }
         }
@@ -700,6 +940,13 @@ impl<A: Clone + Ord> TxGraph<A> {
                 *last_seen = new_last_seen;
             }
         }
+
+        for (txid, new_last_evicted) in changeset.last_evicted {
+            let last_evicted = self.last_evicted.entry(txid).or_default();
+            if new_last_evicted > *last_evicted {
+                *last_evicted = new_last_evicted;
+            }
+        }
     }
 
     /// Previews the resultant [`ChangeSet`] when [`Self`] is updated against the `update` graph.
@@ -752,6 +999,13 @@ impl<A: Clone + Ord> TxGraph<A> {
 
         changeset.anchors = update.anchors.difference(&self.anchors).cloned().collect();
 
+        changeset.last_evicted = update
+            .last_evicted
+            .iter()
+            .filter(|&(txid, update_le)| self.last_evicted.get(txid) < Some(update_le))
+            .map(|(&txid, &update_le)| (txid, update_le))
+            .collect();
+
         changeset
     }
 }
@@ -773,6 +1027,9 @@ impl<A: Anchor> TxGraph<A> {
     ///    parameter is the max of all it's descendants' `last_seen_unconfirmed` parameters. If the
     ///    final `last_seen_unconfirmed`s are the same, the transaction with the lower `txid` (by
     ///    lexicographical order) is evicted.
+    /// 4. Unconfirmed transactions last found missing from the mempool (see
+    ///    [`insert_evicted_at`]) at or after their `last_seen_unconfirmed` are evicted, as well as
+    ///    the transactions spending from them.
     ///
     /// # Error
     ///
@@ -780,11 +1037,23 @@ impl<A: Anchor> TxGraph<A> {
     /// [`ChainOracle`] is infallible, [`get_chain_position`] can be used instead.
     ///
     /// [`get_chain_position`]: Self::get_chain_position
+    /// [`insert_evicted_at`]: Self::insert_evicted_at
     pub fn try_get_chain_position<C: ChainOracle>(
         &self,
         chain: &C,
         chain_tip: BlockId,
         txid: Txid,
+    ) -> Result<Option<ChainPosition<&A>>, C::Error> {
+        self.try_get_chain_position_with(chain, chain_tip, txid, &mut WalkBuffers::default())
+    }
+
+    /// [`try_get_chain_position`](Self::try_get_chain_position) walking the graph with `buffers`
+    fn try_get_chain_position_with<C: ChainOracle>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        txid: Txid,
+        buffers: &mut WalkBuffers,
     ) -> Result<Option<ChainPosition<&A>>, C::Error> {
         let (tx_node, anchors, last_seen) = match self.txs.get(&txid) {
             Some(v) => v,
@@ -801,96 +1070,114 @@ impl<A: Anchor> TxGraph<A> {
         // The tx is not anchored to a block in the best chain, which means that it
         // might be in mempool, or it might have been dropped already.
         // Let's check conflicts to find out!
-        let tx = match tx_node {
+        match tx_node {
             TxNodeInternal::Whole(tx) => {
                 // A coinbase tx that is not anchored in the best chain cannot be unconfirmed and
                 // should always be filtered out.
                 if tx.is_coinbase() {
                     return Ok(None);
                 }
-                tx.clone()
             }
             TxNodeInternal::Partial(_) => {
                 // Partial transactions (outputs only) cannot have conflicts.
                 return Ok(None);
             }
-        };
+        }
+
+        // The walks below use explicit stacks and visited sets, both taken from `buffers` so that
+        // canonicalizing many transactions doesn't allocate them again for each one. Only
+        // unconfirmed transactions are walked through: a confirmed transaction is in the best
+        // chain, and so are all its ancestors.
+        let WalkBuffers {
+            visited,
+            stack,
+            conflicts_visited,
+            conflicts_stack,
+        } = buffers;
+
+        // We determine our tx's last seen, which is the max between our last seen,
+        // and our unconf descendants' last seen.
+        let mut tx_last_seen = *last_seen;
+        visited.clear();
+        stack.clear();
+        visited.insert(txid);
+        stack.push(txid);
+        while let Some(descendant_txid) = stack.pop() {
+            let descendant = match self.get_tx_node(descendant_txid) {
+                Some(tx_node) => tx_node,
+                None => continue,
+            };
+            if is_anchored_in_chain(chain, chain_tip, descendant.anchors)? {
+                continue;
+            }
+            tx_last_seen = tx_last_seen.max(descendant.last_seen_unconfirmed);
+            stack.extend(
+                self.spending_txids(descendant_txid)
+                    .filter(|&spend_txid| visited.insert(spend_txid)),
+            );
+        }
 
         // We want to retrieve all the transactions that conflict with us, plus all the
         // transactions that conflict with our unconfirmed ancestors, since they conflict with us
         // as well.
-        // We only traverse unconfirmed ancestors since conflicts of confirmed transactions
-        // cannot be in the best chain.
-
-        // First of all, we retrieve all our ancestors. Since we're using `new_include_root`, the
-        // resulting array will also include `tx`
-        let unconfirmed_ancestor_txs =
-            TxAncestors::new_include_root(self, tx.clone(), |_, ancestor_tx: Arc<Transaction>| {
-                let tx_node = self.get_tx_node(ancestor_tx.as_ref().compute_txid())?;
-                // We're filtering the ancestors to keep only the unconfirmed ones (= no anchors in
-                // the best chain)
-                for block in tx_node.anchors {
-                    match chain.is_block_in_chain(block.anchor_block(), chain_tip) {
-                        Ok(Some(true)) => return None,
-                        Err(e) => return Some(Err(e)),
-                        _ => continue,
-                    }
-                }
-                Some(Ok(tx_node))
-            })
-            .collect::<Result<Vec<_>, C::Error>>()?;
+        visited.clear();
+        visited.insert(txid);
+        stack.push(txid);
+        while let Some(ancestor_txid) = stack.pop() {
+            let ancestor = match self.get_tx_node(ancestor_txid) {
+                Some(tx_node) => tx_node,
+                None => continue,
+            };
+            if is_anchored_in_chain(chain, chain_tip, ancestor.anchors)? {
+                continue;
+            }
 
-        // We determine our tx's last seen, which is the max between our last seen,
-        // and our unconf descendants' last seen.
-        let unconfirmed_descendants_txs = TxDescendants::new_include_root(
-            self,
-            tx.as_ref().compute_txid(),
-            |_, descendant_txid: Txid| {
-                let tx_node = self.get_tx_node(descendant_txid)?;
-                // We're filtering the ancestors to keep only the unconfirmed ones (= no anchors in
-                // the best chain)
-                for block in tx_node.anchors {
-                    match chain.is_block_in_chain(block.anchor_block(), chain_tip) {
-                        Ok(Some(true)) => return None,
-                        Err(e) => return Some(Err(e)),
-                        _ => continue,
-                    }
+            // An ancestor which left the mempool after it was last seen takes us with it
+            if let Some(&last_evicted) = self.last_evicted.get(&ancestor_txid) {
+                if last_evicted >= ancestor.last_seen_unconfirmed {
+                    return Ok(None);
                 }
-                Some(Ok(tx_node))
-            },
-        )
-        .collect::<Result<Vec<_>, C::Error>>()?;
+            }
 
-        let tx_last_seen = unconfirmed_descendants_txs
-            .iter()
-            .max_by_key(|tx| tx.last_seen_unconfirmed)
-            .map(|tx| tx.last_seen_unconfirmed)
-            .expect("descendants always includes at least one transaction (the root tx");
-
-        // Now we traverse our ancestors and consider all their conflicts
-        for tx_node in unconfirmed_ancestor_txs {
-            // We retrieve all the transactions conflicting with this specific ancestor
-            let conflicting_txs =
-                self.walk_conflicts(tx_node.tx.as_ref(), |_, txid| self.get_tx_node(txid));
-
-            // If a conflicting tx is in the best chain, or has `last_seen` higher than this ancestor, then
-            // this tx cannot exist in the best chain
-            for conflicting_tx in conflicting_txs {
-                for block in conflicting_tx.anchors {
-                    if chain.is_block_in_chain(block.anchor_block(), chain_tip)? == Some(true) {
-                        return Ok(None);
-                    }
+            // We walk all the transactions conflicting with this specific ancestor, and their
+            // descendants. If a conflicting tx is in the best chain, or has `last_seen` higher
+            // than this ancestor, then this tx cannot exist in the best chain
+            conflicts_visited.clear();
+            conflicts_stack.clear();
+            conflicts_stack.extend(
+                self.direct_conflicts(&ancestor.tx)
+                    .map(|(_, conflict_txid)| conflict_txid)
+                    .filter(|&conflict_txid| conflicts_visited.insert(conflict_txid)),
+            );
+            while let Some(conflict_txid) = conflicts_stack.pop() {
+                let conflict = match self.get_tx_node(conflict_txid) {
+                    Some(tx_node) => tx_node,
+                    None => continue,
+                };
+                if is_anchored_in_chain(chain, chain_tip, conflict.anchors)? {
+                    return Ok(None);
                 }
-                if conflicting_tx.last_seen_unconfirmed > tx_last_seen {
+                if conflict.last_seen_unconfirmed > tx_last_seen {
                     return Ok(None);
                 }
-                if conflicting_tx.last_seen_unconfirmed == *last_seen
-                    && conflicting_tx.as_ref().compute_txid() > tx.as_ref().compute_txid()
-                {
+                if conflict.last_seen_unconfirmed == *last_seen && conflict_txid > txid {
                     // Conflicting tx has priority if txid of conflicting tx > txid of original tx
                     return Ok(None);
                 }
+                conflicts_stack.extend(
+                    self.spending_txids(conflict_txid)
+                        .filter(|&spend_txid| conflicts_visited.insert(spend_txid)),
+                );
             }
+
+            stack.extend(
+                ancestor
+                    .tx
+                    .input
+                    .iter()
+                    .map(|txin| txin.previous_output.txid)
+                    .filter(|&prev_txid| visited.insert(prev_txid)),
+            );
         }
 
         Ok(Some(ChainPosition::Unconfirmed(*last_seen)))
@@ -928,16 +1215,29 @@ impl<A: Anchor> TxGraph<A> {
         chain: &C,
         chain_tip: BlockId,
         outpoint: OutPoint,
+    ) -> Result<Option<(ChainPosition<&A>, Txid)>, C::Error> {
+        self.try_get_chain_spend_with(chain, chain_tip, outpoint, &mut WalkBuffers::default())
+    }
+
+    /// [`try_get_chain_spend`](Self::try_get_chain_spend) walking the graph with `buffers`
+    fn try_get_chain_spend_with<C: ChainOracle>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        outpoint: OutPoint,
+        buffers: &mut WalkBuffers,
     ) -> Result<Option<(ChainPosition<&A>, Txid)>, C::Error> {
         if self
-            .try_get_chain_position(chain, chain_tip, outpoint.txid)?
+            .try_get_chain_position_with(chain, chain_tip, outpoint.txid, buffers)?
             .is_none()
         {
             return Ok(None);
         }
         if let Some(spends) = self.spends.get(&outpoint) {
             for &txid in spends {
-                if let Some(observed_at) = self.try_get_chain_position(chain, chain_tip, txid)? {
+                if let Some(observed_at) =
+                    self.try_get_chain_position_with(chain, chain_tip, txid, buffers)?
+                {
                     return Ok(Some((observed_at, txid)));
                 }
             }
@@ -979,8 +1279,9 @@ impl<A: Anchor> TxGraph<A> {
         chain: &'a C,
         chain_tip: BlockId,
     ) -> impl Iterator<Item = Result<CanonicalTx<'a, Arc<Transaction>, A>, C::Error>> {
+        let mut buffers = WalkBuffers::default();
         self.full_txs().filter_map(move |tx| {
-            self.try_get_chain_position(chain, chain_tip, tx.txid)
+            self.try_get_chain_position_with(chain, chain_tip, tx.txid, &mut buffers)
                 .map(|v| {
                     v.map(|observed_in| CanonicalTx {
                         chain_position: observed_in,
@@ -1030,6 +1331,7 @@ impl<A: Anchor> TxGraph<A> {
         chain_tip: BlockId,
         outpoints: impl IntoIterator<Item = (OI, OutPoint)> + 'a,
     ) -> impl Iterator<Item = Result<(OI, FullTxOut<A>), C::Error>> + 'a {
+        let mut buffers = WalkBuffers::default();
         outpoints
             .into_iter()
             .map(
@@ -1044,14 +1346,18 @@ impl<A: Anchor> TxGraph<A> {
                         None => return Ok(None),
                     };
 
-                    let chain_position =
-                        match self.try_get_chain_position(chain, chain_tip, op.txid)? {
-                            Some(pos) => pos.cloned(),
-                            None => return Ok(None),
-                        };
+                    let chain_position = match self.try_get_chain_position_with(
+                        chain,
+                        chain_tip,
+                        op.txid,
+                        &mut buffers,
+                    )? {
+                        Some(pos) => pos.cloned(),
+                        None => return Ok(None),
+                    };
 
                     let spent_by = self
-                        .try_get_chain_spend(chain, chain_tip, op)?
+                        .try_get_chain_spend_with(chain, chain_tip, op, &mut buffers)?
                         .map(|(a, txid)| (a.cloned(), txid));
 
                     Ok(Some((
@@ -1234,6 +1540,11 @@ pub struct ChangeSet<A = ()> {
     pub anchors: BTreeSet<(A, Txid)>,
     /// Added last-seen unix timestamps of transactions.
     pub last_seen: BTreeMap<Txid, u64>,
+    /// Added unix timestamps of when transactions were found missing from the mempool.
+    ///
+    /// Empty in a changeset persisted before the timestamps were recorded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub last_evicted: BTreeMap<Txid, u64>,
 }
 
 impl<A> Default for ChangeSet<A> {
@@ -1243,6 +1554,7 @@ impl<A> Default for ChangeSet<A> {
             txouts: Default::default(),
             anchors: Default::default(),
             last_seen: Default::default(),
+            last_evicted: Default::default(),
         }
     }
 }
@@ -1297,6 +1609,14 @@ impl<A: Ord> Append for ChangeSet<A> {
                 .filter(|(txid, update_ls)| self.last_seen.get(txid) < Some(update_ls))
                 .collect::<Vec<_>>(),
         );
+        // so should last_evicted timestamps
+        self.last_evicted.extend(
+            other
+                .last_evicted
+                .into_iter()
+                .filter(|(txid, update_le)| self.last_evicted.get(txid) < Some(update_le))
+                .collect::<Vec<_>>(),
+        );
     }
 
     fn is_empty(&self) -> bool {
@@ -1304,6 +1624,7 @@ impl<A: Ord> Append for ChangeSet<A> {
             && self.txouts.is_empty()
             && self.anchors.is_empty()
             && self.last_seen.is_empty()
+            && self.last_evicted.is_empty()
     }
 }
 
@@ -1323,6 +1644,7 @@ impl<A: Ord> ChangeSet<A> {
                 self.anchors.into_iter().map(|(a, txid)| (f(a), txid)),
             ),
             last_seen: self.last_seen,
+            last_evicted: self.last_evicted,
         }
     }
 }
@@ -1349,6 +1671,7 @@ pub struct TxAncestors<'g, A, F> {
 
 impl<'g, A, F> TxAncestors<'g, A, F> {
     /// Creates a `TxAncestors` that includes the starting `Transaction` when iterating.
+    #[allow(unused)]
     pub(crate) fn new_include_root(
         graph: &'g TxGraph<A>,
         tx: impl Into<Arc<Transaction>>,
@@ -1473,7 +1796,7 @@ impl<'g, A, F> TxDescendants<'g, A, F> {
     pub(crate) fn new_include_root(graph: &'g TxGraph<A>, txid: Txid, filter_map: F) -> Self {
         Self {
             graph,
-            visited: Default::default(),
+            visited: core::iter::once(txid).collect(),
             queue: [(0, txid)].into(),
             filter_map,
         }
@@ -1501,10 +1824,16 @@ impl<'g, A, F> TxDescendants<'g, A, F> {
     where
         I: IntoIterator<Item = Txid>,
     {
+        let mut visited = HashSet::<Txid>::default();
+        let queue = txids
+            .into_iter()
+            .filter(|&txid| visited.insert(txid))
+            .map(|txid| (0, txid))
+            .collect();
         Self {
             graph,
-            visited: Default::default(),
-            queue: txids.into_iter().map(|txid| (0, txid)).collect(),
+            visited,
+            queue,
             filter_map,
         }
     }
@@ -1535,12 +1864,13 @@ impl<'g, A, F> TxDescendants<'g, A, F> {
 
 impl<'g, A, F> TxDescendants<'g, A, F> {
     fn populate_queue(&mut self, depth: usize, txid: Txid) {
+        let visited = &mut self.visited;
         let spend_paths = self
             .graph
-            .spends
-            .range(tx_outpoint_range(txid))
-            .flat_map(|(_, spends)| spends)
-            .map(|&txid| (depth, txid));
+            .spending_txids(txid)
+            // a transaction is only queued once, so the queue never outgrows the graph
+            .filter(|&txid| visited.insert(txid))
+            .map(|txid| (depth, txid));
         self.queue.extend(spend_paths);
     }
 }
@@ -1555,12 +1885,9 @@ where
         let (op_spends, txid, item) = loop {
             // we have exhausted all paths when queue is empty
             let (op_spends, txid) = self.queue.pop_front()?;
-            // we do not want to visit the same transaction twice
-            if self.visited.insert(txid) {
-                // ignore paths when user filters them out
-                if let Some(item) = (self.filter_map)(op_spends, txid) {
-                    break (op_spends, txid, item);
-                }
+            // ignore paths when user filters them out
+            if let Some(item) = (self.filter_map)(op_spends, txid) {
+                break (op_spends, txid, item);
             }
         };
 
@@ -1569,6 +1896,32 @@ where
     }
 }
 
+/// The buffers of the walks of [`TxGraph::try_get_chain_position`]
+///
+/// They are kept from one transaction to the next when canonicalizing many transactions, so that
+/// their allocations are reused.
+#[derive(Debug, Default)]
+struct WalkBuffers {
+    visited: HashSet<Txid>,
+    stack: Vec<Txid>,
+    conflicts_visited: HashSet<Txid>,
+    conflicts_stack: Vec<Txid>,
+}
+
+/// Whether one of `anchors` is in the chain of `chain_tip`.
+fn is_anchored_in_chain<A: Anchor, C: ChainOracle>(
+    chain: &C,
+    chain_tip: BlockId,
+    anchors: &BTreeSet<A>,
+) -> Result<bool, C::Error> {
+    for anchor in anchors {
+        if chain.is_block_in_chain(anchor.anchor_block(), chain_tip)? == Some(true) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 fn tx_outpoint_range(txid: Txid) -> RangeInclusive<OutPoint> {
     OutPoint::new(txid, u32::MIN)..=OutPoint::new(txid, u32::MAX)
 }
@@ -0,0 +1,166 @@
+#![cfg(all(feature = "serde", feature = "miniscript"))]
+
+//! Test vectors of the serialization of the changesets: equal states must serialize to the same
+//! bytes whatever the insertion order, see the "Deterministic ordering" section of the crate
+//! documentation.
+
+use std::str::FromStr;
+
+use bdk_chain::{keychain::KeychainTxOutIndex, local_chain, Append, BlockId, TxGraph};
+use bitcoin::{
+    absolute, hashes::Hash, transaction, Amount, BlockHash, OutPoint, ScriptBuf, Transaction, TxIn,
+    TxOut, Txid,
+};
+use miniscript::{Descriptor, DescriptorPublicKey};
+
+const EXTERNAL: &str = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)";
+const INTERNAL: &str = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/1/*)";
+
+fn tx(previous_output: OutPoint, value: u64) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::new(),
+        }],
+    }
+}
+
+fn block_hash(height: u32) -> BlockHash {
+    BlockHash::hash(&height.to_le_bytes())
+}
+
+#[test]
+fn test_local_chain_changeset_vector() {
+    let changeset: local_chain::ChangeSet = [
+        (2, None),
+        (0, Some(block_hash(0))),
+        (1, Some(block_hash(1))),
+    ]
+    .into_iter()
+    .collect();
+    let json = serde_json::to_string(&changeset).unwrap();
+    assert_eq!(
+        json,
+        r#"{"0":"b90ca5b5653eabdc3341c6f96b3b80689cdd1bd6870265adfe17c8172501b98c","1":"1bd164d5d22d98dc2b5720c02ca95f732d0cb60cfcb495378d07cce5f258f741","2":null}"#
+    );
+}
+
+#[test]
+fn test_tx_graph_changeset_vector() {
+    let parent = tx(OutPoint::new(Txid::all_zeros(), 0), 10_000);
+    let child = tx(OutPoint::new(parent.compute_txid(), 0), 9_000);
+    let floating = OutPoint::new(Txid::from_byte_array([1; 32]), 3);
+    let anchor = BlockId {
+        height: 1,
+        hash: block_hash(1),
+    };
+
+    // the same state, inserted in opposite orders
+    let mut graph = TxGraph::<BlockId>::default();
+    let mut changeset = graph.insert_tx(parent.clone());
+    changeset.append(graph.insert_tx(child.clone()));
+    changeset.append(graph.insert_txout(
+        floating,
+        TxOut {
+            value: Amount::from_sat(1),
+            script_pubkey: ScriptBuf::new(),
+        },
+    ));
+    changeset.append(graph.insert_anchor(parent.compute_txid(), anchor));
+    changeset.append(graph.insert_seen_at(child.compute_txid(), 10));
+
+    let mut other = TxGraph::<BlockId>::default();
+    let mut other_changeset = other.insert_seen_at(child.compute_txid(), 10);
+    other_changeset.append(other.insert_anchor(parent.compute_txid(), anchor));
+    other_changeset.append(other.insert_txout(
+        floating,
+        TxOut {
+            value: Amount::from_sat(1),
+            script_pubkey: ScriptBuf::new(),
+        },
+    ));
+    other_changeset.append(other.insert_tx(child.clone()));
+    other_changeset.append(other.insert_tx(parent.clone()));
+
+    assert_eq!(changeset, other_changeset);
+    let json = serde_json::to_string(&changeset).unwrap();
+    assert_eq!(json, serde_json::to_string(&other_changeset).unwrap());
+    assert_eq!(
+        json,
+        serde_json::to_string(&other.initial_changeset()).unwrap()
+    );
+    assert_eq!(
+        json,
+        r#"{"txs":[{"version":2,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:0","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":10000,"script_pubkey":""}]},{"version":2,"lock_time":0,"input":[{"previous_output":"32529b84aa95731d7ee41b7deb72cbd4d9e967627ce881ceba59d866f8de8b89:0","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":9000,"script_pubkey":""}]}],"txouts":{"0101010101010101010101010101010101010101010101010101010101010101:3":{"value":1,"script_pubkey":""}},"anchors":[[{"height":1,"hash":"1bd164d5d22d98dc2b5720c02ca95f732d0cb60cfcb495378d07cce5f258f741"},"32529b84aa95731d7ee41b7deb72cbd4d9e967627ce881ceba59d866f8de8b89"]],"last_seen":{"6fb318d5b309d1271d81c0bf4199fb9843369d88d390cb2d796f323dd59bbd15":10},"last_evicted":{}}"#
+    );
+}
+
+#[test]
+fn test_keychain_changeset_vector() {
+    let external = Descriptor::<DescriptorPublicKey>::from_str(EXTERNAL).unwrap();
+    let internal = Descriptor::<DescriptorPublicKey>::from_str(INTERNAL).unwrap();
+
+    let mut index = KeychainTxOutIndex::<u8>::new(0);
+    let mut changeset = index.insert_descriptor(1, internal.clone()).unwrap();
+    changeset.append(index.insert_descriptor(0, external.clone()).unwrap());
+    changeset.append(index.reveal_to_target(&1, 1).unwrap().1);
+    changeset.append(index.reveal_to_target(&0, 2).unwrap().1);
+
+    let mut other = KeychainTxOutIndex::<u8>::new(0);
+    let mut other_changeset = other.insert_descriptor(0, external).unwrap();
+    other_changeset.append(other.insert_descriptor(1, internal).unwrap());
+    other_changeset.append(other.reveal_to_target(&0, 2).unwrap().1);
+    other_changeset.append(other.reveal_to_target(&1, 1).unwrap().1);
+
+    assert_eq!(changeset, other_changeset);
+    let json = serde_json::to_string(&changeset).unwrap();
+    assert_eq!(json, serde_json::to_string(&other_changeset).unwrap());
+    assert_eq!(
+        json,
+        r#"{"keychains_added":{"0":"wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)#g084qxg2","1":"wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/1/*)#emz5ancj"},"last_revealed":{"375102dda258bbc1044a4178370e70398c27b5d4db6f770711e6f700162066a6":1,"527aa362a529db735e5f5b87c42db34c40b4d4ad4286604365233fb42e8622ca":2},"marked_used":{}}"#
+    );
+}
+
+#[test]
+fn test_tx_graph_iteration_order() {
+    // transactions inserted in the reverse order of their txids
+    let mut txs = (0..10)
+        .map(|i| tx(OutPoint::new(Txid::all_zeros(), 0), 1_000 + i))
+        .collect::<Vec<_>>();
+    txs.sort_by_key(|tx| core::cmp::Reverse(tx.compute_txid()));
+    let mut graph = TxGraph::<BlockId>::default();
+    for tx in &txs {
+        let _ = graph.insert_tx(tx.clone());
+    }
+
+    let mut txids = txs
+        .iter()
+        .map(Transaction::compute_txid)
+        .collect::<Vec<_>>();
+    txids.sort();
+    assert_eq!(
+        graph.full_txs().map(|node| node.txid).collect::<Vec<_>>(),
+        txids
+    );
+    assert_eq!(
+        graph
+            .outspends(OutPoint::new(Txid::all_zeros(), 0))
+            .iter()
+            .copied()
+            .collect::<Vec<_>>(),
+        txids
+    );
+    let outpoints = graph
+        .all_txouts()
+        .map(|(outpoint, _)| outpoint)
+        .collect::<Vec<_>>();
+    let mut sorted = outpoints.clone();
+    sorted.sort();
+    assert_eq!(outpoints, sorted);
+}
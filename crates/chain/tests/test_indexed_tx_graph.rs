@@ -10,7 +10,7 @@ use bdk_chain::{
     indexed_tx_graph::{self, IndexedTxGraph},
     keychain::{self, Balance, KeychainTxOutIndex},
     local_chain::LocalChain,
-    tx_graph, Append, ChainPosition, ConfirmationHeightAnchor, DescriptorExt,
+    tx_graph, Append, ChainPosition, ConfirmationHeightAnchor, DescriptorExt, SpkTxOutIndex,
 };
 use bitcoin::{
     secp256k1::Secp256k1, Amount, OutPoint, Script, ScriptBuf, Transaction, TxIn, TxOut,
@@ -78,6 +78,7 @@ fn insert_relevant_txs() {
         },
         indexer: keychain::ChangeSet {
             last_revealed: [(descriptor.descriptor_id(), 9_u32)].into(),
+            marked_used: Default::default(),
             keychains_added: [].into(),
         },
     };
@@ -92,6 +93,7 @@ fn insert_relevant_txs() {
         graph: changeset.graph,
         indexer: keychain::ChangeSet {
             last_revealed: changeset.indexer.last_revealed,
+            marked_used: Default::default(),
             keychains_added: [((), descriptor)].into(),
         },
     };
@@ -99,6 +101,115 @@ fn insert_relevant_txs() {
     assert_eq!(graph.initial_changeset(), initial_changeset);
 }
 
+/// Ensure [`IndexedTxGraph::reindex`] indexes the transactions inserted before the descriptor
+/// tracking their scripts.
+#[test]
+fn reindex_after_inserting_descriptor() {
+    let (descriptor, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), DESCRIPTORS[0])
+        .expect("must be valid");
+    let spk = descriptor.at_derivation_index(3).unwrap().script_pubkey();
+
+    let mut graph = IndexedTxGraph::<ConfirmationHeightAnchor, KeychainTxOutIndex<()>>::new(
+        KeychainTxOutIndex::new(10),
+    );
+    let tx = Transaction {
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: spk,
+        }],
+        ..common::new_tx(0)
+    };
+    let _ = graph.insert_tx(tx.clone());
+    let _ = graph
+        .index
+        .insert_descriptor((), descriptor.clone())
+        .unwrap();
+    assert!(graph.index.outpoints().is_empty());
+
+    let changeset = graph.reindex();
+    assert_eq!(
+        changeset.last_revealed,
+        [(descriptor.descriptor_id(), 3)].into()
+    );
+    assert_eq!(
+        graph.index.outpoints(),
+        &[(((), 3), OutPoint::new(tx.compute_txid(), 0))].into()
+    );
+
+    // indexing again doesn't change anything
+    assert!(graph.reindex().is_empty());
+}
+
+/// Outputs beyond the lookahead are found when reindexing reveals the scripts before them.
+#[test]
+fn reindex_beyond_lookahead() {
+    let (descriptor, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), DESCRIPTORS[0])
+        .expect("must be valid");
+
+    let mut graph = IndexedTxGraph::<ConfirmationHeightAnchor, KeychainTxOutIndex<()>>::new(
+        KeychainTxOutIndex::new(10),
+    );
+    // each output is within the lookahead of the previous one only
+    let txs = [3, 12, 21]
+        .iter()
+        .map(|&index| Transaction {
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: descriptor
+                    .at_derivation_index(index)
+                    .unwrap()
+                    .script_pubkey(),
+            }],
+            ..common::new_tx(index)
+        })
+        .collect::<Vec<_>>();
+    for tx in &txs {
+        let _ = graph.insert_tx(tx.clone());
+    }
+    let _ = graph
+        .index
+        .insert_descriptor((), descriptor.clone())
+        .unwrap();
+
+    let changeset = graph.reindex();
+    assert_eq!(
+        changeset.last_revealed,
+        [(descriptor.descriptor_id(), 21)].into()
+    );
+    assert_eq!(
+        graph.index.outpoints(),
+        &[3, 12, 21]
+            .iter()
+            .zip(&txs)
+            .map(|(&index, tx)| (((), index), OutPoint::new(tx.compute_txid(), 0)))
+            .collect()
+    );
+    assert!(graph.reindex().is_empty());
+}
+
+/// Scripts inserted in a [`SpkTxOutIndex`] after the transactions are indexed when reindexing.
+#[test]
+fn reindex_spk_txout_index() {
+    let spk = ScriptBuf::from_bytes(vec![0x51]);
+    let mut graph = IndexedTxGraph::<ConfirmationHeightAnchor, SpkTxOutIndex<u32>>::new(
+        SpkTxOutIndex::default(),
+    );
+    let tx = Transaction {
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: spk.clone(),
+        }],
+        ..common::new_tx(0)
+    };
+    let outpoint = OutPoint::new(tx.compute_txid(), 0);
+    let _ = graph.insert_tx(tx);
+    assert!(graph.index.insert_spk(0, spk));
+    assert!(graph.index.txout(outpoint).is_none());
+
+    graph.reindex();
+    assert_eq!(graph.index.outpoints(), &[(0, outpoint)].into());
+}
+
 /// Ensure consistency IndexedTxGraph list_* and balance methods. These methods lists
 /// relevant txouts and utxos from the information fetched from a ChainOracle (here a LocalChain).
 ///
@@ -84,10 +84,12 @@ fn append_changesets_check_last_revealed() {
     let mut lhs = ChangeSet {
         keychains_added: BTreeMap::<(), _>::new(),
         last_revealed: lhs_di,
+        marked_used: Default::default(),
     };
     let rhs = ChangeSet {
         keychains_added: BTreeMap::<(), _>::new(),
         last_revealed: rhs_di,
+        marked_used: Default::default(),
     };
     lhs.append(rhs);
 
@@ -101,6 +103,41 @@ fn append_changesets_check_last_revealed() {
     assert_eq!(lhs.last_revealed.get(&descriptor_ids[3]), Some(&4));
 }
 
+#[test]
+fn apply_changesets_marked_used() {
+    let external_descriptor = parse_descriptor(DESCRIPTORS[0]);
+    let internal_descriptor = parse_descriptor(DESCRIPTORS[1]);
+    let external_id = external_descriptor.descriptor_id();
+    let mut txout_index = init_txout_index(external_descriptor, internal_descriptor, 0);
+
+    // the latest mark of each index wins
+    let mut changeset = ChangeSet {
+        last_revealed: [(external_id, 3)].into(),
+        marked_used: [(external_id, [(1, true), (2, true)].into())].into(),
+        ..Default::default()
+    };
+    changeset.append(ChangeSet {
+        marked_used: [(external_id, [(2, false), (3, true)].into())].into(),
+        ..Default::default()
+    });
+    assert_eq!(
+        changeset.marked_used,
+        [(external_id, [(1, true), (2, false), (3, true)].into())].into()
+    );
+
+    txout_index.apply_changeset(changeset);
+    assert!(txout_index.is_used(TestKeychain::External, 1));
+    assert!(!txout_index.is_used(TestKeychain::External, 2));
+    assert!(txout_index.is_used(TestKeychain::External, 3));
+    assert_eq!(
+        txout_index
+            .unused_keychain_spks(&TestKeychain::External)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>(),
+        [0, 2]
+    );
+}
+
 #[test]
 fn when_apply_contradictory_changesets_they_are_ignored() {
     let external_descriptor = parse_descriptor(DESCRIPTORS[0]);
@@ -118,6 +155,7 @@ fn when_apply_contradictory_changesets_they_are_ignored() {
     let changeset = ChangeSet {
         keychains_added: [(TestKeychain::External, internal_descriptor.clone())].into(),
         last_revealed: [].into(),
+        marked_used: Default::default(),
     };
     txout_index.apply_changeset(changeset);
 
@@ -132,6 +170,7 @@ fn when_apply_contradictory_changesets_they_are_ignored() {
     let changeset = ChangeSet {
         keychains_added: [(TestKeychain::Internal, external_descriptor.clone())].into(),
         last_revealed: [].into(),
+        marked_used: Default::default(),
     };
     txout_index.apply_changeset(changeset);
 
@@ -163,7 +202,8 @@ fn test_set_all_derivation_indices() {
         txout_index.reveal_to_target_multi(&derive_to),
         ChangeSet {
             keychains_added: BTreeMap::new(),
-            last_revealed: last_revealed.clone()
+            last_revealed: last_revealed.clone(),
+            marked_used: Default::default(),
         }
     );
     assert_eq!(txout_index.last_revealed_indices(), derive_to);
@@ -175,6 +215,44 @@ fn test_set_all_derivation_indices() {
     assert_eq!(txout_index.initial_changeset().last_revealed, last_revealed);
 }
 
+#[test]
+fn test_initial_changeset_keeps_marked_used() {
+    let external_descriptor = parse_descriptor(DESCRIPTORS[0]);
+    let internal_descriptor = parse_descriptor(DESCRIPTORS[1]);
+    let mut txout_index =
+        init_txout_index(external_descriptor.clone(), internal_descriptor.clone(), 0);
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 3);
+    assert!(txout_index.mark_used(TestKeychain::External, 1));
+    assert!(txout_index.mark_used(TestKeychain::External, 2));
+    assert!(txout_index.unmark_used(TestKeychain::External, 2));
+
+    // an index used by an output is not a manual mark
+    let tx = Transaction {
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: spk_at_index(&external_descriptor, 3),
+        }],
+        ..common::new_tx(0)
+    };
+    let _ = txout_index.index_tx(&tx);
+
+    let initial_changeset = txout_index.initial_changeset();
+    assert_eq!(
+        initial_changeset.marked_used,
+        [(external_descriptor.descriptor_id(), [(1, true)].into())].into()
+    );
+
+    let mut restored = KeychainTxOutIndex::<TestKeychain>::new(0);
+    restored.apply_changeset(initial_changeset);
+    let _ = restored.index_tx(&tx);
+    for index in 0..=3 {
+        assert_eq!(
+            restored.is_used(TestKeychain::External, index),
+            txout_index.is_used(TestKeychain::External, index)
+        );
+    }
+}
+
 #[test]
 fn test_lookahead() {
     let external_descriptor = parse_descriptor(DESCRIPTORS[0]);
@@ -645,7 +723,8 @@ fn insert_descriptor_no_change() {
         txout_index.insert_descriptor((), desc.clone()),
         Ok(keychain::ChangeSet {
             keychains_added: [((), desc.clone())].into(),
-            last_revealed: Default::default()
+            last_revealed: Default::default(),
+            marked_used: Default::default(),
         }),
     );
     assert_eq!(
@@ -663,10 +742,12 @@ fn applying_changesets_one_by_one_vs_aggregate_must_have_same_result() {
         ChangeSet {
             keychains_added: [(TestKeychain::Internal, desc.clone())].into(),
             last_revealed: [].into(),
+            marked_used: Default::default(),
         },
         ChangeSet {
             keychains_added: [(TestKeychain::External, desc.clone())].into(),
             last_revealed: [(desc.descriptor_id(), 12)].into(),
+            marked_used: Default::default(),
         },
     ];
 
@@ -845,4 +845,16 @@ proptest! {
         let heights = cp.range(range).map(|cp| cp.height()).collect::<Vec<u32>>();
         prop_assert_eq!(heights, exp_heights);
     }
+    /// Ensure that [`CheckPoint::get`] finds the checkpoints of dense and sparse chains, by
+    /// comparing it against a linear search.
+    #[test]
+    fn checkpoint_get(
+        heights in proptest::collection::vec(0..2_200_u32, 1..50),
+        cp in generate_checkpoints(2_100, 2_100)
+    ) {
+        for height in heights {
+            let exp = cp.iter().find(|cp| cp.height() == height).map(|cp| cp.block_id());
+            prop_assert_eq!(cp.get(height).map(|cp| cp.block_id()), exp);
+        }
+    }
 }
@@ -105,7 +105,8 @@ fn insert_txouts() {
                     txs: [].into(),
                     txouts: [].into(),
                     anchors: [(unconf_anchor, outpoint.txid)].into(),
-                    last_seen: [].into()
+                    last_seen: [].into(),
+                    last_evicted: [].into()
                 }
             );
             // Mark them last seen at.
@@ -115,7 +116,8 @@ fn insert_txouts() {
                     txs: [].into(),
                     txouts: [].into(),
                     anchors: [].into(),
-                    last_seen: [(outpoint.txid, 1000000)].into()
+                    last_seen: [(outpoint.txid, 1000000)].into(),
+                    last_evicted: [].into()
                 }
             );
         }
@@ -135,7 +137,8 @@ fn insert_txouts() {
                 txs: [].into(),
                 txouts: [].into(),
                 anchors: [(conf_anchor, update_txs.compute_txid())].into(),
-                last_seen: [].into()
+                last_seen: [].into(),
+                last_evicted: [].into()
             }
         );
         graph
@@ -154,7 +157,8 @@ fn insert_txouts() {
                 (unconf_anchor, h!("tx2"))
             ]
             .into(),
-            last_seen: [(h!("tx2"), 1000000)].into()
+            last_seen: [(h!("tx2"), 1000000)].into(),
+            last_evicted: [].into()
         }
     );
 
@@ -211,7 +215,8 @@ fn insert_txouts() {
                 (unconf_anchor, h!("tx2"))
             ]
             .into(),
-            last_seen: [(h!("tx2"), 1000000)].into()
+            last_seen: [(h!("tx2"), 1000000)].into(),
+            last_evicted: [].into()
         }
     );
 }
@@ -271,7 +276,7 @@ fn insert_tx_graph_keeps_track_of_spend() {
 
     assert_eq!(
         graph1.outspends(op),
-        &iter::once(tx2.compute_txid()).collect::<HashSet<_>>()
+        &iter::once(tx2.compute_txid()).collect::<BTreeSet<_>>()
     );
     assert_eq!(graph2.outspends(op), graph1.outspends(op));
 }
@@ -1120,6 +1125,63 @@ fn update_last_seen_unconfirmed() {
     assert_eq!(graph.full_txs().next().unwrap().last_seen_unconfirmed, 2);
 }
 
+#[test]
+fn insert_evicted_at() {
+    let chain = local_chain![(0, h!("genesis"))];
+    let tip = chain.tip().block_id();
+    let parent = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(h!("funding"), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new(),
+        }],
+        ..new_tx(0)
+    };
+    let child = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(parent.compute_txid(), 0),
+            ..Default::default()
+        }],
+        ..new_tx(1)
+    };
+    let (parent_txid, child_txid) = (parent.compute_txid(), child.compute_txid());
+
+    let mut graph = TxGraph::<ConfirmationHeightAnchor>::new([parent, child]);
+    let _ = graph.insert_seen_at(parent_txid, 100);
+    let _ = graph.insert_seen_at(child_txid, 100);
+    let is_canonical =
+        |graph: &TxGraph<_>, txid| graph.get_chain_position(&chain, tip, txid).is_some();
+
+    // an eviction before the tx was last seen has no effect
+    let changeset = graph.insert_evicted_at(parent_txid, 50);
+    assert_eq!(changeset.last_evicted, [(parent_txid, 50)].into());
+    assert!(is_canonical(&graph, parent_txid));
+    assert!(is_canonical(&graph, child_txid));
+
+    // the evicted tx and its descendants are no longer canonical
+    let _ = graph.insert_evicted_at(parent_txid, 150);
+    assert_eq!(graph.last_evicted(parent_txid), Some(150));
+    assert!(!is_canonical(&graph, parent_txid));
+    assert!(!is_canonical(&graph, child_txid));
+
+    // only the latest eviction is kept
+    assert!(graph.insert_evicted_at(parent_txid, 120).is_empty());
+    assert_eq!(graph.last_evicted(parent_txid), Some(150));
+
+    // until it is seen again
+    let _ = graph.insert_seen_at(parent_txid, 200);
+    assert!(is_canonical(&graph, parent_txid));
+    assert!(is_canonical(&graph, child_txid));
+
+    // evictions are part of the changesets
+    let mut restored = TxGraph::<ConfirmationHeightAnchor>::default();
+    restored.apply_changeset(graph.initial_changeset());
+    assert_eq!(restored, graph);
+}
+
 #[test]
 /// The `map_anchors` allow a caller to pass a function to reconstruct the [`TxGraph`] with any [`Anchor`],
 /// even though the function is non-deterministic.
@@ -1202,3 +1264,116 @@ fn call_map_anchors_with_non_deterministic_anchor() {
         ]
     );
 }
+
+/// The shared store needs `std`, for its lock
+#[cfg(feature = "std")]
+#[test]
+fn shared_tx_store() {
+    use bdk_chain::tx_graph::TxStore;
+
+    const GRAPHS: usize = 10;
+    let txs = (0..10_000)
+        .map(|i| Transaction {
+            input: vec![TxIn {
+                previous_output: OutPoint::new(h!("funding"), i),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+            ..new_tx(0)
+        })
+        .collect::<Vec<_>>();
+    let store = Arc::new(TxStore::new());
+
+    // each graph gets its own copy of the transactions, like wallets loading them from storage
+    let mut graphs = Vec::new();
+    let mut shared_graphs = Vec::new();
+    for _ in 0..GRAPHS {
+        let mut graph = TxGraph::<()>::default();
+        let mut shared_graph = TxGraph::<()>::new_with_store(store.clone());
+        for tx in &txs {
+            let changeset = graph.insert_tx(tx.clone());
+            assert_eq!(shared_graph.insert_tx(tx.clone()), changeset);
+            let _ = graph.insert_anchor(tx.compute_txid(), ());
+            let _ = shared_graph.insert_anchor(tx.compute_txid(), ());
+        }
+        assert_eq!(shared_graph, graph);
+        assert_eq!(shared_graph.initial_changeset(), graph.initial_changeset());
+        graphs.push(graph);
+        shared_graphs.push(shared_graph);
+    }
+    assert_eq!(store.len(), txs.len());
+    let txid = txs[0].compute_txid();
+    assert!(Arc::ptr_eq(
+        &shared_graphs[0].get_tx(txid).unwrap(),
+        &shared_graphs[1].get_tx(txid).unwrap()
+    ));
+
+    // the shared transactions are counted once across the graphs, up to a byte per transaction
+    // and graph, and the rest is not shared
+    let footprint = graphs[0].memory_footprint();
+    let shared_footprint = shared_graphs[0].memory_footprint();
+    assert!(footprint.txs > 0);
+    assert!(footprint.txs / GRAPHS - shared_footprint.txs <= txs.len());
+    assert_eq!(shared_footprint.txouts, footprint.txouts);
+    assert_eq!(shared_footprint.anchors, footprint.anchors);
+    assert_eq!(shared_footprint.indexes, footprint.indexes);
+    let total = |graphs: &[TxGraph]| {
+        graphs
+            .iter()
+            .map(|graph| graph.memory_footprint().total())
+            .sum::<usize>()
+    };
+    let saved = total(&graphs) - total(&shared_graphs);
+    assert!(saved >= (GRAPHS - 1) * footprint.txs);
+    assert!(saved <= (GRAPHS - 1) * footprint.txs + GRAPHS * txs.len());
+
+    // the transactions are freed with the graphs
+    shared_graphs.truncate(1);
+    assert_eq!(shared_graphs[0].memory_footprint(), footprint);
+    drop(shared_graphs);
+    assert!(store.is_empty());
+}
+
+#[test]
+fn txids_paying_to() {
+    let spk = ScriptBuf::from_bytes(vec![0x51]);
+    let pay = |vout: u32, script_pubkey: ScriptBuf| Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(h!("funding"), vout),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey,
+        }],
+        ..new_tx(0)
+    };
+    let (tx_a, tx_b, tx_c) = (
+        pay(0, spk.clone()),
+        pay(1, spk.clone()),
+        pay(2, ScriptBuf::new()),
+    );
+
+    let mut graph = TxGraph::<()>::default();
+    // floating txouts are not payments
+    let _ = graph.insert_txout(
+        OutPoint::new(tx_a.compute_txid(), 0),
+        tx_a.output[0].clone(),
+    );
+    assert!(graph.txids_paying_to(&spk).is_empty());
+    for tx in [&tx_a, &tx_b, &tx_c] {
+        let _ = graph.insert_tx(tx.clone());
+    }
+    assert_eq!(
+        graph.txids_paying_to(&spk),
+        &[tx_a.compute_txid(), tx_b.compute_txid()].into()
+    );
+
+    // the index is rebuilt from the changesets
+    let mut restored = TxGraph::<()>::default();
+    restored.apply_changeset(graph.initial_changeset());
+    assert_eq!(restored.txids_paying_to(&spk), graph.txids_paying_to(&spk));
+}
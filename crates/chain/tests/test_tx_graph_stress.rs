@@ -0,0 +1,144 @@
+//! Canonicalization of large pathological graphs.
+//!
+//! This is its own test binary since it installs a counting global allocator, the single test
+//! runs alone so that the counts only include its own allocations.
+
+// The bound on the allocations assumes hash collections, the `collections` of `bdk_chain` are
+// B-trees without `std` and `hashbrown`.
+#![cfg(any(feature = "std", feature = "hashbrown"))]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bdk_chain::{local_chain::LocalChain, tx_graph::TxGraph, BlockId};
+use bitcoin::{
+    absolute, hashes::Hash, transaction, Amount, BlockHash, OutPoint, ScriptBuf, Transaction, TxIn,
+    TxOut, Txid,
+};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            let current = CURRENT_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// The number of allocations and the peak of allocated bytes above the bytes allocated before,
+/// while running `f`.
+fn measure<R>(f: impl FnOnce() -> R) -> (R, usize, usize) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let base = CURRENT_BYTES.load(Ordering::Relaxed);
+    PEAK_BYTES.store(base, Ordering::Relaxed);
+    let r = f();
+    (
+        r,
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        PEAK_BYTES.load(Ordering::Relaxed) - base,
+    )
+}
+
+fn spend(previous_output: OutPoint, tag: u32) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::from_consensus(tag),
+        input: vec![TxIn {
+            previous_output,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new(),
+        }],
+    }
+}
+
+/// A long chain of unconfirmed transactions, and a transaction double spent by many
+/// unconfirmed transactions which all have a child.
+fn pathological_graph(chain_len: u32, conflicts: u32) -> (TxGraph<BlockId>, Vec<OutPoint>) {
+    let mut graph = TxGraph::<BlockId>::default();
+    let mut outpoints = vec![];
+
+    let mut previous_output = OutPoint::new(Txid::all_zeros(), 0);
+    for i in 0..chain_len {
+        let tx = spend(previous_output, i);
+        previous_output = OutPoint::new(tx.compute_txid(), 0);
+        outpoints.push(previous_output);
+        let _ = graph.insert_tx(tx);
+        let _ = graph.insert_seen_at(previous_output.txid, u64::from(i));
+    }
+
+    let parent = spend(OutPoint::new(Txid::all_zeros(), 1), 0);
+    let parent_outpoint = OutPoint::new(parent.compute_txid(), 0);
+    let _ = graph.insert_tx(parent);
+    for i in 0..conflicts {
+        let conflict = spend(parent_outpoint, i);
+        let child = spend(OutPoint::new(conflict.compute_txid(), 0), i);
+        for tx in [conflict, child] {
+            let txid = tx.compute_txid();
+            outpoints.push(OutPoint::new(txid, 0));
+            let _ = graph.insert_tx(tx);
+            let _ = graph.insert_seen_at(txid, u64::from(i));
+        }
+    }
+    (graph, outpoints)
+}
+
+#[test]
+fn canonicalize_pathological_graph() {
+    const CHAIN_LEN: u32 = 500;
+    const CONFLICTS: u32 = 200;
+
+    let (graph, outpoints) = pathological_graph(CHAIN_LEN, CONFLICTS);
+    let chain = LocalChain::from_genesis_hash(BlockHash::all_zeros()).0;
+    let tip = chain.tip().block_id();
+
+    let (canonical_txs, allocations, peak_bytes) =
+        measure(|| graph.list_chain_txs(&chain, tip).count());
+    // the whole chain, the parent of the conflicts and the last seen conflict with its child
+    assert_eq!(canonical_txs, CHAIN_LEN as usize + 3);
+    // the walks reuse their buffers from one transaction to the next
+    let txs = (CHAIN_LEN + 1 + 2 * CONFLICTS) as usize;
+    assert!(
+        allocations < txs,
+        "{} allocations to canonicalize {} transactions",
+        allocations,
+        txs
+    );
+    assert!(peak_bytes < 1 << 20, "peak of {} bytes", peak_bytes);
+
+    let (unspents, allocations, peak_bytes) = measure(|| {
+        graph
+            .filter_chain_unspents(&chain, tip, outpoints.iter().map(|&op| ((), op)))
+            .count()
+    });
+    // the end of the chain and the child of the last seen conflict
+    assert_eq!(unspents, 2);
+    assert!(
+        allocations < 2 * outpoints.len(),
+        "{} allocations to filter {} outpoints",
+        allocations,
+        outpoints.len()
+    );
+    assert!(peak_bytes < 1 << 20, "peak of {} bytes", peak_bytes);
+}
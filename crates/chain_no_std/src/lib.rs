@@ -0,0 +1,231 @@
+//! Exercises the core structures of [`bdk_chain`] built without `std`.
+//!
+//! The crate is `#![no_std]`, building it for a target without `std` (such as
+//! `wasm32-unknown-unknown` or `thumbv7em-none-eabihf`) checks that `bdk_chain` does too. Its tests
+//! run the same code on the host.
+
+#![no_std]
+#![warn(missing_docs)]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::str::FromStr;
+
+use bdk_chain::{
+    bitcoin::{
+        absolute, hashes::Hash, transaction, Amount, BlockHash, OutPoint, ScriptBuf, Transaction,
+        TxIn, TxOut,
+    },
+    keychain::{Balance, KeychainTxOutIndex},
+    local_chain::{CannotConnectError, CheckPoint, LocalChain},
+    miniscript::{Descriptor, DescriptorPublicKey},
+    BlockId, ConfirmationTimeHeightAnchor, IndexedTxGraph,
+};
+
+/// The keychains of [`Tracker`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Keychain {
+    /// The receiving addresses
+    External,
+    /// The change addresses
+    Internal,
+}
+
+/// A deterministic block hash for `height`
+pub fn block_hash(height: u32) -> BlockHash {
+    BlockHash::hash(&height.to_le_bytes())
+}
+
+/// The checkpoints of the blocks `0..len`, with the hashes of [`block_hash`]
+pub fn checkpoints(len: u32) -> CheckPoint {
+    CheckPoint::from_block_ids((0..len).map(|height| BlockId {
+        height,
+        hash: block_hash(height),
+    }))
+    .expect("the heights are increasing")
+}
+
+/// A local chain, the transactions relevant to two keychains and their index
+#[derive(Debug)]
+pub struct Tracker {
+    /// The local chain
+    pub chain: LocalChain,
+    /// The transactions and the index of the script pubkeys of the keychains
+    pub graph: IndexedTxGraph<ConfirmationTimeHeightAnchor, KeychainTxOutIndex<Keychain>>,
+}
+
+impl Tracker {
+    /// A tracker of the `external` and `internal` descriptors, with a chain of the genesis block
+    /// of [`block_hash`]
+    pub fn new(external: &str, internal: &str) -> Result<Self, String> {
+        let mut index = KeychainTxOutIndex::new(10);
+        for (keychain, descriptor) in [
+            (Keychain::External, external),
+            (Keychain::Internal, internal),
+        ] {
+            let descriptor = Descriptor::<DescriptorPublicKey>::from_str(descriptor)
+                .map_err(|e| alloc::format!("{}", e))?;
+            let _ = index
+                .insert_descriptor(keychain, descriptor)
+                .map_err(|e| alloc::format!("{}", e))?;
+        }
+        let (chain, _) = LocalChain::from_genesis_hash(block_hash(0));
+        Ok(Self {
+            chain,
+            graph: IndexedTxGraph::new(index),
+        })
+    }
+
+    /// Extend the local chain to the blocks `0..len`
+    pub fn extend_chain(&mut self, len: u32) -> Result<(), CannotConnectError> {
+        self.chain.apply_update(checkpoints(len))?;
+        Ok(())
+    }
+
+    /// Reveal the next script pubkey of `keychain`
+    pub fn reveal_next(&mut self, keychain: Keychain) -> ScriptBuf {
+        let ((_, spk), changeset) = self
+            .graph
+            .index
+            .reveal_next_spk(&keychain)
+            .expect("the keychains are in the index");
+        self.graph.apply_changeset(changeset.into());
+        spk
+    }
+
+    /// Insert a transaction paying `value` to `spk`, spending `previous_output`, and its anchor if
+    /// it's confirmed at `confirmation_height`
+    pub fn receive(
+        &mut self,
+        previous_output: OutPoint,
+        spk: ScriptBuf,
+        value: Amount,
+        confirmation_height: Option<u32>,
+    ) -> OutPoint {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: Vec::from([TxIn {
+                previous_output,
+                ..Default::default()
+            }]),
+            output: Vec::from([TxOut {
+                value,
+                script_pubkey: spk,
+            }]),
+        };
+        let txid = tx.compute_txid();
+        let _ = self.graph.insert_tx(tx);
+        match confirmation_height {
+            Some(height) => {
+                let _ = self.graph.insert_anchor(
+                    txid,
+                    ConfirmationTimeHeightAnchor {
+                        confirmation_height: height,
+                        anchor_block: BlockId {
+                            height,
+                            hash: block_hash(height),
+                        },
+                        confirmation_time: u64::from(height) * 600,
+                    },
+                );
+            }
+            None => {
+                let _ = self.graph.insert_seen_at(txid, 1);
+            }
+        }
+        OutPoint::new(txid, 0)
+    }
+
+    /// The balance of the keychains, the internal one being trusted
+    pub fn balance(&self) -> Balance {
+        self.graph.graph().balance(
+            &self.chain,
+            self.chain.tip().block_id(),
+            self.graph.index.outpoints().iter().cloned(),
+            |(keychain, _), _| *keychain == Keychain::Internal,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXTERNAL: &str = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)";
+    const INTERNAL: &str = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/1/*)";
+
+    #[test]
+    fn test_checkpoints() {
+        let tip = checkpoints(10);
+        assert_eq!(tip.height(), 9);
+        assert_eq!(tip.iter().count(), 10);
+        assert_eq!(tip.get(4).map(|cp| cp.hash()), Some(block_hash(4)));
+        assert!(tip.get(10).is_none());
+    }
+
+    #[test]
+    fn test_local_chain() {
+        let (mut chain, _) = LocalChain::from_genesis_hash(block_hash(0));
+        let changeset = chain.apply_update(checkpoints(5)).expect("must connect");
+        assert_eq!(changeset.len(), 4);
+        assert_eq!(chain.tip().height(), 4);
+
+        // An update replacing blocks without a point of agreement doesn't connect.
+        let other = CheckPoint::from_block_ids([3, 4, 5].map(|height| BlockId {
+            height,
+            hash: block_hash(100 + height),
+        }))
+        .expect("the heights are increasing");
+        assert!(chain.apply_update(other).is_err());
+        assert_eq!(chain.tip().height(), 4);
+    }
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = Tracker::new(EXTERNAL, INTERNAL).expect("valid descriptors");
+        tracker.extend_chain(10).expect("must connect");
+
+        let external = tracker.reveal_next(Keychain::External);
+        let internal = tracker.reveal_next(Keychain::Internal);
+        assert_ne!(external, internal);
+        assert_eq!(
+            tracker.graph.index.index_of_spk(&external),
+            Some(&(Keychain::External, 0))
+        );
+
+        let funding = tracker.receive(
+            OutPoint::null(),
+            external,
+            Amount::from_sat(50_000),
+            Some(5),
+        );
+        let _change = tracker.receive(funding, internal, Amount::from_sat(40_000), None);
+        assert_eq!(
+            tracker.balance(),
+            Balance {
+                trusted_pending: Amount::from_sat(40_000),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            tracker.graph.index.last_revealed_index(&Keychain::External),
+            Some(0)
+        );
+
+        // An unrelated transaction doesn't change the balance.
+        let _ = tracker.receive(
+            OutPoint::null(),
+            ScriptBuf::new(),
+            Amount::from_sat(1_000),
+            Some(6),
+        );
+        assert_eq!(tracker.balance().total(), Amount::from_sat(40_000));
+    }
+
+    #[test]
+    fn test_invalid_descriptor() {
+        assert!(Tracker::new("wpkh(invalid)", INTERNAL).is_err());
+    }
+}
@@ -1,42 +1,294 @@
 use bdk_chain::{
-    bitcoin::{OutPoint, ScriptBuf, Transaction, Txid},
-    collections::{BTreeMap, HashMap, HashSet},
+    bitcoin::{block::Header, FeeRate, OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     local_chain::CheckPoint,
-    spk_client::{FullScanRequest, FullScanResult, SyncRequest, SyncResult},
+    prevout::PrevoutSource,
+    spk_client::{
+        BackendError, BackendOptions, BroadcastBackend, BroadcastError, FullScanRequest,
+        FullScanResult, SyncBackend, SyncRequest, SyncResult,
+    },
+    tiered_fetch::{BlockTxSource, BlockTxs},
     tx_graph::TxGraph,
     BlockId, ConfirmationHeightAnchor, ConfirmationTimeHeightAnchor,
 };
 use core::str::FromStr;
-use electrum_client::{ElectrumApi, Error, HeaderNotification};
-use std::sync::{Arc, Mutex};
+use core::{cell::Cell, fmt};
+use electrum_client::{
+    utils::validate_merkle_proof, Batch, ElectrumApi, Error, GetHistoryRes, GetMerkleRes,
+    HeaderNotification, Param,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::Duration;
 
 /// We include a chain suffix of a certain length for the purpose of robustness.
 const CHAIN_SUFFIX_LENGTH: u32 = 8;
 
+/// The default interval between two polls of a [`HeaderSubscription`].
+const HEADER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Options of [`BdkElectrumClient::sync_with_options`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncOptions {
+    /// The max number of script pubkeys, transactions, txids or outpoints to request for in a
+    /// single batch request
+    pub batch_size: usize,
+    /// Whether to fetch the previous `TxOut`s of the transactions, for fee calculation
+    pub fetch_prev_txouts: bool,
+    /// Whether to verify the confirmations reported by the server with SPV merkle proofs
+    ///
+    /// For every anchor of the update, the merkle proof of the transaction and the header of its
+    /// confirmation block are fetched, and the proof is verified against the merkle root of the
+    /// header. The anchor is then moved to the confirmation block, which is added to the chain
+    /// update. The anchors which fail verification are handled according to
+    /// [`invalid_proof`](Self::invalid_proof).
+    ///
+    /// This makes one more request per confirmed transaction, verification is off by default.
+    pub verify_merkle: bool,
+    /// What to do with an anchor which fails merkle proof verification
+    pub invalid_proof: InvalidProof,
+    /// Whether to track the mempool: the unconfirmed transactions of the update are given a last
+    /// seen time of now, and the txids of the [`SyncRequest`] which are neither confirmed nor in
+    /// the mempool anymore are given an eviction time of now
+    ///
+    /// The script histories of the Electrum protocol include the mempool, so this makes no more
+    /// requests. The txids to check are typically the unconfirmed transactions of the wallet.
+    pub detect_evictions: bool,
+    /// Whether to fetch the unconfirmed parents of the unconfirmed transactions of the update
+    ///
+    /// The parents are added to the update, so that the fee of an incoming unconfirmed payment can
+    /// be calculated and the fee rate of its package estimated. Only one level of ancestors is
+    /// fetched. The previous `TxOut`s spent from confirmed parents are added too.
+    pub fetch_unconfirmed_parents: bool,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 25,
+            fetch_prev_txouts: false,
+            verify_merkle: false,
+            invalid_proof: InvalidProof::Reject,
+            detect_evictions: true,
+            fetch_unconfirmed_parents: false,
+        }
+    }
+}
+
+/// Options of the reconnecting mode of a [`BdkElectrumClient`], see
+/// [`BdkElectrumClient::with_reconnect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectOptions {
+    /// The max number of times a request is retried after a transport error
+    pub max_retries: u8,
+    /// The delay before the first reconnection, doubled for each next one
+    pub initial_backoff: Duration,
+    /// The max delay before a reconnection
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ReconnectOptions {
+    /// The delay before reconnecting after `retries` retries
+    fn backoff(&self, retries: u8) -> Duration {
+        self.initial_backoff
+            .checked_mul(1 << u32::from(retries).min(31))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+/// The reconnecting mode of a [`BdkElectrumClient`]
+struct Reconnect<E> {
+    options: ReconnectOptions,
+    connect: Box<dyn Fn() -> Result<E, Error> + Send + Sync>,
+}
+
+impl<E> fmt::Debug for Reconnect<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reconnect")
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The protocol version and optional features of an Electrum server, detected by
+/// [`BdkElectrumClient::negotiate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    /// The protocol version negotiated with `server.version`, such as `1.4`
+    pub protocol_version: String,
+    /// Whether the server answers batched requests, probed with a batch of
+    /// `blockchain.scripthash.get_mempool`
+    ///
+    /// The requests of a batch are made one by one if it doesn't.
+    pub supports_batch_mempool: bool,
+    /// Whether the server notifies the new chain tips with `blockchain.headers.subscribe` in the
+    /// format of the protocol 1.3 and later, which is needed to sync
+    pub supports_headers_subscribe: bool,
+    /// The software of the server, such as `ElectrumX 1.16.0` or `Fulcrum 1.9.8`
+    pub server_software: String,
+}
+
+impl ServerCapabilities {
+    /// Whether the negotiated protocol version is at least `major.minor`
+    fn protocol_at_least(&self, major: u32, minor: u32) -> bool {
+        let mut version = self
+            .protocol_version
+            .split('.')
+            .map(|n| n.parse::<u32>().unwrap_or(0));
+        let version = (version.next().unwrap_or(0), version.next().unwrap_or(0));
+        version >= (major, minor)
+    }
+}
+
+/// What to do with a confirmation which fails merkle proof verification, see
+/// [`SyncOptions::verify_merkle`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidProof {
+    /// Fail the sync with an [`Error::Message`]
+    #[default]
+    Reject,
+    /// Drop the anchor, so that the transaction is reported as unconfirmed
+    Unconfirmed,
+}
+
 /// Wrapper around an [`electrum_client::ElectrumApi`] which includes an internal in-memory
 /// transaction cache to avoid re-fetching already downloaded transactions.
 #[derive(Debug)]
 pub struct BdkElectrumClient<E> {
-    /// The internal [`electrum_client::ElectrumApi`]
-    pub inner: E,
+    /// The internal [`electrum_client::ElectrumApi`], replaced on reconnection
+    inner: RwLock<E>,
+    /// The reconnecting mode, if enabled
+    reconnect: Option<Reconnect<E>>,
+    /// The number of reconnections
+    reconnect_count: AtomicUsize,
+    /// The capabilities of the server, if negotiated
+    capabilities: RwLock<Option<ServerCapabilities>>,
     /// The transaction cache
     tx_cache: Mutex<HashMap<Txid, Arc<Transaction>>>,
+    /// The cache of the previous `TxOut`s whose transactions are not cached
+    txout_cache: Mutex<HashMap<OutPoint, TxOut>>,
 }
 
 impl<E: ElectrumApi> BdkElectrumClient<E> {
     /// Creates a new bdk client from a [`electrum_client::ElectrumApi`]
     pub fn new(client: E) -> Self {
         Self {
-            inner: client,
+            inner: RwLock::new(client),
+            reconnect: None,
+            reconnect_count: AtomicUsize::new(0),
+            capabilities: RwLock::new(None),
             tx_cache: Default::default(),
+            txout_cache: Default::default(),
+        }
+    }
+
+    /// Enable the reconnecting mode: a request which fails with a transport error, such as a
+    /// connection reset by the server, is retried on a new connection made with `connect`.
+    ///
+    /// The new connection negotiates the protocol version with `server.version`. Requests are
+    /// retried up to [`ReconnectOptions::max_retries`] times, with an exponential backoff before
+    /// each reconnection. [`transaction_broadcast`](Self::transaction_broadcast) is only retried
+    /// if the server doesn't know the transaction already.
+    ///
+    /// An `electrum_client::Client` passed to [`new`](Self::new) reconnects on its own, retrying
+    /// every request including broadcasts, unless it is configured with no retries. The
+    /// [`Builder`](crate::Builder) does so for
+    /// [`reconnect`](crate::Builder::reconnect).
+    pub fn with_reconnect(
+        mut self,
+        options: ReconnectOptions,
+        connect: impl Fn() -> Result<E, Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.reconnect = Some(Reconnect {
+            options,
+            connect: Box::new(connect),
+        });
+        self
+    }
+
+    /// The internal [`electrum_client::ElectrumApi`]
+    ///
+    /// The guard must be dropped before making requests through `self`, which may reconnect.
+    pub fn inner(&self) -> RwLockReadGuard<'_, E> {
+        self.inner.read().expect("must lock")
+    }
+
+    /// The number of times the client reconnected to the server, see
+    /// [`with_reconnect`](Self::with_reconnect)
+    pub fn reconnect_count(&self) -> usize {
+        self.reconnect_count.load(Ordering::Relaxed)
+    }
+
+    /// Negotiate the protocol version with `server.version` and detect the capabilities of the
+    /// server, which gate the optional behaviors of the client.
+    ///
+    /// The [`Builder`](crate::Builder) negotiates on connect, and the reconnecting mode on each new
+    /// connection. Some servers accept a single `server.version` per connection, this must only be
+    /// called for a client passed to [`new`](Self::new). Until then, the server is assumed to
+    /// support everything.
+    pub fn negotiate(&self) -> Result<ServerCapabilities, Error> {
+        let capabilities = self.call(|inner| negotiate_capabilities(inner))?;
+        *self.capabilities.write().expect("must lock") = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    /// The capabilities of the server, if [negotiated](Self::negotiate)
+    pub fn capabilities(&self) -> Option<ServerCapabilities> {
+        self.capabilities.read().expect("must lock").clone()
+    }
+
+    /// Whether the negotiated capabilities fail `f`
+    fn lacks(&self, f: impl Fn(&ServerCapabilities) -> bool) -> bool {
+        match &*self.capabilities.read().expect("must lock") {
+            Some(capabilities) => !f(capabilities),
+            None => false,
+        }
+    }
+
+    /// Make a request with `f`, retrying it on a new connection after a transport error in the
+    /// reconnecting mode.
+    fn call<T>(&self, f: impl Fn(&E) -> Result<T, Error>) -> Result<T, Error> {
+        let mut retries = 0;
+        loop {
+            let err = match f(&self.inner()) {
+                Err(err) if is_transport_error(&err) => err,
+                res => return res,
+            };
+            let reconnect = match &self.reconnect {
+                Some(reconnect) if retries < reconnect.options.max_retries => reconnect,
+                _ => return Err(err),
+            };
+            std::thread::sleep(reconnect.options.backoff(retries));
+            retries += 1;
+            // the server may still be down, the request then fails again on the old connection
+            if let Ok(client) = (reconnect.connect)() {
+                if let Ok(capabilities) = negotiate_capabilities(&client) {
+                    *self.inner.write().expect("must lock") = client;
+                    *self.capabilities.write().expect("must lock") = Some(capabilities);
+                    self.reconnect_count.fetch_add(1, Ordering::Relaxed);
+                }
+            }
         }
     }
 
     /// Inserts transactions into the transaction cache so that the client will not fetch these
     /// transactions.
+    ///
+    /// The floating txouts of `tx_graph`, such as the previous `TxOut`s fetched for fees, are cached
+    /// too, so that their transactions are not fetched again with `fetch_prev_txouts`.
     pub fn populate_tx_cache<A>(&self, tx_graph: impl AsRef<TxGraph<A>>) {
+        let tx_graph = tx_graph.as_ref();
         let txs = tx_graph
-            .as_ref()
             .full_txs()
             .map(|tx_node| (tx_node.txid, tx_node.tx));
 
@@ -44,6 +296,11 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
         for (txid, tx) in txs {
             tx_cache.insert(txid, tx);
         }
+
+        let mut txout_cache = self.txout_cache.lock().unwrap();
+        for (outpoint, txout) in tx_graph.floating_txouts() {
+            txout_cache.insert(outpoint, txout.clone());
+        }
     }
 
     /// Fetch transaction of given `txid`.
@@ -58,18 +315,163 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
 
         drop(tx_cache);
 
-        let tx = Arc::new(self.inner.transaction_get(&txid)?);
+        let tx = Arc::new(self.call(|inner| inner.transaction_get(&txid))?);
 
         self.tx_cache.lock().unwrap().insert(txid, Arc::clone(&tx));
 
         Ok(tx)
     }
 
+    /// Fetch the histories of `scripts`, in a batch unless the server doesn't support batches.
+    fn batch_script_get_history<'s>(
+        &self,
+        scripts: impl IntoIterator<Item = &'s Script> + Clone,
+    ) -> Result<Vec<Vec<GetHistoryRes>>, Error> {
+        if self.lacks(|c| c.supports_batch_mempool) {
+            return scripts
+                .into_iter()
+                .map(|script| self.call(|inner| inner.script_get_history(script)))
+                .collect();
+        }
+        self.call(|inner| inner.batch_script_get_history(scripts.clone()))
+    }
+
+    /// Fetch the transactions of `txids`, in a batch unless the server doesn't support batches.
+    fn batch_transaction_get(&self, txids: &[Txid]) -> Result<Vec<Transaction>, Error> {
+        if self.lacks(|c| c.supports_batch_mempool) {
+            return txids
+                .iter()
+                .map(|txid| self.call(|inner| inner.transaction_get(txid)))
+                .collect();
+        }
+        self.call(|inner| inner.batch_transaction_get(txids))
+    }
+
+    /// Fetch the block headers at `heights`, in a batch unless the server doesn't support batches.
+    fn batch_block_header(&self, heights: &[u32]) -> Result<Vec<Header>, Error> {
+        if self.lacks(|c| c.supports_batch_mempool) {
+            return heights
+                .iter()
+                .map(|&height| self.call(|inner| inner.block_header(height as usize)))
+                .collect();
+        }
+        self.call(|inner| inner.batch_block_header(heights.iter().copied()))
+    }
+
+    /// Fetch the transactions of `txids` which are not in the cache, in batches of at most
+    /// `batch_size` transactions, and insert them into the cache.
+    ///
+    /// A batch which the server rejects, such as one with an unknown txid, is skipped, its
+    /// transactions are left to [`fetch_tx`](Self::fetch_tx).
+    fn prefetch_txs(
+        &self,
+        txids: impl IntoIterator<Item = Txid>,
+        batch_size: usize,
+    ) -> Result<(), Error> {
+        let missing_txids = {
+            let tx_cache = self.tx_cache.lock().unwrap();
+            txids
+                .into_iter()
+                .filter(|txid| !tx_cache.contains_key(txid))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        for txids in missing_txids.chunks(batch_size.max(1)) {
+            let txs = match self.batch_transaction_get(txids) {
+                Ok(txs) => txs,
+                Err(Error::Protocol(_)) => continue,
+                Err(err) => return Err(err),
+            };
+            let mut tx_cache = self.tx_cache.lock().unwrap();
+            for (&txid, tx) in txids.iter().zip(txs) {
+                tx_cache.insert(txid, Arc::new(tx));
+            }
+        }
+        Ok(())
+    }
+
     /// Broadcasts a transaction to the network.
     ///
-    /// This is a re-export of [`ElectrumApi::transaction_broadcast`].
+    /// This is a re-export of [`ElectrumApi::transaction_broadcast`]. In the reconnecting mode, a
+    /// broadcast which fails with a transport error may have reached the server, it is retried
+    /// only if the server doesn't know the transaction.
     pub fn transaction_broadcast(&self, tx: &Transaction) -> Result<Txid, Error> {
-        self.inner.transaction_broadcast(tx)
+        let txid = tx.compute_txid();
+        let attempted = Cell::new(false);
+        self.call(|inner| {
+            if attempted.get() {
+                match inner.transaction_get(&txid) {
+                    Ok(_) => return Ok(txid),
+                    Err(Error::Protocol(_)) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            attempted.set(true);
+            inner.transaction_broadcast(tx)
+        })
+    }
+
+    /// Broadcast `txs` one after the other, returning the result of each in the same order.
+    ///
+    /// A failed broadcast doesn't stop the next ones. The transactions of a package must be
+    /// ordered parents first: a child broadcast before its parent fails with
+    /// [`BroadcastError::MissingInputs`]. A transaction already in the mempool of the server is
+    /// reported as broadcast.
+    pub fn broadcast_all(&self, txs: &[Transaction]) -> Vec<Result<Txid, BroadcastError<Error>>> {
+        txs.iter()
+            .map(|tx| match self.transaction_broadcast(tx) {
+                Ok(txid) => Ok(txid),
+                Err(Error::Protocol(reason)) => {
+                    // the error object of the server has a `code` and a `message`
+                    let reason = reason
+                        .get("message")
+                        .and_then(|message| message.as_str())
+                        .map_or_else(|| reason.to_string(), str::to_string);
+                    match BroadcastError::from_reject_reason(&reason) {
+                        Some(err) => Err(err),
+                        None => Ok(tx.compute_txid()),
+                    }
+                }
+                Err(err) => Err(BroadcastError::Request(err)),
+            })
+            .collect()
+    }
+
+    /// Estimate the fee rate for a transaction to be confirmed within `target_blocks` blocks.
+    ///
+    /// The estimate of `blockchain.estimatefee`, in BTC/kvB, is rounded up to the next sat/kwu.
+    /// Returns `None` if the server has no estimate for `target_blocks`, which it reports with `-1`.
+    ///
+    /// ElectrumX, Fulcrum and electrs all take the estimate from the `estimatesmartfee` RPC
+    /// of their bitcoind. That RPC supports targets from 1 to 1008 blocks. Until bitcoind has
+    /// seen enough transactions confirm, for example on a fresh regtest chain, it has no
+    /// estimate. A fallback such as [`relay_fee`](Self::relay_fee) is then needed.
+    pub fn estimate_fee(&self, target_blocks: usize) -> Result<Option<FeeRate>, Error> {
+        let btc_per_kvb = self.call(|inner| inner.estimate_fee(target_blocks))?;
+        Ok(fee_rate_from_btc_per_kvb(btc_per_kvb))
+    }
+
+    /// The minimum fee rate of the transactions relayed by the server, from `blockchain.relayfee`.
+    pub fn relay_fee(&self) -> Result<FeeRate, Error> {
+        let btc_per_kvb = self.call(|inner| inner.relay_fee())?;
+        fee_rate_from_btc_per_kvb(btc_per_kvb)
+            .ok_or_else(|| Error::Message(format!("invalid relay fee {} BTC/kvB", btc_per_kvb)))
+    }
+
+    /// Subscribe to the new chain tips of the server, with `blockchain.headers.subscribe`.
+    ///
+    /// The returned iterator blocks until the server notifies a new tip, and yields it with an
+    /// update of the chain connected to the previous tip, starting from `chain_tip`. See
+    /// [`HeaderSubscription`].
+    pub fn subscribe_headers(&self, chain_tip: CheckPoint) -> HeaderSubscription<'_, E> {
+        HeaderSubscription {
+            client: self,
+            tip: chain_tip,
+            poll_interval: HEADER_POLL_INTERVAL,
+            polled: false,
+        }
     }
 
     /// Full scan the keychain scripts specified with the blockchain (via an Electrum client) and
@@ -79,16 +481,27 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
     ///              see [`FullScanRequest`]
     /// - `stop_gap`: the full scan for each keychain stops after a gap of script pubkeys with no
     ///              associated transactions
-    /// - `batch_size`: specifies the max number of script pubkeys to request for in a single batch
-    ///              request
+    /// - `batch_size`: specifies the max number of script pubkeys or transactions to request for in
+    ///              a single batch request
     /// - `fetch_prev_txouts`: specifies whether or not we want previous `TxOut`s for fee
+    ///
+    /// The transactions of the script histories of a batch are fetched in batches too, the ones of
+    /// the script pubkeys after the stop gap are not fetched.
+    ///
+    /// A keychain with a stop gap set with [`FullScanRequest::set_stop_gap_for_keychain`] uses it
+    /// instead of `stop_gap`. The result has no [`scan_stats`](FullScanResult::scan_stats).
+    ///
+    /// The closure set with [`FullScanRequest::on_progress`] is called after each batch of script
+    /// histories is fetched.
     pub fn full_scan<K: Ord + Clone>(
         &self,
-        request: FullScanRequest<K>,
+        mut request: FullScanRequest<K>,
         stop_gap: usize,
         batch_size: usize,
         fetch_prev_txouts: bool,
     ) -> Result<ElectrumFullScanResult<K>, Error> {
+        let start = std::time::Instant::now();
+        let mut progress = request.progress_tracker(stop_gap);
         let mut request_spks = request.spks_by_keychain;
 
         // We keep track of already-scanned spks just in case a reorg happens and we need to do a
@@ -100,7 +513,8 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
         let mut scanned_spks = BTreeMap::<(K, u32), (ScriptBuf, bool)>::new();
 
         let update = loop {
-            let (tip, _) = construct_update_tip(&self.inner, request.chain_tip.clone())?;
+            let (tip, _) =
+                self.call(|inner| construct_update_tip(inner, request.chain_tip.clone()))?;
             let mut graph_update = TxGraph::<ConfirmationHeightAnchor>::default();
             let cps = tip
                 .iter()
@@ -119,6 +533,7 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
                                 .map(|(i, (spk, _))| (i.clone(), spk.clone())),
                             stop_gap,
                             batch_size,
+                            |_, _| {},
                         )?,
                     );
                 }
@@ -128,24 +543,36 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
                             &cps,
                             &mut graph_update,
                             keychain_spks,
-                            stop_gap,
+                            request.stop_gaps.get(keychain).copied().unwrap_or(stop_gap),
                             batch_size,
+                            |tx_counts, gap_reached| {
+                                for &txs in tx_counts {
+                                    progress.spk_checked(keychain, txs);
+                                }
+                                if gap_reached {
+                                    progress.keychain_done(keychain);
+                                }
+                                progress.batch_done(start.elapsed());
+                            },
                         )?
                         .into_iter()
                         .map(|(spk_i, spk)| ((keychain.clone(), spk_i), spk)),
                     );
+                    progress.keychain_done(keychain);
                 }
             }
 
             // check for reorgs during scan process
-            let server_blockhash = self.inner.block_header(tip.height() as usize)?.block_hash();
+            let server_blockhash = self
+                .call(|inner| inner.block_header(tip.height() as usize))?
+                .block_hash();
             if tip.hash() != server_blockhash {
                 continue; // reorg
             }
 
             // Fetch previous `TxOut`s for fee calculation if flag is enabled.
             if fetch_prev_txouts {
-                self.fetch_prev_txout(&mut graph_update)?;
+                self.fetch_prev_txout(&mut graph_update, batch_size)?;
             }
 
             let chain_update = tip;
@@ -161,10 +588,12 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
                 })
                 .collect::<BTreeMap<_, _>>();
 
+            progress.finish(start.elapsed());
             break FullScanResult {
                 graph_update,
                 chain_update,
                 last_active_indices: keychain_update,
+                scan_stats: BTreeMap::new(),
             };
         };
 
@@ -176,8 +605,8 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
     ///
     /// - `request`: struct with data required to perform a spk-based blockchain client sync,
     ///              see [`SyncRequest`]
-    /// - `batch_size`: specifies the max number of script pubkeys to request for in a single batch
-    ///              request
+    /// - `batch_size`: specifies the max number of script pubkeys, transactions, txids or outpoints
+    ///              to request for in a single batch request
     /// - `fetch_prev_txouts`: specifies whether or not we want previous `TxOut`s for fee
     ///              calculation
     ///
@@ -191,33 +620,161 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
         batch_size: usize,
         fetch_prev_txouts: bool,
     ) -> Result<ElectrumSyncResult, Error> {
+        self.sync_with_options(
+            request,
+            SyncOptions {
+                batch_size,
+                fetch_prev_txouts,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Like [`sync`](Self::sync), with the options specified with [`SyncOptions`].
+    ///
+    /// With [`SyncOptions::verify_merkle`], the confirmations reported by the server are verified
+    /// with merkle proofs, and the verified blocks are included in the chain update.
+    pub fn sync_with_options(
+        &self,
+        request: SyncRequest,
+        options: SyncOptions,
+    ) -> Result<ElectrumSyncResult, Error> {
+        let batch_size = options.batch_size;
         let full_scan_req = FullScanRequest::from_chain_tip(request.chain_tip.clone())
             .set_spks_for_keychain((), request.spks.enumerate().map(|(i, spk)| (i as u32, spk)));
         let mut full_scan_res = self
             .full_scan(full_scan_req, usize::MAX, batch_size, false)?
             .with_confirmation_height_anchor();
 
-        let (tip, _) = construct_update_tip(&self.inner, request.chain_tip)?;
+        let (tip, _) = self.call(|inner| construct_update_tip(inner, request.chain_tip.clone()))?;
         let cps = tip
             .iter()
             .take(10)
             .map(|cp| (cp.height(), cp))
             .collect::<BTreeMap<u32, CheckPoint>>();
 
-        self.populate_with_txids(&cps, &mut full_scan_res.graph_update, request.txids)?;
-        self.populate_with_outpoints(&cps, &mut full_scan_res.graph_update, request.outpoints)?;
+        let now = std::time::UNIX_EPOCH
+            .elapsed()
+            .expect("must get the time")
+            .as_secs();
+        self.populate_with_txids(
+            &cps,
+            &mut full_scan_res.graph_update,
+            request.txids,
+            batch_size,
+            options.detect_evictions.then_some(now),
+        )?;
+        self.populate_with_outpoints(
+            &cps,
+            &mut full_scan_res.graph_update,
+            request.outpoints,
+            batch_size,
+        )?;
+        if options.fetch_unconfirmed_parents {
+            self.populate_with_unconfirmed_parents(
+                &cps,
+                &mut full_scan_res.graph_update,
+                batch_size,
+            )?;
+        }
+        if options.detect_evictions {
+            let _ = full_scan_res.graph_update.update_last_seen_unconfirmed(now);
+        }
+
+        let (mut graph_update, chain_update) = if options.verify_merkle {
+            self.verify_merkle_proofs(
+                full_scan_res.graph_update,
+                full_scan_res.chain_update,
+                options,
+            )?
+        } else {
+            (full_scan_res.graph_update, full_scan_res.chain_update)
+        };
 
         // Fetch previous `TxOut`s for fee calculation if flag is enabled.
-        if fetch_prev_txouts {
-            self.fetch_prev_txout(&mut full_scan_res.graph_update)?;
+        if options.fetch_prev_txouts {
+            self.fetch_prev_txout(&mut graph_update, batch_size)?;
         }
 
         Ok(ElectrumSyncResult(SyncResult {
-            chain_update: full_scan_res.chain_update,
-            graph_update: full_scan_res.graph_update,
+            chain_update,
+            graph_update,
         }))
     }
 
+    /// Verify the anchors of `graph_update` with merkle proofs, see [`SyncOptions::verify_merkle`].
+    ///
+    /// The anchors of the verified transactions are moved to their confirmation blocks, which are
+    /// inserted into `chain_update`.
+    fn verify_merkle_proofs(
+        &self,
+        graph_update: TxGraph<ConfirmationHeightAnchor>,
+        mut chain_update: CheckPoint,
+        options: SyncOptions,
+    ) -> Result<(TxGraph<ConfirmationHeightAnchor>, CheckPoint), Error> {
+        let heights = graph_update
+            .all_anchors()
+            .iter()
+            .map(|(anchor, _)| anchor.confirmation_height)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let mut headers = BTreeMap::<u32, Header>::new();
+        for heights in heights.chunks(options.batch_size.max(1)) {
+            let batch_headers = self.batch_block_header(heights)?;
+            headers.extend(heights.iter().copied().zip(batch_headers));
+        }
+
+        let confirmations = graph_update
+            .all_anchors()
+            .iter()
+            .map(|(anchor, txid)| (*txid, anchor.confirmation_height))
+            .collect::<BTreeSet<_>>();
+        let mut anchors = BTreeSet::new();
+        for (txid, height) in confirmations {
+            let header = headers.get(&height).expect("header must be fetched");
+            let is_valid =
+                match self.call(|inner| inner.transaction_get_merkle(&txid, height as usize)) {
+                    Ok(merkle) => merkle_proof_is_valid(&txid, height, header, &merkle),
+                    // a server which fails to prove a confirmation is treated as lying about it
+                    Err(Error::Protocol(_)) => false,
+                    Err(err) => return Err(err),
+                };
+            if !is_valid {
+                match options.invalid_proof {
+                    InvalidProof::Reject => {
+                        return Err(Error::Message(format!(
+                            "invalid merkle proof of transaction {} at height {}",
+                            txid, height
+                        )))
+                    }
+                    InvalidProof::Unconfirmed => continue,
+                }
+            }
+
+            let block = BlockId {
+                height,
+                hash: header.block_hash(),
+            };
+            if height > 0 {
+                chain_update = chain_update.insert(block);
+            }
+            anchors.insert((
+                ConfirmationHeightAnchor {
+                    anchor_block: block,
+                    confirmation_height: height,
+                },
+                txid,
+            ));
+        }
+
+        let mut changeset = graph_update.initial_changeset();
+        changeset.anchors = anchors;
+        let mut graph_update = TxGraph::default();
+        graph_update.apply_changeset(changeset);
+        Ok((graph_update, chain_update))
+    }
+
     /// Populate the `graph_update` with transactions/anchors associated with the given `spks`.
     ///
     /// Transactions that contains an output with requested spk, or spends form an output with
@@ -232,6 +789,7 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
         spks: &mut impl Iterator<Item = (I, ScriptBuf)>,
         stop_gap: usize,
         batch_size: usize,
+        mut on_batch: impl FnMut(&[usize], bool),
     ) -> Result<BTreeMap<I, (ScriptBuf, bool)>, Error> {
         let mut unused_spk_count = 0_usize;
         let mut scanned_spks = BTreeMap::new();
@@ -244,50 +802,91 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
                 return Ok(scanned_spks);
             }
 
-            let spk_histories = self
-                .inner
-                .batch_script_get_history(spks.iter().map(|(_, s)| s.as_script()))?;
+            let spk_histories =
+                self.batch_script_get_history(spks.iter().map(|(_, s)| s.as_script()))?;
 
+            // the histories of the spks after the stop gap are discarded
+            let mut gap_reached = false;
+            let mut histories = Vec::with_capacity(spk_histories.len());
             for ((spk_index, spk), spk_history) in spks.into_iter().zip(spk_histories) {
                 if spk_history.is_empty() {
-                    scanned_spks.insert(spk_index, (spk, false));
                     unused_spk_count += 1;
-                    if unused_spk_count > stop_gap {
-                        return Ok(scanned_spks);
-                    }
-                    continue;
+                    gap_reached = unused_spk_count > stop_gap;
                 } else {
-                    scanned_spks.insert(spk_index, (spk, true));
                     unused_spk_count = 0;
                 }
+                scanned_spks.insert(spk_index, (spk, !spk_history.is_empty()));
+                histories.push(spk_history);
+                if gap_reached {
+                    break;
+                }
+            }
 
-                for tx_res in spk_history {
-                    let _ = graph_update.insert_tx(self.fetch_tx(tx_res.tx_hash)?);
-                    if let Some(anchor) = determine_tx_anchor(cps, tx_res.height, tx_res.tx_hash) {
-                        let _ = graph_update.insert_anchor(tx_res.tx_hash, anchor);
-                    }
+            self.prefetch_txs(
+                histories.iter().flatten().map(|tx_res| tx_res.tx_hash),
+                batch_size,
+            )?;
+            let tx_counts = histories.iter().map(Vec::len).collect::<Vec<_>>();
+            for tx_res in histories.into_iter().flatten() {
+                let _ = graph_update.insert_tx(self.fetch_tx(tx_res.tx_hash)?);
+                if let Some(anchor) = determine_tx_anchor(cps, tx_res.height, tx_res.tx_hash) {
+                    let _ = graph_update.insert_anchor(tx_res.tx_hash, anchor);
                 }
             }
+            on_batch(&tx_counts, gap_reached);
+
+            if gap_reached {
+                return Ok(scanned_spks);
+            }
         }
     }
 
     // Helper function which fetches the `TxOut`s of our relevant transactions' previous transactions,
-    // which we do not have by default. This data is needed to calculate the transaction fee.
+    // which we do not have by default. This data is needed to calculate the transaction fee. Only
+    // the previous transactions which are neither in `graph_update` nor in the caches are fetched.
     fn fetch_prev_txout(
         &self,
         graph_update: &mut TxGraph<ConfirmationHeightAnchor>,
+        batch_size: usize,
     ) -> Result<(), Error> {
-        let full_txs: Vec<Arc<Transaction>> =
-            graph_update.full_txs().map(|tx_node| tx_node.tx).collect();
-        for tx in full_txs {
-            for vin in &tx.input {
-                let outpoint = vin.previous_output;
-                let vout = outpoint.vout;
-                let prev_tx = self.fetch_tx(outpoint.txid)?;
-                let txout = prev_tx.output[vout as usize].clone();
-                let _ = graph_update.insert_txout(outpoint, txout);
+        // the previous `TxOut`s in `graph_update`, such as the ones of its own txs, are not fetched
+        let outpoints = graph_update
+            .full_txs()
+            .flat_map(|tx_node| tx_node.tx.input.clone())
+            .map(|vin| vin.previous_output)
+            .filter(|outpoint| graph_update.get_txout(*outpoint).is_none())
+            .collect::<BTreeSet<_>>();
+
+        let mut txouts = Vec::with_capacity(outpoints.len());
+        let mut missing_outpoints = Vec::new();
+        {
+            let tx_cache = self.tx_cache.lock().unwrap();
+            let txout_cache = self.txout_cache.lock().unwrap();
+            for outpoint in outpoints {
+                let cached_txout = match tx_cache.get(&outpoint.txid) {
+                    Some(prev_tx) => prev_tx.output.get(outpoint.vout as usize).cloned(),
+                    None => txout_cache.get(&outpoint).cloned(),
+                };
+                match cached_txout {
+                    Some(txout) => txouts.push((outpoint, txout)),
+                    None => missing_outpoints.push(outpoint),
+                }
             }
         }
+
+        self.prefetch_txs(
+            missing_outpoints.iter().map(|outpoint| outpoint.txid),
+            batch_size,
+        )?;
+        for outpoint in missing_outpoints {
+            let prev_tx = self.fetch_tx(outpoint.txid)?;
+            let txout = prev_tx.output[outpoint.vout as usize].clone();
+            txouts.push((outpoint, txout));
+        }
+
+        for (outpoint, txout) in txouts {
+            let _ = graph_update.insert_txout(outpoint, txout);
+        }
         Ok(())
     }
 
@@ -302,47 +901,74 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
         cps: &BTreeMap<u32, CheckPoint>,
         graph_update: &mut TxGraph<ConfirmationHeightAnchor>,
         outpoints: impl IntoIterator<Item = OutPoint>,
+        batch_size: usize,
     ) -> Result<(), Error> {
+        let outpoints = outpoints.into_iter().collect::<Vec<_>>();
+        self.prefetch_txs(outpoints.iter().map(|op| op.txid), batch_size)?;
+        let mut op_txs = Vec::with_capacity(outpoints.len());
         for outpoint in outpoints {
-            let op_txid = outpoint.txid;
-            let op_tx = self.fetch_tx(op_txid)?;
-            let op_txout = match op_tx.output.get(outpoint.vout as usize) {
-                Some(txout) => txout,
-                None => continue,
-            };
-            debug_assert_eq!(op_tx.compute_txid(), op_txid);
-
-            // attempt to find the following transactions (alongside their chain positions), and
-            // add to our sparsechain `update`:
-            let mut has_residing = false; // tx in which the outpoint resides
-            let mut has_spending = false; // tx that spends the outpoint
-            for res in self.inner.script_get_history(&op_txout.script_pubkey)? {
-                if has_residing && has_spending {
-                    break;
-                }
+            let op_tx = self.fetch_tx(outpoint.txid)?;
+            if op_tx.output.len() > outpoint.vout as usize {
+                op_txs.push((outpoint, op_tx));
+            }
+        }
 
-                if !has_residing && res.tx_hash == op_txid {
-                    has_residing = true;
-                    let _ = graph_update.insert_tx(Arc::clone(&op_tx));
-                    if let Some(anchor) = determine_tx_anchor(cps, res.height, res.tx_hash) {
-                        let _ = graph_update.insert_anchor(res.tx_hash, anchor);
-                    }
+        for op_txs in op_txs.chunks(batch_size.max(1)) {
+            let histories = self.batch_script_get_history(
+                op_txs
+                    .iter()
+                    .map(|(op, op_tx)| op_tx.output[op.vout as usize].script_pubkey.as_script()),
+            )?;
+            for ((outpoint, op_tx), history) in op_txs.iter().zip(histories) {
+                self.populate_with_outpoint(cps, graph_update, *outpoint, op_tx, history)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Populate the `graph_update` with the transactions residing and spending `outpoint`, given
+    /// the transaction `op_tx` of the outpoint and the `history` of its script pubkey.
+    fn populate_with_outpoint(
+        &self,
+        cps: &BTreeMap<u32, CheckPoint>,
+        graph_update: &mut TxGraph<ConfirmationHeightAnchor>,
+        outpoint: OutPoint,
+        op_tx: &Arc<Transaction>,
+        history: Vec<GetHistoryRes>,
+    ) -> Result<(), Error> {
+        let op_txid = outpoint.txid;
+        debug_assert_eq!(op_tx.compute_txid(), op_txid);
+
+        // attempt to find the following transactions (alongside their chain positions), and
+        // add to our sparsechain `update`:
+        let mut has_residing = false; // tx in which the outpoint resides
+        let mut has_spending = false; // tx that spends the outpoint
+        for res in history {
+            if has_residing && has_spending {
+                break;
+            }
+
+            if !has_residing && res.tx_hash == op_txid {
+                has_residing = true;
+                let _ = graph_update.insert_tx(Arc::clone(op_tx));
+                if let Some(anchor) = determine_tx_anchor(cps, res.height, res.tx_hash) {
+                    let _ = graph_update.insert_anchor(res.tx_hash, anchor);
                 }
+            }
 
-                if !has_spending && res.tx_hash != op_txid {
-                    let res_tx = self.fetch_tx(res.tx_hash)?;
-                    // we exclude txs/anchors that do not spend our specified outpoint(s)
-                    has_spending = res_tx
-                        .input
-                        .iter()
-                        .any(|txin| txin.previous_output == outpoint);
-                    if !has_spending {
-                        continue;
-                    }
-                    let _ = graph_update.insert_tx(Arc::clone(&res_tx));
-                    if let Some(anchor) = determine_tx_anchor(cps, res.height, res.tx_hash) {
-                        let _ = graph_update.insert_anchor(res.tx_hash, anchor);
-                    }
+            if !has_spending && res.tx_hash != op_txid {
+                let res_tx = self.fetch_tx(res.tx_hash)?;
+                // we exclude txs/anchors that do not spend our specified outpoint(s)
+                has_spending = res_tx
+                    .input
+                    .iter()
+                    .any(|txin| txin.previous_output == outpoint);
+                if !has_spending {
+                    continue;
+                }
+                let _ = graph_update.insert_tx(Arc::clone(&res_tx));
+                if let Some(anchor) = determine_tx_anchor(cps, res.height, res.tx_hash) {
+                    let _ = graph_update.insert_anchor(res.tx_hash, anchor);
                 }
             }
         }
@@ -355,41 +981,330 @@ impl<E: ElectrumApi> BdkElectrumClient<E> {
         cps: &BTreeMap<u32, CheckPoint>,
         graph_update: &mut TxGraph<ConfirmationHeightAnchor>,
         txids: impl IntoIterator<Item = Txid>,
+        batch_size: usize,
+        evicted_at: Option<u64>,
     ) -> Result<(), Error> {
+        // the txids in the histories of the spks are confirmed or in the mempool
+        let txids = txids
+            .into_iter()
+            .filter(|&txid| graph_update.get_tx(txid).is_none())
+            .collect::<Vec<_>>();
+        self.prefetch_txs(txids.iter().copied(), batch_size)?;
+        let mut txs = Vec::with_capacity(txids.len());
         for txid in txids {
-            let tx = match self.fetch_tx(txid) {
-                Ok(tx) => tx,
-                Err(electrum_client::Error::Protocol(_)) => continue,
+            match self.fetch_tx(txid) {
+                Ok(tx) => txs.push((txid, tx)),
+                Err(electrum_client::Error::Protocol(_)) => {
+                    // the server knows neither confirmed nor mempool tx
+                    if let Some(evicted_at) = evicted_at {
+                        let _ = graph_update.insert_evicted_at(txid, evicted_at);
+                    }
+                }
                 Err(other_err) => return Err(other_err),
             };
+        }
 
-            let spk = tx
-                .output
-                .first()
-                .map(|txo| &txo.script_pubkey)
-                .expect("tx must have an output");
-
+        for txs in txs.chunks(batch_size.max(1)) {
             // because of restrictions of the Electrum API, we have to use the `script_get_history`
             // call to get confirmation status of our transaction
-            let anchor = match self
-                .inner
-                .script_get_history(spk)?
-                .into_iter()
-                .find(|r| r.tx_hash == txid)
-            {
-                Some(r) => determine_tx_anchor(cps, r.height, txid),
-                None => continue,
-            };
+            let histories = self.batch_script_get_history(txs.iter().map(|(_, tx)| {
+                tx.output
+                    .first()
+                    .map(|txo| txo.script_pubkey.as_script())
+                    .expect("tx must have an output")
+            }))?;
+
+            for ((txid, tx), history) in txs.iter().zip(histories) {
+                let anchor = match history.into_iter().find(|r| r.tx_hash == *txid) {
+                    Some(r) => determine_tx_anchor(cps, r.height, *txid),
+                    None => {
+                        if let Some(evicted_at) = evicted_at {
+                            let _ = graph_update.insert_evicted_at(*txid, evicted_at);
+                        }
+                        continue;
+                    }
+                };
+
+                let _ = graph_update.insert_tx(Arc::clone(tx));
+                if let Some(anchor) = anchor {
+                    let _ = graph_update.insert_anchor(*txid, anchor);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Populate the `graph_update` with the unconfirmed parents of its unconfirmed transactions,
+    /// see [`SyncOptions::fetch_unconfirmed_parents`].
+    ///
+    /// The status of a parent is found in the history of the script pubkey of the output spent
+    /// from it. Confirmed parents are not added, only the `TxOut`s spent from them.
+    fn populate_with_unconfirmed_parents(
+        &self,
+        cps: &BTreeMap<u32, CheckPoint>,
+        graph_update: &mut TxGraph<ConfirmationHeightAnchor>,
+        batch_size: usize,
+    ) -> Result<(), Error> {
+        let unconfirmed_txs = graph_update
+            .full_txs()
+            .filter(|tx_node| tx_node.anchors.is_empty())
+            .map(|tx_node| tx_node.tx)
+            .collect::<Vec<_>>();
+        let outpoints = unconfirmed_txs
+            .iter()
+            .flat_map(|tx| tx.input.iter().map(|vin| vin.previous_output))
+            .filter(|outpoint| !outpoint.is_null() && graph_update.get_tx(outpoint.txid).is_none())
+            .collect::<BTreeSet<_>>();
 
-            let _ = graph_update.insert_tx(tx);
-            if let Some(anchor) = anchor {
-                let _ = graph_update.insert_anchor(txid, anchor);
+        self.prefetch_txs(outpoints.iter().map(|outpoint| outpoint.txid), batch_size)?;
+        let mut parents = Vec::with_capacity(outpoints.len());
+        for outpoint in outpoints {
+            let parent = self.fetch_tx(outpoint.txid)?;
+            if let Some(txout) = parent.output.get(outpoint.vout as usize).cloned() {
+                parents.push((outpoint, parent, txout));
+            }
+        }
+
+        for parents in parents.chunks(batch_size.max(1)) {
+            let histories = self.batch_script_get_history(
+                parents
+                    .iter()
+                    .map(|(_, _, txout)| txout.script_pubkey.as_script()),
+            )?;
+            for ((outpoint, parent, txout), history) in parents.iter().zip(histories) {
+                let is_confirmed = history
+                    .iter()
+                    .find(|res| res.tx_hash == outpoint.txid)
+                    .and_then(|res| determine_tx_anchor(cps, res.height, res.tx_hash))
+                    .is_some();
+                if is_confirmed {
+                    let _ = graph_update.insert_txout(*outpoint, txout.clone());
+                } else {
+                    let _ = graph_update.insert_tx(Arc::clone(parent));
+                }
             }
         }
         Ok(())
     }
 }
 
+/// A new chain tip yielded by a [`HeaderSubscription`]
+#[derive(Debug, Clone)]
+pub struct HeaderUpdate {
+    /// The notification of the new tip by the server
+    pub notification: HeaderNotification,
+    /// The update of the chain to the new tip, connected to the previous tip
+    ///
+    /// It can be applied to a [`LocalChain`](bdk_chain::local_chain::LocalChain) with
+    /// `apply_update`.
+    pub chain_update: CheckPoint,
+}
+
+/// An iterator over the new chain tips of an Electrum server, returned by
+/// [`BdkElectrumClient::subscribe_headers`]
+///
+/// Each [`HeaderUpdate`] connects to the previous one. A reorg, notified as a new tip at the same
+/// or a lower height, is handled like any other new tip: the headers are fetched back to the point
+/// of agreement with the previous tip, so that the update invalidates the reorged blocks.
+///
+/// The notifications of the electrum client are only received with the responses of other calls.
+/// When none is queued, the iterator waits for the [`poll_interval`](Self::poll_interval) and
+/// subscribes again, which returns the current tip. So a tip isn't missed if the
+/// [`electrum_client::Client`] reconnects after the server drops the connection, as the new
+/// connection is subscribed too. An error is yielded when the call fails, the next call of `next`
+/// subscribes again.
+pub struct HeaderSubscription<'a, E> {
+    client: &'a BdkElectrumClient<E>,
+    tip: CheckPoint,
+    poll_interval: Duration,
+    polled: bool,
+}
+
+impl<'a, E: ElectrumApi> HeaderSubscription<'a, E> {
+    /// Set the interval between two polls of the server, one second by default.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// The last tip yielded, or the chain tip the subscription started from.
+    pub fn tip(&self) -> &CheckPoint {
+        &self.tip
+    }
+
+    /// Subscribe to the headers, returning the current tip of the server.
+    fn subscribe(&mut self) -> Result<HeaderNotification, Error> {
+        if self.client.lacks(|c| c.supports_headers_subscribe) {
+            return Err(Error::Message(
+                "the server doesn't support blockchain.headers.subscribe".to_string(),
+            ));
+        }
+        if self.polled {
+            std::thread::sleep(self.poll_interval);
+        }
+        self.polled = true;
+        let notification = self.client.call(|inner| inner.block_headers_subscribe())?;
+        // the notifications received before the response are not newer than it
+        while self
+            .client
+            .call(|inner| inner.block_headers_pop())?
+            .is_some()
+        {}
+        Ok(notification)
+    }
+
+    fn next_notification(&mut self) -> Result<HeaderNotification, Error> {
+        match self.client.call(|inner| inner.block_headers_pop())? {
+            Some(notification) => Ok(notification),
+            None => self.subscribe(),
+        }
+    }
+}
+
+impl<'a, E: ElectrumApi> Iterator for HeaderSubscription<'a, E> {
+    type Item = Result<HeaderUpdate, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let notification = match self.next_notification() {
+                Ok(notification) => notification,
+                Err(err) => return Some(Err(err)),
+            };
+            let block = BlockId {
+                height: notification.height as u32,
+                hash: notification.header.block_hash(),
+            };
+            if block == self.tip.block_id() {
+                continue;
+            }
+
+            let chain_update = match self
+                .client
+                .call(|inner| construct_update_tip_at(inner, self.tip.clone(), block.height))
+            {
+                Ok((chain_update, _)) => chain_update,
+                Err(err) => return Some(Err(err)),
+            };
+            self.tip = chain_update.clone();
+            return Some(Ok(HeaderUpdate {
+                notification,
+                chain_update,
+            }));
+        }
+    }
+}
+
+/// The anchors of the results are fetched with
+/// [`with_confirmation_time_height_anchor`](ElectrumFullScanResult::with_confirmation_time_height_anchor),
+/// the other options of the sync are the [default ones](SyncOptions::default).
+impl<E: ElectrumApi, K: Ord + Clone> SyncBackend<K> for BdkElectrumClient<E> {
+    fn full_scan(
+        &self,
+        request: FullScanRequest<K>,
+        options: BackendOptions,
+    ) -> Result<FullScanResult<K>, BackendError> {
+        let res = self
+            .full_scan(
+                request,
+                options.stop_gap,
+                options.batch_size,
+                options.fetch_prev_txouts,
+            )?
+            .with_confirmation_time_height_anchor(self)?;
+        Ok(res)
+    }
+
+    fn sync(
+        &self,
+        request: SyncRequest,
+        options: BackendOptions,
+    ) -> Result<SyncResult, BackendError> {
+        let options = SyncOptions {
+            batch_size: options.batch_size,
+            fetch_prev_txouts: options.fetch_prev_txouts,
+            ..SyncOptions::default()
+        };
+        let res = self
+            .sync_with_options(request, options)?
+            .with_confirmation_time_height_anchor(self)?;
+        Ok(res)
+    }
+}
+
+/// The transactions are broadcast like with [`BdkElectrumClient::broadcast_all`], and looked up
+/// with `blockchain.transaction.get`, bypassing the transaction cache: an error of the server
+/// means the transaction is unknown.
+impl<E: ElectrumApi> BroadcastBackend for BdkElectrumClient<E> {
+    fn name(&self) -> &str {
+        "electrum"
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, BroadcastError<BackendError>> {
+        self.broadcast_all(core::slice::from_ref(tx))
+            .pop()
+            .expect("one result per transaction")
+            .map_err(|err| err.map_request(|err| err.into()))
+    }
+
+    fn has_tx(&self, txid: Txid) -> Result<bool, BackendError> {
+        match self.call(|inner| inner.transaction_get(&txid)) {
+            Ok(_) => Ok(true),
+            Err(Error::Protocol(_)) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// The outputs are looked up in the transaction cache, the missing transactions are fetched from
+/// the server, in a single batch for [`get_txouts`](PrevoutSource::get_txouts). An output is
+/// `None` if its transaction can't be fetched.
+impl<E: ElectrumApi> PrevoutSource for BdkElectrumClient<E> {
+    fn get_txout(&self, outpoint: OutPoint) -> Option<TxOut> {
+        let tx = self.fetch_tx(outpoint.txid).ok()?;
+        tx.output.get(outpoint.vout as usize).cloned()
+    }
+
+    fn get_txouts(&self, outpoints: &[OutPoint]) -> Vec<Option<TxOut>> {
+        let _ = self.prefetch_txs(outpoints.iter().map(|op| op.txid), outpoints.len());
+        outpoints
+            .iter()
+            .map(|&outpoint| self.get_txout(outpoint))
+            .collect()
+    }
+}
+
+/// The transactions of the block are found in the histories of the script pubkeys, fetched in a
+/// single batch, and the header of the block gives its hash and time. Only the relevant
+/// transactions are fetched, but each one counts as a request.
+impl<E: ElectrumApi> BlockTxSource for BdkElectrumClient<E> {
+    fn name(&self) -> &str {
+        "electrum"
+    }
+
+    fn block_txs(&self, height: u32, spks: &BTreeSet<ScriptBuf>) -> Result<BlockTxs, BackendError> {
+        let header = self.call(|inner| inner.block_header(height as usize))?;
+        let txids = self
+            .batch_script_get_history(spks.iter().map(|spk| spk.as_script()))?
+            .into_iter()
+            .flatten()
+            .filter(|res| res.height == height as i32)
+            .map(|res| res.tx_hash)
+            .collect::<BTreeSet<_>>();
+        let txs = txids
+            .into_iter()
+            .map(|txid| self.fetch_tx(txid).map(|tx| tx.as_ref().clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(BlockTxs {
+            block: BlockId {
+                height,
+                hash: header.block_hash(),
+            },
+            time: header.time as u64,
+            txs,
+        })
+    }
+}
+
 /// The result of [`BdkElectrumClient::full_scan`].
 ///
 /// This can be transformed into a [`FullScanResult`] with either [`ConfirmationHeightAnchor`] or
@@ -411,9 +1326,10 @@ impl<K> ElectrumFullScanResult<K> {
     ) -> Result<FullScanResult<K, ConfirmationTimeHeightAnchor>, Error> {
         let res = self.0;
         Ok(FullScanResult {
-            graph_update: try_into_confirmation_time_result(res.graph_update, &client.inner)?,
+            graph_update: try_into_confirmation_time_result(res.graph_update, client)?,
             chain_update: res.chain_update,
             last_active_indices: res.last_active_indices,
+            scan_stats: res.scan_stats,
         })
     }
 }
@@ -439,7 +1355,7 @@ impl ElectrumSyncResult {
     ) -> Result<SyncResult<ConfirmationTimeHeightAnchor>, Error> {
         let res = self.0;
         Ok(SyncResult {
-            graph_update: try_into_confirmation_time_result(res.graph_update, &client.inner)?,
+            graph_update: try_into_confirmation_time_result(res.graph_update, client)?,
             chain_update: res.chain_update,
         })
     }
@@ -447,7 +1363,7 @@ impl ElectrumSyncResult {
 
 fn try_into_confirmation_time_result(
     graph_update: TxGraph<ConfirmationHeightAnchor>,
-    client: &impl ElectrumApi,
+    client: &BdkElectrumClient<impl ElectrumApi>,
 ) -> Result<TxGraph<ConfirmationTimeHeightAnchor>, Error> {
     let relevant_heights = graph_update
         .all_anchors()
@@ -455,12 +1371,13 @@ fn try_into_confirmation_time_result(
         .map(|(a, _)| a.confirmation_height)
         .collect::<HashSet<_>>();
 
+    let relevant_heights = relevant_heights.into_iter().collect::<Vec<_>>();
     let height_to_time = relevant_heights
-        .clone()
-        .into_iter()
+        .iter()
+        .copied()
         .zip(
             client
-                .batch_block_header(relevant_heights)?
+                .batch_block_header(&relevant_heights)?
                 .into_iter()
                 .map(|bh| bh.time as u64),
         )
@@ -487,6 +1404,18 @@ fn construct_update_tip(
         return Ok((prev_tip.clone(), Some(prev_tip.height())));
     }
 
+    construct_update_tip_at(client, prev_tip, new_tip_height)
+}
+
+/// Construct an update of `prev_tip` to the chain of the server, up to `new_tip_height`.
+///
+/// The checkpoints of `prev_tip` above `new_tip_height` are not checked, the point of agreement is
+/// below it.
+fn construct_update_tip_at(
+    client: &impl ElectrumApi,
+    prev_tip: CheckPoint,
+    new_tip_height: u32,
+) -> Result<(CheckPoint, Option<u32>), Error> {
     // Atomically fetch the latest `CHAIN_SUFFIX_LENGTH` count of blocks from Electrum. We use this
     // to construct our checkpoint update.
     let mut new_blocks = {
@@ -504,13 +1433,12 @@ fn construct_update_tip(
         let mut agreement_cp = Option::<CheckPoint>::None;
         for cp in prev_tip.iter() {
             let cp_block = cp.block_id();
+            if cp_block.height > new_tip_height {
+                continue;
+            }
             let hash = match new_blocks.get(&cp_block.height) {
                 Some(&hash) => hash,
                 None => {
-                    assert!(
-                        new_tip_height >= cp_block.height,
-                        "already checked that electrum's tip cannot be smaller"
-                    );
                     let hash = client.block_header(cp_block.height as _)?.block_hash();
                     new_blocks.insert(cp_block.height, hash);
                     hash
@@ -524,6 +1452,20 @@ fn construct_update_tip(
         agreement_cp
     };
 
+    // no checkpoint is in the chain of the server, not even the genesis block: it's on another
+    // network. `electrum_client::Error` has no variant for it, so it's reported as a message.
+    if agreement_cp.is_none() {
+        if let (Some(genesis), Some(got)) = (prev_tip.get(0), new_blocks.get(&0)) {
+            if genesis.hash() != *got {
+                return Err(Error::Message(format!(
+                    "genesis mismatch: the genesis block of the server is {}, expected {}",
+                    got,
+                    genesis.hash()
+                )));
+            }
+        }
+    }
+
     let agreement_height = agreement_cp.as_ref().map(CheckPoint::height);
 
     let new_tip = new_blocks
@@ -542,6 +1484,62 @@ fn construct_update_tip(
     Ok((new_tip, agreement_height))
 }
 
+/// Whether `err` is an error of the connection to the server, after which a request can be
+/// retried on a new connection
+fn is_transport_error(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::IOError(_)
+            | Error::SharedIOError(_)
+            | Error::CouldntLockReader
+            | Error::Mpsc
+            | Error::AllAttemptsErrored(_)
+    )
+}
+
+/// Negotiate the protocol version of a new connection with `server.version`, and detect the
+/// capabilities of the server.
+fn negotiate_capabilities(client: &impl ElectrumApi) -> Result<ServerCapabilities, Error> {
+    let version = client.raw_call(
+        "server.version",
+        [
+            Param::String(format!("bdk_electrum {}", env!("CARGO_PKG_VERSION"))),
+            Param::String("1.4".to_string()),
+        ],
+    )?;
+    let (server_software, protocol_version) = match version.as_array().map(Vec::as_slice) {
+        Some([software, protocol]) if software.is_string() && protocol.is_string() => (
+            software.as_str().unwrap_or_default().to_string(),
+            protocol.as_str().unwrap_or_default().to_string(),
+        ),
+        _ => return Err(Error::InvalidResponse(version)),
+    };
+
+    // an error in a batch is answered with a null result, the mempool of the zero script hash is
+    // empty on the servers which support it
+    let mut batch = Batch::default();
+    batch.raw(
+        "blockchain.scripthash.get_mempool".to_string(),
+        vec![Param::String(
+            "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+        )],
+    );
+    let supports_batch_mempool = match client.batch_call(&batch) {
+        Ok(results) => results.iter().all(|result| result.is_array()),
+        Err(err) if is_transport_error(&err) => return Err(err),
+        Err(_) => false,
+    };
+
+    let mut capabilities = ServerCapabilities {
+        protocol_version,
+        supports_batch_mempool,
+        supports_headers_subscribe: false,
+        server_software,
+    };
+    capabilities.supports_headers_subscribe = capabilities.protocol_at_least(1, 3);
+    Ok(capabilities)
+}
+
 /// A [tx status] comprises of a concatenation of `tx_hash:height:`s. We transform a single one of
 /// these concatenations into a [`ConfirmationHeightAnchor`] if possible.
 ///
@@ -549,6 +1547,30 @@ fn construct_update_tip(
 /// cannot be found, or the transaction is unconfirmed, [`None`] is returned.
 ///
 /// [tx status](https://electrumx-spesmilo.readthedocs.io/en/latest/protocol-basics.html#status)
+/// Convert a fee rate in BTC/kvB, as in the Electrum protocol, rounding it up to the next sat/kwu.
+///
+/// Returns `None` for negative fee rates, which mean there is no estimate, and NaN.
+fn fee_rate_from_btc_per_kvb(btc_per_kvb: f64) -> Option<FeeRate> {
+    // the fee rates have a precision of a sat/kvB, one sat/kvB being 1/4 sat/kwu
+    let sat_per_kvb = (btc_per_kvb * 100_000_000.0).round();
+    if sat_per_kvb.is_nan() || sat_per_kvb < 0.0 {
+        return None;
+    }
+    if sat_per_kvb >= u64::MAX as f64 {
+        return Some(FeeRate::MAX);
+    }
+    let sat_per_kvb = sat_per_kvb as u64;
+    Some(FeeRate::from_sat_per_kwu(
+        sat_per_kvb / 4 + u64::from(sat_per_kvb % 4 != 0),
+    ))
+}
+
+/// Whether `merkle` proves that the transaction `txid` is in the block of `header` at `height`.
+fn merkle_proof_is_valid(txid: &Txid, height: u32, header: &Header, merkle: &GetMerkleRes) -> bool {
+    merkle.block_height == height as usize
+        && validate_merkle_proof(txid, &header.merkle_root, merkle)
+}
+
 fn determine_tx_anchor(
     cps: &BTreeMap<u32, CheckPoint>,
     raw_height: i32,
@@ -587,3 +1609,98 @@ fn determine_tx_anchor(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk_chain::bitcoin::{
+        block::Version, hashes::Hash, merkle_tree::calculate_root, BlockHash, CompactTarget,
+        TxMerkleNode,
+    };
+
+    /// The display order bytes of `hash`, as used by the Electrum protocol.
+    fn merkle_bytes(hash: Txid) -> [u8; 32] {
+        let mut bytes = hash.to_byte_array();
+        bytes.reverse();
+        bytes
+    }
+
+    #[test]
+    fn test_fee_rate_from_btc_per_kvb() {
+        let sat_per_kwu =
+            |btc_per_kvb| fee_rate_from_btc_per_kvb(btc_per_kvb).map(FeeRate::to_sat_per_kwu);
+        // the default relay fee of bitcoind is 1 sat/vB
+        assert_eq!(sat_per_kwu(0.00001), Some(250));
+        assert_eq!(sat_per_kwu(0.00068285), Some(17_072));
+        assert_eq!(sat_per_kwu(0.00000001), Some(1));
+        assert_eq!(sat_per_kwu(0.0), Some(0));
+        // no estimate
+        assert_eq!(sat_per_kwu(-1.0), None);
+        assert_eq!(sat_per_kwu(f64::NAN), None);
+        assert_eq!(sat_per_kwu(f64::INFINITY), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_merkle_proof_is_valid() {
+        let txids = (0..4_u8).map(|i| Txid::hash(&[i])).collect::<Vec<_>>();
+        let root = calculate_root(txids.iter().copied()).expect("must have txids");
+        let header = Header {
+            version: Version::ONE,
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::from_raw_hash(root.to_raw_hash()),
+            time: 0,
+            bits: CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        };
+        // the proof of the second tx is the first tx and the hash of the last two
+        let merkle = GetMerkleRes {
+            block_height: 7,
+            pos: 1,
+            merkle: vec![
+                merkle_bytes(txids[0]),
+                merkle_bytes(calculate_root(txids[2..].iter().copied()).expect("must have txids")),
+            ],
+        };
+        assert!(merkle_proof_is_valid(&txids[1], 7, &header, &merkle));
+
+        // a proof of another tx, height or position is invalid
+        assert!(!merkle_proof_is_valid(&txids[2], 7, &header, &merkle));
+        assert!(!merkle_proof_is_valid(&txids[1], 8, &header, &merkle));
+        let mut wrong_pos = merkle.clone();
+        wrong_pos.pos = 0;
+        assert!(!merkle_proof_is_valid(&txids[1], 7, &header, &wrong_pos));
+
+        // as is a tampered proof
+        let mut tampered = merkle.clone();
+        tampered.merkle[1][0] ^= 1;
+        assert!(!merkle_proof_is_valid(&txids[1], 7, &header, &tampered));
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let options = ReconnectOptions {
+            max_retries: u8::MAX,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        };
+        assert_eq!(options.backoff(0), Duration::from_secs(1));
+        assert_eq!(options.backoff(3), Duration::from_secs(8));
+        assert_eq!(options.backoff(5), Duration::from_secs(30));
+        assert_eq!(options.backoff(u8::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_protocol_at_least() {
+        let capabilities = |protocol_version: &str| ServerCapabilities {
+            protocol_version: protocol_version.to_string(),
+            supports_batch_mempool: true,
+            supports_headers_subscribe: true,
+            server_software: "mock".to_string(),
+        };
+        assert!(capabilities("1.4").protocol_at_least(1, 3));
+        assert!(capabilities("1.3").protocol_at_least(1, 3));
+        assert!(capabilities("1.10.2").protocol_at_least(1, 4));
+        assert!(!capabilities("1.2").protocol_at_least(1, 3));
+        assert!(!capabilities("").protocol_at_least(1, 3));
+    }
+}
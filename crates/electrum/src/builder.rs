@@ -0,0 +1,534 @@
+use bdk_chain::bitcoin::hashes::sha256;
+#[cfg(feature = "use-rustls")]
+use bdk_chain::bitcoin::hashes::Hash;
+#[cfg(feature = "use-rustls")]
+use electrum_client::{
+    raw_client::{ElectrumSslStream, RawClient},
+    socks::Socks5Stream,
+};
+use electrum_client::{Client, Config, Socks5Config};
+#[cfg(feature = "use-rustls")]
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    client::WebPkiServerVerifier,
+    crypto::CryptoProvider,
+    pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore,
+    SignatureScheme, StreamOwned,
+};
+#[cfg(feature = "use-rustls")]
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    sync::Arc,
+};
+use std::{fmt, path::PathBuf, time::Duration};
+
+use crate::{BdkElectrumClient, ReconnectOptions};
+
+/// Builds a [`BdkElectrumClient`] connected to an Electrum server, see
+/// [`BdkElectrumClient::builder`]
+///
+/// The scheme of the URL selects the transport:
+///
+/// - `tcp://host:port`, or no scheme, for a plaintext connection
+/// - `ssl://host:port` for a TLS connection, the certificate of the server is validated against the
+///   web PKI unless [`validate_domain`] is `false`
+///
+/// Either can go through a socks5 proxy, which is required to reach an `.onion` address, for
+/// example a local Tor daemon at `127.0.0.1:9050`.
+///
+/// A server with a self-signed certificate can be trusted with [`ca_cert`] or
+/// [`pin_cert_sha256`]. The inner `electrum_client::Client` can't be given custom certificates, so
+/// such a server must be connected to with [`build_ssl`] instead of [`build`], which needs the
+/// `use-rustls` feature.
+///
+/// The built client has [negotiated](BdkElectrumClient::negotiate) the protocol version and the
+/// capabilities of the server.
+///
+/// [`validate_domain`]: Self::validate_domain
+/// [`ca_cert`]: Self::ca_cert
+/// [`pin_cert_sha256`]: Self::pin_cert_sha256
+/// [`build_ssl`]: Self::build_ssl
+/// [`build`]: Self::build
+#[derive(Debug, Clone)]
+pub struct Builder {
+    url: String,
+    socks5: Option<Socks5Config>,
+    timeout: Option<Duration>,
+    retry: u8,
+    validate_domain: bool,
+    ca_cert: Option<PathBuf>,
+    pinned_cert: Option<sha256::Hash>,
+    reconnect: Option<ReconnectOptions>,
+}
+
+impl BdkElectrumClient<Client> {
+    /// Start building a client connected to the Electrum server at `url`, see [`Builder`].
+    pub fn builder(url: impl ToString) -> Builder {
+        Builder::new(url)
+    }
+}
+
+impl Builder {
+    /// Start building a client connected to the Electrum server at `url`.
+    pub fn new(url: impl ToString) -> Self {
+        let config = Config::default();
+        Self {
+            url: url.to_string(),
+            socks5: None,
+            timeout: None,
+            retry: config.retry(),
+            validate_domain: config.validate_domain(),
+            ca_cert: None,
+            pinned_cert: None,
+            reconnect: None,
+        }
+    }
+
+    /// Connect through the socks5 proxy at `addr`.
+    pub fn socks5(mut self, addr: impl ToString) -> Self {
+        self.socks5 = Some(Socks5Config::new(addr));
+        self
+    }
+
+    /// Connect through the socks5 proxy at `addr`, authenticating with `username` and `password`.
+    pub fn socks5_with_credentials(
+        mut self,
+        addr: impl ToString,
+        username: impl ToString,
+        password: impl ToString,
+    ) -> Self {
+        self.socks5 = Some(Socks5Config::with_credentials(
+            addr,
+            username.to_string(),
+            password.to_string(),
+        ));
+        self
+    }
+
+    /// Set the timeout of the connection and of each read and write.
+    ///
+    /// [`build`](Self::build) rounds it up to whole seconds, up to 255.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the number of times a request is retried after an error, reconnecting to the server.
+    ///
+    /// Only the client of [`build`](Self::build) reconnects, this is ignored if
+    /// [`reconnect`](Self::reconnect) is set.
+    pub fn retry(mut self, retry: u8) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Build a client in the reconnecting mode of [`BdkElectrumClient::with_reconnect`], which
+    /// reconnects with the options of this builder.
+    ///
+    /// Unlike [`retry`](Self::retry), this never retries a broadcast of a transaction already
+    /// known to the server, and is supported by [`build_ssl`](Self::build_ssl).
+    pub fn reconnect(mut self, options: ReconnectOptions) -> Self {
+        self.reconnect = Some(options);
+        self
+    }
+
+    /// Set whether to check that the certificate of an `ssl://` server is valid for its domain,
+    /// `true` by default.
+    ///
+    /// Without a [`ca_cert`](Self::ca_cert) or a [`pin_cert_sha256`](Self::pin_cert_sha256),
+    /// `false` accepts any certificate.
+    pub fn validate_domain(mut self, validate_domain: bool) -> Self {
+        self.validate_domain = validate_domain;
+        self
+    }
+
+    /// Trust the certificates of the PEM file at `path` as the roots of an `ssl://` server's
+    /// certificate, instead of the web PKI.
+    ///
+    /// This can be the self-signed certificate of the server itself.
+    pub fn ca_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_cert = Some(path.into());
+        self
+    }
+
+    /// Trust only the `ssl://` server certificate whose DER encoding has the SHA256 `fingerprint`.
+    ///
+    /// The chain, domain and expiry of a pinned certificate are not checked.
+    pub fn pin_cert_sha256(mut self, fingerprint: sha256::Hash) -> Self {
+        self.pinned_cert = Some(fingerprint);
+        self
+    }
+
+    /// Connect with an `electrum_client::Client`, which reconnects to the server on errors.
+    ///
+    /// Returns [`BuilderError::CustomCertificate`] if a [`ca_cert`](Self::ca_cert) or a
+    /// [`pin_cert_sha256`](Self::pin_cert_sha256) is set, use [`build_ssl`](Self::build_ssl).
+    pub fn build(self) -> Result<BdkElectrumClient<Client>, BuilderError> {
+        self.parse_url()?;
+        if self.ca_cert.is_some() || self.pinned_cert.is_some() {
+            return Err(BuilderError::CustomCertificate);
+        }
+        let timeout = self
+            .timeout
+            .map(|timeout| {
+                let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+                u8::try_from(secs).map_err(|_| BuilderError::InvalidTimeout(timeout))
+            })
+            .transpose()?;
+        let config = Config::builder()
+            .socks5(self.socks5)
+            .timeout(timeout)
+            // the client must not retry on its own in the reconnecting mode
+            .retry(if self.reconnect.is_some() {
+                0
+            } else {
+                self.retry
+            })
+            .validate_domain(self.validate_domain)
+            .build();
+        let client = BdkElectrumClient::new(Client::from_config(&self.url, config.clone())?);
+        client.negotiate()?;
+        Ok(match self.reconnect {
+            Some(options) => {
+                let url = self.url;
+                client.with_reconnect(options, move || Client::from_config(&url, config.clone()))
+            }
+            None => client,
+        })
+    }
+
+    /// Connect to an `ssl://` server with a TLS configuration of this crate, which supports the
+    /// [`ca_cert`](Self::ca_cert) and [`pin_cert_sha256`](Self::pin_cert_sha256) options.
+    ///
+    /// The returned client doesn't reconnect, a new one must be built after a connection error.
+    #[cfg(feature = "use-rustls")]
+    pub fn build_ssl(
+        self,
+    ) -> Result<BdkElectrumClient<RawClient<ElectrumSslStream>>, BuilderError> {
+        let client = BdkElectrumClient::new(self.connect_ssl()?);
+        client.negotiate()?;
+        Ok(match self.reconnect {
+            Some(options) => client.with_reconnect(options, move || {
+                self.connect_ssl()
+                    .map_err(|err| electrum_client::Error::Message(err.to_string()))
+            }),
+            None => client,
+        })
+    }
+
+    #[cfg(feature = "use-rustls")]
+    fn connect_ssl(&self) -> Result<RawClient<ElectrumSslStream>, BuilderError> {
+        let (scheme, addr) = self.parse_url()?;
+        if scheme != Scheme::Ssl {
+            return Err(BuilderError::NotSsl(self.url.clone()));
+        }
+        let host = host(addr);
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|_| BuilderError::InvalidUrl(self.url.clone()))?;
+
+        // the provider of `electrum_client`, the process default
+        let provider = ClientConfig::builder().crypto_provider().clone();
+        let webpki = match &self.ca_cert {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_file_iter(path)
+                    .map_err(|err| BuilderError::Certificate(err.to_string()))?
+                {
+                    let cert = cert.map_err(|err| BuilderError::Certificate(err.to_string()))?;
+                    roots.add(cert)?;
+                }
+                let webpki =
+                    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                        .build()
+                        .map_err(|err| BuilderError::Certificate(err.to_string()))?;
+                Some(webpki)
+            }
+            None if self.pinned_cert.is_none() && self.validate_domain => {
+                // the web PKI roots are the ones of electrum_client
+                let client = match &self.socks5 {
+                    Some(socks5) => RawClient::new_proxy_ssl(addr, true, socks5, self.timeout)?,
+                    None => RawClient::new_ssl(addr, true, self.timeout)?,
+                };
+                return Ok(client);
+            }
+            None => None,
+        };
+        let verifier = CertVerifier {
+            webpki,
+            pinned_cert: self.pinned_cert,
+            validate_domain: self.validate_domain,
+            provider: provider.clone(),
+        };
+        let tls_config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+
+        let stream = self.connect(addr)?;
+        let connection = ClientConnection::new(Arc::new(tls_config), server_name)?;
+        Ok(RawClient::from(StreamOwned::new(connection, stream)))
+    }
+
+    fn parse_url(&self) -> Result<(Scheme, &str), BuilderError> {
+        let (scheme, addr) = match self.url.split_once("://") {
+            Some(("tcp", addr)) => (Scheme::Tcp, addr),
+            Some(("ssl", addr)) => (Scheme::Ssl, addr),
+            Some(_) => return Err(BuilderError::InvalidUrl(self.url.clone())),
+            None => (Scheme::Tcp, self.url.as_str()),
+        };
+        if addr.is_empty() || !addr.contains(':') {
+            return Err(BuilderError::InvalidUrl(self.url.clone()));
+        }
+        if host(addr).ends_with(".onion") && self.socks5.is_none() {
+            return Err(BuilderError::OnionWithoutProxy(self.url.clone()));
+        }
+        Ok((scheme, addr))
+    }
+
+    #[cfg(feature = "use-rustls")]
+    fn connect(&self, addr: &str) -> Result<TcpStream, BuilderError> {
+        let stream = match &self.socks5 {
+            Some(socks5) => match &socks5.credentials {
+                Some(credentials) => Socks5Stream::connect_with_password(
+                    &socks5.addr,
+                    addr,
+                    &credentials.username,
+                    &credentials.password,
+                    self.timeout,
+                )?,
+                None => Socks5Stream::connect(&socks5.addr, addr, self.timeout)?,
+            }
+            .into_inner(),
+            None => match self.timeout {
+                Some(timeout) => {
+                    let mut last_err = None;
+                    let mut stream = None;
+                    for socket_addr in addr.to_socket_addrs()? {
+                        match TcpStream::connect_timeout(&socket_addr, timeout) {
+                            Ok(s) => {
+                                stream = Some(s);
+                                break;
+                            }
+                            Err(err) => last_err = Some(err),
+                        }
+                    }
+                    match (stream, last_err) {
+                        (Some(stream), _) => stream,
+                        (None, Some(err)) => return Err(err.into()),
+                        (None, None) => return Err(BuilderError::InvalidUrl(self.url.clone())),
+                    }
+                }
+                None => TcpStream::connect(addr)?,
+            },
+        };
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        Ok(stream)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    Tcp,
+    Ssl,
+}
+
+/// The host of a `host:port` address, without the brackets of an IPv6 address
+fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Verifies the certificate of a server against the custom roots or the pinned fingerprint of a
+/// [`Builder`]
+#[cfg(feature = "use-rustls")]
+#[derive(Debug)]
+struct CertVerifier {
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    pinned_cert: Option<sha256::Hash>,
+    validate_domain: bool,
+    provider: Arc<CryptoProvider>,
+}
+
+#[cfg(feature = "use-rustls")]
+impl ServerCertVerifier for CertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(pinned_cert) = self.pinned_cert {
+            if sha256::Hash::hash(end_entity.as_ref()) != pinned_cert {
+                return Err(rustls::Error::InvalidCertificate(
+                    CertificateError::ApplicationVerificationFailure,
+                ));
+            }
+            return Ok(ServerCertVerified::assertion());
+        }
+        let webpki = match &self.webpki {
+            Some(webpki) => webpki,
+            None => return Ok(ServerCertVerified::assertion()),
+        };
+        match webpki.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) if !self.validate_domain => Ok(ServerCertVerified::assertion()),
+            res => res,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// An error building a client with a [`Builder`]
+#[derive(Debug)]
+pub enum BuilderError {
+    /// The URL isn't a `tcp://` or `ssl://` URL with a host and a port
+    InvalidUrl(String),
+    /// [`Builder::build_ssl`] is only for `ssl://` URLs
+    NotSsl(String),
+    /// An `.onion` address can only be reached through a socks5 proxy
+    OnionWithoutProxy(String),
+    /// [`Builder::build`] can't use custom certificates
+    CustomCertificate,
+    /// The timeout is longer than 255 seconds
+    InvalidTimeout(Duration),
+    /// The CA certificates can't be read
+    Certificate(String),
+    /// Error setting up the TLS connection
+    #[cfg(feature = "use-rustls")]
+    Tls(rustls::Error),
+    /// Error connecting to the server
+    Io(std::io::Error),
+    /// Error connecting to the server with `electrum_client`
+    Electrum(electrum_client::Error),
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(
+                f,
+                "invalid electrum URL `{}`, expected `tcp://host:port` or `ssl://host:port`",
+                url
+            ),
+            Self::NotSsl(url) => write!(f, "`{}` is not an `ssl://` URL", url),
+            Self::OnionWithoutProxy(url) => {
+                write!(f, "the onion address `{}` needs a socks5 proxy", url)
+            }
+            Self::CustomCertificate => write!(
+                f,
+                "custom certificates are not supported by `build`, use `build_ssl`"
+            ),
+            Self::InvalidTimeout(timeout) => {
+                write!(f, "timeout of {:?} is longer than 255 seconds", timeout)
+            }
+            Self::Certificate(err) => write!(f, "invalid CA certificate: {}", err),
+            #[cfg(feature = "use-rustls")]
+            Self::Tls(err) => write!(f, "TLS error: {}", err),
+            Self::Io(err) => write!(f, "connection error: {}", err),
+            Self::Electrum(err) => write!(f, "electrum error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BuilderError {}
+
+#[cfg(feature = "use-rustls")]
+impl From<rustls::Error> for BuilderError {
+    fn from(err: rustls::Error) -> Self {
+        Self::Tls(err)
+    }
+}
+
+impl From<std::io::Error> for BuilderError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<electrum_client::Error> for BuilderError {
+    fn from(err: electrum_client::Error) -> Self {
+        Self::Electrum(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_builder_errors() {
+        let build_err = |builder: Builder| builder.build().err().expect("must fail");
+        assert!(matches!(
+            build_err(Builder::new("http://127.0.0.1:50001")),
+            BuilderError::InvalidUrl(_)
+        ));
+        assert!(matches!(
+            build_err(Builder::new("ssl://127.0.0.1")),
+            BuilderError::InvalidUrl(_)
+        ));
+        assert!(matches!(
+            build_err(Builder::new("tcp://example.onion:50001")),
+            BuilderError::OnionWithoutProxy(_)
+        ));
+        assert!(matches!(
+            build_err(Builder::new("ssl://127.0.0.1:50002").ca_cert("ca.pem")),
+            BuilderError::CustomCertificate
+        ));
+        assert!(matches!(
+            build_err(Builder::new("tcp://127.0.0.1:50001").timeout(Duration::from_secs(256))),
+            BuilderError::InvalidTimeout(_)
+        ));
+        #[cfg(feature = "use-rustls")]
+        assert!(matches!(
+            Builder::new("tcp://127.0.0.1:50001").build_ssl().err(),
+            Some(BuilderError::NotSsl(_))
+        ));
+    }
+
+    #[test]
+    fn test_host() {
+        assert_eq!(host("example.com:50002"), "example.com");
+        assert_eq!(host("[::1]:50002"), "::1");
+        assert_eq!(host("127.0.0.1:50002"), "127.0.0.1");
+    }
+}
@@ -17,6 +17,8 @@
 
 mod bdk_electrum_client;
 pub use bdk_electrum_client::*;
+mod builder;
+pub use builder::*;
 
 pub use bdk_chain;
 pub use electrum_client;
@@ -1,12 +1,27 @@
 use bdk_chain::{
-    bitcoin::{hashes::Hash, Address, Amount, ScriptBuf, WScriptHash},
+    bitcoin::{
+        absolute, hashes::Hash, relative, transaction, Address, Amount, OutPoint, ScriptBuf,
+        Sequence, Transaction, TxIn, TxOut, Txid, WScriptHash,
+    },
     keychain::Balance,
     local_chain::LocalChain,
-    spk_client::SyncRequest,
+    spk_client::{BroadcastError, FullScanRequest, SyncRequest},
+    tx_graph::TxGraph,
+    BlockId, ChainPosition, ConfirmationHeightAnchor, ConfirmationTime,
     ConfirmationTimeHeightAnchor, IndexedTxGraph, SpkTxOutIndex,
 };
-use bdk_electrum::BdkElectrumClient;
+use bdk_electrum::{BdkElectrumClient, Builder, ReconnectOptions, SyncOptions};
 use bdk_testenv::{anyhow, bitcoincore_rpc::RpcApi, TestEnv};
+use bdk_wallet::miniscript::{plan::Assets, ForEachKey};
+use bdk_wallet::wallet::error::CreateTxError;
+use bdk_wallet::{KeychainKind, SignOptions, Wallet};
+use electrum_client::ElectrumApi;
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 fn get_balance(
     recv_chain: &LocalChain,
@@ -218,3 +233,987 @@ fn tx_can_become_unconfirmed_after_reorg() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Ensure that a tx confirmed in the replacement blocks of a reorg is anchored to the new block.
+///
+/// 1. Mine 101 blocks.
+/// 2. Send a tx to a tracked address and mine a block to confirm it.
+/// 3. Reorg that block with an empty block and check the tx becomes unconfirmed.
+/// 4. Reorg 2 blocks, confirming the tx in the first replacement block, and check its anchor.
+#[test]
+fn tx_is_anchored_to_replacement_block_after_reorg() -> anyhow::Result<()> {
+    const SEND_AMOUNT: Amount = Amount::from_sat(10_000);
+
+    let env = TestEnv::new()?;
+    let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+    let client = BdkElectrumClient::new(electrum_client);
+
+    // Setup addresses.
+    let spk_to_track = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+    let addr_to_track = Address::from_script(&spk_to_track, bdk_chain::bitcoin::Network::Regtest)?;
+
+    // Setup receiver.
+    let (mut recv_chain, _) = LocalChain::from_genesis_hash(env.bitcoind.client.get_block_hash(0)?);
+    let mut recv_graph = IndexedTxGraph::<ConfirmationTimeHeightAnchor, _>::new({
+        let mut recv_index = SpkTxOutIndex::default();
+        recv_index.insert_spk((), spk_to_track.clone());
+        recv_index
+    });
+    let sync = |recv_chain: &mut LocalChain,
+                recv_graph: &mut IndexedTxGraph<ConfirmationTimeHeightAnchor, _>|
+     -> anyhow::Result<()> {
+        env.wait_until_electrum_sees_block()?;
+        let update = client
+            .sync(
+                SyncRequest::from_chain_tip(recv_chain.tip()).chain_spks([spk_to_track.clone()]),
+                5,
+                false,
+            )?
+            .with_confirmation_time_height_anchor(&client)?;
+        let _ = recv_chain
+            .apply_update(update.chain_update)
+            .map_err(|err| anyhow::anyhow!("LocalChain update error: {:?}", err))?;
+        let _ = recv_graph.apply_update(update.graph_update);
+        Ok(())
+    };
+
+    // Mine some blocks and confirm a tx to the tracked address.
+    env.mine_blocks(101, None)?;
+    let txid = env.send(&addr_to_track, SEND_AMOUNT)?;
+    let tx = env.bitcoind.client.get_raw_transaction(&txid, None)?;
+    env.mine_blocks(1, None)?;
+    sync(&mut recv_chain, &mut recv_graph)?;
+    assert_eq!(
+        get_balance(&recv_chain, &recv_graph)?,
+        Balance {
+            confirmed: SEND_AMOUNT,
+            ..Balance::default()
+        },
+    );
+
+    // The tx isn't in the replacement block.
+    env.reorg_with_txs(1, &[])?;
+    sync(&mut recv_chain, &mut recv_graph)?;
+    assert_eq!(
+        get_balance(&recv_chain, &recv_graph)?,
+        Balance {
+            trusted_pending: SEND_AMOUNT,
+            ..Balance::default()
+        },
+    );
+
+    // The tx is in the first of the replacement blocks.
+    let block_hashes = env.reorg_with_txs(2, &[tx])?;
+    sync(&mut recv_chain, &mut recv_graph)?;
+    assert_eq!(
+        get_balance(&recv_chain, &recv_graph)?,
+        Balance {
+            confirmed: SEND_AMOUNT,
+            ..Balance::default()
+        },
+    );
+    let exp_height = env.bitcoind.client.get_block_info(&block_hashes[0])?.height as u32;
+    let position = recv_graph
+        .graph()
+        .get_chain_position(&recv_chain, recv_chain.tip().block_id(), txid)
+        .expect("tx must be in the best chain");
+    match position {
+        ChainPosition::Confirmed(anchor) => assert_eq!(anchor.confirmation_height, exp_height),
+        ChainPosition::Unconfirmed(_) => panic!("tx must be confirmed"),
+    }
+
+    Ok(())
+}
+
+/// Ensure that a larger `batch_size` makes fewer calls to the server for the same update.
+///
+/// 1. Mine 101 blocks.
+/// 2. Send a tx to the spks at index 2 and 7 of the tracked spks, with a gap of 4 between them.
+/// 3. Mine extra block to confirm sent txs.
+/// 4. Full scan with a stop gap of 5 and batch sizes of 1 and 4.
+/// 5. Check the updates are the same, and that the second scan made fewer calls.
+#[test]
+fn scan_batches_requests() -> anyhow::Result<()> {
+    const SEND_AMOUNT: Amount = Amount::from_sat(10_000);
+    const STOP_GAP: usize = 5;
+
+    let env = TestEnv::new()?;
+    let addr_to_mine = env
+        .bitcoind
+        .client
+        .get_new_address(None, None)?
+        .assume_checked();
+    let spks = (0..10_u8)
+        .map(|i| ScriptBuf::new_p2wsh(&WScriptHash::hash(&[i])))
+        .collect::<Vec<_>>();
+    let (chain, _) = LocalChain::from_genesis_hash(env.bitcoind.client.get_block_hash(0)?);
+
+    env.mine_blocks(101, Some(addr_to_mine))?;
+    for i in [2, 7] {
+        let addr = Address::from_script(&spks[i], bdk_chain::bitcoin::Network::Regtest)?;
+        env.send(&addr, SEND_AMOUNT)?;
+    }
+    env.mine_blocks(1, None)?;
+    env.wait_until_electrum_sees_block()?;
+
+    let full_scan = |batch_size| -> anyhow::Result<_> {
+        let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+        let client = BdkElectrumClient::new(electrum_client);
+        let request = FullScanRequest::from_chain_tip(chain.tip())
+            .set_spks_for_keychain(0, (0_u32..).zip(spks.clone()));
+        let update = client
+            .full_scan(request, STOP_GAP, batch_size, true)?
+            .with_confirmation_height_anchor();
+        let calls = client.inner().calls_made()?;
+        Ok((update, calls))
+    };
+    let (update, calls) = full_scan(1)?;
+    let (batched_update, batched_calls) = full_scan(4)?;
+
+    assert_eq!(update.last_active_indices, [(0, 7)].into());
+    assert_eq!(update.graph_update.full_txs().count(), 2);
+    assert_eq!(
+        batched_update.last_active_indices,
+        update.last_active_indices
+    );
+    assert_eq!(batched_update.graph_update, update.graph_update);
+    assert_eq!(batched_update.chain_update, update.chain_update);
+    assert!(
+        batched_calls < calls,
+        "batching must make fewer calls: {} >= {}",
+        batched_calls,
+        calls
+    );
+
+    Ok(())
+}
+
+/// Ensure that the confirmations of a sync with [`SyncOptions::verify_merkle`] are verified and
+/// anchored to their confirmation blocks.
+///
+/// 1. Mine 101 blocks.
+/// 2. Send a tx.
+/// 3. Mine extra blocks to confirm sent tx.
+/// 4. Sync with merkle proof verification.
+/// 5. Check the tx is anchored to its confirmation block, which is in the chain update.
+#[test]
+fn sync_verifies_merkle_proofs() -> anyhow::Result<()> {
+    const SEND_AMOUNT: Amount = Amount::from_sat(10_000);
+
+    let env = TestEnv::new()?;
+    let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+    let client = BdkElectrumClient::new(electrum_client);
+
+    let addr_to_mine = env
+        .bitcoind
+        .client
+        .get_new_address(None, None)?
+        .assume_checked();
+    let spk_to_track = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+    let addr_to_track = Address::from_script(&spk_to_track, bdk_chain::bitcoin::Network::Regtest)?;
+    let (chain, _) = LocalChain::from_genesis_hash(env.bitcoind.client.get_block_hash(0)?);
+
+    env.mine_blocks(101, Some(addr_to_mine))?;
+    let txid = env.send(&addr_to_track, SEND_AMOUNT)?;
+    let confirmation_block = BlockId {
+        height: 102,
+        hash: env.mine_blocks(1, None)?[0],
+    };
+    env.mine_blocks(20, None)?;
+    env.wait_until_electrum_sees_block()?;
+
+    let update = client
+        .sync_with_options(
+            SyncRequest::from_chain_tip(chain.tip()).chain_spks(core::iter::once(spk_to_track)),
+            SyncOptions {
+                verify_merkle: true,
+                ..Default::default()
+            },
+        )?
+        .with_confirmation_height_anchor();
+
+    assert_eq!(
+        update.graph_update.all_anchors().iter().collect::<Vec<_>>(),
+        [&(
+            ConfirmationHeightAnchor {
+                anchor_block: confirmation_block,
+                confirmation_height: confirmation_block.height,
+            },
+            txid
+        )]
+    );
+    assert_eq!(
+        update
+            .chain_update
+            .get(confirmation_block.height)
+            .map(|cp| cp.block_id()),
+        Some(confirmation_block)
+    );
+
+    Ok(())
+}
+
+/// Ensure that a transaction built with the fee rate estimated by the server pays it and is
+/// relayed.
+///
+/// 1. Mine 101 blocks.
+/// 2. Send a tx to a wallet and mine a block to confirm it.
+/// 3. Full scan the wallet.
+/// 4. Build a tx with the estimated fee rate, falling back to the relay fee, and broadcast it.
+/// 5. Check the tx pays at least the fee rate and is in the mempool of bitcoind.
+#[test]
+fn tx_builder_uses_fee_estimate() -> anyhow::Result<()> {
+    const RECV_AMOUNT: Amount = Amount::from_sat(100_000);
+    const SEND_AMOUNT: Amount = Amount::from_sat(10_000);
+
+    let env = TestEnv::new()?;
+    let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+    let client = BdkElectrumClient::new(electrum_client);
+
+    let addr_to_mine = env
+        .bitcoind
+        .client
+        .get_new_address(None, None)?
+        .assume_checked();
+    let mut wallet = Wallet::new_with_genesis_hash(
+        "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)",
+        "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/1/*)",
+        bdk_chain::bitcoin::Network::Regtest,
+        env.bitcoind.client.get_block_hash(0)?,
+    )?;
+
+    env.mine_blocks(101, Some(addr_to_mine.clone()))?;
+    env.send(
+        &wallet.next_unused_address(KeychainKind::External).address,
+        RECV_AMOUNT,
+    )?;
+    env.mine_blocks(1, None)?;
+    env.wait_until_electrum_sees_block()?;
+
+    let update = client
+        .full_scan(wallet.start_full_scan(), 10, 5, false)?
+        .with_confirmation_time_height_anchor(&client)?;
+    wallet.apply_update(update)?;
+    assert_eq!(wallet.balance().confirmed, RECV_AMOUNT);
+
+    // regtest has no fee history, the estimate is missing until txs confirm with fees
+    let fee_rate = match client.estimate_fee(6)? {
+        Some(fee_rate) => fee_rate,
+        None => client.relay_fee()?,
+    };
+    let mut tx_builder = wallet.build_tx();
+    tx_builder
+        .add_recipient(addr_to_mine.script_pubkey(), SEND_AMOUNT)
+        .fee_rate(fee_rate);
+    let mut psbt = tx_builder.finish()?;
+    assert!(wallet.sign(&mut psbt, SignOptions::default())?);
+    let tx = psbt.extract_tx()?;
+
+    let fee = wallet.calculate_fee(&tx)?;
+    assert!(fee >= fee_rate * tx.weight());
+    let txid = client.transaction_broadcast(&tx)?;
+    assert!(env.rpc_client().get_mempool_entry(&txid).is_ok());
+
+    Ok(())
+}
+
+/// Ensure that the header subscription yields the new tips, including the ones of reorgs.
+///
+/// 1. Mine 101 blocks and start the subscription at the tip.
+/// 2. Mine 2 blocks and check they are yielded in updates which connect to the local chain.
+/// 3. Reorg the last 2 blocks to 1 and check the local chain follows the reorg.
+#[test]
+fn subscribe_headers_yields_new_tips() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+    let client = BdkElectrumClient::new(electrum_client);
+
+    env.mine_blocks(101, None)?;
+    env.wait_until_electrum_sees_block()?;
+    let mut chain = LocalChain::from_tip(env.make_checkpoint_tip())?;
+    let mut headers = client
+        .subscribe_headers(chain.tip())
+        .poll_interval(Duration::from_millis(100));
+
+    let mut apply_until = |chain: &mut LocalChain, tip: BlockId| -> anyhow::Result<()> {
+        while chain.tip().block_id() != tip {
+            let update = headers.next().expect("subscription never ends")?;
+            chain
+                .apply_update(update.chain_update)
+                .map_err(|err| anyhow::anyhow!("LocalChain update error: {:?}", err))?;
+        }
+        Ok(())
+    };
+
+    let mined = env.mine_blocks(2, None)?;
+    apply_until(
+        &mut chain,
+        BlockId {
+            height: 103,
+            hash: mined[1],
+        },
+    )?;
+    assert_eq!(chain.get(102).map(|cp| cp.hash()), Some(mined[0]));
+
+    // a reorg to a lower tip invalidates the reorged blocks
+    env.invalidate_blocks(2)?;
+    let reorged = env.mine_blocks(1, None)?;
+    apply_until(
+        &mut chain,
+        BlockId {
+            height: 102,
+            hash: reorged[0],
+        },
+    )?;
+    assert!(chain.get(103).is_none());
+    assert_eq!(chain, LocalChain::from_tip(env.make_checkpoint_tip())?);
+
+    Ok(())
+}
+
+/// Ensure that syncing again with `fetch_prev_txouts` doesn't fetch the previous transactions
+/// already known.
+///
+/// 1. Mine 101 blocks.
+/// 2. Send 3 txs and mine a block to confirm them.
+/// 3. Sync with `fetch_prev_txouts`.
+/// 4. Sync again with a client populated with the resulting graph, with and without
+///    `fetch_prev_txouts`, and check both syncs make the same number of calls.
+#[test]
+fn sync_only_fetches_missing_prevouts() -> anyhow::Result<()> {
+    const SEND_AMOUNT: Amount = Amount::from_sat(10_000);
+
+    let env = TestEnv::new()?;
+    let addr_to_mine = env
+        .bitcoind
+        .client
+        .get_new_address(None, None)?
+        .assume_checked();
+    let spk_to_track = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+    let addr_to_track = Address::from_script(&spk_to_track, bdk_chain::bitcoin::Network::Regtest)?;
+    let (chain, _) = LocalChain::from_genesis_hash(env.bitcoind.client.get_block_hash(0)?);
+
+    env.mine_blocks(101, Some(addr_to_mine))?;
+    for _ in 0..3 {
+        env.send(&addr_to_track, SEND_AMOUNT)?;
+    }
+    env.mine_blocks(1, None)?;
+    env.wait_until_electrum_sees_block()?;
+
+    let sync = |graph: Option<&TxGraph<ConfirmationHeightAnchor>>, fetch_prev_txouts| {
+        let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+        let client = BdkElectrumClient::new(electrum_client);
+        if let Some(graph) = graph {
+            client.populate_tx_cache(graph);
+        }
+        let request = SyncRequest::from_chain_tip(chain.tip()).chain_spks([spk_to_track.clone()]);
+        let update = client
+            .sync(request, 5, fetch_prev_txouts)?
+            .with_confirmation_height_anchor();
+        let calls = client.inner().calls_made()?;
+        anyhow::Ok((update.graph_update, calls))
+    };
+
+    let (graph, _) = sync(None, true)?;
+    assert_eq!(graph.full_txs().count(), 3);
+    assert!(graph.floating_txouts().count() >= 3);
+
+    let (resynced_graph, calls) = sync(Some(&graph), true)?;
+    let (_, calls_without_prevouts) = sync(Some(&graph), false)?;
+    assert_eq!(resynced_graph, graph);
+    assert_eq!(calls, calls_without_prevouts);
+
+    Ok(())
+}
+
+/// Wait until the history of `spk` on the Electrum server contains `txid`.
+fn wait_until_electrum_sees_txid(env: &TestEnv, spk: &ScriptBuf, txid: Txid) -> anyhow::Result<()> {
+    while !env
+        .electrum_client()
+        .script_get_history(spk)?
+        .iter()
+        .any(|res| res.tx_hash == txid)
+    {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// Ensure that a sync reports the replaced txids of the request as evicted.
+///
+/// 1. Mine 101 blocks.
+/// 2. Send a tx to the tracked spk and replace it by bumping its fee.
+/// 3. Sync with the tx in the txids of the request.
+/// 4. Check the tx is evicted and the replacement is seen in the mempool.
+#[test]
+fn sync_detects_evicted_txs() -> anyhow::Result<()> {
+    const SEND_AMOUNT: Amount = Amount::from_sat(10_000);
+
+    let env = TestEnv::new()?;
+    let addr_to_mine = env
+        .bitcoind
+        .client
+        .get_new_address(None, None)?
+        .assume_checked();
+    let spk_to_track = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+    let addr_to_track = Address::from_script(&spk_to_track, bdk_chain::bitcoin::Network::Regtest)?;
+    let (chain, _) = LocalChain::from_genesis_hash(env.bitcoind.client.get_block_hash(0)?);
+
+    env.mine_blocks(101, Some(addr_to_mine))?;
+    env.wait_until_electrum_sees_block()?;
+    let txid = env.send(&addr_to_track, SEND_AMOUNT)?;
+    wait_until_electrum_sees_txid(&env, &spk_to_track, txid)?;
+    let bumped = env
+        .rpc_client()
+        .call::<bdk_testenv::bitcoincore_rpc::jsonrpc::serde_json::Value>(
+            "bumpfee",
+            &[txid.to_string().into()],
+        )?;
+    let replacement_txid = Txid::from_str(bumped["txid"].as_str().expect("txid"))?;
+    wait_until_electrum_sees_txid(&env, &spk_to_track, replacement_txid)?;
+
+    let sync = |detect_evictions| {
+        let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+        let client = BdkElectrumClient::new(electrum_client);
+        let request = SyncRequest::from_chain_tip(chain.tip())
+            .chain_spks([spk_to_track.clone()])
+            .chain_txids([txid]);
+        let options = SyncOptions {
+            detect_evictions,
+            ..Default::default()
+        };
+        let update = client
+            .sync_with_options(request, options)?
+            .with_confirmation_height_anchor();
+        anyhow::Ok(update.graph_update)
+    };
+
+    let graph = sync(true)?;
+    assert!(graph.last_evicted(txid).is_some());
+    assert!(graph.last_evicted(replacement_txid).is_none());
+    let replacement = graph
+        .full_txs()
+        .find(|tx_node| tx_node.txid == replacement_txid)
+        .expect("replacement must be in the update");
+    assert!(replacement.last_seen_unconfirmed > 0);
+
+    let graph = sync(false)?;
+    assert!(graph.last_evicted(txid).is_none());
+    assert!(graph
+        .full_txs()
+        .all(|tx_node| tx_node.last_seen_unconfirmed == 0));
+
+    Ok(())
+}
+
+/// Ensure that a sync can fetch the unconfirmed parents of the unconfirmed txs.
+///
+/// 1. Mine 101 blocks.
+/// 2. Send an unconfirmed parent tx to bitcoind's wallet and a child spending it to the tracked
+///    spk.
+/// 3. Sync and check the parent is only in the update if requested, making the fee of the child
+///    computable.
+#[test]
+fn sync_fetches_unconfirmed_parents() -> anyhow::Result<()> {
+    const PARENT_AMOUNT: Amount = Amount::from_sat(100_000);
+    const CHILD_FEE: Amount = Amount::from_sat(10_000);
+
+    let env = TestEnv::new()?;
+    let addr_to_mine = env
+        .bitcoind
+        .client
+        .get_new_address(None, None)?
+        .assume_checked();
+    let spk_to_track = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+    let (chain, _) = LocalChain::from_genesis_hash(env.bitcoind.client.get_block_hash(0)?);
+
+    env.mine_blocks(101, Some(addr_to_mine.clone()))?;
+    env.wait_until_electrum_sees_block()?;
+    let parent_txid = env.send(&addr_to_mine, PARENT_AMOUNT)?;
+    let parent = env.rpc_client().get_raw_transaction(&parent_txid, None)?;
+    let vout = parent
+        .output
+        .iter()
+        .position(|txout| txout.script_pubkey == addr_to_mine.script_pubkey())
+        .expect("parent must pay to the address");
+    let child = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(parent_txid, vout as u32),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: PARENT_AMOUNT - CHILD_FEE,
+            script_pubkey: spk_to_track.clone(),
+        }],
+    };
+    let child = env
+        .rpc_client()
+        .sign_raw_transaction_with_wallet(&child, None, None)?
+        .transaction()?;
+    let child_txid = env.rpc_client().send_raw_transaction(&child)?;
+    wait_until_electrum_sees_txid(&env, &spk_to_track, child_txid)?;
+
+    let sync = |fetch_unconfirmed_parents| {
+        let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+        let client = BdkElectrumClient::new(electrum_client);
+        let request = SyncRequest::from_chain_tip(chain.tip()).chain_spks([spk_to_track.clone()]);
+        let options = SyncOptions {
+            fetch_unconfirmed_parents,
+            ..Default::default()
+        };
+        let update = client
+            .sync_with_options(request, options)?
+            .with_confirmation_height_anchor();
+        anyhow::Ok(update.graph_update)
+    };
+
+    let graph = sync(false)?;
+    assert!(graph.get_tx(child_txid).is_some());
+    assert!(graph.get_tx(parent_txid).is_none());
+    assert!(graph.calculate_fee(&child).is_err());
+
+    let graph = sync(true)?;
+    assert!(graph.get_tx(parent_txid).is_some());
+    assert_eq!(graph.calculate_fee(&child).ok(), Some(CHILD_FEE));
+
+    Ok(())
+}
+
+/// Serve the Electrum `server.ping` requests of one connection over TLS with the self-signed
+/// certificate of `tests/data`, returning the address to connect to.
+#[cfg(feature = "use-rustls")]
+fn spawn_tls_server() -> anyhow::Result<std::net::SocketAddr> {
+    use bdk_testenv::bitcoincore_rpc::jsonrpc::serde_json;
+    use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+    use std::io::{BufRead, BufReader, Write};
+
+    let certs = vec![CertificateDer::from_pem_file(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/localhost.crt"
+    ))?];
+    let key = PrivateKeyDer::from_pem_file(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/localhost.key"
+    ))?;
+    let config = std::sync::Arc::new(
+        rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?,
+    );
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    std::thread::spawn(move || -> anyhow::Result<()> {
+        let (stream, _) = listener.accept()?;
+        let connection = rustls::ServerConnection::new(config)?;
+        let mut stream = BufReader::new(rustls::StreamOwned::new(connection, stream));
+        let mut line = String::new();
+        while stream.read_line(&mut line)? > 0 {
+            let request: serde_json::Value = serde_json::from_str(&line)?;
+            let result = match request["method"].as_str() {
+                Some("server.version") => serde_json::json!(["mock", "1.4"]),
+                _ => serde_json::Value::Null,
+            };
+            let response =
+                serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+            let stream = stream.get_mut();
+            writeln!(stream, "{}", response)?;
+            stream.flush()?;
+            line.clear();
+        }
+        Ok(())
+    });
+    Ok(addr)
+}
+
+/// Ensure that the builder connects to a TLS server with a self-signed certificate only if the
+/// certificate is trusted.
+#[test]
+#[cfg(feature = "use-rustls")]
+fn builder_connects_with_custom_certificates() -> anyhow::Result<()> {
+    use bdk_chain::bitcoin::hashes::sha256;
+    use rustls::pki_types::{pem::PemObject, CertificateDer};
+
+    const CA_CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/localhost.crt");
+
+    let fingerprint = sha256::Hash::hash(CertificateDer::from_pem_file(CA_CERT)?.as_ref());
+    let ping = |builder: fn(String) -> Builder| {
+        let url = format!("ssl://{}", spawn_tls_server()?);
+        // the builder negotiates with the server, which fails if the handshake does
+        let is_ok = builder(url)
+            .timeout(Duration::from_secs(5))
+            .build_ssl()
+            .map_or(false, |client| client.inner().ping().is_ok());
+        anyhow::Ok(is_ok)
+    };
+
+    assert!(ping(|url| Builder::new(url).ca_cert(CA_CERT))?);
+    assert!(ping(|url| Builder::new(url).validate_domain(false))?);
+    assert!(!ping(Builder::new)?);
+
+    let url = format!("ssl://{}", spawn_tls_server()?);
+    let client = Builder::new(url).pin_cert_sha256(fingerprint).build_ssl()?;
+    assert!(client.inner().ping().is_ok());
+    let url = format!("ssl://{}", spawn_tls_server()?);
+    assert!(Builder::new(url)
+        .pin_cert_sha256(sha256::Hash::all_zeros())
+        .build_ssl()
+        .is_err());
+
+    Ok(())
+}
+
+/// Serve the Electrum requests of one connection like `software`, for a regtest chain of only the
+/// genesis block, returning the address to connect to and whether requests were pipelined.
+///
+/// `server.version` answers with `protocol`, and `blockchain.scripthash.get_mempool` is answered
+/// only if `batches` is `true`.
+fn spawn_electrum_server(
+    software: &'static str,
+    protocol: &'static str,
+    batches: bool,
+) -> anyhow::Result<(std::net::SocketAddr, Arc<std::sync::atomic::AtomicBool>)> {
+    use bdk_chain::bitcoin::{blockdata::constants::genesis_block, consensus, Network};
+    use bdk_testenv::bitcoincore_rpc::jsonrpc::serde_json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let header = consensus::encode::serialize_hex(&genesis_block(Network::Regtest).header);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let pipelined = Arc::new(AtomicBool::new(false));
+    let is_pipelined = Arc::clone(&pipelined);
+    std::thread::spawn(move || -> anyhow::Result<()> {
+        let (stream, _) = listener.accept()?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            // the next request was sent before this one is answered
+            if !reader.buffer().is_empty() {
+                is_pipelined.store(true, Ordering::Relaxed);
+            }
+            let request: serde_json::Value = serde_json::from_str(&line)?;
+            let result = match request["method"].as_str().unwrap_or_default() {
+                "server.version" => Ok(serde_json::json!([software, protocol])),
+                "server.ping" => Ok(serde_json::Value::Null),
+                "blockchain.scripthash.get_mempool" if batches => Ok(serde_json::json!([])),
+                "blockchain.scripthash.get_history" => Ok(serde_json::json!([])),
+                "blockchain.headers.subscribe" => {
+                    Ok(serde_json::json!({"height": 0, "hex": header}))
+                }
+                "blockchain.block.header" => Ok(serde_json::json!(header)),
+                "blockchain.block.headers" => {
+                    Ok(serde_json::json!({"count": 1, "hex": header, "max": 2016}))
+                }
+                method => Err(format!("unknown method {}", method)),
+            };
+            let response = match result {
+                Ok(result) => {
+                    serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+                }
+                Err(message) => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "error": {"code": -32601, "message": message},
+                }),
+            };
+            writeln!(writer, "{}", response)?;
+            writer.flush()?;
+            line.clear();
+        }
+        Ok(())
+    });
+    Ok((addr, pipelined))
+}
+
+/// Ensure that the capabilities of the known server implementations are detected, and that the
+/// client falls back to single requests for a server which doesn't support batches.
+#[test]
+fn builder_detects_server_capabilities() -> anyhow::Result<()> {
+    use bdk_chain::bitcoin::{blockdata::constants::genesis_block, Network};
+    use std::sync::atomic::Ordering;
+
+    let servers = [
+        ("electrs/0.10.5", "1.4", true),
+        ("Fulcrum 1.11.1", "1.4.5", true),
+        ("ElectrumX 1.18.0", "1.4.3", true),
+        ("electrs-esplora 0.4.1", "1.4", false),
+    ];
+    for (software, protocol, batches) in servers {
+        let (addr, pipelined) = spawn_electrum_server(software, protocol, batches)?;
+        let client = Builder::new(format!("tcp://{}", addr))
+            .timeout(Duration::from_secs(5))
+            .build()?;
+        let capabilities = client.capabilities().expect("must be negotiated");
+        assert_eq!(capabilities.server_software, software);
+        assert_eq!(capabilities.protocol_version, protocol);
+        assert_eq!(capabilities.supports_batch_mempool, batches);
+        assert!(capabilities.supports_headers_subscribe);
+
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let (chain, _) = LocalChain::from_genesis_hash(genesis_hash);
+        let spks = (0..4u8)
+            .map(|i| ScriptBuf::new_p2wsh(&WScriptHash::hash(&[i])))
+            .collect::<Vec<_>>();
+        let update = client.sync(
+            SyncRequest::from_chain_tip(chain.tip()).chain_spks(spks),
+            4,
+            false,
+        )?;
+        assert_eq!(
+            update
+                .with_confirmation_height_anchor()
+                .chain_update
+                .height(),
+            0
+        );
+        assert_eq!(pipelined.load(Ordering::Relaxed), batches, "{}", software);
+    }
+
+    // a server older than the protocol 1.3 can't notify the headers
+    let (addr, _) = spawn_electrum_server("ElectrumX 1.8.5", "1.2", true)?;
+    let client = Builder::new(format!("tcp://{}", addr)).build()?;
+    let capabilities = client.capabilities().expect("must be negotiated");
+    assert!(!capabilities.supports_headers_subscribe);
+    let (chain, _) = LocalChain::from_genesis_hash(genesis_block(Network::Regtest).block_hash());
+    assert!(matches!(
+        client.subscribe_headers(chain.tip()).next(),
+        Some(Err(electrum_client::Error::Message(_)))
+    ));
+
+    Ok(())
+}
+
+/// Ensure that the builder connects to electrsd.
+#[test]
+fn builder_connects_to_electrsd() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let client = BdkElectrumClient::builder(format!("tcp://{}", env.electrsd.electrum_url))
+        .timeout(Duration::from_secs(5))
+        .retry(2)
+        .build()?;
+    client.inner().ping()?;
+    #[cfg(feature = "use-rustls")]
+    assert!(Builder::new(env.electrsd.electrum_url.as_str())
+        .build_ssl()
+        .is_err());
+    Ok(())
+}
+
+/// Ensure that a client in the reconnecting mode completes a sync after electrsd restarts.
+///
+/// 1. Mine 101 blocks, send a tx to the tracked spk and confirm it.
+/// 2. Sync, then restart electrsd, which drops the connection of the client.
+/// 3. Sync again and check the client reconnected and found the tx.
+#[test]
+fn sync_reconnects_after_electrsd_restart() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let spk_to_track = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+    let addr_to_track = Address::from_script(&spk_to_track, bdk_chain::bitcoin::Network::Regtest)?;
+    let (chain, _) = LocalChain::from_genesis_hash(env.bitcoind.client.get_block_hash(0)?);
+
+    let url = Arc::new(Mutex::new(env.electrsd.electrum_url.clone()));
+    let connect = {
+        let url = Arc::clone(&url);
+        move || {
+            let config = electrum_client::Config::builder().retry(0).build();
+            electrum_client::Client::from_config(&url.lock().unwrap(), config)
+        }
+    };
+    let options = ReconnectOptions {
+        initial_backoff: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let client = BdkElectrumClient::new(connect()?).with_reconnect(options, connect);
+
+    env.mine_blocks(101, None)?;
+    let txid = env.send(&addr_to_track, Amount::from_sat(10_000))?;
+    env.mine_blocks(1, None)?;
+    env.wait_until_electrum_sees_block()?;
+    let request = || SyncRequest::from_chain_tip(chain.tip()).chain_spks([spk_to_track.clone()]);
+    client.sync(request(), 5, false)?;
+    assert_eq!(client.reconnect_count(), 0);
+
+    let env = env.reset_electrsd()?;
+    *url.lock().unwrap() = env.electrsd.electrum_url.clone();
+    env.wait_until_electrum_sees_block()?;
+    let update = client
+        .sync(request(), 5, false)?
+        .with_confirmation_height_anchor();
+    assert!(update.graph_update.get_tx(txid).is_some());
+    assert!(client.reconnect_count() >= 1);
+
+    Ok(())
+}
+
+/// Ensure that `broadcast_all` reports the result of each broadcast, in order.
+///
+/// 1. Mine 101 blocks and create a parent tx and a child spending it, without broadcasting them.
+/// 2. Broadcast the child before the parent, check the child is rejected for its missing inputs.
+/// 3. Broadcast both in order, check both are reported as broadcast.
+#[test]
+fn broadcast_all_reports_each_tx() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+    let client = BdkElectrumClient::new(electrum_client);
+    let address = env
+        .bitcoind
+        .client
+        .get_new_address(None, None)?
+        .assume_checked();
+    env.mine_blocks(101, Some(address.clone()))?;
+    env.wait_until_electrum_sees_block()?;
+    let (parent, child) = env.create_unbroadcast_package(
+        &address,
+        Amount::from_sat(100_000),
+        Amount::from_sat(10_000),
+    )?;
+
+    let results = client.broadcast_all(&[child.clone(), parent.clone()]);
+    assert!(matches!(results[0], Err(BroadcastError::MissingInputs)));
+    assert_eq!(results[1].as_ref().ok(), Some(&parent.compute_txid()));
+
+    let results = client.broadcast_all(&[parent.clone(), child.clone()]);
+    let txids = results.into_iter().map(Result::ok).collect::<Vec<_>>();
+    assert_eq!(
+        txids,
+        vec![Some(parent.compute_txid()), Some(child.compute_txid())]
+    );
+
+    Ok(())
+}
+
+/// Spend the timelocked recovery branch of `or_d(pk(A),and_v(v:pk(B),older(6)))` end-to-end, with
+/// a wallet which only has the private key of B.
+///
+/// 1. Mine 101 blocks, fund the wallet and confirm the funding tx.
+/// 2. Check the recovery branch can't be planned, nor built with `TxBuilder::policy_path`.
+/// 3. Mine 6 blocks and check the recovery branch is now planned with a relative timelock of 6.
+/// 4. Build the recovery spend with `TxBuilder::policy_path`, sign it with B and confirm it.
+#[test]
+fn spend_csv_recovery_path() -> anyhow::Result<()> {
+    const RECV_AMOUNT: Amount = Amount::from_sat(100_000);
+    const SEND_AMOUNT: Amount = Amount::from_sat(50_000);
+    // the xpub of tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L
+    const KEY_A: &str = "tpubD6NzVbkrYhZ4XS88FM9UegpoPq8MHbNzz5Y8imrsHPVV5Uyhfqe3skHVAaziMyehys4CPCzsB8KQZuYEbmJJ3NQgnhSkfDuAqkW2PGyewpB";
+    const KEY_B: &str = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+
+    let env = TestEnv::new()?;
+    let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+    let client = BdkElectrumClient::new(electrum_client);
+
+    let mut wallet = Wallet::new_with_genesis_hash(
+        &format!("wsh(or_d(pk({KEY_A}/0/*),and_v(v:pk({KEY_B}/0/*),older(6))))"),
+        &format!("wsh(or_d(pk({KEY_A}/1/*),and_v(v:pk({KEY_B}/1/*),older(6))))"),
+        bdk_chain::bitcoin::Network::Regtest,
+        env.bitcoind.client.get_block_hash(0)?,
+    )?;
+    let sync = |wallet: &mut Wallet| -> anyhow::Result<()> {
+        env.wait_until_electrum_sees_block()?;
+        let update = client
+            .sync(wallet.start_sync_with_revealed_spks(), 5, false)?
+            .with_confirmation_time_height_anchor(&client)?;
+        wallet.apply_update(update)?;
+        Ok(())
+    };
+
+    // The public descriptor has the key B as its second key.
+    let mut keys = Vec::new();
+    wallet
+        .public_descriptor(KeychainKind::External)
+        .for_each_key(|key| {
+            keys.push(key.clone());
+            true
+        });
+    let recovery_key = keys[1].clone();
+    let recipient = env
+        .bitcoind
+        .client
+        .get_new_address(None, None)?
+        .assume_checked();
+
+    env.mine_blocks(101, None)?;
+    let address = wallet.next_unused_address(KeychainKind::External);
+    env.send(&address.address, RECV_AMOUNT)?;
+    env.mine_blocks(1, None)?;
+    sync(&mut wallet)?;
+    assert_eq!(wallet.balance().confirmed, RECV_AMOUNT);
+    let confirmation_height = match wallet
+        .list_unspent()
+        .next()
+        .map(|utxo| utxo.confirmation_time)
+    {
+        Some(ConfirmationTime::Confirmed { height, .. }) => height,
+        _ => panic!("the funding tx must be confirmed"),
+    };
+    let definite_descriptor = wallet
+        .public_descriptor(KeychainKind::External)
+        .at_derivation_index(address.index)?;
+    // the assets of B, given the blocks mined since the funding tx confirmed
+    let recovery_assets = |wallet: &Wallet| {
+        let blocks = (wallet.latest_checkpoint().height() - confirmation_height) as u16;
+        Assets::new()
+            .add(recovery_key.clone())
+            .older(relative::LockTime::from_height(blocks))
+    };
+
+    // The recovery branch can't be planned nor built before 6 blocks.
+    assert!(definite_descriptor
+        .clone()
+        .plan(&recovery_assets(&wallet))
+        .is_err());
+    let policy = wallet
+        .policies(KeychainKind::External)?
+        .expect("must have a policy");
+    // child #1 is and(pk(B),older(6))
+    let path: BTreeMap<_, _> = vec![(policy.id, vec![1])].into_iter().collect();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(recipient.script_pubkey(), SEND_AMOUNT)
+        .policy_path(path.clone(), KeychainKind::External);
+    match builder.finish() {
+        Err(CreateTxError::TimelockNotMature { valid_at_height }) => {
+            assert_eq!(valid_at_height, confirmation_height + 6)
+        }
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    // After 6 blocks the recovery branch is planned with its relative timelock.
+    env.mine_blocks(6, None)?;
+    sync(&mut wallet)?;
+    let plan = definite_descriptor
+        .plan(&recovery_assets(&wallet))
+        .map_err(|_| anyhow::anyhow!("the recovery branch must be plannable"))?;
+    assert_eq!(
+        plan.relative_timelock,
+        Some(relative::LockTime::from_height(6))
+    );
+
+    // Build the recovery spend, sign it with B only and confirm it.
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(recipient.script_pubkey(), SEND_AMOUNT)
+        .policy_path(path, KeychainKind::External);
+    let mut psbt = builder.finish()?;
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
+    assert!(wallet.sign(&mut psbt, SignOptions::default())?);
+    let tx = psbt.extract_tx()?;
+    let txid = client.transaction_broadcast(&tx)?;
+    env.mine_blocks(1, None)?;
+    sync(&mut wallet)?;
+    let confirmations = env
+        .bitcoind
+        .client
+        .get_transaction(&txid, None)?
+        .info
+        .confirmations;
+    assert_eq!(confirmations, 1);
+    assert_eq!(
+        wallet.balance().confirmed,
+        RECV_AMOUNT - SEND_AMOUNT - wallet.calculate_fee(&tx)?
+    );
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use bdk_chain::{bitcoin::Amount, spk_client::BackendOptions, spk_client::SyncBackend};
+use bdk_electrum::BdkElectrumClient;
+use bdk_esplora::{esplora_client, EsploraBackend};
+use bdk_testenv::{anyhow, bitcoincore_rpc::RpcApi, electrum_client::ElectrumApi, TestEnv};
+use bdk_wallet::{KeychainKind, Wallet};
+
+const EXTERNAL_DESC: &str = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)";
+const INTERNAL_DESC: &str = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/1/*)";
+
+fn new_wallet(env: &TestEnv) -> anyhow::Result<Wallet> {
+    Ok(Wallet::new_with_genesis_hash(
+        EXTERNAL_DESC,
+        INTERNAL_DESC,
+        bdk_chain::bitcoin::Network::Regtest,
+        env.bitcoind.client.get_block_hash(0)?,
+    )?)
+}
+
+/// The txids of the wallet, sorted, with their confirmation height if confirmed
+fn wallet_txs(wallet: &Wallet) -> Vec<(bdk_chain::bitcoin::Txid, Option<u32>)> {
+    let mut txs = wallet
+        .transactions()
+        .map(|tx| {
+            let height = match tx.chain_position {
+                bdk_chain::ChainPosition::Confirmed(anchor) => Some(anchor.confirmation_height),
+                bdk_chain::ChainPosition::Unconfirmed(_) => None,
+            };
+            (tx.tx_node.txid, height)
+        })
+        .collect::<Vec<_>>();
+    txs.sort();
+    txs
+}
+
+/// Ensure that the chain sources can be used interchangeably behind [`SyncBackend`].
+///
+/// 1. Mine 101 blocks, send 2 txs to the wallet addresses at index 0 and 3, and confirm them.
+/// 2. Full scan a new wallet with each backend and check the wallets are the same.
+/// 3. Send a third tx, leave it unconfirmed and sync each wallet with its backend.
+/// 4. Check the wallets are still the same and have the unconfirmed tx.
+#[test]
+fn sync_backends_are_interchangeable() -> anyhow::Result<()> {
+    const SEND_AMOUNT: Amount = Amount::from_sat(10_000);
+
+    let env = TestEnv::new()?;
+    let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+    let esplora_url = format!(
+        "http://{}",
+        env.electrsd
+            .esplora_url
+            .as_ref()
+            .expect("esplora is enabled")
+    );
+    let backends: Vec<Box<dyn SyncBackend<KeychainKind>>> = vec![
+        Box::new(BdkElectrumClient::new(electrum_client)),
+        Box::new(EsploraBackend::new(
+            esplora_client::Builder::new(&esplora_url).build_blocking(),
+        )),
+    ];
+    let options = BackendOptions {
+        stop_gap: 5,
+        ..BackendOptions::default()
+    };
+
+    let wallet = new_wallet(&env)?;
+    env.mine_blocks(101, None)?;
+    for index in [0, 3] {
+        let address = wallet.peek_address(KeychainKind::External, index).address;
+        env.send(&address, SEND_AMOUNT)?;
+    }
+    env.mine_blocks(1, None)?;
+    env.wait_until_electrum_sees_block()?;
+
+    let mut wallets = Vec::new();
+    for backend in &backends {
+        let mut wallet = new_wallet(&env)?;
+        let update = backend
+            .full_scan(wallet.start_full_scan(), options)
+            .map_err(|err| anyhow::anyhow!(err))?;
+        wallet.apply_update(update)?;
+        assert_eq!(wallet.balance().confirmed, SEND_AMOUNT * 2);
+        assert_eq!(
+            wallet.derivation_index(KeychainKind::External),
+            Some(3),
+            "the full scan must find the last active index"
+        );
+        wallets.push(wallet);
+    }
+    assert_eq!(wallet_txs(&wallets[0]), wallet_txs(&wallets[1]));
+
+    let address = wallet.peek_address(KeychainKind::External, 1).address;
+    let txid = env.send(&address, SEND_AMOUNT)?;
+    // wait for the tx to reach the server
+    while env
+        .electrum_client()
+        .script_get_history(&address.script_pubkey())?
+        .is_empty()
+    {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    for (backend, wallet) in backends.iter().zip(&mut wallets) {
+        let update = backend
+            .sync(wallet.start_sync_with_revealed_spks(), options)
+            .map_err(|err| anyhow::anyhow!(err))?;
+        wallet.apply_update(update)?;
+        assert_eq!(wallet.balance().untrusted_pending, SEND_AMOUNT);
+    }
+    let txs = wallet_txs(&wallets[0]);
+    assert_eq!(txs, wallet_txs(&wallets[1]));
+    assert!(txs.contains(&(txid, None)));
+
+    Ok(())
+}
+
+/// Ensure that the accounts of a key are recovered from the standard templates.
+///
+/// 1. Mine 101 blocks, send a tx to the BIP-84 account 0 and 2 txs to the BIP-86 account 1 of
+///    the key, and confirm them.
+/// 2. Scan the templates and check both accounts are found, with the amount they received.
+#[test]
+fn recovery_finds_funded_templates() -> anyhow::Result<()> {
+    use bdk_wallet::bitcoin::bip32::Xpriv;
+    use bdk_wallet::wallet::recovery::{scan_templates, RecoveryOptions, RecoveryTemplate};
+    use core::str::FromStr;
+
+    const TPRV: &str = "tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L";
+
+    let env = TestEnv::new()?;
+    let electrum_client = electrum_client::Client::new(env.electrsd.electrum_url.as_str())?;
+    let backend = BdkElectrumClient::new(electrum_client);
+
+    let bip84_0 = Wallet::new(
+        &format!("wpkh({}/84'/1'/0'/0/*)", TPRV),
+        &format!("wpkh({}/84'/1'/0'/1/*)", TPRV),
+        bdk_chain::bitcoin::Network::Regtest,
+    )?;
+    let bip86_1 = Wallet::new(
+        &format!("tr({}/86'/1'/1'/0/*)", TPRV),
+        &format!("tr({}/86'/1'/1'/1/*)", TPRV),
+        bdk_chain::bitcoin::Network::Regtest,
+    )?;
+    env.mine_blocks(101, None)?;
+    env.send(
+        &bip84_0.peek_address(KeychainKind::External, 1).address,
+        Amount::from_sat(10_000),
+    )?;
+    env.send(
+        &bip86_1.peek_address(KeychainKind::External, 0).address,
+        Amount::from_sat(20_000),
+    )?;
+    env.send(
+        &bip86_1.peek_address(KeychainKind::Internal, 2).address,
+        Amount::from_sat(30_000),
+    )?;
+    env.mine_blocks(1, None)?;
+    env.wait_until_electrum_sees_block()?;
+
+    let accounts = scan_templates(
+        &backend,
+        Xpriv::from_str(TPRV)?,
+        bdk_chain::bitcoin::Network::Regtest,
+        &RecoveryOptions::default(),
+    )?;
+    assert_eq!(
+        accounts
+            .iter()
+            .map(|account| (
+                account.template,
+                account.account,
+                account.last_active_external,
+                account.last_active_internal,
+                account.received,
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                RecoveryTemplate::Bip84,
+                0,
+                Some(1),
+                None,
+                Amount::from_sat(10_000)
+            ),
+            (
+                RecoveryTemplate::Bip86,
+                1,
+                Some(0),
+                Some(2),
+                Amount::from_sat(50_000)
+            ),
+        ]
+    );
+
+    // the descriptors recover the funds
+    let mut wallet = Wallet::new_with_genesis_hash(
+        &accounts[1].descriptor,
+        &accounts[1].change_descriptor,
+        bdk_chain::bitcoin::Network::Regtest,
+        env.bitcoind.client.get_block_hash(0)?,
+    )?;
+    let update = SyncBackend::full_scan(
+        &backend,
+        wallet.start_full_scan(),
+        BackendOptions::default(),
+    )
+    .map_err(|err| anyhow::anyhow!(err))?;
+    wallet.apply_update(update)?;
+    assert_eq!(wallet.balance().total(), Amount::from_sat(50_000));
+
+    Ok(())
+}
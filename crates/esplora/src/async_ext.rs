@@ -1,21 +1,30 @@
 use std::collections::BTreeSet;
+use std::future::Future;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use bdk_chain::spk_client::{FullScanRequest, FullScanResult, SyncRequest, SyncResult};
+use bdk_chain::spk_client::{
+    BroadcastError, CpfpInfo, FullScanRequest, FullScanResult, ScanProgressTracker, ScanStats,
+    SyncRequest, SyncResult,
+};
 use bdk_chain::{
-    bitcoin::{BlockHash, OutPoint, ScriptBuf, TxOut, Txid},
+    bitcoin::{block::Header, BlockHash, OutPoint, ScriptBuf, Transaction, Txid},
     collections::BTreeMap,
     local_chain::CheckPoint,
     BlockId, ConfirmationTimeHeightAnchor, TxGraph,
 };
 use bdk_chain::{Anchor, Indexed};
-use esplora_client::{Amount, TxStatus};
-use futures::{stream::FuturesOrdered, TryStreamExt};
-
-use crate::anchor_from_status;
+use futures::{
+    stream::{FuturesOrdered, FuturesUnordered},
+    TryStreamExt,
+};
 
-/// [`esplora_client::Error`]
-type Error = Box<esplora_client::Error>;
+use crate::mempool_space::{get_opt_json, CpfpResponse};
+use crate::{
+    anchor_from_status, broadcast_result, check_header, genesis_mismatch, is_transient,
+    previous_outputs, unix_time, Error, EsploraBackend, FeeEstimates, RetryPolicy, ScanOptions,
+    ServerFlavor, Stopwatch,
+};
 
 /// Trait to extend the functionality of [`esplora_client::AsyncClient`].
 ///
@@ -32,8 +41,22 @@ pub trait EsploraAsyncExt {
     ///              see [`FullScanRequest`]
     ///
     /// The full scan for each keychain stops after a gap of `stop_gap` script pubkeys with no
-    /// associated transactions. `parallel_requests` specifies the max number of HTTP requests to
-    /// make in parallel.
+    /// associated transactions, or of the stop gap set for the keychain with
+    /// [`FullScanRequest::set_stop_gap_for_keychain`]. `options` specifies the max number of HTTP requests to make in
+    /// parallel, how far ahead of the stop gap evaluation script pubkeys are requested and how
+    /// failed requests are retried, see [`ScanOptions`]. The result is the same whatever the
+    /// options: script pubkeys are checked against the stop gap in order, and the responses for
+    /// script pubkeys past the stop gap are discarded.
+    ///
+    /// The transactions come with the script histories, which Esplora returns in pages of 25
+    /// confirmed transactions, so there is no request per transaction: a script pubkey with a
+    /// history of 500 transactions takes 21 requests.
+    ///
+    /// The [`FullScanResult::scan_stats`] of each keychain tell how many script pubkeys were
+    /// checked, the last active index, the number of transactions fetched and the time spent.
+    ///
+    /// The closure set with [`FullScanRequest::on_progress`] is called with the progress of the
+    /// scan as the script histories are checked against the stop gap.
     ///
     /// ## Note
     ///
@@ -51,7 +74,7 @@ pub trait EsploraAsyncExt {
         &self,
         request: FullScanRequest<K>,
         stop_gap: usize,
-        parallel_requests: usize,
+        options: ScanOptions,
     ) -> Result<FullScanResult<K>, Error>;
 
     /// Sync a set of scripts with the blockchain (via an Esplora client) for the data
@@ -64,11 +87,43 @@ pub trait EsploraAsyncExt {
     /// may include scripts that have been used, use [`full_scan`] with the keychain.
     ///
     /// [`full_scan`]: EsploraAsyncExt::full_scan
-    async fn sync(
-        &self,
-        request: SyncRequest,
-        parallel_requests: usize,
-    ) -> Result<SyncResult, Error>;
+    async fn sync(&self, request: SyncRequest, options: ScanOptions) -> Result<SyncResult, Error>;
+
+    /// Fetch the fee rates estimated by the server for its confirmation targets, see
+    /// [`FeeEstimates`].
+    ///
+    /// The request is retried according to `retry`, [`RetryPolicy::none`] not to retry it.
+    async fn fee_estimates(&self, retry: RetryPolicy) -> Result<FeeEstimates, Error>;
+
+    /// Fetch the headers of the blocks at `heights`, in the same order.
+    ///
+    /// Every header must hash to the block hash the server reports at its height and satisfy the
+    /// proof of work of its target, otherwise [`Error::InvalidHeader`] is returned. The requests
+    /// are retried with [`RetryPolicy::default`].
+    async fn get_headers(&self, heights: &[u32]) -> Result<Vec<Header>, Error>;
+
+    /// Broadcast `txs` one after the other, returning the result of each in the same order.
+    ///
+    /// A failed broadcast doesn't stop the next ones. The transactions of a package must be
+    /// ordered parents first: a child broadcast before its parent fails with
+    /// [`BroadcastError::MissingInputs`]. A transaction already in the mempool of the server is
+    /// reported as broadcast. The requests are retried with [`RetryPolicy::default`].
+    async fn broadcast_all(&self, txs: &[Transaction]) -> Vec<Result<Txid, BroadcastError<Error>>>;
+
+    /// Tell whether the server serves the API of mempool.space, to set
+    /// [`ScanOptions::server_flavor`], see the [crate-level documentation](crate#mempoolspace).
+    ///
+    /// The server is a mempool.space one if it answers `GET /v1/fees/recommended`, it's an
+    /// Esplora one if it answers with an HTTP error. The request is retried with
+    /// [`RetryPolicy::default`].
+    async fn detect_server_flavor(&self) -> Result<ServerFlavor, Error>;
+
+    /// Fetch the unconfirmed ancestors and descendants of the transaction `txid` in the mempool of
+    /// a mempool.space server, with `GET /v1/cpfp/:txid`, see [`CpfpInfo`].
+    ///
+    /// Returns `None` if the server doesn't know the transaction, or if it isn't a mempool.space
+    /// one and answers with an HTTP error. The request is retried with [`RetryPolicy::default`].
+    async fn tx_cpfp_info(&self, txid: Txid) -> Result<Option<CpfpInfo>, Error>;
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -76,51 +131,67 @@ pub trait EsploraAsyncExt {
 impl EsploraAsyncExt for esplora_client::AsyncClient {
     async fn full_scan<K: Ord + Clone + Send>(
         &self,
-        request: FullScanRequest<K>,
+        mut request: FullScanRequest<K>,
         stop_gap: usize,
-        parallel_requests: usize,
+        options: ScanOptions,
     ) -> Result<FullScanResult<K>, Error> {
-        let latest_blocks = fetch_latest_blocks(self).await?;
-        let (graph_update, last_active_indices) = full_scan_for_index_and_graph(
+        let latest_blocks = fetch_latest_blocks(self, options.retry).await?;
+        let mut progress = request.progress_tracker(stop_gap);
+        let (graph_update, scan_stats) = full_scan_for_index_and_graph(
             self,
             request.spks_by_keychain,
+            request.stop_gaps,
             stop_gap,
-            parallel_requests,
+            &options,
+            &mut progress,
         )
         .await?;
+        let graph_update = if options.verify_anchors {
+            verify_anchors(self, graph_update, &options).await?
+        } else {
+            graph_update
+        };
         let chain_update = chain_update(
             self,
             &latest_blocks,
             &request.chain_tip,
             graph_update.all_anchors(),
+            options.retry,
         )
         .await?;
+        let last_active_indices = scan_stats
+            .iter()
+            .filter_map(|(keychain, stats)| Some((keychain.clone(), stats.last_active_index?)))
+            .collect();
         Ok(FullScanResult {
             chain_update,
             graph_update,
             last_active_indices,
+            scan_stats,
         })
     }
 
-    async fn sync(
-        &self,
-        request: SyncRequest,
-        parallel_requests: usize,
-    ) -> Result<SyncResult, Error> {
-        let latest_blocks = fetch_latest_blocks(self).await?;
+    async fn sync(&self, request: SyncRequest, options: ScanOptions) -> Result<SyncResult, Error> {
+        let latest_blocks = fetch_latest_blocks(self, options.retry).await?;
         let graph_update = sync_for_index_and_graph(
             self,
             request.spks,
             request.txids,
             request.outpoints,
-            parallel_requests,
+            &options,
         )
         .await?;
+        let graph_update = if options.verify_anchors {
+            verify_anchors(self, graph_update, &options).await?
+        } else {
+            graph_update
+        };
         let chain_update = chain_update(
             self,
             &latest_blocks,
             &request.chain_tip,
             graph_update.all_anchors(),
+            options.retry,
         )
         .await?;
         Ok(SyncResult {
@@ -128,6 +199,95 @@ impl EsploraAsyncExt for esplora_client::AsyncClient {
             graph_update,
         })
     }
+    async fn fee_estimates(&self, retry: RetryPolicy) -> Result<FeeEstimates, Error> {
+        let estimates = with_retry(retry, || self.get_fee_estimates()).await?;
+        Ok(FeeEstimates::from_sat_per_vb(estimates))
+    }
+
+    async fn get_headers(&self, heights: &[u32]) -> Result<Vec<Header>, Error> {
+        let retry = RetryPolicy::default();
+        let mut headers = Vec::with_capacity(heights.len());
+        for &height in heights {
+            let hash = with_retry(retry, || self.get_block_hash(height)).await?;
+            let header = with_retry(retry, || self.get_header_by_hash(&hash)).await?;
+            check_header(BlockId { height, hash }, &header)?;
+            headers.push(header);
+        }
+        Ok(headers)
+    }
+    async fn broadcast_all(&self, txs: &[Transaction]) -> Vec<Result<Txid, BroadcastError<Error>>> {
+        let mut results = Vec::with_capacity(txs.len());
+        for tx in txs {
+            let result = with_retry(RetryPolicy::default(), || self.broadcast(tx)).await;
+            results.push(broadcast_result(tx.compute_txid(), result));
+        }
+        results
+    }
+
+    async fn detect_server_flavor(&self) -> Result<ServerFlavor, Error> {
+        let result = with_retry(RetryPolicy::default(), || {
+            get_opt_json::<serde::de::IgnoredAny>(self, "/v1/fees/recommended")
+        })
+        .await;
+        match or_http_error(result)? {
+            Some(Some(_)) => Ok(ServerFlavor::MempoolSpace),
+            _ => Ok(ServerFlavor::Esplora),
+        }
+    }
+
+    async fn tx_cpfp_info(&self, txid: Txid) -> Result<Option<CpfpInfo>, Error> {
+        let path = format!("/v1/cpfp/{}", txid);
+        let result = with_retry(RetryPolicy::default(), || {
+            get_opt_json::<CpfpResponse>(self, &path)
+        })
+        .await;
+        Ok(or_http_error(result)?.flatten().map(CpfpInfo::from))
+    }
+}
+
+/// `None` if the request of `result` failed with an HTTP error which retrying doesn't fix, which
+/// is how a server answers the requests it doesn't serve.
+fn or_http_error<T>(result: Result<T, Error>) -> Result<Option<T>, Error> {
+    match result {
+        Ok(response) => Ok(Some(response)),
+        Err(Error::Request { error, .. })
+            if matches!(*error, esplora_client::Error::HttpResponse { .. })
+                && !is_transient(&error) =>
+        {
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Fetch the headers of the anchor blocks of `graph` and verify them, see
+/// [`ScanOptions::verify_anchors`].
+async fn verify_anchors(
+    client: &esplora_client::AsyncClient,
+    graph: TxGraph<ConfirmationTimeHeightAnchor>,
+    options: &ScanOptions,
+) -> Result<TxGraph<ConfirmationTimeHeightAnchor>, Error> {
+    let parallel_requests = Ord::max(options.parallel_requests, 1);
+    let blocks = graph
+        .all_anchors()
+        .iter()
+        .map(|(anchor, _)| anchor.anchor_block)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let mut headers = BTreeMap::new();
+    for chunk in blocks.chunks(parallel_requests) {
+        let handles = chunk
+            .iter()
+            .map(|&block| async move {
+                with_retry(options.retry, || client.get_header_by_hash(&block.hash))
+                    .await
+                    .map(|header| (block, header))
+            })
+            .collect::<FuturesOrdered<_>>();
+        headers.extend(handles.try_collect::<Vec<_>>().await?);
+    }
+    crate::verify_anchors(graph, &headers)
 }
 
 /// Fetch latest blocks from Esplora in an atomic call.
@@ -139,9 +299,9 @@ impl EsploraAsyncExt for esplora_client::AsyncClient {
 /// alternating between chain-sources.
 async fn fetch_latest_blocks(
     client: &esplora_client::AsyncClient,
+    retry: RetryPolicy,
 ) -> Result<BTreeMap<u32, BlockHash>, Error> {
-    Ok(client
-        .get_blocks(None)
+    Ok(with_retry(retry, || client.get_blocks(None))
         .await?
         .into_iter()
         .map(|b| (b.time.height, b.id))
@@ -155,6 +315,7 @@ async fn fetch_block(
     client: &esplora_client::AsyncClient,
     latest_blocks: &BTreeMap<u32, BlockHash>,
     height: u32,
+    retry: RetryPolicy,
 ) -> Result<Option<BlockHash>, Error> {
     if let Some(&hash) = latest_blocks.get(&height) {
         return Ok(Some(hash));
@@ -170,7 +331,9 @@ async fn fetch_block(
         return Ok(None);
     }
 
-    Ok(Some(client.get_block_hash(height).await?))
+    Ok(Some(
+        with_retry(retry, || client.get_block_hash(height)).await?,
+    ))
 }
 
 /// Create the [`local_chain::Update`].
@@ -182,11 +345,13 @@ async fn chain_update<A: Anchor>(
     latest_blocks: &BTreeMap<u32, BlockHash>,
     local_tip: &CheckPoint,
     anchors: &BTreeSet<(A, Txid)>,
+    retry: RetryPolicy,
 ) -> Result<CheckPoint, Error> {
     let mut point_of_agreement = None;
     let mut conflicts = vec![];
     for local_cp in local_tip.iter() {
-        let remote_hash = match fetch_block(client, latest_blocks, local_cp.height()).await? {
+        let remote_hash = match fetch_block(client, latest_blocks, local_cp.height(), retry).await?
+        {
             Some(hash) => hash,
             None => continue,
         };
@@ -204,7 +369,10 @@ async fn chain_update<A: Anchor>(
         }
     }
 
-    let mut tip = point_of_agreement.expect("remote esplora should have same genesis block");
+    let mut tip = match point_of_agreement {
+        Some(tip) => tip,
+        None => return Err(genesis_mismatch(local_tip, &conflicts)),
+    };
 
     tip = tip
         .extend(conflicts.into_iter().rev())
@@ -213,7 +381,7 @@ async fn chain_update<A: Anchor>(
     for anchor in anchors {
         let height = anchor.0.anchor_block().height;
         if tip.get(height).is_none() {
-            let hash = match fetch_block(client, latest_blocks, height).await? {
+            let hash = match fetch_block(client, latest_blocks, height, retry).await? {
                 Some(hash) => hash,
                 None => continue,
             };
@@ -238,93 +406,123 @@ async fn full_scan_for_index_and_graph<K: Ord + Clone + Send>(
         K,
         impl IntoIterator<IntoIter = impl Iterator<Item = Indexed<ScriptBuf>> + Send> + Send,
     >,
+    stop_gaps: BTreeMap<K, usize>,
     stop_gap: usize,
-    parallel_requests: usize,
-) -> Result<(TxGraph<ConfirmationTimeHeightAnchor>, BTreeMap<K, u32>), Error> {
+    options: &ScanOptions,
+    progress: &mut ScanProgressTracker<K>,
+) -> Result<
+    (
+        TxGraph<ConfirmationTimeHeightAnchor>,
+        BTreeMap<K, ScanStats>,
+    ),
+    Error,
+> {
     type TxsOfSpkIndex = (u32, Vec<esplora_client::Tx>);
-    let parallel_requests = Ord::max(parallel_requests, 1);
+    let parallel_requests = Ord::max(options.parallel_requests, 1);
+    let batch_size = Ord::max(options.batch_size, 1);
     let mut graph = TxGraph::<ConfirmationTimeHeightAnchor>::default();
-    let mut last_active_indexes = BTreeMap::<K, u32>::new();
+    let mut scan_stats = BTreeMap::<K, ScanStats>::new();
+    let scan_stopwatch = Stopwatch::start();
 
     for (keychain, spks) in keychain_spks {
-        let mut spks = spks.into_iter();
-        let mut last_index = Option::<u32>::None;
+        let stopwatch = Stopwatch::start();
+        let stop_gap = stop_gaps.get(&keychain).copied().unwrap_or(stop_gap);
+        let mut stats = ScanStats::default();
+        let mut spks = spks.into_iter().enumerate();
+        let mut spks_exhausted = false;
+        // the requests in flight, each fetching the pages of one spk history one after another
+        let mut handles = FuturesUnordered::new();
+        // the histories which are fetched but not checked against the stop gap yet, by position
+        let mut fetched = BTreeMap::<usize, TxsOfSpkIndex>::new();
+        // the position of the next spk to check against the stop gap
+        let mut next_position = 0_usize;
         let mut last_active_index = Option::<u32>::None;
 
-        loop {
-            let handles = spks
-                .by_ref()
-                .take(parallel_requests)
-                .map(|(spk_index, spk)| {
-                    let client = client.clone();
-                    async move {
-                        let mut last_seen = None;
-                        let mut spk_txs = Vec::new();
-                        loop {
-                            let txs = client.scripthash_txs(&spk, last_seen).await?;
-                            let tx_count = txs.len();
-                            last_seen = txs.last().map(|tx| tx.txid);
-                            spk_txs.extend(txs);
-                            if tx_count < 25 {
-                                break Result::<_, Error>::Ok((spk_index, spk_txs));
-                            }
+        'scan: loop {
+            while !spks_exhausted
+                && handles.len() < parallel_requests
+                && handles.len() + fetched.len() < batch_size
+            {
+                let (position, (spk_index, spk)) = match spks.next() {
+                    Some(spk) => spk,
+                    None => {
+                        spks_exhausted = true;
+                        break;
+                    }
+                };
+                let client = client.clone();
+                handles.push(async move {
+                    let mut last_seen = None;
+                    let mut spk_txs = Vec::new();
+                    loop {
+                        let txs =
+                            with_retry(options.retry, || client.scripthash_txs(&spk, last_seen))
+                                .await?;
+                        let tx_count = txs.len();
+                        last_seen = txs.last().map(|tx| tx.txid);
+                        spk_txs.extend(txs);
+                        if tx_count < 25 {
+                            break Result::<_, Error>::Ok((position, (spk_index, spk_txs)));
                         }
                     }
-                })
-                .collect::<FuturesOrdered<_>>();
-
-            if handles.is_empty() {
-                break;
+                });
             }
 
-            for (index, txs) in handles.try_collect::<Vec<TxsOfSpkIndex>>().await? {
-                last_index = Some(index);
+            let (position, txs_of_spk) = match handles.try_next().await? {
+                Some(result) => result,
+                None => break,
+            };
+            fetched.insert(position, txs_of_spk);
+            let checked_before = next_position;
+
+            // check the spks in order, the ones after the stop gap are discarded
+            while let Some((index, txs)) = fetched.remove(&next_position) {
+                next_position += 1;
+                stats.spks_checked += 1;
+                stats.txs_fetched += txs.len();
+                progress.spk_checked(&keychain, txs.len());
                 if !txs.is_empty() {
                     last_active_index = Some(index);
                 }
                 for tx in txs {
-                    let _ = graph.insert_tx(tx.to_tx());
+                    let tx_body = tx.to_tx();
+                    if let Some(cache) = &options.tx_cache {
+                        cache.put(&tx_body);
+                    }
+                    let _ = graph.insert_tx(tx_body);
                     if let Some(anchor) = anchor_from_status(&tx.status) {
                         let _ = graph.insert_anchor(tx.txid, anchor);
                     }
 
-                    let previous_outputs = tx.vin.iter().filter_map(|vin| {
-                        let prevout = vin.prevout.as_ref()?;
-                        Some((
-                            OutPoint {
-                                txid: vin.txid,
-                                vout: vin.vout,
-                            },
-                            TxOut {
-                                script_pubkey: prevout.scriptpubkey.clone(),
-                                value: Amount::from_sat(prevout.value),
-                            },
-                        ))
-                    });
-
-                    for (outpoint, txout) in previous_outputs {
+                    for (outpoint, txout) in previous_outputs(&tx) {
                         let _ = graph.insert_txout(outpoint, txout);
                     }
                 }
-            }
 
-            let last_index = last_index.expect("Must be set since handles wasn't empty.");
-            let gap_limit_reached = if let Some(i) = last_active_index {
-                last_index >= i.saturating_add(stop_gap as u32)
-            } else {
-                last_index + 1 >= stop_gap as u32
-            };
-            if gap_limit_reached {
-                break;
+                let gap_limit_reached = if let Some(i) = last_active_index {
+                    index >= i.saturating_add(stop_gap as u32)
+                } else {
+                    index + 1 >= stop_gap as u32
+                };
+                if gap_limit_reached {
+                    progress.keychain_done(&keychain);
+                    progress.batch_done(scan_stopwatch.elapsed());
+                    break 'scan;
+                }
+            }
+            if next_position > checked_before {
+                progress.batch_done(scan_stopwatch.elapsed());
             }
         }
+        progress.keychain_done(&keychain);
 
-        if let Some(last_active_index) = last_active_index {
-            last_active_indexes.insert(keychain, last_active_index);
-        }
+        stats.last_active_index = last_active_index;
+        stats.elapsed = stopwatch.elapsed();
+        scan_stats.insert(keychain, stats);
     }
 
-    Ok((graph, last_active_indexes))
+    progress.finish(scan_stopwatch.elapsed());
+    Ok((graph, scan_stats))
 }
 
 async fn sync_for_index_and_graph(
@@ -332,8 +530,9 @@ async fn sync_for_index_and_graph(
     misc_spks: impl IntoIterator<IntoIter = impl Iterator<Item = ScriptBuf> + Send> + Send,
     txids: impl IntoIterator<IntoIter = impl Iterator<Item = Txid> + Send> + Send,
     outpoints: impl IntoIterator<IntoIter = impl Iterator<Item = OutPoint> + Send> + Send,
-    parallel_requests: usize,
+    options: &ScanOptions,
 ) -> Result<TxGraph<ConfirmationTimeHeightAnchor>, Error> {
+    let parallel_requests = Ord::max(options.parallel_requests, 1);
     let mut graph = full_scan_for_index_and_graph(
         client,
         [(
@@ -344,56 +543,91 @@ async fn sync_for_index_and_graph(
                 .map(|(i, spk)| (i as u32, spk)),
         )]
         .into(),
+        BTreeMap::new(),
         usize::MAX,
-        parallel_requests,
+        options,
+        &mut ScanProgressTracker::default(),
     )
     .await
     .map(|(g, _)| g)?;
 
-    let mut txids = txids.into_iter();
-    loop {
-        let handles = txids
-            .by_ref()
-            .take(parallel_requests)
-            .filter(|&txid| graph.get_tx(txid).is_none())
-            .map(|txid| {
+    // the txids which are not in the histories of the spks may have left the mempool
+    let txids = txids
+        .into_iter()
+        .filter(|&txid| graph.get_tx(txid).is_none())
+        .collect::<Vec<_>>();
+    let evicted_at = unix_time();
+    for chunk in txids.chunks(parallel_requests) {
+        let handles = chunk
+            .iter()
+            .map(|&txid| {
                 let client = client.clone();
-                async move { client.get_tx_status(&txid).await.map(|s| (txid, s)) }
+                async move {
+                    if let Some(tx_info) = fetch_tx_info(&client, txid, options).await {
+                        return Ok(match tx_info {
+                            Some(tx) => (
+                                txid,
+                                anchor_from_status(&tx.status),
+                                Some(tx.to_tx()),
+                                previous_outputs(&tx).collect(),
+                            ),
+                            None => (txid, None, None, Vec::new()),
+                        });
+                    }
+                    let status = with_retry(options.retry, || client.get_tx_status(&txid)).await?;
+                    let anchor = anchor_from_status(&status);
+                    // an unknown tx is reported as unconfirmed, fetching it tells whether it's
+                    // still in the mempool, so only confirmed txs are taken from the cache
+                    let tx = match anchor {
+                        Some(_) => options.tx_cache.as_ref().and_then(|cache| cache.get(txid)),
+                        None => {
+                            let tx = with_retry(options.retry, || client.get_tx(&txid)).await?;
+                            if let (Some(cache), Some(tx)) = (&options.tx_cache, &tx) {
+                                cache.put(tx);
+                            }
+                            tx
+                        }
+                    };
+                    Ok::<_, Error>((txid, anchor, tx, Vec::new()))
+                }
             })
             .collect::<FuturesOrdered<_>>();
 
-        if handles.is_empty() {
-            break;
-        }
-
-        for (txid, status) in handles.try_collect::<Vec<(Txid, TxStatus)>>().await? {
-            if let Some(anchor) = anchor_from_status(&status) {
-                let _ = graph.insert_anchor(txid, anchor);
+        for (txid, anchor, tx, previous_outputs) in handles.try_collect::<Vec<_>>().await? {
+            for (outpoint, txout) in previous_outputs {
+                let _ = graph.insert_txout(outpoint, txout);
+            }
+            match (anchor, tx, evicted_at) {
+                (Some(anchor), tx, _) => {
+                    if let Some(tx) = tx {
+                        let _ = graph.insert_tx(tx);
+                    }
+                    let _ = graph.insert_anchor(txid, anchor);
+                }
+                (None, Some(tx), _) => {
+                    let _ = graph.insert_tx(tx);
+                }
+                (None, None, Some(evicted_at)) => {
+                    let _ = graph.insert_evicted_at(txid, evicted_at);
+                }
+                (None, None, None) => {}
             }
         }
     }
 
     for op in outpoints.into_iter() {
         if graph.get_tx(op.txid).is_none() {
-            if let Some(tx) = client.get_tx(&op.txid).await? {
-                let _ = graph.insert_tx(tx);
-            }
-            let status = client.get_tx_status(&op.txid).await?;
-            if let Some(anchor) = anchor_from_status(&status) {
-                let _ = graph.insert_anchor(op.txid, anchor);
-            }
+            fetch_tx_and_status(client, op.txid, &mut graph, options).await?;
         }
 
-        if let Some(op_status) = client.get_output_status(&op.txid, op.vout as _).await? {
+        let op_status = with_retry(options.retry, || {
+            client.get_output_status(&op.txid, op.vout as _)
+        })
+        .await?;
+        if let Some(op_status) = op_status {
             if let Some(txid) = op_status.txid {
                 if graph.get_tx(txid).is_none() {
-                    if let Some(tx) = client.get_tx(&txid).await? {
-                        let _ = graph.insert_tx(tx);
-                    }
-                    let status = client.get_tx_status(&txid).await?;
-                    if let Some(anchor) = anchor_from_status(&status) {
-                        let _ = graph.insert_anchor(txid, anchor);
-                    }
+                    fetch_tx_and_status(client, txid, &mut graph, options).await?;
                 }
             }
         }
@@ -402,19 +636,174 @@ async fn sync_for_index_and_graph(
     Ok(graph)
 }
 
+/// Fetch the transaction `txid` and its confirmation status into `graph`, with its previous
+/// outputs if the server is a mempool.space one.
+async fn fetch_tx_and_status(
+    client: &esplora_client::AsyncClient,
+    txid: Txid,
+    graph: &mut TxGraph<ConfirmationTimeHeightAnchor>,
+    options: &ScanOptions,
+) -> Result<(), Error> {
+    if let Some(tx_info) = fetch_tx_info(client, txid, options).await {
+        if let Some(tx) = tx_info {
+            let _ = graph.insert_tx(tx.to_tx());
+            if let Some(anchor) = anchor_from_status(&tx.status) {
+                let _ = graph.insert_anchor(txid, anchor);
+            }
+            for (outpoint, txout) in previous_outputs(&tx) {
+                let _ = graph.insert_txout(outpoint, txout);
+            }
+        }
+        return Ok(());
+    }
+    if let Some(tx) = fetch_tx(client, txid, options).await? {
+        let _ = graph.insert_tx(tx);
+    }
+    let status = with_retry(options.retry, || client.get_tx_status(&txid)).await?;
+    if let Some(anchor) = anchor_from_status(&status) {
+        let _ = graph.insert_anchor(txid, anchor);
+    }
+    Ok(())
+}
+
+/// Fetch the transaction `txid` with its confirmation status and previous outputs in one request
+/// with `GET /tx/:txid`, if [`ScanOptions::server_flavor`] is [`ServerFlavor::MempoolSpace`]. The
+/// transaction is put in the [`ScanOptions::tx_cache`].
+///
+/// Returns `None` for the Esplora servers and if the request fails, for the caller to fall back
+/// to the requests of Esplora, and `Some(None)` if the server doesn't know the transaction.
+async fn fetch_tx_info(
+    client: &esplora_client::AsyncClient,
+    txid: Txid,
+    options: &ScanOptions,
+) -> Option<Option<esplora_client::Tx>> {
+    if options.server_flavor != ServerFlavor::MempoolSpace {
+        return None;
+    }
+    let path = format!("/tx/{}", txid);
+    let tx = with_retry(options.retry, || {
+        get_opt_json::<esplora_client::Tx>(client, &path)
+    })
+    .await
+    .ok()?;
+    if let (Some(cache), Some(tx)) = (&options.tx_cache, &tx) {
+        cache.put(&tx.to_tx());
+    }
+    Some(tx)
+}
+
+/// Fetch the transaction `txid`, taking it from the [`ScanOptions::tx_cache`] if it's there.
+async fn fetch_tx(
+    client: &esplora_client::AsyncClient,
+    txid: Txid,
+    options: &ScanOptions,
+) -> Result<Option<Transaction>, Error> {
+    if let Some(tx) = options.tx_cache.as_ref().and_then(|cache| cache.get(txid)) {
+        return Ok(Some(tx));
+    }
+    let tx = with_retry(options.retry, || client.get_tx(&txid)).await?;
+    if let (Some(cache), Some(tx)) = (&options.tx_cache, &tx) {
+        cache.put(tx);
+    }
+    Ok(tx)
+}
+
+/// Make the request built by `request`, retrying it according to `retry`.
+async fn with_retry<T, F, Fut>(retry: RetryPolicy, mut request: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, esplora_client::Error>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let error = match request().await {
+            Ok(response) => return Ok(response),
+            Err(error) => error,
+        };
+        match retry.retry_delay(attempts, &error) {
+            Some(delay) => sleep(delay).await,
+            None => {
+                return Err(Error::Request {
+                    error: Box::new(error),
+                    attempts,
+                })
+            }
+        }
+    }
+}
+
+/// The futures of an [`AsyncSyncBackend`](bdk_chain::spk_client::AsyncSyncBackend) are `Send`,
+/// which the requests of an [`esplora_client::AsyncClient`] aren't on wasm.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+impl<K: Ord + Clone + Send> bdk_chain::spk_client::AsyncSyncBackend<K>
+    for EsploraBackend<esplora_client::AsyncClient>
+{
+    fn full_scan<'a>(
+        &'a self,
+        request: FullScanRequest<K>,
+        options: bdk_chain::spk_client::BackendOptions,
+    ) -> bdk_chain::spk_client::BackendFuture<'a, FullScanResult<K>>
+    where
+        K: 'a,
+    {
+        Box::pin(async move {
+            let scan_options = self.scan_options(options);
+            let res =
+                EsploraAsyncExt::full_scan(&self.client, request, options.stop_gap, scan_options)
+                    .await?;
+            Ok(res)
+        })
+    }
+
+    fn sync<'a>(
+        &'a self,
+        request: SyncRequest,
+        options: bdk_chain::spk_client::BackendOptions,
+    ) -> bdk_chain::spk_client::BackendFuture<'a, SyncResult> {
+        Box::pin(async move {
+            let res =
+                EsploraAsyncExt::sync(&self.client, request, self.scan_options(options)).await?;
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn sleep(delay: Duration) {
+    tokio::time::sleep(delay).await
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn sleep(_delay: Duration) {}
+
 #[cfg(test)]
-mod test {
-    use std::{collections::BTreeSet, time::Duration};
+pub(crate) mod test {
+    use std::collections::{BTreeSet, HashMap};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     use bdk_chain::{
-        bitcoin::{hashes::Hash, Txid},
+        bitcoin::{
+            absolute,
+            block::{Header, Version},
+            consensus::encode::serialize_hex,
+            hashes::{sha256, Hash},
+            transaction, Amount, BlockHash, CompactTarget, OutPoint, ScriptBuf, Transaction, TxIn,
+            TxMerkleNode, TxOut, Txid,
+        },
         local_chain::LocalChain,
+        spk_client::{FullScanRequest, ScanProgress, SyncRequest},
         BlockId,
     };
     use bdk_testenv::{anyhow, bitcoincore_rpc::RpcApi, TestEnv};
     use esplora_client::Builder;
 
     use crate::async_ext::{chain_update, fetch_latest_blocks};
+    use crate::{Error, EsploraAsyncExt, MemoryTxCache, RetryPolicy, ScanOptions, ServerFlavor};
 
     macro_rules! h {
         ($index:literal) => {{
@@ -499,9 +888,10 @@ mod test {
                     .collect::<anyhow::Result<BTreeSet<_>>>()?;
                 let update = chain_update(
                     &client,
-                    &fetch_latest_blocks(&client).await?,
+                    &fetch_latest_blocks(&client, RetryPolicy::none()).await?,
                     &chain.tip(),
                     &anchors,
+                    RetryPolicy::none(),
                 )
                 .await?;
                 chain.apply_update(update)?;
@@ -537,9 +927,10 @@ mod test {
                     .collect::<anyhow::Result<_>>()?;
                 chain_update(
                     &client,
-                    &fetch_latest_blocks(&client).await?,
+                    &fetch_latest_blocks(&client, RetryPolicy::none()).await?,
                     &local_chain.tip(),
                     &anchors,
+                    RetryPolicy::none(),
                 )
                 .await?
             };
@@ -587,4 +978,681 @@ mod test {
 
         Ok(())
     }
+
+    /// A regtest block header after `prev_blockhash`, with a valid proof of work.
+    fn mine_header(prev_blockhash: BlockHash, time: u32) -> Header {
+        let mut header = Header {
+            version: Version::ONE,
+            prev_blockhash,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits: CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    /// A mock Esplora server which serves a chain of two blocks, fee estimates and scripts without
+    /// history, and knows no other transaction, recording the requests, the script histories and
+    /// transactions requested and the maximum number of requests in flight.
+    ///
+    /// The first `rate_limited` script history requests are answered with a 429. The server claims
+    /// there is a block at height 2, but serves the genesis header for its hash.
+    struct MockServer {
+        url: String,
+        genesis_hash: BlockHash,
+        headers: [Header; 2],
+        histories: Arc<Mutex<HashMap<String, String>>>,
+        requests: Arc<Mutex<Vec<String>>>,
+        scripthash_requests: Arc<Mutex<Vec<String>>>,
+        tx_requests: Arc<Mutex<Vec<String>>>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl MockServer {
+        fn start(rate_limited: usize) -> std::io::Result<Self> {
+            Self::start_with_histories(rate_limited, &[])
+        }
+
+        /// Like [`MockServer::start`], but the scripts of `active_spks` have a history of one
+        /// unconfirmed transaction each.
+        fn start_with_histories(
+            rate_limited: usize,
+            active_spks: &[ScriptBuf],
+        ) -> std::io::Result<Self> {
+            let histories = active_spks
+                .iter()
+                .map(|spk| {
+                    let path = format!("/scripthash/{:x}/txs", sha256::Hash::hash(spk.as_bytes()));
+                    let txid: Txid = Hash::hash(spk.as_bytes());
+                    let history = format!(
+                        r#"[{{"txid":"{}","version":2,"locktime":0,"vin":[{{"txid":"{}","vout":0,"prevout":null,"scriptsig":"","witness":[],"sequence":4294967295,"is_coinbase":false}}],"vout":[{{"value":10000,"scriptpubkey":"{}"}}],"status":{{"confirmed":false}},"fee":200}}]"#,
+                        txid,
+                        Txid::all_zeros(),
+                        spk.to_hex_string()
+                    );
+                    (path, history)
+                })
+                .collect::<HashMap<_, _>>();
+            let histories = Arc::new(Mutex::new(histories));
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let url = format!("http://{}", listener.local_addr()?);
+            let genesis = mine_header(BlockHash::all_zeros(), 1_296_688_602);
+            let genesis_hash = genesis.block_hash();
+            let tip = mine_header(genesis_hash, 1_296_688_928);
+            let tip_hash = tip.block_hash();
+            let stale_hash: BlockHash = h!("block 2");
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let scripthash_requests = Arc::new(Mutex::new(Vec::new()));
+            let tx_requests = Arc::new(Mutex::new(Vec::new()));
+            let max_in_flight = Arc::new(AtomicUsize::new(0));
+            let in_flight = Arc::new(AtomicUsize::new(0));
+            let rate_limited = Arc::new(AtomicUsize::new(rate_limited));
+            let blocks = format!(
+                r#"[{{"id":"{}","height":1,"timestamp":0,"previousblockhash":"{}","merkle_root":"{}"}}]"#,
+                tip_hash,
+                genesis_hash,
+                TxMerkleNode::all_zeros()
+            );
+
+            let server = Self {
+                url,
+                genesis_hash,
+                headers: [genesis, tip],
+                histories: histories.clone(),
+                requests: requests.clone(),
+                scripthash_requests: scripthash_requests.clone(),
+                tx_requests: tx_requests.clone(),
+                max_in_flight: max_in_flight.clone(),
+            };
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => break,
+                    };
+                    let blocks = blocks.clone();
+                    let requests = requests.clone();
+                    let scripthash_requests = scripthash_requests.clone();
+                    let tx_requests = tx_requests.clone();
+                    let max_in_flight = max_in_flight.clone();
+                    let in_flight = in_flight.clone();
+                    let rate_limited = rate_limited.clone();
+                    let histories = histories.clone();
+                    std::thread::spawn(move || {
+                        let mut reader = BufReader::new(stream.try_clone().expect("must clone"));
+                        let mut stream = stream;
+                        loop {
+                            let mut request_line = String::new();
+                            match reader.read_line(&mut request_line) {
+                                Ok(0) | Err(_) => break,
+                                Ok(_) => {}
+                            }
+                            // skip the headers, the requests have no body
+                            loop {
+                                let mut header = String::new();
+                                match reader.read_line(&mut header) {
+                                    Ok(0) | Err(_) => return,
+                                    Ok(_) if header == "\r\n" => break,
+                                    Ok(_) => {}
+                                }
+                            }
+                            let path = request_line
+                                .split_whitespace()
+                                .nth(1)
+                                .unwrap_or_default()
+                                .to_string();
+                            requests.lock().unwrap().push(path.clone());
+
+                            let now_in_flight = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                            max_in_flight.fetch_max(now_in_flight, Ordering::SeqCst);
+                            let mut status = "200 OK";
+                            let body = if path == "/blocks" {
+                                blocks.clone()
+                            } else if path == "/block-height/0" {
+                                genesis_hash.to_string()
+                            } else if path == "/block-height/1" {
+                                tip_hash.to_string()
+                            } else if path == "/block-height/2" {
+                                stale_hash.to_string()
+                            } else if path == format!("/block/{}/header", tip_hash) {
+                                serialize_hex(&tip)
+                            } else if path.starts_with("/block/") && path.ends_with("/header") {
+                                serialize_hex(&genesis)
+                            } else if path.starts_with("/tx/") && path.ends_with("/status") {
+                                // as esplora, an unknown tx is reported as unconfirmed
+                                r#"{"confirmed":false}"#.to_string()
+                            } else if path.starts_with("/tx/") && path.ends_with("/raw") {
+                                tx_requests.lock().unwrap().push(path);
+                                status = "404 Not Found";
+                                "Transaction not found".to_string()
+                            } else if path.starts_with("/tx/") && path.contains("/outspend/") {
+                                r#"{"spent":false}"#.to_string()
+                            } else if path == "/fee-estimates" {
+                                r#"{"1":87.882,"6":68.285,"144":0.5}"#.to_string()
+                            } else if path.starts_with("/scripthash/")
+                                && rate_limited
+                                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                                        n.checked_sub(1)
+                                    })
+                                    .is_ok()
+                            {
+                                status = "429 Too Many Requests";
+                                "rate limited".to_string()
+                            } else if path.starts_with("/scripthash/") {
+                                let history = histories
+                                    .lock()
+                                    .unwrap()
+                                    .get(&path)
+                                    .cloned()
+                                    .unwrap_or_else(|| "[]".into());
+                                scripthash_requests.lock().unwrap().push(path);
+                                // give the other requests the time to arrive
+                                std::thread::sleep(Duration::from_millis(50));
+                                history
+                            } else {
+                                panic!("unexpected request {}", path);
+                            };
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                            let response = format!(
+                                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                                status,
+                                body.len(),
+                                body
+                            );
+                            if stream.write_all(response.as_bytes()).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+            });
+            Ok(server)
+        }
+
+        /// Give `spk` a history of `count` transactions confirmed in the tip, served in pages of
+        /// 25 like Esplora, returning their txids.
+        fn insert_confirmed_history(&self, spk: &ScriptBuf, count: u32) -> Vec<Txid> {
+            let tip_hash = self.headers[1].block_hash();
+            let path = format!("/scripthash/{:x}/txs", sha256::Hash::hash(spk.as_bytes()));
+            let txs = (0..count)
+                .map(|i| Transaction {
+                    version: transaction::Version::TWO,
+                    lock_time: absolute::LockTime::from_consensus(i),
+                    input: vec![TxIn {
+                        previous_output: OutPoint::new(Txid::all_zeros(), i),
+                        ..Default::default()
+                    }],
+                    output: vec![TxOut {
+                        value: Amount::from_sat(10_000),
+                        script_pubkey: spk.clone(),
+                    }],
+                })
+                .collect::<Vec<_>>();
+            let mut histories = self.histories.lock().unwrap();
+            let mut page_path = path.clone();
+            for page in txs.chunks(25) {
+                let page_json = page
+                    .iter()
+                    .map(|tx| {
+                        format!(
+                            r#"{{"txid":"{}","version":2,"locktime":{},"vin":[{{"txid":"{}","vout":{},"prevout":null,"scriptsig":"","witness":[],"sequence":{},"is_coinbase":false}}],"vout":[{{"value":10000,"scriptpubkey":"{}"}}],"status":{{"confirmed":true,"block_height":1,"block_hash":"{}","block_time":1296688928}},"fee":200}}"#,
+                            tx.compute_txid(),
+                            tx.lock_time.to_consensus_u32(),
+                            tx.input[0].previous_output.txid,
+                            tx.input[0].previous_output.vout,
+                            tx.input[0].sequence.to_consensus_u32(),
+                            spk.to_hex_string(),
+                            tip_hash
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                histories.insert(page_path, format!("[{}]", page_json.join(",")));
+                let last_seen = page.last().expect("pages aren't empty").compute_txid();
+                page_path = format!("{}/chain/{}", path, last_seen);
+            }
+            txs.iter().map(Transaction::compute_txid).collect()
+        }
+
+        /// The requests of single transactions, `/tx/:txid` and its sub-paths
+        fn per_tx_requests(&self) -> Vec<String> {
+            let requests = self.requests.lock().unwrap();
+            requests
+                .iter()
+                .filter(|path| path.starts_with("/tx/"))
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// The transactions of a long history come with its pages, `full_scan` and `sync` make no
+    /// request per transaction for them.
+    #[tokio::test]
+    pub async fn test_long_history_request_count() -> anyhow::Result<()> {
+        let server = MockServer::start(0)?;
+        let client = Builder::new(&server.url).build_async()?;
+        let (chain, _) = LocalChain::from_genesis_hash(server.genesis_hash);
+        let spk = ScriptBuf::from_bytes(vec![0x51]);
+        let txids = server.insert_confirmed_history(&spk, 500);
+        let options = ScanOptions {
+            retry: RetryPolicy::none(),
+            ..Default::default()
+        };
+
+        let request = FullScanRequest::from_chain_tip(chain.tip())
+            .set_spks_for_keychain(0, [(0_u32, spk.clone())]);
+        let update = client.full_scan(request, 1, options.clone()).await?;
+        assert_eq!(update.graph_update.full_txs().count(), 500);
+        // 20 full pages and the empty one after
+        assert_eq!(server.scripthash_requests.lock().unwrap().len(), 21);
+        assert_eq!(server.per_tx_requests(), Vec::<String>::new());
+
+        // only the txid which isn't in the history is requested on its own
+        server.scripthash_requests.lock().unwrap().clear();
+        let unknown_txid: Txid = h!("unknown");
+        let mut txids = txids;
+        txids.push(unknown_txid);
+        let request = SyncRequest::from_chain_tip(chain.tip())
+            .chain_spks([spk])
+            .chain_txids(txids);
+        let update = client.sync(request, options).await?;
+        assert_eq!(update.graph_update.full_txs().count(), 500);
+        assert_eq!(server.scripthash_requests.lock().unwrap().len(), 21);
+        assert_eq!(
+            server.per_tx_requests(),
+            [
+                format!("/tx/{}/status", unknown_txid),
+                format!("/tx/{}/raw", unknown_txid),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_full_scan_scan_options() -> anyhow::Result<()> {
+        let stop_gap = 10;
+        for (parallel_requests, batch_size) in [(1, 20), (3, 20), (5, 2), (4, 100)] {
+            let server = MockServer::start(0)?;
+            let client = Builder::new(&server.url).build_async()?;
+            let (chain, _) = LocalChain::from_genesis_hash(server.genesis_hash);
+            let spks = (0..100_u32).map(|i| (i, ScriptBuf::from_bytes(vec![i as u8])));
+            let request =
+                FullScanRequest::from_chain_tip(chain.tip()).set_spks_for_keychain(0, spks);
+            let options = ScanOptions {
+                parallel_requests,
+                batch_size,
+                retry: RetryPolicy::none(),
+                verify_anchors: false,
+                tx_cache: None,
+                server_flavor: ServerFlavor::Esplora,
+            };
+
+            let update = client.full_scan(request, stop_gap, options).await?;
+            assert!(update.last_active_indices.is_empty());
+            assert!(update.graph_update.full_txs().next().is_none());
+
+            assert_eq!(
+                server.max_in_flight.load(Ordering::SeqCst),
+                Ord::min(parallel_requests, batch_size),
+                "parallel_requests: {}, batch_size: {}",
+                parallel_requests,
+                batch_size,
+            );
+            // every spk up to the stop gap is requested exactly once, up to `batch_size - 1` spks
+            // after it may have been requested in advance
+            let requests = server.scripthash_requests.lock().unwrap().clone();
+            let unique_requests = requests.iter().collect::<BTreeSet<_>>();
+            assert_eq!(unique_requests.len(), requests.len());
+            assert!(requests.len() >= stop_gap);
+            assert!(requests.len() < stop_gap + batch_size);
+            if parallel_requests == 1 {
+                assert_eq!(requests.len(), stop_gap);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_full_scan_retries_rate_limited_requests() -> anyhow::Result<()> {
+        let stop_gap = 3;
+        let spks = || (0..10_u32).map(|i| (i, ScriptBuf::from_bytes(vec![i as u8])));
+        let options = |max_retries| ScanOptions {
+            parallel_requests: 1,
+            batch_size: 1,
+            retry: RetryPolicy {
+                max_retries,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(10),
+                jitter: false,
+            },
+            verify_anchors: false,
+            tx_cache: None,
+            server_flavor: ServerFlavor::Esplora,
+        };
+
+        // the two 429s are retried and the scan completes
+        let server = MockServer::start(2)?;
+        let client = Builder::new(&server.url).build_async()?;
+        let (chain, _) = LocalChain::from_genesis_hash(server.genesis_hash);
+        let request = FullScanRequest::from_chain_tip(chain.tip()).set_spks_for_keychain(0, spks());
+        let update = client.full_scan(request, stop_gap, options(5)).await?;
+        assert!(update.last_active_indices.is_empty());
+        let requests = server.scripthash_requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), stop_gap);
+
+        // out of retries, the error records the number of attempts
+        let server = MockServer::start(2)?;
+        let client = Builder::new(&server.url).build_async()?;
+        let request = FullScanRequest::from_chain_tip(chain.tip()).set_spks_for_keychain(0, spks());
+        match client.full_scan(request, stop_gap, options(1)).await {
+            Err(Error::Request { error, attempts }) => {
+                assert_eq!(attempts, 2);
+                assert!(matches!(
+                    *error,
+                    esplora_client::Error::HttpResponse { status: 429, .. }
+                ));
+            }
+            _ => panic!("must run out of retries"),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_fee_estimates() -> anyhow::Result<()> {
+        let server = MockServer::start(0)?;
+        let client = Builder::new(&server.url).build_async()?;
+        let estimates = client.fee_estimates(RetryPolicy::none()).await?;
+        let sat_per_kwu = estimates
+            .0
+            .iter()
+            .map(|(target, fee_rate)| (*target, fee_rate.to_sat_per_kwu()))
+            .collect::<Vec<_>>();
+        assert_eq!(sat_per_kwu, [(1, 21971), (6, 17072), (144, 250)]);
+        assert_eq!(estimates.for_target(3), estimates.0.get(&1).copied());
+        Ok(())
+    }
+
+    /// A SOCKS5 proxy without authentication, returning its URL and the number of connections it
+    /// forwarded.
+    pub(crate) fn start_socks5_proxy() -> std::io::Result<(String, Arc<AtomicUsize>)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("socks5h://{}", listener.local_addr()?);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let forwarded = connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut client = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                forwarded.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || -> std::io::Result<()> {
+                    // greeting: version, number of methods and methods, answered with "no auth"
+                    let mut greeting = [0_u8; 2];
+                    client.read_exact(&mut greeting)?;
+                    client.read_exact(&mut vec![0_u8; greeting[1] as usize])?;
+                    client.write_all(&[5, 0])?;
+                    // request: version, command, reserved, address type, address and port
+                    let mut request = [0_u8; 4];
+                    client.read_exact(&mut request)?;
+                    let host = match request[3] {
+                        1 => {
+                            let mut ip = [0_u8; 4];
+                            client.read_exact(&mut ip)?;
+                            Ipv4Addr::from(ip).to_string()
+                        }
+                        3 => {
+                            let mut len = [0_u8; 1];
+                            client.read_exact(&mut len)?;
+                            let mut name = vec![0_u8; len[0] as usize];
+                            client.read_exact(&mut name)?;
+                            String::from_utf8_lossy(&name).into_owned()
+                        }
+                        _ => return Ok(()),
+                    };
+                    let mut port = [0_u8; 2];
+                    client.read_exact(&mut port)?;
+                    let mut server = TcpStream::connect((host.as_str(), u16::from_be_bytes(port)))?;
+                    client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+
+                    let mut client_read = client.try_clone()?;
+                    let mut server_write = server.try_clone()?;
+                    std::thread::spawn(move || std::io::copy(&mut client_read, &mut server_write));
+                    std::io::copy(&mut server, &mut client)?;
+                    Ok(())
+                });
+            }
+        });
+        Ok((url, connections))
+    }
+
+    #[tokio::test]
+    pub async fn test_socks5_proxy() -> anyhow::Result<()> {
+        let server = MockServer::start(0)?;
+        let (proxy, connections) = start_socks5_proxy()?;
+        let client = Builder::new(&server.url)
+            .proxy(&proxy)
+            .timeout(10)
+            .build_async()?;
+
+        let estimates = client.fee_estimates(RetryPolicy::none()).await?;
+        assert_eq!(estimates.0.len(), 3);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_sync_reports_evicted_txs() -> anyhow::Result<()> {
+        let server = MockServer::start(0)?;
+        let client = Builder::new(&server.url).build_async()?;
+        let (chain, _) = LocalChain::from_genesis_hash(server.genesis_hash);
+        let txid: Txid = h!("replaced");
+        let request = SyncRequest::from_chain_tip(chain.tip())
+            .set_spks([ScriptBuf::from_bytes(vec![1])])
+            .set_txids([txid]);
+
+        let update = client.sync(request, ScanOptions::default()).await?;
+        assert!(update.graph_update.get_tx(txid).is_none());
+        assert!(update.graph_update.last_evicted(txid).is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_get_headers() -> anyhow::Result<()> {
+        let server = MockServer::start(0)?;
+        let client = Builder::new(&server.url).build_async()?;
+        let headers = client.get_headers(&[1, 0]).await?;
+        assert_eq!(headers, [server.headers[1], server.headers[0]]);
+
+        // the header served for the block at height 2 doesn't hash to its hash
+        match client.get_headers(&[0, 2]).await {
+            Err(Error::InvalidHeader { height, hash }) => {
+                assert_eq!(height, 2);
+                assert_eq!(hash, h!("block 2"));
+            }
+            _ => panic!("the header must be rejected"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_full_scan_stop_gap_per_keychain() -> anyhow::Result<()> {
+        let spk = |keychain: u8, index: u32| ScriptBuf::from_bytes(vec![keychain, index as u8]);
+        let spks = |keychain| (0..20_u32).map(move |i| (i, spk(keychain, i)));
+        // keychain 0 is active at index 4 with a stop gap of 5, the activity of keychain 1 sits
+        // right after its stop gap of 3 and the one of keychain 2 at the end of the default one
+        let server = MockServer::start_with_histories(0, &[spk(0, 4), spk(1, 3), spk(2, 2)])?;
+        let client = Builder::new(&server.url).build_async()?;
+        let (chain, _) = LocalChain::from_genesis_hash(server.genesis_hash);
+        let request = FullScanRequest::from_chain_tip(chain.tip())
+            .set_spks_for_keychain(0, spks(0))
+            .set_spks_for_keychain(1, spks(1))
+            .set_spks_for_keychain(2, spks(2))
+            .set_stop_gap_for_keychain(0, 5)
+            .set_stop_gap_for_keychain(1, 3);
+        let options = ScanOptions {
+            retry: RetryPolicy::none(),
+            ..Default::default()
+        };
+
+        let update = client.full_scan(request, 3, options).await?;
+        assert_eq!(update.last_active_indices, [(0, 4), (2, 2)].into());
+        assert_eq!(update.graph_update.full_txs().count(), 2);
+        let stats = update
+            .scan_stats
+            .iter()
+            .map(|(keychain, stats)| {
+                (
+                    *keychain,
+                    stats.spks_checked,
+                    stats.last_active_index,
+                    stats.txs_fetched,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stats,
+            [(0, 10, Some(4), 1), (1, 3, None, 0), (2, 6, Some(2), 1)]
+        );
+        assert!(update
+            .scan_stats
+            .values()
+            .all(|stats| !stats.elapsed.is_zero()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_full_scan_progress() -> anyhow::Result<()> {
+        let spk = |keychain: u8, index: u32| ScriptBuf::from_bytes(vec![keychain, index as u8]);
+        let spks = |keychain| (0..30_u32).map(move |i| (i, spk(keychain, i)));
+        // each script history takes the mock server 50ms to serve
+        let server = MockServer::start_with_histories(0, &[spk(0, 4), spk(1, 2)])?;
+        let client = Builder::new(&server.url).build_async()?;
+        let (chain, _) = LocalChain::from_genesis_hash(server.genesis_hash);
+        let events = Arc::new(Mutex::new(Vec::<ScanProgress<u8>>::new()));
+        let request = FullScanRequest::from_chain_tip(chain.tip())
+            .set_spks_for_keychain(0, spks(0))
+            .set_spks_for_keychain(1, spks(1))
+            .on_progress({
+                let events = events.clone();
+                move |progress| events.lock().unwrap().push(progress.clone())
+            });
+        let options = ScanOptions {
+            retry: RetryPolicy::none(),
+            parallel_requests: 2,
+            batch_size: 2,
+            ..Default::default()
+        };
+
+        let update = client.full_scan(request, 5, options).await?;
+        let events = events.lock().unwrap();
+        assert!(events.len() > 5, "a progress per batch");
+        for (before, after) in events.iter().zip(events.iter().skip(1)) {
+            assert!(after.batch >= before.batch);
+            assert!(after.elapsed >= before.elapsed);
+            assert!(after.txs_fetched >= before.txs_fetched);
+            for (keychain, progress) in &after.keychains {
+                let previous = &before.keychains[keychain];
+                assert!(progress.spks_checked >= previous.spks_checked);
+                assert!(progress.done || !previous.done);
+            }
+            assert!(after.spks_checked() > before.spks_checked() || after.batch == before.batch);
+        }
+
+        // before the activity is found, the total of a keychain is its stop gap
+        let first = &events[0];
+        assert!(first.total_is_estimate);
+        assert_eq!(first.keychains[&1].spks_total, 5);
+        // the throughput of the batches gives an eta while the scan goes on
+        assert!(events
+            .iter()
+            .any(|progress| progress.total_is_estimate && progress.eta > Some(Duration::ZERO)));
+
+        let last = events.last().expect("must have a progress");
+        assert!(!last.total_is_estimate);
+        assert_eq!(last.eta, Some(Duration::ZERO));
+        assert_eq!(last.txs_fetched, 2);
+        for (keychain, stats) in &update.scan_stats {
+            let progress = &last.keychains[keychain];
+            assert!(progress.done);
+            assert_eq!(progress.spks_checked, stats.spks_checked);
+            assert_eq!(progress.spks_total, stats.spks_checked);
+        }
+        assert_eq!(last.keychains[&0].spks_checked, 10);
+        assert_eq!(last.keychains[&1].spks_checked, 8);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_full_scan_genesis_mismatch() -> anyhow::Result<()> {
+        let server = MockServer::start(0)?;
+        let client = Builder::new(&server.url).build_async()?;
+        let other_genesis: BlockHash = h!("other genesis");
+        let (chain, _) = LocalChain::from_genesis_hash(other_genesis);
+        let request = FullScanRequest::from_chain_tip(chain.tip())
+            .set_spks_for_keychain(0, [(0, ScriptBuf::from_bytes(vec![1]))]);
+        let options = ScanOptions {
+            retry: RetryPolicy::none(),
+            ..Default::default()
+        };
+        match client.full_scan(request, 3, options).await {
+            Err(Error::GenesisMismatch { expected, got }) => {
+                assert_eq!(expected, other_genesis);
+                assert_eq!(got, server.genesis_hash);
+            }
+            _ => panic!("the full scan must fail"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_tx_cache() -> anyhow::Result<()> {
+        let spk = ScriptBuf::from_bytes(vec![1]);
+        let server = MockServer::start_with_histories(0, std::slice::from_ref(&spk))?;
+        let client = Builder::new(&server.url).build_async()?;
+        let (chain, _) = LocalChain::from_genesis_hash(server.genesis_hash);
+        let cache = MemoryTxCache::new();
+        let options = ScanOptions {
+            retry: RetryPolicy::none(),
+            tx_cache: Some(Arc::new(cache.clone())),
+            ..Default::default()
+        };
+
+        // the full scan gets the transactions with the histories and caches them
+        let mut txids = BTreeSet::new();
+        for _ in 0..2 {
+            let request = FullScanRequest::from_chain_tip(chain.tip())
+                .set_spks_for_keychain(0, [(0, spk.clone())]);
+            let update = client.full_scan(request, 3, options.clone()).await?;
+            txids.extend(update.graph_update.full_txs().map(|tx| tx.txid));
+        }
+        assert_eq!(txids.len(), 1);
+        assert_eq!(cache.len(), 1);
+        assert!(server.tx_requests.lock().unwrap().is_empty());
+
+        // the server knows no transaction, the sync of an outpoint gets it from the cache
+        let txid = txids.into_iter().next().expect("must have a tx");
+        let outpoint = OutPoint::new(txid, 0);
+        let request = SyncRequest::from_chain_tip(chain.tip()).chain_outpoints([outpoint]);
+        let update = client.sync(request, options).await?;
+        assert!(update.graph_update.get_tx(txid).is_some());
+        assert!(server.tx_requests.lock().unwrap().is_empty());
+
+        // without a cache, the transaction is requested
+        let request = SyncRequest::from_chain_tip(chain.tip()).chain_outpoints([outpoint]);
+        let options = ScanOptions {
+            retry: RetryPolicy::none(),
+            ..Default::default()
+        };
+        let update = client.sync(request, options).await?;
+        assert!(update.graph_update.get_tx(txid).is_none());
+        assert_eq!(server.tx_requests.lock().unwrap().len(), 1);
+        Ok(())
+    }
 }
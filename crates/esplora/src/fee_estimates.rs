@@ -0,0 +1,156 @@
+//! Typed fee estimates of the Esplora `/fee-estimates` endpoint
+
+use bdk_chain::bitcoin::FeeRate;
+use bdk_chain::collections::BTreeMap;
+
+/// Fee rates estimated by the Esplora server, by confirmation target in blocks
+///
+/// Returned by [`EsploraExt::fee_estimates`] and [`EsploraAsyncExt::fee_estimates`]. The fee rates
+/// can be passed to `TxBuilder::fee_rate` of `bdk_wallet` directly:
+///
+/// ```ignore
+/// let fee_rate = client
+///     .fee_estimates(RetryPolicy::default())?
+///     .for_target(6)
+///     .unwrap_or(FeeRate::BROADCAST_MIN);
+/// tx_builder.fee_rate(fee_rate);
+/// ```
+///
+/// [`EsploraExt::fee_estimates`]: crate::EsploraExt::fee_estimates
+/// [`EsploraAsyncExt::fee_estimates`]: crate::EsploraAsyncExt::fee_estimates
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeeEstimates(pub BTreeMap<u16, FeeRate>);
+
+impl FeeEstimates {
+    /// Convert the estimates returned by Esplora, in sat/vB.
+    ///
+    /// A [`FeeRate`] is stored in sat/kwu, one sat/vB being 250 sat/kwu. The fee rates are rounded
+    /// up to the next sat/kwu, so that the fee paid is never below the estimate. Fee rates below 1
+    /// sat/vB, as well as negative and NaN ones, are raised to [`FeeRate::BROADCAST_MIN`]:
+    /// transactions paying less are not relayed by default.
+    pub fn from_sat_per_vb(estimates: impl IntoIterator<Item = (u16, f64)>) -> Self {
+        Self(
+            estimates
+                .into_iter()
+                .map(|(target, sat_per_vb)| (target, fee_rate_from_sat_per_vb(sat_per_vb)))
+                .collect(),
+        )
+    }
+
+    /// The fee rate to confirm within `blocks` blocks.
+    ///
+    /// If there is no estimate for `blocks`, the estimate of the nearest lower target is used,
+    /// which confirms faster. If `blocks` is below every target, the lowest target is used.
+    /// Returns `None` if there are no estimates.
+    pub fn for_target(&self, blocks: u16) -> Option<FeeRate> {
+        self.0
+            .range(..=blocks)
+            .next_back()
+            .or_else(|| self.0.iter().next())
+            .map(|(_, fee_rate)| *fee_rate)
+    }
+}
+
+impl From<FeeEstimates> for BTreeMap<u16, FeeRate> {
+    fn from(estimates: FeeEstimates) -> Self {
+        estimates.0
+    }
+}
+
+fn fee_rate_from_sat_per_vb(sat_per_vb: f64) -> FeeRate {
+    let sat_per_kwu = (sat_per_vb * 250.0).ceil();
+    if sat_per_kwu.is_nan() || sat_per_kwu < FeeRate::BROADCAST_MIN.to_sat_per_kwu() as f64 {
+        return FeeRate::BROADCAST_MIN;
+    }
+    if sat_per_kwu >= u64::MAX as f64 {
+        return FeeRate::MAX;
+    }
+    FeeRate::from_sat_per_kwu(sat_per_kwu as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_sat_per_vb() {
+        // a response of mempool.space
+        let estimates = FeeEstimates::from_sat_per_vb([
+            (1, 87.882),
+            (2, 87.882),
+            (3, 87.882),
+            (6, 68.285),
+            (144, 1.027),
+            (504, 1.027),
+            (1008, 1.0),
+        ]);
+        let sat_per_kwu = estimates
+            .0
+            .iter()
+            .map(|(target, fee_rate)| (*target, fee_rate.to_sat_per_kwu()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sat_per_kwu,
+            [
+                (1, 21971),
+                (2, 21971),
+                (3, 21971),
+                (6, 17072),
+                (144, 257),
+                (504, 257),
+                (1008, 250),
+            ]
+        );
+        // rounded up, the fee is never below the estimate
+        assert_eq!(
+            estimates.0[&6].to_sat_per_vb_ceil(),
+            69,
+            "68.285 sat/vB is 17071.25 sat/kwu"
+        );
+
+        // whole sat/kwu values are not rounded up by floating point errors
+        let estimates = FeeEstimates::from_sat_per_vb([(1, 20.0), (2, 3.3), (3, 1.1)]);
+        assert_eq!(estimates.0[&1], FeeRate::from_sat_per_kwu(5000));
+        assert_eq!(estimates.0[&2].to_sat_per_kwu(), 825);
+        assert_eq!(estimates.0[&3].to_sat_per_kwu(), 275);
+    }
+
+    #[test]
+    fn test_from_sat_per_vb_floor() {
+        let estimates = FeeEstimates::from_sat_per_vb([
+            (1, 0.999),
+            (2, 0.1),
+            (3, 0.0),
+            (4, -1.0),
+            (5, f64::NAN),
+            (6, 1.001),
+        ]);
+        for target in 1..=5 {
+            assert_eq!(estimates.0[&target], FeeRate::BROADCAST_MIN);
+        }
+        assert_eq!(estimates.0[&6].to_sat_per_kwu(), 251);
+        assert_eq!(
+            FeeEstimates::from_sat_per_vb([(1, f64::INFINITY)]).0[&1],
+            FeeRate::MAX
+        );
+    }
+
+    #[test]
+    fn test_for_target() {
+        let estimates = FeeEstimates::from_sat_per_vb([(2, 30.0), (6, 20.0), (144, 5.0)]);
+        let sat_per_vb = |blocks| {
+            estimates
+                .for_target(blocks)
+                .map(|fee_rate| fee_rate.to_sat_per_vb_floor())
+        };
+        assert_eq!(sat_per_vb(6), Some(20));
+        // the nearest lower target
+        assert_eq!(sat_per_vb(10), Some(20));
+        assert_eq!(sat_per_vb(1000), Some(5));
+        // below every target
+        assert_eq!(sat_per_vb(1), Some(30));
+        assert_eq!(sat_per_vb(0), Some(30));
+
+        assert_eq!(FeeEstimates::default().for_target(6), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! The extensions of the mempool.space API, see the [crate-level documentation](crate#mempoolspace)
+
+use bdk_chain::bitcoin::{Amount, FeeRate, Txid, Weight};
+use bdk_chain::spk_client::{CpfpInfo, MempoolTx};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// The response of `GET /v1/cpfp/:txid`
+///
+/// The ancestors and descendants fields are missing when there are none.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CpfpResponse {
+    #[serde(default)]
+    ancestors: Vec<CpfpTx>,
+    #[serde(default)]
+    descendants: Vec<CpfpTx>,
+    effective_fee_per_vsize: Option<f64>,
+}
+
+/// An ancestor or a descendant of a [`CpfpResponse`]
+#[derive(Debug, Deserialize)]
+struct CpfpTx {
+    txid: Txid,
+    fee: u64,
+    weight: u64,
+}
+
+impl From<CpfpTx> for MempoolTx {
+    fn from(tx: CpfpTx) -> Self {
+        Self {
+            txid: tx.txid,
+            fee: Amount::from_sat(tx.fee),
+            weight: Weight::from_wu(tx.weight),
+        }
+    }
+}
+
+impl From<CpfpResponse> for CpfpInfo {
+    fn from(response: CpfpResponse) -> Self {
+        Self {
+            ancestors: response.ancestors.into_iter().map(Into::into).collect(),
+            descendants: response.descendants.into_iter().map(Into::into).collect(),
+            // in sat/vB, one sat/vB being 250 sat/kwu
+            effective_fee_rate: response
+                .effective_fee_per_vsize
+                .filter(|sat_per_vb| sat_per_vb.is_finite() && *sat_per_vb >= 0.0)
+                .map(|sat_per_vb| FeeRate::from_sat_per_kwu((sat_per_vb * 250.0).round() as u64)),
+        }
+    }
+}
+
+/// Get the JSON response of `path`, `None` if the server answers with a 404.
+///
+/// [`esplora_client::AsyncClient`] has no method for the endpoints of mempool.space, so they're
+/// requested with its HTTP client, and its errors.
+pub(crate) async fn get_opt_json<T: DeserializeOwned>(
+    client: &esplora_client::AsyncClient,
+    path: &str,
+) -> Result<Option<T>, esplora_client::Error> {
+    let response = client
+        .client()
+        .get(format!("{}{}", client.url(), path))
+        .send()
+        .await?;
+    let status = response.status().as_u16();
+    if status == 404 {
+        return Ok(None);
+    }
+    if !(200..300).contains(&status) {
+        let message = response.text().await.unwrap_or_default();
+        return Err(esplora_client::Error::HttpResponse { status, message });
+    }
+    Ok(Some(response.json().await?))
+}
+
+#[cfg(test)]
+mod test {
+    use bdk_chain::bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[test]
+    fn test_cpfp_info_from_response() {
+        // a response of mempool.space, with mock txids
+        let response: CpfpResponse = serde_json::from_str(&format!(
+            r#"{{"ancestors":[{{"txid":"{}","fee":1410,"weight":562}}],"bestDescendant":null,"descendants":[{{"txid":"{}","fee":2820,"weight":561}}],"effectiveFeePerVsize":15.0625,"sigops":4,"adjustedVsize":140.5}}"#,
+            Txid::from_byte_array([1; 32]),
+            Txid::from_byte_array([2; 32]),
+        ))
+        .unwrap();
+        let info = CpfpInfo::from(response);
+        assert_eq!(
+            info.ancestors,
+            vec![MempoolTx {
+                txid: Txid::from_byte_array([1; 32]),
+                fee: Amount::from_sat(1410),
+                weight: Weight::from_wu(562),
+            }]
+        );
+        assert_eq!(info.descendants.len(), 1);
+        assert_eq!(info.descendants[0].fee, Amount::from_sat(2820));
+        assert_eq!(
+            info.effective_fee_rate,
+            Some(FeeRate::from_sat_per_kwu(3766))
+        );
+
+        // a transaction without ancestors nor descendants
+        let response: CpfpResponse =
+            serde_json::from_str(r#"{"ancestors":[],"effectiveFeePerVsize":2.5}"#).unwrap();
+        let info = CpfpInfo::from(response);
+        assert!(info.ancestors.is_empty() && info.descendants.is_empty());
+        assert_eq!(
+            info.effective_fee_rate,
+            Some(FeeRate::from_sat_per_kwu(625))
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Caches of the transactions fetched from Esplora
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use bdk_chain::bitcoin::consensus::{deserialize, serialize};
+use bdk_chain::bitcoin::{Transaction, Txid};
+
+/// A cache of transactions, consulted by the full scan and sync of [`EsploraExt`] and
+/// [`EsploraAsyncExt`] before fetching a transaction, see [`ScanOptions::tx_cache`]
+///
+/// A transaction is immutable once its txid is known, so cached transactions never expire. Only
+/// transactions are cached: the confirmation status of a transaction and the block hash at a
+/// height may change with a reorg, they are always fetched.
+///
+/// The cache is shared by the parallel requests, its methods must not block for long. Errors are
+/// not reported, a transaction which fails to be cached is fetched again next time.
+///
+/// [`EsploraExt`]: crate::EsploraExt
+/// [`EsploraAsyncExt`]: crate::EsploraAsyncExt
+/// [`ScanOptions::tx_cache`]: crate::ScanOptions::tx_cache
+pub trait TxCache: Send + Sync {
+    /// The cached transaction with `txid`, if any.
+    fn get(&self, txid: Txid) -> Option<Transaction>;
+
+    /// Add `tx` to the cache.
+    fn put(&self, tx: &Transaction);
+}
+
+/// A [`TxCache`] in memory, which doesn't survive the process
+///
+/// Clones share the same cache.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTxCache {
+    txs: Arc<Mutex<HashMap<Txid, Arc<Transaction>>>>,
+}
+
+impl MemoryTxCache {
+    /// An empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of cached transactions
+    pub fn len(&self) -> usize {
+        self.txs.lock().expect("must lock").len()
+    }
+
+    /// Whether no transaction is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl TxCache for MemoryTxCache {
+    fn get(&self, txid: Txid) -> Option<Transaction> {
+        let txs = self.txs.lock().expect("must lock");
+        txs.get(&txid).map(|tx| tx.as_ref().clone())
+    }
+
+    fn put(&self, tx: &Transaction) {
+        let mut txs = self.txs.lock().expect("must lock");
+        txs.entry(tx.compute_txid())
+            .or_insert_with(|| Arc::new(tx.clone()));
+    }
+}
+
+/// A [`TxCache`] in a directory, with one file per transaction in the consensus encoding
+///
+/// The transactions are written to a temporary file which is then renamed, so that a process
+/// interrupted while writing doesn't leave a truncated transaction behind. A file which can't be
+/// decoded is treated as missing.
+#[derive(Debug, Clone)]
+pub struct FileTxCache {
+    dir: PathBuf,
+}
+
+impl FileTxCache {
+    /// Cache the transactions in `dir`, which is created if it doesn't exist.
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, txid: Txid) -> PathBuf {
+        self.dir.join(format!("{}.tx", txid))
+    }
+}
+
+impl TxCache for FileTxCache {
+    fn get(&self, txid: Txid) -> Option<Transaction> {
+        let bytes = fs::read(self.path(txid)).ok()?;
+        let tx = deserialize::<Transaction>(&bytes).ok()?;
+        // a file renamed or corrupted on disk must not be returned for another txid
+        if tx.compute_txid() != txid {
+            return None;
+        }
+        Some(tx)
+    }
+
+    fn put(&self, tx: &Transaction) {
+        let path = self.path(tx.compute_txid());
+        if path.exists() {
+            return;
+        }
+        let tmp_path = path.with_extension(format!("tx.{}.tmp", std::process::id()));
+        if fs::write(&tmp_path, serialize(tx)).is_err() || fs::rename(&tmp_path, &path).is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk_chain::bitcoin::{
+        absolute, transaction, Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Witness,
+    };
+
+    fn tx(value: u64) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn check_cache(cache: &impl TxCache) {
+        let (tx_1, tx_2) = (tx(1), tx(2));
+        assert_eq!(cache.get(tx_1.compute_txid()), None);
+        cache.put(&tx_1);
+        cache.put(&tx_1);
+        assert_eq!(cache.get(tx_1.compute_txid()), Some(tx_1));
+        assert_eq!(cache.get(tx_2.compute_txid()), None);
+    }
+
+    #[test]
+    fn test_memory_tx_cache() {
+        let cache = MemoryTxCache::new();
+        check_cache(&cache);
+        assert_eq!(cache.len(), 1);
+        // the clones share the cache
+        let clone = cache.clone();
+        clone.put(&tx(3));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_file_tx_cache() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "bdk_esplora_tx_cache_{}_{}",
+            std::process::id(),
+            crate::random_u64()
+        ));
+        let cache = FileTxCache::new(&dir)?;
+        check_cache(&cache);
+
+        // the cache survives the process
+        let tx = tx(1);
+        let reopened = FileTxCache::new(&dir)?;
+        assert_eq!(reopened.get(tx.compute_txid()), Some(tx.clone()));
+
+        // a file which doesn't match its txid is ignored
+        fs::write(reopened.path(tx.compute_txid()), serialize(&self::tx(5)))?;
+        assert_eq!(reopened.get(tx.compute_txid()), None);
+
+        fs::remove_dir_all(&dir)
+    }
+}
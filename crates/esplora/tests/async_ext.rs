@@ -1,5 +1,7 @@
-use bdk_chain::spk_client::{FullScanRequest, SyncRequest};
-use bdk_esplora::EsploraAsyncExt;
+use bdk_chain::spk_client::{
+    AsyncSyncBackend, BackendOptions, BroadcastError, FullScanRequest, SyncRequest,
+};
+use bdk_esplora::{EsploraAsyncExt, EsploraBackend, RetryPolicy, ScanOptions, ServerFlavor};
 use esplora_client::{self, Builder};
 use std::collections::{BTreeSet, HashSet};
 use std::str::FromStr;
@@ -9,6 +11,17 @@ use std::time::Duration;
 use bdk_chain::bitcoin::{Address, Amount, Txid};
 use bdk_testenv::{anyhow, bitcoincore_rpc::RpcApi, TestEnv};
 
+fn scan_options() -> ScanOptions {
+    ScanOptions {
+        parallel_requests: 1,
+        batch_size: 1,
+        retry: RetryPolicy::none(),
+        verify_anchors: true,
+        tx_cache: None,
+        server_flavor: ServerFlavor::Esplora,
+    }
+}
+
 #[tokio::test]
 pub async fn test_update_tx_graph_without_keychain() -> anyhow::Result<()> {
     let env = TestEnv::new()?;
@@ -56,7 +69,7 @@ pub async fn test_update_tx_graph_without_keychain() -> anyhow::Result<()> {
 
     let sync_update = {
         let request = SyncRequest::from_chain_tip(cp_tip.clone()).set_spks(misc_spks);
-        client.sync(request, 1).await?
+        client.sync(request, scan_options()).await?
     };
 
     assert!(
@@ -162,14 +175,14 @@ pub async fn test_async_update_tx_graph_stop_gap() -> anyhow::Result<()> {
     let full_scan_update = {
         let request =
             FullScanRequest::from_chain_tip(cp_tip.clone()).set_spks_for_keychain(0, spks.clone());
-        client.full_scan(request, 3, 1).await?
+        client.full_scan(request, 3, scan_options()).await?
     };
     assert!(full_scan_update.graph_update.full_txs().next().is_none());
     assert!(full_scan_update.last_active_indices.is_empty());
     let full_scan_update = {
         let request =
             FullScanRequest::from_chain_tip(cp_tip.clone()).set_spks_for_keychain(0, spks.clone());
-        client.full_scan(request, 4, 1).await?
+        client.full_scan(request, 4, scan_options()).await?
     };
     assert_eq!(
         full_scan_update
@@ -203,7 +216,7 @@ pub async fn test_async_update_tx_graph_stop_gap() -> anyhow::Result<()> {
     let full_scan_update = {
         let request =
             FullScanRequest::from_chain_tip(cp_tip.clone()).set_spks_for_keychain(0, spks.clone());
-        client.full_scan(request, 5, 1).await?
+        client.full_scan(request, 5, scan_options()).await?
     };
     let txs: HashSet<_> = full_scan_update
         .graph_update
@@ -216,7 +229,7 @@ pub async fn test_async_update_tx_graph_stop_gap() -> anyhow::Result<()> {
     let full_scan_update = {
         let request =
             FullScanRequest::from_chain_tip(cp_tip.clone()).set_spks_for_keychain(0, spks.clone());
-        client.full_scan(request, 6, 1).await?
+        client.full_scan(request, 6, scan_options()).await?
     };
     let txs: HashSet<_> = full_scan_update
         .graph_update
@@ -229,3 +242,77 @@ pub async fn test_async_update_tx_graph_stop_gap() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Test that `broadcast_all` reports the result of each broadcast, in order, and that a child
+/// broadcast before its parent is rejected for its missing inputs.
+#[tokio::test]
+pub async fn test_broadcast_all() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let base_url = format!("http://{}", &env.electrsd.esplora_url.clone().unwrap());
+    let client = Builder::new(base_url.as_str()).build_async()?;
+    let address = env
+        .rpc_client()
+        .get_new_address(None, None)?
+        .assume_checked();
+    let _block_hashes = env.mine_blocks(101, Some(address.clone()))?;
+    let (parent, child) = env.create_unbroadcast_package(
+        &address,
+        Amount::from_sat(100_000),
+        Amount::from_sat(10_000),
+    )?;
+
+    let results = client.broadcast_all(&[child.clone(), parent.clone()]).await;
+    assert!(matches!(results[0], Err(BroadcastError::MissingInputs)));
+    assert_eq!(results[1].as_ref().ok(), Some(&parent.compute_txid()));
+
+    // the parent already in the mempool is reported as broadcast
+    let results = client.broadcast_all(&[parent.clone(), child.clone()]).await;
+    let txids = results.into_iter().map(Result::ok).collect::<Vec<_>>();
+    assert_eq!(
+        txids,
+        vec![Some(parent.compute_txid()), Some(child.compute_txid())]
+    );
+
+    Ok(())
+}
+
+/// Ensure that the async client can be used behind [`AsyncSyncBackend`], with the same results as
+/// [`EsploraAsyncExt::sync`].
+#[tokio::test]
+pub async fn test_async_sync_backend() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let base_url = format!("http://{}", &env.electrsd.esplora_url.clone().unwrap());
+    let backend: Box<dyn AsyncSyncBackend<()>> = Box::new(EsploraBackend::new(
+        Builder::new(base_url.as_str()).build_async()?,
+    ));
+    let client = Builder::new(base_url.as_str()).build_async()?;
+
+    let address =
+        Address::from_str("bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm")?.assume_checked();
+    env.mine_blocks(101, None)?;
+    let txid = env.send(&address, Amount::from_sat(10_000))?;
+    env.mine_blocks(1, None)?;
+    while client.get_height().await.unwrap() < 102 {
+        sleep(Duration::from_millis(10))
+    }
+
+    let request = || {
+        SyncRequest::from_chain_tip(env.make_checkpoint_tip()).chain_spks([address.script_pubkey()])
+    };
+    let res = backend
+        .sync(request(), BackendOptions::default())
+        .await
+        .map_err(|err| anyhow::anyhow!(err))?;
+    let exp = client.sync(request(), ScanOptions::default()).await?;
+    let txids = |graph: &bdk_chain::TxGraph<_>| {
+        graph
+            .full_txs()
+            .map(|tx| tx.txid)
+            .collect::<BTreeSet<Txid>>()
+    };
+    assert_eq!(txids(&res.graph_update), [txid].into());
+    assert_eq!(txids(&res.graph_update), txids(&exp.graph_update));
+    assert_eq!(res.chain_update.block_id(), exp.chain_update.block_id());
+
+    Ok(())
+}
@@ -1,5 +1,5 @@
-use bdk_chain::spk_client::{FullScanRequest, SyncRequest};
-use bdk_esplora::EsploraExt;
+use bdk_chain::spk_client::{BroadcastError, FullScanRequest, SyncRequest};
+use bdk_esplora::{EsploraExt, RetryPolicy, ScanOptions, ServerFlavor};
 use esplora_client::{self, Builder};
 use std::collections::{BTreeSet, HashSet};
 use std::str::FromStr;
@@ -9,6 +9,17 @@ use std::time::Duration;
 use bdk_chain::bitcoin::{Address, Amount, Txid};
 use bdk_testenv::{anyhow, bitcoincore_rpc::RpcApi, TestEnv};
 
+fn scan_options() -> ScanOptions {
+    ScanOptions {
+        parallel_requests: 1,
+        batch_size: 1,
+        retry: RetryPolicy::none(),
+        verify_anchors: true,
+        tx_cache: None,
+        server_flavor: ServerFlavor::Esplora,
+    }
+}
+
 #[test]
 pub fn test_update_tx_graph_without_keychain() -> anyhow::Result<()> {
     let env = TestEnv::new()?;
@@ -56,7 +67,7 @@ pub fn test_update_tx_graph_without_keychain() -> anyhow::Result<()> {
 
     let sync_update = {
         let request = SyncRequest::from_chain_tip(cp_tip.clone()).set_spks(misc_spks);
-        client.sync(request, 1)?
+        client.sync(request, scan_options())?
     };
 
     assert!(
@@ -108,6 +119,87 @@ pub fn test_update_tx_graph_without_keychain() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Test that a sync reports the replaced transactions of the request's txids as evicted.
+#[test]
+pub fn test_sync_reports_evicted_txs() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let base_url = format!("http://{}", &env.electrsd.esplora_url.clone().unwrap());
+    let client = Builder::new(base_url.as_str()).build_blocking();
+    let receive_address =
+        Address::from_str("bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm")?.assume_checked();
+
+    let _block_hashes = env.mine_blocks(101, None)?;
+    let txid = env.send(&receive_address, Amount::from_sat(10_000))?;
+    while client.get_tx(&txid)?.is_none() {
+        sleep(Duration::from_millis(10))
+    }
+    let bumped = env
+        .rpc_client()
+        .call::<bdk_testenv::bitcoincore_rpc::jsonrpc::serde_json::Value>(
+            "bumpfee",
+            &[txid.to_string().into()],
+        )?;
+    let replacement_txid = Txid::from_str(bumped["txid"].as_str().expect("txid"))?;
+    while client.get_tx(&txid)?.is_some() {
+        sleep(Duration::from_millis(10))
+    }
+
+    let request = SyncRequest::from_chain_tip(env.make_checkpoint_tip())
+        .set_spks([receive_address.script_pubkey()])
+        .set_txids([txid]);
+    let graph_update = client.sync(request, scan_options())?.graph_update;
+    assert!(graph_update.last_evicted(txid).is_some());
+    assert!(graph_update.get_tx(replacement_txid).is_some());
+    assert!(graph_update.last_evicted(replacement_txid).is_none());
+
+    Ok(())
+}
+
+/// Test that a sync follows a reorg replacing a confirmed tx with a double spend of it.
+#[test]
+pub fn test_sync_after_double_spend_reorg() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let base_url = format!("http://{}", &env.electrsd.esplora_url.clone().unwrap());
+    let client = Builder::new(base_url.as_str()).build_blocking();
+    let receive_address0 =
+        Address::from_str("bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm")?.assume_checked();
+    let receive_address1 =
+        Address::from_str("bcrt1qfjg5lv3dvc9az8patec8fjddrs4aqtauadnagr")?.assume_checked();
+
+    let _block_hashes = env.mine_blocks(101, None)?;
+    let txid = env.send(&receive_address0, Amount::from_sat(10_000))?;
+    let block_hash = env.mine_blocks(1, None)?[0];
+    while client.get_tip_hash()? != block_hash {
+        sleep(Duration::from_millis(10))
+    }
+
+    // reorg the confirmed tx out, and confirm a double spend of it instead
+    env.invalidate_blocks(1)?;
+    let double_spend_txid = env.double_spend(txid, &receive_address1)?;
+    let reorged_block_hash = env.mine_blocks(1, None)?[0];
+    while client.get_tip_hash()? != reorged_block_hash {
+        sleep(Duration::from_millis(10))
+    }
+
+    let request = SyncRequest::from_chain_tip(env.make_checkpoint_tip())
+        .set_spks([
+            receive_address0.script_pubkey(),
+            receive_address1.script_pubkey(),
+        ])
+        .set_txids([txid]);
+    let graph_update = client.sync(request, scan_options())?.graph_update;
+    assert!(graph_update.last_evicted(txid).is_some());
+    let anchors = graph_update
+        .all_anchors()
+        .iter()
+        .map(|(anchor, txid)| (anchor.anchor_block.hash, *txid))
+        .collect::<HashSet<_>>();
+    assert!(anchors.contains(&(reorged_block_hash, double_spend_txid)));
+    assert!(!anchors.iter().any(|(hash, _)| *hash == block_hash));
+
+    Ok(())
+}
+
 /// Test the bounds of the address scan depending on the `stop_gap`.
 #[test]
 pub fn test_update_tx_graph_stop_gap() -> anyhow::Result<()> {
@@ -163,14 +255,14 @@ pub fn test_update_tx_graph_stop_gap() -> anyhow::Result<()> {
     let full_scan_update = {
         let request =
             FullScanRequest::from_chain_tip(cp_tip.clone()).set_spks_for_keychain(0, spks.clone());
-        client.full_scan(request, 3, 1)?
+        client.full_scan(request, 3, scan_options())?
     };
     assert!(full_scan_update.graph_update.full_txs().next().is_none());
     assert!(full_scan_update.last_active_indices.is_empty());
     let full_scan_update = {
         let request =
             FullScanRequest::from_chain_tip(cp_tip.clone()).set_spks_for_keychain(0, spks.clone());
-        client.full_scan(request, 4, 1)?
+        client.full_scan(request, 4, scan_options())?
     };
     assert_eq!(
         full_scan_update
@@ -204,7 +296,7 @@ pub fn test_update_tx_graph_stop_gap() -> anyhow::Result<()> {
     let full_scan_update = {
         let request =
             FullScanRequest::from_chain_tip(cp_tip.clone()).set_spks_for_keychain(0, spks.clone());
-        client.full_scan(request, 5, 1)?
+        client.full_scan(request, 5, scan_options())?
     };
     let txs: HashSet<_> = full_scan_update
         .graph_update
@@ -217,7 +309,7 @@ pub fn test_update_tx_graph_stop_gap() -> anyhow::Result<()> {
     let full_scan_update = {
         let request =
             FullScanRequest::from_chain_tip(cp_tip.clone()).set_spks_for_keychain(0, spks.clone());
-        client.full_scan(request, 6, 1)?
+        client.full_scan(request, 6, scan_options())?
     };
     let txs: HashSet<_> = full_scan_update
         .graph_update
@@ -228,5 +320,56 @@ pub fn test_update_tx_graph_stop_gap() -> anyhow::Result<()> {
     assert!(txs.contains(&txid_4th_addr) && txs.contains(&txid_last_addr));
     assert_eq!(full_scan_update.last_active_indices[&0], 9);
 
+    // The stop gap can be set per keychain. The first transaction sits right after the stop gap
+    // of 3 of keychain 0, keychain 1 has a stop gap of 6 and finds both.
+    let full_scan_update = {
+        let request = FullScanRequest::from_chain_tip(cp_tip.clone())
+            .set_spks_for_keychain(0, spks.clone())
+            .set_spks_for_keychain(1, spks.clone())
+            .set_stop_gap_for_keychain(1, 6);
+        client.full_scan(request, 3, scan_options())?
+    };
+    assert_eq!(full_scan_update.last_active_indices, [(1, 9)].into());
+    let stats = &full_scan_update.scan_stats;
+    assert_eq!(stats[&0].spks_checked, 3);
+    assert_eq!(stats[&0].last_active_index, None);
+    assert_eq!(stats[&0].txs_fetched, 0);
+    assert_eq!(stats[&1].spks_checked, 10);
+    assert_eq!(stats[&1].last_active_index, Some(9));
+    assert_eq!(stats[&1].txs_fetched, 2);
+
+    Ok(())
+}
+
+/// Test that `broadcast_all` reports the result of each broadcast, in order, and that a child
+/// broadcast before its parent is rejected for its missing inputs.
+#[test]
+pub fn test_broadcast_all() -> anyhow::Result<()> {
+    let env = TestEnv::new()?;
+    let base_url = format!("http://{}", &env.electrsd.esplora_url.clone().unwrap());
+    let client = Builder::new(base_url.as_str()).build_blocking();
+    let address = env
+        .rpc_client()
+        .get_new_address(None, None)?
+        .assume_checked();
+    let _block_hashes = env.mine_blocks(101, Some(address.clone()))?;
+    let (parent, child) = env.create_unbroadcast_package(
+        &address,
+        Amount::from_sat(100_000),
+        Amount::from_sat(10_000),
+    )?;
+
+    let results = client.broadcast_all(&[child.clone(), parent.clone()]);
+    assert!(matches!(results[0], Err(BroadcastError::MissingInputs)));
+    assert_eq!(results[1].as_ref().ok(), Some(&parent.compute_txid()));
+
+    // the parent already in the mempool is reported as broadcast
+    let results = client.broadcast_all(&[parent.clone(), child.clone()]);
+    let txids = results.into_iter().map(Result::ok).collect::<Vec<_>>();
+    assert_eq!(
+        txids,
+        vec![Some(parent.compute_txid()), Some(child.compute_txid())]
+    );
+
     Ok(())
 }
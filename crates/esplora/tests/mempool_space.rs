@@ -0,0 +1,417 @@
+#![cfg(feature = "async")]
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+use bdk_chain::bitcoin::consensus::serialize;
+use bdk_chain::bitcoin::constants::genesis_block;
+use bdk_chain::bitcoin::hashes::Hash;
+use bdk_chain::bitcoin::{
+    absolute, transaction, Amount, BlockHash, FeeRate, Network, OutPoint, ScriptBuf, Transaction,
+    TxIn, TxMerkleNode, TxOut, Txid, Weight,
+};
+use bdk_chain::spk_client::{MempoolTx, SyncRequest};
+use bdk_esplora::esplora_client::{AsyncClient, Builder};
+use bdk_esplora::{EsploraAsyncExt, RetryPolicy, ScanOptions, ServerFlavor};
+use bdk_testenv::anyhow;
+use bdk_wallet::{KeychainKind, Wallet};
+
+const EXTERNAL_DESC: &str = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)";
+const INTERNAL_DESC: &str = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/1/*)";
+
+/// A payment to the wallet, spending the output of a transaction of the sender
+struct Payment {
+    tx: Transaction,
+    prevout: (OutPoint, TxOut),
+    /// The status of the payment, as served by Esplora
+    status: String,
+}
+
+impl Payment {
+    fn new(parent: u8, spk: ScriptBuf, value: Amount, fee: Amount, status: String) -> Self {
+        let prevout = (
+            OutPoint::new(Txid::from_byte_array([parent; 32]), 0),
+            TxOut {
+                script_pubkey: ScriptBuf::from_bytes(vec![parent]),
+                value: value + fee,
+            },
+        );
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prevout.0,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                script_pubkey: spk,
+                value,
+            }],
+        };
+        Self {
+            tx,
+            prevout,
+            status,
+        }
+    }
+
+    /// The response of `GET /tx/:txid`, with the previous output of the input
+    fn json(&self) -> String {
+        let (outpoint, prevout) = &self.prevout;
+        let txout = &self.tx.output[0];
+        format!(
+            r#"{{"txid":"{}","version":2,"locktime":0,"vin":[{{"txid":"{}","vout":{},"prevout":{{"value":{},"scriptpubkey":"{}"}},"scriptsig":"","witness":[],"sequence":{},"is_coinbase":false}}],"vout":[{{"value":{},"scriptpubkey":"{}"}}],"status":{},"fee":{}}}"#,
+            self.tx.compute_txid(),
+            outpoint.txid,
+            outpoint.vout,
+            prevout.value.to_sat(),
+            prevout.script_pubkey.to_hex_string(),
+            self.tx.input[0].sequence.0,
+            txout.value.to_sat(),
+            txout.script_pubkey.to_hex_string(),
+            self.status,
+            (prevout.value - txout.value).to_sat(),
+        )
+    }
+}
+
+/// A mock server with a chain of two blocks and the transactions of `payments`, serving the
+/// extensions of mempool.space if `mempool_space` is set, and recording the requests
+///
+/// The unknown paths are answered with a 404, like Esplora does.
+///
+/// The unconfirmed transactions have an unconfirmed ancestor of 500 sat and 400 wu.
+struct MockServer {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+    failing: Arc<Mutex<HashSet<String>>>,
+}
+
+impl MockServer {
+    fn start(mempool_space: bool, block_hash: BlockHash, payments: &[Payment]) -> Self {
+        let genesis_hash = genesis_block(Network::Regtest).block_hash();
+        let mut routes = HashMap::<String, (&str, Vec<u8>)>::new();
+        let mut route = |path: String, body: Vec<u8>| {
+            routes.insert(path, ("200 OK", body));
+        };
+        route(
+            "/blocks".into(),
+            format!(
+                r#"[{{"id":"{}","height":1,"timestamp":1700000000,"previousblockhash":"{}","merkle_root":"{}"}}]"#,
+                block_hash,
+                genesis_hash,
+                TxMerkleNode::from_byte_array([0; 32]),
+            )
+            .into_bytes(),
+        );
+        route(
+            "/block-height/0".into(),
+            genesis_hash.to_string().into_bytes(),
+        );
+        route(
+            "/block-height/1".into(),
+            block_hash.to_string().into_bytes(),
+        );
+        for payment in payments {
+            let txid = payment.tx.compute_txid();
+            route(format!("/tx/{}", txid), payment.json().into_bytes());
+            route(format!("/tx/{}/raw", txid), serialize(&payment.tx));
+            route(
+                format!("/tx/{}/status", txid),
+                payment.status.clone().into_bytes(),
+            );
+            route(
+                format!("/tx/{}/outspend/0", txid),
+                br#"{"spent":false}"#.to_vec(),
+            );
+            if mempool_space && !payment.status.contains("true") {
+                let (outpoint, _) = &payment.prevout;
+                route(
+                    format!("/v1/cpfp/{}", txid),
+                    format!(
+                        r#"{{"ancestors":[{{"txid":"{}","fee":500,"weight":400}}],"effectiveFeePerVsize":3.5}}"#,
+                        outpoint.txid
+                    )
+                    .into_bytes(),
+                );
+            }
+        }
+        if mempool_space {
+            route(
+                "/v1/fees/recommended".into(),
+                br#"{"fastestFee":4,"halfHourFee":3,"hourFee":2,"economyFee":1,"minimumFee":1}"#
+                    .to_vec(),
+            );
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("must bind");
+        let url = format!(
+            "http://{}",
+            listener.local_addr().expect("must have address")
+        );
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let failing = Arc::new(Mutex::new(HashSet::<String>::new()));
+        let server = Self {
+            url,
+            requests: requests.clone(),
+            failing: failing.clone(),
+        };
+        let routes = Arc::new(routes);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                let routes = routes.clone();
+                let requests = requests.clone();
+                let failing = failing.clone();
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().expect("must clone"));
+                    let mut stream = stream;
+                    loop {
+                        let mut request_line = String::new();
+                        match reader.read_line(&mut request_line) {
+                            Ok(0) | Err(_) => break,
+                            Ok(_) => {}
+                        }
+                        // skip the headers, the requests have no body
+                        loop {
+                            let mut header = String::new();
+                            match reader.read_line(&mut header) {
+                                Ok(0) | Err(_) => return,
+                                Ok(_) if header == "\r\n" => break,
+                                Ok(_) => {}
+                            }
+                        }
+                        let path = request_line
+                            .split_whitespace()
+                            .nth(1)
+                            .unwrap_or_default()
+                            .to_string();
+                        requests.lock().unwrap().push(path.clone());
+                        let (status, body) = if failing.lock().unwrap().contains(&path) {
+                            ("501 Not Implemented", b"Not Implemented".to_vec())
+                        } else {
+                            routes
+                                .get(&path)
+                                .cloned()
+                                .unwrap_or(("404 Not Found", b"Not Found".to_vec()))
+                        };
+                        let mut response = format!(
+                            "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+                            status,
+                            body.len()
+                        )
+                        .into_bytes();
+                        response.extend(body);
+                        if stream.write_all(&response).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        server
+    }
+
+    fn client(&self) -> AsyncClient {
+        Builder::new(&self.url)
+            .build_async()
+            .expect("must build client")
+    }
+
+    /// Answer the requests of `path` with an error.
+    fn fail(&self, path: String) {
+        self.failing.lock().unwrap().insert(path);
+    }
+
+    fn request_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+/// A wallet which saw a confirmed and an unconfirmed payment in the mempool, and the mock server
+/// which has them
+fn setup(mempool_space: bool) -> (Wallet, MockServer, [Txid; 2]) {
+    let mut wallet = Wallet::new(EXTERNAL_DESC, INTERNAL_DESC, Network::Regtest).unwrap();
+    let block_hash = BlockHash::from_byte_array([42; 32]);
+    let unconfirmed = Payment::new(
+        1,
+        wallet
+            .reveal_next_address(KeychainKind::External)
+            .script_pubkey(),
+        Amount::from_sat(40_000),
+        Amount::from_sat(1_000),
+        r#"{"confirmed":false}"#.into(),
+    );
+    let confirmed = Payment::new(
+        2,
+        wallet
+            .reveal_next_address(KeychainKind::External)
+            .script_pubkey(),
+        Amount::from_sat(30_000),
+        Amount::from_sat(2_000),
+        format!(
+            r#"{{"confirmed":true,"block_height":1,"block_hash":"{}","block_time":1700000000}}"#,
+            block_hash
+        ),
+    );
+    let txids = [unconfirmed.tx.compute_txid(), confirmed.tx.compute_txid()];
+    for payment in [&unconfirmed, &confirmed] {
+        wallet
+            .insert_tx(
+                payment.tx.clone(),
+                bdk_chain::ConfirmationTime::Unconfirmed { last_seen: 0 },
+            )
+            .unwrap();
+    }
+    let server = MockServer::start(mempool_space, block_hash, &[unconfirmed, confirmed]);
+    (wallet, server, txids)
+}
+
+/// Sync the payments of `wallet` by txid and outpoint, the way the transactions which may have
+/// confirmed or left the mempool are checked.
+async fn sync_payments(
+    wallet: &mut Wallet,
+    client: &AsyncClient,
+    txids: [Txid; 2],
+    server_flavor: ServerFlavor,
+) -> anyhow::Result<()> {
+    let request = SyncRequest::from_chain_tip(wallet.latest_checkpoint())
+        .chain_txids(txids)
+        .chain_outpoints(txids.map(|txid| OutPoint::new(txid, 0)));
+    let options = ScanOptions {
+        retry: RetryPolicy::none(),
+        server_flavor,
+        ..Default::default()
+    };
+    let update = client.sync(request, options).await?;
+    wallet.apply_update(update)?;
+    Ok(())
+}
+
+/// The state of `wallet`: its balance, and its transactions with whether they're confirmed
+fn wallet_state(wallet: &Wallet) -> (bdk_chain::keychain::Balance, Vec<(Txid, bool)>) {
+    let mut txs = wallet
+        .transactions()
+        .map(|tx| (tx.tx_node.txid, tx.chain_position.is_confirmed()))
+        .collect::<Vec<_>>();
+    txs.sort();
+    (wallet.balance(), txs)
+}
+
+#[tokio::test]
+pub async fn test_sync_server_flavors() -> anyhow::Result<()> {
+    let mut states = Vec::new();
+    let mut request_counts = Vec::new();
+    for server_flavor in [ServerFlavor::Esplora, ServerFlavor::MempoolSpace] {
+        let (mut wallet, server, txids) = setup(true);
+        sync_payments(&mut wallet, &server.client(), txids, server_flavor).await?;
+        let state = wallet_state(&wallet);
+        let mut expected_txs = vec![(txids[0], false), (txids[1], true)];
+        expected_txs.sort();
+        assert_eq!(state.1, expected_txs);
+        // only the transactions of mempool.space come with their previous outputs
+        let fee = wallet.calculate_fee(&wallet.get_tx(txids[0]).unwrap().tx_node.tx);
+        match server_flavor {
+            ServerFlavor::Esplora => assert!(fee.is_err()),
+            ServerFlavor::MempoolSpace => assert_eq!(fee?, Amount::from_sat(1_000)),
+        }
+        states.push(state);
+        request_counts.push(server.request_count());
+    }
+
+    // the same wallet state, with a request per transaction instead of the status and the raw
+    // transaction
+    assert_eq!(states[0], states[1]);
+    assert_eq!(
+        states[0].0.trusted_pending + states[0].0.untrusted_pending + states[0].0.confirmed,
+        Amount::from_sat(70_000)
+    );
+    assert_eq!(request_counts[0] - request_counts[1], 3);
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_sync_falls_back_to_esplora() -> anyhow::Result<()> {
+    let (mut expected, server, txids) = setup(true);
+    sync_payments(
+        &mut expected,
+        &server.client(),
+        txids,
+        ServerFlavor::Esplora,
+    )
+    .await?;
+    let esplora_request_count = server.request_count();
+
+    // the server fails to serve `GET /tx/:txid`, the transactions are fetched as from Esplora
+    let (mut wallet, server, _) = setup(true);
+    for txid in txids {
+        server.fail(format!("/tx/{}", txid));
+    }
+    sync_payments(
+        &mut wallet,
+        &server.client(),
+        txids,
+        ServerFlavor::MempoolSpace,
+    )
+    .await?;
+    assert_eq!(wallet_state(&wallet), wallet_state(&expected));
+    // a failed request each time a transaction is fetched: both payments by txid, and the
+    // confirmed one again by outpoint, since its status alone doesn't bring the transaction
+    assert_eq!(server.request_count(), esplora_request_count + 3);
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_tx_cpfp_info() -> anyhow::Result<()> {
+    // a plain Esplora server has no CPFP info
+    let (_, server, txids) = setup(false);
+    let client = server.client();
+    assert_eq!(client.detect_server_flavor().await?, ServerFlavor::Esplora);
+    assert_eq!(client.tx_cpfp_info(txids[0]).await?, None);
+
+    let (mut wallet, server, txids) = setup(true);
+    let client = server.client();
+    assert_eq!(
+        client.detect_server_flavor().await?,
+        ServerFlavor::MempoolSpace
+    );
+    let cpfp_info = client
+        .tx_cpfp_info(txids[0])
+        .await?
+        .expect("must have CPFP info");
+    assert_eq!(
+        cpfp_info.ancestors,
+        vec![MempoolTx {
+            txid: Txid::from_byte_array([1; 32]),
+            fee: Amount::from_sat(500),
+            weight: Weight::from_wu(400),
+        }]
+    );
+    assert_eq!(
+        cpfp_info.effective_fee_rate,
+        Some(FeeRate::from_sat_per_kwu(875))
+    );
+    // a transaction the server doesn't know
+    assert_eq!(
+        client.tx_cpfp_info(Txid::from_byte_array([9; 32])).await?,
+        None
+    );
+
+    // the sync brings the fee of the payment, the package includes its ancestor
+    sync_payments(&mut wallet, &client, txids, ServerFlavor::MempoolSpace).await?;
+    let weight = wallet.get_tx(txids[0]).unwrap().tx_node.tx.weight();
+    assert_eq!(
+        wallet.tx_package_fee_rate(txids[0], Some(&cpfp_info)),
+        Some(Amount::from_sat(1_500) / (weight + Weight::from_wu(400)))
+    );
+    assert_eq!(
+        wallet.tx_package_fee_rate(txids[0], None),
+        Some(Amount::from_sat(1_000) / weight)
+    );
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::{fmt, io};
+
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+
+/// The length of the key-check header, written after the magic bytes of an encrypted store.
+pub(crate) const KEY_CHECK_LEN: usize = NONCE_LEN + 16;
+
+/// A changeset encrypted with ChaCha20-Poly1305, with the random nonce it is encrypted with.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct EncryptedEntry {
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+/// Encrypts and authenticates the entries of a [`Store`](crate::Store).
+///
+/// An entry is authenticated with the magic bytes of the store and its index, so that an entry
+/// which is moved to another position or copied from another store fails to decrypt.
+pub(crate) struct Cipher {
+    key: LessSafeKey,
+    magic: Vec<u8>,
+    rng: SystemRandom,
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the key must not be printed
+        f.debug_struct("Cipher").finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    /// The cipher of a store with `magic`.
+    pub(crate) fn new(key: [u8; 32], magic: &[u8]) -> Self {
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key).expect("key must be 32 bytes");
+        Self {
+            key: LessSafeKey::new(key),
+            magic: magic.to_vec(),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// The key-check header of the store, which is the tag of the empty plaintext authenticated
+    /// with the magic bytes, after its nonce.
+    pub(crate) fn key_check(&self) -> Result<Vec<u8>, io::Error> {
+        let nonce = self.random_nonce()?;
+        let mut tag = Vec::new();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&self.magic),
+                &mut tag,
+            )
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to encrypt"))?;
+        let mut header = nonce.to_vec();
+        header.extend(tag);
+        Ok(header)
+    }
+
+    /// Whether the key-check `header` of the store is authenticated by this key.
+    pub(crate) fn verify_key_check(&self, header: &[u8]) -> bool {
+        let (nonce, tag) = header.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).expect("must be the nonce length");
+        let mut tag = tag.to_vec();
+        self.key
+            .open_in_place(nonce, Aad::from(&self.magic), &mut tag)
+            .is_ok()
+    }
+
+    /// Encrypt `plaintext` as the entry of the store at `index`, counted from 0.
+    pub(crate) fn encrypt(
+        &self,
+        mut plaintext: Vec<u8>,
+        index: u64,
+    ) -> Result<EncryptedEntry, io::Error> {
+        let nonce = self.random_nonce()?;
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                self.entry_aad(index),
+                &mut plaintext,
+            )
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to encrypt"))?;
+        Ok(EncryptedEntry {
+            nonce,
+            ciphertext: plaintext,
+        })
+    }
+
+    /// Decrypt `entry`, the entry of the store at `index`, or `None` if it fails to authenticate.
+    pub(crate) fn decrypt(&self, entry: EncryptedEntry, index: u64) -> Option<Vec<u8>> {
+        let mut ciphertext = entry.ciphertext;
+        let len = self
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(entry.nonce),
+                self.entry_aad(index),
+                &mut ciphertext,
+            )
+            .ok()?
+            .len();
+        ciphertext.truncate(len);
+        Some(ciphertext)
+    }
+
+    /// The additional data of the entry at `index`: the magic bytes, then the index as 8
+    /// big-endian bytes.
+    fn entry_aad(&self, index: u64) -> Aad<Vec<u8>> {
+        let mut aad = self.magic.clone();
+        aad.extend_from_slice(&index.to_be_bytes());
+        Aad::from(aad)
+    }
+
+    fn random_nonce(&self) -> Result<[u8; NONCE_LEN], io::Error> {
+        let mut nonce = [0_u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "failed to generate a nonce"))?;
+        Ok(nonce)
+    }
+}
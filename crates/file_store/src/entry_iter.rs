@@ -1,3 +1,4 @@
+#[cfg(feature = "encryption")]
 use bincode::Options;
 use std::{
     fs::File,
@@ -5,7 +6,12 @@ use std::{
     marker::PhantomData,
 };
 
-use crate::bincode_options;
+#[cfg(feature = "encryption")]
+use crate::{
+    bincode_options,
+    encryption::{Cipher, EncryptedEntry},
+};
+use crate::format;
 
 /// Iterator over entries in a file store.
 ///
@@ -19,18 +25,91 @@ pub struct EntryIter<'t, T> {
     finished: bool,
     /// The file position for the first read of `db_file`.
     start_pos: Option<u64>,
+    /// The format version of the file, see [`FORMAT_VERSION`](crate::FORMAT_VERSION).
+    format_version: u16,
+    /// Whether an entry which is cut short by the end of the file ends the iteration, rather than
+    /// being an error.
+    partial_tail_is_end: bool,
+    /// The cipher of the entries of an encrypted store.
+    #[cfg(feature = "encryption")]
+    cipher: Option<&'t Cipher>,
+    /// The index of the next entry in the store, which an encrypted entry is authenticated with.
+    #[cfg(feature = "encryption")]
+    index: u64,
     types: PhantomData<T>,
 }
 
 impl<'t, T> EntryIter<'t, T> {
+    /// Iterate over the entries of a file of format version 0, from `start_pos`.
     pub fn new(start_pos: u64, db_file: &'t mut File) -> Self {
+        Self::with_format(start_pos, db_file, 0)
+    }
+
+    /// Iterate over the entries of a file of `format_version`, from `start_pos`.
+    pub(crate) fn with_format(start_pos: u64, db_file: &'t mut File, format_version: u16) -> Self {
         Self {
             db_file: BufReader::new(db_file),
             start_pos: Some(start_pos),
             finished: false,
+            format_version,
+            partial_tail_is_end: false,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            #[cfg(feature = "encryption")]
+            index: 0,
             types: PhantomData,
         }
     }
+
+    /// Iterate over the entries of an encrypted store, which are decrypted with `cipher`, from
+    /// `start_pos`, the start of the entry at `start_index`.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn with_cipher(
+        start_pos: u64,
+        start_index: u64,
+        db_file: &'t mut File,
+        format_version: u16,
+        cipher: &'t Cipher,
+    ) -> Self {
+        let mut iter = Self::with_format(start_pos, db_file, format_version);
+        iter.cipher = Some(cipher);
+        iter.index = start_index;
+        iter
+    }
+
+    /// End the iteration at an entry which is cut short by the end of the file, which is an
+    /// entry a writer is still appending when the file is read by a read-only handle.
+    pub(crate) fn stop_at_partial_tail(mut self) -> Self {
+        self.partial_tail_is_end = true;
+        self
+    }
+}
+
+impl<'t, T> EntryIter<'t, T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    fn read_entry(&mut self) -> Result<T, IterError> {
+        if self.format_version == 0 {
+            return format::decode_format_0(&mut self.db_file).map_err(|e| IterError::Bincode(*e));
+        }
+        let payload = format::read_entry(&mut self.db_file)?;
+        #[cfg(feature = "encryption")]
+        let payload = match self.cipher {
+            Some(cipher) => {
+                let entry: EncryptedEntry = bincode_options()
+                    .deserialize(&payload)
+                    .map_err(|e| IterError::Bincode(*e))?;
+                let payload = cipher
+                    .decrypt(entry, self.index)
+                    .ok_or(IterError::Decryption)?;
+                self.index += 1;
+                payload
+            }
+            None => payload,
+        };
+        format::decode_payload(&payload).map_err(|e| IterError::Bincode(*e))
+    }
 }
 
 impl<'t, T> Iterator for EntryIter<'t, T>
@@ -40,6 +119,21 @@ where
     type Item = Result<T, IterError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_offset()
+            .map(|res| res.map(|(_, changeset)| changeset))
+    }
+}
+
+impl<'t, T> EntryIter<'t, T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    /// Iterate over the entries alongside the byte offsets of the entries in the file.
+    pub fn with_offsets(self) -> OffsetEntryIter<'t, T> {
+        OffsetEntryIter(self)
+    }
+
+    fn next_with_offset(&mut self) -> Option<Result<(u64, T), IterError>> {
         if self.finished {
             return None;
         }
@@ -49,21 +143,25 @@ where
             }
 
             let pos_before_read = self.db_file.stream_position()?;
-            match bincode_options().deserialize_from(&mut self.db_file) {
-                Ok(changeset) => Ok(Some(changeset)),
+            match self.read_entry() {
+                Ok(changeset) => Ok(Some((pos_before_read, changeset))),
                 Err(e) => {
                     self.finished = true;
                     let pos_after_read = self.db_file.stream_position()?;
                     // allow unexpected EOF if 0 bytes were read
-                    if let bincode::ErrorKind::Io(inner) = &*e {
-                        if inner.kind() == io::ErrorKind::UnexpectedEof
-                            && pos_after_read == pos_before_read
-                        {
-                            return Ok(None);
+                    if let IterError::Bincode(bincode::ErrorKind::Io(inner)) = &e {
+                        if inner.kind() == io::ErrorKind::UnexpectedEof {
+                            if pos_after_read == pos_before_read {
+                                return Ok(None);
+                            }
+                            if self.partial_tail_is_end {
+                                self.db_file.seek(io::SeekFrom::Start(pos_before_read))?;
+                                return Ok(None);
+                            }
                         }
                     }
                     self.db_file.seek(io::SeekFrom::Start(pos_before_read))?;
-                    Err(IterError::Bincode(*e))
+                    Err(e)
                 }
             }
         })()
@@ -71,6 +169,24 @@ where
     }
 }
 
+/// Iterator over entries in a file store alongside their byte offsets, see
+/// [`EntryIter::with_offsets`].
+///
+/// The offset of an entry is where it starts in the file, which stays valid as changesets are
+/// appended, until the store is compacted.
+pub struct OffsetEntryIter<'t, T>(EntryIter<'t, T>);
+
+impl<'t, T> Iterator for OffsetEntryIter<'t, T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    type Item = Result<(u64, T), IterError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_with_offset()
+    }
+}
+
 impl<'t, T> Drop for EntryIter<'t, T> {
     fn drop(&mut self) {
         // This syncs the underlying file's offset with the buffer's position. This way, we
@@ -88,6 +204,16 @@ pub enum IterError {
     Io(io::Error),
     /// Failure to decode data from the file.
     Bincode(bincode::ErrorKind),
+    /// Failure to authenticate an entry of an encrypted store, which is corrupted.
+    Decryption,
+    /// The entry is written by a newer version of the crate, with an entry version which is not
+    /// supported.
+    UnsupportedEntryVersion {
+        /// The entry version of the entry
+        found: u16,
+        /// The latest supported entry version, [`ENTRY_VERSION`](crate::ENTRY_VERSION)
+        supported: u16,
+    },
 }
 
 impl core::fmt::Display for IterError {
@@ -95,6 +221,12 @@ impl core::fmt::Display for IterError {
         match self {
             IterError::Io(e) => write!(f, "io error trying to read entry {}", e),
             IterError::Bincode(e) => write!(f, "bincode error while reading entry {}", e),
+            IterError::Decryption => write!(f, "failed to authenticate encrypted entry"),
+            IterError::UnsupportedEntryVersion { found, supported } => write!(
+                f,
+                "entry has entry version {} but only versions up to {} are supported",
+                found, supported
+            ),
         }
     }
 }
@@ -0,0 +1,421 @@
+//! The on-disk format of a [`Store`](crate::Store).
+//!
+//! A file starts with the magic bytes of the application, followed by [`FORMAT_MARKER`] and the
+//! format version as a little-endian `u16`. Each entry is then the entry version and the length
+//! of the payload, as bincode varints, followed by the payload, which is the bincode of the
+//! changeset.
+//!
+//! Files written before the format was versioned have no format marker, and their entries are the
+//! bincode of the changesets, without a header. They are read as format version 0, with the
+//! layouts the changesets of `bdk_chain` had then, see [`decode_format_0`].
+
+use std::{
+    cell::Cell,
+    io::{self, Read, Write},
+};
+
+use bincode::Options;
+use serde::de::{self, DeserializeSeed, SeqAccess, Visitor};
+
+use crate::{bincode_options, IterError};
+
+/// The bytes after the magic bytes of a file with a format version.
+///
+/// `0xff` can't start the bincode of a length or of a collection of a changeset, so this doesn't
+/// start the first entry of a file of format version 0.
+pub(crate) const FORMAT_MARKER: [u8; 2] = [0xff, b'v'];
+
+/// The format version of the files written by this version of the crate.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// The entry version of the entries written by this version of the crate.
+pub const ENTRY_VERSION: u16 = 1;
+
+/// The fields of the changesets of `bdk_chain` in the files of format version 0, for the
+/// changesets which gained fields since.
+///
+/// The fields are added with `#[serde(default)]`, so that they take their default when an entry
+/// written with the layout is read.
+const FORMAT_0_LAYOUTS: &[&[&str]] = &[
+    // `CombinedChangeSet`, the changeset of `bdk_wallet`
+    &["chain", "indexed_tx_graph", "network"],
+    // `tx_graph::ChangeSet`
+    &["txs", "txouts", "anchors", "last_seen"],
+    // `keychain::ChangeSet`
+    &["keychains_added", "last_revealed"],
+];
+
+/// The length of the format header after the magic bytes.
+pub(crate) const HEADER_LEN: usize = FORMAT_MARKER.len() + 2;
+
+/// Write the format header of [`FORMAT_VERSION`].
+pub(crate) fn write_header(writer: &mut impl Write) -> Result<(), io::Error> {
+    writer.write_all(&FORMAT_MARKER)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())
+}
+
+/// Read the format version from the format `header`, which is the bytes after the magic bytes;
+/// `None` if the file has no format header, so it is of format version 0.
+pub(crate) fn read_version(header: &[u8]) -> Option<u16> {
+    if header.len() < HEADER_LEN || header[..FORMAT_MARKER.len()] != FORMAT_MARKER {
+        return None;
+    }
+    let version = &header[FORMAT_MARKER.len()..HEADER_LEN];
+    Some(u16::from_le_bytes([version[0], version[1]]))
+}
+
+/// The bytes of an entry of [`ENTRY_VERSION`] with `payload`.
+pub(crate) fn encode_entry(payload: &[u8]) -> Vec<u8> {
+    let mut entry = bincode_options()
+        .serialize(&(ENTRY_VERSION, payload.len() as u64))
+        .expect("must serialize the entry header");
+    entry.extend_from_slice(payload);
+    entry
+}
+
+/// Read the payload of the next entry from `reader`.
+pub(crate) fn read_entry(reader: &mut impl Read) -> Result<Vec<u8>, IterError> {
+    let (version, len) = bincode_options()
+        .deserialize_from::<_, (u16, u64)>(&mut *reader)
+        .map_err(|e| IterError::Bincode(*e))?;
+    if version > ENTRY_VERSION {
+        return Err(IterError::UnsupportedEntryVersion {
+            found: version,
+            supported: ENTRY_VERSION,
+        });
+    }
+    // the payload is read in chunks, so that a corrupted length doesn't allocate it all
+    let mut payload = Vec::new();
+    reader.take(len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != len {
+        return Err(IterError::Bincode(bincode::ErrorKind::Io(
+            io::ErrorKind::UnexpectedEof.into(),
+        )));
+    }
+    Ok(payload)
+}
+
+/// Deserialize the next entry of a file of format version 0 from `reader`.
+///
+/// The entries of format version 0 are not framed, so where a changeset ends is only known from
+/// the layout it was written with. A struct whose fields start with one of the
+/// [`FORMAT_0_LAYOUTS`] is read with the fields of the layout, its other fields take their
+/// `#[serde(default)]`, which migrates the files written before the fields were added. This
+/// applies to the changeset and to the structs in its fields, at any depth.
+pub(crate) fn decode_format_0<T: de::DeserializeOwned>(
+    reader: &mut impl Read,
+) -> Result<T, bincode::Error> {
+    let mut deserializer = bincode::Deserializer::with_reader(reader, bincode_options());
+    T::deserialize(Format0 {
+        de: &mut deserializer,
+    })
+}
+
+/// Deserialize the bincode `payload` of an entry.
+///
+/// The trailing fields of a changeset struct which are missing from `payload`, because it is
+/// written by an older version of the changeset, are left to the `#[serde(default)]` of the
+/// fields. The trailing bytes of a payload written by a newer version of the changeset are
+/// ignored.
+///
+/// Only the trailing fields of the top-level changeset struct are forward-compatible this way,
+/// and only if they have `#[serde(default)]`. The payload doesn't tell where a nested struct ends,
+/// so adding a field to a nested changeset, such as `tx_graph::ChangeSet`, is a breaking change
+/// of the format.
+pub(crate) fn decode_payload<T: de::DeserializeOwned>(payload: &[u8]) -> Result<T, bincode::Error> {
+    let remaining = Cell::new(payload.len());
+    let reader = TrackingReader {
+        data: payload,
+        remaining: &remaining,
+    };
+    let mut deserializer = bincode::Deserializer::with_reader(reader, bincode_options());
+    T::deserialize(TrailingDefaults {
+        de: &mut deserializer,
+        remaining: &remaining,
+    })
+}
+
+/// Reads a payload, keeping track of the number of bytes left to read.
+struct TrackingReader<'a> {
+    data: &'a [u8],
+    remaining: &'a Cell<usize>,
+}
+
+impl<'a> Read for TrackingReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.data.read(buf)?;
+        self.remaining.set(self.data.len());
+        Ok(n)
+    }
+}
+
+/// A bincode deserializer which ends a struct where the payload ends.
+struct TrailingDefaults<'a, D> {
+    de: &'a mut D,
+    remaining: &'a Cell<usize>,
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.de.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'a, 'de, D> de::Deserializer<'de> for TrailingDefaults<'a, D>
+where
+    for<'b> &'b mut D: de::Deserializer<'de, Error = bincode::Error>,
+{
+    type Error = bincode::Error;
+
+    forward_deserialize!(
+        deserialize_any,
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_option,
+        deserialize_unit,
+        deserialize_seq,
+        deserialize_map,
+        deserialize_identifier,
+        deserialize_ignored_any,
+    );
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        // bincode writes the fields of a struct in order, as a tuple
+        visitor.visit_seq(TrailingFields {
+            de: self.de,
+            remaining: self.remaining,
+            fields: fields.len(),
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_enum(name, variants, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The fields of a struct, which end early if the payload ends.
+struct TrailingFields<'a, D> {
+    de: &'a mut D,
+    remaining: &'a Cell<usize>,
+    fields: usize,
+}
+
+impl<'a, 'de, D> SeqAccess<'de> for TrailingFields<'a, D>
+where
+    for<'b> &'b mut D: de::Deserializer<'de, Error = bincode::Error>,
+{
+    type Error = bincode::Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Self::Error> {
+        if self.fields == 0 || self.remaining.get() == 0 {
+            return Ok(None);
+        }
+        self.fields -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields)
+    }
+}
+
+/// A bincode deserializer which reads the structs of [`FORMAT_0_LAYOUTS`] with the fields of
+/// their layout.
+struct Format0<'a, D> {
+    de: &'a mut D,
+}
+
+impl<'a, 'de, D> de::Deserializer<'de> for Format0<'a, D>
+where
+    for<'b> &'b mut D: de::Deserializer<'de, Error = bincode::Error>,
+{
+    type Error = bincode::Error;
+
+    forward_deserialize!(
+        deserialize_any,
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_option,
+        deserialize_unit,
+        deserialize_seq,
+        deserialize_map,
+        deserialize_identifier,
+        deserialize_ignored_any,
+    );
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let fields = FORMAT_0_LAYOUTS
+            .iter()
+            .find(|layout| fields.starts_with(layout))
+            .map_or(fields.len(), |layout| layout.len());
+        visitor.visit_seq(Format0Fields {
+            de: self.de,
+            fields,
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.deserialize_enum(name, variants, visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The fields of a struct in a file of format version 0.
+struct Format0Fields<'a, D> {
+    de: &'a mut D,
+    fields: usize,
+}
+
+impl<'a, 'de, D> SeqAccess<'de> for Format0Fields<'a, D>
+where
+    for<'b> &'b mut D: de::Deserializer<'de, Error = bincode::Error>,
+{
+    type Error = bincode::Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Self::Error> {
+        if self.fields == 0 {
+            return Ok(None);
+        }
+        self.fields -= 1;
+        seed.deserialize(Format0 { de: &mut *self.de }).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields)
+    }
+}
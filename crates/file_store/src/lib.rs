@@ -1,10 +1,15 @@
 #![doc = include_str!("../README.md")]
+#[cfg(feature = "encryption")]
+mod encryption;
 mod entry_iter;
+mod format;
+mod lock;
 mod store;
 use std::io;
 
 use bincode::{DefaultOptions, Options};
 pub use entry_iter::*;
+pub use format::{ENTRY_VERSION, FORMAT_VERSION};
 pub use store::*;
 
 pub(crate) fn bincode_options() -> impl bincode::Options {
@@ -18,6 +23,25 @@ pub enum FileError {
     Io(io::Error),
     /// Magic bytes do not match what is expected.
     InvalidMagicBytes { got: Vec<u8>, expected: Vec<u8> },
+    /// A stored changeset failed to read.
+    Entry(IterError),
+    /// The store is locked by another handle, in this or another process.
+    AlreadyLocked {
+        /// The process id of the handle which opened the store, if it is known.
+        pid_hint: Option<u32>,
+    },
+    /// The store is not encrypted with the given key.
+    WrongKey,
+    /// The store is opened read-only, see [`Store::open_read_only`], so it can't be written to.
+    ReadOnly,
+    /// The file is written by a newer version of the crate, with a format version which is not
+    /// supported.
+    UnsupportedVersion {
+        /// The format version of the file
+        found: u16,
+        /// The latest supported format version, [`FORMAT_VERSION`]
+        supported: u16,
+    },
 }
 
 impl core::fmt::Display for FileError {
@@ -29,14 +53,61 @@ impl core::fmt::Display for FileError {
                 "file has invalid magic bytes: expected={:?} got={:?}",
                 expected, got,
             ),
+            Self::Entry(e) => write!(f, "failed to read a stored changeset: {}", e),
+            Self::AlreadyLocked {
+                pid_hint: Some(pid),
+            } => write!(f, "store is locked by process {}", pid),
+            Self::AlreadyLocked { pid_hint: None } => write!(f, "store is locked"),
+            Self::WrongKey => write!(f, "store is not encrypted with the given key"),
+            Self::ReadOnly => write!(f, "{}", ReadOnlyError),
+            Self::UnsupportedVersion { found, supported } => write!(
+                f,
+                "file has format version {} but only versions up to {} are supported",
+                found, supported
+            ),
         }
     }
 }
 
 impl From<io::Error> for FileError {
     fn from(value: io::Error) -> Self {
+        if ReadOnlyError::is_read_only(&value) {
+            return Self::ReadOnly;
+        }
         Self::Io(value)
     }
 }
 
 impl std::error::Error for FileError {}
+
+/// The error inside the [`io::Error`] of a method which writes to a store opened read-only, such
+/// as [`Store::append_changeset`].
+///
+/// The [`io::Error`] is of kind [`io::ErrorKind::PermissionDenied`], use
+/// [`ReadOnlyError::is_read_only`] to tell it from the other errors. Converted to a
+/// [`FileError`], it is [`FileError::ReadOnly`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadOnlyError;
+
+impl ReadOnlyError {
+    /// Whether `error` is the error of a write to a store opened read-only.
+    pub fn is_read_only(error: &io::Error) -> bool {
+        error
+            .get_ref()
+            .map_or(false, |e| e.downcast_ref::<Self>().is_some())
+    }
+}
+
+impl core::fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "store is opened read-only")
+    }
+}
+
+impl std::error::Error for ReadOnlyError {}
+
+impl From<ReadOnlyError> for io::Error {
+    fn from(value: ReadOnlyError) -> Self {
+        io::Error::new(io::ErrorKind::PermissionDenied, value)
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::FileError;
+
+/// An advisory lock of a [`Store`](crate::Store) against other writers, held until it is dropped.
+///
+/// The lock is taken on a `<file_path>.lock` file next to the store's file, rather than on the
+/// store's file, which [`Store::compact_to`](crate::Store::compact_to) replaces. The holder
+/// writes its process id to the lock file, for the error of the next one. Read-only handles don't
+/// take the lock, see [`Store::open_read_only`](crate::Store::open_read_only).
+///
+/// The OS releases the lock when the lock file is closed, including when unwinding from a panic
+/// or when the process dies.
+#[derive(Debug)]
+pub(crate) struct StoreLock {
+    _file: File,
+}
+
+impl StoreLock {
+    /// Take the lock of the store at `file_path`.
+    ///
+    /// Returns [`FileError::AlreadyLocked`] if the lock is held by another handle, in this or
+    /// another process.
+    pub(crate) fn acquire(file_path: &Path) -> Result<Self, FileError> {
+        let mut lock_path = file_path.to_path_buf().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(lock_path)?;
+
+        match try_lock(&file) {
+            Ok(true) => {}
+            Ok(false) => {
+                let mut pid = String::new();
+                let pid_hint = file
+                    .read_to_string(&mut pid)
+                    .ok()
+                    .and_then(|_| pid.trim().parse().ok());
+                return Err(FileError::AlreadyLocked { pid_hint });
+            }
+            Err(e) => return Err(FileError::Io(e)),
+        }
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Try to lock `file` without blocking, returns whether it is locked.
+#[cfg(unix)]
+fn try_lock(file: &File) -> Result<bool, io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the file descriptor is valid for the lifetime of `file`
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+/// Try to lock `file` without blocking, returns whether it is locked.
+#[cfg(windows)]
+fn try_lock(file: &File) -> Result<bool, io::Error> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::{
+        Foundation::{ERROR_LOCK_VIOLATION, HANDLE},
+        Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY},
+        System::IO::OVERLAPPED,
+    };
+
+    // SAFETY: the handle is valid for the lifetime of `file`, and `overlapped` outlives the
+    // synchronous call
+    let locked = unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        // lock a byte past the end of the file, so that the process id stays readable
+        overlapped.Anonymous.Anonymous.OffsetHigh = 1;
+        LockFileEx(
+            file.as_raw_handle() as HANDLE,
+            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+            0,
+            1,
+            0,
+            &mut overlapped,
+        )
+    };
+    if locked != 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+/// Advisory locks are not supported on this platform, so the store is never locked.
+#[cfg(not(any(unix, windows)))]
+fn try_lock(_file: &File) -> Result<bool, io::Error> {
+    Ok(true)
+}
@@ -1,4 +1,9 @@
-use crate::{bincode_options, EntryIter, FileError, IterError};
+#[cfg(feature = "encryption")]
+use crate::encryption::{Cipher, KEY_CHECK_LEN};
+use crate::{
+    bincode_options, format, lock::StoreLock, EntryIter, FileError, IterError, OffsetEntryIter,
+    ReadOnlyError, FORMAT_VERSION,
+};
 use bdk_chain::Append;
 use bincode::Options;
 use std::{
@@ -6,7 +11,7 @@ use std::{
     fs::{File, OpenOptions},
     io::{self, Read, Seek, Write},
     marker::PhantomData,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 /// Persists an append-only list of changesets (`C`) to a single file.
@@ -16,7 +21,23 @@ where
     C: Sync + Send,
 {
     magic_len: usize,
+    /// The format version of the file, see [`FORMAT_VERSION`].
+    format_version: u16,
+    /// The length of the format header after the magic bytes, 0 for format version 0.
+    header_len: usize,
     db_file: File,
+    file_path: PathBuf,
+    /// The lock against other writers, `None` if the store is opened read-only.
+    _lock: Option<StoreLock>,
+    read_only: bool,
+    durability: DurabilityPolicy,
+    /// The cipher of the entries, if the store is encrypted.
+    #[cfg(feature = "encryption")]
+    cipher: Option<Cipher>,
+    /// The file position and the index of the entry after the last appended one, so that the
+    /// entries are not counted again to encrypt the next one.
+    #[cfg(feature = "encryption")]
+    next_entry: Option<(u64, u64)>,
     marker: PhantomData<C>,
 }
 
@@ -31,7 +52,8 @@ where
     /// Create a new [`Store`] file in write-only mode; error if the file exists.
     ///
     /// `magic` is the prefixed bytes to write to the new file. This will be checked when opening
-    /// the `Store` in the future with [`open`].
+    /// the `Store` in the future with [`open`]. The store is locked until it is dropped, as with
+    /// [`open`].
     ///
     /// [`open`]: Store::open
     pub fn create_new<P>(magic: &[u8], file_path: P) -> Result<Self, FileError>
@@ -46,35 +68,97 @@ where
                 "file already exists",
             )));
         }
+        let lock = StoreLock::acquire(file_path.as_ref())?;
         let mut f = OpenOptions::new()
             .create(true)
             .read(true)
             .write(true)
             .truncate(true)
-            .open(file_path)?;
+            .open(&file_path)?;
         f.write_all(magic)?;
+        format::write_header(&mut f)?;
         Ok(Self {
             magic_len: magic.len(),
+            format_version: FORMAT_VERSION,
+            header_len: format::HEADER_LEN,
             db_file: f,
+            file_path: file_path.as_ref().to_path_buf(),
+            _lock: Some(lock),
+            read_only: false,
+            durability: DurabilityPolicy::default(),
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            #[cfg(feature = "encryption")]
+            next_entry: None,
             marker: Default::default(),
         })
     }
 
     /// Open an existing [`Store`].
     ///
-    /// Use [`create_new`] to create a new `Store`.
+    /// Use [`create_new`] to create a new `Store`. The store is locked against other writers until
+    /// it is dropped, see [`open_read_only`] to only read it.
     ///
     /// # Errors
     ///
     /// If the prefixed bytes of the opened file does not match the provided `magic`, the
-    /// [`FileError::InvalidMagicBytes`] error variant will be returned.
+    /// [`FileError::InvalidMagicBytes`] error variant will be returned. If the store is opened by
+    /// another writer, in this or another process, [`FileError::AlreadyLocked`] is returned.
     ///
     /// [`create_new`]: Store::create_new
+    /// [`open_read_only`]: Store::open_read_only
     pub fn open<P>(magic: &[u8], file_path: P) -> Result<Self, FileError>
     where
         P: AsRef<Path>,
     {
-        let mut f = OpenOptions::new().read(true).write(true).open(file_path)?;
+        let f = OpenOptions::new().read(true).write(true).open(&file_path)?;
+        Self::open_file(magic, file_path.as_ref(), f, false)
+    }
+
+    /// Open an existing [`Store`] to read it only, such as for a backup or inspection tool.
+    ///
+    /// The file is opened read-only and never written to. [`iter_changesets`] and
+    /// [`aggregate_changesets`] read the changesets as usual, while the methods which would write
+    /// to the store, such as [`append_changeset`] and [`compact`], fail with [`ReadOnlyError`] or
+    /// [`FileError::ReadOnly`].
+    ///
+    /// A read-only handle doesn't take the lock of the writers, so the store can be opened
+    /// read-only while a writer has it open with [`open`], such as a running daemon, and a writer
+    /// can open it while read-only handles have it open. A read-only handle reads the changesets
+    /// which the writer has appended so far: a changeset which the writer is still appending is
+    /// left out, rather than read as a corrupted entry. A compaction by the writer replaces the
+    /// file, which the read-only handle keeps reading as it was until it is opened again. On
+    /// Windows, the replacement may fail while the file is opened read-only.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`open`], apart from [`FileError::AlreadyLocked`].
+    ///
+    /// [`open`]: Store::open
+    /// [`iter_changesets`]: Store::iter_changesets
+    /// [`aggregate_changesets`]: Store::aggregate_changesets
+    /// [`append_changeset`]: Store::append_changeset
+    /// [`compact`]: Store::compact
+    /// [`ReadOnlyError`]: crate::ReadOnlyError
+    pub fn open_read_only<P>(magic: &[u8], file_path: P) -> Result<Self, FileError>
+    where
+        P: AsRef<Path>,
+    {
+        let f = OpenOptions::new().read(true).open(&file_path)?;
+        Self::open_file(magic, file_path.as_ref(), f, true)
+    }
+
+    fn open_file(
+        magic: &[u8],
+        file_path: &Path,
+        mut f: File,
+        read_only: bool,
+    ) -> Result<Self, FileError> {
+        let lock = if read_only {
+            None
+        } else {
+            Some(StoreLock::acquire(file_path)?)
+        };
 
         let mut magic_buf = vec![0_u8; magic.len()];
         f.read_exact(&mut magic_buf)?;
@@ -85,13 +169,91 @@ where
             });
         }
 
+        let mut header = Vec::new();
+        (&mut f)
+            .take(format::HEADER_LEN as u64)
+            .read_to_end(&mut header)?;
+        let (format_version, header_len) = match format::read_version(&header) {
+            Some(version) if version > FORMAT_VERSION => {
+                return Err(FileError::UnsupportedVersion {
+                    found: version,
+                    supported: FORMAT_VERSION,
+                })
+            }
+            Some(version) => (version, format::HEADER_LEN),
+            None => {
+                f.seek(io::SeekFrom::Start(magic.len() as u64))?;
+                (0, 0)
+            }
+        };
+
         Ok(Self {
             magic_len: magic.len(),
+            format_version,
+            header_len,
             db_file: f,
+            file_path: file_path.to_path_buf(),
+            _lock: lock,
+            read_only,
+            durability: DurabilityPolicy::default(),
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            #[cfg(feature = "encryption")]
+            next_entry: None,
             marker: Default::default(),
         })
     }
 
+    /// Create a new encrypted [`Store`] file; error if the file exists.
+    ///
+    /// Each changeset is encrypted and authenticated with ChaCha20-Poly1305 under `key`, with a
+    /// random nonce stored alongside it, so that the transactions and addresses of a wallet are
+    /// not readable from the file. A key-check header after the magic bytes lets [`open_encrypted`]
+    /// detect a wrong key. Deriving `key` from a passphrase is left to the caller.
+    ///
+    /// See [`create_new`] for `magic`.
+    ///
+    /// [`create_new`]: Store::create_new
+    /// [`open_encrypted`]: Store::open_encrypted
+    #[cfg(feature = "encryption")]
+    pub fn create_encrypted<P>(magic: &[u8], file_path: P, key: [u8; 32]) -> Result<Self, FileError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut store = Self::create_new(magic, file_path)?;
+        let cipher = Cipher::new(key, magic);
+        store.db_file.write_all(&cipher.key_check()?)?;
+        store.cipher = Some(cipher);
+        Ok(store)
+    }
+
+    /// Open an existing [`Store`] created with [`create_encrypted`] or encrypted with
+    /// [`encrypt_in_place`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FileError::WrongKey`] if the store is not encrypted with `key`, and the errors
+    /// of [`open`].
+    ///
+    /// [`create_encrypted`]: Store::create_encrypted
+    /// [`encrypt_in_place`]: Store::encrypt_in_place
+    /// [`open`]: Store::open
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted<P>(magic: &[u8], file_path: P, key: [u8; 32]) -> Result<Self, FileError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut store = Self::open(magic, file_path)?;
+        let mut header = [0_u8; KEY_CHECK_LEN];
+        store.db_file.read_exact(&mut header)?;
+        let cipher = Cipher::new(key, magic);
+        if !cipher.verify_key_check(&header) {
+            return Err(FileError::WrongKey);
+        }
+        store.cipher = Some(cipher);
+        Ok(store)
+    }
+
     /// Attempt to open existing [`Store`] file; create it if the file is non-existent.
     ///
     /// Internally, this calls either [`open`] or [`create_new`].
@@ -109,6 +271,85 @@ where
         }
     }
 
+    /// Open an existing [`Store`], and move the bytes after the last readable changeset to a
+    /// backup file.
+    ///
+    /// A write cut short, such as by a power loss, leaves a partial changeset at the end of the
+    /// file, which [`aggregate_changesets`] reports as an error. This recovers the changesets
+    /// before it: the bytes from the first unreadable changeset to the end of the file are moved
+    /// to a file named `<file_path>.corrupt-<unix timestamp in milliseconds>` next to the store's
+    /// file, and the store's file is truncated to the readable changesets. A store which is not
+    /// corrupted is opened as with [`open`], without a backup file.
+    ///
+    /// Recovery discards the changesets after a corrupted one, so it is not done by [`open`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`open`], or [`FileError::Io`] if reading the changesets, writing the
+    /// backup or truncating fails.
+    ///
+    /// [`aggregate_changesets`]: Store::aggregate_changesets
+    /// [`open`]: Store::open
+    pub fn open_or_recover<P>(
+        magic: &[u8],
+        file_path: P,
+    ) -> Result<(Self, RecoveryReport), FileError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut store = Self::open(magic, file_path)?;
+        let mut recovered_entries = 0;
+        for changeset in store.iter_changesets() {
+            match changeset {
+                Ok(_) => recovered_entries += 1,
+                Err(IterError::Io(e)) => return Err(FileError::Io(e)),
+                // the iterator stops at the start of the unreadable changeset
+                Err(IterError::Bincode(_)) | Err(IterError::Decryption) => break,
+                // a newer entry is not corrupted, it must not be discarded
+                Err(e @ IterError::UnsupportedEntryVersion { .. }) => {
+                    return Err(FileError::Entry(e))
+                }
+            }
+        }
+
+        let good_len = store.db_file.stream_position()?;
+        let discarded_bytes = store.len_bytes()? - good_len;
+        let mut backup_path = None;
+        if discarded_bytes > 0 {
+            let mut tail = Vec::new();
+            store.db_file.read_to_end(&mut tail)?;
+
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default();
+            let mut path = store.file_path.clone().into_os_string();
+            path.push(format!(".corrupt-{}", timestamp));
+            let path = PathBuf::from(path);
+            let mut backup_file = OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(&path)?;
+            backup_file.write_all(&tail)?;
+            // the tail must be backed up before it is truncated
+            backup_file.sync_all()?;
+
+            store.db_file.set_len(good_len)?;
+            store.db_file.sync_all()?;
+            store.db_file.seek(io::SeekFrom::Start(good_len))?;
+            backup_path = Some(path);
+        }
+
+        Ok((
+            store,
+            RecoveryReport {
+                recovered_entries,
+                discarded_bytes,
+                backup_path,
+            },
+        ))
+    }
+
     /// Iterates over the stored changeset from first to last, changing the seek position at each
     /// iteration.
     ///
@@ -119,7 +360,91 @@ where
     /// always iterate over all entries until `None` is returned if you want your next write to go
     /// at the end; otherwise, you will write over existing entries.
     pub fn iter_changesets(&mut self) -> EntryIter<C> {
-        EntryIter::new(self.magic_len as u64, &mut self.db_file)
+        let start_pos = self.entries_start();
+        self.iter_changesets_from(start_pos, 0)
+    }
+
+    /// Iterates over the stored changesets as [`iter_changesets`], alongside the byte offsets of
+    /// the changesets in the file.
+    ///
+    /// The changesets are read one at a time, so the file is never loaded into memory. An offset
+    /// stays valid as changesets are appended, so that it can be passed to [`load_from`] later,
+    /// or used to copy only the bytes after it. [`compact`] and [`compact_to`] rewrite the file,
+    /// which invalidates the offsets.
+    ///
+    /// **WARNING**: This method changes the write position in the underlying file, as
+    /// [`iter_changesets`].
+    ///
+    /// [`iter_changesets`]: Store::iter_changesets
+    /// [`load_from`]: Store::load_from
+    /// [`compact`]: Store::compact
+    /// [`compact_to`]: Store::compact_to
+    pub fn iter_changesets_with_offsets(&mut self) -> OffsetEntryIter<'_, C> {
+        self.iter_changesets().with_offsets()
+    }
+
+    /// Aggregate the stored changesets from the changeset at `offset` to the last, such as the
+    /// changesets after a snapshot.
+    ///
+    /// `offset` is an offset from [`iter_changesets_with_offsets`]; an offset which is not the
+    /// start of a changeset fails to read. Returns `None` if there is no changeset from
+    /// `offset`. The errors are those of [`aggregate_changesets`], an `offset` before the first
+    /// changeset or past the end of the file is an [`IterError::Io`] error.
+    ///
+    /// **WARNING**: This method changes the write position of the underlying file, as
+    /// [`aggregate_changesets`].
+    ///
+    /// [`iter_changesets_with_offsets`]: Store::iter_changesets_with_offsets
+    /// [`aggregate_changesets`]: Store::aggregate_changesets
+    pub fn load_from(&mut self, offset: u64) -> Result<Option<C>, AggregateChangesetsError<C>> {
+        let len = self.len_bytes().map_err(|e| AggregateChangesetsError {
+            changeset: None,
+            iter_error: IterError::Io(e),
+        })?;
+        if offset < self.entries_start() || offset > len {
+            return Err(AggregateChangesetsError {
+                changeset: None,
+                iter_error: IterError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "offset is not within the stored changesets",
+                )),
+            });
+        }
+        #[cfg(feature = "encryption")]
+        let start_index = match self.cipher {
+            Some(_) => self
+                .entry_index_at(offset)
+                .map_err(|e| AggregateChangesetsError {
+                    changeset: None,
+                    iter_error: IterError::Io(e),
+                })?,
+            None => 0,
+        };
+        #[cfg(not(feature = "encryption"))]
+        let start_index = 0;
+        aggregate(self.iter_changesets_from(offset, start_index))
+    }
+
+    /// Iterate over the changesets from `start_pos`, the start of the entry at `start_index`.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn iter_changesets_from(&mut self, start_pos: u64, start_index: u64) -> EntryIter<'_, C> {
+        #[cfg(feature = "encryption")]
+        let iter = match &self.cipher {
+            Some(cipher) => EntryIter::with_cipher(
+                start_pos,
+                start_index,
+                &mut self.db_file,
+                self.format_version,
+                cipher,
+            ),
+            None => EntryIter::with_format(start_pos, &mut self.db_file, self.format_version),
+        };
+        #[cfg(not(feature = "encryption"))]
+        let iter = EntryIter::with_format(start_pos, &mut self.db_file, self.format_version);
+        if self.read_only {
+            return iter.stop_at_partial_tail();
+        }
+        iter
     }
 
     /// Loads all the changesets that have been stored as one giant changeset.
@@ -135,23 +460,7 @@ where
     /// **WARNING**: This method changes the write position of the underlying file. The next
     /// changeset will be written over the erroring entry (or the end of the file if none existed).
     pub fn aggregate_changesets(&mut self) -> Result<Option<C>, AggregateChangesetsError<C>> {
-        let mut changeset = Option::<C>::None;
-        for next_changeset in self.iter_changesets() {
-            let next_changeset = match next_changeset {
-                Ok(next_changeset) => next_changeset,
-                Err(iter_error) => {
-                    return Err(AggregateChangesetsError {
-                        changeset,
-                        iter_error,
-                    })
-                }
-            };
-            match &mut changeset {
-                Some(changeset) => changeset.append(next_changeset),
-                changeset => *changeset = Some(next_changeset),
-            }
-        }
-        Ok(changeset)
+        aggregate(self.iter_changesets())
     }
 
     /// Append a new changeset to the file and truncate the file to the end of the appended
@@ -159,29 +468,425 @@ where
     ///
     /// The truncation is to avoid the possibility of having a valid but inconsistent changeset
     /// directly after the appended changeset.
+    ///
+    /// A file of format version 0 is upgraded to [`FORMAT_VERSION`] with [`compact`] first, from
+    /// the changesets before the write position.
+    ///
+    /// [`compact`]: Store::compact
     pub fn append_changeset(&mut self, changeset: &C) -> Result<(), io::Error> {
         // no need to write anything if changeset is empty
         if changeset.is_empty() {
             return Ok(());
         }
-
-        bincode_options()
-            .serialize_into(&mut self.db_file, changeset)
-            .map_err(|e| match *e {
-                bincode::ErrorKind::Io(error) => error,
-                unexpected_err => panic!("unexpected bincode error: {}", unexpected_err),
+        self.check_writable()?;
+        if self.format_version == 0 {
+            // an entry of format version 0 has the layout of the changeset when the format was
+            // current, so the file is upgraded before the changeset is appended, with the
+            // entries which the append keeps
+            let pos = self.db_file.stream_position()?;
+            self.db_file.set_len(pos)?;
+            self.compact().map_err(|e| match e {
+                FileError::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
             })?;
+        }
+
+        #[cfg(feature = "encryption")]
+        let index = match self.cipher {
+            Some(_) => {
+                let pos = self.db_file.stream_position()?;
+                self.entry_index_at(pos)?
+            }
+            None => 0,
+        };
+        #[cfg(not(feature = "encryption"))]
+        let index = 0;
+        let entry = self.encode_entry(changeset, self.format_version, index)?;
+        self.db_file.write_all(&entry)?;
 
         // truncate file after this changeset addition
         // if this is not done, data after this changeset may represent valid changesets, however
         // applying those changesets on top of this one may result in an inconsistent state
         let pos = self.db_file.stream_position()?;
         self.db_file.set_len(pos)?;
+        #[cfg(feature = "encryption")]
+        {
+            self.next_entry = Some((pos, index + 1));
+        }
+
+        match self.durability {
+            DurabilityPolicy::None => {}
+            DurabilityPolicy::FsyncData => self.db_file.sync_data()?,
+            DurabilityPolicy::FsyncAll => self.db_file.sync_all()?,
+        }
+        Ok(())
+    }
+
+    /// Replace all the stored changesets with the single `changeset`.
+    ///
+    /// `changeset` should be equivalent to the aggregate of the stored changesets, such as a
+    /// wallet snapshot, so that the store is faster to load. It is written to a new file next to
+    /// the store's file, which then replaces it: if this fails, the stored changesets are left
+    /// as they were.
+    ///
+    /// The next changeset is appended after `changeset`.
+    pub fn compact_to(&mut self, changeset: &C) -> Result<(), io::Error> {
+        self.check_writable()?;
+        let (compact_path, compact_file) = self.write_compact_file(changeset)?;
+        self.replace_with(&compact_path, compact_file)
+    }
+
+    /// Aggregate the stored changesets and replace them with the aggregate, with
+    /// [`compact_to`].
+    ///
+    /// The file of a store which is appended to on every sync grows without bound, even though
+    /// the aggregate is small. Use [`len_bytes`] or [`entry_count`] to decide when to compact.
+    ///
+    /// # Errors
+    ///
+    /// If a stored changeset fails to read, [`FileError::Entry`] is returned and the file is left
+    /// as it was, since the changesets after it would be lost.
+    ///
+    /// [`compact_to`]: Store::compact_to
+    /// [`len_bytes`]: Store::len_bytes
+    /// [`entry_count`]: Store::entry_count
+    pub fn compact(&mut self) -> Result<CompactStats, FileError> {
+        self.check_writable()?;
+        let bytes_before = self.len_bytes()?;
+        let mut entries_before = 0;
+        let mut aggregate = C::default();
+        for changeset in self.iter_changesets() {
+            aggregate.append(changeset.map_err(FileError::Entry)?);
+            entries_before += 1;
+        }
+        self.compact_to(&aggregate)?;
+        Ok(CompactStats {
+            entries_before,
+            entries_after: if aggregate.is_empty() { 0 } else { 1 },
+            bytes_before,
+            bytes_after: self.len_bytes()?,
+        })
+    }
+
+    /// Encrypt the store under `key`, as with [`create_encrypted`], by compacting it.
+    ///
+    /// The stored changesets are aggregated and written encrypted to a new file, which replaces
+    /// the store's file, see [`compact_to`]. An encrypted store is encrypted again under the new
+    /// `key`. The blocks of the replaced file may still be readable on the disk until they are
+    /// overwritten.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`compact`], the store is left as it was then.
+    ///
+    /// [`create_encrypted`]: Store::create_encrypted
+    /// [`compact_to`]: Store::compact_to
+    /// [`compact`]: Store::compact
+    #[cfg(feature = "encryption")]
+    pub fn encrypt_in_place(&mut self, key: [u8; 32]) -> Result<(), FileError> {
+        self.check_writable()?;
+        let mut aggregate = C::default();
+        for changeset in self.iter_changesets() {
+            aggregate.append(changeset.map_err(FileError::Entry)?);
+        }
+        let magic = self.read_magic()?;
+        let old_cipher = self.cipher.replace(Cipher::new(key, &magic));
+        if let Err(e) = self.compact_to(&aggregate) {
+            self.cipher = old_cipher;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// The format version of the file, see [`FORMAT_VERSION`].
+    ///
+    /// A file of an older format version is read and appended to in its format, until it is
+    /// upgraded to [`FORMAT_VERSION`] by [`compact`](Store::compact) or
+    /// [`compact_to`](Store::compact_to). A file of format version 0 is upgraded by the first
+    /// changeset appended to it.
+    pub fn format_version(&self) -> u16 {
+        self.format_version
+    }
+
+    /// Set when the changesets are synced to disk, see [`DurabilityPolicy`].
+    ///
+    /// With [`DurabilityPolicy::FsyncAll`], the store's file and its directory are synced right
+    /// away, so that the creation of the store is on disk. Call this right after constructing the
+    /// store, the default is [`DurabilityPolicy::None`].
+    pub fn with_durability(mut self, durability: DurabilityPolicy) -> Result<Self, io::Error> {
+        self.durability = durability;
+        if durability == DurabilityPolicy::FsyncAll && !self.read_only {
+            self.db_file.sync_all()?;
+            self.sync_dir()?;
+        }
+        Ok(self)
+    }
 
+    /// The [`DurabilityPolicy`] of the store.
+    pub fn durability(&self) -> DurabilityPolicy {
+        self.durability
+    }
+
+    /// Sync the store's file to disk, including the changesets appended since the last sync.
+    ///
+    /// This is for [`DurabilityPolicy::None`], to sync at points chosen by the application, such
+    /// as after a batch of changesets.
+    pub fn sync(&self) -> Result<(), io::Error> {
+        // a read-only handle has nothing to sync, and may not be allowed to
+        if self.read_only {
+            return Ok(());
+        }
+        self.db_file.sync_all()
+    }
+
+    /// The length of the file in bytes, including the magic bytes.
+    pub fn len_bytes(&self) -> Result<u64, io::Error> {
+        Ok(self.db_file.metadata()?.len())
+    }
+
+    /// The number of stored changesets.
+    ///
+    /// This reads every changeset of the file, but keeps the write position of the underlying
+    /// file.
+    pub fn entry_count(&mut self) -> Result<usize, IterError> {
+        let pos = self.db_file.stream_position()?;
+        let count = self
+            .iter_changesets()
+            .try_fold(0, |count, changeset| changeset.map(|_| count + 1));
+        self.db_file.seek(io::SeekFrom::Start(pos))?;
+        count
+    }
+
+    /// Sync the directory of the store's file, so that the creation or replacement of the file is
+    /// on disk.
+    fn sync_dir(&self) -> Result<(), io::Error> {
+        // directories can't be opened as files on other platforms
+        #[cfg(unix)]
+        if let Some(dir) = self.file_path.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), io::Error> {
+        if self.read_only {
+            return Err(ReadOnlyError.into());
+        }
+        Ok(())
+    }
+
+    /// The file position of the first changeset.
+    fn entries_start(&self) -> u64 {
+        let start = self.magic_len + self.header_len;
+        #[cfg(feature = "encryption")]
+        if self.cipher.is_some() {
+            return (start + KEY_CHECK_LEN) as u64;
+        }
+        start as u64
+    }
+
+    /// The index of the entry which starts at the file position `pos`, which is the number of
+    /// entries before it.
+    ///
+    /// The entries are not decrypted, only their lengths are read.
+    #[cfg(feature = "encryption")]
+    fn entry_index_at(&mut self, pos: u64) -> Result<u64, io::Error> {
+        if let Some((next_pos, next_index)) = self.next_entry {
+            if next_pos == pos {
+                return Ok(next_index);
+            }
+        }
+        let restore_pos = self.db_file.stream_position()?;
+        let start = self.entries_start();
+        let mut reader = io::BufReader::new(&mut self.db_file);
+        reader.seek(io::SeekFrom::Start(start))?;
+        let mut index = 0;
+        let mut entry_pos = start;
+        while entry_pos < pos {
+            format::read_entry(&mut reader).map_err(|e| match e {
+                IterError::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+            })?;
+            index += 1;
+            entry_pos = reader.stream_position()?;
+        }
+        drop(reader);
+        self.db_file.seek(io::SeekFrom::Start(restore_pos))?;
+        if entry_pos != pos {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "position is not the start of a changeset",
+            ));
+        }
+        Ok(index)
+    }
+
+    /// The bytes of the entry of `changeset` in a file of `format_version`, which is encrypted as
+    /// the entry at `index` if the store is.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn encode_entry(
+        &self,
+        changeset: &C,
+        format_version: u16,
+        index: u64,
+    ) -> Result<Vec<u8>, io::Error> {
+        let to_io_error = |e: bincode::Error| match *e {
+            bincode::ErrorKind::Io(error) => error,
+            unexpected_err => panic!("unexpected bincode error: {}", unexpected_err),
+        };
+        let payload = bincode_options()
+            .serialize(changeset)
+            .map_err(to_io_error)?;
+        if format_version == 0 {
+            return Ok(payload);
+        }
+        #[cfg(feature = "encryption")]
+        let payload = match &self.cipher {
+            Some(cipher) => bincode_options()
+                .serialize(&cipher.encrypt(payload, index)?)
+                .map_err(to_io_error)?,
+            None => payload,
+        };
+        Ok(format::encode_entry(&payload))
+    }
+
+    /// Write the magic bytes, the format header of [`FORMAT_VERSION`] and `changeset` to a new
+    /// file next to the store's file.
+    fn write_compact_file(&mut self, changeset: &C) -> Result<(PathBuf, File), io::Error> {
+        let magic = self.read_magic()?;
+
+        let mut compact_path = self.file_path.clone().into_os_string();
+        compact_path.push(".compact");
+        let compact_path = PathBuf::from(compact_path);
+        let mut compact_file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&compact_path)?;
+        compact_file.write_all(&magic)?;
+        format::write_header(&mut compact_file)?;
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            compact_file.write_all(&cipher.key_check()?)?;
+        }
+        if !changeset.is_empty() {
+            compact_file.write_all(&self.encode_entry(changeset, FORMAT_VERSION, 0)?)?;
+        }
+        // the new file must be on disk before it replaces the old one
+        compact_file.sync_all()?;
+        Ok((compact_path, compact_file))
+    }
+
+    /// The magic bytes of the store's file, which changes the write position of the file.
+    fn read_magic(&mut self) -> Result<Vec<u8>, io::Error> {
+        let mut magic = vec![0_u8; self.magic_len];
+        self.db_file.rewind()?;
+        self.db_file.read_exact(&mut magic)?;
+        Ok(magic)
+    }
+
+    /// Atomically replace the store's file with the `compact_file` at `compact_path`.
+    fn replace_with(
+        &mut self,
+        compact_path: &Path,
+        mut compact_file: File,
+    ) -> Result<(), io::Error> {
+        std::fs::rename(compact_path, &self.file_path)?;
+        if self.durability == DurabilityPolicy::FsyncAll {
+            self.sync_dir()?;
+        }
+
+        compact_file.seek(io::SeekFrom::End(0))?;
+        self.db_file = compact_file;
+        self.format_version = FORMAT_VERSION;
+        self.header_len = format::HEADER_LEN;
+        #[cfg(feature = "encryption")]
+        {
+            self.next_entry = None;
+        }
         Ok(())
     }
 }
 
+/// Aggregate the changesets of `iter`, see [`Store::aggregate_changesets`].
+fn aggregate<C: Append + serde::de::DeserializeOwned>(
+    iter: EntryIter<C>,
+) -> Result<Option<C>, AggregateChangesetsError<C>> {
+    let mut changeset = Option::<C>::None;
+    for next_changeset in iter {
+        let next_changeset = match next_changeset {
+            Ok(next_changeset) => next_changeset,
+            Err(iter_error) => {
+                return Err(AggregateChangesetsError {
+                    changeset,
+                    iter_error,
+                })
+            }
+        };
+        match &mut changeset {
+            Some(changeset) => changeset.append(next_changeset),
+            changeset => *changeset = Some(next_changeset),
+        }
+    }
+    Ok(changeset)
+}
+
+/// When a [`Store`] syncs its file to disk, see [`Store::with_durability`].
+///
+/// Until a changeset is synced, it is only in the buffers of the OS, and a power loss or a crash
+/// of the OS loses it, even though [`Store::append_changeset`] returned `Ok`. A crash of the
+/// process doesn't lose it. Syncing costs a round trip to the disk per changeset, which is
+/// from tens of microseconds on an SSD to tens of milliseconds on a spinning disk, compared to
+/// microseconds to only write to the buffers.
+///
+/// Compaction always syncs the compacted file before it replaces the store's file, so that a
+/// crash leaves either the old or the new file intact, whatever the policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DurabilityPolicy {
+    /// Leave the syncing to the OS, which is the fastest. The last changesets may be lost on a
+    /// power loss; use [`Store::sync`] to sync at chosen points.
+    #[default]
+    None,
+    /// Sync the data of the file after each appended changeset, with `fdatasync` on Unix. This
+    /// skips the metadata which is not needed to read the data back, such as the modification
+    /// time, so it is cheaper than [`FsyncAll`](Self::FsyncAll).
+    FsyncData,
+    /// Sync the data and the metadata of the file after each appended changeset, and the
+    /// directory of the file when the store is created or replaced by compaction, so that the
+    /// file itself survives a power loss. This is the slowest.
+    FsyncAll,
+}
+
+/// The sizes of a [`Store`] before and after [`Store::compact`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactStats {
+    /// The number of changesets before compaction.
+    pub entries_before: usize,
+    /// The number of changesets after compaction, which is 1 unless the aggregate is empty.
+    pub entries_after: usize,
+    /// The length of the file in bytes before compaction.
+    pub bytes_before: u64,
+    /// The length of the file in bytes after compaction.
+    pub bytes_after: u64,
+}
+
+/// What [`Store::open_or_recover`] recovered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// The number of readable changesets which are kept.
+    pub recovered_entries: usize,
+    /// The number of bytes after the readable changesets which are moved to the backup file.
+    pub discarded_bytes: u64,
+    /// The path of the backup file of the discarded bytes, `None` if nothing is discarded.
+    pub backup_path: Option<PathBuf>,
+}
+
 /// Error type for [`Store::aggregate_changesets`].
 #[derive(Debug)]
 pub struct AggregateChangesetsError<C> {
@@ -204,9 +909,10 @@ impl<C: fmt::Debug> std::error::Error for AggregateChangesetsError<C> {}
 mod test {
     use super::*;
 
+    use crate::ENTRY_VERSION;
     use bincode::DefaultOptions;
     use std::{
-        collections::BTreeSet,
+        collections::{BTreeMap, BTreeSet},
         io::{Read, Write},
         vec::Vec,
     };
@@ -305,21 +1011,18 @@ mod test {
 
         drop(store);
 
-        let got_bytes = {
-            let mut buf = Vec::new();
-            file.reopen()
-                .unwrap()
-                .read_to_end(&mut buf)
-                .expect("should read");
-            buf
-        };
+        // the upgrade replaces the file, so it is read from its path
+        let got_bytes = std::fs::read(file.path()).expect("should read");
 
+        // the file of format version 0 is upgraded by the append
         let expected_bytes = {
             let mut buf = TEST_MAGIC_BYTES.to_vec();
-            DefaultOptions::new()
+            format::write_header(&mut buf).expect("should write");
+            let payload = DefaultOptions::new()
                 .with_varint_encoding()
-                .serialize_into(&mut buf, &changeset)
+                .serialize(&changeset)
                 .expect("should encode");
+            buf.extend(format::encode_entry(&payload));
             buf
         };
 
@@ -336,7 +1039,8 @@ mod test {
             TestChangeSet::from(["4".into(), "5".into(), "6".into()]),
         ];
         let last_changeset = TestChangeSet::from(["7".into(), "8".into(), "9".into()]);
-        let last_changeset_bytes = bincode_options().serialize(&last_changeset).unwrap();
+        let last_changeset_bytes =
+            format::encode_entry(&bincode_options().serialize(&last_changeset).unwrap());
 
         for short_write_len in 1..last_changeset_bytes.len() - 1 {
             let file_path = temp_dir.path().join(format!("{}.dat", short_write_len));
@@ -440,4 +1144,1032 @@ mod test {
             assert_eq!(aggregation, exp_aggregation);
         }
     }
+
+    #[test]
+    fn compact_to_replaces_changesets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changesets = [
+            TestChangeSet::from(["1".into()]),
+            TestChangeSet::from(["2".into(), "3".into()]),
+        ];
+
+        let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        for changeset in &changesets {
+            db.append_changeset(changeset).unwrap();
+        }
+        let snapshot = db.aggregate_changesets().unwrap().unwrap();
+        db.compact_to(&snapshot).expect("must compact");
+        assert!(!temp_dir.path().join("db_file.compact").exists());
+
+        // the store keeps appending after the snapshot
+        let last_changeset = TestChangeSet::from(["4".into()]);
+        db.append_changeset(&last_changeset).unwrap();
+        drop(db);
+
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        let stored = db
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("must read changesets");
+        assert_eq!(stored, [snapshot, last_changeset]);
+    }
+
+    #[test]
+    fn compact_aggregates_changesets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changesets = (0..20)
+            .map(|n| TestChangeSet::from([format!("{}", n % 5)]))
+            .collect::<Vec<_>>();
+
+        let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert_eq!(db.entry_count().unwrap(), 0);
+        for changeset in &changesets {
+            db.append_changeset(changeset).unwrap();
+        }
+        assert_eq!(db.entry_count().unwrap(), changesets.len());
+        let len_bytes = db.len_bytes().unwrap();
+        assert_eq!(len_bytes, std::fs::metadata(&file_path).unwrap().len());
+        let aggregate = db.aggregate_changesets().unwrap();
+
+        let stats = db.compact().expect("must compact");
+        assert_eq!(
+            stats,
+            CompactStats {
+                entries_before: changesets.len(),
+                entries_after: 1,
+                bytes_before: len_bytes,
+                bytes_after: db.len_bytes().unwrap(),
+            }
+        );
+        assert!(stats.bytes_after < stats.bytes_before);
+        assert_eq!(db.entry_count().unwrap(), 1);
+
+        // the store keeps appending after the aggregate
+        let last_changeset = TestChangeSet::from(["last".into()]);
+        db.append_changeset(&last_changeset).unwrap();
+        drop(db);
+
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        let stored = db
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("must read changesets");
+        assert_eq!(stored, [aggregate.unwrap(), last_changeset]);
+    }
+
+    #[test]
+    fn compact_fails_on_unreadable_changeset() {
+        let mut file = NamedTempFile::new().unwrap();
+        let mut data = TEST_MAGIC_BYTES.to_vec();
+        bincode_options()
+            .serialize_into(&mut data, &TestChangeSet::from(["1".into()]))
+            .unwrap();
+        data.extend([255_u8; 10]);
+        file.write_all(&data).unwrap();
+
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, file.path()).unwrap();
+        match db.compact() {
+            Err(FileError::Entry(IterError::Bincode(_))) => {}
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+        drop(db);
+
+        let mut got_bytes = Vec::new();
+        file.reopen().unwrap().read_to_end(&mut got_bytes).unwrap();
+        assert_eq!(got_bytes, data, "the file must be left as it was");
+    }
+
+    /// A crash between writing the compacted file and renaming it over the store's file leaves
+    /// both files intact.
+    #[test]
+    fn compact_crash_before_rename_leaves_store_intact() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changesets = [
+            TestChangeSet::from(["1".into()]),
+            TestChangeSet::from(["2".into(), "3".into()]),
+        ];
+
+        let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        for changeset in &changesets {
+            db.append_changeset(changeset).unwrap();
+        }
+        let aggregate = db.aggregate_changesets().unwrap().unwrap();
+        // the process dies before the rename
+        let (compact_path, _) = db.write_compact_file(&aggregate).unwrap();
+        drop(db);
+
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        let stored = db
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("the old file must be intact");
+        assert_eq!(stored, changesets);
+
+        let mut compacted = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, compact_path).unwrap();
+        let stored = compacted
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("the new file must be intact");
+        assert_eq!(stored, core::slice::from_ref(&aggregate));
+
+        // the next compaction overwrites the leftover file
+        let stats = db.compact().expect("must compact");
+        assert_eq!(stats.entries_after, 1);
+        assert!(!temp_dir.path().join("db_file.compact").exists());
+        assert_eq!(db.aggregate_changesets().unwrap(), Some(aggregate));
+    }
+
+    #[test]
+    fn open_or_recover_moves_corrupt_tail_to_backup() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let changesets = [
+            TestChangeSet::from(["1".into()]),
+            TestChangeSet::from(["2".into(), "3".into()]),
+        ];
+        // the length prefix of a payload of over 250 bytes takes 3 bytes
+        let last_changeset = TestChangeSet::from(["4".repeat(300)]);
+        let last_changeset_bytes =
+            format::encode_entry(&bincode_options().serialize(&last_changeset).unwrap());
+        // the entry version, then the marker byte of the payload length prefix
+        assert_eq!(last_changeset_bytes[1], 251);
+
+        let cases = [
+            ("mid-length-prefix", last_changeset_bytes[..2].to_vec()),
+            ("mid-payload", last_changeset_bytes[..100].to_vec()),
+            ("trailing-garbage", vec![255_u8; 50]),
+        ];
+        for (name, tail) in cases {
+            let file_path = temp_dir.path().join(name);
+            let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+            for changeset in &changesets {
+                db.append_changeset(changeset).unwrap();
+            }
+            let good_len = db.len_bytes().unwrap();
+            db.db_file.write_all(&tail).unwrap();
+            drop(db);
+
+            // `open` doesn't recover
+            let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+            db.aggregate_changesets()
+                .expect_err("must fail to read the corrupt tail");
+            drop(db);
+
+            let (mut db, report) =
+                Store::<TestChangeSet>::open_or_recover(&TEST_MAGIC_BYTES, &file_path)
+                    .expect("must recover");
+            assert_eq!(report.recovered_entries, changesets.len(), "{}", name);
+            assert_eq!(report.discarded_bytes, tail.len() as u64, "{}", name);
+            let backup_path = report.backup_path.expect("must back up the tail");
+            assert_eq!(std::fs::read(&backup_path).unwrap(), tail, "{}", name);
+            assert_eq!(db.len_bytes().unwrap(), good_len, "{}", name);
+
+            db.append_changeset(&last_changeset).unwrap();
+            drop(db);
+            let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+            let stored = db
+                .iter_changesets()
+                .collect::<Result<Vec<_>, _>>()
+                .expect("must read changesets");
+            assert_eq!(
+                stored,
+                [
+                    changesets[0].clone(),
+                    changesets[1].clone(),
+                    last_changeset.clone()
+                ],
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn open_or_recover_keeps_intact_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changeset = TestChangeSet::from(["1".into()]);
+
+        let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        db.append_changeset(&changeset).unwrap();
+        drop(db);
+
+        let (mut db, report) =
+            Store::<TestChangeSet>::open_or_recover(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert_eq!(
+            report,
+            RecoveryReport {
+                recovered_entries: 1,
+                discarded_bytes: 0,
+                backup_path: None,
+            }
+        );
+        assert!(
+            std::fs::read_dir(temp_dir.path())
+                .unwrap()
+                .all(|entry| !entry
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .contains(".corrupt-")),
+            "must not back up anything"
+        );
+        assert_eq!(db.aggregate_changesets().unwrap(), Some(changeset));
+    }
+
+    #[test]
+    fn store_is_locked_while_open() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+
+        let db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        match Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path) {
+            Err(FileError::AlreadyLocked { pid_hint }) => {
+                assert_eq!(pid_hint, Some(std::process::id()))
+            }
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+        // readers don't take the lock of the writers
+        let reader_a =
+            Store::<TestChangeSet>::open_read_only(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        drop(db);
+        let reader_b =
+            Store::<TestChangeSet>::open_read_only(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        let db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path)
+            .expect("must not be locked by the readers");
+        drop((reader_a, reader_b, db));
+
+        Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).expect("must be unlocked");
+    }
+
+    #[test]
+    fn store_lock_is_released_after_panic() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+
+        let path = file_path.clone();
+        let res = std::thread::spawn(move || {
+            let _db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, path).unwrap();
+            panic!("the holder of the lock panics");
+        })
+        .join();
+        assert!(res.is_err());
+
+        Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).expect("must be unlocked");
+    }
+
+    /// The environment variable with the path of the store which the child process opens.
+    const CHILD_STORE_PATH: &str = "BDK_FILE_STORE_TEST_CHILD_STORE_PATH";
+
+    /// The environment variable with the id of the parent process, which holds the lock.
+    const CHILD_PARENT_PID: &str = "BDK_FILE_STORE_TEST_CHILD_PARENT_PID";
+
+    /// The exit code of the child process which found the store locked by its parent.
+    const CHILD_FOUND_LOCK: i32 = 42;
+
+    /// The test runs itself in a child process, told apart by [`CHILD_STORE_PATH`], which must
+    /// fail to open the store locked by the parent.
+    #[test]
+    fn store_is_locked_across_processes() {
+        if let Some(file_path) = std::env::var_os(CHILD_STORE_PATH) {
+            let parent = std::env::var(CHILD_PARENT_PID)
+                .ok()
+                .and_then(|pid| pid.parse::<u32>().ok());
+            match Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, file_path) {
+                Err(FileError::AlreadyLocked { pid_hint }) if pid_hint == parent => {
+                    std::process::exit(CHILD_FOUND_LOCK)
+                }
+                unexpected => panic!("unexpected result: {:?}", unexpected),
+            }
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let _db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "store::test::store_is_locked_across_processes"])
+            .env(CHILD_STORE_PATH, &file_path)
+            .env(CHILD_PARENT_PID, std::process::id().to_string())
+            .output()
+            .expect("must run the test binary");
+        assert_eq!(output.status.code(), Some(CHILD_FOUND_LOCK), "{:?}", output);
+    }
+
+    #[cfg(feature = "encryption")]
+    const TEST_ADDRESS: &str = "bcrt1qjkmghjsxs5fkzrrl0df7ugp8jgzty2qtksvchm";
+
+    #[cfg(feature = "encryption")]
+    fn contains(bytes: &[u8], needle: &[u8]) -> bool {
+        bytes.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_store_round_trips() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let key = [7_u8; 32];
+        let changesets = [
+            TestChangeSet::from([TEST_ADDRESS.to_string()]),
+            TestChangeSet::from(["2".into(), "3".into()]),
+        ];
+
+        let mut db =
+            Store::<TestChangeSet>::create_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        for changeset in &changesets {
+            db.append_changeset(changeset).unwrap();
+        }
+        drop(db);
+
+        let bytes = std::fs::read(&file_path).unwrap();
+        assert!(bytes.starts_with(&TEST_MAGIC_BYTES));
+        assert!(
+            !contains(&bytes, TEST_ADDRESS.as_bytes()),
+            "must not store plaintext"
+        );
+
+        let mut db =
+            Store::<TestChangeSet>::open_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        let stored = db
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("must decrypt changesets");
+        assert_eq!(stored, changesets);
+
+        // compaction keeps the store encrypted
+        db.compact().expect("must compact");
+        drop(db);
+        let bytes = std::fs::read(&file_path).unwrap();
+        assert!(
+            !contains(&bytes, TEST_ADDRESS.as_bytes()),
+            "must not store plaintext"
+        );
+        let mut db =
+            Store::<TestChangeSet>::open_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        assert_eq!(
+            db.aggregate_changesets().unwrap(),
+            Some(TestChangeSet::from([
+                TEST_ADDRESS.to_string(),
+                "2".into(),
+                "3".into()
+            ]))
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_store_fails_with_wrong_key() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+
+        let mut db =
+            Store::<TestChangeSet>::create_encrypted(&TEST_MAGIC_BYTES, &file_path, [1; 32])
+                .unwrap();
+        db.append_changeset(&TestChangeSet::from(["1".into()]))
+            .unwrap();
+        drop(db);
+
+        match Store::<TestChangeSet>::open_encrypted(&TEST_MAGIC_BYTES, &file_path, [2; 32]) {
+            Err(FileError::WrongKey) => {}
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_entry_fails_to_authenticate_when_corrupted() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let key = [3_u8; 32];
+
+        let mut db =
+            Store::<TestChangeSet>::create_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        db.append_changeset(&TestChangeSet::from(["1".into()]))
+            .unwrap();
+        drop(db);
+
+        let mut bytes = std::fs::read(&file_path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&file_path, bytes).unwrap();
+
+        let mut db =
+            Store::<TestChangeSet>::open_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        let res = db.iter_changesets().next();
+        match res {
+            Some(Err(IterError::Decryption)) => {}
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_entries_fail_to_authenticate_when_swapped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let key = [4_u8; 32];
+
+        let mut db =
+            Store::<TestChangeSet>::create_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        db.append_changeset(&TestChangeSet::from(["1".into()]))
+            .unwrap();
+        db.append_changeset(&TestChangeSet::from(["2".into()]))
+            .unwrap();
+        drop(db);
+
+        // the two entries have the same length, swap them
+        let mut bytes = std::fs::read(&file_path).unwrap();
+        let entries_start = TEST_MAGIC_BYTES_LEN + format::HEADER_LEN + KEY_CHECK_LEN;
+        let entry_len = (bytes.len() - entries_start) / 2;
+        let (first, second) = bytes[entries_start..].split_at_mut(entry_len);
+        first.swap_with_slice(second);
+        std::fs::write(&file_path, bytes).unwrap();
+
+        let mut db =
+            Store::<TestChangeSet>::open_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        let res = db.iter_changesets().next();
+        match res {
+            Some(Err(IterError::Decryption)) => {}
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_store_appends_and_loads_after_reopen() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let key = [5_u8; 32];
+        let changesets = [
+            TestChangeSet::from(["1".into()]),
+            TestChangeSet::from(["2".into()]),
+            TestChangeSet::from(["3".into()]),
+        ];
+
+        let mut db =
+            Store::<TestChangeSet>::create_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        for changeset in &changesets[..2] {
+            db.append_changeset(changeset).unwrap();
+        }
+        drop(db);
+
+        // the index of the appended entry is counted from the stored ones
+        let mut db =
+            Store::<TestChangeSet>::open_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        db.aggregate_changesets().unwrap();
+        db.append_changeset(&changesets[2]).unwrap();
+        drop(db);
+
+        let mut db =
+            Store::<TestChangeSet>::open_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        let entries = db
+            .iter_changesets_with_offsets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("must decrypt changesets");
+        assert_eq!(
+            entries.iter().map(|(_, c)| c.clone()).collect::<Vec<_>>(),
+            changesets
+        );
+        assert_eq!(
+            db.load_from(entries[1].0).unwrap(),
+            Some(TestChangeSet::from(["2".into(), "3".into()]))
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypt_in_place_migrates_plaintext_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let key = [9_u8; 32];
+        let changesets = [
+            TestChangeSet::from([TEST_ADDRESS.to_string()]),
+            TestChangeSet::from(["2".into()]),
+        ];
+
+        let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        for changeset in &changesets {
+            db.append_changeset(changeset).unwrap();
+        }
+        assert!(contains(
+            &std::fs::read(&file_path).unwrap(),
+            TEST_ADDRESS.as_bytes()
+        ));
+
+        db.encrypt_in_place(key).expect("must encrypt");
+        let last_changeset = TestChangeSet::from(["3".into()]);
+        db.append_changeset(&last_changeset).unwrap();
+        drop(db);
+
+        let bytes = std::fs::read(&file_path).unwrap();
+        assert!(
+            !contains(&bytes, TEST_ADDRESS.as_bytes()),
+            "must not store plaintext"
+        );
+        let mut db =
+            Store::<TestChangeSet>::open_encrypted(&TEST_MAGIC_BYTES, &file_path, key).unwrap();
+        let stored = db
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("must decrypt changesets");
+        assert_eq!(
+            stored,
+            [
+                TestChangeSet::from([TEST_ADDRESS.to_string(), "2".into()]),
+                last_changeset
+            ]
+        );
+    }
+
+    /// The first version of a test changeset, the fixture `format_v1.dat` is written with it.
+    #[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct ChangeSetV1 {
+        names: BTreeSet<String>,
+    }
+
+    impl Append for ChangeSetV1 {
+        fn append(&mut self, other: Self) {
+            self.names.extend(other.names);
+        }
+
+        fn is_empty(&self) -> bool {
+            self.names.is_empty()
+        }
+    }
+
+    /// The next version of [`ChangeSetV1`], with a field added.
+    #[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct ChangeSetV2 {
+        names: BTreeSet<String>,
+        #[serde(default)]
+        heights: BTreeSet<u32>,
+    }
+
+    impl Append for ChangeSetV2 {
+        fn append(&mut self, other: Self) {
+            self.names.extend(other.names);
+            self.heights.extend(other.heights);
+        }
+
+        fn is_empty(&self) -> bool {
+            self.names.is_empty() && self.heights.is_empty()
+        }
+    }
+
+    /// Copy the fixture `name` to `dir`, so that the store's lock file is not written next to
+    /// the fixture.
+    fn copy_fixture(dir: &Path, name: &str) -> PathBuf {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name);
+        let file_path = dir.join(name);
+        std::fs::copy(fixture, &file_path).unwrap();
+        file_path
+    }
+
+    /// `format_v0.dat` is written by bdk_file_store 0.13, before the format was versioned.
+    #[test]
+    fn reads_format_v0_fixture() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = copy_fixture(temp_dir.path(), "format_v0.dat");
+        let exp_changesets = [
+            TestChangeSet::from(["1".into()]),
+            TestChangeSet::from(["2".into(), "3".into()]),
+        ];
+
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert_eq!(db.format_version(), 0);
+        let stored = db
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("must read the fixture");
+        assert_eq!(stored, exp_changesets);
+
+        // appending upgrades the format
+        let last_changeset = TestChangeSet::from(["4".into()]);
+        db.append_changeset(&last_changeset).unwrap();
+        assert_eq!(db.format_version(), FORMAT_VERSION);
+        drop(db);
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert_eq!(db.format_version(), FORMAT_VERSION);
+        assert_eq!(
+            db.aggregate_changesets().unwrap(),
+            Some(TestChangeSet::from([
+                "1".into(),
+                "2".into(),
+                "3".into(),
+                "4".into()
+            ]))
+        );
+    }
+
+    /// The layout of `tx_graph::ChangeSet` in the files of format version 0, before
+    /// `last_evicted` was added.
+    #[derive(serde::Serialize)]
+    struct TxGraphChangeSetV0 {
+        txs: BTreeSet<bdk_chain::bitcoin::Transaction>,
+        txouts: BTreeMap<bdk_chain::bitcoin::OutPoint, bdk_chain::bitcoin::TxOut>,
+        anchors: BTreeSet<((), bdk_chain::bitcoin::Txid)>,
+        last_seen: BTreeMap<bdk_chain::bitcoin::Txid, u64>,
+    }
+
+    #[test]
+    fn reads_format_v0_tx_graph_changesets_without_evictions() {
+        use bdk_chain::bitcoin::{hashes::Hash, Txid};
+        use bdk_chain::tx_graph;
+
+        let txids = [Txid::from_byte_array([1; 32]), Txid::from_byte_array([2; 32])];
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&TEST_MAGIC_BYTES).unwrap();
+        for (i, txid) in txids.iter().enumerate() {
+            let changeset = TxGraphChangeSetV0 {
+                txs: BTreeSet::new(),
+                txouts: BTreeMap::new(),
+                anchors: BTreeSet::new(),
+                last_seen: [(*txid, i as u64 + 1)].into(),
+            };
+            file.write_all(&bincode_options().serialize(&changeset).unwrap())
+                .unwrap();
+        }
+
+        let mut db =
+            Store::<tx_graph::ChangeSet<()>>::open(&TEST_MAGIC_BYTES, file.path()).unwrap();
+        assert_eq!(db.format_version(), 0);
+        let stored = db
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("must read the entries with the layout of format version 0");
+        assert_eq!(stored.len(), 2);
+        for (i, (changeset, txid)) in stored.iter().zip(txids).enumerate() {
+            assert_eq!(changeset.last_seen, [(txid, i as u64 + 1)].into());
+            assert!(changeset.last_evicted.is_empty());
+        }
+    }
+
+    /// `format_v1.dat` is written by bdk_file_store 0.13 with [`ChangeSetV1`], after the format
+    /// was versioned.
+    #[test]
+    fn reads_format_v1_fixture_after_field_addition() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = copy_fixture(temp_dir.path(), "format_v1.dat");
+        let names = BTreeSet::from(["alice".to_string(), "bob".into(), "carol".into()]);
+
+        let mut db = Store::<ChangeSetV2>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert_eq!(db.format_version(), 1);
+        assert_eq!(
+            db.aggregate_changesets().unwrap(),
+            Some(ChangeSetV2 {
+                names: names.clone(),
+                heights: BTreeSet::new(),
+            }),
+            "the missing field must be defaulted"
+        );
+        let last_changeset = ChangeSetV2 {
+            names: BTreeSet::from(["dave".into()]),
+            heights: BTreeSet::from([42]),
+        };
+        db.append_changeset(&last_changeset).unwrap();
+        drop(db);
+
+        let mut db = Store::<ChangeSetV2>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        let aggregate = db.aggregate_changesets().unwrap().unwrap();
+        assert_eq!(aggregate.heights, BTreeSet::from([42]));
+        drop(db);
+
+        // an older reader ignores the added field
+        let mut db = Store::<ChangeSetV1>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert_eq!(
+            db.aggregate_changesets().unwrap().unwrap().names,
+            aggregate.names
+        );
+    }
+
+    #[test]
+    fn refuses_newer_format_version() {
+        let mut file = NamedTempFile::new().unwrap();
+        let mut data = TEST_MAGIC_BYTES.to_vec();
+        data.extend(format::FORMAT_MARKER);
+        data.extend((FORMAT_VERSION + 1).to_le_bytes());
+        file.write_all(&data).unwrap();
+
+        match Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, file.path()) {
+            Err(err @ FileError::UnsupportedVersion { .. }) => {
+                assert_eq!(
+                    err.to_string(),
+                    format!(
+                        "file has format version {} but only versions up to {} are supported",
+                        FORMAT_VERSION + 1,
+                        FORMAT_VERSION
+                    )
+                );
+            }
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+    }
+
+    #[test]
+    fn refuses_newer_entry_version() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        db.append_changeset(&TestChangeSet::from(["1".into()]))
+            .unwrap();
+        let payload = bincode_options()
+            .serialize(&TestChangeSet::from(["2".into()]))
+            .unwrap();
+        bincode_options()
+            .serialize_into(&mut db.db_file, &(ENTRY_VERSION + 1, payload.len() as u64))
+            .unwrap();
+        db.db_file.write_all(&payload).unwrap();
+        drop(db);
+
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        let err = db
+            .aggregate_changesets()
+            .expect_err("must refuse the entry");
+        assert_eq!(err.changeset, Some(TestChangeSet::from(["1".into()])));
+        assert!(matches!(
+            err.iter_error,
+            IterError::UnsupportedEntryVersion { found, supported }
+                if found == ENTRY_VERSION + 1 && supported == ENTRY_VERSION
+        ));
+        drop(db);
+
+        // the newer entry is not discarded as corrupted
+        assert!(matches!(
+            Store::<TestChangeSet>::open_or_recover(&TEST_MAGIC_BYTES, &file_path),
+            Err(FileError::Entry(IterError::UnsupportedEntryVersion { .. }))
+        ));
+    }
+
+    #[test]
+    fn offsets_stay_valid_across_appends() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changesets = (0..10)
+            .map(|n| TestChangeSet::from([format!("{}", n)]))
+            .collect::<Vec<_>>();
+
+        let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        for changeset in &changesets[..5] {
+            db.append_changeset(changeset).unwrap();
+        }
+        let entries = db
+            .iter_changesets_with_offsets()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            entries.iter().map(|(_, cs)| cs).collect::<Vec<_>>(),
+            changesets[..5].iter().collect::<Vec<_>>()
+        );
+        let offsets = entries
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect::<Vec<_>>();
+        assert!(offsets.windows(2).all(|w| w[0] < w[1]));
+
+        for changeset in &changesets[5..] {
+            db.append_changeset(changeset).unwrap();
+        }
+        drop(db);
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        let new_offsets = db
+            .iter_changesets_with_offsets()
+            .map(|res| res.map(|(offset, _)| offset))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(new_offsets[..5], offsets[..]);
+
+        // only the suffix is aggregated
+        for (i, &offset) in new_offsets.iter().enumerate() {
+            let exp =
+                changesets[i..]
+                    .iter()
+                    .cloned()
+                    .fold(TestChangeSet::default(), |mut acc, cs| {
+                        Append::append(&mut acc, cs);
+                        acc
+                    });
+            assert_eq!(db.load_from(offset).unwrap(), Some(exp));
+        }
+        let len = db.len_bytes().unwrap();
+        assert_eq!(db.load_from(len).unwrap(), None);
+        assert!(matches!(
+            db.load_from(len + 1),
+            Err(AggregateChangesetsError {
+                iter_error: IterError::Io(_),
+                ..
+            })
+        ));
+        assert!(matches!(
+            db.load_from(0),
+            Err(AggregateChangesetsError {
+                iter_error: IterError::Io(_),
+                ..
+            })
+        ));
+
+        // the bytes after an offset are the entries after it
+        let bytes = std::fs::read(&file_path).unwrap();
+        let header_len = new_offsets[0] as usize;
+        let mut suffix_file = NamedTempFile::new().unwrap();
+        suffix_file.write_all(&bytes[..header_len]).unwrap();
+        suffix_file
+            .write_all(&bytes[new_offsets[7] as usize..])
+            .unwrap();
+        let mut suffix_db =
+            Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, suffix_file.path()).unwrap();
+        let suffix = suffix_db
+            .iter_changesets()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(suffix, changesets[7..]);
+    }
+
+    #[test]
+    fn offsets_reset_after_compaction() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+
+        let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        for n in 0..5 {
+            db.append_changeset(&TestChangeSet::from([format!("{}", n)]))
+                .unwrap();
+        }
+        let offsets = db
+            .iter_changesets_with_offsets()
+            .map(|res| res.map(|(offset, _)| offset))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let aggregate = db.aggregate_changesets().unwrap();
+
+        db.compact().unwrap();
+        let last_changeset = TestChangeSet::from(["last".into()]);
+        db.append_changeset(&last_changeset).unwrap();
+        let entries = db
+            .iter_changesets_with_offsets()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        // the compacted changeset is where the first changeset was
+        assert_eq!(entries[0], (offsets[0], aggregate.unwrap()));
+        assert_eq!(db.load_from(entries[1].0).unwrap(), Some(last_changeset));
+    }
+
+    #[test]
+    fn changesets_persist_under_each_durability_policy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        for (n, policy) in [
+            DurabilityPolicy::None,
+            DurabilityPolicy::FsyncData,
+            DurabilityPolicy::FsyncAll,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let file_path = temp_dir.path().join(format!("db_file_{}", n));
+            let changesets = (0..3)
+                .map(|i| TestChangeSet::from([format!("{}", i)]))
+                .collect::<Vec<_>>();
+            {
+                let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path)
+                    .unwrap()
+                    .with_durability(policy)
+                    .unwrap();
+                assert_eq!(db.durability(), policy);
+                for changeset in &changesets {
+                    db.append_changeset(changeset).unwrap();
+                }
+                db.compact().unwrap();
+                db.append_changeset(&changesets[0]).unwrap();
+                db.sync().unwrap();
+            }
+
+            let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+            // the policy is not stored in the file
+            assert_eq!(db.durability(), DurabilityPolicy::None);
+            assert_eq!(
+                db.aggregate_changesets().unwrap(),
+                Some(changesets.into_iter().flatten().collect())
+            );
+        }
+    }
+
+    #[test]
+    fn read_only_store_refuses_writes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changeset = TestChangeSet::from(["0".into()]);
+        {
+            let mut db = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+            db.append_changeset(&changeset).unwrap();
+        }
+        let bytes = std::fs::read(&file_path).unwrap();
+
+        let mut db = Store::<TestChangeSet>::open_read_only(&TEST_MAGIC_BYTES, &file_path)
+            .unwrap()
+            .with_durability(DurabilityPolicy::FsyncAll)
+            .unwrap();
+        assert_eq!(
+            db.iter_changesets().collect::<Result<Vec<_>, _>>().unwrap(),
+            vec![changeset.clone()]
+        );
+        assert_eq!(db.aggregate_changesets().unwrap(), Some(changeset.clone()));
+
+        let err = db
+            .append_changeset(&TestChangeSet::from(["1".into()]))
+            .expect_err("must not append to a read-only store");
+        assert!(ReadOnlyError::is_read_only(&err));
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        let err = db
+            .compact_to(&changeset)
+            .expect_err("must not compact a read-only store");
+        assert!(ReadOnlyError::is_read_only(&err));
+        assert!(matches!(db.compact(), Err(FileError::ReadOnly)));
+        #[cfg(feature = "encryption")]
+        assert!(matches!(
+            db.encrypt_in_place([7; 32]),
+            Err(FileError::ReadOnly)
+        ));
+        db.sync().unwrap();
+
+        assert_eq!(std::fs::read(&file_path).unwrap(), bytes);
+        let mut dir = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        dir.sort();
+        assert_eq!(dir, ["db_file", "db_file.lock"], "must not leave new files");
+    }
+
+    /// A read-only handle reads the changesets of a writer which has the store open, leaving out
+    /// the changeset which the writer is still appending.
+    #[test]
+    fn read_only_store_reads_alongside_writer() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        let changesets = (0..3)
+            .map(|n| TestChangeSet::from([format!("{}", n)]))
+            .collect::<Vec<_>>();
+
+        let mut writer = Store::<TestChangeSet>::create_new(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        writer.append_changeset(&changesets[0]).unwrap();
+        let mut reader =
+            Store::<TestChangeSet>::open_read_only(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert_eq!(
+            reader.aggregate_changesets().unwrap(),
+            Some(changesets[0].clone())
+        );
+
+        writer.append_changeset(&changesets[1]).unwrap();
+        assert_eq!(
+            reader.aggregate_changesets().unwrap(),
+            Some(changesets[..2].iter().flatten().cloned().collect())
+        );
+
+        // the writer is halfway through appending the next changeset
+        let entry = format::encode_entry(&bincode_options().serialize(&changesets[2]).unwrap());
+        OpenOptions::new()
+            .append(true)
+            .open(&file_path)
+            .unwrap()
+            .write_all(&entry[..entry.len() / 2])
+            .unwrap();
+        assert_eq!(
+            reader
+                .iter_changesets()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            changesets[..2]
+        );
+        // a writer would read the partial changeset as a corrupted one
+        drop(writer);
+        let mut writer = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert!(writer.aggregate_changesets().is_err());
+        writer.append_changeset(&changesets[2]).unwrap();
+        assert_eq!(
+            reader.aggregate_changesets().unwrap(),
+            Some(changesets.iter().flatten().cloned().collect())
+        );
+
+        // the reader keeps reading the replaced file until it is opened again
+        #[cfg(unix)]
+        {
+            writer.compact_to(&changesets[0]).unwrap();
+            assert_eq!(
+                reader.aggregate_changesets().unwrap(),
+                Some(changesets.iter().flatten().cloned().collect())
+            );
+            let mut reader =
+                Store::<TestChangeSet>::open_read_only(&TEST_MAGIC_BYTES, &file_path).unwrap();
+            assert_eq!(
+                reader.aggregate_changesets().unwrap(),
+                Some(changesets[0].clone())
+            );
+        }
+    }
 }
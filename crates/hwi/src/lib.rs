@@ -35,6 +35,14 @@
 //! # }
 //! ```
 //!
+//! The device is reached through the [`HWIConnection`] trait, so a signer can also be created
+//! with [`HWISigner::new`] over another transport, or over a mock device in tests. Devices which
+//! need global xpubs or complete key origins get them with [`HWISigner::with_xpubs`], and devices
+//! which only sign for registered wallet policies get them registered with
+//! [`HWISigner::with_policy_registration`]. The errors of the device are mapped to the
+//! [`SignerError`] of their cause by [`signer_error`].
+//!
+//! [`SignerError`]: bdk_wallet::signer::SignerError
 //! [`TransactionSigner`]: bdk_wallet::wallet::signer::TransactionSigner
 
 mod signer;
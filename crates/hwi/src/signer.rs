@@ -1,94 +1,364 @@
-use bdk_wallet::bitcoin::bip32::Fingerprint;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bdk_wallet::bitcoin::bip32::{Fingerprint, KeySource, Xpub};
 use bdk_wallet::bitcoin::secp256k1::{All, Secp256k1};
 use bdk_wallet::bitcoin::Psbt;
 
-use hwi::error::Error;
+use hwi::error::{Error, ErrorCode};
 use hwi::types::{HWIChain, HWIDevice};
 use hwi::HWIClient;
 
 use bdk_wallet::signer::{SignerCommon, SignerError, SignerId, TransactionSigner};
 
+/// A connection to a hardware wallet, which signs PSBTs
+///
+/// This is implemented by [`HWIClient`], and can be implemented over other transports to the
+/// device, or by mock devices in tests.
+pub trait HWIConnection: fmt::Debug + Send + Sync {
+    /// Send `psbt` to the device and return the PSBT signed by the device
+    fn sign_tx(&self, psbt: &Psbt) -> Result<Psbt, Error>;
+}
+
+impl HWIConnection for HWIClient {
+    fn sign_tx(&self, psbt: &Psbt) -> Result<Psbt, Error> {
+        HWIClient::sign_tx(self, psbt).map(|signed| signed.psbt)
+    }
+}
+
+/// Registration of the wallet policy of a PSBT on a device, before the device signs it
+///
+/// Some devices only sign for multisig wallet policies which were registered on them first, per
+/// [BIP-388]. The registration is specific to each device, for example it may have to keep the
+/// proof of registration returned by the device and add it to `psbt` as a proprietary field.
+///
+/// [BIP-388]: https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki
+pub trait PolicyRegistration: fmt::Debug + Send + Sync {
+    /// Make sure the wallet policy of `psbt` is registered on the device with `fingerprint`
+    fn register(&self, fingerprint: Fingerprint, psbt: &mut Psbt) -> Result<(), SignerError>;
+}
+
 #[derive(Debug)]
 /// Custom signer for Hardware Wallets
 ///
 /// This ignores `sign_options` and leaves the decisions up to the hardware wallet.
-pub struct HWISigner {
+///
+/// Before sending a PSBT to the device, the signer adds the extended public keys it knows of,
+/// see [`HWISigner::with_xpubs`], and registers the wallet policy of the PSBT if needed, see
+/// [`HWISigner::with_policy_registration`]. The signatures of the device are then merged into
+/// the PSBT.
+pub struct HWISigner<C = HWIClient> {
     fingerprint: Fingerprint,
-    client: HWIClient,
+    client: C,
+    xpubs: BTreeMap<Xpub, KeySource>,
+    policy_registration: Option<Box<dyn PolicyRegistration>>,
 }
 
 impl HWISigner {
     /// Create a instance from the specified device and chain
     pub fn from_device(device: &HWIDevice, chain: HWIChain) -> Result<HWISigner, Error> {
         let client = HWIClient::get_client(device, false, chain)?;
-        Ok(HWISigner {
-            fingerprint: device.fingerprint,
+        Ok(HWISigner::new(device.fingerprint, client))
+    }
+}
+
+impl<C: HWIConnection> HWISigner<C> {
+    /// Create a signer for the device with the master key `fingerprint`, connected by `client`
+    pub fn new(fingerprint: Fingerprint, client: C) -> Self {
+        HWISigner {
+            fingerprint,
             client,
-        })
+            xpubs: BTreeMap::new(),
+            policy_registration: None,
+        }
+    }
+
+    /// Add `xpubs` and their key origins to the PSBTs sent to the device
+    ///
+    /// Each xpub becomes a global xpub of the PSBT, which devices use to check the change of
+    /// multisig wallets. The key origins of the inputs and outputs which are relative to an xpub,
+    /// because its descriptor has no key origin, are completed with the origin of the xpub, so
+    /// that the device finds the derivation of its keys from its master key.
+    pub fn with_xpubs(mut self, xpubs: impl IntoIterator<Item = (Xpub, KeySource)>) -> Self {
+        self.xpubs.extend(xpubs);
+        self
+    }
+
+    /// Register the wallet policy of the PSBTs with `registration` before the device signs them
+    pub fn with_policy_registration(
+        mut self,
+        registration: impl PolicyRegistration + 'static,
+    ) -> Self {
+        self.policy_registration = Some(Box::new(registration));
+        self
+    }
+
+    /// Add the global xpubs and complete the key origins of `psbt`
+    fn add_key_origins(&self, psbt: &mut Psbt) {
+        for (xpub, (fingerprint, path)) in &self.xpubs {
+            psbt.xpub
+                .entry(*xpub)
+                .or_insert_with(|| (*fingerprint, path.clone()));
+
+            let xpub_fingerprint = xpub.fingerprint();
+            if xpub_fingerprint == *fingerprint {
+                continue;
+            }
+            let complete = |source: &mut KeySource| {
+                if source.0 == xpub_fingerprint {
+                    *source = (*fingerprint, path.extend(&source.1));
+                }
+            };
+            for input in &mut psbt.inputs {
+                input.bip32_derivation.values_mut().for_each(complete);
+                input
+                    .tap_key_origins
+                    .values_mut()
+                    .for_each(|(_, source)| complete(source));
+            }
+            for output in &mut psbt.outputs {
+                output.bip32_derivation.values_mut().for_each(complete);
+                output
+                    .tap_key_origins
+                    .values_mut()
+                    .for_each(|(_, source)| complete(source));
+            }
+        }
     }
 }
 
-impl SignerCommon for HWISigner {
+impl<C: HWIConnection> SignerCommon for HWISigner<C> {
     fn id(&self, _secp: &Secp256k1<All>) -> SignerId {
         SignerId::Fingerprint(self.fingerprint)
     }
 }
 
-impl TransactionSigner for HWISigner {
+impl<C: HWIConnection> TransactionSigner for HWISigner<C> {
     fn sign_transaction(
         &self,
         psbt: &mut Psbt,
         _sign_options: &bdk_wallet::SignOptions,
         _secp: &Secp256k1<All>,
     ) -> Result<(), SignerError> {
-        psbt.combine(
-            self.client
-                .sign_tx(psbt)
-                .map_err(|e| {
-                    SignerError::External(format!("While signing with hardware wallet: {}", e))
-                })?
-                .psbt,
-        )
-        .expect("Failed to combine HW signed psbt with passed PSBT");
+        let mut request = psbt.clone();
+        self.add_key_origins(&mut request);
+        if let Some(registration) = &self.policy_registration {
+            registration.register(self.fingerprint, &mut request)?;
+        }
+
+        let signed = self.client.sign_tx(&request).map_err(signer_error)?;
+        request.combine(signed).map_err(|e| {
+            SignerError::External(format!("The hardware wallet returned another PSBT: {}", e))
+        })?;
+        *psbt = request;
         Ok(())
     }
 }
 
-// TODO: re-enable this once we have the `get_funded_wallet` test util
-// #[cfg(test)]
-// mod tests {
-//     #[test]
-//     fn test_hardware_signer() {
-//         use std::sync::Arc;
-//
-//         use bdk_wallet::tests::get_funded_wallet;
-//         use bdk_wallet::signer::SignerOrdering;
-//         use bdk_wallet::bitcoin::Network;
-//         use crate::HWISigner;
-//         use hwi::HWIClient;
-//
-//         let mut devices = HWIClient::enumerate().unwrap();
-//         if devices.is_empty() {
-//             panic!("No devices found!");
-//         }
-//         let device = devices.remove(0).unwrap();
-//         let client = HWIClient::get_client(&device, true, Network::Regtest.into()).unwrap();
-//         let descriptors = client.get_descriptors::<String>(None).unwrap();
-//         let custom_signer = HWISigner::from_device(&device, Network::Regtest.into()).unwrap();
-//
-//         let (mut wallet, _) = get_funded_wallet(&descriptors.internal[0]);
-//         wallet.add_signer(
-//             bdk_wallet::KeychainKind::External,
-//             SignerOrdering(200),
-//             Arc::new(custom_signer),
-//         );
-//
-//         let addr = wallet.get_address(bdk_wallet::wallet::AddressIndex::LastUnused);
-//         let mut builder = wallet.build_tx();
-//         builder.drain_to(addr.script_pubkey()).drain_wallet();
-//         let (mut psbt, _) = builder.finish().unwrap();
-//
-//         let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-//         assert!(finalized);
-//     }
-// }
+/// Map a device error to the [`SignerError`] of its cause
+pub fn signer_error(err: Error) -> SignerError {
+    match err {
+        Error::Hwi(
+            message,
+            Some(
+                ErrorCode::NoDeviceType
+                | ErrorCode::DeviceConnError
+                | ErrorCode::UnknownDeviceType
+                | ErrorCode::DeviceNotInitialized,
+            ),
+        ) => SignerError::DeviceNotFound(message),
+        Error::Hwi(
+            message,
+            Some(
+                ErrorCode::NoPassword
+                | ErrorCode::DeviceNotReady
+                | ErrorCode::DeviceAlreadyUnlocked
+                | ErrorCode::DeviceBusy,
+            ),
+        ) => SignerError::DeviceNotReady(message),
+        Error::Hwi(_, Some(ErrorCode::ActionCanceled)) => SignerError::UserCanceled,
+        err => SignerError::External(format!("While signing with hardware wallet: {}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    use bdk_wallet::bitcoin::bip32::{DerivationPath, Xpriv};
+    use bdk_wallet::bitcoin::hashes::Hash;
+    use bdk_wallet::bitcoin::psbt::raw::ProprietaryKey;
+    use bdk_wallet::bitcoin::{
+        absolute, transaction, Amount, BlockHash, Network, OutPoint, Transaction, TxIn, TxOut, Txid,
+    };
+    use bdk_wallet::chain::{BlockId, ConfirmationTime};
+    use bdk_wallet::signer::SignerOrdering;
+    use bdk_wallet::{KeychainKind, SignOptions, Wallet};
+
+    const TPRV: &str = "tprv8ZgxMBicQKsPd3krDUsBAmtnRsK3rb8u5yi1zhQgMhF1tR8MW7xfE4rnrbbsrbPR52e7rKapu6ztw1jXveJSCGHEriUGZV7mCe88duLp5pj";
+    const ACCOUNT: &str = "m/84'/1'/0'";
+
+    fn proprietary_key() -> ProprietaryKey {
+        ProprietaryKey {
+            prefix: b"mock".to_vec(),
+            subtype: 0,
+            key: vec![],
+        }
+    }
+
+    /// A device which signs with the keys of `TPRV`, or fails with `error`
+    #[derive(Debug, Default)]
+    struct MockDevice {
+        error: Option<ErrorCode>,
+        /// The PSBTs sent to the device
+        received: Mutex<Vec<Psbt>>,
+    }
+
+    impl HWIConnection for Arc<MockDevice> {
+        fn sign_tx(&self, psbt: &Psbt) -> Result<Psbt, Error> {
+            self.received.lock().unwrap().push(psbt.clone());
+            if let Some(code) = self.error {
+                return Err(Error::Hwi("mock error".to_string(), Some(code)));
+            }
+            let mut signed = psbt.clone();
+            let master = Xpriv::from_str(TPRV).unwrap();
+            signed
+                .sign(&master, &Secp256k1::new())
+                .map_err(|_| Error::Hwi("failed to sign".to_string(), None))?;
+            // devices only return their signatures
+            for input in &mut signed.inputs {
+                *input = bdk_wallet::bitcoin::psbt::Input {
+                    partial_sigs: core::mem::take(&mut input.partial_sigs),
+                    ..Default::default()
+                };
+            }
+            Ok(signed)
+        }
+    }
+
+    /// Registers the policy by adding a proprietary field, like a proof of registration
+    #[derive(Debug)]
+    struct MockRegistration;
+
+    impl PolicyRegistration for MockRegistration {
+        fn register(&self, _fingerprint: Fingerprint, psbt: &mut Psbt) -> Result<(), SignerError> {
+            psbt.proprietary.insert(proprietary_key(), vec![1]);
+            Ok(())
+        }
+    }
+
+    /// A watch-only wallet without key origins in its descriptors, funded with one output
+    fn funded_watch_only_wallet() -> (Wallet, Fingerprint, (Xpub, KeySource)) {
+        let secp = Secp256k1::new();
+        let master = Xpriv::from_str(TPRV).unwrap();
+        let path = DerivationPath::from_str(ACCOUNT).unwrap();
+        let account = Xpub::from_priv(&secp, &master.derive_priv(&secp, &path).unwrap());
+        let fingerprint = master.fingerprint(&secp);
+
+        let mut wallet = Wallet::new(
+            &format!("wpkh({}/0/*)", account),
+            &format!("wpkh({}/1/*)", account),
+            Network::Regtest,
+        )
+        .unwrap();
+        let address = wallet.peek_address(KeychainKind::External, 0);
+        let tx = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        wallet
+            .insert_checkpoint(BlockId {
+                height: 1_000,
+                hash: BlockHash::all_zeros(),
+            })
+            .unwrap();
+        wallet
+            .insert_tx(
+                tx,
+                ConfirmationTime::Confirmed {
+                    height: 1_000,
+                    time: 100,
+                },
+            )
+            .unwrap();
+        (wallet, fingerprint, (account, (fingerprint, path)))
+    }
+
+    fn sign_with(device: Option<ErrorCode>) -> (Result<bool, SignerError>, Vec<Psbt>) {
+        let (mut wallet, fingerprint, account) = funded_watch_only_wallet();
+        let device = Arc::new(MockDevice {
+            error: device,
+            ..Default::default()
+        });
+        let signer = HWISigner::new(fingerprint, Arc::clone(&device))
+            .with_xpubs([account])
+            .with_policy_registration(MockRegistration);
+        wallet.add_signer(
+            KeychainKind::External,
+            SignerOrdering(200),
+            Arc::new(signer),
+        );
+
+        let address = wallet.peek_address(KeychainKind::External, 1);
+        let mut builder = wallet.build_tx();
+        builder.drain_to(address.script_pubkey()).drain_wallet();
+        let mut psbt = builder.finish().unwrap();
+        let result = wallet.sign(&mut psbt, SignOptions::default());
+        let received = device.received.lock().unwrap().clone();
+        (result, received)
+    }
+
+    #[test]
+    fn test_hardware_signer_round_trip() {
+        let (result, received) = sign_with(None);
+        assert!(
+            result.unwrap(),
+            "the signatures of the device must finalize"
+        );
+
+        let (_, fingerprint, (account, origin)) = funded_watch_only_wallet();
+        let request = &received[0];
+        assert_eq!(request.xpub.get(&account), Some(&origin));
+        assert_eq!(request.proprietary.get(&proprietary_key()), Some(&vec![1]));
+        // the key origins start at the master key of the device
+        let (_, (origin_fingerprint, path)) =
+            request.inputs[0].bip32_derivation.iter().next().unwrap();
+        assert_eq!(*origin_fingerprint, fingerprint);
+        assert_eq!(path.to_string(), "84'/1'/0'/0/0");
+    }
+
+    #[test]
+    fn test_hardware_signer_errors() {
+        for (code, check) in [
+            (
+                ErrorCode::DeviceConnError,
+                (|e| matches!(e, SignerError::DeviceNotFound(_))) as fn(&SignerError) -> bool,
+            ),
+            (ErrorCode::NoPassword, |e| {
+                matches!(e, SignerError::DeviceNotReady(_))
+            }),
+            (ErrorCode::ActionCanceled, |e| {
+                matches!(e, SignerError::UserCanceled)
+            }),
+            (ErrorCode::InvalidTx, |e| {
+                matches!(e, SignerError::External(_))
+            }),
+        ] {
+            let (result, received) = sign_with(Some(code));
+            assert_eq!(received.len(), 1);
+            let err = result.expect_err("the device must fail");
+            assert!(check(&err), "{:?}: {:?}", code, err);
+        }
+    }
+}
@@ -0,0 +1,411 @@
+//! A shared test-suite for the persistence backends of [`bdk_wallet`].
+//!
+//! A backend implements [`WalletPersister`] and calls [`run`] from its tests, so that all the
+//! backends prove that they store and load a [`Wallet`] alike. The tests of the suite can also be
+//! called one at a time.
+//!
+//! Each test takes a function which opens a new, empty storage and returns a function which opens
+//! the same storage again, as a new handle. The storage is opened again by the test to check what
+//! was persisted, as a wallet which is loaded after a restart. For a file-based backend, the
+//! outer function creates a temporary file, which the inner function opens:
+//!
+//! ```rust,ignore
+//! bdk_persist_testsuite::run(|| {
+//!     let dir = tempfile::tempdir().unwrap();
+//!     let path = dir.path().join("wallet.db");
+//!     move || {
+//!         let _dir = &dir;
+//!         MyPersister::open_or_create(&path).unwrap()
+//!     }
+//! });
+//! ```
+//!
+//! A persister which keeps its changes in memory, such as
+//! [`MemoryPersister`](bdk_wallet::wallet::persist::MemoryPersister), is shared between the
+//! handles with [`Shared`].
+
+use bdk_wallet::{
+    bitcoin::{self, hashes::Hash, key::Secp256k1, BlockHash, Network},
+    chain::Append,
+    descriptor::IntoWalletDescriptor,
+    wallet::{persist::WalletPersister, ChangeSet, NewOrLoadError},
+    KeychainKind, Wallet,
+};
+use core::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// A descriptor pair of a taproot wallet with ranged keychains.
+const TR_DESCRIPTORS: (&str, &str) = (
+    "tr(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/0/*)",
+    "tr(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/1/*)",
+);
+
+/// A descriptor pair of a segwit v0 wallet with single key keychains.
+const WPKH_DESCRIPTORS: (&str, &str) = (
+    "wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)",
+    "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/1/0)",
+);
+
+/// A descriptor which is neither of [`WPKH_DESCRIPTORS`].
+const OTHER_DESCRIPTOR: &str = "tr(cNJmN3fH9DDbDt131fQNkVakkpzawJBSeybCUNmP1BovpmGQ45xG)";
+
+/// A handle to a persister shared by all its clones, to run the suite over a persister which
+/// can't be opened again, such as one in memory
+///
+/// ```rust,ignore
+/// bdk_persist_testsuite::run(|| {
+///     let persister = Shared::new(MemoryPersister::new());
+///     move || persister.clone()
+/// });
+/// ```
+#[derive(Debug, Default)]
+pub struct Shared<P>(Arc<Mutex<P>>);
+
+impl<P> Shared<P> {
+    /// Share `persister`
+    pub fn new(persister: P) -> Self {
+        Self(Arc::new(Mutex::new(persister)))
+    }
+}
+
+impl<P> Clone for Shared<P> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<P: WalletPersister> WalletPersister for Shared<P> {
+    type Error = P::Error;
+
+    fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+        self.0.lock().expect("must lock persister").initialize()
+    }
+
+    fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+        self.0
+            .lock()
+            .expect("must lock persister")
+            .persist(changeset)
+    }
+}
+
+/// Run all the tests of the suite, see the [crate-level documentation](crate).
+pub fn run<P, O>(mut new_storage: impl FnMut() -> O)
+where
+    P: WalletPersister,
+    P::Error: Debug,
+    O: FnMut() -> P,
+{
+    initialize_empty_storage(new_storage());
+    wallet_round_trip(new_storage());
+    changesets_are_aggregated(new_storage());
+    new_or_load_checks_params(new_storage());
+    legacy_wallet_gets_first_revisions(new_storage());
+}
+
+/// Initializing an empty storage returns no changes.
+pub fn initialize_empty_storage<P>(mut open: impl FnMut() -> P)
+where
+    P: WalletPersister,
+    P::Error: Debug,
+{
+    let changeset = open().initialize().expect("must initialize storage");
+    assert_eq!(changeset, None, "a new storage must be empty");
+    // initializing again doesn't create anything
+    let changeset = open().initialize().expect("must initialize storage");
+    assert_eq!(changeset, None, "an initialized storage must stay empty");
+}
+
+/// A persisted wallet is loaded with the same network, descriptors and revealed indices.
+pub fn wallet_round_trip<P>(mut open: impl FnMut() -> P)
+where
+    P: WalletPersister,
+    P::Error: Debug,
+{
+    let (desc, change_desc) = TR_DESCRIPTORS;
+
+    let (wallet_spk_index, wallet_history) = {
+        let mut wallet =
+            Wallet::new(desc, change_desc, Network::Testnet).expect("must create wallet");
+        wallet.reveal_next_address(KeychainKind::External);
+
+        let mut persister = open();
+        assert_eq!(
+            persister.initialize().expect("must initialize storage"),
+            None
+        );
+        assert!(wallet.persist(&mut persister).expect("must persist wallet"));
+        (
+            wallet.spk_index().clone(),
+            [KeychainKind::External, KeychainKind::Internal]
+                .map(|keychain| wallet.descriptor_history(keychain)),
+        )
+    };
+
+    let changeset = open()
+        .initialize()
+        .expect("must initialize storage")
+        .expect("must have persisted changes");
+    let wallet = Wallet::load_from_changeset(changeset).expect("must load wallet");
+    assert_eq!(wallet.network(), Network::Testnet);
+    assert_eq!(
+        wallet.spk_index().keychains().collect::<Vec<_>>(),
+        wallet_spk_index.keychains().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        wallet.spk_index().last_revealed_indices(),
+        wallet_spk_index.last_revealed_indices()
+    );
+    let secp = Secp256k1::new();
+    assert_eq!(
+        *wallet.get_descriptor_for_keychain(KeychainKind::External),
+        desc.into_wallet_descriptor(&secp, wallet.network())
+            .expect("must parse descriptor")
+            .0
+    );
+    assert_eq!(
+        [KeychainKind::External, KeychainKind::Internal]
+            .map(|keychain| wallet.descriptor_history(keychain)),
+        wallet_history
+    );
+}
+
+/// The changes persisted in several calls are loaded as their aggregate, including the changes
+/// persisted by another handle of the storage.
+pub fn changesets_are_aggregated<P>(mut open: impl FnMut() -> P)
+where
+    P: WalletPersister,
+    P::Error: Debug,
+{
+    let (desc, change_desc) = TR_DESCRIPTORS;
+    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).expect("must create wallet");
+    let mut staged = wallet
+        .staged()
+        .cloned()
+        .expect("a new wallet must have changes");
+
+    let mut persister = open();
+    persister.initialize().expect("must initialize storage");
+    wallet.persist(&mut persister).expect("must persist wallet");
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        wallet.reveal_next_address(keychain);
+        wallet.reveal_next_address(keychain);
+        staged.append(
+            wallet
+                .staged()
+                .cloned()
+                .expect("must stage revealed address"),
+        );
+        wallet.persist(&mut persister).expect("must persist wallet");
+    }
+    // nothing is left to persist
+    assert!(!wallet.persist(&mut persister).expect("must persist wallet"));
+    drop(persister);
+
+    // the next changes are persisted on top, with a new handle
+    let mut persister = open();
+    let changeset = persister.initialize().expect("must initialize storage");
+    let mut wallet = Wallet::load_from_changeset(changeset.expect("must have persisted changes"))
+        .expect("must load wallet");
+    wallet.reveal_next_address(KeychainKind::External);
+    staged.append(
+        wallet
+            .staged()
+            .cloned()
+            .expect("must stage revealed address"),
+    );
+    wallet.persist(&mut persister).expect("must persist wallet");
+    drop(persister);
+
+    let changeset = open().initialize().expect("must initialize storage");
+    assert_eq!(changeset.as_ref(), Some(&staged));
+    let wallet = Wallet::load_from_changeset(changeset.expect("must have persisted changes"))
+        .expect("must load wallet");
+    assert_eq!(
+        wallet.spk_index().last_revealed_indices(),
+        [(KeychainKind::External, 2), (KeychainKind::Internal, 1)].into()
+    );
+}
+
+/// The parameters of [`Wallet::new_or_load`] are checked against the persisted wallet.
+pub fn new_or_load_checks_params<P>(mut open: impl FnMut() -> P)
+where
+    P: WalletPersister,
+    P::Error: Debug,
+{
+    let (desc, change_desc) = WPKH_DESCRIPTORS;
+    let secp = Secp256k1::new();
+
+    // init wallet when non-existent
+    let wallet_keychains = {
+        let mut persister = open();
+        let changeset = persister.initialize().expect("must initialize storage");
+        let mut wallet = Wallet::new_or_load(desc, change_desc, changeset, Network::Testnet)
+            .expect("must create wallet");
+        wallet.persist(&mut persister).expect("must persist wallet");
+        wallet
+            .keychains()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    // wrong network
+    {
+        let changeset = open().initialize().expect("must initialize storage");
+        let err = Wallet::new_or_load(desc, change_desc, changeset, Network::Bitcoin)
+            .expect_err("wrong network");
+        assert!(
+            matches!(
+                err,
+                NewOrLoadError::LoadedNetworkDoesNotMatch {
+                    got: Some(Network::Testnet),
+                    expected: Network::Bitcoin
+                }
+            ),
+            "err: {}",
+            err,
+        );
+    }
+
+    // wrong genesis hash
+    {
+        let exp_blockhash = BlockHash::all_zeros();
+        let got_blockhash =
+            bitcoin::blockdata::constants::genesis_block(Network::Testnet).block_hash();
+
+        let changeset = open().initialize().expect("must initialize storage");
+        let err = Wallet::new_or_load_with_genesis_hash(
+            desc,
+            change_desc,
+            changeset,
+            Network::Testnet,
+            exp_blockhash,
+        )
+        .expect_err("wrong genesis hash");
+        assert!(
+            matches!(
+                err,
+                NewOrLoadError::LoadedGenesisDoesNotMatch { got, expected }
+                if got == Some(got_blockhash) && expected == exp_blockhash
+            ),
+            "err: {}",
+            err,
+        );
+    }
+
+    // wrong external descriptor
+    {
+        let (exp_descriptor, exp_change_desc) = TR_DESCRIPTORS;
+        let got_descriptor = desc
+            .into_wallet_descriptor(&secp, Network::Testnet)
+            .expect("must parse descriptor")
+            .0;
+
+        let changeset = open().initialize().expect("must initialize storage");
+        let err = Wallet::new_or_load(exp_descriptor, exp_change_desc, changeset, Network::Testnet)
+            .expect_err("wrong external descriptor");
+        assert!(
+            matches!(
+                err,
+                NewOrLoadError::LoadedDescriptorDoesNotMatch { ref got, keychain }
+                if got == &Some(got_descriptor) && keychain == KeychainKind::External
+            ),
+            "err: {}",
+            err,
+        );
+    }
+
+    // wrong internal descriptor
+    {
+        let got_descriptor = change_desc
+            .into_wallet_descriptor(&secp, Network::Testnet)
+            .expect("must parse descriptor")
+            .0;
+
+        let changeset = open().initialize().expect("must initialize storage");
+        let err = Wallet::new_or_load(desc, OTHER_DESCRIPTOR, changeset, Network::Testnet)
+            .expect_err("wrong internal descriptor");
+        assert!(
+            matches!(
+                err,
+                NewOrLoadError::LoadedDescriptorDoesNotMatch { ref got, keychain }
+                if got == &Some(got_descriptor) && keychain == KeychainKind::Internal
+            ),
+            "err: {}",
+            err,
+        );
+    }
+
+    // all parameters match
+    {
+        let changeset = open().initialize().expect("must initialize storage");
+        let wallet = Wallet::new_or_load(desc, change_desc, changeset, Network::Testnet)
+            .expect("must load wallet");
+        assert_eq!(wallet.network(), Network::Testnet);
+        assert!(wallet
+            .keychains()
+            .map(|(k, v)| (*k, v.clone()))
+            .eq(wallet_keychains));
+    }
+}
+
+/// A wallet persisted before the revisions of its descriptors were recorded is loaded with the
+/// revision 0 of its keychains, which is persisted with the next changes.
+pub fn legacy_wallet_gets_first_revisions<P>(mut open: impl FnMut() -> P)
+where
+    P: WalletPersister,
+    P::Error: Debug,
+{
+    let (desc, change_desc) = TR_DESCRIPTORS;
+    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).expect("must create wallet");
+    wallet.reveal_next_address(KeychainKind::External);
+    let mut legacy = wallet
+        .staged()
+        .cloned()
+        .expect("a new wallet must have changes");
+    legacy.descriptor_revisions.clear();
+
+    let mut persister = open();
+    persister.initialize().expect("must initialize storage");
+    persister.persist(&legacy).expect("must persist changes");
+    drop(persister);
+
+    let mut persister = open();
+    let changeset = persister.initialize().expect("must initialize storage");
+    let mut wallet = Wallet::load_from_changeset(changeset.expect("must have persisted changes"))
+        .expect("must load wallet");
+    let history = [KeychainKind::External, KeychainKind::Internal]
+        .map(|keychain| wallet.descriptor_history(keychain));
+    for (keychain_history, last_revealed) in history.iter().zip([Some(0), None]) {
+        assert_eq!(keychain_history.len(), 1);
+        let revision = &keychain_history[0];
+        assert_eq!(revision.revision, 0);
+        assert_eq!(revision.activation_height, None);
+        assert_eq!(revision.last_revealed, last_revealed);
+        assert!(revision.descriptor.is_some());
+    }
+    let staged = wallet
+        .staged()
+        .cloned()
+        .expect("the first revisions must be staged");
+    assert_eq!(staged.descriptor_revisions.len(), 2);
+    assert!(wallet.persist(&mut persister).expect("must persist wallet"));
+    drop(persister);
+
+    let changeset = open()
+        .initialize()
+        .expect("must initialize storage")
+        .expect("must have persisted changes");
+    assert_eq!(changeset.descriptor_revisions, staged.descriptor_revisions);
+    let wallet = Wallet::load_from_changeset(changeset).expect("must load wallet");
+    assert_eq!(
+        wallet.staged(),
+        None,
+        "the revisions must not be synthesized again"
+    );
+    assert_eq!(
+        [KeychainKind::External, KeychainKind::Internal]
+            .map(|keychain| wallet.descriptor_history(keychain)),
+        history
+    );
+}
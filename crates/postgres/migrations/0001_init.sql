@@ -0,0 +1,117 @@
+-- The wallets, and the components of their changesets which are a single value. A changeset of a
+-- wallet is written while holding the lock of its row.
+CREATE TABLE wallet (
+    wallet_id TEXT PRIMARY KEY NOT NULL,
+    network TEXT,
+    change_address_policy TEXT,
+    change_rotation BIGINT,
+    birthday TEXT
+);
+
+-- The blocks of the local chain.
+CREATE TABLE block (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    height BIGINT NOT NULL,
+    hash BYTEA NOT NULL,
+    PRIMARY KEY (wallet_id, height)
+);
+
+-- The descriptors of the keychains, which are JSON. A keychain keeps its first descriptor, and a
+-- descriptor its first keychain.
+CREATE TABLE keychain (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    keychain TEXT NOT NULL,
+    descriptor TEXT NOT NULL,
+    PRIMARY KEY (wallet_id, keychain),
+    UNIQUE (wallet_id, descriptor)
+);
+
+-- The last revealed derivation index of each descriptor.
+CREATE TABLE last_revealed (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    descriptor_id BYTEA NOT NULL,
+    last_revealed BIGINT NOT NULL,
+    PRIMARY KEY (wallet_id, descriptor_id)
+);
+
+-- The derivation indices marked as used or unused.
+CREATE TABLE marked_used (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    descriptor_id BYTEA NOT NULL,
+    idx BIGINT NOT NULL,
+    used BOOLEAN NOT NULL,
+    PRIMARY KEY (wallet_id, descriptor_id, idx)
+);
+
+-- The consensus encoded transactions.
+CREATE TABLE tx (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    txid BYTEA NOT NULL,
+    raw_tx BYTEA NOT NULL,
+    PRIMARY KEY (wallet_id, txid)
+);
+
+-- The consensus encoded floating outputs.
+CREATE TABLE txout (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    txid BYTEA NOT NULL,
+    vout BIGINT NOT NULL,
+    raw_txout BYTEA NOT NULL,
+    PRIMARY KEY (wallet_id, txid, vout)
+);
+
+-- The anchors of the transactions, which are JSON.
+CREATE TABLE anchor (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    txid BYTEA NOT NULL,
+    anchor TEXT NOT NULL,
+    PRIMARY KEY (wallet_id, txid, anchor)
+);
+
+-- The last-seen unix timestamps of the transactions.
+CREATE TABLE last_seen (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    txid BYTEA NOT NULL,
+    seen_at BIGINT NOT NULL,
+    PRIMARY KEY (wallet_id, txid)
+);
+
+-- When the transactions were found missing from the mempool.
+CREATE TABLE last_evicted (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    txid BYTEA NOT NULL,
+    evicted_at BIGINT NOT NULL,
+    PRIMARY KEY (wallet_id, txid)
+);
+
+-- The labels, by the labeled item as JSON.
+CREATE TABLE label (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    label_ref TEXT NOT NULL,
+    label TEXT NOT NULL,
+    PRIMARY KEY (wallet_id, label_ref)
+);
+
+-- The broadcast attempts as JSON, by the order they were recorded in.
+CREATE TABLE broadcast (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    position BIGINT NOT NULL,
+    record TEXT NOT NULL,
+    PRIMARY KEY (wallet_id, position)
+);
+
+-- When each keychain was last synced, in unix seconds.
+CREATE TABLE last_synced (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    keychain TEXT NOT NULL,
+    synced_at BIGINT NOT NULL,
+    PRIMARY KEY (wallet_id, keychain)
+);
+
+-- The invoices as JSON.
+CREATE TABLE invoice (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    id BIGINT NOT NULL,
+    record TEXT NOT NULL,
+    PRIMARY KEY (wallet_id, id)
+);
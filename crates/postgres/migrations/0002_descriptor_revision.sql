@@ -0,0 +1,16 @@
+-- The revisions of the descriptors of the keychains, which are JSON. The activation height is the
+-- height of the tip of the chain of the wallet when the revision became active, NULL if unknown.
+CREATE TABLE descriptor_revision (
+    wallet_id TEXT NOT NULL REFERENCES wallet ON DELETE CASCADE,
+    keychain TEXT NOT NULL,
+    revision BIGINT NOT NULL,
+    descriptor_id BYTEA NOT NULL,
+    activation_height BIGINT,
+    PRIMARY KEY (wallet_id, keychain, revision)
+);
+
+-- The keychains stored before had the same descriptor from the start, their revision 0. The id of
+-- a descriptor is the SHA256 of the descriptor without its checksum.
+INSERT INTO descriptor_revision (wallet_id, keychain, revision, descriptor_id, activation_height)
+SELECT wallet_id, keychain, 0, sha256(convert_to(split_part(descriptor, '#', 1), 'UTF8')), NULL
+FROM keychain;
@@ -0,0 +1,91 @@
+#![doc = include_str!("../README.md")]
+// only enables the `doc_cfg` feature when the `docsrs` configuration attribute is defined
+#![cfg_attr(docsrs, feature(doc_cfg))]
+
+mod store;
+
+use bdk_wallet::bitcoin::{consensus, Network};
+pub use sqlx;
+use sqlx::{migrate::Migrator, PgPool};
+pub use store::Store;
+
+/// The migrations of the schema, in the `migrations` directory of this crate.
+///
+/// They are run by [`Store::new`], [`list_wallets`] and [`delete_wallet`]. sqlx records the
+/// migrations which are applied in the `_sqlx_migrations` table, and holds a lock of the database
+/// while it runs them, so that several instances of an application can start at the same time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// List the identifiers of the wallets stored in the database, see [`Store::new`].
+///
+/// A wallet is stored once a changeset is written with its [`Store`].
+pub async fn list_wallets(pool: &PgPool) -> Result<Vec<String>, Error> {
+    MIGRATOR.run(pool).await?;
+    let wallet_ids: Vec<(String,)> =
+        sqlx::query_as("SELECT wallet_id FROM wallet ORDER BY wallet_id")
+            .fetch_all(pool)
+            .await?;
+    Ok(wallet_ids
+        .into_iter()
+        .map(|(wallet_id,)| wallet_id)
+        .collect())
+}
+
+/// Delete all the data of the wallet `wallet_id`, and return whether it was stored.
+pub async fn delete_wallet(pool: &PgPool, wallet_id: &str) -> Result<bool, Error> {
+    MIGRATOR.run(pool).await?;
+    // the rows of the other tables are deleted along with the row of the wallet
+    let deleted = sqlx::query("DELETE FROM wallet WHERE wallet_id = $1")
+        .bind(wallet_id)
+        .execute(pool)
+        .await?;
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// Error that occurs while reading or writing changesets with the PostgreSQL database.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid network, cannot change the one already stored in the database.
+    Network { expected: Network, given: Network },
+    /// sqlx error.
+    Sqlx(sqlx::Error),
+    /// The schema failed to migrate.
+    Migrate(Box<sqlx::migrate::MigrateError>),
+    /// A stored transaction, output or hash fails to decode.
+    Consensus(consensus::encode::Error),
+    /// A stored value which is serialized as JSON fails to decode.
+    Json(serde_json::Error),
+    /// A stored descriptor fails to parse.
+    Descriptor(bdk_wallet::miniscript::Error),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Network { expected, given } => write!(
+                f,
+                "network error trying to read or write change set, expected {}, given {}",
+                expected, given
+            ),
+            Self::Sqlx(e) => write!(f, "postgres error reading or writing changeset: {}", e),
+            Self::Migrate(e) => write!(f, "failed to migrate the database schema: {}", e),
+            Self::Consensus(e) => write!(f, "failed to decode stored chain data: {}", e),
+            Self::Json(e) => write!(f, "failed to decode stored value: {}", e),
+            Self::Descriptor(e) => write!(f, "failed to parse stored descriptor: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        Self::Sqlx(error)
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for Error {
+    fn from(error: sqlx::migrate::MigrateError) -> Self {
+        Self::Migrate(Box::new(error))
+    }
+}
@@ -0,0 +1,997 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bdk_wallet::bitcoin::hashes::{sha256, Hash};
+use bdk_wallet::bitcoin::{consensus, BlockHash, Network, OutPoint, Transaction, TxOut, Txid};
+use bdk_wallet::chain::{
+    indexed_tx_graph, keychain, tx_graph, Append, ConfirmationTimeHeightAnchor, DescriptorId,
+    DescriptorRevisionRecord,
+};
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey};
+use bdk_wallet::wallet::persist::{AsyncWalletPersister, FutureResult};
+use bdk_wallet::wallet::ChangeSet;
+use bdk_wallet::KeychainKind;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{PgPool, Postgres, Transaction as DbTransaction};
+
+use crate::{Error, MIGRATOR};
+
+/// Persists the changes of the [`Wallet`](bdk_wallet::Wallet) of `wallet_id` in a PostgreSQL
+/// database.
+///
+/// The stores of all the wallets of a database share its tables. Each changeset is written in a
+/// single transaction, which first locks the row of the wallet in the `wallet` table: the
+/// transactions of two stores of the same wallet, such as in two instances of an application,
+/// run one after the other, and none of their changes is lost.
+#[derive(Debug, Clone)]
+pub struct Store {
+    pool: PgPool,
+    wallet_id: String,
+}
+
+impl Store {
+    /// Migrate the schema of the database to the latest version, and open the store of the wallet
+    /// `wallet_id`.
+    pub async fn new(pool: PgPool, wallet_id: &str) -> Result<Self, Error> {
+        MIGRATOR.run(&pool).await?;
+        Ok(Self {
+            pool,
+            wallet_id: wallet_id.to_string(),
+        })
+    }
+
+    /// The identifier of the wallet of the store.
+    pub fn wallet_id(&self) -> &str {
+        &self.wallet_id
+    }
+
+    /// Read the rows of the wallet as the aggregate changeset.
+    ///
+    /// Returns `None` if nothing was written. The rows are read in one transaction, so that a
+    /// concurrent write is either fully read or not at all.
+    pub async fn read(&self) -> Result<Option<ChangeSet>, Error> {
+        let mut db_transaction = self.pool.begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *db_transaction)
+            .await?;
+        let changeset = read_changeset(&mut db_transaction, &self.wallet_id).await?;
+        db_transaction.commit().await?;
+        Ok(Some(changeset).filter(|changeset| !changeset.is_empty()))
+    }
+
+    /// Write the given `changeset` atomically.
+    pub async fn write(&self, changeset: &ChangeSet) -> Result<(), Error> {
+        // no need to write anything if changeset is empty
+        if changeset.is_empty() {
+            return Ok(());
+        }
+        let mut db_transaction = self.pool.begin().await?;
+        write_changeset(&mut db_transaction, &self.wallet_id, changeset).await?;
+        db_transaction.commit().await?;
+        Ok(())
+    }
+}
+
+impl AsyncWalletPersister for Store {
+    type Error = Error;
+
+    fn initialize<'a>(&'a mut self) -> FutureResult<'a, Option<ChangeSet>, Self::Error>
+    where
+        Self: 'a,
+    {
+        Box::pin(async move {
+            MIGRATOR.run(&self.pool).await?;
+            self.read().await
+        })
+    }
+
+    fn persist<'a>(&'a mut self, changeset: &'a ChangeSet) -> FutureResult<'a, (), Self::Error>
+    where
+        Self: 'a,
+    {
+        Box::pin(self.write(changeset))
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("value must serialize")
+}
+
+fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, Error> {
+    serde_json::from_str(json).map_err(Error::Json)
+}
+
+fn decode<T: consensus::Decodable>(bytes: &[u8]) -> Result<T, Error> {
+    consensus::deserialize(bytes).map_err(Error::Consensus)
+}
+
+async fn write_changeset(
+    db_transaction: &mut DbTransaction<'_, Postgres>,
+    wallet_id: &str,
+    changeset: &ChangeSet,
+) -> Result<(), Error> {
+    // the row of the wallet is locked until the transaction ends, the writes of the other stores
+    // of the wallet wait for it
+    sqlx::query("INSERT INTO wallet (wallet_id) VALUES ($1) ON CONFLICT DO NOTHING")
+        .bind(wallet_id)
+        .execute(&mut **db_transaction)
+        .await?;
+    let (current_network,): (Option<String>,) =
+        sqlx::query_as("SELECT network FROM wallet WHERE wallet_id = $1 FOR UPDATE")
+            .bind(wallet_id)
+            .fetch_one(&mut **db_transaction)
+            .await?;
+
+    if let Some(network) = changeset.network {
+        match current_network
+            .as_deref()
+            .map(from_json::<Network>)
+            .transpose()?
+        {
+            // if no network change do nothing
+            Some(current) if current == network => {}
+            // if new network not the same as current, error
+            Some(current) => {
+                return Err(Error::Network {
+                    expected: current,
+                    given: network,
+                })
+            }
+            None => {
+                sqlx::query("UPDATE wallet SET network = $2 WHERE wallet_id = $1")
+                    .bind(wallet_id)
+                    .bind(to_json(&network))
+                    .execute(&mut **db_transaction)
+                    .await?;
+            }
+        }
+    }
+    if let Some(policy) = &changeset.change_address_policy {
+        sqlx::query("UPDATE wallet SET change_address_policy = $2 WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .bind(to_json(policy))
+            .execute(&mut **db_transaction)
+            .await?;
+    }
+    if let Some(rotation) = changeset.change_rotation {
+        sqlx::query("UPDATE wallet SET change_rotation = $2 WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .bind(i64::from(rotation))
+            .execute(&mut **db_transaction)
+            .await?;
+    }
+    if let Some(birthday) = &changeset.birthday {
+        sqlx::query("UPDATE wallet SET birthday = $2 WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .bind(to_json(birthday))
+            .execute(&mut **db_transaction)
+            .await?;
+    }
+
+    for (height, hash) in &changeset.chain {
+        match hash {
+            Some(hash) => sqlx::query(
+                "INSERT INTO block (wallet_id, height, hash) VALUES ($1, $2, $3)
+                  ON CONFLICT (wallet_id, height) DO UPDATE SET hash = $3",
+            )
+            .bind(wallet_id)
+            .bind(i64::from(*height))
+            .bind(consensus::serialize(hash)),
+            None => sqlx::query("DELETE FROM block WHERE wallet_id = $1 AND height = $2")
+                .bind(wallet_id)
+                .bind(i64::from(*height)),
+        }
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+
+    let keychain_changeset = &changeset.indexed_tx_graph.indexer;
+    for (keychain, descriptor) in &keychain_changeset.keychains_added {
+        // a keychain keeps its descriptor, and a descriptor its keychain, as with `Append`
+        sqlx::query(
+            "INSERT INTO keychain (wallet_id, keychain, descriptor) VALUES ($1, $2, $3)
+              ON CONFLICT DO NOTHING",
+        )
+        .bind(wallet_id)
+        .bind(to_json(keychain))
+        .bind(descriptor.to_string())
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    for (descriptor_id, index) in &keychain_changeset.last_revealed {
+        sqlx::query(
+            "INSERT INTO last_revealed (wallet_id, descriptor_id, last_revealed) VALUES ($1, $2, $3)
+              ON CONFLICT (wallet_id, descriptor_id)
+              DO UPDATE SET last_revealed = GREATEST(last_revealed.last_revealed, $3)",
+        )
+        .bind(wallet_id)
+        .bind(descriptor_id.to_byte_array().to_vec())
+        .bind(i64::from(*index))
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    for (descriptor_id, marks) in &keychain_changeset.marked_used {
+        for (index, used) in marks {
+            sqlx::query(
+                "INSERT INTO marked_used (wallet_id, descriptor_id, idx, used) VALUES ($1, $2, $3, $4)
+                  ON CONFLICT (wallet_id, descriptor_id, idx) DO UPDATE SET used = $4",
+            )
+            .bind(wallet_id)
+            .bind(descriptor_id.to_byte_array().to_vec())
+            .bind(i64::from(*index))
+            .bind(*used)
+            .execute(&mut **db_transaction)
+            .await?;
+        }
+    }
+
+    let graph_changeset = &changeset.indexed_tx_graph.graph;
+    for tx in &graph_changeset.txs {
+        sqlx::query(
+            "INSERT INTO tx (wallet_id, txid, raw_tx) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        )
+        .bind(wallet_id)
+        .bind(consensus::serialize(&tx.compute_txid()))
+        .bind(consensus::serialize(tx.as_ref()))
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    for (outpoint, txout) in &graph_changeset.txouts {
+        sqlx::query(
+            "INSERT INTO txout (wallet_id, txid, vout, raw_txout) VALUES ($1, $2, $3, $4)
+              ON CONFLICT (wallet_id, txid, vout) DO UPDATE SET raw_txout = $4",
+        )
+        .bind(wallet_id)
+        .bind(consensus::serialize(&outpoint.txid))
+        .bind(i64::from(outpoint.vout))
+        .bind(consensus::serialize(txout))
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    for (anchor, txid) in &graph_changeset.anchors {
+        sqlx::query(
+            "INSERT INTO anchor (wallet_id, txid, anchor) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        )
+        .bind(wallet_id)
+        .bind(consensus::serialize(txid))
+        .bind(to_json(anchor))
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    for (txid, seen_at) in &graph_changeset.last_seen {
+        sqlx::query(
+            "INSERT INTO last_seen (wallet_id, txid, seen_at) VALUES ($1, $2, $3)
+              ON CONFLICT (wallet_id, txid) DO UPDATE SET seen_at = GREATEST(last_seen.seen_at, $3)",
+        )
+        .bind(wallet_id)
+        .bind(consensus::serialize(txid))
+        .bind(*seen_at as i64)
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    for (txid, evicted_at) in &graph_changeset.last_evicted {
+        sqlx::query(
+            "INSERT INTO last_evicted (wallet_id, txid, evicted_at) VALUES ($1, $2, $3)
+              ON CONFLICT (wallet_id, txid)
+              DO UPDATE SET evicted_at = GREATEST(last_evicted.evicted_at, $3)",
+        )
+        .bind(wallet_id)
+        .bind(consensus::serialize(txid))
+        .bind(*evicted_at as i64)
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+
+    for (label_ref, label) in &changeset.labels {
+        match label {
+            Some(label) => sqlx::query(
+                "INSERT INTO label (wallet_id, label_ref, label) VALUES ($1, $2, $3)
+                  ON CONFLICT (wallet_id, label_ref) DO UPDATE SET label = $3",
+            )
+            .bind(wallet_id)
+            .bind(to_json(label_ref))
+            .bind(label.clone()),
+            None => sqlx::query("DELETE FROM label WHERE wallet_id = $1 AND label_ref = $2")
+                .bind(wallet_id)
+                .bind(to_json(label_ref)),
+        }
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    if !changeset.broadcasts.is_empty() {
+        // the lock of the wallet keeps another store from taking the same positions
+        let (next_position,): (i64,) = sqlx::query_as(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM broadcast WHERE wallet_id = $1",
+        )
+        .bind(wallet_id)
+        .fetch_one(&mut **db_transaction)
+        .await?;
+        for (position, broadcast) in (next_position..).zip(&changeset.broadcasts) {
+            sqlx::query("INSERT INTO broadcast (wallet_id, position, record) VALUES ($1, $2, $3)")
+                .bind(wallet_id)
+                .bind(position)
+                .bind(to_json(broadcast))
+                .execute(&mut **db_transaction)
+                .await?;
+        }
+    }
+    for (keychain, synced_at) in &changeset.last_synced {
+        sqlx::query(
+            "INSERT INTO last_synced (wallet_id, keychain, synced_at) VALUES ($1, $2, $3)
+              ON CONFLICT (wallet_id, keychain)
+              DO UPDATE SET synced_at = GREATEST(last_synced.synced_at, $3)",
+        )
+        .bind(wallet_id)
+        .bind(to_json(keychain))
+        .bind(*synced_at as i64)
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    for (id, invoice) in &changeset.invoices {
+        sqlx::query(
+            "INSERT INTO invoice (wallet_id, id, record) VALUES ($1, $2, $3)
+              ON CONFLICT (wallet_id, id) DO UPDATE SET record = $3",
+        )
+        .bind(wallet_id)
+        .bind(i64::from(*id))
+        .bind(to_json(invoice))
+        .execute(&mut **db_transaction)
+        .await?;
+    }
+    for (keychain, revisions) in &changeset.descriptor_revisions {
+        for (revision, record) in revisions {
+            // a revision never changes once recorded
+            sqlx::query(
+                "INSERT INTO descriptor_revision (wallet_id, keychain, revision, descriptor_id, activation_height)
+                  VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+            )
+            .bind(wallet_id)
+            .bind(to_json(keychain))
+            .bind(i64::from(*revision))
+            .bind(record.descriptor_id.to_byte_array().to_vec())
+            .bind(record.activation_height.map(i64::from))
+            .execute(&mut **db_transaction)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The `network`, `change_address_policy`, `change_rotation` and `birthday` of a wallet.
+type WalletRow = (Option<String>, Option<String>, Option<i64>, Option<String>);
+
+async fn read_changeset(
+    db_transaction: &mut DbTransaction<'_, Postgres>,
+    wallet_id: &str,
+) -> Result<ChangeSet, Error> {
+    let mut changeset = ChangeSet::default();
+
+    let wallet: Option<WalletRow> = sqlx::query_as(
+        "SELECT network, change_address_policy, change_rotation, birthday FROM wallet
+              WHERE wallet_id = $1",
+    )
+    .bind(wallet_id)
+    .fetch_optional(&mut **db_transaction)
+    .await?;
+    let (network, change_address_policy, change_rotation, birthday) = match wallet {
+        Some(wallet) => wallet,
+        // nothing was written
+        None => return Ok(changeset),
+    };
+    changeset.network = network.as_deref().map(from_json).transpose()?;
+    changeset.change_address_policy = change_address_policy
+        .as_deref()
+        .map(from_json)
+        .transpose()?;
+    changeset.change_rotation = change_rotation.map(|rotation| rotation as u32);
+    changeset.birthday = birthday.as_deref().map(from_json).transpose()?;
+
+    let blocks: Vec<(i64, Vec<u8>)> =
+        sqlx::query_as("SELECT height, hash FROM block WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (height, hash) in blocks {
+        changeset
+            .chain
+            .insert(height as u32, Some(decode::<BlockHash>(&hash)?));
+    }
+
+    let mut indexer = keychain::ChangeSet::<KeychainKind>::default();
+    let keychains: Vec<(String, String)> =
+        sqlx::query_as("SELECT keychain, descriptor FROM keychain WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (keychain, descriptor) in keychains {
+        let descriptor =
+            Descriptor::<DescriptorPublicKey>::from_str(&descriptor).map_err(Error::Descriptor)?;
+        indexer
+            .keychains_added
+            .insert(from_json(&keychain)?, descriptor);
+    }
+    let last_revealed: Vec<(Vec<u8>, i64)> = sqlx::query_as(
+        "SELECT descriptor_id, last_revealed FROM last_revealed WHERE wallet_id = $1",
+    )
+    .bind(wallet_id)
+    .fetch_all(&mut **db_transaction)
+    .await?;
+    for (descriptor_id, index) in last_revealed {
+        let descriptor_id = DescriptorId(decode::<sha256::Hash>(&descriptor_id)?);
+        indexer.last_revealed.insert(descriptor_id, index as u32);
+    }
+    let marked_used: Vec<(Vec<u8>, i64, bool)> =
+        sqlx::query_as("SELECT descriptor_id, idx, used FROM marked_used WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (descriptor_id, index, used) in marked_used {
+        let descriptor_id = DescriptorId(decode::<sha256::Hash>(&descriptor_id)?);
+        indexer
+            .marked_used
+            .entry(descriptor_id)
+            .or_default()
+            .insert(index as u32, used);
+    }
+
+    let mut graph = tx_graph::ChangeSet::<ConfirmationTimeHeightAnchor>::default();
+    let txs: Vec<(Vec<u8>,)> = sqlx::query_as("SELECT raw_tx FROM tx WHERE wallet_id = $1")
+        .bind(wallet_id)
+        .fetch_all(&mut **db_transaction)
+        .await?;
+    for (raw_tx,) in txs {
+        graph.txs.insert(Arc::new(decode::<Transaction>(&raw_tx)?));
+    }
+    let txouts: Vec<(Vec<u8>, i64, Vec<u8>)> =
+        sqlx::query_as("SELECT txid, vout, raw_txout FROM txout WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (txid, vout, raw_txout) in txouts {
+        let outpoint = OutPoint::new(decode::<Txid>(&txid)?, vout as u32);
+        graph.txouts.insert(outpoint, decode::<TxOut>(&raw_txout)?);
+    }
+    let anchors: Vec<(Vec<u8>, String)> =
+        sqlx::query_as("SELECT txid, anchor FROM anchor WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (txid, anchor) in anchors {
+        graph
+            .anchors
+            .insert((from_json(&anchor)?, decode::<Txid>(&txid)?));
+    }
+    let last_seen: Vec<(Vec<u8>, i64)> =
+        sqlx::query_as("SELECT txid, seen_at FROM last_seen WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (txid, seen_at) in last_seen {
+        graph
+            .last_seen
+            .insert(decode::<Txid>(&txid)?, seen_at as u64);
+    }
+    let last_evicted: Vec<(Vec<u8>, i64)> =
+        sqlx::query_as("SELECT txid, evicted_at FROM last_evicted WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (txid, evicted_at) in last_evicted {
+        graph
+            .last_evicted
+            .insert(decode::<Txid>(&txid)?, evicted_at as u64);
+    }
+    changeset.indexed_tx_graph = indexed_tx_graph::ChangeSet { graph, indexer };
+
+    let labels: Vec<(String, String)> =
+        sqlx::query_as("SELECT label_ref, label FROM label WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (label_ref, label) in labels {
+        changeset.labels.insert(from_json(&label_ref)?, Some(label));
+    }
+    let broadcasts: Vec<(String,)> =
+        sqlx::query_as("SELECT record FROM broadcast WHERE wallet_id = $1 ORDER BY position")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (record,) in broadcasts {
+        changeset.broadcasts.push(from_json(&record)?);
+    }
+    let last_synced: Vec<(String, i64)> =
+        sqlx::query_as("SELECT keychain, synced_at FROM last_synced WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (keychain, synced_at) in last_synced {
+        changeset
+            .last_synced
+            .insert(from_json(&keychain)?, synced_at as u64);
+    }
+    let invoices: Vec<(i64, String)> =
+        sqlx::query_as("SELECT id, record FROM invoice WHERE wallet_id = $1")
+            .bind(wallet_id)
+            .fetch_all(&mut **db_transaction)
+            .await?;
+    for (id, record) in invoices {
+        changeset.invoices.insert(id as u32, from_json(&record)?);
+    }
+    let descriptor_revisions: Vec<(String, i64, Vec<u8>, Option<i64>)> = sqlx::query_as(
+        "SELECT keychain, revision, descriptor_id, activation_height FROM descriptor_revision
+          WHERE wallet_id = $1",
+    )
+    .bind(wallet_id)
+    .fetch_all(&mut **db_transaction)
+    .await?;
+    for (keychain, revision, descriptor_id, activation_height) in descriptor_revisions {
+        let record = DescriptorRevisionRecord {
+            descriptor_id: DescriptorId(decode::<sha256::Hash>(&descriptor_id)?),
+            activation_height: activation_height.map(|height| height as u32),
+        };
+        changeset
+            .descriptor_revisions
+            .entry(from_json(&keychain)?)
+            .or_default()
+            .insert(revision as u32, record);
+    }
+
+    Ok(changeset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{delete_wallet, list_wallets};
+    use bdk_wallet::bitcoin::{absolute, transaction, Amount, ScriptBuf};
+    use bdk_wallet::chain::{
+        BlockId, BlockTimeOrHeight, BroadcastOutcome, BroadcastRecord, ChangeAddressPolicy,
+        DescriptorExt, InvoiceRecord, LabelRef,
+    };
+    use bdk_wallet::wallet::persist::WalletPersister;
+    use bdk_wallet::Wallet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const DESCRIPTORS: (&str, &str) = (
+        "tr(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/0/*)",
+        "tr(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/1/*)",
+    );
+
+    /// The pool of the database at `DATABASE_URL`, or `None` if it isn't set: the tests need a
+    /// database they can create tables in, and pass without one.
+    async fn pool() -> Option<PgPool> {
+        let url = std::env::var("DATABASE_URL").ok()?;
+        Some(
+            PgPool::connect(&url)
+                .await
+                .expect("must connect to DATABASE_URL"),
+        )
+    }
+
+    /// A wallet id which no other test, or run of the tests, uses.
+    fn unique_wallet_id(name: &str) -> String {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("must be after the epoch")
+            .as_nanos();
+        format!(
+            "{}-{}-{}-{}",
+            name,
+            std::process::id(),
+            nanos,
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    /// A [`WalletPersister`] over a [`Store`], for the persist test-suite.
+    struct BlockingStore {
+        runtime: Arc<tokio::runtime::Runtime>,
+        store: Store,
+    }
+
+    impl WalletPersister for BlockingStore {
+        type Error = Error;
+
+        fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+            self.runtime
+                .block_on(AsyncWalletPersister::initialize(&mut self.store))
+        }
+
+        fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+            self.runtime
+                .block_on(AsyncWalletPersister::persist(&mut self.store, changeset))
+        }
+    }
+
+    #[test]
+    fn passes_persist_testsuite() {
+        let runtime = Arc::new(tokio::runtime::Runtime::new().expect("must start runtime"));
+        let pool = match runtime.block_on(pool()) {
+            Some(pool) => pool,
+            None => return,
+        };
+        bdk_persist_testsuite::run(|| {
+            let wallet_id = unique_wallet_id("testsuite");
+            let runtime = Arc::clone(&runtime);
+            let pool = pool.clone();
+            move || BlockingStore {
+                store: runtime
+                    .block_on(Store::new(pool.clone(), &wallet_id))
+                    .expect("must open store"),
+                runtime: Arc::clone(&runtime),
+            }
+        });
+    }
+
+    /// A changeset of a new wallet, with a transaction and every other component.
+    fn wallet_changeset() -> ChangeSet {
+        let (desc, change_desc) = DESCRIPTORS;
+        let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).expect("must create");
+        let address = wallet.reveal_next_address(KeychainKind::External);
+        let mut changeset = wallet.take_staged().expect("must stage the new wallet");
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let txid = tx.compute_txid();
+        let block = BlockId {
+            height: 1,
+            hash: BlockHash::all_zeros(),
+        };
+        changeset.chain.insert(block.height, Some(block.hash));
+        let graph = &mut changeset.indexed_tx_graph.graph;
+        graph.txs.insert(Arc::new(tx));
+        graph.txouts.insert(
+            OutPoint::new(Txid::all_zeros(), 1),
+            TxOut {
+                value: Amount::from_sat(5_000),
+                script_pubkey: ScriptBuf::new(),
+            },
+        );
+        graph.anchors.insert((
+            ConfirmationTimeHeightAnchor {
+                confirmation_height: 1,
+                confirmation_time: 100,
+                anchor_block: block,
+            },
+            txid,
+        ));
+        graph.last_seen.insert(txid, 100);
+        graph.last_evicted.insert(txid, 50);
+        changeset
+            .labels
+            .insert(LabelRef::Tx(txid), Some("coffee".to_string()));
+        changeset
+            .labels
+            .insert(LabelRef::Addr(address.script_pubkey()), Some("shop".into()));
+        changeset.broadcasts.push(BroadcastRecord {
+            txid,
+            backend: "esplora".to_string(),
+            outcome: BroadcastOutcome::Rejected("bad-txns".to_string()),
+            timestamp: 90,
+        });
+        changeset.change_address_policy = Some(ChangeAddressPolicy::RotateWithin(5));
+        changeset.change_rotation = Some(2);
+        changeset.birthday = Some(BlockTimeOrHeight::Height(1));
+        changeset.last_synced.insert(KeychainKind::External, 100);
+        changeset.invoices.insert(
+            0,
+            InvoiceRecord {
+                script_pubkey: address.script_pubkey(),
+                amount: Amount::from_sat(10_000),
+                expiry: BlockTimeOrHeight::Height(144),
+                min_confirmations: 1,
+            },
+        );
+        changeset
+    }
+
+    #[tokio::test]
+    async fn changesets_are_read_as_their_aggregate() {
+        let pool = match pool().await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let store = Store::new(pool, &unique_wallet_id("aggregate"))
+            .await
+            .expect("must open store");
+        let first = wallet_changeset();
+        let txid = first
+            .indexed_tx_graph
+            .graph
+            .txs
+            .iter()
+            .next()
+            .expect("must have a tx")
+            .compute_txid();
+        let descriptor_id = *first
+            .indexed_tx_graph
+            .indexer
+            .last_revealed
+            .keys()
+            .next()
+            .expect("must reveal an address");
+
+        let mut second = ChangeSet::default();
+        // a block is replaced, and another one is removed
+        second.chain.insert(0, Some(BlockHash::all_zeros()));
+        second.chain.insert(1, None);
+        // the timestamps only move forward
+        second.indexed_tx_graph.graph.last_seen.insert(txid, 200);
+        second.indexed_tx_graph.graph.last_evicted.insert(txid, 10);
+        second
+            .indexed_tx_graph
+            .indexer
+            .last_revealed
+            .insert(descriptor_id, 0);
+        second
+            .indexed_tx_graph
+            .indexer
+            .marked_used
+            .insert(descriptor_id, [(3, true)].into());
+        second.labels.insert(LabelRef::Tx(txid), None);
+        second.broadcasts.push(BroadcastRecord {
+            txid,
+            backend: "electrum".to_string(),
+            outcome: BroadcastOutcome::Accepted,
+            timestamp: 95,
+        });
+        second.last_synced.insert(KeychainKind::External, 50);
+        second.last_synced.insert(KeychainKind::Internal, 100);
+        // a recorded revision doesn't change, and the next one is added
+        second.descriptor_revisions.insert(
+            KeychainKind::External,
+            [
+                (
+                    0,
+                    DescriptorRevisionRecord {
+                        descriptor_id,
+                        activation_height: Some(5),
+                    },
+                ),
+                (
+                    1,
+                    DescriptorRevisionRecord {
+                        descriptor_id: DescriptorId::from_byte_array([1; 32]),
+                        activation_height: Some(7),
+                    },
+                ),
+            ]
+            .into(),
+        );
+
+        assert_eq!(store.read().await.expect("must read"), None);
+        store.write(&first).await.expect("must write");
+        store.write(&second).await.expect("must write");
+
+        let mut expected = first;
+        expected.append(second);
+        // the removed block and label are not stored at all
+        expected.chain.remove(&1);
+        expected.labels.remove(&LabelRef::Tx(txid));
+        assert_eq!(store.read().await.expect("must read"), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn wallets_are_listed_and_deleted() {
+        let pool = match pool().await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let changeset = wallet_changeset();
+        let wallet_ids = [unique_wallet_id("listed"), unique_wallet_id("listed")];
+        for wallet_id in &wallet_ids {
+            let store = Store::new(pool.clone(), wallet_id)
+                .await
+                .expect("must open store");
+            store.write(&changeset).await.expect("must write");
+        }
+        let listed = list_wallets(&pool).await.expect("must list wallets");
+        assert!(wallet_ids
+            .iter()
+            .all(|wallet_id| listed.contains(wallet_id)));
+
+        assert!(delete_wallet(&pool, &wallet_ids[0])
+            .await
+            .expect("must delete wallet"));
+        let listed = list_wallets(&pool).await.expect("must list wallets");
+        assert!(!listed.contains(&wallet_ids[0]));
+        assert!(listed.contains(&wallet_ids[1]));
+        let deleted = Store::new(pool.clone(), &wallet_ids[0])
+            .await
+            .expect("must open store");
+        assert_eq!(deleted.read().await.expect("must read"), None);
+        assert!(!delete_wallet(&pool, &wallet_ids[0])
+            .await
+            .expect("must delete wallet"));
+
+        // the other wallet is untouched
+        let kept = Store::new(pool, &wallet_ids[1])
+            .await
+            .expect("must open store");
+        assert_eq!(kept.read().await.expect("must read"), Some(changeset));
+    }
+
+    #[tokio::test]
+    async fn failed_write_leaves_database_unchanged() {
+        let pool = match pool().await {
+            Some(pool) => pool,
+            None => return,
+        };
+        let store = Store::new(pool, &unique_wallet_id("failed"))
+            .await
+            .expect("must open store");
+        let changeset = wallet_changeset();
+        store.write(&changeset).await.expect("must write");
+
+        let mut other_network = ChangeSet {
+            network: Some(Network::Bitcoin),
+            ..Default::default()
+        };
+        other_network
+            .labels
+            .insert(LabelRef::Tx(Txid::all_zeros()), Some("lost".to_string()));
+        match store.write(&other_network).await {
+            Err(Error::Network { expected, given }) => {
+                assert_eq!(expected, Network::Testnet);
+                assert_eq!(given, Network::Bitcoin);
+            }
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+        assert_eq!(store.read().await.expect("must read"), Some(changeset));
+    }
+
+    #[tokio::test]
+    async fn keychains_are_migrated_as_their_first_revision() {
+        let url = match std::env::var("DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        // the tables of the first version of the schema, in a schema of their own
+        let schema = unique_wallet_id("legacy").replace('-', "_");
+        let admin = PgPool::connect(&url).await.expect("must connect");
+        sqlx::query(&format!("CREATE SCHEMA {}", schema))
+            .execute(&admin)
+            .await
+            .expect("must create schema");
+        let options = sqlx::postgres::PgConnectOptions::from_str(&url)
+            .expect("must parse DATABASE_URL")
+            .options([("search_path", schema.as_str())]);
+        let pool = PgPool::connect_with(options).await.expect("must connect");
+        sqlx::raw_sql(include_str!("../migrations/0001_init.sql"))
+            .execute(&pool)
+            .await
+            .expect("must create the first schema");
+
+        let (desc, _) = DESCRIPTORS;
+        let wallet = Wallet::create_single(desc, Network::Testnet).expect("must create wallet");
+        let descriptor = wallet.get_descriptor_for_keychain(KeychainKind::External);
+        sqlx::query("INSERT INTO wallet (wallet_id) VALUES ('legacy')")
+            .execute(&pool)
+            .await
+            .expect("must insert wallet");
+        sqlx::query(
+            "INSERT INTO keychain (wallet_id, keychain, descriptor) VALUES ('legacy', $1, $2)",
+        )
+        .bind(to_json(&KeychainKind::External))
+        .bind(descriptor.to_string())
+        .execute(&pool)
+        .await
+        .expect("must insert keychain");
+
+        sqlx::raw_sql(include_str!("../migrations/0002_descriptor_revision.sql"))
+            .execute(&pool)
+            .await
+            .expect("must migrate");
+        let mut db_transaction = pool.begin().await.expect("must begin");
+        let changeset = read_changeset(&mut db_transaction, "legacy")
+            .await
+            .expect("must read");
+        drop(db_transaction);
+        assert_eq!(
+            changeset.descriptor_revisions,
+            [(
+                KeychainKind::External,
+                [(
+                    0,
+                    DescriptorRevisionRecord {
+                        descriptor_id: descriptor.descriptor_id(),
+                        activation_height: None,
+                    },
+                )]
+                .into(),
+            )]
+            .into()
+        );
+
+        pool.close().await;
+        sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema))
+            .execute(&admin)
+            .await
+            .expect("must drop schema");
+    }
+
+    /// Two instances of an application persist the same wallet at the same time, each with its
+    /// own pool of connections.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writers_of_a_wallet_lose_nothing() {
+        const WRITES: u64 = 25;
+        let url = match std::env::var("DATABASE_URL") {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let wallet_id = unique_wallet_id("race");
+        let txid = Txid::all_zeros();
+
+        let mut instances = Vec::new();
+        for instance in 0..2_u64 {
+            let pool = PgPool::connect(&url).await.expect("must connect");
+            let mut store = Store::new(pool, &wallet_id).await.expect("must open store");
+            instances.push(tokio::spawn(async move {
+                for write in 0..WRITES {
+                    let mut changeset = ChangeSet::default();
+                    let timestamp = write * 2 + instance;
+                    changeset.broadcasts.push(BroadcastRecord {
+                        txid,
+                        backend: format!("instance-{}", instance),
+                        outcome: BroadcastOutcome::Accepted,
+                        timestamp,
+                    });
+                    changeset
+                        .indexed_tx_graph
+                        .graph
+                        .last_seen
+                        .insert(txid, timestamp);
+                    changeset
+                        .last_synced
+                        .insert(KeychainKind::External, timestamp);
+                    AsyncWalletPersister::persist(&mut store, &changeset)
+                        .await
+                        .expect("concurrent writes must not fail");
+                }
+            }));
+        }
+        for instance in instances {
+            instance.await.expect("instance must not panic");
+        }
+
+        let pool = PgPool::connect(&url).await.expect("must connect");
+        let mut store = Store::new(pool, &wallet_id).await.expect("must open store");
+        let changeset = AsyncWalletPersister::initialize(&mut store)
+            .await
+            .expect("must read")
+            .expect("must have changes");
+        // every broadcast is recorded once, in the order of each instance
+        assert_eq!(changeset.broadcasts.len() as u64, 2 * WRITES);
+        for instance in 0..2 {
+            let timestamps = changeset
+                .broadcasts
+                .iter()
+                .filter(|record| record.backend == format!("instance-{}", instance))
+                .map(|record| record.timestamp)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                timestamps,
+                (0..WRITES)
+                    .map(|write| write * 2 + instance)
+                    .collect::<Vec<_>>()
+            );
+        }
+        let latest = 2 * WRITES - 1;
+        assert_eq!(
+            changeset.indexed_tx_graph.graph.last_seen,
+            [(txid, latest)].into()
+        );
+        assert_eq!(
+            changeset.last_synced,
+            [(KeychainKind::External, latest)].into()
+        );
+    }
+}
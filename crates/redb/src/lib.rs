@@ -0,0 +1,49 @@
+#![doc = include_str!("../README.md")]
+// only enables the `doc_cfg` feature when the `docsrs` configuration attribute is defined
+#![cfg_attr(docsrs, feature(doc_cfg))]
+
+mod store;
+
+use bdk_wallet::bitcoin::{consensus, Network};
+pub use redb;
+pub use store::Store;
+
+/// Error that occurs while reading or writing changesets with the redb database.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid network, cannot change the one already stored in the database.
+    Network { expected: Network, given: Network },
+    /// redb error, boxed as it is much larger than the others.
+    Redb(Box<redb::Error>),
+    /// A stored transaction, output or outpoint fails to decode.
+    Consensus(consensus::encode::Error),
+    /// A stored value which is serialized as JSON fails to decode.
+    Json(serde_json::Error),
+    /// A stored descriptor fails to parse.
+    Descriptor(bdk_wallet::miniscript::Error),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Network { expected, given } => write!(
+                f,
+                "network error trying to read or write change set, expected {}, given {}",
+                expected, given
+            ),
+            Self::Redb(e) => write!(f, "redb error reading or writing changeset: {}", e),
+            Self::Consensus(e) => write!(f, "failed to decode stored chain data: {}", e),
+            Self::Json(e) => write!(f, "failed to decode stored value: {}", e),
+            Self::Descriptor(e) => write!(f, "failed to parse stored descriptor: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Wrap any of the errors of redb, which all convert into [`redb::Error`].
+    pub(crate) fn redb(error: impl Into<redb::Error>) -> Self {
+        Self::Redb(Box::new(error.into()))
+    }
+}
@@ -0,0 +1,757 @@
+use core::fmt::Debug;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bdk_wallet::bitcoin::{
+    consensus, hashes::sha256, BlockHash, Network, OutPoint, Transaction, TxOut,
+};
+use bdk_wallet::bitcoin::{hashes::Hash, Txid};
+use bdk_wallet::chain::{
+    indexed_tx_graph, keychain, tx_graph, Append, BlockTimeOrHeight, BroadcastRecord,
+    ChangeAddressPolicy, ConfirmationTimeHeightAnchor, DescriptorId, DescriptorRevisionRecord,
+    InvoiceRecord, LabelRef,
+};
+use bdk_wallet::miniscript::{Descriptor, DescriptorPublicKey};
+use bdk_wallet::wallet::persist::{AsyncWalletPersister, FutureResult, WalletPersister};
+use bdk_wallet::wallet::ChangeSet;
+use bdk_wallet::KeychainKind;
+use redb::{Database, ReadTransaction, ReadableTable, TableDefinition, WriteTransaction};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Error;
+
+/// The components of a changeset which are a single value, as JSON by their name.
+const WALLET: TableDefinition<&str, &[u8]> = TableDefinition::new("wallet");
+/// The blocks of the local chain, hashes by height.
+const BLOCKS: TableDefinition<u32, &[u8]> = TableDefinition::new("blocks");
+/// The descriptors of the keychains, by keychain as JSON.
+const KEYCHAINS: TableDefinition<&str, &str> = TableDefinition::new("keychains");
+/// The last revealed derivation index of each descriptor, by descriptor id.
+const LAST_REVEALED: TableDefinition<&[u8], u32> = TableDefinition::new("last_revealed");
+/// The derivation indices marked as used or unused, by descriptor id and index.
+const MARKED_USED: TableDefinition<(&[u8], u32), bool> = TableDefinition::new("marked_used");
+/// The consensus encoded transactions, by txid.
+const TXS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("txs");
+/// The consensus encoded floating outputs, by outpoint.
+const TXOUTS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("txouts");
+/// The anchors of the transactions, as the pair of the anchor as JSON and the txid.
+const ANCHORS: TableDefinition<(&[u8], &[u8]), ()> = TableDefinition::new("anchors");
+/// The last-seen unix timestamps of the transactions, by txid.
+const LAST_SEEN: TableDefinition<&[u8], u64> = TableDefinition::new("last_seen");
+/// When the transactions were found missing from the mempool, by txid.
+const LAST_EVICTED: TableDefinition<&[u8], u64> = TableDefinition::new("last_evicted");
+/// The labels, by the labeled item as JSON.
+const LABELS: TableDefinition<&str, &str> = TableDefinition::new("labels");
+/// The broadcast attempts as JSON, by the order they were recorded in.
+const BROADCASTS: TableDefinition<u64, &[u8]> = TableDefinition::new("broadcasts");
+/// When each keychain was last synced, by keychain as JSON.
+const LAST_SYNCED: TableDefinition<&str, u64> = TableDefinition::new("last_synced");
+/// The invoices as JSON, by id.
+const INVOICES: TableDefinition<u32, &[u8]> = TableDefinition::new("invoices");
+/// The revisions of the descriptors as JSON, by keychain as JSON and revision.
+const DESCRIPTOR_REVISIONS: TableDefinition<(&str, u32), &[u8]> =
+    TableDefinition::new("descriptor_revisions");
+
+const NETWORK: &str = "network";
+const CHANGE_ADDRESS_POLICY: &str = "change_address_policy";
+const CHANGE_ROTATION: &str = "change_rotation";
+const BIRTHDAY: &str = "birthday";
+
+/// Persists the changes of a [`Wallet`](bdk_wallet::Wallet) in a redb [`Database`].
+///
+/// Each component of the changesets has its own table, and each changeset is written in a single
+/// transaction: a changeset which fails to be written leaves the database as it was. A redb
+/// database file can only be opened once at a time, the wallet has to keep its [`Store`] open
+/// rather than opening one for every write.
+pub struct Store {
+    db: Database,
+}
+
+impl Debug for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.db, f)
+    }
+}
+
+impl Store {
+    /// Open the database at `path`, creating it if the file doesn't exist or is empty.
+    pub fn open_or_create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Database::create(path).map(Self::new).map_err(Error::redb)
+    }
+
+    /// Use the tables of an open `db`, which may hold the tables of the application as well.
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    /// The underlying database.
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Create the tables which don't exist yet, and read them as the aggregate changeset.
+    ///
+    /// Returns `None` if nothing was written.
+    pub fn read(&self) -> Result<Option<ChangeSet>, Error> {
+        let db_transaction = self.db.begin_write().map_err(Error::redb)?;
+        create_tables(&db_transaction)?;
+        db_transaction.commit().map_err(Error::redb)?;
+
+        let db_transaction = self.db.begin_read().map_err(Error::redb)?;
+        let changeset = read_changeset(&db_transaction)?;
+        Ok(Some(changeset).filter(|changeset| !changeset.is_empty()))
+    }
+
+    /// Write the given `changeset` atomically.
+    pub fn write(&self, changeset: &ChangeSet) -> Result<(), Error> {
+        // no need to write anything if changeset is empty
+        if changeset.is_empty() {
+            return Ok(());
+        }
+        let db_transaction = self.db.begin_write().map_err(Error::redb)?;
+        write_changeset(&db_transaction, changeset)?;
+        db_transaction.commit().map_err(Error::redb)
+    }
+}
+
+impl WalletPersister for Store {
+    type Error = Error;
+
+    fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+        self.read()
+    }
+
+    fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+        self.write(changeset)
+    }
+}
+
+/// The database is read and written when the futures are created, as redb only has a blocking
+/// interface. Its transactions are fast enough not to need a thread of their own.
+impl AsyncWalletPersister for Store {
+    type Error = Error;
+
+    fn initialize<'a>(&'a mut self) -> FutureResult<'a, Option<ChangeSet>, Self::Error>
+    where
+        Self: 'a,
+    {
+        let result = self.read();
+        Box::pin(async move { result })
+    }
+
+    fn persist<'a>(&'a mut self, changeset: &'a ChangeSet) -> FutureResult<'a, (), Self::Error>
+    where
+        Self: 'a,
+    {
+        let result = self.write(changeset);
+        Box::pin(async move { result })
+    }
+}
+
+fn create_tables(db_transaction: &WriteTransaction) -> Result<(), Error> {
+    db_transaction.open_table(WALLET).map_err(Error::redb)?;
+    db_transaction.open_table(BLOCKS).map_err(Error::redb)?;
+    db_transaction.open_table(KEYCHAINS).map_err(Error::redb)?;
+    db_transaction
+        .open_table(LAST_REVEALED)
+        .map_err(Error::redb)?;
+    db_transaction
+        .open_table(MARKED_USED)
+        .map_err(Error::redb)?;
+    db_transaction.open_table(TXS).map_err(Error::redb)?;
+    db_transaction.open_table(TXOUTS).map_err(Error::redb)?;
+    db_transaction.open_table(ANCHORS).map_err(Error::redb)?;
+    db_transaction.open_table(LAST_SEEN).map_err(Error::redb)?;
+    db_transaction
+        .open_table(LAST_EVICTED)
+        .map_err(Error::redb)?;
+    db_transaction.open_table(LABELS).map_err(Error::redb)?;
+    db_transaction.open_table(BROADCASTS).map_err(Error::redb)?;
+    db_transaction
+        .open_table(LAST_SYNCED)
+        .map_err(Error::redb)?;
+    db_transaction.open_table(INVOICES).map_err(Error::redb)?;
+    db_transaction
+        .open_table(DESCRIPTOR_REVISIONS)
+        .map_err(Error::redb)?;
+    Ok(())
+}
+
+fn to_json<T: Serialize>(value: &T) -> Vec<u8> {
+    serde_json::to_vec(value).expect("value must serialize")
+}
+
+fn to_json_string<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("value must serialize")
+}
+
+fn from_json<T: DeserializeOwned>(json: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(json).map_err(Error::Json)
+}
+
+fn decode<T: consensus::Decodable>(bytes: &[u8]) -> Result<T, Error> {
+    consensus::deserialize(bytes).map_err(Error::Consensus)
+}
+
+/// Insert `value` at `key` of `table` if it is larger than the value which is there.
+fn insert_max(table: &mut redb::Table<&[u8], u64>, key: &[u8], value: u64) -> Result<(), Error> {
+    let current = table.get(key).map_err(Error::redb)?.map(|v| v.value());
+    if current < Some(value) {
+        table.insert(key, value).map_err(Error::redb)?;
+    }
+    Ok(())
+}
+
+fn write_changeset(db_transaction: &WriteTransaction, changeset: &ChangeSet) -> Result<(), Error> {
+    let mut wallet = db_transaction.open_table(WALLET).map_err(Error::redb)?;
+    if let Some(network) = changeset.network {
+        let current = match wallet.get(NETWORK).map_err(Error::redb)? {
+            Some(json) => Some(from_json::<Network>(json.value())?),
+            None => None,
+        };
+        match current {
+            // if no network change do nothing
+            Some(current) if current == network => {}
+            // if new network not the same as current, error
+            Some(current) => {
+                return Err(Error::Network {
+                    expected: current,
+                    given: network,
+                })
+            }
+            None => {
+                wallet
+                    .insert(NETWORK, to_json(&network).as_slice())
+                    .map_err(Error::redb)?;
+            }
+        }
+    }
+    if let Some(policy) = &changeset.change_address_policy {
+        wallet
+            .insert(CHANGE_ADDRESS_POLICY, to_json(policy).as_slice())
+            .map_err(Error::redb)?;
+    }
+    if let Some(rotation) = &changeset.change_rotation {
+        wallet
+            .insert(CHANGE_ROTATION, to_json(rotation).as_slice())
+            .map_err(Error::redb)?;
+    }
+    if let Some(birthday) = &changeset.birthday {
+        wallet
+            .insert(BIRTHDAY, to_json(birthday).as_slice())
+            .map_err(Error::redb)?;
+    }
+
+    let mut blocks = db_transaction.open_table(BLOCKS).map_err(Error::redb)?;
+    for (height, hash) in &changeset.chain {
+        match hash {
+            Some(hash) => blocks
+                .insert(height, hash.as_byte_array().as_slice())
+                .map_err(Error::redb)?,
+            None => blocks.remove(height).map_err(Error::redb)?,
+        };
+    }
+
+    let keychain_changeset = &changeset.indexed_tx_graph.indexer;
+    let mut keychains = db_transaction.open_table(KEYCHAINS).map_err(Error::redb)?;
+    for (keychain, descriptor) in &keychain_changeset.keychains_added {
+        let keychain = to_json_string(keychain);
+        let descriptor = descriptor.to_string();
+        // a keychain keeps its descriptor, and a descriptor its keychain, as with `Append`
+        if keychains
+            .get(keychain.as_str())
+            .map_err(Error::redb)?
+            .is_some()
+        {
+            continue;
+        }
+        let mut descriptor_exists = false;
+        for entry in keychains.iter().map_err(Error::redb)? {
+            let (_, stored) = entry.map_err(Error::redb)?;
+            descriptor_exists |= stored.value() == descriptor;
+        }
+        if !descriptor_exists {
+            keychains
+                .insert(keychain.as_str(), descriptor.as_str())
+                .map_err(Error::redb)?;
+        }
+    }
+    let mut last_revealed = db_transaction
+        .open_table(LAST_REVEALED)
+        .map_err(Error::redb)?;
+    for (descriptor_id, index) in &keychain_changeset.last_revealed {
+        let key = descriptor_id.to_byte_array();
+        let current = last_revealed
+            .get(key.as_slice())
+            .map_err(Error::redb)?
+            .map(|v| v.value());
+        if current < Some(*index) {
+            last_revealed
+                .insert(key.as_slice(), index)
+                .map_err(Error::redb)?;
+        }
+    }
+    let mut marked_used = db_transaction
+        .open_table(MARKED_USED)
+        .map_err(Error::redb)?;
+    for (descriptor_id, marks) in &keychain_changeset.marked_used {
+        let descriptor_id = descriptor_id.to_byte_array();
+        for (index, used) in marks {
+            marked_used
+                .insert((descriptor_id.as_slice(), *index), used)
+                .map_err(Error::redb)?;
+        }
+    }
+
+    let graph_changeset = &changeset.indexed_tx_graph.graph;
+    let mut txs = db_transaction.open_table(TXS).map_err(Error::redb)?;
+    for tx in &graph_changeset.txs {
+        let txid = consensus::serialize(&tx.compute_txid());
+        txs.insert(
+            txid.as_slice(),
+            consensus::serialize(tx.as_ref()).as_slice(),
+        )
+        .map_err(Error::redb)?;
+    }
+    let mut txouts = db_transaction.open_table(TXOUTS).map_err(Error::redb)?;
+    for (outpoint, txout) in &graph_changeset.txouts {
+        txouts
+            .insert(
+                consensus::serialize(outpoint).as_slice(),
+                consensus::serialize(txout).as_slice(),
+            )
+            .map_err(Error::redb)?;
+    }
+    let mut anchors = db_transaction.open_table(ANCHORS).map_err(Error::redb)?;
+    for (anchor, txid) in &graph_changeset.anchors {
+        anchors
+            .insert(
+                (
+                    to_json(anchor).as_slice(),
+                    consensus::serialize(txid).as_slice(),
+                ),
+                (),
+            )
+            .map_err(Error::redb)?;
+    }
+    let mut last_seen = db_transaction.open_table(LAST_SEEN).map_err(Error::redb)?;
+    for (txid, seen_at) in &graph_changeset.last_seen {
+        insert_max(&mut last_seen, &consensus::serialize(txid), *seen_at)?;
+    }
+    let mut last_evicted = db_transaction
+        .open_table(LAST_EVICTED)
+        .map_err(Error::redb)?;
+    for (txid, evicted_at) in &graph_changeset.last_evicted {
+        insert_max(&mut last_evicted, &consensus::serialize(txid), *evicted_at)?;
+    }
+
+    let mut labels = db_transaction.open_table(LABELS).map_err(Error::redb)?;
+    for (label_ref, label) in &changeset.labels {
+        let label_ref = to_json_string(label_ref);
+        match label {
+            Some(label) => labels
+                .insert(label_ref.as_str(), label.as_str())
+                .map_err(Error::redb)?,
+            None => labels.remove(label_ref.as_str()).map_err(Error::redb)?,
+        };
+    }
+    let mut broadcasts = db_transaction.open_table(BROADCASTS).map_err(Error::redb)?;
+    let next_broadcast = match broadcasts.last().map_err(Error::redb)? {
+        Some((position, _)) => position.value() + 1,
+        None => 0,
+    };
+    for (position, broadcast) in (next_broadcast..).zip(&changeset.broadcasts) {
+        broadcasts
+            .insert(position, to_json(broadcast).as_slice())
+            .map_err(Error::redb)?;
+    }
+    let mut last_synced = db_transaction
+        .open_table(LAST_SYNCED)
+        .map_err(Error::redb)?;
+    for (keychain, synced_at) in &changeset.last_synced {
+        let keychain = to_json_string(keychain);
+        let current = last_synced
+            .get(keychain.as_str())
+            .map_err(Error::redb)?
+            .map(|v| v.value());
+        if current < Some(*synced_at) {
+            last_synced
+                .insert(keychain.as_str(), synced_at)
+                .map_err(Error::redb)?;
+        }
+    }
+    let mut invoices = db_transaction.open_table(INVOICES).map_err(Error::redb)?;
+    for (id, invoice) in &changeset.invoices {
+        invoices
+            .insert(id, to_json(invoice).as_slice())
+            .map_err(Error::redb)?;
+    }
+    let mut descriptor_revisions = db_transaction
+        .open_table(DESCRIPTOR_REVISIONS)
+        .map_err(Error::redb)?;
+    for (keychain, revisions) in &changeset.descriptor_revisions {
+        let keychain = to_json_string(keychain);
+        for (revision, record) in revisions {
+            // a revision never changes once recorded
+            let key = (keychain.as_str(), *revision);
+            if descriptor_revisions
+                .get(key)
+                .map_err(Error::redb)?
+                .is_none()
+            {
+                descriptor_revisions
+                    .insert(key, to_json(record).as_slice())
+                    .map_err(Error::redb)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_changeset(db_transaction: &ReadTransaction) -> Result<ChangeSet, Error> {
+    let mut changeset = ChangeSet::default();
+
+    let wallet = db_transaction.open_table(WALLET).map_err(Error::redb)?;
+    let get_json = |name: &str| wallet.get(name).map_err(Error::redb);
+    if let Some(json) = get_json(NETWORK)? {
+        changeset.network = Some(from_json::<Network>(json.value())?);
+    }
+    if let Some(json) = get_json(CHANGE_ADDRESS_POLICY)? {
+        changeset.change_address_policy = Some(from_json::<ChangeAddressPolicy>(json.value())?);
+    }
+    if let Some(json) = get_json(CHANGE_ROTATION)? {
+        changeset.change_rotation = Some(from_json::<u32>(json.value())?);
+    }
+    if let Some(json) = get_json(BIRTHDAY)? {
+        changeset.birthday = Some(from_json::<BlockTimeOrHeight>(json.value())?);
+    }
+
+    let blocks = db_transaction.open_table(BLOCKS).map_err(Error::redb)?;
+    for entry in blocks.iter().map_err(Error::redb)? {
+        let (height, hash) = entry.map_err(Error::redb)?;
+        let hash = decode::<BlockHash>(hash.value())?;
+        changeset.chain.insert(height.value(), Some(hash));
+    }
+
+    let mut indexer = keychain::ChangeSet::<KeychainKind>::default();
+    let keychains = db_transaction.open_table(KEYCHAINS).map_err(Error::redb)?;
+    for entry in keychains.iter().map_err(Error::redb)? {
+        let (keychain, descriptor) = entry.map_err(Error::redb)?;
+        let keychain = from_json::<KeychainKind>(keychain.value().as_bytes())?;
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(descriptor.value())
+            .map_err(Error::Descriptor)?;
+        indexer.keychains_added.insert(keychain, descriptor);
+    }
+    let last_revealed = db_transaction
+        .open_table(LAST_REVEALED)
+        .map_err(Error::redb)?;
+    for entry in last_revealed.iter().map_err(Error::redb)? {
+        let (descriptor_id, index) = entry.map_err(Error::redb)?;
+        let descriptor_id = DescriptorId(decode::<sha256::Hash>(descriptor_id.value())?);
+        indexer.last_revealed.insert(descriptor_id, index.value());
+    }
+    let marked_used = db_transaction
+        .open_table(MARKED_USED)
+        .map_err(Error::redb)?;
+    for entry in marked_used.iter().map_err(Error::redb)? {
+        let (key, used) = entry.map_err(Error::redb)?;
+        let (descriptor_id, index) = key.value();
+        let descriptor_id = DescriptorId(decode::<sha256::Hash>(descriptor_id)?);
+        indexer
+            .marked_used
+            .entry(descriptor_id)
+            .or_default()
+            .insert(index, used.value());
+    }
+
+    let mut graph = tx_graph::ChangeSet::<ConfirmationTimeHeightAnchor>::default();
+    let txs = db_transaction.open_table(TXS).map_err(Error::redb)?;
+    for entry in txs.iter().map_err(Error::redb)? {
+        let (_, tx) = entry.map_err(Error::redb)?;
+        graph
+            .txs
+            .insert(Arc::new(decode::<Transaction>(tx.value())?));
+    }
+    let txouts = db_transaction.open_table(TXOUTS).map_err(Error::redb)?;
+    for entry in txouts.iter().map_err(Error::redb)? {
+        let (outpoint, txout) = entry.map_err(Error::redb)?;
+        graph.txouts.insert(
+            decode::<OutPoint>(outpoint.value())?,
+            decode::<TxOut>(txout.value())?,
+        );
+    }
+    let anchors = db_transaction.open_table(ANCHORS).map_err(Error::redb)?;
+    for entry in anchors.iter().map_err(Error::redb)? {
+        let (key, _) = entry.map_err(Error::redb)?;
+        let (anchor, txid) = key.value();
+        graph
+            .anchors
+            .insert((from_json(anchor)?, decode::<Txid>(txid)?));
+    }
+    let last_seen = db_transaction.open_table(LAST_SEEN).map_err(Error::redb)?;
+    for entry in last_seen.iter().map_err(Error::redb)? {
+        let (txid, seen_at) = entry.map_err(Error::redb)?;
+        graph
+            .last_seen
+            .insert(decode::<Txid>(txid.value())?, seen_at.value());
+    }
+    let last_evicted = db_transaction
+        .open_table(LAST_EVICTED)
+        .map_err(Error::redb)?;
+    for entry in last_evicted.iter().map_err(Error::redb)? {
+        let (txid, evicted_at) = entry.map_err(Error::redb)?;
+        graph
+            .last_evicted
+            .insert(decode::<Txid>(txid.value())?, evicted_at.value());
+    }
+    changeset.indexed_tx_graph = indexed_tx_graph::ChangeSet { graph, indexer };
+
+    let labels = db_transaction.open_table(LABELS).map_err(Error::redb)?;
+    for entry in labels.iter().map_err(Error::redb)? {
+        let (label_ref, label) = entry.map_err(Error::redb)?;
+        let label_ref = from_json::<LabelRef>(label_ref.value().as_bytes())?;
+        changeset
+            .labels
+            .insert(label_ref, Some(label.value().to_string()));
+    }
+    let broadcasts = db_transaction.open_table(BROADCASTS).map_err(Error::redb)?;
+    for entry in broadcasts.iter().map_err(Error::redb)? {
+        let (_, broadcast) = entry.map_err(Error::redb)?;
+        changeset
+            .broadcasts
+            .push(from_json::<BroadcastRecord>(broadcast.value())?);
+    }
+    let last_synced = db_transaction
+        .open_table(LAST_SYNCED)
+        .map_err(Error::redb)?;
+    for entry in last_synced.iter().map_err(Error::redb)? {
+        let (keychain, synced_at) = entry.map_err(Error::redb)?;
+        let keychain = from_json::<KeychainKind>(keychain.value().as_bytes())?;
+        changeset.last_synced.insert(keychain, synced_at.value());
+    }
+    let invoices = db_transaction.open_table(INVOICES).map_err(Error::redb)?;
+    for entry in invoices.iter().map_err(Error::redb)? {
+        let (id, invoice) = entry.map_err(Error::redb)?;
+        changeset
+            .invoices
+            .insert(id.value(), from_json::<InvoiceRecord>(invoice.value())?);
+    }
+    let descriptor_revisions = db_transaction
+        .open_table(DESCRIPTOR_REVISIONS)
+        .map_err(Error::redb)?;
+    for entry in descriptor_revisions.iter().map_err(Error::redb)? {
+        let (key, record) = entry.map_err(Error::redb)?;
+        let (keychain, revision) = key.value();
+        changeset
+            .descriptor_revisions
+            .entry(from_json::<KeychainKind>(keychain.as_bytes())?)
+            .or_default()
+            .insert(
+                revision,
+                from_json::<DescriptorRevisionRecord>(record.value())?,
+            );
+    }
+
+    Ok(changeset)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk_wallet::bitcoin::{absolute, transaction, Amount, ScriptBuf};
+    use bdk_wallet::chain::{BlockId, BroadcastOutcome};
+    use bdk_wallet::Wallet;
+
+    const DESCRIPTORS: (&str, &str) = (
+        "tr(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/0/*)",
+        "tr(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/1/*)",
+    );
+
+    fn open(dir: &tempfile::TempDir) -> Store {
+        Store::open_or_create(dir.path().join("wallet.redb")).expect("must open db")
+    }
+
+    #[test]
+    fn passes_persist_testsuite() {
+        bdk_persist_testsuite::run(|| {
+            let dir = tempfile::tempdir().expect("must create tempdir");
+            move || open(&dir)
+        });
+    }
+
+    /// A changeset of a new wallet, with a transaction and every other component.
+    fn wallet_changeset() -> ChangeSet {
+        let (desc, change_desc) = DESCRIPTORS;
+        let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).expect("must create");
+        let address = wallet.reveal_next_address(KeychainKind::External);
+        let mut changeset = wallet.take_staged().expect("must stage the new wallet");
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let txid = tx.compute_txid();
+        let block = BlockId {
+            height: 1,
+            hash: BlockHash::all_zeros(),
+        };
+        changeset.chain.insert(block.height, Some(block.hash));
+        let graph = &mut changeset.indexed_tx_graph.graph;
+        graph.txs.insert(Arc::new(tx));
+        graph.txouts.insert(
+            OutPoint::new(Txid::all_zeros(), 1),
+            TxOut {
+                value: Amount::from_sat(5_000),
+                script_pubkey: ScriptBuf::new(),
+            },
+        );
+        graph.anchors.insert((
+            ConfirmationTimeHeightAnchor {
+                confirmation_height: 1,
+                confirmation_time: 100,
+                anchor_block: block,
+            },
+            txid,
+        ));
+        graph.last_seen.insert(txid, 100);
+        graph.last_evicted.insert(txid, 50);
+        changeset
+            .labels
+            .insert(LabelRef::Tx(txid), Some("coffee".to_string()));
+        changeset
+            .labels
+            .insert(LabelRef::Addr(address.script_pubkey()), Some("shop".into()));
+        changeset.broadcasts.push(BroadcastRecord {
+            txid,
+            backend: "esplora".to_string(),
+            outcome: BroadcastOutcome::Rejected("bad-txns".to_string()),
+            timestamp: 90,
+        });
+        changeset.change_address_policy = Some(ChangeAddressPolicy::RotateWithin(5));
+        changeset.change_rotation = Some(2);
+        changeset.birthday = Some(BlockTimeOrHeight::Height(1));
+        changeset.last_synced.insert(KeychainKind::External, 100);
+        changeset.invoices.insert(
+            0,
+            InvoiceRecord {
+                script_pubkey: address.script_pubkey(),
+                amount: Amount::from_sat(10_000),
+                expiry: BlockTimeOrHeight::Height(144),
+                min_confirmations: 1,
+            },
+        );
+        changeset
+    }
+
+    #[test]
+    fn changesets_are_read_as_their_aggregate() {
+        let dir = tempfile::tempdir().expect("must create tempdir");
+        let first = wallet_changeset();
+        let txid = first
+            .indexed_tx_graph
+            .graph
+            .txs
+            .iter()
+            .next()
+            .expect("must have a tx")
+            .compute_txid();
+        let descriptor_id = *first
+            .indexed_tx_graph
+            .indexer
+            .last_revealed
+            .keys()
+            .next()
+            .expect("must reveal an address");
+
+        let mut second = ChangeSet::default();
+        // a block is replaced, and another one is removed
+        second.chain.insert(0, Some(BlockHash::all_zeros()));
+        second.chain.insert(1, None);
+        // the timestamps only move forward
+        second.indexed_tx_graph.graph.last_seen.insert(txid, 200);
+        second.indexed_tx_graph.graph.last_evicted.insert(txid, 10);
+        second
+            .indexed_tx_graph
+            .indexer
+            .last_revealed
+            .insert(descriptor_id, 0);
+        second
+            .indexed_tx_graph
+            .indexer
+            .marked_used
+            .insert(descriptor_id, [(3, true)].into());
+        second.labels.insert(LabelRef::Tx(txid), None);
+        second.broadcasts.push(BroadcastRecord {
+            txid,
+            backend: "electrum".to_string(),
+            outcome: BroadcastOutcome::Accepted,
+            timestamp: 95,
+        });
+        second.last_synced.insert(KeychainKind::External, 50);
+        second.last_synced.insert(KeychainKind::Internal, 100);
+        // a recorded revision doesn't change, and the next one is added
+        second.descriptor_revisions.insert(
+            KeychainKind::External,
+            [
+                (
+                    0,
+                    DescriptorRevisionRecord {
+                        descriptor_id,
+                        activation_height: Some(5),
+                    },
+                ),
+                (
+                    1,
+                    DescriptorRevisionRecord {
+                        descriptor_id: DescriptorId::from_byte_array([1; 32]),
+                        activation_height: Some(7),
+                    },
+                ),
+            ]
+            .into(),
+        );
+
+        let store = open(&dir);
+        assert_eq!(store.read().expect("must read"), None);
+        store.write(&first).expect("must write");
+        store.write(&second).expect("must write");
+        drop(store);
+
+        let mut expected = first;
+        expected.append(second);
+        // the removed block and label are not stored at all
+        expected.chain.remove(&1);
+        expected.labels.remove(&LabelRef::Tx(txid));
+        assert_eq!(open(&dir).read().expect("must read"), Some(expected));
+    }
+
+    #[test]
+    fn failed_write_leaves_database_unchanged() {
+        let dir = tempfile::tempdir().expect("must create tempdir");
+        let changeset = wallet_changeset();
+        let store = open(&dir);
+        store.write(&changeset).expect("must write");
+
+        let mut other_network = ChangeSet {
+            network: Some(Network::Bitcoin),
+            ..Default::default()
+        };
+        other_network
+            .labels
+            .insert(LabelRef::Tx(Txid::all_zeros()), Some("lost".to_string()));
+        match store.write(&other_network) {
+            Err(Error::Network { expected, given }) => {
+                assert_eq!(expected, Network::Testnet);
+                assert_eq!(given, Network::Bitcoin);
+            }
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        }
+        assert_eq!(store.read().expect("must read"), Some(changeset));
+    }
+}
@@ -0,0 +1,11 @@
+-- manually marked derivation indexes of a descriptor,
+-- descriptor_id is a sha256::Hash id of the descriptor string w/o the checksum,
+-- derivation index is a u32,
+-- used is 1 if the index was marked as used and 0 if it was marked as unused
+CREATE TABLE keychain_marked_used
+(
+    descriptor_id BLOB    NOT NULL,
+    idx           INTEGER NOT NULL,
+    used          INTEGER NOT NULL,
+    PRIMARY KEY (descriptor_id, idx)
+) STRICT;
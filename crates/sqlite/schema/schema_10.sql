@@ -0,0 +1,13 @@
+-- the invoices of the wallet, expiry_kind is 'height' or 'time' and expiry is the block height or
+-- the time in unix seconds the invoice expires at
+CREATE TABLE invoice
+(
+    wallet_id         TEXT    NOT NULL,
+    id                INTEGER NOT NULL,
+    script_pubkey     BLOB    NOT NULL,
+    amount            INTEGER NOT NULL,
+    expiry_kind       TEXT    NOT NULL,
+    expiry            INTEGER NOT NULL,
+    min_confirmations INTEGER NOT NULL,
+    PRIMARY KEY (wallet_id, id)
+) STRICT;
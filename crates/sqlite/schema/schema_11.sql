@@ -0,0 +1,15 @@
+-- the revisions of the descriptors of the keychains, keychain is the json serialized keychain
+-- structure as JSONB and activation_height is the height of the tip of the chain of the wallet
+-- when the revision became active, NULL if unknown
+CREATE TABLE descriptor_revision
+(
+    wallet_id         TEXT    NOT NULL,
+    keychain          BLOB    NOT NULL,
+    revision          INTEGER NOT NULL,
+    descriptor_id     BLOB    NOT NULL,
+    activation_height INTEGER,
+    PRIMARY KEY (wallet_id, keychain, revision)
+) STRICT;
+-- the keychains stored before had the same descriptor from the start, their revision 0
+INSERT INTO descriptor_revision (wallet_id, keychain, revision, descriptor_id, activation_height)
+SELECT wallet_id, keychain, 0, descriptor_id, NULL FROM keychain;
@@ -0,0 +1,3 @@
+-- schema versions are tracked in the schema_migrations table, with one row per applied migration,
+-- the legacy version table only holds the number of statements executed before it
+DROP TABLE version;
@@ -0,0 +1,107 @@
+-- every table is namespaced by wallet_id, so that multiple wallets can share a database,
+-- the data stored so far belongs to the 'default' wallet
+
+-- network is the valid network for all other table data of the wallet
+CREATE TABLE network_new
+(
+    wallet_id TEXT PRIMARY KEY NOT NULL,
+    name      TEXT             NOT NULL
+) STRICT;
+INSERT INTO network_new (wallet_id, name)
+SELECT 'default', name FROM network;
+
+CREATE TABLE keychain_new
+(
+    wallet_id     TEXT    NOT NULL,
+    keychain      BLOB    NOT NULL,
+    descriptor    TEXT    NOT NULL,
+    descriptor_id BLOB    NOT NULL,
+    last_revealed INTEGER,
+    PRIMARY KEY (wallet_id, keychain)
+) STRICT;
+INSERT INTO keychain_new (wallet_id, keychain, descriptor, descriptor_id, last_revealed)
+SELECT 'default', keychain, descriptor, descriptor_id, last_revealed FROM keychain;
+
+CREATE TABLE keychain_marked_used_new
+(
+    wallet_id     TEXT    NOT NULL,
+    descriptor_id BLOB    NOT NULL,
+    idx           INTEGER NOT NULL,
+    used          INTEGER NOT NULL,
+    PRIMARY KEY (wallet_id, descriptor_id, idx)
+) STRICT;
+INSERT INTO keychain_marked_used_new (wallet_id, descriptor_id, idx, used)
+SELECT 'default', descriptor_id, idx, used FROM keychain_marked_used;
+
+CREATE TABLE block_new
+(
+    wallet_id TEXT    NOT NULL,
+    hash      TEXT    NOT NULL,
+    height    INTEGER NOT NULL,
+    PRIMARY KEY (wallet_id, hash)
+) STRICT;
+INSERT INTO block_new (wallet_id, hash, height)
+SELECT 'default', hash, height FROM block;
+
+CREATE TABLE tx_new
+(
+    wallet_id TEXT NOT NULL,
+    txid      TEXT NOT NULL,
+    whole_tx  BLOB,
+    last_seen INTEGER,
+    PRIMARY KEY (wallet_id, txid)
+) STRICT;
+INSERT INTO tx_new (wallet_id, txid, whole_tx, last_seen)
+SELECT 'default', txid, whole_tx, last_seen FROM tx;
+
+CREATE TABLE txout_new
+(
+    wallet_id TEXT    NOT NULL,
+    txid      TEXT    NOT NULL,
+    vout      INTEGER NOT NULL,
+    value     INTEGER NOT NULL,
+    script    BLOB    NOT NULL,
+    PRIMARY KEY (wallet_id, txid, vout)
+) STRICT;
+INSERT INTO txout_new (wallet_id, txid, vout, value, script)
+SELECT 'default', txid, vout, value, script FROM txout;
+
+-- anchors are moved back last, once the tables they reference are in place
+CREATE TABLE anchor_tx_old
+(
+    wallet_id  TEXT NOT NULL,
+    block_hash TEXT NOT NULL,
+    anchor     BLOB NOT NULL,
+    txid       TEXT NOT NULL
+) STRICT;
+INSERT INTO anchor_tx_old (wallet_id, block_hash, anchor, txid)
+SELECT 'default', block_hash, anchor, txid FROM anchor_tx;
+
+DROP TABLE anchor_tx;
+DROP TABLE txout;
+DROP TABLE tx;
+DROP TABLE block;
+DROP TABLE keychain_marked_used;
+DROP TABLE keychain;
+DROP TABLE network;
+
+ALTER TABLE network_new RENAME TO network;
+ALTER TABLE keychain_new RENAME TO keychain;
+ALTER TABLE keychain_marked_used_new RENAME TO keychain_marked_used;
+ALTER TABLE block_new RENAME TO block;
+ALTER TABLE tx_new RENAME TO tx;
+ALTER TABLE txout_new RENAME TO txout;
+
+CREATE TABLE anchor_tx
+(
+    wallet_id  TEXT NOT NULL,
+    block_hash TEXT NOT NULL,
+    anchor     BLOB NOT NULL,
+    txid       TEXT NOT NULL,
+    UNIQUE (wallet_id, anchor, txid),
+    FOREIGN KEY (wallet_id, txid) REFERENCES tx (wallet_id, txid),
+    FOREIGN KEY (wallet_id, block_hash) REFERENCES block (wallet_id, hash)
+) STRICT;
+INSERT INTO anchor_tx (wallet_id, block_hash, anchor, txid)
+SELECT wallet_id, block_hash, anchor, txid FROM anchor_tx_old;
+DROP TABLE anchor_tx_old;
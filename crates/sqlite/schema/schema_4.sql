@@ -0,0 +1,11 @@
+-- labels of the wallet's transactions, addresses, outputs and inputs,
+-- type is one of 'tx', 'addr', 'output' or 'input' as in BIP-329,
+-- ref is the txid for 'tx', the hex script pubkey for 'addr' and <txid>:<index> otherwise
+CREATE TABLE label
+(
+    wallet_id TEXT NOT NULL,
+    type      TEXT NOT NULL,
+    ref       TEXT NOT NULL,
+    label     TEXT NOT NULL,
+    PRIMARY KEY (wallet_id, type, ref)
+) STRICT;
@@ -0,0 +1,2 @@
+-- last evicted is a u64 unix epoch seconds of when the tx was found missing from the mempool
+ALTER TABLE tx ADD COLUMN last_evicted INTEGER;
@@ -0,0 +1,12 @@
+-- the broadcast journal of the wallet, append-only and read in rowid order,
+-- outcome is 'accepted' or 'rejected', reason is the reason of the rejection,
+-- timestamp is a u64 unix epoch seconds of the broadcast
+CREATE TABLE broadcast
+(
+    wallet_id TEXT    NOT NULL,
+    txid      TEXT    NOT NULL,
+    backend   TEXT    NOT NULL,
+    outcome   TEXT    NOT NULL,
+    reason    TEXT,
+    timestamp INTEGER NOT NULL
+) STRICT;
@@ -0,0 +1,10 @@
+-- the change address policy of the wallet, policy is 'fresh_always', 'rotate_within' or
+-- 'fixed' and value is the number of addresses or the index of the policy, rotation is the
+-- position of the next change address in the cycle of 'rotate_within'
+CREATE TABLE change_policy
+(
+    wallet_id TEXT PRIMARY KEY NOT NULL,
+    policy    TEXT,
+    value     INTEGER,
+    rotation  INTEGER
+) STRICT;
@@ -0,0 +1,8 @@
+-- the birthday of the wallet, kind is 'height' or 'time' and value is the block height or the
+-- time in unix seconds
+CREATE TABLE birthday
+(
+    wallet_id TEXT PRIMARY KEY NOT NULL,
+    kind      TEXT    NOT NULL,
+    value     INTEGER NOT NULL
+) STRICT;
@@ -0,0 +1,9 @@
+-- when each keychain was last synced, keychain is the json serialized keychain structure as
+-- JSONB and last_synced is the time in unix seconds
+CREATE TABLE keychain_sync
+(
+    wallet_id   TEXT    NOT NULL,
+    keychain    BLOB    NOT NULL,
+    last_synced INTEGER NOT NULL,
+    PRIMARY KEY (wallet_id, keychain)
+) STRICT;
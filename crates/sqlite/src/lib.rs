@@ -9,6 +9,25 @@ use bdk_chain::bitcoin::Network;
 pub use rusqlite;
 pub use store::Store;
 
+use rusqlite::Connection;
+
+/// The identifier of the wallet used by [`Store::new`].
+pub const DEFAULT_WALLET_ID: &str = "default";
+
+/// List the identifiers of the wallets stored in the database, see [`Store::new_with_wallet_id`].
+///
+/// This migrates the database schema to the latest version, like [`Store::new`].
+pub fn list_wallets(conn: &mut Connection) -> Result<Vec<String>, Error> {
+    Store::<(), ()>::migrate(conn)?;
+    let mut stmt = conn
+        .prepare("SELECT wallet_id FROM network UNION SELECT wallet_id FROM keychain UNION SELECT wallet_id FROM block ORDER BY wallet_id")
+        .map_err(Error::Sqlite)?;
+    let wallet_ids = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(Error::Sqlite)?;
+    wallet_ids.collect::<Result<_, _>>().map_err(Error::Sqlite)
+}
+
 /// Error that occurs while reading or writing change sets with the SQLite database.
 #[derive(Debug)]
 pub enum Error {
@@ -16,6 +35,8 @@ pub enum Error {
     Network { expected: Network, given: Network },
     /// SQLite error.
     Sqlite(rusqlite::Error),
+    /// The database schema is newer than the ones this version of the crate can migrate.
+    SchemaVersion { found: u32, expected: u32 },
 }
 
 impl core::fmt::Display for Error {
@@ -27,6 +48,11 @@ impl core::fmt::Display for Error {
                 expected, given
             ),
             Self::Sqlite(e) => write!(f, "sqlite error reading or writing changeset: {}", e),
+            Self::SchemaVersion { found, expected } => write!(
+                f,
+                "unsupported database schema version {}, expected at most {}",
+                found, expected
+            ),
         }
     }
 }
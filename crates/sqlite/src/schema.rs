@@ -1,95 +1,198 @@
-use crate::Store;
-use rusqlite::{named_params, Connection, Error};
+use crate::{Error, Store};
+use rusqlite::{named_params, Connection, OptionalExtension, Transaction, TransactionBehavior};
 
 const SCHEMA_0: &str = include_str!("../schema/schema_0.sql");
-const MIGRATIONS: &[&str] = &[SCHEMA_0];
+const SCHEMA_1: &str = include_str!("../schema/schema_1.sql");
+const SCHEMA_2: &str = include_str!("../schema/schema_2.sql");
+const SCHEMA_3: &str = include_str!("../schema/schema_3.sql");
+const SCHEMA_4: &str = include_str!("../schema/schema_4.sql");
+const SCHEMA_5: &str = include_str!("../schema/schema_5.sql");
+const SCHEMA_6: &str = include_str!("../schema/schema_6.sql");
+const SCHEMA_7: &str = include_str!("../schema/schema_7.sql");
+const SCHEMA_8: &str = include_str!("../schema/schema_8.sql");
+const SCHEMA_9: &str = include_str!("../schema/schema_9.sql");
+const SCHEMA_10: &str = include_str!("../schema/schema_10.sql");
+const SCHEMA_11: &str = include_str!("../schema/schema_11.sql");
+
+/// A schema migration, upgrading the database by one version.
+pub(crate) struct Migration {
+    /// SQL statements changing the schema.
+    pub(crate) up: &'static str,
+    /// Transformation of the existing data, run after the `up` statements.
+    pub(crate) transform: Option<fn(&Transaction) -> rusqlite::Result<()>>,
+}
+
+/// All the schema migrations, in order. The schema version of a database is the number of
+/// migrations that were applied to it.
+pub(crate) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        up: SCHEMA_0,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_1,
+        transform: None,
+    },
+    // versions are tracked in the `schema_migrations` table from here on
+    Migration {
+        up: SCHEMA_2,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_3,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_4,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_5,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_6,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_7,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_8,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_9,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_10,
+        transform: None,
+    },
+    Migration {
+        up: SCHEMA_11,
+        transform: None,
+    },
+];
+
+/// Split `sql` into its statements, removing comments and extra whitespace.
+pub(crate) fn statements(sql: &str) -> Vec<String> {
+    // remove comment lines
+    let s = sql
+        .split('\n')
+        .filter(|l| !l.starts_with("--") && !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    // split into statements
+    s.split(';')
+        // remove extra spaces
+        .map(|s| {
+            s.trim()
+                .split(' ')
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        // remove empty statements
+        .filter(|s| !s.is_empty())
+        .collect()
+}
 
 /// Schema migration related functions.
 impl<K, A> Store<K, A> {
     /// Migrate sqlite db schema to latest version.
+    ///
+    /// All the pending migrations run in a single transaction: if any of them fails the database
+    /// is left untouched. Databases created by a newer version of this crate are rejected with
+    /// [`Error::SchemaVersion`].
     pub(crate) fn migrate(conn: &mut Connection) -> Result<(), Error> {
-        let stmts = &MIGRATIONS
-            .iter()
-            .flat_map(|stmt| {
-                // remove comment lines
-                let s = stmt
-                    .split('\n')
-                    .filter(|l| !l.starts_with("--") && !l.is_empty())
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                // split into statements
-                s.split(';')
-                    // remove extra spaces
-                    .map(|s| {
-                        s.trim()
-                            .split(' ')
-                            .filter(|s| !s.is_empty())
-                            .collect::<Vec<_>>()
-                            .join(" ")
-                    })
-                    .collect::<Vec<_>>()
-            })
-            // remove empty statements
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<String>>();
-
-        let version = Self::get_schema_version(conn)?;
-        let stmts = &stmts[(version as usize)..];
-
         // begin transaction, all migration statements and new schema version commit or rollback
-        let tx = conn.transaction()?;
+        let tx = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(Error::Sqlite)?;
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY NOT NULL) STRICT",
+            [],
+        )
+        .map_err(Error::Sqlite)?;
 
-        // execute every statement and return `Some` new schema version
-        // if execution fails, return `Error::Rusqlite`
-        // if no statements executed returns `None`
-        let new_version = stmts
-            .iter()
-            .enumerate()
-            .map(|version_stmt| {
-                tx.execute(version_stmt.1.as_str(), [])
-                    // map result value to next migration version
-                    .map(|_| version_stmt.0 as i32 + version + 1)
-            })
-            .last()
-            .transpose()?;
+        let version = Self::get_schema_version(&tx)?;
+        let expected = MIGRATIONS.len() as u32;
+        if version > expected {
+            return Err(Error::SchemaVersion {
+                found: version,
+                expected,
+            });
+        }
 
-        // if `Some` new statement version, set new schema version
-        if let Some(version) = new_version {
-            Self::set_schema_version(&tx, version)?;
+        // record the migrations applied with the legacy versioning too
+        for version in 1..=version {
+            Self::set_schema_version(&tx, version).map_err(Error::Sqlite)?;
+        }
+
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            for stmt in statements(migration.up) {
+                tx.execute(&stmt, []).map_err(Error::Sqlite)?;
+            }
+            if let Some(transform) = migration.transform {
+                transform(&tx).map_err(Error::Sqlite)?;
+            }
+            Self::set_schema_version(&tx, version as u32 + 1).map_err(Error::Sqlite)?;
         }
 
         // commit transaction
-        tx.commit()?;
-        Ok(())
+        tx.commit().map_err(Error::Sqlite)
+    }
+
+    /// The number of migrations applied to the database.
+    fn get_schema_version(tx: &Transaction) -> Result<u32, Error> {
+        let version = tx
+            .query_row("SELECT MAX(version) FROM schema_migrations", [], |row| {
+                row.get::<_, Option<u32>>(0)
+            })
+            .map_err(Error::Sqlite)?;
+        match version {
+            Some(version) => Ok(version),
+            None => Self::get_legacy_schema_version(tx),
+        }
     }
 
-    fn get_schema_version(conn: &Connection) -> rusqlite::Result<i32> {
-        let statement = conn.prepare_cached("SELECT version FROM version");
-        match statement {
-            Err(Error::SqliteFailure(e, Some(msg))) => {
-                if msg == "no such table: version" {
-                    Ok(0)
-                } else {
-                    Err(Error::SqliteFailure(e, Some(msg)))
-                }
+    /// Before the `schema_migrations` table, the `version` table stored the number of SQL
+    /// statements executed so far. Map it back to the number of migrations applied.
+    fn get_legacy_schema_version(tx: &Transaction) -> Result<u32, Error> {
+        let statement_count = match tx
+            .query_row("SELECT version FROM version", [], |row| {
+                row.get::<_, u32>(0)
+            })
+            .optional()
+        {
+            Ok(statement_count) => statement_count.unwrap_or(0),
+            Err(rusqlite::Error::SqliteFailure(_, Some(msg)))
+                if msg == "no such table: version" =>
+            {
+                0
             }
-            Ok(mut stmt) => {
-                let mut rows = stmt.query([])?;
-                match rows.next()? {
-                    Some(row) => {
-                        let version: i32 = row.get(0)?;
-                        Ok(version)
-                    }
-                    None => Ok(0),
-                }
+            Err(e) => return Err(Error::Sqlite(e)),
+        };
+
+        let mut statements_applied = 0;
+        for (version, migration) in MIGRATIONS.iter().enumerate() {
+            if statements_applied == statement_count {
+                return Ok(version as u32);
             }
-            _ => Ok(0),
+            statements_applied += statements(migration.up).len() as u32;
         }
+        // the statement count doesn't match any of the migrations that used the legacy versioning
+        Err(Error::SchemaVersion {
+            found: statement_count,
+            expected: MIGRATIONS.len() as u32,
+        })
     }
 
-    fn set_schema_version(conn: &Connection, version: i32) -> rusqlite::Result<usize> {
-        conn.execute(
-            "UPDATE version SET version=:version",
+    fn set_schema_version(tx: &Transaction, version: u32) -> rusqlite::Result<usize> {
+        tx.execute(
+            "INSERT OR IGNORE INTO schema_migrations (version) VALUES (:version)",
             named_params! {":version": version},
         )
     }
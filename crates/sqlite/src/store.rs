@@ -3,7 +3,7 @@ use bdk_chain::bitcoin::hashes::Hash;
 use bdk_chain::bitcoin::{Amount, Network, OutPoint, ScriptBuf, Transaction, TxOut};
 use bdk_chain::bitcoin::{BlockHash, Txid};
 use bdk_chain::miniscript::descriptor::{Descriptor, DescriptorPublicKey};
-use rusqlite::{named_params, Connection};
+use rusqlite::{named_params, Connection, TransactionBehavior};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
@@ -11,11 +11,14 @@ use std::marker::PhantomData;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
-use crate::Error;
-use bdk_chain::CombinedChangeSet;
+use crate::{Error, DEFAULT_WALLET_ID};
 use bdk_chain::{
     indexed_tx_graph, keychain, local_chain, tx_graph, Anchor, Append, DescriptorExt, DescriptorId,
 };
+use bdk_chain::{
+    BlockTimeOrHeight, BroadcastOutcome, BroadcastRecord, ChangeAddressPolicy, CombinedChangeSet,
+    DescriptorRevisionRecord, InvoiceRecord, LabelRef,
+};
 
 /// Persists data in to a relational schema based [SQLite] database file.
 ///
@@ -25,6 +28,8 @@ use bdk_chain::{
 pub struct Store<K, A> {
     // A rusqlite connection to the SQLite database. Uses a Mutex for thread safety.
     conn: Mutex<Connection>,
+    // The namespace of the wallet's data in the database.
+    wallet_id: String,
     keychain_marker: PhantomData<K>,
     anchor_marker: PhantomData<A>,
 }
@@ -41,19 +46,49 @@ where
     A: Anchor + for<'de> Deserialize<'de> + Serialize + Send,
 {
     /// Creates a new store from a [`Connection`].
-    pub fn new(mut conn: Connection) -> Result<Self, rusqlite::Error> {
+    ///
+    /// This migrates the database schema to the latest version, and fails with
+    /// [`Error::SchemaVersion`] if the database was created by a newer version of this crate.
+    ///
+    /// The store reads and writes the data of the [`DEFAULT_WALLET_ID`] wallet, see
+    /// [`Store::new_with_wallet_id`] to keep multiple wallets in the same database.
+    pub fn new(conn: Connection) -> Result<Self, Error> {
+        Self::new_with_wallet_id(conn, DEFAULT_WALLET_ID)
+    }
+
+    /// Creates a new store from a [`Connection`], for the wallet identified by `wallet_id`.
+    ///
+    /// Every wallet only sees its own data, so many of them can share the same database: use
+    /// [`list_wallets`] to list the ones that are stored. Each store should use its own
+    /// [`Connection`] to the database, writes from different connections wait for each other
+    /// for up to the connection's [busy timeout].
+    ///
+    /// [`list_wallets`]: crate::list_wallets
+    /// [busy timeout]: Connection::busy_timeout
+    pub fn new_with_wallet_id(mut conn: Connection, wallet_id: &str) -> Result<Self, Error> {
         Self::migrate(&mut conn)?;
 
         Ok(Self {
             conn: Mutex::new(conn),
+            wallet_id: wallet_id.to_string(),
             keychain_marker: Default::default(),
             anchor_marker: Default::default(),
         })
     }
 
+    /// The identifier of the wallet the store reads and writes.
+    pub fn wallet_id(&self) -> &str {
+        &self.wallet_id
+    }
+
     pub(crate) fn db_transaction(&mut self) -> Result<rusqlite::Transaction, Error> {
         let connection = self.conn.get_mut().expect("unlocked connection mutex");
-        connection.transaction().map_err(Error::Sqlite)
+        // take the write lock right away: a deferred transaction upgrading from a read lock
+        // fails immediately with `SQLITE_BUSY` when another connection is writing, instead of
+        // waiting for the busy timeout
+        connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(Error::Sqlite)
     }
 }
 
@@ -65,6 +100,7 @@ impl<K, A> Store<K, A> {
     fn insert_network(
         current_network: &Option<Network>,
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
         network_changeset: &Option<Network>,
     ) -> Result<(), Error> {
         if let Some(network) = network_changeset {
@@ -79,11 +115,13 @@ impl<K, A> Store<K, A> {
                 // insert network if none exists
                 None => {
                     let insert_network_stmt = &mut db_transaction
-                        .prepare_cached("INSERT INTO network (name) VALUES (:name)")
+                        .prepare_cached(
+                            "INSERT INTO network (wallet_id, name) VALUES (:wallet_id, :name)",
+                        )
                         .expect("insert network statement");
                     let name = network.to_string();
                     insert_network_stmt
-                        .execute(named_params! {":name": name })
+                        .execute(named_params! {":wallet_id": wallet_id, ":name": name })
                         .map_err(Error::Sqlite)?;
                     Ok(())
                 }
@@ -94,13 +132,16 @@ impl<K, A> Store<K, A> {
     }
 
     /// Select the valid [`Network`] for this database, or `None` if not set.
-    fn select_network(db_transaction: &rusqlite::Transaction) -> Result<Option<Network>, Error> {
+    fn select_network(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<Option<Network>, Error> {
         let mut select_network_stmt = db_transaction
-            .prepare_cached("SELECT name FROM network WHERE rowid = 1")
+            .prepare_cached("SELECT name FROM network WHERE wallet_id = :wallet_id")
             .expect("select network statement");
 
         let network = select_network_stmt
-            .query_row([], |row| {
+            .query_row(named_params! {":wallet_id": wallet_id}, |row| {
                 let network = row.get_unwrap::<usize, String>(0);
                 let network = Network::from_str(network.as_str()).expect("valid network");
                 Ok(network)
@@ -121,6 +162,7 @@ impl<K, A> Store<K, A> {
     /// Error if trying to insert existing block hash.
     fn insert_or_delete_blocks(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
         chain_changeset: &local_chain::ChangeSet,
     ) -> Result<(), Error> {
         for (height, hash) in chain_changeset.iter() {
@@ -128,20 +170,22 @@ impl<K, A> Store<K, A> {
                 // add new hash at height
                 Some(hash) => {
                     let insert_block_stmt = &mut db_transaction
-                        .prepare_cached("INSERT INTO block (hash, height) VALUES (:hash, :height)")
+                        .prepare_cached("INSERT INTO block (wallet_id, hash, height) VALUES (:wallet_id, :hash, :height)")
                         .expect("insert block statement");
                     let hash = hash.to_string();
                     insert_block_stmt
-                        .execute(named_params! {":hash": hash, ":height": height })
+                        .execute(named_params! {":wallet_id": wallet_id, ":hash": hash, ":height": height })
                         .map_err(Error::Sqlite)?;
                 }
                 // delete block at height
                 None => {
                     let delete_block_stmt = &mut db_transaction
-                        .prepare_cached("DELETE FROM block WHERE height IS :height")
+                        .prepare_cached(
+                            "DELETE FROM block WHERE wallet_id = :wallet_id AND height IS :height",
+                        )
                         .expect("delete block statement");
                     delete_block_stmt
-                        .execute(named_params! {":height": height })
+                        .execute(named_params! {":wallet_id": wallet_id, ":height": height })
                         .map_err(Error::Sqlite)?;
                 }
             }
@@ -153,13 +197,18 @@ impl<K, A> Store<K, A> {
     /// Select all blocks.
     fn select_blocks(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
     ) -> Result<BTreeMap<u32, Option<BlockHash>>, Error> {
+        // the blocks replaced by a reorg stay in the table, the latest block written at a height
+        // is the one of the chain
         let mut select_blocks_stmt = db_transaction
-            .prepare_cached("SELECT height, hash FROM block")
+            .prepare_cached(
+                "SELECT height, hash FROM block WHERE wallet_id = :wallet_id ORDER BY rowid",
+            )
             .expect("select blocks statement");
 
         let blocks = select_blocks_stmt
-            .query_map([], |row| {
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
                 let height = row.get_unwrap::<usize, u32>(0);
                 let hash = row.get_unwrap::<usize, String>(1);
                 let hash = Some(BlockHash::from_str(hash.as_str()).expect("block hash"));
@@ -187,17 +236,18 @@ where
     /// If keychain exists only update last active index.
     fn insert_keychains(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
         tx_graph_changeset: &indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
     ) -> Result<(), Error> {
         let keychain_changeset = &tx_graph_changeset.indexer;
         for (keychain, descriptor) in keychain_changeset.keychains_added.iter() {
             let insert_keychain_stmt = &mut db_transaction
-                .prepare_cached("INSERT INTO keychain (keychain, descriptor, descriptor_id) VALUES (jsonb(:keychain), :descriptor, :descriptor_id)")
+                .prepare_cached("INSERT INTO keychain (wallet_id, keychain, descriptor, descriptor_id) VALUES (:wallet_id, jsonb(:keychain), :descriptor, :descriptor_id)")
                 .expect("insert keychain statement");
             let keychain_json = serde_json::to_string(keychain).expect("keychain json");
             let descriptor_id = descriptor.descriptor_id().to_byte_array();
             let descriptor = descriptor.to_string();
-            insert_keychain_stmt.execute(named_params! {":keychain": keychain_json, ":descriptor": descriptor, ":descriptor_id": descriptor_id })
+            insert_keychain_stmt.execute(named_params! {":wallet_id": wallet_id, ":keychain": keychain_json, ":descriptor": descriptor, ":descriptor_id": descriptor_id })
                 .map_err(Error::Sqlite)?;
         }
         Ok(())
@@ -206,6 +256,7 @@ where
     /// Update descriptor last revealed index.
     fn update_last_revealed(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
         tx_graph_changeset: &indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
     ) -> Result<(), Error> {
         let keychain_changeset = &tx_graph_changeset.indexer;
@@ -213,26 +264,53 @@ where
             let update_last_revealed_stmt = &mut db_transaction
                 .prepare_cached(
                     "UPDATE keychain SET last_revealed = :last_revealed
-                              WHERE descriptor_id = :descriptor_id",
+                              WHERE wallet_id = :wallet_id AND descriptor_id = :descriptor_id",
                 )
                 .expect("update last revealed statement");
             let descriptor_id = descriptor_id.to_byte_array();
-            update_last_revealed_stmt.execute(named_params! {":descriptor_id": descriptor_id, ":last_revealed": * last_revealed })
+            update_last_revealed_stmt.execute(named_params! {":wallet_id": wallet_id, ":descriptor_id": descriptor_id, ":last_revealed": * last_revealed })
                 .map_err(Error::Sqlite)?;
         }
         Ok(())
     }
 
+    /// Insert or update the derivation indexes manually marked as used or unused.
+    fn update_marked_used(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        tx_graph_changeset: &indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
+    ) -> Result<(), Error> {
+        let keychain_changeset = &tx_graph_changeset.indexer;
+        for (descriptor_id, marks) in keychain_changeset.marked_used.iter() {
+            let descriptor_id = descriptor_id.to_byte_array();
+            for (index, used) in marks {
+                let update_marked_used_stmt = &mut db_transaction
+                    .prepare_cached(
+                        "INSERT INTO keychain_marked_used (wallet_id, descriptor_id, idx, used) VALUES (:wallet_id, :descriptor_id, :idx, :used)
+                              ON CONFLICT (wallet_id, descriptor_id, idx) DO UPDATE SET used = :used",
+                    )
+                    .expect("insert or update marked used statement");
+                update_marked_used_stmt
+                    .execute(named_params! {":wallet_id": wallet_id, ":descriptor_id": descriptor_id, ":idx": index, ":used": used })
+                    .map_err(Error::Sqlite)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Select keychains added.
     fn select_keychains(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
     ) -> Result<BTreeMap<K, Descriptor<DescriptorPublicKey>>, Error> {
         let mut select_keychains_added_stmt = db_transaction
-            .prepare_cached("SELECT json(keychain), descriptor FROM keychain")
+            .prepare_cached(
+                "SELECT json(keychain), descriptor FROM keychain WHERE wallet_id = :wallet_id",
+            )
             .expect("select keychains statement");
 
         let keychains = select_keychains_added_stmt
-            .query_map([], |row| {
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
                 let keychain = row.get_unwrap::<usize, String>(0);
                 let keychain = serde_json::from_str::<K>(keychain.as_str()).expect("keychain");
                 let descriptor = row.get_unwrap::<usize, String>(1);
@@ -249,15 +327,16 @@ where
     /// Select descriptor last revealed indexes.
     fn select_last_revealed(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
     ) -> Result<BTreeMap<DescriptorId, u32>, Error> {
         let mut select_last_revealed_stmt = db_transaction
             .prepare_cached(
-                "SELECT descriptor, last_revealed FROM keychain WHERE last_revealed IS NOT NULL",
+                "SELECT descriptor, last_revealed FROM keychain WHERE wallet_id = :wallet_id AND last_revealed IS NOT NULL",
             )
             .expect("select last revealed statement");
 
         let last_revealed = select_last_revealed_stmt
-            .query_map([], |row| {
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
                 let descriptor = row.get_unwrap::<usize, String>(0);
                 let descriptor = Descriptor::from_str(descriptor.as_str()).expect("descriptor");
                 let descriptor_id = descriptor.descriptor_id();
@@ -270,6 +349,38 @@ where
             .map(|row| row.map_err(Error::Sqlite))
             .collect()
     }
+
+    /// Select the derivation indexes manually marked as used or unused.
+    fn select_marked_used(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<BTreeMap<DescriptorId, BTreeMap<u32, bool>>, Error> {
+        let mut select_marked_used_stmt = db_transaction
+            .prepare_cached(
+                "SELECT descriptor_id, idx, used FROM keychain_marked_used WHERE wallet_id = :wallet_id",
+            )
+            .expect("select marked used statement");
+
+        let marks = select_marked_used_stmt
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
+                let descriptor_id = row.get_unwrap::<usize, [u8; 32]>(0);
+                let descriptor_id = DescriptorId::from_byte_array(descriptor_id);
+                let index = row.get_unwrap::<usize, u32>(1);
+                let used = row.get_unwrap::<usize, bool>(2);
+                Ok((descriptor_id, index, used))
+            })
+            .map_err(Error::Sqlite)?;
+
+        let mut marked_used = BTreeMap::<DescriptorId, BTreeMap<u32, bool>>::new();
+        for row in marks {
+            let (descriptor_id, index, used) = row.map_err(Error::Sqlite)?;
+            marked_used
+                .entry(descriptor_id)
+                .or_default()
+                .insert(index, used);
+        }
+        Ok(marked_used)
+    }
 }
 
 /// Tx (transaction) and txout (transaction output) table related functions.
@@ -279,16 +390,19 @@ impl<K, A> Store<K, A> {
     /// Error if trying to insert existing txid.
     fn insert_txs(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
         tx_graph_changeset: &indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
     ) -> Result<(), Error> {
         for tx in tx_graph_changeset.graph.txs.iter() {
             let insert_tx_stmt = &mut db_transaction
-                .prepare_cached("INSERT INTO tx (txid, whole_tx) VALUES (:txid, :whole_tx) ON CONFLICT (txid) DO UPDATE SET whole_tx = :whole_tx WHERE txid = :txid")
+                .prepare_cached("INSERT INTO tx (wallet_id, txid, whole_tx) VALUES (:wallet_id, :txid, :whole_tx) ON CONFLICT (wallet_id, txid) DO UPDATE SET whole_tx = :whole_tx")
                 .expect("insert or update tx whole_tx statement");
             let txid = tx.compute_txid().to_string();
             let whole_tx = serialize(&tx);
             insert_tx_stmt
-                .execute(named_params! {":txid": txid, ":whole_tx": whole_tx })
+                .execute(
+                    named_params! {":wallet_id": wallet_id, ":txid": txid, ":whole_tx": whole_tx },
+                )
                 .map_err(Error::Sqlite)?;
         }
         Ok(())
@@ -297,13 +411,16 @@ impl<K, A> Store<K, A> {
     /// Select all transactions.
     fn select_txs(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
     ) -> Result<BTreeSet<Arc<Transaction>>, Error> {
         let mut select_tx_stmt = db_transaction
-            .prepare_cached("SELECT whole_tx FROM tx WHERE whole_tx IS NOT NULL")
+            .prepare_cached(
+                "SELECT whole_tx FROM tx WHERE wallet_id = :wallet_id AND whole_tx IS NOT NULL",
+            )
             .expect("select tx statement");
 
         let txs = select_tx_stmt
-            .query_map([], |row| {
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
                 let whole_tx = row.get_unwrap::<usize, Vec<u8>>(0);
                 let whole_tx: Transaction = deserialize(&whole_tx).expect("transaction");
                 Ok(Arc::new(whole_tx))
@@ -318,14 +435,17 @@ impl<K, A> Store<K, A> {
     /// Select all transactions with last_seen values.
     fn select_last_seen(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
     ) -> Result<BTreeMap<Txid, u64>, Error> {
         // load tx last_seen
         let mut select_last_seen_stmt = db_transaction
-            .prepare_cached("SELECT txid, last_seen FROM tx WHERE last_seen IS NOT NULL")
+            .prepare_cached(
+                "SELECT txid, last_seen FROM tx WHERE wallet_id = :wallet_id AND last_seen IS NOT NULL",
+            )
             .expect("select tx last seen statement");
 
         let last_seen = select_last_seen_stmt
-            .query_map([], |row| {
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
                 let txid = row.get_unwrap::<usize, String>(0);
                 let txid = Txid::from_str(&txid).expect("txid");
                 let last_seen = row.get_unwrap::<usize, u64>(1);
@@ -338,22 +458,48 @@ impl<K, A> Store<K, A> {
             .collect()
     }
 
+    /// Select all transactions with last_evicted values.
+    fn select_last_evicted(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<BTreeMap<Txid, u64>, Error> {
+        let mut select_last_evicted_stmt = db_transaction
+            .prepare_cached(
+                "SELECT txid, last_evicted FROM tx WHERE wallet_id = :wallet_id AND last_evicted IS NOT NULL",
+            )
+            .expect("select tx last evicted statement");
+
+        let last_evicted = select_last_evicted_stmt
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
+                let txid = row.get_unwrap::<usize, String>(0);
+                let txid = Txid::from_str(&txid).expect("txid");
+                let last_evicted = row.get_unwrap::<usize, u64>(1);
+                Ok((txid, last_evicted))
+            })
+            .map_err(Error::Sqlite)?;
+        last_evicted
+            .into_iter()
+            .map(|row| row.map_err(Error::Sqlite))
+            .collect()
+    }
+
     /// Insert txouts.
     ///
     /// Error if trying to insert existing outpoint.
     fn insert_txouts(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
         tx_graph_changeset: &indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
     ) -> Result<(), Error> {
         for txout in tx_graph_changeset.graph.txouts.iter() {
             let insert_txout_stmt = &mut db_transaction
-                .prepare_cached("INSERT INTO txout (txid, vout, value, script) VALUES (:txid, :vout, :value, :script)")
+                .prepare_cached("INSERT INTO txout (wallet_id, txid, vout, value, script) VALUES (:wallet_id, :txid, :vout, :value, :script)")
                 .expect("insert txout statement");
             let txid = txout.0.txid.to_string();
             let vout = txout.0.vout;
             let value = txout.1.value.to_sat();
             let script = txout.1.script_pubkey.as_bytes();
-            insert_txout_stmt.execute(named_params! {":txid": txid, ":vout": vout, ":value": value, ":script": script })
+            insert_txout_stmt.execute(named_params! {":wallet_id": wallet_id, ":txid": txid, ":vout": vout, ":value": value, ":script": script })
                 .map_err(Error::Sqlite)?;
         }
         Ok(())
@@ -362,14 +508,17 @@ impl<K, A> Store<K, A> {
     /// Select all transaction outputs.
     fn select_txouts(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
     ) -> Result<BTreeMap<OutPoint, TxOut>, Error> {
         // load tx outs
         let mut select_txout_stmt = db_transaction
-            .prepare_cached("SELECT txid, vout, value, script FROM txout")
+            .prepare_cached(
+                "SELECT txid, vout, value, script FROM txout WHERE wallet_id = :wallet_id",
+            )
             .expect("select txout statement");
 
         let txouts = select_txout_stmt
-            .query_map([], |row| {
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
                 let txid = row.get_unwrap::<usize, String>(0);
                 let txid = Txid::from_str(&txid).expect("txid");
                 let vout = row.get_unwrap::<usize, u32>(1);
@@ -393,16 +542,34 @@ impl<K, A> Store<K, A> {
     /// Update transaction last seen times.
     fn update_last_seen(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
         tx_graph_changeset: &indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
     ) -> Result<(), Error> {
         for tx_last_seen in tx_graph_changeset.graph.last_seen.iter() {
             let insert_or_update_tx_stmt = &mut db_transaction
-                .prepare_cached("INSERT INTO tx (txid, last_seen) VALUES (:txid, :last_seen) ON CONFLICT (txid) DO UPDATE SET last_seen = :last_seen WHERE txid = :txid")
+                .prepare_cached("INSERT INTO tx (wallet_id, txid, last_seen) VALUES (:wallet_id, :txid, :last_seen) ON CONFLICT (wallet_id, txid) DO UPDATE SET last_seen = :last_seen")
                 .expect("insert or update tx last_seen statement");
             let txid = tx_last_seen.0.to_string();
             let last_seen = *tx_last_seen.1;
             insert_or_update_tx_stmt
-                .execute(named_params! {":txid": txid, ":last_seen": last_seen })
+                .execute(named_params! {":wallet_id": wallet_id, ":txid": txid, ":last_seen": last_seen })
+                .map_err(Error::Sqlite)?;
+        }
+        Ok(())
+    }
+
+    /// Update transaction last evicted times.
+    fn update_last_evicted(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        tx_graph_changeset: &indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
+    ) -> Result<(), Error> {
+        for (txid, last_evicted) in tx_graph_changeset.graph.last_evicted.iter() {
+            let insert_or_update_tx_stmt = &mut db_transaction
+                .prepare_cached("INSERT INTO tx (wallet_id, txid, last_evicted) VALUES (:wallet_id, :txid, :last_evicted) ON CONFLICT (wallet_id, txid) DO UPDATE SET last_evicted = :last_evicted")
+                .expect("insert or update tx last_evicted statement");
+            insert_or_update_tx_stmt
+                .execute(named_params! {":wallet_id": wallet_id, ":txid": txid.to_string(), ":last_evicted": last_evicted })
                 .map_err(Error::Sqlite)?;
         }
         Ok(())
@@ -418,17 +585,18 @@ where
     /// Insert anchors.
     fn insert_anchors(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
         tx_graph_changeset: &indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
     ) -> Result<(), Error> {
         // serde_json::to_string
         for anchor in tx_graph_changeset.graph.anchors.iter() {
             let insert_anchor_stmt = &mut db_transaction
-                .prepare_cached("INSERT INTO anchor_tx (block_hash, anchor, txid) VALUES (:block_hash, jsonb(:anchor), :txid)")
+                .prepare_cached("INSERT INTO anchor_tx (wallet_id, block_hash, anchor, txid) VALUES (:wallet_id, :block_hash, jsonb(:anchor), :txid)")
                 .expect("insert anchor statement");
             let block_hash = anchor.0.anchor_block().hash.to_string();
             let anchor_json = serde_json::to_string(&anchor.0).expect("anchor json");
             let txid = anchor.1.to_string();
-            insert_anchor_stmt.execute(named_params! {":block_hash": block_hash, ":anchor": anchor_json, ":txid": txid })
+            insert_anchor_stmt.execute(named_params! {":wallet_id": wallet_id, ":block_hash": block_hash, ":anchor": anchor_json, ":txid": txid })
                 .map_err(Error::Sqlite)?;
         }
         Ok(())
@@ -437,13 +605,16 @@ where
     /// Select all anchors.
     fn select_anchors(
         db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
     ) -> Result<BTreeSet<(A, Txid)>, Error> {
         // serde_json::from_str
         let mut select_anchor_stmt = db_transaction
-            .prepare_cached("SELECT block_hash, json(anchor), txid FROM anchor_tx")
+            .prepare_cached(
+                "SELECT block_hash, json(anchor), txid FROM anchor_tx WHERE wallet_id = :wallet_id",
+            )
             .expect("select anchor statement");
         let anchors = select_anchor_stmt
-            .query_map([], |row| {
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
                 let hash = row.get_unwrap::<usize, String>(0);
                 let hash = BlockHash::from_str(hash.as_str()).expect("block hash");
                 let anchor = row.get_unwrap::<usize, String>(1);
@@ -462,6 +633,475 @@ where
     }
 }
 
+/// Label table related functions.
+impl<K, A> Store<K, A> {
+    /// Insert, update or delete labels.
+    fn upsert_or_delete_labels(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        labels: &BTreeMap<LabelRef, Option<String>>,
+    ) -> Result<(), Error> {
+        for (label_ref, label) in labels {
+            let (label_type, label_ref) = Self::label_ref_to_columns(label_ref);
+            match label {
+                Some(label) => {
+                    let upsert_label_stmt = &mut db_transaction
+                        .prepare_cached(
+                            "INSERT INTO label (wallet_id, type, ref, label) VALUES (:wallet_id, :type, :ref, :label)
+                              ON CONFLICT (wallet_id, type, ref) DO UPDATE SET label = :label",
+                        )
+                        .expect("upsert label statement");
+                    upsert_label_stmt
+                        .execute(named_params! {":wallet_id": wallet_id, ":type": label_type, ":ref": label_ref, ":label": label })
+                        .map_err(Error::Sqlite)?;
+                }
+                None => {
+                    let delete_label_stmt = &mut db_transaction
+                        .prepare_cached(
+                            "DELETE FROM label WHERE wallet_id = :wallet_id AND type = :type AND ref = :ref",
+                        )
+                        .expect("delete label statement");
+                    delete_label_stmt
+                        .execute(
+                            named_params! {":wallet_id": wallet_id, ":type": label_type, ":ref": label_ref },
+                        )
+                        .map_err(Error::Sqlite)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Select all labels.
+    fn select_labels(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<BTreeMap<LabelRef, Option<String>>, Error> {
+        let mut select_labels_stmt = db_transaction
+            .prepare_cached("SELECT type, ref, label FROM label WHERE wallet_id = :wallet_id")
+            .expect("select labels statement");
+
+        let labels = select_labels_stmt
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
+                let label_type = row.get_unwrap::<usize, String>(0);
+                let label_ref = row.get_unwrap::<usize, String>(1);
+                let label = row.get_unwrap::<usize, String>(2);
+                Ok((
+                    Self::label_ref_from_columns(&label_type, &label_ref),
+                    Some(label),
+                ))
+            })
+            .map_err(Error::Sqlite)?;
+        labels
+            .into_iter()
+            .map(|row| row.map_err(Error::Sqlite))
+            .collect()
+    }
+
+    fn label_ref_to_columns(label_ref: &LabelRef) -> (&'static str, String) {
+        match label_ref {
+            LabelRef::Tx(txid) => ("tx", txid.to_string()),
+            LabelRef::Addr(script_pubkey) => ("addr", script_pubkey.to_hex_string()),
+            LabelRef::Output(outpoint) => ("output", outpoint.to_string()),
+            LabelRef::Input(outpoint) => ("input", outpoint.to_string()),
+        }
+    }
+
+    fn label_ref_from_columns(label_type: &str, label_ref: &str) -> LabelRef {
+        match label_type {
+            "tx" => LabelRef::Tx(Txid::from_str(label_ref).expect("txid")),
+            "addr" => LabelRef::Addr(ScriptBuf::from_hex(label_ref).expect("script pubkey")),
+            "output" => LabelRef::Output(OutPoint::from_str(label_ref).expect("outpoint")),
+            "input" => LabelRef::Input(OutPoint::from_str(label_ref).expect("outpoint")),
+            _ => panic!("invalid label type {}", label_type),
+        }
+    }
+}
+
+/// Broadcast table related functions.
+impl<K, A> Store<K, A> {
+    /// Append broadcast records.
+    fn insert_broadcasts(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        broadcasts: &[BroadcastRecord],
+    ) -> Result<(), Error> {
+        for record in broadcasts {
+            let (outcome, reason) = match &record.outcome {
+                BroadcastOutcome::Accepted => ("accepted", None),
+                BroadcastOutcome::Rejected(reason) => ("rejected", Some(reason)),
+            };
+            let insert_broadcast_stmt = &mut db_transaction
+                .prepare_cached(
+                    "INSERT INTO broadcast (wallet_id, txid, backend, outcome, reason, timestamp)
+                      VALUES (:wallet_id, :txid, :backend, :outcome, :reason, :timestamp)",
+                )
+                .expect("insert broadcast statement");
+            insert_broadcast_stmt
+                .execute(named_params! {
+                    ":wallet_id": wallet_id,
+                    ":txid": record.txid.to_string(),
+                    ":backend": record.backend,
+                    ":outcome": outcome,
+                    ":reason": reason,
+                    ":timestamp": record.timestamp,
+                })
+                .map_err(Error::Sqlite)?;
+        }
+        Ok(())
+    }
+
+    /// Select all broadcast records, in the order they were inserted.
+    fn select_broadcasts(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<Vec<BroadcastRecord>, Error> {
+        let mut select_broadcasts_stmt = db_transaction
+            .prepare_cached(
+                "SELECT txid, backend, outcome, reason, timestamp FROM broadcast
+                  WHERE wallet_id = :wallet_id ORDER BY rowid",
+            )
+            .expect("select broadcasts statement");
+
+        let broadcasts = select_broadcasts_stmt
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
+                let txid = row.get_unwrap::<usize, String>(0);
+                let outcome = match row.get_unwrap::<usize, String>(2).as_str() {
+                    "accepted" => BroadcastOutcome::Accepted,
+                    "rejected" => BroadcastOutcome::Rejected(
+                        row.get_unwrap::<usize, Option<String>>(3)
+                            .unwrap_or_default(),
+                    ),
+                    outcome => panic!("invalid broadcast outcome {}", outcome),
+                };
+                Ok(BroadcastRecord {
+                    txid: Txid::from_str(&txid).expect("txid"),
+                    backend: row.get_unwrap::<usize, String>(1),
+                    outcome,
+                    timestamp: row.get_unwrap::<usize, u64>(4),
+                })
+            })
+            .map_err(Error::Sqlite)?;
+        broadcasts
+            .into_iter()
+            .map(|row| row.map_err(Error::Sqlite))
+            .collect()
+    }
+}
+
+/// Change policy table related functions.
+impl<K, A> Store<K, A> {
+    /// Insert or update the change address policy and the position of its rotation.
+    fn upsert_change_policy(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        policy: Option<ChangeAddressPolicy>,
+        rotation: Option<u32>,
+    ) -> Result<(), Error> {
+        if let Some(policy) = policy {
+            let (policy, value) = match policy {
+                ChangeAddressPolicy::FreshAlways => ("fresh_always", None),
+                ChangeAddressPolicy::RotateWithin(n) => ("rotate_within", Some(n)),
+                ChangeAddressPolicy::Fixed(index) => ("fixed", Some(index)),
+            };
+            let upsert_policy_stmt = &mut db_transaction
+                .prepare_cached(
+                    "INSERT INTO change_policy (wallet_id, policy, value) VALUES (:wallet_id, :policy, :value)
+                      ON CONFLICT (wallet_id) DO UPDATE SET policy = :policy, value = :value",
+                )
+                .expect("upsert change policy statement");
+            upsert_policy_stmt
+                .execute(
+                    named_params! {":wallet_id": wallet_id, ":policy": policy, ":value": value },
+                )
+                .map_err(Error::Sqlite)?;
+        }
+        if let Some(rotation) = rotation {
+            let upsert_rotation_stmt = &mut db_transaction
+                .prepare_cached(
+                    "INSERT INTO change_policy (wallet_id, rotation) VALUES (:wallet_id, :rotation)
+                      ON CONFLICT (wallet_id) DO UPDATE SET rotation = :rotation",
+                )
+                .expect("upsert change rotation statement");
+            upsert_rotation_stmt
+                .execute(named_params! {":wallet_id": wallet_id, ":rotation": rotation })
+                .map_err(Error::Sqlite)?;
+        }
+        Ok(())
+    }
+
+    /// Select the change address policy and the position of its rotation.
+    fn select_change_policy(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<(Option<ChangeAddressPolicy>, Option<u32>), Error> {
+        let mut select_policy_stmt = db_transaction
+            .prepare_cached(
+                "SELECT policy, value, rotation FROM change_policy WHERE wallet_id = :wallet_id",
+            )
+            .expect("select change policy statement");
+        let row = select_policy_stmt.query_row(named_params! {":wallet_id": wallet_id}, |row| {
+            let policy = row.get_unwrap::<usize, Option<String>>(0);
+            let value = row.get_unwrap::<usize, Option<u32>>(1);
+            let policy = policy.map(|policy| match (policy.as_str(), value) {
+                ("fresh_always", _) => ChangeAddressPolicy::FreshAlways,
+                ("rotate_within", Some(n)) => ChangeAddressPolicy::RotateWithin(n),
+                ("fixed", Some(index)) => ChangeAddressPolicy::Fixed(index),
+                (policy, _) => panic!("invalid change policy {}", policy),
+            });
+            Ok((policy, row.get_unwrap::<usize, Option<u32>>(2)))
+        });
+        match row {
+            Ok(row) => Ok(row),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok((None, None)),
+            Err(e) => Err(Error::Sqlite(e)),
+        }
+    }
+}
+
+/// Birthday table related functions.
+impl<K, A> Store<K, A> {
+    /// Insert or update the birthday of the wallet.
+    fn upsert_birthday(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        birthday: Option<BlockTimeOrHeight>,
+    ) -> Result<(), Error> {
+        let (kind, value) = match birthday {
+            Some(BlockTimeOrHeight::Height(height)) => ("height", u64::from(height)),
+            Some(BlockTimeOrHeight::Time(time)) => ("time", time),
+            None => return Ok(()),
+        };
+        let upsert_birthday_stmt = &mut db_transaction
+            .prepare_cached(
+                "INSERT INTO birthday (wallet_id, kind, value) VALUES (:wallet_id, :kind, :value)
+                  ON CONFLICT (wallet_id) DO UPDATE SET kind = :kind, value = :value",
+            )
+            .expect("upsert birthday statement");
+        upsert_birthday_stmt
+            .execute(named_params! {":wallet_id": wallet_id, ":kind": kind, ":value": value })
+            .map_err(Error::Sqlite)?;
+        Ok(())
+    }
+
+    /// Select the birthday of the wallet.
+    fn select_birthday(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<Option<BlockTimeOrHeight>, Error> {
+        let mut select_birthday_stmt = db_transaction
+            .prepare_cached("SELECT kind, value FROM birthday WHERE wallet_id = :wallet_id")
+            .expect("select birthday statement");
+        let row = select_birthday_stmt.query_row(named_params! {":wallet_id": wallet_id}, |row| {
+            let kind = row.get_unwrap::<usize, String>(0);
+            Ok(match kind.as_str() {
+                "height" => BlockTimeOrHeight::Height(row.get_unwrap::<usize, u32>(1)),
+                "time" => BlockTimeOrHeight::Time(row.get_unwrap::<usize, u64>(1)),
+                kind => panic!("invalid birthday kind {}", kind),
+            })
+        });
+        match row {
+            Ok(birthday) => Ok(Some(birthday)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(Error::Sqlite(e)),
+        }
+    }
+}
+
+/// Invoice table related functions.
+impl<K, A> Store<K, A> {
+    /// Insert the invoices, an invoice never changes once created.
+    fn insert_invoices(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        invoices: &BTreeMap<u32, InvoiceRecord>,
+    ) -> Result<(), Error> {
+        for (id, invoice) in invoices {
+            let (expiry_kind, expiry) = match invoice.expiry {
+                BlockTimeOrHeight::Height(height) => ("height", u64::from(height)),
+                BlockTimeOrHeight::Time(time) => ("time", time),
+            };
+            let insert_invoice_stmt = &mut db_transaction
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO invoice (wallet_id, id, script_pubkey, amount, expiry_kind, expiry, min_confirmations)
+                      VALUES (:wallet_id, :id, :script_pubkey, :amount, :expiry_kind, :expiry, :min_confirmations)",
+                )
+                .expect("insert invoice statement");
+            insert_invoice_stmt
+                .execute(named_params! {
+                    ":wallet_id": wallet_id,
+                    ":id": id,
+                    ":script_pubkey": invoice.script_pubkey.as_bytes(),
+                    ":amount": invoice.amount.to_sat(),
+                    ":expiry_kind": expiry_kind,
+                    ":expiry": expiry,
+                    ":min_confirmations": invoice.min_confirmations,
+                })
+                .map_err(Error::Sqlite)?;
+        }
+        Ok(())
+    }
+
+    /// Select all the invoices.
+    fn select_invoices(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<BTreeMap<u32, InvoiceRecord>, Error> {
+        let mut select_invoices_stmt = db_transaction
+            .prepare_cached(
+                "SELECT id, script_pubkey, amount, expiry_kind, expiry, min_confirmations FROM invoice WHERE wallet_id = :wallet_id",
+            )
+            .expect("select invoices statement");
+        let invoices = select_invoices_stmt
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
+                let id = row.get_unwrap::<usize, u32>(0);
+                let script_pubkey = ScriptBuf::from_bytes(row.get_unwrap::<usize, Vec<u8>>(1));
+                let amount = Amount::from_sat(row.get_unwrap::<usize, u64>(2));
+                let expiry_kind = row.get_unwrap::<usize, String>(3);
+                let expiry = match expiry_kind.as_str() {
+                    "height" => BlockTimeOrHeight::Height(row.get_unwrap::<usize, u32>(4)),
+                    "time" => BlockTimeOrHeight::Time(row.get_unwrap::<usize, u64>(4)),
+                    kind => panic!("invalid invoice expiry kind {}", kind),
+                };
+                let min_confirmations = row.get_unwrap::<usize, u32>(5);
+                Ok((
+                    id,
+                    InvoiceRecord {
+                        script_pubkey,
+                        amount,
+                        expiry,
+                        min_confirmations,
+                    },
+                ))
+            })
+            .map_err(Error::Sqlite)?;
+        invoices
+            .into_iter()
+            .map(|row| row.map_err(Error::Sqlite))
+            .collect()
+    }
+}
+
+/// Keychain sync table related functions.
+impl<K, A> Store<K, A>
+where
+    K: Ord + for<'de> Deserialize<'de> + Serialize + Send,
+{
+    /// Insert or update the time each keychain was last synced, keeping the latest.
+    fn upsert_last_synced(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        last_synced: &BTreeMap<K, u64>,
+    ) -> Result<(), Error> {
+        for (keychain, last_synced) in last_synced {
+            let upsert_last_synced_stmt = &mut db_transaction
+                .prepare_cached(
+                    "INSERT INTO keychain_sync (wallet_id, keychain, last_synced) VALUES (:wallet_id, jsonb(:keychain), :last_synced)
+                      ON CONFLICT (wallet_id, keychain) DO UPDATE SET last_synced = max(last_synced, :last_synced)",
+                )
+                .expect("upsert last synced statement");
+            let keychain_json = serde_json::to_string(keychain).expect("keychain json");
+            upsert_last_synced_stmt
+                .execute(named_params! {":wallet_id": wallet_id, ":keychain": keychain_json, ":last_synced": last_synced })
+                .map_err(Error::Sqlite)?;
+        }
+        Ok(())
+    }
+
+    /// Select the time each keychain was last synced.
+    fn select_last_synced(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<BTreeMap<K, u64>, Error> {
+        let mut select_last_synced_stmt = db_transaction
+            .prepare_cached(
+                "SELECT json(keychain), last_synced FROM keychain_sync WHERE wallet_id = :wallet_id",
+            )
+            .expect("select last synced statement");
+        let last_synced = select_last_synced_stmt
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
+                let keychain = row.get_unwrap::<usize, String>(0);
+                let keychain = serde_json::from_str::<K>(keychain.as_str()).expect("keychain");
+                let last_synced = row.get_unwrap::<usize, u64>(1);
+                Ok((keychain, last_synced))
+            })
+            .map_err(Error::Sqlite)?;
+        last_synced
+            .into_iter()
+            .map(|row| row.map_err(Error::Sqlite))
+            .collect()
+    }
+}
+
+/// Descriptor revision table related functions.
+impl<K, A> Store<K, A>
+where
+    K: Ord + for<'de> Deserialize<'de> + Serialize + Send,
+{
+    /// Insert the revisions of the descriptors, a revision never changes once recorded.
+    fn insert_descriptor_revisions(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        descriptor_revisions: &BTreeMap<K, BTreeMap<u32, DescriptorRevisionRecord>>,
+    ) -> Result<(), Error> {
+        for (keychain, revisions) in descriptor_revisions {
+            let keychain_json = serde_json::to_string(keychain).expect("keychain json");
+            for (revision, record) in revisions {
+                let insert_revision_stmt = &mut db_transaction
+                    .prepare_cached(
+                        "INSERT OR IGNORE INTO descriptor_revision (wallet_id, keychain, revision, descriptor_id, activation_height)
+                          VALUES (:wallet_id, jsonb(:keychain), :revision, :descriptor_id, :activation_height)",
+                    )
+                    .expect("insert descriptor revision statement");
+                insert_revision_stmt
+                    .execute(named_params! {
+                        ":wallet_id": wallet_id,
+                        ":keychain": keychain_json,
+                        ":revision": revision,
+                        ":descriptor_id": record.descriptor_id.to_byte_array(),
+                        ":activation_height": record.activation_height,
+                    })
+                    .map_err(Error::Sqlite)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Select the revisions of the descriptors of the keychains.
+    fn select_descriptor_revisions(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+    ) -> Result<BTreeMap<K, BTreeMap<u32, DescriptorRevisionRecord>>, Error> {
+        let mut select_revisions_stmt = db_transaction
+            .prepare_cached(
+                "SELECT json(keychain), revision, descriptor_id, activation_height FROM descriptor_revision WHERE wallet_id = :wallet_id",
+            )
+            .expect("select descriptor revisions statement");
+        let rows = select_revisions_stmt
+            .query_map(named_params! {":wallet_id": wallet_id}, |row| {
+                let keychain = row.get_unwrap::<usize, String>(0);
+                let keychain = serde_json::from_str::<K>(keychain.as_str()).expect("keychain");
+                let revision = row.get_unwrap::<usize, u32>(1);
+                let descriptor_id = row.get_unwrap::<usize, [u8; 32]>(2);
+                let record = DescriptorRevisionRecord {
+                    descriptor_id: DescriptorId::from_byte_array(descriptor_id),
+                    activation_height: row.get_unwrap::<usize, Option<u32>>(3),
+                };
+                Ok((keychain, revision, record))
+            })
+            .map_err(Error::Sqlite)?;
+        let mut descriptor_revisions = BTreeMap::<K, BTreeMap<u32, _>>::new();
+        for row in rows {
+            let (keychain, revision, record) = row.map_err(Error::Sqlite)?;
+            descriptor_revisions
+                .entry(keychain)
+                .or_default()
+                .insert(revision, record);
+        }
+        Ok(descriptor_revisions)
+    }
+}
+
 /// Functions to read and write all [`CombinedChangeSet`] data.
 impl<K, A> Store<K, A>
 where
@@ -475,60 +1115,179 @@ where
             return Ok(());
         }
 
+        let wallet_id = self.wallet_id.clone();
+        let db_transaction = self.db_transaction()?;
+        Self::write_changeset(&db_transaction, &wallet_id, changeset)?;
+        db_transaction.commit().map_err(Error::Sqlite)
+    }
+
+    /// Replace all the data of the wallet with the given `snapshot` atomically.
+    ///
+    /// `snapshot` should be equivalent to the aggregate of the written changesets, such as a
+    /// wallet snapshot, so that the deleted history of the wallet doesn't have to be read
+    /// anymore. The data of the other wallets of the database is left untouched.
+    ///
+    /// Like with [`Store::write`], the blocks of anchors which are not in the chain anymore are
+    /// kept, because the anchors reference them.
+    pub fn compact_to(&mut self, snapshot: &CombinedChangeSet<K, A>) -> Result<(), Error> {
+        let wallet_id = self.wallet_id.clone();
         let db_transaction = self.db_transaction()?;
+        // the anchors reference the blocks and transactions, delete them first
+        for table in [
+            "anchor_tx",
+            "txout",
+            "tx",
+            "block",
+            "keychain_marked_used",
+            "keychain",
+            "label",
+            "broadcast",
+            "change_policy",
+            "birthday",
+            "keychain_sync",
+            "invoice",
+            "descriptor_revision",
+            "network",
+        ] {
+            db_transaction
+                .execute(
+                    &format!("DELETE FROM {} WHERE wallet_id = :wallet_id", table),
+                    named_params! {":wallet_id": wallet_id},
+                )
+                .map_err(Error::Sqlite)?;
+        }
+        // the stale blocks are written first, so that the blocks of the chain at the same heights
+        // are the ones read back
+        let stale_blocks = snapshot
+            .indexed_tx_graph
+            .graph
+            .anchors
+            .iter()
+            .map(|(anchor, _)| anchor.anchor_block())
+            .filter(|block| snapshot.chain.get(&block.height) != Some(&Some(block.hash)))
+            .collect::<BTreeSet<_>>();
+        for block in stale_blocks {
+            Self::insert_or_delete_blocks(
+                &db_transaction,
+                &wallet_id,
+                &[(block.height, Some(block.hash))].into(),
+            )?;
+        }
+        Self::write_changeset(&db_transaction, &wallet_id, snapshot)?;
+        db_transaction.commit().map_err(Error::Sqlite)
+    }
 
+    fn write_changeset(
+        db_transaction: &rusqlite::Transaction,
+        wallet_id: &str,
+        changeset: &CombinedChangeSet<K, A>,
+    ) -> Result<(), Error> {
         let network_changeset = &changeset.network;
-        let current_network = Self::select_network(&db_transaction)?;
-        Self::insert_network(&current_network, &db_transaction, network_changeset)?;
+        let current_network = Self::select_network(db_transaction, wallet_id)?;
+        Self::insert_network(
+            &current_network,
+            db_transaction,
+            wallet_id,
+            network_changeset,
+        )?;
 
         let chain_changeset = &changeset.chain;
-        Self::insert_or_delete_blocks(&db_transaction, chain_changeset)?;
+        Self::insert_or_delete_blocks(db_transaction, wallet_id, chain_changeset)?;
 
         let tx_graph_changeset = &changeset.indexed_tx_graph;
-        Self::insert_keychains(&db_transaction, tx_graph_changeset)?;
-        Self::update_last_revealed(&db_transaction, tx_graph_changeset)?;
-        Self::insert_txs(&db_transaction, tx_graph_changeset)?;
-        Self::insert_txouts(&db_transaction, tx_graph_changeset)?;
-        Self::insert_anchors(&db_transaction, tx_graph_changeset)?;
-        Self::update_last_seen(&db_transaction, tx_graph_changeset)?;
-        db_transaction.commit().map_err(Error::Sqlite)
+        Self::insert_keychains(db_transaction, wallet_id, tx_graph_changeset)?;
+        Self::update_last_revealed(db_transaction, wallet_id, tx_graph_changeset)?;
+        Self::update_marked_used(db_transaction, wallet_id, tx_graph_changeset)?;
+        Self::insert_txs(db_transaction, wallet_id, tx_graph_changeset)?;
+        Self::insert_txouts(db_transaction, wallet_id, tx_graph_changeset)?;
+        Self::insert_anchors(db_transaction, wallet_id, tx_graph_changeset)?;
+        Self::update_last_seen(db_transaction, wallet_id, tx_graph_changeset)?;
+        Self::update_last_evicted(db_transaction, wallet_id, tx_graph_changeset)?;
+        Self::upsert_or_delete_labels(db_transaction, wallet_id, &changeset.labels)?;
+        Self::insert_broadcasts(db_transaction, wallet_id, &changeset.broadcasts)?;
+        Self::upsert_change_policy(
+            db_transaction,
+            wallet_id,
+            changeset.change_address_policy,
+            changeset.change_rotation,
+        )?;
+        Self::upsert_birthday(db_transaction, wallet_id, changeset.birthday)?;
+        Self::upsert_last_synced(db_transaction, wallet_id, &changeset.last_synced)?;
+        Self::insert_invoices(db_transaction, wallet_id, &changeset.invoices)?;
+        Self::insert_descriptor_revisions(
+            db_transaction,
+            wallet_id,
+            &changeset.descriptor_revisions,
+        )
     }
 
     /// Read the entire database and return the aggregate [`CombinedChangeSet`].
     pub fn read(&mut self) -> Result<Option<CombinedChangeSet<K, A>>, Error> {
+        let wallet_id = self.wallet_id.clone();
         let db_transaction = self.db_transaction()?;
 
-        let network = Self::select_network(&db_transaction)?;
-        let chain = Self::select_blocks(&db_transaction)?;
-        let keychains_added = Self::select_keychains(&db_transaction)?;
-        let last_revealed = Self::select_last_revealed(&db_transaction)?;
-        let txs = Self::select_txs(&db_transaction)?;
-        let last_seen = Self::select_last_seen(&db_transaction)?;
-        let txouts = Self::select_txouts(&db_transaction)?;
-        let anchors = Self::select_anchors(&db_transaction)?;
+        let network = Self::select_network(&db_transaction, &wallet_id)?;
+        let chain = Self::select_blocks(&db_transaction, &wallet_id)?;
+        let keychains_added = Self::select_keychains(&db_transaction, &wallet_id)?;
+        let last_revealed = Self::select_last_revealed(&db_transaction, &wallet_id)?;
+        let marked_used = Self::select_marked_used(&db_transaction, &wallet_id)?;
+        let txs = Self::select_txs(&db_transaction, &wallet_id)?;
+        let last_seen = Self::select_last_seen(&db_transaction, &wallet_id)?;
+        let last_evicted = Self::select_last_evicted(&db_transaction, &wallet_id)?;
+        let txouts = Self::select_txouts(&db_transaction, &wallet_id)?;
+        let anchors = Self::select_anchors(&db_transaction, &wallet_id)?;
+        let labels = Self::select_labels(&db_transaction, &wallet_id)?;
+        let broadcasts = Self::select_broadcasts(&db_transaction, &wallet_id)?;
+        let (change_address_policy, change_rotation) =
+            Self::select_change_policy(&db_transaction, &wallet_id)?;
+        let birthday = Self::select_birthday(&db_transaction, &wallet_id)?;
+        let last_synced = Self::select_last_synced(&db_transaction, &wallet_id)?;
+        let invoices = Self::select_invoices(&db_transaction, &wallet_id)?;
+        let descriptor_revisions = Self::select_descriptor_revisions(&db_transaction, &wallet_id)?;
 
         let graph: tx_graph::ChangeSet<A> = tx_graph::ChangeSet {
             txs,
             txouts,
             anchors,
             last_seen,
+            last_evicted,
         };
 
         let indexer: keychain::ChangeSet<K> = keychain::ChangeSet {
             keychains_added,
             last_revealed,
+            marked_used,
         };
 
         let indexed_tx_graph: indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>> =
             indexed_tx_graph::ChangeSet { graph, indexer };
 
-        if network.is_none() && chain.is_empty() && indexed_tx_graph.is_empty() {
+        if network.is_none()
+            && chain.is_empty()
+            && indexed_tx_graph.is_empty()
+            && labels.is_empty()
+            && broadcasts.is_empty()
+            && change_address_policy.is_none()
+            && change_rotation.is_none()
+            && birthday.is_none()
+            && last_synced.is_empty()
+            && invoices.is_empty()
+            && descriptor_revisions.is_empty()
+        {
             Ok(None)
         } else {
             Ok(Some(CombinedChangeSet {
                 chain,
                 indexed_tx_graph,
                 network,
+                labels,
+                broadcasts,
+                change_address_policy,
+                change_rotation,
+                birthday,
+                last_synced,
+                invoices,
+                descriptor_revisions,
             }))
         }
     }
@@ -543,7 +1302,7 @@ mod test {
     use bdk_chain::bitcoin::hashes::hex::FromHex;
     use bdk_chain::bitcoin::transaction::Transaction;
     use bdk_chain::bitcoin::Network::Testnet;
-    use bdk_chain::bitcoin::{secp256k1, BlockHash, OutPoint};
+    use bdk_chain::bitcoin::{absolute, secp256k1, transaction, BlockHash, OutPoint};
     use bdk_chain::miniscript::Descriptor;
     use bdk_chain::CombinedChangeSet;
     use bdk_chain::{
@@ -619,6 +1378,322 @@ mod test {
         assert_eq!(agg_changeset, Some(agg_test_changesets));
     }
 
+    /// Create a database the way it was before the `schema_migrations` table, with the first
+    /// `migrations` migrations applied and the number of executed statements in the `version`
+    /// table.
+    fn create_legacy_store<A>(migrations: usize) -> Store<Keychain, A> {
+        let conn = Connection::open_in_memory().expect("in memory connection");
+        let stmts = crate::schema::MIGRATIONS[..migrations]
+            .iter()
+            .flat_map(|migration| crate::schema::statements(migration.up))
+            .collect::<Vec<_>>();
+        for stmt in &stmts {
+            conn.execute(stmt, []).expect("legacy statement");
+        }
+        conn.execute(
+            "UPDATE version SET version=:version",
+            named_params! {":version": stmts.len()},
+        )
+        .expect("legacy version");
+
+        Store {
+            conn: Mutex::new(conn),
+            wallet_id: DEFAULT_WALLET_ID.to_string(),
+            keychain_marker: Default::default(),
+            anchor_marker: Default::default(),
+        }
+    }
+
+    #[test]
+    fn migrate_legacy_database() {
+        let secp = &secp256k1::Secp256k1::signing_only();
+        let (descriptor, _) = Descriptor::parse_descriptor(secp, "wpkh(tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy/0/*)").unwrap();
+        let descriptor_id = descriptor.descriptor_id();
+        let keychain = Keychain::External {
+            account: 0,
+            name: "ext test".to_string(),
+        };
+        let hash = genesis_block(Testnet).block_hash();
+        let anchor = BlockId { height: 0, hash };
+        let tx = Arc::new(Transaction {
+            version: transaction::Version::ONE,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        });
+        let txid = tx.compute_txid();
+
+        // write the data as the previous schema stored it
+        let mut store = create_legacy_store::<BlockId>(2);
+        {
+            let conn = store.conn.get_mut().expect("unlocked connection mutex");
+            type Params<'a> = &'a [(&'a str, &'a dyn rusqlite::ToSql)];
+            let stmts: &[(&str, Params)] = &[
+                (
+                    "INSERT INTO network (name) VALUES (:name)",
+                    named_params! {":name": Testnet.to_string()},
+                ),
+                (
+                    "INSERT INTO block (hash, height) VALUES (:hash, 0)",
+                    named_params! {":hash": hash.to_string()},
+                ),
+                (
+                    "INSERT INTO keychain (keychain, descriptor, descriptor_id, last_revealed) VALUES (jsonb(:keychain), :descriptor, :descriptor_id, 5)",
+                    named_params! {
+                        ":keychain": serde_json::to_string(&keychain).unwrap(),
+                        ":descriptor": descriptor.to_string(),
+                        ":descriptor_id": descriptor_id.to_byte_array(),
+                    },
+                ),
+                (
+                    "INSERT INTO keychain_marked_used (descriptor_id, idx, used) VALUES (:descriptor_id, 2, 1)",
+                    named_params! {":descriptor_id": descriptor_id.to_byte_array()},
+                ),
+                (
+                    "INSERT INTO tx (txid, whole_tx, last_seen) VALUES (:txid, :whole_tx, 100)",
+                    named_params! {":txid": txid.to_string(), ":whole_tx": serialize(tx.as_ref())},
+                ),
+                (
+                    "INSERT INTO anchor_tx (block_hash, anchor, txid) VALUES (:hash, jsonb(:anchor), :txid)",
+                    named_params! {
+                        ":hash": hash.to_string(),
+                        ":anchor": serde_json::to_string(&anchor).unwrap(),
+                        ":txid": txid.to_string(),
+                    },
+                ),
+            ];
+            for (stmt, params) in stmts {
+                conn.execute(stmt, *params).expect("legacy insert");
+            }
+        }
+
+        let conn = store.conn.into_inner().expect("unlocked connection mutex");
+        let mut store = Store::<Keychain, BlockId>::new(conn).expect("migrate legacy db");
+        let expected = CombinedChangeSet {
+            chain: [(0, Some(hash))].into(),
+            indexed_tx_graph: indexed_tx_graph::ChangeSet {
+                graph: tx_graph::ChangeSet {
+                    txs: [tx].into(),
+                    txouts: BTreeMap::new(),
+                    anchors: [(anchor, txid)].into(),
+                    last_seen: [(txid, 100)].into(),
+                    last_evicted: BTreeMap::new(),
+                },
+                indexer: keychain::ChangeSet {
+                    keychains_added: [(keychain.clone(), descriptor)].into(),
+                    last_revealed: [(descriptor_id, 5)].into(),
+                    marked_used: [(descriptor_id, [(2, true)].into())].into(),
+                },
+            },
+            network: Some(Testnet),
+            labels: BTreeMap::new(),
+            broadcasts: Vec::new(),
+            change_address_policy: None,
+            change_rotation: None,
+            birthday: None,
+            last_synced: BTreeMap::new(),
+            invoices: BTreeMap::new(),
+            // the keychain is migrated as its revision 0, with an unknown activation height
+            descriptor_revisions: [(
+                keychain,
+                [(
+                    0,
+                    DescriptorRevisionRecord {
+                        descriptor_id,
+                        activation_height: None,
+                    },
+                )]
+                .into(),
+            )]
+            .into(),
+        };
+        assert_eq!(store.read().expect("aggregated changeset"), Some(expected));
+
+        let conn = store.conn.get_mut().expect("unlocked connection mutex");
+        let versions = conn
+            .prepare("SELECT version FROM schema_migrations")
+            .expect("select versions statement")
+            .query_map([], |row| row.get::<_, u32>(0))
+            .expect("select versions")
+            .collect::<Result<Vec<_>, _>>()
+            .expect("versions");
+        assert_eq!(
+            versions,
+            (1..=crate::schema::MIGRATIONS.len() as u32).collect::<Vec<_>>()
+        );
+        // the legacy version table is gone
+        assert!(conn.prepare("SELECT version FROM version").is_err());
+
+        // the legacy data belongs to the default wallet
+        assert_eq!(
+            crate::list_wallets(conn).expect("list wallets"),
+            [DEFAULT_WALLET_ID]
+        );
+    }
+
+    #[test]
+    fn migrate_legacy_database_missing_migrations() {
+        // the `keychain_marked_used` table is created by the migration
+        let store = create_legacy_store::<BlockId>(1);
+        let conn = store.conn.into_inner().expect("unlocked connection mutex");
+        let mut store = Store::<Keychain, BlockId>::new(conn).expect("migrate legacy db");
+
+        let (test_changesets, agg_test_changesets) =
+            create_test_changesets(&|height, _time, hash| BlockId { height, hash });
+        test_changesets.iter().for_each(|changeset| {
+            store.write(changeset).expect("write changeset");
+        });
+        assert_eq!(
+            store.read().expect("aggregated changeset"),
+            Some(agg_test_changesets)
+        );
+    }
+
+    #[test]
+    fn reject_newer_database() {
+        let conn = Connection::open_in_memory().expect("in memory connection");
+        let mut store = Store::<Keychain, BlockId>::new(conn).expect("create new memory db store");
+        let expected = crate::schema::MIGRATIONS.len() as u32;
+
+        let conn = store.conn.get_mut().expect("unlocked connection mutex");
+        conn.execute(
+            "INSERT INTO schema_migrations (version) VALUES (:version)",
+            named_params! {":version": expected + 1},
+        )
+        .expect("insert version");
+
+        let conn = store.conn.into_inner().expect("unlocked connection mutex");
+        assert!(matches!(
+            Store::<Keychain, BlockId>::new(conn),
+            Err(Error::SchemaVersion { found, expected: e }) if found == expected + 1 && e == expected
+        ));
+    }
+
+    #[test]
+    fn wallets_are_namespaced() {
+        let (test_changesets, agg_test_changesets) =
+            create_test_changesets(&|height, _time, hash| BlockId { height, hash });
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join("wallets.sqlite");
+
+        let mut alice = Store::<Keychain, BlockId>::new_with_wallet_id(
+            Connection::open(&file_path).expect("open connection"),
+            "alice",
+        )
+        .expect("create alice store");
+        test_changesets.iter().for_each(|changeset| {
+            alice.write(changeset).expect("write changeset");
+        });
+
+        // the other wallet neither sees alice's data, nor is bound to its network
+        let mut bob = Store::<Keychain, BlockId>::new_with_wallet_id(
+            Connection::open(&file_path).expect("open connection"),
+            "bob",
+        )
+        .expect("create bob store");
+        assert_eq!(bob.read().expect("read bob"), None);
+        let bob_changeset = CombinedChangeSet::<Keychain, BlockId> {
+            network: Some(bdk_chain::bitcoin::Network::Bitcoin),
+            ..Default::default()
+        };
+        bob.write(&bob_changeset).expect("write bob");
+
+        assert_eq!(alice.read().expect("read alice"), Some(agg_test_changesets));
+        assert_eq!(bob.read().expect("read bob"), Some(bob_changeset));
+
+        let mut conn = Connection::open(&file_path).expect("open connection");
+        assert_eq!(
+            crate::list_wallets(&mut conn).expect("list wallets"),
+            ["alice", "bob"]
+        );
+    }
+
+    #[test]
+    fn compact_to_replaces_wallet_data() {
+        let (test_changesets, agg_test_changesets) =
+            create_test_changesets(&|height, _time, hash| BlockId { height, hash });
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join("wallets.sqlite");
+
+        let mut alice = Store::<Keychain, BlockId>::new_with_wallet_id(
+            Connection::open(&file_path).expect("open connection"),
+            "alice",
+        )
+        .expect("create alice store");
+        let mut bob = Store::<Keychain, BlockId>::new_with_wallet_id(
+            Connection::open(&file_path).expect("open connection"),
+            "bob",
+        )
+        .expect("create bob store");
+        test_changesets.iter().for_each(|changeset| {
+            alice.write(changeset).expect("write alice changeset");
+            bob.write(changeset).expect("write bob changeset");
+        });
+
+        alice
+            .compact_to(&agg_test_changesets)
+            .expect("compact alice");
+        assert_eq!(
+            alice.read().expect("read alice"),
+            Some(agg_test_changesets.clone())
+        );
+
+        // nothing of the previous data is left, even what the snapshot doesn't mention
+        let snapshot = test_changesets[0].clone();
+        alice.compact_to(&snapshot).expect("compact alice");
+        assert_eq!(alice.read().expect("read alice"), Some(snapshot));
+        assert_eq!(bob.read().expect("read bob"), Some(agg_test_changesets));
+    }
+
+    #[test]
+    fn concurrent_writes_from_multiple_wallets() {
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join("wallets.sqlite");
+        let txid = Txid::all_zeros();
+        const WRITES: u64 = 50;
+
+        let handles = ["alice", "bob", "carol"]
+            .into_iter()
+            .map(|wallet_id| {
+                let mut store = Store::<Keychain, BlockId>::new_with_wallet_id(
+                    Connection::open(&file_path).expect("open connection"),
+                    wallet_id,
+                )
+                .expect("create store");
+                std::thread::spawn(move || {
+                    // every write conflicts with the ones of the other wallets, they must wait
+                    // for each other instead of failing with `SQLITE_BUSY`
+                    for last_seen in 1..=WRITES {
+                        let changeset = CombinedChangeSet::<Keychain, BlockId> {
+                            indexed_tx_graph: indexed_tx_graph::ChangeSet {
+                                graph: tx_graph::ChangeSet {
+                                    last_seen: [(txid, last_seen)].into(),
+                                    ..Default::default()
+                                },
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        };
+                        store.write(&changeset).expect("concurrent write");
+                    }
+                    store
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            let mut store = handle.join().expect("writer thread");
+            let changeset = store.read().expect("read").expect("changeset");
+            assert_eq!(
+                changeset.indexed_tx_graph.graph.last_seen,
+                [(txid, WRITES)].into()
+            );
+        }
+    }
+
     fn create_test_changesets<A: Anchor + Copy>(
         anchor_fn: &dyn Fn(u32, u64, BlockHash) -> A,
     ) -> (
@@ -650,6 +1725,8 @@ mod test {
         };
         let (ext_desc, _ext_keymap) = Descriptor::parse_descriptor(secp, "wpkh(tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy/0/*)").unwrap();
         let ext_desc_id = ext_desc.descriptor_id();
+        let invoice_spk_0 = ext_desc.at_derivation_index(0).unwrap().script_pubkey();
+        let invoice_spk_1 = ext_desc.at_derivation_index(1).unwrap().script_pubkey();
         let int_keychain = Keychain::Internal {
             account: 0,
             name: "int test".to_string(),
@@ -682,11 +1759,17 @@ mod test {
                 (tx2.compute_txid(), 1608919121),
             ]
             .into(),
+            last_evicted: [(tx2.compute_txid(), 1608919200)].into(),
         };
 
         let keychain_changeset = keychain::ChangeSet {
-            keychains_added: [(ext_keychain, ext_desc), (int_keychain, int_desc)].into(),
+            keychains_added: [
+                (ext_keychain.clone(), ext_desc),
+                (int_keychain.clone(), int_desc),
+            ]
+            .into(),
             last_revealed: [(ext_desc_id, 124), (int_desc_id, 421)].into(),
+            marked_used: [(ext_desc_id, [(3, true), (5, true)].into())].into(),
         };
 
         let graph_changeset: indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<Keychain>> =
@@ -698,30 +1781,161 @@ mod test {
         // test changesets to write to db
         let mut changesets = Vec::new();
 
+        let labels = [
+            (LabelRef::Tx(tx0.compute_txid()), Some("tx0".to_string())),
+            (
+                LabelRef::Addr(ScriptBuf::from_hex("0014a1b2c3").unwrap()),
+                Some("address".to_string()),
+            ),
+            (
+                LabelRef::Output(OutPoint::new(tx0.compute_txid(), 0)),
+                Some("output".to_string()),
+            ),
+            (
+                LabelRef::Input(OutPoint::new(tx1.compute_txid(), 0)),
+                Some("input".to_string()),
+            ),
+        ]
+        .into();
+
         changesets.push(CombinedChangeSet {
             chain: block_changeset,
             indexed_tx_graph: graph_changeset,
             network: network_changeset,
+            labels,
+            broadcasts: vec![BroadcastRecord {
+                txid: tx1.compute_txid(),
+                backend: "esplora".to_string(),
+                outcome: BroadcastOutcome::Rejected("missing-inputs".to_string()),
+                timestamp: 1708919120,
+            }],
+            change_address_policy: Some(ChangeAddressPolicy::RotateWithin(3)),
+            change_rotation: Some(1),
+            birthday: Some(BlockTimeOrHeight::Time(1_700_000_000)),
+            last_synced: [(ext_keychain.clone(), 1708919120)].into(),
+            invoices: [(
+                0,
+                InvoiceRecord {
+                    script_pubkey: invoice_spk_0,
+                    amount: Amount::from_sat(25_000),
+                    expiry: BlockTimeOrHeight::Height(900_000),
+                    min_confirmations: 1,
+                },
+            )]
+            .into(),
+            descriptor_revisions: [
+                (
+                    ext_keychain.clone(),
+                    [(
+                        0,
+                        DescriptorRevisionRecord {
+                            descriptor_id: ext_desc_id,
+                            activation_height: Some(0),
+                        },
+                    )]
+                    .into(),
+                ),
+                (
+                    int_keychain.clone(),
+                    [(
+                        0,
+                        DescriptorRevisionRecord {
+                            descriptor_id: int_desc_id,
+                            activation_height: None,
+                        },
+                    )]
+                    .into(),
+                ),
+            ]
+            .into(),
         });
 
-        // create changeset that sets the whole tx2 and updates it's lastseen where before there was only the txid and last_seen
+        // create changeset that sets the whole tx2 and updates it's lastseen where before there was only the txid and last_seen,
         let tx_graph_changeset2 = tx_graph::ChangeSet::<A> {
             txs: [tx2.clone()].into(),
             txouts: BTreeMap::default(),
             anchors: BTreeSet::default(),
             last_seen: [(tx2.compute_txid(), 1708919121)].into(),
+            last_evicted: BTreeMap::default(),
+        };
+
+        // and unmarks one of the indexes marked as used
+        let keychain_changeset2 = keychain::ChangeSet {
+            marked_used: [(ext_desc_id, [(5, false)].into())].into(),
+            ..Default::default()
         };
 
         let graph_changeset2: indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<Keychain>> =
             indexed_tx_graph::ChangeSet {
                 graph: tx_graph_changeset2,
-                indexer: keychain::ChangeSet::default(),
+                indexer: keychain_changeset2,
             };
 
+        // and renames a label and removes another one
+        let labels2 = [
+            (
+                LabelRef::Tx(tx0.compute_txid()),
+                Some("renamed tx0".to_string()),
+            ),
+            (
+                LabelRef::Addr(ScriptBuf::from_hex("0014a1b2c3").unwrap()),
+                None,
+            ),
+        ]
+        .into();
+
         changesets.push(CombinedChangeSet {
             chain: local_chain::ChangeSet::default(),
             indexed_tx_graph: graph_changeset2,
             network: None,
+            labels: labels2,
+            // and records a successful broadcast after the failed one
+            broadcasts: vec![BroadcastRecord {
+                txid: tx1.compute_txid(),
+                backend: "electrum".to_string(),
+                outcome: BroadcastOutcome::Accepted,
+                timestamp: 1708919121,
+            }],
+            change_address_policy: None,
+            change_rotation: Some(2),
+            birthday: Some(BlockTimeOrHeight::Height(800_000)),
+            last_synced: [
+                (ext_keychain.clone(), 1708919100),
+                (int_keychain.clone(), 1708919122),
+            ]
+            .into(),
+            invoices: [(
+                1,
+                InvoiceRecord {
+                    script_pubkey: invoice_spk_1,
+                    amount: Amount::from_sat(100_000),
+                    expiry: BlockTimeOrHeight::Time(1_708_920_000),
+                    min_confirmations: 6,
+                },
+            )]
+            .into(),
+            // a recorded revision doesn't change, and the next one is added
+            descriptor_revisions: [(
+                ext_keychain.clone(),
+                [
+                    (
+                        0,
+                        DescriptorRevisionRecord {
+                            descriptor_id: ext_desc_id,
+                            activation_height: Some(5),
+                        },
+                    ),
+                    (
+                        1,
+                        DescriptorRevisionRecord {
+                            descriptor_id: DescriptorId::from_byte_array([1; 32]),
+                            activation_height: Some(800_000),
+                        },
+                    ),
+                ]
+                .into(),
+            )]
+            .into(),
         });
 
         // create changeset that adds a new anchor2 for tx0 and tx1
@@ -730,6 +1944,7 @@ mod test {
             txouts: BTreeMap::default(),
             anchors: [(anchor2, tx0.compute_txid()), (anchor2, tx1.compute_txid())].into(),
             last_seen: BTreeMap::default(),
+            last_evicted: BTreeMap::default(),
         };
 
         let graph_changeset3: indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<Keychain>> =
@@ -742,16 +1957,21 @@ mod test {
             chain: local_chain::ChangeSet::default(),
             indexed_tx_graph: graph_changeset3,
             network: None,
+            ..Default::default()
         });
 
         // aggregated test changesets
-        let agg_test_changesets =
+        let mut agg_test_changesets =
             changesets
                 .iter()
                 .fold(CombinedChangeSet::<Keychain, A>::default(), |mut i, cs| {
                     i.append(cs.clone());
                     i
                 });
+        // removed labels are not stored
+        agg_test_changesets
+            .labels
+            .retain(|_, label| label.is_some());
 
         (changesets, agg_test_changesets)
     }
@@ -1,14 +1,16 @@
 use bdk_chain::{
     bitcoin::{
-        address::NetworkChecked, block::Header, hash_types::TxMerkleNode, hashes::Hash,
-        secp256k1::rand::random, transaction, Address, Amount, Block, BlockHash, CompactTarget,
-        ScriptBuf, ScriptHash, Transaction, TxIn, TxOut, Txid,
+        address::NetworkChecked, block::Header, consensus::encode::serialize_hex,
+        hash_types::TxMerkleNode, hashes::Hash, secp256k1::rand::random, transaction, Address,
+        Amount, Block, BlockHash, CompactTarget, ScriptBuf, ScriptHash, Sequence, Transaction,
+        TxIn, TxOut, Txid,
     },
     local_chain::CheckPoint,
     BlockId,
 };
 use bitcoincore_rpc::{
     bitcoincore_rpc_json::{GetBlockTemplateModes, GetBlockTemplateRules},
+    jsonrpc::serde_json,
     RpcApi,
 };
 pub use electrsd;
@@ -17,8 +19,12 @@ pub use electrsd::bitcoind::anyhow;
 pub use electrsd::bitcoind::bitcoincore_rpc;
 pub use electrsd::electrum_client;
 use electrsd::electrum_client::ElectrumApi;
+use std::str::FromStr;
 use std::time::Duration;
 
+mod tx_factory;
+pub use tx_factory::*;
+
 /// Struct for running a regtest environment with a single `bitcoind` node with an `electrs`
 /// instance connected to it.
 pub struct TestEnv {
@@ -29,22 +35,47 @@ pub struct TestEnv {
 impl TestEnv {
     /// Construct a new [`TestEnv`] instance with default configurations.
     pub fn new() -> anyhow::Result<Self> {
+        Self::new_with_bitcoind_args(&[])
+    }
+
+    /// Construct a new [`TestEnv`] instance, passing the extra `args` to `bitcoind`, such as
+    /// `-zmqpubhashblock=tcp://127.0.0.1:28332`.
+    pub fn new_with_bitcoind_args(args: &[&str]) -> anyhow::Result<Self> {
+        let mut bitcoind_conf = electrsd::bitcoind::Conf::default();
+        bitcoind_conf.args.extend_from_slice(args);
+        Self::with_bitcoind_conf(bitcoind_conf)
+    }
+
+    /// Construct a new [`TestEnv`] on a custom signet whose blocks must satisfy the `challenge`
+    /// script, in hex.
+    ///
+    /// The blocks of a signet with the `51` (`OP_TRUE`) challenge need no signature, but they still
+    /// need the proof of work of signet, so mining them is slow.
+    pub fn new_signet(challenge: &str) -> anyhow::Result<Self> {
+        let challenge_arg = format!("-signetchallenge={}", challenge);
+        let mut bitcoind_conf = electrsd::bitcoind::Conf::default();
+        bitcoind_conf.network = "signet";
+        bitcoind_conf.args = vec!["-signet", &challenge_arg, "-fallbackfee=0.0001"];
+        Self::with_bitcoind_conf(bitcoind_conf)
+    }
+
+    fn with_bitcoind_conf(bitcoind_conf: electrsd::bitcoind::Conf) -> anyhow::Result<Self> {
         let bitcoind = match std::env::var_os("BITCOIND_EXE") {
-            Some(bitcoind_path) => electrsd::bitcoind::BitcoinD::new(bitcoind_path),
+            Some(bitcoind_path) => {
+                electrsd::bitcoind::BitcoinD::with_conf(bitcoind_path, &bitcoind_conf)
+            }
             None => {
                 let bitcoind_exe = electrsd::bitcoind::downloaded_exe_path()
                     .expect(
                 "you need to provide an env var BITCOIND_EXE or specify a bitcoind version feature",
                 );
-                electrsd::bitcoind::BitcoinD::with_conf(
-                    bitcoind_exe,
-                    &electrsd::bitcoind::Conf::default(),
-                )
+                electrsd::bitcoind::BitcoinD::with_conf(bitcoind_exe, &bitcoind_conf)
             }
         }?;
 
         let mut electrsd_conf = electrsd::Conf::default();
         electrsd_conf.http_enabled = true;
+        electrsd_conf.network = bitcoind_conf.network;
         let electrsd = match std::env::var_os("ELECTRS_EXE") {
             Some(env_electrs_exe) => {
                 electrsd::ElectrsD::with_conf(env_electrs_exe, &bitcoind, &electrsd_conf)
@@ -235,6 +266,124 @@ impl TestEnv {
         Ok(res)
     }
 
+    /// Reorg a number of blocks of a given size `count`, including the given `txs` in the first
+    /// replacement block.
+    ///
+    /// The replacement blocks contain exactly the `txs` and no other transaction, so the
+    /// transactions of the reorged blocks that are not part of `txs` go back to the mempool. The
+    /// `txs` don't need to be in the mempool, and can conflict with the transactions of the reorged
+    /// blocks, which allows replacing a confirmed transaction with a double spend of it.
+    ///
+    /// Returns the hashes of the replacement blocks.
+    pub fn reorg_with_txs(
+        &self,
+        count: usize,
+        txs: &[Transaction],
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        anyhow::ensure!(count > 0, "must reorg at least one block");
+        let start_height = self.bitcoind.client.get_block_count()?;
+        self.invalidate_blocks(count)?;
+
+        let coinbase_address = self
+            .bitcoind
+            .client
+            .get_new_address(None, None)?
+            .assume_checked();
+        let mut txs = txs.iter().map(serialize_hex).collect::<Vec<_>>();
+        let mut block_hashes = Vec::with_capacity(count);
+        for _ in 0..count {
+            let block: serde_json::Value = self.bitcoind.client.call(
+                "generateblock",
+                &[
+                    coinbase_address.to_string().into(),
+                    core::mem::take(&mut txs).into(),
+                ],
+            )?;
+            let hash = block["hash"]
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("generateblock must return the block hash"))?;
+            block_hashes.push(BlockHash::from_str(hash)?);
+        }
+        assert_eq!(
+            self.bitcoind.client.get_block_count()?,
+            start_height,
+            "reorg should not result in height change"
+        );
+        Ok(block_hashes)
+    }
+
+    /// Broadcast a transaction spending the same inputs as the transaction of `original_txid` to
+    /// `new_recipient`, replacing it in the mempool.
+    ///
+    /// The inputs must belong to the wallet of `bitcoind`, and the original transaction must be
+    /// replaceable. To double spend a confirmed transaction, invalidate its block first (see
+    /// [`TestEnv::invalidate_blocks`]) so that it goes back to the mempool.
+    pub fn double_spend(
+        &self,
+        original_txid: Txid,
+        new_recipient: &Address<NetworkChecked>,
+    ) -> anyhow::Result<Txid> {
+        let client = &self.bitcoind.client;
+        let original = self.get_tx(original_txid)?;
+
+        let mut input_value = Amount::ZERO;
+        for txin in &original.input {
+            let prev_tx = self.get_tx(txin.previous_output.txid)?;
+            let prevout = prev_tx
+                .output
+                .get(txin.previous_output.vout as usize)
+                .ok_or_else(|| anyhow::anyhow!("missing prevout {}", txin.previous_output))?;
+            input_value += prevout.value;
+        }
+        let output_value = original.output.iter().map(|txout| txout.value).sum();
+        let original_fee = input_value
+            .checked_sub(output_value)
+            .ok_or_else(|| anyhow::anyhow!("invalid fee for {}", original_txid))?;
+        // pay more than the original fee, by enough to cover the replacement at 10 sat/vb
+        let fee = original_fee + Amount::from_sat(10 * (original.vsize() as u64 + 50));
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: bdk_chain::bitcoin::absolute::LockTime::ZERO,
+            input: original
+                .input
+                .iter()
+                .map(|txin| TxIn {
+                    previous_output: txin.previous_output,
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: input_value
+                    .checked_sub(fee)
+                    .ok_or_else(|| anyhow::anyhow!("inputs can't pay for the double spend"))?,
+                script_pubkey: new_recipient.script_pubkey(),
+            }],
+        };
+        let signed = client.sign_raw_transaction_with_wallet(&tx, None, None)?;
+        anyhow::ensure!(
+            signed.complete,
+            "the wallet of bitcoind can't sign the inputs of {}",
+            original_txid
+        );
+        Ok(client.send_raw_transaction(&signed.transaction()?)?)
+    }
+
+    /// Get a transaction of the wallet of `bitcoind` or of its mempool.
+    fn get_tx(&self, txid: Txid) -> anyhow::Result<Transaction> {
+        match self.bitcoind.client.get_transaction(&txid, Some(true)) {
+            Ok(tx) => Ok(tx.transaction()?),
+            Err(_) => Ok(self.bitcoind.client.get_raw_transaction(&txid, None)?),
+        }
+    }
+
+    /// Get a [`TxFactory`] to create transactions with specific properties, funded by the wallet
+    /// of `bitcoind`.
+    pub fn tx_factory(&self) -> TxFactory<'_> {
+        TxFactory::new(&self.bitcoind.client)
+    }
+
     /// Send a tx of a given `amount` to a given `address`.
     pub fn send(&self, address: &Address<NetworkChecked>, amount: Amount) -> anyhow::Result<Txid> {
         let txid = self
@@ -244,6 +393,52 @@ impl TestEnv {
         Ok(txid)
     }
 
+    /// Create a parent tx paying `amount` to `address` and a child tx spending it to `address`
+    /// with a fee of `child_fee`, both signed by the wallet of `bitcoind` but not broadcast.
+    pub fn create_unbroadcast_package(
+        &self,
+        address: &Address<NetworkChecked>,
+        amount: Amount,
+        child_fee: Amount,
+    ) -> anyhow::Result<(Transaction, Transaction)> {
+        let client = &self.bitcoind.client;
+        let outs = [(address.to_string(), amount)].into_iter().collect();
+        let parent = client.create_raw_transaction(&[], &outs, None, None)?;
+        let parent = client.fund_raw_transaction(&parent, None, None)?;
+        let parent = client
+            .sign_raw_transaction_with_wallet(&parent.hex, None, None)?
+            .transaction()?;
+        let vout = parent
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey == address.script_pubkey())
+            .expect("parent must pay to the address") as u32;
+
+        let child = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: bdk_chain::bitcoin::absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: bdk_chain::bitcoin::OutPoint::new(parent.compute_txid(), vout),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: amount - child_fee,
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let prevout = bitcoincore_rpc::bitcoincore_rpc_json::SignRawTransactionInput {
+            txid: parent.compute_txid(),
+            vout,
+            script_pub_key: address.script_pubkey(),
+            redeem_script: None,
+            amount: Some(amount),
+        };
+        let child = client
+            .sign_raw_transaction_with_wallet(&child, Some(&[prevout]), None)?
+            .transaction()?;
+        Ok((parent, child))
+    }
+
     /// Create a checkpoint linked list of all the blocks in the chain.
     pub fn make_checkpoint_tip(&self) -> CheckPoint {
         CheckPoint::from_block_ids((0_u32..).map_while(|height| {
@@ -266,7 +461,9 @@ impl TestEnv {
 #[cfg(test)]
 mod test {
     use crate::TestEnv;
+    use bdk_chain::bitcoin::Amount;
     use electrsd::bitcoind::{anyhow::Result, bitcoincore_rpc::RpcApi};
+    use electrsd::electrum_client::ElectrumApi;
 
     /// This checks that reorgs initiated by `bitcoind` is detected by our `electrsd` instance.
     #[test]
@@ -301,4 +498,103 @@ mod test {
 
         Ok(())
     }
+
+    /// This checks that the replacement blocks of `reorg_with_txs` contain exactly the given txs,
+    /// as seen by both `bitcoind` and `electrsd`.
+    #[test]
+    fn test_reorg_with_txs() -> Result<()> {
+        let env = TestEnv::new()?;
+        env.mine_blocks(101, None)?;
+        let address = env
+            .bitcoind
+            .client
+            .get_new_address(None, None)?
+            .assume_checked();
+        let txid = env.send(&address, Amount::from_sat(10_000))?;
+        let tx = env.bitcoind.client.get_raw_transaction(&txid, None)?;
+        env.mine_blocks(1, None)?;
+        env.wait_until_electrum_sees_block()?;
+
+        // Reorg the block confirming the tx with an empty block: the tx is back in the mempool.
+        env.reorg_with_txs(1, &[])?;
+        env.wait_until_electrum_sees_block()?;
+        assert!(env.bitcoind.client.get_raw_mempool()?.contains(&txid));
+        let history = env
+            .electrum_client()
+            .script_get_history(&address.script_pubkey())?;
+        assert_eq!(history.len(), 1);
+        assert!(history[0].height <= 0, "tx must be unconfirmed");
+
+        // Reorg two blocks, confirming the tx in the first replacement block.
+        let block_hashes = env.reorg_with_txs(2, &[tx])?;
+        env.wait_until_electrum_sees_block()?;
+        assert_eq!(block_hashes.len(), 2);
+        let info = env.bitcoind.client.get_transaction(&txid, None)?.info;
+        assert_eq!(info.blockhash, Some(block_hashes[0]));
+        let height = env.bitcoind.client.get_block_info(&block_hashes[0])?.height;
+        let history = env
+            .electrum_client()
+            .script_get_history(&address.script_pubkey())?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].height, height as i32);
+        let second_block = env.bitcoind.client.get_block(&block_hashes[1])?;
+        assert_eq!(second_block.txdata.len(), 1, "only the coinbase tx");
+
+        Ok(())
+    }
+
+    /// This checks that `double_spend` replaces unconfirmed txs, and confirmed txs once their block
+    /// is reorged.
+    #[test]
+    fn test_double_spend() -> Result<()> {
+        let env = TestEnv::new()?;
+        env.mine_blocks(101, None)?;
+        let address_a = env
+            .bitcoind
+            .client
+            .get_new_address(None, None)?
+            .assume_checked();
+        let address_b = env
+            .bitcoind
+            .client
+            .get_new_address(None, None)?
+            .assume_checked();
+
+        // The double spend replaces the original tx in the mempool.
+        let txid = env.send(&address_a, Amount::from_sat(10_000))?;
+        let replacement_txid = env.double_spend(txid, &address_b)?;
+        let mempool = env.bitcoind.client.get_raw_mempool()?;
+        assert!(mempool.contains(&replacement_txid));
+        assert!(!mempool.contains(&txid));
+
+        // Confirm the replacement, then double spend it in a reorg.
+        env.mine_blocks(1, None)?;
+        env.wait_until_electrum_sees_block()?;
+        env.invalidate_blocks(1)?;
+        let double_spend_txid = env.double_spend(replacement_txid, &address_a)?;
+        let block_hash = env.mine_blocks(1, None)?[0];
+        env.wait_until_electrum_sees_block()?;
+
+        let block = env.bitcoind.client.get_block(&block_hash)?;
+        let block_txids = block
+            .txdata
+            .iter()
+            .map(|tx| tx.compute_txid())
+            .collect::<Vec<_>>();
+        assert!(block_txids.contains(&double_spend_txid));
+        assert!(!block_txids.contains(&replacement_txid));
+        let history_a = env
+            .electrum_client()
+            .script_get_history(&address_a.script_pubkey())?;
+        assert_eq!(
+            history_a.iter().map(|res| res.tx_hash).collect::<Vec<_>>(),
+            vec![double_spend_txid]
+        );
+        assert!(env
+            .electrum_client()
+            .script_get_history(&address_b.script_pubkey())?
+            .is_empty());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,373 @@
+use bdk_chain::bitcoin::{
+    absolute, transaction, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Txid,
+};
+use electrsd::bitcoind::{
+    anyhow,
+    bitcoincore_rpc::{
+        bitcoincore_rpc_json::{FundRawTransactionOptions, SignRawTransactionInput},
+        Client, RpcApi,
+    },
+};
+
+/// Whether a transaction created by [`TxFactory::send_with`] is left in the mempool or mined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    /// Broadcast the transaction and leave it in the mempool.
+    Mempool,
+    /// Broadcast the transaction and mine a block containing it.
+    Mined,
+}
+
+/// The shape of a transaction created by a [`TxFactory`].
+///
+/// Use [`SendParams::new`] for the defaults and override the fields that matter to the test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendParams {
+    /// The script pubkey the outputs pay to.
+    pub spk: ScriptBuf,
+    /// The value of each output paying to `spk`. It may be below the dust limit.
+    pub value: Amount,
+    /// The number of outputs paying `value` to `spk`.
+    pub outputs: usize,
+    /// The fee rate of the transaction, or `None` to let `bitcoind` pick one.
+    pub fee_rate: Option<FeeRate>,
+    /// Whether the inputs signal replaceability (BIP125).
+    pub rbf: bool,
+    /// The locktime of the transaction.
+    pub lock_time: absolute::LockTime,
+    /// Whether the transaction is left in the mempool or mined.
+    pub confirmation: Confirmation,
+}
+
+impl SendParams {
+    /// Parameters for a single replaceable output of `value` to `spk`, left in the mempool.
+    pub fn new(spk: ScriptBuf, value: Amount) -> Self {
+        Self {
+            spk,
+            value,
+            outputs: 1,
+            fee_rate: None,
+            rbf: true,
+            lock_time: absolute::LockTime::ZERO,
+            confirmation: Confirmation::Mempool,
+        }
+    }
+}
+
+/// Creates transactions with specific properties, funded and signed by the wallet of `bitcoind`.
+///
+/// Get one with [`TestEnv::tx_factory`](crate::TestEnv::tx_factory).
+pub struct TxFactory<'a> {
+    client: &'a Client,
+}
+
+impl<'a> TxFactory<'a> {
+    /// Construct a [`TxFactory`] over the wallet of the `bitcoind` behind `client`.
+    pub fn new(client: &'a Client) -> Self {
+        Self { client }
+    }
+
+    /// Create and sign a transaction shaped by `params`, without broadcasting it.
+    ///
+    /// This is useful for transactions that `bitcoind` refuses to relay, such as ones with a
+    /// locktime in the future or with dust outputs. `params.confirmation` is ignored.
+    pub fn create_tx(&self, params: &SendParams) -> anyhow::Result<Transaction> {
+        // `bitcoind` refuses to fund dust outputs, so fund them at the dust limit and give the
+        // difference to the fee afterwards.
+        let funded_value = params.value.max(params.spk.minimal_non_dust());
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: params.lock_time,
+            input: vec![],
+            output: (0..params.outputs)
+                .map(|_| TxOut {
+                    value: funded_value,
+                    script_pubkey: params.spk.clone(),
+                })
+                .collect(),
+        };
+        let options = FundRawTransactionOptions {
+            fee_rate: params
+                .fee_rate
+                .map(|fee_rate| Amount::from_sat(fee_rate.to_sat_per_kwu() * 4)),
+            replaceable: Some(params.rbf),
+            ..Default::default()
+        };
+        // a tx without inputs is ambiguous to deserialize, so tell `bitcoind` it isn't segwit
+        let mut tx = self
+            .client
+            .fund_raw_transaction(&tx, Some(&options), Some(false))?
+            .transaction()?;
+
+        let sequence = match (params.rbf, params.lock_time == absolute::LockTime::ZERO) {
+            (true, _) => Sequence::ENABLE_RBF_NO_LOCKTIME,
+            (false, false) => Sequence::ENABLE_LOCKTIME_NO_RBF,
+            (false, true) => Sequence::MAX,
+        };
+        for txin in &mut tx.input {
+            txin.sequence = sequence;
+        }
+        for txout in &mut tx.output {
+            if txout.script_pubkey == params.spk && txout.value == funded_value {
+                txout.value = params.value;
+            }
+        }
+        self.sign(&tx, None)
+    }
+
+    /// Create a transaction shaped by `params`, broadcast it and, depending on
+    /// `params.confirmation`, mine it.
+    pub fn send_with(&self, params: &SendParams) -> anyhow::Result<Txid> {
+        let tx = self.create_tx(params)?;
+        let txid = self.client.send_raw_transaction(&tx)?;
+        if params.confirmation == Confirmation::Mined {
+            self.mine_block()?;
+        }
+        Ok(txid)
+    }
+
+    /// Create `count` separate UTXOs of `value` paying to `spk`, all confirmed in one block.
+    ///
+    /// Returns the outpoints of the UTXOs, which all belong to the same transaction.
+    pub fn fund_address_multiple(
+        &self,
+        spk: ScriptBuf,
+        count: usize,
+        value: Amount,
+    ) -> anyhow::Result<Vec<OutPoint>> {
+        let params = SendParams {
+            outputs: count,
+            ..SendParams::new(spk.clone(), value)
+        };
+        let tx = self.create_tx(&params)?;
+        let txid = self.client.send_raw_transaction(&tx)?;
+        self.mine_block()?;
+        Ok(tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, txout)| txout.script_pubkey == spk && txout.value == value)
+            .map(|(vout, _)| OutPoint::new(txid, vout as u32))
+            .collect())
+    }
+
+    /// Broadcast a chain of `depth` unconfirmed transactions, each paying `value` to `spk` and
+    /// spending the change of the previous one.
+    ///
+    /// Returns the txids from the first (the ancestor of all others) to the last. The mempool of
+    /// `bitcoind` accepts chains of up to 25 transactions by default.
+    pub fn send_chain(
+        &self,
+        spk: ScriptBuf,
+        depth: usize,
+        value: Amount,
+    ) -> anyhow::Result<Vec<Txid>> {
+        let mut txids = Vec::with_capacity(depth);
+        if depth == 0 {
+            return Ok(txids);
+        }
+        let first = self.create_tx(&SendParams::new(spk.clone(), value))?;
+        let mut change = Self::change_of(&first, &spk)?;
+        txids.push(self.client.send_raw_transaction(&first)?);
+
+        // well above the minimum relay fee of these one-input, two-output txs
+        let fee = Amount::from_sat(400);
+        for _ in 1..depth {
+            let change_spk = self
+                .client
+                .get_raw_change_address(None)?
+                .assume_checked()
+                .script_pubkey();
+            let change_value =
+                change.1.value.checked_sub(value + fee).ok_or_else(|| {
+                    anyhow::anyhow!("the change is too small to extend the chain")
+                })?;
+            let tx = Transaction {
+                version: transaction::Version::TWO,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: change.0,
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    ..Default::default()
+                }],
+                output: vec![
+                    TxOut {
+                        value,
+                        script_pubkey: spk.clone(),
+                    },
+                    TxOut {
+                        value: change_value,
+                        script_pubkey: change_spk,
+                    },
+                ],
+            };
+            let prevout = SignRawTransactionInput {
+                txid: change.0.txid,
+                vout: change.0.vout,
+                script_pub_key: change.1.script_pubkey.clone(),
+                redeem_script: None,
+                amount: Some(change.1.value),
+            };
+            let tx = self.sign(&tx, Some(&[prevout]))?;
+            let txid = self.client.send_raw_transaction(&tx)?;
+            change = (OutPoint::new(txid, 1), tx.output[1].clone());
+            txids.push(txid);
+        }
+        Ok(txids)
+    }
+
+    /// Sign all the inputs of `tx` with the wallet of `bitcoind`.
+    fn sign(
+        &self,
+        tx: &Transaction,
+        prevouts: Option<&[SignRawTransactionInput]>,
+    ) -> anyhow::Result<Transaction> {
+        let signed = self
+            .client
+            .sign_raw_transaction_with_wallet(tx, prevouts, None)?;
+        anyhow::ensure!(
+            signed.complete,
+            "the wallet of bitcoind can't sign {}",
+            tx.compute_txid()
+        );
+        Ok(signed.transaction()?)
+    }
+
+    /// Mine a block to a new address of the wallet of `bitcoind`.
+    fn mine_block(&self) -> anyhow::Result<()> {
+        let address = self.client.get_new_address(None, None)?.assume_checked();
+        self.client.generate_to_address(1, &address)?;
+        Ok(())
+    }
+
+    /// Find the change output of a tx funded by `bitcoind`, i.e. the one not paying to `spk`.
+    fn change_of(tx: &Transaction, spk: &ScriptBuf) -> anyhow::Result<(OutPoint, TxOut)> {
+        tx.output
+            .iter()
+            .enumerate()
+            .find(|(_, txout)| &txout.script_pubkey != spk)
+            .map(|(vout, txout)| (OutPoint::new(tx.compute_txid(), vout as u32), txout.clone()))
+            .ok_or_else(|| anyhow::anyhow!("{} has no change output", tx.compute_txid()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Confirmation, SendParams};
+    use crate::TestEnv;
+    use bdk_chain::bitcoin::{absolute, Amount, FeeRate};
+    use electrsd::bitcoind::{anyhow::Result, bitcoincore_rpc::RpcApi};
+
+    /// This checks that the txs of `send_with` have the requested properties.
+    #[test]
+    fn test_send_with() -> Result<()> {
+        let env = TestEnv::new()?;
+        env.mine_blocks(101, None)?;
+        let client = &env.bitcoind.client;
+        let spk = client
+            .get_new_address(None, None)?
+            .assume_checked()
+            .script_pubkey();
+        let factory = env.tx_factory();
+
+        // A non-RBF tx with three outputs at 5 sat/vb, left in the mempool.
+        let txid = factory.send_with(&SendParams {
+            outputs: 3,
+            fee_rate: Some(FeeRate::from_sat_per_vb_u32(5)),
+            rbf: false,
+            ..SendParams::new(spk.clone(), Amount::from_sat(10_000))
+        })?;
+        let tx = client.get_raw_transaction(&txid, None)?;
+        assert_eq!(
+            tx.output
+                .iter()
+                .filter(|txout| txout.script_pubkey == spk)
+                .count(),
+            3
+        );
+        assert!(!tx.is_explicitly_rbf());
+        let entry = client.get_mempool_entry(&txid)?;
+        let fee_rate = entry.fees.base.to_sat() as f64 / entry.vsize as f64;
+        assert!((5.0..6.0).contains(&fee_rate), "fee rate is {}", fee_rate);
+
+        // A mined RBF tx.
+        let txid = factory.send_with(&SendParams {
+            confirmation: Confirmation::Mined,
+            ..SendParams::new(spk.clone(), Amount::from_sat(10_000))
+        })?;
+        assert!(client.get_raw_transaction(&txid, None)?.is_explicitly_rbf());
+        assert!(client.get_transaction(&txid, None)?.info.confirmations > 0);
+
+        // A tx with a locktime in the future can't be broadcast yet.
+        let height = client.get_block_count()? as u32;
+        let params = SendParams {
+            lock_time: absolute::LockTime::from_height(height + 10)?,
+            ..SendParams::new(spk.clone(), Amount::from_sat(10_000))
+        };
+        let tx = factory.create_tx(&params)?;
+        assert_eq!(tx.lock_time, params.lock_time);
+        assert!(client.send_raw_transaction(&tx).is_err());
+
+        // A tx with a dust output.
+        let tx = factory.create_tx(&SendParams::new(spk.clone(), Amount::from_sat(1)))?;
+        assert!(tx
+            .output
+            .iter()
+            .any(|txout| txout.script_pubkey == spk && txout.value == Amount::from_sat(1)));
+
+        Ok(())
+    }
+
+    /// This checks that `fund_address_multiple` creates the UTXOs in a single block.
+    #[test]
+    fn test_fund_address_multiple() -> Result<()> {
+        let env = TestEnv::new()?;
+        env.mine_blocks(101, None)?;
+        let client = &env.bitcoind.client;
+        let spk = client
+            .get_new_address(None, None)?
+            .assume_checked()
+            .script_pubkey();
+
+        let outpoints =
+            env.tx_factory()
+                .fund_address_multiple(spk.clone(), 30, Amount::from_sat(5_000))?;
+        assert_eq!(outpoints.len(), 30);
+        let tip = client.get_best_block_hash()?;
+        let block = client.get_block(&tip)?;
+        for outpoint in outpoints {
+            let tx = block
+                .txdata
+                .iter()
+                .find(|tx| tx.compute_txid() == outpoint.txid)
+                .expect("tx must be in the tip");
+            assert_eq!(tx.output[outpoint.vout as usize].script_pubkey, spk);
+        }
+
+        Ok(())
+    }
+
+    /// This checks that `send_chain` creates a chain of unconfirmed descendants.
+    #[test]
+    fn test_send_chain() -> Result<()> {
+        let env = TestEnv::new()?;
+        env.mine_blocks(101, None)?;
+        let client = &env.bitcoind.client;
+        let spk = client
+            .get_new_address(None, None)?
+            .assume_checked()
+            .script_pubkey();
+
+        let txids = env
+            .tx_factory()
+            .send_chain(spk, 25, Amount::from_sat(10_000))?;
+        assert_eq!(txids.len(), 25);
+        let last = client.get_mempool_entry(txids.last().expect("must have txs"))?;
+        assert_eq!(last.ancestor_count, 25);
+        let first = client.get_mempool_entry(&txids[0])?;
+        assert_eq!(first.descendant_count, 25);
+
+        Ok(())
+    }
+}
@@ -134,6 +134,27 @@ mod test {
         );
     }
 
+    // test vectors of Bitcoin Core's `descriptor_tests.cpp`
+    #[test]
+    fn test_calc_checksum_core_vectors() {
+        let desc = "sh(multi(2,[00000000/111'/222]xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc,xprv9uPDJpEQgRQfDcW7BkF7eTya6RPxXeJCqCJGHuCJ4GiRVLzkTXBAJMu2qaMWPrS7AANYqdq6vcBcBUdJCVVFceUvJFjaPdGZ2y9WACViL4L/0))";
+        assert_eq!(calc_checksum(desc).unwrap(), "ggrsrxfy");
+        assert_eq!(
+            calc_checksum(&format!("{}#ggrsrxfy", desc)).unwrap(),
+            "ggrsrxfy"
+        );
+
+        let desc = "sh(multi(2,[00000000/111'/222]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL,xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0))";
+        assert_eq!(calc_checksum(desc).unwrap(), "tjg09x5t");
+        // a checksum of the wrong length or with a typo
+        for checksum in ["tjg09x5", "tjg09x5tt", "tjq09x5t"] {
+            assert_matches!(
+                calc_checksum(&format!("{}#{}", desc, checksum)),
+                Err(DescriptorError::InvalidDescriptorChecksum)
+            );
+        }
+    }
+
     #[test]
     fn test_calc_checksum_invalid_character() {
         let sparkle_heart = unsafe { core::str::from_utf8_unchecked(&[240, 159, 146, 150]) };
@@ -171,9 +171,35 @@ impl SatisfiableItem {
     }
 
     /// Returns a unique id for the [`SatisfiableItem`]
+    ///
+    /// The id doesn't depend on the `satisfaction` of the items, so it's the same whatever the
+    /// PSBT or the chain the policy is built with.
     pub fn id(&self) -> String {
-        calc_checksum(&serde_json::to_string(self).expect("Failed to serialize a SatisfiableItem"))
-            .expect("Failed to compute a SatisfiableItem id")
+        calc_checksum(
+            &serde_json::to_string(&self.without_satisfaction())
+                .expect("Failed to serialize a SatisfiableItem"),
+        )
+        .expect("Failed to compute a SatisfiableItem id")
+    }
+
+    /// A copy of the item with the `satisfaction` of its items recursively reset to what it is
+    /// with [`BuildSatisfaction::None`]
+    fn without_satisfaction(&self) -> SatisfiableItem {
+        match self {
+            SatisfiableItem::Thresh { items, threshold } => SatisfiableItem::Thresh {
+                items: items
+                    .iter()
+                    .map(|policy| Policy {
+                        id: policy.id.clone(),
+                        item: policy.item.without_satisfaction(),
+                        satisfaction: policy.satisfaction.unsatisfied(),
+                        contribution: policy.contribution.clone(),
+                    })
+                    .collect(),
+                threshold: *threshold,
+            },
+            item => item.clone(),
+        }
     }
 }
 
@@ -303,6 +329,21 @@ pub enum Satisfaction {
 }
 
 impl Satisfaction {
+    /// The satisfaction of the same policy node when nothing is satisfied
+    fn unsatisfied(&self) -> Satisfaction {
+        match self {
+            Satisfaction::Partial { n, m, sorted, .. }
+            | Satisfaction::PartialComplete { n, m, sorted, .. } => Satisfaction::Partial {
+                n: *n,
+                m: *m,
+                items: vec![],
+                sorted: *sorted,
+                conditions: Default::default(),
+            },
+            Satisfaction::Complete { .. } | Satisfaction::None => Satisfaction::None,
+        }
+    }
+
     /// Returns whether the [`Satisfaction`] is a leaf item
     pub fn is_leaf(&self) -> bool {
         match self {
@@ -613,6 +654,14 @@ impl Policy {
                     },
                     index,
                 )?;
+                if build_sat.assume_signed() {
+                    satisfaction.add(
+                        &Satisfaction::Complete {
+                            condition: Default::default(),
+                        },
+                        index,
+                    )?;
+                }
             }
             if let Some(psbt) = build_sat.psbt() {
                 if Ctx::find_signature(psbt, key, secp) {
@@ -769,7 +818,9 @@ fn make_generic_signature<M: Fn() -> SatisfiableItem, F: Fn(&Psbt) -> bool>(
         Satisfaction::None
     };
 
-    if let Some(psbt) = build_sat.psbt() {
+    if build_sat.assume_signed() {
+        policy.satisfaction = policy.contribution.clone();
+    } else if let Some(psbt) = build_sat.psbt() {
         policy.satisfaction = if find_sig(psbt) {
             Satisfaction::Complete {
                 condition: Default::default(),
@@ -942,6 +993,12 @@ impl<Ctx: ScriptContext + 'static> ExtractPolicy for Miniscript<DescriptorPublic
                         policy.satisfaction = policy.contribution.clone();
                     }
                 }
+                if let BuildSatisfaction::Timelocks { current_height, .. } = build_sat {
+                    let after = After::new(Some(current_height), false);
+                    if Satisfier::<bitcoin::PublicKey>::check_after(&after, (*value).into()) {
+                        policy.satisfaction = policy.contribution.clone();
+                    }
+                }
 
                 Some(policy)
             }
@@ -972,6 +1029,16 @@ impl<Ctx: ScriptContext + 'static> ExtractPolicy for Miniscript<DescriptorPublic
                         policy.satisfaction = policy.contribution.clone();
                     }
                 }
+                if let BuildSatisfaction::Timelocks {
+                    current_height,
+                    input_max_height: Some(input_max_height),
+                } = build_sat
+                {
+                    let older = Older::new(Some(current_height), Some(input_max_height), false);
+                    if Satisfier::<bitcoin::PublicKey>::check_older(&older, (*value).into()) {
+                        policy.satisfaction = policy.contribution.clone();
+                    }
+                }
 
                 Some(policy)
             }
@@ -1066,6 +1133,15 @@ pub enum BuildSatisfaction<'a> {
         /// CSV should consider different inputs, but we consider the worst condition for the tx as whole
         input_max_height: u32,
     },
+    /// Check for expired timelocks without a PSBT, considering every key the wallet can sign
+    /// with as signed: the `satisfaction` tells what the wallet can spend on its own
+    Timelocks {
+        /// Current blockchain height
+        current_height: u32,
+        /// The highest confirmation height between the coins to spend, `None` if they are not
+        /// confirmed and relative timelocks can't be satisfied
+        input_max_height: Option<u32>,
+    },
 }
 impl<'a> BuildSatisfaction<'a> {
     fn psbt(&self) -> Option<&'a Psbt> {
@@ -1073,8 +1149,14 @@ impl<'a> BuildSatisfaction<'a> {
             BuildSatisfaction::None => None,
             BuildSatisfaction::Psbt(psbt) => Some(psbt),
             BuildSatisfaction::PsbtTimelocks { psbt, .. } => Some(psbt),
+            BuildSatisfaction::Timelocks { .. } => None,
         }
     }
+
+    /// Whether the keys of the wallet are considered as signed
+    fn assume_signed(&self) -> bool {
+        matches!(self, BuildSatisfaction::Timelocks { .. })
+    }
 }
 
 impl ExtractPolicy for Descriptor<DescriptorPublicKey> {
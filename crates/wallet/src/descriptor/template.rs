@@ -14,6 +14,8 @@
 //! This module contains the definition of various common script templates that are ready to be
 //! used. See the documentation of each template for an example.
 
+use alloc::vec::Vec;
+
 use bitcoin::bip32;
 use bitcoin::Network;
 
@@ -492,6 +494,168 @@ impl<K: DerivableKey<Tap>> DescriptorTemplate for Bip86Public<K> {
     }
 }
 
+/// Script types of the [`Bip48`] and [`Bip48Public`] multisig templates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bip48ScriptType {
+    /// Nested segwit multisig, `sh(wsh(sortedmulti(...)))`, with script type `1'`
+    P2shP2wsh,
+    /// Native segwit multisig, `wsh(sortedmulti(...))`, with script type `2'`
+    P2wsh,
+}
+
+impl Bip48ScriptType {
+    /// The index of the script type in the BIP48 derivation path
+    pub fn index(&self) -> u32 {
+        match self {
+            Bip48ScriptType::P2shP2wsh => 1,
+            Bip48ScriptType::P2wsh => 2,
+        }
+    }
+}
+
+/// BIP48 multisig template. Expands to `wsh(sortedmulti(threshold, key/48'/{0,1}'/0'/2'/{0,1}/*, ...))`
+/// or, with [`Bip48ScriptType::P2shP2wsh`], to `sh(wsh(sortedmulti(threshold, key/48'/{0,1}'/0'/1'/{0,1}/*, ...)))`
+///
+/// Since there are hardened derivation steps, this template requires private derivable keys (generally `xprv`/`tprv`) for
+/// all the cosigners.
+///
+/// See [`Bip48Public`] for a template that can work with the `xpub`/`tpub` of the cosigners.
+///
+/// ## Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::{PrivateKey, Network};
+/// # use bdk_wallet::{Wallet,  KeychainKind};
+/// use bdk_wallet::template::{Bip48, Bip48ScriptType};
+///
+/// let key_a = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPeZRHk4rTG6orPS2CRNFX3njhUXx5vj9qGog5ZMH4uGReDWN5kCkY3jmWEtWause41CDvBRXD1shKknAMKxT99o9qUTRVC6m")?;
+/// let key_b = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy")?;
+/// let keys = vec![key_a, key_b];
+/// let mut wallet = Wallet::new(
+///     Bip48(1, keys.clone(), Bip48ScriptType::P2wsh, KeychainKind::External),
+///     Bip48(1, keys, Bip48ScriptType::P2wsh, KeychainKind::Internal),
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External).to_string(), "tb1q5vtnyltj9uzl08q4xef755r7hcg9xk22l2wdrk6rkcfkvy9dghlqlkg7r7");
+/// assert_eq!(wallet.public_descriptor(KeychainKind::External).to_string(), "wsh(sortedmulti(1,[c55b303f/48'/1'/0'/2']tpubDEU3eBekc59Yw68Nb3dmoXcinikF3qhGW9ymz39kMuBFSHFqX8MFuyt4mC3y9EiCWDmVw1rmQ3s7GjERKkARjGFwA2dAWRLpeMCU5oUuMXU/0/*,[34b00776/48'/1'/0'/2']tpubDE8WcdSH7SBJWrXiJYbWyLuYCoeFv925voAtzYssJsfXgi2WGA3kxMbdp1fP2zWX4sL14jXJyEPtTDjTAJfDrjpPZoTnK9UMcsgAbTD4c7W/0/*))#vkmtn24z");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct Bip48<K: DerivableKey<Segwitv0>>(
+    pub usize,
+    pub Vec<K>,
+    pub Bip48ScriptType,
+    pub KeychainKind,
+);
+
+impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip48<K> {
+    fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let mut derivation_path = bip48_account_path(self.2, network)?;
+        derivation_path.push(keychain_child(self.3)?);
+        let derivation_path: bip32::DerivationPath = derivation_path.into();
+
+        let keys = self
+            .1
+            .into_iter()
+            .map(|key| (key, derivation_path.clone()))
+            .collect::<Vec<_>>();
+        match self.2 {
+            Bip48ScriptType::P2shP2wsh => descriptor!(sh(wsh(sortedmulti_vec(self.0, keys)))),
+            Bip48ScriptType::P2wsh => descriptor!(wsh(sortedmulti_vec(self.0, keys))),
+        }
+    }
+}
+
+/// BIP48 public multisig template. Expands to `wsh(sortedmulti(threshold, key/{0,1}/*, ...))` or, with
+/// [`Bip48ScriptType::P2shP2wsh`], to `sh(wsh(sortedmulti(threshold, key/{0,1}/*, ...)))`
+///
+/// This assumes that the keys used have already been derived with `m/48'/0'/0'/{1,2}'` for Mainnet or
+/// `m/48'/1'/0'/{1,2}'` for Testnet.
+///
+/// This template requires the parent fingerprint of every key to populate correctly the metadata of PSBTs.
+///
+/// See [`Bip48`] for a template that does the full derivation, but requires private data
+/// for the keys.
+///
+/// ## Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::{PrivateKey, Network};
+/// # use bdk_wallet::{Wallet,  KeychainKind};
+/// use bdk_wallet::template::{Bip48Public, Bip48ScriptType};
+///
+/// let key_a = bitcoin::bip32::Xpub::from_str("tpubDEU3eBekc59Yw68Nb3dmoXcinikF3qhGW9ymz39kMuBFSHFqX8MFuyt4mC3y9EiCWDmVw1rmQ3s7GjERKkARjGFwA2dAWRLpeMCU5oUuMXU")?;
+/// let fingerprint_a = bitcoin::bip32::Fingerprint::from_str("c55b303f")?;
+/// let key_b = bitcoin::bip32::Xpub::from_str("tpubDE8WcdSH7SBJWrXiJYbWyLuYCoeFv925voAtzYssJsfXgi2WGA3kxMbdp1fP2zWX4sL14jXJyEPtTDjTAJfDrjpPZoTnK9UMcsgAbTD4c7W")?;
+/// let fingerprint_b = bitcoin::bip32::Fingerprint::from_str("34b00776")?;
+/// let keys = vec![(key_a, fingerprint_a), (key_b, fingerprint_b)];
+/// let mut wallet = Wallet::new(
+///     Bip48Public(1, keys.clone(), Bip48ScriptType::P2wsh, KeychainKind::External),
+///     Bip48Public(1, keys, Bip48ScriptType::P2wsh, KeychainKind::Internal),
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External).to_string(), "tb1q5vtnyltj9uzl08q4xef755r7hcg9xk22l2wdrk6rkcfkvy9dghlqlkg7r7");
+/// assert_eq!(wallet.public_descriptor(KeychainKind::External).to_string(), "wsh(sortedmulti(1,[c55b303f/48'/1'/0'/2']tpubDEU3eBekc59Yw68Nb3dmoXcinikF3qhGW9ymz39kMuBFSHFqX8MFuyt4mC3y9EiCWDmVw1rmQ3s7GjERKkARjGFwA2dAWRLpeMCU5oUuMXU/0/*,[34b00776/48'/1'/0'/2']tpubDE8WcdSH7SBJWrXiJYbWyLuYCoeFv925voAtzYssJsfXgi2WGA3kxMbdp1fP2zWX4sL14jXJyEPtTDjTAJfDrjpPZoTnK9UMcsgAbTD4c7W/0/*))#vkmtn24z");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct Bip48Public<K: DerivableKey<Segwitv0>>(
+    pub usize,
+    pub Vec<(K, bip32::Fingerprint)>,
+    pub Bip48ScriptType,
+    pub KeychainKind,
+);
+
+impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip48Public<K> {
+    fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let source_path: bip32::DerivationPath = bip48_account_path(self.2, network)?.into();
+        let derivation_path: bip32::DerivationPath = vec![keychain_child(self.3)?].into();
+
+        let keys = self
+            .1
+            .into_iter()
+            .map(|(key, fingerprint)| {
+                (
+                    key,
+                    (fingerprint, source_path.clone()),
+                    derivation_path.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        match self.2 {
+            Bip48ScriptType::P2shP2wsh => descriptor!(sh(wsh(sortedmulti_vec(self.0, keys)))),
+            Bip48ScriptType::P2wsh => descriptor!(wsh(sortedmulti_vec(self.0, keys))),
+        }
+    }
+}
+
+/// The `m/48'/{0,1}'/0'/{1,2}'` account path of BIP48
+fn bip48_account_path(
+    script_type: Bip48ScriptType,
+    network: Network,
+) -> Result<Vec<bip32::ChildNumber>, DescriptorError> {
+    Ok(vec![
+        bip32::ChildNumber::from_hardened_idx(48)?,
+        match network {
+            Network::Bitcoin => bip32::ChildNumber::from_hardened_idx(0)?,
+            _ => bip32::ChildNumber::from_hardened_idx(1)?,
+        },
+        bip32::ChildNumber::from_hardened_idx(0)?,
+        bip32::ChildNumber::from_hardened_idx(script_type.index())?,
+    ])
+}
+
+/// The BIP44 change level of `keychain`, extra keychains don't have one
+fn keychain_child(keychain: KeychainKind) -> Result<bip32::ChildNumber, DescriptorError> {
+    Ok(match keychain {
+        KeychainKind::External => bip32::ChildNumber::from_normal_idx(0)?,
+        KeychainKind::Internal => bip32::ChildNumber::from_normal_idx(1)?,
+        KeychainKind::Extra(_) => return Err(DescriptorError::InvalidHdKeyPath),
+    })
+}
+
 macro_rules! expand_make_bipxx {
     ( $mod_name:ident, $ctx:ty ) => {
         mod $mod_name {
@@ -516,14 +680,7 @@ macro_rules! expand_make_bipxx {
                 }
                 derivation_path.push(bip32::ChildNumber::from_hardened_idx(0)?);
 
-                match keychain {
-                    KeychainKind::External => {
-                        derivation_path.push(bip32::ChildNumber::from_normal_idx(0)?)
-                    }
-                    KeychainKind::Internal => {
-                        derivation_path.push(bip32::ChildNumber::from_normal_idx(1)?)
-                    }
-                };
+                derivation_path.push(keychain_child(keychain)?);
 
                 let derivation_path: bip32::DerivationPath = derivation_path.into();
 
@@ -536,10 +693,7 @@ macro_rules! expand_make_bipxx {
                 keychain: KeychainKind,
                 network: Network,
             ) -> Result<impl IntoDescriptorKey<$ctx>, DescriptorError> {
-                let derivation_path: bip32::DerivationPath = match keychain {
-                    KeychainKind::External => vec![bip32::ChildNumber::from_normal_idx(0)?].into(),
-                    KeychainKind::Internal => vec![bip32::ChildNumber::from_normal_idx(1)?].into(),
-                };
+                let derivation_path: bip32::DerivationPath = vec![keychain_child(keychain)?].into();
 
                 let source_path = bip32::DerivationPath::from(vec![
                     bip32::ChildNumber::from_hardened_idx(bip)?,
@@ -571,8 +725,9 @@ mod test {
     use crate::descriptor::{DescriptorError, DescriptorMeta};
     use crate::keys::ValidNetworks;
     use assert_matches::assert_matches;
+    use bitcoin::secp256k1::Secp256k1;
     use miniscript::descriptor::{DescriptorPublicKey, KeyMap};
-    use miniscript::Descriptor;
+    use miniscript::{Descriptor, ForEachKey};
 
     // BIP44 `pkh(key/44'/{0,1}'/0'/{0,1}/*)`
     #[test]
@@ -994,4 +1149,210 @@ mod test {
             ],
         );
     }
+
+    // Master keys of the "abandon abandon abandon abandon abandon abandon abandon abandon abandon
+    // abandon abandon about" mnemonic, used in the test vectors of BIP49, BIP84 and BIP86
+    const MAINNET_MASTER: &str = "xprv9s21ZrQH143K3GJpoapnV8SFfukcVBSfeCficPSGfubmSFDxo1kuHnLisriDvSnRRuL2Qrg5ggqHKNVpxR86QEC8w35uxmGoggxtQTPvfUu";
+    const TESTNET_MASTER: &str = "tprv8ZgxMBicQKsPe5YMU9gHen4Ez3ApihUfykaqUorj9t6FDqy3nP6eoXiAo2ssvpAjoLroQxHqr3R5nE3a5dU3DHTjTgJDd7zrbniJr6nrCzd";
+
+    // the expanded descriptors, with the network-correct coin type and their checksum
+    #[test]
+    fn test_bip_templates_descriptors() {
+        let mainnet = bitcoin::bip32::Xpriv::from_str(MAINNET_MASTER).unwrap();
+        let testnet = bitcoin::bip32::Xpriv::from_str(TESTNET_MASTER).unwrap();
+        let descriptor = |template: Result<DescriptorTemplateOut, DescriptorError>| {
+            template.unwrap().0.to_string()
+        };
+
+        for (template, expected) in [
+            (
+                Bip44(mainnet, KeychainKind::External).build(Network::Bitcoin),
+                "pkh([73c5da0a/44'/0'/0']xpub6BosfCnifzxcFwrSzQiqu2DBVTshkCXacvNsWGYJVVhhawA7d4R5WSWGFNbi8Aw6ZRc1brxMyWMzG3DSSSSoekkudhUd9yLb6qx39T9nMdj/0/*)#8w4z8fed",
+            ),
+            (
+                Bip49(mainnet, KeychainKind::External).build(Network::Bitcoin),
+                "sh(wpkh([73c5da0a/49'/0'/0']xpub6C6nQwHaWbSrzs5tZ1q7m5R9cPK9eYpNMFesiXsYrgc1P8bvLLAet9JfHjYXKjToD8cBRswJXXbbFpXgwsswVPAZzKMa1jUp2kVkGVUaJa7/0/*))#gvfpdstz",
+            ),
+            (
+                Bip84(mainnet, KeychainKind::External).build(Network::Bitcoin),
+                "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/0/*)#wc3n3van",
+            ),
+            (
+                Bip86(mainnet, KeychainKind::External).build(Network::Bitcoin),
+                "tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)#rg247h69",
+            ),
+            (
+                Bip86(mainnet, KeychainKind::Internal).build(Network::Bitcoin),
+                "tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/1/*)#ju05rz2a",
+            ),
+            (
+                Bip44(testnet, KeychainKind::External).build(Network::Testnet),
+                "pkh([73c5da0a/44'/1'/0']tpubDC5FSnBiZDMmhiuCmWAYsLwgLYrrT9rAqvTySfuCCrgsWz8wxMXUS9Tb9iVMvcRbvFcAHGkMD5Kx8koh4GquNGNTfohfk7pgjhaPCdXpoba/0/*)#sq4mwcyd",
+            ),
+            (
+                Bip49(testnet, KeychainKind::External).build(Network::Testnet),
+                "sh(wpkh([73c5da0a/49'/1'/0']tpubDD7tXK8KeQ3YY83yWq755fHY2JW8Ha8Q765tknUM5rSvjPcGWfUppDFMpQ1ScziKfW3ZNtZvAD7M3u7bSs7HofjTD3KP3YxPK7X6hwV8Rk2/0/*))#jpfa90p7",
+            ),
+            (
+                Bip84(testnet, KeychainKind::External).build(Network::Testnet),
+                "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/0/*)#2ag6nxcd",
+            ),
+            (
+                Bip86(testnet, KeychainKind::External).build(Network::Testnet),
+                "tr([73c5da0a/86'/1'/0']tpubDDfvzhdVV4unsoKt5aE6dcsNsfeWbTgmLZPi8LQDYU2xixrYemMfWJ3BaVneH3u7DBQePdTwhpybaKRU95pi6PMUtLPBJLVQRpzEnjfjZzX/0/*)#4smjh7d5",
+            ),
+            (
+                Bip86(testnet, KeychainKind::Internal).build(Network::Testnet),
+                "tr([73c5da0a/86'/1'/0']tpubDDfvzhdVV4unsoKt5aE6dcsNsfeWbTgmLZPi8LQDYU2xixrYemMfWJ3BaVneH3u7DBQePdTwhpybaKRU95pi6PMUtLPBJLVQRpzEnjfjZzX/1/*)#yy7n2tav",
+            ),
+        ] {
+            assert_eq!(descriptor(template), expected);
+        }
+
+        // the public templates expand to the same descriptors
+        let secp = Secp256k1::new();
+        let fingerprint = mainnet.fingerprint(&secp);
+        let account = |master: &bitcoin::bip32::Xpriv, path: &str| {
+            let path = bitcoin::bip32::DerivationPath::from_str(path).unwrap();
+            bitcoin::bip32::Xpub::from_priv(&secp, &master.derive_priv(&secp, &path).unwrap())
+        };
+        assert_eq!(
+            descriptor(
+                Bip44Public(
+                    account(&mainnet, "m/44'/0'/0'"),
+                    fingerprint,
+                    KeychainKind::External
+                )
+                .build(Network::Bitcoin)
+            ),
+            descriptor(Bip44(mainnet, KeychainKind::External).build(Network::Bitcoin))
+        );
+        assert_eq!(
+            descriptor(
+                Bip49Public(
+                    account(&testnet, "m/49'/1'/0'"),
+                    fingerprint,
+                    KeychainKind::Internal
+                )
+                .build(Network::Testnet)
+            ),
+            descriptor(Bip49(testnet, KeychainKind::Internal).build(Network::Testnet))
+        );
+        assert_eq!(
+            descriptor(
+                Bip84Public(
+                    account(&mainnet, "m/84'/0'/0'"),
+                    fingerprint,
+                    KeychainKind::Internal
+                )
+                .build(Network::Bitcoin)
+            ),
+            descriptor(Bip84(mainnet, KeychainKind::Internal).build(Network::Bitcoin))
+        );
+        assert_eq!(
+            descriptor(
+                Bip86Public(
+                    account(&testnet, "m/86'/1'/0'"),
+                    fingerprint,
+                    KeychainKind::External
+                )
+                .build(Network::Testnet)
+            ),
+            descriptor(Bip86(testnet, KeychainKind::External).build(Network::Testnet))
+        );
+    }
+
+    // BIP48 `wsh(sortedmulti(thresh, key/48'/{0,1}'/0'/2'/{0,1}/*, ...))` and
+    // `sh(wsh(sortedmulti(thresh, key/48'/{0,1}'/0'/1'/{0,1}/*, ...)))`
+    #[test]
+    fn test_bip48_template() {
+        let secp = Secp256k1::new();
+        let mainnet = bitcoin::bip32::Xpriv::from_str(MAINNET_MASTER).unwrap();
+        let mainnet_cosigner = bitcoin::bip32::Xpriv::from_str("xprv9s21ZrQH143K2fpbqApQL69a4oKdGVnVN52R82Ft7d1pSqgKmajF62acJo3aMszZb6qQ22QsVECSFxvf9uyxFUvFYQMq3QbtwtRSMjLAhMf").unwrap();
+        let testnet = bitcoin::bip32::Xpriv::from_str(TESTNET_MASTER).unwrap();
+        let testnet_cosigner = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
+
+        for (network, keys) in [
+            (Network::Bitcoin, [mainnet, mainnet_cosigner]),
+            (Network::Testnet, [testnet, testnet_cosigner]),
+        ] {
+            for script_type in [Bip48ScriptType::P2wsh, Bip48ScriptType::P2shP2wsh] {
+                for keychain in [KeychainKind::External, KeychainKind::Internal] {
+                    let (desc, key_map, _) = Bip48(2, keys.to_vec(), script_type, keychain)
+                        .build(network)
+                        .unwrap();
+                    assert_eq!(key_map.len(), 2);
+                    assert!(desc.is_witness());
+                    assert_matches!(
+                        (&desc, script_type),
+                        (Descriptor::Wsh(_), Bip48ScriptType::P2wsh)
+                            | (Descriptor::Sh(_), Bip48ScriptType::P2shP2wsh)
+                    );
+                    let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+                    assert!(desc.for_each_key(|key| {
+                        let path: Vec<_> = key.full_derivation_path().unwrap().into();
+                        path == [
+                            bip32::ChildNumber::from_hardened_idx(48).unwrap(),
+                            bip32::ChildNumber::from_hardened_idx(coin_type).unwrap(),
+                            bip32::ChildNumber::from_hardened_idx(0).unwrap(),
+                            bip32::ChildNumber::from_hardened_idx(script_type.index()).unwrap(),
+                            keychain_child(keychain).unwrap(),
+                        ]
+                    }));
+
+                    // the public template expands to the same descriptor
+                    let path = format!("m/48'/{}'/0'/{}'", coin_type, script_type.index());
+                    let path = bitcoin::bip32::DerivationPath::from_str(&path).unwrap();
+                    let public_keys = keys
+                        .iter()
+                        .map(|key| {
+                            let account = key.derive_priv(&secp, &path).unwrap();
+                            (
+                                bitcoin::bip32::Xpub::from_priv(&secp, &account),
+                                key.fingerprint(&secp),
+                            )
+                        })
+                        .collect();
+                    let (public_desc, key_map, _) =
+                        Bip48Public(2, public_keys, script_type, keychain)
+                            .build(network)
+                            .unwrap();
+                    assert!(key_map.is_empty());
+                    assert_eq!(public_desc, desc);
+                }
+            }
+        }
+
+        let (desc, _, _) = Bip48(
+            2,
+            vec![mainnet, mainnet_cosigner],
+            Bip48ScriptType::P2wsh,
+            KeychainKind::External,
+        )
+        .build(Network::Bitcoin)
+        .unwrap();
+        // the first key is the BIP48 account key commonly listed for the "abandon ... about" mnemonic
+        assert_eq!(desc.to_string(), "wsh(sortedmulti(2,[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf/0/*,[65af6f2e/48'/0'/0'/2']xpub6DkUn2RxmuiGC13w4YswSjoUrT9sB4z7TDgLtgJqiVopPhCTLzEndxHXyvhdqsqYrsDfFseSNUmZD2M57VXAL78vuHBFgZXzqYdbunLvkKM/0/*))#smc6gsgt");
+        let (desc, _, _) = Bip48(
+            1,
+            vec![testnet, testnet_cosigner],
+            Bip48ScriptType::P2shP2wsh,
+            KeychainKind::Internal,
+        )
+        .build(Network::Testnet)
+        .unwrap();
+        assert_eq!(desc.to_string(),  "sh(wsh(sortedmulti(1,[73c5da0a/48'/1'/0'/1']tpubDFH9dgzveyD8yHQb8VrpG8FYAuwcLMHMje2CCcbBo1FpaGzYVtJeYYxcYgRqSTta5utUFts8nPPHs9C2bqoxrey5jia6Dwf9mpwrPq7YvcJ/1/*,[34b00776/48'/1'/0'/1']tpubDE8WcdSH7SBJTb7o8gC4cyBgwsPnuPjEdiBFAJd4zvietipew6m4fofTEsgc5cQhcTnfGDsTQyvq8wTMtDLW5fbBnXXvqQhV8Meug5Cdp3F/1/*)))#ql34k3d8");
+
+        // the threshold can't be greater than the number of cosigners
+        assert_matches!(
+            Bip48(
+                3,
+                vec![testnet, testnet_cosigner],
+                Bip48ScriptType::P2wsh,
+                KeychainKind::External
+            )
+            .build(Network::Testnet),
+            Err(_)
+        );
+    }
 }
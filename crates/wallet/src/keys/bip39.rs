@@ -16,8 +16,10 @@
 
 use alloc::string::String;
 use bitcoin::bip32;
+use bitcoin::secp256k1::Secp256k1;
 use bitcoin::Network;
 
+use miniscript::descriptor::{DescriptorSecretKey, DescriptorXKey, Wildcard};
 use miniscript::ScriptContext;
 
 pub use bip39::{Error, Language, Mnemonic};
@@ -149,18 +151,79 @@ impl<Ctx: ScriptContext> GeneratableKey<Ctx> for Mnemonic {
     }
 }
 
+/// Extension trait to derive descriptor keys from a [`Mnemonic`]
+pub trait MnemonicExt {
+    /// Derive the extended private key at `origin_path` from the seed of the mnemonic and its
+    /// optional `passphrase`
+    ///
+    /// The key is returned as a [`DescriptorSecretKey`] with the fingerprint of the master key and
+    /// `origin_path` as its origin, and without any further derivation step or wildcard.
+    ///
+    /// Unlike the [`DerivableKey`] implementation of [`Mnemonic`], the key is encoded for
+    /// `network`: using it in a descriptor meant for a different network, or along with keys of a
+    /// different network, fails with [`KeyError::InvalidNetwork`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// use bdk_wallet::bitcoin::bip32::DerivationPath;
+    /// use bdk_wallet::bitcoin::Network;
+    /// use bdk_wallet::keys::bip39::{Mnemonic, MnemonicExt};
+    ///
+    /// let mnemonic = Mnemonic::parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about")?;
+    /// let path = DerivationPath::from_str("m/84'/0'/0'")?;
+    /// let key = mnemonic.to_descriptor_key(None, &path, Network::Bitcoin)?;
+    ///
+    /// assert_eq!(key.to_string(), "[73c5da0a/84'/0'/0']xprv9ybY78BftS5UGANki6oSifuQEjkpyAC8ZmBvBNTshQnCBcxnefjHS7buPMkkqhcRzmoGZ5bokx7GuyDAiktd5HemohAU4wV1ZPMDRmLpBMm");
+    /// # Ok::<_, Box<dyn std::error::Error>>(())
+    /// ```
+    fn to_descriptor_key(
+        &self,
+        passphrase: Option<&str>,
+        origin_path: &bip32::DerivationPath,
+        network: Network,
+    ) -> Result<DescriptorSecretKey, KeyError>;
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "keys-bip39")))]
+impl MnemonicExt for Mnemonic {
+    fn to_descriptor_key(
+        &self,
+        passphrase: Option<&str>,
+        origin_path: &bip32::DerivationPath,
+        network: Network,
+    ) -> Result<DescriptorSecretKey, KeyError> {
+        let secp = Secp256k1::new();
+        let seed: Seed = self.to_seed(passphrase.unwrap_or(""));
+        let master = bip32::Xpriv::new_master(network, &seed[..])?;
+        let xkey = master.derive_priv(&secp, origin_path)?;
+
+        Ok(DescriptorSecretKey::XPrv(DescriptorXKey {
+            origin: Some((master.fingerprint(&secp), origin_path.clone())),
+            xkey,
+            derivation_path: bip32::DerivationPath::default(),
+            wildcard: Wildcard::None,
+        }))
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use alloc::string::ToString;
+    use alloc::{string::ToString, vec, vec::Vec};
     use core::str::FromStr;
 
     use bitcoin::bip32;
 
     use bip39::{Language, Mnemonic};
 
-    use crate::keys::{any_network, GeneratableKey, GeneratedKey};
+    use crate::descriptor::{DescriptorError, IntoWalletDescriptor};
+    use crate::keys::{
+        any_network, DerivableKey, ExtendedKey, GeneratableKey, GeneratedKey, KeyError,
+    };
 
-    use super::WordCount;
+    use super::{MnemonicExt, WordCount};
+    use assert_matches::assert_matches;
 
     #[test]
     fn test_keys_bip39_mnemonic() {
@@ -224,4 +287,170 @@ mod test {
             Mnemonic::generate((WordCount::Words24, Language::English)).unwrap();
         assert_eq!(generated_mnemonic.valid_networks, any_network());
     }
+
+    // Test vectors of BIP39, from https://github.com/trezor/python-mnemonic/blob/master/vectors.json
+    #[test]
+    fn test_keys_bip39_vectors() {
+        use bitcoin::hex::FromHex;
+
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+                "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+                "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+                "xprv9s21ZrQH143K2gA81bYFHqU68xz1cX2APaSq5tt6MFSLeXnCKV1RVUJt9FWNTbrrryem4ZckN8k4Ls1H6nwdvDTvnV7zEXs2HgPezuVccsq",
+            ),
+            (
+                "80808080808080808080808080808080",
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+                "d71de856f81a8acc65e6fc851a38d4d7ec216fd0796d0a6827a3ad6ed5511a30fa280f12eb2e47ed2ac03b5c462a0358d18d69fe4f985ec81778c1b370b652a8",
+                "xprv9s21ZrQH143K2shfP28KM3nr5Ap1SXjz8gc2rAqqMEynmjt6o1qboCDpxckqXavCwdnYds6yBHZGKHv7ef2eTXy461PXUjBFQg6PrwY4Gzq",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+                "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069",
+                "xprv9s21ZrQH143K2V4oox4M8Zmhi2Fjx5XK4Lf7GKRvPSgydU3mjZuKGCTg7UPiBUD7ydVPvSLtg9hjp7MQTYsW67rZHAXeccqYqrsx8LcXnyd",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+                "bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd3097170af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8",
+                "xprv9s21ZrQH143K32qBagUJAMU2LsHg3ka7jqMcV98Y7gVeVyNStwYS3U7yVVoDZ4btbRNf4h6ibWpY22iRmXq35qgLs79f312g2kj5539ebPM",
+            ),
+        ];
+
+        for (entropy, phrase, seed, xprv) in vectors {
+            let entropy = Vec::<u8>::from_hex(entropy).unwrap();
+            let mnemonic = Mnemonic::from_entropy_in(Language::English, &entropy).unwrap();
+            assert_eq!(mnemonic.to_string(), phrase);
+            assert_eq!(
+                Mnemonic::parse_in(Language::English, phrase).unwrap(),
+                mnemonic
+            );
+            assert_eq!(
+                mnemonic.to_seed("TREZOR").to_vec(),
+                Vec::<u8>::from_hex(seed).unwrap()
+            );
+
+            let key: ExtendedKey<miniscript::Segwitv0> = (mnemonic, Some("TREZOR".into()))
+                .into_extended_key()
+                .unwrap();
+            assert_eq!(
+                key.into_xprv(bitcoin::Network::Bitcoin)
+                    .unwrap()
+                    .to_string(),
+                xprv
+            );
+        }
+    }
+
+    #[test]
+    fn test_keys_bip39_descriptor_key() {
+        let mnemonic =
+            "aim bunker wash balance finish force paper analyst cabin spoon stable organ";
+        let mnemonic = Mnemonic::parse_in(Language::English, mnemonic).unwrap();
+        let path = bip32::DerivationPath::from_str("m/44'/0'/0'").unwrap();
+
+        // same key as the one derived through the `DerivableKey` implementation
+        let key = mnemonic
+            .to_descriptor_key(None, &path, bitcoin::Network::Bitcoin)
+            .unwrap();
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        assert_eq!(key.to_public(&secp).unwrap().to_string(), "[be83839f/44'/0'/0']xpub6DCQ1YcqvZtSwGWMrwHELPehjWV3f2MGZ69yBADTxFEUAoLwb5Mp5GniQK6tTp3AgbngVz9zEFbBJUPVnkG7LFYt8QMTfbrNqs6FNEwAPKA");
+        let key = mnemonic
+            .to_descriptor_key(Some("passphrase"), &path, bitcoin::Network::Bitcoin)
+            .unwrap();
+        assert_eq!(key.to_public(&secp).unwrap().to_string(), "[8f6cb80c/44'/0'/0']xpub6DWYS8bbihFevy29M4cbw4ZR3P5E12jB8R88gBDWCTCNpYiDHhYWNywrCF9VZQYagzPmsZpxXpytzSoxynyeFr4ZyzheVjnpLKuse4fiwZw");
+
+        let path = bip32::DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let key = mnemonic
+            .to_descriptor_key(None, &path, bitcoin::Network::Testnet)
+            .unwrap();
+        assert!(key.to_string().starts_with("[be83839f/84'/1'/0'/0/0]tprv"));
+    }
+
+    #[test]
+    fn test_keys_bip39_descriptor_key_wrong_network() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let mnemonic =
+            "aim bunker wash balance finish force paper analyst cabin spoon stable organ";
+        let mnemonic = Mnemonic::parse_in(Language::English, mnemonic).unwrap();
+        let path = bip32::DerivationPath::from_str("m/84'/0'/0'").unwrap();
+
+        let mainnet_key = mnemonic
+            .to_descriptor_key(None, &path, bitcoin::Network::Bitcoin)
+            .unwrap();
+        let testnet_key = mnemonic
+            .to_descriptor_key(None, &path, bitcoin::Network::Testnet)
+            .unwrap();
+
+        let desc = crate::descriptor!(wpkh(mainnet_key.clone())).unwrap();
+        assert!(desc
+            .clone()
+            .into_wallet_descriptor(&secp, bitcoin::Network::Bitcoin)
+            .is_ok());
+        assert_matches!(
+            desc.into_wallet_descriptor(&secp, bitcoin::Network::Regtest),
+            Err(DescriptorError::Key(KeyError::InvalidNetwork))
+        );
+
+        // mixing keys of different networks leaves no valid network
+        let desc = crate::descriptor!(wsh(multi(1, mainnet_key, testnet_key))).unwrap();
+        assert!(desc.2.is_empty());
+        for network in [bitcoin::Network::Bitcoin, bitcoin::Network::Testnet] {
+            assert_matches!(
+                desc.clone().into_wallet_descriptor(&secp, network),
+                Err(DescriptorError::Key(KeyError::InvalidNetwork))
+            );
+        }
+    }
+
+    #[test]
+    fn test_keys_bip39_wallet_regtest() {
+        use crate::template::Bip86;
+        use crate::{KeychainKind, SignOptions, Wallet};
+        use bdk_chain::ConfirmationTime;
+        use bitcoin::{absolute, transaction, Amount, Transaction, TxOut};
+
+        let mnemonic = Mnemonic::parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap();
+        let mut wallet = Wallet::new(
+            Bip86(mnemonic.clone(), KeychainKind::External),
+            Bip86(mnemonic, KeychainKind::Internal),
+            bitcoin::Network::Regtest,
+        )
+        .unwrap();
+        assert!(wallet
+            .public_descriptor(KeychainKind::External)
+            .to_string()
+            .starts_with("tr([73c5da0a/86'/1'/0']tpub"));
+
+        let address = wallet.next_unused_address(KeychainKind::External);
+        assert!(address.to_string().starts_with("bcrt1p"));
+        let tx = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                script_pubkey: address.script_pubkey(),
+                value: Amount::from_sat(50_000),
+            }],
+        };
+        wallet
+            .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+            .unwrap();
+        assert_eq!(wallet.balance().total(), Amount::from_sat(50_000));
+
+        let mut builder = wallet.build_tx();
+        builder.drain_to(address.script_pubkey()).drain_wallet();
+        let mut psbt = builder.finish().unwrap();
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        assert!(psbt.extract_tx().is_ok());
+    }
 }
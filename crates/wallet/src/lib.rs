@@ -28,6 +28,7 @@ pub mod descriptor;
 pub mod keys;
 pub mod psbt;
 pub(crate) mod types;
+pub mod units;
 pub mod wallet;
 
 pub use descriptor::template;
@@ -0,0 +1,401 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Size management of PSBTs for air-gapped signers
+//!
+//! Signers communicating over QR codes can only handle small PSBTs. [`shrink_for_signing`] drops
+//! the fields the signer doesn't need, and [`split_per_input`] turns a PSBT into one PSBT per
+//! input, all sharing the same unsigned transaction. Once the signer returned them,
+//! [`rejoin`] checks that only the signatures of each part changed and merges them back.
+//!
+//! ```no_run
+//! # use bdk_wallet::bitcoin::{bip32::Fingerprint, Psbt};
+//! # use bdk_wallet::psbt::airgap::{rejoin, shrink_for_signing, split_per_input, FieldMask};
+//! # fn send_to_signer(parts: Vec<Psbt>) -> Vec<Psbt> { parts }
+//! # let mut psbt: Psbt = unimplemented!();
+//! # let fingerprint: Fingerprint = unimplemented!();
+//! let shrunk = shrink_for_signing(&psbt, FieldMask::NONE, fingerprint);
+//! let parts = split_per_input(&shrunk)?;
+//! let signed = rejoin(&shrunk, &send_to_signer(parts))?;
+//! // put the signatures back into the full PSBT
+//! psbt.combine(signed)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{BitOr, BitOrAssign};
+
+use bitcoin::bip32::Fingerprint;
+use bitcoin::psbt::{Input, PsbtSighashType};
+use bitcoin::{EcdsaSighashType, Psbt, ScriptBuf, TapSighashType};
+
+use super::PsbtUtils;
+
+/// The optional fields [`shrink_for_signing`] keeps
+///
+/// Masks are combined with `|`, the fields which aren't part of the mask are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FieldMask(u8);
+
+impl FieldMask {
+    /// Drop all the optional fields
+    pub const NONE: Self = Self(0);
+    /// The previous transactions of the segwit inputs
+    ///
+    /// The previous transactions of the legacy inputs are always kept, they are needed to sign
+    /// them. Some signers also require them for segwit v0 inputs, to verify their amounts.
+    pub const NON_WITNESS_UTXO: Self = Self(1);
+    /// The key origins of the inputs which have none from the signer
+    pub const FOREIGN_DERIVATIONS: Self = Self(1 << 1);
+    /// The extended public keys of the global map
+    pub const GLOBAL_XPUBS: Self = Self(1 << 2);
+    /// The proprietary fields of all the maps
+    pub const PROPRIETARY: Self = Self(1 << 3);
+    /// The unknown fields of all the maps
+    pub const UNKNOWN: Self = Self(1 << 4);
+    /// Keep all the fields
+    pub const ALL: Self = Self(0b1_1111);
+
+    /// Whether all the fields of `other` are part of the mask
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for FieldMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for FieldMask {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0
+    }
+}
+
+/// Error of [`split_per_input`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    /// The input isn't signed with `SIGHASH_ALL`, so its signature doesn't commit to the whole
+    /// transaction, and the other parts could be changed without invalidating it
+    UnsafeSighash {
+        /// The index of the input
+        index: usize,
+        /// The sighash type of the input
+        sighash_type: PsbtSighashType,
+    },
+    /// The previous output of the input is unknown
+    MissingUtxo {
+        /// The index of the input
+        index: usize,
+    },
+    /// A legacy input doesn't have the previous transaction its signer needs
+    MissingNonWitnessUtxo {
+        /// The index of the input
+        index: usize,
+    },
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsafeSighash {
+                index,
+                sighash_type,
+            } => write!(
+                f,
+                "input {} uses sighash {}, only SIGHASH_ALL inputs can be signed separately",
+                index, sighash_type
+            ),
+            Self::MissingUtxo { index } => {
+                write!(f, "the previous output of input {} is unknown", index)
+            }
+            Self::MissingNonWitnessUtxo { index } => write!(
+                f,
+                "legacy input {} is missing its previous transaction",
+                index
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SplitError {}
+
+/// Error of [`rejoin`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejoinError {
+    /// The PSBT can't be split, see [`split_per_input`]
+    Split(SplitError),
+    /// The number of parts isn't the number of inputs of the PSBT
+    PartCount {
+        /// The number of inputs of the PSBT
+        expected: usize,
+        /// The number of parts
+        got: usize,
+    },
+    /// The unsigned transaction of a part isn't the one of the PSBT
+    TxMismatch {
+        /// The index of the part
+        part: usize,
+    },
+    /// A part changed something else than the signatures of its input
+    UnexpectedChange {
+        /// The index of the part
+        part: usize,
+    },
+}
+
+impl fmt::Display for RejoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Split(e) => write!(f, "{}", e),
+            Self::PartCount { expected, got } => {
+                write!(f, "expected {} parts, one per input, got {}", expected, got)
+            }
+            Self::TxMismatch { part } => write!(
+                f,
+                "the unsigned transaction of part {} doesn't match the PSBT",
+                part
+            ),
+            Self::UnexpectedChange { part } => write!(
+                f,
+                "part {} changed something else than the signatures of its input",
+                part
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RejoinError {}
+
+impl From<SplitError> for RejoinError {
+    fn from(e: SplitError) -> Self {
+        Self::Split(e)
+    }
+}
+
+/// Drop the fields of `psbt` which the signer of `fingerprint` doesn't need, except those of
+/// `keep`
+///
+/// The previous transaction of a segwit input is replaced by its spent output, the previous
+/// transactions of the legacy inputs are always kept. The outputs and the key origins of the
+/// inputs of the signer are always kept, the signer needs them to sign and to recognize its
+/// change.
+pub fn shrink_for_signing(psbt: &Psbt, keep: FieldMask, fingerprint: Fingerprint) -> Psbt {
+    let mut psbt = psbt.clone();
+    if !keep.contains(FieldMask::GLOBAL_XPUBS) {
+        psbt.xpub.clear();
+    }
+    if !keep.contains(FieldMask::PROPRIETARY) {
+        psbt.proprietary.clear();
+        psbt.outputs.iter_mut().for_each(|o| o.proprietary.clear());
+    }
+    if !keep.contains(FieldMask::UNKNOWN) {
+        psbt.unknown.clear();
+        psbt.outputs.iter_mut().for_each(|o| o.unknown.clear());
+    }
+
+    for index in 0..psbt.inputs.len() {
+        let utxo = psbt.get_utxo_for(index);
+        let input = &mut psbt.inputs[index];
+        if !keep.contains(FieldMask::NON_WITNESS_UTXO) && input.non_witness_utxo.is_some() {
+            if let Some(utxo) = utxo.filter(|utxo| !is_legacy(input, &utxo.script_pubkey)) {
+                input.witness_utxo = Some(utxo);
+                input.non_witness_utxo = None;
+            }
+        }
+        if !keep.contains(FieldMask::FOREIGN_DERIVATIONS) && !has_origin_of(input, fingerprint) {
+            input.bip32_derivation.clear();
+            input.tap_key_origins.clear();
+        }
+        if !keep.contains(FieldMask::PROPRIETARY) {
+            input.proprietary.clear();
+        }
+        if !keep.contains(FieldMask::UNKNOWN) {
+            input.unknown.clear();
+        }
+    }
+    psbt
+}
+
+/// Split `psbt` into one PSBT per input, in the order of the inputs
+///
+/// All the parts have the unsigned transaction, the global fields and the outputs of `psbt`.
+/// In the part of an input, the other inputs only keep their previous outputs, which the
+/// signature hash of taproot inputs commits to, so the part of an input can only be used to
+/// sign that input.
+///
+/// Inputs not signed with `SIGHASH_ALL` are rejected, their signatures don't commit to the
+/// parts of the transaction the other parts could change.
+pub fn split_per_input(psbt: &Psbt) -> Result<Vec<Psbt>, SplitError> {
+    let utxos = (0..psbt.inputs.len())
+        .map(|index| {
+            let input = &psbt.inputs[index];
+            if let Some(sighash_type) = input.sighash_type {
+                if !is_sighash_all(sighash_type) {
+                    return Err(SplitError::UnsafeSighash {
+                        index,
+                        sighash_type,
+                    });
+                }
+            }
+            let utxo = psbt
+                .get_utxo_for(index)
+                .ok_or(SplitError::MissingUtxo { index })?;
+            if input.non_witness_utxo.is_none() && is_legacy(input, &utxo.script_pubkey) {
+                return Err(SplitError::MissingNonWitnessUtxo { index });
+            }
+            Ok(utxo)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((0..psbt.inputs.len())
+        .map(|index| {
+            let is_taproot = utxos[index].script_pubkey.is_p2tr();
+            let mut part = psbt.clone();
+            for (other, input) in part.inputs.iter_mut().enumerate() {
+                if other != index {
+                    *input = stripped_input(input, is_taproot);
+                }
+            }
+            part
+        })
+        .collect())
+}
+
+/// Merge the signatures of the `parts` of `psbt` returned by the signer
+///
+/// `psbt` is the PSBT given to [`split_per_input`]. Each part must only differ from the one
+/// [`split_per_input`] made by the signatures of its input: partial signatures, taproot
+/// signatures and finalized scripts. Use [`Psbt::combine`] to add the signatures to the PSBT
+/// `psbt` was shrunk from.
+pub fn rejoin(psbt: &Psbt, parts: &[Psbt]) -> Result<Psbt, RejoinError> {
+    let expected_parts = split_per_input(psbt)?;
+    if parts.len() != expected_parts.len() {
+        return Err(RejoinError::PartCount {
+            expected: expected_parts.len(),
+            got: parts.len(),
+        });
+    }
+
+    let mut psbt = psbt.clone();
+    for (index, (part, expected)) in parts.iter().zip(expected_parts).enumerate() {
+        if part.unsigned_tx != expected.unsigned_tx {
+            return Err(RejoinError::TxMismatch { part: index });
+        }
+        let mut unsigned = part.clone();
+        let signed = core::mem::take(&mut unsigned.inputs[index]);
+        unsigned.inputs[index] = without_signatures(&signed);
+        let mut expected = expected;
+        expected.inputs[index] = without_signatures(&expected.inputs[index]);
+        if unsigned != expected {
+            return Err(RejoinError::UnexpectedChange { part: index });
+        }
+
+        let input = &mut psbt.inputs[index];
+        input.partial_sigs.extend(signed.partial_sigs);
+        input.tap_script_sigs.extend(signed.tap_script_sigs);
+        if signed.tap_key_sig.is_some() {
+            input.tap_key_sig = signed.tap_key_sig;
+        }
+        if signed.final_script_sig.is_some() {
+            input.final_script_sig = signed.final_script_sig;
+        }
+        if signed.final_script_witness.is_some() {
+            input.final_script_witness = signed.final_script_witness;
+        }
+    }
+    Ok(psbt)
+}
+
+/// Whether `input`, spending `script_pubkey`, is signed with the legacy signature hash
+fn is_legacy(input: &Input, script_pubkey: &ScriptBuf) -> bool {
+    if script_pubkey.is_p2sh() {
+        !input
+            .redeem_script
+            .as_ref()
+            .map_or(false, |script| script.is_witness_program())
+    } else {
+        !script_pubkey.is_witness_program()
+    }
+}
+
+fn has_origin_of(input: &Input, fingerprint: Fingerprint) -> bool {
+    input
+        .bip32_derivation
+        .values()
+        .any(|(origin, _)| *origin == fingerprint)
+        || input
+            .tap_key_origins
+            .values()
+            .any(|(_, (origin, _))| *origin == fingerprint)
+}
+
+fn is_sighash_all(sighash_type: PsbtSighashType) -> bool {
+    match sighash_type.taproot_hash_ty() {
+        Ok(TapSighashType::Default | TapSighashType::All) => true,
+        Ok(_) => false,
+        Err(_) => sighash_type.ecdsa_hash_ty() == Ok(EcdsaSighashType::All),
+    }
+}
+
+/// The input of another part: only its previous output, for the taproot signature hash
+fn stripped_input(input: &Input, is_taproot: bool) -> Input {
+    Input {
+        witness_utxo: input.witness_utxo.clone(),
+        non_witness_utxo: if is_taproot && input.witness_utxo.is_none() {
+            input.non_witness_utxo.clone()
+        } else {
+            None
+        },
+        ..Default::default()
+    }
+}
+
+fn without_signatures(input: &Input) -> Input {
+    Input {
+        partial_sigs: Default::default(),
+        tap_key_sig: None,
+        tap_script_sigs: Default::default(),
+        final_script_sig: None,
+        final_script_witness: None,
+        ..input.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_field_mask() {
+        let mask = FieldMask::NON_WITNESS_UTXO | FieldMask::UNKNOWN;
+        assert!(mask.contains(FieldMask::UNKNOWN));
+        assert!(!mask.contains(FieldMask::GLOBAL_XPUBS));
+        assert!(mask.contains(FieldMask::NONE));
+        assert!(FieldMask::ALL.contains(mask | FieldMask::FOREIGN_DERIVATIONS));
+    }
+
+    #[test]
+    fn test_is_sighash_all() {
+        assert!(is_sighash_all(TapSighashType::Default.into()));
+        assert!(is_sighash_all(EcdsaSighashType::All.into()));
+        assert!(!is_sighash_all(
+            TapSighashType::SinglePlusAnyoneCanPay.into()
+        ));
+        assert!(!is_sighash_all(EcdsaSighashType::None.into()));
+    }
+}
@@ -17,6 +17,11 @@ use bitcoin::FeeRate;
 use bitcoin::Psbt;
 use bitcoin::TxOut;
 
+pub mod airgap;
+#[cfg(feature = "psbt-v2")]
+#[cfg_attr(docsrs, doc(cfg(feature = "psbt-v2")))]
+pub mod v2;
+
 // TODO upstream the functions here to `rust-bitcoin`?
 
 /// Trait to add functions to extract utxos and calculate fees.
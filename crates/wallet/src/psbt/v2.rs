@@ -0,0 +1,819 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! PSBT version 2, per [`BIP370`]
+//!
+//! A PSBT v2 has no unsigned transaction: the previous output and sequence of each input, and the
+//! amount and script of each output, are fields of their own maps, so that inputs and outputs can
+//! be added to the PSBT after its creation.
+//!
+//! `rust-bitcoin` only supports PSBT v0, so [`PsbtV2`] keeps the fields which are common to both
+//! versions in the [`Input`] and [`Output`] of `rust-bitcoin`, and only (de)serializes the
+//! fields which are specific to v2 itself. A [`PsbtV2`] is signed with
+//! [`Wallet::sign_psbt_v2`], over the transaction of [`PsbtV2::unsigned_tx`], so its signatures
+//! are the ones of the equivalent v0 PSBT.
+//!
+//! [`BIP370`]: https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki
+//! [`Wallet::sign_psbt_v2`]: crate::Wallet::sign_psbt_v2
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{fmt, str::FromStr};
+
+use bitcoin::base64::prelude::{Engine as _, BASE64_STANDARD};
+use bitcoin::bip32::{KeySource, Xpub};
+use bitcoin::consensus::encode::{self, VarInt};
+use bitcoin::psbt::{self, raw, Input, Output, Psbt};
+use bitcoin::{
+    absolute, transaction, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+
+/// The version of a PSBT v2
+pub const PSBT_V2_VERSION: u32 = 2;
+
+/// The magic bytes and separator starting every PSBT
+const MAGIC: &[u8] = b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u64 = 0x00;
+const PSBT_GLOBAL_TX_VERSION: u64 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u64 = 0x03;
+const PSBT_GLOBAL_INPUT_COUNT: u64 = 0x04;
+const PSBT_GLOBAL_OUTPUT_COUNT: u64 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u64 = 0x06;
+const PSBT_GLOBAL_VERSION: u64 = 0xfb;
+
+const PSBT_IN_PREVIOUS_TXID: u64 = 0x0e;
+const PSBT_IN_OUTPUT_INDEX: u64 = 0x0f;
+const PSBT_IN_SEQUENCE: u64 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u64 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u64 = 0x12;
+
+const PSBT_OUT_AMOUNT: u64 = 0x03;
+const PSBT_OUT_SCRIPT: u64 = 0x04;
+
+/// The global fields of a PSBT v2 which are excluded from a PSBT v0, with their names
+const V2_GLOBAL_FIELDS: &[(u64, &str)] = &[
+    (PSBT_GLOBAL_TX_VERSION, "PSBT_GLOBAL_TX_VERSION"),
+    (
+        PSBT_GLOBAL_FALLBACK_LOCKTIME,
+        "PSBT_GLOBAL_FALLBACK_LOCKTIME",
+    ),
+    (PSBT_GLOBAL_INPUT_COUNT, "PSBT_GLOBAL_INPUT_COUNT"),
+    (PSBT_GLOBAL_OUTPUT_COUNT, "PSBT_GLOBAL_OUTPUT_COUNT"),
+    (PSBT_GLOBAL_TX_MODIFIABLE, "PSBT_GLOBAL_TX_MODIFIABLE"),
+];
+
+/// The input fields of a PSBT v2 which are excluded from a PSBT v0, with their names
+const V2_INPUT_FIELDS: &[(u64, &str)] = &[
+    (PSBT_IN_PREVIOUS_TXID, "PSBT_IN_PREVIOUS_TXID"),
+    (PSBT_IN_OUTPUT_INDEX, "PSBT_IN_OUTPUT_INDEX"),
+    (PSBT_IN_SEQUENCE, "PSBT_IN_SEQUENCE"),
+    (
+        PSBT_IN_REQUIRED_TIME_LOCKTIME,
+        "PSBT_IN_REQUIRED_TIME_LOCKTIME",
+    ),
+    (
+        PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+        "PSBT_IN_REQUIRED_HEIGHT_LOCKTIME",
+    ),
+];
+
+/// The output fields of a PSBT v2 which are excluded from a PSBT v0, with their names
+const V2_OUTPUT_FIELDS: &[(u64, &str)] = &[
+    (PSBT_OUT_AMOUNT, "PSBT_OUT_AMOUNT"),
+    (PSBT_OUT_SCRIPT, "PSBT_OUT_SCRIPT"),
+];
+
+/// A partially signed transaction of version 2, see the [module-level documentation](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsbtV2 {
+    /// The version of the transaction
+    pub tx_version: transaction::Version,
+    /// The locktime of the transaction if none of the inputs requires one
+    ///
+    /// A missing fallback locktime is a locktime of zero.
+    pub fallback_lock_time: Option<absolute::LockTime>,
+    /// The flags of the parts of the transaction which can still be modified, see
+    /// [`PsbtV2::INPUTS_MODIFIABLE`], [`PsbtV2::OUTPUTS_MODIFIABLE`] and
+    /// [`PsbtV2::HAS_SIGHASH_SINGLE`]
+    pub tx_modifiable: Option<u8>,
+    /// The extended public keys of the wallets of the inputs and outputs
+    pub xpub: BTreeMap<Xpub, KeySource>,
+    /// The global proprietary key-value pairs
+    pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
+    /// The global key-value pairs of unknown types
+    pub unknown: BTreeMap<raw::Key, Vec<u8>>,
+    /// The inputs of the transaction
+    pub inputs: Vec<InputV2>,
+    /// The outputs of the transaction
+    pub outputs: Vec<OutputV2>,
+}
+
+/// An input of a [`PsbtV2`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputV2 {
+    /// The txid of the transaction of the spent output
+    pub previous_txid: Txid,
+    /// The index of the spent output in its transaction
+    pub output_index: u32,
+    /// The sequence of the input, [`Sequence::MAX`] if it's missing
+    pub sequence: Option<Sequence>,
+    /// The time-based locktime the transaction must have to spend this input
+    pub required_time_lock_time: Option<absolute::Time>,
+    /// The height-based locktime the transaction must have to spend this input
+    pub required_height_lock_time: Option<absolute::Height>,
+    /// The fields of the input which are common to PSBT v0
+    pub input: Input,
+}
+
+impl InputV2 {
+    /// The spent output
+    pub fn previous_output(&self) -> OutPoint {
+        OutPoint::new(self.previous_txid, self.output_index)
+    }
+}
+
+/// An output of a [`PsbtV2`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputV2 {
+    /// The amount of the output
+    pub amount: Amount,
+    /// The script pubkey of the output
+    pub script_pubkey: ScriptBuf,
+    /// The fields of the output which are common to PSBT v0
+    pub output: Output,
+}
+
+impl PsbtV2 {
+    /// Flag of [`PsbtV2::tx_modifiable`]: inputs can be added or removed
+    pub const INPUTS_MODIFIABLE: u8 = 0x01;
+    /// Flag of [`PsbtV2::tx_modifiable`]: outputs can be added or removed
+    pub const OUTPUTS_MODIFIABLE: u8 = 0x02;
+    /// Flag of [`PsbtV2::tx_modifiable`]: an input has a `SIGHASH_SINGLE` signature, whose output
+    /// must keep its index
+    pub const HAS_SIGHASH_SINGLE: u8 = 0x04;
+
+    /// Convert a PSBT v0 to a PSBT v2
+    ///
+    /// The locktime of the transaction becomes the fallback locktime, so the conversion is
+    /// lossless: [`to_v0`](Self::to_v0) returns `psbt` back.
+    pub fn from_v0(psbt: Psbt) -> Result<Self, PsbtV2Error> {
+        if psbt.version != 0 {
+            return Err(PsbtV2Error::UnsupportedVersion(psbt.version));
+        }
+        check_excluded(psbt.unknown.keys(), V2_GLOBAL_FIELDS)?;
+        for input in &psbt.inputs {
+            check_excluded(input.unknown.keys(), V2_INPUT_FIELDS)?;
+        }
+        for output in &psbt.outputs {
+            check_excluded(output.unknown.keys(), V2_OUTPUT_FIELDS)?;
+        }
+        let tx = psbt.unsigned_tx;
+        if tx.input.len() != psbt.inputs.len() || tx.output.len() != psbt.outputs.len() {
+            return Err(PsbtV2Error::InvalidField("PSBT_GLOBAL_UNSIGNED_TX"));
+        }
+
+        Ok(Self {
+            tx_version: tx.version,
+            fallback_lock_time: Some(tx.lock_time),
+            tx_modifiable: None,
+            xpub: psbt.xpub,
+            proprietary: psbt.proprietary,
+            unknown: psbt.unknown,
+            inputs: tx
+                .input
+                .into_iter()
+                .zip(psbt.inputs)
+                .map(|(txin, input)| InputV2 {
+                    previous_txid: txin.previous_output.txid,
+                    output_index: txin.previous_output.vout,
+                    sequence: Some(txin.sequence),
+                    required_time_lock_time: None,
+                    required_height_lock_time: None,
+                    input,
+                })
+                .collect(),
+            outputs: tx
+                .output
+                .into_iter()
+                .zip(psbt.outputs)
+                .map(|(txout, output)| OutputV2 {
+                    amount: txout.value,
+                    script_pubkey: txout.script_pubkey,
+                    output,
+                })
+                .collect(),
+        })
+    }
+
+    /// Convert this PSBT to a PSBT v0
+    ///
+    /// Fails with [`PsbtV2Error::Lossy`] if the PSBT has a field which a PSBT v0 can't keep, that
+    /// is [`tx_modifiable`](Self::tx_modifiable) or a required locktime of an input.
+    pub fn to_v0(&self) -> Result<Psbt, PsbtV2Error> {
+        if self.tx_modifiable.is_some() {
+            return Err(PsbtV2Error::Lossy("PSBT_GLOBAL_TX_MODIFIABLE"));
+        }
+        for input in &self.inputs {
+            if input.required_time_lock_time.is_some() {
+                return Err(PsbtV2Error::Lossy("PSBT_IN_REQUIRED_TIME_LOCKTIME"));
+            }
+            if input.required_height_lock_time.is_some() {
+                return Err(PsbtV2Error::Lossy("PSBT_IN_REQUIRED_HEIGHT_LOCKTIME"));
+            }
+        }
+        self.as_v0()
+    }
+
+    /// The PSBT v0 of the transaction of this PSBT, dropping the fields which only exist in v2
+    pub(crate) fn as_v0(&self) -> Result<Psbt, PsbtV2Error> {
+        Ok(self.v0_with_tx(self.unsigned_tx()?))
+    }
+
+    /// The transaction of this PSBT with `lock_time`, without any signature
+    fn tx_with_lock_time(&self, lock_time: absolute::LockTime) -> Transaction {
+        Transaction {
+            version: self.tx_version,
+            lock_time,
+            input: self
+                .inputs
+                .iter()
+                .map(|input| TxIn {
+                    previous_output: input.previous_output(),
+                    script_sig: ScriptBuf::new(),
+                    sequence: input.sequence.unwrap_or(Sequence::MAX),
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: self
+                .outputs
+                .iter()
+                .map(|output| TxOut {
+                    value: output.amount,
+                    script_pubkey: output.script_pubkey.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn v0_with_tx(&self, unsigned_tx: Transaction) -> Psbt {
+        Psbt {
+            unsigned_tx,
+            version: 0,
+            xpub: self.xpub.clone(),
+            proprietary: self.proprietary.clone(),
+            unknown: self.unknown.clone(),
+            inputs: self
+                .inputs
+                .iter()
+                .map(|input| input.input.clone())
+                .collect(),
+            outputs: self
+                .outputs
+                .iter()
+                .map(|output| output.output.clone())
+                .collect(),
+        }
+    }
+
+    /// Update the fields common to both versions from `psbt`, the result of signing or finalizing
+    /// the PSBT v0 of [`as_v0`](Self::as_v0)
+    pub(crate) fn update_from_v0(&mut self, psbt: Psbt) {
+        debug_assert_eq!(psbt.inputs.len(), self.inputs.len());
+        debug_assert_eq!(psbt.outputs.len(), self.outputs.len());
+        for (input, v0) in self.inputs.iter_mut().zip(psbt.inputs) {
+            input.input = v0;
+        }
+        for (output, v0) in self.outputs.iter_mut().zip(psbt.outputs) {
+            output.output = v0;
+        }
+        self.xpub = psbt.xpub;
+        self.proprietary = psbt.proprietary;
+        self.unknown = psbt.unknown;
+    }
+
+    /// The locktime of the transaction, as determined by [`BIP370`]
+    ///
+    /// If no input requires a locktime, this is the fallback locktime. Otherwise it's the
+    /// greatest required locktime of the type which all the inputs requiring a locktime support,
+    /// preferring heights if they support both.
+    ///
+    /// [`BIP370`]: https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki
+    pub fn lock_time(&self) -> Result<absolute::LockTime, PsbtV2Error> {
+        let requiring = self.inputs.iter().filter(|input| {
+            input.required_time_lock_time.is_some() || input.required_height_lock_time.is_some()
+        });
+        let mut any_required = false;
+        let mut all_height = true;
+        let mut all_time = true;
+        let mut max_height = None;
+        let mut max_time = None;
+        for input in requiring {
+            any_required = true;
+            all_height &= input.required_height_lock_time.is_some();
+            all_time &= input.required_time_lock_time.is_some();
+            max_height = max_height.max(input.required_height_lock_time);
+            max_time = max_time.max(input.required_time_lock_time);
+        }
+        if !any_required {
+            return Ok(self.fallback_lock_time.unwrap_or(absolute::LockTime::ZERO));
+        }
+        match (all_height, all_time, max_height, max_time) {
+            (true, _, Some(height), _) => Ok(absolute::LockTime::Blocks(height)),
+            (false, true, _, Some(time)) => Ok(absolute::LockTime::Seconds(time)),
+            _ => Err(PsbtV2Error::LockTimeConflict),
+        }
+    }
+
+    /// The transaction of this PSBT, without any signature
+    pub fn unsigned_tx(&self) -> Result<Transaction, PsbtV2Error> {
+        Ok(self.tx_with_lock_time(self.lock_time()?))
+    }
+
+    /// Serialize the PSBT to its binary format
+    pub fn serialize(&self) -> Vec<u8> {
+        // the fields common to both versions are serialized by rust-bitcoin, through a PSBT v0
+        // whose unsigned transaction is then replaced by the fields of v2, so its locktime
+        // doesn't matter
+        let v0 = self
+            .v0_with_tx(self.tx_with_lock_time(absolute::LockTime::ZERO))
+            .serialize();
+        let mut maps = RawPsbt::parse(&v0, |_| Ok((self.inputs.len(), self.outputs.len())))
+            .expect("rust-bitcoin must serialize a valid PSBT v0");
+
+        maps.global
+            .retain(|(key, _)| !matches!(key_type(key), Ok(PSBT_GLOBAL_UNSIGNED_TX)));
+        maps.global.extend([
+            field(PSBT_GLOBAL_VERSION, PSBT_V2_VERSION.to_le_bytes().to_vec()),
+            field(PSBT_GLOBAL_TX_VERSION, encode::serialize(&self.tx_version)),
+            field(
+                PSBT_GLOBAL_INPUT_COUNT,
+                encode::serialize(&VarInt(self.inputs.len() as u64)),
+            ),
+            field(
+                PSBT_GLOBAL_OUTPUT_COUNT,
+                encode::serialize(&VarInt(self.outputs.len() as u64)),
+            ),
+        ]);
+        if let Some(lock_time) = self.fallback_lock_time {
+            maps.global.push(field(
+                PSBT_GLOBAL_FALLBACK_LOCKTIME,
+                encode::serialize(&lock_time),
+            ));
+        }
+        if let Some(flags) = self.tx_modifiable {
+            maps.global
+                .push(field(PSBT_GLOBAL_TX_MODIFIABLE, alloc::vec![flags]));
+        }
+
+        for (map, input) in maps.inputs.iter_mut().zip(&self.inputs) {
+            map.extend([
+                field(
+                    PSBT_IN_PREVIOUS_TXID,
+                    encode::serialize(&input.previous_txid),
+                ),
+                field(
+                    PSBT_IN_OUTPUT_INDEX,
+                    input.output_index.to_le_bytes().to_vec(),
+                ),
+            ]);
+            if let Some(sequence) = input.sequence {
+                map.push(field(PSBT_IN_SEQUENCE, encode::serialize(&sequence)));
+            }
+            if let Some(time) = input.required_time_lock_time {
+                map.push(field(
+                    PSBT_IN_REQUIRED_TIME_LOCKTIME,
+                    time.to_consensus_u32().to_le_bytes().to_vec(),
+                ));
+            }
+            if let Some(height) = input.required_height_lock_time {
+                map.push(field(
+                    PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+                    height.to_consensus_u32().to_le_bytes().to_vec(),
+                ));
+            }
+        }
+
+        for (map, output) in maps.outputs.iter_mut().zip(&self.outputs) {
+            map.extend([
+                field(PSBT_OUT_AMOUNT, encode::serialize(&output.amount)),
+                field(PSBT_OUT_SCRIPT, output.script_pubkey.to_bytes()),
+            ]);
+        }
+
+        maps.serialize()
+    }
+
+    /// Deserialize a PSBT v2 from its binary format
+    ///
+    /// A PSBT v0 is rejected with [`PsbtV2Error::UnsupportedVersion`], it is deserialized with
+    /// [`Psbt::deserialize`] and converted with [`PsbtV2::from_v0`].
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, PsbtV2Error> {
+        let mut maps = RawPsbt::parse(bytes, parse_counts)?;
+
+        let global = &mut maps.global;
+        take_field(global, PSBT_GLOBAL_VERSION, "PSBT_GLOBAL_VERSION")?;
+        if take_field(global, PSBT_GLOBAL_UNSIGNED_TX, "PSBT_GLOBAL_UNSIGNED_TX")?.is_some() {
+            return Err(PsbtV2Error::ExcludedField("PSBT_GLOBAL_UNSIGNED_TX"));
+        }
+        let tx_version = required(global, PSBT_GLOBAL_TX_VERSION, "PSBT_GLOBAL_TX_VERSION")?;
+        let tx_version = transaction::Version(i32::from_le_bytes(fixed(
+            &tx_version,
+            "PSBT_GLOBAL_TX_VERSION",
+        )?));
+        let fallback_lock_time = take_field(
+            global,
+            PSBT_GLOBAL_FALLBACK_LOCKTIME,
+            "PSBT_GLOBAL_FALLBACK_LOCKTIME",
+        )?
+        .map(|value| {
+            fixed(&value, "PSBT_GLOBAL_FALLBACK_LOCKTIME")
+                .map(|bytes| absolute::LockTime::from_consensus(u32::from_le_bytes(bytes)))
+        })
+        .transpose()?;
+        let tx_modifiable = take_field(
+            global,
+            PSBT_GLOBAL_TX_MODIFIABLE,
+            "PSBT_GLOBAL_TX_MODIFIABLE",
+        )?
+        .map(|value| fixed::<1>(&value, "PSBT_GLOBAL_TX_MODIFIABLE").map(|[flags]| flags))
+        .transpose()?;
+        // already parsed, for the number of maps
+        take_field(global, PSBT_GLOBAL_INPUT_COUNT, "PSBT_GLOBAL_INPUT_COUNT")?;
+        take_field(global, PSBT_GLOBAL_OUTPUT_COUNT, "PSBT_GLOBAL_OUTPUT_COUNT")?;
+
+        let mut inputs = Vec::with_capacity(maps.inputs.len());
+        for map in &mut maps.inputs {
+            let previous_txid = required(map, PSBT_IN_PREVIOUS_TXID, "PSBT_IN_PREVIOUS_TXID")?;
+            let previous_txid = encode::deserialize(&previous_txid)
+                .map_err(|_| PsbtV2Error::InvalidField("PSBT_IN_PREVIOUS_TXID"))?;
+            let output_index = required(map, PSBT_IN_OUTPUT_INDEX, "PSBT_IN_OUTPUT_INDEX")?;
+            let output_index = u32::from_le_bytes(fixed(&output_index, "PSBT_IN_OUTPUT_INDEX")?);
+            let sequence = take_field(map, PSBT_IN_SEQUENCE, "PSBT_IN_SEQUENCE")?
+                .map(|value| {
+                    fixed(&value, "PSBT_IN_SEQUENCE")
+                        .map(|bytes| Sequence::from_consensus(u32::from_le_bytes(bytes)))
+                })
+                .transpose()?;
+            let required_time_lock_time = take_field(
+                map,
+                PSBT_IN_REQUIRED_TIME_LOCKTIME,
+                "PSBT_IN_REQUIRED_TIME_LOCKTIME",
+            )?
+            .map(|value| {
+                let time = u32::from_le_bytes(fixed(&value, "PSBT_IN_REQUIRED_TIME_LOCKTIME")?);
+                absolute::Time::from_consensus(time)
+                    .map_err(|_| PsbtV2Error::InvalidField("PSBT_IN_REQUIRED_TIME_LOCKTIME"))
+            })
+            .transpose()?;
+            let required_height_lock_time = take_field(
+                map,
+                PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+                "PSBT_IN_REQUIRED_HEIGHT_LOCKTIME",
+            )?
+            .map(|value| {
+                let height = u32::from_le_bytes(fixed(&value, "PSBT_IN_REQUIRED_HEIGHT_LOCKTIME")?);
+                absolute::Height::from_consensus(height)
+                    .map_err(|_| PsbtV2Error::InvalidField("PSBT_IN_REQUIRED_HEIGHT_LOCKTIME"))
+            })
+            .transpose()?;
+            inputs.push(InputV2 {
+                previous_txid,
+                output_index,
+                sequence,
+                required_time_lock_time,
+                required_height_lock_time,
+                input: Input::default(),
+            });
+        }
+
+        let mut outputs = Vec::with_capacity(maps.outputs.len());
+        for map in &mut maps.outputs {
+            let amount = required(map, PSBT_OUT_AMOUNT, "PSBT_OUT_AMOUNT")?;
+            let amount = i64::from_le_bytes(fixed(&amount, "PSBT_OUT_AMOUNT")?);
+            let amount = u64::try_from(amount)
+                .map(Amount::from_sat)
+                .map_err(|_| PsbtV2Error::InvalidField("PSBT_OUT_AMOUNT"))?;
+            let script_pubkey = required(map, PSBT_OUT_SCRIPT, "PSBT_OUT_SCRIPT")?;
+            outputs.push(OutputV2 {
+                amount,
+                script_pubkey: ScriptBuf::from_bytes(script_pubkey),
+                output: Output::default(),
+            });
+        }
+
+        let mut psbt = PsbtV2 {
+            tx_version,
+            fallback_lock_time,
+            tx_modifiable,
+            xpub: BTreeMap::new(),
+            proprietary: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs,
+            outputs,
+        };
+
+        // the remaining fields are common to both versions, they are deserialized by
+        // rust-bitcoin as a PSBT v0 with the transaction of the PSBT, whose locktime doesn't
+        // matter
+        let unsigned_tx = psbt.tx_with_lock_time(absolute::LockTime::ZERO);
+        maps.global.push(field(
+            PSBT_GLOBAL_UNSIGNED_TX,
+            encode::serialize(&unsigned_tx),
+        ));
+        let v0 = Psbt::deserialize(&maps.serialize()).map_err(PsbtV2Error::Psbt)?;
+        psbt.update_from_v0(v0);
+        Ok(psbt)
+    }
+}
+
+impl fmt::Display for PsbtV2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", BASE64_STANDARD.encode(self.serialize()))
+    }
+}
+
+impl FromStr for PsbtV2 {
+    type Err = PsbtV2Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = BASE64_STANDARD.decode(s).map_err(PsbtV2Error::Base64)?;
+        Self::deserialize(&bytes)
+    }
+}
+
+impl TryFrom<Psbt> for PsbtV2 {
+    type Error = PsbtV2Error;
+
+    fn try_from(psbt: Psbt) -> Result<Self, Self::Error> {
+        Self::from_v0(psbt)
+    }
+}
+
+impl TryFrom<&PsbtV2> for Psbt {
+    type Error = PsbtV2Error;
+
+    fn try_from(psbt: &PsbtV2) -> Result<Self, Self::Error> {
+        psbt.to_v0()
+    }
+}
+
+/// Error of a [`PsbtV2`]
+#[derive(Debug)]
+pub enum PsbtV2Error {
+    /// The data doesn't start with the magic bytes of a PSBT
+    InvalidMagic,
+    /// The data ends in the middle of a map
+    UnexpectedEof,
+    /// The data continues after the last output map
+    TrailingData,
+    /// A compact size of the data is invalid
+    Encode(encode::Error),
+    /// A map has the same key twice
+    DuplicateKey(Vec<u8>),
+    /// The PSBT is not of version 2
+    UnsupportedVersion(u32),
+    /// A field required by the PSBT version is missing
+    MissingField(&'static str),
+    /// A field excluded by the PSBT version is present
+    ExcludedField(&'static str),
+    /// The key or the value of a field is invalid
+    InvalidField(&'static str),
+    /// A field common to both versions is invalid
+    Psbt(psbt::Error),
+    /// The string isn't valid base64
+    Base64(bitcoin::base64::DecodeError),
+    /// No locktime satisfies the required locktimes of all the inputs
+    LockTimeConflict,
+    /// The conversion to a PSBT v0 would drop this field
+    Lossy(&'static str),
+}
+
+impl fmt::Display for PsbtV2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "invalid PSBT magic bytes"),
+            Self::UnexpectedEof => write!(f, "unexpected end of the PSBT data"),
+            Self::TrailingData => write!(f, "data after the last output map of the PSBT"),
+            Self::Encode(e) => write!(f, "invalid PSBT encoding: {}", e),
+            Self::DuplicateKey(key) => write!(f, "duplicate PSBT key {:x?}", key),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported PSBT version {}, expected 2", version)
+            }
+            Self::MissingField(field) => write!(f, "missing required field {}", field),
+            Self::ExcludedField(field) => write!(f, "field {} is not allowed", field),
+            Self::InvalidField(field) => write!(f, "invalid field {}", field),
+            Self::Psbt(e) => write!(f, "invalid PSBT: {}", e),
+            Self::Base64(e) => write!(f, "invalid base64: {}", e),
+            Self::LockTimeConflict => write!(
+                f,
+                "the inputs require locktimes of different types, no locktime satisfies them all"
+            ),
+            Self::Lossy(field) => write!(f, "a PSBT v0 can't have the field {}", field),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PsbtV2Error {}
+
+impl From<encode::Error> for PsbtV2Error {
+    fn from(e: encode::Error) -> Self {
+        Self::Encode(e)
+    }
+}
+
+/// The key-value pairs of a map, in order
+type Map = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// The maps of a PSBT of any version, with their raw keys and values
+struct RawPsbt {
+    global: Map,
+    inputs: Vec<Map>,
+    outputs: Vec<Map>,
+}
+
+impl RawPsbt {
+    /// Parse the maps of `bytes`, with the number of input and output maps returned by `counts`
+    /// from the global map
+    fn parse(
+        mut bytes: &[u8],
+        counts: impl FnOnce(&Map) -> Result<(usize, usize), PsbtV2Error>,
+    ) -> Result<Self, PsbtV2Error> {
+        bytes = bytes.strip_prefix(MAGIC).ok_or(PsbtV2Error::InvalidMagic)?;
+        let global = parse_map(&mut bytes)?;
+        let (input_count, output_count) = counts(&global)?;
+        let inputs = (0..input_count)
+            .map(|_| parse_map(&mut bytes))
+            .collect::<Result<_, _>>()?;
+        let outputs = (0..output_count)
+            .map(|_| parse_map(&mut bytes))
+            .collect::<Result<_, _>>()?;
+        if !bytes.is_empty() {
+            return Err(PsbtV2Error::TrailingData);
+        }
+        Ok(Self {
+            global,
+            inputs,
+            outputs,
+        })
+    }
+
+    /// Serialize the maps, each in the order of its keys
+    fn serialize(mut self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for map in core::iter::once(&mut self.global)
+            .chain(&mut self.inputs)
+            .chain(&mut self.outputs)
+        {
+            map.sort();
+            for (key, value) in map.iter() {
+                bytes.extend(encode::serialize(&VarInt(key.len() as u64)));
+                bytes.extend(key);
+                bytes.extend(encode::serialize(&VarInt(value.len() as u64)));
+                bytes.extend(value);
+            }
+            bytes.push(0x00);
+        }
+        bytes
+    }
+}
+
+/// Parse a map from the start of `bytes`, up to its separator
+fn parse_map(bytes: &mut &[u8]) -> Result<Map, PsbtV2Error> {
+    let mut map = Map::new();
+    loop {
+        let key_len = read_compact_size(bytes)?;
+        if key_len == 0 {
+            return Ok(map);
+        }
+        let key = read_bytes(bytes, key_len)?;
+        let value_len = read_compact_size(bytes)?;
+        let value = read_bytes(bytes, value_len)?;
+        if map.iter().any(|(k, _)| *k == key) {
+            return Err(PsbtV2Error::DuplicateKey(key));
+        }
+        map.push((key, value));
+    }
+}
+
+fn read_compact_size(bytes: &mut &[u8]) -> Result<u64, PsbtV2Error> {
+    if bytes.is_empty() {
+        return Err(PsbtV2Error::UnexpectedEof);
+    }
+    let (VarInt(n), len) = encode::deserialize_partial::<VarInt>(bytes).map_err(|e| match e {
+        encode::Error::Io(_) => PsbtV2Error::UnexpectedEof,
+        e => PsbtV2Error::Encode(e),
+    })?;
+    *bytes = &bytes[len..];
+    Ok(n)
+}
+
+fn read_bytes(bytes: &mut &[u8], len: u64) -> Result<Vec<u8>, PsbtV2Error> {
+    let len = usize::try_from(len).map_err(|_| PsbtV2Error::UnexpectedEof)?;
+    if bytes.len() < len {
+        return Err(PsbtV2Error::UnexpectedEof);
+    }
+    let (read, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(read.to_vec())
+}
+
+/// The type of a raw `key`
+fn key_type(mut key: &[u8]) -> Result<u64, PsbtV2Error> {
+    read_compact_size(&mut key)
+}
+
+/// A key-value pair of a field of `key_type` without key data
+fn field(key_type: u64, value: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+    (encode::serialize(&VarInt(key_type)), value)
+}
+
+/// Remove the field of `field_type` from `map` and return its value
+///
+/// The fields of the PSBT v2 have no key data, a key of the same type with key data is invalid.
+fn take_field(
+    map: &mut Map,
+    field_type: u64,
+    name: &'static str,
+) -> Result<Option<Vec<u8>>, PsbtV2Error> {
+    let key = encode::serialize(&VarInt(field_type));
+    let mut value = None;
+    let mut invalid = false;
+    map.retain(|(k, v)| {
+        if *k == key {
+            value = Some(v.clone());
+            return false;
+        }
+        invalid |= matches!(key_type(k), Ok(t) if t == field_type);
+        true
+    });
+    if invalid {
+        return Err(PsbtV2Error::InvalidField(name));
+    }
+    Ok(value)
+}
+
+fn required(map: &mut Map, field_type: u64, name: &'static str) -> Result<Vec<u8>, PsbtV2Error> {
+    take_field(map, field_type, name)?.ok_or(PsbtV2Error::MissingField(name))
+}
+
+fn fixed<const N: usize>(value: &[u8], name: &'static str) -> Result<[u8; N], PsbtV2Error> {
+    value
+        .try_into()
+        .map_err(|_| PsbtV2Error::InvalidField(name))
+}
+
+/// The input and output counts of the global map of a PSBT v2, after checking its version
+fn parse_counts(global: &Map) -> Result<(usize, usize), PsbtV2Error> {
+    let find = |field_type: u64| {
+        let key = encode::serialize(&VarInt(field_type));
+        global
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    };
+    let version = match find(PSBT_GLOBAL_VERSION) {
+        Some(value) => u32::from_le_bytes(fixed(value, "PSBT_GLOBAL_VERSION")?),
+        None => 0,
+    };
+    if version != PSBT_V2_VERSION {
+        return Err(PsbtV2Error::UnsupportedVersion(version));
+    }
+    let count = |field_type: u64, name: &'static str| -> Result<usize, PsbtV2Error> {
+        let value = find(field_type).ok_or(PsbtV2Error::MissingField(name))?;
+        let (VarInt(count), len) = encode::deserialize_partial::<VarInt>(value)
+            .map_err(|_| PsbtV2Error::InvalidField(name))?;
+        if len != value.len() {
+            return Err(PsbtV2Error::InvalidField(name));
+        }
+        usize::try_from(count).map_err(|_| PsbtV2Error::InvalidField(name))
+    };
+    Ok((
+        count(PSBT_GLOBAL_INPUT_COUNT, "PSBT_GLOBAL_INPUT_COUNT")?,
+        count(PSBT_GLOBAL_OUTPUT_COUNT, "PSBT_GLOBAL_OUTPUT_COUNT")?,
+    ))
+}
+
+/// Check that none of the unknown `keys` of a PSBT v0 is one of the fields of v2 `excluded`
+fn check_excluded<'a>(
+    keys: impl IntoIterator<Item = &'a raw::Key>,
+    excluded: &[(u64, &'static str)],
+) -> Result<(), PsbtV2Error> {
+    for key in keys {
+        if let Some((_, name)) = excluded
+            .iter()
+            .find(|(field_type, _)| *field_type == u64::from(key.type_value))
+        {
+            return Err(PsbtV2Error::ExcludedField(name));
+        }
+    }
+    Ok(())
+}
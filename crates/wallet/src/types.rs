@@ -10,21 +10,31 @@
 // licenses.
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use core::convert::AsRef;
+use core::fmt;
 
+use crate::collections::BTreeMap;
 use bdk_chain::ConfirmationTime;
 use bitcoin::blockdata::transaction::{OutPoint, Sequence, TxOut};
-use bitcoin::psbt;
+use bitcoin::{psbt, Amount, SignedAmount, Txid};
 
 use serde::{Deserialize, Serialize};
 
 /// Types of keychains
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub enum KeychainKind {
     /// External keychain, used for deriving recipient addresses.
-    External = 0,
+    External,
     /// Internal keychain, used for deriving change addresses.
-    Internal = 1,
+    Internal,
+    /// An extra keychain, added with [`Wallet::add_keychain`].
+    ///
+    /// The methods of the wallet taking a keychain panic when given an extra keychain which wasn't
+    /// added.
+    ///
+    /// [`Wallet::add_keychain`]: crate::Wallet::add_keychain
+    Extra(KeychainLabel),
 }
 
 impl KeychainKind {
@@ -33,16 +43,154 @@ impl KeychainKind {
         match self {
             KeychainKind::External => b'e',
             KeychainKind::Internal => b'i',
+            KeychainKind::Extra(_) => b'x',
         }
     }
 }
 
+/// The labels `e` and `i` are reserved, so that an extra keychain never has the bytes of the
+/// external or internal one.
 impl AsRef<[u8]> for KeychainKind {
     fn as_ref(&self) -> &[u8] {
         match self {
             KeychainKind::External => b"e",
             KeychainKind::Internal => b"i",
+            KeychainKind::Extra(label) => label.as_str().as_bytes(),
+        }
+    }
+}
+
+/// The binary representation of [`KeychainKind`], the one derived by serde
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "KeychainKind")]
+enum KeychainKindRepr {
+    External,
+    Internal,
+    Extra(KeychainLabel),
+}
+
+/// Human-readable formats serialize keychains as strings, `extra:<label>` for the extra ones, so
+/// that they can be used as the keys of JSON maps.
+impl Serialize for KeychainKind {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match (self, serializer.is_human_readable()) {
+            (KeychainKind::Extra(label), true) => {
+                serializer.collect_str(&format_args!("extra:{}", label))
+            }
+            (KeychainKind::External, _) => KeychainKindRepr::External.serialize(serializer),
+            (KeychainKind::Internal, _) => KeychainKindRepr::Internal.serialize(serializer),
+            (KeychainKind::Extra(label), false) => {
+                KeychainKindRepr::Extra(*label).serialize(serializer)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeychainKind {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let keychain = String::deserialize(deserializer)?;
+            match keychain.as_str() {
+                "External" => Ok(KeychainKind::External),
+                "Internal" => Ok(KeychainKind::Internal),
+                other => other
+                    .strip_prefix("extra:")
+                    .and_then(KeychainLabel::new)
+                    .map(KeychainKind::Extra)
+                    .ok_or_else(|| {
+                        serde::de::Error::invalid_value(
+                            serde::de::Unexpected::Str(other),
+                            &"External, Internal or extra:<label>",
+                        )
+                    }),
+            }
+        } else {
+            Ok(match KeychainKindRepr::deserialize(deserializer)? {
+                KeychainKindRepr::External => KeychainKind::External,
+                KeychainKindRepr::Internal => KeychainKind::Internal,
+                KeychainKindRepr::Extra(label) => KeychainKind::Extra(label),
+            })
+        }
+    }
+}
+
+/// The label of an extra keychain, see [`KeychainKind::Extra`]
+///
+/// A label is a non-empty string of at most [`KeychainLabel::MAX_LEN`] bytes, stored inline so
+/// that [`KeychainKind`] stays `Copy`. The labels `e` and `i` are reserved: they are the bytes of
+/// [`KeychainKind::External`] and [`KeychainKind::Internal`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeychainLabel {
+    len: u8,
+    bytes: [u8; KeychainLabel::MAX_LEN],
+}
+
+impl KeychainLabel {
+    /// The maximum length of a label, in bytes
+    pub const MAX_LEN: usize = 32;
+
+    /// The labels which can't be used, the bytes of the external and internal keychains
+    pub const RESERVED: [&'static str; 2] = ["e", "i"];
+
+    /// Create a label, `None` if `label` is empty, longer than [`KeychainLabel::MAX_LEN`] or
+    /// reserved
+    pub fn new(label: &str) -> Option<Self> {
+        if label.is_empty() || label.len() > Self::MAX_LEN || Self::RESERVED.contains(&label) {
+            return None;
         }
+        let mut bytes = [0; Self::MAX_LEN];
+        bytes[..label.len()].copy_from_slice(label.as_bytes());
+        Some(Self {
+            len: label.len() as u8,
+            bytes,
+        })
+    }
+
+    /// The label as a string
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len as usize]).expect("built from a str")
+    }
+}
+
+impl PartialOrd for KeychainLabel {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KeychainLabel {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl fmt::Debug for KeychainLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for KeychainLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for KeychainLabel {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeychainLabel {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let label = String::deserialize(deserializer)?;
+        KeychainLabel::new(&label).ok_or_else(|| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&label),
+                &"a label of 1 to 32 bytes, other than e and i",
+            )
+        })
     }
 }
 
@@ -63,6 +211,72 @@ pub struct LocalOutput {
     pub derivation_index: u32,
     /// The confirmation time for transaction containing this utxo
     pub confirmation_time: ConfirmationTime,
+    /// Whether this UTXO is an output of a coinbase transaction, which can't be spent before it
+    /// matures, see [`mature_at_height`](Self::mature_at_height)
+    #[serde(default)]
+    pub is_coinbase: bool,
+}
+
+impl LocalOutput {
+    /// The height of the first block which can include a transaction spending this UTXO, for a
+    /// coinbase output confirmed at height `h` it's `h + 100`
+    ///
+    /// Returns `None` if this isn't a coinbase output, and `Some(u32::MAX)` for an unconfirmed
+    /// coinbase output, which can't happen outside of a reorg.
+    pub fn mature_at_height(&self) -> Option<u32> {
+        if !self.is_coinbase {
+            return None;
+        }
+        Some(match self.confirmation_time {
+            ConfirmationTime::Confirmed { height, .. } => {
+                height.saturating_add(bdk_chain::COINBASE_MATURITY)
+            }
+            ConfirmationTime::Unconfirmed { .. } => u32::MAX,
+        })
+    }
+
+    /// Whether a transaction built on a chain whose tip is at `tip_height` can spend this UTXO,
+    /// which is always the case unless it's a coinbase output that isn't mature yet
+    ///
+    /// The transaction is included at best in the next block, so a coinbase output confirmed at
+    /// height `h` can be spent once the tip is at `h + 99`: with 100 confirmations.
+    pub fn is_mature(&self, tip_height: u32) -> bool {
+        self.mature_at_height()
+            .map_or(true, |height| tip_height.saturating_add(1) >= height)
+    }
+}
+
+/// The effect of a transaction on a [`Wallet`], see [`Wallet::tx_details`].
+///
+/// [`Wallet`]: crate::Wallet
+/// [`Wallet::tx_details`]: crate::Wallet::tx_details
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxDetails {
+    /// Transaction id
+    pub txid: Txid,
+    /// Sum of the inputs spending outputs owned by the wallet
+    pub sent: Amount,
+    /// Sum of the outputs paying to script pubkeys owned by the wallet
+    pub received: Amount,
+    /// Fee paid by the transaction, `None` if some of the outputs it spends are unknown
+    pub fee: Option<Amount>,
+    /// The confirmation time of the transaction
+    pub confirmation_time: ConfirmationTime,
+    /// The outputs paying to the wallet, by output index, with the keychain and derivation index
+    /// of their script pubkey
+    pub owned_outputs: BTreeMap<u32, (KeychainKind, u32)>,
+    /// The outputs paying to script pubkeys not owned by the wallet, by output index
+    pub counterparty_outputs: BTreeMap<u32, TxOut>,
+}
+
+impl TxDetails {
+    /// The net effect of the transaction on the wallet balance, i.e. `received - sent`
+    ///
+    /// When all the outputs of the transaction are owned by the wallet this is minus the fee.
+    pub fn net(&self) -> SignedAmount {
+        self.received.to_signed().expect("valid amount")
+            - self.sent.to_signed().expect("valid amount")
+    }
 }
 
 /// A [`Utxo`] with its `satisfaction_weight`.
@@ -0,0 +1,574 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Fee rate and amount units
+//!
+//! [`FeeRate`] is stored in sat/kwu, while users and other software usually deal in sat/vB or
+//! BTC/kvB. This module converts between these units without losing precision, rounding up to
+//! the next sat/kwu whenever a conversion to a [`FeeRate`] isn't exact, so that the fee paid is
+//! never lower than the one requested. It also formats and parses amounts and fee rates with
+//! their unit, as entered by users.
+//!
+//! ```
+//! # use bdk_wallet::bitcoin::{Amount, Denomination, FeeRate};
+//! use bdk_wallet::units::{self, FeeRateUnit, SatPerVb};
+//!
+//! let fee_rate = units::parse_fee_rate("2.5 sat/vB", FeeRateUnit::SatPerVb)?;
+//! assert_eq!(fee_rate, FeeRate::from(SatPerVb::from_msat(2_500)));
+//! assert_eq!(units::format_fee_rate(fee_rate, FeeRateUnit::BtcPerKvb), "0.00002500 BTC/kvB");
+//!
+//! let amount = units::parse_amount("0.12345678 BTC", Denomination::Satoshi)?;
+//! assert_eq!(amount, Amount::from_sat(12_345_678));
+//! assert_eq!(units::format_amount(amount, Denomination::Bit), "123456.78 bits");
+//! # Ok::<_, units::ParseError>(())
+//! ```
+
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::amount::ParseAmountError;
+use bitcoin::{Amount, Denomination, FeeRate};
+
+/// A fee rate in sat/vB, with a precision of a millisatoshi per vbyte
+///
+/// It converts to a [`FeeRate`] rounding up to the next sat/kwu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SatPerVb(u64);
+
+impl SatPerVb {
+    /// A fee rate of `sat_per_vb` sat/vB, `None` if it overflows
+    pub fn from_sat(sat_per_vb: u64) -> Option<Self> {
+        sat_per_vb.checked_mul(1_000).map(Self)
+    }
+
+    /// A fee rate of `msat_per_vb` millisatoshis per vbyte
+    pub fn from_msat(msat_per_vb: u64) -> Self {
+        Self(msat_per_vb)
+    }
+
+    /// The fee rate in millisatoshis per vbyte
+    pub fn to_msat(self) -> u64 {
+        self.0
+    }
+
+    /// The exact sat/vB of `fee_rate`, `None` if it overflows
+    pub fn from_fee_rate(fee_rate: FeeRate) -> Option<Self> {
+        // 1 sat/kwu is 4 msat/vB
+        fee_rate.to_sat_per_kwu().checked_mul(4).map(Self)
+    }
+}
+
+impl From<SatPerVb> for FeeRate {
+    fn from(fee_rate: SatPerVb) -> Self {
+        FeeRate::from_sat_per_kwu(div_ceil(fee_rate.0, 4))
+    }
+}
+
+impl From<BtcPerKvb> for SatPerVb {
+    fn from(fee_rate: BtcPerKvb) -> Self {
+        // 1 sat/kvB is 1 msat/vB
+        Self(fee_rate.0.to_sat())
+    }
+}
+
+impl fmt::Display for SatPerVb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = decimal_string(self.0.into(), 3, true);
+        write!(f, "{} {}", value, FeeRateUnit::SatPerVb)
+    }
+}
+
+/// A fee rate in BTC/kvB, the unit of the fee rates of Bitcoin Core
+///
+/// It converts to a [`FeeRate`] rounding up to the next sat/kwu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BtcPerKvb(Amount);
+
+impl BtcPerKvb {
+    /// A fee rate of `amount` per kvB
+    pub fn new(amount: Amount) -> Self {
+        Self(amount)
+    }
+
+    /// The amount paid per kvB
+    pub fn to_amount(self) -> Amount {
+        self.0
+    }
+
+    /// The exact BTC/kvB of `fee_rate`, `None` if it overflows
+    pub fn from_fee_rate(fee_rate: FeeRate) -> Option<Self> {
+        SatPerVb::from_fee_rate(fee_rate).map(Self::from)
+    }
+}
+
+impl From<BtcPerKvb> for FeeRate {
+    fn from(fee_rate: BtcPerKvb) -> Self {
+        SatPerVb::from(fee_rate).into()
+    }
+}
+
+impl From<SatPerVb> for BtcPerKvb {
+    fn from(fee_rate: SatPerVb) -> Self {
+        Self(Amount::from_sat(fee_rate.0))
+    }
+}
+
+impl fmt::Display for BtcPerKvb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = decimal_string(self.0.to_sat().into(), 8, false);
+        write!(f, "{} {}", value, FeeRateUnit::BtcPerKvb)
+    }
+}
+
+/// A unit of fee rates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeeRateUnit {
+    /// Satoshis per virtual byte, with up to 3 decimals
+    SatPerVb,
+    /// Satoshis per 1000 weight units, the unit of [`FeeRate`]
+    SatPerKwu,
+    /// Bitcoins per 1000 virtual bytes, with up to 8 decimals
+    BtcPerKvb,
+}
+
+impl FeeRateUnit {
+    /// The number of decimals of a fee rate in this unit
+    fn decimals(self) -> u32 {
+        match self {
+            FeeRateUnit::SatPerVb => 3,
+            FeeRateUnit::SatPerKwu => 0,
+            FeeRateUnit::BtcPerKvb => 8,
+        }
+    }
+}
+
+impl fmt::Display for FeeRateUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeRateUnit::SatPerVb => write!(f, "sat/vB"),
+            FeeRateUnit::SatPerKwu => write!(f, "sat/kwu"),
+            FeeRateUnit::BtcPerKvb => write!(f, "BTC/kvB"),
+        }
+    }
+}
+
+impl FromStr for FeeRateUnit {
+    type Err = ParseError;
+
+    /// Parse a unit, ignoring the case: `sat/vB`, `sats/vB`, `sat/vbyte`, `sat/kwu` or `BTC/kvB`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sat/vb" | "sats/vb" | "sat/vbyte" => Ok(FeeRateUnit::SatPerVb),
+            "sat/kwu" | "sats/kwu" => Ok(FeeRateUnit::SatPerKwu),
+            "btc/kvb" => Ok(FeeRateUnit::BtcPerKvb),
+            _ => Err(ParseError::UnknownUnit(s.to_string())),
+        }
+    }
+}
+
+/// Error parsing an amount or a fee rate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// There is no value before the unit
+    MissingValue,
+    /// The unit is not a known denomination or fee rate unit
+    UnknownUnit(String),
+    /// The value of a fee rate is not a decimal number
+    InvalidNumber(String),
+    /// The value of a fee rate has more decimals than its unit allows
+    TooPrecise {
+        /// The unit of the fee rate
+        unit: FeeRateUnit,
+    },
+    /// The fee rate is too large
+    Overflow,
+    /// The amount is invalid
+    Amount(ParseAmountError),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingValue => write!(f, "Missing value"),
+            ParseError::UnknownUnit(unit) => write!(f, "Unknown unit `{}`", unit),
+            ParseError::InvalidNumber(value) => write!(f, "Invalid number `{}`", value),
+            ParseError::TooPrecise { unit } => write!(
+                f,
+                "A fee rate in {} can't have more than {} decimals",
+                unit,
+                unit.decimals()
+            ),
+            ParseError::Overflow => write!(f, "The fee rate is too large"),
+            ParseError::Amount(err) => write!(f, "Invalid amount: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+impl From<ParseAmountError> for ParseError {
+    fn from(err: ParseAmountError) -> Self {
+        ParseError::Amount(err)
+    }
+}
+
+/// Parse a fee rate entered by a user, like `2.5 sat/vB` or `0.00001 BTC/kvB`
+///
+/// The unit is parsed with [`FeeRateUnit::from_str`], a value without a unit is in
+/// `default_unit`. Fee rates which are not a whole sat/kwu are rounded up.
+pub fn parse_fee_rate(s: &str, default_unit: FeeRateUnit) -> Result<FeeRate, ParseError> {
+    let (value, unit) = split_unit(s)?;
+    let unit = match unit {
+        Some(unit) => unit.parse()?,
+        None => default_unit,
+    };
+    let value = parse_decimal(value, unit)?;
+    Ok(match unit {
+        FeeRateUnit::SatPerVb => SatPerVb::from_msat(value).into(),
+        FeeRateUnit::SatPerKwu => FeeRate::from_sat_per_kwu(value),
+        FeeRateUnit::BtcPerKvb => BtcPerKvb::new(Amount::from_sat(value)).into(),
+    })
+}
+
+/// Format `fee_rate` in `unit`, with the unit
+///
+/// The formatted fee rate is exact, and parses back to `fee_rate` with [`parse_fee_rate`] unless
+/// it's too large to be represented in `unit`.
+pub fn format_fee_rate(fee_rate: FeeRate, unit: FeeRateUnit) -> String {
+    let sat_per_kwu = u128::from(fee_rate.to_sat_per_kwu());
+    let value = match unit {
+        // 1 sat/kwu is 4 msat/vB, and 4 sat/kvB
+        FeeRateUnit::SatPerVb => decimal_string(sat_per_kwu * 4, 3, true),
+        FeeRateUnit::SatPerKwu => decimal_string(sat_per_kwu, 0, true),
+        FeeRateUnit::BtcPerKvb => decimal_string(sat_per_kwu * 4, 8, false),
+    };
+    format!("{} {}", value, unit)
+}
+
+/// Format `amount` in `denomination`, with all its decimals and the denomination
+///
+/// For example 12345678 sats are formatted as `0.12345678 BTC`. The formatted amount parses back
+/// to `amount` with [`parse_amount`].
+pub fn format_amount(amount: Amount, denomination: Denomination) -> String {
+    let sat = u128::from(amount.to_sat());
+    let value = match denomination {
+        Denomination::Bitcoin => decimal_string(sat, 8, false),
+        Denomination::CentiBitcoin => decimal_string(sat, 6, false),
+        Denomination::MilliBitcoin => decimal_string(sat, 5, false),
+        Denomination::MicroBitcoin | Denomination::Bit => decimal_string(sat, 2, false),
+        Denomination::Satoshi => return format!("{} sat", sat),
+        Denomination::NanoBitcoin => (sat * 10).to_string(),
+        Denomination::PicoBitcoin => (sat * 10_000).to_string(),
+        Denomination::MilliSatoshi => (sat * 1_000).to_string(),
+        _ => amount.display_in(denomination).to_string(),
+    };
+    format!("{} {}", value, denomination)
+}
+
+/// Parse an amount entered by a user, like `0.1 BTC` or `1000 sat`
+///
+/// The denomination is parsed with [`Denomination::from_str`], a value without a denomination is
+/// in `default_denomination`.
+pub fn parse_amount(s: &str, default_denomination: Denomination) -> Result<Amount, ParseError> {
+    let (value, unit) = split_unit(s)?;
+    let denomination = match unit {
+        Some(unit) => {
+            Denomination::from_str(unit).map_err(|_| ParseError::UnknownUnit(unit.to_string()))?
+        }
+        None => default_denomination,
+    };
+    Ok(Amount::from_str_in(value, denomination)?)
+}
+
+/// Split `s` into its value and its unit, if any
+fn split_unit(s: &str) -> Result<(&str, Option<&str>), ParseError> {
+    let s = s.trim();
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => (s[..i].trim(), Some(s[i..].trim())),
+        None => (s, None),
+    };
+    if value.is_empty() {
+        return Err(ParseError::MissingValue);
+    }
+    Ok((value, unit))
+}
+
+/// Parse a non-negative decimal number as an integer in the smallest fraction of `unit`
+fn parse_decimal(value: &str, unit: FeeRateUnit) -> Result<u64, ParseError> {
+    let decimals = unit.decimals();
+    let invalid = || ParseError::InvalidNumber(value.to_string());
+    let (int, frac) = match value.split_once('.') {
+        Some((int, frac)) => (int, frac.trim_end_matches('0')),
+        None => (value, ""),
+    };
+    let is_number = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (int.is_empty() && frac.is_empty()) || !is_number(int) || !is_number(frac) {
+        return Err(invalid());
+    }
+    if frac.len() > decimals as usize {
+        return Err(ParseError::TooPrecise { unit });
+    }
+    let scale = 10_u64.pow(decimals);
+    let int = match int {
+        "" => 0,
+        int => int.parse::<u64>().map_err(|_| ParseError::Overflow)?,
+    };
+    let frac = match frac {
+        "" => 0,
+        frac => {
+            frac.parse::<u64>().map_err(|_| invalid())? * 10_u64.pow(decimals - frac.len() as u32)
+        }
+    };
+    int.checked_mul(scale)
+        .and_then(|int| int.checked_add(frac))
+        .ok_or(ParseError::Overflow)
+}
+
+/// Format `value`, in units of `10^-decimals`, as a decimal number
+///
+/// With `trim`, the trailing zeros of the decimals are not written.
+fn decimal_string(value: u128, decimals: u32, trim: bool) -> String {
+    let scale = 10_u128.pow(decimals);
+    let (int, frac) = (value / scale, value % scale);
+    if decimals == 0 || (trim && frac == 0) {
+        return int.to_string();
+    }
+    let frac = format!("{:0width$}", frac, width = decimals as usize);
+    let frac = if trim {
+        frac.trim_end_matches('0')
+    } else {
+        &frac
+    };
+    format!("{}.{}", int, frac)
+}
+
+fn div_ceil(a: u64, b: u64) -> u64 {
+    a / b + u64::from(a % b != 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    const DENOMINATIONS: [Denomination; 9] = [
+        Denomination::Bitcoin,
+        Denomination::CentiBitcoin,
+        Denomination::MilliBitcoin,
+        Denomination::MicroBitcoin,
+        Denomination::NanoBitcoin,
+        Denomination::PicoBitcoin,
+        Denomination::Bit,
+        Denomination::Satoshi,
+        Denomination::MilliSatoshi,
+    ];
+
+    #[test]
+    fn test_fee_rate_conversions() {
+        // 1 sat/vB is 250 sat/kwu and 0.00001 BTC/kvB
+        let sat_per_vb = SatPerVb::from_sat(1).unwrap();
+        assert_eq!(FeeRate::from(sat_per_vb), FeeRate::from_sat_per_kwu(250));
+        assert_eq!(
+            BtcPerKvb::from(sat_per_vb).to_amount(),
+            Amount::from_sat(1_000)
+        );
+        // rounded up to the next sat/kwu
+        assert_eq!(
+            FeeRate::from(SatPerVb::from_msat(1_001)),
+            FeeRate::from_sat_per_kwu(251)
+        );
+        assert_eq!(
+            FeeRate::from(BtcPerKvb::new(Amount::from_sat(1))),
+            FeeRate::from_sat_per_kwu(1)
+        );
+        assert_eq!(SatPerVb::from_sat(u64::MAX), None);
+        assert_eq!(SatPerVb::from_fee_rate(FeeRate::MAX), None);
+        assert_eq!(BtcPerKvb::from_fee_rate(FeeRate::MAX), None);
+    }
+
+    #[test]
+    fn test_parse_fee_rate() {
+        let parse = |s| parse_fee_rate(s, FeeRateUnit::SatPerVb);
+        assert_eq!(parse("2"), Ok(FeeRate::from_sat_per_kwu(500)));
+        assert_eq!(parse(" 2.5 sat/vB "), Ok(FeeRate::from_sat_per_kwu(625)));
+        assert_eq!(parse("2.5sats/vb"), Ok(FeeRate::from_sat_per_kwu(625)));
+        assert_eq!(parse(".5 sat/vbyte"), Ok(FeeRate::from_sat_per_kwu(125)));
+        assert_eq!(parse("1.0010 sat/vB"), Ok(FeeRate::from_sat_per_kwu(251)));
+        assert_eq!(parse("253 sat/kwu"), Ok(FeeRate::from_sat_per_kwu(253)));
+        assert_eq!(parse("0.00001 BTC/kvB"), Ok(FeeRate::from_sat_per_kwu(250)));
+        assert_eq!(
+            parse_fee_rate("0.00001", FeeRateUnit::BtcPerKvb),
+            Ok(FeeRate::from_sat_per_kwu(250))
+        );
+
+        assert_eq!(parse(""), Err(ParseError::MissingValue));
+        assert_eq!(parse("sat/vB"), Err(ParseError::MissingValue));
+        assert_eq!(
+            parse("1 sat/byte"),
+            Err(ParseError::UnknownUnit("sat/byte".into()))
+        );
+        assert_eq!(
+            parse("1.2.3"),
+            Err(ParseError::InvalidNumber("1.2.3".into()))
+        );
+        assert_eq!(parse("."), Err(ParseError::InvalidNumber(".".into())));
+        assert_eq!(
+            parse("1.0001"),
+            Err(ParseError::TooPrecise {
+                unit: FeeRateUnit::SatPerVb
+            })
+        );
+        assert_eq!(
+            parse("1.5 sat/kwu"),
+            Err(ParseError::TooPrecise {
+                unit: FeeRateUnit::SatPerKwu
+            })
+        );
+        assert_eq!(parse("18446744073709552 sat/vB"), Err(ParseError::Overflow));
+        assert_eq!(
+            parse("99999999999999999999 sat/kwu"),
+            Err(ParseError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_format_fee_rate() {
+        let fee_rate = FeeRate::from_sat_per_kwu(625);
+        assert_eq!(
+            format_fee_rate(fee_rate, FeeRateUnit::SatPerVb),
+            "2.5 sat/vB"
+        );
+        assert_eq!(
+            format_fee_rate(fee_rate, FeeRateUnit::SatPerKwu),
+            "625 sat/kwu"
+        );
+        assert_eq!(
+            format_fee_rate(fee_rate, FeeRateUnit::BtcPerKvb),
+            "0.00002500 BTC/kvB"
+        );
+        assert_eq!(
+            format_fee_rate(FeeRate::from_sat_per_kwu(250), FeeRateUnit::SatPerVb),
+            "1 sat/vB"
+        );
+        assert_eq!(SatPerVb::from_msat(1_004).to_string(), "1.004 sat/vB");
+        assert_eq!(
+            BtcPerKvb::new(Amount::from_sat(1_000)).to_string(),
+            "0.00001000 BTC/kvB"
+        );
+    }
+
+    #[test]
+    fn test_format_and_parse_amount() {
+        let amount = Amount::from_sat(12_345_678);
+        assert_eq!(
+            format_amount(amount, Denomination::Bitcoin),
+            "0.12345678 BTC"
+        );
+        assert_eq!(
+            format_amount(amount, Denomination::MilliBitcoin),
+            "123.45678 mBTC"
+        );
+        assert_eq!(format_amount(amount, Denomination::Bit), "123456.78 bits");
+        assert_eq!(format_amount(amount, Denomination::Satoshi), "12345678 sat");
+        assert_eq!(
+            format_amount(amount, Denomination::MilliSatoshi),
+            "12345678000 msat"
+        );
+        assert_eq!(
+            format_amount(Amount::ONE_BTC, Denomination::Bitcoin),
+            "1.00000000 BTC"
+        );
+
+        let parse = |s| parse_amount(s, Denomination::Satoshi);
+        assert_eq!(parse("0.12345678 BTC"), Ok(amount));
+        assert_eq!(parse("0.12345678BTC"), Ok(amount));
+        assert_eq!(parse("12345678"), Ok(amount));
+        assert_eq!(parse("12345678 sats"), Ok(amount));
+        assert_eq!(parse(""), Err(ParseError::MissingValue));
+        assert_eq!(parse("1 XBT"), Err(ParseError::UnknownUnit("XBT".into())));
+        assert!(matches!(
+            parse("0.123456789 BTC"),
+            Err(ParseError::Amount(_))
+        ));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig {
+            ..Default::default()
+        })]
+
+        /// Converting a fee rate to sat/vB or BTC/kvB and back is lossless.
+        #[test]
+        fn fee_rate_round_trip(sat_per_kwu in any::<u64>()) {
+            let fee_rate = FeeRate::from_sat_per_kwu(sat_per_kwu);
+            match SatPerVb::from_fee_rate(fee_rate) {
+                Some(sat_per_vb) => {
+                    prop_assert_eq!(FeeRate::from(sat_per_vb), fee_rate);
+                    let btc_per_kvb = BtcPerKvb::from_fee_rate(fee_rate);
+                    prop_assert_eq!(btc_per_kvb, Some(BtcPerKvb::from(sat_per_vb)));
+                    prop_assert_eq!(FeeRate::from(btc_per_kvb.unwrap()), fee_rate);
+                    let units = [
+                        FeeRateUnit::SatPerVb,
+                        FeeRateUnit::SatPerKwu,
+                        FeeRateUnit::BtcPerKvb,
+                    ];
+                    for unit in units {
+                        let s = format_fee_rate(fee_rate, unit);
+                        prop_assert_eq!(parse_fee_rate(&s, unit), Ok(fee_rate));
+                    }
+                }
+                None => prop_assert!(sat_per_kwu > u64::MAX / 4),
+            }
+        }
+
+        /// Converting sat/vB to a fee rate never under-pays, and over-pays by less than 1 sat/kwu.
+        #[test]
+        fn sat_per_vb_rounds_up(msat_per_vb in any::<u64>()) {
+            let fee_rate = FeeRate::from(SatPerVb::from_msat(msat_per_vb));
+            let msat_per_vb = u128::from(msat_per_vb);
+            let paid = u128::from(fee_rate.to_sat_per_kwu()) * 4;
+            prop_assert!(paid >= msat_per_vb);
+            prop_assert!(paid < msat_per_vb + 4);
+            let sat_per_vb = SatPerVb::from_msat(msat_per_vb as u64);
+            prop_assert_eq!(SatPerVb::from(BtcPerKvb::from(sat_per_vb)), sat_per_vb);
+        }
+
+        /// The conversions to a fee rate preserve the order of the fee rates.
+        #[test]
+        fn fee_rate_conversions_are_monotonic(a in any::<u64>(), b in any::<u64>()) {
+            let (a, b) = (a.min(b), a.max(b));
+            prop_assert!(
+                FeeRate::from(SatPerVb::from_msat(a)) <= FeeRate::from(SatPerVb::from_msat(b))
+            );
+            prop_assert!(
+                FeeRate::from(BtcPerKvb::new(Amount::from_sat(a)))
+                    <= FeeRate::from(BtcPerKvb::new(Amount::from_sat(b)))
+            );
+            prop_assert!(
+                SatPerVb::from_fee_rate(FeeRate::from_sat_per_kwu(a / 4))
+                    <= SatPerVb::from_fee_rate(FeeRate::from_sat_per_kwu(b / 4))
+            );
+        }
+
+        /// Formatting an amount in any denomination and parsing it back is lossless.
+        #[test]
+        fn amount_round_trip(
+            sat in 0..=Amount::MAX_MONEY.to_sat(),
+            index in 0..DENOMINATIONS.len()
+        ) {
+            let amount = Amount::from_sat(sat);
+            let denomination = DENOMINATIONS[index];
+            let s = format_amount(amount, denomination);
+            prop_assert_eq!(parse_amount(&s, Denomination::Satoshi), Ok(amount));
+        }
+    }
+}
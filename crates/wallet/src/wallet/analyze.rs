@@ -0,0 +1,529 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! PSBT input analysis
+//!
+//! [`Wallet::finalize_psbt`] only tells whether all the inputs could be finalized.
+//! [`Wallet::analyze_psbt`] explains, for each input, what is still preventing it: the
+//! signatures which are missing or don't verify, the hash preimages which aren't in the PSBT and
+//! the timelocks the transaction doesn't meet.
+//!
+//! Unlike [`Wallet::finalize_psbt`], the analysis verifies the signatures, and only the
+//! signatures which are valid for the sighash type requested by the input are used to decide
+//! whether it is finalizable. The timelocks are checked against the lock time of the transaction
+//! and the sequence of the input, the chain tip is not taken into account.
+
+use alloc::vec::Vec;
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoin::key::TapTweak;
+use bitcoin::psbt::{self, PsbtSighashType};
+use bitcoin::secp256k1::{Message, XOnlyPublicKey};
+use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
+use bitcoin::taproot::{LeafVersion, TapLeafHash};
+use bitcoin::{absolute, relative, Psbt, PublicKey, Sequence, TxIn, TxOut};
+use miniscript::descriptor::{DefiniteDescriptorKey, Descriptor, ShInner, WshInner};
+use miniscript::miniscript::decode::Terminal;
+use miniscript::psbt::PsbtInputSatisfier;
+use miniscript::{ForEachKey, Legacy, Miniscript, ScriptContext, Segwitv0, Tap, ToPublicKey};
+
+use super::signer::ComputeSighash;
+use super::Wallet;
+use crate::collections::BTreeSet;
+use crate::descriptor::{DerivedDescriptor, DescriptorMeta};
+use crate::psbt::PsbtUtils;
+use crate::KeychainKind;
+
+/// The analysis of an input of a PSBT, see [`Wallet::analyze_psbt`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputAnalysis {
+    /// The index of the input
+    pub index: usize,
+    /// The keychain of the output spent by the input, if it belongs to the wallet
+    pub keychain: Option<KeychainKind>,
+    /// The derivation index of the output spent by the input, if it belongs to the wallet
+    pub derivation_index: Option<u32>,
+    /// The descriptor of the output spent by the input, `None` if the wallet can't tell it
+    pub descriptor: Option<DerivedDescriptor>,
+    /// Whether the input already has its final `scriptSig` or `scriptWitness`
+    ///
+    /// The signatures of a finalized input are removed, nothing else is reported about it.
+    pub finalized: bool,
+    /// Whether the input can be finalized with the valid signatures of the PSBT
+    pub finalizable: bool,
+    /// The keys with a valid signature
+    pub signed: Vec<SignatureKey>,
+    /// The signatures which are present but can't be used
+    pub invalid_signatures: Vec<InvalidSignature>,
+    /// The keys of the descriptor without a valid signature
+    ///
+    /// Only reported when the input isn't finalizable. All the keys of the descriptor are listed,
+    /// not only the ones of a specific spending path.
+    pub missing_signatures: Vec<SignatureKey>,
+    /// The hashes of the descriptor whose preimage isn't in the PSBT
+    ///
+    /// Only reported when the input isn't finalizable.
+    pub missing_preimages: Vec<MissingPreimage>,
+    /// The timelocks of the descriptor not met by the transaction
+    ///
+    /// Only reported when the input isn't finalizable.
+    pub timelocks: Vec<TimelockObstacle>,
+}
+
+/// The key of a signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SignatureKey {
+    /// An ECDSA key, in a legacy or segwit v0 script
+    Ecdsa(PublicKey),
+    /// The internal key of a taproot output, signing for the key path
+    TapKey(XOnlyPublicKey),
+    /// A key in a taproot leaf
+    TapScript(XOnlyPublicKey, TapLeafHash),
+}
+
+/// A signature which is present in the PSBT but can't be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidSignature {
+    /// The key of the signature
+    pub key: SignatureKey,
+    /// Why the signature can't be used
+    pub issue: SignatureIssue,
+}
+
+/// Why a signature can't be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureIssue {
+    /// The signature commits to another sighash type than the one requested by the input
+    WrongSighash {
+        /// The sighash type requested by the input, or the default one
+        expected: PsbtSighashType,
+        /// The sighash type of the signature
+        found: PsbtSighashType,
+    },
+    /// The key path signature was made with the internal key, or without the merkle root of the
+    /// script tree in the tweak
+    WrongTweak,
+    /// The key isn't one of the keys of the descriptor
+    UnknownKey,
+    /// The signature doesn't verify
+    Invalid,
+    /// The sighash can't be computed, usually because some previous outputs are missing
+    Unverifiable,
+}
+
+/// A hash whose preimage is missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MissingPreimage {
+    /// A `sha256()` fragment
+    Sha256(sha256::Hash),
+    /// A `hash256()` fragment
+    Hash256(sha256d::Hash),
+    /// A `ripemd160()` fragment
+    Ripemd160(ripemd160::Hash),
+    /// A `hash160()` fragment
+    Hash160(hash160::Hash),
+}
+
+/// A timelock of the descriptor not met by the transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimelockObstacle {
+    /// An `after()` fragment, the lock time of the transaction is lower, of another unit, or the
+    /// sequence of the input disables it
+    After {
+        /// The lock time required by the descriptor
+        required: absolute::LockTime,
+        /// The lock time of the transaction
+        lock_time: absolute::LockTime,
+    },
+    /// An `older()` fragment, the sequence of the input is lower, of another unit, disables the
+    /// relative lock time, or the version of the transaction is lower than 2
+    Older {
+        /// The relative lock time required by the descriptor
+        required: relative::LockTime,
+        /// The sequence of the input
+        sequence: Sequence,
+    },
+}
+
+/// The signing context of an input, told by the output it spends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Legacy,
+    Segwitv0,
+    Tap,
+}
+
+impl Context {
+    fn of(psbt_input: &psbt::Input, utxo: &TxOut) -> Self {
+        let spk = &utxo.script_pubkey;
+        if spk.is_p2tr() {
+            Context::Tap
+        } else if spk.is_p2wpkh()
+            || spk.is_p2wsh()
+            || (spk.is_p2sh()
+                && psbt_input
+                    .redeem_script
+                    .as_ref()
+                    .map_or(false, |script| script.is_p2wpkh() || script.is_p2wsh()))
+        {
+            Context::Segwitv0
+        } else {
+            Context::Legacy
+        }
+    }
+}
+
+/// The keys, hashes and timelocks of a descriptor
+#[derive(Default)]
+struct Requirements {
+    keys: BTreeSet<SignatureKey>,
+    preimages: BTreeSet<MissingPreimage>,
+    after: Vec<absolute::LockTime>,
+    older: Vec<relative::LockTime>,
+}
+
+impl Requirements {
+    fn of(desc: &DerivedDescriptor) -> Self {
+        let mut requirements = Requirements::default();
+        match desc {
+            Descriptor::Tr(tr) => {
+                requirements
+                    .keys
+                    .insert(SignatureKey::TapKey(tr.internal_key().to_x_only_pubkey()));
+                for (_, ms) in tr.iter_scripts() {
+                    let leaf_hash = TapLeafHash::from_script(&ms.encode(), LeafVersion::TapScript);
+                    for pk in ms.iter_pk() {
+                        requirements
+                            .keys
+                            .insert(SignatureKey::TapScript(pk.to_x_only_pubkey(), leaf_hash));
+                    }
+                    requirements.add_fragments(ms);
+                }
+            }
+            _ => {
+                desc.for_each_key(|pk| {
+                    requirements
+                        .keys
+                        .insert(SignatureKey::Ecdsa(pk.to_public_key()));
+                    true
+                });
+                match desc {
+                    Descriptor::Bare(bare) => requirements.add_fragments(bare.as_inner()),
+                    Descriptor::Sh(sh) => match sh.as_inner() {
+                        ShInner::Wsh(wsh) => {
+                            if let WshInner::Ms(ms) = wsh.as_inner() {
+                                requirements.add_fragments(ms);
+                            }
+                        }
+                        ShInner::Ms(ms) => requirements.add_fragments(ms),
+                        ShInner::Wpkh(_) | ShInner::SortedMulti(_) => {}
+                    },
+                    Descriptor::Wsh(wsh) => {
+                        if let WshInner::Ms(ms) = wsh.as_inner() {
+                            requirements.add_fragments(ms);
+                        }
+                    }
+                    Descriptor::Pkh(_) | Descriptor::Wpkh(_) | Descriptor::Tr(_) => {}
+                }
+            }
+        }
+        requirements
+    }
+
+    fn add_fragments<Ctx: ScriptContext>(&mut self, ms: &Miniscript<DefiniteDescriptorKey, Ctx>) {
+        for node in ms.iter() {
+            match &node.node {
+                Terminal::After(lock_time) => self.after.push((*lock_time).into()),
+                Terminal::Older(lock_time) => self.older.push((*lock_time).into()),
+                Terminal::Sha256(hash) => {
+                    self.preimages.insert(MissingPreimage::Sha256(*hash));
+                }
+                Terminal::Hash256(hash) => {
+                    self.preimages.insert(MissingPreimage::Hash256(
+                        sha256d::Hash::from_byte_array(hash.to_byte_array()),
+                    ));
+                }
+                Terminal::Ripemd160(hash) => {
+                    self.preimages.insert(MissingPreimage::Ripemd160(*hash));
+                }
+                Terminal::Hash160(hash) => {
+                    self.preimages.insert(MissingPreimage::Hash160(*hash));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Wallet {
+    /// Analyze the inputs of `psbt`, explaining what prevents each of them from being finalized
+    ///
+    /// See the [module documentation](crate::wallet::analyze) for how signatures and timelocks
+    /// are checked.
+    pub fn analyze_psbt(&self, psbt: &Psbt) -> Vec<InputAnalysis> {
+        (0..psbt.inputs.len().min(psbt.unsigned_tx.input.len()))
+            .map(|n| self.analyze_input(psbt, n))
+            .collect()
+    }
+
+    fn analyze_input(&self, psbt: &Psbt, n: usize) -> InputAnalysis {
+        let psbt_input = &psbt.inputs[n];
+        let utxo = psbt.get_utxo_for(n);
+
+        // same lookup as `finalize_psbt`
+        let mut keychain = None;
+        let mut derivation_index = None;
+        let mut descriptor = None;
+        if let Some(&(k, i)) = utxo
+            .as_ref()
+            .and_then(|txout| self.indexed_graph.index.index_of_spk(&txout.script_pubkey))
+        {
+            keychain = Some(k);
+            derivation_index = Some(i);
+            descriptor = self
+                .get_descriptor_for_keychain(k)
+                .at_derivation_index(i)
+                .ok();
+        }
+        if descriptor.is_none() {
+            if let Some((k, desc)) = self.indexed_graph.index.keychains().find_map(|(k, desc)| {
+                desc.derive_from_psbt_input(psbt_input, utxo.clone(), &self.secp)
+                    .map(|desc| (k, desc))
+            }) {
+                keychain = Some(*k);
+                descriptor = Some(desc);
+            }
+        }
+
+        let mut analysis = InputAnalysis {
+            index: n,
+            keychain,
+            derivation_index,
+            descriptor,
+            finalized: psbt_input.final_script_sig.is_some()
+                || psbt_input.final_script_witness.is_some(),
+            finalizable: false,
+            signed: Vec::new(),
+            invalid_signatures: Vec::new(),
+            missing_signatures: Vec::new(),
+            missing_preimages: Vec::new(),
+            timelocks: Vec::new(),
+        };
+        if analysis.finalized {
+            analysis.finalizable = true;
+            return analysis;
+        }
+
+        let requirements = analysis.descriptor.as_ref().map(Requirements::of);
+        let is_known_key = |key: &SignatureKey| {
+            requirements
+                .as_ref()
+                .map_or(true, |requirements| requirements.keys.contains(key))
+        };
+
+        // a copy of the PSBT with only the usable signatures, to try to satisfy the descriptor
+        let mut usable = psbt.clone();
+        let usable_input = &mut usable.inputs[n];
+        usable_input.partial_sigs.clear();
+        usable_input.tap_key_sig = None;
+        usable_input.tap_script_sigs.clear();
+
+        let context = utxo.as_ref().map(|utxo| Context::of(psbt_input, utxo));
+        let mut signed = Vec::new();
+        let mut invalid_signatures = Vec::new();
+        let mut report = |key: SignatureKey, issue: Option<SignatureIssue>| match issue {
+            None => signed.push(key),
+            Some(issue) => invalid_signatures.push(InvalidSignature { key, issue }),
+        };
+
+        let expected_ecdsa = psbt_input
+            .sighash_type
+            .unwrap_or_else(|| EcdsaSighashType::All.into());
+        for (pk, sig) in &psbt_input.partial_sigs {
+            let key = SignatureKey::Ecdsa(*pk);
+            let found = PsbtSighashType::from(sig.sighash_type);
+            let issue = if found != expected_ecdsa {
+                Some(SignatureIssue::WrongSighash {
+                    expected: expected_ecdsa,
+                    found,
+                })
+            } else if !is_known_key(&key) {
+                Some(SignatureIssue::UnknownKey)
+            } else {
+                let sighash = match context {
+                    Some(Context::Legacy) => Legacy::sighash(psbt, n, ())
+                        .map(|(sighash, _)| Message::from_digest(sighash.to_byte_array())),
+                    Some(Context::Segwitv0) => Segwitv0::sighash(psbt, n, ())
+                        .map(|(sighash, _)| Message::from_digest(sighash.to_byte_array())),
+                    _ => Err(crate::signer::SignerError::InvalidSighash),
+                };
+                match sighash {
+                    Ok(msg) => match self.secp.verify_ecdsa(&msg, &sig.signature, &pk.inner) {
+                        Ok(()) => None,
+                        Err(_) => Some(SignatureIssue::Invalid),
+                    },
+                    Err(_) => Some(SignatureIssue::Unverifiable),
+                }
+            };
+            if issue.is_none() {
+                usable.inputs[n].partial_sigs.insert(*pk, *sig);
+            }
+            report(key, issue);
+        }
+
+        let expected_tap = psbt_input
+            .sighash_type
+            .unwrap_or_else(|| TapSighashType::Default.into());
+        let tap_sighash = |leaf_hash: Option<TapLeafHash>| {
+            if context != Some(Context::Tap) {
+                return None;
+            }
+            Tap::sighash(psbt, n, leaf_hash)
+                .ok()
+                .map(|(sighash, _)| Message::from_digest(sighash.to_byte_array()))
+        };
+
+        let output_key = utxo
+            .as_ref()
+            .filter(|_| context == Some(Context::Tap))
+            .and_then(|utxo| XOnlyPublicKey::from_slice(&utxo.script_pubkey.as_bytes()[2..]).ok());
+        let internal_key =
+            psbt_input
+                .tap_internal_key
+                .or_else(|| match analysis.descriptor.as_ref() {
+                    Some(Descriptor::Tr(tr)) => Some(tr.internal_key().to_x_only_pubkey()),
+                    _ => None,
+                });
+        // without an internal key nor a taproot utxo the signature can't be attributed
+        if let (Some(sig), Some(key)) = (&psbt_input.tap_key_sig, internal_key.or(output_key)) {
+            let key = SignatureKey::TapKey(key);
+            let found = PsbtSighashType::from(sig.sighash_type);
+            let issue = if found != expected_tap {
+                Some(SignatureIssue::WrongSighash {
+                    expected: expected_tap,
+                    found,
+                })
+            } else {
+                match (tap_sighash(None), output_key) {
+                    (Some(msg), Some(output_key)) => {
+                        let verifies = |key: &XOnlyPublicKey| {
+                            self.secp.verify_schnorr(&sig.signature, &msg, key).is_ok()
+                        };
+                        if verifies(&output_key) {
+                            None
+                        } else if internal_key.map_or(false, |internal_key| {
+                            verifies(&internal_key)
+                                || verifies(
+                                    &internal_key
+                                        .tap_tweak(&self.secp, None)
+                                        .0
+                                        .to_x_only_public_key(),
+                                )
+                        }) {
+                            Some(SignatureIssue::WrongTweak)
+                        } else {
+                            Some(SignatureIssue::Invalid)
+                        }
+                    }
+                    _ => Some(SignatureIssue::Unverifiable),
+                }
+            };
+            if issue.is_none() {
+                usable.inputs[n].tap_key_sig = Some(*sig);
+            }
+            report(key, issue);
+        }
+
+        for (&(pk, leaf_hash), sig) in &psbt_input.tap_script_sigs {
+            let key = SignatureKey::TapScript(pk, leaf_hash);
+            let found = PsbtSighashType::from(sig.sighash_type);
+            let issue = if found != expected_tap {
+                Some(SignatureIssue::WrongSighash {
+                    expected: expected_tap,
+                    found,
+                })
+            } else if !is_known_key(&key) {
+                Some(SignatureIssue::UnknownKey)
+            } else {
+                match tap_sighash(Some(leaf_hash)) {
+                    Some(msg) => match self.secp.verify_schnorr(&sig.signature, &msg, &pk) {
+                        Ok(()) => None,
+                        Err(_) => Some(SignatureIssue::Invalid),
+                    },
+                    None => Some(SignatureIssue::Unverifiable),
+                }
+            };
+            if issue.is_none() {
+                usable.inputs[n]
+                    .tap_script_sigs
+                    .insert((pk, leaf_hash), *sig);
+            }
+            report(key, issue);
+        }
+        analysis.signed = signed;
+        analysis.invalid_signatures = invalid_signatures;
+
+        let (descriptor, requirements) = match (analysis.descriptor.as_ref(), requirements) {
+            (Some(descriptor), Some(requirements)) => (descriptor, requirements),
+            _ => return analysis,
+        };
+        let satisfier = PsbtInputSatisfier::new(&usable, n);
+        analysis.finalizable = descriptor.satisfy(&mut TxIn::default(), &satisfier).is_ok();
+        if analysis.finalizable {
+            return analysis;
+        }
+
+        analysis.missing_signatures = requirements
+            .keys
+            .into_iter()
+            .filter(|key| !analysis.signed.contains(key))
+            .collect();
+        analysis.missing_preimages = requirements
+            .preimages
+            .into_iter()
+            .filter(|preimage| match preimage {
+                MissingPreimage::Sha256(hash) => !psbt_input.sha256_preimages.contains_key(hash),
+                MissingPreimage::Hash256(hash) => !psbt_input.hash256_preimages.contains_key(hash),
+                MissingPreimage::Ripemd160(hash) => {
+                    !psbt_input.ripemd160_preimages.contains_key(hash)
+                }
+                MissingPreimage::Hash160(hash) => !psbt_input.hash160_preimages.contains_key(hash),
+            })
+            .collect();
+        let tx = &psbt.unsigned_tx;
+        analysis.timelocks = requirements
+            .after
+            .into_iter()
+            .filter(|&required| {
+                !miniscript::Satisfier::<DefiniteDescriptorKey>::check_after(&satisfier, required)
+            })
+            .map(|required| TimelockObstacle::After {
+                required,
+                lock_time: tx.lock_time,
+            })
+            .chain(
+                requirements
+                    .older
+                    .into_iter()
+                    .filter(|&required| {
+                        !miniscript::Satisfier::<DefiniteDescriptorKey>::check_older(
+                            &satisfier, required,
+                        )
+                    })
+                    .map(|required| TimelockObstacle::Older {
+                        required,
+                        sequence: tx.input[n].sequence,
+                    }),
+            )
+            .collect();
+
+        analysis
+    }
+}
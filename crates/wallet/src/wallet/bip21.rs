@@ -0,0 +1,327 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! BIP-21 payment URIs
+//!
+//! This module implements parsing and serialization of
+//! [BIP-21](https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki) `bitcoin:` URIs. The
+//! [`Wallet`] can create one for its next unused address with [`Wallet::receive_uri`], and
+//! [`TxBuilder::add_recipient_from_uri`] pays one.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk_wallet::wallet::bip21::Bip21Uri;
+//! # use bitcoin::{Amount, Network};
+//! let uri: Bip21Uri =
+//!     "BITCOIN:tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a?amount=0.001&label=Coffee%20shop"
+//!         .parse()?;
+//! assert!(uri.address.is_valid_for_network(Network::Testnet));
+//! assert_eq!(uri.amount, Some(Amount::from_sat(100_000)));
+//! assert_eq!(uri.label.as_deref(), Some("Coffee shop"));
+//! assert_eq!(
+//!     uri.to_string(),
+//!     "bitcoin:tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a?amount=0.001&label=Coffee%20shop"
+//! );
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`TxBuilder::add_recipient_from_uri`]: crate::wallet::tx_builder::TxBuilder::add_recipient_from_uri
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::address::{NetworkUnchecked, ParseError};
+use bitcoin::amount::ParseAmountError;
+use bitcoin::{Address, Amount, Denomination};
+
+use crate::KeychainKind;
+
+use super::Wallet;
+
+const SCHEME: &str = "bitcoin:";
+
+/// A BIP-21 `bitcoin:` URI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bip21Uri {
+    /// The address to pay, its network is checked by whoever uses it
+    pub address: Address<NetworkUnchecked>,
+    /// The amount to pay
+    pub amount: Option<Amount>,
+    /// A label for the address, such as the name of the receiver
+    pub label: Option<String>,
+    /// A message describing the payment
+    pub message: Option<String>,
+    /// The other parameters, percent-decoded and in their original order
+    ///
+    /// None of them starts with `req-`: required parameters which aren't known fail the parsing.
+    pub extra_params: Vec<(String, String)>,
+}
+
+/// Error while parsing a [`Bip21Uri`]
+#[derive(Debug, PartialEq)]
+pub enum Bip21Error {
+    /// The URI doesn't start with `bitcoin:`
+    InvalidScheme,
+    /// The address can't be parsed
+    Address(ParseError),
+    /// The amount can't be parsed
+    Amount(ParseAmountError),
+    /// A parameter is not a `key=value` pair, or isn't correctly percent-encoded
+    InvalidParam(String),
+    /// A parameter appears more than once
+    DuplicateParam(String),
+    /// A `req-` parameter isn't supported
+    UnknownRequiredParam(String),
+}
+
+impl fmt::Display for Bip21Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidScheme => write!(f, "the URI scheme is not `bitcoin:`"),
+            Self::Address(e) => write!(f, "invalid address: {}", e),
+            Self::Amount(e) => write!(f, "invalid amount: {}", e),
+            Self::InvalidParam(param) => write!(f, "invalid parameter `{}`", param),
+            Self::DuplicateParam(key) => write!(f, "duplicate parameter `{}`", key),
+            Self::UnknownRequiredParam(key) => {
+                write!(f, "unknown required parameter `{}`", key)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Bip21Error {}
+
+impl Bip21Uri {
+    /// Create a URI for `address`, without any parameter
+    pub fn new(address: Address<NetworkUnchecked>) -> Self {
+        Self {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+            extra_params: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Bip21Uri {
+    type Err = Bip21Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the scheme is case-insensitive
+        let rest = match s.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &s[SCHEME.len()..],
+            _ => return Err(Bip21Error::InvalidScheme),
+        };
+        let (address, query) = match rest.split_once('?') {
+            Some((address, query)) => (address, Some(query)),
+            None => (rest, None),
+        };
+        let address = Address::from_str(address).map_err(Bip21Error::Address)?;
+        let mut uri = Bip21Uri::new(address);
+
+        for param in query.into_iter().flat_map(|query| query.split('&')) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| Bip21Error::InvalidParam(param.to_string()))?;
+            let key = percent_decode(key).ok_or_else(|| Bip21Error::InvalidParam(param.into()))?;
+            let value =
+                percent_decode(value).ok_or_else(|| Bip21Error::InvalidParam(param.into()))?;
+            match key.as_str() {
+                "amount" => {
+                    if uri.amount.is_some() {
+                        return Err(Bip21Error::DuplicateParam(key));
+                    }
+                    let amount = Amount::from_str_in(&value, Denomination::Bitcoin)
+                        .map_err(Bip21Error::Amount)?;
+                    uri.amount = Some(amount);
+                }
+                "label" | "message" => {
+                    let field = if key == "label" {
+                        &mut uri.label
+                    } else {
+                        &mut uri.message
+                    };
+                    if field.is_some() {
+                        return Err(Bip21Error::DuplicateParam(key));
+                    }
+                    *field = Some(value);
+                }
+                _ if key.starts_with("req-") => {
+                    return Err(Bip21Error::UnknownRequiredParam(key));
+                }
+                _ => uri.extra_params.push((key, value)),
+            }
+        }
+
+        Ok(uri)
+    }
+}
+
+impl fmt::Display for Bip21Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, self.address.assume_checked_ref())?;
+        let mut separator = '?';
+        let mut write_param = |f: &mut fmt::Formatter<'_>, key: &str, value: &str| {
+            write!(
+                f,
+                "{}{}={}",
+                separator,
+                percent_encode(key),
+                percent_encode(value)
+            )?;
+            separator = '&';
+            Ok(())
+        };
+        if let Some(amount) = self.amount {
+            write_param(f, "amount", &amount.to_string_in(Denomination::Bitcoin))?;
+        }
+        if let Some(label) = &self.label {
+            write_param(f, "label", label)?;
+        }
+        if let Some(message) = &self.message {
+            write_param(f, "message", message)?;
+        }
+        for (key, value) in &self.extra_params {
+            write_param(f, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Percent-encode everything but the RFC 3986 unreserved characters.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&alloc::format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decode the percent-encoded UTF-8 string `s`, `None` if it's not valid.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let high = (iter.next()? as char).to_digit(16)?;
+            let low = (iter.next()? as char).to_digit(16)?;
+            bytes.push((high * 16 + low) as u8);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl Wallet {
+    /// Reveal the next unused external address and return a [`Bip21Uri`] requesting `amount`
+    /// to it, with an optional `label`.
+    ///
+    /// Like [`Wallet::next_unused_address`], this stages the revealed address: persist the
+    /// changes so that the address is not given out again.
+    pub fn receive_uri(&mut self, amount: Option<Amount>, label: Option<String>) -> Bip21Uri {
+        let address = self.next_unused_address(KeychainKind::External).address;
+        Bip21Uri {
+            amount,
+            label,
+            ..Bip21Uri::new(address.into_unchecked())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::Network;
+
+    const ADDRESS: &str = "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a";
+
+    #[test]
+    fn parse_and_serialize() {
+        let uri = Bip21Uri::from_str(&alloc::format!(
+            "bitcoin:{}?amount=20.3&label=Luke-Jr&message=Donation%20for%20project%20xyz&foo=bar%26baz",
+            ADDRESS
+        ))
+        .unwrap();
+        assert!(uri.address.is_valid_for_network(Network::Testnet));
+        assert_eq!(uri.amount, Some(Amount::from_sat(2_030_000_000)));
+        assert_eq!(uri.label.as_deref(), Some("Luke-Jr"));
+        assert_eq!(uri.message.as_deref(), Some("Donation for project xyz"));
+        assert_eq!(uri.extra_params, [("foo".into(), "bar&baz".into())]);
+        assert_eq!(Bip21Uri::from_str(&uri.to_string()), Ok(uri));
+
+        let uri = Bip21Uri::from_str(&alloc::format!("bitcoin:{}", ADDRESS)).unwrap();
+        assert_eq!(uri.amount, None);
+        assert_eq!(uri.to_string(), alloc::format!("bitcoin:{}", ADDRESS));
+    }
+
+    #[test]
+    fn uppercase_scheme_and_percent_encoded_label() {
+        let uri = Bip21Uri::from_str(&alloc::format!(
+            "BITCOIN:{}?label=Caf%C3%A9%20%26%20Bar",
+            ADDRESS.to_uppercase()
+        ))
+        .unwrap();
+        assert_eq!(uri.label.as_deref(), Some("Café & Bar"));
+        assert_eq!(
+            uri.to_string(),
+            alloc::format!("bitcoin:{}?label=Caf%C3%A9%20%26%20Bar", ADDRESS)
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse =
+            |params: &str| Bip21Uri::from_str(&alloc::format!("bitcoin:{}{}", ADDRESS, params));
+        assert_eq!(Bip21Uri::from_str(ADDRESS), Err(Bip21Error::InvalidScheme));
+        assert!(matches!(
+            Bip21Uri::from_str("bitcoin:notanaddress"),
+            Err(Bip21Error::Address(_))
+        ));
+        assert!(matches!(parse("?amount=1.2.3"), Err(Bip21Error::Amount(_))));
+        assert_eq!(
+            parse("?amount=1&amount=2"),
+            Err(Bip21Error::DuplicateParam("amount".into()))
+        );
+        assert_eq!(
+            parse("?label"),
+            Err(Bip21Error::InvalidParam("label".into()))
+        );
+        assert_eq!(
+            parse("?label=%ZZ"),
+            Err(Bip21Error::InvalidParam("label=%ZZ".into()))
+        );
+        // unknown required parameters must be rejected, unknown optional ones are kept
+        assert_eq!(
+            parse("?req-somethingyoudontunderstand=50"),
+            Err(Bip21Error::UnknownRequiredParam(
+                "req-somethingyoudontunderstand".into()
+            ))
+        );
+        let uri = parse("?somethingyoudontunderstand=50&somethingelseyoudontget=999").unwrap();
+        assert_eq!(
+            uri.to_string(),
+            alloc::format!(
+                "bitcoin:{}?somethingyoudontunderstand=50&somethingelseyoudontget=999",
+                ADDRESS
+            )
+        );
+    }
+}
@@ -0,0 +1,553 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Generic signed messages, per [`BIP322`]
+//!
+//! A BIP322 signature proves the ownership of an address: it is the witness of a virtual
+//! `to_sign` transaction spending an output of the address, committing to the message, which
+//! can't be broadcast. [`Wallet::sign_message`] signs a message with the keys of one of the
+//! wallet's addresses and [`verify_message`] checks a signature without any private material.
+//!
+//! Segwit addresses are signed in the "simple" format, which is the witness alone. P2PKH
+//! addresses can't be signed in this format, they use the legacy `signmessage` format of Bitcoin
+//! Core instead. Only P2WPKH and P2TR key path signatures can be verified.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk_wallet::wallet::bip322::{verify_message, Bip322Signature};
+//! # use bitcoin::Address;
+//! # use core::str::FromStr;
+//! let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")?.assume_checked();
+//! let signature = Bip322Signature::from_str(
+//!     "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=",
+//! )?;
+//! assert!(verify_message(&address, b"Hello World", &signature).is_ok());
+//! assert!(verify_message(&address, b"Hello", &signature).is_err());
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`BIP322`]: https://github.com/bitcoin/bips/blob/master/bip-0322.mediawiki
+
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::base64::prelude::{Engine as _, BASE64_STANDARD};
+use bitcoin::bip32::ChildNumber;
+use bitcoin::hashes::{sha256, sha256d, Hash, HashEngine};
+use bitcoin::key::{CompressedPublicKey, PublicKey};
+use bitcoin::opcodes::all::{OP_PUSHBYTES_0, OP_RETURN};
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::{Message, Secp256k1, XOnlyPublicKey};
+use bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoin::sign_message::MessageSignature;
+use bitcoin::{
+    absolute, consensus, ecdsa, taproot, transaction, Address, Amount, OutPoint, Script, ScriptBuf,
+    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use miniscript::descriptor::{DescriptorSecretKey, Wildcard};
+use miniscript::psbt::{PsbtExt, PsbtInputSatisfier};
+use miniscript::Descriptor;
+
+use crate::signer::{SignOptions, SignerError};
+use crate::KeychainKind;
+
+use super::Wallet;
+
+/// The tag of the hash of the message committed to by the `to_spend` transaction
+const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+/// The prefix of the messages signed in the legacy format
+const LEGACY_PREFIX: &[u8] = b"\x18Bitcoin Signed Message:\n";
+
+/// A BIP322 signature
+///
+/// Its string form is the base64 encoding used by other implementations, such as
+/// `bitcoin-cli verifymessage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bip322Signature {
+    /// A signature in the simple format: the witness of the `to_sign` transaction
+    Simple(Witness),
+    /// A signature in the legacy `signmessage` format, for P2PKH addresses
+    Legacy(MessageSignature),
+}
+
+impl fmt::Display for Bip322Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = match self {
+            Self::Simple(witness) => consensus::serialize(witness),
+            Self::Legacy(signature) => signature.serialize().to_vec(),
+        };
+        f.write_str(&BASE64_STANDARD.encode(bytes))
+    }
+}
+
+impl FromStr for Bip322Signature {
+    type Err = Bip322Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = BASE64_STANDARD.decode(s).map_err(Bip322Error::Base64)?;
+        // the header byte of a legacy signature is between 27 and 42, no sensible witness has
+        // this many elements in 65 bytes
+        if bytes.len() == 65 && (27..=42).contains(&bytes[0]) {
+            return MessageSignature::from_slice(&bytes)
+                .map(Self::Legacy)
+                .map_err(|_| Bip322Error::MalformedSignature);
+        }
+        consensus::deserialize(&bytes)
+            .map(Self::Simple)
+            .map_err(|_| Bip322Error::MalformedSignature)
+    }
+}
+
+/// The address whose keys sign a message, see [`Wallet::sign_message`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageAddress {
+    /// An address of the wallet, which must have been revealed
+    Address(Address),
+    /// The address at a derivation index of a keychain
+    Derivation(KeychainKind, u32),
+}
+
+impl From<Address> for MessageAddress {
+    fn from(address: Address) -> Self {
+        Self::Address(address)
+    }
+}
+
+impl From<(KeychainKind, u32)> for MessageAddress {
+    fn from((keychain, index): (KeychainKind, u32)) -> Self {
+        Self::Derivation(keychain, index)
+    }
+}
+
+/// Error signing or verifying a BIP322 message
+#[derive(Debug)]
+pub enum Bip322Error {
+    /// The address doesn't belong to the wallet
+    UnknownAddress,
+    /// The script of the address can't be signed or verified
+    UnsupportedScript(ScriptBuf),
+    /// The wallet doesn't have the keys to sign for the address
+    MissingKeys,
+    /// A signer failed
+    Signer(SignerError),
+    /// The signature isn't valid base64
+    Base64(bitcoin::base64::DecodeError),
+    /// The signature isn't well formed for the address
+    MalformedSignature,
+    /// The signature doesn't sign the message with the keys of the address
+    InvalidSignature,
+}
+
+impl fmt::Display for Bip322Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownAddress => write!(f, "The address doesn't belong to the wallet"),
+            Self::UnsupportedScript(script) => {
+                write!(f, "Messages can't be signed for the script {}", script)
+            }
+            Self::MissingKeys => write!(f, "The wallet can't sign for the address"),
+            Self::Signer(err) => write!(f, "Signer error: {}", err),
+            Self::Base64(err) => write!(f, "Invalid base64: {}", err),
+            Self::MalformedSignature => write!(f, "Malformed signature"),
+            Self::InvalidSignature => write!(f, "Invalid signature"),
+        }
+    }
+}
+
+impl From<SignerError> for Bip322Error {
+    fn from(err: SignerError) -> Self {
+        Bip322Error::Signer(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Bip322Error {}
+
+/// The tagged hash of `message` committed to by the `to_spend` transaction
+pub fn message_hash(message: &[u8]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(MESSAGE_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message);
+    sha256::Hash::from_engine(engine)
+}
+
+/// The virtual `to_spend` transaction, paying the `message` to `script_pubkey`
+pub fn to_spend(script_pubkey: &Script, message: &[u8]) -> Transaction {
+    let script_sig = bitcoin::script::Builder::new()
+        .push_opcode(OP_PUSHBYTES_0)
+        .push_slice(message_hash(message).to_byte_array())
+        .into_script();
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFFFFFF),
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.into(),
+        }],
+    }
+}
+
+/// The virtual `to_sign` transaction, spending the output of the `to_spend` transaction, with an
+/// empty witness
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: bitcoin::script::Builder::new()
+                .push_opcode(OP_RETURN)
+                .into_script(),
+        }],
+    }
+}
+
+/// The hash signed by a legacy signature of `message`
+fn legacy_message_hash(message: &[u8]) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(LEGACY_PREFIX);
+    engine.input(&consensus::serialize(&consensus::encode::VarInt::from(
+        message.len(),
+    )));
+    engine.input(message);
+    sha256d::Hash::from_engine(engine)
+}
+
+/// Verify that `signature` signs `message` for `address`
+///
+/// This fails with [`Bip322Error::UnsupportedScript`] for the signatures that can't be checked
+/// here: those of addresses other than P2PKH, P2WPKH and P2TR, and the P2TR script path ones.
+pub fn verify_message(
+    address: &Address,
+    message: &[u8],
+    signature: &Bip322Signature,
+) -> Result<(), Bip322Error> {
+    let secp = Secp256k1::verification_only();
+    let script_pubkey = address.script_pubkey();
+
+    let witness = match signature {
+        Bip322Signature::Legacy(signature) => {
+            if !script_pubkey.is_p2pkh() {
+                return Err(Bip322Error::MalformedSignature);
+            }
+            return match signature.is_signed_by_address(
+                &secp,
+                address,
+                legacy_message_hash(message),
+            ) {
+                Ok(true) => Ok(()),
+                _ => Err(Bip322Error::InvalidSignature),
+            };
+        }
+        Bip322Signature::Simple(witness) => witness,
+    };
+
+    let to_spend = to_spend(&script_pubkey, message);
+    let mut to_sign = to_sign(&to_spend);
+    to_sign.input[0].witness = witness.clone();
+    let mut cache = SighashCache::new(&to_sign);
+
+    if script_pubkey.is_p2wpkh() {
+        let (signature, public_key) = match (witness.len(), witness.nth(0), witness.nth(1)) {
+            (2, Some(signature), Some(public_key)) => (signature, public_key),
+            _ => return Err(Bip322Error::MalformedSignature),
+        };
+        let signature =
+            ecdsa::Signature::from_slice(signature).map_err(|_| Bip322Error::MalformedSignature)?;
+        let public_key = CompressedPublicKey::from_slice(public_key)
+            .map_err(|_| Bip322Error::MalformedSignature)?;
+        if ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()) != script_pubkey {
+            return Err(Bip322Error::InvalidSignature);
+        }
+        let sighash = cache
+            .p2wpkh_signature_hash(0, &script_pubkey, Amount::ZERO, signature.sighash_type)
+            .map_err(|_| Bip322Error::MalformedSignature)?;
+        secp.verify_ecdsa(
+            &Message::from_digest(sighash.to_byte_array()),
+            &signature.signature,
+            &public_key.0,
+        )
+        .map_err(|_| Bip322Error::InvalidSignature)
+    } else if script_pubkey.is_p2tr() {
+        let signature = match (witness.len(), witness.nth(0)) {
+            (1, Some(signature)) => signature,
+            _ => return Err(Bip322Error::UnsupportedScript(script_pubkey)),
+        };
+        let signature = taproot::Signature::from_slice(signature)
+            .map_err(|_| Bip322Error::MalformedSignature)?;
+        let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+            .map_err(|_| Bip322Error::InvalidSignature)?;
+        let sighash = cache
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&to_spend.output),
+                signature.sighash_type,
+            )
+            .map_err(|_| Bip322Error::MalformedSignature)?;
+        secp.verify_schnorr(
+            &signature.signature,
+            &Message::from_digest(sighash.to_byte_array()),
+            &output_key,
+        )
+        .map_err(|_| Bip322Error::InvalidSignature)
+    } else {
+        Err(Bip322Error::UnsupportedScript(script_pubkey))
+    }
+}
+
+impl Wallet {
+    /// Sign `message` with the keys of one of the wallet's addresses, per [`BIP322`]
+    ///
+    /// Segwit addresses get a [`Bip322Signature::Simple`] signature, made by the wallet's signers
+    /// like the input of a transaction. P2PKH addresses get a [`Bip322Signature::Legacy`]
+    /// signature, which requires the private key of the address to be in the descriptor.
+    /// Nested segwit addresses aren't supported.
+    ///
+    /// [`BIP322`]: https://github.com/bitcoin/bips/blob/master/bip-0322.mediawiki
+    pub fn sign_message(
+        &self,
+        address: impl Into<MessageAddress>,
+        message: &[u8],
+    ) -> Result<Bip322Signature, Bip322Error> {
+        let (keychain, index) = match address.into() {
+            MessageAddress::Address(address) => self
+                .derivation_of_spk(&address.script_pubkey())
+                .ok_or(Bip322Error::UnknownAddress)?,
+            MessageAddress::Derivation(keychain, index) => (self.map_keychain(keychain), index),
+        };
+        let descriptor = self
+            .public_descriptor(keychain)
+            .at_derivation_index(index)
+            .map_err(|_| Bip322Error::UnknownAddress)?;
+        let script_pubkey = descriptor.script_pubkey();
+
+        if script_pubkey.is_p2pkh() {
+            return self.sign_legacy_message(keychain, index, &script_pubkey, message);
+        }
+        if !script_pubkey.is_witness_program() {
+            return Err(Bip322Error::UnsupportedScript(script_pubkey));
+        }
+
+        let to_spend = to_spend(&script_pubkey, message);
+        let mut psbt = Psbt::from_unsigned_tx(to_sign(&to_spend))
+            .expect("the to_sign transaction is unsigned");
+        psbt.inputs[0].witness_utxo = Some(to_spend.output[0].clone());
+        psbt.update_input_with_descriptor(0, &descriptor)
+            .map_err(|_| Bip322Error::UnsupportedScript(script_pubkey.clone()))?;
+        self.sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                try_finalize: false,
+                ..Default::default()
+            },
+        )?;
+
+        let mut txin = TxIn::default();
+        descriptor
+            .satisfy(&mut txin, PsbtInputSatisfier::new(&psbt, 0))
+            .map_err(|_| Bip322Error::MissingKeys)?;
+        Ok(Bip322Signature::Simple(txin.witness))
+    }
+
+    /// Sign `message` in the legacy format with the private key of the P2PKH address at `index`
+    fn sign_legacy_message(
+        &self,
+        keychain: KeychainKind,
+        index: u32,
+        script_pubkey: &Script,
+        message: &[u8],
+    ) -> Result<Bip322Signature, Bip322Error> {
+        let key = match self.public_descriptor(keychain) {
+            Descriptor::Pkh(pkh) => pkh.as_inner().clone(),
+            _ => return Err(Bip322Error::UnsupportedScript(script_pubkey.into())),
+        };
+        let private_key = match self.keymap(keychain).get(&key) {
+            Some(DescriptorSecretKey::Single(single)) => single.key,
+            Some(DescriptorSecretKey::XPrv(xkey)) => {
+                let path = match xkey.wildcard {
+                    Wildcard::None => xkey.derivation_path.clone(),
+                    Wildcard::Unhardened => xkey.derivation_path.child(
+                        ChildNumber::from_normal_idx(index)
+                            .map_err(|_| Bip322Error::MissingKeys)?,
+                    ),
+                    Wildcard::Hardened => xkey.derivation_path.child(
+                        ChildNumber::from_hardened_idx(index)
+                            .map_err(|_| Bip322Error::MissingKeys)?,
+                    ),
+                };
+                xkey.xkey
+                    .derive_priv(&self.secp, &path)
+                    .map_err(|_| Bip322Error::MissingKeys)?
+                    .to_priv()
+            }
+            _ => return Err(Bip322Error::MissingKeys),
+        };
+        if ScriptBuf::new_p2pkh(
+            &PublicKey::from_private_key(&self.secp, &private_key).pubkey_hash(),
+        ) != *script_pubkey
+        {
+            return Err(Bip322Error::MissingKeys);
+        }
+
+        let message = Message::from_digest(legacy_message_hash(message).to_byte_array());
+        let signature = self
+            .secp
+            .sign_ecdsa_recoverable(&message, &private_key.inner);
+        Ok(Bip322Signature::Legacy(MessageSignature::new(
+            signature,
+            private_key.compressed,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+    use assert_matches::assert_matches;
+    use bitcoin::hashes::hex::FromHex;
+
+    const P2WPKH: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const P2TR: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+
+    fn address(s: &str) -> Address {
+        Address::from_str(s).unwrap().assume_checked()
+    }
+
+    #[test]
+    fn message_hashes() {
+        assert_eq!(
+            message_hash(b"").to_byte_array(),
+            <[u8; 32]>::from_hex(
+                "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            message_hash(b"Hello World").to_byte_array(),
+            <[u8; 32]>::from_hex(
+                "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn virtual_transactions() {
+        let script_pubkey = address(P2WPKH).script_pubkey();
+        for (message, to_spend_txid, to_sign_txid) in [
+            (
+                &b""[..],
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+                "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6",
+            ),
+            (
+                &b"Hello World"[..],
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+                "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf",
+            ),
+        ] {
+            let to_spend = to_spend(&script_pubkey, message);
+            assert_eq!(to_spend.compute_txid().to_string(), to_spend_txid);
+            assert_eq!(to_sign(&to_spend).compute_txid().to_string(), to_sign_txid);
+        }
+    }
+
+    #[test]
+    fn verify_test_vectors() {
+        for (address_str, message, signature) in [
+            (
+                P2WPKH,
+                &b""[..],
+                "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=",
+            ),
+            (
+                P2WPKH,
+                &b"Hello World"[..],
+                "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=",
+            ),
+            (
+                P2WPKH,
+                &b"Hello World"[..],
+                "AkgwRQIhAOzyynlqt93lOKJr+wmmxIens//zPzl9tqIOua93wO6MAiBi5n5EyAcPScOjf1lAqIUIQtr3zKNeavYabHyR8eGhowEhAsfxIAMZZEKUPYWI4BruhAQjzFT8FSFSajuFwrDL1Yhy",
+            ),
+            (
+                P2TR,
+                &b"Hello World"[..],
+                "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==",
+            ),
+        ] {
+            let signature = Bip322Signature::from_str(signature).unwrap();
+            assert_eq!(
+                BASE64_STANDARD.decode(signature.to_string()).unwrap(),
+                consensus::serialize(match &signature {
+                    Bip322Signature::Simple(witness) => witness,
+                    _ => panic!("expected a simple signature"),
+                })
+            );
+            let address = address(address_str);
+            verify_message(&address, message, &signature).unwrap();
+            assert_matches!(
+                verify_message(&address, b"Hello", &signature),
+                Err(Bip322Error::InvalidSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_signature() {
+        let message = "This is an example of a signed message.";
+        assert_eq!(
+            legacy_message_hash(message.as_bytes()),
+            bitcoin::sign_message::signed_msg_hash(message)
+        );
+
+        let secp = Secp256k1::new();
+        let private_key =
+            bitcoin::PrivateKey::from_wif("L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k")
+                .unwrap();
+        let p2pkh = Address::p2pkh(
+            PublicKey::from_private_key(&secp, &private_key),
+            bitcoin::Network::Bitcoin,
+        );
+        let digest = Message::from_digest(legacy_message_hash(message.as_bytes()).to_byte_array());
+        let signature = Bip322Signature::Legacy(MessageSignature::new(
+            secp.sign_ecdsa_recoverable(&digest, &private_key.inner),
+            true,
+        ));
+        let signature = Bip322Signature::from_str(&signature.to_string()).unwrap();
+        assert_matches!(signature, Bip322Signature::Legacy(_));
+        verify_message(&p2pkh, message.as_bytes(), &signature).unwrap();
+        assert_matches!(
+            verify_message(&p2pkh, b"Hello", &signature),
+            Err(Bip322Error::InvalidSignature)
+        );
+        assert_matches!(
+            verify_message(&address(P2WPKH), message.as_bytes(), &signature),
+            Err(Bip322Error::MalformedSignature)
+        );
+    }
+}
@@ -0,0 +1,43 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! The point of the chain before which the wallet has no history, see [`Wallet::set_birthday`]
+
+use bdk_chain::Append;
+pub use bdk_chain::BlockTimeOrHeight;
+
+use super::{ChangeSet, Wallet};
+
+impl Wallet {
+    /// Set the birthday of the wallet, the block height or the time before which it has no
+    /// history, such as when its keys were generated. The birthday is persisted.
+    ///
+    /// The birthday is a hint for the chain sources which scan the blocks: they can start from it
+    /// instead of the genesis block, for example with the `start_height` of the
+    /// `bdk_bitcoind_rpc` emitters, computed with `bdk_bitcoind_rpc::birthday_height`. The wallet
+    /// itself doesn't use it: the blocks and transactions applied to the wallet are all kept,
+    /// whether they are before or after the birthday, so rescanning from an earlier height still
+    /// finds the history the birthday skipped.
+    ///
+    /// The history before the birthday is left unscanned on purpose, see
+    /// [`HealthReport::birthday`](super::HealthReport::birthday).
+    pub fn set_birthday(&mut self, birthday: BlockTimeOrHeight) {
+        self.birthday = Some(birthday);
+        self.stage.append(ChangeSet {
+            birthday: Some(birthday),
+            ..Default::default()
+        });
+    }
+
+    /// The birthday set with [`Wallet::set_birthday`], if any.
+    pub fn birthday(&self) -> Option<BlockTimeOrHeight> {
+        self.birthday
+    }
+}
@@ -0,0 +1,228 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! The broadcast journal of the wallet, see [`Wallet::record_broadcast`]
+
+use alloc::string::ToString;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::fmt;
+use core::ops::RangeBounds;
+
+#[cfg(feature = "std")]
+use bdk_chain::spk_client::{BackendError, BroadcastError};
+use bdk_chain::Append;
+pub use bdk_chain::{BroadcastOutcome, BroadcastRecord};
+#[cfg(feature = "std")]
+use bitcoin::Transaction;
+use bitcoin::Txid;
+
+use super::{ChangeSet, Wallet};
+
+/// Error returned by [`Wallet::broadcast_package`]
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Debug)]
+pub struct PackageBroadcastError {
+    /// The first transaction which wasn't broadcast
+    pub txid: Txid,
+    /// Why it wasn't broadcast
+    pub error: BroadcastError<BackendError>,
+    /// The transactions which were broadcast, in the order of the broadcast
+    pub broadcast: Vec<Txid>,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for PackageBroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The transaction {} of the package wasn't broadcast: {}",
+            self.txid, self.error
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PackageBroadcastError {}
+
+/// `txs` sorted so that the parents come before their children, in their order otherwise
+#[cfg(feature = "std")]
+fn parents_first(txs: &[Transaction]) -> Vec<Transaction> {
+    let mut pending = txs.to_vec();
+    let mut sorted = Vec::with_capacity(txs.len());
+    while !pending.is_empty() {
+        let txids = pending
+            .iter()
+            .map(|tx| tx.compute_txid())
+            .collect::<Vec<_>>();
+        // a transaction can't be its own ancestor, there is always one without pending parents
+        let position = pending
+            .iter()
+            .position(|tx| {
+                tx.input
+                    .iter()
+                    .all(|txin| !txids.contains(&txin.previous_output.txid))
+            })
+            .expect("the transactions are acyclic");
+        sorted.push(pending.remove(position));
+    }
+    sorted
+}
+
+impl Wallet {
+    /// Record that the transaction `txid` was broadcast through `backend` at `timestamp`, in unix
+    /// epoch seconds, with the given `result`.
+    ///
+    /// The journal is append-only: every attempt is kept, in the order it is recorded, and is
+    /// staged and persisted like every other change of the wallet. Discarding the staged changes
+    /// doesn't remove it, see [`Wallet::discard_staged`].
+    ///
+    /// A rejected broadcast releases the inputs reserved by the transaction, see
+    /// [`TxBuilder::reserve_inputs`](super::tx_builder::TxBuilder::reserve_inputs).
+    pub fn record_broadcast(
+        &mut self,
+        txid: Txid,
+        backend: &str,
+        result: BroadcastOutcome,
+        timestamp: u64,
+    ) {
+        let record = BroadcastRecord {
+            txid,
+            backend: backend.to_string(),
+            outcome: result,
+            timestamp,
+        };
+        if let BroadcastOutcome::Rejected(_) = record.outcome {
+            self.release_reservation(txid);
+        }
+        self.broadcasts.push(record.clone());
+        self.stage.append(ChangeSet {
+            broadcasts: [record].into(),
+            ..Default::default()
+        });
+    }
+
+    /// Broadcast `tx` through `client` and record the outcome with
+    /// [`record_broadcast`](Self::record_broadcast), under the name of the client and the current
+    /// time.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn broadcast_with<B>(
+        &mut self,
+        client: &B,
+        tx: &bitcoin::Transaction,
+    ) -> Result<Txid, bdk_chain::spk_client::BroadcastError<bdk_chain::spk_client::BackendError>>
+    where
+        B: bdk_chain::spk_client::BroadcastBackend + ?Sized,
+    {
+        let result = client.broadcast(tx);
+        let outcome = match &result {
+            Ok(_) => BroadcastOutcome::Accepted,
+            Err(err) => BroadcastOutcome::Rejected(err.to_string()),
+        };
+        let timestamp = std::time::UNIX_EPOCH
+            .elapsed()
+            .expect("the clock is after the unix epoch")
+            .as_secs();
+        self.record_broadcast(tx.compute_txid(), client.name(), outcome, timestamp);
+        result
+    }
+
+    /// Broadcast the package `txs` through `client`, parents first, such as the transactions of
+    /// [`Wallet::build_chain`] once signed, and record the outcomes like
+    /// [`broadcast_with`](Self::broadcast_with).
+    ///
+    /// The transactions are sorted so that a parent is broadcast before its children, and
+    /// submitted with [`BroadcastBackend::broadcast_package`]. If one is rejected, the ones
+    /// which weren't broadcast are abandoned: their reservations are released, see
+    /// [`TxBuilder::reserve_inputs`]. The txids are returned in the order of the broadcast.
+    ///
+    /// [`BroadcastBackend::broadcast_package`]: bdk_chain::spk_client::BroadcastBackend::broadcast_package
+    /// [`TxBuilder::reserve_inputs`]: super::tx_builder::TxBuilder::reserve_inputs
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn broadcast_package<B>(
+        &mut self,
+        client: &B,
+        txs: &[Transaction],
+    ) -> Result<Vec<Txid>, PackageBroadcastError>
+    where
+        B: bdk_chain::spk_client::BroadcastBackend + ?Sized,
+    {
+        let txs = parents_first(txs);
+        let results = client.broadcast_package(&txs);
+        let timestamp = std::time::UNIX_EPOCH
+            .elapsed()
+            .expect("the clock is after the unix epoch")
+            .as_secs();
+
+        let mut broadcast = Vec::with_capacity(txs.len());
+        let mut failure = None;
+        for (tx, result) in txs.iter().zip(results) {
+            let txid = tx.compute_txid();
+            let outcome = match result {
+                Ok(_) => {
+                    broadcast.push(txid);
+                    BroadcastOutcome::Accepted
+                }
+                Err(error) => {
+                    let outcome = BroadcastOutcome::Rejected(error.to_string());
+                    if failure.is_none() {
+                        failure = Some((txid, error));
+                    }
+                    outcome
+                }
+            };
+            self.record_broadcast(txid, client.name(), outcome, timestamp);
+        }
+        match failure {
+            None if broadcast.len() == txs.len() => Ok(broadcast),
+            failure => {
+                for tx in &txs {
+                    let txid = tx.compute_txid();
+                    if !broadcast.contains(&txid) {
+                        self.release_reservation(txid);
+                    }
+                }
+                let (txid, error) = failure.unwrap_or_else(|| {
+                    let txid = txs[broadcast.len()].compute_txid();
+                    (
+                        txid,
+                        BroadcastError::Rejected("not broadcast by the chain source".to_string()),
+                    )
+                });
+                Err(PackageBroadcastError {
+                    txid,
+                    error,
+                    broadcast,
+                })
+            }
+        }
+    }
+
+    /// The broadcast attempts of the transaction `txid`, in the order they were recorded.
+    pub fn broadcast_history(&self, txid: Txid) -> impl Iterator<Item = &BroadcastRecord> + '_ {
+        self.broadcasts
+            .iter()
+            .filter(move |record| record.txid == txid)
+    }
+
+    /// The broadcast attempts with a timestamp in `range`, in the order they were recorded.
+    pub fn all_broadcasts<R>(&self, range: R) -> impl Iterator<Item = &BroadcastRecord> + '_
+    where
+        R: RangeBounds<u64> + 'static,
+    {
+        self.broadcasts
+            .iter()
+            .filter(move |record| range.contains(&record.timestamp))
+    }
+}
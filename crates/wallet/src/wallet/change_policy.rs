@@ -0,0 +1,200 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Which internal address receives the change, see [`Wallet::set_change_address_policy`]
+
+use core::fmt;
+
+use bdk_chain::Append;
+pub use bdk_chain::ChangeAddressPolicy;
+use bitcoin::bip32::ChildNumber;
+use bitcoin::ScriptBuf;
+
+use super::{ChangeSet, RevealGuardError, Wallet};
+use crate::KeychainKind;
+
+/// Error of [`Wallet::set_change_address_policy`] and
+/// [`TxBuilder::change_address_policy`](super::tx_builder::TxBuilder::change_address_policy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeAddressPolicyError {
+    /// [`ChangeAddressPolicy::RotateWithin`] needs at least one address
+    EmptyRotation,
+    /// The index is hardened, the change addresses are derived at non-hardened indices
+    HardenedIndex(u32),
+    /// Revealing the addresses of the policy would exceed the limit set with
+    /// [`Wallet::set_reveal_guard`]
+    RevealGuard(RevealGuardError),
+}
+
+impl fmt::Display for ChangeAddressPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyRotation => write!(f, "Cannot rotate within zero addresses"),
+            Self::HardenedIndex(index) => {
+                write!(f, "The change address index {} is hardened", index)
+            }
+            Self::RevealGuard(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ChangeAddressPolicyError {}
+
+impl From<RevealGuardError> for ChangeAddressPolicyError {
+    fn from(e: RevealGuardError) -> Self {
+        Self::RevealGuard(e)
+    }
+}
+
+impl Wallet {
+    /// Set how the internal address of the change outputs is picked, the policy is persisted.
+    ///
+    /// With [`ChangeAddressPolicy::FreshAlways`], the default, every transaction uses the first
+    /// unused internal address, so no change address is ever reused unless a transaction is
+    /// canceled. [`ChangeAddressPolicy::RotateWithin`] cycles through the first `n` internal
+    /// addresses in order, and [`ChangeAddressPolicy::Fixed`] always uses the same one. The
+    /// addresses of these two policies are revealed when they are first used, so the last
+    /// revealed index never goes beyond the policy, and a scan with a gap limit of at least `n`
+    /// (or `index + 1`) finds all of them.
+    ///
+    /// The policy applies to the change of [`Wallet::build_tx`], to the drain output of
+    /// [`Wallet::build_cancel`] and to the child of [`Wallet::build_cpfp`], it can be overridden
+    /// for a single transaction with [`TxBuilder::change_address_policy`]. Wallets without an
+    /// internal descriptor send their change to their external addresses, the policy then
+    /// applies to them.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the policy has no address or a hardened index, or if a reveal guard is set (see
+    /// [`Wallet::set_reveal_guard`]) and revealing all the addresses of the policy would exceed
+    /// it.
+    ///
+    /// [`TxBuilder::change_address_policy`]: super::tx_builder::TxBuilder::change_address_policy
+    pub fn set_change_address_policy(
+        &mut self,
+        policy: ChangeAddressPolicy,
+    ) -> Result<(), ChangeAddressPolicyError> {
+        self.check_change_address_policy(policy)?;
+        self.change_address_policy = policy;
+        self.stage.append(ChangeSet {
+            change_address_policy: Some(policy),
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    /// The policy set with [`Wallet::set_change_address_policy`].
+    pub fn change_address_policy(&self) -> ChangeAddressPolicy {
+        self.change_address_policy
+    }
+
+    /// Check that the addresses of `policy` can be revealed.
+    pub(crate) fn check_change_address_policy(
+        &self,
+        policy: ChangeAddressPolicy,
+    ) -> Result<(), ChangeAddressPolicyError> {
+        let last_index = match policy {
+            ChangeAddressPolicy::FreshAlways => return Ok(()),
+            ChangeAddressPolicy::RotateWithin(0) => {
+                return Err(ChangeAddressPolicyError::EmptyRotation)
+            }
+            ChangeAddressPolicy::RotateWithin(n) => n - 1,
+            ChangeAddressPolicy::Fixed(index) => index,
+        };
+        if ChildNumber::from_normal_idx(last_index).is_err() {
+            return Err(ChangeAddressPolicyError::HardenedIndex(last_index));
+        }
+
+        let max = match self.reveal_guard {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        let keychain = self.map_keychain(KeychainKind::Internal);
+        let index = &self.indexed_graph.index;
+        let is_revealed = index
+            .last_revealed_index(&keychain)
+            .map_or(false, |last_revealed| last_index <= last_revealed);
+        let last_funded = index
+            .keychain_outpoints(&keychain)
+            .next_back()
+            .map(|(last_funded, _)| last_funded);
+        let gap = match last_funded {
+            Some(last_funded) => last_index.saturating_sub(last_funded),
+            None => last_index + 1,
+        };
+        if !is_revealed && gap > max {
+            return Err(RevealGuardError::GapLimit { keychain, gap, max }.into());
+        }
+        Ok(())
+    }
+
+    /// The derivation index and script pubkey of the next change output under `policy`,
+    /// revealing and marking it used.
+    pub(crate) fn next_change_spk(&mut self, policy: ChangeAddressPolicy) -> (u32, ScriptBuf) {
+        let keychain = self.map_keychain(KeychainKind::Internal);
+        let index = match policy {
+            ChangeAddressPolicy::FreshAlways => {
+                let ((index, spk), index_changeset) = self
+                    .indexed_graph
+                    .index
+                    .next_unused_spk(&keychain)
+                    .expect("keychain must exist");
+                self.stage.append(index_changeset.into());
+                self.mark_used(keychain, index);
+                return (index, spk);
+            }
+            ChangeAddressPolicy::RotateWithin(n) => {
+                let index = self.change_rotation % n;
+                self.change_rotation = (index + 1) % n;
+                self.stage.append(ChangeSet {
+                    change_rotation: Some(self.change_rotation),
+                    ..Default::default()
+                });
+                index
+            }
+            ChangeAddressPolicy::Fixed(index) => index,
+        };
+        let address = self.peek_address(keychain, index);
+        let (_, index_changeset) = self
+            .indexed_graph
+            .index
+            .reveal_to_target(&keychain, address.index)
+            .expect("keychain must exist");
+        self.stage.append(index_changeset.into());
+        self.mark_used(keychain, address.index);
+        (address.index, address.script_pubkey())
+    }
+
+    /// Like [`Wallet::next_change_spk`], without changing the wallet.
+    pub(crate) fn peek_change_spk(&self, policy: ChangeAddressPolicy) -> (u32, ScriptBuf) {
+        let keychain = self.map_keychain(KeychainKind::Internal);
+        let index = match policy {
+            ChangeAddressPolicy::FreshAlways => {
+                let index = &self.indexed_graph.index;
+                match index.unused_keychain_spks(&keychain).next() {
+                    Some((index, spk)) => return (index, ScriptBuf::from(spk)),
+                    None => index.next_index(&keychain).expect("keychain must exist").0,
+                }
+            }
+            ChangeAddressPolicy::RotateWithin(n) => self.change_rotation % n,
+            ChangeAddressPolicy::Fixed(index) => index,
+        };
+        let address = self.peek_address(keychain, index);
+        (address.index, address.script_pubkey())
+    }
+
+    /// Whether the change would go to the static address of the wallet: it has no internal
+    /// descriptor and its external descriptor has no wildcard, see [`Wallet::create_single`].
+    pub(crate) fn has_static_change(&self) -> bool {
+        let keychain = self.map_keychain(KeychainKind::Internal);
+        keychain == KeychainKind::External && !self.public_descriptor(keychain).has_wildcard()
+    }
+}
@@ -0,0 +1,128 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Unspent outputs with the details shown by coin control interfaces
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Deref;
+
+use bdk_chain::ConfirmationTime;
+use bitcoin::Address;
+
+use super::labels::LabelRef;
+use super::Wallet;
+use crate::{KeychainKind, LocalOutput};
+
+/// An unspent output of the wallet, see [`Wallet::list_unspent_detailed`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxoDetails {
+    /// The output
+    pub utxo: LocalOutput,
+    /// Number of confirmations at the current tip, 0 if unconfirmed
+    pub confirmations: u32,
+    /// The address of the output, `None` if its script pubkey has no address form
+    pub address: Option<Address>,
+    /// The label of the output, or else of its address
+    pub label: Option<String>,
+    /// Whether the output was created by a transaction spending only outputs of the wallet,
+    /// usually the change of one of its payments
+    pub is_change: bool,
+}
+
+/// A list of [`UtxoDetails`], see [`Wallet::list_unspent_detailed`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UtxoList(Vec<UtxoDetails>);
+
+impl UtxoList {
+    /// Sort the outputs from the largest to the smallest value.
+    pub fn by_value_desc(mut self) -> Self {
+        self.0
+            .sort_by_key(|utxo| core::cmp::Reverse(utxo.utxo.txout.value));
+        self
+    }
+
+    /// Keep the outputs with at least `confirmations` confirmations.
+    pub fn min_confirmations(mut self, confirmations: u32) -> Self {
+        self.0.retain(|utxo| utxo.confirmations >= confirmations);
+        self
+    }
+
+    /// Keep the outputs of `keychain`.
+    pub fn keychain(mut self, keychain: KeychainKind) -> Self {
+        self.0.retain(|utxo| utxo.utxo.keychain == keychain);
+        self
+    }
+
+    /// Get the outputs.
+    pub fn into_vec(self) -> Vec<UtxoDetails> {
+        self.0
+    }
+}
+
+impl Deref for UtxoList {
+    type Target = [UtxoDetails];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl IntoIterator for UtxoList {
+    type Item = UtxoDetails;
+    type IntoIter = alloc::vec::IntoIter<UtxoDetails>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl Wallet {
+    /// List the unspent outputs of the wallet, like [`Wallet::list_unspent`], with their
+    /// confirmations, address, label and whether they are change.
+    pub fn list_unspent_detailed(&self) -> UtxoList {
+        let tip_height = self.chain.tip().height();
+        let graph = self.indexed_graph.graph();
+        let index = &self.indexed_graph.index;
+        let utxos = self
+            .list_unspent()
+            .map(|utxo| {
+                let confirmations = match utxo.confirmation_time {
+                    ConfirmationTime::Confirmed { height, .. } => {
+                        tip_height.saturating_sub(height) + 1
+                    }
+                    ConfirmationTime::Unconfirmed { .. } => 0,
+                };
+                let script_pubkey = &utxo.txout.script_pubkey;
+                let address = Address::from_script(script_pubkey, self.network).ok();
+                let label = self
+                    .labels
+                    .get(&LabelRef::Output(utxo.outpoint))
+                    .or_else(|| self.labels.get(&LabelRef::Addr(script_pubkey.clone())))
+                    .cloned();
+                let is_change = graph.get_tx(utxo.outpoint.txid).map_or(false, |tx| {
+                    !tx.input.is_empty()
+                        && tx
+                            .input
+                            .iter()
+                            .all(|txin| index.txout(txin.previous_output).is_some())
+                });
+                UtxoDetails {
+                    utxo,
+                    confirmations,
+                    address,
+                    label,
+                    is_change,
+                }
+            })
+            .collect();
+        UtxoList(utxos)
+    }
+}
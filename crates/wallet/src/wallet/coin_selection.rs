@@ -31,19 +31,21 @@
 //! # use bdk_wallet::*;
 //! # use bdk_wallet::wallet::coin_selection::decide_change;
 //! # use anyhow::Error;
+//! # use rand::RngCore;
 //! #[derive(Debug)]
 //! struct AlwaysSpendEverything;
 //!
 //! impl CoinSelectionAlgorithm for AlwaysSpendEverything {
-//!     fn coin_select(
+//!     fn coin_select<R: RngCore>(
 //!         &self,
 //!         required_utxos: Vec<WeightedUtxo>,
 //!         optional_utxos: Vec<WeightedUtxo>,
 //!         fee_rate: FeeRate,
-//!         target_amount: u64,
+//!         target_amount: Amount,
 //!         drain_script: &Script,
+//!         _rand: &mut R,
 //!     ) -> Result<CoinSelectionResult, coin_selection::Error> {
-//!         let mut selected_amount = 0;
+//!         let mut selected_amount = Amount::ZERO;
 //!         let mut additional_weight = Weight::ZERO;
 //!         let all_utxos_selected = required_utxos
 //!             .into_iter()
@@ -51,7 +53,7 @@
 //!             .scan(
 //!                 (&mut selected_amount, &mut additional_weight),
 //!                 |(selected_amount, additional_weight), weighted_utxo| {
-//!                     **selected_amount += weighted_utxo.utxo.txout().value.to_sat();
+//!                     **selected_amount += weighted_utxo.utxo.txout().value;
 //!                     **additional_weight += Weight::from_wu(
 //!                         (TxIn::default().segwit_weight().to_wu()
 //!                             + weighted_utxo.satisfaction_weight as u64)
@@ -61,7 +63,7 @@
 //!                 },
 //!             )
 //!             .collect::<Vec<_>>();
-//!         let additional_fees = (fee_rate * additional_weight).to_sat();
+//!         let additional_fees = fee_rate * additional_weight;
 //!         let amount_needed_with_fees = additional_fees + target_amount;
 //!         if selected_amount < amount_needed_with_fees {
 //!             return Err(coin_selection::Error::InsufficientFunds {
@@ -101,11 +103,13 @@
 //! ```
 
 use crate::chain::collections::HashSet;
-use crate::wallet::utils::IsDust;
+use crate::chain::ConfirmationTime;
+use crate::wallet::utils::{dust_value, DEFAULT_DUST_RELAY_FEERATE};
 use crate::Utxo;
 use crate::WeightedUtxo;
-use bitcoin::FeeRate;
+use bitcoin::{Amount, FeeRate};
 
+use alloc::string::String;
 use alloc::vec::Vec;
 use bitcoin::consensus::encode::serialize;
 use bitcoin::OutPoint;
@@ -115,6 +119,7 @@ use bitcoin::{Script, Weight};
 use core::convert::TryInto;
 use core::fmt::{self, Formatter};
 use rand::seq::SliceRandom;
+use rand::RngCore;
 
 /// Default coin selection algorithm used by [`TxBuilder`](super::tx_builder::TxBuilder) if not
 /// overridden
@@ -125,10 +130,10 @@ pub type DefaultCoinSelectionAlgorithm = BranchAndBoundCoinSelection;
 pub enum Error {
     /// Wallet's UTXO set is not enough to cover recipient's requested plus fee
     InsufficientFunds {
-        /// Sats needed for some transaction
-        needed: u64,
-        /// Sats available for spending
-        available: u64,
+        /// Amount needed for some transaction
+        needed: Amount,
+        /// Amount available for spending
+        available: Amount,
     },
     /// Branch and bound coin selection tries to avoid needing a change by finding the right inputs for
     /// the desired outputs plus fee, if there is not such combination this error is thrown
@@ -136,6 +141,12 @@ pub enum Error {
     /// Branch and bound coin selection possible attempts with sufficiently big UTXO set could grow
     /// exponentially, thus a limit is set, and when hit, this error is thrown
     BnBTotalTriesExceeded,
+    /// The selection spends a UTXO which is not one of the candidates, or spends it twice
+    NotACandidate(OutPoint),
+    /// The selection doesn't spend a required UTXO
+    RequiredNotSelected(OutPoint),
+    /// An error of a custom coin selection algorithm
+    Custom(String),
 }
 
 impl fmt::Display for Error {
@@ -144,12 +155,24 @@ impl fmt::Display for Error {
             Self::InsufficientFunds { needed, available } => write!(
                 f,
                 "Insufficient funds: {} sat available of {} sat needed",
-                available, needed
+                available.to_sat(),
+                needed.to_sat()
             ),
             Self::BnBTotalTriesExceeded => {
                 write!(f, "Branch and bound coin selection: total tries exceeded")
             }
             Self::BnBNoExactMatch => write!(f, "Branch and bound coin selection: not exact match"),
+            Self::NotACandidate(outpoint) => write!(
+                f,
+                "Coin selection: {} is not a candidate or is selected twice",
+                outpoint
+            ),
+            Self::RequiredNotSelected(outpoint) => write!(
+                f,
+                "Coin selection: the required UTXO {} is not selected",
+                outpoint
+            ),
+            Self::Custom(err) => write!(f, "Coin selection: {}", err),
         }
     }
 }
@@ -163,18 +186,18 @@ pub enum Excess {
     /// It's not possible to create spendable output from excess using the current drain output
     NoChange {
         /// Threshold to consider amount as dust for this particular change script_pubkey
-        dust_threshold: u64,
+        dust_threshold: Amount,
         /// Exceeding amount of current selection over outgoing value and fee costs
-        remaining_amount: u64,
+        remaining_amount: Amount,
         /// The calculated fee for the drain TxOut with the selected script_pubkey
-        change_fee: u64,
+        change_fee: Amount,
     },
     /// It's possible to create spendable output from excess using the current drain output
     Change {
         /// Effective amount available to create change after deducting the change output fee
-        amount: u64,
+        amount: Amount,
         /// The deducted change output fee
-        fee: u64,
+        fee: Amount,
     },
 }
 
@@ -183,28 +206,55 @@ pub enum Excess {
 pub struct CoinSelectionResult {
     /// List of outputs selected for use as inputs
     pub selected: Vec<Utxo>,
-    /// Total fee amount for the selected utxos in satoshis
-    pub fee_amount: u64,
+    /// Total fee amount for the selected utxos
+    pub fee_amount: Amount,
     /// Remaining amount after deducing fees and outgoing outputs
     pub excess: Excess,
 }
 
 impl CoinSelectionResult {
     /// The total value of the inputs selected.
-    pub fn selected_amount(&self) -> u64 {
-        self.selected.iter().map(|u| u.txout().value.to_sat()).sum()
+    pub fn selected_amount(&self) -> Amount {
+        self.selected.iter().map(|u| u.txout().value).sum()
     }
 
     /// The total value of the inputs selected from the local wallet.
-    pub fn local_selected_amount(&self) -> u64 {
+    pub fn local_selected_amount(&self) -> Amount {
         self.selected
             .iter()
             .filter_map(|u| match u {
-                Utxo::Local(_) => Some(u.txout().value.to_sat()),
+                Utxo::Local(_) => Some(u.txout().value),
                 _ => None,
             })
             .sum()
     }
+
+    /// The total value of the inputs selected, in satoshis.
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "use `selected_amount`, which returns an `Amount`"
+    )]
+    pub fn selected_amount_sat(&self) -> u64 {
+        self.selected_amount().to_sat()
+    }
+
+    /// The total value of the inputs selected from the local wallet, in satoshis.
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "use `local_selected_amount`, which returns an `Amount`"
+    )]
+    pub fn local_selected_amount_sat(&self) -> u64 {
+        self.local_selected_amount().to_sat()
+    }
+
+    /// Total fee amount for the selected utxos, in satoshis.
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "use the `fee_amount` field, an `Amount`"
+    )]
+    pub fn fee_amount_sat(&self) -> u64 {
+        self.fee_amount.to_sat()
+    }
 }
 
 /// Trait for generalized coin selection algorithms
@@ -213,6 +263,14 @@ impl CoinSelectionResult {
 /// selection algorithm when it creates transactions.
 ///
 /// For an example see [this module](crate::wallet::coin_selection)'s documentation.
+///
+/// The candidates already exclude the UTXOs the transaction can't spend, such as the unspendable
+/// ones or the ones which are not manually selected with
+/// [`manually_selected_only`](super::tx_builder::TxBuilder::manually_selected_only). The wallet
+/// checks that the result spends each required UTXO, only spends candidates, and covers the
+/// target amount with its fee and excess, failing with [`Error::NotACandidate`],
+/// [`Error::RequiredNotSelected`] or [`Error::InsufficientFunds`] otherwise. Algorithms can
+/// report their own failures with [`Error::Custom`].
 pub trait CoinSelectionAlgorithm: core::fmt::Debug {
     /// Perform the coin selection
     ///
@@ -223,18 +281,46 @@ pub trait CoinSelectionAlgorithm: core::fmt::Debug {
     /// - `optional_utxos`: the remaining available utxos to satisfy `target_amount` with their
     ///                     weight cost
     /// - `fee_rate`: fee rate to use
-    /// - `target_amount`: the outgoing amount in satoshis and the fees already
+    /// - `target_amount`: the outgoing amount and the fees already
     ///                    accumulated from added outputs and transaction’s header.
     /// - `drain_script`: the script to use in case of change
+    /// - `rand`: the source of randomness, for algorithms which need one
     #[allow(clippy::too_many_arguments)]
-    fn coin_select(
+    fn coin_select<R: RngCore>(
         &self,
         required_utxos: Vec<WeightedUtxo>,
         optional_utxos: Vec<WeightedUtxo>,
         fee_rate: FeeRate,
-        target_amount: u64,
+        target_amount: Amount,
         drain_script: &Script,
+        rand: &mut R,
     ) -> Result<CoinSelectionResult, Error>;
+
+    /// Perform the coin selection with a `target_amount` in satoshis, see
+    /// [`coin_select`](Self::coin_select)
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "use `coin_select`, which takes the `target_amount` as an `Amount`"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn coin_select_sat<R: RngCore>(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+        rand: &mut R,
+    ) -> Result<CoinSelectionResult, Error> {
+        self.coin_select(
+            required_utxos,
+            optional_utxos,
+            fee_rate,
+            Amount::from_sat(target_amount),
+            drain_script,
+            rand,
+        )
+    }
 }
 
 /// Simple and dumb coin selection
@@ -245,13 +331,14 @@ pub trait CoinSelectionAlgorithm: core::fmt::Debug {
 pub struct LargestFirstCoinSelection;
 
 impl CoinSelectionAlgorithm for LargestFirstCoinSelection {
-    fn coin_select(
+    fn coin_select<R: RngCore>(
         &self,
         required_utxos: Vec<WeightedUtxo>,
         mut optional_utxos: Vec<WeightedUtxo>,
         fee_rate: FeeRate,
-        target_amount: u64,
+        target_amount: Amount,
         drain_script: &Script,
+        _rand: &mut R,
     ) -> Result<CoinSelectionResult, Error> {
         // We put the "required UTXOs" first and make sure the optional UTXOs are sorted,
         // initially smallest to largest, before being reversed with `.rev()`.
@@ -275,13 +362,14 @@ impl CoinSelectionAlgorithm for LargestFirstCoinSelection {
 pub struct OldestFirstCoinSelection;
 
 impl CoinSelectionAlgorithm for OldestFirstCoinSelection {
-    fn coin_select(
+    fn coin_select<R: RngCore>(
         &self,
         required_utxos: Vec<WeightedUtxo>,
         mut optional_utxos: Vec<WeightedUtxo>,
         fee_rate: FeeRate,
-        target_amount: u64,
+        target_amount: Amount,
         drain_script: &Script,
+        _rand: &mut R,
     ) -> Result<CoinSelectionResult, Error> {
         // We put the "required UTXOs" first and make sure the optional UTXOs are sorted from
         // oldest to newest according to blocktime
@@ -302,20 +390,121 @@ impl CoinSelectionAlgorithm for OldestFirstCoinSelection {
     }
 }
 
+/// Coin selection presets consuming the UTXOs in a fixed order
+///
+/// The required UTXOs are always selected first, then the optional ones in the order of the
+/// preset until the target amount is reached, ties broken by outpoint. Foreign UTXOs, whose
+/// confirmation height is unknown, come last in both [`OldestFirst`] and [`NewestFirst`].
+///
+/// [`OldestFirst`]: SelectionPreset::OldestFirst
+/// [`NewestFirst`]: SelectionPreset::NewestFirst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelectionPreset {
+    /// Confirmed UTXOs by confirmation height ascending, then the unconfirmed ones
+    OldestFirst,
+    /// Unconfirmed UTXOs, then the confirmed ones by confirmation height descending
+    NewestFirst,
+    /// UTXOs by value descending
+    LargestFirst,
+}
+
+impl SelectionPreset {
+    fn cmp(&self, a: &WeightedUtxo, b: &WeightedUtxo) -> core::cmp::Ordering {
+        use core::cmp::Reverse;
+        // (rank, height): foreign UTXOs have no known height
+        let position = |wu: &WeightedUtxo| match &wu.utxo {
+            Utxo::Local(local) => match local.confirmation_time {
+                ConfirmationTime::Confirmed { height, .. } => (0, height),
+                ConfirmationTime::Unconfirmed { .. } => (1, 0),
+            },
+            Utxo::Foreign { .. } => (2, 0),
+        };
+        let ordering = match self {
+            SelectionPreset::OldestFirst => position(a).cmp(&position(b)),
+            SelectionPreset::NewestFirst => {
+                let key = |wu: &WeightedUtxo| match position(wu) {
+                    (0, height) => (1, Reverse(height)),
+                    (1, _) => (0, Reverse(0)),
+                    _ => (2, Reverse(0)),
+                };
+                key(a).cmp(&key(b))
+            }
+            SelectionPreset::LargestFirst => b.utxo.txout().value.cmp(&a.utxo.txout().value),
+        };
+        ordering.then_with(|| a.utxo.outpoint().cmp(&b.utxo.outpoint()))
+    }
+}
+
+impl CoinSelectionAlgorithm for SelectionPreset {
+    fn coin_select<R: RngCore>(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        mut optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: Amount,
+        drain_script: &Script,
+        _rand: &mut R,
+    ) -> Result<CoinSelectionResult, Error> {
+        optional_utxos.sort_by(|a, b| self.cmp(a, b));
+        let utxos = required_utxos
+            .into_iter()
+            .map(|utxo| (true, utxo))
+            .chain(optional_utxos.into_iter().map(|utxo| (false, utxo)));
+
+        select_sorted_utxos(utxos, fee_rate, target_amount, drain_script)
+    }
+}
+
 /// Decide if change can be created
 ///
 /// - `remaining_amount`: the amount in which the selected coins exceed the target amount
 /// - `fee_rate`: required fee rate for the current selection
 /// - `drain_script`: script to consider change creation
-pub fn decide_change(remaining_amount: u64, fee_rate: FeeRate, drain_script: &Script) -> Excess {
+pub fn decide_change(remaining_amount: Amount, fee_rate: FeeRate, drain_script: &Script) -> Excess {
+    decide_change_with_dust_relay_feerate(
+        remaining_amount,
+        fee_rate,
+        drain_script,
+        DEFAULT_DUST_RELAY_FEERATE,
+    )
+}
+
+/// Decide if change can be created, with the `remaining_amount` in satoshis, see
+/// [`decide_change`]
+#[deprecated(
+    since = "1.0.0-alpha.13",
+    note = "use `decide_change`, which takes the `remaining_amount` as an `Amount`"
+)]
+pub fn decide_change_sat(
+    remaining_amount: u64,
+    fee_rate: FeeRate,
+    drain_script: &Script,
+) -> Excess {
+    decide_change(Amount::from_sat(remaining_amount), fee_rate, drain_script)
+}
+
+/// Decide if change can be created, with the dust limit of `drain_script` computed at
+/// `dust_relay_rate` instead of the default dust relay feerate
+///
+/// See [`decide_change`] and [`dust_value`].
+///
+/// [`dust_value`]: crate::wallet::dust_value
+pub fn decide_change_with_dust_relay_feerate(
+    remaining_amount: Amount,
+    fee_rate: FeeRate,
+    drain_script: &Script,
+    dust_relay_rate: FeeRate,
+) -> Excess {
     // drain_output_len = size(len(script_pubkey)) + len(script_pubkey) + size(output_value)
     let drain_output_len = serialize(drain_script).len() + 8usize;
     let change_fee =
-        (fee_rate * Weight::from_vb(drain_output_len as u64).expect("overflow occurred")).to_sat();
-    let drain_val = remaining_amount.saturating_sub(change_fee);
+        fee_rate * Weight::from_vb(drain_output_len as u64).expect("overflow occurred");
+    let drain_val = remaining_amount
+        .checked_sub(change_fee)
+        .unwrap_or(Amount::ZERO);
 
-    if drain_val.is_dust(drain_script) {
-        let dust_threshold = drain_script.minimal_non_dust().to_sat();
+    let dust_threshold = dust_value(drain_script, dust_relay_rate);
+    if drain_val < dust_threshold {
         Excess::NoChange {
             dust_threshold,
             change_fee,
@@ -329,26 +518,46 @@ pub fn decide_change(remaining_amount: u64, fee_rate: FeeRate, drain_script: &Sc
     }
 }
 
+/// Decide if change can be created, with the `remaining_amount` in satoshis, see
+/// [`decide_change_with_dust_relay_feerate`]
+#[deprecated(
+    since = "1.0.0-alpha.13",
+    note = "use `decide_change_with_dust_relay_feerate`, which takes the `remaining_amount` as an \
+            `Amount`"
+)]
+pub fn decide_change_with_dust_relay_feerate_sat(
+    remaining_amount: u64,
+    fee_rate: FeeRate,
+    drain_script: &Script,
+    dust_relay_rate: FeeRate,
+) -> Excess {
+    decide_change_with_dust_relay_feerate(
+        Amount::from_sat(remaining_amount),
+        fee_rate,
+        drain_script,
+        dust_relay_rate,
+    )
+}
+
 fn select_sorted_utxos(
     utxos: impl Iterator<Item = (bool, WeightedUtxo)>,
     fee_rate: FeeRate,
-    target_amount: u64,
+    target_amount: Amount,
     drain_script: &Script,
 ) -> Result<CoinSelectionResult, Error> {
-    let mut selected_amount = 0;
-    let mut fee_amount = 0;
+    let mut selected_amount = Amount::ZERO;
+    let mut fee_amount = Amount::ZERO;
     let selected = utxos
         .scan(
             (&mut selected_amount, &mut fee_amount),
             |(selected_amount, fee_amount), (must_use, weighted_utxo)| {
                 if must_use || **selected_amount < target_amount + **fee_amount {
-                    **fee_amount += (fee_rate
+                    **fee_amount += fee_rate
                         * Weight::from_wu(
                             TxIn::default().segwit_weight().to_wu()
                                 + weighted_utxo.satisfaction_weight as u64,
-                        ))
-                    .to_sat();
-                    **selected_amount += weighted_utxo.utxo.txout().value.to_sat();
+                        );
+                    **selected_amount += weighted_utxo.utxo.txout().value;
                     Some(weighted_utxo.utxo)
                 } else {
                     None
@@ -378,22 +587,22 @@ fn select_sorted_utxos(
 
 #[derive(Debug, Clone)]
 // Adds fee information to an UTXO.
-struct OutputGroup {
+pub(crate) struct OutputGroup {
     weighted_utxo: WeightedUtxo,
     // Amount of fees for spending a certain utxo, calculated using a certain FeeRate
-    fee: u64,
+    fee: Amount,
     // The effective value of the UTXO, i.e., the utxo value minus the fee for spending it
-    effective_value: i64,
+    pub(crate) effective_value: i64,
 }
 
 impl OutputGroup {
-    fn new(weighted_utxo: WeightedUtxo, fee_rate: FeeRate) -> Self {
-        let fee = (fee_rate
+    pub(crate) fn new(weighted_utxo: WeightedUtxo, fee_rate: FeeRate) -> Self {
+        let fee = fee_rate
             * Weight::from_wu(
                 TxIn::default().segwit_weight().to_wu() + weighted_utxo.satisfaction_weight as u64,
-            ))
-        .to_sat();
-        let effective_value = weighted_utxo.utxo.txout().value.to_sat() as i64 - fee as i64;
+            );
+        let effective_value =
+            weighted_utxo.utxo.txout().value.to_sat() as i64 - fee.to_sat() as i64;
         OutputGroup {
             weighted_utxo,
             fee,
@@ -429,13 +638,14 @@ impl BranchAndBoundCoinSelection {
 const BNB_TOTAL_TRIES: usize = 100_000;
 
 impl CoinSelectionAlgorithm for BranchAndBoundCoinSelection {
-    fn coin_select(
+    fn coin_select<R: RngCore>(
         &self,
         required_utxos: Vec<WeightedUtxo>,
         optional_utxos: Vec<WeightedUtxo>,
         fee_rate: FeeRate,
-        target_amount: u64,
+        target_amount: Amount,
         drain_script: &Script,
+        rand: &mut R,
     ) -> Result<CoinSelectionResult, Error> {
         // Mapping every (UTXO, usize) to an output group
         let required_utxos: Vec<OutputGroup> = required_utxos
@@ -475,19 +685,20 @@ impl CoinSelectionAlgorithm for BranchAndBoundCoinSelection {
         // if we actually run the BnB.
         let total_value: Result<u64, _> = (curr_available_value + curr_value).try_into();
         match total_value {
-            Ok(v) if v >= target_amount => {}
+            Ok(v) if v >= target_amount.to_sat() => {}
             _ => {
                 // Assume we spend all the UTXOs we can (all the required + all the optional with
                 // positive effective value), sum their value and their fee cost.
-                let (utxo_fees, utxo_value) = required_utxos
-                    .iter()
-                    .chain(optional_utxos.iter())
-                    .fold((0, 0), |(mut fees, mut value), utxo| {
-                        fees += utxo.fee;
-                        value += utxo.weighted_utxo.utxo.txout().value.to_sat();
+                let (utxo_fees, utxo_value) =
+                    required_utxos.iter().chain(optional_utxos.iter()).fold(
+                        (Amount::ZERO, Amount::ZERO),
+                        |(mut fees, mut value), utxo| {
+                            fees += utxo.fee;
+                            value += utxo.weighted_utxo.utxo.txout().value;
 
-                        (fees, value)
-                    });
+                            (fees, value)
+                        },
+                    );
 
                 // Add to the target the fee cost of the UTXOs
                 return Err(Error::InsufficientFunds {
@@ -498,6 +709,7 @@ impl CoinSelectionAlgorithm for BranchAndBoundCoinSelection {
         }
 
         let target_amount = target_amount
+            .to_sat()
             .try_into()
             .expect("Bitcoin amount to fit into i64");
 
@@ -505,7 +717,7 @@ impl CoinSelectionAlgorithm for BranchAndBoundCoinSelection {
             // remaining_amount can't be negative as that would mean the
             // selection wasn't successful
             // target_amount = amount_needed + (fee_amount - vin_fees)
-            let remaining_amount = (curr_value - target_amount) as u64;
+            let remaining_amount = Amount::from_sat((curr_value - target_amount) as u64);
 
             let excess = decide_change(remaining_amount, fee_rate, drain_script);
 
@@ -535,6 +747,7 @@ impl CoinSelectionAlgorithm for BranchAndBoundCoinSelection {
                     target_amount,
                     drain_script,
                     fee_rate,
+                    rand,
                 )
             }))
     }
@@ -652,7 +865,7 @@ impl BranchAndBoundCoinSelection {
         // remaining_amount can't be negative as that would mean the
         // selection wasn't successful
         // target_amount = amount_needed + (fee_amount - vin_fees)
-        let remaining_amount = (selected_amount - target_amount) as u64;
+        let remaining_amount = Amount::from_sat((selected_amount - target_amount) as u64);
 
         let excess = decide_change(remaining_amount, fee_rate, drain_script);
 
@@ -664,7 +877,7 @@ impl BranchAndBoundCoinSelection {
     }
 
     #[allow(clippy::too_many_arguments)]
-    fn single_random_draw(
+    fn single_random_draw<R: RngCore>(
         &self,
         required_utxos: Vec<OutputGroup>,
         mut optional_utxos: Vec<OutputGroup>,
@@ -672,8 +885,9 @@ impl BranchAndBoundCoinSelection {
         target_amount: i64,
         drain_script: &Script,
         fee_rate: FeeRate,
+        rand: &mut R,
     ) -> CoinSelectionResult {
-        optional_utxos.shuffle(&mut rand::thread_rng());
+        optional_utxos.shuffle(rand);
         let selected_utxos = optional_utxos.into_iter().fold(
             (curr_value, vec![]),
             |(mut amount, mut utxos), utxo| {
@@ -690,7 +904,7 @@ impl BranchAndBoundCoinSelection {
         // remaining_amount can't be negative as that would mean the
         // selection wasn't successful
         // target_amount = amount_needed + (fee_amount - vin_fees)
-        let remaining_amount = (selected_utxos.0 - target_amount) as u64;
+        let remaining_amount = Amount::from_sat((selected_utxos.0 - target_amount) as u64);
 
         let excess = decide_change(remaining_amount, fee_rate, drain_script);
 
@@ -703,7 +917,7 @@ impl BranchAndBoundCoinSelection {
         excess: Excess,
     ) -> CoinSelectionResult {
         selected_utxos.append(&mut required_utxos);
-        let fee_amount = selected_utxos.iter().map(|u| u.fee).sum::<u64>();
+        let fee_amount = selected_utxos.iter().map(|u| u.fee).sum::<Amount>();
         let selected = selected_utxos
             .into_iter()
             .map(|u| u.weighted_utxo.utxo)
@@ -717,6 +931,137 @@ impl BranchAndBoundCoinSelection {
     }
 }
 
+/// Check the result of a coin selection algorithm
+///
+/// The selection must spend each of the `required` UTXOs and only spend `candidates`, at most
+/// once, so that custom algorithms can't bypass the UTXOs filtered out of the candidates, such as
+/// the unspendable ones. The selected value must also cover `target_amount`, the fee of the
+/// inputs and the excess.
+pub(crate) fn check_selection(
+    result: &CoinSelectionResult,
+    required: &[OutPoint],
+    candidates: impl IntoIterator<Item = OutPoint>,
+    target_amount: Amount,
+) -> Result<(), Error> {
+    let mut candidates = candidates.into_iter().collect::<HashSet<_>>();
+    for utxo in &result.selected {
+        if !candidates.remove(&utxo.outpoint()) {
+            return Err(Error::NotACandidate(utxo.outpoint()));
+        }
+    }
+    if let Some(outpoint) = required.iter().find(|op| candidates.contains(op)) {
+        return Err(Error::RequiredNotSelected(*outpoint));
+    }
+
+    let excess = match result.excess {
+        Excess::NoChange {
+            remaining_amount, ..
+        } => remaining_amount,
+        Excess::Change { amount, fee } => amount.checked_add(fee).unwrap_or(Amount::MAX),
+    };
+    let needed = target_amount
+        .checked_add(result.fee_amount)
+        .and_then(|needed| needed.checked_add(excess))
+        .unwrap_or(Amount::MAX);
+    let available = result.selected_amount();
+    if available < needed {
+        return Err(Error::InsufficientFunds { needed, available });
+    }
+    Ok(())
+}
+
+/// The UTXOs with an effective value below this, in satoshis, are the nuisances swept by
+/// [`consolidate_opportunistically`]
+pub(crate) const NUISANCE_THRESHOLD: i64 = 10_000;
+
+/// The limits of [`consolidate_opportunistically`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Consolidation {
+    /// The maximum number of inputs added to the selection
+    pub max_extra_inputs: usize,
+    /// The maximum weight of the inputs added to the selection, once satisfied
+    pub max_extra_weight: Weight,
+}
+
+/// Add tiny UTXOs of `candidates` to a selection which already meets its target and has a change
+/// output, within the `limits`
+///
+/// The candidates with an effective value at `fee_rate` in `(0, NUISANCE_THRESHOLD)` are drawn at
+/// random, the smaller ones with a higher probability. Each one pays for its own input at
+/// `fee_rate` and adds the rest of its value to the change, so the feerate of the transaction
+/// doesn't drop and the change only grows: it can't fall below the dust limit at
+/// `dust_relay_rate`. A selection without change is left untouched since the swept value would
+/// go to the fee.
+pub(crate) fn consolidate_opportunistically(
+    result: &mut CoinSelectionResult,
+    candidates: Vec<WeightedUtxo>,
+    limits: Consolidation,
+    fee_rate: FeeRate,
+    drain_script: &Script,
+    dust_relay_rate: FeeRate,
+    rng: &mut impl RngCore,
+) {
+    let mut remaining_amount = match result.excess {
+        Excess::Change { amount, fee } => amount + fee,
+        Excess::NoChange { .. } => return,
+    };
+
+    let selected = result
+        .selected
+        .iter()
+        .map(|utxo| utxo.outpoint())
+        .collect::<HashSet<_>>();
+    let mut nuisances = candidates
+        .into_iter()
+        .filter(|utxo| !selected.contains(&utxo.utxo.outpoint()))
+        .map(|utxo| OutputGroup::new(utxo, fee_rate))
+        .filter(|group| group.effective_value > 0 && group.effective_value < NUISANCE_THRESHOLD)
+        .collect::<Vec<_>>();
+
+    let mut extra_inputs = 0;
+    let mut extra_weight = Weight::ZERO;
+    while extra_inputs < limits.max_extra_inputs && !nuisances.is_empty() {
+        // weighted draw, the weight of a nuisance is how far it is below the threshold
+        let weights = nuisances
+            .iter()
+            .map(|group| (NUISANCE_THRESHOLD - group.effective_value) as u64)
+            .collect::<Vec<_>>();
+        let mut draw = rng.next_u64() % weights.iter().sum::<u64>();
+        let index = weights
+            .iter()
+            .position(|&weight| {
+                if draw < weight {
+                    true
+                } else {
+                    draw -= weight;
+                    false
+                }
+            })
+            .expect("the draw is below the total weight");
+        let group = nuisances.swap_remove(index);
+
+        let weight = TxIn::default().segwit_weight()
+            + Weight::from_wu(group.weighted_utxo.satisfaction_weight as u64);
+        if extra_weight + weight > limits.max_extra_weight {
+            continue;
+        }
+        extra_inputs += 1;
+        extra_weight += weight;
+        remaining_amount += Amount::from_sat(group.effective_value as u64);
+        result.fee_amount += group.fee;
+        result.selected.push(group.weighted_utxo.utxo);
+    }
+
+    if extra_inputs > 0 {
+        result.excess = decide_change_with_dust_relay_feerate(
+            remaining_amount,
+            fee_rate,
+            drain_script,
+            dust_relay_rate,
+        );
+    }
+}
+
 /// Remove duplicate UTXOs.
 ///
 /// If a UTXO appears in both `required` and `optional`, the appearance in `required` is kept.
@@ -750,7 +1095,7 @@ mod test {
 
     use rand::rngs::StdRng;
     use rand::seq::SliceRandom;
-    use rand::{Rng, RngCore, SeedableRng};
+    use rand::{thread_rng, Rng, RngCore, SeedableRng};
 
     // signature len (1WU) + signature and sighash (72WU)
     // + pubkey len (1WU) + pubkey (33WU)
@@ -775,6 +1120,7 @@ mod test {
                 },
                 keychain: KeychainKind::External,
                 is_spent: false,
+                is_coinbase: false,
                 derivation_index: 42,
                 confirmation_time,
             }),
@@ -839,6 +1185,7 @@ mod test {
                     },
                     keychain: KeychainKind::External,
                     is_spent: false,
+                    is_coinbase: false,
                     derivation_index: rng.next_u32(),
                     confirmation_time: if rng.gen_bool(0.5) {
                         ConfirmationTime::Confirmed {
@@ -870,6 +1217,7 @@ mod test {
                     },
                     keychain: KeychainKind::External,
                     is_spent: false,
+                    is_coinbase: false,
                     derivation_index: 42,
                     confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
                 }),
@@ -890,7 +1238,7 @@ mod test {
     fn test_largest_first_coin_selection_success() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 250_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(250_000 + FEE_AMOUNT);
 
         let result = LargestFirstCoinSelection
             .coin_select(
@@ -899,19 +1247,55 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 300_010);
-        assert_eq!(result.fee_amount, 204)
+        assert_eq!(result.selected_amount(), Amount::from_sat(300_010));
+        assert_eq!(result.fee_amount, Amount::from_sat(204))
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_sat_shims_forward_to_amounts() {
+        let drain_script = ScriptBuf::default();
+        let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
+
+        let result = LargestFirstCoinSelection
+            .coin_select_sat(
+                get_test_utxos(),
+                vec![],
+                fee_rate,
+                250_000 + FEE_AMOUNT,
+                &drain_script,
+                &mut thread_rng(),
+            )
+            .unwrap();
+        assert_eq!(result.selected_amount_sat(), 300_010);
+        assert_eq!(result.local_selected_amount_sat(), 300_010);
+        assert_eq!(result.fee_amount_sat(), 204);
+
+        assert_matches!(
+            decide_change_sat(10_000, fee_rate, &drain_script),
+            Excess::Change { amount, .. } if amount == Amount::from_sat(9_991)
+        );
+        assert_matches!(
+            decide_change_with_dust_relay_feerate_sat(
+                400,
+                fee_rate,
+                &drain_script,
+                FeeRate::from_sat_per_vb_unchecked(1_000),
+            ),
+            Excess::NoChange { remaining_amount, .. } if remaining_amount == Amount::from_sat(400)
+        );
     }
 
     #[test]
     fn test_largest_first_coin_selection_use_all() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(20_000 + FEE_AMOUNT);
 
         let result = LargestFirstCoinSelection
             .coin_select(
@@ -920,19 +1304,20 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 300_010);
-        assert_eq!(result.fee_amount, 204);
+        assert_eq!(result.selected_amount(), Amount::from_sat(300_010));
+        assert_eq!(result.fee_amount, Amount::from_sat(204));
     }
 
     #[test]
     fn test_largest_first_coin_selection_use_only_necessary() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(20_000 + FEE_AMOUNT);
 
         let result = LargestFirstCoinSelection
             .coin_select(
@@ -941,12 +1326,13 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 1);
-        assert_eq!(result.selected_amount(), 200_000);
-        assert_eq!(result.fee_amount, 68);
+        assert_eq!(result.selected_amount(), Amount::from_sat(200_000));
+        assert_eq!(result.fee_amount, Amount::from_sat(68));
     }
 
     #[test]
@@ -954,7 +1340,7 @@ mod test {
     fn test_largest_first_coin_selection_insufficient_funds() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 500_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(500_000 + FEE_AMOUNT);
 
         LargestFirstCoinSelection
             .coin_select(
@@ -963,6 +1349,7 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
     }
@@ -972,7 +1359,7 @@ mod test {
     fn test_largest_first_coin_selection_insufficient_funds_high_fees() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 250_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(250_000 + FEE_AMOUNT);
 
         LargestFirstCoinSelection
             .coin_select(
@@ -981,6 +1368,7 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1000),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
     }
@@ -989,7 +1377,7 @@ mod test {
     fn test_oldest_first_coin_selection_success() {
         let utxos = get_oldest_first_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 180_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(180_000 + FEE_AMOUNT);
 
         let result = OldestFirstCoinSelection
             .coin_select(
@@ -998,19 +1386,20 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 2);
-        assert_eq!(result.selected_amount(), 200_000);
-        assert_eq!(result.fee_amount, 136)
+        assert_eq!(result.selected_amount(), Amount::from_sat(200_000));
+        assert_eq!(result.fee_amount, Amount::from_sat(136))
     }
 
     #[test]
     fn test_oldest_first_coin_selection_use_all() {
         let utxos = get_oldest_first_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(20_000 + FEE_AMOUNT);
 
         let result = OldestFirstCoinSelection
             .coin_select(
@@ -1019,19 +1408,20 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 500_000);
-        assert_eq!(result.fee_amount, 204);
+        assert_eq!(result.selected_amount(), Amount::from_sat(500_000));
+        assert_eq!(result.fee_amount, Amount::from_sat(204));
     }
 
     #[test]
     fn test_oldest_first_coin_selection_use_only_necessary() {
         let utxos = get_oldest_first_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(20_000 + FEE_AMOUNT);
 
         let result = OldestFirstCoinSelection
             .coin_select(
@@ -1040,12 +1430,13 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 1);
-        assert_eq!(result.selected_amount(), 120_000);
-        assert_eq!(result.fee_amount, 68);
+        assert_eq!(result.selected_amount(), Amount::from_sat(120_000));
+        assert_eq!(result.fee_amount, Amount::from_sat(68));
     }
 
     #[test]
@@ -1053,7 +1444,7 @@ mod test {
     fn test_oldest_first_coin_selection_insufficient_funds() {
         let utxos = get_oldest_first_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 600_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(600_000 + FEE_AMOUNT);
 
         OldestFirstCoinSelection
             .coin_select(
@@ -1062,6 +1453,7 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
     }
@@ -1071,11 +1463,8 @@ mod test {
     fn test_oldest_first_coin_selection_insufficient_funds_high_fees() {
         let utxos = get_oldest_first_test_utxos();
 
-        let target_amount: u64 = utxos
-            .iter()
-            .map(|wu| wu.utxo.txout().value.to_sat())
-            .sum::<u64>()
-            - 50;
+        let target_amount =
+            utxos.iter().map(|wu| wu.utxo.txout().value).sum::<Amount>() - Amount::from_sat(50);
         let drain_script = ScriptBuf::default();
 
         OldestFirstCoinSelection
@@ -1085,8 +1474,100 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1000),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
+            )
+            .unwrap();
+    }
+
+    fn selected_outpoints(result: &CoinSelectionResult) -> Vec<OutPoint> {
+        result.selected.iter().map(|utxo| utxo.outpoint()).collect()
+    }
+
+    #[test]
+    fn test_selection_preset_order() {
+        let confirmed = |height| ConfirmationTime::Confirmed { height, time: 0 };
+        let utxos = vec![
+            utxo(50_000, 5, confirmed(3)),
+            utxo(70_000, 4, ConfirmationTime::Unconfirmed { last_seen: 0 }),
+            utxo(50_000, 3, confirmed(1)),
+            utxo(60_000, 2, confirmed(3)),
+            utxo(50_000, 1, confirmed(1)),
+        ];
+        let outpoints = |indexes: &[u32]| {
+            indexes
+                .iter()
+                .map(|&i| utxo(0, i, confirmed(0)).utxo.outpoint())
+                .collect::<Vec<_>>()
+        };
+        let drain_script = ScriptBuf::default();
+        let select = |preset: SelectionPreset, target_amount| {
+            preset
+                .coin_select(
+                    vec![],
+                    utxos.clone(),
+                    FeeRate::from_sat_per_vb_u32(1),
+                    target_amount,
+                    &drain_script,
+                    &mut thread_rng(),
+                )
+                .unwrap()
+        };
+
+        // ties are broken by outpoint
+        let result = select(SelectionPreset::OldestFirst, Amount::from_sat(120_000));
+        assert_eq!(selected_outpoints(&result), outpoints(&[1, 3, 2]));
+        let result = select(SelectionPreset::NewestFirst, Amount::from_sat(150_000));
+        assert_eq!(selected_outpoints(&result), outpoints(&[4, 2, 5]));
+        let result = select(SelectionPreset::LargestFirst, Amount::from_sat(150_000));
+        assert_eq!(selected_outpoints(&result), outpoints(&[4, 2, 1]));
+
+        // the order doesn't depend on the order of the candidates
+        let mut shuffled = utxos.clone();
+        shuffled.reverse();
+        let result = SelectionPreset::OldestFirst
+            .coin_select(
+                vec![],
+                shuffled,
+                FeeRate::from_sat_per_vb_u32(1),
+                Amount::from_sat(120_000),
+                &drain_script,
+                &mut thread_rng(),
+            )
+            .unwrap();
+        assert_eq!(selected_outpoints(&result), outpoints(&[1, 3, 2]));
+    }
+
+    #[test]
+    fn test_selection_preset_required_and_change() {
+        let utxos = get_oldest_first_test_utxos();
+        let drain_script = ScriptBuf::default();
+        let fee_rate = FeeRate::from_sat_per_vb_u32(2);
+
+        // the required UTXO comes first, whatever its position
+        let result = SelectionPreset::OldestFirst
+            .coin_select(
+                vec![utxos[2].clone()],
+                utxos[..2].to_vec(),
+                fee_rate,
+                Amount::from_sat(250_000 + FEE_AMOUNT),
+                &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
+        assert_eq!(selected_outpoints(&result), [utxos[2].utxo.outpoint()]);
+        // the excess goes to a change output, and the inputs pay for their weight
+        assert_matches!(result.excess, Excess::Change { amount, .. } if amount > Amount::ZERO);
+        assert_eq!(result.fee_amount, fee_rate * Weight::from_wu(272));
+
+        let result = SelectionPreset::NewestFirst.coin_select(
+            vec![],
+            utxos,
+            fee_rate,
+            Amount::from_sat(600_000),
+            &drain_script,
+            &mut thread_rng(),
+        );
+        assert_matches!(result, Err(Error::InsufficientFunds { .. }));
     }
 
     #[test]
@@ -1097,7 +1578,7 @@ mod test {
 
         let drain_script = ScriptBuf::default();
 
-        let target_amount = 250_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(250_000 + FEE_AMOUNT);
 
         let result = BranchAndBoundCoinSelection::default()
             .coin_select(
@@ -1106,19 +1587,20 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 300_000);
-        assert_eq!(result.fee_amount, 204);
+        assert_eq!(result.selected_amount(), Amount::from_sat(300_000));
+        assert_eq!(result.fee_amount, Amount::from_sat(204));
     }
 
     #[test]
     fn test_bnb_coin_selection_required_are_enough() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 20_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(20_000 + FEE_AMOUNT);
 
         let result = BranchAndBoundCoinSelection::default()
             .coin_select(
@@ -1127,19 +1609,20 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 3);
-        assert_eq!(result.selected_amount(), 300_010);
-        assert_eq!(result.fee_amount, 204);
+        assert_eq!(result.selected_amount(), Amount::from_sat(300_010));
+        assert_eq!(result.fee_amount, Amount::from_sat(204));
     }
 
     #[test]
     fn test_bnb_coin_selection_optional_are_enough() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 299756 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(299756 + FEE_AMOUNT);
 
         let result = BranchAndBoundCoinSelection::default()
             .coin_select(
@@ -1148,12 +1631,13 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 2);
-        assert_eq!(result.selected_amount(), 300000);
-        assert_eq!(result.fee_amount, 136);
+        assert_eq!(result.selected_amount(), Amount::from_sat(300000));
+        assert_eq!(result.fee_amount, Amount::from_sat(136));
     }
 
     #[test]
@@ -1176,7 +1660,7 @@ mod test {
         assert!(amount > 150_000);
         let drain_script = ScriptBuf::default();
 
-        let target_amount = 150_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(150_000 + FEE_AMOUNT);
 
         let result = BranchAndBoundCoinSelection::default()
             .coin_select(
@@ -1185,12 +1669,13 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
 
         assert_eq!(result.selected.len(), 2);
-        assert_eq!(result.selected_amount(), 300_000);
-        assert_eq!(result.fee_amount, 136);
+        assert_eq!(result.selected_amount(), Amount::from_sat(300_000));
+        assert_eq!(result.fee_amount, Amount::from_sat(136));
     }
 
     #[test]
@@ -1198,7 +1683,7 @@ mod test {
     fn test_bnb_coin_selection_insufficient_funds() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 500_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(500_000 + FEE_AMOUNT);
 
         BranchAndBoundCoinSelection::default()
             .coin_select(
@@ -1207,6 +1692,7 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
     }
@@ -1216,7 +1702,7 @@ mod test {
     fn test_bnb_coin_selection_insufficient_funds_high_fees() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 250_000 + FEE_AMOUNT;
+        let target_amount = Amount::from_sat(250_000 + FEE_AMOUNT);
 
         BranchAndBoundCoinSelection::default()
             .coin_select(
@@ -1225,6 +1711,7 @@ mod test {
                 FeeRate::from_sat_per_vb_unchecked(1000),
                 target_amount,
                 &drain_script,
+                &mut thread_rng(),
             )
             .unwrap();
     }
@@ -1233,19 +1720,26 @@ mod test {
     fn test_bnb_coin_selection_check_fee_rate() {
         let utxos = get_test_utxos();
         let drain_script = ScriptBuf::default();
-        let target_amount = 99932; // first utxo's effective value
+        let target_amount = Amount::from_sat(99932); // first utxo's effective value
         let feerate = FeeRate::BROADCAST_MIN;
 
         let result = BranchAndBoundCoinSelection::new(0)
-            .coin_select(vec![], utxos, feerate, target_amount, &drain_script)
+            .coin_select(
+                vec![],
+                utxos,
+                feerate,
+                target_amount,
+                &drain_script,
+                &mut thread_rng(),
+            )
             .unwrap();
 
         assert_eq!(result.selected.len(), 1);
-        assert_eq!(result.selected_amount(), 100_000);
+        assert_eq!(result.selected_amount(), Amount::from_sat(100_000));
         let input_weight =
             TxIn::default().segwit_weight().to_wu() + P2WPKH_SATISFACTION_SIZE as u64;
         // the final fee rate should be exactly the same as the fee rate given
-        let result_feerate = Amount::from_sat(result.fee_amount) / Weight::from_wu(input_weight);
+        let result_feerate = result.fee_amount / Weight::from_wu(input_weight);
         assert_eq!(result_feerate, feerate);
     }
 
@@ -1263,11 +1757,12 @@ mod test {
                     vec![],
                     optional_utxos,
                     FeeRate::ZERO,
-                    target_amount,
+                    Amount::from_sat(target_amount),
                     &drain_script,
+                    &mut thread_rng(),
                 )
                 .unwrap();
-            assert_eq!(result.selected_amount(), target_amount);
+            assert_eq!(result.selected_amount(), Amount::from_sat(target_amount));
         }
     }
 
@@ -1366,8 +1861,8 @@ mod test {
                 fee_rate,
             )
             .unwrap();
-        assert_eq!(result.selected_amount(), 100_000);
-        assert_eq!(result.fee_amount, 136);
+        assert_eq!(result.selected_amount(), Amount::from_sat(100_000));
+        assert_eq!(result.fee_amount, Amount::from_sat(136));
     }
 
     // TODO: bnb() function should be optimized, and this test should be done with more utxos
@@ -1406,7 +1901,10 @@ mod test {
                     fee_rate,
                 )
                 .unwrap();
-            assert_eq!(result.selected_amount(), target_amount as u64);
+            assert_eq!(
+                result.selected_amount(),
+                Amount::from_sat(target_amount as u64)
+            );
         }
     }
 
@@ -1432,10 +1930,14 @@ mod test {
             target_amount as i64,
             &drain_script,
             fee_rate,
+            &mut rng,
         );
 
-        assert!(result.selected_amount() > target_amount);
-        assert_eq!(result.fee_amount, (result.selected.len() * 68) as u64);
+        assert!(result.selected_amount() > Amount::from_sat(target_amount));
+        assert_eq!(
+            result.fee_amount,
+            Amount::from_sat((result.selected.len() * 68) as u64)
+        );
     }
 
     #[test]
@@ -1447,16 +1949,14 @@ mod test {
             vec![],
             utxos,
             FeeRate::from_sat_per_vb_unchecked(10),
-            500_000,
+            Amount::from_sat(500_000),
             &drain_script,
+            &mut thread_rng(),
         );
 
         assert_matches!(
             selection,
-            Err(Error::InsufficientFunds {
-                available: 300_000,
-                ..
-            })
+            Err(Error::InsufficientFunds { available, .. }) if available == Amount::from_sat(300_000)
         );
     }
 
@@ -1473,16 +1973,14 @@ mod test {
             required,
             optional,
             FeeRate::from_sat_per_vb_unchecked(10),
-            500_000,
+            Amount::from_sat(500_000),
             &drain_script,
+            &mut thread_rng(),
         );
 
         assert_matches!(
             selection,
-            Err(Error::InsufficientFunds {
-                available: 300_010,
-                ..
-            })
+            Err(Error::InsufficientFunds { available, .. }) if available == Amount::from_sat(300_010)
         );
     }
 
@@ -1495,16 +1993,14 @@ mod test {
             utxos,
             vec![],
             FeeRate::from_sat_per_vb_unchecked(10_000),
-            500_000,
+            Amount::from_sat(500_000),
             &drain_script,
+            &mut thread_rng(),
         );
 
         assert_matches!(
             selection,
-            Err(Error::InsufficientFunds {
-                available: 300_010,
-                ..
-            })
+            Err(Error::InsufficientFunds { available, .. }) if available == Amount::from_sat(300_010)
         );
     }
 
@@ -1521,6 +2017,7 @@ mod test {
                     },
                     keychain: KeychainKind::External,
                     is_spent: false,
+                    is_coinbase: false,
                     derivation_index: 0,
                     confirmation_time: ConfirmationTime::Confirmed {
                         height: 12345,
@@ -1598,4 +2095,51 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_check_selection() {
+        let utxos = get_test_utxos();
+        let outpoints = utxos.iter().map(|u| u.utxo.outpoint()).collect::<Vec<_>>();
+        let result = |selected: &[usize], fee_amount: u64, excess: Excess| CoinSelectionResult {
+            selected: selected.iter().map(|&i| utxos[i].utxo.clone()).collect(),
+            fee_amount: Amount::from_sat(fee_amount),
+            excess,
+        };
+        let change = |amount: u64| Excess::Change {
+            amount: Amount::from_sat(amount),
+            fee: Amount::from_sat(100),
+        };
+
+        let ok = result(&[0, 2], 500, change(99_400));
+        assert!(check_selection(
+            &ok,
+            &outpoints[..1],
+            outpoints.clone(),
+            Amount::from_sat(200_000)
+        )
+        .is_ok());
+
+        // the required UTXO is not selected
+        assert_matches!(
+            check_selection(&ok, &outpoints[1..2], outpoints.clone(), Amount::from_sat(200_000)),
+            Err(Error::RequiredNotSelected(op)) if op == outpoints[1]
+        );
+        // a UTXO which is not a candidate, or which is selected twice
+        assert_matches!(
+            check_selection(&ok, &[], outpoints[..2].to_vec(), Amount::from_sat(200_000)),
+            Err(Error::NotACandidate(op)) if op == outpoints[2]
+        );
+        let twice = result(&[0, 0], 500, change(100));
+        assert_matches!(
+            check_selection(&twice, &[], outpoints.clone(), Amount::from_sat(100_000)),
+            Err(Error::NotACandidate(op)) if op == outpoints[0]
+        );
+        // the change is larger than what is left
+        let overspent = result(&[0, 2], 500, change(99_401));
+        assert_matches!(
+            check_selection(&overspent, &[], outpoints, Amount::from_sat(200_000)),
+            Err(Error::InsufficientFunds { needed, available })
+                if needed == Amount::from_sat(300_001) && available == Amount::from_sat(300_000)
+        );
+    }
 }
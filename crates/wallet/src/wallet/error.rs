@@ -14,8 +14,11 @@
 use crate::descriptor::policy::PolicyError;
 use crate::descriptor::DescriptorError;
 use crate::wallet::coin_selection;
+use crate::wallet::tx_builder::{ParamError, RecipientError};
 use crate::{descriptor, KeychainKind};
 use alloc::string::String;
+use alloc::vec::Vec;
+use bdk_chain::tx_graph::CalculateFeeError;
 use bitcoin::{absolute, psbt, Amount, OutPoint, Sequence, Txid};
 use core::fmt;
 
@@ -55,6 +58,10 @@ pub enum CreateTxError {
     /// Spending policy is not compatible with this [`KeychainKind`]
     SpendingPolicyRequired(KeychainKind),
     /// Requested invalid transaction version '0'
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "reported as `ParamError::Version0` in `CreateTxError::InvalidParams`"
+    )]
     Version0,
     /// Requested transaction version `1`, but at least `2` is needed to use OP_CSV
     Version1Csv,
@@ -65,7 +72,27 @@ pub enum CreateTxError {
         /// Required `LockTime`
         required: absolute::LockTime,
     },
+    /// Requested a `LockTime` in blocks while the script requires one in seconds, or the other way
+    /// around
+    MixedLockTimeUnits {
+        /// Requested `LockTime`
+        requested: absolute::LockTime,
+        /// Required `LockTime`
+        required: absolute::LockTime,
+    },
+    /// The spending path selected with [`TxBuilder::policy_path`] requires a timelock that hasn't
+    /// expired yet
+    ///
+    /// [`TxBuilder::policy_path`]: crate::wallet::tx_builder::TxBuilder::policy_path
+    TimelockNotMature {
+        /// Block height at which the selected spending path becomes valid
+        valid_at_height: u32,
+    },
     /// Cannot enable RBF with a `Sequence` >= 0xFFFFFFFE
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "reported as `ParamError::RbfSequence` in `CreateTxError::InvalidParams`"
+    )]
     RbfSequence,
     /// Cannot enable RBF with `Sequence` given a required OP_CSV
     RbfSequenceCsv {
@@ -85,13 +112,93 @@ pub enum CreateTxError {
         required: bitcoin::FeeRate,
     },
     /// `manually_selected_only` option is selected but no utxo has been passed
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "reported as `ParamError::NoUtxosSelected` in `CreateTxError::InvalidParams`"
+    )]
     NoUtxosSelected,
-    /// Output created is under the dust limit, 546 satoshis
+    /// These manually selected outpoints are reserved by another transaction, see
+    /// [`TxBuilder::reserve_inputs`]
+    ///
+    /// The transaction can be built again with other inputs, or once the reservation is released.
+    ///
+    /// [`TxBuilder::reserve_inputs`]: super::tx_builder::TxBuilder::reserve_inputs
+    InputsReserved(Vec<OutPoint>),
+    /// This manually selected output of a coinbase transaction can't be spent yet
+    ImmatureCoinbase {
+        /// The coinbase output
+        outpoint: OutPoint,
+        /// The height of the first block which can include a transaction spending it, see
+        /// [`LocalOutput::mature_at_height`]
+        ///
+        /// [`LocalOutput::mature_at_height`]: crate::LocalOutput::mature_at_height
+        mature_at_height: u32,
+    },
+    /// The fee is above the maximum of the [`FeeLimits`]
+    ///
+    /// [`FeeLimits`]: crate::wallet::FeeLimits
+    FeeAboveLimit {
+        /// The fee of the transaction
+        fee: Amount,
+        /// The maximum fee
+        max: Amount,
+    },
+    /// The fee rate, over the estimated weight of the transaction, is above the maximum of the
+    /// [`FeeLimits`]
+    ///
+    /// [`FeeLimits`]: crate::wallet::FeeLimits
+    FeeRateAboveLimit {
+        /// The fee rate of the transaction
+        fee_rate: bitcoin::FeeRate,
+        /// The maximum fee rate
+        max: bitcoin::FeeRate,
+    },
+    /// The output at this index is under the dust limit of its script, see [`dust_value`]
+    ///
+    /// [`dust_value`]: crate::wallet::dust_value
     OutputBelowDustLimit(usize),
     /// There was an error with coin selection
     CoinSelection(coin_selection::Error),
     /// Cannot build a tx without recipients
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "reported as `ParamError::NoRecipients` in `CreateTxError::InvalidParams`"
+    )]
     NoRecipients,
+    /// The transaction has change, but the wallet only has the static address of a descriptor
+    /// without wildcard to send it to, see [`Wallet::create_single`]
+    ///
+    /// [`Wallet::create_single`]: crate::Wallet::create_single
+    StaticChangeAddress,
+    /// The parameters of the [`TxBuilder`] are inconsistent, see [`TxBuilder::validate`]
+    ///
+    /// [`TxBuilder`]: crate::wallet::tx_builder::TxBuilder
+    /// [`TxBuilder::validate`]: crate::wallet::tx_builder::TxBuilder::validate
+    InvalidParams(Vec<ParamError>),
+    /// Some of the recipients passed to [`TxBuilder::add_recipients`] are invalid, each is given
+    /// with its index
+    ///
+    /// [`TxBuilder::add_recipients`]: crate::wallet::tx_builder::TxBuilder::add_recipients
+    InvalidRecipients(Vec<(usize, RecipientError)>),
+    /// [`TxBuilder::change_position`] can't be used together with
+    /// [`TxOrdering::Bip69Lexicographic`]
+    ///
+    /// [`TxBuilder::change_position`]: crate::wallet::tx_builder::TxBuilder::change_position
+    /// [`TxOrdering::Bip69Lexicographic`]: crate::wallet::tx_builder::TxOrdering::Bip69Lexicographic
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "reported as `ParamError::ChangePositionBip69` in `CreateTxError::InvalidParams`"
+    )]
+    ChangePositionBip69,
+    /// The position requested with [`TxBuilder::change_position`] is past the last output
+    ///
+    /// [`TxBuilder::change_position`]: crate::wallet::tx_builder::TxBuilder::change_position
+    ChangePositionOutOfRange {
+        /// Requested position of the change output
+        position: usize,
+        /// Number of outputs of the transaction, change included
+        outputs: usize,
+    },
     /// Partially signed bitcoin transaction error
     Psbt(psbt::Error),
     /// In order to use the [`TxBuilder::add_global_xpubs`] option every extended
@@ -106,8 +213,25 @@ pub enum CreateTxError {
     MissingNonWitnessUtxo(OutPoint),
     /// Miniscript PSBT error
     MiniscriptPsbt(MiniscriptPsbtError),
+    /// A recipient was already paid within the window of
+    /// [`TxBuilder::deduplicate_recipients`]
+    ///
+    /// [`TxBuilder::deduplicate_recipients`]: crate::wallet::tx_builder::TxBuilder::deduplicate_recipients
+    PossibleDuplicatePayment {
+        /// The transactions paying the recipients
+        txids: Vec<Txid>,
+    },
+    /// The transaction can't be the original of a payjoin
+    #[cfg(feature = "payjoin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "payjoin")))]
+    Payjoin(crate::wallet::payjoin::PayjoinError),
+    /// A silent payment output can't be derived
+    #[cfg(feature = "silent-payments")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "silent-payments")))]
+    SilentPayment(crate::wallet::silent_payments::SilentPaymentError),
 }
 
+#[allow(deprecated)]
 impl fmt::Display for CreateTxError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -129,7 +253,20 @@ impl fmt::Display for CreateTxError {
                 requested,
                 required,
             } => {
-                write!(f, "TxBuilder requested timelock of `{:?}`, but at least `{:?}` is required to spend from this script", required, requested)
+                write!(f, "TxBuilder requested timelock of `{:?}`, but at least `{:?}` is required to spend from this script", requested, required)
+            }
+            CreateTxError::MixedLockTimeUnits {
+                requested,
+                required,
+            } => {
+                write!(f, "TxBuilder requested timelock of `{:?}`, but the script requires a timelock of `{:?}` in other units", requested, required)
+            }
+            CreateTxError::TimelockNotMature { valid_at_height } => {
+                write!(
+                    f,
+                    "The selected spending policy path is not valid until height {}",
+                    valid_at_height
+                )
             }
             CreateTxError::RbfSequence => {
                 write!(f, "Cannot enable RBF with a nSequence >= 0xFFFFFFFE")
@@ -156,6 +293,39 @@ impl fmt::Display for CreateTxError {
             CreateTxError::NoUtxosSelected => {
                 write!(f, "No UTXO selected")
             }
+            CreateTxError::InputsReserved(outpoints) => {
+                write!(
+                    f,
+                    "The inputs {:?} are reserved by another transaction",
+                    outpoints
+                )
+            }
+            CreateTxError::ImmatureCoinbase {
+                outpoint,
+                mature_at_height,
+            } => {
+                write!(
+                    f,
+                    "The coinbase output {} can't be spent before the block {}",
+                    outpoint, mature_at_height
+                )
+            }
+            CreateTxError::FeeAboveLimit { fee, max } => {
+                write!(
+                    f,
+                    "The fee {} is above the maximum {}",
+                    fee.display_dynamic(),
+                    max.display_dynamic()
+                )
+            }
+            CreateTxError::FeeRateAboveLimit { fee_rate, max } => {
+                write!(
+                    f,
+                    "The fee rate {} sat/kwu is above the maximum {} sat/kwu",
+                    fee_rate.to_sat_per_kwu(),
+                    max.to_sat_per_kwu()
+                )
+            }
             CreateTxError::OutputBelowDustLimit(limit) => {
                 write!(f, "Output below the dust limit: {}", limit)
             }
@@ -163,6 +333,38 @@ impl fmt::Display for CreateTxError {
             CreateTxError::NoRecipients => {
                 write!(f, "Cannot build tx without recipients")
             }
+            CreateTxError::StaticChangeAddress => {
+                write!(
+                    f,
+                    "The wallet has no change address, set one with `drain_to` or `drain_to_change`"
+                )
+            }
+            CreateTxError::InvalidRecipients(errors) => {
+                write!(f, "Invalid recipients:")?;
+                for (i, (index, err)) in errors.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { "," };
+                    write!(f, "{} #{}: {}", sep, index, err)?;
+                }
+                Ok(())
+            }
+            CreateTxError::InvalidParams(errors) => {
+                write!(f, "Invalid transaction parameters:")?;
+                for (i, err) in errors.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { "," };
+                    write!(f, "{} {}", sep, err)?;
+                }
+                Ok(())
+            }
+            CreateTxError::ChangePositionBip69 => {
+                write!(f, "Cannot pin the change position with BIP69 ordering")
+            }
+            CreateTxError::ChangePositionOutOfRange { position, outputs } => {
+                write!(
+                    f,
+                    "Change position {} out of range, the transaction has {} outputs",
+                    position, outputs
+                )
+            }
             CreateTxError::Psbt(e) => e.fmt(f),
             CreateTxError::MissingKeyOrigin(err) => {
                 write!(f, "Missing key origin: {}", err)
@@ -176,6 +378,22 @@ impl fmt::Display for CreateTxError {
             CreateTxError::MiniscriptPsbt(err) => {
                 write!(f, "Miniscript PSBT error: {}", err)
             }
+            CreateTxError::PossibleDuplicatePayment { txids } => {
+                write!(f, "Possible duplicate payment, already paid by:")?;
+                for (i, txid) in txids.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { "," };
+                    write!(f, "{} {}", sep, txid)?;
+                }
+                Ok(())
+            }
+            #[cfg(feature = "payjoin")]
+            CreateTxError::Payjoin(err) => {
+                write!(f, "Invalid payjoin original: {}", err)
+            }
+            #[cfg(feature = "silent-payments")]
+            CreateTxError::SilentPayment(err) => {
+                write!(f, "Silent payment error: {}", err)
+            }
         }
     }
 }
@@ -210,6 +428,20 @@ impl From<coin_selection::Error> for CreateTxError {
     }
 }
 
+#[cfg(feature = "payjoin")]
+impl From<crate::wallet::payjoin::PayjoinError> for CreateTxError {
+    fn from(err: crate::wallet::payjoin::PayjoinError) -> Self {
+        CreateTxError::Payjoin(err)
+    }
+}
+
+#[cfg(feature = "silent-payments")]
+impl From<crate::wallet::silent_payments::SilentPaymentError> for CreateTxError {
+    fn from(err: crate::wallet::silent_payments::SilentPaymentError) -> Self {
+        CreateTxError::SilentPayment(err)
+    }
+}
+
 #[cfg(feature = "std")]
 impl std::error::Error for CreateTxError {}
 
@@ -228,6 +460,8 @@ pub enum BuildFeeBumpError {
     IrreplaceableTransaction(Txid),
     /// Node doesn't have data to estimate a fee rate
     FeeRateUnavailable,
+    /// Trying to cancel a tx that doesn't spend any of the wallet's outputs
+    NoWalletInput(Txid),
 }
 
 impl fmt::Display for BuildFeeBumpError {
@@ -252,9 +486,259 @@ impl fmt::Display for BuildFeeBumpError {
                 write!(f, "Transaction can't be replaced with txid: {}", txid)
             }
             Self::FeeRateUnavailable => write!(f, "Fee rate unavailable"),
+            Self::NoWalletInput(txid) => {
+                write!(
+                    f,
+                    "Transaction doesn't spend any wallet output with txid: {}",
+                    txid
+                )
+            }
         }
     }
 }
 
 #[cfg(feature = "std")]
 impl std::error::Error for BuildFeeBumpError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::build_cpfp`]
+///
+/// [`Wallet::build_cpfp`]: super::Wallet::build_cpfp
+pub enum BuildCpfpError {
+    /// Thrown when a tx is not found in the internal database
+    TransactionNotFound(Txid),
+    /// Happens when trying to accelerate a transaction that is already confirmed
+    TransactionConfirmed(Txid),
+    /// The fee of the parent transaction or of one of its unconfirmed ancestors can't be computed,
+    /// usually because some of their previous outputs are missing from the wallet (see
+    /// [`Wallet::insert_txout`])
+    ///
+    /// [`Wallet::insert_txout`]: super::Wallet::insert_txout
+    ParentFeeUnavailable(CalculateFeeError),
+    /// The transaction has no unspent output owned by the wallet that the child could spend
+    NoSpendableOutput(Txid),
+}
+
+impl fmt::Display for BuildCpfpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TransactionNotFound(txid) => {
+                write!(
+                    f,
+                    "Transaction not found in the internal database with txid: {}",
+                    txid
+                )
+            }
+            Self::TransactionConfirmed(txid) => {
+                write!(f, "Transaction already confirmed with txid: {}", txid)
+            }
+            Self::ParentFeeUnavailable(e) => {
+                write!(f, "Fee of the parent transaction unavailable: {}", e)
+            }
+            Self::NoSpendableOutput(txid) => {
+                write!(
+                    f,
+                    "Transaction has no unspent output owned by the wallet with txid: {}",
+                    txid
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildCpfpError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::build_chain`]
+///
+/// [`Wallet::build_chain`]: super::Wallet::build_chain
+pub enum BuildChainError {
+    /// The transaction at `index` of the chain can't be built
+    CreateTx {
+        /// The index of the transaction in the chain
+        index: usize,
+        /// The error building it
+        error: CreateTxError,
+    },
+    /// The transaction at `index` must spend the change of its parent, which has none
+    NoParentChange {
+        /// The index of the transaction in the chain
+        index: usize,
+    },
+    /// The transaction at `index` has a legacy input, its txid changes once signed and its child
+    /// can't spend its change
+    MalleableTxid {
+        /// The index of the transaction in the chain
+        index: usize,
+    },
+}
+
+impl fmt::Display for BuildChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateTx { index, error } => {
+                write!(
+                    f,
+                    "Can't build the transaction {} of the chain: {}",
+                    index, error
+                )
+            }
+            Self::NoParentChange { index } => write!(
+                f,
+                "The parent of the transaction {} of the chain has no change",
+                index
+            ),
+            Self::MalleableTxid { index } => write!(
+                f,
+                "The transaction {} of the chain has a legacy input, its txid isn't final",
+                index
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildChainError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::build_sweep`] and [`Wallet::build_sweep_utxos`]
+///
+/// [`Wallet::build_sweep`]: super::Wallet::build_sweep
+/// [`Wallet::build_sweep_utxos`]: super::Wallet::build_sweep_utxos
+pub enum BuildSweepError {
+    /// Happens when trying to spend an UTXO that is not in the internal database
+    UnknownUtxo(OutPoint),
+    /// There are no UTXOs to sweep
+    NoUtxos,
+    /// Happens when trying to sweep a coinbase output that hasn't reached maturity yet
+    ImmatureCoinbase(OutPoint),
+    /// All the UTXOs being swept are unconfirmed
+    UnconfirmedUtxos,
+}
+
+impl fmt::Display for BuildSweepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownUtxo(outpoint) => write!(
+                f,
+                "UTXO not found in the internal database with txid: {}, vout: {}",
+                outpoint.txid, outpoint.vout
+            ),
+            Self::NoUtxos => write!(f, "No UTXOs to sweep"),
+            Self::ImmatureCoinbase(outpoint) => write!(
+                f,
+                "Coinbase output not mature yet with txid: {}, vout: {}",
+                outpoint.txid, outpoint.vout
+            ),
+            Self::UnconfirmedUtxos => write!(f, "All the UTXOs to sweep are unconfirmed"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildSweepError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::combine_psbts`]
+///
+/// [`Wallet::combine_psbts`]: super::Wallet::combine_psbts
+pub enum CombineError {
+    /// One of the PSBTs doesn't spend the same unsigned transaction as the base PSBT
+    DifferentTransaction {
+        /// Txid of the unsigned transaction of the base PSBT
+        expected: Txid,
+        /// Txid of the unsigned transaction of the other PSBT
+        found: Txid,
+    },
+    /// The sighash needed to check a signature couldn't be computed, usually because some of the
+    /// UTXOs spent by the transaction are missing from the base PSBT
+    Sighash {
+        /// Index of the input being checked
+        input_index: usize,
+        /// The error returned by miniscript
+        error: miniscript::psbt::SighashError,
+    },
+    /// One of the signatures doesn't match its public key and the transaction sighash
+    InvalidSignature {
+        /// Index of the input with the invalid signature
+        input_index: usize,
+    },
+    /// The policy of the wallet's descriptor couldn't be extracted
+    Policy(DescriptorError),
+}
+
+impl fmt::Display for CombineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DifferentTransaction { expected, found } => write!(
+                f,
+                "The PSBT spends a different transaction, expected txid: {}, found: {}",
+                expected, found
+            ),
+            Self::Sighash { input_index, error } => write!(
+                f,
+                "Cannot compute the sighash of the psbt input #{}: {}",
+                input_index, error
+            ),
+            Self::InvalidSignature { input_index } => {
+                write!(f, "Invalid signature in the psbt input #{}", input_index)
+            }
+            Self::Policy(e) => write!(f, "Policy error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CombineError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::add_keychain`]
+///
+/// [`Wallet::add_keychain`]: super::Wallet::add_keychain
+pub enum AddKeychainError {
+    /// The label is empty, longer than [`KeychainLabel::MAX_LEN`] bytes or one of
+    /// [`KeychainLabel::RESERVED`]
+    ///
+    /// [`KeychainLabel::MAX_LEN`]: crate::KeychainLabel::MAX_LEN
+    /// [`KeychainLabel::RESERVED`]: crate::KeychainLabel::RESERVED
+    InvalidLabel(String),
+    /// The descriptor is invalid
+    Descriptor(DescriptorError),
+    /// A keychain with the same label was already added
+    KeychainAlreadyAssigned(KeychainKind),
+    /// The descriptor is already the one of another keychain
+    DescriptorAlreadyAssigned(KeychainKind),
+}
+
+impl fmt::Display for AddKeychainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLabel(label) => write!(
+                f,
+                "Invalid keychain label `{}`: it must be 1 to {} bytes long, other than {:?}",
+                label,
+                crate::KeychainLabel::MAX_LEN,
+                crate::KeychainLabel::RESERVED
+            ),
+            Self::Descriptor(e) => write!(f, "Descriptor error: {}", e),
+            Self::KeychainAlreadyAssigned(keychain) => {
+                write!(f, "The keychain {:?} was already added", keychain)
+            }
+            Self::DescriptorAlreadyAssigned(keychain) => write!(
+                f,
+                "The descriptor is already assigned to the keychain {:?}",
+                keychain
+            ),
+        }
+    }
+}
+
+impl From<DescriptorError> for AddKeychainError {
+    fn from(err: DescriptorError) -> Self {
+        AddKeychainError::Descriptor(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AddKeychainError {}
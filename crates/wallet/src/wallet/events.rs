@@ -0,0 +1,270 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Events describing how the state of the wallet changed
+//!
+//! The `*_with_events` variants of the methods applying data to the wallet, such as
+//! [`Wallet::apply_update_with_events`], compare the canonical transactions, the balance and the
+//! revealed addresses of the wallet before and after the application, and report the
+//! differences as a list of [`WalletEvent`]s. With the `std` feature, the subscriptions of
+//! [`Wallet::subscribe`] receive the events of every application, see
+//! [`subscriptions`](crate::wallet::subscriptions).
+
+use alloc::vec::Vec;
+
+use bdk_chain::local_chain::{ApplyHeaderError, CannotConnectError};
+use bdk_chain::{Anchor, BlockId, ChainPosition};
+use bitcoin::{Address, Block, Txid};
+
+use super::{Balance, Update, Wallet};
+use crate::collections::{BTreeMap, BTreeSet};
+use crate::KeychainKind;
+
+/// A change in the state of the wallet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletEvent {
+    /// An address was revealed
+    AddressRevealed {
+        /// The keychain of the address
+        keychain: KeychainKind,
+        /// The derivation index of the address
+        index: u32,
+        /// The address
+        address: Address,
+    },
+    /// A transaction which wasn't part of the wallet's history now is, unconfirmed
+    TxSeen {
+        /// The txid of the transaction
+        txid: Txid,
+    },
+    /// A transaction is now confirmed in `block_id`
+    ///
+    /// This is also reported when a confirmed transaction moves to another block because of a
+    /// reorg, and for a transaction which is confirmed the first time the wallet sees it.
+    TxConfirmed {
+        /// The txid of the transaction
+        txid: Txid,
+        /// The block confirming the transaction
+        block_id: BlockId,
+    },
+    /// The block confirming a transaction was reorged out, the transaction is unconfirmed again
+    TxUnconfirmed {
+        /// The txid of the transaction
+        txid: Txid,
+        /// The block which used to confirm the transaction
+        block_id: BlockId,
+    },
+    /// A transaction is no longer part of the wallet's history because it conflicts with other
+    /// transactions which are
+    TxReplaced {
+        /// The txid of the transaction
+        txid: Txid,
+        /// The transactions of the wallet's history spending some of the same inputs
+        replaced_by: Vec<Txid>,
+    },
+    /// A transaction is no longer part of the wallet's history, without any known replacement,
+    /// for instance because it left the mempool
+    TxEvicted {
+        /// The txid of the transaction
+        txid: Txid,
+    },
+    /// The balance of the wallet changed
+    BalanceChanged {
+        /// The balance before the change
+        old: Balance,
+        /// The balance after the change
+        new: Balance,
+    },
+}
+
+/// The part of the state of the wallet which is compared to compute the events
+#[derive(Debug)]
+pub(crate) struct EventSnapshot {
+    /// The block confirming each canonical transaction, `None` if unconfirmed
+    txs: BTreeMap<Txid, Option<BlockId>>,
+    balance: Balance,
+    revealed: BTreeMap<KeychainKind, u32>,
+}
+
+impl Wallet {
+    /// Take a snapshot of the wallet state, to be compared by [`Wallet::events_since`]
+    pub(crate) fn event_snapshot(&self) -> EventSnapshot {
+        let txs = self
+            .transactions()
+            .map(|tx| {
+                let block_id = match tx.chain_position {
+                    ChainPosition::Confirmed(anchor) => Some(
+                        self.chain
+                            .get(anchor.confirmation_height)
+                            .map(|cp| cp.block_id())
+                            .unwrap_or_else(|| anchor.anchor_block()),
+                    ),
+                    ChainPosition::Unconfirmed(_) => None,
+                };
+                (tx.tx_node.txid, block_id)
+            })
+            .collect();
+        EventSnapshot {
+            txs,
+            balance: self.balance(),
+            revealed: self.indexed_graph.index.last_revealed_indices(),
+        }
+    }
+
+    /// The events turning the state of `before` into the current state of the wallet
+    ///
+    /// The revealed addresses come first, by keychain and index, then the events of the
+    /// transactions by txid, and the balance change last.
+    pub(crate) fn events_since(&self, before: EventSnapshot) -> Vec<WalletEvent> {
+        let after = self.event_snapshot();
+        let mut events = Vec::new();
+
+        for (&keychain, &last) in &after.revealed {
+            let first = before.revealed.get(&keychain).map_or(0, |&index| index + 1);
+            for index in first..=last {
+                events.push(WalletEvent::AddressRevealed {
+                    keychain,
+                    index,
+                    address: self.peek_address(keychain, index).address,
+                });
+            }
+        }
+
+        let txids = before
+            .txs
+            .keys()
+            .chain(after.txs.keys())
+            .copied()
+            .collect::<BTreeSet<_>>();
+        for txid in txids {
+            let event = match (before.txs.get(&txid), after.txs.get(&txid)) {
+                (None, Some(None)) => WalletEvent::TxSeen { txid },
+                (None | Some(None), Some(&Some(block_id))) => {
+                    WalletEvent::TxConfirmed { txid, block_id }
+                }
+                (Some(&Some(old)), Some(&Some(block_id))) if old != block_id => {
+                    WalletEvent::TxConfirmed { txid, block_id }
+                }
+                (Some(&Some(block_id)), Some(None)) => {
+                    WalletEvent::TxUnconfirmed { txid, block_id }
+                }
+                (Some(_), None) => {
+                    let replaced_by = self
+                        .indexed_graph
+                        .graph()
+                        .get_tx(txid)
+                        .map(|tx| {
+                            self.indexed_graph
+                                .graph()
+                                .direct_conflicts(&tx)
+                                .map(|(_, conflict)| conflict)
+                                .filter(|conflict| after.txs.contains_key(conflict))
+                                .collect::<BTreeSet<_>>()
+                        })
+                        .unwrap_or_default();
+                    if replaced_by.is_empty() {
+                        WalletEvent::TxEvicted { txid }
+                    } else {
+                        WalletEvent::TxReplaced {
+                            txid,
+                            replaced_by: replaced_by.into_iter().collect(),
+                        }
+                    }
+                }
+                _ => continue,
+            };
+            events.push(event);
+        }
+
+        if before.balance != after.balance {
+            events.push(WalletEvent::BalanceChanged {
+                old: before.balance,
+                new: after.balance,
+            });
+        }
+        events
+    }
+
+    /// Run `apply`, returning its result and the events of the changes it made, which are
+    /// published to the subscriptions of the wallet
+    pub(crate) fn apply_with_events<T>(
+        &mut self,
+        apply: impl FnOnce(&mut Self) -> T,
+    ) -> (T, Vec<WalletEvent>) {
+        let before = self.event_snapshot();
+        #[cfg(feature = "std")]
+        let nested = core::mem::replace(&mut self.subscriptions.applying, true);
+        let result = apply(self);
+        let events = self.events_since(before);
+        #[cfg(feature = "std")]
+        {
+            self.subscriptions.applying = nested;
+            if !nested {
+                self.subscriptions.publish(&events);
+            }
+        }
+        (result, events)
+    }
+
+    /// Run `apply`, publishing the events of the changes it made to the subscriptions of the
+    /// wallet, if any
+    pub(crate) fn notify_subscriptions<T>(&mut self, apply: impl FnOnce(&mut Self) -> T) -> T {
+        #[cfg(feature = "std")]
+        if self.subscriptions.is_live() && !self.subscriptions.applying {
+            return self.apply_with_events(apply).0;
+        }
+        apply(self)
+    }
+
+    /// Apply an `update` like [`Wallet::apply_update`], returning the resulting events.
+    ///
+    /// The events are computed by comparing the state of the wallet before and after the update,
+    /// so a transaction which both appears and disappears within the update is not reported.
+    /// Their order is deterministic: the revealed addresses come first, by keychain and index,
+    /// then the events of the transactions by txid, and the [`WalletEvent::BalanceChanged`]
+    /// event last.
+    pub fn apply_update_with_events(
+        &mut self,
+        update: impl Into<Update>,
+    ) -> Result<Vec<WalletEvent>, CannotConnectError> {
+        let (result, events) = self.apply_with_events(|wallet| wallet.apply_update(update));
+        result?;
+        Ok(events)
+    }
+
+    /// Apply a `block` like [`Wallet::apply_block`], returning the resulting events.
+    ///
+    /// See [`Wallet::apply_update_with_events`] for how the events are computed.
+    pub fn apply_block_with_events(
+        &mut self,
+        block: &Block,
+        height: u32,
+    ) -> Result<Vec<WalletEvent>, CannotConnectError> {
+        let (result, events) = self.apply_with_events(|wallet| wallet.apply_block(block, height));
+        result?;
+        Ok(events)
+    }
+
+    /// Apply a `block` like [`Wallet::apply_block_connected_to`], returning the resulting events.
+    ///
+    /// See [`Wallet::apply_update_with_events`] for how the events are computed.
+    pub fn apply_block_connected_to_with_events(
+        &mut self,
+        block: &Block,
+        height: u32,
+        connected_to: BlockId,
+    ) -> Result<Vec<WalletEvent>, ApplyHeaderError> {
+        let (result, events) = self.apply_with_events(|wallet| {
+            wallet.apply_block_connected_to(block, height, connected_to)
+        });
+        result?;
+        Ok(events)
+    }
+}
@@ -37,6 +37,26 @@
 //! # Ok::<_, Box<dyn std::error::Error>>(())
 //! ```
 //!
+//! ### Import a multipath export
+//!
+//! Exports might also encode both the external and internal descriptors with the `<0;1>`
+//! multipath shorthand, in which case [`Wallet::create_from_export`] splits them back:
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk_wallet::wallet::export::*;
+//! # use bdk_wallet::*;
+//! let import = r#"{
+//!     "descriptor": "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/<0;1>/*)#3mdl82a0",
+//!     "blockheight":1782088,
+//!     "label":"testnet"
+//! }"#;
+//!
+//! let import = FullyNodedExport::parse(import)?;
+//! let wallet = Wallet::create_from_export(&import, Network::Testnet)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
 //! ### Export a `Wallet`
 //! ```
 //! # use bitcoin::*;
@@ -61,9 +81,13 @@ use serde::{Deserialize, Serialize};
 use miniscript::descriptor::{ShInner, WshInner};
 use miniscript::{Descriptor, ScriptContext, Terminal};
 
+use crate::descriptor::{calc_checksum, DescriptorError};
 use crate::types::KeychainKind;
 use crate::wallet::Wallet;
 
+/// Multipath shorthand for the external and internal derivation branches
+const MULTIPATH_BRANCHES: &str = "/<0;1>/*";
+
 /// Alias for [`FullyNodedExport`]
 #[deprecated(since = "0.18.0", note = "Please use [`FullyNodedExport`] instead")]
 pub type WalletExport = FullyNodedExport;
@@ -94,6 +118,32 @@ impl FromStr for FullyNodedExport {
     }
 }
 
+/// Errors that can happen while importing a [`FullyNodedExport`]
+#[derive(Debug)]
+pub enum ExportError {
+    /// The export is not valid JSON, or is missing some of its fields
+    Json(serde_json::Error),
+    /// The exported descriptor is invalid, or its checksum doesn't match
+    Descriptor(DescriptorError),
+    /// The export doesn't contain a change descriptor
+    MissingChangeDescriptor,
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "Invalid export: {}", e),
+            Self::Descriptor(e) => write!(f, "Invalid exported descriptor: {}", e),
+            Self::MissingChangeDescriptor => {
+                write!(f, "The export doesn't contain a change descriptor")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ExportError {}
+
 fn remove_checksum(s: String) -> String {
     s.split_once('#').map(|(a, _)| String::from(a)).unwrap()
 }
@@ -160,6 +210,46 @@ impl FullyNodedExport {
         Ok(export)
     }
 
+    /// Export a wallet, encoding both of its descriptors with the `<0;1>` multipath shorthand
+    ///
+    /// The exported descriptor includes its checksum. Other than that, this works like
+    /// [`FullyNodedExport::export_wallet`], and fails in the same cases.
+    pub fn export_wallet_multipath(
+        wallet: &Wallet,
+        label: &str,
+        include_blockheight: bool,
+    ) -> Result<Self, &'static str> {
+        let mut export = Self::export_wallet(wallet, label, include_blockheight)?;
+        // `export_wallet` already checked that the change descriptor only differs in the branch
+        let descriptor = export.descriptor.replace("/0/*", MULTIPATH_BRANCHES);
+        let checksum = calc_checksum(&descriptor).map_err(|_| "Invalid descriptor")?;
+        export.descriptor = format!("{}#{}", descriptor, checksum);
+
+        Ok(export)
+    }
+
+    /// Parse an export from JSON, validating the checksum of its descriptor if present
+    pub fn parse(json: &str) -> Result<Self, ExportError> {
+        let export = Self::from_str(json).map_err(ExportError::Json)?;
+        if export.descriptor.contains('#') {
+            calc_checksum(&export.descriptor).map_err(ExportError::Descriptor)?;
+        }
+
+        Ok(export)
+    }
+
+    /// Return the descriptor without its checksum, replacing the derivation branch `from` with
+    /// `branch`. The checksum is dropped since it wouldn't match anymore.
+    fn with_branch(&self, from: &str, branch: u32) -> Option<String> {
+        let descriptor = self
+            .descriptor
+            .split_once('#')
+            .map_or(self.descriptor.as_str(), |(d, _)| d);
+        descriptor
+            .contains(from)
+            .then(|| descriptor.replace(from, &format!("/{}/*", branch)))
+    }
+
     fn is_compatible_with_core(descriptor: &str) -> Result<(), &'static str> {
         fn check_ms<Ctx: ScriptContext>(
             terminal: &Terminal<String, Ctx>,
@@ -194,18 +284,22 @@ impl FullyNodedExport {
 
     /// Return the external descriptor
     pub fn descriptor(&self) -> String {
-        self.descriptor.clone()
+        self.with_branch(MULTIPATH_BRANCHES, 0)
+            .unwrap_or_else(|| self.descriptor.clone())
     }
 
     /// Return the internal descriptor, if present
     pub fn change_descriptor(&self) -> Option<String> {
-        let replaced = self.descriptor.replace("/0/*", "/1/*");
+        self.with_branch(MULTIPATH_BRANCHES, 1)
+            .or_else(|| self.with_branch("/0/*", 1))
+    }
 
-        if replaced != self.descriptor {
-            Some(replaced)
-        } else {
-            None
-        }
+    /// Return the external and internal descriptors, as needed to create a [`Wallet`]
+    pub(crate) fn descriptors(&self) -> Result<(String, String), ExportError> {
+        let change_descriptor = self
+            .change_descriptor()
+            .ok_or(ExportError::MissingChangeDescriptor)?;
+        Ok((self.descriptor(), change_descriptor))
     }
 }
 
@@ -214,7 +308,7 @@ mod test {
     use core::str::FromStr;
 
     use crate::std::string::ToString;
-    use bdk_chain::{BlockId, ConfirmationTime};
+    use bdk_chain::{BlockId, BlockTimeOrHeight, ConfirmationTime};
     use bitcoin::hashes::Hash;
     use bitcoin::{transaction, BlockHash, Network, Transaction};
 
@@ -346,4 +440,71 @@ mod test {
         assert_eq!(export.blockheight, 5000);
         assert_eq!(export.label, "Test Label");
     }
+
+    // Exports in the multipath format written by Sparrow, with their expected external and
+    // internal descriptors
+    const SPARROW_EXPORTS: &[(&str, &str, &str)] = &[
+        (
+            r#"{"descriptor":"wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/<0;1>/*)#3mdl82a0","blockheight":1782088,"label":"testnet"}"#,
+            "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)",
+            "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/1/*)",
+        ),
+        (
+            r#"{"descriptor":"wsh(sortedmulti(2,[73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/<0;1>/*,[f9f62194/48'/0'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4/<0;1>/*))#qfcyfkyu","blockheight":2500000,"label":"multisig"}"#,
+            "wsh(sortedmulti(2,[73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*,[f9f62194/48'/0'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4/0/*))",
+            "wsh(sortedmulti(2,[73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/1/*,[f9f62194/48'/0'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4/1/*))",
+        ),
+    ];
+
+    #[test]
+    fn test_export_multipath_round_trip() {
+        for (json, descriptor, change_descriptor) in SPARROW_EXPORTS {
+            let import = FullyNodedExport::parse(json).unwrap();
+            assert_eq!(import.descriptor(), *descriptor);
+            assert_eq!(
+                import.change_descriptor().as_deref(),
+                Some(*change_descriptor)
+            );
+
+            let wallet = Wallet::create_from_export(&import, Network::Testnet).unwrap();
+            assert_eq!(
+                wallet.birthday(),
+                Some(BlockTimeOrHeight::Height(import.blockheight))
+            );
+            let mut export = wallet.export(&import.label, false).unwrap();
+            assert_eq!(export.blockheight, 0);
+            export.blockheight = import.blockheight;
+            assert_eq!(export.to_string(), *json);
+        }
+    }
+
+    #[test]
+    fn test_export_multipath_checksum() {
+        let (json, _, _) = SPARROW_EXPORTS[0];
+        let tampered = json.replace("#3mdl82a0", "#3mdl82a1");
+        assert!(matches!(
+            FullyNodedExport::parse(&tampered),
+            Err(ExportError::Descriptor(
+                DescriptorError::InvalidDescriptorChecksum
+            ))
+        ));
+
+        // exports without a checksum are still accepted
+        let (descriptor, _) = json.split_once('#').unwrap();
+        let no_checksum = format!(r#"{}","blockheight":0,"label":"testnet"}}"#, descriptor);
+        let import = FullyNodedExport::parse(&no_checksum).unwrap();
+        let wallet = Wallet::create_from_export(&import, Network::Testnet).unwrap();
+        // a zero block height is no birthday
+        assert_eq!(wallet.birthday(), None);
+    }
+
+    #[test]
+    fn test_export_missing_change_descriptor() {
+        let import = FullyNodedExport::parse(r#"{"descriptor":"wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/0)","blockheight":0,"label":"single"}"#).unwrap();
+        assert_eq!(import.change_descriptor(), None);
+        assert!(matches!(
+            Wallet::create_from_export(&import, Network::Testnet),
+            Err(ExportError::MissingChangeDescriptor)
+        ));
+    }
 }
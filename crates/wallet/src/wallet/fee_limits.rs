@@ -0,0 +1,166 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! The caps on the fee of the transactions built and signed by a wallet, see
+//! [`Wallet::set_fee_limits`]
+
+use alloc::vec::Vec;
+
+use bitcoin::{psbt::Psbt, Amount, FeeRate, Weight};
+
+use super::error::CreateTxError;
+use super::signer::SignerError;
+use super::tx_builder::TxParams;
+use super::Wallet;
+use crate::psbt::PsbtUtils;
+
+/// The maximum absolute fee and fee rate of a transaction, like the `maxtxfee` of Bitcoin Core
+///
+/// The default has no cap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeLimits {
+    /// The maximum absolute fee
+    pub max_fee: Option<Amount>,
+    /// The maximum fee rate
+    pub max_fee_rate: Option<FeeRate>,
+}
+
+impl FeeLimits {
+    /// The tightest caps of `self` and `other`
+    pub fn tightest(self, other: FeeLimits) -> FeeLimits {
+        fn min<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        FeeLimits {
+            max_fee: min(self.max_fee, other.max_fee),
+            max_fee_rate: min(self.max_fee_rate, other.max_fee_rate),
+        }
+    }
+
+    /// Whether there is no cap
+    pub fn is_unlimited(&self) -> bool {
+        self.max_fee.is_none() && self.max_fee_rate.is_none()
+    }
+
+    /// The cap exceeded by `fee`, then by the fee rate of `fee` over `weight`
+    pub(crate) fn check(&self, fee: Amount, weight: Weight) -> Result<(), FeeLimitExceeded> {
+        if let Some(max) = self.max_fee {
+            if fee > max {
+                return Err(FeeLimitExceeded::Fee { fee, max });
+            }
+        }
+        if let Some(max) = self.max_fee_rate {
+            let fee_rate = fee / weight;
+            if fee_rate > max {
+                return Err(FeeLimitExceeded::FeeRate { fee_rate, max });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The cap exceeded by a transaction, converted to the errors of the builder and of the signers
+pub(crate) enum FeeLimitExceeded {
+    Fee { fee: Amount, max: Amount },
+    FeeRate { fee_rate: FeeRate, max: FeeRate },
+}
+
+impl From<FeeLimitExceeded> for CreateTxError {
+    fn from(exceeded: FeeLimitExceeded) -> Self {
+        match exceeded {
+            FeeLimitExceeded::Fee { fee, max } => CreateTxError::FeeAboveLimit { fee, max },
+            FeeLimitExceeded::FeeRate { fee_rate, max } => {
+                CreateTxError::FeeRateAboveLimit { fee_rate, max }
+            }
+        }
+    }
+}
+
+impl From<FeeLimitExceeded> for SignerError {
+    fn from(exceeded: FeeLimitExceeded) -> Self {
+        match exceeded {
+            FeeLimitExceeded::Fee { fee, max } => SignerError::FeeAboveLimit { fee, max },
+            FeeLimitExceeded::FeeRate { fee_rate, max } => {
+                SignerError::FeeRateAboveLimit { fee_rate, max }
+            }
+        }
+    }
+}
+
+impl Wallet {
+    /// Cap the fee of the transactions built with [`TxBuilder`] and signed with
+    /// [`Wallet::sign`]
+    ///
+    /// The limits aren't persisted, by default there is no cap. A build exceeding them fails with
+    /// [`CreateTxError::FeeAboveLimit`] or [`CreateTxError::FeeRateAboveLimit`], unless other
+    /// limits are acknowledged with [`TxBuilder::fee_limits`]. When building, the fee rate is
+    /// computed over the estimated weight of the transaction, an upper bound: the fee rate of the
+    /// signed transaction may be higher, it's checked again when signing.
+    ///
+    /// [`Wallet::sign`] refuses to sign a PSBT exceeding the tightest of these limits and of
+    /// [`SignOptions::max_fee`] and [`SignOptions::max_fee_rate`]. The fee is computed from the
+    /// previous outputs in the PSBT, see [`SignOptions::allow_unknown_prevouts`].
+    ///
+    /// [`TxBuilder`]: super::tx_builder::TxBuilder
+    /// [`TxBuilder::fee_limits`]: super::tx_builder::TxBuilder::fee_limits
+    /// [`CreateTxError::FeeAboveLimit`]: super::error::CreateTxError::FeeAboveLimit
+    /// [`CreateTxError::FeeRateAboveLimit`]: super::error::CreateTxError::FeeRateAboveLimit
+    /// [`SignOptions::max_fee`]: super::signer::SignOptions::max_fee
+    /// [`SignOptions::max_fee_rate`]: super::signer::SignOptions::max_fee_rate
+    /// [`SignOptions::allow_unknown_prevouts`]: super::signer::SignOptions::allow_unknown_prevouts
+    pub fn set_fee_limits(&mut self, limits: FeeLimits) {
+        self.fee_limits = limits;
+    }
+
+    /// The limits set with [`Wallet::set_fee_limits`]
+    pub fn fee_limits(&self) -> FeeLimits {
+        self.fee_limits
+    }
+
+    /// The limits of a build with `params`, see [`TxBuilder::fee_limits`]
+    ///
+    /// [`TxBuilder::fee_limits`]: super::tx_builder::TxBuilder::fee_limits
+    pub(crate) fn build_fee_limits(&self, params: &TxParams) -> FeeLimits {
+        match params.fee_limits {
+            Some(limits) if params.acknowledge_fee_limits => limits,
+            Some(limits) => limits.tightest(self.fee_limits),
+            None => self.fee_limits,
+        }
+    }
+}
+
+/// The fee of `psbt` computed from the previous outputs of its inputs, or the indexes of the
+/// inputs without one
+pub(crate) fn psbt_prevout_fee(psbt: &Psbt) -> Result<Amount, Vec<usize>> {
+    let mut missing = Vec::new();
+    let mut input_value = Amount::ZERO;
+    for index in 0..psbt.inputs.len() {
+        match psbt.get_utxo_for(index) {
+            Some(txout) => input_value += txout.value,
+            None => missing.push(index),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+    let output_value = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|txout| txout.value)
+        .sum::<Amount>();
+    // a negative fee can't exceed a cap
+    Ok(input_value
+        .checked_sub(output_value)
+        .unwrap_or(Amount::ZERO))
+}
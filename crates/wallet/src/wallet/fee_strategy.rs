@@ -0,0 +1,409 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Fee rate suggestions from backend estimates
+//!
+//! Backends estimate the fee rate needed to confirm within a number of blocks: Esplora's
+//! `fee-estimates` endpoint returns a table of sat/vB by target, Electrum's `estimatefee` and
+//! Bitcoin Core's `estimatesmartfee` return BTC/kvB for one target. [`FeeEstimateTable`] collects
+//! them behind the [`FeeEstimates`] trait, and a [`FeeStrategy`] turns them into the fee rate of
+//! a transaction, clamped between the minimum relay fee of the backend and a ceiling chosen by
+//! the caller. The returned [`FeeResolution`] tells which target and raw estimate were used.
+//!
+//! ```
+//! # use bdk_wallet::bitcoin::FeeRate;
+//! use bdk_wallet::wallet::fee_strategy::{FeeEstimateTable, FeeStrategy};
+//!
+//! // as returned by Esplora, in sat/vB
+//! let estimates = FeeEstimateTable::from_sat_per_vb([(1, 25.3), (6, 12.0), (144, 2.1)]);
+//! let max_fee_rate = FeeRate::from_sat_per_vb(200).unwrap();
+//!
+//! // there is no estimate for 3 blocks, the one for 1 block is used
+//! let resolution = FeeStrategy::Priority { confirm_within_blocks: 3 }
+//!     .resolve(&estimates, max_fee_rate)?;
+//! assert_eq!(resolution.target, Some(1));
+//! assert_eq!(resolution.fee_rate, FeeRate::from_sat_per_kwu(6_325));
+//! # Ok::<_, bdk_wallet::wallet::fee_strategy::FeeStrategyError>(())
+//! ```
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::FeeRate;
+
+use crate::collections::BTreeMap;
+use crate::units::SatPerVb;
+
+/// The confirmation target of [`FeeStrategy::Economic`], about a day
+pub const ECONOMIC_TARGET: u16 = 144;
+
+/// Fee rate estimates of a backend
+pub trait FeeEstimates {
+    /// The fee rate estimated to confirm a transaction within `target` blocks, `None` if the
+    /// backend has no estimate for this target
+    fn estimate(&self, target: u16) -> Option<FeeRate>;
+
+    /// The targets with an estimate, in increasing order
+    fn targets(&self) -> Vec<u16>;
+
+    /// The minimum fee rate of the transactions relayed by the backend
+    fn min_relay_fee(&self) -> FeeRate {
+        FeeRate::BROADCAST_MIN
+    }
+}
+
+/// A table of fee rate estimates by confirmation target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeEstimateTable {
+    estimates: BTreeMap<u16, FeeRate>,
+    min_relay_fee: FeeRate,
+}
+
+impl Default for FeeEstimateTable {
+    fn default() -> Self {
+        Self {
+            estimates: BTreeMap::new(),
+            min_relay_fee: FeeRate::BROADCAST_MIN,
+        }
+    }
+}
+
+impl FeeEstimateTable {
+    /// An empty table, with a minimum relay fee of 1 sat/vB
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A table of estimates in sat/vB, like the ones returned by Esplora
+    ///
+    /// Estimates which are negative or not finite are ignored.
+    pub fn from_sat_per_vb(estimates: impl IntoIterator<Item = (u16, f64)>) -> Self {
+        let mut table = Self::new();
+        for (target, sat_per_vb) in estimates {
+            if let Some(fee_rate) = fee_rate_from_msat_per_vb(sat_per_vb * 1_000.0) {
+                table.insert(target, fee_rate);
+            }
+        }
+        table
+    }
+
+    /// A table of estimates in BTC/kvB, like the ones returned by Electrum and Bitcoin Core
+    ///
+    /// Estimates which are negative or not finite are ignored, such as the `-1` returned by
+    /// Electrum when it has no estimate.
+    pub fn from_btc_per_kvb(estimates: impl IntoIterator<Item = (u16, f64)>) -> Self {
+        let mut table = Self::new();
+        for (target, btc_per_kvb) in estimates {
+            // 1 sat/kvB is 1 msat/vB
+            if let Some(fee_rate) = fee_rate_from_msat_per_vb(btc_per_kvb * 100_000_000.0) {
+                table.insert(target, fee_rate);
+            }
+        }
+        table
+    }
+
+    /// Set the minimum relay fee of the backend
+    pub fn with_min_relay_fee(mut self, min_relay_fee: FeeRate) -> Self {
+        self.min_relay_fee = min_relay_fee;
+        self
+    }
+
+    /// Add the estimate for `target` blocks, returning the one it replaces
+    pub fn insert(&mut self, target: u16, fee_rate: FeeRate) -> Option<FeeRate> {
+        self.estimates.insert(target, fee_rate)
+    }
+}
+
+impl FeeEstimates for FeeEstimateTable {
+    fn estimate(&self, target: u16) -> Option<FeeRate> {
+        self.estimates.get(&target).copied()
+    }
+
+    fn targets(&self) -> Vec<u16> {
+        self.estimates.keys().copied().collect()
+    }
+
+    fn min_relay_fee(&self) -> FeeRate {
+        self.min_relay_fee
+    }
+}
+
+/// Convert `msat_per_vb`, rounding up, `None` if it's negative, not finite or overflows
+fn fee_rate_from_msat_per_vb(msat_per_vb: f64) -> Option<FeeRate> {
+    if !msat_per_vb.is_finite() || msat_per_vb < 0.0 || msat_per_vb >= u64::MAX as f64 {
+        return None;
+    }
+    let mut msat = msat_per_vb as u64;
+    if (msat as f64) < msat_per_vb {
+        msat += 1;
+    }
+    Some(SatPerVb::from_msat(msat).into())
+}
+
+/// How to choose the fee rate of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeStrategy {
+    /// Confirm within `confirm_within_blocks` blocks
+    ///
+    /// Without an estimate for this target, the one of the closest shorter target is used, or of
+    /// the shortest target if none is shorter.
+    Priority {
+        /// The confirmation target, in blocks
+        confirm_within_blocks: u16,
+    },
+    /// Confirm without hurry, within [`ECONOMIC_TARGET`] blocks
+    Economic,
+    /// A fixed fee rate, still clamped like the estimates
+    Custom(FeeRate),
+}
+
+/// The outcome of resolving a [`FeeStrategy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeResolution {
+    /// The resolved strategy
+    pub strategy: FeeStrategy,
+    /// The target of the estimate used, `None` for [`FeeStrategy::Custom`]
+    pub target: Option<u16>,
+    /// The estimate of `target`, or the fee rate of [`FeeStrategy::Custom`], before clamping
+    pub raw_fee_rate: FeeRate,
+    /// The fee rate to use, `raw_fee_rate` clamped between the minimum relay fee and the ceiling
+    pub fee_rate: FeeRate,
+}
+
+impl FeeResolution {
+    /// Whether the raw fee rate was outside of the bounds and got clamped
+    pub fn is_clamped(&self) -> bool {
+        self.raw_fee_rate != self.fee_rate
+    }
+}
+
+/// Error resolving a [`FeeStrategy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeeStrategyError {
+    /// The backend has no estimate at all
+    NoEstimates,
+    /// The ceiling is below the minimum relay fee of the backend
+    InvalidBounds {
+        /// The minimum relay fee of the backend
+        min_relay_fee: FeeRate,
+        /// The ceiling chosen by the caller
+        max_fee_rate: FeeRate,
+    },
+}
+
+impl fmt::Display for FeeStrategyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeeStrategyError::NoEstimates => write!(f, "The backend has no fee estimates"),
+            FeeStrategyError::InvalidBounds {
+                min_relay_fee,
+                max_fee_rate,
+            } => write!(
+                f,
+                "The maximum fee rate ({} sat/kwu) is below the minimum relay fee ({} sat/kwu)",
+                max_fee_rate.to_sat_per_kwu(),
+                min_relay_fee.to_sat_per_kwu()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FeeStrategyError {}
+
+impl FeeStrategy {
+    /// Resolve the strategy against `estimates`
+    ///
+    /// The fee rate is clamped between the minimum relay fee of the backend and `max_fee_rate`,
+    /// which protects against absurd estimates.
+    pub fn resolve<E: FeeEstimates + ?Sized>(
+        &self,
+        estimates: &E,
+        max_fee_rate: FeeRate,
+    ) -> Result<FeeResolution, FeeStrategyError> {
+        let min_relay_fee = estimates.min_relay_fee();
+        if max_fee_rate < min_relay_fee {
+            return Err(FeeStrategyError::InvalidBounds {
+                min_relay_fee,
+                max_fee_rate,
+            });
+        }
+
+        let (target, raw_fee_rate) = match *self {
+            FeeStrategy::Custom(fee_rate) => (None, fee_rate),
+            FeeStrategy::Priority {
+                confirm_within_blocks,
+            } => Self::lookup(estimates, confirm_within_blocks)?,
+            FeeStrategy::Economic => Self::lookup(estimates, ECONOMIC_TARGET)?,
+        };
+
+        Ok(FeeResolution {
+            strategy: *self,
+            target,
+            raw_fee_rate,
+            fee_rate: raw_fee_rate.clamp(min_relay_fee, max_fee_rate),
+        })
+    }
+
+    /// The estimate of the closest target not above `target`, or of the shortest target
+    fn lookup<E: FeeEstimates + ?Sized>(
+        estimates: &E,
+        target: u16,
+    ) -> Result<(Option<u16>, FeeRate), FeeStrategyError> {
+        let targets = estimates.targets();
+        let used = targets
+            .iter()
+            .rev()
+            .find(|&&t| t <= target)
+            .or_else(|| targets.first())
+            .copied()
+            .ok_or(FeeStrategyError::NoEstimates)?;
+        let fee_rate = estimates
+            .estimate(used)
+            .ok_or(FeeStrategyError::NoEstimates)?;
+        Ok((Some(used), fee_rate))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sat_per_vb(sat_per_vb: u64) -> FeeRate {
+        FeeRate::from_sat_per_vb(sat_per_vb).unwrap()
+    }
+
+    fn table() -> FeeEstimateTable {
+        FeeEstimateTable::from_sat_per_vb([
+            (1, 40.0),
+            (2, 30.0),
+            (6, 12.5),
+            (25, 5.0),
+            (144, 1.9),
+            (1008, 0.5),
+        ])
+    }
+
+    #[test]
+    fn test_from_backend_units() {
+        let table = FeeEstimateTable::from_sat_per_vb([(1, 12.345), (2, -1.0), (3, f64::NAN)]);
+        // 12.345 sat/vB is 3086.25 sat/kwu
+        assert_eq!(table.estimate(1), Some(FeeRate::from_sat_per_kwu(3_087)));
+        assert_eq!(table.targets(), [1]);
+
+        let table = FeeEstimateTable::from_btc_per_kvb([(2, 0.00012345), (6, -1.0)]);
+        assert_eq!(table.estimate(2), Some(FeeRate::from_sat_per_kwu(3_087)));
+        assert_eq!(table.estimate(6), None);
+    }
+
+    #[test]
+    fn test_priority_exact_target() {
+        let resolution = FeeStrategy::Priority {
+            confirm_within_blocks: 6,
+        }
+        .resolve(&table(), sat_per_vb(100))
+        .unwrap();
+        assert_eq!(resolution.target, Some(6));
+        assert_eq!(resolution.raw_fee_rate, FeeRate::from_sat_per_kwu(3_125));
+        assert_eq!(resolution.fee_rate, resolution.raw_fee_rate);
+        assert!(!resolution.is_clamped());
+    }
+
+    #[test]
+    fn test_priority_missing_target() {
+        let table = table();
+        // the closest shorter target meets the deadline
+        let resolution = FeeStrategy::Priority {
+            confirm_within_blocks: 10,
+        }
+        .resolve(&table, sat_per_vb(100))
+        .unwrap();
+        assert_eq!(resolution.target, Some(6));
+
+        // without a shorter target, the shortest one is used
+        let table = FeeEstimateTable::from_sat_per_vb([(3, 20.0), (12, 8.0)]);
+        let resolution = FeeStrategy::Priority {
+            confirm_within_blocks: 1,
+        }
+        .resolve(&table, sat_per_vb(100))
+        .unwrap();
+        assert_eq!(resolution.target, Some(3));
+        assert_eq!(resolution.fee_rate, sat_per_vb(20));
+    }
+
+    #[test]
+    fn test_economic() {
+        let resolution = FeeStrategy::Economic
+            .resolve(&table(), sat_per_vb(100))
+            .unwrap();
+        assert_eq!(resolution.target, Some(ECONOMIC_TARGET));
+        assert_eq!(resolution.fee_rate, FeeRate::from_sat_per_kwu(475));
+
+        // a longer target is not used, even if cheaper
+        let table = FeeEstimateTable::from_sat_per_vb([(12, 8.0), (1008, 1.0)]);
+        let resolution = FeeStrategy::Economic
+            .resolve(&table, sat_per_vb(100))
+            .unwrap();
+        assert_eq!(resolution.target, Some(12));
+    }
+
+    #[test]
+    fn test_clamping() {
+        // an absurd estimate is capped by the ceiling
+        let mut table = table();
+        table.insert(1, sat_per_vb(5_000));
+        let resolution = FeeStrategy::Priority {
+            confirm_within_blocks: 1,
+        }
+        .resolve(&table, sat_per_vb(200))
+        .unwrap();
+        assert_eq!(resolution.raw_fee_rate, sat_per_vb(5_000));
+        assert_eq!(resolution.fee_rate, sat_per_vb(200));
+        assert!(resolution.is_clamped());
+
+        // an estimate below the minimum relay fee is raised to it
+        let resolution = FeeStrategy::Priority {
+            confirm_within_blocks: 1008,
+        }
+        .resolve(&table, sat_per_vb(200))
+        .unwrap();
+        assert_eq!(resolution.raw_fee_rate, FeeRate::from_sat_per_kwu(125));
+        assert_eq!(resolution.fee_rate, FeeRate::BROADCAST_MIN);
+
+        let table = table.with_min_relay_fee(sat_per_vb(3));
+        let resolution = FeeStrategy::Custom(sat_per_vb(2))
+            .resolve(&table, sat_per_vb(200))
+            .unwrap();
+        assert_eq!(resolution.target, None);
+        assert_eq!(resolution.raw_fee_rate, sat_per_vb(2));
+        assert_eq!(resolution.fee_rate, sat_per_vb(3));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            FeeStrategy::Economic.resolve(&FeeEstimateTable::new(), sat_per_vb(100)),
+            Err(FeeStrategyError::NoEstimates)
+        );
+        // a custom fee rate doesn't need estimates
+        assert!(FeeStrategy::Custom(sat_per_vb(5))
+            .resolve(&FeeEstimateTable::new(), sat_per_vb(100))
+            .is_ok());
+
+        let table = table().with_min_relay_fee(sat_per_vb(2));
+        assert_eq!(
+            FeeStrategy::Economic.resolve(&table, sat_per_vb(1)),
+            Err(FeeStrategyError::InvalidBounds {
+                min_relay_fee: sat_per_vb(2),
+                max_fee_rate: sat_per_vb(1),
+            })
+        );
+    }
+}
@@ -0,0 +1,305 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! The fee rate of signed transactions, compared to the one they target, see
+//! [`Wallet::check_feerate`]
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bdk_chain::tx_graph::CalculateFeeError;
+use bitcoin::psbt::{self, Psbt};
+use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
+use bitcoin::{Amount, FeeRate, SignedAmount, Transaction, Weight};
+
+use super::signer::{FeeAdjustment, SignOptions, SignerError};
+use super::utils::IsDust;
+use super::Wallet;
+use crate::psbt::PsbtUtils;
+
+/// How many times the inputs are signed again, in case each signature changes the weight
+const MAX_FEE_ADJUSTMENTS: usize = 3;
+
+/// A transaction or a finalized PSBT, whose fee rate is checked by [`Wallet::check_feerate`]
+#[derive(Debug, Clone, Copy)]
+pub enum TxOrPsbt<'a> {
+    /// A signed transaction, spending outputs of the wallet's graph
+    Tx(&'a Transaction),
+    /// A finalized PSBT
+    Psbt(&'a Psbt),
+}
+
+impl<'a> From<&'a Transaction> for TxOrPsbt<'a> {
+    fn from(tx: &'a Transaction) -> Self {
+        TxOrPsbt::Tx(tx)
+    }
+}
+
+impl<'a> From<&'a Psbt> for TxOrPsbt<'a> {
+    fn from(psbt: &'a Psbt) -> Self {
+        TxOrPsbt::Psbt(psbt)
+    }
+}
+
+/// The fee rate a signed transaction achieves, compared to its target, see
+/// [`Wallet::check_feerate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeRateDrift {
+    /// The absolute fee
+    pub fee: Amount,
+    /// The weight of the signed transaction
+    pub weight: Weight,
+    /// The fee rate achieved by the signed transaction
+    pub fee_rate: FeeRate,
+    /// The target fee rate
+    pub target: FeeRate,
+}
+
+impl FeeRateDrift {
+    /// How far the fee is from the fee at the target fee rate: positive if the transaction pays
+    /// more than its target
+    pub fn drift(&self) -> SignedAmount {
+        let vsize = Weight::from_vb_unchecked(self.weight.to_vbytes_ceil());
+        SignedAmount::from_sat(self.fee.to_sat() as i64 - (self.target * vsize).to_sat() as i64)
+    }
+
+    /// How far the achieved fee rate is from the target, in sat/kwu: positive if the transaction
+    /// pays more than its target
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "use `drift`, which returns the difference of the fees as a `SignedAmount`"
+    )]
+    pub fn drift_sat_per_kwu(&self) -> i64 {
+        self.fee_rate.to_sat_per_kwu() as i64 - self.target.to_sat_per_kwu() as i64
+    }
+
+    /// The fee missing to reach the target fee rate (or the minimum relay fee rate if higher),
+    /// zero if the transaction pays enough
+    pub fn shortfall(&self) -> Amount {
+        let vsize = Weight::from_vb_unchecked(self.weight.to_vbytes_ceil());
+        let required = (self.target * vsize).max(FeeRate::BROADCAST_MIN * vsize);
+        required.checked_sub(self.fee).unwrap_or(Amount::ZERO)
+    }
+
+    /// Whether the transaction pays less than its target fee rate
+    pub fn is_below_target(&self) -> bool {
+        self.fee_rate < self.target
+    }
+
+    /// Whether the transaction pays less than the minimum relay fee rate, 1 sat/vB
+    pub fn is_below_min_relay(&self) -> bool {
+        let vsize = Weight::from_vb_unchecked(self.weight.to_vbytes_ceil());
+        self.fee < FeeRate::BROADCAST_MIN * vsize
+    }
+}
+
+/// Error returned by [`Wallet::check_feerate`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum FeeRateCheckError {
+    /// The fee of the transaction can't be computed
+    Fee(CalculateFeeError),
+    /// The inputs at these indexes of the PSBT aren't finalized, its weight isn't known yet
+    NotFinalized(Vec<usize>),
+}
+
+impl fmt::Display for FeeRateCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fee(err) => write!(f, "Can't compute the fee: {}", err),
+            Self::NotFinalized(inputs) => {
+                write!(f, "The PSBT inputs {:?} aren't finalized", inputs)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FeeRateCheckError {}
+
+impl From<CalculateFeeError> for FeeRateCheckError {
+    fn from(err: CalculateFeeError) -> Self {
+        Self::Fee(err)
+    }
+}
+
+/// Whether the signature of `input` commits to the output at `output_index`
+fn commits_to_output(input: &psbt::Input, input_index: usize, output_index: usize) -> bool {
+    let sighash = match input.sighash_type {
+        None => return true,
+        Some(sighash) => sighash,
+    };
+    let (none, single) = if input.tap_internal_key.is_some() {
+        match sighash.taproot_hash_ty() {
+            Ok(TapSighashType::None | TapSighashType::NonePlusAnyoneCanPay) => (true, false),
+            Ok(TapSighashType::Single | TapSighashType::SinglePlusAnyoneCanPay) => (false, true),
+            _ => (false, false),
+        }
+    } else {
+        match sighash.ecdsa_hash_ty() {
+            Ok(EcdsaSighashType::None | EcdsaSighashType::NonePlusAnyoneCanPay) => (true, false),
+            Ok(EcdsaSighashType::Single | EcdsaSighashType::SinglePlusAnyoneCanPay) => {
+                (false, true)
+            }
+            _ => (false, false),
+        }
+    };
+    !none && (!single || input_index == output_index)
+}
+
+impl Wallet {
+    /// Compute the fee rate of a signed transaction or of a finalized PSBT, and how far it is
+    /// from `target`
+    ///
+    /// The fee is computed from the outputs spent by the transaction, the ones of the PSBT
+    /// inputs or of the wallet's graph. The target is typically the [`FeeTarget::fee_rate`]
+    /// returned by [`TxBuilder::finish_with_fee_target`]: the fee is computed from an upper
+    /// bound of the weight of the signed transaction, which may be lighter (the fee rate then
+    /// drifts above the target) or, with some signers, heavier.
+    ///
+    /// [`FeeTarget::fee_rate`]: super::tx_builder::FeeTarget::fee_rate
+    /// [`TxBuilder::finish_with_fee_target`]: super::tx_builder::TxBuilder::finish_with_fee_target
+    pub fn check_feerate<'a>(
+        &self,
+        tx: impl Into<TxOrPsbt<'a>>,
+        target: FeeRate,
+    ) -> Result<FeeRateDrift, FeeRateCheckError> {
+        let (fee, weight) = match tx.into() {
+            TxOrPsbt::Tx(tx) => (self.calculate_fee(tx)?, tx.weight()),
+            TxOrPsbt::Psbt(psbt) => {
+                let not_finalized = psbt
+                    .inputs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, input)| {
+                        input.final_script_sig.is_none() && input.final_script_witness.is_none()
+                    })
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>();
+                if !not_finalized.is_empty() {
+                    return Err(FeeRateCheckError::NotFinalized(not_finalized));
+                }
+                let tx = psbt.clone().extract_tx_unchecked_fee_rate();
+                (self.psbt_fee(psbt)?, tx.weight())
+            }
+        };
+        Ok(FeeRateDrift {
+            fee,
+            weight,
+            fee_rate: fee / weight,
+            target,
+        })
+    }
+
+    /// The fee of `psbt`, from the outputs of its inputs or of the wallet's graph
+    fn psbt_fee(&self, psbt: &Psbt) -> Result<Amount, CalculateFeeError> {
+        let graph = self.indexed_graph.graph();
+        let mut missing = Vec::new();
+        let mut input_value = Amount::ZERO;
+        for (index, txin) in psbt.unsigned_tx.input.iter().enumerate() {
+            let txout = psbt
+                .get_utxo_for(index)
+                .or_else(|| graph.get_txout(txin.previous_output).cloned());
+            match txout {
+                Some(txout) => input_value += txout.value,
+                None => missing.push(txin.previous_output),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(CalculateFeeError::MissingTxOut(missing));
+        }
+        let output_value = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .map(|txout| txout.value)
+            .sum::<Amount>();
+        input_value.checked_sub(output_value).ok_or_else(|| {
+            CalculateFeeError::NegativeFee(SignedAmount::from_sat(
+                input_value.to_sat() as i64 - output_value.to_sat() as i64,
+            ))
+        })
+    }
+
+    /// Reduce the change of the signed `psbt` following `adjustment`, and sign again the inputs
+    /// committing to it, see [`FeeAdjustment`]
+    ///
+    /// `signed_before` are the inputs which had signatures, or were finalized, before
+    /// [`Wallet::sign`] was called.
+    pub(crate) fn adjust_fee(
+        &self,
+        psbt: &mut Psbt,
+        adjustment: &FeeAdjustment,
+        sign_options: &SignOptions,
+        signed_before: &[usize],
+    ) -> Result<(), SignerError> {
+        let change_index = adjustment.change_index;
+        let change = psbt
+            .unsigned_tx
+            .output
+            .get(change_index)
+            .filter(|txout| self.is_mine(&txout.script_pubkey))
+            .ok_or(SignerError::InvalidChangeOutput(change_index))?;
+        let change_script = change.script_pubkey.clone();
+        let original_change = change.value;
+
+        let affected = psbt
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(index, input)| commits_to_output(input, *index, change_index))
+            .map(|(index, _)| index)
+            .collect::<alloc::collections::BTreeSet<_>>();
+        if affected.iter().any(|index| signed_before.contains(index)) {
+            return Ok(());
+        }
+
+        for _ in 0..MAX_FEE_ADJUSTMENTS {
+            let mut finalized = psbt.clone();
+            if !self.finalize_psbt(&mut finalized, sign_options.clone())? {
+                return Ok(());
+            }
+            let shortfall = match self.check_feerate(&finalized, adjustment.target) {
+                Ok(drift) => drift.shortfall(),
+                Err(_) => return Ok(()),
+            };
+            if shortfall == Amount::ZERO {
+                return Ok(());
+            }
+            let change = &mut psbt.unsigned_tx.output[change_index];
+            let reduced = match change.value.checked_sub(shortfall) {
+                Some(reduced)
+                    if original_change - reduced <= adjustment.max_reduction
+                        && !reduced.to_sat().is_dust(&change_script) =>
+                {
+                    reduced
+                }
+                _ => return Ok(()),
+            };
+            change.value = reduced;
+
+            for index in &affected {
+                let input = &mut psbt.inputs[*index];
+                input.partial_sigs.clear();
+                input.tap_key_sig = None;
+                input.tap_script_sigs.clear();
+            }
+            self.sign_psbt(
+                psbt,
+                SignOptions {
+                    inputs: Some(affected.clone()),
+                    try_finalize: false,
+                    fee_adjustment: None,
+                    ..sign_options.clone()
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A digest of the state of a wallet, to check that two devices agree, see
+//! [`Wallet::state_fingerprint`]
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bdk_chain::ChainPosition;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use serde::{Deserialize, Serialize};
+
+use super::Wallet;
+use crate::KeychainKind;
+
+/// The version of the preimage of [`Wallet::state_fingerprint`], it changes whenever the preimage
+/// does
+pub const STATE_FINGERPRINT_VERSION: u32 = 1;
+
+/// A component of the state of a wallet hashed by [`Wallet::state_fingerprint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FingerprintComponent {
+    /// The canonical transactions, with their confirmation height and time
+    Transactions,
+    /// The unspent outputs
+    Utxos,
+    /// The last revealed index of each keychain
+    RevealedIndices,
+    /// The tip of the local chain
+    ChainTip,
+}
+
+impl FingerprintComponent {
+    /// All the components, in the order of the preimage of the fingerprint
+    pub const ALL: [FingerprintComponent; 4] = [
+        FingerprintComponent::Transactions,
+        FingerprintComponent::Utxos,
+        FingerprintComponent::RevealedIndices,
+        FingerprintComponent::ChainTip,
+    ];
+
+    /// The name of the component in the preimage of its hash
+    fn name(&self) -> &'static str {
+        match self {
+            FingerprintComponent::Transactions => "txs",
+            FingerprintComponent::Utxos => "utxos",
+            FingerprintComponent::RevealedIndices => "revealed",
+            FingerprintComponent::ChainTip => "tip",
+        }
+    }
+}
+
+/// The hashes of the components of the state of a wallet, see [`Wallet::state_fingerprint`]
+///
+/// A device can send it to another one to find out which component they disagree on, with
+/// [`StateFingerprint::diff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StateFingerprint {
+    /// The version of the preimage, [`STATE_FINGERPRINT_VERSION`] when built by this crate
+    pub version: u32,
+    /// The hash of [`FingerprintComponent::Transactions`]
+    pub transactions: [u8; 32],
+    /// The hash of [`FingerprintComponent::Utxos`]
+    pub utxos: [u8; 32],
+    /// The hash of [`FingerprintComponent::RevealedIndices`]
+    pub revealed_indices: [u8; 32],
+    /// The hash of [`FingerprintComponent::ChainTip`]
+    pub chain_tip: [u8; 32],
+}
+
+impl StateFingerprint {
+    /// The hash of `component`
+    pub fn component(&self, component: FingerprintComponent) -> [u8; 32] {
+        match component {
+            FingerprintComponent::Transactions => self.transactions,
+            FingerprintComponent::Utxos => self.utxos,
+            FingerprintComponent::RevealedIndices => self.revealed_indices,
+            FingerprintComponent::ChainTip => self.chain_tip,
+        }
+    }
+
+    /// The fingerprint of the state, the hash of the hashes of the components, see
+    /// [`Wallet::state_fingerprint`]
+    pub fn digest(&self) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        engine.input(format!("bdk_wallet/state_fingerprint/v{}", self.version).as_bytes());
+        for component in FingerprintComponent::ALL {
+            engine.input(&self.component(component));
+        }
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+
+    /// Which components differ between this fingerprint and `remote`, `None` if they're the same
+    pub fn diff(&self, remote: &StateFingerprint) -> Option<FingerprintMismatch> {
+        if self.version != remote.version {
+            return Some(FingerprintMismatch::Version {
+                local: self.version,
+                remote: remote.version,
+            });
+        }
+        let components = FingerprintComponent::ALL
+            .into_iter()
+            .filter(|&component| self.component(component) != remote.component(component))
+            .collect::<Vec<_>>();
+        if components.is_empty() {
+            None
+        } else {
+            Some(FingerprintMismatch::Components(components))
+        }
+    }
+}
+
+/// How the states of two wallets differ, see [`StateFingerprint::diff`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FingerprintMismatch {
+    /// The fingerprints have different versions, their components can't be compared
+    Version {
+        /// The version of the local fingerprint
+        local: u32,
+        /// The version of the remote fingerprint
+        remote: u32,
+    },
+    /// The components which differ, in the order of [`FingerprintComponent::ALL`]
+    Components(Vec<FingerprintComponent>),
+}
+
+impl FingerprintMismatch {
+    /// Which components differ between the states of `local` and `remote`, `None` if they're the
+    /// same
+    pub fn between(local: &Wallet, remote: &Wallet) -> Option<Self> {
+        local
+            .state_fingerprint_components()
+            .diff(&remote.state_fingerprint_components())
+    }
+}
+
+impl fmt::Display for FingerprintMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Version { local, remote } => write!(
+                f,
+                "The fingerprint versions differ: {} locally, {} remotely",
+                local, remote
+            ),
+            Self::Components(components) => {
+                write!(f, "The wallet states differ in:")?;
+                for component in components {
+                    write!(f, " {}", component.name())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Hash the `records` of `component`, in the lexicographic order of their bytes.
+fn component_hash(component: FingerprintComponent, mut records: Vec<Vec<u8>>) -> [u8; 32] {
+    records.sort_unstable();
+    let mut engine = sha256::Hash::engine();
+    engine.input(
+        format!(
+            "bdk_wallet/state_fingerprint/v{}/{}",
+            STATE_FINGERPRINT_VERSION,
+            component.name()
+        )
+        .as_bytes(),
+    );
+    engine.input(&(records.len() as u64).to_le_bytes());
+    for record in records {
+        engine.input(&record);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Append the encoding of `keychain` to `record`.
+pub(super) fn push_keychain(record: &mut Vec<u8>, keychain: &KeychainKind) {
+    record.push(keychain.as_byte());
+    if let KeychainKind::Extra(label) = keychain {
+        record.push(label.as_str().len() as u8);
+        record.extend_from_slice(label.as_str().as_bytes());
+    }
+}
+
+impl Wallet {
+    /// A digest of the canonical state of the wallet, to check cheaply that two devices syncing
+    /// the same descriptors agree
+    ///
+    /// The fingerprint only depends on the state, not on how it was reached: the order the data
+    /// was inserted in, or whether the wallet was loaded from compacted or persisted changesets.
+    /// [`Wallet::state_fingerprint_components`] returns the hashes of the components, to find out
+    /// which one differs.
+    ///
+    /// # Preimage
+    ///
+    /// This is version 1 ([`STATE_FINGERPRINT_VERSION`]) of the preimage, any change to it comes
+    /// with a new version. All the integers are little-endian, the txids and block hashes are
+    /// in the byte order of their serialization, and a keychain is encoded as `e` (external), `i`
+    /// (internal), or `x`, the byte length of its label and its label in UTF-8 (extra keychain).
+    ///
+    /// Each component is hashed as `SHA256("bdk_wallet/state_fingerprint/v1/" || name || count ||
+    /// records)`, `count` being the number of records as a `u64` and the records being sorted in
+    /// the lexicographic order of their bytes:
+    ///
+    /// - `txs`: for each canonical transaction, its txid, then `0x00` if it's unconfirmed, or
+    ///   `0x01`, the confirmation height as a `u32` and the confirmation time as a `u64`.
+    /// - `utxos`: for each unspent output, its txid, its vout as a `u32`, its value in satoshis
+    ///   as a `u64`, its keychain and its derivation index as a `u32`.
+    /// - `revealed`: for each keychain with revealed script pubkeys, the keychain and its last
+    ///   revealed index as a `u32`.
+    /// - `tip`: the height of the tip of the local chain as a `u32` and its block hash.
+    ///
+    /// The fingerprint is `SHA256("bdk_wallet/state_fingerprint/v1" || txs || utxos || revealed ||
+    /// tip)`, see [`StateFingerprint::digest`].
+    ///
+    /// The last seen times of the unconfirmed transactions and the anchor blocks of the confirmed
+    /// ones are left out: they depend on when and with which chain source the wallet was synced.
+    pub fn state_fingerprint(&self) -> [u8; 32] {
+        self.state_fingerprint_components().digest()
+    }
+
+    /// The hashes of the components of [`Wallet::state_fingerprint`]
+    pub fn state_fingerprint_components(&self) -> StateFingerprint {
+        let transactions = self
+            .transactions()
+            .map(|tx| {
+                let mut record = tx.tx_node.txid.to_byte_array().to_vec();
+                match tx.chain_position {
+                    ChainPosition::Confirmed(anchor) => {
+                        record.push(0x01);
+                        record.extend_from_slice(&anchor.confirmation_height.to_le_bytes());
+                        record.extend_from_slice(&anchor.confirmation_time.to_le_bytes());
+                    }
+                    ChainPosition::Unconfirmed(_) => record.push(0x00),
+                }
+                record
+            })
+            .collect();
+        let utxos = self
+            .list_unspent()
+            .map(|utxo| {
+                let mut record = utxo.outpoint.txid.to_byte_array().to_vec();
+                record.extend_from_slice(&utxo.outpoint.vout.to_le_bytes());
+                record.extend_from_slice(&utxo.txout.value.to_sat().to_le_bytes());
+                push_keychain(&mut record, &utxo.keychain);
+                record.extend_from_slice(&utxo.derivation_index.to_le_bytes());
+                record
+            })
+            .collect();
+        let revealed_indices = self
+            .indexed_graph
+            .index
+            .last_revealed_indices()
+            .into_iter()
+            .map(|(keychain, index)| {
+                let mut record = Vec::new();
+                push_keychain(&mut record, &keychain);
+                record.extend_from_slice(&index.to_le_bytes());
+                record
+            })
+            .collect();
+        let tip = self.chain.tip();
+        let mut tip_record = tip.height().to_le_bytes().to_vec();
+        tip_record.extend_from_slice(&tip.hash().to_byte_array());
+
+        StateFingerprint {
+            version: STATE_FINGERPRINT_VERSION,
+            transactions: component_hash(FingerprintComponent::Transactions, transactions),
+            utxos: component_hash(FingerprintComponent::Utxos, utxos),
+            revealed_indices: component_hash(
+                FingerprintComponent::RevealedIndices,
+                revealed_indices,
+            ),
+            chain_tip: component_hash(FingerprintComponent::ChainTip, vec![tip_record]),
+        }
+    }
+}
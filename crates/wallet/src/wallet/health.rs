@@ -0,0 +1,234 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A summary of the state of the wallet, see [`Wallet::health_report`]
+
+use alloc::vec::Vec;
+
+use bdk_chain::collections::BTreeMap;
+use bdk_chain::ChainPosition;
+use bitcoin::{Amount, FeeRate, Txid};
+use serde::{Deserialize, Serialize};
+
+use super::coin_selection::OutputGroup;
+use super::utils::ScriptType;
+use super::{BlockTimeOrHeight, Wallet};
+use crate::{KeychainKind, Utxo, WeightedUtxo};
+
+/// The lower bounds of the value buckets of [`HealthReport::size_buckets`], in satoshis
+const SIZE_BUCKET_BOUNDS: [u64; 6] = [0, 1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+/// A summary of the state of the wallet, see [`Wallet::health_report`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// The feerate at which the unspent outputs are evaluated
+    pub feerate: FeeRate,
+    /// The unspent outputs by script type
+    pub script_types: BTreeMap<ScriptType, UtxoStats>,
+    /// The unspent outputs by value, from the smallest to the largest values
+    pub size_buckets: Vec<SizeBucket>,
+    /// The unspent outputs which cost at least their value to spend at
+    /// [`feerate`](Self::feerate), which coin selection only spends when it must
+    pub uneconomical: UtxoStats,
+    /// The script pubkeys of the wallet which received more than one output
+    pub reused_scripts: Vec<ReusedScript>,
+    /// How far each keychain is from its gap limit
+    pub gaps: BTreeMap<KeychainKind, GapStatus>,
+    /// The unconfirmed transaction of the wallet which was last seen the longest ago
+    pub oldest_unconfirmed: Option<UnconfirmedTx>,
+    /// The birthday of the wallet, see [`Wallet::set_birthday`]
+    ///
+    /// The chain sources starting from the birthday leave the history before it unscanned on
+    /// purpose, so the report doesn't cover the outputs received before the birthday unless the
+    /// wallet was rescanned from an earlier height.
+    pub birthday: Option<BlockTimeOrHeight>,
+}
+
+/// The number and total value of a set of unspent outputs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoStats {
+    /// The number of outputs
+    pub count: usize,
+    /// The total value of the outputs
+    pub value: Amount,
+}
+
+impl UtxoStats {
+    fn add(&mut self, value: Amount) {
+        self.count += 1;
+        self.value += value;
+    }
+}
+
+/// The unspent outputs with a value in `[min_value, max_value)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeBucket {
+    /// The smallest value of the bucket
+    pub min_value: Amount,
+    /// The value above the bucket, `None` for the bucket of the largest values
+    pub max_value: Option<Amount>,
+    /// The outputs in the bucket
+    pub stats: UtxoStats,
+}
+
+/// A script pubkey of the wallet which received more than one output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReusedScript {
+    /// The keychain of the script pubkey
+    pub keychain: KeychainKind,
+    /// The derivation index of the script pubkey
+    pub index: u32,
+    /// The number of outputs it received, spent or not
+    pub outputs: usize,
+}
+
+/// How far a keychain is from its gap limit
+///
+/// A wallet restored by scanning the script pubkeys of the keychain until `gap_limit` consecutive
+/// ones are unused doesn't find the outputs received beyond that gap: when
+/// [`unused_revealed`](Self::unused_revealed) reaches `gap_limit`, the next revealed address is
+/// at risk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GapStatus {
+    /// The last revealed derivation index, `None` if none is revealed
+    pub last_revealed_index: Option<u32>,
+    /// The last derivation index which received an output, `None` if none did
+    pub last_used_index: Option<u32>,
+    /// The number of revealed script pubkeys after the last used one
+    pub unused_revealed: u32,
+    /// The gap limit, the lookahead of the wallet
+    pub gap_limit: u32,
+}
+
+/// An unconfirmed transaction of the wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnconfirmedTx {
+    /// The txid of the transaction
+    pub txid: Txid,
+    /// When the transaction was last seen in the mempool
+    pub last_seen: u64,
+}
+
+impl Wallet {
+    /// Summarize the state of the wallet, with the unspent outputs evaluated at `feerate`.
+    ///
+    /// An unspent output is uneconomical when its effective value, its value minus the fee for
+    /// spending it at `feerate`, isn't positive. The effective value is computed like in coin
+    /// selection.
+    pub fn health_report(&self, feerate: FeeRate) -> HealthReport {
+        let mut script_types = BTreeMap::<ScriptType, UtxoStats>::new();
+        let mut size_buckets = SIZE_BUCKET_BOUNDS
+            .iter()
+            .enumerate()
+            .map(|(i, &min_value)| SizeBucket {
+                min_value: Amount::from_sat(min_value),
+                max_value: SIZE_BUCKET_BOUNDS.get(i + 1).copied().map(Amount::from_sat),
+                stats: UtxoStats::default(),
+            })
+            .collect::<Vec<_>>();
+        let mut uneconomical = UtxoStats::default();
+        let mut outputs_by_script = BTreeMap::<(KeychainKind, u32), usize>::new();
+        let mut satisfaction_weights = BTreeMap::<KeychainKind, usize>::new();
+
+        for output in self.list_output() {
+            *outputs_by_script
+                .entry((output.keychain, output.derivation_index))
+                .or_default() += 1;
+            if output.is_spent {
+                continue;
+            }
+
+            let value = output.txout.value;
+            script_types
+                .entry(ScriptType::of(&output.txout.script_pubkey))
+                .or_default()
+                .add(value);
+            let bucket = size_buckets
+                .iter_mut()
+                .rev()
+                .find(|bucket| bucket.min_value <= value)
+                .expect("the first bucket starts at zero");
+            bucket.stats.add(value);
+
+            let satisfaction_weight =
+                *satisfaction_weights
+                    .entry(output.keychain)
+                    .or_insert_with(|| {
+                        self.get_descriptor_for_keychain(output.keychain)
+                            .max_weight_to_satisfy()
+                            .unwrap()
+                            .to_wu() as usize
+                    });
+            let candidate = OutputGroup::new(
+                WeightedUtxo {
+                    satisfaction_weight,
+                    utxo: Utxo::Local(output),
+                },
+                feerate,
+            );
+            if !candidate.effective_value.is_positive() {
+                uneconomical.add(value);
+            }
+        }
+
+        let reused_scripts = outputs_by_script
+            .into_iter()
+            .filter(|&(_, outputs)| outputs > 1)
+            .map(|((keychain, index), outputs)| ReusedScript {
+                keychain,
+                index,
+                outputs,
+            })
+            .collect();
+
+        let index = &self.indexed_graph.index;
+        let gaps = index
+            .keychains()
+            .map(|(keychain, _)| {
+                let last_revealed_index = index.last_revealed_index(keychain);
+                let last_used_index = index.last_used_index(keychain);
+                let unused_revealed = match (last_revealed_index, last_used_index) {
+                    (Some(revealed), Some(used)) => revealed.saturating_sub(used),
+                    (Some(revealed), None) => revealed + 1,
+                    (None, _) => 0,
+                };
+                let status = GapStatus {
+                    last_revealed_index,
+                    last_used_index,
+                    unused_revealed,
+                    gap_limit: index.lookahead(),
+                };
+                (*keychain, status)
+            })
+            .collect();
+
+        let oldest_unconfirmed = self
+            .transactions()
+            .filter_map(|tx| match tx.chain_position {
+                ChainPosition::Unconfirmed(last_seen) => Some(UnconfirmedTx {
+                    txid: tx.tx_node.txid,
+                    last_seen,
+                }),
+                ChainPosition::Confirmed(_) => None,
+            })
+            .min_by_key(|tx| (tx.last_seen, tx.txid));
+
+        HealthReport {
+            feerate,
+            script_types,
+            size_buckets,
+            uneconomical,
+            reused_scripts,
+            gaps,
+            oldest_unconfirmed,
+            birthday: self.birthday,
+        }
+    }
+}
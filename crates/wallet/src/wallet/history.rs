@@ -0,0 +1,378 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Transaction history export
+//!
+//! [`Wallet::export_history`] writes the wallet's view of its history, for audits or support
+//! tooling, in one of the [`HistoryFormat`]s. There is one record per canonical transaction,
+//! followed by a snapshot of the unspent outputs.
+//!
+//! Transactions are ordered by chain position: confirmed ones by height, then unconfirmed ones
+//! by last seen time, ties broken by txid. Unspent outputs are ordered by outpoint. Records are
+//! written one at a time, only references to the transactions are held to order them.
+//!
+//! ## JSON Lines
+//!
+//! Each line is a JSON object whose `record` field is `tx` or `utxo`:
+//!
+//! ```text
+//! {"record":"tx","txid":"…","status":"confirmed","height":2000,"time":200,"last_seen":null,
+//!  "fee":1000,"net_value":-26000,"inputs":[{"outpoint":"…:0","value":76000,"mine":true}],
+//!  "outputs":[{"vout":0,"value":50000,"address":"…","mine":true}],
+//!  "anchors":[{"block_height":2000,"block_hash":"…","confirmation_height":2000,
+//!  "confirmation_time":200}]}
+//! {"record":"utxo","outpoint":"…:0","value":50000,"keychain":"external","derivation_index":0,
+//!  "status":"confirmed","height":2000,"time":200,"last_seen":null}
+//! ```
+//!
+//! The `fee` and the `value` of an input are `null` when the previous outputs are unknown,
+//! `address` is `null` for scripts without an address form. `net_value` is the value received
+//! by the wallet minus the value it sent, in satoshis. The `keychain` of an output of an extra
+//! keychain is `extra:<label>`.
+//!
+//! ## CSV
+//!
+//! Two sections, each starting with its header line: the transactions, then the unspent
+//! outputs. Unknown values are empty, the lists are separated by `;`:
+//!
+//! ```text
+//! record,txid,status,height,time,last_seen,fee,net_value,inputs,outputs,anchors
+//! tx,…,confirmed,2000,200,,1000,-26000,…:0=76000=mine,0=50000=mine;1=25000=other,2000=…
+//! record,outpoint,value,keychain,derivation_index,status,height,time,last_seen
+//! utxo,…:0,50000,external,0,confirmed,2000,200,
+//! ```
+//!
+//! An input is `<outpoint>=<value>=<mine|other>`, an output `<vout>=<value>=<mine|other>` and an
+//! anchor `<block height>=<block hash>`.
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::io::{self, Write};
+
+use bdk_chain::tx_graph::CanonicalTx;
+use bdk_chain::{Anchor, ChainPosition, ConfirmationTimeHeightAnchor};
+use bitcoin::{Address, SignedAmount, Transaction};
+use serde::Serialize;
+
+use super::Wallet;
+use crate::{KeychainKind, LocalOutput};
+
+/// The format of [`Wallet::export_history`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// One JSON object per line
+    JsonLines,
+    /// Comma separated values, one section for the transactions and one for the unspent outputs
+    Csv,
+}
+
+const CSV_TX_HEADER: &str =
+    "record,txid,status,height,time,last_seen,fee,net_value,inputs,outputs,anchors";
+const CSV_UTXO_HEADER: &str =
+    "record,outpoint,value,keychain,derivation_index,status,height,time,last_seen";
+
+#[derive(Debug, Serialize)]
+struct TxRecord {
+    record: &'static str,
+    txid: String,
+    #[serde(flatten)]
+    position: Position,
+    fee: Option<u64>,
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    net_value: SignedAmount,
+    inputs: Vec<InputRecord>,
+    outputs: Vec<OutputRecord>,
+    anchors: Vec<AnchorRecord>,
+}
+
+#[derive(Debug, Serialize)]
+struct InputRecord {
+    outpoint: String,
+    value: Option<u64>,
+    mine: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct OutputRecord {
+    vout: u32,
+    value: u64,
+    address: Option<String>,
+    mine: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct AnchorRecord {
+    block_height: u32,
+    block_hash: String,
+    confirmation_height: u32,
+    confirmation_time: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct UtxoRecord {
+    record: &'static str,
+    outpoint: String,
+    value: u64,
+    keychain: String,
+    derivation_index: u32,
+    #[serde(flatten)]
+    position: Position,
+}
+
+#[derive(Debug, Serialize)]
+struct Position {
+    status: &'static str,
+    height: Option<u32>,
+    time: Option<u64>,
+    last_seen: Option<u64>,
+}
+
+impl From<ChainPosition<&ConfirmationTimeHeightAnchor>> for Position {
+    fn from(position: ChainPosition<&ConfirmationTimeHeightAnchor>) -> Self {
+        match position {
+            ChainPosition::Confirmed(anchor) => Position {
+                status: "confirmed",
+                height: Some(anchor.confirmation_height),
+                time: Some(anchor.confirmation_time),
+                last_seen: None,
+            },
+            ChainPosition::Unconfirmed(last_seen) => Position {
+                status: "unconfirmed",
+                height: None,
+                time: None,
+                last_seen: Some(last_seen),
+            },
+        }
+    }
+}
+
+impl From<crate::chain::ConfirmationTime> for Position {
+    fn from(confirmation_time: crate::chain::ConfirmationTime) -> Self {
+        match confirmation_time {
+            crate::chain::ConfirmationTime::Confirmed { height, time } => Position {
+                status: "confirmed",
+                height: Some(height),
+                time: Some(time),
+                last_seen: None,
+            },
+            crate::chain::ConfirmationTime::Unconfirmed { last_seen } => Position {
+                status: "unconfirmed",
+                height: None,
+                time: None,
+                last_seen: Some(last_seen),
+            },
+        }
+    }
+}
+
+impl Position {
+    fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(
+            writer,
+            "{},{},{},{}",
+            self.status,
+            csv_opt(self.height),
+            csv_opt(self.time),
+            csv_opt(self.last_seen)
+        )
+    }
+}
+
+fn csv_opt<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn mine_str(mine: bool) -> &'static str {
+    if mine {
+        "mine"
+    } else {
+        "other"
+    }
+}
+
+fn keychain_str(keychain: KeychainKind) -> String {
+    match keychain {
+        KeychainKind::External => "external".to_string(),
+        KeychainKind::Internal => "internal".to_string(),
+        KeychainKind::Extra(label) => format!("extra:{}", label),
+    }
+}
+
+impl Wallet {
+    /// Write the history of the wallet to `writer` in `format`
+    ///
+    /// See the [module documentation](crate::wallet::history) for the records and their order.
+    pub fn export_history<W: Write>(&self, mut writer: W, format: HistoryFormat) -> io::Result<()> {
+        let mut txs = self.transactions().collect::<Vec<_>>();
+        txs.sort_by(|a, b| {
+            position_key(&a.chain_position)
+                .cmp(&position_key(&b.chain_position))
+                .then_with(|| a.tx_node.txid.cmp(&b.tx_node.txid))
+        });
+
+        if format == HistoryFormat::Csv {
+            writeln!(writer, "{}", CSV_TX_HEADER)?;
+        }
+        for canonical_tx in txs {
+            let record = self.tx_record(&canonical_tx);
+            match format {
+                HistoryFormat::JsonLines => write_json_line(&mut writer, &record)?,
+                HistoryFormat::Csv => write_csv_tx(&mut writer, &record)?,
+            }
+        }
+
+        let mut utxos = self.list_unspent().collect::<Vec<_>>();
+        utxos.sort_by_key(|utxo| utxo.outpoint);
+        if format == HistoryFormat::Csv {
+            writeln!(writer, "{}", CSV_UTXO_HEADER)?;
+        }
+        for utxo in utxos {
+            let record = utxo_record(utxo);
+            match format {
+                HistoryFormat::JsonLines => write_json_line(&mut writer, &record)?,
+                HistoryFormat::Csv => {
+                    write!(
+                        writer,
+                        "{},{},{},{},{},",
+                        record.record,
+                        record.outpoint,
+                        record.value,
+                        record.keychain,
+                        record.derivation_index
+                    )?;
+                    record.position.write_csv(&mut writer)?;
+                    writeln!(writer)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn tx_record(
+        &self,
+        canonical_tx: &CanonicalTx<'_, Arc<Transaction>, ConfirmationTimeHeightAnchor>,
+    ) -> TxRecord {
+        let tx = &canonical_tx.tx_node.tx;
+        let graph = self.indexed_graph.graph();
+        let (sent, received) = self.sent_and_received(tx);
+        let inputs = if tx.is_coinbase() {
+            Vec::new()
+        } else {
+            tx.input
+                .iter()
+                .map(|txin| {
+                    let prevout = graph.get_txout(txin.previous_output);
+                    InputRecord {
+                        outpoint: txin.previous_output.to_string(),
+                        value: prevout.map(|txout| txout.value.to_sat()),
+                        mine: prevout.map_or(false, |txout| self.is_mine(&txout.script_pubkey)),
+                    }
+                })
+                .collect()
+        };
+        let outputs = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(vout, txout)| OutputRecord {
+                vout: vout as u32,
+                value: txout.value.to_sat(),
+                address: Address::from_script(&txout.script_pubkey, self.network)
+                    .ok()
+                    .map(|address| address.to_string()),
+                mine: self.is_mine(&txout.script_pubkey),
+            })
+            .collect();
+        let anchors = canonical_tx
+            .tx_node
+            .anchors
+            .iter()
+            .map(|anchor| AnchorRecord {
+                block_height: anchor.anchor_block().height,
+                block_hash: anchor.anchor_block().hash.to_string(),
+                confirmation_height: anchor.confirmation_height,
+                confirmation_time: anchor.confirmation_time,
+            })
+            .collect();
+
+        TxRecord {
+            record: "tx",
+            txid: canonical_tx.tx_node.txid.to_string(),
+            position: canonical_tx.chain_position.into(),
+            fee: graph.calculate_fee(tx).ok().map(|fee| fee.to_sat()),
+            net_value: received.to_signed().expect("valid amount")
+                - sent.to_signed().expect("valid amount"),
+            inputs,
+            outputs,
+            anchors,
+        }
+    }
+}
+
+/// Confirmed transactions first by height, then unconfirmed ones by last seen time
+fn position_key(position: &ChainPosition<&ConfirmationTimeHeightAnchor>) -> (u8, u64) {
+    match position {
+        ChainPosition::Confirmed(anchor) => (0, anchor.confirmation_height as u64),
+        ChainPosition::Unconfirmed(last_seen) => (1, *last_seen),
+    }
+}
+
+fn utxo_record(utxo: LocalOutput) -> UtxoRecord {
+    UtxoRecord {
+        record: "utxo",
+        outpoint: utxo.outpoint.to_string(),
+        value: utxo.txout.value.to_sat(),
+        keychain: keychain_str(utxo.keychain),
+        derivation_index: utxo.derivation_index,
+        position: utxo.confirmation_time.into(),
+    }
+}
+
+fn write_json_line<W: Write, T: Serialize>(writer: &mut W, record: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record).map_err(io::Error::from)?;
+    writer.write_all(b"\n")
+}
+
+fn write_csv_tx<W: Write>(writer: &mut W, record: &TxRecord) -> io::Result<()> {
+    write!(writer, "{},{},", record.record, record.txid)?;
+    record.position.write_csv(writer)?;
+    let inputs = record
+        .inputs
+        .iter()
+        .map(|input| {
+            format!(
+                "{}={}={}",
+                input.outpoint,
+                csv_opt(input.value),
+                mine_str(input.mine)
+            )
+        })
+        .collect::<Vec<_>>();
+    let outputs = record
+        .outputs
+        .iter()
+        .map(|output| format!("{}={}={}", output.vout, output.value, mine_str(output.mine)))
+        .collect::<Vec<_>>();
+    let anchors = record
+        .anchors
+        .iter()
+        .map(|anchor| format!("{}={}", anchor.block_height, anchor.block_hash))
+        .collect::<Vec<_>>();
+    writeln!(
+        writer,
+        ",{},{},{},{},{}",
+        csv_opt(record.fee),
+        record.net_value.to_sat(),
+        inputs.join(";"),
+        outputs.join(";"),
+        anchors.join(";")
+    )
+}
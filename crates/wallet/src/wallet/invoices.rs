@@ -0,0 +1,270 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Invoices, an address of the wallet expecting an amount before an expiry, see
+//! [`Wallet::create_invoice`]
+
+use alloc::vec::Vec;
+
+use bdk_chain::{Append, BlockTimeOrHeight, ChainPosition, InvoiceRecord};
+use bitcoin::{Address, Amount, Txid};
+
+use super::{ChangeSet, Wallet};
+use crate::KeychainKind;
+
+/// An invoice created with [`Wallet::create_invoice`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invoice {
+    /// The id of the invoice
+    pub id: u32,
+    /// The address the invoice must be paid to
+    pub address: Address,
+    /// The amount expected
+    pub amount: Amount,
+    /// The block height or the UNIX timestamp at which the invoice expires
+    pub expiry: BlockTimeOrHeight,
+    /// How many confirmations the payments need for the invoice to be settled
+    pub min_confirmations: u32,
+}
+
+/// The state of an invoice, see [`Wallet::invoice_status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InvoiceState {
+    /// Nothing was received before the expiry, which isn't reached yet
+    Unpaid,
+    /// Less than the amount was received, and the expiry isn't reached yet
+    PartiallyPaid,
+    /// The amount was received before the expiry, but some of the payments don't have enough
+    /// confirmations yet
+    PaidUnconfirmed,
+    /// The amount was received before the expiry, with enough confirmations
+    Settled,
+    /// The expiry was reached before the amount was received
+    Expired,
+}
+
+/// The payments of an invoice, see [`Wallet::invoice_status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvoiceStatus {
+    /// The state of the invoice
+    pub state: InvoiceState,
+    /// The amount received before the expiry
+    pub received: Amount,
+    /// The transactions paying the invoice before the expiry, sorted
+    pub txids: Vec<Txid>,
+    /// How much more than the amount was received before the expiry
+    pub overpayment: Amount,
+    /// The amount received after the expiry, not counted in `received`
+    pub late_received: Amount,
+    /// The transactions paying the invoice after the expiry, sorted
+    pub late_txids: Vec<Txid>,
+}
+
+/// Which invoices [`Wallet::list_invoices`] returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvoiceFilter {
+    /// All the invoices
+    All,
+    /// The invoices which may still change state by being paid: neither settled nor expired
+    Open,
+    /// The invoices in this state
+    State(InvoiceState),
+}
+
+impl InvoiceFilter {
+    fn matches(&self, state: InvoiceState) -> bool {
+        match self {
+            Self::All => true,
+            Self::Open => !matches!(state, InvoiceState::Settled | InvoiceState::Expired),
+            Self::State(filter) => *filter == state,
+        }
+    }
+}
+
+impl Wallet {
+    /// Create an invoice of `amount`, paid to the next external address of the wallet, which
+    /// expires at block `expiry` or at the UNIX timestamp `expiry`, and is settled once payments
+    /// with at least `min_confirmations` confirmations reach `amount`
+    ///
+    /// The address is revealed and marked used, so that [`Wallet::next_unused_address`] doesn't
+    /// return it for something else. The invoices are numbered from 0 in the order they're
+    /// created, and persisted with the staged changes: like with
+    /// [`Wallet::reveal_next_address`], they must be persisted before the address is given out.
+    pub fn create_invoice(
+        &mut self,
+        amount: Amount,
+        expiry: BlockTimeOrHeight,
+        min_confirmations: u32,
+    ) -> Invoice {
+        let address = self.reveal_next_address(KeychainKind::External);
+        let _ = self.mark_used(KeychainKind::External, address.index);
+        let id = self
+            .invoices
+            .keys()
+            .next_back()
+            .map_or(0, |last_id| last_id + 1);
+        let record = InvoiceRecord {
+            script_pubkey: address.script_pubkey(),
+            amount,
+            expiry,
+            min_confirmations,
+        };
+        self.invoices.insert(id, record.clone());
+        self.stage.append(ChangeSet {
+            invoices: [(id, record)].into(),
+            ..Default::default()
+        });
+        Invoice {
+            id,
+            address: address.address,
+            amount,
+            expiry,
+            min_confirmations,
+        }
+    }
+
+    /// The invoice `id` created with [`Wallet::create_invoice`]
+    pub fn invoice(&self, id: u32) -> Option<Invoice> {
+        self.invoices
+            .get(&id)
+            .map(|record| self.to_invoice(id, record))
+    }
+
+    /// The state of the invoice `id` and its payments at the UNIX timestamp `now`, from the
+    /// canonical transactions of the wallet
+    ///
+    /// The payments are the canonical transactions with outputs to the address of the invoice,
+    /// all of them are summed: several partial payments can pay an invoice, and what's received
+    /// beyond the amount is reported as [`InvoiceStatus::overpayment`].
+    ///
+    /// A payment counts if it's confirmed in a block before the expiry (a block below the expiry
+    /// height, or with a timestamp before the expiry time), or if it's unconfirmed while the
+    /// invoice isn't expired yet, the tip of the chain being below the expiry height or `now`
+    /// before the expiry time. The other payments are late, they're reported in
+    /// [`InvoiceStatus::late_received`] but don't pay the invoice: a payment still unconfirmed
+    /// at the expiry becomes late, even if it then confirms. An invoice paid before the expiry
+    /// stays settled once it's expired.
+    ///
+    /// The state is, in this order of precedence:
+    ///
+    /// * [`InvoiceState::Settled`] if the counted payments with at least `min_confirmations`
+    ///   confirmations reach the amount.
+    /// * [`InvoiceState::PaidUnconfirmed`] if the counted payments reach the amount.
+    /// * [`InvoiceState::Expired`] if the invoice is expired.
+    /// * [`InvoiceState::PartiallyPaid`] if something was received.
+    /// * [`InvoiceState::Unpaid`] otherwise.
+    ///
+    /// Returns `None` if there is no invoice `id`.
+    pub fn invoice_status(&self, id: u32, now: u64) -> Option<InvoiceStatus> {
+        self.invoices
+            .get(&id)
+            .map(|record| self.record_status(record, now))
+    }
+
+    /// The invoices matching `filter` at the UNIX timestamp `now`, with their status, sorted by
+    /// id, see [`Wallet::invoice_status`]
+    pub fn list_invoices(&self, filter: InvoiceFilter, now: u64) -> Vec<(Invoice, InvoiceStatus)> {
+        self.invoices
+            .iter()
+            .map(|(id, record)| {
+                (
+                    self.to_invoice(*id, record),
+                    self.record_status(record, now),
+                )
+            })
+            .filter(|(_, status)| filter.matches(status.state))
+            .collect()
+    }
+
+    fn to_invoice(&self, id: u32, record: &InvoiceRecord) -> Invoice {
+        Invoice {
+            id,
+            address: Address::from_script(&record.script_pubkey, self.network)
+                .expect("must have address form"),
+            amount: record.amount,
+            expiry: record.expiry,
+            min_confirmations: record.min_confirmations,
+        }
+    }
+
+    fn record_status(&self, record: &InvoiceRecord, now: u64) -> InvoiceStatus {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+        let expired = match record.expiry {
+            BlockTimeOrHeight::Height(height) => chain_tip.height >= height,
+            BlockTimeOrHeight::Time(time) => now >= time,
+        };
+
+        let mut status = InvoiceStatus {
+            state: InvoiceState::Unpaid,
+            received: Amount::ZERO,
+            txids: Vec::new(),
+            overpayment: Amount::ZERO,
+            late_received: Amount::ZERO,
+            late_txids: Vec::new(),
+        };
+        let mut settled = Amount::ZERO;
+        // the txids paying to a script pubkey are sorted
+        for &txid in graph.txids_paying_to(&record.script_pubkey) {
+            let (tx, position) = match (
+                graph.get_tx(txid),
+                graph.get_chain_position(&self.chain, chain_tip, txid),
+            ) {
+                (Some(tx), Some(position)) => (tx, position),
+                _ => continue,
+            };
+            let value = tx
+                .output
+                .iter()
+                .filter(|txout| txout.script_pubkey == record.script_pubkey)
+                .map(|txout| txout.value)
+                .sum::<Amount>();
+            let (on_time, confirmations) = match position {
+                ChainPosition::Confirmed(anchor) => {
+                    let on_time = match record.expiry {
+                        BlockTimeOrHeight::Height(height) => anchor.confirmation_height < height,
+                        BlockTimeOrHeight::Time(time) => anchor.confirmation_time < time,
+                    };
+                    let confirmations =
+                        chain_tip.height.saturating_sub(anchor.confirmation_height) + 1;
+                    (on_time, confirmations)
+                }
+                ChainPosition::Unconfirmed(_) => (!expired, 0),
+            };
+            if on_time {
+                status.received += value;
+                status.txids.push(txid);
+                if confirmations >= record.min_confirmations {
+                    settled += value;
+                }
+            } else {
+                status.late_received += value;
+                status.late_txids.push(txid);
+            }
+        }
+
+        status.overpayment = status
+            .received
+            .checked_sub(record.amount)
+            .unwrap_or(Amount::ZERO);
+        status.state = if settled >= record.amount {
+            InvoiceState::Settled
+        } else if status.received >= record.amount {
+            InvoiceState::PaidUnconfirmed
+        } else if expired {
+            InvoiceState::Expired
+        } else if status.received > Amount::ZERO {
+            InvoiceState::PartiallyPaid
+        } else {
+            InvoiceState::Unpaid
+        };
+        status
+    }
+}
@@ -0,0 +1,299 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Wallet labels
+//!
+//! The [`Wallet`] can attach a label to its transactions, addresses, outputs and inputs. Labels
+//! are staged and persisted like every other change of the wallet.
+//!
+//! Labels are exchanged with other wallets in the
+//! [BIP-329](https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki) JSON Lines format
+//! with [`Wallet::export_labels`] and [`Wallet::import_labels`].
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk_wallet::wallet::labels::LabelRef;
+//! # use bdk_wallet::{KeychainKind, Wallet};
+//! # use bitcoin::Network;
+//! # let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/0/*)";
+//! # let change_descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1/*)";
+//! let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Testnet)?;
+//! let address = wallet.reveal_next_address(KeychainKind::External);
+//! wallet.set_label(LabelRef::Addr(address.script_pubkey()), "donations".to_string());
+//!
+//! let mut exported = Vec::new();
+//! wallet.export_labels(&mut exported)?;
+//! assert_eq!(
+//!     String::from_utf8(exported)?,
+//!     format!("{{\"type\":\"addr\",\"ref\":\"{}\",\"label\":\"donations\"}}\n", address.address)
+//! );
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use alloc::string::String;
+
+use bdk_chain::Append;
+pub use bdk_chain::LabelRef;
+
+use super::{ChangeSet, Wallet};
+
+#[cfg(feature = "std")]
+pub use self::bip329::{ImportStats, LabelError, SkipReason, SkippedLabel};
+
+impl Wallet {
+    /// Set the `label` of `label_ref`, replacing its previous label if any.
+    pub fn set_label(&mut self, label_ref: LabelRef, label: String) {
+        if self.labels.get(&label_ref) == Some(&label) {
+            return;
+        }
+        self.labels.insert(label_ref.clone(), label.clone());
+        self.stage.append(ChangeSet {
+            labels: [(label_ref, Some(label))].into(),
+            ..Default::default()
+        });
+    }
+
+    /// Remove the label of `label_ref`, returning it if there was one.
+    pub fn remove_label(&mut self, label_ref: &LabelRef) -> Option<String> {
+        let label = self.labels.remove(label_ref)?;
+        self.stage.append(ChangeSet {
+            labels: [(label_ref.clone(), None)].into(),
+            ..Default::default()
+        });
+        Some(label)
+    }
+
+    /// Get the label of `label_ref`, if any.
+    pub fn label(&self, label_ref: &LabelRef) -> Option<&str> {
+        self.labels.get(label_ref).map(String::as_str)
+    }
+
+    /// Iterate over all the labels of the wallet, ordered by what they are attached to.
+    pub fn labels(&self) -> impl Iterator<Item = (&LabelRef, &str)> + '_ {
+        self.labels
+            .iter()
+            .map(|(label_ref, label)| (label_ref, label.as_str()))
+    }
+}
+
+#[cfg(feature = "std")]
+mod bip329 {
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::fmt;
+    use core::str::FromStr;
+    use std::io::{self, BufRead, Write};
+
+    use bitcoin::{Address, OutPoint, Txid};
+    use serde::{Deserialize, Serialize};
+
+    use super::{LabelRef, Wallet};
+
+    /// A BIP-329 record. Optional fields other than the label (`origin`, `spendable`, ...) are
+    /// ignored when importing.
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Record {
+        #[serde(rename = "type")]
+        label_type: String,
+        #[serde(rename = "ref")]
+        label_ref: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    }
+
+    /// Error while importing or exporting labels.
+    #[derive(Debug)]
+    pub enum LabelError {
+        /// Reading or writing failed.
+        Io(io::Error),
+        /// A line is not a valid BIP-329 record.
+        Json {
+            /// The line number, starting at 1.
+            line: usize,
+            /// The parsing error.
+            error: serde_json::Error,
+        },
+    }
+
+    impl fmt::Display for LabelError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                LabelError::Io(e) => e.fmt(f),
+                LabelError::Json { line, error } => {
+                    write!(f, "invalid label record at line {}: {}", line, error)
+                }
+            }
+        }
+    }
+
+    impl std::error::Error for LabelError {}
+
+    impl From<io::Error> for LabelError {
+        fn from(e: io::Error) -> Self {
+            LabelError::Io(e)
+        }
+    }
+
+    /// Why a label record was not imported.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SkipReason {
+        /// The record type is not one of `tx`, `addr`, `output` or `input`.
+        UnsupportedType,
+        /// The reference can't be parsed, or is an address of another network.
+        InvalidRef,
+        /// The wallet doesn't know the referenced transaction, address, output or input.
+        UnknownRef,
+        /// The record has no label.
+        MissingLabel,
+    }
+
+    /// A label record that was not imported.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SkippedLabel {
+        /// The line of the record, starting at 1.
+        pub line: usize,
+        /// The record type.
+        pub label_type: String,
+        /// The record reference.
+        pub label_ref: String,
+        /// Why the record was skipped.
+        pub reason: SkipReason,
+    }
+
+    /// Summary of [`Wallet::import_labels`].
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct ImportStats {
+        /// Number of labels set.
+        pub imported: usize,
+        /// The records that were not imported.
+        pub skipped: Vec<SkippedLabel>,
+    }
+
+    impl Wallet {
+        /// Write all the labels of the wallet to `writer` in the BIP-329 format, one JSON record
+        /// per line.
+        ///
+        /// Labels of script pubkeys that have no address form are not exported.
+        pub fn export_labels<W: Write>(&self, mut writer: W) -> Result<(), LabelError> {
+            for (label_ref, label) in &self.labels {
+                let (label_type, label_ref) = match label_ref {
+                    LabelRef::Tx(txid) => ("tx", txid.to_string()),
+                    LabelRef::Addr(script_pubkey) => {
+                        match Address::from_script(script_pubkey, self.network) {
+                            Ok(address) => ("addr", address.to_string()),
+                            Err(_) => continue,
+                        }
+                    }
+                    LabelRef::Output(outpoint) => ("output", outpoint.to_string()),
+                    LabelRef::Input(input) => ("input", input.to_string()),
+                };
+                let record = Record {
+                    label_type: label_type.to_string(),
+                    label_ref,
+                    label: Some(label.clone()),
+                };
+                serde_json::to_writer(&mut writer, &record).map_err(io::Error::from)?;
+                writer.write_all(b"\n")?;
+            }
+            Ok(())
+        }
+
+        /// Read BIP-329 records from `reader` and set the labels of the transactions, addresses,
+        /// outputs and inputs known to the wallet.
+        ///
+        /// Records which don't apply to the wallet are skipped and reported in the returned
+        /// [`ImportStats`]: other record types (such as `xpub`), records without a label and
+        /// references the wallet doesn't know. Addresses are known if they belong to one of the
+        /// wallet's keychains. Empty lines are ignored.
+        pub fn import_labels<R: BufRead>(&mut self, reader: R) -> Result<ImportStats, LabelError> {
+            let mut stats = ImportStats::default();
+            for (index, line) in reader.lines().enumerate() {
+                let line_number = index + 1;
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record =
+                    serde_json::from_str::<Record>(&line).map_err(|error| LabelError::Json {
+                        line: line_number,
+                        error,
+                    })?;
+
+                match self.known_label_ref(&record) {
+                    Ok(label_ref) => match record.label {
+                        Some(label) => {
+                            self.set_label(label_ref, label);
+                            stats.imported += 1;
+                        }
+                        None => stats.skipped.push(SkippedLabel {
+                            line: line_number,
+                            label_type: record.label_type,
+                            label_ref: record.label_ref,
+                            reason: SkipReason::MissingLabel,
+                        }),
+                    },
+                    Err(reason) => stats.skipped.push(SkippedLabel {
+                        line: line_number,
+                        label_type: record.label_type,
+                        label_ref: record.label_ref,
+                        reason,
+                    }),
+                }
+            }
+            Ok(stats)
+        }
+
+        /// Parse the reference of `record`, checking that the wallet knows about it.
+        fn known_label_ref(&self, record: &Record) -> Result<LabelRef, SkipReason> {
+            let graph = self.indexed_graph.graph();
+            let label_ref = record.label_ref.as_str();
+            match record.label_type.as_str() {
+                "tx" => {
+                    let txid = Txid::from_str(label_ref).map_err(|_| SkipReason::InvalidRef)?;
+                    graph
+                        .get_tx(txid)
+                        .map(|_| LabelRef::Tx(txid))
+                        .ok_or(SkipReason::UnknownRef)
+                }
+                "addr" => {
+                    let address = Address::from_str(label_ref)
+                        .ok()
+                        .and_then(|address| address.require_network(self.network).ok())
+                        .ok_or(SkipReason::InvalidRef)?;
+                    let script_pubkey = address.script_pubkey();
+                    self.indexed_graph
+                        .index
+                        .index_of_spk(&script_pubkey)
+                        .map(|_| LabelRef::Addr(script_pubkey))
+                        .ok_or(SkipReason::UnknownRef)
+                }
+                "output" => {
+                    let outpoint =
+                        OutPoint::from_str(label_ref).map_err(|_| SkipReason::InvalidRef)?;
+                    graph
+                        .get_txout(outpoint)
+                        .map(|_| LabelRef::Output(outpoint))
+                        .ok_or(SkipReason::UnknownRef)
+                }
+                "input" => {
+                    let input =
+                        OutPoint::from_str(label_ref).map_err(|_| SkipReason::InvalidRef)?;
+                    graph
+                        .get_tx(input.txid)
+                        .filter(|tx| (input.vout as usize) < tx.input.len())
+                        .map(|_| LabelRef::Input(input))
+                        .ok_or(SkipReason::UnknownRef)
+                }
+                _ => Err(SkipReason::UnsupportedType),
+            }
+        }
+    }
+}
@@ -12,7 +12,7 @@
 //! Wallet
 //!
 //! This module defines the [`Wallet`].
-use crate::collections::{BTreeMap, HashMap};
+use crate::collections::{BTreeMap, BTreeSet, HashMap};
 use alloc::{
     boxed::Box,
     string::{String, ToString},
@@ -21,48 +21,134 @@ use alloc::{
 };
 pub use bdk_chain::keychain::Balance;
 use bdk_chain::{
-    indexed_tx_graph,
-    keychain::KeychainTxOutIndex,
+    indexed_tx_graph::{self, Indexer},
+    keychain::{self, KeychainTxOutIndex},
     local_chain::{
         self, ApplyHeaderError, CannotConnectError, CheckPoint, CheckPointIter, LocalChain,
     },
+    prevout::{PrevoutCache, PrevoutResolver},
     spk_client::{FullScanRequest, FullScanResult, SyncRequest, SyncResult},
     tx_graph::{CanonicalTx, TxGraph},
-    Append, BlockId, ChainPosition, ConfirmationTime, ConfirmationTimeHeightAnchor, FullTxOut,
-    Indexed, IndexedTxGraph,
+    Append, BlockId, ChainPosition, ConfirmationTime, ConfirmationTimeHeightAnchor, DescriptorExt,
+    DescriptorId, FullTxOut, Indexed, IndexedTxGraph, InvoiceRecord, SpkIterator,
 };
-use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::key::XOnlyPublicKey;
+use bitcoin::secp256k1::{self, All, Secp256k1};
+use bitcoin::sighash::SighashCache;
 use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
+use bitcoin::taproot::TapLeafHash;
 use bitcoin::{
-    absolute, psbt, Address, Block, FeeRate, Network, OutPoint, Script, ScriptBuf, Sequence,
-    Transaction, TxOut, Txid, Witness,
+    absolute, psbt, relative, Address, Block, FeeRate, Network, OutPoint, Script, ScriptBuf,
+    Sequence, Transaction, TxOut, Txid, Weight, Witness,
 };
-use bitcoin::{consensus::encode::serialize, transaction, BlockHash, Psbt};
-use bitcoin::{constants::genesis_block, Amount};
+use bitcoin::{
+    address::NetworkUnchecked, consensus::encode::serialize, transaction, BlockHash, Psbt,
+};
+use bitcoin::{Amount, SignedAmount};
 use core::fmt;
 use core::mem;
-use core::ops::Deref;
+use core::ops::{Deref, RangeBounds};
 use descriptor::error::Error as DescriptorError;
+use miniscript::descriptor::KeyMap;
 use miniscript::psbt::{PsbtExt, PsbtInputExt, PsbtInputSatisfier};
 
 use bdk_chain::tx_graph::CalculateFeeError;
-
+use rand::RngCore;
+
+pub mod analyze;
+#[cfg(feature = "bip21")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bip21")))]
+pub mod bip21;
+#[cfg(feature = "bip322")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bip322")))]
+pub mod bip322;
+mod birthday;
+mod broadcasts;
+mod change_policy;
+mod coin_control;
 pub mod coin_selection;
+pub mod events;
 pub mod export;
+mod fee_limits;
+pub mod fee_strategy;
+mod feerate_check;
+mod fingerprint;
+mod health;
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub mod history;
+mod invoices;
+pub mod labels;
+mod package;
+mod params;
+#[cfg(feature = "payjoin")]
+#[cfg_attr(docsrs, doc(cfg(feature = "payjoin")))]
+pub mod payjoin;
+mod payments;
+pub mod persist;
+#[cfg(feature = "recovery")]
+#[cfg_attr(docsrs, doc(cfg(feature = "recovery")))]
+pub mod recovery;
+mod replacement;
+mod reservations;
+mod reveal_guard;
+mod revisions;
 pub mod signer;
+#[cfg(feature = "silent-payments")]
+#[cfg_attr(docsrs, doc(cfg(feature = "silent-payments")))]
+pub mod silent_payments;
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub mod subscriptions;
+mod sync_bundle;
+mod sync_scheduler;
+pub mod tx_analysis;
 pub mod tx_builder;
 pub(crate) mod utils;
+mod verify;
+pub mod wallet_policy;
+mod witness_provider;
 
 pub mod error;
 
-pub use utils::IsDust;
+pub use birthday::BlockTimeOrHeight;
+#[cfg(feature = "std")]
+pub use broadcasts::PackageBroadcastError;
+pub use broadcasts::{BroadcastOutcome, BroadcastRecord};
+pub use change_policy::{ChangeAddressPolicy, ChangeAddressPolicyError};
+pub use coin_control::{UtxoDetails, UtxoList};
+pub use fee_limits::FeeLimits;
+pub use feerate_check::{FeeRateCheckError, FeeRateDrift, TxOrPsbt};
+pub use fingerprint::{
+    FingerprintComponent, FingerprintMismatch, StateFingerprint, STATE_FINGERPRINT_VERSION,
+};
+pub use health::{GapStatus, HealthReport, ReusedScript, SizeBucket, UnconfirmedTx, UtxoStats};
+pub use invoices::{Invoice, InvoiceFilter, InvoiceState, InvoiceStatus};
+pub use package::TxBuilderSpec;
+pub use params::{LoadParams, NetworkParams};
+pub use payments::TimeOrHeightWindow;
+pub use replacement::{BumpCandidate, ReplacementInfo};
+pub use reservations::DEFAULT_RESERVATION_TTL;
+pub use reveal_guard::RevealGuardError;
+pub use revisions::DescriptorRevision;
+#[cfg(feature = "std")]
+pub use subscriptions::{WalletSubscription, DEFAULT_SUBSCRIPTION_CAPACITY};
+pub use sync_bundle::{ApplyBundleError, ApplyReport, SyncMarker, WalletUpdateBundle};
+pub use sync_scheduler::{KeychainRequest, RequestBudget, Scheduled, SyncScheduler, SyncTicket};
+pub use utils::{dust_value, IsDust, ScriptType, DEFAULT_DUST_RELAY_FEERATE};
+pub use verify::{TxReport, VerifyError, VerifyOptions};
+pub use witness_provider::{WitnessContext, WitnessProvider};
 
 use coin_selection::DefaultCoinSelectionAlgorithm;
-use signer::{SignOptions, SignerOrdering, SignersContainer, TransactionSigner};
+use export::{ExportError, FullyNodedExport};
+use signer::{
+    taproot_annex_key, SignDetails, SignOptions, SignerOrdering, SignersContainer,
+    TransactionSigner,
+};
 use tx_builder::{FeePolicy, TxBuilder, TxParams};
 use utils::{check_nsequence_rbf, After, Older, SecpCtx};
 
-use crate::descriptor::policy::BuildSatisfaction;
+use crate::descriptor::policy::{BuildSatisfaction, Satisfaction};
 use crate::descriptor::{
     self, calc_checksum, into_wallet_descriptor_checked, DerivedDescriptor, DescriptorMeta,
     ExtendedDescriptor, ExtractPolicy, IntoWalletDescriptor, Policy, XKeyUtils,
@@ -71,12 +157,13 @@ use crate::psbt::PsbtUtils;
 use crate::signer::SignerError;
 use crate::types::*;
 use crate::wallet::coin_selection::Excess::{Change, NoChange};
-use crate::wallet::error::{BuildFeeBumpError, CreateTxError, MiniscriptPsbtError};
+use crate::wallet::error::{
+    AddKeychainError, BuildCpfpError, BuildFeeBumpError, BuildSweepError, CombineError,
+    CreateTxError, MiniscriptPsbtError,
+};
 
 use self::coin_selection::Error;
 
-const COINBASE_MATURITY: u32 = 100;
-
 /// A Bitcoin wallet
 ///
 /// The `Wallet` acts as a way of coherently interfacing with output descriptors and related transactions.
@@ -95,9 +182,47 @@ const COINBASE_MATURITY: u32 = 100;
 pub struct Wallet {
     signers: Arc<SignersContainer>,
     change_signers: Arc<SignersContainer>,
+    /// The signers of the keychains added with [`Wallet::add_keychain`].
+    extra_signers: BTreeMap<KeychainKind, Arc<SignersContainer>>,
     chain: LocalChain,
     indexed_graph: IndexedTxGraph<ConfirmationTimeHeightAnchor, KeychainTxOutIndex<KeychainKind>>,
     stage: ChangeSet,
+    /// The chain as it was when the staged changes were last taken, see
+    /// [`Wallet::discard_staged`].
+    committed_chain: LocalChain,
+    /// The last revealed indices as they were when the staged changes were last taken.
+    committed_last_revealed: BTreeMap<KeychainKind, u32>,
+    labels: BTreeMap<labels::LabelRef, String>,
+    /// The broadcast journal, see [`Wallet::record_broadcast`].
+    broadcasts: Vec<BroadcastRecord>,
+    /// The limit set with [`Wallet::set_reveal_guard`].
+    reveal_guard: Option<u32>,
+    /// The policy set with [`Wallet::set_change_address_policy`].
+    change_address_policy: ChangeAddressPolicy,
+    /// The position of the next change address in the cycle of
+    /// [`ChangeAddressPolicy::RotateWithin`].
+    change_rotation: u32,
+    /// The birthday set with [`Wallet::set_birthday`].
+    birthday: Option<BlockTimeOrHeight>,
+    /// When each keychain was last synced, see [`Wallet::record_keychain_synced`].
+    last_synced: BTreeMap<KeychainKind, u64>,
+    /// The invoices created with [`Wallet::create_invoice`], by id.
+    invoices: BTreeMap<u32, InvoiceRecord>,
+    /// The revisions of the descriptors of the keychains, see [`Wallet::descriptor_history`].
+    descriptor_revisions: revisions::Revisions,
+    /// The inputs reserved by the transactions built with [`TxBuilder::reserve_inputs`], they
+    /// aren't persisted.
+    reservations: BTreeMap<OutPoint, reservations::Reservation>,
+    /// The duration set with [`Wallet::set_reservation_ttl`].
+    reservation_ttl: u64,
+    /// The limits set with [`Wallet::set_fee_limits`].
+    fee_limits: FeeLimits,
+    /// The providers of the inputs added with
+    /// [`TxBuilder::add_utxo_with_witness_provider`], they aren't persisted.
+    witness_providers: BTreeMap<OutPoint, Arc<dyn WitnessProvider>>,
+    /// The subscriptions created with [`Wallet::subscribe`], they aren't persisted.
+    #[cfg(feature = "std")]
+    subscriptions: subscriptions::Subscriptions,
     network: Network,
     secp: SecpCtx,
 }
@@ -153,6 +278,9 @@ pub struct AddressInfo {
     pub address: Address,
     /// Type of keychain
     pub keychain: KeychainKind,
+    /// The revision of the descriptor of the keychain the address is derived from, see
+    /// [`Wallet::descriptor_history`]
+    pub revision: u32,
 }
 
 impl Deref for AddressInfo {
@@ -169,6 +297,76 @@ impl fmt::Display for AddressInfo {
     }
 }
 
+/// The signatures collected by an input of a PSBT, see [`Wallet::combine_psbts`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputSignatures {
+    /// Number of signatures present in the input
+    pub present: usize,
+    /// Number of signatures required by the policy of the descriptor spent by the input
+    pub required: usize,
+}
+
+impl InputSignatures {
+    /// Whether the input has enough signatures to be finalized
+    pub fn is_complete(&self) -> bool {
+        self.present >= self.required
+    }
+}
+
+/// The outcome of [`Wallet::combine_psbts`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CombineReport {
+    /// The signatures collected by every input of the PSBT, or `None` for the inputs that don't
+    /// spend one of the wallet's descriptors
+    pub inputs: Vec<Option<InputSignatures>>,
+}
+
+impl CombineReport {
+    /// Whether every input spending one of the wallet's descriptors has enough signatures
+    pub fn is_complete(&self) -> bool {
+        self.inputs
+            .iter()
+            .flatten()
+            .all(InputSignatures::is_complete)
+    }
+}
+
+/// How a transaction fed to [`Wallet::apply_unconfirmed_txs`] was applied to the wallet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxApplied {
+    /// The txid of the transaction
+    pub txid: Txid,
+    /// Whether the transaction was inserted in the wallet: it spends or pays one of the wallet's
+    /// outputs or script pubkeys, or it conflicts with one of the wallet's transactions
+    pub relevant: bool,
+    /// The wallet's transactions spending some of the same inputs, when it was applied
+    pub conflicts: Vec<Txid>,
+    /// Whether the transaction is part of the wallet's history once the whole batch is applied
+    pub canonical: bool,
+    /// The change of the total balance of the wallet caused by applying the transaction
+    pub balance_delta: SignedAmount,
+}
+
+/// The unsigned transaction selected by [`Wallet::create_tx`], before it's completed into a PSBT
+struct DraftTx {
+    tx: Transaction,
+    selected: Vec<Utxo>,
+    fee_amount: Amount,
+    change_output: Option<tx_builder::ChangeOutput>,
+    /// The weight the selected inputs add once satisfied
+    satisfaction_weight: Weight,
+    /// How the locktime of the transaction was set
+    locktime: tx_builder::LocktimeDecision,
+}
+
+impl DraftTx {
+    /// An upper bound of the weight of the transaction once its inputs are satisfied
+    fn estimated_weight(&self) -> Weight {
+        // the segwit marker and flag are counted even if no input ends up with a witness
+        self.tx.weight() + self.satisfaction_weight + Weight::from_wu(2)
+    }
+}
+
 /// The error type when constructing a fresh [`Wallet`].
 ///
 /// Methods [`new`] and [`new_with_genesis_hash`] may return this error.
@@ -194,7 +392,7 @@ impl std::error::Error for NewError {}
 
 /// The error type when loading a [`Wallet`] from a [`ChangeSet`].
 ///
-/// Method [`load_from_changeset`] may return this error.
+/// Methods [`load_from_changeset`] and [`LoadParams::load_wallet`] may return this error.
 ///
 /// [`load_from_changeset`]: Wallet::load_from_changeset
 #[derive(Debug)]
@@ -207,6 +405,8 @@ pub enum LoadError {
     MissingGenesis,
     /// Data loaded from persistence is missing descriptor.
     MissingDescriptor(KeychainKind),
+    /// Data loaded from persistence doesn't match what was expected.
+    Mismatch(LoadMismatch),
 }
 
 impl fmt::Display for LoadError {
@@ -218,6 +418,7 @@ impl fmt::Display for LoadError {
             LoadError::MissingDescriptor(k) => {
                 write!(f, "loaded data is missing descriptor for keychain {k:?}")
             }
+            LoadError::Mismatch(e) => e.fmt(f),
         }
     }
 }
@@ -225,6 +426,59 @@ impl fmt::Display for LoadError {
 #[cfg(feature = "std")]
 impl std::error::Error for LoadError {}
 
+/// A difference between the data loaded from persistence and what [`LoadParams`] expected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadMismatch {
+    /// The network is not the expected one.
+    Network {
+        /// The network loaded from persistence.
+        loaded: Network,
+        /// The expected network.
+        expected: Network,
+    },
+    /// The genesis block hash is not the expected one.
+    Genesis {
+        /// The genesis block hash loaded from persistence.
+        loaded: BlockHash,
+        /// The expected genesis block hash.
+        expected: BlockHash,
+    },
+    /// The descriptor of a keychain is not the expected one.
+    Descriptor {
+        /// The keychain of the descriptor.
+        keychain: KeychainKind,
+        /// The descriptor loaded from persistence, `None` if the wallet has no such keychain.
+        loaded: Option<Box<ExtendedDescriptor>>,
+        /// The expected descriptor, `None` if the wallet wasn't expected to have this keychain.
+        expected: Option<Box<ExtendedDescriptor>>,
+    },
+}
+
+impl fmt::Display for LoadMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadMismatch::Network { loaded, expected } => {
+                write!(f, "loaded network type is not {}, got {}", expected, loaded)
+            }
+            LoadMismatch::Genesis { loaded, expected } => {
+                write!(f, "loaded genesis hash is not {}, got {}", expected, loaded)
+            }
+            LoadMismatch::Descriptor {
+                keychain,
+                loaded,
+                expected,
+            } => write!(
+                f,
+                "loaded descriptor for keychain {:?} is different from what was expected, expected {:?}, got {:?}",
+                keychain, expected, loaded
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LoadMismatch {}
+
 /// Error type for when we try load a [`Wallet`] from persistence and creating it if non-existent.
 ///
 /// Methods [`new_or_load`] and [`new_or_load_with_genesis_hash`] may return this error.
@@ -344,6 +598,60 @@ impl fmt::Display for ApplyBlockError {
 #[cfg(feature = "std")]
 impl std::error::Error for ApplyBlockError {}
 
+/// An error that may occur when applying a batch of blocks with [`Wallet::apply_blocks`].
+#[derive(Debug)]
+pub enum ApplyBlocksError {
+    /// The first block of the batch cannot connect to the wallet's chain.
+    CannotConnect {
+        /// Height of the block.
+        height: u32,
+        /// Hash of the block.
+        hash: BlockHash,
+        /// Why it doesn't connect.
+        error: ApplyHeaderError,
+    },
+    /// A block doesn't build on the block before it in the batch.
+    NotConsecutive {
+        /// Height of the block.
+        height: u32,
+        /// Hash of the block.
+        hash: BlockHash,
+        /// Height of the previous block of the batch.
+        prev_height: u32,
+        /// Hash of the previous block of the batch.
+        prev_hash: BlockHash,
+    },
+}
+
+impl fmt::Display for ApplyBlocksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyBlocksError::CannotConnect {
+                height,
+                hash,
+                error,
+            } => write!(
+                f,
+                "block {} at height {} cannot connect to the wallet chain: {}",
+                hash, height, error
+            ),
+            ApplyBlocksError::NotConsecutive {
+                height,
+                hash,
+                prev_height,
+                prev_hash,
+            } => write!(
+                f,
+                "block {} at height {} doesn't build on the previous block {} at height {}",
+                hash, height, prev_hash, prev_height
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ApplyBlocksError {}
+
 impl Wallet {
     /// Initialize an empty [`Wallet`].
     pub fn new<E: IntoWalletDescriptor>(
@@ -351,8 +659,7 @@ impl Wallet {
         change_descriptor: E,
         network: Network,
     ) -> Result<Self, NewError> {
-        let genesis_hash = genesis_block(network).block_hash();
-        Self::new_with_genesis_hash(descriptor, change_descriptor, network, genesis_hash)
+        Self::new_with_network_params(descriptor, change_descriptor, network.into())
     }
 
     /// Initialize an empty [`Wallet`] with a custom genesis hash.
@@ -365,6 +672,69 @@ impl Wallet {
         network: Network,
         genesis_hash: BlockHash,
     ) -> Result<Self, NewError> {
+        let params = NetworkParams::new(network).with_genesis_hash(genesis_hash);
+        Self::new_with_network_params(descriptor, change_descriptor, params)
+    }
+
+    /// Initialize an empty [`Wallet`] on the network and genesis block of `params`.
+    ///
+    /// This is how to create a wallet for a custom signet or regtest chain, whose genesis block
+    /// isn't the one of its network.
+    pub fn new_with_network_params<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: E,
+        params: NetworkParams,
+    ) -> Result<Self, NewError> {
+        Self::create_with_network_params(descriptor, Some(change_descriptor), params)
+    }
+
+    /// Initialize an empty single-keychain [`Wallet`], that only has an external `descriptor`.
+    ///
+    /// Change is sent back to the external keychain. Every method taking a [`KeychainKind`]
+    /// treats [`KeychainKind::Internal`] as [`KeychainKind::External`]: for example
+    /// `next_unused_address(KeychainKind::Internal)` returns the next unused external address,
+    /// and [`Wallet::keychains`] only yields the external keychain.
+    ///
+    /// Since every output of the wallet belongs to the external keychain:
+    ///
+    /// * The wallet's own unconfirmed change counts as [`Balance::untrusted_pending`].
+    /// * [`ChangeSpendPolicy::OnlyChange`] doesn't select any UTXO, while
+    ///   [`ChangeSpendPolicy::ChangeForbidden`] doesn't exclude any.
+    ///
+    ///
+    /// A descriptor without wildcard, such as `wpkh(<public key>)`, watches a single static
+    /// address: [`Wallet::reveal_next_address`] always returns it, and the full scans request its
+    /// only script pubkey. There is no address to send the change to, so the transactions with
+    /// change must send it elsewhere with [`TxBuilder::drain_to`] or
+    /// [`TxBuilder::drain_to_change`], [`CreateTxError::StaticChangeAddress`] otherwise.
+    ///
+    /// [`ChangeSpendPolicy::OnlyChange`]: tx_builder::ChangeSpendPolicy::OnlyChange
+    /// [`ChangeSpendPolicy::ChangeForbidden`]: tx_builder::ChangeSpendPolicy::ChangeForbidden
+    pub fn create_single<E: IntoWalletDescriptor>(
+        descriptor: E,
+        network: Network,
+    ) -> Result<Self, NewError> {
+        Self::create_single_with_network_params(descriptor, network.into())
+    }
+
+    /// Initialize an empty single-keychain [`Wallet`] on the network and genesis block of
+    /// `params`, see [`Wallet::create_single`].
+    pub fn create_single_with_network_params<E: IntoWalletDescriptor>(
+        descriptor: E,
+        params: NetworkParams,
+    ) -> Result<Self, NewError> {
+        Self::create_with_network_params(descriptor, None, params)
+    }
+
+    fn create_with_network_params<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        params: NetworkParams,
+    ) -> Result<Self, NewError> {
+        let NetworkParams {
+            network,
+            genesis_hash,
+        } = params;
         let secp = Secp256k1::new();
         let (chain, chain_changeset) = LocalChain::from_genesis_hash(genesis_hash);
         let mut index = KeychainTxOutIndex::<KeychainKind>::default();
@@ -374,17 +744,48 @@ impl Wallet {
                 .map_err(NewError::Descriptor)?;
 
         let indexed_graph = IndexedTxGraph::new(index);
+        let descriptor_revisions = revisions::first_revisions(
+            &indexed_graph.index,
+            &BTreeMap::new(),
+            Some(chain.tip().height()),
+        );
 
         let staged = ChangeSet {
             chain: chain_changeset,
             indexed_tx_graph: indexed_graph.initial_changeset(),
             network: Some(network),
+            labels: BTreeMap::new(),
+            broadcasts: Vec::new(),
+            change_address_policy: None,
+            change_rotation: None,
+            birthday: None,
+            last_synced: BTreeMap::new(),
+            invoices: BTreeMap::new(),
+            descriptor_revisions: descriptor_revisions.clone(),
         };
 
         Ok(Wallet {
             signers,
             change_signers,
+            extra_signers: BTreeMap::new(),
             network,
+            committed_chain: chain.clone(),
+            committed_last_revealed: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            broadcasts: Vec::new(),
+            reveal_guard: None,
+            change_address_policy: ChangeAddressPolicy::default(),
+            change_rotation: 0,
+            birthday: None,
+            last_synced: BTreeMap::new(),
+            invoices: BTreeMap::new(),
+            descriptor_revisions,
+            reservations: BTreeMap::new(),
+            reservation_ttl: DEFAULT_RESERVATION_TTL,
+            fee_limits: FeeLimits::default(),
+            witness_providers: BTreeMap::new(),
+            #[cfg(feature = "std")]
+            subscriptions: Default::default(),
             chain,
             indexed_graph,
             stage: staged,
@@ -392,6 +793,45 @@ impl Wallet {
         })
     }
 
+    /// Initialize an empty [`Wallet`] from a [`FullyNodedExport`].
+    ///
+    /// Both the plain `/0/*` form and the `<0;1>` multipath shorthand are supported, and are split
+    /// back into the external and internal descriptors. Their checksums are recomputed after
+    /// splitting.
+    ///
+    /// The wallet doesn't know anything about the chain yet: the export's
+    /// [`blockheight`](FullyNodedExport::blockheight), unless it's zero, becomes the wallet's
+    /// birthday (see [`Wallet::set_birthday`]), and should be used as the height to start scanning
+    /// from (e.g. the `start_height` of the `bdk_bitcoind_rpc` emitter). Blocks below it can't
+    /// contain any of the wallet's transactions.
+    pub fn create_from_export(
+        export: &FullyNodedExport,
+        network: Network,
+    ) -> Result<Self, ExportError> {
+        let (descriptor, change_descriptor) = export.descriptors()?;
+        let mut wallet =
+            Self::new(&descriptor, &change_descriptor, network).map_err(|e| match e {
+                NewError::Descriptor(e) => ExportError::Descriptor(e),
+            })?;
+        if export.blockheight > 0 {
+            wallet.set_birthday(BlockTimeOrHeight::Height(export.blockheight));
+        }
+        Ok(wallet)
+    }
+
+    /// Export the wallet in the format used by FullyNoded and Sparrow, see [`export`].
+    ///
+    /// Both descriptors are encoded in a single one using the `<0;1>` multipath shorthand, and
+    /// include the private keys of the wallet, if any. This fails if the descriptors can't be
+    /// expressed in that format, see [`FullyNodedExport::export_wallet`].
+    pub fn export(
+        &self,
+        label: &str,
+        include_blockheight: bool,
+    ) -> Result<FullyNodedExport, &'static str> {
+        FullyNodedExport::export_wallet_multipath(self, label, include_blockheight)
+    }
+
     /// Load [`Wallet`] from the given previously persisted [`ChangeSet`].
     ///
     /// Note that the descriptor secret keys are not persisted to the db; this means that after
@@ -434,6 +874,10 @@ impl Wallet {
     ///
     /// Alternatively, you can call [`Wallet::new_or_load`], which will add the private keys of the
     /// passed-in descriptors to the [`Wallet`].
+    ///
+    /// Use [`Wallet::load`] to check that the loaded data is what you expect, and get a
+    /// [`LoadMismatch`] error instead of a wallet on the wrong network or with the wrong
+    /// descriptors.
     pub fn load_from_changeset(changeset: ChangeSet) -> Result<Self, LoadError> {
         let secp = Secp256k1::new();
         let network = changeset.network.ok_or(LoadError::MissingNetwork)?;
@@ -447,13 +891,13 @@ impl Wallet {
             .get(&KeychainKind::External)
             .ok_or(LoadError::MissingDescriptor(KeychainKind::External))?
             .clone();
+        // single-keychain wallets don't have a change descriptor
         let change_descriptor = changeset
             .indexed_tx_graph
             .indexer
             .keychains_added
             .get(&KeychainKind::Internal)
-            .ok_or(LoadError::MissingDescriptor(KeychainKind::Internal))?
-            .clone();
+            .cloned();
 
         let (signers, change_signers) =
             create_signers(&mut index, &secp, descriptor, change_descriptor, network)
@@ -462,11 +906,46 @@ impl Wallet {
         let mut indexed_graph = IndexedTxGraph::new(index);
         indexed_graph.apply_changeset(changeset.indexed_tx_graph);
 
-        let stage = ChangeSet::default();
+        let labels = changeset
+            .labels
+            .into_iter()
+            .filter_map(|(label_ref, label)| Some((label_ref, label?)))
+            .collect();
+
+        let broadcasts = changeset.broadcasts;
+
+        // the revisions of the keychains persisted before they were recorded are persisted with
+        // the next changes
+        let mut descriptor_revisions = changeset.descriptor_revisions;
+        let first_revisions =
+            revisions::first_revisions(&indexed_graph.index, &descriptor_revisions, None);
+        descriptor_revisions.extend(first_revisions.clone());
+        let stage = ChangeSet {
+            descriptor_revisions: first_revisions,
+            ..Default::default()
+        };
 
         Ok(Wallet {
             signers,
             change_signers,
+            extra_signers: BTreeMap::new(),
+            committed_chain: chain.clone(),
+            committed_last_revealed: indexed_graph.index.last_revealed_indices(),
+            labels,
+            broadcasts,
+            reveal_guard: None,
+            change_address_policy: changeset.change_address_policy.unwrap_or_default(),
+            change_rotation: changeset.change_rotation.unwrap_or(0),
+            birthday: changeset.birthday,
+            last_synced: changeset.last_synced,
+            invoices: changeset.invoices,
+            descriptor_revisions,
+            reservations: BTreeMap::new(),
+            reservation_ttl: DEFAULT_RESERVATION_TTL,
+            fee_limits: FeeLimits::default(),
+            witness_providers: BTreeMap::new(),
+            #[cfg(feature = "std")]
+            subscriptions: Default::default(),
             chain,
             indexed_graph,
             stage,
@@ -475,6 +954,15 @@ impl Wallet {
         })
     }
 
+    /// Build [`LoadParams`] to load a [`Wallet`] from a [`ChangeSet`], checking the loaded data.
+    ///
+    /// Checking the network matters even where descriptors and addresses look the same: testnet
+    /// and signet share their extended key and address prefixes, so a signet wallet would
+    /// otherwise silently load in a testnet application.
+    pub fn load() -> LoadParams {
+        LoadParams::new()
+    }
+
     /// Either loads [`Wallet`] from the given [`ChangeSet`] or initializes it if one does not exist.
     ///
     /// This method will fail if the loaded [`ChangeSet`] has different parameters to those provided.
@@ -499,13 +987,11 @@ impl Wallet {
         changeset: Option<ChangeSet>,
         network: Network,
     ) -> Result<Self, NewOrLoadError> {
-        let genesis_hash = genesis_block(network).block_hash();
-        Self::new_or_load_with_genesis_hash(
+        Self::new_or_load_with_network_params(
             descriptor,
             change_descriptor,
             changeset,
-            network,
-            genesis_hash,
+            network.into(),
         )
     }
 
@@ -522,8 +1008,32 @@ impl Wallet {
         network: Network,
         genesis_hash: BlockHash,
     ) -> Result<Self, NewOrLoadError> {
+        Self::new_or_load_with_network_params(
+            descriptor,
+            change_descriptor,
+            changeset,
+            NetworkParams::new(network).with_genesis_hash(genesis_hash),
+        )
+    }
+
+    /// Either loads [`Wallet`] from a [`ChangeSet`] or initializes it if one does not exist, on
+    /// the network and genesis block of `params`.
+    ///
+    /// This is like [`Wallet::new_or_load`], the loaded wallet must be on the network and genesis
+    /// block of `params`.
+    #[allow(clippy::result_large_err)]
+    pub fn new_or_load_with_network_params<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: E,
+        changeset: Option<ChangeSet>,
+        params: NetworkParams,
+    ) -> Result<Self, NewOrLoadError> {
+        let NetworkParams {
+            network,
+            genesis_hash,
+        } = params;
         if let Some(changeset) = changeset {
-            let mut wallet = Self::load_from_changeset(changeset).map_err(|e| match e {
+            let map_err = |e: LoadError| match e {
                 LoadError::Descriptor(e) => NewOrLoadError::Descriptor(e),
                 LoadError::MissingNetwork => NewOrLoadError::LoadedNetworkDoesNotMatch {
                     expected: network,
@@ -539,78 +1049,49 @@ impl Wallet {
                         keychain,
                     }
                 }
-            })?;
-            if wallet.network != network {
-                return Err(NewOrLoadError::LoadedNetworkDoesNotMatch {
-                    expected: network,
-                    got: Some(wallet.network),
-                });
-            }
-            if wallet.chain.genesis_hash() != genesis_hash {
-                return Err(NewOrLoadError::LoadedGenesisDoesNotMatch {
-                    expected: genesis_hash,
-                    got: Some(wallet.chain.genesis_hash()),
-                });
-            }
-
-            let (expected_descriptor, expected_descriptor_keymap) = descriptor
+                LoadError::Mismatch(LoadMismatch::Network { loaded, expected }) => {
+                    NewOrLoadError::LoadedNetworkDoesNotMatch {
+                        expected,
+                        got: Some(loaded),
+                    }
+                }
+                LoadError::Mismatch(LoadMismatch::Genesis { loaded, expected }) => {
+                    NewOrLoadError::LoadedGenesisDoesNotMatch {
+                        expected,
+                        got: Some(loaded),
+                    }
+                }
+                LoadError::Mismatch(LoadMismatch::Descriptor {
+                    keychain, loaded, ..
+                }) => NewOrLoadError::LoadedDescriptorDoesNotMatch {
+                    got: loaded.map(|descriptor| *descriptor),
+                    keychain,
+                },
+            };
+            // check the network first, the descriptors are parsed for it
+            let mut wallet = Self::load()
+                .check_network_params(params)
+                .load_wallet(changeset)
+                .map_err(map_err)?;
+            let descriptor = descriptor
                 .into_wallet_descriptor(&wallet.secp, network)
                 .map_err(NewOrLoadError::Descriptor)?;
-            let wallet_descriptor = wallet.public_descriptor(KeychainKind::External);
-            if wallet_descriptor != &expected_descriptor {
-                return Err(NewOrLoadError::LoadedDescriptorDoesNotMatch {
-                    got: Some(wallet_descriptor.clone()),
-                    keychain: KeychainKind::External,
-                });
-            }
-            // if expected descriptor has private keys add them as new signers
-            if !expected_descriptor_keymap.is_empty() {
-                let signer_container = SignersContainer::build(
-                    expected_descriptor_keymap,
-                    &expected_descriptor,
-                    &wallet.secp,
-                );
-                signer_container.signers().into_iter().for_each(|signer| {
-                    wallet.add_signer(
-                        KeychainKind::External,
-                        SignerOrdering::default(),
-                        signer.clone(),
-                    )
-                });
-            }
-
-            let (expected_change_descriptor, expected_change_descriptor_keymap) = change_descriptor
+            let change_descriptor = change_descriptor
                 .into_wallet_descriptor(&wallet.secp, network)
                 .map_err(NewOrLoadError::Descriptor)?;
-            let wallet_change_descriptor = wallet.public_descriptor(KeychainKind::Internal);
-            if wallet_change_descriptor != &expected_change_descriptor {
-                return Err(NewOrLoadError::LoadedDescriptorDoesNotMatch {
-                    got: Some(wallet_change_descriptor.clone()),
-                    keychain: KeychainKind::Internal,
-                });
-            }
-            // if expected change descriptor has private keys add them as new signers
-            if !expected_change_descriptor_keymap.is_empty() {
-                let signer_container = SignersContainer::build(
-                    expected_change_descriptor_keymap,
-                    &expected_change_descriptor,
-                    &wallet.secp,
-                );
-                signer_container.signers().into_iter().for_each(|signer| {
-                    wallet.add_signer(
-                        KeychainKind::Internal,
-                        SignerOrdering::default(),
-                        signer.clone(),
-                    )
-                });
-            }
-
+            Self::load()
+                .descriptor(KeychainKind::External, Some(descriptor))
+                .descriptor(KeychainKind::Internal, Some(change_descriptor))
+                .extract_keys()
+                .check_wallet(&mut wallet)
+                .map_err(map_err)?;
             Ok(wallet)
         } else {
-            Self::new_with_genesis_hash(descriptor, change_descriptor, network, genesis_hash)
-                .map_err(|e| match e {
+            Self::new_with_network_params(descriptor, change_descriptor, params).map_err(
+                |e| match e {
                     NewError::Descriptor(e) => NewOrLoadError::Descriptor(e),
-                })
+                },
+            )
         }
     }
 
@@ -619,11 +1100,104 @@ impl Wallet {
         self.network
     }
 
+    /// Get the network of the wallet and the genesis block of its chain.
+    pub fn network_params(&self) -> NetworkParams {
+        NetworkParams::new(self.network).with_genesis_hash(self.chain.genesis_hash())
+    }
+
+    /// Parse `address`, which must be valid for the network of the wallet.
+    ///
+    /// The addresses of a custom signet are the ones of [`Network::Signet`], and those of a
+    /// custom regtest are the ones of [`Network::Regtest`].
+    pub fn parse_address(&self, address: &str) -> Result<Address, bitcoin::address::ParseError> {
+        address
+            .parse::<Address<NetworkUnchecked>>()?
+            .require_network(self.network)
+    }
+
+    /// The keychain that is actually used in place of `keychain`.
+    ///
+    /// Single-keychain wallets (see [`Wallet::create_single`]) use the external keychain for
+    /// change too.
+    pub(crate) fn map_keychain(&self, keychain: KeychainKind) -> KeychainKind {
+        match keychain {
+            KeychainKind::Internal
+                if self
+                    .indexed_graph
+                    .index
+                    .get_descriptor(&KeychainKind::Internal)
+                    .is_none() =>
+            {
+                KeychainKind::External
+            }
+            keychain => keychain,
+        }
+    }
+
     /// Iterator over all keychains in this wallet
     pub fn keychains(&self) -> impl Iterator<Item = (&KeychainKind, &ExtendedDescriptor)> {
         self.indexed_graph.index.keychains()
     }
 
+    /// Track an extra `descriptor`, in the keychain [`KeychainKind::Extra`] labeled `label`.
+    ///
+    /// This allows a single wallet to track several accounts, or an old descriptor whose funds
+    /// are being migrated: transactions between the keychains are understood as internal
+    /// transfers. The outputs of the extra keychains are part of the balance and of the coin
+    /// selection candidates, their scripts are part of the sync and full scan requests and the
+    /// private keys of `descriptor`, if any, are used by [`Wallet::sign`]. The change still goes
+    /// to the internal keychain, unless [`TxBuilder::drain_to_change`] is used.
+    ///
+    /// The descriptor is staged to be persisted, the transactions already known by the wallet are
+    /// indexed again to find the ones of the new keychain. Like for the other keychains, the
+    /// private keys aren't persisted and the signers have to be added again after loading the
+    /// wallet, see [`Wallet::load_from_changeset`].
+    ///
+    /// Returns the new keychain, to be passed to the methods taking a [`KeychainKind`].
+    pub fn add_keychain<E: IntoWalletDescriptor>(
+        &mut self,
+        label: String,
+        descriptor: E,
+    ) -> Result<KeychainKind, AddKeychainError> {
+        let keychain = match KeychainLabel::new(&label) {
+            Some(label) => KeychainKind::Extra(label),
+            None => return Err(AddKeychainError::InvalidLabel(label)),
+        };
+        let (descriptor, keymap) =
+            into_wallet_descriptor_checked(descriptor, &self.secp, self.network)?;
+        let signers = Arc::new(SignersContainer::build(keymap, &descriptor, &self.secp));
+        let mut index_changeset = self
+            .indexed_graph
+            .index
+            .insert_descriptor(keychain, descriptor)
+            .map_err(|e| {
+                use bdk_chain::keychain::InsertDescriptorError;
+                match e {
+                    InsertDescriptorError::KeychainAlreadyAssigned { keychain, .. } => {
+                        AddKeychainError::KeychainAlreadyAssigned(keychain)
+                    }
+                    InsertDescriptorError::DescriptorAlreadyAssigned {
+                        existing_assignment,
+                        ..
+                    } => AddKeychainError::DescriptorAlreadyAssigned(existing_assignment),
+                }
+            })?;
+        self.extra_signers.insert(keychain, signers);
+        let descriptor_revisions = revisions::first_revisions(
+            &self.indexed_graph.index,
+            &self.descriptor_revisions,
+            Some(self.chain.tip().height()),
+        );
+        self.descriptor_revisions
+            .extend(descriptor_revisions.clone());
+
+        index_changeset.append(self.indexed_graph.reindex());
+        let mut changeset = ChangeSet::from(indexed_tx_graph::ChangeSet::from(index_changeset));
+        changeset.descriptor_revisions = descriptor_revisions;
+        self.stage.append(changeset);
+        Ok(keychain)
+    }
+
     /// Peek an address of the given `keychain` at `index` without revealing it.
     ///
     /// For non-wildcard descriptors this returns the same address at every provided index.
@@ -633,6 +1207,8 @@ impl Wallet {
     /// This panics when the caller requests for an address of derivation index greater than the
     /// [BIP32](https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki) max index.
     pub fn peek_address(&self, keychain: KeychainKind, mut index: u32) -> AddressInfo {
+        let keychain = self.map_keychain(keychain);
+        let revision = self.current_revision(keychain);
         let mut spk_iter = self
             .indexed_graph
             .index
@@ -649,6 +1225,7 @@ impl Wallet {
             index,
             address: Address::from_script(&spk, self.network).expect("must have address form"),
             keychain,
+            revision,
         }
     }
 
@@ -659,6 +1236,9 @@ impl Wallet {
     /// index defined in [BIP32](https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki),
     /// then the last revealed address will be returned.
     ///
+    /// This ignores the limit of [`Wallet::set_reveal_guard`], see
+    /// [`Wallet::try_reveal_next_address`].
+    ///
     /// **WARNING**: To avoid address reuse you must persist the changes resulting from one or more
     /// calls to this method before closing the wallet. For example:
     ///
@@ -680,6 +1260,8 @@ impl Wallet {
     /// # Ok::<(), anyhow::Error>(())
     /// ```
     pub fn reveal_next_address(&mut self, keychain: KeychainKind) -> AddressInfo {
+        let keychain = self.map_keychain(keychain);
+        let revision = self.current_revision(keychain);
         let index = &mut self.indexed_graph.index;
         let stage = &mut self.stage;
 
@@ -694,6 +1276,7 @@ impl Wallet {
             address: Address::from_script(spk.as_script(), self.network)
                 .expect("must have address form"),
             keychain,
+            revision,
         }
     }
 
@@ -711,6 +1294,8 @@ impl Wallet {
         keychain: KeychainKind,
         index: u32,
     ) -> impl Iterator<Item = AddressInfo> + '_ {
+        let keychain = self.map_keychain(keychain);
+        let revision = self.current_revision(keychain);
         let (spks, index_changeset) = self
             .indexed_graph
             .index
@@ -723,6 +1308,7 @@ impl Wallet {
             index,
             address: Address::from_script(&spk, self.network).expect("must have address form"),
             keychain,
+            revision,
         })
     }
 
@@ -731,10 +1317,14 @@ impl Wallet {
     ///
     /// This will attempt to derive and reveal a new address if no newly revealed addresses
     /// are available. See also [`reveal_next_address`](Self::reveal_next_address).
+    /// Like it, this ignores the limit of [`Wallet::set_reveal_guard`], see
+    /// [`Wallet::try_next_unused_address`].
     ///
     /// **WARNING**: To avoid address reuse you must persist the changes resulting from one or more
     /// calls to this method before closing the wallet. See [`Wallet::reveal_next_address`].
     pub fn next_unused_address(&mut self, keychain: KeychainKind) -> AddressInfo {
+        let keychain = self.map_keychain(keychain);
+        let revision = self.current_revision(keychain);
         let index = &mut self.indexed_graph.index;
 
         let ((index, spk), index_changeset) = index
@@ -749,14 +1339,40 @@ impl Wallet {
             address: Address::from_script(spk.as_script(), self.network)
                 .expect("must have address form"),
             keychain,
+            revision,
         }
     }
 
     /// Marks an address used of the given `keychain` at `index`.
     ///
+    /// The address won't be returned by [`next_unused_address`] or [`list_unused_addresses`]
+    /// until [`unmark_used`] is called. If `index` is beyond the last revealed index of the
+    /// keychain, the addresses up to `index` are revealed as well.
+    ///
     /// Returns whether the given index was present and then removed from the unused set.
+    ///
+    /// **WARNING**: The mark is only kept across restarts if you persist the staged changes.
+    ///
+    /// [`next_unused_address`]: Self::next_unused_address
+    /// [`list_unused_addresses`]: Self::list_unused_addresses
+    /// [`unmark_used`]: Self::unmark_used
     pub fn mark_used(&mut self, keychain: KeychainKind, index: u32) -> bool {
-        self.indexed_graph.index.mark_used(keychain, index)
+        let keychain = self.map_keychain(keychain);
+        let (_, mut index_changeset) = self
+            .indexed_graph
+            .index
+            .reveal_to_target(&keychain, index)
+            .expect("keychain must exist");
+
+        let marked = self.indexed_graph.index.mark_used(keychain, index);
+        if marked {
+            index_changeset.marked_used =
+                [(self.descriptor_id(keychain), [(index, true)].into())].into();
+        }
+
+        self.stage
+            .append(indexed_tx_graph::ChangeSet::from(index_changeset).into());
+        marked
     }
 
     /// Undoes the effect of [`mark_used`] and returns whether the `index` was inserted
@@ -768,7 +1384,26 @@ impl Wallet {
     ///
     /// [`mark_used`]: Self::mark_used
     pub fn unmark_used(&mut self, keychain: KeychainKind, index: u32) -> bool {
-        self.indexed_graph.index.unmark_used(keychain, index)
+        let keychain = self.map_keychain(keychain);
+        let unmarked = self.indexed_graph.index.unmark_used(keychain, index);
+        if unmarked {
+            let index_changeset = keychain::ChangeSet {
+                marked_used: [(self.descriptor_id(keychain), [(index, false)].into())].into(),
+                ..Default::default()
+            };
+            self.stage
+                .append(indexed_tx_graph::ChangeSet::from(index_changeset).into());
+        }
+        unmarked
+    }
+
+    /// The [`DescriptorId`] of the descriptor of `keychain`
+    fn descriptor_id(&self, keychain: KeychainKind) -> DescriptorId {
+        self.indexed_graph
+            .index
+            .get_descriptor(&keychain)
+            .expect("keychain must exist")
+            .descriptor_id()
     }
 
     /// List addresses that are revealed but unused.
@@ -780,6 +1415,8 @@ impl Wallet {
         &self,
         keychain: KeychainKind,
     ) -> impl DoubleEndedIterator<Item = AddressInfo> + '_ {
+        let keychain = self.map_keychain(keychain);
+        let revision = self.current_revision(keychain);
         self.indexed_graph
             .index
             .unused_keychain_spks(&keychain)
@@ -787,9 +1424,45 @@ impl Wallet {
                 index,
                 address: Address::from_script(spk, self.network).expect("must have address form"),
                 keychain,
+                revision,
             })
     }
 
+    /// List the addresses of the given `keychain` at the derivation indices in `range`, without
+    /// revealing them.
+    ///
+    /// Like [`Wallet::peek_address`] this doesn't change the keychain's derivation index, and it
+    /// can derive addresses beyond the revealed and lookahead indices. Non-wildcard descriptors
+    /// only have the address at index 0, which is returned if `range` contains it.
+    pub fn list_addresses(
+        &self,
+        keychain: KeychainKind,
+        range: impl RangeBounds<u32>,
+    ) -> impl Iterator<Item = AddressInfo> + '_ {
+        let keychain = self.map_keychain(keychain);
+        let revision = self.current_revision(keychain);
+        let descriptor = self
+            .indexed_graph
+            .index
+            .get_descriptor(&keychain)
+            .expect("keychain must exist");
+
+        SpkIterator::new_with_range(descriptor, range).map(move |(index, spk)| AddressInfo {
+            index,
+            address: Address::from_script(&spk, self.network).expect("must have address form"),
+            keychain,
+            revision,
+        })
+    }
+
+    /// Find the keychain and derivation index of an `address` of this wallet.
+    ///
+    /// This looks up the revealed and lookahead indices of both keychains; addresses beyond the
+    /// lookahead are not found.
+    pub fn find_address_index(&self, address: &Address) -> Option<(KeychainKind, u32)> {
+        self.derivation_of_spk(&address.script_pubkey())
+    }
+
     /// Return whether or not a `script` is part of this wallet (either internal or external)
     pub fn is_mine(&self, script: &Script) -> bool {
         self.indexed_graph.index.index_of_spk(script).is_some()
@@ -861,6 +1534,7 @@ impl Wallet {
         &self,
         keychain: KeychainKind,
     ) -> impl Iterator<Item = Indexed<ScriptBuf>> + Clone {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph
             .index
             .unbounded_spk_iter(&keychain)
@@ -885,8 +1559,9 @@ impl Wallet {
     /// Inserts a [`TxOut`] at [`OutPoint`] into the wallet's transaction graph.
     ///
     /// This is used for providing a previous output's value so that we can use [`calculate_fee`]
-    /// or [`calculate_fee_rate`] on a given transaction. Outputs inserted with this method will
-    /// not be returned in [`list_unspent`] or [`list_output`].
+    /// or [`calculate_fee_rate`] on a given transaction, and so that [`tx_details`] reports its
+    /// fee, for instance for transactions imported without their parents. Outputs inserted with
+    /// this method will not be returned in [`list_unspent`] or [`list_output`].
     ///
     /// **WARNINGS:** This should only be used to add `TxOut`s that the wallet does not own. Only
     /// insert `TxOut`s that you trust the values for!
@@ -897,6 +1572,7 @@ impl Wallet {
     ///
     /// [`calculate_fee`]: Self::calculate_fee
     /// [`calculate_fee_rate`]: Self::calculate_fee_rate
+    /// [`tx_details`]: Self::tx_details
     /// [`list_unspent`]: Self::list_unspent
     /// [`list_output`]: Self::list_output
     pub fn insert_txout(&mut self, outpoint: OutPoint, txout: TxOut) {
@@ -935,14 +1611,64 @@ impl Wallet {
         self.indexed_graph.graph().calculate_fee(tx)
     }
 
-    /// Calculate the [`FeeRate`] for a given transaction.
-    ///
-    /// To calculate the fee rate for a [`Transaction`] with inputs not owned by this wallet you must
-    /// manually insert the TxOut(s) into the tx graph using the [`insert_txout`] function.
+    /// Look up the outputs spent by `tx` which are missing from the wallet's graph with
+    /// `resolver`, and insert the ones it finds with [`insert_txout`]. Returns how many outputs
+    /// were inserted.
     ///
-    /// Note `tx` does not have to be in the graph for this to work.
+    /// The wallet's graph is always checked first, `resolver` only needs the other sources, such
+    /// as a chain source client. As with [`insert_txout`], only use sources you trust.
     ///
-    /// # Examples
+    /// [`insert_txout`]: Self::insert_txout
+    pub fn resolve_prevouts<C: PrevoutCache>(
+        &mut self,
+        tx: &Transaction,
+        resolver: &mut PrevoutResolver<'_, C>,
+    ) -> usize {
+        if tx.is_coinbase() {
+            return 0;
+        }
+        let graph = self.indexed_graph.graph();
+        let missing = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .filter(|&outpoint| graph.get_txout(outpoint).is_none())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return 0;
+        }
+        let mut inserted = 0;
+        for (outpoint, txout) in missing.iter().zip(resolver.get_txouts(&missing)) {
+            if let Some(txout) = txout {
+                self.insert_txout(*outpoint, txout);
+                inserted += 1;
+            }
+        }
+        inserted
+    }
+
+    /// Calculate the fee of `tx` like [`calculate_fee`], after looking up the missing outputs it
+    /// spends with `resolver`, see [`resolve_prevouts`].
+    ///
+    /// [`calculate_fee`]: Self::calculate_fee
+    /// [`resolve_prevouts`]: Self::resolve_prevouts
+    pub fn calculate_fee_checked<C: PrevoutCache>(
+        &mut self,
+        tx: &Transaction,
+        resolver: &mut PrevoutResolver<'_, C>,
+    ) -> Result<Amount, CalculateFeeError> {
+        self.resolve_prevouts(tx, resolver);
+        self.calculate_fee(tx)
+    }
+
+    /// Calculate the [`FeeRate`] for a given transaction.
+    ///
+    /// To calculate the fee rate for a [`Transaction`] with inputs not owned by this wallet you must
+    /// manually insert the TxOut(s) into the tx graph using the [`insert_txout`] function.
+    ///
+    /// Note `tx` does not have to be in the graph for this to work.
+    ///
+    /// # Examples
     ///
     /// ```rust, no_run
     /// # use bitcoin::Txid;
@@ -1054,6 +1780,108 @@ impl Wallet {
         })
     }
 
+    /// Get the [`TxDetails`] of a canonical transaction of the wallet, i.e. the amounts it sends
+    /// from and receives to the wallet, its fee and its outputs split between the wallet and its
+    /// counterparties.
+    ///
+    /// The fee is `None` if some of the outputs spent by the transaction are missing from the
+    /// wallet's graph, see [`Wallet::insert_txout`].
+    ///
+    /// ```rust, no_run
+    /// # use bdk_wallet::Wallet;
+    /// # let wallet: Wallet = todo!();
+    /// # let my_txid: bitcoin::Txid = todo!();
+    /// let details = wallet.tx_details(my_txid).expect("transaction exists");
+    /// println!("net: {}, fee: {:?}", details.net(), details.fee);
+    /// ```
+    pub fn tx_details(&self, txid: Txid) -> Option<TxDetails> {
+        self.get_tx(txid)
+            .map(|canonical_tx| self.canonical_tx_details(canonical_tx))
+    }
+
+    /// Get the [`TxDetails`] of a canonical transaction like [`tx_details`], after looking up the
+    /// missing outputs it spends with `resolver`, so that its fee is known. See
+    /// [`resolve_prevouts`].
+    ///
+    /// [`tx_details`]: Self::tx_details
+    /// [`resolve_prevouts`]: Self::resolve_prevouts
+    pub fn tx_details_resolved<C: PrevoutCache>(
+        &mut self,
+        txid: Txid,
+        resolver: &mut PrevoutResolver<'_, C>,
+    ) -> Option<TxDetails> {
+        let tx = self.indexed_graph.graph().get_tx(txid)?;
+        self.resolve_prevouts(&tx, resolver);
+        self.tx_details(txid)
+    }
+
+    /// Iterate over the [`TxDetails`] of all the canonical transactions of the wallet.
+    ///
+    /// See [`Wallet::tx_details`] and [`Wallet::transactions`].
+    pub fn list_tx_details(&self) -> impl Iterator<Item = TxDetails> + '_ {
+        self.transactions()
+            .map(move |canonical_tx| self.canonical_tx_details(canonical_tx))
+    }
+
+    fn canonical_tx_details(
+        &self,
+        canonical_tx: CanonicalTx<'_, Arc<Transaction>, ConfirmationTimeHeightAnchor>,
+    ) -> TxDetails {
+        let graph = self.indexed_graph.graph();
+        let index = &self.indexed_graph.index;
+        let tx = &canonical_tx.tx_node.tx;
+
+        let mut sent = Amount::ZERO;
+        // `None` as soon as one of the spent outputs is unknown
+        let mut inputs_sum = Some(Amount::ZERO);
+        if !tx.is_coinbase() {
+            for txin in &tx.input {
+                match graph.get_txout(txin.previous_output) {
+                    Some(txout) => {
+                        if index.index_of_spk(&txout.script_pubkey).is_some() {
+                            sent += txout.value;
+                        }
+                        inputs_sum = inputs_sum.map(|sum| sum + txout.value);
+                    }
+                    None => inputs_sum = None,
+                }
+            }
+        }
+
+        let mut received = Amount::ZERO;
+        let mut outputs_sum = Amount::ZERO;
+        let mut owned_outputs = BTreeMap::new();
+        let mut counterparty_outputs = BTreeMap::new();
+        for (vout, txout) in (0..).zip(&tx.output) {
+            outputs_sum += txout.value;
+            match index.index_of_spk(&txout.script_pubkey) {
+                Some(&(keychain, derivation_index)) => {
+                    received += txout.value;
+                    owned_outputs.insert(vout, (keychain, derivation_index));
+                }
+                None => {
+                    counterparty_outputs.insert(vout, txout.clone());
+                }
+            }
+        }
+
+        let fee = if tx.is_coinbase() {
+            Some(Amount::ZERO)
+        } else {
+            inputs_sum.and_then(|sum| sum.checked_sub(outputs_sum))
+        };
+
+        TxDetails {
+            txid: canonical_tx.tx_node.txid,
+            sent,
+            received,
+            fee,
+            confirmation_time: canonical_tx.chain_position.cloned().into(),
+            owned_outputs,
+            counterparty_outputs,
+        }
+    }
+
     /// Add a new checkpoint to the wallet's internal view of the chain.
     ///
     /// Returns whether anything changed with the insertion (e.g. `false` if checkpoint was already
@@ -1157,6 +1985,29 @@ impl Wallet {
         )
     }
 
+    /// Return the balance of each keychain, see [`Wallet::balance`].
+    ///
+    /// The keychains without any output are included, with an empty balance.
+    pub fn balance_by_keychain(&self) -> BTreeMap<KeychainKind, Balance> {
+        let outpoints = self.indexed_graph.index.outpoints();
+        self.indexed_graph
+            .index
+            .keychains()
+            .map(|(&keychain, _)| {
+                let balance = self.indexed_graph.graph().balance(
+                    &self.chain,
+                    self.chain.tip().block_id(),
+                    outpoints
+                        .iter()
+                        .filter(|((k, _), _)| *k == keychain)
+                        .cloned(),
+                    |&(k, _), _| k == KeychainKind::Internal,
+                );
+                (keychain, balance)
+            })
+            .collect()
+    }
+
     /// Add an external signer
     ///
     /// See [the `signer` module](signer) for an example.
@@ -1166,9 +2017,13 @@ impl Wallet {
         ordering: SignerOrdering,
         signer: Arc<dyn TransactionSigner>,
     ) {
+        let keychain = self.map_keychain(keychain);
         let signers = match keychain {
             KeychainKind::External => Arc::make_mut(&mut self.signers),
             KeychainKind::Internal => Arc::make_mut(&mut self.change_signers),
+            KeychainKind::Extra(_) => {
+                Arc::make_mut(self.extra_signers.entry(keychain).or_default())
+            }
         };
 
         signers.add_external(signer.id(&self.secp), ordering, signer);
@@ -1192,12 +2047,33 @@ impl Wallet {
     /// Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
     pub fn get_signers(&self, keychain: KeychainKind) -> Arc<SignersContainer> {
+        let keychain = self.map_keychain(keychain);
         match keychain {
             KeychainKind::External => Arc::clone(&self.signers),
             KeychainKind::Internal => Arc::clone(&self.change_signers),
+            KeychainKind::Extra(_) => self
+                .extra_signers
+                .get(&keychain)
+                .cloned()
+                .unwrap_or_default(),
         }
     }
 
+    /// Returns whether the wallet is watch-only, i.e. it has no signer for either keychain.
+    ///
+    /// This is the case when it's created from descriptors holding only public keys: the
+    /// wallet can still create PSBTs (complete with the key origins of the descriptors) to be
+    /// signed elsewhere, and finalize them with [`Wallet::finalize_psbt`] once signed. Calling
+    /// [`Wallet::sign`] on it returns [`SignerError::WatchOnly`].
+    pub fn is_watch_only(&self) -> bool {
+        self.signers.signers().is_empty()
+            && self.change_signers.signers().is_empty()
+            && self
+                .extra_signers
+                .values()
+                .all(|signers| signers.signers().is_empty())
+    }
+
     /// Start building a transaction.
     ///
     /// This returns a blank [`TxBuilder`] from which you can specify the parameters for the transaction.
@@ -1238,16 +2114,89 @@ impl Wallet {
         &mut self,
         coin_selection: Cs,
         params: TxParams,
-    ) -> Result<Psbt, CreateTxError> {
-        let keychains: BTreeMap<_, _> = self.indexed_graph.index.keychains().collect();
-        let external_descriptor = keychains.get(&KeychainKind::External).expect("must exist");
-        let internal_descriptor = keychains.get(&KeychainKind::Internal).expect("must exist");
+        rng: &mut impl RngCore,
+    ) -> Result<(Psbt, tx_builder::FeeTarget), CreateTxError> {
+        self.prune_reservations();
+        let reserve_inputs = params.reserve_inputs;
+        if reserve_inputs {
+            // the coin selection skips the reserved outpoints, only the manually selected ones
+            // can conflict
+            let reserved = params
+                .utxos
+                .iter()
+                .map(|utxo| utxo.utxo.outpoint())
+                .filter(|outpoint| self.is_reserved(*outpoint))
+                .collect::<Vec<_>>();
+            if !reserved.is_empty() {
+                return Err(CreateTxError::InputsReserved(reserved));
+            }
+        }
+        let draft = self.draft_tx(&coin_selection, &params, false, rng)?;
+        let fee = draft.fee_amount;
+        let estimated_weight = draft.estimated_weight();
+        self.build_fee_limits(&params)
+            .check(fee, estimated_weight)?;
+        let fee_target = tx_builder::FeeTarget {
+            fee,
+            fee_rate: match params.fee_policy.unwrap_or_default() {
+                FeePolicy::FeeRate(rate) => rate,
+                FeePolicy::FeeAmount(_) => fee / estimated_weight,
+            },
+            estimated_weight,
+            change: draft.change_output,
+        };
+        let witness_providers = params.witness_providers.clone();
+        let psbt = self.complete_transaction(draft.tx, draft.selected, params)?;
+        if reserve_inputs {
+            self.reserve_inputs(&psbt.unsigned_tx)?;
+        }
+        self.witness_providers.extend(witness_providers);
+        Ok((psbt, fee_target))
+    }
 
-        let external_policy = external_descriptor
-            .extract_policy(&self.signers, BuildSatisfaction::None, &self.secp)?
+    pub(crate) fn estimate_tx<Cs: coin_selection::CoinSelectionAlgorithm>(
+        &mut self,
+        coin_selection: &Cs,
+        params: &TxParams,
+        rng: &mut impl RngCore,
+    ) -> Result<tx_builder::TxEstimate, CreateTxError> {
+        self.prune_reservations();
+        let draft = self.draft_tx(coin_selection, params, true, rng)?;
+        let weight = draft.estimated_weight();
+        let fee = draft.fee_amount;
+        self.build_fee_limits(params).check(fee, weight)?;
+        Ok(tx_builder::TxEstimate {
+            fee,
+            fee_rate: fee / weight,
+            weight,
+            input_value: draft.selected.iter().map(|u| u.txout().value).sum(),
+            change: draft.change_output,
+            selected: draft
+                .tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect(),
+            locktime: draft.locktime,
+        })
+    }
+
+    /// Select the coins and build the unsigned transaction of `params`.
+    ///
+    /// With `dry_run` the wallet is left untouched, the change goes to the next unused internal
+    /// script pubkey without revealing it.
+    fn draft_tx<Cs: coin_selection::CoinSelectionAlgorithm>(
+        &mut self,
+        coin_selection: &Cs,
+        params: &TxParams,
+        dry_run: bool,
+        rng: &mut impl RngCore,
+    ) -> Result<DraftTx, CreateTxError> {
+        let external_policy = self
+            .extract_policy(KeychainKind::External, BuildSatisfaction::None)?
             .unwrap();
-        let internal_policy = internal_descriptor
-            .extract_policy(&self.change_signers, BuildSatisfaction::None, &self.secp)?
+        let internal_policy = self
+            .extract_policy(KeychainKind::Internal, BuildSatisfaction::None)?
             .unwrap();
 
         // The policy allows spending external outputs, but it requires a policy path that hasn't been
@@ -1260,8 +2209,9 @@ impl Wallet {
                 KeychainKind::External,
             ));
         };
-        // Same for the internal_policy path
+        // Same for the internal_policy path, unless there's no internal keychain to spend from
         if params.change_policy != tx_builder::ChangeSpendPolicy::ChangeForbidden
+            && self.map_keychain(KeychainKind::Internal) == KeychainKind::Internal
             && internal_policy.requires_path()
             && params.internal_policy_path.is_none()
         {
@@ -1276,17 +2226,44 @@ impl Wallet {
                 .as_ref()
                 .unwrap_or(&BTreeMap::new()),
         )?;
-        let internal_requirements = internal_policy.get_condition(
-            params
-                .internal_policy_path
-                .as_ref()
-                .unwrap_or(&BTreeMap::new()),
-        )?;
+        let internal_requirements =
+            if self.map_keychain(KeychainKind::Internal) == KeychainKind::Internal {
+                internal_policy.get_condition(
+                    params
+                        .internal_policy_path
+                        .as_ref()
+                        .unwrap_or(&BTreeMap::new()),
+                )?
+            } else {
+                Default::default()
+            };
+
+        let mut requirements = external_requirements.merge(&internal_requirements)?;
 
-        let requirements = external_requirements.merge(&internal_requirements)?;
+        // the outputs of the extra keychains are candidates too, unless only change is spent
+        if params.change_policy != tx_builder::ChangeSpendPolicy::OnlyChange {
+            let extra_keychains = self
+                .indexed_graph
+                .index
+                .keychains()
+                .map(|(keychain, _)| *keychain)
+                .filter(|keychain| matches!(keychain, KeychainKind::Extra(_)))
+                .collect::<Vec<_>>();
+            for keychain in extra_keychains {
+                let policy = self
+                    .extract_policy(keychain, BuildSatisfaction::None)?
+                    .unwrap();
+                let policy_path = params.extra_policy_paths.get(&keychain);
+                if policy.requires_path() && policy_path.is_none() {
+                    return Err(CreateTxError::SpendingPolicyRequired(keychain));
+                }
+                let extra_requirements =
+                    policy.get_condition(policy_path.unwrap_or(&BTreeMap::new()))?;
+                requirements = requirements.merge(&extra_requirements)?;
+            }
+        }
 
         let version = match params.version {
-            Some(tx_builder::Version(0)) => return Err(CreateTxError::Version0),
             Some(tx_builder::Version(1)) if requirements.csv.is_some() => {
                 return Err(CreateTxError::Version1Csv)
             }
@@ -1305,45 +2282,13 @@ impl Wallet {
             Some(h) => h,
         };
 
-        let lock_time = match params.locktime {
-            // When no nLockTime is specified, we try to prevent fee sniping, if possible
-            None => {
-                // Fee sniping can be partially prevented by setting the timelock
-                // to current_height. If we don't know the current_height,
-                // we default to 0.
-                let fee_sniping_height = current_height;
-
-                // We choose the biggest between the required nlocktime and the fee sniping
-                // height
-                match requirements.timelock {
-                    // No requirement, just use the fee_sniping_height
-                    None => fee_sniping_height,
-                    // There's a block-based requirement, but the value is lower than the fee_sniping_height
-                    Some(value @ absolute::LockTime::Blocks(_)) if value < fee_sniping_height => {
-                        fee_sniping_height
-                    }
-                    // There's a time-based requirement or a block-based requirement greater
-                    // than the fee_sniping_height use that value
-                    Some(value) => value,
-                }
-            }
-            // Specific nLockTime required and we have no constraints, so just set to that value
-            Some(x) if requirements.timelock.is_none() => x,
-            // Specific nLockTime required and it's compatible with the constraints
-            Some(x)
-                if requirements.timelock.unwrap().is_same_unit(x)
-                    && x >= requirements.timelock.unwrap() =>
-            {
-                x
-            }
-            // Invalid nLockTime required
-            Some(x) => {
-                return Err(CreateTxError::LockTime {
-                    requested: x,
-                    required: requirements.timelock.unwrap(),
-                })
-            }
-        };
+        let locktime = resolve_locktime(
+            params.locktime_policy,
+            requirements.timelock,
+            current_height,
+            rng,
+        )?;
+        let lock_time = locktime.locktime;
 
         // The nSequence to be by default for inputs unless an explicit sequence is specified.
         let n_sequence = match (params.rbf, requirements.csv) {
@@ -1359,10 +2304,6 @@ impl Wallet {
             // don't bother checking for it here. The same is true for all the other branches below
             (None, Some(csv)) => csv,
 
-            // RBF with a specific value but that value is too high
-            (Some(tx_builder::RbfValue::Value(rbf)), _) if !rbf.is_rbf() => {
-                return Err(CreateTxError::RbfSequence)
-            }
             // RBF with a specific value requested, but the value is incompatible with CSV
             (Some(tx_builder::RbfValue::Value(rbf)), Some(csv))
                 if !check_nsequence_rbf(rbf, csv) =>
@@ -1383,7 +2324,7 @@ impl Wallet {
                 if let Some(previous_fee) = params.bumping_fee {
                     if fee < previous_fee.absolute {
                         return Err(CreateTxError::FeeTooLow {
-                            required: Amount::from_sat(previous_fee.absolute),
+                            required: previous_fee.absolute,
                         });
                     }
                 }
@@ -1391,17 +2332,14 @@ impl Wallet {
             }
             FeePolicy::FeeRate(rate) => {
                 if let Some(previous_fee) = params.bumping_fee {
-                    let required_feerate = FeeRate::from_sat_per_kwu(
-                        previous_fee.rate.to_sat_per_kwu()
-                            + FeeRate::BROADCAST_MIN.to_sat_per_kwu(), // +1 sat/vb
-                    );
+                    let required_feerate = previous_fee.min_replacement_fee_rate();
                     if rate < required_feerate {
                         return Err(CreateTxError::FeeRateTooLow {
                             required: required_feerate,
                         });
                     }
                 }
-                (rate, 0)
+                (rate, Amount::ZERO)
             }
         };
 
@@ -1412,17 +2350,22 @@ impl Wallet {
             output: vec![],
         };
 
-        if params.manually_selected_only && params.utxos.is_empty() {
-            return Err(CreateTxError::NoUtxosSelected);
-        }
-
         let mut outgoing = Amount::ZERO;
         let mut received = Amount::ZERO;
 
-        let recipients = params.recipients.iter().map(|(r, v)| (r, *v));
+        let merged_recipients;
+        let recipients = if params.merge_duplicate_recipients {
+            merged_recipients = merge_duplicate_recipients(&params.recipients);
+            &merged_recipients
+        } else {
+            &params.recipients
+        };
+        let recipients = recipients.iter().map(|(r, v)| (r, *v));
 
         for (index, (script_pubkey, value)) in recipients.enumerate() {
-            if !params.allow_dust && value.is_dust(script_pubkey) && !script_pubkey.is_op_return() {
+            // OP_RETURN outputs have a dust limit of zero
+            let dust_limit = dust_value(script_pubkey, params.dust_relay_feerate());
+            if !params.allow_dust && Amount::from_sat(value) < dust_limit {
                 return Err(CreateTxError::OutputBelowDustLimit(index));
             }
 
@@ -1440,39 +2383,160 @@ impl Wallet {
             outgoing += Amount::from_sat(value);
         }
 
-        fee_amount += (fee_rate * tx.weight()).to_sat();
-
-        let (required_utxos, optional_utxos) =
-            self.preselect_utxos(&params, Some(current_height.to_consensus_u32()));
+        if let Some(window) = params.deduplicate_recipients {
+            // the transactions this one replaces are not duplicates
+            let replaced = params
+                .utxos
+                .iter()
+                .map(|utxo| utxo.utxo.outpoint())
+                .collect();
+            let txids = self.duplicate_payments(&tx.output, window, &replaced);
+            if !txids.is_empty() {
+                return Err(CreateTxError::PossibleDuplicatePayment {
+                    txids: txids.into_iter().collect(),
+                });
+            }
+        }
 
-        // get drain script
-        let drain_script = match params.drain_to {
-            Some(ref drain_recipient) => drain_recipient.clone(),
-            None => {
-                let change_keychain = KeychainKind::Internal;
-                let ((index, spk), index_changeset) = self
-                    .indexed_graph
-                    .index
-                    .next_unused_spk(&change_keychain)
-                    .expect("keychain must exist");
-                self.indexed_graph.index.mark_used(change_keychain, index);
-                self.stage.append(index_changeset.into());
-                spk
+        // the scripts of the silent payments depend on the inputs, until they are selected the
+        // outputs have scripts of the same size
+        #[cfg(feature = "silent-payments")]
+        let silent_payment_outputs = {
+            if !params.silent_payment_recipients.is_empty() && self.is_watch_only() {
+                return Err(silent_payments::SilentPaymentError::WatchOnly.into());
+            }
+            let first = tx.output.len();
+            for (index, (address, value)) in params.silent_payment_recipients.iter().enumerate() {
+                let script_pubkey =
+                    silent_payments::output_script(address.spend_key.x_only_public_key().0);
+                if !params.allow_dust
+                    && Amount::from_sat(*value)
+                        < dust_value(&script_pubkey, params.dust_relay_feerate())
+                {
+                    return Err(CreateTxError::OutputBelowDustLimit(first + index));
+                }
+                tx.output.push(TxOut {
+                    script_pubkey,
+                    value: Amount::from_sat(*value),
+                });
+                outgoing += Amount::from_sat(*value);
             }
+            first..tx.output.len()
         };
 
+        fee_amount += fee_rate * tx.weight();
+
+        let (required_utxos, optional_utxos) =
+            self.preselect_utxos(params, Some(current_height.to_consensus_u32()));
+        // the coin selection skips the immature coinbase outputs, but not the manually selected
+        // ones
+        for weighted_utxo in &required_utxos {
+            if let Utxo::Local(utxo) = &weighted_utxo.utxo {
+                if !utxo.is_mature(current_height.to_consensus_u32()) {
+                    return Err(CreateTxError::ImmatureCoinbase {
+                        outpoint: utxo.outpoint,
+                        mature_at_height: utxo.mature_at_height().unwrap_or(u32::MAX),
+                    });
+                }
+            }
+        }
+
+        // get drain script, and its derivation index if it's a change address of the wallet
+        let change_policy = params
+            .change_address_policy
+            .unwrap_or(self.change_address_policy);
+        // the static address of a descriptor without wildcard can't receive the change, it's
+        // only peeked to compute the weight of the change output
+        let static_change = self.has_static_change();
+        let (change_index, drain_script) =
+            match params.drain_to.as_ref().or(params.change_script.as_ref()) {
+                Some(drain_recipient) => (None, drain_recipient.clone()),
+                None if dry_run || static_change => {
+                    let (index, spk) = self.peek_change_spk(change_policy);
+                    (Some(index), spk)
+                }
+                None => {
+                    let (index, spk) = self.next_change_spk(change_policy);
+                    (Some(index), spk)
+                }
+            };
+
         let (required_utxos, optional_utxos) =
             coin_selection::filter_duplicates(required_utxos, optional_utxos);
 
-        let coin_selection = coin_selection.coin_select(
+        // remember how much weight each candidate adds once satisfied, we need it later to
+        // estimate the final size of the transaction
+        let satisfaction_weights: HashMap<OutPoint, usize> = required_utxos
+            .iter()
+            .chain(optional_utxos.iter())
+            .map(|wu| (wu.utxo.outpoint(), wu.satisfaction_weight))
+            .collect();
+
+        let required_outpoints = required_utxos
+            .iter()
+            .map(|wu| wu.utxo.outpoint())
+            .collect::<Vec<_>>();
+        let target_amount = outgoing + fee_amount;
+        let consolidation_candidates = params
+            .opportunistic_consolidation
+            .map(|limits| (limits, optional_utxos.clone()));
+        let mut coin_selection = coin_selection.coin_select(
             required_utxos,
             optional_utxos,
             fee_rate,
-            outgoing.to_sat() + fee_amount,
+            target_amount,
             &drain_script,
+            rng,
+        )?;
+        coin_selection::check_selection(
+            &coin_selection,
+            &required_outpoints,
+            satisfaction_weights.keys().copied(),
+            target_amount,
         )?;
+        if let Some((limits, candidates)) = consolidation_candidates {
+            coin_selection::consolidate_opportunistically(
+                &mut coin_selection,
+                candidates,
+                limits,
+                fee_rate,
+                &drain_script,
+                params.dust_relay_feerate(),
+                rng,
+            );
+        }
         fee_amount += coin_selection.fee_amount;
-        let excess = &coin_selection.excess;
+        // the coin selection algorithms use the default dust relay feerate, with another one the
+        // excess may or may not be enough for a change output
+        let redecided_excess = params.dust_relay_feerate.map(|dust_relay_rate| {
+            let remaining_amount = match coin_selection.excess {
+                NoChange {
+                    remaining_amount, ..
+                } => remaining_amount,
+                Change { amount, fee } => amount + fee,
+            };
+            coin_selection::decide_change_with_dust_relay_feerate(
+                remaining_amount,
+                fee_rate,
+                &drain_script,
+                dust_relay_rate,
+            )
+        });
+        let excess = redecided_excess.as_ref().unwrap_or(&coin_selection.excess);
+
+        // If the caller explicitly picked a spending path, make sure its timelocks have already
+        // expired for the inputs we selected, otherwise the transaction can't be finalized yet
+        if params.external_policy_path.is_some() || params.internal_policy_path.is_some() {
+            if let Some(height) = timelock_valid_at(
+                &requirements,
+                &coin_selection.selected,
+                current_height.to_consensus_u32(),
+            ) {
+                return Err(CreateTxError::TimelockNotMature {
+                    valid_at_height: height,
+                });
+            }
+        }
 
         tx.input = coin_selection
             .selected
@@ -1485,46 +2549,60 @@ impl Wallet {
             })
             .collect();
 
+        #[cfg(feature = "silent-payments")]
+        if !silent_payment_outputs.is_empty() {
+            let recipients = params
+                .silent_payment_recipients
+                .iter()
+                .map(|(address, _)| *address)
+                .collect::<Vec<_>>();
+            let scripts = self.silent_payment_scripts(&coin_selection.selected, &recipients)?;
+            for (txout, script_pubkey) in tx.output[silent_payment_outputs].iter_mut().zip(scripts)
+            {
+                txout.script_pubkey = script_pubkey;
+            }
+        }
+
         if tx.output.is_empty() {
-            // Uh oh, our transaction has no outputs.
-            // We allow this when:
-            // - We have a drain_to address and the utxos we must spend (this happens,
-            // for example, when we RBF)
-            // - We have a drain_to address and drain_wallet set
-            // Otherwise, we don't know who we should send the funds to, and how much
-            // we should send!
-            if params.drain_to.is_some() && (params.drain_wallet || !params.utxos.is_empty()) {
-                if let NoChange {
-                    dust_threshold,
-                    remaining_amount,
-                    change_fee,
-                } = excess
-                {
-                    return Err(CreateTxError::CoinSelection(Error::InsufficientFunds {
-                        needed: *dust_threshold,
-                        available: remaining_amount.saturating_sub(*change_fee),
-                    }));
-                }
-            } else {
-                return Err(CreateTxError::NoRecipients);
+            // Our transaction has no outputs, which `TxBuilder::validate` only allows when we have a
+            // drain_to address and the utxos we must spend (this happens, for example, when we
+            // RBF), or a drain_to address and drain_wallet set. The drained value must then be
+            // enough for an output.
+            if let NoChange {
+                dust_threshold,
+                remaining_amount,
+                change_fee,
+            } = excess
+            {
+                return Err(CreateTxError::CoinSelection(Error::InsufficientFunds {
+                    needed: *dust_threshold,
+                    available: remaining_amount
+                        .checked_sub(*change_fee)
+                        .unwrap_or(Amount::ZERO),
+                }));
             }
         }
 
+        let mut change_output = None;
         match excess {
             NoChange {
                 remaining_amount, ..
-            } => fee_amount += remaining_amount,
+            } => fee_amount += *remaining_amount,
+            Change { .. } if change_index.is_some() && static_change => {
+                return Err(CreateTxError::StaticChangeAddress);
+            }
             Change { amount, fee } => {
                 if self.is_mine(&drain_script) {
-                    received += Amount::from_sat(*amount);
+                    received += *amount;
                 }
-                fee_amount += fee;
+                fee_amount += *fee;
 
                 // create drain output
                 let drain_output = TxOut {
-                    value: Amount::from_sat(*amount),
+                    value: *amount,
                     script_pubkey: drain_script,
                 };
+                change_output = Some(drain_output.clone());
 
                 // TODO: We should pay attention when adding a new output: this might increase
                 // the length of the "number of vouts" parameter by 2 bytes, potentially making
@@ -1533,11 +2611,61 @@ impl Wallet {
             }
         };
 
-        // sort input/outputs according to the chosen algorithm
-        params.ordering.sort_tx(&mut tx);
+        let satisfaction_weight: usize = coin_selection
+            .selected
+            .iter()
+            .filter_map(|u| satisfaction_weights.get(&u.outpoint()))
+            .sum();
+        let satisfaction_weight = Weight::from_wu(satisfaction_weight as u64);
+
+        // BIP125 rules 3 and 4: the replacement must pay at least the absolute fee of the
+        // original transaction plus the minimum relay fee for its own size. The feerate check
+        // above doesn't guarantee it when the replacement is smaller than the original.
+        if let (Some(previous_fee), FeePolicy::FeeRate(_)) =
+            (params.bumping_fee, params.fee_policy.unwrap_or_default())
+        {
+            let weight = tx.weight() + satisfaction_weight;
+            let required = previous_fee.min_replacement_fee(weight);
+            if fee_amount < required {
+                return Err(CreateTxError::FeeTooLow { required });
+            }
+        }
 
-        let psbt = self.complete_transaction(tx, coin_selection.selected, params)?;
-        Ok(psbt)
+        // sort input/outputs according to the chosen algorithm, keeping the change output at
+        // its pinned position if requested
+        match (params.change_position, &change_output) {
+            (Some(position), Some(_)) => {
+                let drain_output = tx.output.pop().expect("drain output was just added");
+                if position > tx.output.len() {
+                    return Err(CreateTxError::ChangePositionOutOfRange {
+                        position,
+                        outputs: tx.output.len() + 1,
+                    });
+                }
+                params.ordering.sort_tx_with_aux_rand(&mut tx, rng);
+                tx.output.insert(position, drain_output);
+            }
+            _ => params.ordering.sort_tx_with_aux_rand(&mut tx, rng),
+        }
+
+        let change_output = change_output.map(|drain_output| tx_builder::ChangeOutput {
+            index: tx
+                .output
+                .iter()
+                .position(|txout| *txout == drain_output)
+                .expect("drain output must be in the transaction"),
+            value: drain_output.value,
+            derivation_index: change_index,
+        });
+
+        Ok(DraftTx {
+            tx,
+            selected: coin_selection.selected,
+            fee_amount,
+            change_output,
+            satisfaction_weight,
+            locktime,
+        })
     }
 
     /// Bump the fee of a transaction previously created with this wallet.
@@ -1651,6 +2779,7 @@ impl Wallet {
                                 is_spent: true,
                                 derivation_index,
                                 confirmation_time,
+                                is_coinbase: prev_tx.is_coinbase(),
                             }),
                             satisfaction_weight,
                         }
@@ -1680,7 +2809,7 @@ impl Wallet {
         if tx.output.len() > 1 {
             let mut change_index = None;
             for (index, txout) in tx.output.iter().enumerate() {
-                let change_keychain = KeychainKind::Internal;
+                let change_keychain = self.map_keychain(KeychainKind::Internal);
                 match txout_index.index_of_spk(&txout.script_pubkey) {
                     Some((keychain, _)) if *keychain == change_keychain => {
                         change_index = Some(index)
@@ -1704,7 +2833,7 @@ impl Wallet {
                 .collect(),
             utxos: original_utxos,
             bumping_fee: Some(tx_builder::PreviousFee {
-                absolute: fee.to_sat(),
+                absolute: fee,
                 rate: fee_rate,
             }),
             ..Default::default()
@@ -1717,121 +2846,890 @@ impl Wallet {
         })
     }
 
-    /// Sign a transaction with all the wallet's signers, in the order specified by every signer's
-    /// [`SignerOrdering`]. This function returns the `Result` type with an encapsulated `bool` that has the value true if the PSBT was finalized, or false otherwise.
+    /// Build a replacement of an unconfirmed transaction that sends its inputs back to the wallet,
+    /// cancelling the payment.
     ///
-    /// The [`SignOptions`] can be used to tweak the behavior of the software signers, and the way
-    /// the transaction is finalized at the end. Note that it can't be guaranteed that *every*
-    /// signers will follow the options, but the "software signers" (WIF keys and `xprv`) defined
-    /// in this library will.
+    /// The returned [`TxBuilder`] spends only the inputs of `txid` owned by the wallet and drains
+    /// them to a new address of the internal keychain at `fee_rate`. Like for
+    /// [`Wallet::build_fee_bump`], [`TxBuilder::finish`] checks that the replacement pays a
+    /// higher fee rate and absolute fee than the original transaction, as required by BIP125. The
+    /// replacement itself signals RBF.
+    ///
+    /// Returns the errors of [`Wallet::build_fee_bump`], and
+    /// [`BuildFeeBumpError::NoWalletInput`] if the transaction spends no output of the wallet.
     ///
     /// ## Example
     ///
-    /// ```
+    /// ```no_run
     /// # use std::str::FromStr;
     /// # use bitcoin::*;
     /// # use bdk_wallet::*;
-    /// # use bdk_wallet::wallet::ChangeSet;
-    /// # use bdk_wallet::wallet::error::CreateTxError;
     /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
     /// # let mut wallet = doctest_wallet!();
-    /// # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap().assume_checked();
+    /// # let txid = Txid::from_str("a3b3b6f27f3b2dc4c1ab5a6fa2e3c3e4f7b7fd7d5b9b4f1e0e8c3f2a1d0c9b8a").unwrap();
+    /// // the payment `txid` was a mistake, send the coins back to ourselves before it confirms
     /// let mut psbt = {
-    ///     let mut builder = wallet.build_tx();
-    ///     builder.add_recipient(to_address.script_pubkey(), Amount::from_sat(50_000));
+    ///     let builder = wallet.build_cancel(txid, FeeRate::from_sat_per_vb(10).expect("valid feerate"))?;
     ///     builder.finish()?
     /// };
-    /// let finalized = wallet.sign(&mut psbt, SignOptions::default())?;
-    /// assert!(finalized, "we should have signed all the inputs");
-    /// # Ok::<(),anyhow::Error>(())
-    pub fn sign(&self, psbt: &mut Psbt, sign_options: SignOptions) -> Result<bool, SignerError> {
-        // This adds all the PSBT metadata for the inputs, which will help us later figure out how
-        // to derive our keys
-        self.update_psbt_with_descriptor(psbt)
-            .map_err(SignerError::MiniscriptPsbt)?;
-
-        // If we aren't allowed to use `witness_utxo`, ensure that every input (except p2tr and finalized ones)
-        // has the `non_witness_utxo`
-        if !sign_options.trust_witness_utxo
-            && psbt
-                .inputs
-                .iter()
-                .filter(|i| i.final_script_witness.is_none() && i.final_script_sig.is_none())
-                .filter(|i| i.tap_internal_key.is_none() && i.tap_merkle_root.is_none())
-                .any(|i| i.non_witness_utxo.is_none())
-        {
-            return Err(SignerError::MissingNonWitnessUtxo);
-        }
-
-        // If the user hasn't explicitly opted-in, refuse to sign the transaction unless every input
-        // is using `SIGHASH_ALL` or `SIGHASH_DEFAULT` for taproot
-        if !sign_options.allow_all_sighashes
-            && !psbt.inputs.iter().all(|i| {
-                i.sighash_type.is_none()
-                    || i.sighash_type == Some(EcdsaSighashType::All.into())
-                    || i.sighash_type == Some(TapSighashType::All.into())
-                    || i.sighash_type == Some(TapSighashType::Default.into())
-            })
-        {
-            return Err(SignerError::NonStandardSighash);
-        }
-
-        for signer in self
-            .signers
-            .signers()
-            .iter()
-            .chain(self.change_signers.signers().iter())
-        {
-            signer.sign_transaction(psbt, &sign_options, &self.secp)?;
-        }
-
-        // attempt to finalize
-        if sign_options.try_finalize {
-            self.finalize_psbt(psbt, sign_options)
-        } else {
-            Ok(false)
+    /// let _ = wallet.sign(&mut psbt, SignOptions::default())?;
+    /// let cancel_tx = psbt.extract_tx();
+    /// // broadcast cancel_tx to replace the original transaction
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn build_cancel(
+        &mut self,
+        txid: Txid,
+        fee_rate: FeeRate,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, BuildFeeBumpError> {
+        let mut builder = self.build_fee_bump(txid)?;
+
+        // the foreign inputs can't be signed by the wallet, conflicting with one input is enough
+        builder
+            .params
+            .utxos
+            .retain(|u| matches!(u.utxo, Utxo::Local(_)));
+        if builder.params.utxos.is_empty() {
+            return Err(BuildFeeBumpError::NoWalletInput(txid));
         }
-    }
 
-    /// Return the spending policies for the wallet's descriptor
-    pub fn policies(&self, keychain: KeychainKind) -> Result<Option<Policy>, DescriptorError> {
-        let signers = match keychain {
-            KeychainKind::External => &self.signers,
-            KeychainKind::Internal => &self.change_signers,
+        let drain_script = {
+            let mut wallet = builder.wallet.borrow_mut();
+            let policy = wallet.change_address_policy;
+            wallet.next_change_spk(policy).1
         };
 
-        self.public_descriptor(keychain).extract_policy(
-            signers,
-            BuildSatisfaction::None,
-            &self.secp,
-        )
+        builder.params.recipients.clear();
+        builder.params.manually_selected_only = true;
+        builder.params.drain_to = Some(drain_script);
+        builder.params.rbf = Some(tx_builder::RbfValue::Default);
+        // not requested with the builder, so that an absolute fee set with it doesn't conflict
+        builder.params.fee_policy = Some(FeePolicy::FeeRate(fee_rate));
+        Ok(builder)
     }
 
-    /// Return the "public" version of the wallet's descriptor, meaning a new descriptor that has
-    /// the same structure but with every secret key removed
+    /// Build a transaction spending the wallet's outputs of an unconfirmed transaction so that
+    /// the two together reach `target_feerate` (*child pays for parent*, CPFP).
     ///
-    /// This can be used to build a watch-only version of a wallet
-    pub fn public_descriptor(&self, keychain: KeychainKind) -> &ExtendedDescriptor {
-        self.indexed_graph
-            .index
-            .keychains()
-            .find(|(k, _)| *k == &keychain)
-            .map(|(_, d)| d)
-            .expect("keychain must exist")
-    }
-
-    /// Finalize a PSBT, i.e., for each input determine if sufficient data is available to pass
-    /// validation and construct the respective `scriptSig` or `scriptWitness`. Please refer to
-    /// [BIP174](https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki#Input_Finalizer),
-    /// and [BIP371](https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki)
-    /// for further information.
+    /// The returned [`TxBuilder`] spends only the unspent outputs of `txid` owned by the wallet
+    /// and drains them to a new internal address. Its absolute fee is set so that the feerate of
+    /// the whole package (the unconfirmed ancestors of the parent, the parent and the child) is
+    /// `target_feerate`, meaning the child also pays for the part of their weight not covered by
+    /// their own fees. The sizes are counted in whole vbytes, as the mempool counts the size of
+    /// the ancestors of the child. If the package already pays more than `target_feerate` the
+    /// child pays `target_feerate` for itself.
     ///
-    /// Returns `true` if the PSBT could be finalized, and `false` otherwise.
+    /// Returns an error if the transaction is already confirmed, if its fee or the fee of one of
+    /// its unconfirmed ancestors can't be computed because some of their previous outputs are
+    /// unknown (see [`Wallet::insert_txout`]) or if the wallet doesn't own any of its unspent
+    /// outputs.
     ///
-    /// The [`SignOptions`] can be used to tweak the behavior of the finalizer.
-    pub fn finalize_psbt(
-        &self,
-        psbt: &mut Psbt,
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk_wallet::*;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let mut wallet = doctest_wallet!();
+    /// # let stuck_txid = Txid::from_str("a3b3b6f27f3b2dc4c1ab5a6fa2e3c3e4f7b7fd7d5b9b4f1e0e8c3f2a1d0c9b8a").unwrap();
+    /// // an incoming payment is taking too long to confirm, so we spend it with a higher fee
+    /// let mut psbt = {
+    ///     let builder = wallet.build_cpfp(stuck_txid, FeeRate::from_sat_per_vb(10).expect("valid feerate"))?;
+    ///     builder.finish()?
+    /// };
+    /// let _ = wallet.sign(&mut psbt, SignOptions::default())?;
+    /// let child_tx = psbt.extract_tx();
+    /// // broadcast child_tx
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn build_cpfp(
+        &mut self,
+        txid: Txid,
+        target_feerate: FeeRate,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, BuildCpfpError> {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+
+        let parent = graph
+            .get_tx(txid)
+            .ok_or(BuildCpfpError::TransactionNotFound(txid))?;
+
+        let pos = graph
+            .get_chain_position(&self.chain, chain_tip, txid)
+            .ok_or(BuildCpfpError::TransactionNotFound(txid))?;
+        if let ChainPosition::Confirmed(_) = pos {
+            return Err(BuildCpfpError::TransactionConfirmed(txid));
+        }
+
+        let package = self
+            .unconfirmed_package(&parent)
+            .map_err(BuildCpfpError::ParentFeeUnavailable)?;
+
+        let utxos = self.cpfp_utxos(&parent);
+        if utxos.is_empty() {
+            return Err(BuildCpfpError::NoSpendableOutput(txid));
+        }
+
+        let (_, drain_script) = self.next_change_spk(self.change_address_policy);
+
+        // the child spends all the selected outputs to a single drain output, so we can estimate
+        // its final weight before building it
+        let child = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: utxos
+                .iter()
+                .map(|u| bitcoin::TxIn {
+                    previous_output: u.utxo.outpoint(),
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: drain_script.clone(),
+            }],
+        };
+        let satisfaction_weight: usize = utxos.iter().map(|u| u.satisfaction_weight).sum();
+        // + 2 WU for the segwit marker and flag
+        let child_weight = child.weight() + Weight::from_wu(satisfaction_weight as u64 + 2);
+
+        let vsize = |weight: Weight| Weight::from_vb_unchecked(weight.to_vbytes_ceil());
+        let package_fee = package.iter().map(|(fee, _)| *fee).sum::<Amount>();
+        let package_vsize = package
+            .iter()
+            .map(|(_, weight)| vsize(*weight))
+            .sum::<Weight>()
+            + vsize(child_weight);
+        let child_fee = (target_feerate * package_vsize)
+            .checked_sub(package_fee)
+            .unwrap_or(Amount::ZERO)
+            .max(target_feerate * child_weight);
+
+        let params = TxParams {
+            utxos,
+            manually_selected_only: true,
+            drain_to: Some(drain_script),
+            fee_policy: Some(FeePolicy::FeeAmount(child_fee)),
+            ..Default::default()
+        };
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+        })
+    }
+
+    /// The unspent outputs of `parent` owned by the wallet, spent by [`Wallet::build_cpfp`]
+    fn cpfp_utxos(&self, parent: &Transaction) -> Vec<WeightedUtxo> {
+        let txid = parent.compute_txid();
+        (0..parent.output.len() as u32)
+            .filter_map(|vout| self.get_utxo(OutPoint::new(txid, vout)))
+            .map(|utxo| {
+                let satisfaction_weight = self
+                    .get_descriptor_for_keychain(utxo.keychain)
+                    .max_weight_to_satisfy()
+                    .unwrap()
+                    .to_wu() as usize;
+                WeightedUtxo {
+                    satisfaction_weight,
+                    utxo: Utxo::Local(utxo),
+                }
+            })
+            .collect()
+    }
+
+    /// Sweep all the spendable UTXOs of the wallet to `to`.
+    ///
+    /// This is the "send max" operation: the returned [`TxBuilder`] spends every UTXO of the
+    /// wallet, creates no change and the fee, computed from `fee_rate`, is subtracted from the
+    /// swept amount. Coinbase outputs that haven't reached maturity yet are left out.
+    ///
+    /// The transaction fails to build with [`coin_selection::Error::InsufficientFunds`] if
+    /// the swept amount minus the fee would be below the dust limit of `to`.
+    ///
+    /// See [`Wallet::build_sweep_utxos`] to sweep only some of the UTXOs and for the errors
+    /// returned.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk_wallet::*;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let mut wallet = doctest_wallet!();
+    /// # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap().assume_checked();
+    /// let psbt = {
+    ///     let builder = wallet.build_sweep(
+    ///         to_address.script_pubkey(),
+    ///         FeeRate::from_sat_per_vb(5).expect("valid feerate"),
+    ///     )?;
+    ///     builder.finish()?
+    /// };
+    /// assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn build_sweep(
+        &mut self,
+        to: ScriptBuf,
+        fee_rate: FeeRate,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, BuildSweepError> {
+        let current_height = self.chain.tip().height();
+        let outpoints = self
+            .list_unspent()
+            .filter(|utxo| utxo.is_mature(current_height))
+            .map(|utxo| utxo.outpoint)
+            .collect::<Vec<_>>();
+
+        self.build_sweep_utxos(&outpoints, to, fee_rate)
+    }
+
+    /// Sweep the UTXOs at `outpoints` to `to`.
+    ///
+    /// The returned [`TxBuilder`] spends exactly these UTXOs, creates no change and the fee,
+    /// computed from `fee_rate`, is subtracted from the swept amount. The transaction fails to
+    /// build with [`coin_selection::Error::InsufficientFunds`] if the swept amount minus the fee
+    /// would be below the dust limit of `to`.
+    ///
+    /// Returns an error if there's nothing to sweep, if one of the UTXOs is unknown or is a
+    /// coinbase output that isn't mature yet, or if all the UTXOs are unconfirmed: the feerate
+    /// of such a sweep would be dragged down by its unconfirmed parents.
+    pub fn build_sweep_utxos(
+        &mut self,
+        outpoints: &[OutPoint],
+        to: ScriptBuf,
+        fee_rate: FeeRate,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, BuildSweepError> {
+        let current_height = self.chain.tip().height();
+        let utxos = outpoints
+            .iter()
+            .map(|outpoint| {
+                let utxo = self
+                    .get_utxo(*outpoint)
+                    .ok_or(BuildSweepError::UnknownUtxo(*outpoint))?;
+                if !utxo.is_mature(current_height) {
+                    return Err(BuildSweepError::ImmatureCoinbase(*outpoint));
+                }
+                Ok(utxo)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if utxos.is_empty() {
+            return Err(BuildSweepError::NoUtxos);
+        }
+        if utxos
+            .iter()
+            .all(|utxo| !utxo.confirmation_time.is_confirmed())
+        {
+            return Err(BuildSweepError::UnconfirmedUtxos);
+        }
+
+        let utxos = utxos
+            .into_iter()
+            .map(|utxo| {
+                let satisfaction_weight = self
+                    .get_descriptor_for_keychain(utxo.keychain)
+                    .max_weight_to_satisfy()
+                    .unwrap()
+                    .to_wu() as usize;
+                WeightedUtxo {
+                    satisfaction_weight,
+                    utxo: Utxo::Local(utxo),
+                }
+            })
+            .collect();
+
+        let params = TxParams {
+            utxos,
+            manually_selected_only: true,
+            drain_to: Some(to),
+            fee_policy: Some(FeePolicy::FeeRate(fee_rate)),
+            ..Default::default()
+        };
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+        })
+    }
+
+    /// Sign a transaction with all the wallet's signers, in the order specified by every signer's
+    /// [`SignerOrdering`]. This function returns the `Result` type with an encapsulated `bool` that has the value true if the PSBT was finalized, or false otherwise.
+    ///
+    /// The [`SignOptions`] can be used to tweak the behavior of the software signers, and the way
+    /// the transaction is finalized at the end. Note that it can't be guaranteed that *every*
+    /// signers will follow the options, but the "software signers" (WIF keys and `xprv`) defined
+    /// in this library will.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk_wallet::*;
+    /// # use bdk_wallet::wallet::ChangeSet;
+    /// # use bdk_wallet::wallet::error::CreateTxError;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let mut wallet = doctest_wallet!();
+    /// # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap().assume_checked();
+    /// let mut psbt = {
+    ///     let mut builder = wallet.build_tx();
+    ///     builder.add_recipient(to_address.script_pubkey(), Amount::from_sat(50_000));
+    ///     builder.finish()?
+    /// };
+    /// let finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+    /// assert!(finalized, "we should have signed all the inputs");
+    /// # Ok::<(),anyhow::Error>(())
+    pub fn sign(&self, psbt: &mut Psbt, sign_options: SignOptions) -> Result<bool, SignerError> {
+        let details = self.sign_with_details(psbt, sign_options)?;
+        match details.signer_errors.into_iter().next() {
+            Some((_, err)) if !details.finalized => Err(err),
+            _ => Ok(details.finalized),
+        }
+    }
+
+    /// Sign a PSBT v2 like [`Wallet::sign`].
+    ///
+    /// The inputs are signed over the transaction of [`PsbtV2::unsigned_tx`], so the signatures
+    /// are the same as those of the equivalent PSBT v0. The fields which only exist in a PSBT v2
+    /// are left untouched.
+    ///
+    /// [`PsbtV2::unsigned_tx`]: crate::psbt::v2::PsbtV2::unsigned_tx
+    #[cfg(feature = "psbt-v2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "psbt-v2")))]
+    pub fn sign_psbt_v2(
+        &self,
+        psbt: &mut crate::psbt::v2::PsbtV2,
+        sign_options: SignOptions,
+    ) -> Result<bool, SignerError> {
+        let mut psbt_v0 = psbt.as_v0().map_err(SignerError::PsbtV2)?;
+        let finalized = self.sign(&mut psbt_v0, sign_options);
+        // the signers may have signed some inputs before failing
+        psbt.update_from_v0(psbt_v0);
+        finalized
+    }
+
+    /// Sign a transaction like [`Wallet::sign`], also reporting which inputs were signed.
+    ///
+    /// Signers returning a non-fatal error (see [`SignerError::is_fatal`]) don't stop the
+    /// remaining signers from running: their errors are collected in
+    /// [`SignDetails::signer_errors`]. [`Wallet::sign`] only reports the first of them if the
+    /// PSBT couldn't be finalized.
+    ///
+    /// This is mostly useful together with [`SignOptions::inputs`] and
+    /// [`SignOptions::keychains`], which restrict the inputs the signers are allowed to touch.
+    /// Only the selected inputs are checked against [`SignOptions::trust_witness_utxo`] and
+    /// [`SignOptions::allow_all_sighashes`]. The inputs added with
+    /// [`TxBuilder::add_utxo_with_witness_provider`] are never selected, they are finalized by
+    /// their provider.
+    ///
+    /// The PSBT is left untouched if its fee exceeds the tightest of the limits of
+    /// [`Wallet::set_fee_limits`] and of [`SignOptions::max_fee`] and
+    /// [`SignOptions::max_fee_rate`]. The fee rate is computed over the weight of the finalized
+    /// transaction, or over the weight of the unsigned transaction if the PSBT isn't finalized:
+    /// only [`SignOptions::max_fee`] is meaningful for the PSBTs signed by several parties.
+    pub fn sign_with_details(
+        &self,
+        psbt: &mut Psbt,
+        sign_options: SignOptions,
+    ) -> Result<SignDetails, SignerError> {
+        let limits = self.fee_limits.tightest(FeeLimits {
+            max_fee: sign_options.max_fee,
+            max_fee_rate: sign_options.max_fee_rate,
+        });
+        if limits.is_unlimited() {
+            return self.sign_psbt(psbt, sign_options);
+        }
+        let fee = match fee_limits::psbt_prevout_fee(psbt) {
+            Ok(fee) => fee,
+            Err(_) if sign_options.allow_unknown_prevouts => {
+                return self.sign_psbt(psbt, sign_options)
+            }
+            Err(inputs) => return Err(SignerError::UnknownPrevouts(inputs)),
+        };
+        // the fee rate is only known once the inputs are signed
+        limits.check(fee, Weight::MAX)?;
+        let mut signed = psbt.clone();
+        let details = self.sign_psbt(&mut signed, sign_options)?;
+        let fee = fee_limits::psbt_prevout_fee(&signed).unwrap_or(fee);
+        let weight = if details.finalized {
+            signed.clone().extract_tx_unchecked_fee_rate().weight()
+        } else {
+            signed.unsigned_tx.weight()
+        };
+        limits.check(fee, weight)?;
+        *psbt = signed;
+        Ok(details)
+    }
+
+    /// Sign `psbt` like [`Wallet::sign_with_details`], without checking its fee
+    pub(crate) fn sign_psbt(
+        &self,
+        psbt: &mut Psbt,
+        sign_options: SignOptions,
+    ) -> Result<SignDetails, SignerError> {
+        if self.is_watch_only() {
+            return Err(SignerError::WatchOnly);
+        }
+
+        let selected_inputs = (0..psbt.inputs.len())
+            .filter(|i| {
+                sign_options
+                    .inputs
+                    .as_ref()
+                    .map_or(true, |inputs| inputs.contains(i))
+            })
+            .filter(|i| match &sign_options.keychains {
+                Some(keychains) => psbt
+                    .get_utxo_for(*i)
+                    .and_then(|txout| self.indexed_graph.index.index_of_spk(&txout.script_pubkey))
+                    .map_or(false, |(keychain, _)| {
+                        keychains.iter().any(|k| self.map_keychain(*k) == *keychain)
+                    }),
+                None => true,
+            })
+            // the inputs with a witness provider are left to their provider
+            .filter(|i| {
+                psbt.unsigned_tx.input.get(*i).map_or(true, |txin| {
+                    !self.witness_providers.contains_key(&txin.previous_output)
+                })
+            })
+            .collect::<BTreeSet<_>>();
+        let is_filtered = sign_options.inputs.is_some()
+            || sign_options.keychains.is_some()
+            || selected_inputs.len() < psbt.inputs.len();
+
+        // This adds all the PSBT metadata for the inputs, which will help us later figure out how
+        // to derive our keys
+        self.update_psbt_with_descriptor(psbt, is_filtered.then_some(&selected_inputs))
+            .map_err(SignerError::MiniscriptPsbt)?;
+
+        let is_selected = |i: &(usize, &psbt::Input)| selected_inputs.contains(&i.0);
+
+        // If we aren't allowed to use `witness_utxo`, ensure that every input (except p2tr and finalized ones)
+        // has the `non_witness_utxo`
+        if !sign_options.trust_witness_utxo
+            && psbt
+                .inputs
+                .iter()
+                .enumerate()
+                .filter(is_selected)
+                .map(|(_, i)| i)
+                .filter(|i| i.final_script_witness.is_none() && i.final_script_sig.is_none())
+                .filter(|i| i.tap_internal_key.is_none() && i.tap_merkle_root.is_none())
+                // the witness versions after taproot are left untouched
+                .filter(|i| {
+                    i.witness_utxo.as_ref().map_or(true, |txout| {
+                        !is_future_witness_version(&txout.script_pubkey)
+                    })
+                })
+                .any(|i| i.non_witness_utxo.is_none())
+        {
+            return Err(SignerError::MissingNonWitnessUtxo);
+        }
+
+        // If the user hasn't explicitly opted-in, refuse to sign the transaction unless every input
+        // is using `SIGHASH_ALL` or `SIGHASH_DEFAULT` for taproot
+        if !sign_options.allow_all_sighashes {
+            let non_standard =
+                psbt.inputs
+                    .iter()
+                    .enumerate()
+                    .filter(is_selected)
+                    .find_map(|(input_index, i)| {
+                        i.sighash_type
+                            .filter(|sighash| {
+                                *sighash != EcdsaSighashType::All.into()
+                                    && *sighash != TapSighashType::All.into()
+                                    && *sighash != TapSighashType::Default.into()
+                            })
+                            .map(|sighash| (input_index, sighash))
+                    });
+            if let Some((input_index, sighash)) = non_standard {
+                return Err(SignerError::NonStandardSighash {
+                    input_index,
+                    sighash,
+                });
+            }
+        }
+
+        let signatures_before = psbt.inputs.iter().map(signatures_count).collect::<Vec<_>>();
+        let signature_sources_before = psbt
+            .inputs
+            .iter()
+            .map(signature_key_sources)
+            .collect::<Vec<_>>();
+        let signed_before = psbt
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| {
+                signatures_count(input) > 0
+                    || input.final_script_sig.is_some()
+                    || input.final_script_witness.is_some()
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        // the keychain of each input, the inputs of the extra keychains are only signed by the
+        // signers of their keychain, whose script type may differ from the one of the others
+        let input_keychains = (0..psbt.inputs.len())
+            .map(|i| {
+                psbt.get_utxo_for(i)
+                    .and_then(|txout| self.indexed_graph.index.index_of_spk(&txout.script_pubkey))
+                    .map(|(keychain, _)| *keychain)
+            })
+            .collect::<Vec<_>>();
+        let is_extra = |keychain: &KeychainKind| matches!(keychain, KeychainKind::Extra(_));
+        let has_extra_inputs = input_keychains.iter().flatten().any(is_extra);
+        // the signers only need to know about the final selection of inputs
+        let signer_options = |signer_keychain: KeychainKind| {
+            let restricted = has_extra_inputs || is_extra(&signer_keychain);
+            let inputs = selected_inputs
+                .iter()
+                .copied()
+                .filter(|&i| match input_keychains[i] {
+                    Some(keychain) if is_extra(&keychain) || is_extra(&signer_keychain) => {
+                        keychain == signer_keychain
+                    }
+                    _ => true,
+                })
+                .collect();
+            SignOptions {
+                inputs: (is_filtered || restricted).then_some(inputs),
+                ..sign_options.clone()
+            }
+        };
+        // run the signers of all the keychains by increasing ordering, so that every signer sees
+        // the signatures added by the previous ones
+        let mut signers = [
+            (KeychainKind::External, &self.signers),
+            (KeychainKind::Internal, &self.change_signers),
+        ]
+        .into_iter()
+        .chain(self.extra_signers.iter().map(|(k, s)| (*k, s)))
+        .flat_map(|(keychain, signers)| {
+            signers
+                .signers_with_ordering()
+                .map(move |(ordering, signer)| (ordering, keychain, signer))
+        })
+        .collect::<Vec<_>>();
+        signers.sort_by_key(|(ordering, _, _)| *ordering);
+
+        let mut signer_errors = Vec::new();
+        for (_, keychain, signer) in signers {
+            match signer.sign_transaction(psbt, &signer_options(keychain), &self.secp) {
+                Ok(()) => {}
+                Err(err) if !err.is_fatal() => signer_errors.push((signer.id(&self.secp), err)),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let signed_inputs = psbt
+            .inputs
+            .iter()
+            .map(signatures_count)
+            .zip(signatures_before)
+            .enumerate()
+            .filter(|(_, (after, before))| after > before)
+            .map(|(i, _)| i)
+            .collect::<BTreeSet<_>>();
+        // the inputs with a new signature by a key which none of the descriptors derives
+        let arbitrary_derivation_inputs = signed_inputs
+            .iter()
+            .copied()
+            .filter(|_| sign_options.sign_arbitrary_derivations)
+            .filter(|&i| {
+                signature_key_sources(&psbt.inputs[i])
+                    .difference(&signature_sources_before[i])
+                    .any(|key_source| {
+                        ![&self.signers, &self.change_signers]
+                            .into_iter()
+                            .chain(self.extra_signers.values())
+                            .any(|signers| signers.matches_key_source(key_source, &self.secp))
+                    })
+            })
+            .collect();
+
+        if let (true, Some(adjustment)) = (sign_options.try_finalize, &sign_options.fee_adjustment)
+        {
+            self.adjust_fee(psbt, adjustment, &sign_options, &signed_before)?;
+        }
+
+        // attempt to finalize
+        let finalized = if sign_options.try_finalize {
+            self.finalize_psbt(psbt, sign_options)?
+        } else {
+            false
+        };
+
+        Ok(SignDetails {
+            finalized,
+            signed_inputs,
+            arbitrary_derivation_inputs,
+            signer_errors,
+        })
+    }
+
+    /// Return the spending policies for the wallet's descriptor
+    ///
+    /// The `contribution` of each node tells whether the wallet's keys can satisfy it, and its
+    /// `satisfaction` whether the wallet can satisfy it on its own right now: the wallet can
+    /// sign for its keys and the timelocks have expired at the current tip. Relative timelocks
+    /// are checked against the most recently confirmed UTXO of `keychain`, they are never
+    /// satisfied if none is confirmed.
+    ///
+    /// The ids of the nodes only depend on the descriptor, they can be used in
+    /// [`TxBuilder::policy_path`]. Taproot descriptors with a tree have a root threshold of one
+    /// with the key spend first, followed by every leaf.
+    ///
+    /// [`TxBuilder::policy_path`]: crate::wallet::tx_builder::TxBuilder::policy_path
+    pub fn policies(&self, keychain: KeychainKind) -> Result<Option<Policy>, DescriptorError> {
+        let keychain = self.map_keychain(keychain);
+        let input_max_height = self
+            .list_unspent()
+            .filter(|utxo| utxo.keychain == keychain)
+            .filter_map(|utxo| match utxo.confirmation_time {
+                ConfirmationTime::Confirmed { height, .. } => Some(height),
+                ConfirmationTime::Unconfirmed { .. } => None,
+            })
+            .max();
+        self.extract_policy(
+            keychain,
+            BuildSatisfaction::Timelocks {
+                current_height: self.chain.tip().height(),
+                input_max_height,
+            },
+        )
+    }
+
+    /// The policy of the descriptor of `keychain`, with the satisfaction built from `build_sat`
+    fn extract_policy(
+        &self,
+        keychain: KeychainKind,
+        build_sat: BuildSatisfaction,
+    ) -> Result<Option<Policy>, DescriptorError> {
+        let keychain = self.map_keychain(keychain);
+        let signers = self.get_signers(keychain);
+
+        self.public_descriptor(keychain)
+            .extract_policy(&signers, build_sat, &self.secp)
+    }
+
+    /// Return the "public" version of the wallet's descriptor, meaning a new descriptor that has
+    /// the same structure but with every secret key removed
+    ///
+    /// This can be used to build a watch-only version of a wallet
+    pub fn public_descriptor(&self, keychain: KeychainKind) -> &ExtendedDescriptor {
+        let keychain = self.map_keychain(keychain);
+        self.indexed_graph
+            .index
+            .keychains()
+            .find(|(k, _)| *k == &keychain)
+            .map(|(_, d)| d)
+            .expect("keychain must exist")
+    }
+
+    /// Return the [`public_descriptor`] of `keychain` as a string, followed by its `#checksum`
+    ///
+    /// The string never contains secret keys, so it can be handed over to watch-only software
+    /// such as Bitcoin Core's `importdescriptors`.
+    ///
+    /// [`public_descriptor`]: Self::public_descriptor
+    pub fn public_descriptor_string(&self, keychain: KeychainKind) -> String {
+        // the `Display` implementation of descriptors appends the checksum
+        self.public_descriptor(keychain).to_string()
+    }
+
+    /// Return the secret keys of the signers of `keychain`, indexed by their public key
+    ///
+    /// Along with the [`public_descriptor`] this gives back the secret descriptor, for example to
+    /// move the keys to another signer. The map is empty for watch-only keychains.
+    ///
+    /// [`public_descriptor`]: Self::public_descriptor
+    pub fn keymap(&self, keychain: KeychainKind) -> KeyMap {
+        self.get_signers(keychain).as_key_map(&self.secp)
+    }
+
+    /// Combine the PSBTs signed by different cosigners into `base`
+    ///
+    /// All the PSBTs must spend the same unsigned transaction as `base`. The signatures they carry
+    /// (`partial_sigs`, `tap_key_sig` and `tap_script_sigs`) are checked against their public key
+    /// and the transaction sighash before being added to `base`: if any of them is invalid an
+    /// error is returned and `base` is left untouched.
+    ///
+    /// The returned [`CombineReport`] tells, for every input spending one of the wallet's
+    /// descriptors, how many of the signatures required by the descriptor policy are now present.
+    pub fn combine_psbts(
+        &self,
+        base: &mut Psbt,
+        others: impl IntoIterator<Item = Psbt>,
+    ) -> Result<CombineReport, CombineError> {
+        let txid = base.unsigned_tx.compute_txid();
+        let mut combined = base.clone();
+
+        for other in others {
+            let found = other.unsigned_tx.compute_txid();
+            if found != txid {
+                return Err(CombineError::DifferentTransaction {
+                    expected: txid,
+                    found,
+                });
+            }
+
+            for (input_index, other_input) in other.inputs.into_iter().enumerate() {
+                if input_index >= combined.inputs.len() {
+                    break;
+                }
+
+                for (pubkey, sig) in other_input.partial_sigs {
+                    if combined.inputs[input_index]
+                        .partial_sigs
+                        .contains_key(&pubkey)
+                    {
+                        continue;
+                    }
+                    let msg = psbt_sighash_msg(&mut combined, input_index, sig.sighash_type, None)?;
+                    if self
+                        .secp
+                        .verify_ecdsa(&msg, &sig.signature, &pubkey.inner)
+                        .is_err()
+                    {
+                        return Err(CombineError::InvalidSignature { input_index });
+                    }
+                    combined.inputs[input_index]
+                        .partial_sigs
+                        .insert(pubkey, sig);
+                }
+
+                if let (None, Some(sig)) = (
+                    &combined.inputs[input_index].tap_key_sig,
+                    other_input.tap_key_sig,
+                ) {
+                    // check the key spend signature against the output key of the spent
+                    // script_pubkey, so that we don't have to trust the other PSBT metadata
+                    let output_key = combined
+                        .get_utxo_for(input_index)
+                        .filter(|txout| txout.script_pubkey.is_p2tr())
+                        .and_then(|txout| {
+                            XOnlyPublicKey::from_slice(&txout.script_pubkey.as_bytes()[2..]).ok()
+                        })
+                        .ok_or(CombineError::InvalidSignature { input_index })?;
+                    let msg = psbt_sighash_msg(&mut combined, input_index, sig.sighash_type, None)?;
+                    if self
+                        .secp
+                        .verify_schnorr(&sig.signature, &msg, &output_key)
+                        .is_err()
+                    {
+                        return Err(CombineError::InvalidSignature { input_index });
+                    }
+                    combined.inputs[input_index].tap_key_sig = Some(sig);
+                }
+
+                for ((pubkey, leaf_hash), sig) in other_input.tap_script_sigs {
+                    if combined.inputs[input_index]
+                        .tap_script_sigs
+                        .contains_key(&(pubkey, leaf_hash))
+                    {
+                        continue;
+                    }
+                    let msg = psbt_sighash_msg(
+                        &mut combined,
+                        input_index,
+                        sig.sighash_type,
+                        Some(leaf_hash),
+                    )?;
+                    if self
+                        .secp
+                        .verify_schnorr(&sig.signature, &msg, &pubkey)
+                        .is_err()
+                    {
+                        return Err(CombineError::InvalidSignature { input_index });
+                    }
+                    combined.inputs[input_index]
+                        .tap_script_sigs
+                        .insert((pubkey, leaf_hash), sig);
+                }
+            }
+        }
+
+        let inputs = (0..combined.inputs.len())
+            .map(|input_index| self.input_signatures(&combined, input_index))
+            .collect::<Result<_, _>>()?;
+        *base = combined;
+
+        Ok(CombineReport { inputs })
+    }
+
+    /// Count the signatures present in a PSBT input against the ones required by the policy of
+    /// the wallet's descriptor it spends
+    fn input_signatures(
+        &self,
+        psbt: &Psbt,
+        input_index: usize,
+    ) -> Result<Option<InputSignatures>, CombineError> {
+        let keychain = match psbt
+            .get_utxo_for(input_index)
+            .and_then(|txout| self.indexed_graph.index.index_of_spk(&txout.script_pubkey))
+        {
+            Some((keychain, _)) => *keychain,
+            None => return Ok(None),
+        };
+        let signers = self.get_signers(keychain);
+
+        // the policy looks for signatures in every input of the psbt: only give it the one we
+        // are interested in
+        let single_input = Psbt {
+            inputs: vec![psbt.inputs[input_index].clone()],
+            outputs: vec![],
+            ..Psbt::from_unsigned_tx(Transaction {
+                input: vec![],
+                output: vec![],
+                ..psbt.unsigned_tx.clone()
+            })
+            .expect("the transaction has no scripts")
+        };
+        let policy = self
+            .public_descriptor(keychain)
+            .extract_policy(&signers, BuildSatisfaction::Psbt(&single_input), &self.secp)
+            .map_err(CombineError::Policy)?;
+
+        Ok(policy.map(|policy| match policy.satisfaction {
+            Satisfaction::Partial { m, items, .. }
+            | Satisfaction::PartialComplete { m, items, .. } => InputSignatures {
+                present: items.len(),
+                required: m,
+            },
+            Satisfaction::Complete { .. } => InputSignatures {
+                present: 1,
+                required: 1,
+            },
+            Satisfaction::None => InputSignatures {
+                present: 0,
+                required: 1,
+            },
+        }))
+    }
+
+    /// Finalize a PSBT, i.e., for each input determine if sufficient data is available to pass
+    /// validation and construct the respective `scriptSig` or `scriptWitness`. Please refer to
+    /// [BIP174](https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki#Input_Finalizer),
+    /// and [BIP371](https://github.com/bitcoin/bips/blob/master/bip-0371.mediawiki)
+    /// for further information.
+    ///
+    /// Returns `true` if the PSBT could be finalized, and `false` otherwise.
+    ///
+    /// The inputs added with [`TxBuilder::add_utxo_with_witness_provider`] are finalized with the
+    /// witness of their provider, which needs the previous outputs of all the inputs. The errors
+    /// of the providers are returned as is.
+    ///
+    /// The [`SignOptions`] can be used to tweak the behavior of the finalizer.
+    pub fn finalize_psbt(
+        &self,
+        psbt: &mut Psbt,
         sign_options: SignOptions,
     ) -> Result<bool, SignerError> {
         let chain_tip = self.chain.tip().block_id();
@@ -1847,6 +3745,30 @@ impl Wallet {
             if psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some() {
                 continue;
             }
+            if let Some(provider) = self.witness_providers.get(&input.previous_output) {
+                let prevouts = (0..tx.input.len())
+                    .map(|i| psbt.get_utxo_for(i))
+                    .collect::<Option<Vec<_>>>();
+                match prevouts {
+                    Some(prevouts) => {
+                        let witness = provider.provide_witness(&WitnessContext {
+                            tx,
+                            input_index: n,
+                            prevouts: &prevouts,
+                            leaf_hash: provider.leaf_hash(),
+                        })?;
+                        let original = mem::take(&mut psbt.inputs[n]);
+                        let psbt_input = &mut psbt.inputs[n];
+                        psbt_input.non_witness_utxo = original.non_witness_utxo;
+                        psbt_input.witness_utxo = original.witness_utxo;
+                        psbt_input.proprietary = original.proprietary;
+                        psbt_input.unknown = original.unknown;
+                        psbt_input.final_script_witness = Some(witness);
+                    }
+                    None => finished = false,
+                }
+                continue;
+            }
             let confirmation_height = self
                 .indexed_graph
                 .graph()
@@ -1886,12 +3808,21 @@ impl Wallet {
                         ),
                     ) {
                         Ok(_) => {
-                            // Set the UTXO fields, final script_sig and witness
-                            // and clear everything else.
+                            // Set the UTXO fields, final script_sig and witness, keep the
+                            // proprietary and unknown fields and clear everything else.
                             let original = mem::take(&mut psbt.inputs[n]);
                             let psbt_input = &mut psbt.inputs[n];
                             psbt_input.non_witness_utxo = original.non_witness_utxo;
                             psbt_input.witness_utxo = original.witness_utxo;
+                            // the key spend signature commits to the annex, which goes last
+                            let annex = original.proprietary.get(&taproot_annex_key());
+                            if let (Some(annex), Some(_)) = (annex, original.tap_key_sig) {
+                                if tmp_input.witness.len() == 1 {
+                                    tmp_input.witness.push(annex);
+                                }
+                            }
+                            psbt_input.proprietary = original.proprietary;
+                            psbt_input.unknown = original.unknown;
                             if !tmp_input.script_sig.is_empty() {
                                 psbt_input.final_script_sig = Some(tmp_input.script_sig);
                             }
@@ -1930,11 +3861,13 @@ impl Wallet {
     /// The derivation index of this wallet. It will return `None` if it has not derived any addresses.
     /// Otherwise, it will return the index of the highest address it has derived.
     pub fn derivation_index(&self, keychain: KeychainKind) -> Option<u32> {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph.index.last_revealed_index(&keychain)
     }
 
     /// The index of the next address that you would get if you were to ask the wallet for a new address
     pub fn next_derivation_index(&self, keychain: KeychainKind) -> u32 {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph
             .index
             .next_index(&keychain)
@@ -1945,14 +3878,27 @@ impl Wallet {
     /// Informs the wallet that you no longer intend to broadcast a tx that was built from it.
     ///
     /// This frees up the change address used when creating the tx for use in future transactions.
-    // TODO: Make this free up reserved utxos when that's implemented
+    ///
+    /// Building a transaction doesn't reserve its inputs unless it's built with
+    /// [`TxBuilder::reserve_inputs`], they can be selected again right away as long as the
+    /// transaction is not applied to the wallet. Once it is, for instance after it was broadcast,
+    /// its inputs stay spent until it's replaced: see [`Wallet::build_cancel`]. The reserved
+    /// inputs of the transaction are released.
+    ///
+    /// The witness providers of its inputs are dropped, see
+    /// [`TxBuilder::add_utxo_with_witness_provider`].
     pub fn cancel_tx(&mut self, tx: &Transaction) {
-        let txout_index = &mut self.indexed_graph.index;
+        self.release_reservation(tx.compute_txid());
+        for txin in &tx.input {
+            self.witness_providers.remove(&txin.previous_output);
+        }
         for txout in &tx.output {
-            if let Some((keychain, index)) = txout_index.index_of_spk(&txout.script_pubkey) {
+            if let Some(&(keychain, index)) =
+                self.indexed_graph.index.index_of_spk(&txout.script_pubkey)
+            {
                 // NOTE: unmark_used will **not** make something unused if it has actually been used
                 // by a tx in the tracker. It only removes the superficial marking.
-                txout_index.unmark_used(*keychain, *index);
+                self.unmark_used(keychain, index);
             }
         }
     }
@@ -2024,10 +3970,6 @@ impl Wallet {
             .iter()
             .map(|u| -> bool {
                 let txid = u.0.outpoint.txid;
-                let tx = match self.indexed_graph.graph().get_tx(txid) {
-                    Some(tx) => tx,
-                    None => return false,
-                };
                 let confirmation_time: ConfirmationTime = match self
                     .indexed_graph
                     .graph()
@@ -2042,21 +3984,9 @@ impl Wallet {
                 if must_only_use_confirmed_tx && !confirmation_time.is_confirmed() {
                     return false;
                 }
-                if tx.is_coinbase() {
-                    debug_assert!(
-                        confirmation_time.is_confirmed(),
-                        "coinbase must always be confirmed"
-                    );
-                    if let Some(current_height) = current_height {
-                        match confirmation_time {
-                            ConfirmationTime::Confirmed { height, .. } => {
-                                // https://github.com/bitcoin/bitcoin/blob/c5e67be03bb06a5d7885c55db1f016fbf2333fe3/src/validation.cpp#L373-L375
-                                spendable &=
-                                    (current_height.saturating_sub(height)) >= COINBASE_MATURITY;
-                            }
-                            ConfirmationTime::Unconfirmed { .. } => spendable = false,
-                        }
-                    }
+                if let Some(current_height) = current_height {
+                    // https://github.com/bitcoin/bitcoin/blob/c5e67be03bb06a5d7885c55db1f016fbf2333fe3/src/validation.cpp#L373-L375
+                    spendable &= u.0.is_mature(current_height);
                 }
                 spendable
             })
@@ -2066,6 +3996,7 @@ impl Wallet {
         may_spend.retain(|u| {
             let retain = change_policy.is_satisfied_by(&u.0)
                 && !unspendable.contains(&u.0.outpoint)
+                && !self.reservations.contains_key(&u.0.outpoint)
                 && satisfies_confirmed[i];
             i += 1;
             retain
@@ -2086,6 +4017,50 @@ impl Wallet {
         (must_spend, may_spend)
     }
 
+    /// The scripts of the outputs paying the silent payment `recipients`, in a transaction
+    /// spending `selected`
+    #[cfg(feature = "silent-payments")]
+    fn silent_payment_scripts(
+        &self,
+        selected: &[Utxo],
+        recipients: &[silent_payments::SilentPaymentAddress],
+    ) -> Result<Vec<ScriptBuf>, silent_payments::SilentPaymentError> {
+        use silent_payments::SilentPaymentError;
+
+        let mut input_keys = Vec::new();
+        for utxo in selected {
+            let outpoint = utxo.outpoint();
+            let script_pubkey = &utxo.txout().script_pubkey;
+            if silent_payments::is_unsupported_script(script_pubkey) {
+                return Err(SilentPaymentError::UnsupportedInput(outpoint));
+            }
+            match utxo {
+                Utxo::Local(local) => {
+                    let keymap = self.get_signers(local.keychain).as_key_map(&self.secp);
+                    let key = silent_payments::input_secret_key(
+                        self.get_descriptor_for_keychain(local.keychain),
+                        local.derivation_index,
+                        &keymap,
+                        outpoint,
+                        &self.secp,
+                    )?;
+                    input_keys.extend(key);
+                }
+                Utxo::Foreign { .. } if silent_payments::is_eligible_script(script_pubkey) => {
+                    return Err(SilentPaymentError::MissingSecretKey(outpoint))
+                }
+                Utxo::Foreign { .. } => {}
+            }
+        }
+        let outpoints = selected.iter().map(Utxo::outpoint).collect::<Vec<_>>();
+        Ok(
+            silent_payments::derive_output_keys(&outpoints, &input_keys, recipients, &self.secp)?
+                .into_iter()
+                .map(silent_payments::output_script)
+                .collect(),
+        )
+    }
+
     fn complete_transaction(
         &self,
         tx: Transaction,
@@ -2113,6 +4088,8 @@ impl Wallet {
             }
         }
 
+        psbt.proprietary.extend(params.proprietary);
+
         let mut lookup_output = selected
             .into_iter()
             .map(|utxo| (utxo.outpoint(), utxo))
@@ -2144,12 +4121,16 @@ impl Wallet {
                     psbt_input: foreign_psbt_input,
                     ..
                 } => {
-                    let is_taproot = foreign_psbt_input
+                    // the witness versions after taproot are passed through as they are
+                    let is_taproot_or_later = foreign_psbt_input
                         .witness_utxo
                         .as_ref()
-                        .map(|txout| txout.script_pubkey.is_p2tr())
+                        .map(|txout| {
+                            txout.script_pubkey.is_p2tr()
+                                || is_future_witness_version(&txout.script_pubkey)
+                        })
                         .unwrap_or(false);
-                    if !is_taproot
+                    if !is_taproot_or_later
                         && !params.only_witness_utxo
                         && foreign_psbt_input.non_witness_utxo.is_none()
                     {
@@ -2160,7 +4141,7 @@ impl Wallet {
             }
         }
 
-        self.update_psbt_with_descriptor(&mut psbt)?;
+        self.update_psbt_with_descriptor(&mut psbt, None)?;
 
         Ok(psbt)
     }
@@ -2195,21 +4176,34 @@ impl Wallet {
             .map_err(MiniscriptPsbtError::Conversion)?;
 
         let prev_output = utxo.outpoint;
-        if let Some(prev_tx) = self.indexed_graph.graph().get_tx(prev_output.txid) {
-            if desc.is_witness() || desc.is_taproot() {
-                psbt_input.witness_utxo = Some(prev_tx.output[prev_output.vout as usize].clone());
-            }
-            if !desc.is_taproot() && (!desc.is_witness() || !only_witness_utxo) {
-                psbt_input.non_witness_utxo = Some(prev_tx.as_ref().clone());
+        if desc.is_witness() || desc.is_taproot() {
+            psbt_input.witness_utxo = Some(utxo.txout);
+        }
+        if !desc.is_taproot() && (!desc.is_witness() || !only_witness_utxo) {
+            // signers need the whole previous transaction, and legacy inputs can't be signed
+            // without it
+            match self.indexed_graph.graph().get_tx(prev_output.txid) {
+                Some(prev_tx) => psbt_input.non_witness_utxo = Some(prev_tx.as_ref().clone()),
+                None if !desc.is_witness() => {
+                    return Err(CreateTxError::MissingNonWitnessUtxo(prev_output))
+                }
+                None => {}
             }
         }
         Ok(psbt_input)
     }
 
-    fn update_psbt_with_descriptor(&self, psbt: &mut Psbt) -> Result<(), MiniscriptPsbtError> {
+    /// Add the derivation metadata to the inputs and outputs of `psbt` owned by the wallet,
+    /// restricted to the `inputs` indexes if specified.
+    fn update_psbt_with_descriptor(
+        &self,
+        psbt: &mut Psbt,
+        inputs: Option<&BTreeSet<usize>>,
+    ) -> Result<(), MiniscriptPsbtError> {
         // We need to borrow `psbt` mutably within the loops, so we have to allocate a vec for all
         // the input utxos and outputs
         let utxos = (0..psbt.inputs.len())
+            .filter(|i| inputs.map_or(true, |inputs| inputs.contains(i)))
             .filter_map(|i| psbt.get_utxo_for(i).map(|utxo| (true, i, utxo)))
             .chain(
                 psbt.unsigned_tx
@@ -2266,19 +4260,21 @@ impl Wallet {
     /// [`commit`]: Self::commit
     pub fn apply_update(&mut self, update: impl Into<Update>) -> Result<(), CannotConnectError> {
         let update = update.into();
-        let mut changeset = match update.chain {
-            Some(chain_update) => ChangeSet::from(self.chain.apply_update(chain_update)?),
-            None => ChangeSet::default(),
-        };
+        self.notify_subscriptions(|wallet| {
+            let mut changeset = match update.chain {
+                Some(chain_update) => ChangeSet::from(wallet.chain.apply_update(chain_update)?),
+                None => ChangeSet::default(),
+            };
 
-        let index_changeset = self
-            .indexed_graph
-            .index
-            .reveal_to_target_multi(&update.last_active_indices);
-        changeset.append(index_changeset.into());
-        changeset.append(self.indexed_graph.apply_update(update.graph).into());
-        self.stage.append(changeset);
-        Ok(())
+            let index_changeset = wallet
+                .indexed_graph
+                .index
+                .reveal_to_target_multi(&update.last_active_indices);
+            changeset.append(index_changeset.into());
+            changeset.append(wallet.indexed_graph.apply_update(update.graph).into());
+            wallet.stage.append(changeset);
+            Ok(())
+        })
     }
 
     /// Get a reference of the staged [`ChangeSet`] that are yet to be committed (if any).
@@ -2291,8 +4287,154 @@ impl Wallet {
     }
 
     /// Take the staged [`ChangeSet`] to be persisted now (if any).
+    ///
+    /// The wallet considers the taken changes as persisted: they can't be reverted by
+    /// [`Wallet::discard_staged`] anymore.
     pub fn take_staged(&mut self) -> Option<ChangeSet> {
-        self.stage.take()
+        let changeset = self.stage.take()?;
+        self.committed_chain = self.chain.clone();
+        self.committed_last_revealed = self.indexed_graph.index.last_revealed_indices();
+        Some(changeset)
+    }
+
+    /// Discard the staged [`ChangeSet`], and revert the in-memory state of the wallet to what it
+    /// was when the staged changes were last taken (see [`Wallet::take_staged`]).
+    ///
+    /// This allows to preview an update: apply it, look at the resulting balance or transactions,
+    /// and either persist the staged changes or discard them.
+    ///
+    /// Which addresses were revealed, the chain and all the transactions, txouts and anchors added
+    /// since are reverted. The following changes can't be reverted and are still applied to the
+    /// wallet, they are returned so that the caller can decide whether to persist them:
+    ///
+    /// * the network, descriptors and genesis block of a wallet whose creation wasn't persisted.
+    /// * the keychains added with [`Wallet::add_keychain`].
+    /// * the last seen timestamps of the transactions that were already known to the wallet:
+    ///   their previous values aren't kept, so they stay at the latest time seen.
+    /// * likewise, the eviction timestamps of the transactions that were already known to the
+    ///   wallet.
+    /// * the labels set or removed, see [`Wallet::set_label`].
+    /// * the broadcasts recorded, see [`Wallet::record_broadcast`].
+    /// * the change address policy and the position of its rotation, see
+    ///   [`Wallet::set_change_address_policy`].
+    /// * the birthday, see [`Wallet::set_birthday`].
+    /// * the sync times recorded, see [`Wallet::record_keychain_synced`].
+    /// * the revisions of the descriptors of the keychains, see [`Wallet::descriptor_history`].
+    ///
+    /// The invoices created since, see [`Wallet::create_invoice`], are discarded along with their
+    /// addresses.
+    pub fn discard_staged(&mut self) -> ChangeSet {
+        let staged = match self.stage.take() {
+            Some(staged) => staged,
+            None => return ChangeSet::default(),
+        };
+        let staged_graph = &staged.indexed_tx_graph.graph;
+        let discarded_txids = staged_graph
+            .txs
+            .iter()
+            .map(|tx| tx.compute_txid())
+            .collect::<BTreeSet<_>>();
+
+        // rebuild the graph and index without the staged transactions, txouts and anchors
+        let mut graph = self.indexed_graph.graph().initial_changeset();
+        graph.txs.retain(|tx| !staged_graph.txs.contains(tx));
+        graph
+            .txouts
+            .retain(|outpoint, _| !staged_graph.txouts.contains_key(outpoint));
+        graph
+            .anchors
+            .retain(|anchor| !staged_graph.anchors.contains(anchor));
+        graph
+            .last_seen
+            .retain(|txid, _| !discarded_txids.contains(txid));
+        graph
+            .last_evicted
+            .retain(|txid, _| !discarded_txids.contains(txid));
+
+        let mut index =
+            KeychainTxOutIndex::<KeychainKind>::new(self.indexed_graph.index.lookahead());
+        for (keychain, descriptor) in self.indexed_graph.index.keychains() {
+            let _ = index
+                .insert_descriptor(*keychain, descriptor.clone())
+                .expect("the descriptors are already in the wallet's index");
+        }
+        let _ = index.reveal_to_target_multi(&self.committed_last_revealed);
+        let mut indexed_graph = IndexedTxGraph::new(index);
+        indexed_graph.apply_changeset(indexed_tx_graph::ChangeSet {
+            graph,
+            indexer: keychain::ChangeSet::default(),
+        });
+        self.indexed_graph = indexed_graph;
+        self.chain = self.committed_chain.clone();
+
+        // what's left of the staged changes in the wallet couldn't be reverted
+        let chain = self.chain.initial_changeset();
+        let mut not_reverted = ChangeSet {
+            network: staged.network,
+            ..Default::default()
+        };
+        not_reverted.chain = staged
+            .chain
+            .into_iter()
+            .filter(|(height, hash)| chain.get(height) == Some(hash))
+            .collect();
+        not_reverted.indexed_tx_graph.indexer.keychains_added =
+            staged.indexed_tx_graph.indexer.keychains_added;
+        not_reverted.labels = staged.labels;
+        not_reverted.broadcasts = staged.broadcasts;
+        not_reverted.change_address_policy = staged.change_address_policy;
+        not_reverted.change_rotation = staged.change_rotation;
+        not_reverted.birthday = staged.birthday;
+        not_reverted.last_synced = staged.last_synced;
+        not_reverted.descriptor_revisions = staged.descriptor_revisions;
+        // the addresses of the invoices created since are reverted, so are the invoices
+        for id in staged.invoices.keys() {
+            self.invoices.remove(id);
+        }
+        not_reverted.indexed_tx_graph.graph.last_seen = staged
+            .indexed_tx_graph
+            .graph
+            .last_seen
+            .into_iter()
+            .filter(|(txid, _)| !discarded_txids.contains(txid))
+            .collect();
+        not_reverted.indexed_tx_graph.graph.last_evicted = staged
+            .indexed_tx_graph
+            .graph
+            .last_evicted
+            .into_iter()
+            .filter(|(txid, _)| !discarded_txids.contains(txid))
+            .collect();
+        not_reverted
+    }
+
+    /// Get a single [`ChangeSet`] holding the entire current state of the wallet.
+    ///
+    /// Loading a wallet from the snapshot gives the same wallet as loading it from all the
+    /// changes persisted so far (plus the staged ones), without the history of revealed
+    /// addresses, replaced chains and removed labels. Storages can replace their history with
+    /// it to load faster, with `compact_to` of `bdk_file_store::Store` and `bdk_sqlite::Store`,
+    /// and it can be kept as a backup of the wallet.
+    ///
+    /// The staged changes are left untouched.
+    pub fn snapshot(&self) -> ChangeSet {
+        ChangeSet {
+            chain: self.chain.initial_changeset(),
+            indexed_tx_graph: self.indexed_graph.initial_changeset(),
+            network: Some(self.network),
+            labels: self
+                .labels
+                .iter()
+                .map(|(label_ref, label)| (label_ref.clone(), Some(label.clone())))
+                .collect(),
+            broadcasts: self.broadcasts.clone(),
+            change_address_policy: Some(self.change_address_policy),
+            change_rotation: Some(self.change_rotation),
+            birthday: self.birthday,
+            last_synced: self.last_synced.clone(),
+            invoices: self.invoices.clone(),
+            descriptor_revisions: self.descriptor_revisions.clone(),
+        }
     }
 
     /// Get a reference to the inner [`TxGraph`].
@@ -2353,24 +4495,134 @@ impl Wallet {
         height: u32,
         connected_to: BlockId,
     ) -> Result<(), ApplyHeaderError> {
-        let mut changeset = ChangeSet::default();
-        changeset.append(
-            self.chain
-                .apply_header_connected_to(&block.header, height, connected_to)?
-                .into(),
-        );
-        changeset.append(
-            self.indexed_graph
-                .apply_block_relevant(block, height)
-                .into(),
-        );
-        self.stage.append(changeset);
-        Ok(())
+        self.notify_subscriptions(|wallet| {
+            let mut changeset = ChangeSet::default();
+            changeset.append(
+                wallet
+                    .chain
+                    .apply_header_connected_to(&block.header, height, connected_to)?
+                    .into(),
+            );
+            changeset.append(
+                wallet
+                    .indexed_graph
+                    .apply_block_relevant(block, height)
+                    .into(),
+            );
+            wallet.stage.append(changeset);
+            Ok(())
+        })
+    }
+
+    /// Applies a batch of consecutive `blocks`, given with their height, to the wallet.
+    ///
+    /// The first block connects to the internal [`LocalChain`] through `connected_to`, like with
+    /// [`apply_block_connected_to`], and each of the following blocks must build on the one
+    /// before it. Starting the batch below the tip of the wallet replaces the blocks above
+    /// `connected_to`, which is how a reorg is applied.
+    ///
+    /// The whole batch is checked before being applied: on error the wallet is left untouched and
+    /// the error tells which block failed to connect. Otherwise the relevant transactions of all
+    /// the blocks are inserted and the changes are staged as a single [`ChangeSet`].
+    ///
+    /// **WARNING**: You must persist the changes resulting from one or more calls to this method
+    /// if you need the inserted block data to be reloaded after closing the wallet.
+    /// See [`Wallet::reveal_next_address`].
+    ///
+    /// [`apply_block_connected_to`]: Self::apply_block_connected_to
+    pub fn apply_blocks<'b>(
+        &mut self,
+        blocks: impl IntoIterator<Item = (u32, &'b Block)>,
+        connected_to: BlockId,
+    ) -> Result<(), ApplyBlocksError> {
+        let blocks = blocks.into_iter().collect::<Vec<_>>();
+        self.notify_subscriptions(|wallet| {
+            // connect the blocks to a copy of the chain, so that nothing changes on error
+            let mut chain = wallet.chain.clone();
+            let mut changeset = ChangeSet::default();
+            let mut prev: Option<BlockId> = None;
+            for &(height, block) in &blocks {
+                let hash = block.block_hash();
+                let block_connected_to = match prev {
+                    None => connected_to,
+                    Some(prev) => {
+                        if prev.height.checked_add(1) != Some(height)
+                            || prev.hash != block.header.prev_blockhash
+                        {
+                            return Err(ApplyBlocksError::NotConsecutive {
+                                height,
+                                hash,
+                                prev_height: prev.height,
+                                prev_hash: prev.hash,
+                            });
+                        }
+                        prev
+                    }
+                };
+                changeset.append(
+                    chain
+                        .apply_header_connected_to(&block.header, height, block_connected_to)
+                        .map_err(|error| ApplyBlocksError::CannotConnect {
+                            height,
+                            hash,
+                            error,
+                        })?
+                        .into(),
+                );
+                prev = Some(BlockId { height, hash });
+            }
+            wallet.chain = chain;
+
+            for (height, block) in blocks {
+                changeset.append(
+                    wallet
+                        .indexed_graph
+                        .apply_block_relevant(block, height)
+                        .into(),
+                );
+            }
+            wallet.stage.append(changeset);
+            Ok(())
+        })
+    }
+
+    /// Applies a batch of mempool transactions, as emitted by a block-by-block chain source, to
+    /// the wallet.
+    ///
+    /// This is [`apply_unconfirmed_txs`] taking ownership of `(tx, last_seen)` pairs. It returns
+    /// the wallet's unconfirmed transactions which were evicted by the batch, because they
+    /// conflict with one of its transactions seen more recently. The wallet keeps them but they
+    /// no longer count toward its history and balance.
+    ///
+    /// [`apply_unconfirmed_txs`]: Self::apply_unconfirmed_txs
+    pub fn apply_mempool(&mut self, txs: Vec<(Transaction, u64)>) -> Vec<Txid> {
+        let unconfirmed_before = self
+            .transactions()
+            .filter(|tx| !tx.chain_position.is_confirmed())
+            .map(|tx| tx.tx_node.txid)
+            .collect::<Vec<_>>();
+
+        self.apply_unconfirmed_txs(txs.iter().map(|(tx, last_seen)| (tx, *last_seen)));
+
+        let chain_tip = self.chain.tip().block_id();
+        let graph = self.indexed_graph.graph();
+        unconfirmed_before
+            .into_iter()
+            .filter(|&txid| {
+                graph
+                    .get_chain_position(&self.chain, chain_tip, txid)
+                    .is_none()
+            })
+            .collect()
     }
 
     /// Apply relevant unconfirmed transactions to the wallet.
     ///
-    /// Transactions that are not relevant are filtered out.
+    /// Transactions that are not relevant are filtered out: only the ones spending or paying one
+    /// of the wallet's outputs or script pubkeys, or conflicting with one of the wallet's
+    /// transactions, are inserted. The transactions of the batch may be in any order. For each
+    /// transaction a [`TxApplied`] reports whether it was relevant, which of the wallet's
+    /// transactions it conflicts with and how it changed the balance.
     ///
     /// This method takes in an iterator of `(tx, last_seen)` where `last_seen` is the timestamp of
     /// when the transaction was last seen in the mempool. This is used for conflict resolution
@@ -2383,11 +4635,64 @@ impl Wallet {
     pub fn apply_unconfirmed_txs<'t>(
         &mut self,
         unconfirmed_txs: impl IntoIterator<Item = (&'t Transaction, u64)>,
-    ) {
-        let indexed_graph_changeset = self
-            .indexed_graph
-            .batch_insert_relevant_unconfirmed(unconfirmed_txs);
-        self.stage.append(indexed_graph_changeset.into());
+    ) -> Vec<TxApplied> {
+        let txs = unconfirmed_txs.into_iter().collect::<Vec<_>>();
+        self.notify_subscriptions(|wallet| {
+            // index all the transactions first, one may spend an output of a later one
+            let mut changeset = indexed_tx_graph::ChangeSet::<
+                ConfirmationTimeHeightAnchor,
+                keychain::ChangeSet<KeychainKind>,
+            >::default();
+            for (tx, _) in &txs {
+                changeset
+                    .indexer
+                    .append(wallet.indexed_graph.index.index_tx(tx));
+            }
+
+            let mut applied = Vec::with_capacity(txs.len());
+            for (tx, last_seen) in txs {
+                let txid = tx.compute_txid();
+                let conflicts = wallet
+                    .indexed_graph
+                    .graph()
+                    .direct_conflicts(tx)
+                    .map(|(_, conflict)| conflict)
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                let relevant =
+                    !conflicts.is_empty() || wallet.indexed_graph.index.is_tx_relevant(tx);
+                let mut balance_delta = SignedAmount::ZERO;
+                if relevant {
+                    let before = wallet.balance().total();
+                    changeset.append(
+                        wallet
+                            .indexed_graph
+                            .batch_insert_unconfirmed([(tx.clone(), last_seen)]),
+                    );
+                    let after = wallet.balance().total();
+                    balance_delta =
+                        SignedAmount::from_sat(after.to_sat() as i64 - before.to_sat() as i64);
+                }
+                applied.push(TxApplied {
+                    txid,
+                    relevant,
+                    conflicts,
+                    canonical: false,
+                    balance_delta,
+                });
+            }
+            wallet.stage.append(changeset.into());
+
+            let chain_tip = wallet.chain.tip().block_id();
+            let graph = wallet.indexed_graph.graph();
+            for tx in applied.iter_mut().filter(|tx| tx.relevant) {
+                tx.canonical = graph
+                    .get_chain_position(&wallet.chain, chain_tip, tx.txid)
+                    .is_some();
+            }
+            applied
+        })
     }
 }
 
@@ -2398,9 +4703,20 @@ impl Wallet {
     /// This is the first step when performing a spk-based wallet partial sync, the returned
     /// [`SyncRequest`] collects all revealed script pubkeys from the wallet keychain needed to
     /// start a blockchain sync with a spk based blockchain client.
+    ///
+    /// The txids of the wallet's unconfirmed transactions are included too, so that the chain
+    /// source can report the ones which left the mempool, see [`TxGraph::insert_evicted_at`].
+    ///
+    /// [`TxGraph::insert_evicted_at`]: bdk_chain::tx_graph::TxGraph::insert_evicted_at
     pub fn start_sync_with_revealed_spks(&self) -> SyncRequest {
+        let unconfirmed_txids = self
+            .transactions()
+            .filter(|tx| !tx.chain_position.is_confirmed())
+            .map(|tx| tx.tx_node.txid)
+            .collect::<Vec<_>>();
         SyncRequest::from_chain_tip(self.chain.tip())
             .populate_with_revealed_spks(&self.indexed_graph.index, ..)
+            .chain_txids(unconfirmed_txids)
     }
 
     /// Create a [`FullScanRequest] for this wallet.
@@ -2465,25 +4781,200 @@ fn new_local_utxo(
         confirmation_time: full_txo.chain_position.into(),
         keychain,
         derivation_index,
+        is_coinbase: full_txo.is_on_coinbase,
+    }
+}
+
+/// Number of signatures (ECDSA, taproot key and script path) contained in a PSBT input
+fn signatures_count(input: &psbt::Input) -> usize {
+    input.partial_sigs.len() + input.tap_script_sigs.len() + input.tap_key_sig.iter().count()
+}
+
+/// The origins of the keys of the signatures of `input`, when the PSBT has them
+fn signature_key_sources(input: &psbt::Input) -> BTreeSet<bitcoin::bip32::KeySource> {
+    let ecdsa_keys = input
+        .partial_sigs
+        .keys()
+        .filter_map(|pk| input.bip32_derivation.get(&pk.inner));
+    let schnorr_keys = input
+        .tap_script_sigs
+        .keys()
+        .map(|(pk, _)| *pk)
+        .chain(input.tap_key_sig.and(input.tap_internal_key))
+        .filter_map(|pk| input.tap_key_origins.get(&pk).map(|(_, source)| source));
+    ecdsa_keys.chain(schnorr_keys).cloned().collect()
+}
+
+/// Whether `script_pubkey` is a witness program of a version after taproot, whose spends the
+/// wallet can't interpret
+fn is_future_witness_version(script_pubkey: &Script) -> bool {
+    script_pubkey
+        .witness_version()
+        .map_or(false, |version| version.to_num() > 1)
+}
+
+/// Compute the message signed by a signature with the given `sighash_type` for a PSBT input
+fn psbt_sighash_msg(
+    psbt: &mut Psbt,
+    input_index: usize,
+    sighash_type: impl Into<psbt::PsbtSighashType>,
+    leaf_hash: Option<TapLeafHash>,
+) -> Result<secp256k1::Message, CombineError> {
+    // miniscript computes the sighash requested by the input, temporarily replace it with the
+    // one used by the signature
+    let requested = psbt.inputs[input_index]
+        .sighash_type
+        .replace(sighash_type.into());
+    let msg = psbt.sighash_msg(
+        input_index,
+        &mut SighashCache::new(&psbt.unsigned_tx),
+        leaf_hash,
+    );
+    psbt.inputs[input_index].sighash_type = requested;
+
+    msg.map(|msg| msg.to_secp_msg())
+        .map_err(|error| CombineError::Sighash { input_index, error })
+}
+
+/// Returns the block height at which the height-based timelocks in `requirements` are satisfied
+/// for all the `selected` inputs, or `None` if they are already satisfied at `current_height`.
+///
+/// Time-based timelocks and foreign inputs are not checked, since we don't know enough about
+/// them to tell when they expire.
+fn timelock_valid_at(
+    requirements: &descriptor::policy::Condition,
+    selected: &[Utxo],
+    current_height: u32,
+) -> Option<u32> {
+    let mut valid_at = current_height;
+
+    if let Some(absolute::LockTime::Blocks(height)) = requirements.timelock {
+        valid_at = valid_at.max(height.to_consensus_u32());
+    }
+
+    if let Some(relative::LockTime::Blocks(csv)) =
+        requirements.csv.and_then(|csv| csv.to_relative_lock_time())
+    {
+        for utxo in selected {
+            if let Utxo::Local(local) = utxo {
+                // An unconfirmed input can at best be confirmed in the next block
+                let confirmation_height = match local.confirmation_time {
+                    ConfirmationTime::Confirmed { height, .. } => height,
+                    ConfirmationTime::Unconfirmed { .. } => current_height + 1,
+                };
+                // An input confirmed at `h` can be spent with `older(n)` in block `h + n`, that
+                // is once the tip is at `h + n - 1`
+                let spendable_at = (confirmation_height + u32::from(csv.value())).saturating_sub(1);
+                valid_at = valid_at.max(spendable_at);
+            }
+        }
+    }
+
+    if valid_at > current_height {
+        Some(valid_at)
+    } else {
+        None
+    }
+}
+
+/// Merge the locktime `policy` with the timelock `required` by the descriptors of the inputs.
+///
+/// The required timelock wins over the anti-fee-sniping and the empty locktimes, an exact
+/// locktime must satisfy it.
+fn resolve_locktime(
+    policy: tx_builder::LocktimePolicy,
+    required: Option<absolute::LockTime>,
+    current_height: absolute::LockTime,
+    rng: &mut impl RngCore,
+) -> Result<tx_builder::LocktimeDecision, CreateTxError> {
+    use tx_builder::{LocktimePolicy, LocktimeSource};
+
+    let preferred = match policy {
+        LocktimePolicy::AntiFeeSniping { randomize } => match current_height {
+            absolute::LockTime::Blocks(height) if randomize && rng.next_u32() % 10 == 0 => {
+                let height = height
+                    .to_consensus_u32()
+                    .saturating_sub(rng.next_u32() % 100);
+                absolute::LockTime::from_height(height).expect("lower than a valid height")
+            }
+            _ => current_height,
+        },
+        LocktimePolicy::None => absolute::LockTime::ZERO,
+        LocktimePolicy::Exact(locktime) => match required {
+            Some(required) if !required.is_same_unit(locktime) => {
+                return Err(CreateTxError::MixedLockTimeUnits {
+                    requested: locktime,
+                    required,
+                })
+            }
+            Some(required) if locktime < required => {
+                return Err(CreateTxError::LockTime {
+                    requested: locktime,
+                    required,
+                })
+            }
+            _ => locktime,
+        },
+    };
+
+    let (locktime, source) = match required {
+        // a locktime in other units, or lower, doesn't satisfy the requirement
+        Some(required) if !required.is_same_unit(preferred) || preferred < required => {
+            (required, LocktimeSource::Descriptor)
+        }
+        _ => (preferred, LocktimeSource::Policy),
+    };
+    Ok(tx_builder::LocktimeDecision {
+        locktime,
+        policy,
+        required,
+        source,
+    })
+}
+
+/// Merges the `recipients` paying the same script into the first of them, summing the amounts.
+///
+/// `OP_RETURN` outputs are left alone.
+fn merge_duplicate_recipients(recipients: &[(ScriptBuf, u64)]) -> Vec<(ScriptBuf, u64)> {
+    let mut merged: Vec<(ScriptBuf, u64)> = Vec::with_capacity(recipients.len());
+    let mut positions: HashMap<&Script, usize> = HashMap::new();
+    for (script_pubkey, value) in recipients {
+        if script_pubkey.is_op_return() {
+            merged.push((script_pubkey.clone(), *value));
+            continue;
+        }
+        match positions.get(script_pubkey.as_script()) {
+            Some(&position) => merged[position].1 += value,
+            None => {
+                positions.insert(script_pubkey.as_script(), merged.len());
+                merged.push((script_pubkey.clone(), *value));
+            }
+        }
     }
+    merged
 }
 
 fn create_signers<E: IntoWalletDescriptor>(
     index: &mut KeychainTxOutIndex<KeychainKind>,
     secp: &Secp256k1<All>,
     descriptor: E,
-    change_descriptor: E,
+    change_descriptor: Option<E>,
     network: Network,
 ) -> Result<(Arc<SignersContainer>, Arc<SignersContainer>), DescriptorError> {
     let descriptor = into_wallet_descriptor_checked(descriptor, secp, network)?;
-    let change_descriptor = into_wallet_descriptor_checked(change_descriptor, secp, network)?;
+    let change_descriptor = change_descriptor
+        .map(|d| into_wallet_descriptor_checked(d, secp, network))
+        .transpose()?;
     let (descriptor, keymap) = descriptor;
     let signers = Arc::new(SignersContainer::build(keymap, &descriptor, secp));
     let _ = index
         .insert_descriptor(KeychainKind::External, descriptor)
         .expect("this is the first descriptor we're inserting");
 
-    let (descriptor, keymap) = change_descriptor;
+    let (descriptor, keymap) = match change_descriptor {
+        Some(change_descriptor) => change_descriptor,
+        None => return Ok((signers, Arc::new(SignersContainer::new()))),
+    };
     let change_signers = Arc::new(SignersContainer::build(keymap, &descriptor, secp));
     let _ = index
         .insert_descriptor(KeychainKind::Internal, descriptor)
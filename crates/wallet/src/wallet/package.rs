@@ -0,0 +1,180 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Chains of transactions spending the change of each other, built with [`Wallet::build_chain`]
+
+use alloc::vec::Vec;
+
+use bdk_chain::ConfirmationTime;
+use bitcoin::{psbt, Amount, FeeRate, OutPoint, Psbt, ScriptBuf, Transaction};
+
+use super::error::{BuildChainError, CreateTxError};
+use super::Wallet;
+use crate::descriptor::DescriptorMeta;
+use crate::LocalOutput;
+
+/// A transaction of a chain built by [`Wallet::build_chain`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxBuilderSpec {
+    /// The recipients of the transaction
+    pub recipients: Vec<(ScriptBuf, Amount)>,
+    /// The fee rate of the transaction, the default of [`TxBuilder`] if `None`
+    ///
+    /// [`TxBuilder`]: super::tx_builder::TxBuilder
+    pub fee_rate: Option<FeeRate>,
+    /// Whether the transaction must spend the change of the previous transaction of the chain,
+    /// ignored for the first one
+    pub spend_parent_change: bool,
+}
+
+impl TxBuilderSpec {
+    /// A transaction paying `recipients` and spending the change of the previous transaction
+    pub fn new(recipients: Vec<(ScriptBuf, Amount)>) -> Self {
+        Self {
+            recipients,
+            fee_rate: None,
+            spend_parent_change: true,
+        }
+    }
+
+    /// Set the fee rate of the transaction
+    pub fn fee_rate(mut self, fee_rate: FeeRate) -> Self {
+        self.fee_rate = Some(fee_rate);
+        self
+    }
+}
+
+/// The input of a child spending the change of its parent
+struct ParentChange {
+    outpoint: OutPoint,
+    psbt_input: psbt::Input,
+    satisfaction_weight: usize,
+}
+
+impl Wallet {
+    /// Build a chain of transactions, in the order of `specs`, each one spending the change of
+    /// the previous one when [`TxBuilderSpec::spend_parent_change`] is set
+    ///
+    /// The transactions aren't applied to the wallet: the change of a transaction is added to
+    /// the next one as an unconfirmed input, and the inputs of all of them are reserved, see
+    /// [`TxBuilder::reserve_inputs`], so that none of them is selected twice. Since a child
+    /// commits to the txid of its parent, the parent can't have an input whose txid changes when
+    /// it's signed, such as a legacy one.
+    ///
+    /// The PSBTs are returned parents first, once signed and extracted they can be broadcast
+    /// with [`Wallet::broadcast_package`]. If a transaction can't be built, the reservations of
+    /// the previous ones are released.
+    ///
+    /// [`TxBuilder::reserve_inputs`]: super::tx_builder::TxBuilder::reserve_inputs
+    pub fn build_chain(&mut self, specs: Vec<TxBuilderSpec>) -> Result<Vec<Psbt>, BuildChainError> {
+        let mut psbts = Vec::with_capacity(specs.len());
+        match self.build_chain_into(specs, &mut psbts) {
+            Ok(()) => Ok(psbts),
+            Err(err) => {
+                for psbt in &psbts {
+                    self.cancel_tx(&psbt.unsigned_tx);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Build the transactions of [`Wallet::build_chain`] into `psbts`
+    fn build_chain_into(
+        &mut self,
+        specs: Vec<TxBuilderSpec>,
+        psbts: &mut Vec<Psbt>,
+    ) -> Result<(), BuildChainError> {
+        let mut parent_change: Option<ParentChange> = None;
+        for (index, spec) in specs.into_iter().enumerate() {
+            let input = match psbts.last() {
+                Some(parent) if spec.spend_parent_change => {
+                    // the txid of the parent changes with the script sigs of its legacy inputs
+                    if parent
+                        .inputs
+                        .iter()
+                        .any(|input| input.witness_utxo.is_none())
+                    {
+                        return Err(BuildChainError::MalleableTxid { index: index - 1 });
+                    }
+                    Some(
+                        parent_change
+                            .take()
+                            .ok_or(BuildChainError::NoParentChange { index })?,
+                    )
+                }
+                _ => None,
+            };
+
+            let mut builder = self.build_tx();
+            builder.set_recipients(spec.recipients).reserve_inputs(true);
+            if let Some(fee_rate) = spec.fee_rate {
+                builder.fee_rate(fee_rate);
+            }
+            if let Some(input) = input {
+                builder
+                    .add_foreign_utxo(input.outpoint, input.psbt_input, input.satisfaction_weight)
+                    .expect("the previous transaction of the input is the parent");
+            }
+            let (psbt, change) = builder
+                .finish_with_change()
+                .map_err(|error| BuildChainError::CreateTx { index, error })?;
+
+            parent_change = match change {
+                Some(change) => Some(
+                    self.parent_change(&psbt.unsigned_tx, change.index)
+                        .map_err(|error| BuildChainError::CreateTx { index, error })?,
+                ),
+                None => None,
+            };
+            psbts.push(psbt);
+        }
+        Ok(())
+    }
+
+    /// The input spending the output at `vout` of `tx`, a change output of the wallet
+    fn parent_change(&self, tx: &Transaction, vout: usize) -> Result<ParentChange, CreateTxError> {
+        let outpoint = OutPoint::new(tx.compute_txid(), vout as u32);
+        let txout = tx.output[vout].clone();
+        let &(keychain, derivation_index) = self
+            .indexed_graph
+            .index
+            .index_of_spk(&txout.script_pubkey)
+            .ok_or(CreateTxError::UnknownUtxo)?;
+        let descriptor = self.get_descriptor_for_keychain(keychain);
+        let satisfaction_weight = descriptor
+            .max_weight_to_satisfy()
+            .map_err(|_| CreateTxError::UnknownUtxo)?
+            .to_wu() as usize;
+        let is_taproot = descriptor.is_taproot();
+        let mut psbt_input = self.get_psbt_input(
+            LocalOutput {
+                outpoint,
+                txout,
+                keychain,
+                is_spent: false,
+                derivation_index,
+                confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
+                is_coinbase: false,
+            },
+            None,
+            true,
+        )?;
+        if !is_taproot {
+            // the signers of segwit v0 inputs need the previous transaction, whose txid is final
+            psbt_input.non_witness_utxo = Some(tx.clone());
+        }
+        Ok(ParentChange {
+            outpoint,
+            psbt_input,
+            satisfaction_weight,
+        })
+    }
+}
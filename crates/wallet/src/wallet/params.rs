@@ -0,0 +1,199 @@
+use alloc::boxed::Box;
+use bdk_chain::collections::BTreeMap;
+use bitcoin::{constants::genesis_block, BlockHash, Network};
+use miniscript::descriptor::KeyMap;
+
+use crate::descriptor::{DescriptorError, ExtendedDescriptor, IntoWalletDescriptor};
+use crate::KeychainKind;
+
+use super::signer::{SignerOrdering, SignersContainer};
+use super::utils::SecpCtx;
+use super::{ChangeSet, LoadError, LoadMismatch, Wallet};
+
+/// A descriptor turned into its public part and keys once the network to parse it for is known.
+type DescriptorToExtract = Box<
+    dyn FnOnce(&SecpCtx, Network) -> Result<(ExtendedDescriptor, KeyMap), DescriptorError>
+        + Send
+        + 'static,
+>;
+
+fn make_descriptor_to_extract<D>(descriptor: D) -> DescriptorToExtract
+where
+    D: IntoWalletDescriptor + Send + 'static,
+{
+    Box::new(|secp, network| descriptor.into_wallet_descriptor(secp, network))
+}
+
+/// The network of a [`Wallet`] and the genesis block its chain starts with
+///
+/// The addresses of the wallet are encoded for `network`. The genesis block is the one of
+/// `network` unless it's set with [`with_genesis_hash`](Self::with_genesis_hash), for a chain
+/// which shares the address encoding of `network` but not its genesis block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkParams {
+    /// The network the addresses of the wallet are encoded for
+    pub network: Network,
+    /// The hash of the genesis block of the chain of the wallet
+    pub genesis_hash: BlockHash,
+}
+
+impl NetworkParams {
+    /// The parameters of `network`, with its genesis block.
+    pub fn new(network: Network) -> Self {
+        Self {
+            network,
+            genesis_hash: genesis_block(network).block_hash(),
+        }
+    }
+
+    /// Use the genesis block `genesis_hash` instead of the one of the network.
+    #[must_use]
+    pub fn with_genesis_hash(mut self, genesis_hash: BlockHash) -> Self {
+        self.genesis_hash = genesis_hash;
+        self
+    }
+}
+
+impl From<Network> for NetworkParams {
+    fn from(network: Network) -> Self {
+        Self::new(network)
+    }
+}
+
+/// Parameters for loading a [`Wallet`] from a [`ChangeSet`], returned by [`Wallet::load`].
+///
+/// Every check is optional: the loaded data is compared to the expected values only when they are
+/// set, and a difference is reported as a [`LoadMismatch`].
+///
+/// ```rust
+/// # use bdk_wallet::Wallet;
+/// # use bdk_wallet::KeychainKind;
+/// # use bitcoin::Network;
+/// # let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/0/*)";
+/// # let change_descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1/*)";
+/// # let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Signet)?;
+/// # let changeset = wallet.take_staged().unwrap();
+/// let wallet = Wallet::load()
+///     .check_network(Network::Signet)
+///     .descriptor(KeychainKind::External, Some(descriptor))
+///     .descriptor(KeychainKind::Internal, Some(change_descriptor))
+///     .load_wallet(changeset)?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+#[must_use]
+#[derive(Default)]
+pub struct LoadParams {
+    check_network: Option<Network>,
+    check_genesis_hash: Option<BlockHash>,
+    check_descriptors: BTreeMap<KeychainKind, Option<DescriptorToExtract>>,
+    extract_keys: bool,
+}
+
+impl LoadParams {
+    /// Construct parameters that don't check anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check that the loaded wallet is on `network`.
+    ///
+    /// The expected descriptors are parsed for this network too.
+    pub fn check_network(mut self, network: Network) -> Self {
+        self.check_network = Some(network);
+        self
+    }
+
+    /// Check that the loaded wallet's chain starts with the `genesis_hash` block.
+    pub fn check_genesis_hash(mut self, genesis_hash: BlockHash) -> Self {
+        self.check_genesis_hash = Some(genesis_hash);
+        self
+    }
+
+    /// Check that the loaded wallet is on the network of `params` and that its chain starts with
+    /// their genesis block.
+    pub fn check_network_params(self, params: NetworkParams) -> Self {
+        self.check_network(params.network)
+            .check_genesis_hash(params.genesis_hash)
+    }
+
+    /// Check that the loaded descriptor of `keychain` is `expected_descriptor`.
+    ///
+    /// `None` checks that the wallet has no descriptor for `keychain`, as is the case of the
+    /// [`KeychainKind::Internal`] keychain of single-keychain wallets.
+    pub fn descriptor<D>(mut self, keychain: KeychainKind, expected_descriptor: Option<D>) -> Self
+    where
+        D: IntoWalletDescriptor + Send + 'static,
+    {
+        self.check_descriptors.insert(
+            keychain,
+            expected_descriptor.map(make_descriptor_to_extract),
+        );
+        self
+    }
+
+    /// Add the private keys of the descriptors passed to [`LoadParams::descriptor`] as signers of
+    /// the loaded wallet.
+    pub fn extract_keys(mut self) -> Self {
+        self.extract_keys = true;
+        self
+    }
+
+    /// Load the [`Wallet`] from `changeset`, checking it against the parameters.
+    pub fn load_wallet(self, changeset: ChangeSet) -> Result<Wallet, LoadError> {
+        let mut wallet = Wallet::load_from_changeset(changeset)?;
+        self.check_wallet(&mut wallet)?;
+        Ok(wallet)
+    }
+
+    /// Check the loaded `wallet` against the parameters, and extract the keys if asked to.
+    pub(crate) fn check_wallet(self, wallet: &mut Wallet) -> Result<(), LoadError> {
+        if let Some(expected) = self.check_network {
+            if wallet.network != expected {
+                return Err(LoadError::Mismatch(LoadMismatch::Network {
+                    loaded: wallet.network,
+                    expected,
+                }));
+            }
+        }
+        if let Some(expected) = self.check_genesis_hash {
+            let loaded = wallet.chain.genesis_hash();
+            if loaded != expected {
+                return Err(LoadError::Mismatch(LoadMismatch::Genesis {
+                    loaded,
+                    expected,
+                }));
+            }
+        }
+
+        for (keychain, expected) in self.check_descriptors {
+            let loaded = wallet.indexed_graph.index.get_descriptor(&keychain);
+            let (expected, keymap) = match expected {
+                Some(make_descriptor) => {
+                    let (descriptor, keymap) = make_descriptor(&wallet.secp, wallet.network)
+                        .map_err(LoadError::Descriptor)?;
+                    (Some(descriptor), keymap)
+                }
+                None => (None, KeyMap::default()),
+            };
+            if loaded != expected.as_ref() {
+                return Err(LoadError::Mismatch(LoadMismatch::Descriptor {
+                    keychain,
+                    loaded: loaded.cloned().map(Box::new),
+                    expected: expected.map(Box::new),
+                }));
+            }
+
+            if let (true, Some(expected)) = (self.extract_keys, expected) {
+                if keymap.is_empty() {
+                    continue;
+                }
+                let signer_container = SignersContainer::build(keymap, &expected, &wallet.secp);
+                signer_container.signers().into_iter().for_each(|signer| {
+                    wallet.add_signer(keychain, SignerOrdering::default(), signer.clone())
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,566 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Payjoin sender, per [`BIP78`]
+//!
+//! In a payjoin, the receiver of a payment adds its own inputs to the transaction of the sender,
+//! which breaks the assumption that all the inputs of a transaction belong to the same owner.
+//! The sender:
+//!
+//! 1. builds the original PSBT with [`TxBuilder::finish_payjoin_original`], and signs it with
+//!    [`Wallet::sign`] so that it's finalized;
+//! 2. starts a [`PayjoinSession`] with the finalized original and the [`PayjoinParams`] of the
+//!    request, then sends the [`original_psbt`](PayjoinSession::original_psbt) to the endpoint
+//!    of the receiver;
+//! 3. checks the proposal PSBT returned by the receiver with
+//!    [`process_proposal`](PayjoinSession::process_proposal), and signs the returned PSBT with
+//!    [`Wallet::sign`] before broadcasting it.
+//!
+//! If anything fails, the sender can broadcast the finalized original instead. The HTTP transport
+//! is left to the application.
+//!
+//! ```no_run
+//! # use bdk_wallet::wallet::payjoin::{PayjoinParams, PayjoinSession};
+//! # use bdk_wallet::bitcoin::{Amount, Psbt, ScriptBuf};
+//! # use bdk_wallet::{SignOptions, Wallet};
+//! # fn post_to_receiver(original: &Psbt) -> Psbt { unimplemented!() }
+//! # let mut wallet: Wallet = unimplemented!();
+//! # let receiver_script = ScriptBuf::new();
+//! let mut builder = wallet.build_tx();
+//! builder.add_recipient(receiver_script.clone(), Amount::from_sat(50_000));
+//! let (mut original, change) = builder.finish_payjoin_original()?;
+//! assert!(wallet.sign(&mut original, SignOptions::default())?);
+//!
+//! let mut params = PayjoinParams::new(receiver_script);
+//! if let Some(change) = change {
+//!     params = params.with_fee_contribution(change.index, Amount::from_sat(200));
+//! }
+//! let session = PayjoinSession::new(original, params)?;
+//!
+//! let proposal = post_to_receiver(session.original_psbt());
+//! let mut payjoin = session.process_proposal(proposal)?;
+//! assert!(wallet.sign(&mut payjoin, SignOptions::default())?);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`BIP78`]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+//! [`TxBuilder::finish_payjoin_original`]: crate::wallet::tx_builder::TxBuilder::finish_payjoin_original
+//! [`Wallet::sign`]: crate::Wallet::sign
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::fmt;
+
+use bitcoin::{psbt, Amount, FeeRate, OutPoint, Psbt, ScriptBuf, TxIn, TxOut, Weight};
+
+use super::utils::ScriptType;
+use crate::psbt::PsbtUtils;
+
+/// The parameters of a payjoin request, sent to the receiver alongside the original PSBT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayjoinParams {
+    /// The script pubkey of the payment output, which pays the receiver
+    pub payment_script: ScriptBuf,
+    /// The index of the output the receiver may decrease to pay the fee of its inputs, usually
+    /// the change output of the sender (`additionalfeeoutputindex`)
+    pub additional_fee_output_index: Option<usize>,
+    /// The most the receiver may take from the output of
+    /// [`additional_fee_output_index`](Self::additional_fee_output_index)
+    /// (`maxadditionalfeecontribution`)
+    pub max_additional_fee_contribution: Amount,
+    /// Whether the receiver must keep the payment output as it is, instead of replacing it by
+    /// outputs of its choice (`disableoutputsubstitution`)
+    pub disable_output_substitution: bool,
+    /// The minimum fee rate of the payjoin transaction (`minfeerate`)
+    pub min_fee_rate: Option<FeeRate>,
+}
+
+impl PayjoinParams {
+    /// The parameters of a payment to `payment_script`, where the receiver pays for the fee of
+    /// its inputs
+    pub fn new(payment_script: ScriptBuf) -> Self {
+        Self {
+            payment_script,
+            additional_fee_output_index: None,
+            max_additional_fee_contribution: Amount::ZERO,
+            disable_output_substitution: false,
+            min_fee_rate: None,
+        }
+    }
+
+    /// Let the receiver take up to `max` from the output at `index` for the fee of its inputs
+    ///
+    /// [`BIP78`] recommends the fee of one input of the type of the inputs of the sender, at the
+    /// fee rate of the original transaction.
+    ///
+    /// [`BIP78`]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+    pub fn with_fee_contribution(mut self, index: usize, max: Amount) -> Self {
+        self.additional_fee_output_index = Some(index);
+        self.max_additional_fee_contribution = max;
+        self
+    }
+}
+
+/// A payjoin in progress, from the finalized original PSBT to the PSBT of the payjoin
+/// transaction, see the [module-level documentation](self)
+#[derive(Debug, Clone)]
+pub struct PayjoinSession {
+    original: Psbt,
+    params: PayjoinParams,
+    /// The script type of the inputs of the sender
+    input_type: ScriptType,
+    /// The virtual size of a finalized input of the sender
+    input_vsize: u64,
+    original_fee: Amount,
+    original_fee_rate: FeeRate,
+}
+
+impl PayjoinSession {
+    /// Start a payjoin with the finalized `original` PSBT, requested with `params`
+    pub fn new(original: Psbt, params: PayjoinParams) -> Result<Self, PayjoinError> {
+        if original.inputs.len() != original.unsigned_tx.input.len()
+            || original.outputs.len() != original.unsigned_tx.output.len()
+        {
+            return Err(PayjoinError::MalformedPsbt);
+        }
+        if !original.inputs.iter().all(is_finalized) {
+            return Err(PayjoinError::OriginalNotFinalized);
+        }
+        let input_type = check_original_inputs(&original)?;
+        let outputs = &original.unsigned_tx.output;
+        let payment_index = outputs
+            .iter()
+            .position(|txout| txout.script_pubkey == params.payment_script)
+            .ok_or(PayjoinError::PaymentOutputMissing)?;
+        if let Some(index) = params.additional_fee_output_index {
+            if index >= outputs.len() || index == payment_index {
+                return Err(PayjoinError::InvalidFeeOutputIndex(index));
+            }
+        }
+
+        let original_fee = original.fee().map_err(|_| PayjoinError::MalformedPsbt)?;
+        let tx = original.clone().extract_tx_unchecked_fee_rate();
+        let input_vsize = tx.input[0].segwit_weight().to_vbytes_ceil();
+        let original_fee_rate = original_fee / tx.weight();
+        Ok(Self {
+            original,
+            params,
+            input_type,
+            input_vsize,
+            original_fee,
+            original_fee_rate,
+        })
+    }
+
+    /// The finalized original PSBT, to send to the receiver
+    ///
+    /// It can also be broadcast as it is if the payjoin fails.
+    pub fn original_psbt(&self) -> &Psbt {
+        &self.original
+    }
+
+    /// The parameters of the payjoin request
+    pub fn params(&self) -> &PayjoinParams {
+        &self.params
+    }
+
+    /// Check the `proposal` PSBT of the receiver, and return the PSBT of the payjoin transaction
+    /// to sign with [`Wallet::sign`](crate::Wallet::sign)
+    ///
+    /// This performs the checks of the sender of [`BIP78`]:
+    /// - the version and locktime of the transaction are unchanged;
+    /// - all the inputs of the sender are still there, with their sequences, but not finalized;
+    /// - the inputs of the receiver are finalized, of the same script type as those of the
+    ///   sender, and all the inputs have the same sequence;
+    /// - no input or output has key origins, and no input has partial signatures;
+    /// - the outputs of the sender are unchanged, except for the fee contribution taken from the
+    ///   output of [`PayjoinParams::additional_fee_output_index`] and, unless
+    ///   [`PayjoinParams::disable_output_substitution`], the payment output;
+    /// - the fee contribution is within [`PayjoinParams::max_additional_fee_contribution`] and
+    ///   only pays the fee of the inputs added by the receiver;
+    /// - the absolute fee didn't decrease, and the fee rate isn't below
+    ///   [`PayjoinParams::min_fee_rate`].
+    ///
+    /// The UTXOs of the inputs of the sender, which the receiver has to remove, are restored in
+    /// the returned PSBT so that the wallet can sign them.
+    ///
+    /// [`BIP78`]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+    pub fn process_proposal(&self, mut proposal: Psbt) -> Result<Psbt, PayjoinError> {
+        let original_tx = &self.original.unsigned_tx;
+        let tx = &proposal.unsigned_tx;
+        if proposal.inputs.len() != tx.input.len() || proposal.outputs.len() != tx.output.len() {
+            return Err(PayjoinError::MalformedPsbt);
+        }
+        if tx.version != original_tx.version {
+            return Err(PayjoinError::VersionChanged);
+        }
+        if tx.lock_time != original_tx.lock_time {
+            return Err(PayjoinError::LockTimeChanged);
+        }
+
+        // the inputs of the sender, by outpoint, with their index in the original
+        let sender_inputs = original_tx
+            .input
+            .iter()
+            .enumerate()
+            .map(|(index, txin)| (txin.previous_output, index))
+            .collect::<BTreeMap<_, _>>();
+        let mut sender_input_indexes = Vec::new();
+        let mut receiver_value = Amount::ZERO;
+        let mut sequence = None;
+        for (index, (txin, input)) in tx.input.iter().zip(&proposal.inputs).enumerate() {
+            let outpoint = txin.previous_output;
+            if !input.bip32_derivation.is_empty() || !input.tap_key_origins.is_empty() {
+                return Err(PayjoinError::InputKeyOrigins(index));
+            }
+            if !input.partial_sigs.is_empty()
+                || input.tap_key_sig.is_some()
+                || !input.tap_script_sigs.is_empty()
+            {
+                return Err(PayjoinError::InputPartialSignatures(index));
+            }
+            match sender_inputs.get(&outpoint) {
+                Some(&original_index) => {
+                    if sender_input_indexes
+                        .iter()
+                        .any(|(_, other)| *other == original_index)
+                    {
+                        return Err(PayjoinError::MalformedPsbt);
+                    }
+                    sender_input_indexes.push((index, original_index));
+                    if txin.sequence != original_tx.input[original_index].sequence {
+                        return Err(PayjoinError::SenderInputSequenceChanged(outpoint));
+                    }
+                    if is_finalized(input) {
+                        return Err(PayjoinError::SenderInputFinalized(outpoint));
+                    }
+                    if input.witness_utxo.is_some() || input.non_witness_utxo.is_some() {
+                        return Err(PayjoinError::SenderInputUtxo(outpoint));
+                    }
+                }
+                None => {
+                    if !is_finalized(input) {
+                        return Err(PayjoinError::ReceiverInputNotFinalized(outpoint));
+                    }
+                    let utxo = utxo_of(txin, input)
+                        .ok_or(PayjoinError::ReceiverInputMissingUtxo(outpoint))?;
+                    if ScriptType::of(&utxo.script_pubkey) != self.input_type {
+                        return Err(PayjoinError::MixedInputScripts);
+                    }
+                    receiver_value += utxo.value;
+                }
+            }
+            match sequence {
+                None => sequence = Some(txin.sequence),
+                Some(sequence) if sequence != txin.sequence => {
+                    return Err(PayjoinError::MixedSequence)
+                }
+                Some(_) => {}
+            }
+        }
+        if let Some(missing) = (0..original_tx.input.len())
+            .find(|index| !sender_input_indexes.iter().any(|(_, other)| other == index))
+        {
+            let outpoint = original_tx.input[missing].previous_output;
+            return Err(PayjoinError::SenderInputMissing(outpoint));
+        }
+
+        if let Some(index) = proposal.outputs.iter().position(|output| {
+            !output.bip32_derivation.is_empty() || !output.tap_key_origins.is_empty()
+        }) {
+            return Err(PayjoinError::OutputKeyOrigins(index));
+        }
+        // every original output is matched with an output of the proposal with its script
+        let mut matched = alloc::vec![false; tx.output.len()];
+        let mut contribution = Amount::ZERO;
+        for (original_index, original_out) in original_tx.output.iter().enumerate() {
+            let is_payment = original_out.script_pubkey == self.params.payment_script;
+            if is_payment && !self.params.disable_output_substitution {
+                continue;
+            }
+            let (index, txout) = tx
+                .output
+                .iter()
+                .enumerate()
+                .find(|(index, txout)| {
+                    !matched[*index] && txout.script_pubkey == original_out.script_pubkey
+                })
+                .ok_or(PayjoinError::OutputMissing(original_index))?;
+            matched[index] = true;
+            if Some(original_index) == self.params.additional_fee_output_index {
+                contribution = original_out
+                    .value
+                    .checked_sub(txout.value)
+                    .unwrap_or(Amount::ZERO);
+            } else if is_payment {
+                if txout.value < original_out.value {
+                    return Err(PayjoinError::OutputChanged(original_index));
+                }
+            } else if txout.value != original_out.value {
+                return Err(PayjoinError::OutputChanged(original_index));
+            }
+        }
+
+        let sender_value = self
+            .original
+            .fee()
+            .ok()
+            .and_then(|fee| fee.checked_add(original_tx.output.iter().map(|o| o.value).sum()))
+            .ok_or(PayjoinError::MalformedPsbt)?;
+        let output_value: Amount = tx.output.iter().map(|txout| txout.value).sum();
+        let fee = (sender_value + receiver_value)
+            .checked_sub(output_value)
+            .ok_or(PayjoinError::FeeDecreased)?;
+        if fee < self.original_fee {
+            return Err(PayjoinError::FeeDecreased);
+        }
+        if contribution > self.params.max_additional_fee_contribution {
+            return Err(PayjoinError::FeeContributionExceedsMaximum(contribution));
+        }
+        if contribution > fee - self.original_fee {
+            return Err(PayjoinError::FeeContributionPaysOutputs(contribution));
+        }
+        let added_inputs = (tx.input.len() - original_tx.input.len()) as u64;
+        let added_inputs_fee = self
+            .original_fee_rate
+            .fee_vb(self.input_vsize * added_inputs)
+            .ok_or(PayjoinError::MalformedPsbt)?;
+        if contribution > added_inputs_fee {
+            return Err(PayjoinError::FeeContributionExceedsInputsFee(contribution));
+        }
+        if let Some(min_fee_rate) = self.params.min_fee_rate {
+            let fee_rate = fee / self.signed_weight(&proposal, &sender_input_indexes);
+            if fee_rate < min_fee_rate {
+                return Err(PayjoinError::FeeRateBelowMinimum(fee_rate));
+            }
+        }
+
+        // the wallet needs the UTXOs of its inputs to sign them
+        for (index, original_index) in sender_input_indexes {
+            let original = &self.original.inputs[original_index];
+            let input = &mut proposal.inputs[index];
+            input.witness_utxo = original.witness_utxo.clone();
+            input.non_witness_utxo = original.non_witness_utxo.clone();
+        }
+        Ok(proposal)
+    }
+
+    /// The weight of the payjoin transaction of `proposal` once signed, with the final scripts of
+    /// the original for the inputs of the sender
+    fn signed_weight(&self, proposal: &Psbt, sender_input_indexes: &[(usize, usize)]) -> Weight {
+        let mut tx = proposal.unsigned_tx.clone();
+        for (index, (txin, input)) in tx.input.iter_mut().zip(&proposal.inputs).enumerate() {
+            let input = sender_input_indexes
+                .iter()
+                .find(|(i, _)| *i == index)
+                .map_or(input, |(_, original_index)| {
+                    &self.original.inputs[*original_index]
+                });
+            finalize_txin(txin, input);
+        }
+        tx.weight()
+    }
+}
+
+/// Check the inputs of an original PSBT, returning their script type
+///
+/// All the inputs must have a UTXO, of the same script type, and be signed with `SIGHASH_ALL`.
+pub(crate) fn check_original_inputs(psbt: &Psbt) -> Result<ScriptType, PayjoinError> {
+    let mut input_type = None;
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let utxo = psbt
+            .get_utxo_for(index)
+            .ok_or(PayjoinError::MalformedPsbt)?;
+        let script_type = ScriptType::of(&utxo.script_pubkey);
+        if *input_type.get_or_insert(script_type) != script_type {
+            return Err(PayjoinError::MixedInputScripts);
+        }
+        if input
+            .sighash_type
+            .map_or(false, |sighash| !is_sighash_all(sighash))
+        {
+            return Err(PayjoinError::NonStandardSighash(index));
+        }
+    }
+    input_type.ok_or(PayjoinError::MalformedPsbt)
+}
+
+/// Whether `sighash` signs all the inputs and outputs, as the signatures of a payjoin must
+pub(crate) fn is_sighash_all(sighash: psbt::PsbtSighashType) -> bool {
+    // `SIGHASH_ALL`, or `SIGHASH_DEFAULT` for taproot
+    sighash.to_u32() <= 1
+}
+
+/// The UTXO spent by `txin`, unlike [`PsbtUtils::get_utxo_for`] checked against a previous
+/// transaction which may come from the receiver
+fn utxo_of(txin: &TxIn, input: &psbt::Input) -> Option<TxOut> {
+    match (&input.witness_utxo, &input.non_witness_utxo) {
+        (Some(utxo), _) => Some(utxo.clone()),
+        (None, Some(prev_tx)) if prev_tx.compute_txid() == txin.previous_output.txid => prev_tx
+            .output
+            .get(txin.previous_output.vout as usize)
+            .cloned(),
+        _ => None,
+    }
+}
+
+fn is_finalized(input: &psbt::Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+fn finalize_txin(txin: &mut TxIn, input: &psbt::Input) {
+    if let Some(script_sig) = &input.final_script_sig {
+        txin.script_sig = script_sig.clone();
+    }
+    if let Some(witness) = &input.final_script_witness {
+        txin.witness = witness.clone();
+    }
+}
+
+/// Error of a payjoin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayjoinError {
+    /// A PSBT doesn't have a map for each input and output of its transaction, or an input lacks
+    /// its UTXO
+    MalformedPsbt,
+    /// The inputs of the transaction have different script types
+    MixedInputScripts,
+    /// The input at this index isn't signed with `SIGHASH_ALL`
+    NonStandardSighash(usize),
+    /// The original PSBT isn't finalized
+    OriginalNotFinalized,
+    /// The original PSBT has no output paying [`PayjoinParams::payment_script`]
+    PaymentOutputMissing,
+    /// [`PayjoinParams::additional_fee_output_index`] isn't the index of an output of the sender
+    InvalidFeeOutputIndex(usize),
+    /// The proposal changed the version of the transaction
+    VersionChanged,
+    /// The proposal changed the locktime of the transaction
+    LockTimeChanged,
+    /// The proposal removed this input of the sender
+    SenderInputMissing(OutPoint),
+    /// The proposal changed the sequence of this input of the sender
+    SenderInputSequenceChanged(OutPoint),
+    /// The proposal finalized this input of the sender
+    SenderInputFinalized(OutPoint),
+    /// The proposal kept the UTXO of this input of the sender
+    SenderInputUtxo(OutPoint),
+    /// This input of the receiver isn't finalized
+    ReceiverInputNotFinalized(OutPoint),
+    /// This input of the receiver has no UTXO
+    ReceiverInputMissingUtxo(OutPoint),
+    /// The inputs of the proposal have different sequences
+    MixedSequence,
+    /// The input at this index has key origins
+    InputKeyOrigins(usize),
+    /// The input at this index has partial signatures
+    InputPartialSignatures(usize),
+    /// The output at this index has key origins
+    OutputKeyOrigins(usize),
+    /// The proposal removed the original output at this index
+    OutputMissing(usize),
+    /// The proposal changed the amount of the original output at this index
+    OutputChanged(usize),
+    /// The absolute fee of the proposal is lower than the original fee
+    FeeDecreased,
+    /// The fee contribution is above [`PayjoinParams::max_additional_fee_contribution`]
+    FeeContributionExceedsMaximum(Amount),
+    /// The fee contribution is more than the fee added by the proposal, so it pays for outputs
+    FeeContributionPaysOutputs(Amount),
+    /// The fee contribution is more than the fee of the inputs added by the receiver, at the fee
+    /// rate of the original
+    FeeContributionExceedsInputsFee(Amount),
+    /// The fee rate of the proposal is below [`PayjoinParams::min_fee_rate`]
+    FeeRateBelowMinimum(FeeRate),
+}
+
+impl fmt::Display for PayjoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedPsbt => write!(f, "malformed PSBT"),
+            Self::MixedInputScripts => write!(f, "the inputs have different script types"),
+            Self::NonStandardSighash(index) => {
+                write!(f, "input #{} isn't signed with SIGHASH_ALL", index)
+            }
+            Self::OriginalNotFinalized => write!(f, "the original PSBT isn't finalized"),
+            Self::PaymentOutputMissing => write!(f, "the original PSBT doesn't pay the receiver"),
+            Self::InvalidFeeOutputIndex(index) => {
+                write!(f, "output #{} can't pay for the additional fee", index)
+            }
+            Self::VersionChanged => write!(f, "the proposal changed the transaction version"),
+            Self::LockTimeChanged => write!(f, "the proposal changed the transaction locktime"),
+            Self::SenderInputMissing(outpoint) => {
+                write!(
+                    f,
+                    "the proposal removed the input {} of the sender",
+                    outpoint
+                )
+            }
+            Self::SenderInputSequenceChanged(outpoint) => write!(
+                f,
+                "the proposal changed the sequence of the input {} of the sender",
+                outpoint
+            ),
+            Self::SenderInputFinalized(outpoint) => write!(
+                f,
+                "the proposal finalized the input {} of the sender",
+                outpoint
+            ),
+            Self::SenderInputUtxo(outpoint) => write!(
+                f,
+                "the proposal kept the UTXO of the input {} of the sender",
+                outpoint
+            ),
+            Self::ReceiverInputNotFinalized(outpoint) => {
+                write!(f, "the input {} of the receiver isn't finalized", outpoint)
+            }
+            Self::ReceiverInputMissingUtxo(outpoint) => {
+                write!(f, "the input {} of the receiver has no UTXO", outpoint)
+            }
+            Self::MixedSequence => write!(f, "the inputs have different sequences"),
+            Self::InputKeyOrigins(index) => write!(f, "input #{} has key origins", index),
+            Self::InputPartialSignatures(index) => {
+                write!(f, "input #{} has partial signatures", index)
+            }
+            Self::OutputKeyOrigins(index) => write!(f, "output #{} has key origins", index),
+            Self::OutputMissing(index) => {
+                write!(f, "the proposal removed the original output #{}", index)
+            }
+            Self::OutputChanged(index) => write!(
+                f,
+                "the proposal changed the amount of the original output #{}",
+                index
+            ),
+            Self::FeeDecreased => write!(f, "the proposal decreased the absolute fee"),
+            Self::FeeContributionExceedsMaximum(amount) => write!(
+                f,
+                "the fee contribution of {} exceeds the maximum contribution",
+                amount
+            ),
+            Self::FeeContributionPaysOutputs(amount) => write!(
+                f,
+                "the fee contribution of {} is more than the added fee",
+                amount
+            ),
+            Self::FeeContributionExceedsInputsFee(amount) => write!(
+                f,
+                "the fee contribution of {} is more than the fee of the added inputs",
+                amount
+            ),
+            Self::FeeRateBelowMinimum(fee_rate) => write!(
+                f,
+                "the fee rate of {} sat/vB is below the minimum fee rate",
+                fee_rate.to_sat_per_vb_floor()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayjoinError {}
@@ -0,0 +1,145 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Payments made by the wallet, see [`Wallet::find_payments_to`]
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use bdk_chain::collections::{BTreeSet, HashSet};
+use bdk_chain::{ChainPosition, ConfirmationTimeHeightAnchor};
+use bitcoin::{Amount, OutPoint, Script, Transaction, TxOut, Txid};
+
+use super::Wallet;
+
+/// How far back [`Wallet::find_payments_to`] looks for payments
+///
+/// Unconfirmed and unbroadcast payments are always found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeOrHeightWindow {
+    /// The payments confirmed in the last `n` blocks, the tip included
+    Blocks(u32),
+    /// The payments confirmed in a block with a timestamp at or after this UNIX timestamp
+    Since(u64),
+}
+
+impl TimeOrHeightWindow {
+    fn contains(&self, anchor: &ConfirmationTimeHeightAnchor, tip_height: u32) -> bool {
+        match *self {
+            Self::Blocks(n) => tip_height.saturating_sub(anchor.confirmation_height) < n,
+            Self::Since(time) => anchor.confirmation_time >= time,
+        }
+    }
+}
+
+impl Wallet {
+    /// The transactions of the wallet never seen in the mempool nor in a block, such as the
+    /// transactions applied with [`Wallet::insert_tx`] before they are broadcast.
+    pub fn unbroadcast_transactions(&self) -> impl Iterator<Item = Arc<Transaction>> + '_ {
+        self.indexed_graph
+            .graph()
+            .full_txs()
+            .filter(|tx| tx.anchors.is_empty() && tx.last_seen_unconfirmed == 0)
+            .map(|tx| tx.tx)
+    }
+
+    /// Find the transactions of the wallet paying to `script`, and paying exactly `amount` if it
+    /// is given, within `window`.
+    ///
+    /// The canonical transactions are searched, and the [unbroadcast] ones unless they conflict
+    /// with a confirmed transaction: a payment built and applied to the wallet, but whose
+    /// broadcast wasn't recorded, is found. The txids are sorted.
+    ///
+    /// [unbroadcast]: Self::unbroadcast_transactions
+    pub fn find_payments_to(
+        &self,
+        script: &Script,
+        amount: Option<Amount>,
+        window: TimeOrHeightWindow,
+    ) -> Vec<Txid> {
+        let pays = |txout: &TxOut| {
+            txout.script_pubkey == *script && amount.map_or(true, |amount| txout.value == amount)
+        };
+        self.find_payments(script, pays, window, &HashSet::new())
+            .into_iter()
+            .collect()
+    }
+
+    /// The payments of `window` with the same script and amount as an output of `outputs`, except
+    /// for the transactions spending an outpoint of `replaced`.
+    pub(crate) fn duplicate_payments(
+        &self,
+        outputs: &[TxOut],
+        window: TimeOrHeightWindow,
+        replaced: &HashSet<OutPoint>,
+    ) -> BTreeSet<Txid> {
+        outputs
+            .iter()
+            .flat_map(|output| {
+                self.find_payments(
+                    &output.script_pubkey,
+                    |txout| txout == output,
+                    window,
+                    replaced,
+                )
+            })
+            .collect()
+    }
+
+    fn find_payments(
+        &self,
+        script: &Script,
+        pays: impl Fn(&TxOut) -> bool,
+        window: TimeOrHeightWindow,
+        replaced: &HashSet<OutPoint>,
+    ) -> BTreeSet<Txid> {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+        let is_confirmed = |txid| {
+            matches!(
+                graph.get_chain_position(&self.chain, chain_tip, txid),
+                Some(ChainPosition::Confirmed(_))
+            )
+        };
+        graph
+            .txids_paying_to(script)
+            .iter()
+            .copied()
+            .filter(|&txid| {
+                let tx_node = match graph.get_tx_node(txid) {
+                    Some(tx_node) => tx_node,
+                    None => return false,
+                };
+                if !tx_node.tx.output.iter().any(&pays)
+                    || tx_node
+                        .tx
+                        .input
+                        .iter()
+                        .any(|txin| replaced.contains(&txin.previous_output))
+                {
+                    return false;
+                }
+                match graph.get_chain_position(&self.chain, chain_tip, txid) {
+                    Some(ChainPosition::Confirmed(anchor)) => {
+                        window.contains(anchor, chain_tip.height)
+                    }
+                    Some(ChainPosition::Unconfirmed(_)) => true,
+                    None => {
+                        tx_node.anchors.is_empty()
+                            && tx_node.last_seen_unconfirmed == 0
+                            && !graph
+                                .walk_conflicts(&tx_node.tx, |_, txid| Some(txid))
+                                .any(is_confirmed)
+                    }
+                }
+            })
+            .collect()
+    }
+}
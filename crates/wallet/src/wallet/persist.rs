@@ -0,0 +1,265 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Wallet persistence
+//!
+//! The [`Wallet`] stages every change made to it in a [`ChangeSet`], which has to be written to
+//! some storage to survive restarts. The traits in this module describe such a storage, so that
+//! the wallet can take care of handing it the staged changes with [`Wallet::persist`]:
+//!
+//! * [`WalletPersister`] for blocking storages.
+//! * [`AsyncWalletPersister`] for storages that are accessed asynchronously, used with
+//!   [`Wallet::persist_async`]. Any [`WalletPersister`] can be used as an async one by wrapping
+//!   it in [`SyncPersister`].
+//!
+//! In both cases the staged changes are only cleared once they were persisted successfully: if
+//! the storage returns an error they are kept, and will be written again by the next call.
+//!
+//! The [`kv`] module has a [`WalletPersister`] over any key-value storage, such as the storages of
+//! a browser. [`MemoryPersister`] keeps the changes in memory, for tests and ephemeral wallets.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk_wallet::wallet::persist::WalletPersister;
+//! # use bdk_wallet::wallet::ChangeSet;
+//! # use bdk_wallet::{KeychainKind, Wallet};
+//! # use bdk_wallet::chain::Append;
+//! # use bitcoin::Network;
+//! /// Keeps all the changes in memory
+//! #[derive(Default)]
+//! struct MemoryPersister(ChangeSet);
+//!
+//! impl WalletPersister for MemoryPersister {
+//!     type Error = core::convert::Infallible;
+//!
+//!     fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+//!         Ok(Some(self.0.clone()).filter(|changeset| !changeset.is_empty()))
+//!     }
+//!
+//!     fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+//!         self.0.append(changeset.clone());
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut persister = MemoryPersister::default();
+//! let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/0/*)";
+//! let change_descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1/*)";
+//! let changeset = persister.initialize()?;
+//! let mut wallet = Wallet::new_or_load(descriptor, change_descriptor, changeset, Network::Testnet)?;
+//! let address = wallet.reveal_next_address(KeychainKind::External);
+//! assert!(wallet.persist(&mut persister)?);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod kv;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::future::Future;
+use core::pin::Pin;
+
+use bdk_chain::Append;
+
+use super::{ChangeSet, Wallet};
+
+/// A boxed future returning a `Result`, as returned by [`AsyncWalletPersister`]'s methods
+pub type FutureResult<'a, T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'a>>;
+
+/// A storage for the changes of a [`Wallet`]
+///
+/// See [the `persist` module](crate::wallet::persist) for an example.
+pub trait WalletPersister {
+    /// Error returned by the storage
+    type Error;
+
+    /// Initialize the storage, and return all the changes persisted so far (if any)
+    ///
+    /// The returned [`ChangeSet`] can be used to load the wallet, see [`Wallet::new_or_load`].
+    fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error>;
+
+    /// Persist `changeset` on top of the changes already stored
+    fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error>;
+}
+
+/// An asynchronous storage for the changes of a [`Wallet`]
+///
+/// This is the async counterpart of [`WalletPersister`]. Since this crate supports Rust versions
+/// without `async fn` in traits, the methods return boxed futures.
+pub trait AsyncWalletPersister {
+    /// Error returned by the storage
+    type Error;
+
+    /// Initialize the storage, and return all the changes persisted so far (if any)
+    fn initialize<'a>(&'a mut self) -> FutureResult<'a, Option<ChangeSet>, Self::Error>
+    where
+        Self: 'a;
+
+    /// Persist `changeset` on top of the changes already stored
+    fn persist<'a>(&'a mut self, changeset: &'a ChangeSet) -> FutureResult<'a, (), Self::Error>
+    where
+        Self: 'a;
+}
+
+/// Adapter using a [`WalletPersister`] as an [`AsyncWalletPersister`]
+///
+/// The blocking calls of the inner persister run when the futures are created, so this is only
+/// meant for storages that are quick to write to.
+#[derive(Debug, Default)]
+pub struct SyncPersister<P>(pub P);
+
+impl<P> AsyncWalletPersister for SyncPersister<P>
+where
+    P: WalletPersister + Send,
+    P::Error: Send,
+{
+    type Error = P::Error;
+
+    fn initialize<'a>(&'a mut self) -> FutureResult<'a, Option<ChangeSet>, Self::Error>
+    where
+        Self: 'a,
+    {
+        let result = self.0.initialize();
+        Box::pin(async move { result })
+    }
+
+    fn persist<'a>(&'a mut self, changeset: &'a ChangeSet) -> FutureResult<'a, (), Self::Error>
+    where
+        Self: 'a,
+    {
+        // without `std`, descriptors are not `Sync`: don't hold `changeset` across the future
+        let result = self.0.persist(changeset);
+        Box::pin(async move { result })
+    }
+}
+
+/// A persister which keeps the changes in memory, as a list of changesets
+///
+/// This is a reference implementation of both [`WalletPersister`] and [`AsyncWalletPersister`],
+/// for tests and for ephemeral wallets which don't need to survive restarts. The state can be
+/// captured with [`snapshot`] and reconstructed later, or in another thread, with [`restore`].
+///
+/// [`snapshot`]: MemoryPersister::snapshot
+/// [`restore`]: MemoryPersister::restore
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryPersister {
+    changesets: Vec<ChangeSet>,
+}
+
+impl MemoryPersister {
+    /// A new, empty persister
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The persisted changesets, in the order they were persisted
+    pub fn changesets(&self) -> &[ChangeSet] {
+        &self.changesets
+    }
+
+    /// The aggregate of the persisted changesets
+    pub fn aggregate(&self) -> ChangeSet {
+        let mut aggregate = ChangeSet::default();
+        for changeset in &self.changesets {
+            aggregate.append(changeset.clone());
+        }
+        aggregate
+    }
+
+    /// Serialize the aggregate of the persisted changesets, to [`restore`] it later
+    ///
+    /// [`restore`]: MemoryPersister::restore
+    pub fn snapshot(&self) -> Vec<u8> {
+        serde_json::to_vec(&self.aggregate()).expect("a changeset must serialize")
+    }
+
+    /// Deserialize a [`snapshot`], as a persister whose only changeset is the aggregate of the
+    /// snapshotted persister
+    ///
+    /// [`snapshot`]: MemoryPersister::snapshot
+    pub fn restore(snapshot: &[u8]) -> Result<Self, serde_json::Error> {
+        let aggregate: ChangeSet = serde_json::from_slice(snapshot)?;
+        let mut persister = Self::new();
+        if !aggregate.is_empty() {
+            persister.changesets.push(aggregate);
+        }
+        Ok(persister)
+    }
+}
+
+impl WalletPersister for MemoryPersister {
+    type Error = Infallible;
+
+    fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+        Ok(Some(self.aggregate()).filter(|changeset| !changeset.is_empty()))
+    }
+
+    fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+        if !changeset.is_empty() {
+            self.changesets.push(changeset.clone());
+        }
+        Ok(())
+    }
+}
+
+impl AsyncWalletPersister for MemoryPersister {
+    type Error = Infallible;
+
+    fn initialize<'a>(&'a mut self) -> FutureResult<'a, Option<ChangeSet>, Self::Error>
+    where
+        Self: 'a,
+    {
+        let result = WalletPersister::initialize(self);
+        Box::pin(async move { result })
+    }
+
+    fn persist<'a>(&'a mut self, changeset: &'a ChangeSet) -> FutureResult<'a, (), Self::Error>
+    where
+        Self: 'a,
+    {
+        let result = WalletPersister::persist(self, changeset);
+        Box::pin(async move { result })
+    }
+}
+
+impl Wallet {
+    /// Persist the staged changes of the wallet with `persister`.
+    ///
+    /// Returns whether there was anything to persist. The staged changes are only cleared if
+    /// `persister` succeeds, otherwise they are kept and the error is returned.
+    pub fn persist<P: WalletPersister>(&mut self, persister: &mut P) -> Result<bool, P::Error> {
+        let changeset = match self.staged() {
+            Some(changeset) => changeset,
+            None => return Ok(false),
+        };
+        persister.persist(changeset)?;
+        self.take_staged();
+        Ok(true)
+    }
+
+    /// Persist the staged changes of the wallet with the async `persister`.
+    ///
+    /// This works like [`Wallet::persist`]: the staged changes are only cleared if `persister`
+    /// succeeds. If the returned future is dropped before completing, they are kept as well.
+    pub async fn persist_async<P: AsyncWalletPersister>(
+        &mut self,
+        persister: &mut P,
+    ) -> Result<bool, P::Error> {
+        let changeset = match self.staged() {
+            Some(changeset) => changeset,
+            None => return Ok(false),
+        };
+        persister.persist(changeset).await?;
+        self.take_staged();
+        Ok(true)
+    }
+}
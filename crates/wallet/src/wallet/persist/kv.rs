@@ -0,0 +1,281 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Persistence over a key-value storage
+//!
+//! Storages such as the `localStorage` or `IndexedDB` of a browser have neither files nor SQL,
+//! but they all map keys to values. [`KvPersister`] is a [`WalletPersister`] over any such
+//! storage, which only has to implement the tiny [`KvStore`] trait.
+//!
+//! Each persisted [`ChangeSet`] is written as a new value, under a key made of the prefix of the
+//! persister and of a sequence number which increases with every write, so nothing is ever
+//! overwritten. The changesets are aggregated in the order of their sequence numbers when the
+//! persister is initialized.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk_wallet::wallet::persist::{kv::{KvPersister, MemoryKvStore}, WalletPersister};
+//! # use bdk_wallet::{KeychainKind, Wallet};
+//! # use bitcoin::Network;
+//! let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/0/*)";
+//! let change_descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1/*)";
+//! let store = MemoryKvStore::default();
+//!
+//! let mut persister = KvPersister::new(store.clone());
+//! let changeset = persister.initialize()?;
+//! let mut wallet = Wallet::new_or_load(descriptor, change_descriptor, changeset, Network::Testnet)?;
+//! let address = wallet.reveal_next_address(KeychainKind::External);
+//! assert!(wallet.persist(&mut persister)?);
+//!
+//! // the wallet is loaded from the same storage, such as after a page reload
+//! let changeset = KvPersister::new(store).initialize()?;
+//! let wallet = Wallet::new_or_load(descriptor, change_descriptor, changeset, Network::Testnet)?;
+//! assert_eq!(wallet.derivation_index(KeychainKind::External), Some(address.index));
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+
+use bdk_chain::Append;
+
+use super::WalletPersister;
+use crate::wallet::ChangeSet;
+
+/// The prefix of the keys of a [`KvPersister`] created with [`KvPersister::new`]
+pub const DEFAULT_PREFIX: &str = "bdk_wallet/";
+
+/// The part of the keys of the changesets after the prefix of the persister, before their
+/// sequence numbers
+const CHANGESET_KEY: &str = "changeset/";
+
+/// A storage which maps string keys to byte values
+///
+/// Implement this over the storage of the platform, such as `localStorage` or `IndexedDB` in a
+/// browser, to use it with [`KvPersister`].
+pub trait KvStore {
+    /// Error returned by the storage
+    type Error;
+
+    /// The value of `key`, or `None` if there is none
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// Set the value of `key` to `value`, replacing the previous value if any
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error>;
+
+    /// All the keys starting with `prefix`, in any order
+    fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Self::Error>;
+}
+
+/// A [`WalletPersister`] over a [`KvStore`], see the [module-level documentation](self)
+#[derive(Debug)]
+pub struct KvPersister<S> {
+    store: S,
+    prefix: String,
+    /// The sequence number of the next changeset, `None` until the stored keys are read
+    next_seq: Option<u64>,
+}
+
+impl<S: KvStore> KvPersister<S> {
+    /// Persist to `store` under the keys starting with [`DEFAULT_PREFIX`]
+    pub fn new(store: S) -> Self {
+        Self::with_prefix(store, DEFAULT_PREFIX)
+    }
+
+    /// Persist to `store` under the keys starting with `prefix`
+    ///
+    /// Several wallets can share a storage with different prefixes. No prefix may start with
+    /// another one, or the wallets would read the changes of each other.
+    pub fn with_prefix(store: S, prefix: impl Into<String>) -> Self {
+        Self {
+            store,
+            prefix: prefix.into(),
+            next_seq: None,
+        }
+    }
+
+    /// The inner [`KvStore`]
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Consume the persister, returning the inner [`KvStore`]
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    fn changeset_prefix(&self) -> String {
+        format!("{}{}", self.prefix, CHANGESET_KEY)
+    }
+
+    /// The key of the changeset with sequence number `seq`
+    fn changeset_key(&self, seq: u64) -> String {
+        // zero-padded, so that the keys also sort in the order of the changesets
+        format!("{}{:020}", self.changeset_prefix(), seq)
+    }
+
+    /// The sequence number after the last stored changeset
+    fn next_stored_seq(&self) -> Result<u64, KvPersisterError<S::Error>> {
+        Ok(self
+            .stored_changesets()?
+            .last()
+            .map_or(0, |(seq, _)| seq + 1))
+    }
+
+    /// The sequence numbers of the stored changesets, in order, alongside their keys
+    fn stored_changesets(&self) -> Result<Vec<(u64, String)>, KvPersisterError<S::Error>> {
+        let prefix = self.changeset_prefix();
+        let mut changesets = self
+            .store
+            .keys_with_prefix(&prefix)
+            .map_err(KvPersisterError::Store)?
+            .into_iter()
+            .map(|key| {
+                match key
+                    .strip_prefix(prefix.as_str())
+                    .and_then(|seq| seq.parse::<u64>().ok())
+                {
+                    Some(seq) => Ok((seq, key)),
+                    None => Err(KvPersisterError::InvalidKey(key)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        changesets.sort_unstable();
+        Ok(changesets)
+    }
+}
+
+impl<S: KvStore> WalletPersister for KvPersister<S> {
+    type Error = KvPersisterError<S::Error>;
+
+    fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+        let mut aggregate = ChangeSet::default();
+        let mut next_seq = 0;
+        for (seq, key) in self.stored_changesets()? {
+            let value = match self.store.get(&key).map_err(KvPersisterError::Store)? {
+                Some(value) => value,
+                None => return Err(KvPersisterError::MissingValue(key)),
+            };
+            let changeset: ChangeSet =
+                serde_json::from_slice(&value).map_err(KvPersisterError::Serde)?;
+            aggregate.append(changeset);
+            next_seq = seq + 1;
+        }
+        self.next_seq = Some(next_seq);
+        Ok(Some(aggregate).filter(|changeset| !changeset.is_empty()))
+    }
+
+    fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+        if changeset.is_empty() {
+            return Ok(());
+        }
+        let mut seq = match self.next_seq {
+            Some(seq) => seq,
+            // never overwrite the changesets of a storage which wasn't initialized
+            None => self.next_stored_seq()?,
+        };
+        let mut key = self.changeset_key(seq);
+        // another handle of the storage may have written since
+        if self
+            .store
+            .get(&key)
+            .map_err(KvPersisterError::Store)?
+            .is_some()
+        {
+            seq = self.next_stored_seq()?;
+            key = self.changeset_key(seq);
+        }
+        let value = serde_json::to_vec(changeset).map_err(KvPersisterError::Serde)?;
+        self.store
+            .set(&key, &value)
+            .map_err(KvPersisterError::Store)?;
+        self.next_seq = Some(seq + 1);
+        Ok(())
+    }
+}
+
+/// Error of a [`KvPersister`]
+#[derive(Debug)]
+pub enum KvPersisterError<E> {
+    /// The storage failed
+    Store(E),
+    /// A changeset failed to serialize or deserialize
+    Serde(serde_json::Error),
+    /// A key with the prefix of the changesets doesn't end with a sequence number
+    InvalidKey(String),
+    /// A listed key has no value, it may have been removed in the meantime
+    MissingValue(String),
+}
+
+impl<E: fmt::Display> fmt::Display for KvPersisterError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Store(e) => write!(f, "storage error: {}", e),
+            Self::Serde(e) => write!(f, "failed to (de)serialize a changeset: {}", e),
+            Self::InvalidKey(key) => write!(f, "invalid changeset key `{}`", key),
+            Self::MissingValue(key) => write!(f, "no value for changeset key `{}`", key),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug + fmt::Display> std::error::Error for KvPersisterError<E> {}
+
+/// A [`KvStore`] in memory, for tests
+///
+/// The clones of a `MemoryKvStore` share the same entries, as handles to the same storage.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct MemoryKvStore(
+    std::sync::Arc<std::sync::Mutex<alloc::collections::BTreeMap<String, Vec<u8>>>>,
+);
+
+#[cfg(feature = "std")]
+impl MemoryKvStore {
+    /// The number of entries in the storage
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Whether the storage has no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, alloc::collections::BTreeMap<String, Vec<u8>>> {
+        // the entries are always left consistent, even by a panicking thread
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(feature = "std")]
+impl KvStore for MemoryKvStore {
+    type Error = core::convert::Infallible;
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self.entries().get(key).cloned())
+    }
+
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        self.entries().insert(String::from(key), value.to_vec());
+        Ok(())
+    }
+
+    fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<String>, Self::Error> {
+        Ok(self
+            .entries()
+            .range(String::from(prefix)..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+}
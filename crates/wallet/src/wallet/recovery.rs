@@ -0,0 +1,359 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Recovery of the accounts of a key whose derivation paths aren't known
+//!
+//! A seed restored without the wallet files doesn't tell which derivation standard its wallet
+//! used. [`scan_templates`] probes the standard templates (BIP-44, BIP-49, BIP-84 and BIP-86),
+//! for a few accounts each, with a single full scan of a [`SyncBackend`] and a small stop gap to
+//! bound the number of requests. It reports the accounts with activity, how deep their addresses
+//! were used and how much they received, with the descriptors to create a wallet from.
+//!
+//! ```no_run
+//! # use bdk_wallet::bitcoin::{bip32::Xpriv, Network};
+//! # use bdk_wallet::wallet::recovery::{scan_templates, RecoveryKeychain, RecoveryOptions};
+//! # use bdk_wallet::Wallet;
+//! # use core::str::FromStr;
+//! # fn backend() -> Box<dyn bdk_chain::spk_client::SyncBackend<RecoveryKeychain>> { todo!() }
+//! let xprv = Xpriv::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS")?;
+//! let accounts = scan_templates(&*backend(), xprv, Network::Testnet, &RecoveryOptions::default())?;
+//! // the account which received the most
+//! if let Some(account) = accounts.iter().max_by_key(|account| account.received) {
+//!     let wallet = Wallet::new(
+//!         &account.descriptor,
+//!         &account.change_descriptor,
+//!         Network::Testnet,
+//!     )?;
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use bdk_chain::collections::{BTreeMap, BTreeSet};
+use bdk_chain::keychain::KeychainTxOutIndex;
+use bdk_chain::local_chain::CheckPoint;
+use bdk_chain::spk_client::{BackendError, BackendOptions, FullScanRequest, SyncBackend};
+use bdk_chain::{BlockId, SpkIterator};
+use bitcoin::bip32::{Xpriv, Xpub};
+use bitcoin::constants::genesis_block;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Amount, Network};
+use miniscript::{Descriptor, DescriptorPublicKey};
+
+use crate::descriptor::calc_checksum;
+use crate::KeychainKind;
+
+/// The default stop gap of [`RecoveryOptions`]
+pub const DEFAULT_RECOVERY_STOP_GAP: usize = 5;
+
+/// A standard derivation template probed by [`scan_templates`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RecoveryTemplate {
+    /// BIP-44, `pkh()` at `m/44'/coin'/account'`
+    Bip44,
+    /// BIP-49, `sh(wpkh())` at `m/49'/coin'/account'`
+    Bip49,
+    /// BIP-84, `wpkh()` at `m/84'/coin'/account'`
+    Bip84,
+    /// BIP-86, `tr()` at `m/86'/coin'/account'`
+    Bip86,
+}
+
+impl RecoveryTemplate {
+    /// All the templates
+    pub const ALL: [RecoveryTemplate; 4] = [
+        RecoveryTemplate::Bip44,
+        RecoveryTemplate::Bip49,
+        RecoveryTemplate::Bip84,
+        RecoveryTemplate::Bip86,
+    ];
+
+    /// The purpose of the derivation path of the template
+    pub fn purpose(&self) -> u32 {
+        match self {
+            RecoveryTemplate::Bip44 => 44,
+            RecoveryTemplate::Bip49 => 49,
+            RecoveryTemplate::Bip84 => 84,
+            RecoveryTemplate::Bip86 => 86,
+        }
+    }
+
+    /// The descriptor of the template over the key expression `key`
+    fn wrap(&self, key: &str) -> String {
+        match self {
+            RecoveryTemplate::Bip44 => format!("pkh({})", key),
+            RecoveryTemplate::Bip49 => format!("sh(wpkh({}))", key),
+            RecoveryTemplate::Bip84 => format!("wpkh({})", key),
+            RecoveryTemplate::Bip86 => format!("tr({})", key),
+        }
+    }
+}
+
+/// The key whose accounts [`scan_templates`] looks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryKey {
+    /// A master private key, the accounts are derived from it
+    Xprv(Xpriv),
+    /// An account public key: the hardened derivation of the accounts isn't possible from it,
+    /// only the templates are probed, with this key as their account key
+    Xpub(Xpub),
+}
+
+impl From<Xpriv> for RecoveryKey {
+    fn from(xprv: Xpriv) -> Self {
+        RecoveryKey::Xprv(xprv)
+    }
+}
+
+impl From<Xpub> for RecoveryKey {
+    fn from(xpub: Xpub) -> Self {
+        RecoveryKey::Xpub(xpub)
+    }
+}
+
+/// The keychains of the full scan of [`scan_templates`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RecoveryKeychain {
+    /// The template
+    pub template: RecoveryTemplate,
+    /// The account, always 0 for a [`RecoveryKey::Xpub`]
+    pub account: u32,
+    /// The keychain of the account
+    pub keychain: KeychainKind,
+}
+
+/// The templates and accounts probed by [`scan_templates`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryOptions {
+    /// The templates probed, all of them by default
+    pub templates: Vec<RecoveryTemplate>,
+    /// How many accounts of each template are probed, from account 0, 3 by default
+    pub accounts: u32,
+    /// After how many script pubkeys without transactions the scan of a keychain stops,
+    /// [`DEFAULT_RECOVERY_STOP_GAP`] by default
+    pub stop_gap: usize,
+    /// How many script pubkeys are requested at once
+    pub batch_size: usize,
+}
+
+impl Default for RecoveryOptions {
+    fn default() -> Self {
+        Self {
+            templates: RecoveryTemplate::ALL.to_vec(),
+            accounts: 3,
+            stop_gap: DEFAULT_RECOVERY_STOP_GAP,
+            batch_size: BackendOptions::default().batch_size,
+        }
+    }
+}
+
+/// An account with activity found by [`scan_templates`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveredAccount {
+    /// The template of the account
+    pub template: RecoveryTemplate,
+    /// The account
+    pub account: u32,
+    /// The last derivation index of the external keychain with transactions
+    pub last_active_external: Option<u32>,
+    /// The last derivation index of the internal keychain with transactions
+    pub last_active_internal: Option<u32>,
+    /// The total received by the account, whether it was spent since or not
+    pub received: Amount,
+    /// The number of transactions paying the account
+    pub tx_count: usize,
+    /// The external descriptor of the account, with its checksum, and the private key for a
+    /// [`RecoveryKey::Xprv`]
+    pub descriptor: String,
+    /// The internal descriptor of the account, like [`RecoveredAccount::descriptor`]
+    pub change_descriptor: String,
+}
+
+/// Error returned by [`scan_templates`]
+#[derive(Debug)]
+pub enum RecoveryError {
+    /// A descriptor of the templates can't be built from the key
+    Descriptor(miniscript::Error),
+    /// The full scan failed
+    Backend(BackendError),
+}
+
+impl fmt::Display for RecoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Descriptor(err) => write!(f, "Can't build the descriptor of a template: {}", err),
+            Self::Backend(err) => write!(f, "The full scan failed: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RecoveryError {}
+
+impl From<miniscript::Error> for RecoveryError {
+    fn from(err: miniscript::Error) -> Self {
+        Self::Descriptor(err)
+    }
+}
+
+impl From<BackendError> for RecoveryError {
+    fn from(err: BackendError) -> Self {
+        Self::Backend(err)
+    }
+}
+
+/// The descriptor of `keychain` of `template` and `account`, with its checksum
+fn template_descriptor(key: &RecoveryKey, network: Network, keychain: &RecoveryKeychain) -> String {
+    let change = match keychain.keychain {
+        KeychainKind::Internal => 1,
+        _ => 0,
+    };
+    let key = match key {
+        RecoveryKey::Xprv(xprv) => {
+            let coin = if network == Network::Bitcoin { 0 } else { 1 };
+            format!(
+                "{}/{}'/{}'/{}'/{}/*",
+                xprv,
+                keychain.template.purpose(),
+                coin,
+                keychain.account,
+                change
+            )
+        }
+        RecoveryKey::Xpub(xpub) => format!("{}/{}/*", xpub, change),
+    };
+    let descriptor = keychain.template.wrap(&key);
+    let checksum = calc_checksum(&descriptor).expect("the descriptor is valid");
+    format!("{}#{}", descriptor, checksum)
+}
+
+/// Probe the accounts of the templates of `options` derived from `key`, and return the ones
+/// with activity, in the order of their template and account
+///
+/// All the keychains are scanned with a single full scan of `backend`, from the genesis block
+/// of `network`, each keychain stopping after [`RecoveryOptions::stop_gap`] script pubkeys
+/// without transactions: at most `templates * accounts * 2 * stop_gap` script pubkeys are
+/// requested if none has transactions. A wallet which used an address beyond the stop gap is
+/// found anyway by a full scan of the wallet created from the descriptors returned, with a larger
+/// stop gap.
+///
+/// The coin type of the derivation paths is 0 on [`Network::Bitcoin`] and 1 on the other
+/// networks.
+pub fn scan_templates(
+    backend: &dyn SyncBackend<RecoveryKeychain>,
+    key: impl Into<RecoveryKey>,
+    network: Network,
+    options: &RecoveryOptions,
+) -> Result<Vec<RecoveredAccount>, RecoveryError> {
+    let key = key.into();
+    let accounts = match key {
+        RecoveryKey::Xprv(_) => options.accounts,
+        RecoveryKey::Xpub(_) => options.accounts.min(1),
+    };
+    let secp = Secp256k1::new();
+    let mut descriptors = BTreeMap::new();
+    for template in &options.templates {
+        for account in 0..accounts {
+            for keychain in [KeychainKind::External, KeychainKind::Internal] {
+                let keychain = RecoveryKeychain {
+                    template: *template,
+                    account,
+                    keychain,
+                };
+                let descriptor = template_descriptor(&key, network, &keychain);
+                descriptors.insert(keychain, descriptor);
+            }
+        }
+    }
+
+    let genesis = CheckPoint::new(BlockId {
+        height: 0,
+        hash: genesis_block(network).block_hash(),
+    });
+    let mut request = FullScanRequest::from_chain_tip(genesis);
+    let mut index = KeychainTxOutIndex::<RecoveryKeychain>::new(0);
+    for (keychain, descriptor) in &descriptors {
+        let (descriptor, _) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)?;
+        request = request.set_spks_for_keychain(*keychain, SpkIterator::new(descriptor.clone()));
+        let _ = index
+            .insert_descriptor(*keychain, descriptor)
+            .expect("the templates have different descriptors");
+    }
+    let result = backend.full_scan(
+        request,
+        BackendOptions {
+            stop_gap: options.stop_gap,
+            batch_size: options.batch_size,
+            // the fees of the transactions aren't needed
+            fetch_prev_txouts: false,
+        },
+    )?;
+
+    let _ = index.reveal_to_target_multi(&result.last_active_indices);
+    let mut received = BTreeMap::<(RecoveryTemplate, u32), Amount>::new();
+    let mut txids = BTreeMap::<(RecoveryTemplate, u32), BTreeSet<_>>::new();
+    for tx in result.graph_update.full_txs() {
+        for txout in &tx.output {
+            if let Some((keychain, _)) = index.index_of_spk(&txout.script_pubkey) {
+                let account = (keychain.template, keychain.account);
+                *received.entry(account).or_insert(Amount::ZERO) += txout.value;
+                txids.entry(account).or_default().insert(tx.txid);
+            }
+        }
+    }
+
+    let last_active = |template, account, keychain| {
+        result
+            .last_active_indices
+            .get(&RecoveryKeychain {
+                template,
+                account,
+                keychain,
+            })
+            .copied()
+    };
+    let descriptor = |template, account, keychain| {
+        descriptors[&RecoveryKeychain {
+            template,
+            account,
+            keychain,
+        }]
+            .to_string()
+    };
+    Ok(descriptors
+        .keys()
+        .filter(|keychain| keychain.keychain == KeychainKind::External)
+        .map(|keychain| (keychain.template, keychain.account))
+        .filter_map(|(template, account)| {
+            let last_active_external = last_active(template, account, KeychainKind::External);
+            let last_active_internal = last_active(template, account, KeychainKind::Internal);
+            if last_active_external.is_none() && last_active_internal.is_none() {
+                return None;
+            }
+            Some(RecoveredAccount {
+                template,
+                account,
+                last_active_external,
+                last_active_internal,
+                received: received
+                    .get(&(template, account))
+                    .copied()
+                    .unwrap_or(Amount::ZERO),
+                tx_count: txids.get(&(template, account)).map_or(0, BTreeSet::len),
+                descriptor: descriptor(template, account, KeychainKind::External),
+                change_descriptor: descriptor(template, account, KeychainKind::Internal),
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,252 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Replacement status of the wallet transactions
+//!
+//! A payment is attempted by a transaction and by each of its replacements: the transactions of
+//! the graph which spend one of the inputs of an attempt are attempts of the same payment.
+
+use alloc::vec::Vec;
+
+use bdk_chain::collections::BTreeSet;
+use bdk_chain::spk_client::CpfpInfo;
+use bdk_chain::tx_graph::CalculateFeeError;
+use bdk_chain::ChainPosition;
+use bitcoin::{Amount, FeeRate, OutPoint, Transaction, Txid, Weight};
+
+use super::tx_builder::PreviousFee;
+use super::Wallet;
+
+/// The replacement status of a transaction, see [`Wallet::tx_replacement_info`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacementInfo {
+    /// The transaction
+    pub txid: Txid,
+    /// Whether the transaction explicitly signals replaceability, per BIP-125
+    pub signals_rbf: bool,
+    /// The other attempts of the same payment, ordered by txid
+    ///
+    /// These are the transactions spending one of the inputs of the transaction, and recursively
+    /// the ones spending one of their inputs.
+    pub conflicts: Vec<Txid>,
+    /// The attempt currently preferred by the canonicalization: the transaction itself, one of
+    /// its conflicts, or `None` if all of them were evicted
+    ///
+    /// If the attempts spend different sets of inputs, more than one of them may be canonical: the
+    /// first by txid is returned.
+    pub canonical: Option<Txid>,
+}
+
+impl ReplacementInfo {
+    /// Whether the transaction itself is the canonical attempt
+    pub fn is_canonical(&self) -> bool {
+        self.canonical == Some(self.txid)
+    }
+}
+
+/// How the fee of an unconfirmed transaction of the wallet can be bumped, see
+/// [`Wallet::bump_candidates`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BumpCandidate {
+    /// The transaction
+    pub txid: Txid,
+    /// Whether the transaction explicitly signals replaceability, per BIP-125
+    pub signals_rbf: bool,
+    /// Whether all the inputs of the transaction spend outputs of the wallet, so that it can be
+    /// replaced without the cooperation of another party
+    pub all_inputs_ours: bool,
+    /// The unspent outputs of the transaction owned by the wallet, which a child can spend to
+    /// bump its fee
+    pub cpfp_outputs: Vec<OutPoint>,
+    /// The fee of the transaction, `None` if some of its previous outputs are unknown
+    pub fee: Option<Amount>,
+    /// The fee rate of the transaction together with its unconfirmed ancestors, `None` if the
+    /// fee of one of them is unknown
+    pub package_fee_rate: Option<FeeRate>,
+    /// The minimum fee a replacement of the same weight must pay on top of the fee of the
+    /// transaction, `None` if the fee is unknown
+    pub min_replacement_extra_fee: Option<Amount>,
+}
+
+impl BumpCandidate {
+    /// Whether [`Wallet::build_fee_bump`] can replace the transaction without the signatures of
+    /// another party
+    pub fn can_rbf(&self) -> bool {
+        self.signals_rbf && self.all_inputs_ours && self.fee.is_some()
+    }
+
+    /// Whether [`Wallet::build_cpfp`] can build a child bumping the fee of the transaction
+    pub fn can_cpfp(&self) -> bool {
+        !self.cpfp_outputs.is_empty() && self.fee.is_some()
+    }
+}
+
+impl Wallet {
+    /// How the fee of each unconfirmed canonical transaction of the wallet can be bumped, for
+    /// instance to only offer to speed up the transactions for which it's possible
+    ///
+    /// A transaction can be replaced with [`Wallet::build_fee_bump`] if it signals RBF and spends
+    /// only outputs of the wallet, and a child can spend it with [`Wallet::build_cpfp`] if the
+    /// wallet owns one of its unspent outputs, see [`BumpCandidate::can_rbf`] and
+    /// [`BumpCandidate::can_cpfp`]. Both need the fee of the transaction.
+    ///
+    /// [`BumpCandidate::min_replacement_extra_fee`] follows the checks of
+    /// [`Wallet::build_fee_bump`]: a replacement pays at least the minimum relay fee rate more
+    /// than the transaction, and at least its fee plus the minimum relay fee for its own weight.
+    /// It also pays at least `mempool_min_fee`, the minimum fee rate of the mempool of the chain
+    /// source, to be accepted. The replacement is assumed to weigh as much as the transaction.
+    ///
+    /// The candidates are ordered by txid.
+    pub fn bump_candidates(&self, mempool_min_fee: FeeRate) -> Vec<BumpCandidate> {
+        let mut candidates = self
+            .transactions()
+            .filter(|tx| matches!(tx.chain_position, ChainPosition::Unconfirmed(_)))
+            .map(|tx| self.bump_candidate(&tx.tx_node.tx, mempool_min_fee))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|candidate| candidate.txid);
+        candidates
+    }
+
+    /// The [`BumpCandidate`] of the unconfirmed transaction `tx`
+    fn bump_candidate(&self, tx: &Transaction, mempool_min_fee: FeeRate) -> BumpCandidate {
+        let graph = self.indexed_graph.graph();
+        let all_inputs_ours = tx.input.iter().all(|txin| {
+            graph
+                .get_txout(txin.previous_output)
+                .map_or(false, |txout| self.is_mine(&txout.script_pubkey))
+        });
+        let cpfp_outputs = self
+            .cpfp_utxos(tx)
+            .iter()
+            .map(|utxo| utxo.utxo.outpoint())
+            .collect();
+
+        let previous_fee = match (self.calculate_fee(tx), self.calculate_fee_rate(tx)) {
+            (Ok(fee), Ok(rate)) => Some(PreviousFee {
+                absolute: fee,
+                rate,
+            }),
+            _ => None,
+        };
+        let min_replacement_extra_fee = previous_fee.map(|previous_fee| {
+            let weight = tx.weight();
+            let required = previous_fee
+                .min_replacement_fee(weight)
+                .max(previous_fee.min_replacement_fee_rate() * weight)
+                .max(mempool_min_fee * weight);
+            required - previous_fee.absolute
+        });
+
+        BumpCandidate {
+            txid: tx.compute_txid(),
+            signals_rbf: tx.is_explicitly_rbf(),
+            all_inputs_ours,
+            cpfp_outputs,
+            fee: previous_fee.map(|previous_fee| previous_fee.absolute),
+            package_fee_rate: self.package_fee_rate(tx),
+            min_replacement_extra_fee,
+        }
+    }
+
+    /// The fee rate of the transaction `txid` together with its unconfirmed ancestors
+    ///
+    /// Without `cpfp_info`, the ancestors are the unconfirmed transactions of the graph the
+    /// transaction descends from, whose fees must be known, as for
+    /// [`BumpCandidate::package_fee_rate`]. The ancestors of an incoming payment are usually
+    /// transactions of the sender, whose previous outputs the wallet doesn't have: with
+    /// `cpfp_info`, the ancestors reported by the chain source are used instead, such as the
+    /// ones of `tx_cpfp_info` of `bdk_esplora`, and only the fee of the transaction itself must
+    /// be known.
+    ///
+    /// Returns `None` if the transaction isn't in the wallet or one of the fees is unknown.
+    pub fn tx_package_fee_rate(&self, txid: Txid, cpfp_info: Option<&CpfpInfo>) -> Option<FeeRate> {
+        let tx = self.indexed_graph.graph().get_tx(txid)?;
+        match cpfp_info {
+            Some(cpfp_info) => {
+                let fee = self.calculate_fee(&tx).ok()? + cpfp_info.ancestors_fee();
+                Some(fee / (tx.weight() + cpfp_info.ancestors_weight()))
+            }
+            None => self.package_fee_rate(&tx),
+        }
+    }
+
+    /// The fee rate of `tx` together with its unconfirmed ancestors in the graph
+    fn package_fee_rate(&self, tx: &Transaction) -> Option<FeeRate> {
+        let package = self.unconfirmed_package(tx).ok()?;
+        let fee = package.iter().map(|(fee, _)| *fee).sum::<Amount>();
+        let weight = package.iter().map(|(_, weight)| *weight).sum::<Weight>();
+        Some(fee / weight)
+    }
+
+    /// The fee and weight of `tx`, then of each of its unconfirmed ancestors in the graph: the
+    /// package whose fee rate is bumped by a child of `tx`
+    pub(super) fn unconfirmed_package(
+        &self,
+        tx: &Transaction,
+    ) -> Result<Vec<(Amount, Weight)>, CalculateFeeError> {
+        let graph = self.indexed_graph.graph();
+        let tip = self.chain.tip().block_id();
+        let ancestors = graph.walk_ancestors(tx.clone(), |_, ancestor| {
+            match graph.get_chain_position(&self.chain, tip, ancestor.compute_txid()) {
+                Some(ChainPosition::Unconfirmed(_)) => Some(ancestor),
+                _ => None,
+            }
+        });
+        core::iter::once(Ok((self.calculate_fee(tx)?, tx.weight())))
+            .chain(
+                ancestors.map(|ancestor| Ok((self.calculate_fee(&ancestor)?, ancestor.weight()))),
+            )
+            .collect()
+    }
+
+    /// Get the replacement status of the transaction `txid`: whether it signals RBF, the other
+    /// attempts of the same payment known to the wallet and which of them is canonical.
+    ///
+    /// Returns `None` if the transaction is not in the wallet.
+    pub fn tx_replacement_info(&self, txid: Txid) -> Option<ReplacementInfo> {
+        let graph = self.indexed_graph.graph();
+        let tx = graph.get_tx(txid)?;
+
+        let mut attempts = BTreeSet::from([txid]);
+        let mut to_visit = alloc::vec![txid];
+        while let Some(attempt) = to_visit.pop() {
+            let attempt_tx = graph.get_tx(attempt).expect("attempts are in the graph");
+            for (_, conflict) in graph.direct_conflicts(&attempt_tx) {
+                if attempts.insert(conflict) {
+                    to_visit.push(conflict);
+                }
+            }
+        }
+
+        let tip = self.chain.tip().block_id();
+        let canonical = attempts.iter().copied().find(|&attempt| {
+            graph
+                .get_chain_position(&self.chain, tip, attempt)
+                .is_some()
+        });
+        attempts.remove(&txid);
+
+        Some(ReplacementInfo {
+            txid,
+            signals_rbf: tx.is_explicitly_rbf(),
+            conflicts: attempts.into_iter().collect(),
+            canonical,
+        })
+    }
+
+    /// Follow the replacements of the transaction `txid` to the attempt of the payment which is
+    /// currently canonical, which may be `txid` itself.
+    ///
+    /// Returns `None` if the transaction is not in the wallet, or if all the attempts of the payment
+    /// were evicted.
+    pub fn latest_replacement(&self, txid: Txid) -> Option<Txid> {
+        self.tx_replacement_info(txid)?.canonical
+    }
+}
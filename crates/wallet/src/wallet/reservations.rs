@@ -0,0 +1,133 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! The inputs reserved by the transactions built with [`TxBuilder::reserve_inputs`]
+//!
+//! [`TxBuilder::reserve_inputs`]: super::tx_builder::TxBuilder::reserve_inputs
+
+use alloc::vec::Vec;
+
+use bitcoin::{OutPoint, Transaction, Txid};
+
+use super::error::CreateTxError;
+use super::Wallet;
+
+/// How long the inputs of a transaction stay reserved by default, in seconds, see
+/// [`Wallet::set_reservation_ttl`]
+pub const DEFAULT_RESERVATION_TTL: u64 = 600;
+
+/// The transaction which reserved an outpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Reservation {
+    pub(crate) txid: Txid,
+    /// When the reservation expires, in unix seconds, `None` without a clock
+    pub(crate) expires_at: Option<u64>,
+}
+
+impl Wallet {
+    /// Set how long the inputs of the transactions built with
+    /// [`TxBuilder::reserve_inputs`] stay reserved, in seconds. The default is
+    /// [`DEFAULT_RESERVATION_TTL`].
+    ///
+    /// The reservations expire when the next transaction is built, according to the system clock.
+    /// Without the `std` feature there is no clock, the reservations only expire with
+    /// [`Wallet::release_expired_reservations`].
+    ///
+    /// [`TxBuilder::reserve_inputs`]: super::tx_builder::TxBuilder::reserve_inputs
+    pub fn set_reservation_ttl(&mut self, ttl_secs: u64) {
+        self.reservation_ttl = ttl_secs;
+    }
+
+    /// The reserved outpoints, with the transaction which reserved them
+    pub fn reserved_outpoints(&self) -> impl Iterator<Item = (OutPoint, Txid)> + '_ {
+        self.reservations
+            .iter()
+            .map(|(outpoint, reservation)| (*outpoint, reservation.txid))
+    }
+
+    /// Whether `outpoint` is reserved by a transaction built with
+    /// [`TxBuilder::reserve_inputs`]
+    ///
+    /// [`TxBuilder::reserve_inputs`]: super::tx_builder::TxBuilder::reserve_inputs
+    pub fn is_reserved(&self, outpoint: OutPoint) -> bool {
+        self.reservations.contains_key(&outpoint)
+    }
+
+    /// Release the inputs reserved by the transaction `txid`
+    ///
+    /// They're also released by [`Wallet::cancel_tx`], and when a failed broadcast of the
+    /// transaction is recorded with [`Wallet::record_broadcast`].
+    pub fn release_reservation(&mut self, txid: Txid) {
+        self.reservations
+            .retain(|_, reservation| reservation.txid != txid);
+    }
+
+    /// Release the reservations which expired at the time `now`, in unix seconds
+    ///
+    /// The reservations made without the `std` feature have no expiry time and are all released.
+    pub fn release_expired_reservations(&mut self, now: u64) {
+        self.reservations
+            .retain(|_, reservation| matches!(reservation.expires_at, Some(t) if t > now));
+    }
+
+    /// Release the expired reservations, and those of the transactions which are in the wallet:
+    /// their inputs are spent already.
+    pub(crate) fn prune_reservations(&mut self) {
+        #[cfg(feature = "std")]
+        {
+            let now = std::time::UNIX_EPOCH
+                .elapsed()
+                .expect("the clock is after the unix epoch")
+                .as_secs();
+            self.reservations.retain(|_, reservation| {
+                reservation
+                    .expires_at
+                    .map_or(true, |expires_at| expires_at > now)
+            });
+        }
+        let graph = self.indexed_graph.graph();
+        self.reservations
+            .retain(|_, reservation| graph.get_tx(reservation.txid).is_none());
+    }
+
+    /// Reserve the inputs of `tx`, failing if one of them is reserved by another transaction
+    pub(crate) fn reserve_inputs(&mut self, tx: &Transaction) -> Result<(), CreateTxError> {
+        let txid = tx.compute_txid();
+        let reserved = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .filter(|outpoint| {
+                self.reservations
+                    .get(outpoint)
+                    .map_or(false, |reservation| reservation.txid != txid)
+            })
+            .collect::<Vec<_>>();
+        if !reserved.is_empty() {
+            return Err(CreateTxError::InputsReserved(reserved));
+        }
+
+        #[cfg(feature = "std")]
+        let expires_at = Some(
+            std::time::UNIX_EPOCH
+                .elapsed()
+                .expect("the clock is after the unix epoch")
+                .as_secs()
+                .saturating_add(self.reservation_ttl),
+        );
+        #[cfg(not(feature = "std"))]
+        let expires_at = None;
+        for txin in &tx.input {
+            self.reservations
+                .insert(txin.previous_output, Reservation { txid, expires_at });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Limit on the number of revealed addresses which never received funds
+
+use core::fmt;
+
+use crate::KeychainKind;
+
+use super::{AddressInfo, Wallet};
+
+/// Error of [`Wallet::try_reveal_next_address`] and [`Wallet::try_next_unused_address`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevealGuardError {
+    /// Revealing another address would leave more than `max` consecutive addresses without funds
+    /// at the end of the keychain, which a wallet restored with a gap limit of `max` wouldn't find
+    GapLimit {
+        /// The keychain of the address
+        keychain: KeychainKind,
+        /// The number of revealed addresses after the last one which received funds
+        gap: u32,
+        /// The limit set with [`Wallet::set_reveal_guard`]
+        max: u32,
+    },
+}
+
+impl fmt::Display for RevealGuardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GapLimit { keychain, gap, max } => write!(
+                f,
+                "{} revealed {:?} addresses never received funds, the limit is {}",
+                gap, keychain, max
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RevealGuardError {}
+
+impl Wallet {
+    /// Limit the number of consecutive revealed addresses which never received funds to
+    /// `max_unused_gap`, per keychain.
+    ///
+    /// The limit is enforced by [`Wallet::try_reveal_next_address`] and
+    /// [`Wallet::try_next_unused_address`], so that the wallet can be restored by a scan stopping
+    /// after `max_unused_gap` unused addresses. [`Wallet::reveal_next_address`] and
+    /// [`Wallet::next_unused_address`] ignore it and can be used to force revealing an address.
+    ///
+    /// The guard is not persisted, it must be set again after loading the wallet.
+    pub fn set_reveal_guard(&mut self, max_unused_gap: u32) {
+        self.reveal_guard = Some(max_unused_gap);
+    }
+
+    /// Remove the limit set with [`Wallet::set_reveal_guard`].
+    pub fn clear_reveal_guard(&mut self) {
+        self.reveal_guard = None;
+    }
+
+    /// The limit set with [`Wallet::set_reveal_guard`], if any.
+    pub fn reveal_guard(&self) -> Option<u32> {
+        self.reveal_guard
+    }
+
+    /// The number of revealed addresses of `keychain` after the last one which received funds,
+    /// or all of the revealed addresses if none did.
+    ///
+    /// Addresses marked used with [`Wallet::mark_used`] still count as unused, since they can't be
+    /// found by scanning the chain.
+    pub fn unused_gap(&self, keychain: KeychainKind) -> u32 {
+        let keychain = self.map_keychain(keychain);
+        let index = &self.indexed_graph.index;
+        let last_revealed = match index.last_revealed_index(&keychain) {
+            Some(last_revealed) => last_revealed,
+            None => return 0,
+        };
+        let last_funded = index
+            .keychain_outpoints(&keychain)
+            .next_back()
+            .map(|(last_funded, _)| last_funded);
+        match last_funded {
+            Some(last_funded) => last_revealed.saturating_sub(last_funded),
+            None => last_revealed + 1,
+        }
+    }
+
+    /// Like [`Wallet::reveal_next_address`], but fail if the new address would exceed the limit
+    /// set with [`Wallet::set_reveal_guard`].
+    pub fn try_reveal_next_address(
+        &mut self,
+        keychain: KeychainKind,
+    ) -> Result<AddressInfo, RevealGuardError> {
+        self.check_reveal_guard(keychain)?;
+        Ok(self.reveal_next_address(keychain))
+    }
+
+    /// Like [`Wallet::next_unused_address`], but fail if a new address must be revealed and it
+    /// would exceed the limit set with [`Wallet::set_reveal_guard`].
+    pub fn try_next_unused_address(
+        &mut self,
+        keychain: KeychainKind,
+    ) -> Result<AddressInfo, RevealGuardError> {
+        if self.list_unused_addresses(keychain).next().is_none() {
+            self.check_reveal_guard(keychain)?;
+        }
+        Ok(self.next_unused_address(keychain))
+    }
+
+    /// Check that revealing the next address of `keychain` doesn't exceed the guard.
+    fn check_reveal_guard(&self, keychain: KeychainKind) -> Result<(), RevealGuardError> {
+        let max = match self.reveal_guard {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        let keychain = self.map_keychain(keychain);
+        let (_, is_new) = self
+            .indexed_graph
+            .index
+            .next_index(&keychain)
+            .expect("keychain must exist");
+        let gap = self.unused_gap(keychain);
+        // an address which is already revealed is returned again, the gap doesn't grow
+        if is_new && gap >= max {
+            return Err(RevealGuardError::GapLimit { keychain, gap, max });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! The revisions of the descriptors of the keychains, see [`Wallet::descriptor_history`]
+
+use alloc::vec::Vec;
+
+use bdk_chain::collections::BTreeMap;
+use bdk_chain::keychain::KeychainTxOutIndex;
+use bdk_chain::{DescriptorExt, DescriptorId, DescriptorRevisionRecord};
+
+use super::Wallet;
+use crate::descriptor::ExtendedDescriptor;
+use crate::KeychainKind;
+
+/// The revisions recorded for each keychain, by revision
+pub(crate) type Revisions = BTreeMap<KeychainKind, BTreeMap<u32, DescriptorRevisionRecord>>;
+
+/// A descriptor a keychain had, see [`Wallet::descriptor_history`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorRevision {
+    /// The number of the revision, 0 for the descriptor the keychain was created with
+    pub revision: u32,
+    /// The id of the descriptor of the revision
+    pub descriptor_id: DescriptorId,
+    /// The descriptor of the revision, if the wallet still tracks it
+    pub descriptor: Option<ExtendedDescriptor>,
+    /// The height of the tip of the wallet's chain when the revision became active, `None` if the
+    /// wallet was persisted before revisions were recorded
+    pub activation_height: Option<u32>,
+    /// The last derivation index revealed under the revision, if any
+    pub last_revealed: Option<u32>,
+}
+
+/// The revision 0 of the keychains of `index` which have no revision in `recorded`, activated at
+/// `activation_height`.
+///
+/// Wallets persisted before revisions were recorded only have their keychains, whose descriptor
+/// never changed: it's their revision 0, with an unknown activation height.
+pub(crate) fn first_revisions(
+    index: &KeychainTxOutIndex<KeychainKind>,
+    recorded: &Revisions,
+    activation_height: Option<u32>,
+) -> Revisions {
+    index
+        .keychains()
+        .filter(|(keychain, _)| !recorded.contains_key(*keychain))
+        .map(|(keychain, descriptor)| {
+            let record = DescriptorRevisionRecord {
+                descriptor_id: descriptor.descriptor_id(),
+                activation_height,
+            };
+            (*keychain, [(0, record)].into())
+        })
+        .collect()
+}
+
+impl Wallet {
+    /// The revisions of the descriptor of `keychain`, in order.
+    ///
+    /// A keychain has the revision 0 from its creation, and the revisions of the wallets persisted
+    /// before they were recorded are synthesized when they are loaded, with an unknown
+    /// `activation_height`. The addresses listed by the wallet belong to the last revision, see
+    /// [`AddressInfo::revision`](super::AddressInfo::revision).
+    ///
+    /// # Panics
+    ///
+    /// This panics if `keychain` is an extra keychain which wasn't added.
+    pub fn descriptor_history(&self, keychain: KeychainKind) -> Vec<DescriptorRevision> {
+        let keychain = self.map_keychain(keychain);
+        let index = &self.indexed_graph.index;
+        let descriptor = index
+            .get_descriptor(&keychain)
+            .expect("keychain must exist");
+        self.descriptor_revisions
+            .get(&keychain)
+            .into_iter()
+            .flatten()
+            .map(|(revision, record)| {
+                let is_current = record.descriptor_id == descriptor.descriptor_id();
+                DescriptorRevision {
+                    revision: *revision,
+                    descriptor_id: record.descriptor_id,
+                    descriptor: Some(descriptor.clone()).filter(|_| is_current),
+                    activation_height: record.activation_height,
+                    last_revealed: index.last_revealed_index_of_descriptor(record.descriptor_id),
+                }
+            })
+            .collect()
+    }
+
+    /// The revision of the current descriptor of `keychain`
+    pub(crate) fn current_revision(&self, keychain: KeychainKind) -> u32 {
+        self.descriptor_revisions
+            .get(&keychain)
+            .and_then(|revisions| revisions.keys().next_back())
+            .copied()
+            .unwrap_or(0)
+    }
+}
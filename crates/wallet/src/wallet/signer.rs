@@ -79,7 +79,7 @@
 //! # Ok::<_, anyhow::Error>(())
 //! ```
 
-use crate::collections::BTreeMap;
+use crate::collections::{BTreeMap, BTreeSet};
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -87,13 +87,13 @@ use core::cmp::Ordering;
 use core::fmt;
 use core::ops::{Bound::Included, Deref};
 
-use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv};
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource, Xpriv};
 use bitcoin::hashes::hash160;
 use bitcoin::secp256k1::Message;
-use bitcoin::sighash::{EcdsaSighashType, TapSighash, TapSighashType};
+use bitcoin::sighash::{Annex, EcdsaSighashType, TapSighash, TapSighashType};
 use bitcoin::{ecdsa, psbt, sighash, taproot, transaction};
 use bitcoin::{key::TapTweak, key::XOnlyPublicKey, secp256k1};
-use bitcoin::{PrivateKey, Psbt, PublicKey};
+use bitcoin::{Amount, FeeRate, PrivateKey, Psbt, PublicKey};
 
 use miniscript::descriptor::{
     Descriptor, DescriptorMultiXKey, DescriptorPublicKey, DescriptorSecretKey, DescriptorXKey,
@@ -105,6 +105,7 @@ use super::utils::SecpCtx;
 use crate::descriptor::{DescriptorMeta, XKeyUtils};
 use crate::psbt::PsbtUtils;
 use crate::wallet::error::MiniscriptPsbtError;
+use crate::KeychainKind;
 
 /// Identifier of a signer in the `SignersContainers`. Used as a key to find the right signer among
 /// multiple of them
@@ -156,7 +157,12 @@ pub enum SignerError {
     ///
     /// To enable signing transactions with non-standard sighashes set
     /// [`SignOptions::allow_all_sighashes`] to `true`.
-    NonStandardSighash,
+    NonStandardSighash {
+        /// Index of the input using the sighash
+        input_index: usize,
+        /// The sighash requested by the input
+        sighash: psbt::PsbtSighashType,
+    },
     /// Invalid SIGHASH for the signing context in use
     InvalidSighash,
     /// Error while computing the hash to sign a P2WPKH input.
@@ -171,6 +177,43 @@ pub enum SignerError {
     /// [`TransactionSigner`], so that they can return their own custom errors, without having to
     /// modify [`SignerError`] in BDK.
     External(String),
+    /// The wallet doesn't have any signer, see [`Wallet::is_watch_only`]
+    ///
+    /// [`Wallet::is_watch_only`]: crate::wallet::Wallet::is_watch_only
+    WatchOnly,
+    /// The hardware device of the signer can't be found or connected to
+    DeviceNotFound(String),
+    /// The hardware device of the signer isn't ready to sign, for example because it's locked or
+    /// it's waiting for its passphrase
+    DeviceNotReady(String),
+    /// The annex of a Taproot key spend doesn't start with `0x50`, see
+    /// [`SignOptions::taproot_annex`]
+    InvalidAnnex,
+    /// The transaction of a PSBT v2 can't be determined, see [`PsbtV2::unsigned_tx`]
+    ///
+    /// [`PsbtV2::unsigned_tx`]: crate::psbt::v2::PsbtV2::unsigned_tx
+    #[cfg(feature = "psbt-v2")]
+    PsbtV2(crate::psbt::v2::PsbtV2Error),
+    /// The output reduced by the [`SignOptions::fee_adjustment`] isn't a change output of the
+    /// wallet
+    InvalidChangeOutput(usize),
+    /// The fee of the PSBT is above the maximum, see [`SignOptions::max_fee`]
+    FeeAboveLimit {
+        /// The fee of the PSBT
+        fee: Amount,
+        /// The maximum fee
+        max: Amount,
+    },
+    /// The fee rate of the PSBT is above the maximum, see [`SignOptions::max_fee_rate`]
+    FeeRateAboveLimit {
+        /// The fee rate of the PSBT
+        fee_rate: FeeRate,
+        /// The maximum fee rate
+        max: FeeRate,
+    },
+    /// The previous outputs of the inputs at these indexes are missing from the PSBT, its fee
+    /// can't be checked, see [`SignOptions::allow_unknown_prevouts`]
+    UnknownPrevouts(Vec<usize>),
 }
 
 impl From<transaction::InputsIndexError> for SignerError {
@@ -203,13 +246,23 @@ impl fmt::Display for SignerError {
             Self::MissingWitnessUtxo => write!(f, "Missing witness UTXO"),
             Self::MissingWitnessScript => write!(f, "Missing witness script"),
             Self::MissingHdKeypath => write!(f, "Missing fingerprint and derivation path"),
-            Self::NonStandardSighash => write!(f, "The psbt contains a non standard sighash"),
+            Self::NonStandardSighash { input_index, sighash } => write!(f, "The psbt input #{} requests the non standard sighash {}", input_index, sighash),
             Self::InvalidSighash => write!(f, "Invalid SIGHASH for the signing context in use"),
             Self::SighashP2wpkh(err) => write!(f, "Error while computing the hash to sign a P2WPKH input: {}", err),
             Self::SighashTaproot(err) => write!(f, "Error while computing the hash to sign a Taproot input: {}", err),
             Self::TxInputsIndexError(err) => write!(f, "Error while computing the hash, out of bounds access on the transaction inputs: {}", err),
             Self::MiniscriptPsbt(err) => write!(f, "Miniscript PSBT error: {}", err),
             Self::External(err) => write!(f, "{}", err),
+            Self::WatchOnly => write!(f, "The wallet is watch-only and can't sign"),
+            Self::DeviceNotFound(err) => write!(f, "Hardware device not found: {}", err),
+            Self::DeviceNotReady(err) => write!(f, "Hardware device not ready: {}", err),
+            Self::InvalidAnnex => write!(f, "The Taproot annex doesn't start with 0x50"),
+            #[cfg(feature = "psbt-v2")]
+            Self::PsbtV2(err) => write!(f, "Invalid PSBT v2: {}", err),
+            Self::InvalidChangeOutput(index) => write!(f, "The output #{} isn't a change output of the wallet", index),
+            Self::FeeAboveLimit { fee, max } => write!(f, "The fee {} is above the maximum {}", fee.display_dynamic(), max.display_dynamic()),
+            Self::FeeRateAboveLimit { fee_rate, max } => write!(f, "The fee rate {} sat/kwu is above the maximum {} sat/kwu", fee_rate.to_sat_per_kwu(), max.to_sat_per_kwu()),
+            Self::UnknownPrevouts(inputs) => write!(f, "The previous outputs of the inputs {:?} are unknown, the fee can't be checked", inputs),
         }
     }
 }
@@ -217,6 +270,25 @@ impl fmt::Display for SignerError {
 #[cfg(feature = "std")]
 impl std::error::Error for SignerError {}
 
+impl SignerError {
+    /// Whether the error should abort the whole signing process
+    ///
+    /// Errors that only mean that a signer couldn't contribute its signatures (e.g. a missing
+    /// key, or a user refusing to sign on a hardware device) are not fatal: the wallet will keep
+    /// running the remaining signers. Errors that come from a malformed or unsafe PSBT are fatal.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(
+            self,
+            Self::MissingKey
+                | Self::InvalidKey
+                | Self::UserCanceled
+                | Self::External(_)
+                | Self::DeviceNotFound(_)
+                | Self::DeviceNotReady(_)
+        )
+    }
+}
+
 /// Signing context
 ///
 /// Used by our software signers to determine the type of signatures to make
@@ -311,6 +383,11 @@ impl<T: InputSigner> TransactionSigner for T {
         secp: &SecpCtx,
     ) -> Result<(), SignerError> {
         for input_index in 0..psbt.inputs.len() {
+            if let Some(inputs) = &sign_options.inputs {
+                if !inputs.contains(&input_index) {
+                    continue;
+                }
+            }
             self.sign_input(psbt, input_index, sign_options, secp)?;
         }
 
@@ -350,19 +427,31 @@ impl InputSigner for SignerWrapper<DescriptorXKey<Xpriv>> {
             .tap_key_origins
             .iter()
             .map(|(pk, (_, keysource))| (SinglePubKey::XOnly(*pk), keysource));
-        let (public_key, full_path) = match psbt.inputs[input_index]
+        let key_sources = psbt.inputs[input_index]
             .bip32_derivation
             .iter()
             .map(|(pk, keysource)| (SinglePubKey::FullKey(PublicKey::new(*pk)), keysource))
             .chain(tap_key_origins)
-            .find_map(|(pk, keysource)| {
-                if self.matches(keysource, secp).is_some() {
-                    Some((pk, keysource.1.clone()))
-                } else {
-                    None
-                }
-            }) {
-            Some((pk, full_path)) => (pk, full_path),
+            .collect::<Vec<_>>();
+        let matching = key_sources
+            .iter()
+            .find(|(_, keysource)| self.matches(keysource, secp).is_some())
+            .or_else(|| {
+                // any path below the key, its own origin included
+                let root = self.root_fingerprint(secp);
+                let origin_path = self
+                    .origin
+                    .as_ref()
+                    .map_or(&[][..], |(_, path)| path.as_ref());
+                key_sources
+                    .iter()
+                    .filter(|_| sign_options.sign_arbitrary_derivations)
+                    .find(|(_, (fingerprint, path))| {
+                        *fingerprint == root && path.as_ref().starts_with(origin_path)
+                    })
+            });
+        let (public_key, full_path) = match matching {
+            Some((pk, keysource)) => (pk.clone(), keysource.1.clone()),
             None => return Ok(()),
         };
 
@@ -481,6 +570,12 @@ impl InputSigner for SignerWrapper<PrivateKey> {
                     && sign_options.sign_with_tap_internal_key
                     && x_only_pubkey == psbt_internal_key
                 {
+                    if let Some(annex) = &sign_options.taproot_annex {
+                        Annex::new(annex).map_err(|_| SignerError::InvalidAnnex)?;
+                        psbt.inputs[input_index]
+                            .proprietary
+                            .insert(taproot_annex_key(), annex.clone());
+                    }
                     let (hash, hash_ty) = Tap::sighash(psbt, input_index, None)?;
                     sign_psbt_schnorr(
                         &self.inner,
@@ -688,28 +783,37 @@ impl SignersContainer {
                 _ => SignerContext::Legacy,
             };
 
-            match secret {
-                DescriptorSecretKey::Single(private_key) => container.add_external(
+            let (id, signer): (SignerId, Arc<dyn TransactionSigner>) = match secret {
+                DescriptorSecretKey::Single(private_key) => (
                     SignerId::from(
                         private_key
                             .key
                             .public_key(secp)
                             .to_pubkeyhash(SigType::Ecdsa),
                     ),
-                    SignerOrdering::default(),
                     Arc::new(SignerWrapper::new(private_key.key, ctx)),
                 ),
-                DescriptorSecretKey::XPrv(xprv) => container.add_external(
+                DescriptorSecretKey::XPrv(xprv) => (
                     SignerId::from(xprv.root_fingerprint(secp)),
-                    SignerOrdering::default(),
                     Arc::new(SignerWrapper::new(xprv, ctx)),
                 ),
-                DescriptorSecretKey::MultiXPrv(xprv) => container.add_external(
+                DescriptorSecretKey::MultiXPrv(xprv) => (
                     SignerId::from(xprv.root_fingerprint(secp)),
-                    SignerOrdering::default(),
                     Arc::new(SignerWrapper::new(xprv, ctx)),
                 ),
             };
+
+            // The same master key can appear more than once in a descriptor with different
+            // derivation paths (e.g. as the taproot internal key and in a leaf): keep a signer
+            // for each of them instead of replacing the previous one
+            let mut ordering = SignerOrdering::default();
+            while container
+                .0
+                .contains_key(&(id.clone(), ordering.clone()).into())
+            {
+                ordering.0 += 1;
+            }
+            container.add_external(id, ordering, signer);
         }
 
         container
@@ -755,6 +859,29 @@ impl SignersContainer {
         self.0.values().collect()
     }
 
+    /// Whether one of the extended keys of the signers derives `key_source` following the
+    /// derivation path of its descriptor, see [`SignOptions::sign_arbitrary_derivations`]
+    pub(crate) fn matches_key_source(&self, key_source: &KeySource, secp: &SecpCtx) -> bool {
+        self.0
+            .values()
+            .filter_map(|signer| signer.descriptor_secret_key())
+            .any(|secret_key| match secret_key {
+                DescriptorSecretKey::XPrv(xkey) => xkey.matches(key_source, secp).is_some(),
+                DescriptorSecretKey::MultiXPrv(multikey) => multikey_to_xkeys(multikey)
+                    .iter()
+                    .any(|xkey| xkey.matches(key_source, secp).is_some()),
+                DescriptorSecretKey::Single(_) => false,
+            })
+    }
+
+    /// Returns the signers in the container together with their `ordering`, sorted by lowest to
+    /// highest `ordering`
+    pub(crate) fn signers_with_ordering(
+        &self,
+    ) -> impl Iterator<Item = (&SignerOrdering, &Arc<dyn TransactionSigner>)> {
+        self.0.iter().map(|(key, signer)| (&key.ordering, signer))
+    }
+
     /// Finds the signer with lowest ordering for a given id in the container.
     pub fn find(&self, id: SignerId) -> Option<&Arc<dyn TransactionSigner>> {
         self.0
@@ -822,6 +949,138 @@ pub struct SignOptions {
     /// or not.
     /// Defaults to `true`, i.e., we always grind ECDSA signature to sign with low r.
     pub allow_grinding: bool,
+
+    /// Only sign the inputs at these indexes, leaving every other input untouched.
+    ///
+    /// Defaults to `None`, i.e., the signer will try to sign all the inputs. Custom
+    /// [`TransactionSigner`]s that don't go through [`InputSigner`] are expected to honor this
+    /// too.
+    pub inputs: Option<BTreeSet<usize>>,
+
+    /// Only sign the inputs spending outputs of these keychains, leaving every other input
+    /// (including foreign ones) untouched.
+    ///
+    /// Defaults to `None`, i.e., inputs are signed no matter which keychain they belong to. This
+    /// is only taken into account by [`Wallet::sign`], which knows the keychains of the inputs.
+    ///
+    /// [`Wallet::sign`]: crate::wallet::Wallet::sign
+    pub keychains: Option<BTreeSet<KeychainKind>>,
+
+    /// The annex committed to by the Taproot key spend signatures, see BIP-341
+    ///
+    /// Defaults to `None`, i.e. no annex. The annex must start with `0x50`. It's recorded in the
+    /// PSBT input under the proprietary key [`taproot_annex_key`] when the input is signed, and
+    /// appended as the last element of the witness when it's finalized. Script spends are signed
+    /// without the annex. Note that transactions with an annex are non-standard and won't be
+    /// relayed by most nodes.
+    pub taproot_annex: Option<Vec<u8>>,
+
+    /// Reduce the change so that the fee rate of the signed transaction reaches a target
+    ///
+    /// Defaults to `None`, i.e. the outputs are left untouched. This is only taken into account
+    /// by [`Wallet::sign`], see [`FeeAdjustment`].
+    ///
+    /// [`Wallet::sign`]: crate::wallet::Wallet::sign
+    pub fee_adjustment: Option<FeeAdjustment>,
+
+    /// Refuse to sign a PSBT paying a higher fee, computed from the previous outputs of its
+    /// inputs
+    ///
+    /// Defaults to `None`, i.e. only the limits of the wallet apply. This is only taken into
+    /// account by [`Wallet::sign`], with the tightest of this cap and the one of
+    /// [`Wallet::set_fee_limits`].
+    ///
+    /// [`Wallet::sign`]: crate::wallet::Wallet::sign
+    /// [`Wallet::set_fee_limits`]: crate::wallet::Wallet::set_fee_limits
+    pub max_fee: Option<Amount>,
+
+    /// Refuse to sign a PSBT paying a higher fee rate
+    ///
+    /// Defaults to `None`, i.e. only the limits of the wallet apply. Like for
+    /// [`SignOptions::max_fee`], the tightest of this cap and the one of the wallet applies. The
+    /// fee rate is computed over the weight of the signed transaction, see
+    /// [`Wallet::sign_with_details`].
+    ///
+    /// [`Wallet::sign_with_details`]: crate::wallet::Wallet::sign_with_details
+    pub max_fee_rate: Option<FeeRate>,
+
+    /// Whether to sign a PSBT whose fee can't be checked against the limits, because the
+    /// previous output of one of its inputs is missing
+    ///
+    /// Defaults to `false`, i.e. with a limit, [`Wallet::sign`] fails with
+    /// [`SignerError::UnknownPrevouts`].
+    ///
+    /// [`Wallet::sign`]: crate::wallet::Wallet::sign
+    pub allow_unknown_prevouts: bool,
+
+    /// Whether to sign with the keys derived from the wallet's extended private keys at the paths
+    /// of the PSBT inputs, even when they are outside of the wallet's descriptors
+    ///
+    /// The PSBTs of other software sometimes reference one of the wallet's keys at another path,
+    /// such as another account. With this option, a `bip32_derivation` or `tap_key_origins` entry
+    /// from the master fingerprint of an extended private key of the wallet, at a path below it,
+    /// is derived and signed with if the derived key matches the entry. Only the paths below the
+    /// key of the descriptor can be derived: a descriptor with an account-level key can't sign
+    /// for another account. The inputs signed this way are reported in
+    /// [`SignDetails::arbitrary_derivation_inputs`].
+    ///
+    /// Defaults to `false`, i.e. the keys at other paths are ignored.
+    pub sign_arbitrary_derivations: bool,
+}
+
+/// How [`Wallet::sign`] reduces the change of a transaction whose fee rate is below its target
+/// once signed, see [`SignOptions::fee_adjustment`]
+///
+/// The fee of a transaction is computed from an estimate of its weight once signed. When the
+/// signed transaction is heavier, its fee rate is below the target. Once the inputs are signed
+/// the wallet finalizes a copy of the PSBT to measure its weight, and if the fee rate is below
+/// `target` or the minimum relay fee rate, it reduces the change output by the missing fee. The
+/// inputs whose signatures commit to the change output are signed again, the other outputs are
+/// never touched.
+///
+/// Nothing is adjusted (and the fee rate can be checked with [`Wallet::check_feerate`]) if the
+/// PSBT can't be finalized, if the missing fee is more than `max_reduction` or would leave a
+/// dust change, or if one of the inputs to sign again was signed before: its signatures couldn't
+/// be replaced.
+///
+/// [`Wallet::sign`]: crate::wallet::Wallet::sign
+/// [`Wallet::check_feerate`]: crate::wallet::Wallet::check_feerate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeAdjustment {
+    /// The target fee rate
+    pub target: FeeRate,
+    /// The index of the change output, in the transaction outputs
+    pub change_index: usize,
+    /// The maximum reduction of the change
+    pub max_reduction: Amount,
+}
+
+/// The proprietary key of the PSBT input field holding the annex of a Taproot key spend, see
+/// [`SignOptions::taproot_annex`]
+pub fn taproot_annex_key() -> psbt::raw::ProprietaryKey {
+    psbt::raw::ProprietaryKey {
+        prefix: b"bdk".to_vec(),
+        subtype: 0x00,
+        key: Vec::new(),
+    }
+}
+
+/// The outcome of [`Wallet::sign_with_details`]
+///
+/// [`Wallet::sign_with_details`]: crate::wallet::Wallet::sign_with_details
+#[derive(Debug, Default)]
+pub struct SignDetails {
+    /// Whether the PSBT was finalized
+    pub finalized: bool,
+    /// Indexes of the inputs that received at least one new signature
+    pub signed_inputs: BTreeSet<usize>,
+    /// Indexes of the inputs signed with a key at a path outside of the wallet's descriptors,
+    /// see [`SignOptions::sign_arbitrary_derivations`]
+    pub arbitrary_derivation_inputs: BTreeSet<usize>,
+    /// Non-fatal errors returned by the signers, which didn't stop the other signers from running
+    ///
+    /// See [`SignerError::is_fatal`].
+    pub signer_errors: Vec<(SignerId, SignerError)>,
 }
 
 /// Customize which taproot script-path leaves the signer should sign.
@@ -849,6 +1108,14 @@ impl Default for SignOptions {
             tap_leaves_options: TapLeavesOptions::default(),
             sign_with_tap_internal_key: true,
             allow_grinding: true,
+            inputs: None,
+            keychains: None,
+            taproot_annex: None,
+            fee_adjustment: None,
+            max_fee: None,
+            max_fee_rate: None,
+            allow_unknown_prevouts: false,
+            sign_arbitrary_derivations: false,
         }
     }
 }
@@ -1037,11 +1304,16 @@ impl ComputeSighash for Tap {
             return Err(SignerError::MissingWitnessUtxo);
         };
 
+        // The annex is only used by key spends
+        let annex = match (extra, psbt_input.proprietary.get(&taproot_annex_key())) {
+            (None, Some(annex)) => Some(Annex::new(annex).map_err(|_| SignerError::InvalidAnnex)?),
+            _ => None,
+        };
         // Assume no OP_CODESEPARATOR
         let extra = extra.map(|leaf_hash| (leaf_hash, 0xFFFFFFFF));
 
         Ok((
-            cache.taproot_signature_hash(input_index, &prevouts, None, extra, sighash_type)?,
+            cache.taproot_signature_hash(input_index, &prevouts, annex, extra, sighash_type)?,
             sighash_type,
         ))
     }
@@ -0,0 +1,454 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Silent payments, per [`BIP352`]
+//!
+//! A silent payment address is made of the scan and spend keys of the receiver. The script of an
+//! output paying it is tweaked with a secret shared by the receiver and the sender, who derives it
+//! from the private keys of the inputs of the transaction. The script can thus only be computed
+//! once the coins are selected, by a wallet holding these private keys.
+//!
+//! [`TxBuilder::add_silent_payment_recipient`] adds a recipient whose script is derived when the
+//! transaction is finished. Outputs paying the same receiver get different scripts.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk_wallet::wallet::silent_payments::SilentPaymentAddress;
+//! # use bitcoin::{secp256k1::PublicKey, Network};
+//! # use core::str::FromStr;
+//! let scan_key = PublicKey::from_str("0220bcfac5b99e04ad1a06ddfb016ee13582609d60b6291e98d01a9bc9a16c96d4")?;
+//! let spend_key = PublicKey::from_str("025cc9856d6f8375350e123978daac200c260cb5b5ae83106cab90484dcd8fcf36")?;
+//! let address = SilentPaymentAddress::new(scan_key, spend_key, Network::Bitcoin);
+//! assert!(address.to_string().starts_with("sp1q"));
+//! assert_eq!(address.to_string().parse::<SilentPaymentAddress>()?, address);
+//! assert!(address.is_valid_for_network(Network::Bitcoin));
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`BIP352`]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+//! [`TxBuilder::add_silent_payment_recipient`]: crate::wallet::tx_builder::TxBuilder::add_silent_payment_recipient
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Write};
+use core::str::FromStr;
+
+use bitcoin::bech32::primitives::decode::{CheckedHrpstring, CheckedHrpstringError};
+use bitcoin::bech32::primitives::iter::{ByteIterExt, Fe32IterExt};
+use bitcoin::bech32::{Bech32m, Fe32, Hrp};
+use bitcoin::bip32::ChildNumber;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::{Keypair, Parity, TapTweak, TweakedPublicKey};
+use bitcoin::secp256k1::{self, PublicKey, Scalar, SecretKey, XOnlyPublicKey};
+use bitcoin::{consensus, Network, OutPoint, ScriptBuf};
+use miniscript::descriptor::{DescriptorSecretKey, KeyMap, ShInner, Wildcard};
+use miniscript::{Descriptor, MiniscriptKey};
+
+use crate::descriptor::ExtendedDescriptor;
+use crate::wallet::utils::SecpCtx;
+
+/// The length of the payload of a version 0 address: the scan key and the spend key
+const PAYLOAD_LEN: usize = 66;
+
+/// A silent payment address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SilentPaymentAddress {
+    /// The key the receiver scans the transactions with
+    pub scan_key: PublicKey,
+    /// The key the receiver spends the outputs with
+    pub spend_key: PublicKey,
+    network: AddressNetwork,
+}
+
+/// The network of the human-readable part of an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum AddressNetwork {
+    Main,
+    Test,
+    Regtest,
+}
+
+impl AddressNetwork {
+    fn hrp(self) -> Hrp {
+        Hrp::parse_unchecked(match self {
+            Self::Main => "sp",
+            Self::Test => "tsp",
+            Self::Regtest => "sprt",
+        })
+    }
+}
+
+impl SilentPaymentAddress {
+    /// The address of `scan_key` and `spend_key` on `network`
+    pub fn new(scan_key: PublicKey, spend_key: PublicKey, network: Network) -> Self {
+        let network = match network {
+            Network::Bitcoin => AddressNetwork::Main,
+            Network::Regtest => AddressNetwork::Regtest,
+            _ => AddressNetwork::Test,
+        };
+        Self {
+            scan_key,
+            spend_key,
+            network,
+        }
+    }
+
+    /// Whether the address can be paid on `network`
+    ///
+    /// `tsp` addresses are valid for all the test networks, `sprt` ones only for regtest.
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        match self.network {
+            AddressNetwork::Main => network == Network::Bitcoin,
+            AddressNetwork::Test => network != Network::Bitcoin,
+            AddressNetwork::Regtest => network == Network::Regtest,
+        }
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hrp = self.network.hrp();
+        let (scan_key, spend_key) = (self.scan_key.serialize(), self.spend_key.serialize());
+        scan_key
+            .iter()
+            .chain(&spend_key)
+            .copied()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&hrp)
+            .with_witness_version(Fe32::Q)
+            .chars()
+            .try_for_each(|c| f.write_char(c))
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = SilentPaymentAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let checked =
+            CheckedHrpstring::new::<Bech32m>(s).map_err(SilentPaymentAddressError::Bech32)?;
+        let hrp = checked.hrp();
+        let network = [
+            AddressNetwork::Main,
+            AddressNetwork::Test,
+            AddressNetwork::Regtest,
+        ]
+        .into_iter()
+        .find(|network| network.hrp() == hrp)
+        .ok_or_else(|| SilentPaymentAddressError::InvalidHrp(hrp.to_string()))?;
+        // unlike segwit, the versions go up to 31, so the first character is read by hand
+        let mut data = checked.fe32_iter::<core::iter::Empty<u8>>();
+        let version = data
+            .next()
+            .ok_or(SilentPaymentAddressError::InvalidLength(0))?
+            .to_u8();
+        // version 31 is reserved for a backwards incompatible change
+        if version == 31 {
+            return Err(SilentPaymentAddressError::InvalidVersion(version));
+        }
+        let payload = data.fes_to_bytes().collect::<Vec<_>>();
+        // the later versions may append data to the payload, which is ignored
+        if payload.len() < PAYLOAD_LEN || (version == 0 && payload.len() != PAYLOAD_LEN) {
+            return Err(SilentPaymentAddressError::InvalidLength(payload.len()));
+        }
+        let scan_key =
+            PublicKey::from_slice(&payload[..33]).map_err(SilentPaymentAddressError::InvalidKey)?;
+        let spend_key = PublicKey::from_slice(&payload[33..PAYLOAD_LEN])
+            .map_err(SilentPaymentAddressError::InvalidKey)?;
+        Ok(Self {
+            scan_key,
+            spend_key,
+            network,
+        })
+    }
+}
+
+/// Error while parsing a [`SilentPaymentAddress`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilentPaymentAddressError {
+    /// The address isn't valid bech32m
+    Bech32(CheckedHrpstringError),
+    /// The human-readable part is none of `sp`, `tsp` and `sprt`
+    InvalidHrp(String),
+    /// The version is not supported
+    InvalidVersion(u8),
+    /// The payload has the wrong length for its version
+    InvalidLength(usize),
+    /// The scan key or the spend key is invalid
+    InvalidKey(secp256k1::Error),
+}
+
+impl fmt::Display for SilentPaymentAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bech32(e) => write!(f, "invalid bech32m: {}", e),
+            Self::InvalidHrp(hrp) => write!(f, "invalid human-readable part `{}`", hrp),
+            Self::InvalidVersion(version) => write!(f, "unsupported version {}", version),
+            Self::InvalidLength(len) => write!(f, "invalid payload length {}", len),
+            Self::InvalidKey(e) => write!(f, "invalid key: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SilentPaymentAddressError {}
+
+/// Error while paying a [`SilentPaymentAddress`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilentPaymentError {
+    /// The address is not valid for the wallet's network
+    WrongNetwork {
+        /// The wallet's network
+        expected: Network,
+    },
+    /// The wallet is watch-only, it doesn't have the private keys the scripts are derived from
+    WatchOnly,
+    /// The private key of this input isn't available
+    MissingSecretKey(OutPoint),
+    /// This input spends an output of a segwit version above 1, which silent payments forbid
+    UnsupportedInput(OutPoint),
+    /// No input has a key the scripts can be derived from
+    NoEligibleInputs,
+    /// The shared secret is invalid, which can only happen if the private keys of the inputs
+    /// add up to zero
+    InvalidSharedSecret,
+}
+
+impl fmt::Display for SilentPaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongNetwork { expected } => write!(
+                f,
+                "the silent payment address is not valid for network {}",
+                expected
+            ),
+            Self::WatchOnly => write!(
+                f,
+                "a watch-only wallet can't pay a silent payment address, the private keys of the inputs are needed"
+            ),
+            Self::MissingSecretKey(outpoint) => {
+                write!(f, "the private key of input {} is not available", outpoint)
+            }
+            Self::UnsupportedInput(outpoint) => write!(
+                f,
+                "input {} spends a segwit version unsupported by silent payments",
+                outpoint
+            ),
+            Self::NoEligibleInputs => {
+                write!(f, "no input can be used to derive silent payment outputs")
+            }
+            Self::InvalidSharedSecret => write!(f, "invalid silent payment shared secret"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SilentPaymentError {}
+
+/// Derive the output keys of a transaction paying each of the `recipients`, in order
+///
+/// `outpoints` are the outpoints spent by all the inputs of the transaction, and `input_keys` the
+/// private keys of its inputs eligible per [`BIP352`]. The key of a taproot input is its tweaked
+/// key, negated if the output key has an odd Y coordinate.
+///
+/// The returned keys are the taproot output keys of the outputs, without further tweaking.
+///
+/// [`BIP352`]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+pub fn derive_output_keys(
+    outpoints: &[OutPoint],
+    input_keys: &[SecretKey],
+    recipients: &[SilentPaymentAddress],
+    secp: &SecpCtx,
+) -> Result<Vec<XOnlyPublicKey>, SilentPaymentError> {
+    let (first, others) = input_keys
+        .split_first()
+        .ok_or(SilentPaymentError::NoEligibleInputs)?;
+    let input_key = others.iter().try_fold(*first, |sum, key| {
+        sum.add_tweak(&Scalar::from(*key))
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)
+    })?;
+    let smallest_outpoint = outpoints
+        .iter()
+        .map(consensus::serialize)
+        .min()
+        .ok_or(SilentPaymentError::NoEligibleInputs)?;
+    let input_hash = tagged_hash(
+        "BIP0352/Inputs",
+        &[
+            &smallest_outpoint,
+            &PublicKey::from_secret_key(secp, &input_key).serialize(),
+        ],
+    );
+    let input_key = Scalar::from_be_bytes(input_hash)
+        .ok()
+        .and_then(|input_hash| input_key.mul_tweak(&input_hash).ok())
+        .ok_or(SilentPaymentError::InvalidSharedSecret)?;
+
+    // the outputs paying the same scan key are numbered in the order of the recipients
+    let mut next_k = BTreeMap::<PublicKey, (PublicKey, u32)>::new();
+    recipients
+        .iter()
+        .map(|recipient| {
+            let (shared_secret, k) = match next_k.get_mut(&recipient.scan_key) {
+                Some((shared_secret, k)) => {
+                    *k += 1;
+                    (*shared_secret, *k)
+                }
+                None => {
+                    let shared_secret = recipient
+                        .scan_key
+                        .mul_tweak(secp, &Scalar::from(input_key))
+                        .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+                    next_k.insert(recipient.scan_key, (shared_secret, 0));
+                    (shared_secret, 0)
+                }
+            };
+            let tweak = tagged_hash(
+                "BIP0352/SharedSecret",
+                &[&shared_secret.serialize(), &k.to_be_bytes()],
+            );
+            let output_key = Scalar::from_be_bytes(tweak)
+                .ok()
+                .and_then(|tweak| recipient.spend_key.add_exp_tweak(secp, &tweak).ok())
+                .ok_or(SilentPaymentError::InvalidSharedSecret)?;
+            Ok(output_key.x_only_public_key().0)
+        })
+        .collect()
+}
+
+/// The script of an output with the output key `key`, as derived by [`derive_output_keys`]
+pub(crate) fn output_script(key: XOnlyPublicKey) -> ScriptBuf {
+    ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(key))
+}
+
+/// The unspendable internal key `H` of [`BIP341`], which makes a taproot output spendable only
+/// through its scripts
+///
+/// [`BIP341`]: https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki
+const NUMS_INTERNAL_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// The private key of the input spending the output of `descriptor` at `index`, as used to
+/// derive silent payments
+///
+/// Returns `None` if the output isn't eligible for silent payments, such as a P2WSH output or a
+/// taproot output with the [`NUMS_INTERNAL_KEY`], which the receiver skips too.
+pub(crate) fn input_secret_key(
+    descriptor: &ExtendedDescriptor,
+    index: u32,
+    keymap: &KeyMap,
+    outpoint: OutPoint,
+    secp: &SecpCtx,
+) -> Result<Option<SecretKey>, SilentPaymentError> {
+    let missing_key = SilentPaymentError::MissingSecretKey(outpoint);
+    let derived = descriptor
+        .at_derivation_index(index)
+        .map_err(|_| missing_key.clone())?;
+    let (key, derived_key) = match (single_key(descriptor), single_key(&derived)) {
+        (Some(key), Some(derived_key)) => (key, derived_key),
+        _ => return Ok(None),
+    };
+    let public_key = derived_key
+        .derive_public_key(secp)
+        .map_err(|_| missing_key.clone())?;
+    if matches!(derived, Descriptor::Tr(_))
+        && public_key.inner.x_only_public_key().0.serialize() == NUMS_INTERNAL_KEY
+    {
+        return Ok(None);
+    }
+    let private_key = match keymap.get(key) {
+        Some(DescriptorSecretKey::Single(single)) => single.key,
+        Some(DescriptorSecretKey::XPrv(xkey)) => {
+            let path = match xkey.wildcard {
+                Wildcard::None => xkey.derivation_path.clone(),
+                Wildcard::Unhardened => xkey
+                    .derivation_path
+                    .child(ChildNumber::from_normal_idx(index).map_err(|_| missing_key.clone())?),
+                Wildcard::Hardened => xkey
+                    .derivation_path
+                    .child(ChildNumber::from_hardened_idx(index).map_err(|_| missing_key.clone())?),
+            };
+            xkey.xkey
+                .derive_priv(secp, &path)
+                .map_err(|_| missing_key.clone())?
+                .to_priv()
+        }
+        _ => return Err(missing_key),
+    };
+    if private_key.public_key(secp).inner.x_only_public_key()
+        != public_key.inner.x_only_public_key()
+    {
+        return Err(missing_key);
+    }
+    // the inputs of uncompressed keys are skipped
+    if !private_key.compressed {
+        return Ok(None);
+    }
+
+    match &derived {
+        Descriptor::Tr(tr) => {
+            let keypair = Keypair::from_secret_key(secp, &private_key.inner)
+                .tap_tweak(secp, tr.spend_info().merkle_root())
+                .to_keypair();
+            let secret_key = keypair.secret_key();
+            match keypair.x_only_public_key().1 {
+                Parity::Even => Ok(Some(secret_key)),
+                Parity::Odd => Ok(Some(secret_key.negate())),
+            }
+        }
+        _ => Ok(Some(private_key.inner)),
+    }
+}
+
+/// The key of a descriptor which is eligible for silent payments
+fn single_key<Pk: MiniscriptKey>(descriptor: &Descriptor<Pk>) -> Option<&Pk> {
+    match descriptor {
+        Descriptor::Pkh(pkh) => Some(pkh.as_inner()),
+        Descriptor::Wpkh(wpkh) => Some(wpkh.as_inner()),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wpkh(wpkh) => Some(wpkh.as_inner()),
+            _ => None,
+        },
+        Descriptor::Tr(tr) => Some(tr.internal_key()),
+        _ => None,
+    }
+}
+
+/// Whether an input spending `script_pubkey` could be eligible for silent payments
+pub(crate) fn is_eligible_script(script_pubkey: &bitcoin::Script) -> bool {
+    script_pubkey.is_p2pkh()
+        || script_pubkey.is_p2sh()
+        || script_pubkey.is_p2wpkh()
+        || script_pubkey.is_p2tr()
+}
+
+/// Whether an input spending `script_pubkey` is forbidden in a transaction with silent payments
+pub(crate) fn is_unsupported_script(script_pubkey: &bitcoin::Script) -> bool {
+    script_pubkey
+        .witness_version()
+        .map_or(false, |version| version.to_num() > 1)
+}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
@@ -0,0 +1,199 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Subscriptions to the events of the wallet
+//!
+//! [`Wallet::subscribe`] returns a [`WalletSubscription`], which receives the [`WalletEvent`]s
+//! of the changes applied by [`Wallet::apply_update`], [`Wallet::apply_block`],
+//! [`Wallet::apply_block_connected_to`], [`Wallet::apply_blocks`],
+//! [`Wallet::apply_unconfirmed_txs`] and [`Wallet::apply_mempool`], and by their `*_with_events`
+//! variants. The events are computed like with [`Wallet::apply_update_with_events`], which is only
+//! done while a subscription is live.
+//!
+//! Every subscription has its own buffer, which holds the events in the order the changes were
+//! applied until [`WalletSubscription::poll_events`] takes them. The buffer is bounded, see
+//! [`Wallet::subscribe_with_capacity`]: when it's full, the oldest events are dropped to make
+//! room for the new ones, and counted by [`WalletSubscription::dropped_events`]. A subscriber
+//! which missed events can read the current state of the wallet again, such as its
+//! [`balance`](Wallet::balance). Dropping a subscription drops its buffer, the wallet forgets it
+//! at the next application.
+//!
+//! With the `async` feature, a subscription is a [`Stream`](futures_core::Stream) of events,
+//! which ends once the wallet is dropped and the buffered events are taken.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use std::sync::{Arc, Mutex, Weak};
+
+use super::events::WalletEvent;
+use super::Wallet;
+
+/// The capacity of the buffer of a [`Wallet::subscribe`] subscription, in events
+pub const DEFAULT_SUBSCRIPTION_CAPACITY: usize = 1024;
+
+/// The events of a subscription which are not taken yet
+#[derive(Debug)]
+struct Buffer {
+    events: VecDeque<WalletEvent>,
+    capacity: usize,
+    dropped: u64,
+    /// Whether the wallet was dropped
+    closed: bool,
+    #[cfg(feature = "async")]
+    waker: Option<core::task::Waker>,
+}
+
+impl Buffer {
+    fn push(&mut self, events: &[WalletEvent]) {
+        for event in events {
+            if self.capacity == 0 {
+                self.dropped += 1;
+                continue;
+            }
+            if self.events.len() == self.capacity {
+                self.events.pop_front();
+                self.dropped += 1;
+            }
+            self.events.push_back(event.clone());
+        }
+    }
+}
+
+/// A subscription to the events of a [`Wallet`], see the [module-level documentation](self)
+#[derive(Debug)]
+pub struct WalletSubscription {
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+impl WalletSubscription {
+    /// Take the buffered events, in the order the changes were applied.
+    pub fn poll_events(&self) -> Vec<WalletEvent> {
+        self.lock().events.drain(..).collect()
+    }
+
+    /// The number of events dropped since the subscription was created, because the buffer was
+    /// full
+    pub fn dropped_events(&self) -> u64 {
+        self.lock().dropped
+    }
+
+    /// The max number of events the buffer holds
+    pub fn capacity(&self) -> usize {
+        self.lock().capacity
+    }
+
+    /// Whether the wallet was dropped, no more events will be buffered
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Buffer> {
+        self.buffer.lock().expect("must lock")
+    }
+}
+
+#[cfg(feature = "async")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+impl futures_core::Stream for WalletSubscription {
+    type Item = WalletEvent;
+
+    fn poll_next(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Option<WalletEvent>> {
+        let mut buffer = self.lock();
+        if let Some(event) = buffer.events.pop_front() {
+            return core::task::Poll::Ready(Some(event));
+        }
+        if buffer.closed {
+            return core::task::Poll::Ready(None);
+        }
+        buffer.waker = Some(cx.waker().clone());
+        core::task::Poll::Pending
+    }
+}
+
+/// The subscriptions of a wallet
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions {
+    buffers: Vec<Weak<Mutex<Buffer>>>,
+    /// Whether changes are being applied, so that the nested applications don't publish their
+    /// events on their own
+    pub(crate) applying: bool,
+}
+
+impl Subscriptions {
+    /// Whether a subscription is live
+    pub(crate) fn is_live(&self) -> bool {
+        self.buffers.iter().any(|buffer| buffer.strong_count() > 0)
+    }
+
+    /// Buffer `events` for every live subscription, and forget the dropped ones.
+    pub(crate) fn publish(&mut self, events: &[WalletEvent]) {
+        self.buffers.retain(|buffer| buffer.strong_count() > 0);
+        if events.is_empty() {
+            return;
+        }
+        for buffer in self.buffers.iter().filter_map(Weak::upgrade) {
+            let mut buffer = buffer.lock().expect("must lock");
+            buffer.push(events);
+            // the task is woken once the buffer is unlocked
+            #[cfg(feature = "async")]
+            let waker = buffer.waker.take();
+            drop(buffer);
+            #[cfg(feature = "async")]
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        for buffer in self.buffers.iter().filter_map(Weak::upgrade) {
+            let mut buffer = buffer.lock().expect("must lock");
+            buffer.closed = true;
+            #[cfg(feature = "async")]
+            let waker = buffer.waker.take();
+            drop(buffer);
+            #[cfg(feature = "async")]
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Wallet {
+    /// Subscribe to the events of the wallet, buffering up to
+    /// [`DEFAULT_SUBSCRIPTION_CAPACITY`] events, see the
+    /// [module-level documentation](crate::wallet::subscriptions).
+    pub fn subscribe(&mut self) -> WalletSubscription {
+        self.subscribe_with_capacity(DEFAULT_SUBSCRIPTION_CAPACITY)
+    }
+
+    /// Subscribe to the events of the wallet, buffering up to `capacity` events.
+    ///
+    /// When the buffer is full, the oldest events are dropped to make room for the new ones, and
+    /// counted by [`WalletSubscription::dropped_events`]. A capacity of 0 drops all the events.
+    pub fn subscribe_with_capacity(&mut self, capacity: usize) -> WalletSubscription {
+        let buffer = Arc::new(Mutex::new(Buffer {
+            events: VecDeque::new(),
+            capacity,
+            dropped: 0,
+            closed: false,
+            #[cfg(feature = "async")]
+            waker: None,
+        }));
+        self.subscriptions.buffers.push(Arc::downgrade(&buffer));
+        WalletSubscription { buffer }
+    }
+}
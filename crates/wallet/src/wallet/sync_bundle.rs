@@ -0,0 +1,459 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Exchanging the state of a wallet between devices, see [`Wallet::export_update_since`]
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use bdk_chain::local_chain::{self, CannotConnectError, CheckPoint};
+use bdk_chain::{
+    tx_graph, Anchor, Append, BlockId, BroadcastRecord, ConfirmationTimeHeightAnchor,
+    DescriptorExt, DescriptorId, LabelRef, TxGraph,
+};
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::{BlockHash, Network, Txid};
+use serde::{Deserialize, Serialize};
+
+use super::fingerprint::push_keychain;
+use super::{BlockTimeOrHeight, ChangeSet, Wallet};
+use crate::collections::{BTreeMap, BTreeSet};
+use crate::KeychainKind;
+
+/// A summary of the state of a wallet that two devices can compare, see
+/// [`Wallet::sync_marker`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SyncMarker {
+    /// The tip of the chain of the wallet
+    pub tip: BlockId,
+    /// The hash of the transactions, anchors, last seen and eviction timestamps, revealed
+    /// indices, labels and broadcast journal of the wallet
+    pub content_hash: sha256::Hash,
+}
+
+/// The state of a wallet sent to another device running the same descriptors, see
+/// [`Wallet::export_update_since`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletUpdateBundle {
+    /// The network of the exporting wallet
+    pub network: Network,
+    /// The descriptors of the exporting wallet
+    pub descriptors: BTreeMap<KeychainKind, DescriptorId>,
+    /// The state of the exporting wallet, to export the next bundle since
+    pub marker: SyncMarker,
+    /// The blocks of the chain
+    pub chain: BTreeMap<u32, BlockHash>,
+    /// The transactions, txouts, anchors, last seen and eviction timestamps
+    pub graph: tx_graph::ChangeSet<ConfirmationTimeHeightAnchor>,
+    /// The last revealed index of each keychain
+    pub last_revealed: BTreeMap<KeychainKind, u32>,
+    /// The labels, as a list so that they can be serialized to formats with string keys only
+    pub labels: Vec<(LabelRef, String)>,
+    /// The broadcast journal
+    pub broadcasts: Vec<BroadcastRecord>,
+    /// The birthday of the wallet
+    pub birthday: Option<BlockTimeOrHeight>,
+}
+
+/// What [`Wallet::apply_update_bundle`] changed in the wallet
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyReport {
+    /// The blocks added to (`Some`) or removed from (`None`) the chain
+    pub chain: local_chain::ChangeSet,
+    /// Whether the chain of the bundle was ignored, because it conflicts with the chain of the
+    /// wallet and isn't longer
+    pub ignored_chain: bool,
+    /// The transactions added
+    pub new_txs: BTreeSet<Txid>,
+    /// The transactions already in the wallet that got a new anchor, a later last seen or
+    /// eviction timestamp, or new txouts
+    pub updated_txs: BTreeSet<Txid>,
+    /// The keychains whose last revealed index increased, with the new index
+    pub revealed: BTreeMap<KeychainKind, u32>,
+    /// The labels added
+    pub new_labels: Vec<LabelRef>,
+    /// The labels set differently on both devices, the label of the wallet is kept
+    pub conflicting_labels: Vec<LabelRef>,
+    /// The number of broadcast records added
+    pub new_broadcasts: usize,
+    /// Whether the birthday of the bundle was adopted, the wallet didn't have one
+    pub birthday: bool,
+}
+
+impl ApplyReport {
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+            && self.new_txs.is_empty()
+            && self.updated_txs.is_empty()
+            && self.revealed.is_empty()
+            && self.new_labels.is_empty()
+            && self.new_broadcasts == 0
+            && !self.birthday
+    }
+}
+
+/// Error of [`Wallet::apply_update_bundle`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApplyBundleError {
+    /// The bundle was exported by a wallet on another network
+    NetworkMismatch {
+        /// The network of the wallet
+        expected: Network,
+        /// The network of the bundle
+        found: Network,
+    },
+    /// The bundle has another descriptor for this keychain
+    DescriptorMismatch(KeychainKind),
+    /// The bundle has another genesis block
+    GenesisMismatch,
+    /// The chain of the bundle doesn't connect to the chain of the wallet
+    CannotConnect(CannotConnectError),
+}
+
+impl fmt::Display for ApplyBundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NetworkMismatch { expected, found } => write!(
+                f,
+                "The bundle is for network {}, the wallet is on {}",
+                found, expected
+            ),
+            Self::DescriptorMismatch(keychain) => write!(
+                f,
+                "The bundle has another descriptor for keychain {:?}",
+                keychain
+            ),
+            Self::GenesisMismatch => write!(f, "The bundle has another genesis block"),
+            Self::CannotConnect(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ApplyBundleError {}
+
+impl Wallet {
+    /// The [`SyncMarker`] of the current state of the wallet.
+    ///
+    /// Send it to another device running the same descriptors, which answers with the bundle of
+    /// [`Wallet::export_update_since`]. Two wallets with the same marker have the same
+    /// transactions, revealed addresses, labels and broadcast journal on the same chain tip.
+    pub fn sync_marker(&self) -> SyncMarker {
+        let mut engine = sha256::Hash::engine();
+        let mut input = |bytes: &[u8]| {
+            engine.input(&(bytes.len() as u64).to_le_bytes());
+            engine.input(bytes);
+        };
+
+        // the number of entries of each section, then the entries
+        let graph = self.indexed_graph.graph().initial_changeset();
+        let last_revealed = self.indexed_graph.index.last_revealed_indices();
+        for len in [
+            graph.txs.len(),
+            graph.txouts.len(),
+            graph.anchors.len(),
+            graph.last_seen.len(),
+            graph.last_evicted.len(),
+            last_revealed.len(),
+            self.labels.len(),
+            self.broadcasts.len(),
+        ] {
+            input(&(len as u64).to_le_bytes());
+        }
+        for tx in &graph.txs {
+            input(tx.compute_txid().as_byte_array());
+        }
+        for (outpoint, txout) in &graph.txouts {
+            input(&serialize(outpoint));
+            input(&serialize(txout));
+        }
+        for (anchor, txid) in &graph.anchors {
+            let anchor_block = anchor.anchor_block();
+            input(&anchor_block.height.to_le_bytes());
+            input(anchor_block.hash.as_byte_array());
+            input(&anchor.confirmation_height.to_le_bytes());
+            input(&anchor.confirmation_time.to_le_bytes());
+            input(txid.as_byte_array());
+        }
+        for (txid, last_seen) in &graph.last_seen {
+            input(txid.as_byte_array());
+            input(&last_seen.to_le_bytes());
+        }
+        for (txid, last_evicted) in &graph.last_evicted {
+            input(txid.as_byte_array());
+            input(&last_evicted.to_le_bytes());
+        }
+        for (keychain, index) in last_revealed {
+            let mut record = Vec::new();
+            push_keychain(&mut record, &keychain);
+            input(&record);
+            input(&index.to_le_bytes());
+        }
+        for (label_ref, label) in &self.labels {
+            match label_ref {
+                LabelRef::Tx(txid) => input(&[&[b't'][..], &serialize(txid)].concat()),
+                LabelRef::Addr(spk) => input(&[&[b'a'][..], spk.as_bytes()].concat()),
+                LabelRef::Output(outpoint) => input(&[&[b'o'][..], &serialize(outpoint)].concat()),
+                LabelRef::Input(outpoint) => input(&[&[b'i'][..], &serialize(outpoint)].concat()),
+            }
+            input(label.as_bytes());
+        }
+        for record in &self.broadcasts {
+            input(record.txid.as_byte_array());
+            input(record.backend.as_bytes());
+            match &record.outcome {
+                bdk_chain::BroadcastOutcome::Accepted => input(&[]),
+                bdk_chain::BroadcastOutcome::Rejected(reason) => input(reason.as_bytes()),
+            }
+            input(&record.timestamp.to_le_bytes());
+        }
+
+        SyncMarker {
+            tip: self.chain.tip().block_id(),
+            content_hash: sha256::Hash::from_engine(engine),
+        }
+    }
+
+    /// Export the state of the wallet for another device running the same descriptors, which
+    /// merges it with [`Wallet::apply_update_bundle`].
+    ///
+    /// `since` is the [`SyncMarker`] of the other device, `None` if it's unknown. The bundle only
+    /// has the blocks above the tip of `since` when that tip is still in the chain of the wallet,
+    /// and the blocks the exported transactions are anchored in. The transactions can't be
+    /// diffed against a hash: the bundle has all of them, along with the revealed indices,
+    /// labels and broadcast journal, unless they have the same hash as in `since`. The
+    /// [`marker`](WalletUpdateBundle::marker) of the bundle is the marker of the wallet.
+    ///
+    /// The bundle is serializable with `serde`, the transport is up to the caller.
+    pub fn export_update_since(&self, since: Option<&SyncMarker>) -> WalletUpdateBundle {
+        let marker = self.sync_marker();
+        let same_content = since.map_or(false, |since| since.content_hash == marker.content_hash);
+        let known_tip = since.and_then(|since| {
+            self.chain
+                .get(since.tip.height)
+                .filter(|cp| cp.hash() == since.tip.hash)
+                .map(|cp| cp.height())
+        });
+
+        let mut bundle = WalletUpdateBundle {
+            network: self.network,
+            descriptors: self
+                .indexed_graph
+                .index
+                .keychains()
+                .map(|(keychain, descriptor)| (*keychain, descriptor.descriptor_id()))
+                .collect(),
+            marker,
+            chain: self
+                .chain
+                .iter_checkpoints()
+                .filter(|cp| known_tip.map_or(true, |height| cp.height() >= height))
+                .map(|cp| (cp.height(), cp.hash()))
+                .collect(),
+            graph: tx_graph::ChangeSet::default(),
+            last_revealed: BTreeMap::new(),
+            labels: Vec::new(),
+            broadcasts: Vec::new(),
+            birthday: self.birthday,
+        };
+        if !same_content {
+            bundle.graph = self.indexed_graph.graph().initial_changeset();
+            for (anchor, _) in &bundle.graph.anchors {
+                let anchor_block = anchor.anchor_block();
+                if let Some(cp) = self.chain.get(anchor_block.height) {
+                    bundle.chain.insert(cp.height(), cp.hash());
+                }
+            }
+            bundle.last_revealed = self.indexed_graph.index.last_revealed_indices();
+            bundle.labels = self.labels.clone().into_iter().collect();
+            bundle.broadcasts = self.broadcasts.clone();
+        }
+        bundle
+    }
+
+    /// Merge the state exported by another device with [`Wallet::export_update_since`], staging
+    /// the changes.
+    ///
+    /// The merge is commutative and idempotent, so two devices exchanging bundles in both
+    /// directions end with the same state:
+    ///
+    /// * the transactions, txouts and anchors are the union of both,
+    /// * the last seen and eviction timestamps are the latest of both,
+    /// * the last revealed indices are the highest of both,
+    /// * the labels only set on the other device are added, the labels of the wallet are kept
+    ///   when both set a different label,
+    /// * the broadcast records unknown to the wallet are added,
+    /// * the chains are merged when they agree on the heights they share. Otherwise the longest
+    ///   chain wins, the wallet keeping its own on a tie.
+    ///
+    /// The keychains of the bundle which the wallet doesn't have are ignored: add them first
+    /// with [`Wallet::add_keychain`] to index their transactions.
+    ///
+    /// # Errors
+    ///
+    /// Fails without changing the wallet if the bundle is for another network or genesis block,
+    /// if it has another descriptor for a keychain of the wallet, or if its chain can't be
+    /// connected.
+    pub fn apply_update_bundle(
+        &mut self,
+        bundle: WalletUpdateBundle,
+    ) -> Result<ApplyReport, ApplyBundleError> {
+        if bundle.network != self.network {
+            return Err(ApplyBundleError::NetworkMismatch {
+                expected: self.network,
+                found: bundle.network,
+            });
+        }
+        for (keychain, descriptor) in self.indexed_graph.index.keychains() {
+            match bundle.descriptors.get(keychain) {
+                Some(descriptor_id) if *descriptor_id != descriptor.descriptor_id() => {
+                    return Err(ApplyBundleError::DescriptorMismatch(*keychain));
+                }
+                _ => {}
+            }
+        }
+        let mut report = ApplyReport::default();
+
+        let mut changeset = ChangeSet::default();
+        match self.merge_bundle_chain(&bundle.chain)? {
+            ChainMerge::UpToDate => {}
+            ChainMerge::Ignored => report.ignored_chain = true,
+            ChainMerge::Update(update) => {
+                let chain_changeset = self
+                    .chain
+                    .apply_update(update)
+                    .map_err(ApplyBundleError::CannotConnect)?;
+                report.chain = chain_changeset.clone();
+                changeset.append(chain_changeset.into());
+            }
+        }
+
+        // the keychains the wallet doesn't have are skipped
+        let last_revealed = self.indexed_graph.index.last_revealed_indices();
+        let index_changeset = self
+            .indexed_graph
+            .index
+            .reveal_to_target_multi(&bundle.last_revealed);
+        for (keychain, index) in self.indexed_graph.index.last_revealed_indices() {
+            if last_revealed.get(&keychain) != Some(&index) {
+                report.revealed.insert(keychain, index);
+            }
+        }
+        changeset.append(index_changeset.into());
+
+        let mut graph = TxGraph::default();
+        graph.apply_changeset(bundle.graph);
+        let graph_changeset = self.indexed_graph.apply_update(graph);
+        report.new_txs = graph_changeset
+            .graph
+            .txs
+            .iter()
+            .map(|tx| tx.compute_txid())
+            .collect();
+        report.updated_txs = graph_changeset
+            .graph
+            .txouts
+            .keys()
+            .map(|outpoint| outpoint.txid)
+            .chain(graph_changeset.graph.anchors.iter().map(|(_, txid)| *txid))
+            .chain(graph_changeset.graph.last_seen.keys().copied())
+            .chain(graph_changeset.graph.last_evicted.keys().copied())
+            .filter(|txid| !report.new_txs.contains(txid))
+            .collect();
+        changeset.append(graph_changeset.into());
+
+        for (label_ref, label) in bundle.labels {
+            match self.labels.get(&label_ref) {
+                None => {
+                    self.labels.insert(label_ref.clone(), label.clone());
+                    changeset.labels.insert(label_ref.clone(), Some(label));
+                    report.new_labels.push(label_ref);
+                }
+                Some(ours) if *ours != label => report.conflicting_labels.push(label_ref),
+                Some(_) => {}
+            }
+        }
+        for record in bundle.broadcasts {
+            if !self.broadcasts.contains(&record) {
+                self.broadcasts.push(record.clone());
+                changeset.broadcasts.push(record);
+                report.new_broadcasts += 1;
+            }
+        }
+        if self.birthday.is_none() && bundle.birthday.is_some() {
+            self.birthday = bundle.birthday;
+            changeset.birthday = bundle.birthday;
+            report.birthday = true;
+        }
+
+        self.stage.append(changeset);
+        Ok(report)
+    }
+
+    /// Merge the `blocks` of a bundle with the chain of the wallet.
+    fn merge_bundle_chain(
+        &self,
+        blocks: &BTreeMap<u32, BlockHash>,
+    ) -> Result<ChainMerge, ApplyBundleError> {
+        let ours = self
+            .chain
+            .iter_checkpoints()
+            .map(|cp| (cp.height(), cp.hash()))
+            .collect::<BTreeMap<_, _>>();
+        let conflict = blocks
+            .iter()
+            .find(|(height, hash)| ours.get(height).map_or(false, |ours| ours != *hash))
+            .map(|(&height, _)| height);
+
+        let merged = match conflict {
+            Some(0) => return Err(ApplyBundleError::GenesisMismatch),
+            None => {
+                if blocks.keys().all(|height| ours.contains_key(height)) {
+                    return Ok(ChainMerge::UpToDate);
+                }
+                let mut merged = ours;
+                merged.extend(blocks);
+                merged
+            }
+            Some(conflict) => {
+                let their_tip = blocks.keys().next_back().copied();
+                let our_tip = ours.keys().next_back().copied();
+                if their_tip <= our_tip {
+                    return Ok(ChainMerge::Ignored);
+                }
+                // the blocks of the wallet above the conflict are replaced
+                let mut merged = ours;
+                let _ = merged.split_off(&conflict);
+                merged.extend(blocks);
+                merged
+            }
+        };
+        let update = CheckPoint::from_block_ids(
+            merged
+                .into_iter()
+                .map(|(height, hash)| BlockId { height, hash }),
+        )
+        .expect("the heights are sorted and the chain is not empty");
+        Ok(ChainMerge::Update(update))
+    }
+}
+
+/// The outcome of [`Wallet::merge_bundle_chain`]
+enum ChainMerge {
+    /// The wallet already has all the blocks of the bundle
+    UpToDate,
+    /// The chain of the bundle conflicts with the chain of the wallet and isn't longer
+    Ignored,
+    /// The merged chain
+    Update(CheckPoint),
+}
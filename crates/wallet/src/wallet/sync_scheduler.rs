@@ -0,0 +1,96 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! The sync of many keychains within a request budget, see [`Wallet::sync_scheduler`]
+
+use alloc::vec::Vec;
+
+use bdk_chain::collections::BTreeMap;
+pub use bdk_chain::spk_client::{
+    KeychainRequest, RequestBudget, Scheduled, SyncScheduler, SyncTicket,
+};
+use bdk_chain::{Append, ChainPosition};
+use bitcoin::{ScriptBuf, Txid};
+
+use super::{ChangeSet, Wallet};
+use crate::KeychainKind;
+
+impl Wallet {
+    /// A [`SyncScheduler`] of all the keychains of the wallet, spending at most `budget`
+    ///
+    /// Each keychain is synced with its revealed script pubkeys and its unconfirmed transactions.
+    /// Its priority is the time of its last activity: the confirmation time, or the last seen
+    /// time if unconfirmed, of the latest transaction with an output of the keychain. So the
+    /// recently active keychains are synced first, then the stalest ones, according to the times
+    /// recorded with [`Wallet::record_keychain_synced`].
+    pub fn sync_scheduler(&self, budget: RequestBudget) -> SyncScheduler<KeychainKind> {
+        let index = &self.indexed_graph.index;
+        let mut activity = BTreeMap::<KeychainKind, u64>::new();
+        let mut unconfirmed = BTreeMap::<KeychainKind, Vec<Txid>>::new();
+        for tx in self.transactions() {
+            let time = match tx.chain_position {
+                ChainPosition::Confirmed(anchor) => anchor.confirmation_time,
+                ChainPosition::Unconfirmed(last_seen) => last_seen,
+            };
+            for txout in &tx.tx_node.tx.output {
+                let keychain = match index.index_of_spk(&txout.script_pubkey) {
+                    Some((keychain, _)) => *keychain,
+                    None => continue,
+                };
+                let last_activity = activity.entry(keychain).or_default();
+                *last_activity = (*last_activity).max(time);
+                if let ChainPosition::Unconfirmed(_) = tx.chain_position {
+                    let txids = unconfirmed.entry(keychain).or_default();
+                    if txids.last() != Some(&tx.tx_node.txid) {
+                        txids.push(tx.tx_node.txid);
+                    }
+                }
+            }
+        }
+
+        let mut scheduler = SyncScheduler::new(budget);
+        for (keychain, _) in index.keychains() {
+            let request = KeychainRequest {
+                keychain: *keychain,
+                spks: index
+                    .revealed_keychain_spks(keychain)
+                    .map(|(_, spk)| ScriptBuf::from(spk))
+                    .collect(),
+                txids: unconfirmed.remove(keychain).unwrap_or_default(),
+                last_synced: self.last_synced.get(keychain).copied(),
+            };
+            let priority = activity.get(keychain).copied().unwrap_or(0);
+            scheduler = scheduler.add(request, priority);
+        }
+        scheduler
+    }
+
+    /// Record that `keychain` was synced at the time `at`, in unix seconds. The time is
+    /// persisted, an earlier time than the one recorded is ignored.
+    ///
+    /// Typically called with the [`SyncScheduler::last_synced`] times, once the results of the
+    /// scheduler are applied to the wallet.
+    pub fn record_keychain_synced(&mut self, keychain: KeychainKind, at: u64) {
+        let last_synced = self.last_synced.entry(keychain).or_default();
+        if *last_synced > at {
+            return;
+        }
+        *last_synced = at;
+        self.stage.append(ChangeSet {
+            last_synced: [(keychain, at)].into(),
+            ..Default::default()
+        });
+    }
+
+    /// When `keychain` was last synced, see [`Wallet::record_keychain_synced`].
+    pub fn keychain_last_synced(&self, keychain: KeychainKind) -> Option<u64> {
+        self.last_synced.get(&keychain).copied()
+    }
+}
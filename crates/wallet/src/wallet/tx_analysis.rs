@@ -0,0 +1,417 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Weight of the inputs of signed transactions
+//!
+//! [`input_weights`] splits the weight of each input of a signed transaction between its
+//! signatures, public keys, scripts and control blocks, telling them apart from the shape of the
+//! `scriptSig` pushes and witness elements. [`Wallet::input_weights`] does the same with the
+//! descriptors of the outputs spent, and reports the spending path of each input along with the
+//! satisfaction weight estimated by its descriptor, to check the estimates used to compute fees.
+
+use alloc::vec::Vec;
+
+use bitcoin::script::Instruction;
+use bitcoin::taproot::{LeafVersion, TapLeafHash, TAPROOT_ANNEX_PREFIX};
+use bitcoin::{Script, Transaction, TxIn, Weight, Witness};
+use miniscript::descriptor::{Descriptor, DescriptorType};
+use miniscript::{ForEachKey, ToPublicKey};
+use serde::{Deserialize, Serialize};
+
+use super::Wallet;
+use crate::collections::BTreeSet;
+use crate::descriptor::DerivedDescriptor;
+use crate::KeychainKind;
+
+/// How the weight of an input of a signed transaction splits, see [`input_weights`]
+///
+/// The parts add up to [`InputWeightBreakdown::weight`]. A push or a witness element counts in
+/// one part, the opcode or the length prefix pushing it counts in `overhead`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputWeightBreakdown {
+    /// The index of the input
+    pub index: usize,
+    /// The weight of the input, its witness included if the transaction has any
+    ///
+    /// The segwit marker and flag of the transaction aren't attributed to any input.
+    pub weight: Weight,
+    /// The weight of the `scriptSig` and witness beyond the ones of an input without them, to be
+    /// compared with the estimates of `max_weight_to_satisfy` of the descriptors
+    pub satisfaction_weight: Weight,
+    /// The signatures
+    pub signatures: Weight,
+    /// The public keys
+    pub public_keys: Weight,
+    /// The redeem script, the witness script or the tapscript
+    pub scripts: Weight,
+    /// The taproot control block
+    pub control_blocks: Weight,
+    /// The other pushes and witness elements, such as hash preimages, the empty elements of
+    /// `CHECKMULTISIG` or the annex
+    pub other: Weight,
+    /// The outpoint, the sequence, the length prefixes and the push opcodes
+    pub overhead: Weight,
+}
+
+/// How an input spends its output, see [`Wallet::input_weights`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpendPath {
+    /// With a single key: the key path of a taproot output, or a `pkh`, `wpkh` or `sh(wpkh)`
+    /// output
+    KeySpend,
+    /// With a script: the leaf of a taproot output, or the redeem or witness script of another
+    /// output
+    ScriptSpend {
+        /// The hash of the taproot leaf, `None` for the other outputs
+        leaf_hash: Option<TapLeafHash>,
+    },
+}
+
+/// The weight of an input spending an output of the wallet, see [`Wallet::input_weights`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletInputWeight {
+    /// The split of the weight of the input
+    pub breakdown: InputWeightBreakdown,
+    /// The keychain of the output spent, `None` if it isn't an output of the wallet
+    pub keychain: Option<KeychainKind>,
+    /// The derivation index of the output spent, `None` if it isn't an output of the wallet
+    pub derivation_index: Option<u32>,
+    /// How the input spends the output, `None` if it isn't an output of the wallet
+    pub spend_path: Option<SpendPath>,
+    /// The upper bound of the satisfaction weight computed from the descriptor, which
+    /// [`InputWeightBreakdown::satisfaction_weight`] doesn't exceed if the estimate is right
+    pub estimated_satisfaction_weight: Option<Weight>,
+}
+
+/// The split of the weight of each input of the signed `tx`, in the order of the inputs
+///
+/// Without the outputs spent, the parts are told apart from their shape: DER-encoded and
+/// 64 or 65 bytes taproot signatures, compressed and uncompressed public keys, the taproot control
+/// block and the annex, the witness script as the last witness element and the redeem script as
+/// the last push of the `scriptSig`. Use [`Wallet::input_weights`] to rely on the descriptors of
+/// the wallet instead.
+pub fn input_weights(tx: &Transaction) -> Vec<InputWeightBreakdown> {
+    let segwit = tx.input.iter().any(|txin| !txin.witness.is_empty());
+    tx.input
+        .iter()
+        .enumerate()
+        .map(|(index, txin)| breakdown(index, txin, segwit, None))
+        .collect()
+}
+
+/// The keys and scripts of the descriptor of the output spent by an input
+struct Spent {
+    keys: BTreeSet<Vec<u8>>,
+    scripts: BTreeSet<Vec<u8>>,
+    is_taproot: bool,
+}
+
+impl Spent {
+    fn of(descriptor: &DerivedDescriptor) -> Self {
+        let mut keys = BTreeSet::new();
+        descriptor.for_each_key(|pk| {
+            let pk = pk.to_public_key();
+            keys.insert(pk.to_bytes());
+            keys.insert(pk.to_x_only_pubkey().serialize().to_vec());
+            true
+        });
+        let mut scripts = BTreeSet::new();
+        if let Ok(script) = descriptor.explicit_script() {
+            scripts.insert(script.into_bytes());
+        }
+        // the redeem script of the segwit outputs wrapped in P2SH
+        for instruction in descriptor.unsigned_script_sig().instructions().flatten() {
+            if let Instruction::PushBytes(push) = instruction {
+                scripts.insert(push.as_bytes().to_vec());
+            }
+        }
+        if let Descriptor::Tr(tr) = descriptor {
+            for (_, ms) in tr.iter_scripts() {
+                scripts.insert(ms.encode().into_bytes());
+            }
+        }
+        Spent {
+            keys,
+            scripts,
+            is_taproot: matches!(descriptor, Descriptor::Tr(_)),
+        }
+    }
+}
+
+/// A part of [`InputWeightBreakdown`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Signature,
+    PublicKey,
+    Script,
+    ControlBlock,
+    Other,
+}
+
+fn is_ecdsa_signature(data: &[u8]) -> bool {
+    // a DER signature and the sighash type
+    (9..=73).contains(&data.len()) && data[0] == 0x30 && usize::from(data[1]) + 3 == data.len()
+}
+
+fn is_schnorr_signature(data: &[u8]) -> bool {
+    data.len() == 64 || data.len() == 65
+}
+
+fn is_public_key(data: &[u8]) -> bool {
+    (data.len() == 33 && (data[0] == 0x02 || data[0] == 0x03))
+        || (data.len() == 65 && data[0] == 0x04)
+}
+
+fn is_control_block(data: &[u8]) -> bool {
+    data.len() >= 33 && (data.len() - 33) % 32 == 0 && (data.len() - 33) / 32 <= 128
+}
+
+/// The annex, the script and the control block of a taproot witness, `None` for a key spend
+fn taproot_parts(witness: &Witness) -> (Option<usize>, Option<(usize, usize)>) {
+    let len = witness.len();
+    let annex = match witness.last() {
+        Some(last) if len >= 2 && last.first() == Some(&TAPROOT_ANNEX_PREFIX) => Some(len - 1),
+        _ => None,
+    };
+    let stack_len = annex.unwrap_or(len);
+    let script = if stack_len >= 2 {
+        Some((stack_len - 2, stack_len - 1))
+    } else {
+        None
+    };
+    (annex, script)
+}
+
+/// Whether `witness` looks like the one of a taproot input
+fn looks_taproot(witness: &Witness) -> bool {
+    match taproot_parts(witness) {
+        (_, Some((_, control_block))) => witness.nth(control_block).map_or(false, |data| {
+            is_control_block(data) && data[0] & 0xfe == 0xc0
+        }),
+        (_, None) => witness.nth(0).map_or(false, is_schnorr_signature),
+    }
+}
+
+fn classify(data: &[u8], spent: Option<&Spent>, taproot: bool) -> Part {
+    if let Some(spent) = spent {
+        if spent.keys.contains(data) {
+            return Part::PublicKey;
+        }
+        if spent.scripts.contains(data) {
+            return Part::Script;
+        }
+    }
+    if taproot {
+        if is_schnorr_signature(data) {
+            return Part::Signature;
+        }
+    } else if is_ecdsa_signature(data) {
+        return Part::Signature;
+    }
+    if spent.is_none() && is_public_key(data) {
+        return Part::PublicKey;
+    }
+    Part::Other
+}
+
+struct Weights {
+    signatures: Weight,
+    public_keys: Weight,
+    scripts: Weight,
+    control_blocks: Weight,
+    other: Weight,
+}
+
+impl Weights {
+    const ZERO: Weights = Weights {
+        signatures: Weight::ZERO,
+        public_keys: Weight::ZERO,
+        scripts: Weight::ZERO,
+        control_blocks: Weight::ZERO,
+        other: Weight::ZERO,
+    };
+
+    fn add(&mut self, part: Part, weight: Weight) {
+        let total = match part {
+            Part::Signature => &mut self.signatures,
+            Part::PublicKey => &mut self.public_keys,
+            Part::Script => &mut self.scripts,
+            Part::ControlBlock => &mut self.control_blocks,
+            Part::Other => &mut self.other,
+        };
+        *total += weight;
+    }
+}
+
+fn breakdown(
+    index: usize,
+    txin: &TxIn,
+    segwit: bool,
+    spent: Option<&Spent>,
+) -> InputWeightBreakdown {
+    let mut weights = Weights::ZERO;
+    let witness = &txin.witness;
+    let taproot = spent.map_or_else(|| looks_taproot(witness), |spent| spent.is_taproot);
+
+    // the pushes of the `scriptSig`, its last one being the redeem script of a P2SH output
+    let pushes = txin
+        .script_sig
+        .instructions()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_default();
+    for (i, instruction) in pushes.iter().enumerate() {
+        if let Instruction::PushBytes(push) = instruction {
+            let data = push.as_bytes();
+            let mut part = classify(data, spent, false);
+            if spent.is_none() && part == Part::Other && !data.is_empty() && i + 1 == pushes.len() {
+                part = Part::Script;
+            }
+            weights.add(part, Weight::from_non_witness_data_size(data.len() as u64));
+        }
+    }
+    // the opcodes which aren't pushes count with the other parts, the push opcodes with the
+    // overhead
+    let opcodes = pushes
+        .iter()
+        .filter(|instruction| matches!(instruction, Instruction::Op(_)))
+        .count();
+    weights.add(
+        Part::Other,
+        Weight::from_non_witness_data_size(opcodes as u64),
+    );
+
+    if taproot {
+        let (annex, script) = taproot_parts(witness);
+        for (i, data) in witness.iter().enumerate() {
+            let part = if Some(i) == annex {
+                Part::Other
+            } else if script.map_or(false, |(_, control_block)| i == control_block) {
+                Part::ControlBlock
+            } else if script.map_or(false, |(script, _)| i == script) {
+                Part::Script
+            } else {
+                classify(data, spent, true)
+            };
+            weights.add(part, Weight::from_witness_data_size(data.len() as u64));
+        }
+    } else {
+        let len = witness.len();
+        // a P2WPKH witness is a signature and a public key, the last element of the others is
+        // the witness script
+        let p2wpkh = len == 2
+            && witness.nth(1).map_or(false, |data| match spent {
+                Some(spent) => spent.keys.contains(data),
+                None => is_public_key(data),
+            });
+        for (i, data) in witness.iter().enumerate() {
+            let mut part = classify(data, spent, false);
+            if spent.is_none() && !p2wpkh && i + 1 == len {
+                part = Part::Script;
+            }
+            weights.add(part, Weight::from_witness_data_size(data.len() as u64));
+        }
+    }
+
+    let weight = if segwit {
+        txin.segwit_weight()
+    } else {
+        txin.legacy_weight()
+    };
+    let unsatisfied = TxIn {
+        previous_output: txin.previous_output,
+        sequence: txin.sequence,
+        ..Default::default()
+    };
+    let parts = weights.signatures
+        + weights.public_keys
+        + weights.scripts
+        + weights.control_blocks
+        + weights.other;
+    InputWeightBreakdown {
+        index,
+        weight,
+        satisfaction_weight: txin.segwit_weight() - unsatisfied.segwit_weight(),
+        signatures: weights.signatures,
+        public_keys: weights.public_keys,
+        scripts: weights.scripts,
+        control_blocks: weights.control_blocks,
+        other: weights.other,
+        overhead: weight - parts,
+    }
+}
+
+/// How `txin` spends an output of `descriptor`
+fn spend_path(descriptor: &DerivedDescriptor, txin: &TxIn) -> SpendPath {
+    match descriptor.desc_type() {
+        DescriptorType::Tr => match taproot_parts(&txin.witness) {
+            (_, Some((script, control_block))) => {
+                let leaf_hash = txin
+                    .witness
+                    .nth(script)
+                    .zip(txin.witness.nth(control_block))
+                    .and_then(|(script, control_block)| {
+                        let version = LeafVersion::from_consensus(control_block[0] & 0xfe).ok()?;
+                        Some(TapLeafHash::from_script(
+                            Script::from_bytes(script),
+                            version,
+                        ))
+                    });
+                SpendPath::ScriptSpend { leaf_hash }
+            }
+            (_, None) => SpendPath::KeySpend,
+        },
+        DescriptorType::Pkh | DescriptorType::Wpkh | DescriptorType::ShWpkh => SpendPath::KeySpend,
+        _ => SpendPath::ScriptSpend { leaf_hash: None },
+    }
+}
+
+impl Wallet {
+    /// The split of the weight of each input of the signed `tx`, in the order of the inputs,
+    /// using the descriptors of the outputs of the wallet it spends
+    ///
+    /// The outputs spent are looked up in the wallet's graph. The inputs spending other outputs
+    /// are split like with [`input_weights`], without a keychain nor a spending path.
+    pub fn input_weights(&self, tx: &Transaction) -> Vec<WalletInputWeight> {
+        let segwit = tx.input.iter().any(|txin| !txin.witness.is_empty());
+        let graph = self.indexed_graph.graph();
+        tx.input
+            .iter()
+            .enumerate()
+            .map(|(index, txin)| {
+                let derived = graph
+                    .get_txout(txin.previous_output)
+                    .and_then(|txout| self.indexed_graph.index.index_of_spk(&txout.script_pubkey))
+                    .and_then(|&(keychain, derivation_index)| {
+                        let descriptor = self
+                            .get_descriptor_for_keychain(keychain)
+                            .at_derivation_index(derivation_index)
+                            .ok()?;
+                        Some((keychain, derivation_index, descriptor))
+                    });
+                match derived {
+                    Some((keychain, derivation_index, descriptor)) => WalletInputWeight {
+                        breakdown: breakdown(index, txin, segwit, Some(&Spent::of(&descriptor))),
+                        keychain: Some(keychain),
+                        derivation_index: Some(derivation_index),
+                        spend_path: Some(spend_path(&descriptor, txin)),
+                        estimated_satisfaction_weight: descriptor.max_weight_to_satisfy().ok(),
+                    },
+                    None => WalletInputWeight {
+                        breakdown: breakdown(index, txin, segwit, None),
+                        keychain: None,
+                        derivation_index: None,
+                        spend_path: None,
+                        estimated_satisfaction_weight: None,
+                    },
+                }
+            })
+            .collect()
+    }
+}
@@ -38,16 +38,25 @@
 //! # Ok::<(), anyhow::Error>(())
 //! ```
 
-use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use alloc::{boxed::Box, rc::Rc, string::String, sync::Arc, vec::Vec};
 use core::cell::RefCell;
 use core::fmt;
 
+use bitcoin::address::NetworkUnchecked;
 use bitcoin::psbt::{self, Psbt};
 use bitcoin::script::PushBytes;
-use bitcoin::{absolute, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, Txid};
-
-use super::coin_selection::CoinSelectionAlgorithm;
-use super::{CreateTxError, Wallet};
+use bitcoin::{
+    absolute, Address, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, Txid, Weight,
+};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use super::coin_selection::{CoinSelectionAlgorithm, Consolidation};
+use super::fee_strategy::{FeeEstimates, FeeResolution, FeeStrategy, FeeStrategyError};
+use super::{
+    dust_value, ChangeAddressPolicy, ChangeAddressPolicyError, CreateTxError, Wallet,
+    WitnessProvider,
+};
 use crate::collections::{BTreeMap, HashSet};
 use crate::{KeychainKind, LocalOutput, Utxo, WeightedUtxo};
 
@@ -118,36 +127,128 @@ pub(crate) struct TxParams {
     pub(crate) recipients: Vec<(ScriptBuf, u64)>,
     pub(crate) drain_wallet: bool,
     pub(crate) drain_to: Option<ScriptBuf>,
+    pub(crate) change_script: Option<ScriptBuf>,
+    pub(crate) change_position: Option<usize>,
     pub(crate) fee_policy: Option<FeePolicy>,
+    pub(crate) requested_fee_rate: Option<FeeRate>,
+    pub(crate) requested_fee_absolute: Option<Amount>,
     pub(crate) internal_policy_path: Option<BTreeMap<String, Vec<usize>>>,
     pub(crate) external_policy_path: Option<BTreeMap<String, Vec<usize>>>,
+    pub(crate) extra_policy_paths: BTreeMap<KeychainKind, BTreeMap<String, Vec<usize>>>,
     pub(crate) utxos: Vec<WeightedUtxo>,
     pub(crate) unspendable: HashSet<OutPoint>,
     pub(crate) manually_selected_only: bool,
     pub(crate) sighash: Option<psbt::PsbtSighashType>,
     pub(crate) ordering: TxOrdering,
-    pub(crate) locktime: Option<absolute::LockTime>,
+    pub(crate) locktime_policy: LocktimePolicy,
     pub(crate) rbf: Option<RbfValue>,
     pub(crate) version: Option<Version>,
     pub(crate) change_policy: ChangeSpendPolicy,
     pub(crate) only_witness_utxo: bool,
     pub(crate) add_global_xpubs: bool,
+    pub(crate) proprietary: BTreeMap<psbt::raw::ProprietaryKey, Vec<u8>>,
     pub(crate) include_output_redeem_witness_script: bool,
     pub(crate) bumping_fee: Option<PreviousFee>,
     pub(crate) current_height: Option<absolute::LockTime>,
     pub(crate) allow_dust: bool,
+    pub(crate) dust_relay_feerate: Option<FeeRate>,
+    pub(crate) skip_invalid_recipients: bool,
+    pub(crate) skipped_recipients: Vec<(usize, RecipientError)>,
+    pub(crate) merge_duplicate_recipients: bool,
+    pub(crate) deduplicate_recipients: Option<super::TimeOrHeightWindow>,
+    pub(crate) deterministic_seed: Option<[u8; 32]>,
+    pub(crate) reserve_inputs: bool,
+    pub(crate) fee_limits: Option<super::FeeLimits>,
+    pub(crate) acknowledge_fee_limits: bool,
+    pub(crate) opportunistic_consolidation: Option<Consolidation>,
+    pub(crate) witness_providers: BTreeMap<OutPoint, Arc<dyn WitnessProvider>>,
+    pub(crate) change_address_policy: Option<ChangeAddressPolicy>,
+    #[cfg(feature = "silent-payments")]
+    pub(crate) silent_payment_recipients: Vec<(super::silent_payments::SilentPaymentAddress, u64)>,
+}
+
+impl TxParams {
+    /// The feerate used to compute the dust limits
+    pub(crate) fn dust_relay_feerate(&self) -> FeeRate {
+        self.dust_relay_feerate
+            .unwrap_or(super::DEFAULT_DUST_RELAY_FEERATE)
+    }
+
+    /// Set the fee policy requested with the builder
+    fn set_fee_policy(&mut self, policy: FeePolicy) {
+        match policy {
+            FeePolicy::FeeRate(rate) => self.requested_fee_rate = Some(rate),
+            FeePolicy::FeeAmount(fee) => self.requested_fee_absolute = Some(fee),
+        }
+        self.fee_policy = Some(policy);
+    }
+
+    /// The inconsistencies between the parameters, see [`TxBuilder::validate`]
+    fn param_errors(&self) -> Vec<ParamError> {
+        let mut errors = Vec::new();
+
+        if let (Some(fee_rate), Some(fee)) = (self.requested_fee_rate, self.requested_fee_absolute)
+        {
+            errors.push(ParamError::ConflictingFees {
+                fee_rate,
+                fee_absolute: fee,
+            });
+        }
+        if self.version == Some(Version(0)) {
+            errors.push(ParamError::Version0);
+        }
+        if let Some(RbfValue::Value(sequence)) = self.rbf {
+            if !sequence.is_rbf() {
+                errors.push(ParamError::RbfSequence(sequence));
+            }
+        }
+        if self.manually_selected_only && self.utxos.is_empty() {
+            errors.push(ParamError::NoUtxosSelected);
+        }
+        #[cfg(feature = "silent-payments")]
+        let no_recipients = self.recipients.is_empty() && self.silent_payment_recipients.is_empty();
+        #[cfg(not(feature = "silent-payments"))]
+        let no_recipients = self.recipients.is_empty();
+        // without recipients, the transaction can only drain its inputs
+        if no_recipients
+            && !(self.drain_to.is_some() && (self.drain_wallet || !self.utxos.is_empty()))
+        {
+            errors.push(ParamError::NoRecipients);
+        }
+        if let Some(position) = self.change_position {
+            if self.ordering == TxOrdering::Bip69Lexicographic {
+                errors.push(ParamError::ChangePositionBip69 { position });
+            }
+        }
+        errors
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
 pub(crate) struct PreviousFee {
-    pub absolute: u64,
+    pub absolute: Amount,
     pub rate: FeeRate,
 }
 
+impl PreviousFee {
+    /// The minimum fee rate of a replacement, the minimum relay fee rate above the original one
+    pub(crate) fn min_replacement_fee_rate(&self) -> FeeRate {
+        FeeRate::from_sat_per_kwu(
+            self.rate.to_sat_per_kwu() + FeeRate::BROADCAST_MIN.to_sat_per_kwu(),
+        )
+    }
+
+    /// The minimum absolute fee of a replacement of `weight`, BIP125 rules 3 and 4: the fee of
+    /// the original transaction plus the minimum relay fee for its own size
+    pub(crate) fn min_replacement_fee(&self, weight: Weight) -> Amount {
+        self.absolute + FeeRate::BROADCAST_MIN * weight
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum FeePolicy {
     FeeRate(FeeRate),
-    FeeAmount(u64),
+    FeeAmount(Amount),
 }
 
 impl Default for FeePolicy {
@@ -178,22 +279,44 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
     /// Note that this is really a minimum feerate -- it's possible to
     /// overshoot it slightly since adding a change output to drain the remaining
     /// excess might not be viable.
-    pub fn fee_rate(&mut self, fee_rate: FeeRate) -> &mut Self {
-        self.params.fee_policy = Some(FeePolicy::FeeRate(fee_rate));
+    ///
+    /// Besides a [`FeeRate`], this accepts the fee rate units of the [`units`](crate::units)
+    /// module, such as [`SatPerVb`](crate::units::SatPerVb), which are rounded up to the next
+    /// sat/kwu.
+    pub fn fee_rate(&mut self, fee_rate: impl Into<FeeRate>) -> &mut Self {
+        self.params
+            .set_fee_policy(FeePolicy::FeeRate(fee_rate.into()));
         self
     }
 
+    /// Set the fee rate resolved from a [`FeeStrategy`] and the `estimates` of a backend
+    ///
+    /// The fee rate is clamped between the minimum relay fee of `estimates` and `max_fee_rate`,
+    /// see [`FeeStrategy::resolve`]. The returned [`FeeResolution`] tells which target and raw
+    /// estimate were used. On error the fee policy is left unchanged.
+    pub fn fee_strategy<E: FeeEstimates + ?Sized>(
+        &mut self,
+        strategy: FeeStrategy,
+        estimates: &E,
+        max_fee_rate: FeeRate,
+    ) -> Result<FeeResolution, FeeStrategyError> {
+        let resolution = strategy.resolve(estimates, max_fee_rate)?;
+        self.params
+            .set_fee_policy(FeePolicy::FeeRate(resolution.fee_rate));
+        Ok(resolution)
+    }
+
     /// Set an absolute fee
     /// The fee_absolute method refers to the absolute transaction fee in [`Amount`].
-    /// If anyone sets both the `fee_absolute` method and the `fee_rate` method,
-    /// the `FeePolicy` enum will be set by whichever method was called last,
-    /// as the [`FeeRate`] and `FeeAmount` are mutually exclusive.
+    /// The [`FeeRate`] and the absolute fee are mutually exclusive: if both the `fee_absolute`
+    /// method and the `fee_rate` method are called, [`TxBuilder::validate`] reports
+    /// [`ParamError::ConflictingFees`].
     ///
     /// Note that this is really a minimum absolute fee -- it's possible to
     /// overshoot it slightly since adding a change output to drain the remaining
     /// excess might not be viable.
     pub fn fee_absolute(&mut self, fee_amount: Amount) -> &mut Self {
-        self.params.fee_policy = Some(FeePolicy::FeeAmount(fee_amount.to_sat()));
+        self.params.set_fee_policy(FeePolicy::FeeAmount(fee_amount));
         self
     }
 
@@ -239,6 +362,11 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
     /// If a particularly complex descriptor has multiple ambiguous thresholds in its structure,
     /// multiple entries can be added to the map, one for each node that requires an explicit path.
     ///
+    /// If the selected path contains a height-based timelock that hasn't expired yet for the
+    /// selected inputs at the [current height](Self::current_height), [`finish`](Self::finish)
+    /// will fail with [`CreateTxError::TimelockNotMature`], reporting the height at which the
+    /// path becomes valid.
+    ///
     /// ```
     /// # use std::str::FromStr;
     /// # use std::collections::BTreeMap;
@@ -264,9 +392,14 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         policy_path: BTreeMap<String, Vec<usize>>,
         keychain: KeychainKind,
     ) -> &mut Self {
+        let keychain = self.wallet.borrow().map_keychain(keychain);
         let to_update = match keychain {
             KeychainKind::Internal => &mut self.params.internal_policy_path,
             KeychainKind::External => &mut self.params.external_policy_path,
+            KeychainKind::Extra(_) => {
+                self.params.extra_policy_paths.insert(keychain, policy_path);
+                return self;
+            }
         };
 
         *to_update = Some(policy_path);
@@ -279,6 +412,11 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
     ///
     /// These have priority over the "unspendable" utxos, meaning that if a utxo is present both in
     /// the "utxos" and the "unspendable" list, it will be spent.
+    ///
+    /// A coinbase output which isn't mature yet at the [`current_height`] makes the transaction
+    /// fail to build with [`CreateTxError::ImmatureCoinbase`].
+    ///
+    /// [`current_height`]: Self::current_height
     pub fn add_utxos(&mut self, outpoints: &[OutPoint]) -> Result<&mut Self, AddUtxoError> {
         {
             let wallet = self.wallet.borrow();
@@ -310,6 +448,8 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
     ///
     /// These have priority over the "unspendable" utxos, meaning that if a utxo is present both in
     /// the "utxos" and the "unspendable" list, it will be spent.
+    ///
+    /// See [`TxBuilder::add_utxos`] for the coinbase outputs.
     pub fn add_utxo(&mut self, outpoint: OutPoint) -> Result<&mut Self, AddUtxoError> {
         self.add_utxos(&[outpoint])
     }
@@ -355,7 +495,8 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
     ///
     /// Note unless you set [`only_witness_utxo`] any non-taproot `psbt_input` you pass to this
     /// method must have `non_witness_utxo` set otherwise you will get an error when [`finish`]
-    /// is called.
+    /// is called. Inputs of a witness version after taproot are passed through untouched, the
+    /// wallet neither checks nor finalizes them.
     ///
     /// [`only_witness_utxo`]: Self::only_witness_utxo
     /// [`finish`]: Self::finish
@@ -413,6 +554,61 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         Ok(self)
     }
 
+    /// Add a UTXO the wallet can't satisfy, whose final witness is given by `provider`.
+    ///
+    /// This is an escape hatch for scripts miniscript can't express, such as Taproot leaves with
+    /// a covenant. The output must be in the wallet's transaction graph, for instance inserted
+    /// with [`Wallet::insert_txout`], but it doesn't need to belong to one of its descriptors.
+    ///
+    /// Like a [foreign UTXO](Self::add_foreign_utxo), the input weighs `weight_hint` plus its
+    /// outpoint, sequence and script sig in the coin selection and fee math. When the PSBT is
+    /// finalized, for instance by [`Wallet::sign`], the provider is called with the
+    /// [`WitnessContext`] of the input and its witness becomes the final witness, see
+    /// [`Wallet::finalize_psbt`]. The wallet keeps the provider until the transaction is
+    /// canceled with [`Wallet::cancel_tx`].
+    ///
+    /// The witness isn't checked: a wrong witness or a low `weight_hint` make the transaction
+    /// invalid or lower its feerate.
+    ///
+    /// This is an **EXPERIMENTAL** feature, API and other major changes are expected.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AddUtxoError::UnknownUtxo`] if the output isn't in the transaction graph.
+    ///
+    /// [`WitnessContext`]: super::WitnessContext
+    pub fn add_utxo_with_witness_provider(
+        &mut self,
+        outpoint: OutPoint,
+        weight_hint: usize,
+        provider: Arc<dyn WitnessProvider>,
+    ) -> Result<&mut Self, AddUtxoError> {
+        let psbt_input = {
+            let wallet = self.wallet.borrow();
+            let graph = wallet.tx_graph();
+            let txout = graph
+                .get_txout(outpoint)
+                .ok_or(AddUtxoError::UnknownUtxo(outpoint))?;
+            psbt::Input {
+                witness_utxo: Some(txout.clone()),
+                non_witness_utxo: graph.get_tx(outpoint.txid).map(|tx| tx.as_ref().clone()),
+                ..Default::default()
+            }
+        };
+
+        self.params.utxos.push(WeightedUtxo {
+            satisfaction_weight: weight_hint,
+            utxo: Utxo::Foreign {
+                outpoint,
+                sequence: None,
+                psbt_input: Box::new(psbt_input),
+            },
+        });
+        self.params.witness_providers.insert(outpoint, provider);
+
+        Ok(self)
+    }
+
     /// Only spend utxos added by [`add_utxo`].
     ///
     /// The wallet will **not** add additional utxos to the transaction even if they are needed to
@@ -459,8 +655,21 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
     /// Use a specific nLockTime while creating the transaction
     ///
     /// This can cause conflicts if the wallet's descriptors contain an "after" (OP_CLTV) operator.
+    /// Same as [`locktime_policy`](Self::locktime_policy) with [`LocktimePolicy::Exact`].
     pub fn nlocktime(&mut self, locktime: absolute::LockTime) -> &mut Self {
-        self.params.locktime = Some(locktime);
+        self.params.locktime_policy = LocktimePolicy::Exact(locktime);
+        self
+    }
+
+    /// Choose how the nLockTime of the transaction is set, see [`LocktimePolicy`]
+    ///
+    /// The policy is merged with the timelock required by the descriptors of the spent outputs
+    /// (an "after" operator): the required timelock always wins over
+    /// [`LocktimePolicy::AntiFeeSniping`] and [`LocktimePolicy::None`], while an
+    /// [`LocktimePolicy::Exact`] locktime must satisfy it. The decision is reported by
+    /// [`TxEstimate::locktime`].
+    pub fn locktime_policy(&mut self, policy: LocktimePolicy) -> &mut Self {
+        self.params.locktime_policy = policy;
         self
     }
 
@@ -527,6 +736,14 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         self
     }
 
+    /// Set a global proprietary field (`PSBT_GLOBAL_PROPRIETARY`) of the PSBT
+    ///
+    /// Setting the same `key` more than once overwrites the previous value.
+    pub fn set_proprietary(&mut self, key: psbt::raw::ProprietaryKey, value: Vec<u8>) -> &mut Self {
+        self.params.proprietary.insert(key, value);
+        self
+    }
+
     /// Spend all the available inputs. This respects filters like [`TxBuilder::unspendable`] and the change policy.
     pub fn drain_wallet(&mut self) -> &mut Self {
         self.params.drain_wallet = true;
@@ -570,11 +787,11 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
     ///
     /// This will be used to:
     /// 1. Set the nLockTime for preventing fee sniping.
-    /// **Note**: This will be ignored if you manually specify a nlocktime using [`TxBuilder::nlocktime`].
-    /// 2. Decide whether coinbase outputs are mature or not. If the coinbase outputs are not
-    ///    mature at `current_height`, we ignore them in the coin selection.
-    ///    If you want to create a transaction that spends immature coinbase inputs, manually
-    ///    add them using [`TxBuilder::add_utxos`].
+    ///    **Note**: This is only used by [`LocktimePolicy::AntiFeeSniping`], the default.
+    /// 2. Decide whether coinbase outputs are mature or not, see [`LocalOutput::is_mature`]. The
+    ///    coin selection ignores the coinbase outputs which are not mature at `current_height`,
+    ///    and the transaction fails to build with [`CreateTxError::ImmatureCoinbase`] if one of
+    ///    them is added with [`TxBuilder::add_utxos`].
     ///
     /// In both cases, if you don't provide a current height, we use the last sync height.
     pub fn current_height(&mut self, height: u32) -> &mut Self {
@@ -591,6 +808,43 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         self
     }
 
+    /// Set the feerate used to compute the dust limits of the recipients and of the change output.
+    ///
+    /// Defaults to [`DEFAULT_DUST_RELAY_FEERATE`], the `-dustrelayfee` of Bitcoin Core. A change
+    /// output below the limit isn't created, its value is added to the fee instead. See
+    /// [`dust_value`] for how the limits are computed.
+    ///
+    /// [`DEFAULT_DUST_RELAY_FEERATE`]: crate::wallet::DEFAULT_DUST_RELAY_FEERATE
+    /// [`dust_value`]: crate::wallet::dust_value
+    pub fn dust_relay_feerate(&mut self, rate: FeeRate) -> &mut Self {
+        self.params.dust_relay_feerate = Some(rate);
+        self
+    }
+
+    /// Sweep tiny UTXOs into the transaction if the fee allows it.
+    ///
+    /// Once coin selection meets the target, up to `max_extra_inputs` of the remaining candidates
+    /// whose effective value at the feerate of the transaction, their value minus the fee for
+    /// spending them, is positive but small are also spent, as long as their inputs weigh at most
+    /// `max_extra_weight` once satisfied. They are drawn at random, the smaller ones being more
+    /// likely. Each of them pays for its own input and adds the rest of its value to the change
+    /// output, so the feerate is still met. At high feerates the small UTXOs cost more than their
+    /// value and none is added.
+    ///
+    /// Nothing is added to a transaction without a change output, or when only the manually
+    /// selected UTXOs can be spent.
+    pub fn opportunistic_consolidation(
+        &mut self,
+        max_extra_inputs: usize,
+        max_extra_weight: Weight,
+    ) -> &mut Self {
+        self.params.opportunistic_consolidation = Some(Consolidation {
+            max_extra_inputs,
+            max_extra_weight,
+        });
+        self
+    }
+
     /// Replace the recipients already added with a new list
     pub fn set_recipients(&mut self, recipients: Vec<(ScriptBuf, Amount)>) -> &mut Self {
         self.params.recipients = recipients
@@ -608,6 +862,156 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         self
     }
 
+    /// Add a batch of recipients, validating each of them
+    ///
+    /// Every recipient is checked against the wallet's network and, unless [`allow_dust`] is set,
+    /// against the dust limit of its script. If any of them is invalid nothing is added and this
+    /// fails with [`CreateTxError::InvalidRecipients`], listing the index in `recipients` and the
+    /// problem of each invalid recipient.
+    ///
+    /// With [`skip_invalid_recipients`] the valid recipients are added anyway; the invalid ones are
+    /// dropped and reported by [`skipped_recipients`].
+    ///
+    /// [`allow_dust`]: Self::allow_dust
+    /// [`skip_invalid_recipients`]: Self::skip_invalid_recipients
+    /// [`skipped_recipients`]: Self::skipped_recipients
+    pub fn add_recipients<I>(&mut self, recipients: I) -> Result<&mut Self, CreateTxError>
+    where
+        I: IntoIterator<Item = (Address<NetworkUnchecked>, Amount)>,
+    {
+        let network = self.wallet.borrow().network();
+        let mut valid = Vec::new();
+        let mut invalid = Vec::new();
+        for (index, (address, amount)) in recipients.into_iter().enumerate() {
+            let script_pubkey = match address.clone().require_network(network) {
+                Ok(address) => address.script_pubkey(),
+                Err(_) => {
+                    invalid.push((
+                        index,
+                        RecipientError::WrongNetwork {
+                            address,
+                            expected: network,
+                        },
+                    ));
+                    continue;
+                }
+            };
+            // OP_RETURN outputs have a dust limit of zero
+            let dust_limit = dust_value(&script_pubkey, self.params.dust_relay_feerate());
+            if !self.params.allow_dust && amount < dust_limit {
+                invalid.push((index, RecipientError::BelowDustLimit { amount, dust_limit }));
+                continue;
+            }
+            valid.push((script_pubkey, amount.to_sat()));
+        }
+
+        if !invalid.is_empty() && !self.params.skip_invalid_recipients {
+            return Err(CreateTxError::InvalidRecipients(invalid));
+        }
+        self.params.recipients.extend(valid);
+        self.params.skipped_recipients.extend(invalid);
+        Ok(self)
+    }
+
+    /// Set whether [`add_recipients`] drops the invalid recipients instead of failing.
+    ///
+    /// The dropped recipients are reported by [`skipped_recipients`].
+    ///
+    /// [`add_recipients`]: Self::add_recipients
+    /// [`skipped_recipients`]: Self::skipped_recipients
+    pub fn skip_invalid_recipients(&mut self, skip: bool) -> &mut Self {
+        self.params.skip_invalid_recipients = skip;
+        self
+    }
+
+    /// The recipients dropped by [`add_recipients`] with [`skip_invalid_recipients`] set
+    ///
+    /// Each entry is the index of the recipient in the batch it was passed with, and the reason
+    /// it was dropped.
+    ///
+    /// [`add_recipients`]: Self::add_recipients
+    /// [`skip_invalid_recipients`]: Self::skip_invalid_recipients
+    pub fn skipped_recipients(&self) -> &[(usize, RecipientError)] {
+        &self.params.skipped_recipients
+    }
+
+    /// Set whether the recipients paying the same script are merged into a single output.
+    ///
+    /// The merged output pays the sum of the amounts and takes the place of the first of them.
+    /// `OP_RETURN` outputs are never merged.
+    pub fn merge_duplicate_recipients(&mut self, merge: bool) -> &mut Self {
+        self.params.merge_duplicate_recipients = merge;
+        self
+    }
+
+    /// Fail the build with [`CreateTxError::PossibleDuplicatePayment`] if a recipient was already
+    /// paid the same amount within `window`, see [`Wallet::find_payments_to`].
+    ///
+    /// This guards against paying twice when the wallet crashes between building a payment and
+    /// recording it: apply the transaction to the wallet with [`Wallet::insert_tx`] before
+    /// broadcasting it, and its rebuild fails. The transactions spending the UTXOs added with
+    /// [`add_utxos`](Self::add_utxos), such as the transaction replaced by a fee bump, are not
+    /// considered duplicates.
+    ///
+    /// [`Wallet::find_payments_to`]: crate::wallet::Wallet::find_payments_to
+    /// [`Wallet::insert_tx`]: crate::wallet::Wallet::insert_tx
+    pub fn deduplicate_recipients(&mut self, window: super::TimeOrHeightWindow) -> &mut Self {
+        self.params.deduplicate_recipients = Some(window);
+        self
+    }
+
+    /// Add a recipient paying the silent payment `address`
+    ///
+    /// The script of the output is derived per [`BIP352`] from the private keys of the selected
+    /// inputs when the transaction is finished. This fails with [`SilentPaymentError::WatchOnly`]
+    /// for a watch-only wallet, and with [`SilentPaymentError::MissingSecretKey`] if the key of
+    /// an eligible input, such as a foreign UTXO, isn't known to the wallet. The outputs paying
+    /// the same address get different scripts.
+    ///
+    /// Fails if the address is not valid for the wallet's network.
+    ///
+    /// [`BIP352`]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+    /// [`SilentPaymentError::WatchOnly`]: super::silent_payments::SilentPaymentError::WatchOnly
+    /// [`SilentPaymentError::MissingSecretKey`]: super::silent_payments::SilentPaymentError::MissingSecretKey
+    #[cfg(feature = "silent-payments")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "silent-payments")))]
+    pub fn add_silent_payment_recipient(
+        &mut self,
+        address: super::silent_payments::SilentPaymentAddress,
+        amount: Amount,
+    ) -> Result<&mut Self, super::silent_payments::SilentPaymentError> {
+        let network = self.wallet.borrow().network();
+        if !address.is_valid_for_network(network) {
+            return Err(super::silent_payments::SilentPaymentError::WrongNetwork {
+                expected: network,
+            });
+        }
+        self.params
+            .silent_payment_recipients
+            .push((address, amount.to_sat()));
+        Ok(self)
+    }
+
+    /// Add a recipient paying the address and amount of a BIP-21 `uri`
+    ///
+    /// Fails if the address is not valid for the wallet's network, or if the URI has no amount.
+    #[cfg(feature = "bip21")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bip21")))]
+    pub fn add_recipient_from_uri(
+        &mut self,
+        uri: &super::bip21::Bip21Uri,
+    ) -> Result<&mut Self, AddUriRecipientError> {
+        let network = self.wallet.borrow().network();
+        let address = uri.address.clone().require_network(network).map_err(|_| {
+            AddUriRecipientError::WrongNetwork {
+                address: uri.address.clone(),
+                expected: network,
+            }
+        })?;
+        let amount = uri.amount.ok_or(AddUriRecipientError::MissingAmount)?;
+        Ok(self.add_recipient(address.script_pubkey(), amount))
+    }
+
     /// Add data as an output, using OP_RETURN
     pub fn add_data<T: AsRef<PushBytes>>(&mut self, data: &T) -> &mut Self {
         let script = ScriptBuf::new_op_return(data);
@@ -664,6 +1068,153 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         self.params.drain_to = Some(script_pubkey);
         self
     }
+
+    /// Send the change to `script_pubkey` instead of a new address of the internal keychain.
+    ///
+    /// Unlike [`drain_to`], this only changes where the change computed by coin selection goes:
+    /// it doesn't allow creating a transaction without recipients. If [`drain_to`] is also set it
+    /// takes precedence.
+    ///
+    /// [`drain_to`]: Self::drain_to
+    pub fn drain_to_change(&mut self, script_pubkey: ScriptBuf) -> &mut Self {
+        self.params.change_script = Some(script_pubkey);
+        self
+    }
+
+    /// Pick the change address with `policy` instead of the policy of the wallet, see
+    /// [`Wallet::set_change_address_policy`].
+    ///
+    /// The position of the cycle of [`ChangeAddressPolicy::RotateWithin`] is the one of the
+    /// wallet. This has no effect if [`drain_to`] or [`drain_to_change`] is set.
+    ///
+    /// [`drain_to`]: Self::drain_to
+    /// [`drain_to_change`]: Self::drain_to_change
+    pub fn change_address_policy(
+        &mut self,
+        policy: ChangeAddressPolicy,
+    ) -> Result<&mut Self, ChangeAddressPolicyError> {
+        self.wallet.borrow().check_change_address_policy(policy)?;
+        self.params.change_address_policy = Some(policy);
+        Ok(self)
+    }
+
+    /// Place the change output at index `position` of the transaction outputs.
+    ///
+    /// The other outputs are still ordered around it according to [`ordering`]. Pinning the
+    /// change is not compatible with [`TxOrdering::Bip69Lexicographic`], in that case [`finish`]
+    /// returns [`CreateTxError::InvalidParams`] with [`ParamError::ChangePositionBip69`]. It
+    /// returns
+    /// [`CreateTxError::ChangePositionOutOfRange`] if `position` is past the last output.
+    ///
+    /// This has no effect if the transaction ends up without a change output.
+    ///
+    /// [`ordering`]: Self::ordering
+    /// [`finish`]: Self::finish
+    pub fn change_position(&mut self, position: usize) -> &mut Self {
+        self.params.change_position = Some(position);
+        self
+    }
+
+    /// Derive all the randomness of the transaction from `seed`.
+    ///
+    /// The coin selection, including the random draw BnB falls back to, and the
+    /// [`TxOrdering::Shuffle`] ordering of the inputs and outputs then use a [`StdRng`] seeded with
+    /// `seed` instead of the thread RNG. Note that the change output is ordered along the other
+    /// outputs, unless it's pinned with [`change_position`].
+    ///
+    /// Two parties building a transaction with the same parameters and seed get the same unsigned
+    /// transaction as long as their wallets agree on:
+    ///
+    /// - the descriptors, which determine the scripts and satisfaction weights of the inputs,
+    /// - the unspent outputs available to the coin selection, with their confirmation status,
+    /// - the chain tip, which sets the default locktime and the coinbase maturity,
+    /// - the next unused index of the internal keychain, which gives the change address, unless
+    ///   the change goes to [`drain_to`] or [`drain_to_change`].
+    ///
+    /// This affects [`finish`], [`finish_with_change`] and [`estimate`] along with the methods
+    /// built on them, but not [`finish_with_aux_rand`] and [`estimate_with_aux_rand`] which use
+    /// the RNG they are given.
+    ///
+    /// [`StdRng`]: rand::rngs::StdRng
+    /// [`change_position`]: Self::change_position
+    /// [`drain_to`]: Self::drain_to
+    /// [`drain_to_change`]: Self::drain_to_change
+    /// [`finish`]: TxBuilder::finish
+    /// [`finish_with_change`]: TxBuilder::finish_with_change
+    /// [`estimate`]: TxBuilder::estimate
+    /// [`finish_with_aux_rand`]: TxBuilder::finish_with_aux_rand
+    /// [`estimate_with_aux_rand`]: TxBuilder::estimate_with_aux_rand
+    pub fn deterministic(&mut self, seed: [u8; 32]) -> &mut Self {
+        self.params.deterministic_seed = Some(seed);
+        self
+    }
+
+    /// Reserve the inputs of the transaction when it's built with [`finish`], so that the next
+    /// transactions don't select them until the reservation is released.
+    ///
+    /// The coin selection of every transaction skips the reserved outpoints. If one of the
+    /// outpoints added with [`add_utxo`] is reserved, [`finish`] fails with
+    /// [`CreateTxError::InputsReserved`]: it can be retried with other inputs. The reservation is
+    /// released by [`Wallet::cancel_tx`], by a failed broadcast recorded with
+    /// [`Wallet::record_broadcast`], once the transaction is applied to the wallet, or after the
+    /// duration set with [`Wallet::set_reservation_ttl`].
+    ///
+    /// The coins are selected and reserved while the builder borrows the wallet mutably, so
+    /// threads sharing a wallet behind a lock, such as an `Arc<Mutex<Wallet>>`, never select the
+    /// same inputs for their reserving transactions. The reservations aren't persisted.
+    ///
+    /// [`finish`]: TxBuilder::finish
+    /// [`add_utxo`]: Self::add_utxo
+    pub fn reserve_inputs(&mut self, reserve: bool) -> &mut Self {
+        self.params.reserve_inputs = reserve;
+        self
+    }
+
+    /// Cap the fee of this transaction with `limits`, instead of the ones of
+    /// [`Wallet::set_fee_limits`].
+    ///
+    /// Without [`acknowledge_fee_limits`], `limits` can only tighten the caps of the wallet: the
+    /// tightest of both apply. With it, `limits` replace them, an unlimited [`FeeLimits`] lifts
+    /// all the caps.
+    ///
+    /// [`acknowledge_fee_limits`]: Self::acknowledge_fee_limits
+    /// [`FeeLimits`]: super::FeeLimits
+    pub fn fee_limits(&mut self, limits: super::FeeLimits) -> &mut Self {
+        self.params.fee_limits = Some(limits);
+        self
+    }
+
+    /// Acknowledge that the limits of [`fee_limits`] may be looser than the ones of the wallet,
+    /// and replace them.
+    ///
+    /// [`fee_limits`]: Self::fee_limits
+    pub fn acknowledge_fee_limits(&mut self) -> &mut Self {
+        self.params.acknowledge_fee_limits = true;
+        self
+    }
+
+    /// Check that the parameters are consistent, before any coin is selected.
+    ///
+    /// All the problems found are reported at once, each as a [`ParamError`] naming the
+    /// offending parameters. This is called by [`finish`] and [`estimate`], and the methods
+    /// built on them, which then fail with [`CreateTxError::InvalidParams`].
+    ///
+    /// Recipients on the wrong network are rejected when they are added with [`add_recipients`],
+    /// so they are not reported here. Problems that depend on the descriptors or on the coins of
+    /// the wallet, such as a version `1` with a descriptor using OP_CSV, are only found by
+    /// [`finish`].
+    ///
+    /// [`finish`]: TxBuilder::finish
+    /// [`estimate`]: TxBuilder::estimate
+    /// [`add_recipients`]: Self::add_recipients
+    pub fn validate(&self) -> Result<(), Vec<ParamError>> {
+        let errors = self.params.param_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
 impl<'a, Cs: CoinSelectionAlgorithm> TxBuilder<'a, Cs> {
@@ -676,9 +1227,129 @@ impl<'a, Cs: CoinSelectionAlgorithm> TxBuilder<'a, Cs> {
     /// **WARNING**: To avoid change address reuse you must persist the changes resulting from one
     /// or more calls to this method before closing the wallet. See [`Wallet::reveal_next_address`].
     pub fn finish(self) -> Result<Psbt, CreateTxError> {
+        self.finish_with_change().map(|(psbt, _)| psbt)
+    }
+
+    /// Finish building the transaction as a PSBT v2.
+    ///
+    /// Same as [`finish`](Self::finish), but returns a [`PsbtV2`] per [`BIP370`], whose fallback
+    /// locktime is the locktime of the transaction. Sign it with [`Wallet::sign_psbt_v2`].
+    ///
+    /// [`PsbtV2`]: crate::psbt::v2::PsbtV2
+    /// [`BIP370`]: https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki
+    #[cfg(feature = "psbt-v2")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "psbt-v2")))]
+    pub fn finish_psbt_v2(self) -> Result<crate::psbt::v2::PsbtV2, CreateTxError> {
+        let psbt = self.finish()?;
+        Ok(crate::psbt::v2::PsbtV2::from_v0(psbt)
+            .expect("a PSBT v0 created by the wallet must convert to v2"))
+    }
+
+    /// Finish building the original transaction of a payjoin, also returning its change output.
+    ///
+    /// Same as [`finish_with_change`](Self::finish_with_change), but fails unless the transaction
+    /// follows the rules of [`BIP78`] for the original PSBT: all the inputs have the same script
+    /// type and are signed with `SIGHASH_ALL`. Sign and finalize the PSBT with [`Wallet::sign`]
+    /// before starting a [`PayjoinSession`] with it.
+    ///
+    /// [`BIP78`]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+    /// [`PayjoinSession`]: crate::wallet::payjoin::PayjoinSession
+    #[cfg(feature = "payjoin")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "payjoin")))]
+    pub fn finish_payjoin_original(self) -> Result<(Psbt, Option<ChangeOutput>), CreateTxError> {
+        use super::payjoin::{self, PayjoinError};
+
+        if let Some(sighash) = self.params.sighash {
+            if !payjoin::is_sighash_all(sighash) {
+                return Err(PayjoinError::NonStandardSighash(0).into());
+            }
+        }
+        let (psbt, change) = self.finish_with_change()?;
+        payjoin::check_original_inputs(&psbt)?;
+        Ok((psbt, change))
+    }
+
+    /// Finish building the transaction, also returning its change output.
+    ///
+    /// Same as [`finish`](Self::finish) but also returns the final index and value of the change
+    /// (or drain) output, or `None` if the transaction has no change.
+    pub fn finish_with_change(self) -> Result<(Psbt, Option<ChangeOutput>), CreateTxError> {
+        self.finish_with_fee_target()
+            .map(|(psbt, fee_target)| (psbt, fee_target.change))
+    }
+
+    /// Finish building the transaction, also returning the fee it targets.
+    ///
+    /// Same as [`finish_with_change`](Self::finish_with_change), but also returns the weight the
+    /// fee was computed with and the target fee rate. The fee is computed from an upper bound of
+    /// the weight of the satisfied inputs, once signed the transaction may be lighter (or, with
+    /// some signers, heavier) and its fee rate differ from the target: check it with
+    /// [`Wallet::check_feerate`], or let [`Wallet::sign`] adjust the change with
+    /// [`FeeTarget::adjustment`].
+    ///
+    /// [`Wallet::check_feerate`]: super::Wallet::check_feerate
+    /// [`Wallet::sign`]: super::Wallet::sign
+    pub fn finish_with_fee_target(self) -> Result<(Psbt, FeeTarget), CreateTxError> {
+        match self.params.deterministic_seed {
+            Some(seed) => self.finish_with_fee_target_and_aux_rand(&mut StdRng::from_seed(seed)),
+            None => self.finish_with_fee_target_and_aux_rand(&mut rand::thread_rng()),
+        }
+    }
+
+    /// Finish building the transaction, using `rng` as the source of randomness of the coin
+    /// selection and of the [`TxOrdering::Shuffle`] ordering.
+    ///
+    /// Same as [`finish_with_change`](Self::finish_with_change) otherwise. Given the same wallet
+    /// state and an identically seeded `rng`, the transaction matches the one reported by
+    /// [`estimate_with_aux_rand`](Self::estimate_with_aux_rand).
+    pub fn finish_with_aux_rand(
+        self,
+        rng: &mut impl RngCore,
+    ) -> Result<(Psbt, Option<ChangeOutput>), CreateTxError> {
+        self.finish_with_fee_target_and_aux_rand(rng)
+            .map(|(psbt, fee_target)| (psbt, fee_target.change))
+    }
+
+    /// Same as [`finish_with_fee_target`](Self::finish_with_fee_target), using `rng` as the
+    /// source of randomness.
+    pub fn finish_with_fee_target_and_aux_rand(
+        self,
+        rng: &mut impl RngCore,
+    ) -> Result<(Psbt, FeeTarget), CreateTxError> {
+        self.validate().map_err(CreateTxError::InvalidParams)?;
         self.wallet
             .borrow_mut()
-            .create_tx(self.coin_selection, self.params)
+            .create_tx(self.coin_selection, self.params, rng)
+    }
+
+    /// Run the coin selection and report the fee, inputs and change of the transaction, without
+    /// building it.
+    ///
+    /// Nothing is changed in the wallet: not even the change address is revealed. This fails with
+    /// the same errors as [`finish`](Self::finish).
+    ///
+    /// The coin selection may be random, use [`estimate_with_aux_rand`] and
+    /// [`finish_with_aux_rand`] with identically seeded sources of randomness to get exactly the
+    /// estimated transaction.
+    ///
+    /// [`estimate_with_aux_rand`]: Self::estimate_with_aux_rand
+    /// [`finish_with_aux_rand`]: Self::finish_with_aux_rand
+    pub fn estimate(&self) -> Result<TxEstimate, CreateTxError> {
+        match self.params.deterministic_seed {
+            Some(seed) => self.estimate_with_aux_rand(&mut StdRng::from_seed(seed)),
+            None => self.estimate_with_aux_rand(&mut rand::thread_rng()),
+        }
+    }
+
+    /// Same as [`estimate`](Self::estimate), using `rng` as the source of randomness.
+    pub fn estimate_with_aux_rand(
+        &self,
+        rng: &mut impl RngCore,
+    ) -> Result<TxEstimate, CreateTxError> {
+        self.validate().map_err(CreateTxError::InvalidParams)?;
+        self.wallet
+            .borrow_mut()
+            .estimate_tx(&self.coin_selection, &self.params, rng)
     }
 }
 
@@ -744,6 +1415,255 @@ impl fmt::Display for AddForeignUtxoError {
 #[cfg(feature = "std")]
 impl std::error::Error for AddForeignUtxoError {}
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A recipient rejected by [`TxBuilder::add_recipients`]
+pub enum RecipientError {
+    /// The address is not valid for the wallet's network
+    WrongNetwork {
+        /// The address of the recipient
+        address: Address<NetworkUnchecked>,
+        /// The wallet's network
+        expected: bitcoin::Network,
+    },
+    /// The amount is below the dust limit of the address
+    BelowDustLimit {
+        /// The amount of the recipient
+        amount: Amount,
+        /// The smallest amount that isn't dust for the address
+        dust_limit: Amount,
+    },
+}
+
+impl fmt::Display for RecipientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongNetwork { address, expected } => write!(
+                f,
+                "Address {:?} is not valid for network {}",
+                address, expected
+            ),
+            Self::BelowDustLimit { amount, dust_limit } => write!(
+                f,
+                "Amount {} is below the dust limit of {}",
+                amount.display_dynamic(),
+                dust_limit.display_dynamic()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RecipientError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An inconsistency between the parameters of a [`TxBuilder`], see [`TxBuilder::validate`]
+pub enum ParamError {
+    /// Both [`TxBuilder::fee_rate`] and [`TxBuilder::fee_absolute`] were set
+    ConflictingFees {
+        /// The fee rate that was set
+        fee_rate: FeeRate,
+        /// The absolute fee that was set
+        fee_absolute: Amount,
+    },
+    /// [`TxBuilder::version`] is `0`
+    Version0,
+    /// The sequence of [`TxBuilder::enable_rbf_with_sequence`] is `>= 0xFFFFFFFE` and doesn't
+    /// signal RBF
+    RbfSequence(Sequence),
+    /// [`TxBuilder::manually_selected_only`] is set but no UTXO was added with
+    /// [`TxBuilder::add_utxos`]
+    NoUtxosSelected,
+    /// There is no recipient, and no [`TxBuilder::drain_to`] with [`TxBuilder::drain_wallet`] or
+    /// UTXOs added with [`TxBuilder::add_utxos`] to drain
+    NoRecipients,
+    /// [`TxBuilder::change_position`] is set with [`TxOrdering::Bip69Lexicographic`]
+    ChangePositionBip69 {
+        /// The requested position of the change output
+        position: usize,
+    },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConflictingFees {
+                fee_rate,
+                fee_absolute,
+            } => write!(
+                f,
+                "Both a fee rate of {} sat/kwu and an absolute fee of {} are set",
+                fee_rate.to_sat_per_kwu(),
+                fee_absolute.display_dynamic()
+            ),
+            Self::Version0 => write!(f, "Invalid version `0`"),
+            Self::RbfSequence(sequence) => write!(
+                f,
+                "The nSequence {:#x} doesn't signal RBF",
+                sequence.to_consensus_u32()
+            ),
+            Self::NoUtxosSelected => write!(
+                f,
+                "Only the manually selected UTXOs can be spent, but none was selected"
+            ),
+            Self::NoRecipients => write!(f, "No recipient and nothing to drain"),
+            Self::ChangePositionBip69 { position } => write!(
+                f,
+                "Cannot pin the change at position {} with BIP69 ordering",
+                position
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParamError {}
+
+#[cfg(feature = "bip21")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bip21")))]
+#[derive(Debug)]
+/// Error returned from [`TxBuilder::add_recipient_from_uri`]
+pub enum AddUriRecipientError {
+    /// The address of the URI is not valid for the wallet's network
+    WrongNetwork {
+        /// The address of the URI
+        address: bitcoin::Address<bitcoin::address::NetworkUnchecked>,
+        /// The wallet's network
+        expected: bitcoin::Network,
+    },
+    /// The URI doesn't have an amount
+    MissingAmount,
+}
+
+#[cfg(feature = "bip21")]
+impl fmt::Display for AddUriRecipientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongNetwork { address, expected } => write!(
+                f,
+                "Address {:?} is not valid for network {}",
+                address, expected
+            ),
+            Self::MissingAmount => write!(f, "The URI doesn't have an amount"),
+        }
+    }
+}
+
+#[cfg(all(feature = "bip21", feature = "std"))]
+impl std::error::Error for AddUriRecipientError {}
+
+/// The change output of a transaction, see [`TxBuilder::finish_with_change`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeOutput {
+    /// Index of the change output in the transaction outputs
+    pub index: usize,
+    /// Value of the change output
+    pub value: Amount,
+    /// Derivation index of the change output, in the internal keychain (or the external one
+    /// for wallets without internal descriptor), `None` if the change goes to the script set
+    /// with [`TxBuilder::drain_to`] or [`TxBuilder::drain_to_change`]
+    pub derivation_index: Option<u32>,
+}
+
+/// The fee targeted by a transaction, see [`TxBuilder::finish_with_fee_target`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeTarget {
+    /// The absolute fee
+    pub fee: Amount,
+    /// The target fee rate: the one set with [`TxBuilder::fee_rate`], or the absolute fee over
+    /// the estimated weight
+    pub fee_rate: FeeRate,
+    /// The weight the fee was computed with, an upper bound of the weight of the transaction once
+    /// its inputs are satisfied
+    pub estimated_weight: Weight,
+    /// The change (or drain) output, if any
+    pub change: Option<ChangeOutput>,
+}
+
+impl FeeTarget {
+    /// The [`FeeAdjustment`] reaching the target fee rate by reducing the change by at most
+    /// `max_reduction`, `None` if the transaction has no change
+    ///
+    /// [`FeeAdjustment`]: super::signer::FeeAdjustment
+    pub fn adjustment(&self, max_reduction: Amount) -> Option<super::signer::FeeAdjustment> {
+        self.change.map(|change| super::signer::FeeAdjustment {
+            target: self.fee_rate,
+            change_index: change.index,
+            max_reduction,
+        })
+    }
+}
+
+/// The transaction [`TxBuilder::estimate`] would build
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxEstimate {
+    /// The absolute fee
+    pub fee: Amount,
+    /// The fee rate, computed from the estimated weight
+    pub fee_rate: FeeRate,
+    /// An upper bound of the weight of the transaction once its inputs are satisfied
+    ///
+    /// Like the coin selection of [`TxBuilder::finish`], this uses the maximum satisfaction weight
+    /// of each input's descriptor rather than a spending plan, so that the estimate matches the
+    /// built transaction. The planning module in `nursery/tmp_plan` is an unpublished stopgap
+    /// that the wallet can't depend on.
+    pub weight: Weight,
+    /// The total value of the selected inputs
+    pub input_value: Amount,
+    /// The change (or drain) output, if any
+    pub change: Option<ChangeOutput>,
+    /// The selected UTXOs, in the order of the transaction inputs
+    pub selected: Vec<OutPoint>,
+    /// How the nLockTime of the transaction was set
+    pub locktime: LocktimeDecision,
+}
+
+/// How the nLockTime of a transaction is set, see [`TxBuilder::locktime_policy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LocktimePolicy {
+    /// The current height, see [`TxBuilder::current_height`], so that the transaction can't be
+    /// mined in a reorg of the tip (default)
+    ///
+    /// With `randomize`, one transaction out of ten gets a locktime up to 99 blocks lower, like
+    /// Bitcoin Core does, so that the transactions delayed before their broadcast don't stand
+    /// out.
+    AntiFeeSniping {
+        /// Whether the locktime is sometimes moved back a few blocks
+        randomize: bool,
+    },
+    /// A locktime of `0`, unless the descriptors require one
+    None,
+    /// This locktime, which must satisfy the timelock required by the descriptors if any
+    Exact(absolute::LockTime),
+}
+
+impl Default for LocktimePolicy {
+    fn default() -> Self {
+        Self::AntiFeeSniping { randomize: false }
+    }
+}
+
+/// Where the nLockTime of a transaction comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LocktimeSource {
+    /// The [`LocktimePolicy`], which satisfies the timelock required by the descriptors if any
+    Policy,
+    /// The timelock required by the descriptors, which overrides the [`LocktimePolicy`]
+    Descriptor,
+}
+
+/// The nLockTime of a transaction and how it was decided, see [`TxEstimate::locktime`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LocktimeDecision {
+    /// The nLockTime of the transaction
+    pub locktime: absolute::LockTime,
+    /// The policy requested with [`TxBuilder::locktime_policy`]
+    pub policy: LocktimePolicy,
+    /// The timelock required by the descriptors of the spent outputs, if any
+    pub required: Option<absolute::LockTime>,
+    /// Whether the locktime comes from the policy or from the descriptors
+    pub source: LocktimeSource,
+}
+
 /// Ordering of the transaction's inputs and outputs
 #[derive(Default, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Copy)]
 pub enum TxOrdering {
@@ -759,13 +1679,17 @@ pub enum TxOrdering {
 impl TxOrdering {
     /// Sort transaction inputs and outputs by [`TxOrdering`] variant
     pub fn sort_tx(&self, tx: &mut Transaction) {
+        self.sort_tx_with_aux_rand(tx, &mut rand::thread_rng())
+    }
+
+    /// Same as [`sort_tx`](Self::sort_tx), using `rng` to shuffle the transaction
+    pub fn sort_tx_with_aux_rand(&self, tx: &mut Transaction, rng: &mut impl RngCore) {
         match self {
             TxOrdering::Untouched => {}
             TxOrdering::Shuffle => {
                 use rand::seq::SliceRandom;
-                let mut rng = rand::thread_rng();
-                tx.input.shuffle(&mut rng);
-                tx.output.shuffle(&mut rng);
+                tx.input.shuffle(rng);
+                tx.output.shuffle(rng);
             }
             TxOrdering::Bip69Lexicographic => {
                 tx.input.sort_unstable_by_key(|txin| {
@@ -825,7 +1749,7 @@ impl ChangeSpendPolicy {
         match self {
             ChangeSpendPolicy::ChangeAllowed => true,
             ChangeSpendPolicy::OnlyChange => utxo.keychain == KeychainKind::Internal,
-            ChangeSpendPolicy::ChangeForbidden => utxo.keychain == KeychainKind::External,
+            ChangeSpendPolicy::ChangeForbidden => utxo.keychain != KeychainKind::Internal,
         }
     }
 }
@@ -939,6 +1863,7 @@ mod test {
                 txout: TxOut::NULL,
                 keychain: KeychainKind::External,
                 is_spent: false,
+                is_coinbase: false,
                 confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
                 derivation_index: 0,
             },
@@ -950,6 +1875,7 @@ mod test {
                 txout: TxOut::NULL,
                 keychain: KeychainKind::Internal,
                 is_spent: false,
+                is_coinbase: false,
                 confirmation_time: ConfirmationTime::Confirmed {
                     height: 32,
                     time: 42,
@@ -10,9 +10,10 @@
 // licenses.
 
 use bitcoin::secp256k1::{All, Secp256k1};
-use bitcoin::{absolute, relative, Script, Sequence};
+use bitcoin::{absolute, relative, Amount, FeeRate, Script, Sequence};
 
 use miniscript::{MiniscriptKey, Satisfier, ToPublicKey};
+use serde::{Deserialize, Serialize};
 
 /// Trait to check if a value is below the dust limit.
 /// We are performing dust value calculation for a given script public key using rust-bitcoin to
@@ -26,10 +27,64 @@ pub trait IsDust {
 
 impl IsDust for u64 {
     fn is_dust(&self, script: &Script) -> bool {
-        *self < script.minimal_non_dust().to_sat()
+        *self < dust_value(script, DEFAULT_DUST_RELAY_FEERATE).to_sat()
     }
 }
 
+/// The type of a script pubkey
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptType {
+    /// Pay to public key hash
+    P2pkh,
+    /// Pay to script hash, including the wrapped segwit scripts
+    P2sh,
+    /// Pay to witness public key hash
+    P2wpkh,
+    /// Pay to witness script hash
+    P2wsh,
+    /// Pay to taproot
+    P2tr,
+    /// Any other script
+    Other,
+}
+
+impl ScriptType {
+    /// The type of `script`
+    pub fn of(script: &Script) -> Self {
+        if script.is_p2pkh() {
+            Self::P2pkh
+        } else if script.is_p2sh() {
+            Self::P2sh
+        } else if script.is_p2wpkh() {
+            Self::P2wpkh
+        } else if script.is_p2wsh() {
+            Self::P2wsh
+        } else if script.is_p2tr() {
+            Self::P2tr
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// The dust relay feerate of Bitcoin Core, used by default to compute the dust limits
+pub const DEFAULT_DUST_RELAY_FEERATE: FeeRate = FeeRate::from_sat_per_vb_u32(3);
+
+/// The smallest value an output with `script` can have without being dust, computed like Bitcoin
+/// Core's `GetDustThreshold`
+///
+/// An output is dust when spending it would cost more than its value at `dust_relay_rate`, so the
+/// threshold depends on the size of the script and on whether it's a witness program: at the
+/// default rate it's 546 sats for p2pkh, 294 for p2wpkh and 330 for p2wsh and p2tr outputs.
+/// `OP_RETURN` outputs are never dust.
+pub fn dust_value(script: &Script, dust_relay_rate: FeeRate) -> Amount {
+    if script.is_op_return() {
+        return Amount::ZERO;
+    }
+    script.minimal_non_dust_custom(dust_relay_rate)
+}
+
 pub struct After {
     pub current_height: Option<u32>,
     pub assume_height_reached: bool,
@@ -118,8 +173,8 @@ mod test {
     // otherwise it's time-based
     pub(crate) const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
 
-    use super::{check_nsequence_rbf, IsDust};
-    use crate::bitcoin::{Address, Network, Sequence};
+    use super::{check_nsequence_rbf, dust_value, IsDust, DEFAULT_DUST_RELAY_FEERATE};
+    use crate::bitcoin::{Address, FeeRate, Network, ScriptBuf, Sequence};
     use core::str::FromStr;
 
     #[test]
@@ -143,6 +198,49 @@ mod test {
         assert!(!294.is_dust(&script_p2wpkh));
     }
 
+    #[test]
+    fn test_dust_value() {
+        let script = |address: &str| {
+            Address::from_str(address)
+                .unwrap()
+                .require_network(Network::Bitcoin)
+                .unwrap()
+                .script_pubkey()
+        };
+        let p2pkh = script("1GNgwA8JfG7Kc8akJ8opdNWJUihqUztfPe");
+        let p2wpkh = script("bc1qxlh2mnc0yqwas76gqq665qkggee5m98t8yskd8");
+        let p2wsh = script("bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3");
+        let p2tr = script("bc1p5d7rjq7g6rdk2yhzks9smlaqtedr4dekq08ge8ztwac72sfr9rusxg3297");
+        assert!(p2wsh.is_p2wsh());
+        assert!(p2tr.is_p2tr());
+        let op_return = ScriptBuf::new_op_return([0xaa; 4]);
+
+        let dust = |script: &ScriptBuf, rate| dust_value(script, rate).to_sat();
+        let rate = DEFAULT_DUST_RELAY_FEERATE;
+        assert_eq!(dust(&p2pkh, rate), 546);
+        assert_eq!(dust(&p2wpkh, rate), 294);
+        assert_eq!(dust(&p2wsh, rate), 330);
+        assert_eq!(dust(&p2tr, rate), 330);
+        assert_eq!(dust(&op_return, rate), 0);
+
+        let rate = FeeRate::from_sat_per_vb_u32(1);
+        assert_eq!(dust(&p2pkh, rate), 182);
+        assert_eq!(dust(&p2wpkh, rate), 98);
+        assert_eq!(dust(&p2wsh, rate), 110);
+        assert_eq!(dust(&p2tr, rate), 110);
+        assert_eq!(dust(&op_return, rate), 0);
+
+        // the default matches the one of rust-bitcoin
+        for script in [&p2pkh, &p2wpkh, &p2wsh, &p2tr] {
+            assert_eq!(
+                dust_value(script, DEFAULT_DUST_RELAY_FEERATE),
+                script.minimal_non_dust()
+            );
+            assert!(!dust(script, DEFAULT_DUST_RELAY_FEERATE).is_dust(script));
+            assert!((dust(script, DEFAULT_DUST_RELAY_FEERATE) - 1).is_dust(script));
+        }
+    }
+
     #[test]
     fn test_check_nsequence_rbf_msb_set() {
         let result = check_nsequence_rbf(Sequence(0x80000000), Sequence(5000));
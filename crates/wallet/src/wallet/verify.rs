@@ -0,0 +1,331 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Sanity checks of transactions before they are broadcast
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bdk_chain::collections::{BTreeMap, BTreeSet};
+use bitcoin::{Amount, FeeRate, OutPoint, Transaction, TxOut, Txid, Weight};
+use miniscript::interpreter;
+
+use super::utils::IsDust;
+use super::Wallet;
+
+/// Options of [`Wallet::verify_tx`]
+///
+/// The fee bounds are not checked unless they are set.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// The previous outputs of the inputs that don't spend a wallet UTXO
+    pub foreign_prevouts: BTreeMap<OutPoint, TxOut>,
+    /// The highest acceptable absolute fee
+    pub max_fee: Option<Amount>,
+    /// The highest acceptable fee rate
+    pub max_fee_rate: Option<FeeRate>,
+    /// The lowest acceptable fee rate
+    pub min_fee_rate: Option<FeeRate>,
+}
+
+/// Summary of a transaction that passed [`Wallet::verify_tx`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxReport {
+    /// The absolute fee paid
+    pub fee: Amount,
+    /// The fee rate, computed from the weight of the transaction as it is
+    pub fee_rate: FeeRate,
+    /// The weight of the transaction
+    pub weight: Weight,
+}
+
+/// Reason why a transaction failed [`Wallet::verify_tx`]
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The input doesn't spend a UTXO of the wallet, nor one of the
+    /// [`VerifyOptions::foreign_prevouts`]
+    UnknownInput {
+        /// The index of the input
+        index: usize,
+        /// The output it spends
+        outpoint: OutPoint,
+    },
+    /// The input spends a wallet output which is already spent by another transaction
+    SpentInput {
+        /// The index of the input
+        index: usize,
+        /// The output it spends
+        outpoint: OutPoint,
+        /// The transaction spending it
+        spent_by: Txid,
+    },
+    /// The same output is spent by more than one input
+    DuplicateInput(OutPoint),
+    /// The input has neither a `script_sig` nor a witness
+    NotFinalized(usize),
+    /// The output is below the dust limit and is not an `OP_RETURN`
+    DustOutput {
+        /// The index of the output
+        index: usize,
+        /// Its value
+        value: Amount,
+    },
+    /// The outputs spend more than the inputs
+    OutputsExceedInputs {
+        /// The total value of the inputs
+        inputs: Amount,
+        /// The total value of the outputs
+        outputs: Amount,
+    },
+    /// The fee is higher than [`VerifyOptions::max_fee`]
+    FeeTooHigh {
+        /// The fee of the transaction
+        fee: Amount,
+        /// The highest acceptable fee
+        max: Amount,
+    },
+    /// The fee rate is higher than [`VerifyOptions::max_fee_rate`]
+    FeeRateTooHigh {
+        /// The fee rate of the transaction
+        fee_rate: FeeRate,
+        /// The highest acceptable fee rate
+        max: FeeRate,
+    },
+    /// The fee rate is lower than [`VerifyOptions::min_fee_rate`]
+    FeeRateTooLow {
+        /// The fee rate of the transaction
+        fee_rate: FeeRate,
+        /// The lowest acceptable fee rate
+        min: FeeRate,
+    },
+    /// The satisfaction of a wallet input doesn't meet its descriptor, for example because the
+    /// locktime or sequence of the transaction doesn't satisfy one of its timelocks
+    UnsatisfiedInput {
+        /// The index of the input
+        index: usize,
+        /// The error of the miniscript interpreter
+        error: interpreter::Error,
+    },
+    /// The script of the input fails to execute against its previous output
+    #[cfg(feature = "verify")]
+    Script {
+        /// The index of the input
+        index: usize,
+        /// The error of libbitcoinconsensus
+        error: bitcoin::consensus::validation::BitcoinconsensusError,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownInput { index, outpoint } => write!(
+                f,
+                "input {} spends {} which is not a wallet UTXO nor a provided prevout",
+                index, outpoint
+            ),
+            Self::SpentInput {
+                index,
+                outpoint,
+                spent_by,
+            } => write!(
+                f,
+                "input {} spends {} which is already spent by {}",
+                index, outpoint, spent_by
+            ),
+            Self::DuplicateInput(outpoint) => {
+                write!(f, "{} is spent by more than one input", outpoint)
+            }
+            Self::NotFinalized(index) => write!(f, "input {} is not finalized", index),
+            Self::DustOutput { index, value } => {
+                write!(f, "output {} of {} is dust", index, value)
+            }
+            Self::OutputsExceedInputs { inputs, outputs } => write!(
+                f,
+                "the outputs spend {} but the inputs only provide {}",
+                outputs, inputs
+            ),
+            Self::FeeTooHigh { fee, max } => {
+                write!(f, "the fee of {} is higher than the maximum {}", fee, max)
+            }
+            Self::FeeRateTooHigh { fee_rate, max } => write!(
+                f,
+                "the fee rate of {} sat/vb is higher than the maximum {} sat/vb",
+                fee_rate.to_sat_per_vb_ceil(),
+                max.to_sat_per_vb_ceil()
+            ),
+            Self::FeeRateTooLow { fee_rate, min } => write!(
+                f,
+                "the fee rate of {} sat/vb is lower than the minimum {} sat/vb",
+                fee_rate.to_sat_per_vb_floor(),
+                min.to_sat_per_vb_floor()
+            ),
+            Self::UnsatisfiedInput { index, error } => {
+                write!(f, "input {} is not satisfied: {}", index, error)
+            }
+            #[cfg(feature = "verify")]
+            Self::Script { index, error } => {
+                write!(f, "the script of input {} fails: {}", index, error)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VerifyError {}
+
+impl Wallet {
+    /// Check a finalized transaction before broadcasting it.
+    ///
+    /// This is a last line of defense against bugs and tampering, the transaction is rejected
+    /// unless:
+    ///
+    /// - every input spends a UTXO of the wallet or one of the
+    ///   [`VerifyOptions::foreign_prevouts`], and is finalized;
+    /// - no output is dust, except for `OP_RETURN` outputs;
+    /// - the fee and fee rate are within the bounds of `options`;
+    /// - its locktime and sequences satisfy the timelocks of the wallet descriptors;
+    /// - with the `verify` feature, the script of every input executes successfully against its
+    ///   previous output. libbitcoinconsensus doesn't check taproot spends.
+    ///
+    /// A wallet UTXO already spent by `tx` itself is accepted, so the transaction can be checked
+    /// after being applied to the wallet.
+    pub fn verify_tx(
+        &self,
+        tx: &Transaction,
+        options: &VerifyOptions,
+    ) -> Result<TxReport, VerifyError> {
+        let txid = tx.compute_txid();
+        let mut prevouts = Vec::with_capacity(tx.input.len());
+        let mut spent = BTreeSet::new();
+        for (index, txin) in tx.input.iter().enumerate() {
+            let outpoint = txin.previous_output;
+            if !spent.insert(outpoint) {
+                return Err(VerifyError::DuplicateInput(outpoint));
+            }
+            let prevout = match self.wallet_prevout(index, outpoint, txid)? {
+                Some(prevout) => prevout,
+                None => options
+                    .foreign_prevouts
+                    .get(&outpoint)
+                    .cloned()
+                    .ok_or(VerifyError::UnknownInput { index, outpoint })?,
+            };
+            if txin.script_sig.is_empty() && txin.witness.is_empty() {
+                return Err(VerifyError::NotFinalized(index));
+            }
+            prevouts.push(prevout);
+        }
+
+        for (index, txout) in tx.output.iter().enumerate() {
+            if !txout.script_pubkey.is_op_return()
+                && txout.value.to_sat().is_dust(&txout.script_pubkey)
+            {
+                return Err(VerifyError::DustOutput {
+                    index,
+                    value: txout.value,
+                });
+            }
+        }
+
+        let inputs = prevouts.iter().map(|txout| txout.value).sum::<Amount>();
+        let outputs = tx.output.iter().map(|txout| txout.value).sum::<Amount>();
+        let fee = inputs
+            .checked_sub(outputs)
+            .ok_or(VerifyError::OutputsExceedInputs { inputs, outputs })?;
+        let weight = tx.weight();
+        let fee_rate = fee / weight;
+        if let Some(max) = options.max_fee {
+            if fee > max {
+                return Err(VerifyError::FeeTooHigh { fee, max });
+            }
+        }
+        if let Some(max) = options.max_fee_rate {
+            if fee_rate > max {
+                return Err(VerifyError::FeeRateTooHigh { fee_rate, max });
+            }
+        }
+        if let Some(min) = options.min_fee_rate {
+            if fee_rate < min {
+                return Err(VerifyError::FeeRateTooLow { fee_rate, min });
+            }
+        }
+
+        // the foreign inputs may not be miniscript, only the wallet ones are interpreted
+        for (index, (txin, prevout)) in tx.input.iter().zip(&prevouts).enumerate() {
+            if !self.is_mine(&prevout.script_pubkey) {
+                continue;
+            }
+            let interpreter = interpreter::Interpreter::from_txdata(
+                &prevout.script_pubkey,
+                &txin.script_sig,
+                &txin.witness,
+                txin.sequence,
+                tx.lock_time,
+            )
+            .map_err(|error| VerifyError::UnsatisfiedInput { index, error })?;
+            for constraint in interpreter.iter_assume_sigs() {
+                constraint.map_err(|error| VerifyError::UnsatisfiedInput { index, error })?;
+            }
+        }
+
+        #[cfg(feature = "verify")]
+        {
+            let serialized_tx = bitcoin::consensus::serialize(tx);
+            for (index, prevout) in prevouts.iter().enumerate() {
+                prevout
+                    .script_pubkey
+                    .verify(index, prevout.value, &serialized_tx)
+                    .map_err(|error| VerifyError::Script { index, error })?;
+            }
+        }
+
+        Ok(TxReport {
+            fee,
+            fee_rate,
+            weight,
+        })
+    }
+
+    /// The wallet output spent by input `index` of the transaction `txid`, `None` if it's not an
+    /// output of the wallet.
+    fn wallet_prevout(
+        &self,
+        index: usize,
+        outpoint: OutPoint,
+        txid: Txid,
+    ) -> Result<Option<TxOut>, VerifyError> {
+        if self.indexed_graph.index.txout(outpoint).is_none() {
+            return Ok(None);
+        }
+        let full_txo = self
+            .indexed_graph
+            .graph()
+            .filter_chain_txouts(
+                &self.chain,
+                self.chain.tip().block_id(),
+                core::iter::once(((), outpoint)),
+            )
+            .map(|(_, full_txo)| full_txo)
+            .next();
+        match full_txo {
+            Some(full_txo) => match full_txo.spent_by {
+                Some((_, spent_by)) if spent_by != txid => Err(VerifyError::SpentInput {
+                    index,
+                    outpoint,
+                    spent_by,
+                }),
+                _ => Ok(Some(full_txo.txout)),
+            },
+            // the output of a transaction which is no longer canonical
+            None => Ok(None),
+        }
+    }
+}
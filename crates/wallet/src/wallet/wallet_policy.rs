@@ -0,0 +1,326 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! BIP-388 wallet policies
+//!
+//! Hardware signers register multisig and other non-standard wallets in the
+//! [BIP-388](https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki) wallet policy form:
+//! a descriptor template where the keys are replaced by `@0`, `@1`, ... placeholders, and the
+//! vector of the keys they stand for. [`Wallet::wallet_policy`] returns the policy describing
+//! both keychains of the wallet.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk_wallet::Wallet;
+//! # use bitcoin::Network;
+//! let descriptor = "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)";
+//! let change_descriptor = "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/1/*)";
+//! let wallet = Wallet::new(descriptor, change_descriptor, Network::Testnet)?;
+//!
+//! let policy = wallet.wallet_policy("")?;
+//! assert_eq!(policy.template, "wpkh(@0/**)");
+//! assert_eq!(
+//!     policy.keys,
+//!     ["[c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe"]
+//! );
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use bdk_chain::collections::BTreeMap;
+use bitcoin::bip32::DerivationPath;
+use bitcoin::consensus::encode::{serialize, VarInt};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey, Wildcard};
+use miniscript::{Descriptor, ForEachKey};
+use serde::Serialize;
+
+use crate::descriptor::ExtendedDescriptor;
+use crate::KeychainKind;
+
+use super::Wallet;
+
+/// The longest wallet name accepted by hardware signers
+const MAX_NAME_LEN: usize = 64;
+
+/// The characters around the keys of a descriptor string
+const DELIMITERS: &[char] = &['(', ')', ','];
+
+/// A BIP-388 wallet policy
+///
+/// It serializes to the JSON object of its `name`, `descriptor_template` and `keys_info`, the
+/// form hardware wallet tools take it in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WalletPolicy {
+    /// The name the policy is registered with, empty for standard single-signature wallets
+    pub name: String,
+    /// The descriptor template, with `@i/**` or `@i/<M;N>/*` key placeholders
+    #[serde(rename = "descriptor_template")]
+    pub template: String,
+    /// The key information vector: the `[fingerprint/path]xpub` key `@i` stands for
+    #[serde(rename = "keys_info")]
+    pub keys: Vec<String>,
+    /// The policy id, `SHA256` of the serialized policy
+    ///
+    /// This is the id a Ledger device computes for the policy, the registration HMAC it returns
+    /// is keyed with a secret of the device and committed to this id.
+    #[serde(skip)]
+    pub id: sha256::Hash,
+}
+
+impl WalletPolicy {
+    /// Build a policy from its `name`, `template` and `keys`, computing its id.
+    pub fn new(name: String, template: String, keys: Vec<String>) -> Self {
+        let id = policy_id(&name, &template, &keys);
+        Self {
+            name,
+            template,
+            keys,
+            id,
+        }
+    }
+
+    /// Check that `id`, as returned by a device after registering a policy, is the id of this
+    /// policy.
+    pub fn check_id(&self, id: &[u8]) -> bool {
+        id == self.id.as_byte_array()
+    }
+}
+
+/// Error while building the [`WalletPolicy`] of a wallet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletPolicyError {
+    /// The wallet has no internal keychain, while a policy describes both the receive and the
+    /// change addresses
+    SingleKeychain,
+    /// The descriptor type can't be a policy: only `pkh`, `wpkh`, `sh`, `wsh` and `tr`
+    /// descriptors can
+    UnsupportedDescriptor(String),
+    /// The key is not an extended public key
+    NotExtendedKey(String),
+    /// The key doesn't derive its addresses with a single unhardened step followed by `/*`
+    UnsupportedDerivation(String),
+    /// The internal descriptor is not the external one, with different last derivation steps
+    KeychainsMismatch,
+    /// The name is longer than 64 bytes
+    NameTooLong,
+}
+
+impl fmt::Display for WalletPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SingleKeychain => write!(f, "the wallet has a single keychain"),
+            Self::UnsupportedDescriptor(desc) => {
+                write!(f, "descriptor `{}` can't be a wallet policy", desc)
+            }
+            Self::NotExtendedKey(key) => write!(f, "key `{}` is not an extended key", key),
+            Self::UnsupportedDerivation(key) => write!(
+                f,
+                "key `{}` doesn't derive addresses with `/<NUM>/*`",
+                key
+            ),
+            Self::KeychainsMismatch => write!(
+                f,
+                "the internal descriptor differs from the external one by more than the derivation steps"
+            ),
+            Self::NameTooLong => write!(f, "the name is longer than {} bytes", MAX_NAME_LEN),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for WalletPolicyError {}
+
+impl Wallet {
+    /// Return the BIP-388 wallet policy of the wallet, to be registered under `name`.
+    ///
+    /// The policy describes both keychains: the external and internal descriptors must be the
+    /// same but for the last unhardened derivation step of each key, such as `/0/*` and `/1/*`,
+    /// which are combined as `/<0;1>/*` (written `/**`). All the keys must be extended public
+    /// keys, their origin is kept in the key information vector.
+    pub fn wallet_policy(&self, name: &str) -> Result<WalletPolicy, WalletPolicyError> {
+        if name.len() > MAX_NAME_LEN {
+            return Err(WalletPolicyError::NameTooLong);
+        }
+        let external = self.public_descriptor(KeychainKind::External);
+        if self.map_keychain(KeychainKind::Internal) != KeychainKind::Internal {
+            return Err(WalletPolicyError::SingleKeychain);
+        }
+        let internal = self.public_descriptor(KeychainKind::Internal);
+        if let Descriptor::Bare(_) = external {
+            return Err(WalletPolicyError::UnsupportedDescriptor(format!(
+                "{:#}",
+                external
+            )));
+        }
+
+        let external_keys = descriptor_keys(external);
+        let internal_keys = descriptor_keys(internal);
+        let external = format!("{:#}", external);
+        let internal = format!("{:#}", internal);
+
+        // the descriptors are walked token by token, in the order they are written in, so that
+        // the placeholders are numbered in the order of their first appearance
+        let external_tokens = external.split_inclusive(DELIMITERS).collect::<Vec<_>>();
+        let internal_tokens = internal.split_inclusive(DELIMITERS).collect::<Vec<_>>();
+        if external_tokens.len() != internal_tokens.len() {
+            return Err(WalletPolicyError::KeychainsMismatch);
+        }
+
+        let mut keys = Vec::<String>::new();
+        let mut template = String::with_capacity(external.len());
+        for (external_token, internal_token) in external_tokens.into_iter().zip(internal_tokens) {
+            let external_key = external_token.trim_end_matches(DELIMITERS);
+            let internal_key = internal_token.trim_end_matches(DELIMITERS);
+            let delimiter = &external_token[external_key.len()..];
+            if delimiter != &internal_token[internal_key.len()..] {
+                return Err(WalletPolicyError::KeychainsMismatch);
+            }
+            let (external_key, internal_key) = match (
+                external_keys.get(external_key),
+                internal_keys.get(internal_key),
+            ) {
+                (Some(external_key), Some(internal_key)) => (external_key, internal_key),
+                (None, None) if external_key == internal_key => {
+                    template.push_str(external_token);
+                    continue;
+                }
+                _ => return Err(WalletPolicyError::KeychainsMismatch),
+            };
+
+            let (external_info, receive) = key_info(external_key)?;
+            let (internal_info, change) = key_info(internal_key)?;
+            if external_info != internal_info || receive == change {
+                return Err(WalletPolicyError::KeychainsMismatch);
+            }
+            let index = match keys.iter().position(|k| *k == external_info) {
+                Some(index) => index,
+                None => {
+                    keys.push(external_info);
+                    keys.len() - 1
+                }
+            };
+            if (receive, change) == (0, 1) {
+                template.push_str(&format!("@{}/**", index));
+            } else {
+                template.push_str(&format!("@{}/<{};{}>/*", index, receive, change));
+            }
+            template.push_str(delimiter);
+        }
+
+        Ok(WalletPolicy::new(name.to_string(), template, keys))
+    }
+}
+
+/// The keys of `descriptor`, by their string.
+fn descriptor_keys(descriptor: &ExtendedDescriptor) -> BTreeMap<String, DescriptorPublicKey> {
+    let mut keys = BTreeMap::new();
+    descriptor.for_each_key(|key| {
+        keys.insert(key.to_string(), key.clone());
+        true
+    });
+    keys
+}
+
+/// The `[fingerprint/path]xpub` key information of `key` and the derivation step before its
+/// wildcard.
+fn key_info(key: &DescriptorPublicKey) -> Result<(String, u32), WalletPolicyError> {
+    let xkey = match key {
+        DescriptorPublicKey::XPub(xkey) => xkey,
+        _ => return Err(WalletPolicyError::NotExtendedKey(key.to_string())),
+    };
+    let step = match (xkey.derivation_path.as_ref(), xkey.wildcard) {
+        ([step], Wildcard::Unhardened) if step.is_normal() => u32::from(*step),
+        _ => return Err(WalletPolicyError::UnsupportedDerivation(key.to_string())),
+    };
+    let key_info = DescriptorPublicKey::XPub(DescriptorXKey {
+        origin: xkey.origin.clone(),
+        xkey: xkey.xkey,
+        derivation_path: DerivationPath::master(),
+        wildcard: Wildcard::None,
+    });
+    Ok((key_info.to_string(), step))
+}
+
+/// The id of a Ledger wallet policy: `SHA256` of the version 2 serialization of the policy.
+///
+/// The serialization is the version byte, the length-prefixed name, the varint length of the
+/// template and its `SHA256`, the varint number of keys and the Merkle root of the keys.
+fn policy_id(name: &str, template: &str, keys: &[String]) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&[2, name.len() as u8]);
+    engine.input(name.as_bytes());
+    engine.input(&serialize(&VarInt(template.len() as u64)));
+    engine.input(sha256::Hash::hash(template.as_bytes()).as_byte_array());
+    engine.input(&serialize(&VarInt(keys.len() as u64)));
+    let leaves = keys
+        .iter()
+        .map(|key| tagged_hash(0x00, &[key.as_bytes()]))
+        .collect::<Vec<_>>();
+    engine.input(merkle_root(&leaves).as_byte_array());
+    sha256::Hash::from_engine(engine)
+}
+
+/// `SHA256(prefix || data...)`
+fn tagged_hash(prefix: u8, data: &[&[u8]]) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&[prefix]);
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+/// The root of the Merkle tree of `leaves`, where the left subtree holds the largest power of
+/// two of leaves strictly smaller than their number.
+fn merkle_root(leaves: &[sha256::Hash]) -> sha256::Hash {
+    match leaves {
+        [] => sha256::Hash::all_zeros(),
+        [leaf] => *leaf,
+        _ => {
+            let split = leaves.len().next_power_of_two() / 2;
+            let left = merkle_root(&leaves[..split]);
+            let right = merkle_root(&leaves[split..]);
+            tagged_hash(0x01, &[left.as_byte_array(), right.as_byte_array()])
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merkle_root_split() {
+        let leaves = (0..5u8)
+            .map(|i| tagged_hash(0x00, &[&[i]]))
+            .collect::<Vec<_>>();
+        let node = |l: sha256::Hash, r: sha256::Hash| {
+            tagged_hash(0x01, &[l.as_byte_array(), r.as_byte_array()])
+        };
+        assert_eq!(merkle_root(&[]), sha256::Hash::all_zeros());
+        assert_eq!(merkle_root(&leaves[..1]), leaves[0]);
+        assert_eq!(
+            merkle_root(&leaves[..3]),
+            node(node(leaves[0], leaves[1]), leaves[2])
+        );
+        assert_eq!(
+            merkle_root(&leaves),
+            node(
+                node(node(leaves[0], leaves[1]), node(leaves[2], leaves[3])),
+                leaves[4]
+            )
+        );
+    }
+}
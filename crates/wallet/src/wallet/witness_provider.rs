@@ -0,0 +1,77 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Inputs finalized with a witness provided by the user, see
+//! [`TxBuilder::add_utxo_with_witness_provider`]
+//!
+//! [`TxBuilder::add_utxo_with_witness_provider`]: super::tx_builder::TxBuilder::add_utxo_with_witness_provider
+
+use core::fmt;
+
+use bitcoin::sighash::{Prevouts, SighashCache, TaprootError};
+use bitcoin::{TapLeafHash, TapSighash, TapSighashType, Transaction, TxOut, Witness};
+
+use super::signer::SignerError;
+
+/// Provides the final witness of an input the wallet can't satisfy, for instance a Taproot leaf
+/// with a script miniscript can't express
+///
+/// The provider is called when the PSBT is finalized, see [`Wallet::finalize_psbt`].
+///
+/// [`Wallet::finalize_psbt`]: super::Wallet::finalize_psbt
+pub trait WitnessProvider: fmt::Debug + Send + Sync {
+    /// The hash of the Taproot leaf spent by the witness, `None` for other spends
+    ///
+    /// It's passed to [`provide_witness`](Self::provide_witness) in the [`WitnessContext`].
+    fn leaf_hash(&self) -> Option<TapLeafHash> {
+        None
+    }
+
+    /// Return the final witness of the input
+    ///
+    /// Custom errors can be returned as [`SignerError::External`].
+    fn provide_witness(&self, context: &WitnessContext<'_>) -> Result<Witness, SignerError>;
+}
+
+/// What a [`WitnessProvider`] needs to compute the sighash of its input
+#[derive(Debug, Clone, Copy)]
+pub struct WitnessContext<'a> {
+    /// The unsigned transaction
+    pub tx: &'a Transaction,
+    /// The index of the input in the transaction
+    pub input_index: usize,
+    /// The outputs spent by all the inputs of the transaction, in order
+    pub prevouts: &'a [TxOut],
+    /// The leaf returned by [`WitnessProvider::leaf_hash`]
+    pub leaf_hash: Option<TapLeafHash>,
+}
+
+impl<'a> WitnessContext<'a> {
+    /// The Taproot sighash of the input: of a script spend of [`leaf_hash`](Self::leaf_hash)
+    /// if there is one, of a key spend otherwise
+    pub fn taproot_sighash(
+        &self,
+        sighash_type: TapSighashType,
+    ) -> Result<TapSighash, TaprootError> {
+        let mut cache = SighashCache::new(self.tx);
+        let prevouts = Prevouts::All(self.prevouts);
+        match self.leaf_hash {
+            Some(leaf_hash) => cache.taproot_script_spend_signature_hash(
+                self.input_index,
+                &prevouts,
+                leaf_hash,
+                sighash_type,
+            ),
+            None => {
+                cache.taproot_key_spend_signature_hash(self.input_index, &prevouts, sighash_type)
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+#![cfg(feature = "bip322")]
+
+use assert_matches::assert_matches;
+use bdk_wallet::bitcoin::{Address, Network};
+use bdk_wallet::signer::SignerError;
+use bdk_wallet::wallet::bip322::{verify_message, Bip322Error, Bip322Signature, MessageAddress};
+use bdk_wallet::{KeychainKind, Wallet};
+use core::str::FromStr;
+mod common;
+use common::*;
+
+/// The private key of the BIP322 test vectors
+const VECTOR_KEY: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+/// A change descriptor valid on mainnet
+const CHANGE: &str = "wpkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)";
+
+#[test]
+fn sign_p2wpkh_matches_test_vector() {
+    let wallet = Wallet::new(
+        format!("wpkh({})", VECTOR_KEY).as_str(),
+        CHANGE,
+        Network::Bitcoin,
+    )
+    .unwrap();
+    let address = wallet.peek_address(KeychainKind::External, 0).address;
+    assert_eq!(
+        address.to_string(),
+        "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l"
+    );
+
+    let signature = wallet
+        .sign_message(address.clone(), b"Hello World")
+        .unwrap();
+    assert_eq!(
+        signature.to_string(),
+        "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+    );
+    verify_message(&address, b"Hello World", &signature).unwrap();
+}
+
+#[test]
+fn sign_p2tr_key_path() {
+    let wallet = Wallet::new(
+        format!("tr({})", VECTOR_KEY).as_str(),
+        CHANGE,
+        Network::Bitcoin,
+    )
+    .unwrap();
+    let address = wallet.peek_address(KeychainKind::External, 0).address;
+    assert_eq!(
+        address.to_string(),
+        "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3"
+    );
+
+    let signature = wallet
+        .sign_message(address.clone(), b"Hello World")
+        .unwrap();
+    // the key path witness is the signature alone, with the default sighash
+    assert_matches!(&signature, Bip322Signature::Simple(witness) if witness.len() == 1 && witness[0].len() == 64);
+    let signature = Bip322Signature::from_str(&signature.to_string()).unwrap();
+    verify_message(&address, b"Hello World", &signature).unwrap();
+    assert_matches!(
+        verify_message(&address, b"Hello", &signature),
+        Err(Bip322Error::InvalidSignature)
+    );
+}
+
+#[test]
+fn sign_by_derivation_index() {
+    let (wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+    let address = wallet.peek_address(KeychainKind::External, 42).address;
+
+    let signature = wallet
+        .sign_message((KeychainKind::External, 42), b"proof of ownership")
+        .unwrap();
+    verify_message(&address, b"proof of ownership", &signature).unwrap();
+
+    let other = wallet.peek_address(KeychainKind::External, 41).address;
+    assert_matches!(
+        verify_message(&other, b"proof of ownership", &signature),
+        Err(Bip322Error::InvalidSignature)
+    );
+}
+
+#[test]
+fn sign_p2pkh_legacy() {
+    let wallet = Wallet::new(
+        format!("pkh({})", VECTOR_KEY).as_str(),
+        CHANGE,
+        Network::Bitcoin,
+    )
+    .unwrap();
+    let address = wallet.peek_address(KeychainKind::External, 0).address;
+
+    let signature = wallet
+        .sign_message(address.clone(), b"Hello World")
+        .unwrap();
+    assert_matches!(signature, Bip322Signature::Legacy(_));
+    let signature = Bip322Signature::from_str(&signature.to_string()).unwrap();
+    verify_message(&address, b"Hello World", &signature).unwrap();
+}
+
+#[test]
+fn sign_message_errors() {
+    let (wallet, _) = get_funded_wallet_wpkh();
+    let foreign = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked();
+    assert_matches!(
+        wallet.sign_message(MessageAddress::Address(foreign), b"Hello World"),
+        Err(Bip322Error::UnknownAddress)
+    );
+
+    let (wallet, _) =
+        get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    assert_matches!(
+        wallet.sign_message((KeychainKind::External, 0), b"Hello World"),
+        Err(Bip322Error::UnsupportedScript(_))
+    );
+
+    // only the change keychain has private keys
+    let (wallet, _) = get_funded_wallet(
+        "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)",
+    );
+    assert_matches!(
+        wallet.sign_message((KeychainKind::External, 0), b"Hello World"),
+        Err(Bip322Error::MissingKeys)
+    );
+
+    let wallet = Wallet::new(
+        "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)",
+        "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/1/*)",
+        Network::Regtest,
+    )
+    .unwrap();
+    assert_matches!(
+        wallet.sign_message((KeychainKind::External, 0), b"Hello World"),
+        Err(Bip322Error::Signer(SignerError::WatchOnly))
+    );
+}
@@ -0,0 +1,476 @@
+#![cfg(feature = "payjoin")]
+
+use assert_matches::assert_matches;
+use bdk_wallet::bitcoin::hashes::Hash;
+use bdk_wallet::bitcoin::key::TweakedPublicKey;
+use bdk_wallet::bitcoin::{
+    absolute, bip32, ecdsa, psbt, secp256k1, transaction, Amount, EcdsaSighashType, Psbt,
+    PubkeyHash, PublicKey, ScriptBuf, Sequence, TxIn, Witness, XOnlyPublicKey,
+};
+use bdk_wallet::wallet::error::CreateTxError;
+use bdk_wallet::wallet::payjoin::{PayjoinError, PayjoinParams, PayjoinSession};
+use bdk_wallet::wallet::tx_builder::ChangeOutput;
+use bdk_wallet::{KeychainKind, SignOptions, Wallet};
+use core::str::FromStr;
+mod common;
+use common::*;
+
+/// The descriptors of the wallet of the receiver
+const RECEIVER_DESCRIPTORS: (&str, &str) = (
+    "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)",
+    "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/1/*)",
+);
+
+/// The fee the receiver takes from the change of the sender for its input
+const CONTRIBUTION: Amount = Amount::from_sat(130);
+
+/// The finalized original PSBT of a payment of the sender to the receiver, at 2 sat/vB
+fn original(sender: &mut Wallet, payment_script: ScriptBuf) -> (Psbt, ChangeOutput) {
+    let mut builder = sender.build_tx();
+    builder
+        .add_recipient(payment_script, Amount::from_sat(25_000))
+        .fee_rate(feerate_unchecked(2.0));
+    let (mut psbt, change) = builder.finish_payjoin_original().unwrap();
+    assert!(sender.sign(&mut psbt, SignOptions::default()).unwrap());
+    (psbt, change.expect("must have change"))
+}
+
+/// Play the receiver: add an input of `receiver` paying into the payment output, take
+/// `contribution` from the output at `fee_output` and sign
+fn proposal(
+    receiver: &mut Wallet,
+    original: &Psbt,
+    payment_script: &ScriptBuf,
+    fee_output: usize,
+    contribution: Amount,
+) -> Psbt {
+    let mut psbt = original.clone();
+    let utxo = receiver.list_unspent().next().expect("must have a utxo");
+    let input = receiver.get_psbt_input(utxo.clone(), None, false).unwrap();
+    psbt.unsigned_tx.input.push(TxIn {
+        previous_output: utxo.outpoint,
+        sequence: original.unsigned_tx.input[0].sequence,
+        ..Default::default()
+    });
+    psbt.inputs.push(input);
+    for (index, txout) in psbt.unsigned_tx.output.iter_mut().enumerate() {
+        if &txout.script_pubkey == payment_script {
+            txout.value += utxo.txout.value;
+        }
+        if index == fee_output {
+            txout.value -= contribution;
+        }
+    }
+
+    let receiver_input = psbt.inputs.len() - 1;
+    let options = SignOptions {
+        trust_witness_utxo: true,
+        inputs: Some([receiver_input].into()),
+        ..Default::default()
+    };
+    assert!(receiver.sign(&mut psbt, options).unwrap());
+
+    // the sender fills its inputs again before signing them
+    for input in &mut psbt.inputs[..receiver_input] {
+        *input = psbt::Input::default();
+    }
+    for output in &mut psbt.outputs {
+        *output = psbt::Output::default();
+    }
+    psbt
+}
+
+struct Payjoin {
+    sender: Wallet,
+    original: Psbt,
+    params: PayjoinParams,
+    proposal: Psbt,
+}
+
+/// A valid payjoin between funded wallets, where the receiver takes [`CONTRIBUTION`] from the
+/// change of the sender
+fn payjoin() -> Payjoin {
+    let (mut sender, _) = get_funded_wallet_wpkh();
+    let (mut receiver, _) =
+        get_funded_wallet_with_change(RECEIVER_DESCRIPTORS.0, RECEIVER_DESCRIPTORS.1);
+    let payment_script = receiver
+        .next_unused_address(KeychainKind::External)
+        .script_pubkey();
+    let (original, change) = original(&mut sender, payment_script.clone());
+    let params = PayjoinParams::new(payment_script.clone())
+        .with_fee_contribution(change.index, Amount::from_sat(200));
+    let proposal = proposal(
+        &mut receiver,
+        &original,
+        &payment_script,
+        change.index,
+        CONTRIBUTION,
+    );
+    Payjoin {
+        sender,
+        original,
+        params,
+        proposal,
+    }
+}
+
+impl Payjoin {
+    fn session(&self) -> PayjoinSession {
+        PayjoinSession::new(self.original.clone(), self.params.clone()).unwrap()
+    }
+
+    fn payment_index(&self) -> usize {
+        self.proposal
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey == self.params.payment_script)
+            .unwrap()
+    }
+
+    fn fee_index(&self) -> usize {
+        self.params.additional_fee_output_index.unwrap()
+    }
+
+    /// The index of the input of the receiver in the proposal
+    fn receiver_index(&self) -> usize {
+        self.proposal.inputs.len() - 1
+    }
+
+    /// Process the proposal after applying `mutation` to it
+    fn process_with(&self, mutation: impl FnOnce(&mut Psbt)) -> Result<Psbt, PayjoinError> {
+        let mut proposal = self.proposal.clone();
+        mutation(&mut proposal);
+        self.session().process_proposal(proposal)
+    }
+}
+
+#[test]
+fn test_payjoin_round_trip() {
+    let payjoin = payjoin();
+    let session = payjoin.session();
+    assert_eq!(session.original_psbt(), &payjoin.original);
+    assert_eq!(session.params(), &payjoin.params);
+
+    let mut psbt = session.process_proposal(payjoin.proposal.clone()).unwrap();
+    assert!(payjoin
+        .sender
+        .sign(&mut psbt, SignOptions::default())
+        .unwrap());
+    let original_fee = payjoin.original.fee().unwrap();
+    assert_eq!(psbt.fee().unwrap(), original_fee + CONTRIBUTION);
+
+    let tx = psbt.extract_tx().unwrap();
+    assert_eq!(tx.input.len(), 2);
+    assert!(tx.input.iter().all(|txin| !txin.witness.is_empty()));
+    // the outputs of the sender are unchanged but for the fee contribution
+    let original_tx = payjoin.original.unsigned_tx.clone();
+    let fee_index = payjoin.fee_index();
+    assert_eq!(
+        tx.output[fee_index].value,
+        original_tx.output[fee_index].value - CONTRIBUTION
+    );
+    assert!(tx.output[payjoin.payment_index()].value > original_tx.output[1 - fee_index].value);
+}
+
+#[test]
+fn test_payjoin_output_substitution() {
+    let payjoin = payjoin();
+    let other_script = payjoin
+        .sender
+        .peek_address(KeychainKind::External, 5)
+        .script_pubkey();
+    let substitute = |proposal: &mut Psbt| {
+        proposal.unsigned_tx.output[payjoin.payment_index()].script_pubkey = other_script.clone()
+    };
+    assert!(payjoin.process_with(substitute).is_ok());
+
+    let mut params = payjoin.params.clone();
+    params.disable_output_substitution = true;
+    let session = PayjoinSession::new(payjoin.original.clone(), params).unwrap();
+    let payment_index = payjoin.payment_index();
+    let mut proposal = payjoin.proposal.clone();
+    substitute(&mut proposal);
+    assert_eq!(
+        session.process_proposal(proposal),
+        Err(PayjoinError::OutputMissing(payment_index))
+    );
+    // the receiver can still take more
+    let mut proposal = payjoin.proposal.clone();
+    proposal.unsigned_tx.output[payment_index].value -= Amount::from_sat(1);
+    assert!(session.process_proposal(proposal).is_ok());
+    let mut proposal = payjoin.proposal.clone();
+    let original_payment = payjoin.original.unsigned_tx.output[payment_index].value;
+    proposal.unsigned_tx.output[payment_index].value = original_payment - Amount::from_sat(1);
+    assert_eq!(
+        session.process_proposal(proposal),
+        Err(PayjoinError::OutputChanged(payment_index))
+    );
+}
+
+/// A check of the proposal: its name, the change which breaks it, and the error it fails with
+type ProposalCase<'a> = (&'a str, Box<dyn Fn(&mut Psbt) + 'a>, PayjoinError);
+
+#[test]
+fn test_payjoin_proposal_checks() {
+    let payjoin = payjoin();
+    let sender_outpoint = payjoin.original.unsigned_tx.input[0].previous_output;
+    let receiver_index = payjoin.receiver_index();
+    let receiver_outpoint = payjoin.proposal.unsigned_tx.input[receiver_index].previous_output;
+    let fee_index = payjoin.fee_index();
+    let payment_index = payjoin.payment_index();
+    let sender_final_witness = payjoin.original.inputs[0].final_script_witness.clone();
+    let sender_utxo = payjoin.original.inputs[0].witness_utxo.clone();
+    let key_source = (
+        bip32::Fingerprint::default(),
+        bip32::DerivationPath::default(),
+    );
+    let pubkey = secp256k1::PublicKey::from_str(
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+    )
+    .unwrap();
+
+    let cases: Vec<ProposalCase> = vec![
+        (
+            "version",
+            Box::new(|p| p.unsigned_tx.version = transaction::Version(3)),
+            PayjoinError::VersionChanged,
+        ),
+        (
+            "locktime",
+            Box::new(|p| {
+                p.unsigned_tx.lock_time = absolute::LockTime::from_consensus(1);
+            }),
+            PayjoinError::LockTimeChanged,
+        ),
+        (
+            "sender input removed",
+            Box::new(|p| {
+                p.unsigned_tx.input.remove(0);
+                p.inputs.remove(0);
+            }),
+            PayjoinError::SenderInputMissing(sender_outpoint),
+        ),
+        (
+            "sender sequence",
+            Box::new(|p| {
+                for txin in &mut p.unsigned_tx.input {
+                    txin.sequence = Sequence::MAX;
+                }
+            }),
+            PayjoinError::SenderInputSequenceChanged(sender_outpoint),
+        ),
+        (
+            "mixed sequence",
+            Box::new(move |p| p.unsigned_tx.input[receiver_index].sequence = Sequence::MAX),
+            PayjoinError::MixedSequence,
+        ),
+        (
+            "sender input finalized",
+            Box::new(|p| p.inputs[0].final_script_witness = sender_final_witness.clone()),
+            PayjoinError::SenderInputFinalized(sender_outpoint),
+        ),
+        (
+            "sender utxo kept",
+            Box::new(|p| p.inputs[0].witness_utxo = sender_utxo.clone()),
+            PayjoinError::SenderInputUtxo(sender_outpoint),
+        ),
+        (
+            "receiver input not finalized",
+            Box::new(move |p| p.inputs[receiver_index].final_script_witness = None),
+            PayjoinError::ReceiverInputNotFinalized(receiver_outpoint),
+        ),
+        (
+            "receiver utxo missing",
+            Box::new(move |p| {
+                p.inputs[receiver_index].witness_utxo = None;
+                p.inputs[receiver_index].non_witness_utxo = None;
+            }),
+            PayjoinError::ReceiverInputMissingUtxo(receiver_outpoint),
+        ),
+        (
+            "receiver utxo of another script type",
+            Box::new(move |p| {
+                let input = &mut p.inputs[receiver_index];
+                input.non_witness_utxo = None;
+                let utxo = input.witness_utxo.as_mut().unwrap();
+                utxo.script_pubkey = ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros());
+            }),
+            PayjoinError::MixedInputScripts,
+        ),
+        (
+            "input key origins",
+            Box::new(|p| {
+                p.inputs[0]
+                    .bip32_derivation
+                    .insert(pubkey, key_source.clone());
+            }),
+            PayjoinError::InputKeyOrigins(0),
+        ),
+        (
+            "partial signatures",
+            Box::new(move |p| {
+                let sig = "304402204f67e2afb76142d44fae58a2495d33a3419daa26cd0db8d04f3452b63289ac0f022010762a9fb67e94cc5cad9026f6dc99ff7f070f4278d30fbc7d0c869dd38c7fe701";
+                p.inputs[receiver_index].partial_sigs.insert(
+                    PublicKey::new(pubkey),
+                    ecdsa::Signature::from_str(sig).unwrap(),
+                );
+            }),
+            PayjoinError::InputPartialSignatures(receiver_index),
+        ),
+        (
+            "output key origins",
+            Box::new(|p| {
+                p.outputs[fee_index]
+                    .bip32_derivation
+                    .insert(pubkey, key_source.clone());
+            }),
+            PayjoinError::OutputKeyOrigins(fee_index),
+        ),
+        (
+            "fee output removed",
+            Box::new(move |p| {
+                p.unsigned_tx.output.remove(fee_index);
+                p.outputs.remove(fee_index);
+            }),
+            PayjoinError::OutputMissing(fee_index),
+        ),
+        (
+            "fee decreased",
+            Box::new(move |p| p.unsigned_tx.output[payment_index].value += Amount::from_sat(200)),
+            PayjoinError::FeeDecreased,
+        ),
+        (
+            "contribution above the maximum",
+            Box::new(move |p| p.unsigned_tx.output[fee_index].value -= Amount::from_sat(100)),
+            PayjoinError::FeeContributionExceedsMaximum(Amount::from_sat(230)),
+        ),
+        (
+            "contribution paying outputs",
+            Box::new(move |p| p.unsigned_tx.output[payment_index].value += CONTRIBUTION),
+            PayjoinError::FeeContributionPaysOutputs(CONTRIBUTION),
+        ),
+        (
+            "psbt maps missing",
+            Box::new(|p| {
+                p.outputs.pop();
+            }),
+            PayjoinError::MalformedPsbt,
+        ),
+    ];
+    for (name, mutation, expected) in cases {
+        assert_eq!(payjoin.process_with(mutation), Err(expected), "{}", name);
+    }
+}
+
+#[test]
+fn test_payjoin_fee_checks() {
+    let payjoin = payjoin();
+    let fee_index = payjoin.fee_index();
+
+    // the contribution only pays for the input of the receiver at the original fee rate
+    let mut params = payjoin.params.clone();
+    params.max_additional_fee_contribution = Amount::from_sat(10_000);
+    let session = PayjoinSession::new(payjoin.original.clone(), params).unwrap();
+    let mut proposal = payjoin.proposal.clone();
+    proposal.unsigned_tx.output[fee_index].value -= Amount::from_sat(1_000);
+    assert_eq!(
+        session.process_proposal(proposal),
+        Err(PayjoinError::FeeContributionExceedsInputsFee(
+            CONTRIBUTION + Amount::from_sat(1_000)
+        ))
+    );
+
+    let mut params = payjoin.params.clone();
+    params.min_fee_rate = Some(feerate_unchecked(1.5));
+    let session = PayjoinSession::new(payjoin.original.clone(), params.clone()).unwrap();
+    assert!(session.process_proposal(payjoin.proposal.clone()).is_ok());
+    params.min_fee_rate = Some(feerate_unchecked(10.0));
+    let session = PayjoinSession::new(payjoin.original.clone(), params).unwrap();
+    assert_matches!(
+        session.process_proposal(payjoin.proposal.clone()),
+        Err(PayjoinError::FeeRateBelowMinimum(fee_rate))
+            if fee_rate < feerate_unchecked(3.0)
+    );
+
+    // without a fee output, the receiver pays for its input
+    let mut params = payjoin.params.clone();
+    params.additional_fee_output_index = None;
+    let session = PayjoinSession::new(payjoin.original.clone(), params).unwrap();
+    assert_eq!(
+        session.process_proposal(payjoin.proposal.clone()),
+        Err(PayjoinError::OutputChanged(fee_index))
+    );
+}
+
+#[test]
+fn test_payjoin_session_checks() {
+    let payjoin = payjoin();
+    let params = payjoin.params.clone();
+    let fee_index = payjoin.fee_index();
+
+    let mut unsigned = payjoin.original.clone();
+    unsigned.inputs[0].final_script_witness = None;
+    assert_matches!(
+        PayjoinSession::new(unsigned, params.clone()),
+        Err(PayjoinError::OriginalNotFinalized)
+    );
+    assert_matches!(
+        PayjoinSession::new(
+            payjoin.original.clone(),
+            PayjoinParams::new(ScriptBuf::new())
+        ),
+        Err(PayjoinError::PaymentOutputMissing)
+    );
+    for index in [1 - fee_index, 2] {
+        assert_matches!(
+            PayjoinSession::new(
+                payjoin.original.clone(),
+                params.clone().with_fee_contribution(index, Amount::from_sat(200))
+            ),
+            Err(PayjoinError::InvalidFeeOutputIndex(i)) if i == index
+        );
+    }
+    let mut witness = Witness::new();
+    witness.push([0u8; 64]);
+    let mut mixed = payjoin.original.clone();
+    mixed.inputs[0].witness_utxo.as_mut().unwrap().script_pubkey =
+        ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+            XOnlyPublicKey::from_str(
+                "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+        ));
+    mixed
+        .unsigned_tx
+        .input
+        .push(mixed.unsigned_tx.input[0].clone());
+    mixed.unsigned_tx.input[1].previous_output.vout += 1;
+    let mut input = payjoin.original.inputs[0].clone();
+    input.final_script_witness = Some(witness);
+    mixed.inputs.push(input);
+    assert_matches!(
+        PayjoinSession::new(mixed, params),
+        Err(PayjoinError::MixedInputScripts)
+    );
+}
+
+#[test]
+fn test_finish_payjoin_original_sighash() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .sighash(psbt::PsbtSighashType::from_u32(0x81));
+    assert_matches!(
+        builder.finish_payjoin_original(),
+        Err(CreateTxError::Payjoin(PayjoinError::NonStandardSighash(0)))
+    );
+
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .sighash(EcdsaSighashType::All.into());
+    assert!(builder.finish_payjoin_original().is_ok());
+}
@@ -1,5 +1,15 @@
-use bdk_wallet::bitcoin::{Amount, FeeRate, Psbt, TxIn};
-use bdk_wallet::{psbt, KeychainKind, SignOptions};
+use assert_matches::assert_matches;
+use bdk_chain::ConfirmationTime;
+use bdk_wallet::bitcoin::bip32::Xpriv;
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::bitcoin::{
+    absolute, transaction, Amount, FeeRate, Psbt, TapSighashType, Transaction, TxIn, TxOut,
+};
+use bdk_wallet::psbt::airgap::{
+    rejoin, shrink_for_signing, split_per_input, FieldMask, RejoinError, SplitError,
+};
+use bdk_wallet::psbt::PsbtUtils;
+use bdk_wallet::{psbt, KeychainKind, SignOptions, Wallet};
 use core::str::FromStr;
 mod common;
 use common::*;
@@ -222,3 +232,674 @@ fn test_psbt_multiple_internalkey_signers() {
     let verify_res = secp.verify_schnorr(&signature, &message, &xonlykey);
     assert!(verify_res.is_ok(), "The wrong internal key was used");
 }
+
+/// A wallet with `count` confirmed outputs on its taproot external keychain
+fn get_wallet_with_outputs(descriptor: &str, count: u64) -> Wallet {
+    let (mut wallet, _) = get_funded_wallet(descriptor);
+    let height = wallet.latest_checkpoint().height();
+    for value in 1..count {
+        let address = wallet.next_unused_address(KeychainKind::External);
+        let tx = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                script_pubkey: address.script_pubkey(),
+                value: Amount::from_sat(10_000 + value),
+            }],
+        };
+        wallet
+            .insert_tx(tx, ConfirmationTime::Confirmed { height, time: 0 })
+            .unwrap();
+    }
+    wallet
+}
+
+/// Build a transaction spending all the outputs of `wallet`
+fn drain_psbt(wallet: &mut Wallet) -> Psbt {
+    let drain_to = wallet.next_unused_address(KeychainKind::Internal);
+    let mut builder = wallet.build_tx();
+    builder.drain_wallet().drain_to(drain_to.script_pubkey());
+    builder.finish().unwrap()
+}
+
+/// Sign `psbt` with the signers of the external keychain, like an air-gapped device would
+fn sign_like_device(wallet: &Wallet, psbt: &mut Psbt) {
+    let secp = Secp256k1::new();
+    for signer in wallet.get_signers(KeychainKind::External).signers() {
+        signer
+            .sign_transaction(psbt, &SignOptions::default(), &secp)
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_psbt_airgap_round_trip() {
+    let descriptor = get_test_tr_single_sig_xprv();
+    let mut wallet = get_wallet_with_outputs(descriptor, 20);
+    let drain_to = wallet.next_unused_address(KeychainKind::Internal);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_wallet()
+        .drain_to(drain_to.script_pubkey())
+        .add_global_xpubs();
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.inputs.len(), 20);
+    assert!(!psbt.xpub.is_empty());
+    let fingerprint = Xpriv::from_str(&descriptor[3..descriptor.len() - 3])
+        .unwrap()
+        .fingerprint(&Secp256k1::new());
+
+    let shrunk = shrink_for_signing(&psbt, FieldMask::NONE, fingerprint);
+    assert!(shrunk.serialize().len() < psbt.serialize().len());
+    assert!(shrunk.xpub.is_empty());
+    assert!(shrunk
+        .inputs
+        .iter()
+        .all(|input| input.non_witness_utxo.is_none()
+            && input.witness_utxo.is_some()
+            && !input.tap_key_origins.is_empty()));
+    assert_eq!(shrunk.outputs, psbt.outputs);
+
+    let mut parts = split_per_input(&shrunk).unwrap();
+    assert_eq!(parts.len(), 20);
+    for part in &mut parts {
+        assert_eq!(part.unsigned_tx, shrunk.unsigned_tx);
+        assert!(part.serialize().len() < shrunk.serialize().len());
+        sign_like_device(&wallet, part);
+    }
+    for (index, part) in parts.iter().enumerate() {
+        for (other, input) in part.inputs.iter().enumerate() {
+            assert_eq!(input.tap_key_sig.is_some(), other == index);
+        }
+    }
+
+    let signed = rejoin(&shrunk, &parts).unwrap();
+    assert!(signed
+        .inputs
+        .iter()
+        .all(|input| input.tap_key_sig.is_some()));
+    psbt.combine(signed).unwrap();
+    assert!(psbt.inputs.iter().all(|input| input.tap_key_sig.is_some()));
+
+    assert!(wallet
+        .finalize_psbt(&mut psbt, SignOptions::default())
+        .unwrap());
+    let tx = psbt.extract_tx().unwrap();
+    assert_eq!(tx.input.len(), 20);
+    assert!(tx.input.iter().all(|txin| txin.witness.len() == 1));
+}
+
+#[test]
+fn test_psbt_airgap_rejoin_rejects_changes() {
+    let mut wallet = get_wallet_with_outputs(get_test_tr_single_sig_xprv(), 3);
+    let psbt = drain_psbt(&mut wallet);
+    let mut parts = split_per_input(&psbt).unwrap();
+    for part in &mut parts {
+        sign_like_device(&wallet, part);
+    }
+    assert!(rejoin(&psbt, &parts).is_ok());
+
+    assert_matches!(
+        rejoin(&psbt, &parts[..2]),
+        Err(RejoinError::PartCount {
+            expected: 3,
+            got: 2
+        })
+    );
+
+    let mut changed = parts.clone();
+    changed[1].unsigned_tx.output[0].value = Amount::from_sat(1);
+    assert_matches!(
+        rejoin(&psbt, &changed),
+        Err(RejoinError::TxMismatch { part: 1 })
+    );
+
+    // a signature for an input of another part
+    let mut changed = parts.clone();
+    changed[0].inputs[2].tap_key_sig = parts[2].inputs[2].tap_key_sig;
+    assert_matches!(
+        rejoin(&psbt, &changed),
+        Err(RejoinError::UnexpectedChange { part: 0 })
+    );
+
+    let mut changed = parts.clone();
+    changed[2].inputs[2].sighash_type = Some(TapSighashType::All.into());
+    assert_matches!(
+        rejoin(&psbt, &changed),
+        Err(RejoinError::UnexpectedChange { part: 2 })
+    );
+
+    let mut changed = parts;
+    changed[1].outputs[0].bip32_derivation.clear();
+    assert_matches!(
+        rejoin(&psbt, &changed),
+        Err(RejoinError::UnexpectedChange { part: 1 })
+    );
+}
+
+#[test]
+fn test_psbt_airgap_guards() {
+    // splitting inputs which don't sign the whole transaction is unsafe
+    let mut wallet = get_wallet_with_outputs(get_test_tr_single_sig_xprv(), 2);
+    let mut psbt = drain_psbt(&mut wallet);
+    psbt.inputs[1].sighash_type = Some(TapSighashType::SinglePlusAnyoneCanPay.into());
+    assert_matches!(
+        split_per_input(&psbt),
+        Err(SplitError::UnsafeSighash { index: 1, .. })
+    );
+    psbt.inputs[1].sighash_type = Some(TapSighashType::Default.into());
+    assert!(split_per_input(&psbt).is_ok());
+
+    // the previous transactions of segwit inputs are replaced by their outputs, unless kept
+    let mut wallet = get_wallet_with_outputs(get_test_wpkh(), 2);
+    let psbt = drain_psbt(&mut wallet);
+    assert!(psbt
+        .inputs
+        .iter()
+        .all(|input| input.non_witness_utxo.is_some()));
+    let fingerprint = psbt.inputs[0].bip32_derivation.values().next().unwrap().0;
+    let shrunk = shrink_for_signing(&psbt, FieldMask::NONE, fingerprint);
+    for (index, input) in shrunk.inputs.iter().enumerate() {
+        assert!(input.non_witness_utxo.is_none());
+        assert_eq!(input.witness_utxo, psbt.get_utxo_for(index));
+    }
+    let kept = shrink_for_signing(&psbt, FieldMask::NON_WITNESS_UTXO, fingerprint);
+    assert_eq!(kept, psbt);
+
+    // the previous transactions of legacy inputs are kept
+    let mut wallet = get_wallet_with_outputs(
+        "pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)",
+        2,
+    );
+    let mut psbt = drain_psbt(&mut wallet);
+    let fingerprint = psbt.inputs[0].bip32_derivation.values().next().unwrap().0;
+    let shrunk = shrink_for_signing(&psbt, FieldMask::NONE, fingerprint);
+    assert!(shrunk
+        .inputs
+        .iter()
+        .all(|input| input.non_witness_utxo.is_some()));
+    assert_eq!(shrunk, psbt);
+
+    psbt.inputs[0].witness_utxo = psbt.get_utxo_for(0);
+    psbt.inputs[0].non_witness_utxo = None;
+    assert_matches!(
+        split_per_input(&psbt),
+        Err(SplitError::MissingNonWitnessUtxo { index: 0 })
+    );
+    psbt.inputs[0].witness_utxo = None;
+    assert_matches!(
+        split_per_input(&psbt),
+        Err(SplitError::MissingUtxo { index: 0 })
+    );
+}
+
+const ANNEX: &[u8] = &[0x50, 0xde, 0xad, 0xbe, 0xef];
+
+/// Whether the witness of the key spend of the `input_index` input of `psbt`, finalized by a
+/// single key taproot wallet, is a valid signature committing to `annex`
+fn is_valid_key_spend(psbt: &Psbt, input_index: usize, annex: Option<&[u8]>) -> bool {
+    use bitcoin::key::XOnlyPublicKey;
+    use bitcoin::secp256k1::{schnorr, Message};
+    use bitcoin::sighash::{Annex, Prevouts, SighashCache};
+
+    let prevouts = (0..psbt.inputs.len())
+        .map(|i| psbt.inputs[i].witness_utxo.clone().unwrap())
+        .collect::<Vec<_>>();
+    let witness = psbt.inputs[input_index]
+        .final_script_witness
+        .as_ref()
+        .unwrap();
+    let signature = schnorr::Signature::from_slice(&witness[0]).unwrap();
+    let output_key =
+        XOnlyPublicKey::from_slice(&prevouts[input_index].script_pubkey.as_bytes()[2..]).unwrap();
+    let sighash = SighashCache::new(&psbt.unsigned_tx)
+        .taproot_signature_hash(
+            input_index,
+            &Prevouts::All(&prevouts),
+            annex.map(|annex| Annex::new(annex).unwrap()),
+            None,
+            TapSighashType::Default,
+        )
+        .unwrap();
+    Secp256k1::verification_only()
+        .verify_schnorr(&signature, &Message::from(sighash), &output_key)
+        .is_ok()
+}
+
+#[test]
+fn test_psbt_taproot_annex() {
+    use bdk_wallet::signer::{taproot_annex_key, SignerError};
+    use bitcoin::psbt::raw;
+
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let send_to = wallet.peek_address(KeychainKind::External, 0);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(send_to.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    let proprietary_key = raw::ProprietaryKey {
+        prefix: b"test".to_vec(),
+        subtype: 0x01,
+        key: vec![0x01],
+    };
+    let unknown_key = raw::Key {
+        type_value: 0xf0,
+        key: vec![0x02],
+    };
+    psbt.inputs[0]
+        .proprietary
+        .insert(proprietary_key.clone(), vec![0x03]);
+    psbt.inputs[0]
+        .unknown
+        .insert(unknown_key.clone(), vec![0x04]);
+
+    // the annex must start with 0x50
+    let options = |annex: &[u8]| SignOptions {
+        taproot_annex: Some(annex.to_vec()),
+        ..Default::default()
+    };
+    let mut invalid = psbt.clone();
+    assert_matches!(
+        wallet.sign(&mut invalid, options(&[0x51, 0x00])),
+        Err(SignerError::InvalidAnnex)
+    );
+
+    let mut unannexed = psbt.clone();
+    assert!(wallet.sign(&mut unannexed, SignOptions::default()).unwrap());
+    let witness = unannexed.inputs[0].final_script_witness.as_ref().unwrap();
+    assert_eq!(witness.len(), 1);
+    assert!(is_valid_key_spend(&unannexed, 0, None));
+
+    // signing and finalizing separately, the annex is carried by the PSBT
+    let sign_options = SignOptions {
+        try_finalize: false,
+        ..options(ANNEX)
+    };
+    assert!(!wallet.sign(&mut psbt, sign_options).unwrap());
+    assert_eq!(
+        psbt.inputs[0].proprietary.get(&taproot_annex_key()),
+        Some(&ANNEX.to_vec())
+    );
+    let psbt_bytes = psbt.serialize();
+    let mut psbt = Psbt::deserialize(&psbt_bytes).unwrap();
+    assert!(wallet
+        .finalize_psbt(&mut psbt, SignOptions::default())
+        .unwrap());
+
+    // the annex is the last element of the witness, the unknown fields are kept
+    let witness = psbt.inputs[0].final_script_witness.as_ref().unwrap();
+    assert_eq!(witness.len(), 2);
+    assert_eq!(witness.last(), Some(ANNEX));
+    assert!(is_valid_key_spend(&psbt, 0, Some(ANNEX)));
+    assert!(!is_valid_key_spend(&psbt, 0, None));
+    assert_eq!(
+        psbt.inputs[0].proprietary.get(&proprietary_key),
+        Some(&vec![0x03])
+    );
+    assert_eq!(psbt.inputs[0].unknown.get(&unknown_key), Some(&vec![0x04]));
+    assert_eq!(Psbt::deserialize(&psbt.serialize()).unwrap(), psbt);
+    let tx = psbt.extract_tx().unwrap();
+    assert_eq!(tx.input[0].witness.last(), Some(ANNEX));
+}
+
+#[test]
+fn test_psbt_future_witness_version_passthrough() {
+    use bitcoin::psbt::raw;
+    use bitcoin::{OutPoint, ScriptBuf, WitnessProgram, WitnessVersion};
+
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let program = WitnessProgram::new(WitnessVersion::V2, &[0x42; 32]).unwrap();
+    let foreign_utxo = TxOut {
+        value: Amount::from_sat(20_000),
+        script_pubkey: ScriptBuf::new_witness_program(&program),
+    };
+    let mut foreign_input = bitcoin::psbt::Input {
+        witness_utxo: Some(foreign_utxo),
+        ..Default::default()
+    };
+    foreign_input.unknown.insert(
+        raw::Key {
+            type_value: 0xf0,
+            key: vec![],
+        },
+        vec![0x01],
+    );
+    let outpoint = OutPoint::new(
+        "7b7e093133847cc5d23c9958c1089329c4abee0c4b19166bb0bfb775c84e0868"
+            .parse()
+            .unwrap(),
+        1,
+    );
+    let send_to = wallet.peek_address(KeychainKind::External, 0);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_foreign_utxo(outpoint, foreign_input.clone(), 4 + 1 + 64)
+        .unwrap()
+        .drain_to(send_to.script_pubkey())
+        .drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    let foreign_index = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|txin| txin.previous_output == outpoint)
+        .unwrap();
+    let foreign_input = psbt.inputs[foreign_index].clone();
+
+    // the wallet input is signed and finalized, the foreign one is left as it is
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(!finalized);
+    assert_eq!(psbt.inputs[foreign_index], foreign_input);
+    let wallet_index = 1 - foreign_index;
+    assert!(psbt.inputs[wallet_index].final_script_witness.is_some());
+    assert!(is_valid_key_spend(&psbt, wallet_index, None));
+
+    // as is a witness provided by a third party
+    let witness = bitcoin::Witness::from_slice(&[vec![0x01; 10], ANNEX.to_vec()]);
+    psbt.inputs[foreign_index].final_script_witness = Some(witness.clone());
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(finalized);
+    let tx = psbt.extract_tx().unwrap();
+    assert_eq!(tx.input[foreign_index].witness, witness);
+}
+
+/// An annexed key spend is mined by a regtest node, which only applies the consensus rules to the
+/// transactions of `generateblock`.
+#[test]
+fn test_psbt_taproot_annex_regtest() -> anyhow::Result<()> {
+    use bdk_testenv::bitcoincore_rpc::RpcApi;
+    use bdk_testenv::TestEnv;
+    use bitcoin::consensus::encode::serialize_hex;
+    use bitcoin::Network;
+
+    let env = TestEnv::new()?;
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest)?;
+    let address = wallet.reveal_next_address(KeychainKind::External).address;
+    env.mine_blocks(101, None)?;
+    let txid = env.send(&address, Amount::from_sat(100_000))?;
+    let tx = env.rpc_client().get_raw_transaction(&txid, None)?;
+    wallet.insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })?;
+
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(address.script_pubkey())
+        .drain_wallet()
+        .fee_rate(FeeRate::from_sat_per_vb_u32(2));
+    let mut psbt = builder.finish()?;
+    let options = SignOptions {
+        taproot_annex: Some(ANNEX.to_vec()),
+        ..Default::default()
+    };
+    assert!(wallet.sign(&mut psbt, options)?);
+    let tx = psbt.extract_tx()?;
+    assert_eq!(tx.input[0].witness.last(), Some(ANNEX));
+
+    let block: serde_json::Value = env.rpc_client().call(
+        "generateblock",
+        &[
+            serde_json::json!(address.to_string()),
+            serde_json::json!([serialize_hex(&tx)]),
+        ],
+    )?;
+    let hash = block["hash"].as_str().expect("block hash").parse()?;
+    let block = env.rpc_client().get_block(&hash)?;
+    assert!(block.txdata.contains(&tx));
+    Ok(())
+}
+
+#[cfg(feature = "psbt-v2")]
+mod psbt_v2 {
+    use super::*;
+    use bdk_wallet::bitcoin::{absolute, hashes::Hash, transaction, OutPoint, Sequence, Txid};
+    use bdk_wallet::psbt::v2::{PsbtV2, PsbtV2Error};
+
+    type Map<'a> = &'a [(&'a [u8], &'a [u8])];
+
+    /// Serialize the maps of a PSBT, in the order of their pairs
+    fn psbt_bytes(global: Map, inputs: &[Map], outputs: &[Map]) -> Vec<u8> {
+        let mut bytes = b"psbt\xff".to_vec();
+        for map in core::iter::once(&global).chain(inputs).chain(outputs) {
+            for (key, value) in map.iter() {
+                bytes.push(key.len() as u8);
+                bytes.extend(*key);
+                bytes.push(value.len() as u8);
+                bytes.extend(*value);
+            }
+            bytes.push(0x00);
+        }
+        bytes
+    }
+
+    const TXID: [u8; 32] = [0x11; 32];
+    const SCRIPT: &[u8] = &[
+        0x00, 0x14, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+        0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+    ];
+
+    /// A PSBT v2 of a single input and output, with `extra_input` fields on the input, without
+    /// the fields of `GLOBAL`, `INPUT` and `OUTPUT` whose types are in `skip`
+    fn bip370_psbt(extra_input: Map, skip: &[u8]) -> Vec<u8> {
+        let keep = |map: Map<'static>| -> Vec<(&'static [u8], &'static [u8])> {
+            map.iter()
+                .filter(|(key, _)| !skip.contains(&key[0]))
+                .cloned()
+                .collect()
+        };
+        const GLOBAL: Map = &[
+            (&[0x02], &[0x02, 0x00, 0x00, 0x00]),
+            (&[0x04], &[0x01]),
+            (&[0x05], &[0x01]),
+            (&[0xfb], &[0x02, 0x00, 0x00, 0x00]),
+        ];
+        const INPUT: Map = &[(&[0x0e], &TXID), (&[0x0f], &[0x01, 0x00, 0x00, 0x00])];
+        const OUTPUT: Map = &[
+            (&[0x03], &[0xe8, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+            (&[0x04], SCRIPT),
+        ];
+        let mut input = keep(INPUT);
+        input.extend(extra_input);
+        psbt_bytes(&keep(GLOBAL), &[&input], &[&keep(OUTPUT)])
+    }
+
+    #[test]
+    fn test_psbt_v2_deserialize_bip370_fields() {
+        let psbt = PsbtV2::deserialize(&bip370_psbt(&[(&[0x10], &[0xfd, 0xff, 0xff, 0xff])], &[]))
+            .unwrap();
+        assert_eq!(psbt.tx_version, transaction::Version::TWO);
+        assert_eq!(psbt.fallback_lock_time, None);
+        assert_eq!(psbt.inputs.len(), 1);
+        assert_eq!(
+            psbt.inputs[0].previous_output(),
+            OutPoint::new(Txid::from_byte_array(TXID), 1)
+        );
+        assert_eq!(
+            psbt.inputs[0].sequence,
+            Some(Sequence::ENABLE_RBF_NO_LOCKTIME)
+        );
+        assert_eq!(psbt.outputs[0].amount, Amount::from_sat(1_000));
+        assert_eq!(psbt.outputs[0].script_pubkey.as_bytes(), SCRIPT);
+
+        let tx = psbt.unsigned_tx().unwrap();
+        assert_eq!(tx.lock_time, absolute::LockTime::ZERO);
+        assert_eq!(tx.input[0].sequence, Sequence::ENABLE_RBF_NO_LOCKTIME);
+
+        // the fields are written in the order of their keys, like the vector
+        let bytes = bip370_psbt(&[(&[0x10], &[0xfd, 0xff, 0xff, 0xff])], &[]);
+        assert_eq!(psbt.serialize(), bytes);
+        assert_eq!(PsbtV2::from_str(&psbt.to_string()).unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_psbt_v2_deserialize_invalid_bip370_psbts() {
+        let missing = |field_type: u8| PsbtV2::deserialize(&bip370_psbt(&[], &[field_type]));
+        // PSBT_GLOBAL_TX_VERSION is missing
+        assert!(matches!(
+            missing(0x02),
+            Err(PsbtV2Error::MissingField("PSBT_GLOBAL_TX_VERSION"))
+        ));
+        // PSBT_GLOBAL_INPUT_COUNT and PSBT_OUT_AMOUNT share a type
+        assert!(matches!(
+            missing(0x03),
+            Err(PsbtV2Error::MissingField("PSBT_OUT_AMOUNT"))
+        ));
+        assert!(matches!(
+            missing(0x04),
+            Err(PsbtV2Error::MissingField("PSBT_GLOBAL_INPUT_COUNT"))
+        ));
+        assert!(matches!(
+            missing(0x05),
+            Err(PsbtV2Error::MissingField("PSBT_GLOBAL_OUTPUT_COUNT"))
+        ));
+        assert!(matches!(
+            missing(0x0e),
+            Err(PsbtV2Error::MissingField("PSBT_IN_PREVIOUS_TXID"))
+        ));
+        assert!(matches!(
+            missing(0x0f),
+            Err(PsbtV2Error::MissingField("PSBT_IN_OUTPUT_INDEX"))
+        ));
+        // a PSBT without version is a PSBT v0
+        assert!(matches!(
+            missing(0xfb),
+            Err(PsbtV2Error::UnsupportedVersion(0))
+        ));
+
+        // a required time locktime below the height threshold
+        let time = PsbtV2::deserialize(&bip370_psbt(&[(&[0x11], &[0xff, 0x64, 0xcd, 0x1d])], &[]));
+        assert!(matches!(
+            time,
+            Err(PsbtV2Error::InvalidField("PSBT_IN_REQUIRED_TIME_LOCKTIME"))
+        ));
+        // a required height locktime above the height threshold
+        let height =
+            PsbtV2::deserialize(&bip370_psbt(&[(&[0x12], &[0x00, 0x65, 0xcd, 0x1d])], &[]));
+        assert!(matches!(
+            height,
+            Err(PsbtV2Error::InvalidField(
+                "PSBT_IN_REQUIRED_HEIGHT_LOCKTIME"
+            ))
+        ));
+
+        // the unsigned transaction of a PSBT v0
+        let mut bytes = bip370_psbt(&[], &[]);
+        bytes.splice(5..5, [0x01, 0x00, 0x01, 0x00]);
+        assert!(matches!(
+            PsbtV2::deserialize(&bytes),
+            Err(PsbtV2Error::ExcludedField("PSBT_GLOBAL_UNSIGNED_TX"))
+        ));
+
+        // a PSBT v0 with a field of v2
+        let mut psbt = Psbt::from_str(PSBT_STR).unwrap();
+        psbt.inputs[0].unknown.insert(
+            bitcoin::psbt::raw::Key {
+                type_value: 0x10,
+                key: vec![],
+            },
+            vec![0xff; 4],
+        );
+        let psbt = Psbt::deserialize(&psbt.serialize()).unwrap();
+        assert!(matches!(
+            PsbtV2::from_v0(psbt),
+            Err(PsbtV2Error::ExcludedField("PSBT_IN_SEQUENCE"))
+        ));
+    }
+
+    #[test]
+    fn test_psbt_v2_lock_time() {
+        let mut psbt = PsbtV2::deserialize(&bip370_psbt(&[], &[])).unwrap();
+        psbt.inputs.push(psbt.inputs[0].clone());
+        psbt.fallback_lock_time = Some(absolute::LockTime::from_consensus(100));
+        assert_eq!(psbt.lock_time().unwrap().to_consensus_u32(), 100);
+
+        let height = |h| Some(absolute::Height::from_consensus(h).unwrap());
+        let time = |t| Some(absolute::Time::from_consensus(t).unwrap());
+
+        // the greatest of the required heights
+        psbt.inputs[0].required_height_lock_time = height(1_000);
+        psbt.inputs[1].required_height_lock_time = height(2_000);
+        assert_eq!(psbt.lock_time().unwrap().to_consensus_u32(), 2_000);
+
+        // heights are preferred when both types are supported by all the inputs
+        psbt.inputs[0].required_time_lock_time = time(1_700_000_000);
+        psbt.inputs[1].required_time_lock_time = time(1_600_000_000);
+        assert_eq!(psbt.lock_time().unwrap().to_consensus_u32(), 2_000);
+
+        // times when an input doesn't support heights
+        psbt.inputs[1].required_height_lock_time = None;
+        assert_eq!(psbt.lock_time().unwrap().to_consensus_u32(), 1_700_000_000);
+
+        psbt.inputs[0].required_time_lock_time = None;
+        assert!(matches!(
+            psbt.lock_time(),
+            Err(PsbtV2Error::LockTimeConflict)
+        ));
+        assert!(matches!(
+            psbt.to_v0(),
+            Err(PsbtV2Error::LockTimeConflict) | Err(PsbtV2Error::Lossy(_))
+        ));
+    }
+
+    #[test]
+    fn test_psbt_v2_v0_round_trip() {
+        let psbt = Psbt::from_str(PSBT_STR).unwrap();
+        let psbt_v2 = PsbtV2::from_v0(psbt.clone()).unwrap();
+        assert_eq!(psbt_v2.to_v0().unwrap(), psbt);
+
+        let deserialized = PsbtV2::deserialize(&psbt_v2.serialize()).unwrap();
+        assert_eq!(deserialized, psbt_v2);
+        assert_eq!(deserialized.to_v0().unwrap(), psbt);
+
+        // a PSBT v0 isn't a PSBT v2
+        assert!(matches!(
+            PsbtV2::deserialize(&psbt.serialize()),
+            Err(PsbtV2Error::UnsupportedVersion(0))
+        ));
+        // the fields which only exist in v2 are not dropped silently
+        let mut psbt_v2 = psbt_v2;
+        psbt_v2.tx_modifiable = Some(PsbtV2::INPUTS_MODIFIABLE);
+        assert!(matches!(
+            psbt_v2.to_v0(),
+            Err(PsbtV2Error::Lossy("PSBT_GLOBAL_TX_MODIFIABLE"))
+        ));
+    }
+
+    #[test]
+    fn test_psbt_v2_sign_matches_v0() {
+        let (mut wallet, _) = get_funded_wallet_wpkh();
+        let send_to = wallet.peek_address(KeychainKind::External, 0);
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(send_to.script_pubkey(), Amount::from_sat(10_000));
+        let mut psbt_v2 = builder.finish_psbt_v2().unwrap();
+        let mut psbt = psbt_v2.to_v0().unwrap();
+        assert_eq!(psbt_v2.fallback_lock_time, Some(psbt.unsigned_tx.lock_time));
+        for (input, txin) in psbt_v2.inputs.iter().zip(&psbt.unsigned_tx.input) {
+            assert_eq!(input.previous_output(), txin.previous_output);
+            assert_eq!(input.sequence, Some(txin.sequence));
+        }
+
+        let sign_options = SignOptions {
+            try_finalize: false,
+            ..Default::default()
+        };
+        wallet.sign(&mut psbt, sign_options.clone()).unwrap();
+        wallet.sign_psbt_v2(&mut psbt_v2, sign_options).unwrap();
+        assert!(!psbt.inputs[0].partial_sigs.is_empty());
+        assert_eq!(
+            psbt_v2.inputs[0].input.partial_sigs,
+            psbt.inputs[0].partial_sigs
+        );
+        assert_eq!(psbt_v2.to_v0().unwrap(), psbt);
+
+        // and through serialization, with finalization
+        let mut psbt_v2 = PsbtV2::deserialize(&psbt_v2.serialize()).unwrap();
+        assert!(wallet
+            .sign_psbt_v2(&mut psbt_v2, SignOptions::default())
+            .unwrap());
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        assert_eq!(
+            psbt_v2.to_v0().unwrap().extract_tx().unwrap(),
+            psbt.extract_tx().unwrap()
+        );
+    }
+}
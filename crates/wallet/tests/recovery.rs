@@ -0,0 +1,290 @@
+#![cfg(feature = "recovery")]
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+use bdk_chain::spk_client::{
+    BackendError, BackendOptions, FullScanRequest, FullScanResult, SyncBackend, SyncRequest,
+    SyncResult,
+};
+use bdk_chain::TxGraph;
+use bdk_wallet::bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+use bdk_wallet::bitcoin::{absolute, transaction, Amount, Network, ScriptBuf, Transaction, TxOut};
+use bdk_wallet::wallet::recovery::{
+    scan_templates, RecoveredAccount, RecoveryError, RecoveryKeychain, RecoveryOptions,
+    RecoveryTemplate,
+};
+use bdk_wallet::{KeychainKind, Wallet};
+use core::str::FromStr;
+
+const TPRV: &str = "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS";
+
+/// A chain source with transactions paying its script pubkeys, whose full scans stop after the
+/// stop gap like the ones of the other chain sources
+#[derive(Default)]
+struct ScanBackend {
+    txs: BTreeMap<ScriptBuf, Vec<Transaction>>,
+    requested: Cell<usize>,
+}
+
+impl ScanBackend {
+    fn pay(&mut self, address: ScriptBuf, value: Amount) {
+        let tx = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: absolute::LockTime::from_consensus(self.txs.len() as u32),
+            input: vec![],
+            output: vec![TxOut {
+                script_pubkey: address.clone(),
+                value,
+            }],
+        };
+        self.txs.entry(address).or_default().push(tx);
+    }
+}
+
+impl SyncBackend<RecoveryKeychain> for ScanBackend {
+    fn full_scan(
+        &self,
+        request: FullScanRequest<RecoveryKeychain>,
+        options: BackendOptions,
+    ) -> Result<FullScanResult<RecoveryKeychain>, BackendError> {
+        let mut graph_update = TxGraph::default();
+        let mut last_active_indices = BTreeMap::new();
+        for (keychain, spks) in request.spks_by_keychain {
+            let stop_gap = request
+                .stop_gaps
+                .get(&keychain)
+                .copied()
+                .unwrap_or(options.stop_gap);
+            let mut gap = 0;
+            for (index, spk) in spks {
+                self.requested.set(self.requested.get() + 1);
+                match self.txs.get(&spk) {
+                    Some(txs) => {
+                        for tx in txs {
+                            let _ = graph_update.insert_tx(tx.clone());
+                        }
+                        last_active_indices.insert(keychain, index);
+                        gap = 0;
+                    }
+                    None => gap += 1,
+                }
+                if gap >= stop_gap {
+                    break;
+                }
+            }
+        }
+        Ok(FullScanResult {
+            graph_update,
+            chain_update: request.chain_tip,
+            last_active_indices,
+            scan_stats: BTreeMap::new(),
+        })
+    }
+
+    fn sync(
+        &self,
+        _request: SyncRequest,
+        _options: BackendOptions,
+    ) -> Result<SyncResult, BackendError> {
+        unimplemented!("the recovery only runs full scans")
+    }
+}
+
+/// The wallet of the descriptor `template` at `m/purpose'/1'/account'` of [`TPRV`]
+fn template_wallet(template: &str, purpose: u32, account: u32) -> Wallet {
+    let descriptor = |change| {
+        let key = format!("{}/{}'/1'/{}'/{}/*", TPRV, purpose, account, change);
+        template.replace("KEY", &key)
+    };
+    Wallet::new(&descriptor(0), &descriptor(1), Network::Regtest).unwrap()
+}
+
+fn spk(wallet: &Wallet, keychain: KeychainKind, index: u32) -> ScriptBuf {
+    wallet.peek_address(keychain, index).script_pubkey()
+}
+
+#[test]
+fn test_scan_templates() {
+    let bip84_0 = template_wallet("wpkh(KEY)", 84, 0);
+    let bip86_1 = template_wallet("tr(KEY)", 86, 1);
+    let bip44_0 = template_wallet("pkh(KEY)", 44, 0);
+    let mut backend = ScanBackend::default();
+    backend.pay(
+        spk(&bip84_0, KeychainKind::External, 0),
+        Amount::from_sat(20_000),
+    );
+    backend.pay(
+        spk(&bip84_0, KeychainKind::External, 3),
+        Amount::from_sat(30_000),
+    );
+    backend.pay(
+        spk(&bip84_0, KeychainKind::Internal, 1),
+        Amount::from_sat(5_000),
+    );
+    backend.pay(
+        spk(&bip86_1, KeychainKind::External, 4),
+        Amount::from_sat(70_000),
+    );
+    // beyond the stop gap
+    backend.pay(
+        spk(&bip44_0, KeychainKind::External, 5),
+        Amount::from_sat(1_000),
+    );
+
+    let options = RecoveryOptions::default();
+    let accounts = scan_templates(
+        &backend,
+        Xpriv::from_str(TPRV).unwrap(),
+        Network::Regtest,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(
+        accounts
+            .iter()
+            .map(|account| (
+                account.template,
+                account.account,
+                account.last_active_external,
+                account.last_active_internal,
+                account.received,
+                account.tx_count,
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                RecoveryTemplate::Bip84,
+                0,
+                Some(3),
+                Some(1),
+                Amount::from_sat(55_000),
+                3
+            ),
+            (
+                RecoveryTemplate::Bip86,
+                1,
+                Some(4),
+                None,
+                Amount::from_sat(70_000),
+                1
+            ),
+        ]
+    );
+
+    // 4 templates, 3 accounts, 2 keychains: the stop gap of the 21 inactive keychains, and the
+    // script pubkeys up to the last active index of the active ones followed by the stop gap
+    assert_eq!(
+        backend.requested.get(),
+        21 * 5 + (4 + 5) + (2 + 5) + (5 + 5)
+    );
+
+    // the descriptors recover the wallets
+    for (account, wallet) in accounts.iter().zip([&bip84_0, &bip86_1]) {
+        let recovered = Wallet::new(
+            &account.descriptor,
+            &account.change_descriptor,
+            Network::Regtest,
+        )
+        .unwrap();
+        for keychain in [KeychainKind::External, KeychainKind::Internal] {
+            assert_eq!(spk(&recovered, keychain, 2), spk(wallet, keychain, 2));
+        }
+        assert!(account.descriptor.contains("tprv"));
+        assert!(account.descriptor.contains('#'));
+    }
+
+    // a larger stop gap finds the BIP-44 account
+    let options = RecoveryOptions {
+        templates: vec![RecoveryTemplate::Bip44],
+        accounts: 1,
+        stop_gap: 10,
+        ..Default::default()
+    };
+    let accounts = scan_templates(
+        &backend,
+        Xpriv::from_str(TPRV).unwrap(),
+        Network::Regtest,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].template, RecoveryTemplate::Bip44);
+    assert_eq!(accounts[0].last_active_external, Some(5));
+    assert_eq!(accounts[0].received, Amount::from_sat(1_000));
+}
+
+#[test]
+fn test_scan_templates_xpub() {
+    let secp = Secp256k1::new();
+    let account_xprv = Xpriv::from_str(TPRV)
+        .unwrap()
+        .derive_priv(&secp, &DerivationPath::from_str("m/86'/1'/0'").unwrap())
+        .unwrap();
+    let xpub = Xpub::from_priv(&secp, &account_xprv);
+    let wallet = template_wallet("tr(KEY)", 86, 0);
+    let mut backend = ScanBackend::default();
+    backend.pay(
+        spk(&wallet, KeychainKind::Internal, 2),
+        Amount::from_sat(8_000),
+    );
+
+    let accounts = scan_templates(
+        &backend,
+        xpub,
+        Network::Regtest,
+        &RecoveryOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(accounts.len(), 1);
+    let RecoveredAccount {
+        template,
+        account,
+        last_active_external,
+        last_active_internal,
+        received,
+        ref descriptor,
+        ..
+    } = accounts[0];
+    assert_eq!(template, RecoveryTemplate::Bip86);
+    assert_eq!(account, 0);
+    assert_eq!(last_active_external, None);
+    assert_eq!(last_active_internal, Some(2));
+    assert_eq!(received, Amount::from_sat(8_000));
+    assert!(descriptor.starts_with(&format!("tr({}/0/*)#", xpub)));
+    // the accounts aren't derived from an account key
+    assert_eq!(backend.requested.get(), 7 * 5 + (3 + 5));
+}
+
+#[test]
+fn test_scan_templates_backend_error() {
+    struct FailingBackend;
+    impl SyncBackend<RecoveryKeychain> for FailingBackend {
+        fn full_scan(
+            &self,
+            _request: FullScanRequest<RecoveryKeychain>,
+            _options: BackendOptions,
+        ) -> Result<FullScanResult<RecoveryKeychain>, BackendError> {
+            Err("connection refused".into())
+        }
+
+        fn sync(
+            &self,
+            _request: SyncRequest,
+            _options: BackendOptions,
+        ) -> Result<SyncResult, BackendError> {
+            unimplemented!()
+        }
+    }
+
+    let err = scan_templates(
+        &FailingBackend,
+        Xpriv::from_str(TPRV).unwrap(),
+        Network::Regtest,
+        &RecoveryOptions::default(),
+    )
+    .unwrap_err();
+    assert!(matches!(err, RecoveryError::Backend(_)));
+    assert!(err.to_string().contains("connection refused"));
+}
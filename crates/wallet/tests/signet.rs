@@ -0,0 +1,41 @@
+use bdk_testenv::TestEnv;
+use bdk_wallet::wallet::{ChangeSet, NetworkParams, Update};
+use bdk_wallet::{KeychainKind, Wallet};
+use bitcoin::Network;
+
+mod common;
+use common::*;
+
+const DB_MAGIC: &[u8] = &[0x21, 0x24, 0x48];
+
+/// A wallet on a custom signet is created, persisted and reloaded for the genesis block of the
+/// node, and the chain of the node connects to it.
+#[test]
+fn wallet_on_custom_signet() -> anyhow::Result<()> {
+    let env = TestEnv::new_signet("51")?;
+    let params = NetworkParams::new(Network::Signet).with_genesis_hash(env.genesis_hash()?);
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let temp_dir = tempfile::tempdir()?;
+    let file_path = temp_dir.path().join("signet.db");
+
+    let mut wallet = Wallet::new_with_network_params(desc, change_desc, params)?;
+    let address = wallet.reveal_next_address(KeychainKind::External).address;
+    wallet.apply_update(Update {
+        chain: Some(env.make_checkpoint_tip()),
+        ..Default::default()
+    })?;
+    {
+        let mut db = bdk_file_store::Store::<ChangeSet>::create_new(DB_MAGIC, &file_path)?;
+        db.append_changeset(&wallet.take_staged().expect("staged changes"))?;
+    }
+
+    let changeset = bdk_file_store::Store::<ChangeSet>::open(DB_MAGIC, &file_path)?
+        .aggregate_changesets()?
+        .expect("persisted changes");
+    let wallet =
+        Wallet::new_or_load_with_network_params(desc, change_desc, Some(changeset), params)?;
+    assert_eq!(wallet.network_params(), params);
+    assert_eq!(wallet.local_chain().tip(), env.make_checkpoint_tip());
+    assert_eq!(wallet.parse_address(&address.to_string())?, address);
+    Ok(())
+}
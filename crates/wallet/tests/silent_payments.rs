@@ -0,0 +1,464 @@
+#![cfg(feature = "silent-payments")]
+
+use assert_matches::assert_matches;
+use bdk_chain::ConfirmationTime;
+use bdk_wallet::bitcoin::bech32::primitives::iter::{ByteIterExt, Fe32IterExt};
+use bdk_wallet::bitcoin::bech32::{Bech32m, Fe32, Hrp};
+use bdk_wallet::bitcoin::hashes::{sha256, Hash, HashEngine};
+use bdk_wallet::bitcoin::key::{Parity, Secp256k1};
+use bdk_wallet::bitcoin::secp256k1::{PublicKey, Scalar, SecretKey, XOnlyPublicKey};
+use bdk_wallet::bitcoin::{
+    absolute, consensus, transaction, Amount, Network, OutPoint, Psbt, Transaction, TxOut,
+};
+use bdk_wallet::wallet::error::CreateTxError;
+use bdk_wallet::wallet::silent_payments::{
+    derive_output_keys, SilentPaymentAddress, SilentPaymentAddressError, SilentPaymentError,
+};
+use bdk_wallet::{KeychainKind, SignOptions, Wallet};
+use core::str::FromStr;
+mod common;
+use common::*;
+
+/// The address of the first sending test vectors of BIP352
+const BIP352_ADDRESS: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+
+/// The unspendable internal key `H` of BIP341
+const NUMS_INTERNAL_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// The keys of a receiver: a scan key and spend keys sharing it
+struct Receiver {
+    scan_key: SecretKey,
+    spend_keys: Vec<PublicKey>,
+}
+
+impl Receiver {
+    fn new(spend_keys: usize) -> Self {
+        let secp = Secp256k1::new();
+        Self {
+            scan_key: SecretKey::from_slice(&[0x11; 32]).unwrap(),
+            spend_keys: (0..spend_keys)
+                .map(|i| {
+                    let key = SecretKey::from_slice(&[0x22 + i as u8; 32]).unwrap();
+                    PublicKey::from_secret_key(&secp, &key)
+                })
+                .collect(),
+        }
+    }
+
+    fn address(&self, index: usize) -> SilentPaymentAddress {
+        let secp = Secp256k1::new();
+        SilentPaymentAddress::new(
+            PublicKey::from_secret_key(&secp, &self.scan_key),
+            self.spend_keys[index],
+            Network::Regtest,
+        )
+    }
+
+    /// Scan `tx` like a receiver would, from the public keys of the inputs signed in `psbt`
+    ///
+    /// Returns the index of the spend key and of the output of the outputs found, by `k`.
+    fn scan(&self, psbt: &Psbt, tx: &Transaction) -> Vec<(usize, usize)> {
+        let secp = Secp256k1::new();
+        let input_keys = tx
+            .input
+            .iter()
+            .zip(&psbt.inputs)
+            .filter_map(|(txin, input)| {
+                let script_pubkey = &input.witness_utxo.as_ref().unwrap().script_pubkey;
+                if script_pubkey.is_p2tr() {
+                    // a script path spend with the NUMS internal key is skipped
+                    let control_block = txin.witness.last().unwrap();
+                    if txin.witness.len() > 1 && control_block[1..33] == NUMS_INTERNAL_KEY {
+                        return None;
+                    }
+                    Some(
+                        XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+                            .unwrap()
+                            .public_key(Parity::Even),
+                    )
+                } else {
+                    assert!(script_pubkey.is_p2wpkh());
+                    Some(PublicKey::from_slice(txin.witness.nth(1).unwrap()).unwrap())
+                }
+            })
+            .collect::<Vec<_>>();
+        let input_key = PublicKey::combine_keys(&input_keys.iter().collect::<Vec<_>>()).unwrap();
+        let smallest_outpoint = tx
+            .input
+            .iter()
+            .map(|txin| consensus::serialize(&txin.previous_output))
+            .min()
+            .unwrap();
+        let input_hash = tagged_hash(
+            "BIP0352/Inputs",
+            &[&smallest_outpoint, &input_key.serialize()],
+        );
+        let shared_secret = input_key
+            .mul_tweak(&secp, &Scalar::from_be_bytes(input_hash).unwrap())
+            .unwrap()
+            .mul_tweak(&secp, &Scalar::from(self.scan_key))
+            .unwrap();
+
+        let mut found = Vec::new();
+        for k in 0u32.. {
+            let tweak = tagged_hash(
+                "BIP0352/SharedSecret",
+                &[&shared_secret.serialize(), &k.to_be_bytes()],
+            );
+            let tweak = Scalar::from_be_bytes(tweak).unwrap();
+            let output = self.spend_keys.iter().enumerate().find_map(|(i, spend)| {
+                let key = spend.add_exp_tweak(&secp, &tweak).unwrap();
+                let script = bdk_wallet::bitcoin::ScriptBuf::new_p2tr_tweaked(
+                    bdk_wallet::bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(
+                        key.x_only_public_key().0,
+                    ),
+                );
+                tx.output
+                    .iter()
+                    .position(|txout| txout.script_pubkey == script)
+                    .map(|vout| (i, vout))
+            });
+            match output {
+                Some(output) => found.push(output),
+                None => break,
+            }
+        }
+        found
+    }
+}
+
+/// Pay the addresses of `receiver` from `wallet`, returning the signed PSBT and transaction
+fn pay(wallet: &mut Wallet, recipients: &[SilentPaymentAddress]) -> (Psbt, Transaction) {
+    let other = wallet
+        .peek_address(KeychainKind::External, 5)
+        .script_pubkey();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(other, Amount::from_sat(5_000));
+    for address in recipients {
+        builder
+            .add_silent_payment_recipient(*address, Amount::from_sat(10_000))
+            .unwrap();
+    }
+    let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.clone().extract_tx().unwrap();
+    (psbt, tx)
+}
+
+#[test]
+fn test_silent_payment_bip352_vectors() {
+    let secp = Secp256k1::new();
+    let address = SilentPaymentAddress::from_str(BIP352_ADDRESS).unwrap();
+    let input_keys = [
+        "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+        "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+    ]
+    .map(|key| SecretKey::from_str(key).unwrap());
+    let vectors = [
+        // Simple send: two inputs
+        (
+            [
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:0",
+                "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d:0",
+            ],
+            "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+        ),
+        // Simple send: two inputs from the same transaction
+        (
+            [
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:3",
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:7",
+            ],
+            "79e71baa2ba3fc66396de3a04f168c7bf24d6870ec88ca877754790c1db357b6",
+        ),
+    ];
+    for (outpoints, expected) in vectors {
+        let mut outpoints = outpoints.map(|outpoint| OutPoint::from_str(outpoint).unwrap());
+        let expected = XOnlyPublicKey::from_str(expected).unwrap();
+        assert_eq!(
+            derive_output_keys(&outpoints, &input_keys, &[address], &secp),
+            Ok(vec![expected])
+        );
+        // the order of the inputs doesn't matter
+        outpoints.reverse();
+        let mut input_keys = input_keys;
+        input_keys.reverse();
+        assert_eq!(
+            derive_output_keys(&outpoints, &input_keys, &[address], &secp),
+            Ok(vec![expected])
+        );
+    }
+    assert_eq!(
+        derive_output_keys(&[], &[], &[address], &secp),
+        Err(SilentPaymentError::NoEligibleInputs)
+    );
+}
+
+#[test]
+fn test_silent_payment_address() {
+    let address = SilentPaymentAddress::from_str(BIP352_ADDRESS).unwrap();
+    assert_eq!(
+        address.scan_key.to_string(),
+        "0220bcfac5b99e04ad1a06ddfb016ee13582609d60b6291e98d01a9bc9a16c96d4"
+    );
+    assert_eq!(
+        address.spend_key.to_string(),
+        "025cc9856d6f8375350e123978daac200c260cb5b5ae83106cab90484dcd8fcf36"
+    );
+    assert_eq!(address.to_string(), BIP352_ADDRESS);
+    assert_eq!(
+        SilentPaymentAddress::from_str(&BIP352_ADDRESS.to_uppercase()),
+        Ok(address)
+    );
+    assert!(address.is_valid_for_network(Network::Bitcoin));
+    assert!(!address.is_valid_for_network(Network::Testnet));
+
+    for (network, hrp, valid) in [
+        (
+            Network::Testnet,
+            "tsp1q",
+            &[Network::Testnet, Network::Signet, Network::Regtest][..],
+        ),
+        (
+            Network::Signet,
+            "tsp1q",
+            &[Network::Testnet, Network::Signet, Network::Regtest],
+        ),
+        (Network::Regtest, "sprt1q", &[Network::Regtest]),
+    ] {
+        let test_address = SilentPaymentAddress::new(address.scan_key, address.spend_key, network);
+        let string = test_address.to_string();
+        assert!(string.starts_with(hrp));
+        assert_eq!(SilentPaymentAddress::from_str(&string), Ok(test_address));
+        for other in [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            assert_eq!(
+                test_address.is_valid_for_network(other),
+                valid.contains(&other)
+            );
+        }
+    }
+
+    let encode = |hrp: &str, version: Fe32, payload: &[u8]| -> String {
+        payload
+            .iter()
+            .copied()
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&Hrp::parse(hrp).unwrap())
+            .with_witness_version(version)
+            .chars()
+            .collect()
+    };
+    let keys = [address.scan_key.serialize(), address.spend_key.serialize()].concat();
+    assert_eq!(
+        SilentPaymentAddress::from_str(&encode("sp", Fe32::Q, &keys)),
+        Ok(address)
+    );
+    // later versions may append data
+    let longer = [&keys[..], &[0xab; 10]].concat();
+    assert_eq!(
+        SilentPaymentAddress::from_str(&encode("sp", Fe32::P, &longer)),
+        Ok(address)
+    );
+    assert_eq!(
+        SilentPaymentAddress::from_str(&encode("sp", Fe32::try_from(30u8).unwrap(), &longer)),
+        Ok(address)
+    );
+    assert_eq!(
+        SilentPaymentAddress::from_str(&encode("sp", Fe32::Q, &longer)),
+        Err(SilentPaymentAddressError::InvalidLength(76))
+    );
+    assert_eq!(
+        SilentPaymentAddress::from_str(&encode("sp", Fe32::P, &keys[..65])),
+        Err(SilentPaymentAddressError::InvalidLength(65))
+    );
+    assert_eq!(
+        SilentPaymentAddress::from_str(&encode("sp", Fe32::L, &keys)),
+        Err(SilentPaymentAddressError::InvalidVersion(31))
+    );
+    assert_eq!(
+        SilentPaymentAddress::from_str(&encode("bc", Fe32::Q, &keys)),
+        Err(SilentPaymentAddressError::InvalidHrp("bc".to_string()))
+    );
+    let mut invalid_key = keys.clone();
+    invalid_key[0] = 0x05;
+    assert_matches!(
+        SilentPaymentAddress::from_str(&encode("sp", Fe32::Q, &invalid_key)),
+        Err(SilentPaymentAddressError::InvalidKey(_))
+    );
+    // a typo breaks the checksum
+    let typo = BIP352_ADDRESS.replace("sp1qqg", "sp1qqq");
+    assert_matches!(
+        SilentPaymentAddress::from_str(&typo),
+        Err(SilentPaymentAddressError::Bech32(_))
+    );
+}
+
+#[test]
+fn test_silent_payment_send_and_scan() {
+    let receiver = Receiver::new(2);
+    let recipients = [
+        receiver.address(0),
+        receiver.address(0),
+        receiver.address(1),
+    ];
+    for desc in [
+        get_test_wpkh(),
+        get_test_tr_single_sig_xprv(),
+        get_test_tr_with_taptree_xprv(),
+    ] {
+        let (mut wallet, _) = get_funded_wallet(desc);
+        let (psbt, tx) = pay(&mut wallet, &recipients);
+        let found = receiver.scan(&psbt, &tx);
+        // the outputs paying the same scan key are numbered
+        assert_eq!(
+            found.iter().map(|(spend, _)| *spend).collect::<Vec<_>>(),
+            vec![0, 0, 1],
+            "{}",
+            desc
+        );
+        for (_, vout) in found {
+            assert_eq!(tx.output[vout].value, Amount::from_sat(10_000));
+        }
+    }
+}
+
+#[test]
+fn test_silent_payment_several_inputs() {
+    let receiver = Receiver::new(1);
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    // spend the whole wallet, through several inputs of both keychains
+    let received = wallet.reveal_next_address(KeychainKind::Internal).address;
+    let tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: received.script_pubkey(),
+            value: Amount::from_sat(20_000),
+        }],
+    };
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_silent_payment_recipient(receiver.address(0), Amount::from_sat(60_000))
+        .unwrap();
+    let mut psbt = builder.finish().unwrap();
+    assert!(psbt.inputs.len() > 1);
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.clone().extract_tx().unwrap();
+    assert_eq!(receiver.scan(&psbt, &tx).len(), 1);
+}
+
+/// An input of a taproot output with the NUMS internal key is spent through a script, it is
+/// skipped rather than failing for the missing key.
+#[test]
+fn test_silent_payment_skips_nums_internal_key() {
+    let receiver = Receiver::new(1);
+    let (mut wallet, _) = get_funded_wallet_with_change(
+        "tr(50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0,pk(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/0/*))",
+        "wpkh(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/1/*)",
+    );
+    let received = wallet.reveal_next_address(KeychainKind::Internal).address;
+    let tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: received.script_pubkey(),
+            value: Amount::from_sat(20_000),
+        }],
+    };
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // only the P2WPKH input derives the output
+    let mut builder = wallet.build_tx();
+    builder
+        .add_silent_payment_recipient(receiver.address(0), Amount::from_sat(60_000))
+        .unwrap();
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.inputs.len(), 2);
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.clone().extract_tx().unwrap();
+    assert_eq!(receiver.scan(&psbt, &tx).len(), 1);
+
+    // with only the taproot input, no input is eligible
+    let taproot_utxo = wallet
+        .list_unspent()
+        .find(|utxo| utxo.keychain == KeychainKind::External)
+        .unwrap()
+        .outpoint;
+    let mut builder = wallet.build_tx();
+    builder
+        .add_silent_payment_recipient(receiver.address(0), Amount::from_sat(10_000))
+        .unwrap()
+        .add_utxo(taproot_utxo)
+        .unwrap()
+        .manually_selected_only();
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::SilentPayment(
+            SilentPaymentError::NoEligibleInputs
+        ))
+    );
+}
+
+#[test]
+fn test_silent_payment_errors() {
+    let receiver = Receiver::new(1);
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+
+    let mut builder = wallet.build_tx();
+    let mainnet_address = SilentPaymentAddress::from_str(BIP352_ADDRESS).unwrap();
+    assert_matches!(
+        builder.add_silent_payment_recipient(mainnet_address, Amount::from_sat(10_000)),
+        Err(SilentPaymentError::WrongNetwork {
+            expected: Network::Regtest
+        })
+    );
+    builder
+        .add_silent_payment_recipient(receiver.address(0), Amount::from_sat(100))
+        .unwrap();
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::OutputBelowDustLimit(0))
+    );
+
+    let (mut watch_only, _) = get_funded_wallet_with_change(
+        "wpkh([3c31d632/84'/1'/0']tpubDCYwFkks2cg78N7eoYbBatsFEGje8vW8arSKW4rLwD1AU1s9KJMDRHE32JkvYERuiFjArrsH7qpWSpJATed5ShZbG9KsskA5Rmi6NSYgYN2/0/*)",
+        "wpkh([3c31d632/84'/1'/0']tpubDCYwFkks2cg78N7eoYbBatsFEGje8vW8arSKW4rLwD1AU1s9KJMDRHE32JkvYERuiFjArrsH7qpWSpJATed5ShZbG9KsskA5Rmi6NSYgYN2/1/*)",
+    );
+    let mut builder = watch_only.build_tx();
+    builder
+        .add_silent_payment_recipient(receiver.address(0), Amount::from_sat(10_000))
+        .unwrap();
+    let err = builder.finish().unwrap_err();
+    assert_matches!(
+        err,
+        CreateTxError::SilentPayment(SilentPaymentError::WatchOnly)
+    );
+    assert!(err.to_string().contains("private keys of the inputs"));
+}
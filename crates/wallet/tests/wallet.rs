@@ -1,29 +1,64 @@
+use std::future::Future;
 use std::path::Path;
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
 
 use assert_matches::assert_matches;
-use bdk_chain::collections::BTreeMap;
-use bdk_chain::COINBASE_MATURITY;
-use bdk_chain::{BlockId, ConfirmationTime};
+use bdk_chain::collections::{BTreeMap, BTreeSet};
+use bdk_chain::{Append, DescriptorExt, COINBASE_MATURITY};
+use bdk_chain::{BlockId, ConfirmationTime, ConfirmationTimeHeightAnchor, TxGraph};
 use bdk_sqlite::rusqlite::Connection;
 use bdk_wallet::descriptor::{calc_checksum, DescriptorError, IntoWalletDescriptor};
 use bdk_wallet::psbt::PsbtUtils;
-use bdk_wallet::signer::{SignOptions, SignerError};
-use bdk_wallet::wallet::coin_selection::{self, LargestFirstCoinSelection};
-use bdk_wallet::wallet::error::CreateTxError;
-use bdk_wallet::wallet::tx_builder::AddForeignUtxoError;
-use bdk_wallet::wallet::{AddressInfo, Balance, ChangeSet, NewError, Wallet};
-use bdk_wallet::KeychainKind;
-use bitcoin::hashes::Hash;
+use bdk_wallet::signer::{FeeAdjustment, SignOptions, SignerError};
+use bdk_wallet::wallet::analyze::{InvalidSignature, SignatureIssue, TimelockObstacle};
+use bdk_wallet::wallet::coin_selection::{
+    self, decide_change, CoinSelectionAlgorithm, CoinSelectionResult, Excess,
+    LargestFirstCoinSelection, SelectionPreset,
+};
+use bdk_wallet::wallet::error::{
+    AddKeychainError, BuildChainError, BuildCpfpError, BuildFeeBumpError, BuildSweepError,
+    CombineError, CreateTxError,
+};
+use bdk_wallet::wallet::events::WalletEvent;
+use bdk_wallet::wallet::labels::{LabelError, LabelRef, SkipReason};
+use bdk_wallet::wallet::persist::kv::{KvPersister, KvPersisterError, KvStore, MemoryKvStore};
+use bdk_wallet::wallet::persist::{
+    self, AsyncWalletPersister, FutureResult, SyncPersister, WalletPersister,
+};
+use bdk_wallet::wallet::tx_analysis::{
+    input_weights, InputWeightBreakdown, SpendPath, WalletInputWeight,
+};
+use bdk_wallet::wallet::tx_builder::{
+    AddForeignUtxoError, AddUtxoError, ParamError, RecipientError,
+};
+use bdk_wallet::wallet::wallet_policy::{WalletPolicy, WalletPolicyError};
+use bdk_wallet::wallet::{
+    dust_value, AddressInfo, ApplyBlocksError, ApplyBundleError, Balance, BlockTimeOrHeight,
+    BroadcastOutcome, BumpCandidate, ChangeAddressPolicy, ChangeAddressPolicyError, ChangeSet,
+    FeeLimits, FeeRateCheckError, FingerprintComponent, FingerprintMismatch, GapStatus,
+    HealthReport, InputSignatures, InvoiceFilter, InvoiceState, LoadError, LoadMismatch,
+    NetworkParams, NewError, NewOrLoadError, RequestBudget, ReusedScript, RevealGuardError,
+    ScriptType, StateFingerprint, TimeOrHeightWindow, TxApplied, TxBuilderSpec, UnconfirmedTx,
+    Update, UtxoStats, VerifyError, VerifyOptions, Wallet, WalletUpdateBundle, WitnessContext,
+    WitnessProvider, DEFAULT_DUST_RELAY_FEERATE, DEFAULT_RESERVATION_TTL,
+};
+use bdk_wallet::{KeychainKind, KeychainLabel, LocalOutput, Utxo, WeightedUtxo};
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::key::Secp256k1;
 use bitcoin::psbt;
 use bitcoin::script::PushBytesBuf;
 use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
-use bitcoin::taproot::TapNodeHash;
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash, TapNodeHash, TaprootBuilder};
 use bitcoin::{
-    absolute, transaction, Address, Amount, BlockHash, FeeRate, Network, OutPoint, ScriptBuf,
-    Sequence, Transaction, TxIn, TxOut, Txid, Weight,
+    absolute, transaction, Address, Amount, BlockHash, FeeRate, Network, OutPoint, Script,
+    ScriptBuf, Sequence, SignedAmount, Transaction, TxIn, TxOut, Txid, Weight, Witness,
+    XOnlyPublicKey,
 };
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
 
 mod common;
 use common::*;
@@ -69,236 +104,177 @@ const P2WPKH_FAKE_WITNESS_SIZE: usize = 106;
 
 const DB_MAGIC: &[u8] = &[0x21, 0x24, 0x48];
 
-#[test]
-fn load_recovers_wallet() -> anyhow::Result<()> {
-    fn run<Db, New, Recover, Read, Write>(
-        filename: &str,
-        create_new: New,
-        recover: Recover,
-        read: Read,
-        write: Write,
-    ) -> anyhow::Result<()>
-    where
-        New: Fn(&Path) -> anyhow::Result<Db>,
-        Recover: Fn(&Path) -> anyhow::Result<Db>,
-        Read: Fn(&mut Db) -> anyhow::Result<Option<ChangeSet>>,
-        Write: Fn(&mut Db, &ChangeSet) -> anyhow::Result<()>,
-    {
-        let temp_dir = tempfile::tempdir().expect("must create tempdir");
-        let file_path = temp_dir.path().join(filename);
-        let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+/// A [`WalletPersister`] over a [`bdk_file_store::Store`]
+struct FileStorePersister(bdk_file_store::Store<ChangeSet>);
 
-        // create new wallet
-        let wallet_spk_index = {
-            let mut wallet =
-                Wallet::new(desc, change_desc, Network::Testnet).expect("must init wallet");
+impl WalletPersister for FileStorePersister {
+    type Error = anyhow::Error;
 
-            wallet.reveal_next_address(KeychainKind::External);
+    fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+        Ok(self.0.aggregate_changesets()?)
+    }
 
-            // persist new wallet changes
-            let mut db = create_new(&file_path).expect("must create db");
-            if let Some(changeset) = wallet.take_staged() {
-                write(&mut db, &changeset)?;
-            }
-            wallet.spk_index().clone()
-        };
+    fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+        Ok(self.0.append_changeset(changeset)?)
+    }
+}
 
-        // recover wallet
-        {
-            // load persisted wallet changes
-            let db = &mut recover(&file_path).expect("must recover db");
-            let changeset = read(db).expect("must recover wallet").expect("changeset");
+/// A [`WalletPersister`] over a [`bdk_sqlite::Store`]
+struct SqlitePersister(bdk_sqlite::Store<KeychainKind, ConfirmationTimeHeightAnchor>);
 
-            let wallet = Wallet::load_from_changeset(changeset).expect("must recover wallet");
-            assert_eq!(wallet.network(), Network::Testnet);
-            assert_eq!(
-                wallet.spk_index().keychains().collect::<Vec<_>>(),
-                wallet_spk_index.keychains().collect::<Vec<_>>()
-            );
-            assert_eq!(
-                wallet.spk_index().last_revealed_indices(),
-                wallet_spk_index.last_revealed_indices()
-            );
-            let secp = Secp256k1::new();
-            assert_eq!(
-                *wallet.get_descriptor_for_keychain(KeychainKind::External),
-                desc.into_wallet_descriptor(&secp, wallet.network())
-                    .unwrap()
-                    .0
-            );
-        }
+impl WalletPersister for SqlitePersister {
+    type Error = bdk_sqlite::Error;
 
-        Ok(())
+    fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+        self.0.read()
     }
 
-    run(
-        "store.db",
-        |path| Ok(bdk_file_store::Store::create_new(DB_MAGIC, path)?),
-        |path| Ok(bdk_file_store::Store::open(DB_MAGIC, path)?),
-        |db| Ok(bdk_file_store::Store::aggregate_changesets(db)?),
-        |db, changeset| Ok(bdk_file_store::Store::append_changeset(db, changeset)?),
-    )?;
-    run(
-        "store.sqlite",
-        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
-        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
-        |db| Ok(bdk_sqlite::Store::read(db)?),
-        |db, changeset| Ok(bdk_sqlite::Store::write(db, changeset)?),
-    )?;
+    fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+        self.0.write(changeset)
+    }
+}
 
-    Ok(())
+#[test]
+fn file_store_passes_persist_testsuite() {
+    bdk_persist_testsuite::run(|| {
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join("store.db");
+        move || {
+            let _temp_dir = &temp_dir;
+            FileStorePersister(
+                bdk_file_store::Store::open_or_create_new(DB_MAGIC, &file_path)
+                    .expect("must open db"),
+            )
+        }
+    });
 }
 
 #[test]
-fn new_or_load() -> anyhow::Result<()> {
-    fn run<Db, NewOrRecover, Read, Write>(
-        filename: &str,
-        new_or_load: NewOrRecover,
-        read: Read,
-        write: Write,
-    ) -> anyhow::Result<()>
-    where
-        NewOrRecover: Fn(&Path) -> anyhow::Result<Db>,
-        Read: Fn(&mut Db) -> anyhow::Result<Option<ChangeSet>>,
-        Write: Fn(&mut Db, &ChangeSet) -> anyhow::Result<()>,
-    {
+fn sqlite_passes_persist_testsuite() {
+    bdk_persist_testsuite::run(|| {
         let temp_dir = tempfile::tempdir().expect("must create tempdir");
-        let file_path = temp_dir.path().join(filename);
-        let (desc, change_desc) = get_test_wpkh_with_change_desc();
+        let file_path = temp_dir.path().join("store.sqlite");
+        move || {
+            let _temp_dir = &temp_dir;
+            let conn = Connection::open(&file_path).expect("must open connection");
+            SqlitePersister(bdk_sqlite::Store::new(conn).expect("must open db"))
+        }
+    });
+}
 
-        // init wallet when non-existent
-        let wallet_keychains: BTreeMap<_, _> = {
-            let wallet = &mut Wallet::new_or_load(desc, change_desc, None, Network::Testnet)
-                .expect("must init wallet");
-            let mut db = new_or_load(&file_path).expect("must create db");
-            if let Some(changeset) = wallet.take_staged() {
-                write(&mut db, &changeset)?;
-            }
-            wallet.keychains().map(|(k, v)| (*k, v.clone())).collect()
-        };
+#[test]
+fn kv_persister_passes_persist_testsuite() {
+    bdk_persist_testsuite::run(|| {
+        let store = MemoryKvStore::default();
+        move || KvPersister::new(store.clone())
+    });
+}
 
-        // wrong network
-        {
-            let mut db = new_or_load(&file_path).expect("must create db");
-            let changeset = read(&mut db)?;
-            let err = Wallet::new_or_load(desc, change_desc, changeset, Network::Bitcoin)
-                .expect_err("wrong network");
-            assert!(
-                matches!(
-                    err,
-                    bdk_wallet::wallet::NewOrLoadError::LoadedNetworkDoesNotMatch {
-                        got: Some(Network::Testnet),
-                        expected: Network::Bitcoin
-                    }
-                ),
-                "err: {}",
-                err,
-            );
-        }
+#[test]
+fn memory_persister_passes_persist_testsuite() {
+    bdk_persist_testsuite::run(|| {
+        let persister = bdk_persist_testsuite::Shared::new(persist::MemoryPersister::new());
+        move || persister.clone()
+    });
+}
 
-        // wrong genesis hash
-        {
-            let exp_blockhash = BlockHash::all_zeros();
-            let got_blockhash =
-                bitcoin::blockdata::constants::genesis_block(Network::Testnet).block_hash();
-
-            let db = &mut new_or_load(&file_path).expect("must open db");
-            let changeset = read(db)?;
-            let err = Wallet::new_or_load_with_genesis_hash(
-                desc,
-                change_desc,
-                changeset,
-                Network::Testnet,
-                exp_blockhash,
-            )
-            .expect_err("wrong genesis hash");
-            assert!(
-                matches!(
-                    err,
-                    bdk_wallet::wallet::NewOrLoadError::LoadedGenesisDoesNotMatch { got, expected }
-                    if got == Some(got_blockhash) && expected == exp_blockhash
-                ),
-                "err: {}",
-                err,
-            );
-        }
+#[test]
+fn memory_persister_snapshot_round_trips_wallet() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let mut persister = persist::MemoryPersister::new();
+    assert!(wallet.persist(&mut persister).unwrap());
+    wallet.reveal_next_address(KeychainKind::External);
+    wallet.reveal_next_address(KeychainKind::Internal);
+    receive_output_in_latest_block(&mut wallet, 10_000);
+    assert!(block_on(wallet.persist_async(&mut persister)).unwrap());
+    assert_eq!(persister.changesets().len(), 2);
+
+    // the snapshot is shipped to another thread, which loads the wallet from it
+    let snapshot = persister.snapshot();
+    let restored = std::thread::spawn(move || {
+        let mut persister = persist::MemoryPersister::restore(&snapshot).unwrap();
+        let changeset = WalletPersister::initialize(&mut persister).unwrap();
+        Wallet::load_from_changeset(changeset.expect("must have changes")).unwrap()
+    })
+    .join()
+    .unwrap();
 
-        // wrong external descriptor
-        {
-            let (exp_descriptor, exp_change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
-            let got_descriptor = desc
-                .into_wallet_descriptor(&Secp256k1::new(), Network::Testnet)
-                .unwrap()
-                .0;
+    assert_eq!(restored.balance(), wallet.balance());
+    assert_eq!(
+        restored.spk_index().last_revealed_indices(),
+        wallet.spk_index().last_revealed_indices()
+    );
+    assert_eq!(
+        restored
+            .checkpoints()
+            .map(|cp| cp.block_id())
+            .collect::<Vec<_>>(),
+        wallet
+            .checkpoints()
+            .map(|cp| cp.block_id())
+            .collect::<Vec<_>>()
+    );
 
-            let db = &mut new_or_load(&file_path).expect("must open db");
-            let changeset = read(db)?;
-            let err =
-                Wallet::new_or_load(exp_descriptor, exp_change_desc, changeset, Network::Testnet)
-                    .expect_err("wrong external descriptor");
-            assert!(
-                matches!(
-                    err,
-                    bdk_wallet::wallet::NewOrLoadError::LoadedDescriptorDoesNotMatch { ref got, keychain }
-                    if got == &Some(got_descriptor) && keychain == KeychainKind::External
-                ),
-                "err: {}",
-                err,
-            );
-        }
+    // an empty persister restores as empty
+    let empty = persist::MemoryPersister::new().snapshot();
+    let mut persister = persist::MemoryPersister::restore(&empty).unwrap();
+    assert_eq!(WalletPersister::initialize(&mut persister), Ok(None));
+    assert!(persist::MemoryPersister::restore(b"not a snapshot").is_err());
+}
 
-        // wrong internal descriptor
-        {
-            let exp_descriptor = get_test_tr_single_sig();
-            let got_descriptor = change_desc
-                .into_wallet_descriptor(&Secp256k1::new(), Network::Testnet)
-                .unwrap()
-                .0;
+#[test]
+fn kv_persister_appends_changesets_under_prefix() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let store = MemoryKvStore::default();
+    let mut alice = KvPersister::with_prefix(store.clone(), "alice/");
+    let mut bob = KvPersister::with_prefix(store.clone(), "bob/");
 
-            let db = &mut new_or_load(&file_path).expect("must open db");
-            let changeset = read(db)?;
-            let err = Wallet::new_or_load(desc, exp_descriptor, changeset, Network::Testnet)
-                .expect_err("wrong internal descriptor");
-            assert!(
-                matches!(
-                    err,
-                    bdk_wallet::wallet::NewOrLoadError::LoadedDescriptorDoesNotMatch { ref got, keychain }
-                    if got == &Some(got_descriptor) && keychain == KeychainKind::Internal
-                ),
-                "err: {}",
-                err,
-            );
-        }
+    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).unwrap();
+    assert_eq!(alice.initialize().unwrap(), None);
+    assert!(wallet.persist(&mut alice).unwrap());
+    wallet.reveal_next_address(KeychainKind::External);
+    assert!(wallet.persist(&mut alice).unwrap());
+    let mut keys = store.keys_with_prefix("").unwrap();
+    keys.sort();
+    assert_eq!(
+        keys,
+        [
+            "alice/changeset/00000000000000000000",
+            "alice/changeset/00000000000000000001"
+        ]
+    );
 
-        // all parameters match
-        {
-            let db = &mut new_or_load(&file_path).expect("must open db");
-            let changeset = read(db)?;
-            let wallet = Wallet::new_or_load(desc, change_desc, changeset, Network::Testnet)
-                .expect("must recover wallet");
-            assert_eq!(wallet.network(), Network::Testnet);
-            assert!(wallet
-                .keychains()
-                .map(|(k, v)| (*k, v.clone()))
-                .eq(wallet_keychains));
-        }
-        Ok(())
-    }
+    // the wallets sharing the storage don't see the changes of each other
+    assert_eq!(bob.initialize().unwrap(), None);
+    let changeset = alice.initialize().unwrap().expect("must have changes");
+    let wallet = Wallet::load_from_changeset(changeset).unwrap();
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
 
-    run(
-        "store.db",
-        |path| Ok(bdk_file_store::Store::open_or_create_new(DB_MAGIC, path)?),
-        |db| Ok(bdk_file_store::Store::aggregate_changesets(db)?),
-        |db, changeset| Ok(bdk_file_store::Store::append_changeset(db, changeset)?),
-    )?;
-    run(
-        "store.sqlite",
-        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
-        |db| Ok(bdk_sqlite::Store::read(db)?),
-        |db, changeset| Ok(bdk_sqlite::Store::write(db, changeset)?),
-    )?;
+    // a persister which wasn't initialized, or which is behind another handle, doesn't
+    // overwrite the stored changesets
+    let mut wallet = Wallet::load_from_changeset(alice.initialize().unwrap().unwrap()).unwrap();
+    wallet.reveal_next_address(KeychainKind::Internal);
+    let changeset = wallet.take_staged().unwrap();
+    KvPersister::with_prefix(store.clone(), "alice/")
+        .persist(&changeset)
+        .unwrap();
+    wallet.reveal_next_address(KeychainKind::External);
+    alice.persist(&wallet.take_staged().unwrap()).unwrap();
+    assert_eq!(store.len(), 4);
+    let wallet = Wallet::load_from_changeset(alice.initialize().unwrap().unwrap()).unwrap();
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(1));
+    assert_eq!(wallet.derivation_index(KeychainKind::Internal), Some(0));
+}
 
-    Ok(())
+#[test]
+fn kv_persister_fails_on_invalid_key() {
+    let mut store = MemoryKvStore::default();
+    store.set("bdk_wallet/changeset/latest", b"{}").unwrap();
+    let mut persister = KvPersister::new(store);
+    assert!(matches!(
+        persister.initialize(),
+        Err(KvPersisterError::InvalidKey(key)) if key == "bdk_wallet/changeset/latest"
+    ));
 }
 
 #[test]
@@ -333,6 +309,316 @@ fn test_error_external_and_internal_are_the_same() {
     );
 }
 
+#[test]
+fn test_load_checks_network() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Signet).unwrap();
+    let signet_address = wallet.peek_address(KeychainKind::External, 0).address;
+    let changeset = wallet.take_staged().unwrap();
+
+    // signet and testnet share their address prefixes, only the network check can tell them apart
+    let testnet_wallet = Wallet::new(desc, change_desc, Network::Testnet).unwrap();
+    assert_eq!(
+        testnet_wallet
+            .peek_address(KeychainKind::External, 0)
+            .to_string(),
+        signet_address.to_string()
+    );
+    let err = Wallet::load()
+        .check_network(Network::Testnet)
+        .descriptor(KeychainKind::External, Some(desc))
+        .descriptor(KeychainKind::Internal, Some(change_desc))
+        .load_wallet(changeset.clone())
+        .expect_err("wrong network");
+    assert_matches!(
+        err,
+        LoadError::Mismatch(LoadMismatch::Network {
+            loaded: Network::Signet,
+            expected: Network::Testnet,
+        })
+    );
+
+    let wallet = Wallet::load()
+        .check_network(Network::Signet)
+        .load_wallet(changeset)
+        .expect("must load wallet");
+    assert_eq!(wallet.network(), Network::Signet);
+}
+
+#[test]
+fn test_load_checks_genesis_hash() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let custom_genesis_hash = BlockHash::from_byte_array([1; 32]);
+    let signet_genesis_hash = bitcoin::constants::genesis_block(Network::Signet).block_hash();
+    let mut wallet =
+        Wallet::new_with_genesis_hash(desc, change_desc, Network::Signet, custom_genesis_hash)
+            .unwrap();
+    let changeset = wallet.take_staged().unwrap();
+
+    let err = Wallet::load()
+        .check_network(Network::Signet)
+        .check_genesis_hash(signet_genesis_hash)
+        .load_wallet(changeset.clone())
+        .expect_err("wrong genesis hash");
+    assert_matches!(
+        err,
+        LoadError::Mismatch(LoadMismatch::Genesis { loaded, expected })
+        if loaded == custom_genesis_hash && expected == signet_genesis_hash
+    );
+
+    let wallet = Wallet::load()
+        .check_genesis_hash(custom_genesis_hash)
+        .load_wallet(changeset)
+        .expect("must load wallet");
+    assert_eq!(wallet.local_chain().genesis_hash(), custom_genesis_hash);
+}
+
+#[test]
+fn test_network_params_persist_and_reload() -> anyhow::Result<()> {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    // a custom chain with the addresses of signet
+    let params =
+        NetworkParams::new(Network::Signet).with_genesis_hash(BlockHash::from_byte_array([7; 32]));
+    let temp_dir = tempfile::tempdir()?;
+    let file_path = temp_dir.path().join("store.db");
+
+    let mut wallet = Wallet::new_with_network_params(desc, change_desc, params)?;
+    assert_eq!(wallet.network_params(), params);
+    let address = wallet.reveal_next_address(KeychainKind::External).address;
+    assert!(address.to_string().starts_with("tb1"));
+    {
+        let mut db = bdk_file_store::Store::<ChangeSet>::create_new(DB_MAGIC, &file_path)?;
+        db.append_changeset(&wallet.take_staged().expect("staged changes"))?;
+    }
+
+    let changeset = bdk_file_store::Store::<ChangeSet>::open(DB_MAGIC, &file_path)?
+        .aggregate_changesets()?
+        .expect("persisted changes");
+    let wallet = Wallet::load()
+        .check_network_params(params)
+        .load_wallet(changeset.clone())?;
+    assert_eq!(wallet.network_params(), params);
+    assert_eq!(wallet.local_chain().genesis_hash(), params.genesis_hash);
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 0).address,
+        address
+    );
+
+    // the addresses are parsed for the network of the wallet
+    assert_eq!(wallet.parse_address(&address.to_string())?, address);
+    assert!(wallet
+        .parse_address("bc1qxhmdufsvnuaaaer4ynz88fspdsxq2h9e9cetdj")
+        .is_err());
+
+    // the standard signet has another genesis block
+    let err = Wallet::load()
+        .check_network_params(Network::Signet.into())
+        .load_wallet(changeset.clone())
+        .expect_err("wrong genesis hash");
+    assert_matches!(
+        err,
+        LoadError::Mismatch(LoadMismatch::Genesis { loaded, .. }) if loaded == params.genesis_hash
+    );
+    let err = Wallet::new_or_load_with_network_params(
+        desc,
+        change_desc,
+        Some(changeset.clone()),
+        Network::Signet.into(),
+    )
+    .expect_err("wrong genesis hash");
+    assert_matches!(err, NewOrLoadError::LoadedGenesisDoesNotMatch { got, .. } if got == Some(params.genesis_hash));
+    let wallet =
+        Wallet::new_or_load_with_network_params(desc, change_desc, Some(changeset), params)?;
+    assert_eq!(wallet.network_params(), params);
+    Ok(())
+}
+
+/// `wallet_format_v0.dat` is written by bdk_wallet 1.0.0-alpha.13 with bdk_file_store 0.13,
+/// before the format of the store was versioned: a wallet of
+/// [`get_test_tr_single_sig_xprv_with_change_desc`] funded as [`get_funded_wallet_with_change`],
+/// with the external addresses 0 to 3 and the internal address 0 revealed, and the output 1 of the
+/// funding transaction spent to the external address 2 by an unconfirmed transaction.
+#[test]
+fn test_load_wallet_from_format_v0_store() -> anyhow::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let file_path = temp_dir.path().join("store.db");
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/wallet_format_v0.dat"),
+        &file_path,
+    )?;
+
+    let mut db = bdk_file_store::Store::<ChangeSet>::open(DB_MAGIC, &file_path)?;
+    assert_eq!(db.format_version(), 0);
+    let changeset = db.aggregate_changesets()?.expect("persisted changes");
+    assert!(changeset.indexed_tx_graph.indexer.marked_used.is_empty());
+    let mut wallet = Wallet::load_from_changeset(changeset)?;
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(3));
+    assert_eq!(wallet.derivation_index(KeychainKind::Internal), Some(0));
+    assert_eq!(
+        wallet.balance(),
+        Balance {
+            confirmed: Amount::from_sat(50_000),
+            untrusted_pending: Amount::from_sat(20_000),
+            ..Default::default()
+        }
+    );
+
+    // the marks of the addresses are appended to the upgraded store
+    assert!(wallet.mark_used(KeychainKind::External, 1));
+    db.append_changeset(&wallet.take_staged().expect("staged changes"))?;
+    assert_eq!(db.format_version(), bdk_file_store::FORMAT_VERSION);
+    drop(db);
+    let changeset = bdk_file_store::Store::<ChangeSet>::open(DB_MAGIC, &file_path)?
+        .aggregate_changesets()?
+        .expect("persisted changes");
+    let wallet = Wallet::load_from_changeset(changeset)?;
+    assert!(wallet.spk_index().is_used(KeychainKind::External, 1));
+    assert_eq!(wallet.balance().untrusted_pending, Amount::from_sat(20_000));
+    Ok(())
+}
+
+/// `wallet_format_v1.dat` is written by bdk_file_store with the format version 1, before the
+/// changeset of the wallet had its fields after `labels`: the wallet of `wallet_format_v0.dat`,
+/// see [`test_load_wallet_from_format_v0_store`], with a second unconfirmed transaction to the
+/// external address 3 which is then evicted, the external address 1 marked used and the label
+/// "pending" on the first unconfirmed transaction.
+#[test]
+fn test_load_wallet_from_format_v1_store() -> anyhow::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let file_path = temp_dir.path().join("store.db");
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/wallet_format_v1.dat"),
+        &file_path,
+    )?;
+    let pending =
+        Txid::from_str("1549ead39311249bb14c361a2d7b70288de8e41617d634a23b1b5f97a1b31853")?;
+    let evicted =
+        Txid::from_str("3059ee1fc520772a30a4febad2de456eb1be2da70e653d241821590fe8afb489")?;
+
+    let mut db = bdk_file_store::Store::<ChangeSet>::open(DB_MAGIC, &file_path)?;
+    assert_eq!(db.format_version(), 1);
+    let changeset = db.aggregate_changesets()?.expect("persisted changes");
+    assert!(changeset.broadcasts.is_empty());
+    assert_eq!(changeset.birthday, None);
+    let wallet = Wallet::load_from_changeset(changeset)?;
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(3));
+    assert!(wallet.spk_index().is_used(KeychainKind::External, 1));
+    assert_eq!(wallet.tx_graph().last_evicted(evicted), Some(400));
+    assert_eq!(
+        wallet.labels().collect::<Vec<_>>(),
+        [(&LabelRef::Tx(pending), "pending")]
+    );
+    assert_eq!(
+        wallet.balance(),
+        Balance {
+            confirmed: Amount::from_sat(50_000),
+            untrusted_pending: Amount::from_sat(20_000),
+            ..Default::default()
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_load_checks_descriptors() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).unwrap();
+    let changeset = wallet.take_staged().unwrap();
+    let secp = Secp256k1::new();
+    let parse = |descriptor: &str| {
+        descriptor
+            .into_wallet_descriptor(&secp, Network::Testnet)
+            .unwrap()
+            .0
+    };
+
+    // wrong external descriptor
+    let err = Wallet::load()
+        .descriptor(KeychainKind::External, Some(get_test_tr_single_sig()))
+        .load_wallet(changeset.clone())
+        .expect_err("wrong external descriptor");
+    assert_matches!(
+        err,
+        LoadError::Mismatch(LoadMismatch::Descriptor { keychain: KeychainKind::External, loaded, expected })
+        if loaded == Some(Box::new(parse(desc))) && expected == Some(Box::new(parse(get_test_tr_single_sig())))
+    );
+
+    // wrong internal descriptor
+    let err = Wallet::load()
+        .descriptor(KeychainKind::External, Some(desc))
+        .descriptor(KeychainKind::Internal, Some(desc))
+        .load_wallet(changeset.clone())
+        .expect_err("wrong internal descriptor");
+    assert_matches!(
+        err,
+        LoadError::Mismatch(LoadMismatch::Descriptor { keychain: KeychainKind::Internal, loaded, expected })
+        if loaded == Some(Box::new(parse(change_desc))) && expected == Some(Box::new(parse(desc)))
+    );
+
+    // expected a single-keychain wallet
+    let err = Wallet::load()
+        .descriptor(KeychainKind::Internal, None::<&str>)
+        .load_wallet(changeset.clone())
+        .expect_err("unexpected internal descriptor");
+    assert_matches!(
+        err,
+        LoadError::Mismatch(LoadMismatch::Descriptor { keychain: KeychainKind::Internal, loaded, expected: None })
+        if loaded == Some(Box::new(parse(change_desc)))
+    );
+
+    // the change descriptor of a single-keychain wallet is missing
+    let mut single_wallet = Wallet::create_single(desc, Network::Testnet).unwrap();
+    let single_changeset = single_wallet.take_staged().unwrap();
+    let err = Wallet::load()
+        .descriptor(KeychainKind::Internal, Some(change_desc))
+        .load_wallet(single_changeset.clone())
+        .expect_err("missing internal descriptor");
+    assert_matches!(
+        err,
+        LoadError::Mismatch(LoadMismatch::Descriptor {
+            keychain: KeychainKind::Internal,
+            loaded: None,
+            expected: Some(_),
+        })
+    );
+    Wallet::load()
+        .descriptor(KeychainKind::External, Some(desc))
+        .descriptor(KeychainKind::Internal, None::<&str>)
+        .load_wallet(single_changeset)
+        .expect("must load single-keychain wallet");
+}
+
+#[test]
+fn test_load_extract_keys() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).unwrap();
+    let changeset = wallet.take_staged().unwrap();
+
+    // the keys are not persisted
+    let wallet = Wallet::load()
+        .descriptor(KeychainKind::External, Some(desc))
+        .descriptor(KeychainKind::Internal, Some(change_desc))
+        .load_wallet(changeset.clone())
+        .unwrap();
+    assert!(wallet.is_watch_only());
+
+    let wallet = Wallet::load()
+        .descriptor(KeychainKind::External, Some(desc))
+        .descriptor(KeychainKind::Internal, Some(change_desc))
+        .extract_keys()
+        .load_wallet(changeset)
+        .unwrap();
+    assert!(!wallet.is_watch_only());
+    assert_eq!(
+        wallet.get_signers(KeychainKind::External).signers().len(),
+        1
+    );
+    assert_eq!(
+        wallet.get_signers(KeychainKind::Internal).signers().len(),
+        1
+    );
+}
+
 #[test]
 fn test_descriptor_checksum() {
     let (wallet, _) = get_funded_wallet_wpkh();
@@ -353,20 +639,207 @@ fn test_descriptor_checksum() {
 }
 
 #[test]
-fn test_get_funded_wallet_balance() {
-    let (wallet, _) = get_funded_wallet_wpkh();
+fn test_public_descriptor_string() {
+    let descriptor = "wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)";
+    let wallet = Wallet::create_single(descriptor, Network::Testnet).unwrap();
 
-    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
-    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
-    // sats are the transaction fee.
-    assert_eq!(wallet.balance().confirmed, Amount::from_sat(50_000));
+    let public_descriptor = wallet.public_descriptor_string(KeychainKind::External);
+    assert!(!public_descriptor.contains("tprv"));
+    let (raw_descriptor, checksum) = public_descriptor.split_once('#').unwrap();
+    assert_eq!(checksum, wallet.descriptor_checksum(KeychainKind::External));
+    assert_eq!(calc_checksum(raw_descriptor).unwrap(), checksum);
+    assert_eq!(calc_checksum(&public_descriptor).unwrap(), checksum);
+
+    // a watch-only wallet from the string derives the same addresses
+    let watch_only = Wallet::create_single(&public_descriptor, Network::Testnet).unwrap();
+    assert!(watch_only.is_watch_only());
+    assert_eq!(
+        watch_only.peek_address(KeychainKind::External, 42),
+        wallet.peek_address(KeychainKind::External, 42)
+    );
 }
 
 #[test]
-fn test_get_funded_wallet_sent_and_received() {
-    let (wallet, txid) = get_funded_wallet_wpkh();
+fn test_keymap() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let keymap = wallet.keymap(KeychainKind::External);
+    assert_eq!(keymap.len(), 1);
 
-    let mut tx_amounts: Vec<(Txid, (Amount, Amount))> = wallet
+    // the public descriptor and its keymap give back a wallet able to sign
+    let descriptor = wallet.public_descriptor(KeychainKind::External).clone();
+    let restored = Wallet::create_single((descriptor.clone(), keymap), Network::Regtest).unwrap();
+    assert!(!restored.is_watch_only());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    assert!(restored.sign(&mut psbt, SignOptions::default()).unwrap());
+
+    let watch_only = Wallet::create_single(descriptor, Network::Regtest).unwrap();
+    assert!(watch_only.keymap(KeychainKind::External).is_empty());
+}
+
+#[test]
+fn test_wallet_policy() {
+    let key_a = "[73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3";
+    let key_b = "[c55b303f/84'/1'/0']tpubDC2Qwo2TFsaNC4ju8nrUJ9mqVT3eSgdmy1yPqhgkjwmke3PRXutNGRYAUo6RCHTcVQaDR3ohNU9we59brGHuEKPvH1ags2nevW5opEE9Z5Q";
+    let key_c = "[3c31d632/84'/1'/0']tpubDCYwFkks2cg78N7eoYbBatsFEGje8vW8arSKW4rLwD1AU1s9KJMDRHE32JkvYERuiFjArrsH7qpWSpJATed5ShZbG9KsskA5Rmi6NSYgYN2";
+    let policy_of = |external: String, internal: String| {
+        Wallet::new(&external, &internal, Network::Testnet)
+            .unwrap()
+            .wallet_policy("Cold storage")
+    };
+
+    let policy = policy_of(
+        format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", key_a, key_b),
+        format!("wsh(sortedmulti(2,{}/1/*,{}/1/*))", key_a, key_b),
+    )
+    .unwrap();
+    assert_eq!(policy.name, "Cold storage");
+    assert_eq!(policy.template, "wsh(sortedmulti(2,@0/**,@1/**))");
+    assert_eq!(policy.keys, [key_a, key_b]);
+
+    // the same key can appear more than once, with other derivation steps
+    let policy = policy_of(
+        format!(
+            "tr({}/0/*,and_v(v:multi_a(1,{}/2/*,{}/0/*),older(144)))",
+            key_a, key_a, key_b
+        ),
+        format!(
+            "tr({}/1/*,and_v(v:multi_a(1,{}/3/*,{}/1/*),older(144)))",
+            key_a, key_a, key_b
+        ),
+    )
+    .unwrap();
+    assert_eq!(
+        policy.template,
+        "tr(@0/**,and_v(v:multi_a(1,@0/<2;3>/*,@1/**),older(144)))"
+    );
+    assert_eq!(policy.keys, [key_a, key_b]);
+
+    // the policies of the same keys in another order are different
+    let other = policy_of(
+        format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", key_b, key_c),
+        format!("wsh(sortedmulti(2,{}/1/*,{}/1/*))", key_b, key_c),
+    )
+    .unwrap();
+    let swapped = policy_of(
+        format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", key_c, key_b),
+        format!("wsh(sortedmulti(2,{}/1/*,{}/1/*))", key_c, key_b),
+    )
+    .unwrap();
+    assert_eq!(other.template, swapped.template);
+    assert_eq!(swapped.keys, [key_c, key_b]);
+    assert_ne!(other.id, swapped.id);
+
+    let json = serde_json::to_value(&other).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "name": "Cold storage",
+            "descriptor_template": "wsh(sortedmulti(2,@0/**,@1/**))",
+            "keys_info": [key_b, key_c],
+        })
+    );
+}
+
+#[test]
+fn test_wallet_policy_id() {
+    let xpub = "[c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe";
+    let wallet = Wallet::new(
+        &format!("wpkh({}/0/*)", xpub),
+        &format!("wpkh({}/1/*)", xpub),
+        Network::Testnet,
+    )
+    .unwrap();
+    let policy = wallet.wallet_policy("").unwrap();
+    assert_eq!(policy.template, "wpkh(@0/**)");
+    let key = "[c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe";
+    assert_eq!(policy.keys, [key]);
+
+    // version, empty name, template length and hash, number of keys and the single leaf
+    let mut serialized = vec![2, 0, 11];
+    serialized.extend(sha256::Hash::hash(b"wpkh(@0/**)").as_byte_array());
+    serialized.push(1);
+    serialized.extend(sha256::Hash::hash(&[&[0u8][..], key.as_bytes()].concat()).as_byte_array());
+    let id = sha256::Hash::hash(&serialized);
+    assert_eq!(policy.id, id);
+    assert!(policy.check_id(id.as_byte_array()));
+    assert!(!policy.check_id(&[0; 32]));
+
+    let rebuilt = WalletPolicy::new(
+        policy.name.clone(),
+        policy.template.clone(),
+        policy.keys.clone(),
+    );
+    assert_eq!(rebuilt, policy);
+}
+
+#[test]
+fn test_wallet_policy_errors() {
+    let key = "[c55b303f/84'/1'/0']tpubDC2Qwo2TFsaNC4ju8nrUJ9mqVT3eSgdmy1yPqhgkjwmke3PRXutNGRYAUo6RCHTcVQaDR3ohNU9we59brGHuEKPvH1ags2nevW5opEE9Z5Q";
+    let policy_of = |external: String, internal: String| {
+        Wallet::new(&external, &internal, Network::Testnet)
+            .unwrap()
+            .wallet_policy("")
+    };
+
+    let wallet =
+        Wallet::create_single(format!("wpkh({}/0/*)", key).as_str(), Network::Testnet).unwrap();
+    assert_eq!(
+        wallet.wallet_policy(""),
+        Err(WalletPolicyError::SingleKeychain)
+    );
+    assert_eq!(
+        wallet.wallet_policy(&"a".repeat(65)),
+        Err(WalletPolicyError::NameTooLong)
+    );
+    assert_matches!(
+        policy_of(
+            "wpkh(02e96fe52ef0e22d2f131dd425ce1893073a3c6ad20e8cac36726393dfb4856a4c)".into(),
+            "wpkh(03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd)".into(),
+        ),
+        Err(WalletPolicyError::NotExtendedKey(_))
+    );
+    assert_matches!(
+        policy_of(
+            format!("wpkh({}/0/0/*)", key),
+            format!("wpkh({}/0/1/*)", key)
+        ),
+        Err(WalletPolicyError::UnsupportedDerivation(_))
+    );
+    assert_eq!(
+        policy_of(format!("wpkh({}/0/*)", key), format!("pkh({}/1/*)", key)),
+        Err(WalletPolicyError::KeychainsMismatch)
+    );
+    assert_eq!(
+        policy_of(
+            format!("wsh(and_v(v:pk({}/0/*),older(6)))", key),
+            format!("wsh(and_v(v:pk({}/1/*),older(7)))", key)
+        ),
+        Err(WalletPolicyError::KeychainsMismatch)
+    );
+    assert_matches!(
+        policy_of(format!("pk({}/0/*)", key), format!("pk({}/1/*)", key)),
+        Err(WalletPolicyError::UnsupportedDescriptor(_))
+    );
+}
+
+#[test]
+fn test_get_funded_wallet_balance() {
+    let (wallet, _) = get_funded_wallet_wpkh();
+
+    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
+    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
+    // sats are the transaction fee.
+    assert_eq!(wallet.balance().confirmed, Amount::from_sat(50_000));
+}
+
+#[test]
+fn test_get_funded_wallet_sent_and_received() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
+
+    let mut tx_amounts: Vec<(Txid, (Amount, Amount))> = wallet
         .transactions()
         .map(|ct| (ct.tx_node.txid, wallet.sent_and_received(&ct.tx_node)))
         .collect();
@@ -382,6 +855,240 @@ fn test_get_funded_wallet_sent_and_received() {
     assert_eq!(received.to_sat(), 50_000);
 }
 
+#[test]
+fn test_tx_details() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
+    let tx = wallet.get_tx(txid).expect("transaction").tx_node.tx;
+
+    let details = wallet.tx_details(txid).expect("tx details");
+    assert_eq!(details.txid, txid);
+    assert_eq!(details.sent, Amount::from_sat(76_000));
+    assert_eq!(details.received, Amount::from_sat(50_000));
+    assert_eq!(details.fee, Some(Amount::from_sat(1000)));
+    assert_eq!(details.net(), SignedAmount::from_sat(-26_000));
+    assert_eq!(
+        details.confirmation_time,
+        ConfirmationTime::Confirmed {
+            height: 2_000,
+            time: 200
+        }
+    );
+    assert_eq!(
+        details.owned_outputs,
+        [(0, (KeychainKind::External, 0))].into()
+    );
+    assert_eq!(
+        details.counterparty_outputs,
+        [(1, tx.output[1].clone())].into()
+    );
+
+    // the parent of the funding transaction spends an unknown output
+    let parent_txid = tx.input[0].previous_output.txid;
+    let details = wallet.tx_details(parent_txid).expect("tx details");
+    assert_eq!(details.sent, Amount::ZERO);
+    assert_eq!(details.received, Amount::from_sat(76_000));
+    assert_eq!(details.fee, None);
+
+    assert!(wallet.tx_details(Txid::all_zeros()).is_none());
+
+    let mut listed = wallet
+        .list_tx_details()
+        .map(|details| details.txid)
+        .collect::<Vec<_>>();
+    listed.sort();
+    let mut expected = vec![txid, parent_txid];
+    expected.sort();
+    assert_eq!(listed, expected);
+}
+
+#[test]
+fn test_tx_details_resolved() {
+    use bdk_chain::prevout::{PrevoutResolver, PrevoutSource};
+    use std::cell::Cell;
+
+    /// A remote source counting the outpoints it is asked for
+    struct MockRemote(BTreeMap<OutPoint, TxOut>, Cell<usize>);
+
+    impl PrevoutSource for MockRemote {
+        fn get_txout(&self, outpoint: OutPoint) -> Option<TxOut> {
+            self.1.set(self.1.get() + 1);
+            self.0.get(&outpoint).cloned()
+        }
+    }
+
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    let _ = wallet.take_staged();
+    let tx = wallet.get_tx(txid).expect("transaction").tx_node.tx;
+    // the parent of the funding transaction spends an unknown output
+    let parent_txid = tx.input[0].previous_output.txid;
+    let parent = wallet.get_tx(parent_txid).expect("transaction").tx_node.tx;
+    let missing = parent.input[0].previous_output;
+    assert_eq!(wallet.tx_details(parent_txid).unwrap().fee, None);
+
+    let prevout = TxOut {
+        value: Amount::from_sat(80_000),
+        script_pubkey: ScriptBuf::new(),
+    };
+    let remote = MockRemote([(missing, prevout.clone())].into(), Cell::new(0));
+    let mut resolver = PrevoutResolver::new().with_source(&remote);
+
+    // nothing is missing for the funding transaction
+    assert_eq!(
+        wallet.calculate_fee_checked(&tx, &mut resolver),
+        Ok(Amount::from_sat(1_000))
+    );
+    assert_eq!(remote.1.get(), 0);
+
+    let details = wallet
+        .tx_details_resolved(parent_txid, &mut resolver)
+        .expect("tx details");
+    assert_eq!(details.fee, Some(Amount::from_sat(4_000)));
+    assert_eq!(remote.1.get(), 1);
+
+    // the output is now a floating txout of the graph, staged to be persisted
+    assert_eq!(wallet.tx_graph().get_txout(missing), Some(&prevout));
+    assert_eq!(
+        wallet
+            .staged()
+            .expect("staged")
+            .indexed_tx_graph
+            .graph
+            .txouts,
+        [(missing, prevout)].into()
+    );
+    assert_eq!(
+        wallet.tx_details(parent_txid).unwrap().fee,
+        Some(Amount::from_sat(4_000))
+    );
+    assert_eq!(wallet.resolve_prevouts(&parent, &mut resolver), 0);
+    assert_eq!(remote.1.get(), 1);
+}
+
+#[test]
+fn test_tx_details_self_transfer() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::Internal);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let details = wallet.tx_details(txid).expect("tx details");
+    let fee = details.fee.expect("fee");
+    assert_eq!(details.sent, Amount::from_sat(50_000));
+    assert_eq!(details.received, Amount::from_sat(50_000) - fee);
+    assert_eq!(details.net(), -fee.to_signed().unwrap());
+    assert!(details.counterparty_outputs.is_empty());
+    assert_eq!(details.owned_outputs.len(), 1);
+    assert_eq!(
+        details.confirmation_time,
+        ConfirmationTime::Unconfirmed { last_seen: 0 }
+    );
+}
+
+#[test]
+fn test_insert_txout_persists() -> anyhow::Result<()> {
+    fn run<Db, New, Read, Write>(
+        filename: &str,
+        new: New,
+        read: Read,
+        write: Write,
+    ) -> anyhow::Result<()>
+    where
+        New: Fn(&Path) -> anyhow::Result<Db>,
+        Read: Fn(&mut Db) -> anyhow::Result<Option<ChangeSet>>,
+        Write: Fn(&mut Db, &ChangeSet) -> anyhow::Result<()>,
+    {
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join(filename);
+        let mut db = new(&file_path)?;
+        let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+        let mut wallet = Wallet::new(desc, change_desc, Network::Regtest)?;
+
+        // an imported transaction paying the wallet from two foreign outputs
+        let foreign_spk = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")?
+            .assume_checked()
+            .script_pubkey();
+        let prevouts = [
+            (
+                OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                TxOut {
+                    value: Amount::from_sat(20_000),
+                    script_pubkey: foreign_spk.clone(),
+                },
+            ),
+            (
+                OutPoint::new(Txid::from_byte_array([2; 32]), 3),
+                TxOut {
+                    value: Amount::from_sat(15_000),
+                    script_pubkey: foreign_spk,
+                },
+            ),
+        ];
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: prevouts
+                .iter()
+                .map(|(outpoint, _)| TxIn {
+                    previous_output: *outpoint,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(34_000),
+                script_pubkey: wallet
+                    .reveal_next_address(KeychainKind::External)
+                    .script_pubkey(),
+            }],
+        };
+        let txid = tx.compute_txid();
+        wallet.insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 100 })?;
+        assert!(wallet.calculate_fee(&tx).is_err());
+        for (outpoint, txout) in prevouts.clone() {
+            wallet.insert_txout(outpoint, txout);
+        }
+        assert_eq!(wallet.calculate_fee(&tx)?, Amount::from_sat(1_000));
+        write(&mut db, &wallet.take_staged().expect("staged changes"))?;
+
+        let changeset = read(&mut db)?.expect("persisted changes");
+        assert_eq!(
+            changeset.indexed_tx_graph.graph.txouts,
+            prevouts.into_iter().collect()
+        );
+        let loaded = Wallet::load_from_changeset(changeset)?;
+        assert_eq!(loaded.calculate_fee(&tx)?, Amount::from_sat(1_000));
+        let details = loaded.tx_details(txid).expect("tx details");
+        assert_eq!(details.fee, Some(Amount::from_sat(1_000)));
+        assert_eq!(details.sent, Amount::ZERO);
+        assert_eq!(details.received, Amount::from_sat(34_000));
+        // the foreign outputs are not the wallet's
+        assert_eq!(loaded.list_unspent().count(), 1);
+        assert_eq!(loaded.balance().total(), Amount::from_sat(34_000));
+        Ok(())
+    }
+
+    run(
+        "store.db",
+        |path| Ok(bdk_file_store::Store::create_new(DB_MAGIC, path)?),
+        |db| Ok(bdk_file_store::Store::aggregate_changesets(db)?),
+        |db, changeset| Ok(bdk_file_store::Store::append_changeset(db, changeset)?),
+    )?;
+    run(
+        "store.sqlite",
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+        |db| Ok(bdk_sqlite::Store::read(db)?),
+        |db, changeset| Ok(bdk_sqlite::Store::write(db, changeset)?),
+    )?;
+
+    Ok(())
+}
+
 #[test]
 fn test_get_funded_wallet_tx_fees() {
     let (wallet, txid) = get_funded_wallet_wpkh();
@@ -519,7 +1226,10 @@ fn test_create_tx_version_0() {
     builder
         .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
         .version(0);
-    assert!(matches!(builder.finish(), Err(CreateTxError::Version0)));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::InvalidParams(errors)) if errors == vec![ParamError::Version0]
+    );
 }
 
 #[test]
@@ -630,6 +1340,170 @@ fn test_create_tx_custom_locktime_incompatible_with_cltv() {
         if requested.to_consensus_u32() == 50_000 && required.to_consensus_u32() == 100_000));
 }
 
+#[test]
+fn test_create_tx_locktime_policy() {
+    use bdk_wallet::wallet::tx_builder::{LocktimePolicy, LocktimeSource};
+
+    const TIME: u32 = 1_700_000_000;
+    let time_cltv =
+        "wsh(and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),after(1700000000)))";
+    let height = |h: u32| absolute::LockTime::from_height(h).unwrap();
+    let time = |t: u32| absolute::LockTime::from_time(t).unwrap();
+    let mixed = |requested, required| -> Result<_, CreateTxError> {
+        Err(CreateTxError::MixedLockTimeUnits {
+            requested,
+            required,
+        })
+    };
+    let lower = |requested, required| -> Result<_, CreateTxError> {
+        Err(CreateTxError::LockTime {
+            requested,
+            required,
+        })
+    };
+    let anti_fee_sniping = LocktimePolicy::AntiFeeSniping { randomize: false };
+    let cases = [
+        // no requirement, the policy decides
+        (
+            get_test_wpkh(),
+            anti_fee_sniping,
+            Ok((height(200_000), LocktimeSource::Policy)),
+        ),
+        (
+            get_test_wpkh(),
+            LocktimePolicy::None,
+            Ok((height(0), LocktimeSource::Policy)),
+        ),
+        (
+            get_test_wpkh(),
+            LocktimePolicy::Exact(time(TIME)),
+            Ok((time(TIME), LocktimeSource::Policy)),
+        ),
+        // a block-based requirement
+        (
+            get_test_single_sig_cltv(),
+            anti_fee_sniping,
+            Ok((height(200_000), LocktimeSource::Policy)),
+        ),
+        (
+            get_test_single_sig_cltv(),
+            LocktimePolicy::None,
+            Ok((height(100_000), LocktimeSource::Descriptor)),
+        ),
+        (
+            get_test_single_sig_cltv(),
+            LocktimePolicy::Exact(height(150_000)),
+            Ok((height(150_000), LocktimeSource::Policy)),
+        ),
+        (
+            get_test_single_sig_cltv(),
+            LocktimePolicy::Exact(height(50_000)),
+            lower(height(50_000), height(100_000)),
+        ),
+        (
+            get_test_single_sig_cltv(),
+            LocktimePolicy::Exact(time(TIME)),
+            mixed(time(TIME), height(100_000)),
+        ),
+        // a time-based requirement
+        (
+            time_cltv,
+            anti_fee_sniping,
+            Ok((time(TIME), LocktimeSource::Descriptor)),
+        ),
+        (
+            time_cltv,
+            LocktimePolicy::None,
+            Ok((time(TIME), LocktimeSource::Descriptor)),
+        ),
+        (
+            time_cltv,
+            LocktimePolicy::Exact(time(TIME + 1)),
+            Ok((time(TIME + 1), LocktimeSource::Policy)),
+        ),
+        (
+            time_cltv,
+            LocktimePolicy::Exact(time(TIME - 1)),
+            lower(time(TIME - 1), time(TIME)),
+        ),
+        (
+            time_cltv,
+            LocktimePolicy::Exact(height(150_000)),
+            mixed(height(150_000), time(TIME)),
+        ),
+    ];
+    for (descriptor, policy, expected) in cases {
+        let (mut wallet, _) = get_funded_wallet(descriptor);
+        let addr = wallet.next_unused_address(KeychainKind::External);
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+            .current_height(200_000)
+            .locktime_policy(policy);
+        let result = builder.estimate().map(|estimate| {
+            assert_eq!(estimate.locktime.policy, policy);
+            (estimate.locktime.locktime, estimate.locktime.source)
+        });
+        match (&result, &expected) {
+            (Ok(result), Ok(expected)) => {
+                assert_eq!(result, expected, "{} {:?}", descriptor, policy)
+            }
+            (Err(result), Err(expected)) => {
+                assert_eq!(
+                    result.to_string(),
+                    expected.to_string(),
+                    "{} {:?}",
+                    descriptor,
+                    policy
+                )
+            }
+            _ => panic!("{} {:?}: {:?}", descriptor, policy, result),
+        }
+        // the estimated locktime is the one of the transaction
+        if let Ok((locktime, _)) = expected {
+            let psbt = builder.finish().unwrap();
+            assert_eq!(psbt.unsigned_tx.lock_time, locktime);
+        }
+    }
+}
+
+#[test]
+fn test_create_tx_locktime_policy_randomized() {
+    use bdk_wallet::wallet::tx_builder::{LocktimePolicy, LocktimeSource};
+
+    let policy = LocktimePolicy::AntiFeeSniping { randomize: true };
+    for (descriptor, required) in [(get_test_wpkh(), 0), (get_test_single_sig_cltv(), 100_000)] {
+        let (mut wallet, _) = get_funded_wallet(descriptor);
+        let addr = wallet.next_unused_address(KeychainKind::External);
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+            .current_height(100_050)
+            .locktime_policy(policy);
+        let mut moved_back = 0;
+        for seed in 0..200 {
+            let estimate = builder
+                .estimate_with_aux_rand(&mut StdRng::seed_from_u64(seed))
+                .unwrap();
+            let locktime = estimate.locktime.locktime.to_consensus_u32();
+            assert!((100_050 - 99..=100_050).contains(&locktime));
+            assert!(locktime >= required);
+            if locktime < 100_050 {
+                moved_back += 1;
+            }
+            // the height moved below the requirement is raised to it
+            if locktime > required {
+                assert_eq!(estimate.locktime.source, LocktimeSource::Policy);
+            }
+            if estimate.locktime.source == LocktimeSource::Descriptor {
+                assert_eq!(locktime, required);
+            }
+        }
+        // about one transaction out of ten
+        assert!((5..=40).contains(&moved_back), "{} moved back", moved_back);
+    }
+}
+
 #[test]
 fn test_create_tx_no_rbf_csv() {
     let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
@@ -687,7 +1561,11 @@ fn test_create_tx_invalid_rbf_sequence() {
     builder
         .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
         .enable_rbf_with_sequence(Sequence(0xFFFFFFFE));
-    assert!(matches!(builder.finish(), Err(CreateTxError::RbfSequence)));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::InvalidParams(errors))
+            if errors == vec![ParamError::RbfSequence(Sequence(0xFFFFFFFE))]
+    );
 }
 
 #[test]
@@ -852,40 +1730,112 @@ fn test_create_tx_custom_fee_rate() {
 }
 
 #[test]
-fn test_create_tx_absolute_fee() {
+fn test_create_tx_fee_strategy() {
+    use bdk_wallet::wallet::fee_strategy::{FeeEstimateTable, FeeStrategy, FeeStrategyError};
+
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
+    // as returned by Electrum, in BTC/kvB, with an outlier for the next block
+    let estimates = FeeEstimateTable::from_btc_per_kvb([(1, 0.05), (6, 0.0001), (144, -1.0)]);
+    let max_fee_rate = FeeRate::from_sat_per_vb_u32(50);
+
     let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_absolute(Amount::from_sat(100));
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    assert_eq!(
+        builder.fee_strategy(
+            FeeStrategy::Economic,
+            &FeeEstimateTable::new(),
+            max_fee_rate
+        ),
+        Err(FeeStrategyError::NoEstimates)
+    );
+    let resolution = builder
+        .fee_strategy(FeeStrategy::Economic, &estimates, max_fee_rate)
+        .unwrap();
+    assert_eq!(resolution.target, Some(6));
+    assert_eq!(resolution.fee_rate, FeeRate::from_sat_per_vb_u32(10));
     let psbt = builder.finish().unwrap();
     let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_u32(10), @add_signature);
 
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(100));
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(
-        psbt.unsigned_tx.output[0].value,
-        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
-    );
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let resolution = builder
+        .fee_strategy(
+            FeeStrategy::Priority {
+                confirm_within_blocks: 1,
+            },
+            &estimates,
+            max_fee_rate,
+        )
+        .unwrap();
+    assert_eq!(resolution.raw_fee_rate, FeeRate::from_sat_per_vb_u32(5_000));
+    assert!(resolution.is_clamped());
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), max_fee_rate, @add_signature);
 }
 
 #[test]
-fn test_create_tx_absolute_zero_fee() {
+fn test_create_tx_fee_rate_in_units() {
+    use bdk_wallet::units::{BtcPerKvb, SatPerVb};
+
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
+    // 2.5 sat/vB is 625 sat/kwu
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_absolute(Amount::ZERO);
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(SatPerVb::from_msat(2_500));
     let psbt = builder.finish().unwrap();
     let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_kwu(625), @add_signature);
 
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::ZERO);
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(
+    // 0.00001001 BTC/kvB is rounded up to 251 sat/kwu
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(BtcPerKvb::new(Amount::from_sat(1_001)));
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_kwu(251), @add_signature);
+}
+
+#[test]
+fn test_create_tx_absolute_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_absolute(Amount::from_sat(100));
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(100));
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value,
+        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
+    );
+}
+
+#[test]
+fn test_create_tx_absolute_zero_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_absolute(Amount::ZERO);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::ZERO);
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(
         psbt.unsigned_tx.output[0].value,
         Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
     );
@@ -975,1011 +1925,1220 @@ fn test_create_tx_ordering_respected() {
 }
 
 #[test]
-fn test_create_tx_default_sighash() {
+fn test_create_tx_drain_to_change() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let change_addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
+        .unwrap()
+        .assume_checked();
+    let internal_index = wallet.derivation_index(KeychainKind::Internal);
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(30_000));
-    let psbt = builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .drain_to_change(change_addr.script_pubkey());
+    let (psbt, change) = builder.finish_with_change().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    let change = change.expect("must have change");
 
-    assert_eq!(psbt.inputs[0].sighash_type, None);
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output[change.index].script_pubkey,
+        change_addr.script_pubkey()
+    );
+    assert_eq!(
+        change.value,
+        Amount::from_sat(25_000) - fee.unwrap_or(Amount::ZERO)
+    );
+    assert_eq!(
+        tx.output[1 - change.index].script_pubkey,
+        addr.script_pubkey()
+    );
+    assert_eq!(tx.output[1 - change.index].value, Amount::from_sat(25_000));
+    // no internal address was used for the change
+    assert_eq!(
+        wallet.derivation_index(KeychainKind::Internal),
+        internal_index
+    );
 }
 
 #[test]
-fn test_create_tx_custom_sighash() {
+fn test_create_tx_change_position() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .sighash(EcdsaSighashType::Single.into());
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(
-        psbt.inputs[0].sighash_type,
-        Some(EcdsaSighashType::Single.into())
-    );
+    for position in 0..3 {
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+            .change_position(position);
+        let (psbt, change) = builder.finish_with_change().unwrap();
+        let fee = check_fee!(wallet, psbt);
+        let change = change.expect("must have change");
+
+        assert_eq!(change.index, position);
+        assert_eq!(
+            change.value,
+            Amount::from_sat(10_000) - fee.unwrap_or(Amount::ZERO)
+        );
+        assert_eq!(psbt.unsigned_tx.output[position].value, change.value);
+    }
 }
 
 #[test]
-fn test_create_tx_input_hd_keypaths() {
-    use bitcoin::bip32::{DerivationPath, Fingerprint};
-    use core::str::FromStr;
-
-    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+fn test_create_tx_change_position_out_of_range() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
-    assert_eq!(
-        psbt.inputs[0].bip32_derivation.values().next().unwrap(),
-        &(
-            Fingerprint::from_str("d34db33f").unwrap(),
-            DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
-        )
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .change_position(2);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::ChangePositionOutOfRange {
+            position: 2,
+            outputs: 2
+        })
     );
 }
 
-#[test]
-fn test_create_tx_output_hd_keypaths() {
-    use bitcoin::bip32::{DerivationPath, Fingerprint};
-    use core::str::FromStr;
+/// Select the smallest UTXOs first, until the target is met
+#[derive(Debug)]
+struct SmallestFirstCoinSelection;
+
+impl CoinSelectionAlgorithm for SmallestFirstCoinSelection {
+    fn coin_select<R: RngCore>(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        mut optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: Amount,
+        drain_script: &Script,
+        _rand: &mut R,
+    ) -> Result<CoinSelectionResult, coin_selection::Error> {
+        optional_utxos.sort_by_key(|wu| wu.utxo.txout().value);
+        let (mut selected_amount, mut fee_amount) = (Amount::ZERO, Amount::ZERO);
+        let mut selected = vec![];
+        for (required, wu) in required_utxos
+            .into_iter()
+            .map(|wu| (true, wu))
+            .chain(optional_utxos.into_iter().map(|wu| (false, wu)))
+        {
+            if !required && selected_amount >= target_amount + fee_amount {
+                break;
+            }
+            let weight =
+                TxIn::default().segwit_weight() + Weight::from_wu(wu.satisfaction_weight as u64);
+            fee_amount += fee_rate * weight;
+            selected_amount += wu.utxo.txout().value;
+            selected.push(wu.utxo);
+        }
+        let needed = target_amount + fee_amount;
+        if selected_amount < needed {
+            return Err(coin_selection::Error::InsufficientFunds {
+                needed,
+                available: selected_amount,
+            });
+        }
+        Ok(CoinSelectionResult {
+            selected,
+            fee_amount,
+            excess: decide_change(selected_amount - needed, fee_rate, drain_script),
+        })
+    }
+}
 
-    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+/// Branch and bound with the waste metric of `bdk_coin_select`, or one of its presets
+#[derive(Debug)]
+struct NurseryCoinSelection(Option<bdk_coin_select::SelectionPreset>);
+
+impl CoinSelectionAlgorithm for NurseryCoinSelection {
+    fn coin_select<R: RngCore>(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: Amount,
+        drain_script: &Script,
+        _rand: &mut R,
+    ) -> Result<CoinSelectionResult, coin_selection::Error> {
+        let required_count = required_utxos.len();
+        let utxos = required_utxos
+            .into_iter()
+            .chain(optional_utxos)
+            .collect::<Vec<_>>();
+        let candidates = utxos
+            .iter()
+            .map(|wu| {
+                let txout = wu.utxo.txout();
+                // the wallet's satisfaction weight doesn't include the length of the scriptSig
+                let candidate = bdk_coin_select::WeightedValue::new(
+                    txout.value.to_sat(),
+                    wu.satisfaction_weight as u32 + 4,
+                    txout.script_pubkey.is_witness_program(),
+                );
+                match &wu.utxo {
+                    Utxo::Local(LocalOutput {
+                        confirmation_time: ConfirmationTime::Confirmed { height, .. },
+                        ..
+                    }) => candidate.with_confirmation_height(*height),
+                    _ => candidate,
+                }
+            })
+            .collect::<Vec<_>>();
+        let drain_weight = TxOut {
+            value: Amount::ZERO,
+            script_pubkey: drain_script.into(),
+        }
+        .weight()
+        .to_wu() as u32;
+        let opts = bdk_coin_select::CoinSelectorOpt {
+            // the fee of the transaction without inputs is already part of the target
+            target_value: Some(target_amount.to_sat()),
+            max_extra_target: 0,
+            target_feerate: fee_rate.to_sat_per_kwu() as f32 / 1000.0,
+            long_term_feerate: None,
+            min_absolute_fee: 0,
+            base_weight: 0,
+            drain_weight,
+            spend_drain_weight: bdk_coin_select::TXIN_BASE_WEIGHT + 4 + 108,
+            min_drain_value: drain_script.minimal_non_dust().to_sat(),
+            max_weight: None,
+        };
 
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
+        let mut selector = bdk_coin_select::CoinSelector::new(&candidates, &opts);
+        (0..required_count).for_each(|i| {
+            selector.select(i);
+        });
+        let selection = match self.0 {
+            Some(preset) => selector.select_by_preset(preset),
+            None => bdk_coin_select::coin_select_bnb(10_000, selector.clone())
+                .unwrap_or(selector)
+                .select_until_finished(),
+        }
+        .map_err(|err| coin_selection::Error::Custom(err.to_string()))?;
 
-    assert_eq!(psbt.outputs[0].bip32_derivation.len(), 1);
-    let expected_derivation_path = format!("m/44'/0'/0'/0/{}", addr.index);
-    assert_eq!(
-        psbt.outputs[0].bip32_derivation.values().next().unwrap(),
-        &(
-            Fingerprint::from_str("d34db33f").unwrap(),
-            DerivationPath::from_str(&expected_derivation_path).unwrap()
-        )
-    );
+        let selected = selection
+            .apply_selection(&utxos)
+            .map(|wu| wu.utxo.clone())
+            .collect::<Vec<_>>();
+        let selected_amount = selected
+            .iter()
+            .map(|utxo| utxo.txout().value)
+            .sum::<Amount>();
+        // the excess is what is left once the inputs paid for their own fee
+        let excess = Amount::from_sat(selection.excess);
+        let fee_amount = selected_amount - target_amount - excess;
+        let excess = match selection.best_strategy().1.drain_value {
+            Some(amount) => Excess::Change {
+                amount: Amount::from_sat(amount),
+                fee: excess - Amount::from_sat(amount),
+            },
+            None => Excess::NoChange {
+                dust_threshold: Amount::from_sat(opts.min_drain_value),
+                remaining_amount: excess,
+                change_fee: Amount::from_sat(
+                    (drain_weight as f32 * opts.target_feerate).ceil() as u64
+                ),
+            },
+        };
+        Ok(CoinSelectionResult {
+            selected,
+            fee_amount,
+            excess,
+        })
+    }
 }
 
-#[test]
-fn test_create_tx_set_redeem_script_p2sh() {
-    use bitcoin::hex::FromHex;
-
-    let (mut wallet, _) =
-        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
+/// Select the candidates, plus a UTXO it was told about
+#[derive(Debug)]
+struct SneakyCoinSelection(Utxo);
+
+impl CoinSelectionAlgorithm for SneakyCoinSelection {
+    fn coin_select<R: RngCore>(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: Amount,
+        drain_script: &Script,
+        rand: &mut R,
+    ) -> Result<CoinSelectionResult, coin_selection::Error> {
+        let mut result = LargestFirstCoinSelection.coin_select(
+            required_utxos,
+            optional_utxos,
+            fee_rate,
+            target_amount,
+            drain_script,
+            rand,
+        )?;
+        result.selected.push(self.0.clone());
+        Ok(result)
+    }
+}
 
-    assert_eq!(
-        psbt.inputs[0].redeem_script,
-        Some(ScriptBuf::from(
-            Vec::<u8>::from_hex(
-                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-            )
-            .unwrap()
-        ))
-    );
-    assert_eq!(psbt.inputs[0].witness_script, None);
+fn get_wallet_with_small_utxos() -> (Wallet, Vec<OutPoint>) {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let outpoints = [7_000, 13_000, 21_000, 34_000]
+        .iter()
+        .map(|&value| receive_output_in_latest_block(&mut wallet, value))
+        .collect();
+    (wallet, outpoints)
 }
 
 #[test]
-fn test_create_tx_set_witness_script_p2wsh() {
-    use bitcoin::hex::FromHex;
-
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+fn test_custom_coin_selection() {
+    let (mut wallet, small) = get_wallet_with_small_utxos();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(SmallestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .fee_rate(FeeRate::from_sat_per_vb_u32(2));
+    let estimate = builder.estimate().unwrap();
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.inputs[0].redeem_script, None);
-    assert_eq!(
-        psbt.inputs[0].witness_script,
-        Some(ScriptBuf::from(
-            Vec::<u8>::from_hex(
-                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-            )
-            .unwrap()
+    let inputs = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect::<BTreeSet<_>>();
+    assert_eq!(inputs, small[..3].iter().copied().collect());
+    assert_eq!(estimate.fee, psbt.fee().unwrap());
+    assert!(estimate.fee_rate >= FeeRate::from_sat_per_vb_u32(2));
+
+    // the errors of the algorithm are those of the builder
+    let mut builder = wallet.build_tx().coin_selection(SmallestFirstCoinSelection);
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(1_000_000));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
         ))
     );
 }
 
 #[test]
-fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-
-    let script = ScriptBuf::from_hex(
-        "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
-    )
-    .unwrap();
+fn test_nursery_coin_selection() {
+    let (mut wallet, _) = get_wallet_with_small_utxos();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    for amount in [5_000, 20_000, 33_000, 70_000, 120_000] {
+        let mut builder = wallet.build_tx().coin_selection(NurseryCoinSelection(None));
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(amount))
+            .fee_rate(FeeRate::from_sat_per_vb_u32(3));
+        let estimate = builder.estimate().unwrap();
+        let psbt = builder.finish().unwrap();
+        assert_eq!(estimate.fee, psbt.fee().unwrap());
+        assert!(estimate.fee_rate >= FeeRate::from_sat_per_vb_u32(3));
+    }
 
-    assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_p2wsh()));
-    assert_eq!(psbt.inputs[0].witness_script, Some(script));
+    let mut builder = wallet.build_tx().coin_selection(NurseryCoinSelection(None));
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(1_000_000));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(coin_selection::Error::Custom(
+            _
+        )))
+    );
 }
 
 #[test]
-fn test_create_tx_non_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
+fn test_selection_preset() {
+    // the funding output of 50_000 sats is confirmed at height 2_000
+    let (mut wallet, funding_txid) = get_funded_wallet_wpkh();
+    let funding = OutPoint::new(funding_txid, 0);
+    let confirmed = |height| ConfirmationTime::Confirmed { height, time: 0 };
+    let early = receive_output(&mut wallet, 30_000, confirmed(10));
+    let late = receive_output(&mut wallet, 30_001, confirmed(500));
+    let unconfirmed = receive_output(
+        &mut wallet,
+        30_002,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
+    );
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let inputs = |psbt: &bitcoin::Psbt| {
+        psbt.unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<BTreeSet<_>>()
+    };
 
-    assert!(psbt.inputs[0].non_witness_utxo.is_some());
-    assert!(psbt.inputs[0].witness_utxo.is_none());
-}
+    for (preset, nursery_preset, expected) in [
+        (
+            SelectionPreset::OldestFirst,
+            bdk_coin_select::SelectionPreset::OldestFirst,
+            [early, late],
+        ),
+        (
+            SelectionPreset::NewestFirst,
+            bdk_coin_select::SelectionPreset::NewestFirst,
+            [unconfirmed, funding],
+        ),
+    ] {
+        let mut builder = wallet.build_tx().coin_selection(preset);
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+            .fee_rate(FeeRate::from_sat_per_vb_u32(2));
+        let psbt = builder.finish().unwrap();
+        assert_eq!(inputs(&psbt), expected.into_iter().collect());
+        assert!(psbt.fee_rate().unwrap() >= FeeRate::from_sat_per_vb_u32(2));
+
+        // the nursery presets pick the same candidates
+        let mut builder = wallet
+            .build_tx()
+            .coin_selection(NurseryCoinSelection(Some(nursery_preset)));
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+            .fee_rate(FeeRate::from_sat_per_vb_u32(2));
+        let psbt = builder.finish().unwrap();
+        assert_eq!(inputs(&psbt), expected.into_iter().collect());
+    }
 
-#[test]
-fn test_create_tx_only_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .only_witness_utxo()
-        .drain_wallet();
+    let mut builder = wallet
+        .build_tx()
+        .coin_selection(SelectionPreset::LargestFirst);
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(45_000));
     let psbt = builder.finish().unwrap();
-
-    assert!(psbt.inputs[0].non_witness_utxo.is_none());
-    assert!(psbt.inputs[0].witness_utxo.is_some());
+    assert_eq!(inputs(&psbt), [funding].into_iter().collect());
 }
 
 #[test]
-fn test_create_tx_shwpkh_has_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-
-    assert!(psbt.inputs[0].witness_utxo.is_some());
-}
-
-#[test]
-fn test_create_tx_both_non_witness_utxo_and_witness_utxo_default() {
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-
-    assert!(psbt.inputs[0].non_witness_utxo.is_some());
-    assert!(psbt.inputs[0].witness_utxo.is_some());
-}
-
-#[test]
-fn test_create_tx_add_utxo() {
+fn test_add_keychain_sweep_legacy() {
+    let legacy_desc = "pkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/44'/1'/0'/0/*)";
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let small_output_tx = Transaction {
+    let secp = Secp256k1::new();
+    let (legacy_desc_public, _) =
+        bdk_wallet::descriptor::Descriptor::parse_descriptor(&secp, legacy_desc).unwrap();
+    let legacy_spk = legacy_desc_public
+        .at_derivation_index(0)
+        .unwrap()
+        .script_pubkey();
+
+    // funds which arrived on the legacy descriptor before the wallet tracked it
+    let legacy_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
         input: vec![],
         output: vec![TxOut {
-            script_pubkey: wallet
-                .next_unused_address(KeychainKind::External)
-                .script_pubkey(),
-            value: Amount::from_sat(25_000),
+            script_pubkey: legacy_spk.clone(),
+            value: Amount::from_sat(30_000),
         }],
-        version: transaction::Version::non_standard(0),
-        lock_time: absolute::LockTime::ZERO,
     };
+    let legacy_outpoint = OutPoint::new(legacy_tx.compute_txid(), 0);
     wallet
         .insert_tx(
-            small_output_tx.clone(),
-            ConfirmationTime::Unconfirmed { last_seen: 0 },
+            legacy_tx,
+            ConfirmationTime::Confirmed {
+                height: 2_000,
+                time: 0,
+            },
         )
         .unwrap();
+    assert_eq!(wallet.balance().confirmed, Amount::from_sat(50_000));
+
+    let legacy = wallet
+        .add_keychain("legacy".to_string(), legacy_desc)
+        .unwrap();
+    assert_eq!(
+        legacy,
+        KeychainKind::Extra(KeychainLabel::new("legacy").unwrap())
+    );
+    assert_matches!(
+        wallet.add_keychain("legacy".to_string(), get_test_tr_single_sig()),
+        Err(AddKeychainError::KeychainAlreadyAssigned(k)) if k == legacy
+    );
+    assert_matches!(
+        wallet.add_keychain("again".to_string(), legacy_desc),
+        Err(AddKeychainError::DescriptorAlreadyAssigned(k)) if k == legacy
+    );
+    assert_matches!(
+        wallet.add_keychain(String::new(), get_test_tr_single_sig()),
+        Err(AddKeychainError::InvalidLabel(_))
+    );
+    // the bytes of the external and internal keychains
+    for label in ["e", "i"] {
+        assert_matches!(
+            wallet.add_keychain(label.to_string(), get_test_tr_single_sig()),
+            Err(AddKeychainError::InvalidLabel(_))
+        );
+    }
+
+    // the transaction already known is indexed
+    assert_eq!(wallet.derivation_of_spk(&legacy_spk), Some((legacy, 0)));
+    assert_eq!(wallet.derivation_index(legacy), Some(0));
+    assert_eq!(
+        wallet.reveal_next_address(legacy).address.script_pubkey(),
+        legacy_desc_public
+            .at_derivation_index(1)
+            .unwrap()
+            .script_pubkey()
+    );
+    assert_eq!(wallet.balance().confirmed, Amount::from_sat(80_000));
+    let balances = wallet.balance_by_keychain();
+    assert_eq!(balances[&legacy].confirmed, Amount::from_sat(30_000));
+    assert_eq!(
+        balances[&KeychainKind::External].confirmed,
+        Amount::from_sat(50_000)
+    );
+    assert_eq!(balances[&KeychainKind::Internal], Balance::default());
+
+    // the legacy scripts are synced
+    assert!(wallet
+        .start_sync_with_revealed_spks()
+        .spks
+        .any(|spk| spk == legacy_spk));
+    assert!(wallet
+        .start_full_scan()
+        .spks_by_keychain
+        .contains_key(&legacy));
 
+    // the legacy output is a coin selection candidate, its key signs, change goes to the primary
+    // internal keychain
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(70_000));
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 2);
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let change = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .find(|txout| txout.script_pubkey != addr.script_pubkey())
+        .unwrap();
+    assert_matches!(
+        wallet.derivation_of_spk(&change.script_pubkey),
+        Some((KeychainKind::Internal, _))
+    );
+    wallet.cancel_tx(&psbt.unsigned_tx);
+
+    // sweep the legacy keychain into the primary one
+    let sweep_to = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .add_utxo(OutPoint {
-            txid: small_output_tx.compute_txid(),
-            vout: 0,
-        })
+        .add_utxo(legacy_outpoint)
+        .unwrap()
+        .manually_selected_only()
+        .drain_to(sweep_to.script_pubkey());
+    let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let sweep = psbt.extract_tx().unwrap();
+    let fee = wallet.calculate_fee(&sweep).unwrap();
+    wallet
+        .insert_tx(
+            sweep.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 1 },
+        )
         .unwrap();
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
 
+    // an internal transfer, only the fee leaves the wallet
+    let (sent, received) = wallet.sent_and_received(&sweep);
+    assert_eq!(sent, Amount::from_sat(30_000));
+    assert_eq!(received, Amount::from_sat(30_000) - fee);
+    let balances = wallet.balance_by_keychain();
+    assert_eq!(balances[&legacy], Balance::default());
     assert_eq!(
-        psbt.unsigned_tx.input.len(),
-        2,
-        "should add an additional input since 25_000 < 30_000"
+        balances[&KeychainKind::External].total(),
+        Amount::from_sat(80_000) - fee
     );
+
+    // the keychain is persisted
+    let changeset = wallet.take_staged().unwrap();
+    let changeset: ChangeSet =
+        serde_json::from_str(&serde_json::to_string(&changeset).unwrap()).unwrap();
+    let loaded = Wallet::load_from_changeset(changeset).unwrap();
     assert_eq!(
-        sent_received.0,
-        Amount::from_sat(75_000),
-        "total should be sum of both inputs"
+        loaded.keychains().collect::<Vec<_>>(),
+        wallet.keychains().collect::<Vec<_>>()
     );
+    assert_eq!(loaded.balance_by_keychain(), wallet.balance_by_keychain());
+    assert_eq!(loaded.derivation_index(legacy), Some(1));
+    // the private keys aren't
+    assert!(loaded.get_signers(legacy).signers().is_empty());
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_create_tx_manually_selected_insufficient() {
+fn test_descriptor_history() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let small_output_tx = Transaction {
-        input: vec![],
-        output: vec![TxOut {
-            script_pubkey: wallet
-                .next_unused_address(KeychainKind::External)
-                .script_pubkey(),
-            value: Amount::from_sat(25_000),
-        }],
-        version: transaction::Version::non_standard(0),
-        lock_time: absolute::LockTime::ZERO,
-    };
+    let tip_height = wallet.latest_checkpoint().height();
+    let history = wallet.descriptor_history(KeychainKind::External);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].revision, 0);
+    assert_eq!(
+        history[0].descriptor.as_ref(),
+        Some(wallet.get_descriptor_for_keychain(KeychainKind::External))
+    );
+    assert_eq!(
+        history[0].descriptor_id,
+        wallet
+            .get_descriptor_for_keychain(KeychainKind::External)
+            .descriptor_id()
+    );
+    // the wallet is created before the blocks of its chain are known
+    assert_eq!(history[0].activation_height, Some(0));
 
-    wallet
-        .insert_tx(
-            small_output_tx.clone(),
-            ConfirmationTime::Unconfirmed { last_seen: 0 },
-        )
+    // the revealed addresses belong to the revision
+    let address = wallet.reveal_next_address(KeychainKind::External);
+    assert_eq!(address.revision, 0);
+    assert!(wallet
+        .list_addresses(KeychainKind::External, 0..5)
+        .all(|address| address.revision == 0));
+    assert_eq!(
+        wallet.descriptor_history(KeychainKind::External)[0].last_revealed,
+        Some(address.index)
+    );
+
+    // an added keychain starts at the tip of the chain
+    let legacy = wallet
+        .add_keychain("legacy".to_string(), get_test_tr_single_sig())
         .unwrap();
+    let history = wallet.descriptor_history(legacy);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].activation_height, Some(tip_height));
+    assert_eq!(history[0].last_revealed, None);
+
+    // the revisions are loaded as they were recorded
+    let changeset = wallet.take_staged().unwrap();
+    let loaded = Wallet::load_from_changeset(changeset.clone()).unwrap();
+    assert_eq!(loaded.staged(), None);
+    assert_eq!(loaded.descriptor_history(legacy), history);
+
+    // the changesets persisted before revisions were recorded get a synthesized revision 0
+    let mut legacy_changeset = changeset;
+    legacy_changeset.descriptor_revisions.clear();
+    let loaded = Wallet::load_from_changeset(legacy_changeset).unwrap();
+    for keychain in [KeychainKind::External, KeychainKind::Internal, legacy] {
+        let history = loaded.descriptor_history(keychain);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].revision, 0);
+        assert_eq!(history[0].activation_height, None);
+        assert_eq!(
+            history[0].descriptor.as_ref(),
+            Some(loaded.get_descriptor_for_keychain(keychain))
+        );
+    }
+    assert_eq!(
+        loaded.descriptor_history(KeychainKind::External)[0].last_revealed,
+        Some(address.index)
+    );
+    let staged = loaded.staged().unwrap();
+    assert_eq!(staged.descriptor_revisions.len(), 3);
+}
 
+#[test]
+fn test_custom_coin_selection_respects_candidates() {
+    let (mut wallet, small) = get_wallet_with_small_utxos();
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx();
+    let utxo = |wallet: &Wallet, outpoint| Utxo::Local(wallet.get_utxo(outpoint).unwrap());
+
+    // an unspendable UTXO
+    let sneaky = SneakyCoinSelection(utxo(&wallet, small[0]));
+    let mut builder = wallet.build_tx().coin_selection(sneaky);
     builder
         .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .add_utxo(OutPoint {
-            txid: small_output_tx.compute_txid(),
-            vout: 0,
-        })
+        .add_unspendable(small[0]);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(coin_selection::Error::NotACandidate(op))) if op == small[0]
+    );
+
+    // a UTXO which is not manually selected
+    let sneaky = SneakyCoinSelection(utxo(&wallet, small[0]));
+    let mut builder = wallet.build_tx().coin_selection(sneaky);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
+        .add_utxo(small[3])
         .unwrap()
         .manually_selected_only();
-    builder.finish().unwrap();
-}
-
-#[test]
-#[should_panic(expected = "SpendingPolicyRequired(External)")]
-fn test_create_tx_policy_path_required() {
-    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(coin_selection::Error::NotACandidate(op))) if op == small[0]
+    );
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+    // a UTXO which already is selected
+    let sneaky = SneakyCoinSelection(utxo(&wallet, small[3]));
+    let mut builder = wallet.build_tx().coin_selection(sneaky);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
+        .add_utxo(small[3])
         .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
-    builder.finish().unwrap();
+        .manually_selected_only();
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(coin_selection::Error::NotACandidate(op))) if op == small[3]
+    );
 }
 
-#[test]
-fn test_create_tx_policy_path_no_csv() {
-    let (desc, change_desc) = get_test_wpkh_with_change_desc();
-    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest).expect("wallet");
-
-    let tx = Transaction {
-        version: transaction::Version::non_standard(0),
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![],
-        output: vec![TxOut {
-            script_pubkey: wallet
-                .next_unused_address(KeychainKind::External)
-                .script_pubkey(),
-            value: Amount::from_sat(50_000),
-        }],
-    };
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #0 is just the key "A"
-    let path = vec![(root_id, vec![0])].into_iter().collect();
-
+/// The funded wallet with an unconfirmed payment, for the history export golden files
+fn get_history_fixture_wallet() -> Wallet {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .policy_path(path, KeychainKind::External);
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFF));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .fee_rate(FeeRate::from_sat_per_vb_u32(2))
+        .deterministic([7; 32]);
+    let tx = builder.finish().unwrap().unsigned_tx;
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 300 })
+        .unwrap();
+    wallet
 }
 
 #[test]
-fn test_create_tx_policy_path_use_csv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+fn test_export_history_golden() {
+    use bdk_wallet::wallet::history::HistoryFormat;
 
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #1 is or(pk(B),older(144))
-    let path = vec![(root_id, vec![1])].into_iter().collect();
+    let wallet = get_history_fixture_wallet();
+    for (format, expected) in [
+        (HistoryFormat::JsonLines, include_str!("data/history.jsonl")),
+        (HistoryFormat::Csv, include_str!("data/history.csv")),
+    ] {
+        let mut exported = Vec::new();
+        wallet.export_history(&mut exported, format).unwrap();
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            expected,
+            "{:?}",
+            format
+        );
+    }
+}
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+#[test]
+fn test_deterministic_build() {
+    let wallet = || {
+        let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+        for value in [7_000, 13_000, 21_000, 34_000, 55_000, 89_000] {
+            receive_output_in_latest_block(&mut wallet, value);
+        }
+        wallet
+    };
+    let alice = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .policy_path(path, KeychainKind::External);
-    let psbt = builder.finish().unwrap();
+    let bob = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
+        .unwrap()
+        .assume_checked();
+    let build = |wallet: &mut Wallet, seed: [u8; 32]| {
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(alice.script_pubkey(), Amount::from_sat(100_000))
+            .add_recipient(bob.script_pubkey(), Amount::from_sat(20_000))
+            .fee_rate(FeeRate::from_sat_per_vb_u32(3))
+            .deterministic(seed);
+        let estimate = builder.estimate().unwrap();
+        let psbt = builder.finish().unwrap();
+        assert_eq!(estimate.fee, psbt.fee().unwrap());
+        bitcoin::consensus::serialize(&psbt.unsigned_tx)
+    };
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(144));
+    // two parties with the same wallet state get identical transactions
+    let tx = build(&mut wallet(), [1; 32]);
+    assert_eq!(build(&mut wallet(), [1; 32]), tx);
+
+    // other seeds give other orderings or selections
+    let txs = (2..10u8)
+        .map(|seed| build(&mut wallet(), [seed; 32]))
+        .collect::<BTreeSet<_>>();
+    assert!(txs.iter().any(|other| *other != tx));
 }
 
 #[test]
-fn test_create_tx_policy_path_ignored_subtree_with_csv() {
-    let (mut wallet, _) = get_funded_wallet("wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),or_i(and_v(v:pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(30)),and_v(v:pkh(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),older(90)))))");
-
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #0 is pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)
-    let path = vec![(root_id, vec![0])].into_iter().collect();
-
+fn test_estimate_matches_finish() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    for value in [7_000, 13_000, 21_000, 34_000, 55_000, 89_000] {
+        receive_output_in_latest_block(&mut wallet, value);
+    }
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .policy_path(path, KeychainKind::External);
-    let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
+    let mut selections = BTreeSet::new();
+    for seed in 0..16 {
+        let internal_index = wallet.derivation_index(KeychainKind::Internal);
+        let staged = wallet.staged().cloned();
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(100_000))
+            .fee_rate(FeeRate::from_sat_per_vb_u32(3));
+        let estimate = builder
+            .estimate_with_aux_rand(&mut StdRng::seed_from_u64(seed))
+            .unwrap();
+        // estimating doesn't change the wallet
+        assert_eq!(
+            wallet.derivation_index(KeychainKind::Internal),
+            internal_index
+        );
+        assert_eq!(wallet.staged().cloned(), staged);
+
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(100_000))
+            .fee_rate(FeeRate::from_sat_per_vb_u32(3));
+        let (mut psbt, change) = builder
+            .finish_with_aux_rand(&mut StdRng::seed_from_u64(seed))
+            .unwrap();
+        let tx = &psbt.unsigned_tx;
+        let input_value = tx
+            .input
+            .iter()
+            .map(|txin| wallet.get_utxo(txin.previous_output).unwrap().txout.value)
+            .sum::<Amount>();
+        assert_eq!(estimate.fee, psbt.fee().unwrap());
+        assert_eq!(estimate.input_value, input_value);
+        assert_eq!(estimate.change, change);
+        assert_eq!(
+            estimate.selected,
+            tx.input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect::<Vec<_>>()
+        );
+
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        let signed_tx = psbt.extract_tx().unwrap();
+        assert!(signed_tx.weight() <= estimate.weight);
+        assert_eq!(estimate.fee_rate, estimate.fee / estimate.weight);
+        selections.insert(estimate.selected.into_iter().collect::<BTreeSet<_>>());
+        wallet.cancel_tx(&signed_tx);
+    }
+    // the coin selection is random, but the estimate always matches
+    assert!(selections.len() > 1);
 }
 
 #[test]
-fn test_create_tx_global_xpubs_with_origin() {
-    use bitcoin::bip32;
-    let (mut wallet, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+fn test_estimate_errors() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .add_global_xpubs();
-    let psbt = builder.finish().unwrap();
-
-    let key = bip32::Xpub::from_str("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
-    let fingerprint = bip32::Fingerprint::from_hex("73756c7f").unwrap();
-    let path = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(1_000_000));
+    assert_matches!(
+        builder.estimate(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
+    assert_matches!(
+        wallet.build_tx().estimate(),
+        Err(CreateTxError::InvalidParams(errors)) if errors == vec![ParamError::NoRecipients]
+    );
+}
 
-    assert_eq!(psbt.xpub.len(), 2);
-    assert_eq!(psbt.xpub.get(&key), Some(&(fingerprint, path)));
+#[test]
+fn test_create_tx_change_position_bip69() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .ordering(bdk_wallet::wallet::tx_builder::TxOrdering::Bip69Lexicographic)
+        .change_position(0);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::InvalidParams(errors))
+            if errors == vec![ParamError::ChangePositionBip69 { position: 0 }]
+    );
 }
 
 #[test]
-fn test_add_foreign_utxo() {
-    let (mut wallet1, _) = get_funded_wallet_wpkh();
-    let (wallet2, _) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+fn test_create_tx_validate_param_matrix() {
+    use bdk_wallet::wallet::tx_builder::TxOrdering;
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let spk = addr.script_pubkey();
+    let utxo = OutPoint::new(txid, 0);
+    let fee_rate = FeeRate::from_sat_per_vb_u32(2);
+    let change_index = wallet.derivation_index(KeychainKind::Internal);
+
+    // the consistent combinations
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(spk.clone(), Amount::from_sat(25_000));
+    assert_eq!(builder.validate(), Ok(()));
+    builder
+        .drain_to(spk.clone())
+        .drain_wallet()
+        .fee_rate(fee_rate);
+    assert_eq!(builder.validate(), Ok(()));
+    let mut builder = wallet.build_tx();
+    builder.drain_to(spk.clone()).drain_wallet();
+    assert_eq!(builder.validate(), Ok(()));
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(spk.clone())
+        .add_utxo(utxo)
         .unwrap()
-        .assume_checked();
-    let utxo = wallet2.list_unspent().next().expect("must take!");
-    let foreign_utxo_satisfaction = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+        .manually_selected_only()
+        .fee_absolute(Amount::from_sat(500))
+        .fee_absolute(Amount::from_sat(1_000));
+    assert_eq!(builder.validate(), Ok(()));
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(spk.clone(), Amount::from_sat(25_000))
+        .change_position(0)
+        .ordering(TxOrdering::Untouched)
+        .enable_rbf_with_sequence(Sequence::ENABLE_RBF_NO_LOCKTIME)
+        .version(2);
+    assert_eq!(builder.validate(), Ok(()));
+
+    // a drain_to without anything to drain
+    let mut builder = wallet.build_tx();
+    builder.drain_to(spk.clone());
+    assert_eq!(builder.validate(), Err(vec![ParamError::NoRecipients]));
 
-    let psbt_input = psbt::Input {
-        witness_utxo: Some(utxo.txout.clone()),
-        ..Default::default()
-    };
+    // a fee rate of zero with an absolute fee, in both orders
+    for rate_first in [true, false] {
+        let mut builder = wallet.build_tx();
+        builder.add_recipient(spk.clone(), Amount::from_sat(25_000));
+        if rate_first {
+            builder
+                .fee_rate(FeeRate::ZERO)
+                .fee_absolute(Amount::from_sat(1_000));
+        } else {
+            builder
+                .fee_absolute(Amount::from_sat(1_000))
+                .fee_rate(FeeRate::ZERO);
+        }
+        assert_eq!(
+            builder.validate(),
+            Err(vec![ParamError::ConflictingFees {
+                fee_rate: FeeRate::ZERO,
+                fee_absolute: Amount::from_sat(1_000),
+            }])
+        );
+    }
 
-    let mut builder = wallet1.build_tx();
+    // drain_to and drain_wallet with an empty manual selection
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
-        .only_witness_utxo()
-        .add_foreign_utxo(
-            utxo.outpoint,
-            psbt_input,
-            foreign_utxo_satisfaction.to_wu() as usize,
-        )
-        .unwrap();
-    let mut psbt = builder.finish().unwrap();
-    wallet1.insert_txout(utxo.outpoint, utxo.txout);
-    let fee = check_fee!(wallet1, psbt);
-    let sent_received =
-        wallet1.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-
-    assert_eq!(
-        (sent_received.0 - sent_received.1),
-        Amount::from_sat(10_000) + fee.unwrap_or(Amount::ZERO),
-        "we should have only net spent ~10_000"
+        .drain_to(spk.clone())
+        .drain_wallet()
+        .manually_selected_only();
+    assert_eq!(builder.validate(), Err(vec![ParamError::NoUtxosSelected]));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::InvalidParams(errors)) if errors == vec![ParamError::NoUtxosSelected]
     );
 
-    assert!(
-        psbt.unsigned_tx
-            .input
-            .iter()
-            .any(|input| input.previous_output == utxo.outpoint),
-        "foreign_utxo should be in there"
+    // all the problems are reported at once, before the coin selection
+    let mut builder = wallet.build_tx();
+    builder
+        .manually_selected_only()
+        .fee_rate(fee_rate)
+        .fee_absolute(Amount::from_sat(1_000))
+        .version(0)
+        .enable_rbf_with_sequence(Sequence::MAX)
+        .change_position(1)
+        .ordering(TxOrdering::Bip69Lexicographic);
+    let expected = vec![
+        ParamError::ConflictingFees {
+            fee_rate,
+            fee_absolute: Amount::from_sat(1_000),
+        },
+        ParamError::Version0,
+        ParamError::RbfSequence(Sequence::MAX),
+        ParamError::NoUtxosSelected,
+        ParamError::NoRecipients,
+        ParamError::ChangePositionBip69 { position: 1 },
+    ];
+    assert_eq!(builder.validate(), Err(expected.clone()));
+    assert_matches!(
+        builder.estimate(),
+        Err(CreateTxError::InvalidParams(errors)) if errors == expected
+    );
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::InvalidParams(errors)) if errors == expected
+    );
+    // nothing was revealed
+    assert_eq!(
+        wallet.derivation_index(KeychainKind::Internal),
+        change_index
     );
+}
 
-    let finished = wallet1
-        .sign(
-            &mut psbt,
-            SignOptions {
-                trust_witness_utxo: true,
-                ..Default::default()
-            },
-        )
+#[test]
+fn test_cancel_with_absolute_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
 
-    assert!(
-        !finished,
-        "only one of the inputs should have been signed so far"
-    );
-
-    let finished = wallet2
-        .sign(
-            &mut psbt,
-            SignOptions {
-                trust_witness_utxo: true,
-                ..Default::default()
-            },
-        )
+    // the fee rate set by the cancel is replaced, not in conflict
+    let mut builder = wallet
+        .build_cancel(txid, FeeRate::from_sat_per_vb_u32(5))
         .unwrap();
-    assert!(finished, "all the inputs should have been signed now");
+    builder.fee_absolute(Amount::from_sat(5_000));
+    assert_eq!(builder.validate(), Ok(()));
 }
 
 #[test]
-#[should_panic(
-    expected = "MissingTxOut([OutPoint { txid: 21d7fb1bceda00ab4069fc52d06baa13470803e9050edd16f5736e5d8c4925fd, vout: 0 }])"
-)]
-fn test_calculate_fee_with_missing_foreign_utxo() {
-    let (mut wallet1, _) = get_funded_wallet_wpkh();
-    let (wallet2, _) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let utxo = wallet2.list_unspent().next().expect("must take!");
-    let foreign_utxo_satisfaction = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+fn test_create_tx_default_sighash() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(30_000));
+    let psbt = builder.finish().unwrap();
 
-    let psbt_input = psbt::Input {
-        witness_utxo: Some(utxo.txout.clone()),
-        ..Default::default()
-    };
+    assert_eq!(psbt.inputs[0].sighash_type, None);
+}
 
-    let mut builder = wallet1.build_tx();
+#[test]
+fn test_create_tx_custom_sighash() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
-        .only_witness_utxo()
-        .add_foreign_utxo(
-            utxo.outpoint,
-            psbt_input,
-            foreign_utxo_satisfaction.to_wu() as usize,
-        )
-        .unwrap();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .sighash(EcdsaSighashType::Single.into());
     let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    wallet1.calculate_fee(&tx).unwrap();
+
+    assert_eq!(
+        psbt.inputs[0].sighash_type,
+        Some(EcdsaSighashType::Single.into())
+    );
 }
 
 #[test]
-fn test_add_foreign_utxo_invalid_psbt_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let outpoint = wallet.list_unspent().next().expect("must exist").outpoint;
-    let foreign_utxo_satisfaction = wallet
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+fn test_create_tx_input_hd_keypaths() {
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use core::str::FromStr;
 
+    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    let result = builder.add_foreign_utxo(
-        outpoint,
-        psbt::Input::default(),
-        foreign_utxo_satisfaction.to_wu() as usize,
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
+    assert_eq!(
+        psbt.inputs[0].bip32_derivation.values().next().unwrap(),
+        &(
+            Fingerprint::from_str("d34db33f").unwrap(),
+            DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
+        )
     );
-    assert!(matches!(result, Err(AddForeignUtxoError::MissingUtxo)));
 }
 
 #[test]
-fn test_add_foreign_utxo_where_outpoint_doesnt_match_psbt_input() {
-    let (mut wallet1, txid1) = get_funded_wallet_wpkh();
-    let (wallet2, txid2) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+fn test_create_tx_output_hd_keypaths() {
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use core::str::FromStr;
 
-    let utxo2 = wallet2.list_unspent().next().unwrap();
-    let tx1 = wallet1.get_tx(txid1).unwrap().tx_node.tx.clone();
-    let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx.clone();
+    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
 
-    let satisfaction_weight = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    let mut builder = wallet1.build_tx();
-    assert!(
-        builder
-            .add_foreign_utxo(
-                utxo2.outpoint,
-                psbt::Input {
-                    non_witness_utxo: Some(tx1.as_ref().clone()),
-                    ..Default::default()
-                },
-                satisfaction_weight.to_wu() as usize
-            )
-            .is_err(),
-        "should fail when outpoint doesn't match psbt_input"
-    );
-    assert!(
-        builder
-            .add_foreign_utxo(
-                utxo2.outpoint,
-                psbt::Input {
-                    non_witness_utxo: Some(tx2.as_ref().clone()),
-                    ..Default::default()
-                },
-                satisfaction_weight.to_wu() as usize
-            )
-            .is_ok(),
-        "should be ok when outpoint does match psbt_input"
+    assert_eq!(psbt.outputs[0].bip32_derivation.len(), 1);
+    let expected_derivation_path = format!("m/44'/0'/0'/0/{}", addr.index);
+    assert_eq!(
+        psbt.outputs[0].bip32_derivation.values().next().unwrap(),
+        &(
+            Fingerprint::from_str("d34db33f").unwrap(),
+            DerivationPath::from_str(&expected_derivation_path).unwrap()
+        )
     );
 }
 
 #[test]
-fn test_add_foreign_utxo_only_witness_utxo() {
-    let (mut wallet1, _) = get_funded_wallet_wpkh();
-    let (wallet2, txid2) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let utxo2 = wallet2.list_unspent().next().unwrap();
-
-    let satisfaction_weight = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
-
-    let mut builder = wallet1.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(60_000));
-
-    {
-        let mut builder = builder.clone();
-        let psbt_input = psbt::Input {
-            witness_utxo: Some(utxo2.txout.clone()),
-            ..Default::default()
-        };
-        builder
-            .add_foreign_utxo(
-                utxo2.outpoint,
-                psbt_input,
-                satisfaction_weight.to_wu() as usize,
-            )
-            .unwrap();
-        assert!(
-            builder.finish().is_err(),
-            "psbt_input with witness_utxo should fail with only witness_utxo"
-        );
-    }
+fn test_create_tx_set_redeem_script_p2sh() {
+    use bitcoin::hex::FromHex;
 
-    {
-        let mut builder = builder.clone();
-        let psbt_input = psbt::Input {
-            witness_utxo: Some(utxo2.txout.clone()),
-            ..Default::default()
-        };
-        builder
-            .only_witness_utxo()
-            .add_foreign_utxo(
-                utxo2.outpoint,
-                psbt_input,
-                satisfaction_weight.to_wu() as usize,
-            )
-            .unwrap();
-        assert!(
-            builder.finish().is_ok(),
-            "psbt_input with just witness_utxo should succeed when `only_witness_utxo` is enabled"
-        );
-    }
+    let (mut wallet, _) =
+        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    {
-        let mut builder = builder.clone();
-        let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx;
-        let psbt_input = psbt::Input {
-            non_witness_utxo: Some(tx2.as_ref().clone()),
-            ..Default::default()
-        };
-        builder
-            .add_foreign_utxo(
-                utxo2.outpoint,
-                psbt_input,
-                satisfaction_weight.to_wu() as usize,
+    assert_eq!(
+        psbt.inputs[0].redeem_script,
+        Some(ScriptBuf::from(
+            Vec::<u8>::from_hex(
+                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
             )
-            .unwrap();
-        assert!(
-            builder.finish().is_ok(),
-            "psbt_input with non_witness_utxo should succeed by default"
-        );
-    }
-}
-
-#[test]
-fn test_get_psbt_input() {
-    // this should grab a known good utxo and set the input
-    let (wallet, _) = get_funded_wallet_wpkh();
-    for utxo in wallet.list_unspent() {
-        let psbt_input = wallet.get_psbt_input(utxo, None, false).unwrap();
-        assert!(psbt_input.witness_utxo.is_some() || psbt_input.non_witness_utxo.is_some());
-    }
+            .unwrap()
+        ))
+    );
+    assert_eq!(psbt.inputs[0].witness_script, None);
 }
 
 #[test]
-#[should_panic(
-    expected = "MissingKeyOrigin(\"tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3\")"
-)]
-fn test_create_tx_global_xpubs_origin_missing() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .add_global_xpubs();
-    builder.finish().unwrap();
-}
+fn test_create_tx_set_witness_script_p2wsh() {
+    use bitcoin::hex::FromHex;
 
-#[test]
-fn test_create_tx_global_xpubs_master_without_origin() {
-    use bitcoin::bip32;
-    let (mut wallet, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*)");
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .add_global_xpubs();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
 
-    let key = bip32::Xpub::from_str("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
-    let fingerprint = bip32::Fingerprint::from_hex("997a323b").unwrap();
-
-    assert_eq!(psbt.xpub.len(), 2);
+    assert_eq!(psbt.inputs[0].redeem_script, None);
     assert_eq!(
-        psbt.xpub.get(&key),
-        Some(&(fingerprint, bip32::DerivationPath::default()))
+        psbt.inputs[0].witness_script,
+        Some(ScriptBuf::from(
+            Vec::<u8>::from_hex(
+                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+            )
+            .unwrap()
+        ))
     );
 }
 
 #[test]
-#[should_panic(expected = "IrreplaceableTransaction")]
-fn test_bump_fee_irreplaceable_tx() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
+fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
 
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    let script = ScriptBuf::from_hex(
+        "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
+    )
+    .unwrap();
+
+    assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_p2wsh()));
+    assert_eq!(psbt.inputs[0].witness_script, Some(script));
 }
 
 #[test]
-#[should_panic(expected = "TransactionConfirmed")]
-fn test_bump_fee_confirmed_tx() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
+fn test_create_tx_non_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
 
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
-
-    wallet
-        .insert_tx(
-            tx,
-            ConfirmationTime::Confirmed {
-                height: 42,
-                time: 42_000,
-            },
-        )
-        .unwrap();
-
-    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    assert!(psbt.inputs[0].non_witness_utxo.is_some());
+    assert!(psbt.inputs[0].witness_utxo.is_none());
 }
 
 #[test]
-fn test_bump_fee_low_fee_rate() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
+fn test_create_tx_only_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
+        .drain_to(addr.script_pubkey())
+        .only_witness_utxo()
+        .drain_wallet();
     let psbt = builder.finish().unwrap();
-    let feerate = psbt.fee_rate().unwrap();
-
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
-
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::BROADCAST_MIN);
-    let res = builder.finish();
-    assert_matches!(
-        res,
-        Err(CreateTxError::FeeRateTooLow { .. }),
-        "expected FeeRateTooLow error"
-    );
 
-    let required = feerate.to_sat_per_kwu() + 250; // +1 sat/vb
-    let sat_vb = required as f64 / 250.0;
-    let expect = format!("Fee rate too low: required {} sat/vb", sat_vb);
-    assert_eq!(res.unwrap_err().to_string(), expect);
+    assert!(psbt.inputs[0].non_witness_utxo.is_none());
+    assert!(psbt.inputs[0].witness_utxo.is_some());
 }
 
 #[test]
-#[should_panic(expected = "FeeTooLow")]
-fn test_bump_fee_low_abs() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
+fn test_create_tx_shwpkh_has_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
 
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
-
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(Amount::from_sat(10));
-    builder.finish().unwrap();
+    assert!(psbt.inputs[0].witness_utxo.is_some());
 }
 
 #[test]
-#[should_panic(expected = "FeeTooLow")]
-fn test_bump_fee_zero_abs() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
+fn test_create_tx_both_non_witness_utxo_and_witness_utxo_default() {
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
 
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(Amount::ZERO);
-    builder.finish().unwrap();
+    assert!(psbt.inputs[0].non_witness_utxo.is_some());
+    assert!(psbt.inputs[0].witness_utxo.is_some());
 }
 
 #[test]
-fn test_bump_fee_reduce_change() {
+fn test_create_tx_add_utxo() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let original_sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let original_fee = check_fee!(wallet, psbt);
-
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
+    let small_output_tx = Transaction {
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+        version: transaction::Version::non_standard(0),
+        lock_time: absolute::LockTime::ZERO,
+    };
     wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .insert_tx(
+            small_output_tx.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
         .unwrap();
 
-    let feerate = FeeRate::from_sat_per_kwu(625); // 2.5 sat/vb
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(feerate).enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert_eq!(
-        sent_received.1 + fee.unwrap_or(Amount::ZERO),
-        original_sent_received.1 + original_fee.unwrap_or(Amount::ZERO)
-    );
-    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
-
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
-    );
-
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), feerate, @add_signature);
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(Amount::from_sat(200));
-    builder.enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert_eq!(
-        sent_received.1 + fee.unwrap_or(Amount::ZERO),
-        original_sent_received.1 + original_fee.unwrap_or(Amount::ZERO)
-    );
-    assert!(
-        fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO),
-        "{} > {}",
-        fee.unwrap_or(Amount::ZERO),
-        original_fee.unwrap_or(Amount::ZERO)
-    );
-
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
-    );
-
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(200));
-}
-
-#[test]
-fn test_bump_fee_reduce_single_recipient() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.clone().extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let original_fee = check_fee!(wallet, psbt);
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .add_utxo(OutPoint {
+            txid: small_output_tx.compute_txid(),
+            vout: 0,
+        })
         .unwrap();
-
-    let feerate = FeeRate::from_sat_per_kwu(625); // 2.5 sat/vb
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .fee_rate(feerate)
-        // remove original tx drain_to address and amount
-        .set_recipients(Vec::new())
-        // set back original drain_to address
-        .drain_to(addr.script_pubkey())
-        // drain wallet output amount will be re-calculated with new fee rate
-        .drain_wallet();
     let psbt = builder.finish().unwrap();
     let sent_received =
         wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
 
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
-
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 1);
     assert_eq!(
-        tx.output[0].value + fee.unwrap_or(Amount::ZERO),
-        sent_received.0
+        psbt.unsigned_tx.input.len(),
+        2,
+        "should add an additional input since 25_000 < 30_000"
     );
-
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), feerate, @add_signature);
-}
-
-#[test]
-fn test_bump_fee_absolute_reduce_single_recipient() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let original_fee = check_fee!(wallet, psbt);
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .fee_absolute(Amount::from_sat(300))
-        // remove original tx drain_to address and amount
-        .set_recipients(Vec::new())
-        // set back original drain_to address
-        .drain_to(addr.script_pubkey())
-        // drain wallet output amount will be re-calculated with new fee rate
-        .drain_wallet();
-    let psbt = builder.finish().unwrap();
-    let tx = &psbt.unsigned_tx;
-    let sent_received = wallet.sent_and_received(tx);
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
-
-    assert_eq!(tx.output.len(), 1);
     assert_eq!(
-        tx.output[0].value + fee.unwrap_or(Amount::ZERO),
-        sent_received.0
+        sent_received.0,
+        Amount::from_sat(75_000),
+        "total should be sum of both inputs"
     );
-
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(300));
 }
 
 #[test]
-fn test_bump_fee_drain_wallet() {
+#[should_panic(expected = "InsufficientFunds")]
+fn test_create_tx_manually_selected_insufficient() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    // receive an extra tx so that our wallet has two utxos.
-    let tx = Transaction {
-        version: transaction::Version::ONE,
-        lock_time: absolute::LockTime::ZERO,
+    let small_output_tx = Transaction {
         input: vec![],
         output: vec![TxOut {
             script_pubkey: wallet
@@ -1987,2086 +3146,9788 @@ fn test_bump_fee_drain_wallet() {
                 .script_pubkey(),
             value: Amount::from_sat(25_000),
         }],
+        version: transaction::Version::non_standard(0),
+        lock_time: absolute::LockTime::ZERO,
     };
+
     wallet
         .insert_tx(
-            tx.clone(),
-            ConfirmationTime::Confirmed {
-                height: wallet.latest_checkpoint().height(),
-                time: 42_000,
-            },
+            small_output_tx.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
         )
         .unwrap();
+
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
         .add_utxo(OutPoint {
-            txid: tx.compute_txid(),
+            txid: small_output_tx.compute_txid(),
             vout: 0,
         })
         .unwrap()
-        .manually_selected_only()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-    assert_eq!(original_sent_received.0, Amount::from_sat(25_000));
-
-    // for the new feerate, it should be enough to reduce the output, but since we specify
-    // `drain_wallet` we expect to spend everything
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .drain_wallet()
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.extract_tx().expect("failed to extract tx"));
-
-    assert_eq!(sent_received.0, Amount::from_sat(75_000));
+        .manually_selected_only();
+    builder.finish().unwrap();
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_bump_fee_remove_output_manually_selected_only() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
-    // them, and make sure that `bump_fee` doesn't try to add more. This fails because we've
-    // told the wallet it's not allowed to add more inputs AND it can't reduce the value of the
-    // existing output. In other words, bump_fee + manually_selected_only is always an error
-    // unless there is a change output.
-    let init_tx = Transaction {
-        version: transaction::Version::ONE,
+#[should_panic(expected = "SpendingPolicyRequired(External)")]
+fn test_create_tx_policy_path_required() {
+    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_create_tx_policy_path_no_csv() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest).expect("wallet");
+
+    let tx = Transaction {
+        version: transaction::Version::non_standard(0),
         lock_time: absolute::LockTime::ZERO,
         input: vec![],
         output: vec![TxOut {
             script_pubkey: wallet
                 .next_unused_address(KeychainKind::External)
                 .script_pubkey(),
-            value: Amount::from_sat(25_000),
+            value: Amount::from_sat(50_000),
         }],
     };
     wallet
-        .insert_tx(
-            init_tx.clone(),
-            wallet
-                .transactions()
-                .last()
-                .unwrap()
-                .chain_position
-                .cloned()
-                .into(),
-        )
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
-    let outpoint = OutPoint {
-        txid: init_tx.compute_txid(),
-        vout: 0,
-    };
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #0 is just the key "A"
+    let path = vec![(root_id, vec![0])].into_iter().collect();
+
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .add_utxo(outpoint)
-        .unwrap()
-        .manually_selected_only()
-        .enable_rbf();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path, KeychainKind::External);
     let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-    assert_eq!(original_sent_received.0, Amount::from_sat(25_000));
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .manually_selected_only()
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(255));
-    builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFF));
 }
 
 #[test]
-fn test_bump_fee_add_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let init_tx = Transaction {
-        version: transaction::Version::ONE,
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![],
-        output: vec![TxOut {
-            script_pubkey: wallet
-                .next_unused_address(KeychainKind::External)
-                .script_pubkey(),
-            value: Amount::from_sat(25_000),
-        }],
-    };
-    let pos = wallet
-        .transactions()
-        .last()
-        .unwrap()
-        .chain_position
-        .cloned()
-        .into();
-    wallet.insert_tx(init_tx, pos).unwrap();
+fn test_create_tx_policy_path_use_csv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #1 is or(pk(B),older(144))
+    let path = vec![(root_id, vec![1])].into_iter().collect();
 
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_details = wallet.sent_and_received(&tx);
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path, KeychainKind::External)
+        // the funding tx is confirmed at height 2_000
+        .current_height(2_143);
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
-    assert_eq!(
-        sent_received.0,
-        original_details.0 + Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        fee.unwrap_or(Amount::ZERO) + sent_received.1,
-        Amount::from_sat(30_000)
-    );
-
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
-    );
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(50), @add_signature);
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(144));
 }
 
 #[test]
-fn test_bump_fee_absolute_add_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    receive_output_in_latest_block(&mut wallet, 25_000);
+fn test_create_tx_policy_path_csv_not_mature() {
+    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #1 is or(pk(B),older(144))
+    let path: BTreeMap<_, _> = vec![(root_id, vec![1])].into_iter().collect();
+
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(Amount::from_sat(6_000));
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path.clone(), KeychainKind::External);
 
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        fee.unwrap_or(Amount::ZERO) + sent_received.1,
-        Amount::from_sat(30_000)
+    // the funding tx is confirmed at height 2_000 and the tip is also 2_000
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::TimelockNotMature {
+            valid_at_height: 2_143
+        })
     );
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
+    // one block short of the boundary, the input would be included in block 2_143
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path.clone(), KeychainKind::External)
+        .current_height(2_142);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::TimelockNotMature {
+            valid_at_height: 2_143
+        })
     );
 
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(6_000));
+    // at the boundary the input has 144 confirmations, the transaction can be included in block
+    // 2_144
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path, KeychainKind::External)
+        .current_height(2_143);
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(144));
 }
 
 #[test]
-fn test_bump_fee_no_change_add_input_and_change() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let op = receive_output_in_latest_block(&mut wallet, 25_000);
+fn test_create_tx_policy_path_cltv_not_mature() {
+    // or(pk(Alice),and(pk(Bob),after(100000)))
+    let (mut wallet, _) = get_funded_wallet("wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),and_v(v:pk(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),after(100000))))");
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #1 is and(pk(B),after(100000))
+    let path: BTreeMap<_, _> = vec![(root_id, vec![1])].into_iter().collect();
 
-    // initially make a tx without change by using `drain_to`
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .add_utxo(op)
-        .unwrap()
-        .manually_selected_only()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let original_sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let original_fee = check_fee!(wallet, psbt);
-
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path.clone(), KeychainKind::External);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::TimelockNotMature {
+            valid_at_height: 100_000
+        })
+    );
 
-    // Now bump the fees, the wallet should add an extra input and a change output, and leave
-    // the original output untouched.
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path, KeychainKind::External)
+        .current_height(100_000);
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
+    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 100_000);
+}
 
-    let original_send_all_amount = original_sent_received.0 - original_fee.unwrap_or(Amount::ZERO);
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(50_000)
-    );
-    assert_eq!(
-        sent_received.1,
-        Amount::from_sat(75_000) - original_send_all_amount - fee.unwrap_or(Amount::ZERO)
-    );
-
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        original_send_all_amount
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(75_000) - original_send_all_amount - fee.unwrap_or(Amount::ZERO)
-    );
+#[test]
+fn test_create_tx_policy_path_ignored_subtree_with_csv() {
+    let (mut wallet, _) = get_funded_wallet("wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),or_i(and_v(v:pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(30)),and_v(v:pkh(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),older(90)))))");
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(50), @add_signature);
-}
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #0 is pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)
+    let path = vec![(root_id, vec![0])].into_iter().collect();
 
-#[test]
-fn test_bump_fee_add_input_change_dust() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    receive_output_in_latest_block(&mut wallet, 25_000);
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path, KeychainKind::External);
     let psbt = builder.finish().unwrap();
-    let original_sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let original_fee = check_fee!(wallet, psbt);
-
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // to get realistic weight
-    }
-    let original_tx_weight = tx.weight();
-    assert_eq!(tx.input.len(), 1);
-    assert_eq!(tx.output.len(), 2);
-    let txid = tx.compute_txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    // We set a fee high enough that during rbf we are forced to add
-    // a new input and also that we have to remove the change
-    // that we had previously
 
-    // We calculate the new weight as:
-    //   original weight
-    // + extra input weight: 160 WU = (32 (prevout) + 4 (vout) + 4 (nsequence)) * 4
-    // + input satisfaction weight: 112 WU = 106 (witness) + 2 (witness len) + (1 (script len)) * 4
-    // - change output weight: 124 WU = (8 (value) + 1 (script len) + 22 (script)) * 4
-    let new_tx_weight =
-        original_tx_weight + Weight::from_wu(160) + Weight::from_wu(112) - Weight::from_wu(124);
-    // two inputs (50k, 25k) and one output (45k) - epsilon
-    // We use epsilon here to avoid asking for a slightly too high feerate
-    let fee_abs = 50_000 + 25_000 - 45_000 - 10;
-    builder.fee_rate(Amount::from_sat(fee_abs) / new_tx_weight);
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
+}
 
-    assert_eq!(
-        original_sent_received.1,
-        Amount::from_sat(5_000) - original_fee.unwrap_or(Amount::ZERO)
-    );
+#[test]
+fn test_policies_timelock_satisfaction() {
+    use bdk_wallet::descriptor::policy::{Satisfaction, SatisfiableItem};
 
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(25_000)
+    // or(multi(2, Alice, B, C), and(pk(Bob), older(6))), the wallet has the keys of Alice and Bob
+    let descriptor = "wsh(or_d(multi(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,021403881a5587297818fcaf17d239cefca22fce84a45b3b1d23e836c4af671dbb,025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357),and_v(v:pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),older(6))))";
+    let mut wallet = Wallet::new(descriptor, get_test_wpkh(), Network::Regtest).unwrap();
+    let insert_block = |wallet: &mut Wallet, height: u32| {
+        wallet
+            .insert_checkpoint(BlockId {
+                height,
+                hash: BlockHash::from_byte_array([height as u8; 32]),
+            })
+            .unwrap();
+    };
+    insert_block(&mut wallet, 100);
+    receive_output(
+        &mut wallet,
+        50_000,
+        ConfirmationTime::Confirmed {
+            height: 100,
+            time: 0,
+        },
     );
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(30_000));
-    assert_eq!(sent_received.1, Amount::ZERO);
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 1);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
-    );
+    let recovery_satisfaction = |wallet: &Wallet| {
+        let policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        let items = match &policy.item {
+            SatisfiableItem::Thresh { items, threshold } => {
+                assert_eq!(*threshold, 1);
+                items.clone()
+            }
+            item => panic!("unexpected policy {:?}", item),
+        };
+        // the multisig can't be satisfied by the wallet alone
+        assert!(matches!(
+            &items[0].satisfaction,
+            Satisfaction::Partial { n: 3, m: 2, items, .. } if items == &[0]
+        ));
+        (
+            policy.id,
+            items[1].id.clone(),
+            items[1].satisfaction.clone(),
+        )
+    };
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(140), @dust_change, @add_signature);
-}
+    // the UTXO has 1 confirmation, the recovery branch only has the signature of Bob
+    let (root_id, recovery_id, satisfaction) = recovery_satisfaction(&wallet);
+    assert!(matches!(
+        satisfaction,
+        Satisfaction::Partial { n: 2, m: 2, ref items, .. } if items == &[0]
+    ));
+    insert_block(&mut wallet, 104);
+    let (_, _, satisfaction) = recovery_satisfaction(&wallet);
+    assert!(matches!(satisfaction, Satisfaction::Partial { .. }));
 
-#[test]
-fn test_bump_fee_force_add_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+    // with 6 more blocks the timelock expires
+    insert_block(&mut wallet, 106);
+    let (new_root_id, new_recovery_id, satisfaction) = recovery_satisfaction(&wallet);
+    assert!(matches!(
+        satisfaction,
+        Satisfaction::PartialComplete { n: 2, m: 2, ref items, .. } if items == &[0, 1]
+    ));
+    assert_eq!(new_root_id, root_id);
+    assert_eq!(new_recovery_id, recovery_id);
 
+    // the ids can be used to pick the recovery branch
+    let path = vec![(root_id, vec![1])].into_iter().collect();
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .policy_path(path, KeychainKind::External);
     let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.compute_txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-    // the new fee_rate is low enough that just reducing the change would be fine, but we force
-    // the addition of an extra input with `add_utxo()`
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
+
+    // the key spend and the leaves of a taproot descriptor are alternatives
+    let wallet = Wallet::new(
+        get_test_tr_with_taptree(),
+        get_test_wpkh(),
+        Network::Regtest,
+    )
+    .unwrap();
+    let policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    assert!(matches!(
+        &policy.item,
+        SatisfiableItem::Thresh { items, threshold: 1 } if items.len() == 3
+    ));
+    assert!(matches!(
+        policy.satisfaction,
+        Satisfaction::PartialComplete { n: 3, m: 1, ref items, .. } if items == &[1]
+    ));
+}
+
+#[test]
+fn test_create_tx_global_xpubs_with_origin() {
+    use bitcoin::bip32;
+    let (mut wallet, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
     builder
-        .add_utxo(incoming_op)
-        .unwrap()
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_global_xpubs();
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        fee.unwrap_or(Amount::ZERO) + sent_received.1,
-        Amount::from_sat(30_000)
-    );
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
-    );
+    let key = bip32::Xpub::from_str("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
+    let fingerprint = bip32::Fingerprint::from_hex("73756c7f").unwrap();
+    let path = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(5), @add_signature);
+    assert_eq!(psbt.xpub.len(), 2);
+    assert_eq!(psbt.xpub.get(&key), Some(&(fingerprint, path)));
 }
 
 #[test]
-fn test_bump_fee_absolute_force_add_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+fn test_add_foreign_utxo() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
 
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.compute_txid();
-    // skip saving the new utxos, we know they can't be used anyways
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+    let utxo = wallet2.list_unspent().next().expect("must take!");
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
         .unwrap();
 
-    // the new fee_rate is low enough that just reducing the change would be fine, but we force
-    // the addition of an extra input with `add_utxo()`
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(utxo.txout.clone()),
+        ..Default::default()
+    };
+
+    let mut builder = wallet1.build_tx();
     builder
-        .add_utxo(incoming_op)
-        .unwrap()
-        .fee_absolute(Amount::from_sat(250));
-    let psbt = builder.finish().unwrap();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(
+            utxo.outpoint,
+            psbt_input,
+            foreign_utxo_satisfaction.to_wu() as usize,
+        )
+        .unwrap();
+    let mut psbt = builder.finish().unwrap();
+    wallet1.insert_txout(utxo.outpoint, utxo.txout);
+    let fee = check_fee!(wallet1, psbt);
     let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
+        wallet1.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
 
     assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        fee.unwrap_or(Amount::ZERO) + sent_received.1,
-        Amount::from_sat(30_000)
+        (sent_received.0 - sent_received.1),
+        Amount::from_sat(10_000) + fee.unwrap_or(Amount::ZERO),
+        "we should have only net spent ~10_000"
     );
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
+    assert!(
+        psbt.unsigned_tx
+            .input
             .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
+            .any(|input| input.previous_output == utxo.outpoint),
+        "foreign_utxo should be in there"
     );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
+
+    let finished = wallet1
+        .sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert!(
+        !finished,
+        "only one of the inputs should have been signed so far"
     );
 
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(250));
+    let finished = wallet2
+        .sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(finished, "all the inputs should have been signed now");
+}
+
+/// Spends an anyone-can-spend Taproot leaf with a hand-built witness
+#[derive(Debug)]
+struct AnyoneCanSpendLeaf {
+    leaf: ScriptBuf,
+    control_block: ControlBlock,
+}
+
+impl WitnessProvider for AnyoneCanSpendLeaf {
+    fn leaf_hash(&self) -> Option<TapLeafHash> {
+        Some(TapLeafHash::from_script(&self.leaf, LeafVersion::TapScript))
+    }
+
+    fn provide_witness(&self, context: &WitnessContext<'_>) -> Result<Witness, SignerError> {
+        assert_eq!(context.prevouts.len(), context.tx.input.len());
+        assert_eq!(context.leaf_hash, self.leaf_hash());
+        // the leaf doesn't check any signature, but the context is enough to compute the sighash
+        context
+            .taproot_sighash(TapSighashType::Default)
+            .map_err(|e| SignerError::External(e.to_string()))?;
+        Ok(Witness::from_slice(&[
+            self.leaf.to_bytes(),
+            self.control_block.serialize(),
+        ]))
+    }
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_bump_fee_unconfirmed_inputs_only() {
-    // We try to bump the fee, but:
-    // - We can't reduce the change, as we have no change
-    // - All our UTXOs are unconfirmed
-    // So, we fail with "InsufficientFunds", as per RBF rule 2:
-    // The replacement transaction may only include an unconfirmed input
-    // if that input was included in one of the original transactions.
+fn test_add_utxo_with_witness_provider() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+    let secp = Secp256k1::new();
+    let leaf = ScriptBuf::from_bytes(vec![0x51]); // OP_TRUE
+    let internal_key = XOnlyPublicKey::from_str(
+        "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0",
+    )
+    .unwrap();
+    let spend_info = TaprootBuilder::new()
+        .add_leaf(0, leaf.clone())
         .unwrap()
-        .assume_checked();
+        .finalize(&secp, internal_key)
+        .unwrap();
+    let control_block = spend_info
+        .control_block(&(leaf.clone(), LeafVersion::TapScript))
+        .unwrap();
+    let outpoint = OutPoint::new(Txid::all_zeros(), 7);
+    let txout = TxOut {
+        value: Amount::from_sat(25_000),
+        script_pubkey: ScriptBuf::new_p2tr(&secp, internal_key, spend_info.merkle_root()),
+    };
+    let provider = Arc::new(AnyoneCanSpendLeaf {
+        leaf: leaf.clone(),
+        control_block: control_block.clone(),
+    });
+    // 1 element count, 1 + 1 for the leaf and 1 + 33 for the control block
+    let weight_hint = 37;
+
+    assert_matches!(
+        wallet
+            .build_tx()
+            .add_utxo_with_witness_provider(outpoint, weight_hint, provider.clone()),
+        Err(AddUtxoError::UnknownUtxo(op)) if op == outpoint
+    );
+    wallet.insert_txout(outpoint, txout.clone());
+
+    let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
     builder
-        .drain_wallet()
-        .drain_to(addr.script_pubkey())
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    // Now we receive one transaction with 0 confirmations. We won't be able to use that for
-    // fee bumping, as it's still unconfirmed!
-    receive_output(
-        &mut wallet,
-        25_000,
-        ConfirmationTime::Unconfirmed { last_seen: 0 },
-    );
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .fee_rate(FeeRate::from_sat_per_vb_u32(5))
+        .add_utxo_with_witness_provider(outpoint, weight_hint, provider)
         .unwrap();
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(25));
-    builder.finish().unwrap();
+    let mut psbt = builder.finish().unwrap();
+    let index = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|txin| txin.previous_output == outpoint)
+        .expect("the input must be spent");
+    assert_eq!(psbt.inputs[index].witness_utxo, Some(txout));
+
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(finalized, "the provider must finalize its input");
+    assert_eq!(
+        psbt.inputs[index].final_script_witness,
+        Some(Witness::from_slice(&[
+            leaf.to_bytes(),
+            control_block.serialize(),
+        ]))
+    );
+
+    // the weight hint is accounted for in the fee
+    let tx = psbt.extract_tx().unwrap();
+    assert_eq!(tx.input[index].witness.size(), weight_hint);
+    let fee_rate = wallet.calculate_fee_rate(&tx).unwrap();
+    assert!(fee_rate >= FeeRate::from_sat_per_vb_u32(5));
 }
 
 #[test]
-fn test_bump_fee_unconfirmed_input() {
-    // We create a tx draining the wallet and spending one confirmed
-    // and one unconfirmed UTXO. We check that we can fee bump normally
-    // (BIP125 rule 2 only apply to newly added unconfirmed input, you can
-    // always fee bump with an unconfirmed input if it was included in the
-    // original transaction)
-    let (mut wallet, _) = get_funded_wallet_wpkh();
+fn test_sign_only_selected_inputs() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    // We receive a tx with 0 confirmations, which will be used as an input
-    // in the drain tx.
-    receive_output(&mut wallet, 25_000, ConfirmationTime::unconfirmed(0));
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_wallet()
-        .drain_to(addr.script_pubkey())
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.compute_txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+    let utxo = wallet2.list_unspent().next().expect("must take!");
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
         .unwrap();
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(utxo.txout.clone()),
+        ..Default::default()
+    };
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    let mut builder = wallet1.build_tx();
     builder
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(15))
-        // remove original tx drain_to address and amount
-        .set_recipients(Vec::new())
-        // set back original drain_to address
-        .drain_to(addr.script_pubkey())
-        // drain wallet output amount will be re-calculated with new fee rate
-        .drain_wallet();
-    builder.finish().unwrap();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(
+            utxo.outpoint,
+            psbt_input,
+            foreign_utxo_satisfaction.to_wu() as usize,
+        )
+        .unwrap();
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.inputs.len(), 2);
+    let foreign_index = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|input| input.previous_output == utxo.outpoint)
+        .unwrap();
+    let own_index = 1 - foreign_index;
+
+    // none of the inputs belong to the internal keychain of wallet1
+    let inputs = psbt.inputs.clone();
+    let details = wallet1
+        .sign_with_details(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                try_finalize: false,
+                keychains: Some([KeychainKind::Internal].into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(details.signed_inputs.is_empty());
+    assert_eq!(psbt.inputs, inputs);
+
+    // the wif signer of wallet1 would also put a signature on the foreign input, unless it's
+    // restricted to the inputs of its own keychain
+    let foreign_input = psbt.inputs[foreign_index].clone();
+    let details = wallet1
+        .sign_with_details(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                try_finalize: false,
+                keychains: Some([KeychainKind::External].into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(details.signed_inputs, [own_index].into());
+    assert!(!details.finalized);
+    assert_eq!(psbt.inputs[own_index].partial_sigs.len(), 1);
+    assert_eq!(psbt.inputs[foreign_index], foreign_input);
+
+    let own_input = psbt.inputs[own_index].clone();
+    let details = wallet2
+        .sign_with_details(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                try_finalize: false,
+                inputs: Some([foreign_index].into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(details.signed_inputs, [foreign_index].into());
+    assert_eq!(psbt.inputs[own_index], own_input);
+
+    // finalization is a separate step, each wallet finalizes its own input
+    let sign_options = SignOptions {
+        trust_witness_utxo: true,
+        ..Default::default()
+    };
+    let finalized = wallet1
+        .finalize_psbt(&mut psbt, sign_options.clone())
+        .unwrap();
+    assert!(!finalized);
+    let finalized = wallet2.finalize_psbt(&mut psbt, sign_options).unwrap();
+    assert!(finalized);
 }
 
 #[test]
-fn test_fee_amount_negative_drain_val() {
-    // While building the transaction, bdk would calculate the drain_value
-    // as
-    // current_delta - fee_amount - drain_fee
-    // using saturating_sub, meaning that if the result would end up negative,
-    // it'll remain to zero instead.
-    // This caused a bug in master where we would calculate the wrong fee
-    // for a transaction.
-    // See https://github.com/bitcoindevkit/bdk/issues/660
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let send_to = Address::from_str("tb1ql7w62elx9ucw4pj5lgw4l028hmuw80sndtntxt")
+#[should_panic(
+    expected = "MissingTxOut([OutPoint { txid: 21d7fb1bceda00ab4069fc52d06baa13470803e9050edd16f5736e5d8c4925fd, vout: 0 }])"
+)]
+fn test_calculate_fee_with_missing_foreign_utxo() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let fee_rate = FeeRate::from_sat_per_kwu(500);
-    let incoming_op = receive_output_in_latest_block(&mut wallet, 8859);
+    let utxo = wallet2.list_unspent().next().expect("must take!");
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
 
-    let mut builder = wallet.build_tx();
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(utxo.txout.clone()),
+        ..Default::default()
+    };
+
+    let mut builder = wallet1.build_tx();
     builder
-        .add_recipient(send_to.script_pubkey(), Amount::from_sat(8630))
-        .add_utxo(incoming_op)
-        .unwrap()
-        .enable_rbf()
-        .fee_rate(fee_rate);
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(
+            utxo.outpoint,
+            psbt_input,
+            foreign_utxo_satisfaction.to_wu() as usize,
+        )
+        .unwrap();
     let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(psbt.inputs.len(), 1);
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate, @add_signature);
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    wallet1.calculate_fee(&tx).unwrap();
 }
 
 #[test]
-fn test_sign_single_xprv() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External);
+fn test_add_foreign_utxo_invalid_psbt_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let outpoint = wallet.list_unspent().next().expect("must exist").outpoint;
+    let foreign_utxo_satisfaction = wallet
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    let result = builder.add_foreign_utxo(
+        outpoint,
+        psbt::Input::default(),
+        foreign_utxo_satisfaction.to_wu() as usize,
+    );
+    assert!(matches!(result, Err(AddForeignUtxoError::MissingUtxo)));
+}
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+#[test]
+fn test_add_foreign_utxo_where_outpoint_doesnt_match_psbt_input() {
+    let (mut wallet1, txid1) = get_funded_wallet_wpkh();
+    let (wallet2, txid2) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    let utxo2 = wallet2.list_unspent().next().unwrap();
+    let tx1 = wallet1.get_tx(txid1).unwrap().tx_node.tx.clone();
+    let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx.clone();
+
+    let satisfaction_weight = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+
+    let mut builder = wallet1.build_tx();
+    assert!(
+        builder
+            .add_foreign_utxo(
+                utxo2.outpoint,
+                psbt::Input {
+                    non_witness_utxo: Some(tx1.as_ref().clone()),
+                    ..Default::default()
+                },
+                satisfaction_weight.to_wu() as usize
+            )
+            .is_err(),
+        "should fail when outpoint doesn't match psbt_input"
+    );
+    assert!(
+        builder
+            .add_foreign_utxo(
+                utxo2.outpoint,
+                psbt::Input {
+                    non_witness_utxo: Some(tx2.as_ref().clone()),
+                    ..Default::default()
+                },
+                satisfaction_weight.to_wu() as usize
+            )
+            .is_ok(),
+        "should be ok when outpoint does match psbt_input"
+    );
 }
 
 #[test]
-fn test_sign_single_xprv_with_master_fingerprint_and_path() {
-    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/84h/1h/0h]tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+fn test_add_foreign_utxo_only_witness_utxo() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, txid2) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let utxo2 = wallet2.list_unspent().next().unwrap();
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let satisfaction_weight = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    let mut builder = wallet1.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(60_000));
+
+    {
+        let mut builder = builder.clone();
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(utxo2.txout.clone()),
+            ..Default::default()
+        };
+        builder
+            .add_foreign_utxo(
+                utxo2.outpoint,
+                psbt_input,
+                satisfaction_weight.to_wu() as usize,
+            )
+            .unwrap();
+        assert!(
+            builder.finish().is_err(),
+            "psbt_input with witness_utxo should fail with only witness_utxo"
+        );
+    }
+
+    {
+        let mut builder = builder.clone();
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(utxo2.txout.clone()),
+            ..Default::default()
+        };
+        builder
+            .only_witness_utxo()
+            .add_foreign_utxo(
+                utxo2.outpoint,
+                psbt_input,
+                satisfaction_weight.to_wu() as usize,
+            )
+            .unwrap();
+        assert!(
+            builder.finish().is_ok(),
+            "psbt_input with just witness_utxo should succeed when `only_witness_utxo` is enabled"
+        );
+    }
+
+    {
+        let mut builder = builder.clone();
+        let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx;
+        let psbt_input = psbt::Input {
+            non_witness_utxo: Some(tx2.as_ref().clone()),
+            ..Default::default()
+        };
+        builder
+            .add_foreign_utxo(
+                utxo2.outpoint,
+                psbt_input,
+                satisfaction_weight.to_wu() as usize,
+            )
+            .unwrap();
+        assert!(
+            builder.finish().is_ok(),
+            "psbt_input with non_witness_utxo should succeed by default"
+        );
+    }
 }
 
 #[test]
-fn test_sign_single_xprv_bip44_path() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/44'/0'/0'/0/*)");
+fn test_get_psbt_input() {
+    // this should grab a known good utxo and set the input
+    let (wallet, _) = get_funded_wallet_wpkh();
+    for utxo in wallet.list_unspent() {
+        let psbt_input = wallet.get_psbt_input(utxo, None, false).unwrap();
+        assert!(psbt_input.witness_utxo.is_some() || psbt_input.non_witness_utxo.is_some());
+    }
+}
+
+#[test]
+#[should_panic(
+    expected = "MissingKeyOrigin(\"tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3\")"
+)]
+fn test_create_tx_global_xpubs_origin_missing() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
-
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
-
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_global_xpubs();
+    builder.finish().unwrap();
 }
 
 #[test]
-fn test_sign_single_xprv_sh_wpkh() {
-    let (mut wallet, _) = get_funded_wallet("sh(wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*))");
+fn test_create_tx_global_xpubs_master_without_origin() {
+    use bitcoin::bip32;
+    let (mut wallet, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*)");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_global_xpubs();
+    let psbt = builder.finish().unwrap();
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let key = bip32::Xpub::from_str("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
+    let fingerprint = bip32::Fingerprint::from_hex("997a323b").unwrap();
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    assert_eq!(psbt.xpub.len(), 2);
+    assert_eq!(
+        psbt.xpub.get(&key),
+        Some(&(fingerprint, bip32::DerivationPath::default()))
+    );
 }
 
 #[test]
-fn test_sign_single_wif() {
-    let (mut wallet, _) =
-        get_funded_wallet("wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
+fn test_create_tx_global_xpubs_multisig() {
+    use bitcoin::bip32;
+    let (mut wallet, _) = get_funded_wallet("wsh(multi(2,[73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*,[f9f62194/48'/0'/0'/2']tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*))");
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_global_xpubs();
+    let psbt = builder.finish().unwrap();
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let key_a = bip32::Xpub::from_str("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
+    let key_b = bip32::Xpub::from_str("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
+    let path = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    // both cosigners of the external descriptor plus the change key
+    assert_eq!(psbt.xpub.len(), 3);
+    assert_eq!(
+        psbt.xpub.get(&key_a),
+        Some(&(
+            bip32::Fingerprint::from_hex("73756c7f").unwrap(),
+            path.clone()
+        ))
+    );
+    assert_eq!(
+        psbt.xpub.get(&key_b),
+        Some(&(bip32::Fingerprint::from_hex("f9f62194").unwrap(), path))
+    );
 }
 
 #[test]
-fn test_sign_single_xprv_no_hd_keypaths() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+fn test_create_tx_global_xpubs_skip_single_keys() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
-
-    psbt.inputs[0].bip32_derivation.clear();
-    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 0);
-
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_global_xpubs();
+    let psbt = builder.finish().unwrap();
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    // only the change descriptor has an extended key, the wif one is skipped
+    assert_eq!(psbt.xpub.len(), 1);
 }
 
 #[test]
-fn test_include_output_redeem_witness_script() {
-    let desc = get_test_wpkh();
-    let change_desc = "sh(wsh(multi(1,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))";
-    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_set_proprietary() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let key = psbt::raw::ProprietaryKey {
+        prefix: b"bdk".to_vec(),
+        subtype: 0x01,
+        key: vec![0x42],
+    };
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .include_output_redeem_witness_script();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .set_proprietary(key.clone(), vec![0x00])
+        .set_proprietary(key.clone(), vec![0xde, 0xad]);
     let psbt = builder.finish().unwrap();
 
-    // p2sh-p2wsh transaction should contain both witness and redeem scripts
-    assert!(psbt
-        .outputs
-        .iter()
-        .any(|output| output.redeem_script.is_some() && output.witness_script.is_some()));
+    assert_eq!(psbt.proprietary.len(), 1);
+    assert_eq!(psbt.proprietary.get(&key), Some(&vec![0xde, 0xad]));
 }
 
 #[test]
-fn test_signing_only_one_of_multiple_inputs() {
+#[should_panic(expected = "IrreplaceableTransaction")]
+fn test_bump_fee_irreplaceable_tx() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+    let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .include_output_redeem_witness_script();
-    let mut psbt = builder.finish().unwrap();
-
-    // add another input to the psbt that is at least passable.
-    let dud_input = bitcoin::psbt::Input {
-        witness_utxo: Some(TxOut {
-            value: Amount::from_sat(100_000),
-            script_pubkey: miniscript::Descriptor::<bitcoin::PublicKey>::from_str(
-                "wpkh(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)",
-            )
-            .unwrap()
-            .script_pubkey(),
-        }),
-        ..Default::default()
-    };
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let psbt = builder.finish().unwrap();
 
-    psbt.inputs.push(dud_input);
-    psbt.unsigned_tx.input.push(bitcoin::TxIn::default());
-    let is_final = wallet
-        .sign(
-            &mut psbt,
-            SignOptions {
-                trust_witness_utxo: true,
-                ..Default::default()
-            },
-        )
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
-    assert!(
-        !is_final,
-        "shouldn't be final since we can't sign one of the inputs"
-    );
-    assert!(
-        psbt.inputs[0].final_script_witness.is_some(),
-        "should finalized input it signed"
-    )
+    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
 }
 
 #[test]
-fn test_try_finalize_sign_option() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+#[should_panic(expected = "TransactionConfirmed")]
+fn test_bump_fee_confirmed_tx() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let psbt = builder.finish().unwrap();
 
-    for try_finalize in &[true, false] {
-        let addr = wallet.next_unused_address(KeychainKind::External);
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let mut psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
 
-        let finalized = wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    try_finalize: *try_finalize,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
+    wallet
+        .insert_tx(
+            tx,
+            ConfirmationTime::Confirmed {
+                height: 42,
+                time: 42_000,
+            },
+        )
+        .unwrap();
 
-        psbt.inputs.iter().for_each(|input| {
-            if *try_finalize {
-                assert!(finalized);
-                assert!(input.final_script_sig.is_none());
-                assert!(input.final_script_witness.is_some());
-            } else {
-                assert!(!finalized);
-                assert!(input.final_script_sig.is_none());
-                assert!(input.final_script_witness.is_none());
-            }
-        });
-    }
+    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
 }
 
 #[test]
-fn test_taproot_try_finalize_sign_option() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+fn test_bump_fee_low_fee_rate() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let feerate = psbt.fee_rate().unwrap();
 
-    for try_finalize in &[true, false] {
-        let addr = wallet.next_unused_address(KeychainKind::External);
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let mut psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
 
-        let finalized = wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    try_finalize: *try_finalize,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
 
-        psbt.inputs.iter().for_each(|input| {
-            if *try_finalize {
-                assert!(finalized);
-                assert!(input.final_script_sig.is_none());
-                assert!(input.final_script_witness.is_some());
-                assert!(input.tap_key_sig.is_none());
-                assert!(input.tap_script_sigs.is_empty());
-                assert!(input.tap_scripts.is_empty());
-                assert!(input.tap_key_origins.is_empty());
-                assert!(input.tap_internal_key.is_none());
-                assert!(input.tap_merkle_root.is_none());
-            } else {
-                assert!(!finalized);
-                assert!(input.final_script_sig.is_none());
-                assert!(input.final_script_witness.is_none());
-            }
-        });
-        psbt.outputs.iter().for_each(|output| {
-            if *try_finalize {
-                assert!(finalized);
-                assert!(output.tap_key_origins.is_empty());
-            } else {
-                assert!(!finalized);
-                assert!(!output.tap_key_origins.is_empty());
-            }
-        });
-    }
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::BROADCAST_MIN);
+    let res = builder.finish();
+    assert_matches!(
+        res,
+        Err(CreateTxError::FeeRateTooLow { .. }),
+        "expected FeeRateTooLow error"
+    );
+
+    let required = feerate.to_sat_per_kwu() + 250; // +1 sat/vb
+    let sat_vb = required as f64 / 250.0;
+    let expect = format!("Fee rate too low: required {} sat/vb", sat_vb);
+    assert_eq!(res.unwrap_err().to_string(), expect);
 }
 
 #[test]
-fn test_sign_nonstandard_sighash() {
-    let sighash = EcdsaSighashType::NonePlusAnyoneCanPay;
-
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External);
+fn test_build_cancel() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let balance = wallet.balance().total();
+    let recipient = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .sighash(sighash.into())
-        .drain_wallet();
+        .add_recipient(recipient.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
     let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let original = psbt.extract_tx().expect("failed to extract tx");
+    let original_txid = original.compute_txid();
+    let original_fee = wallet.calculate_fee(&original).unwrap();
+    // broadcast
+    wallet.apply_unconfirmed_txs([(&original, 100)]);
+
+    // the replacement must pay more than the original
+    let builder = wallet
+        .build_cancel(original_txid, FeeRate::BROADCAST_MIN)
+        .unwrap();
+    assert_matches!(builder.finish(), Err(CreateTxError::FeeRateTooLow { .. }));
 
-    let result = wallet.sign(&mut psbt, Default::default());
-    assert!(
-        result.is_err(),
-        "Signing should have failed because the TX uses non-standard sighashes"
+    let fee_rate = FeeRate::from_sat_per_vb_u32(5);
+    let mut psbt = wallet
+        .build_cancel(original_txid, fee_rate)
+        .unwrap()
+        .finish()
+        .unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let cancel = psbt.extract_tx().expect("failed to extract tx");
+    assert!(cancel
+        .input
+        .iter()
+        .any(|txin| original.input[0].previous_output == txin.previous_output));
+    assert!(cancel.is_explicitly_rbf());
+    assert_eq!(cancel.output.len(), 1);
+    assert_matches!(
+        wallet.derivation_of_spk(&cancel.output[0].script_pubkey),
+        Some((KeychainKind::Internal, _))
     );
+    let cancel_fee = wallet.calculate_fee(&cancel).unwrap();
+    assert!(cancel_fee >= original_fee + FeeRate::BROADCAST_MIN * cancel.weight());
+    assert!(wallet.calculate_fee_rate(&cancel).unwrap() >= fee_rate);
+
+    // broadcast the cancellation and confirm it
+    wallet.apply_unconfirmed_txs([(&cancel, 200)]);
+    let block = BlockId {
+        height: 3_000,
+        hash: BlockHash::all_zeros(),
+    };
+    wallet.insert_checkpoint(block).unwrap();
+    wallet
+        .insert_tx(
+            cancel.clone(),
+            ConfirmationTime::Confirmed {
+                height: 3_000,
+                time: 3_000,
+            },
+        )
+        .unwrap();
+
+    // the payment never confirms and the wallet only lost the fee of the cancellation
+    assert!(wallet.get_tx(original_txid).is_none());
+    assert!(wallet
+        .get_tx(cancel.compute_txid())
+        .unwrap()
+        .chain_position
+        .is_confirmed());
+    assert_eq!(wallet.balance().total(), balance - cancel_fee);
     assert_matches!(
-        result,
-        Err(SignerError::NonStandardSighash),
-        "Signing failed with the wrong error type"
+        wallet.build_cancel(cancel.compute_txid(), fee_rate),
+        Err(BuildFeeBumpError::TransactionConfirmed(_))
     );
+}
 
-    // try again after opting-in
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
+#[test]
+fn test_build_cancel_no_wallet_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    // an incoming payment spending someone else's output
+    let foreign_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
             ..Default::default()
-        },
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(20_000),
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(foreign_tx.compute_txid(), 0),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .script_pubkey(),
+        }],
+    };
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(foreign_tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    assert_matches!(
+        wallet.build_cancel(txid, FeeRate::from_sat_per_vb_u32(5)),
+        Err(BuildFeeBumpError::NoWalletInput(t)) if t == txid
     );
-    assert!(result.is_ok(), "Signing should have worked");
-    assert!(
-        result.unwrap(),
-        "Should finalize the input since we can produce signatures"
+}
+
+#[test]
+fn test_tx_replacement_info() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let original = builder.finish().unwrap().extract_tx().unwrap();
+    let original_txid = original.compute_txid();
+    wallet
+        .insert_tx(original, ConfirmationTime::Unconfirmed { last_seen: 100 })
+        .unwrap();
+    let info = wallet.tx_replacement_info(original_txid).unwrap();
+    assert!(info.signals_rbf);
+    assert!(info.conflicts.is_empty());
+    assert!(info.is_canonical());
+    assert_eq!(
+        wallet.latest_replacement(original_txid),
+        Some(original_txid)
+    );
+
+    let mut builder = wallet.build_fee_bump(original_txid).unwrap();
+    builder
+        .fee_rate(FeeRate::from_sat_per_vb_u32(5))
+        .enable_rbf();
+    let first_bump = builder.finish().unwrap().extract_tx().unwrap();
+    let first_bump_txid = first_bump.compute_txid();
+    wallet
+        .insert_tx(first_bump, ConfirmationTime::Unconfirmed { last_seen: 200 })
+        .unwrap();
+    assert_eq!(
+        wallet.latest_replacement(original_txid),
+        Some(first_bump_txid)
+    );
+
+    let mut builder = wallet.build_fee_bump(first_bump_txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_u32(10));
+    let second_bump = builder.finish().unwrap().extract_tx().unwrap();
+    let second_bump_txid = second_bump.compute_txid();
+    wallet
+        .insert_tx(
+            second_bump,
+            ConfirmationTime::Unconfirmed { last_seen: 300 },
+        )
+        .unwrap();
+
+    // the middle attempt is evicted, every attempt leads to the last one
+    assert!(wallet.get_tx(first_bump_txid).is_none());
+    for txid in [original_txid, first_bump_txid, second_bump_txid] {
+        assert_eq!(wallet.latest_replacement(txid), Some(second_bump_txid));
+    }
+    let info = wallet.tx_replacement_info(first_bump_txid).unwrap();
+    let mut conflicts = vec![original_txid, second_bump_txid];
+    conflicts.sort();
+    assert_eq!(info.conflicts, conflicts);
+    assert_eq!(info.canonical, Some(second_bump_txid));
+    assert!(!info.is_canonical());
+    let info = wallet.tx_replacement_info(second_bump_txid).unwrap();
+    assert!(info.is_canonical());
+    // the last bump doesn't signal RBF
+    assert!(!info.signals_rbf);
+
+    assert_eq!(wallet.tx_replacement_info(Txid::all_zeros()), None);
+    assert_eq!(wallet.latest_replacement(Txid::all_zeros()), None);
+}
+
+#[test]
+#[should_panic(expected = "FeeTooLow")]
+fn test_bump_fee_low_abs() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(Amount::from_sat(10));
+    builder.finish().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "FeeTooLow")]
+fn test_bump_fee_zero_abs() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(Amount::ZERO);
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_bump_fee_smaller_replacement_pays_relay_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    for _ in 0..10 {
+        builder.add_recipient(addr.script_pubkey(), Amount::from_sat(1_000));
+    }
+    builder
+        .fee_rate(FeeRate::from_sat_per_kwu(250)) // 1 sat/vb
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_fee = check_fee!(wallet, psbt).unwrap();
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // dropping most of the outputs makes the replacement much smaller, so a higher feerate
+    // alone doesn't pay for the original fee anymore
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .set_recipients(vec![(addr.script_pubkey(), Amount::from_sat(1_000))])
+        .fee_rate(FeeRate::from_sat_per_kwu(750)); // 3 sat/vb
+    match builder.finish() {
+        Err(CreateTxError::FeeTooLow { required }) => assert!(required > original_fee),
+        res => panic!("expected FeeTooLow, got {:?}", res),
+    }
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .set_recipients(vec![(addr.script_pubkey(), Amount::from_sat(1_000))])
+        .fee_rate(FeeRate::from_sat_per_kwu(2_500)); // 10 sat/vb
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
+    assert!(fee > original_fee);
+}
+
+#[test]
+fn test_bump_fee_keeps_recipient_outputs() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let other_addr = Address::from_str("bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_recipient(other_addr.script_pubkey(), Amount::from_sat(10_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut original_outputs = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|txout| !wallet.is_mine(&txout.script_pubkey))
+        .map(bitcoin::consensus::serialize)
+        .collect::<Vec<_>>();
+    assert_eq!(original_outputs.len(), 2);
+    original_outputs.sort();
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // only the change pays for the bump, the recipients are left as they were
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_kwu(1_250)); // 5 sat/vb
+    let psbt = builder.finish().unwrap();
+    let mut outputs = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|txout| !wallet.is_mine(&txout.script_pubkey))
+        .map(bitcoin::consensus::serialize)
+        .collect::<Vec<_>>();
+    // the outputs are shuffled again, only their order may change
+    outputs.sort();
+    assert_eq!(outputs, original_outputs);
+}
+
+#[test]
+fn test_bump_fee_reduce_change() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let original_fee = check_fee!(wallet, psbt);
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let feerate = FeeRate::from_sat_per_kwu(625); // 2.5 sat/vb
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(feerate).enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert_eq!(
+        sent_received.1 + fee.unwrap_or(Amount::ZERO),
+        original_sent_received.1 + original_fee.unwrap_or(Amount::ZERO)
+    );
+    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), feerate, @add_signature);
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(Amount::from_sat(200));
+    builder.enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert_eq!(
+        sent_received.1 + fee.unwrap_or(Amount::ZERO),
+        original_sent_received.1 + original_fee.unwrap_or(Amount::ZERO)
+    );
+    assert!(
+        fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO),
+        "{} > {}",
+        fee.unwrap_or(Amount::ZERO),
+        original_fee.unwrap_or(Amount::ZERO)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(200));
+}
+
+#[test]
+fn test_bump_fee_reduce_single_recipient() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.clone().extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let original_fee = check_fee!(wallet, psbt);
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let feerate = FeeRate::from_sat_per_kwu(625); // 2.5 sat/vb
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_rate(feerate)
+        // remove original tx drain_to address and amount
+        .set_recipients(Vec::new())
+        // set back original drain_to address
+        .drain_to(addr.script_pubkey())
+        // drain wallet output amount will be re-calculated with new fee rate
+        .drain_wallet();
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(
+        tx.output[0].value + fee.unwrap_or(Amount::ZERO),
+        sent_received.0
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), feerate, @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_reduce_single_recipient() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_fee = check_fee!(wallet, psbt);
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_absolute(Amount::from_sat(300))
+        // remove original tx drain_to address and amount
+        .set_recipients(Vec::new())
+        // set back original drain_to address
+        .drain_to(addr.script_pubkey())
+        // drain wallet output amount will be re-calculated with new fee rate
+        .drain_wallet();
+    let psbt = builder.finish().unwrap();
+    let tx = &psbt.unsigned_tx;
+    let sent_received = wallet.sent_and_received(tx);
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
+
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(
+        tx.output[0].value + fee.unwrap_or(Amount::ZERO),
+        sent_received.0
+    );
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(300));
+}
+
+#[test]
+fn test_bump_fee_drain_wallet() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    // receive an extra tx so that our wallet has two utxos.
+    let tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    wallet
+        .insert_tx(
+            tx.clone(),
+            ConfirmationTime::Confirmed {
+                height: wallet.latest_checkpoint().height(),
+                time: 42_000,
+            },
+        )
+        .unwrap();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(OutPoint {
+            txid: tx.compute_txid(),
+            vout: 0,
+        })
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_eq!(original_sent_received.0, Amount::from_sat(25_000));
+
+    // for the new feerate, it should be enough to reduce the output, but since we specify
+    // `drain_wallet` we expect to spend everything
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .drain_wallet()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.extract_tx().expect("failed to extract tx"));
+
+    assert_eq!(sent_received.0, Amount::from_sat(75_000));
+}
+
+#[test]
+#[should_panic(expected = "InsufficientFunds")]
+fn test_bump_fee_remove_output_manually_selected_only() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
+    // them, and make sure that `bump_fee` doesn't try to add more. This fails because we've
+    // told the wallet it's not allowed to add more inputs AND it can't reduce the value of the
+    // existing output. In other words, bump_fee + manually_selected_only is always an error
+    // unless there is a change output.
+    let init_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    wallet
+        .insert_tx(
+            init_tx.clone(),
+            wallet
+                .transactions()
+                .last()
+                .unwrap()
+                .chain_position
+                .cloned()
+                .into(),
+        )
+        .unwrap();
+    let outpoint = OutPoint {
+        txid: init_tx.compute_txid(),
+        vout: 0,
+    };
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(outpoint)
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_eq!(original_sent_received.0, Amount::from_sat(25_000));
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .manually_selected_only()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(255));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_bump_fee_add_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let init_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    let pos = wallet
+        .transactions()
+        .last()
+        .unwrap()
+        .chain_position
+        .cloned()
+        .into();
+    wallet.insert_tx(init_tx, pos).unwrap();
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_details = wallet.sent_and_received(&tx);
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+    assert_eq!(
+        sent_received.0,
+        original_details.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        fee.unwrap_or(Amount::ZERO) + sent_received.1,
+        Amount::from_sat(30_000)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(50), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_add_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(Amount::from_sat(6_000));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        fee.unwrap_or(Amount::ZERO) + sent_received.1,
+        Amount::from_sat(30_000)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(6_000));
+}
+
+#[test]
+fn test_bump_fee_no_change_add_input_and_change() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    // initially make a tx without change by using `drain_to`
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(op)
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let original_fee = check_fee!(wallet, psbt);
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // Now bump the fees, the wallet should add an extra input and a change output, and leave
+    // the original output untouched.
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    let original_send_all_amount = original_sent_received.0 - original_fee.unwrap_or(Amount::ZERO);
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(50_000)
+    );
+    assert_eq!(
+        sent_received.1,
+        Amount::from_sat(75_000) - original_send_all_amount - fee.unwrap_or(Amount::ZERO)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        original_send_all_amount
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(75_000) - original_send_all_amount - fee.unwrap_or(Amount::ZERO)
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(50), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_add_input_change_dust() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let original_fee = check_fee!(wallet, psbt);
+
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // to get realistic weight
+    }
+    let original_tx_weight = tx.weight();
+    assert_eq!(tx.input.len(), 1);
+    assert_eq!(tx.output.len(), 2);
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    // We set a fee high enough that during rbf we are forced to add
+    // a new input and also that we have to remove the change
+    // that we had previously
+
+    // We calculate the new weight as:
+    //   original weight
+    // + extra input weight: 160 WU = (32 (prevout) + 4 (vout) + 4 (nsequence)) * 4
+    // + input satisfaction weight: 112 WU = 106 (witness) + 2 (witness len) + (1 (script len)) * 4
+    // - change output weight: 124 WU = (8 (value) + 1 (script len) + 22 (script)) * 4
+    let new_tx_weight =
+        original_tx_weight + Weight::from_wu(160) + Weight::from_wu(112) - Weight::from_wu(124);
+    // two inputs (50k, 25k) and one output (45k) - epsilon
+    // We use epsilon here to avoid asking for a slightly too high feerate
+    let fee_abs = 50_000 + 25_000 - 45_000 - 10;
+    builder.fee_rate(Amount::from_sat(fee_abs) / new_tx_weight);
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(
+        original_sent_received.1,
+        Amount::from_sat(5_000) - original_fee.unwrap_or(Amount::ZERO)
+    );
+
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(30_000));
+    assert_eq!(sent_received.1, Amount::ZERO);
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(140), @dust_change, @add_signature);
+}
+
+#[test]
+fn test_bump_fee_force_add_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.compute_txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    // the new fee_rate is low enough that just reducing the change would be fine, but we force
+    // the addition of an extra input with `add_utxo()`
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .add_utxo(incoming_op)
+        .unwrap()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        fee.unwrap_or(Amount::ZERO) + sent_received.1,
+        Amount::from_sat(30_000)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(5), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_force_add_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.compute_txid();
+    // skip saving the new utxos, we know they can't be used anyways
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // the new fee_rate is low enough that just reducing the change would be fine, but we force
+    // the addition of an extra input with `add_utxo()`
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .add_utxo(incoming_op)
+        .unwrap()
+        .fee_absolute(Amount::from_sat(250));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        fee.unwrap_or(Amount::ZERO) + sent_received.1,
+        Amount::from_sat(30_000)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(250));
+}
+
+#[test]
+#[should_panic(expected = "InsufficientFunds")]
+fn test_bump_fee_unconfirmed_inputs_only() {
+    // We try to bump the fee, but:
+    // - We can't reduce the change, as we have no change
+    // - All our UTXOs are unconfirmed
+    // So, we fail with "InsufficientFunds", as per RBF rule 2:
+    // The replacement transaction may only include an unconfirmed input
+    // if that input was included in one of the original transactions.
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_wallet()
+        .drain_to(addr.script_pubkey())
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    // Now we receive one transaction with 0 confirmations. We won't be able to use that for
+    // fee bumping, as it's still unconfirmed!
+    receive_output(
+        &mut wallet,
+        25_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
+    );
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(25));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_bump_fee_unconfirmed_input() {
+    // We create a tx draining the wallet and spending one confirmed
+    // and one unconfirmed UTXO. We check that we can fee bump normally
+    // (BIP125 rule 2 only apply to newly added unconfirmed input, you can
+    // always fee bump with an unconfirmed input if it was included in the
+    // original transaction)
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    // We receive a tx with 0 confirmations, which will be used as an input
+    // in the drain tx.
+    receive_output(&mut wallet, 25_000, ConfirmationTime::unconfirmed(0));
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_wallet()
+        .drain_to(addr.script_pubkey())
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(15))
+        // remove original tx drain_to address and amount
+        .set_recipients(Vec::new())
+        // set back original drain_to address
+        .drain_to(addr.script_pubkey())
+        // drain wallet output amount will be re-calculated with new fee rate
+        .drain_wallet();
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_build_cpfp_package_feerate() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(FeeRate::from_sat_per_kwu(250)); // 1 sat/vb
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let parent = psbt.extract_tx().expect("failed to extract tx");
+    let parent_txid = parent.compute_txid();
+    let parent_fee = wallet.calculate_fee(&parent).unwrap();
+    wallet
+        .insert_tx(
+            parent.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
+        .unwrap();
+
+    let target_feerate = FeeRate::from_sat_per_kwu(5_000); // 20 sat/vb
+    let builder = wallet.build_cpfp(parent_txid, target_feerate).unwrap();
+    let mut psbt = builder.finish().unwrap();
+    let child_fee = check_fee!(wallet, psbt).unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let child = psbt.extract_tx().expect("failed to extract tx");
+
+    // the child only spends the wallet output of the parent and drains it back to the wallet
+    assert_eq!(child.input.len(), 1);
+    assert_eq!(child.input[0].previous_output.txid, parent_txid);
+    assert_eq!(child.output.len(), 1);
+    assert!(wallet.is_mine(&child.output[0].script_pubkey));
+
+    // the child pays much more than the target feerate for itself ...
+    assert!(child_fee / child.weight() > target_feerate);
+    // ... so that the whole package reaches it
+    let package_feerate = (parent_fee + child_fee) / (parent.weight() + child.weight());
+    assert!(package_feerate >= target_feerate);
+    assert!(package_feerate < FeeRate::from_sat_per_kwu(5_250)); // 21 sat/vb
+}
+
+#[test]
+fn test_build_cpfp_unconfirmed_ancestors() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let recipient = |n: u8| ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([n; 20]));
+    // the parent spends the change of its own unconfirmed parent, both at 1 sat/vb
+    let specs = (0..2)
+        .map(|n| {
+            TxBuilderSpec::new(vec![(recipient(n), Amount::from_sat(5_000))])
+                .fee_rate(FeeRate::from_sat_per_kwu(250))
+        })
+        .collect::<Vec<_>>();
+    let psbts = wallet.build_chain(specs).unwrap();
+    let ancestors = sign_chain(&wallet, psbts);
+    for tx in &ancestors {
+        wallet
+            .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+            .unwrap();
+    }
+    let parent_txid = ancestors[1].compute_txid();
+
+    let target_feerate = FeeRate::from_sat_per_kwu(5_000); // 20 sat/vb
+    let builder = wallet.build_cpfp(parent_txid, target_feerate).unwrap();
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let child = psbt.extract_tx().expect("failed to extract tx");
+
+    // the package counted as the mempool does, in whole vbytes, reaches the target ...
+    let package = ancestors.iter().chain([&child]);
+    let fee = package
+        .clone()
+        .map(|tx| wallet.calculate_fee(tx).unwrap())
+        .sum::<Amount>();
+    let vsize = package.map(|tx| tx.vsize() as u64).sum::<u64>();
+    assert!(fee / Weight::from_vb_unchecked(vsize) >= target_feerate);
+    // ... which the parent and the child alone would exceed
+    let without_grandparent = fee - wallet.calculate_fee(&ancestors[0]).unwrap();
+    let vsize = vsize - ancestors[0].vsize() as u64;
+    assert!(
+        without_grandparent / Weight::from_vb_unchecked(vsize) > FeeRate::from_sat_per_kwu(5_250)
+    );
+}
+
+#[test]
+fn test_build_cpfp_confirmed_tx() {
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    assert_matches!(
+        wallet.build_cpfp(txid, FeeRate::from_sat_per_kwu(2_500)),
+        Err(BuildCpfpError::TransactionConfirmed(_))
+    );
+}
+
+#[test]
+fn test_build_cpfp_unknown_parent_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let foreign_prevout = OutPoint {
+        txid: Txid::from_byte_array([1; 32]),
+        vout: 0,
+    };
+    let parent = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: foreign_prevout,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            script_pubkey: addr.script_pubkey(),
+            value: Amount::from_sat(30_000),
+        }],
+    };
+    let txid = parent.compute_txid();
+    wallet
+        .insert_tx(parent, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    assert_matches!(
+        wallet.build_cpfp(txid, FeeRate::from_sat_per_kwu(2_500)),
+        Err(BuildCpfpError::ParentFeeUnavailable(_))
+    );
+
+    // once we know the previous output the parent fee can be computed
+    wallet.insert_txout(
+        foreign_prevout,
+        TxOut {
+            script_pubkey: ScriptBuf::new(),
+            value: Amount::from_sat(30_500),
+        },
+    );
+    let builder = wallet
+        .build_cpfp(txid, FeeRate::from_sat_per_kwu(2_500))
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input[0].previous_output.txid, txid);
+}
+
+#[test]
+fn test_build_sweep() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let fee_rate = FeeRate::from_sat_per_kwu(1_250); // 5 sat/vb
+    let builder = wallet.build_sweep(addr.script_pubkey(), fee_rate).unwrap();
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(psbt.unsigned_tx.input.len(), 2);
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].script_pubkey,
+        addr.script_pubkey()
+    );
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value + fee.unwrap_or(Amount::ZERO),
+        Amount::from_sat(75_000)
+    );
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate, @add_signature);
+}
+
+#[test]
+fn test_build_sweep_utxos() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let outpoint = receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let builder = wallet
+        .build_sweep_utxos(
+            &[outpoint],
+            addr.script_pubkey(),
+            FeeRate::from_sat_per_kwu(250),
+        )
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(psbt.unsigned_tx.input.len(), 1);
+    assert_eq!(psbt.unsigned_tx.input[0].previous_output, outpoint);
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value + fee.unwrap_or(Amount::ZERO),
+        Amount::from_sat(25_000)
+    );
+
+    let unknown = OutPoint {
+        txid: Txid::from_byte_array([1; 32]),
+        vout: 0,
+    };
+    assert_matches!(
+        wallet.build_sweep_utxos(&[unknown], addr.script_pubkey(), FeeRate::BROADCAST_MIN),
+        Err(BuildSweepError::UnknownUtxo(op)) if op == unknown
+    );
+    assert_matches!(
+        wallet.build_sweep_utxos(&[], addr.script_pubkey(), FeeRate::BROADCAST_MIN),
+        Err(BuildSweepError::NoUtxos)
+    );
+}
+
+#[test]
+fn test_build_sweep_below_dust() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let outpoint = receive_output_in_latest_block(&mut wallet, 1_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let builder = wallet
+        .build_sweep_utxos(
+            &[outpoint],
+            addr.script_pubkey(),
+            FeeRate::from_sat_per_kwu(2_500), // 10 sat/vb
+        )
+        .unwrap();
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
+}
+
+#[test]
+fn test_build_sweep_unconfirmed() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest).unwrap();
+    let outpoint = receive_output(&mut wallet, 25_000, ConfirmationTime::unconfirmed(0));
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+
+    assert_matches!(
+        wallet.build_sweep(addr.script_pubkey(), FeeRate::BROADCAST_MIN),
+        Err(BuildSweepError::UnconfirmedUtxos)
+    );
+    assert_matches!(
+        wallet.build_sweep_utxos(&[outpoint], addr.script_pubkey(), FeeRate::BROADCAST_MIN),
+        Err(BuildSweepError::UnconfirmedUtxos)
+    );
+}
+
+#[test]
+fn test_build_sweep_immature_coinbase() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let tip = wallet.latest_checkpoint().height();
+    let coinbase_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    let coinbase_outpoint = OutPoint {
+        txid: coinbase_tx.compute_txid(),
+        vout: 0,
+    };
+    wallet
+        .insert_tx(
+            coinbase_tx,
+            ConfirmationTime::Confirmed {
+                height: tip,
+                time: 30_000,
+            },
+        )
+        .unwrap();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+
+    assert_matches!(
+        wallet.build_sweep_utxos(
+            &[coinbase_outpoint],
+            addr.script_pubkey(),
+            FeeRate::BROADCAST_MIN
+        ),
+        Err(BuildSweepError::ImmatureCoinbase(op)) if op == coinbase_outpoint
+    );
+
+    // sweeping the whole wallet leaves the immature coinbase out
+    let builder = wallet
+        .build_sweep(addr.script_pubkey(), FeeRate::BROADCAST_MIN)
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 1);
+    assert_ne!(psbt.unsigned_tx.input[0].previous_output, coinbase_outpoint);
+}
+
+#[test]
+fn test_fee_amount_negative_drain_val() {
+    // While building the transaction, bdk would calculate the drain_value
+    // as
+    // current_delta - fee_amount - drain_fee
+    // using saturating_sub, meaning that if the result would end up negative,
+    // it'll remain to zero instead.
+    // This caused a bug in master where we would calculate the wrong fee
+    // for a transaction.
+    // See https://github.com/bitcoindevkit/bdk/issues/660
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let send_to = Address::from_str("tb1ql7w62elx9ucw4pj5lgw4l028hmuw80sndtntxt")
+        .unwrap()
+        .assume_checked();
+    let fee_rate = FeeRate::from_sat_per_kwu(500);
+    let incoming_op = receive_output_in_latest_block(&mut wallet, 8859);
+
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(send_to.script_pubkey(), Amount::from_sat(8630))
+        .add_utxo(incoming_op)
+        .unwrap()
+        .enable_rbf()
+        .fee_rate(fee_rate);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(psbt.inputs.len(), 1);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate, @add_signature);
+}
+
+#[test]
+fn test_sign_single_xprv() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_xprv_with_master_fingerprint_and_path() {
+    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/84h/1h/0h]tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_xprv_bip44_path() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/44'/0'/0'/0/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_xprv_sh_wpkh() {
+    let (mut wallet, _) = get_funded_wallet("sh(wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*))");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_wif() {
+    let (mut wallet, _) =
+        get_funded_wallet("wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_xprv_no_hd_keypaths() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    psbt.inputs[0].bip32_derivation.clear();
+    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 0);
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_include_output_redeem_witness_script() {
+    let desc = get_test_wpkh();
+    let change_desc = "sh(wsh(multi(1,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))";
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .include_output_redeem_witness_script();
+    let psbt = builder.finish().unwrap();
+
+    // p2sh-p2wsh transaction should contain both witness and redeem scripts
+    assert!(psbt
+        .outputs
+        .iter()
+        .any(|output| output.redeem_script.is_some() && output.witness_script.is_some()));
+}
+
+#[test]
+fn test_signing_only_one_of_multiple_inputs() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .include_output_redeem_witness_script();
+    let mut psbt = builder.finish().unwrap();
+
+    // add another input to the psbt that is at least passable.
+    let dud_input = bitcoin::psbt::Input {
+        witness_utxo: Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: miniscript::Descriptor::<bitcoin::PublicKey>::from_str(
+                "wpkh(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)",
+            )
+            .unwrap()
+            .script_pubkey(),
+        }),
+        ..Default::default()
+    };
+
+    psbt.inputs.push(dud_input);
+    psbt.unsigned_tx.input.push(bitcoin::TxIn::default());
+    let is_final = wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(
+        !is_final,
+        "shouldn't be final since we can't sign one of the inputs"
+    );
+    assert!(
+        psbt.inputs[0].final_script_witness.is_some(),
+        "should finalized input it signed"
+    )
+}
+
+#[test]
+fn test_try_finalize_sign_option() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+
+    for try_finalize in &[true, false] {
+        let addr = wallet.next_unused_address(KeychainKind::External);
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let mut psbt = builder.finish().unwrap();
+
+        let finalized = wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    try_finalize: *try_finalize,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        psbt.inputs.iter().for_each(|input| {
+            if *try_finalize {
+                assert!(finalized);
+                assert!(input.final_script_sig.is_none());
+                assert!(input.final_script_witness.is_some());
+            } else {
+                assert!(!finalized);
+                assert!(input.final_script_sig.is_none());
+                assert!(input.final_script_witness.is_none());
+            }
+        });
+    }
+}
+
+#[test]
+fn test_taproot_try_finalize_sign_option() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+
+    for try_finalize in &[true, false] {
+        let addr = wallet.next_unused_address(KeychainKind::External);
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let mut psbt = builder.finish().unwrap();
+
+        let finalized = wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    try_finalize: *try_finalize,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        psbt.inputs.iter().for_each(|input| {
+            if *try_finalize {
+                assert!(finalized);
+                assert!(input.final_script_sig.is_none());
+                assert!(input.final_script_witness.is_some());
+                assert!(input.tap_key_sig.is_none());
+                assert!(input.tap_script_sigs.is_empty());
+                assert!(input.tap_scripts.is_empty());
+                assert!(input.tap_key_origins.is_empty());
+                assert!(input.tap_internal_key.is_none());
+                assert!(input.tap_merkle_root.is_none());
+            } else {
+                assert!(!finalized);
+                assert!(input.final_script_sig.is_none());
+                assert!(input.final_script_witness.is_none());
+            }
+        });
+        psbt.outputs.iter().for_each(|output| {
+            if *try_finalize {
+                assert!(finalized);
+                assert!(output.tap_key_origins.is_empty());
+            } else {
+                assert!(!finalized);
+                assert!(!output.tap_key_origins.is_empty());
+            }
+        });
+    }
+}
+
+#[test]
+fn test_sign_nonstandard_sighash() {
+    let sighash = EcdsaSighashType::NonePlusAnyoneCanPay;
+
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .sighash(sighash.into())
+        .drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let result = wallet.sign(&mut psbt, Default::default());
+    assert!(
+        result.is_err(),
+        "Signing should have failed because the TX uses non-standard sighashes"
+    );
+    assert_matches!(
+        result,
+        Err(SignerError::NonStandardSighash { input_index: 0, sighash: s }) if s == sighash.into(),
+        "Signing failed with the wrong error type"
+    );
+
+    // try again after opting-in
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+    assert!(result.is_ok(), "Signing should have worked");
+    assert!(
+        result.unwrap(),
+        "Should finalize the input since we can produce signatures"
+    );
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(
+        *extracted.input[0].witness.to_vec()[0].last().unwrap(),
+        sighash.to_u32() as u8,
+        "The signature should have been made with the right sighash"
+    );
+}
+
+#[test]
+fn test_analyze_psbt_wrong_sighash() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let analysis = wallet.analyze_psbt(&psbt);
+    assert_eq!(analysis.len(), 1);
+    assert_eq!(analysis[0].keychain, Some(KeychainKind::External));
+    assert_eq!(analysis[0].derivation_index, Some(0));
+    assert!(!analysis[0].finalizable);
+    assert!(analysis[0].signed.is_empty());
+    assert_eq!(analysis[0].missing_signatures.len(), 1);
+    let key = analysis[0].missing_signatures[0];
+
+    // a device signing with another sighash than the one requested
+    psbt.inputs[0].sighash_type = Some(EcdsaSighashType::None.into());
+    let sign_options = SignOptions {
+        allow_all_sighashes: true,
+        try_finalize: false,
+        ..Default::default()
+    };
+    wallet.sign(&mut psbt, sign_options.clone()).unwrap();
+    psbt.inputs[0].sighash_type = None;
+
+    let analysis = wallet.analyze_psbt(&psbt);
+    assert!(!analysis[0].finalizable);
+    assert!(analysis[0].signed.is_empty());
+    assert_eq!(
+        analysis[0].invalid_signatures,
+        [InvalidSignature {
+            key,
+            issue: SignatureIssue::WrongSighash {
+                expected: EcdsaSighashType::All.into(),
+                found: EcdsaSighashType::None.into(),
+            },
+        }]
+    );
+    assert_eq!(analysis[0].missing_signatures, [key]);
+    // `finalize_psbt` doesn't verify the signature, the analysis does
+    assert!(wallet
+        .finalize_psbt(&mut psbt.clone(), SignOptions::default())
+        .unwrap());
+
+    // a signature with the wrong sighash type byte doesn't verify
+    let mut tampered = psbt.clone();
+    let sig = tampered.inputs[0].partial_sigs.values_mut().next().unwrap();
+    sig.sighash_type = EcdsaSighashType::All;
+    let analysis = wallet.analyze_psbt(&tampered);
+    assert_eq!(
+        analysis[0].invalid_signatures,
+        [InvalidSignature {
+            key,
+            issue: SignatureIssue::Invalid,
+        }]
+    );
+
+    psbt.inputs[0].partial_sigs.clear();
+    wallet.sign(&mut psbt, sign_options).unwrap();
+    let analysis = wallet.analyze_psbt(&psbt);
+    assert!(analysis[0].finalizable);
+    assert_eq!(analysis[0].signed, [key]);
+    assert!(analysis[0].invalid_signatures.is_empty());
+    assert!(analysis[0].missing_signatures.is_empty());
+
+    assert!(wallet
+        .finalize_psbt(&mut psbt, SignOptions::default())
+        .unwrap());
+    let analysis = wallet.analyze_psbt(&psbt);
+    assert!(analysis[0].finalized);
+    assert!(analysis[0].finalizable);
+}
+
+#[test]
+fn test_analyze_psbt_unmet_older() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
+
+    // the sequence is changed before signing, disabling the relative lock time
+    psbt.unsigned_tx.input[0].sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+    let sign_options = SignOptions {
+        try_finalize: false,
+        ..Default::default()
+    };
+    wallet.sign(&mut psbt, sign_options.clone()).unwrap();
+
+    let analysis = wallet.analyze_psbt(&psbt);
+    assert_eq!(analysis[0].keychain, Some(KeychainKind::External));
+    assert!(!analysis[0].finalizable);
+    assert_eq!(analysis[0].signed.len(), 1);
+    assert!(analysis[0].invalid_signatures.is_empty());
+    assert!(analysis[0].missing_signatures.is_empty());
+    assert!(analysis[0].missing_preimages.is_empty());
+    assert_eq!(
+        analysis[0].timelocks,
+        [TimelockObstacle::Older {
+            required: bitcoin::relative::LockTime::from_height(6),
+            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+        }]
+    );
+
+    // too low
+    psbt.unsigned_tx.input[0].sequence = Sequence(5);
+    psbt.inputs[0].partial_sigs.clear();
+    wallet.sign(&mut psbt, sign_options.clone()).unwrap();
+    let analysis = wallet.analyze_psbt(&psbt);
+    assert!(!analysis[0].finalizable);
+    assert_eq!(
+        analysis[0].timelocks,
+        [TimelockObstacle::Older {
+            required: bitcoin::relative::LockTime::from_height(6),
+            sequence: Sequence(5),
+        }]
+    );
+
+    psbt.unsigned_tx.input[0].sequence = Sequence(6);
+    psbt.inputs[0].partial_sigs.clear();
+    wallet.sign(&mut psbt, sign_options).unwrap();
+    let analysis = wallet.analyze_psbt(&psbt);
+    assert!(analysis[0].finalizable);
+    assert!(analysis[0].timelocks.is_empty());
+}
+
+#[derive(Debug)]
+struct MockSigner {
+    name: &'static str,
+    result: fn() -> Result<(), SignerError>,
+    log: std::sync::Arc<std::sync::Mutex<Vec<(&'static str, usize)>>>,
+}
+
+impl bdk_wallet::signer::SignerCommon for MockSigner {
+    fn id(
+        &self,
+        _secp: &bitcoin::secp256k1::Secp256k1<bitcoin::secp256k1::All>,
+    ) -> bdk_wallet::signer::SignerId {
+        bdk_wallet::signer::SignerId::Dummy(self.name.len() as u64)
+    }
+}
+
+impl bdk_wallet::signer::TransactionSigner for MockSigner {
+    fn sign_transaction(
+        &self,
+        psbt: &mut psbt::Psbt,
+        _sign_options: &SignOptions,
+        _secp: &bitcoin::secp256k1::Secp256k1<bitcoin::secp256k1::All>,
+    ) -> Result<(), SignerError> {
+        // record how many signatures the previous signers added
+        self.log
+            .lock()
+            .unwrap()
+            .push((self.name, psbt.inputs[0].partial_sigs.len()));
+        (self.result)()
+    }
+}
+
+#[test]
+fn test_sign_custom_signers_ordering() {
+    use bdk_wallet::signer::SignerOrdering;
+    use std::sync::{Arc, Mutex};
+
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mock = |name, result| {
+        Arc::new(MockSigner {
+            name,
+            result,
+            log: log.clone(),
+        })
+    };
+    // the change keychain signers run together with the external ones, by ordering
+    wallet.add_signer(
+        KeychainKind::Internal,
+        SignerOrdering(200),
+        mock("after", || Ok(())),
+    );
+    wallet.add_signer(
+        KeychainKind::External,
+        SignerOrdering(150),
+        mock("canceled", || Err(SignerError::UserCanceled)),
+    );
+    wallet.add_signer(
+        KeychainKind::Internal,
+        SignerOrdering(50),
+        mock("before", || Ok(())),
+    );
+
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    let unsigned_psbt = psbt.clone();
+
+    let details = wallet
+        .sign_with_details(
+            &mut psbt,
+            SignOptions {
+                try_finalize: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        [("before", 0), ("canceled", 1), ("after", 1)]
+    );
+    assert_eq!(details.signed_inputs, [0].into());
+    assert_matches!(
+        details.signer_errors.as_slice(),
+        [(_, SignerError::UserCanceled)]
+    );
+
+    // the non-fatal error is only reported by `sign` if the psbt can't be finalized
+    assert_matches!(
+        wallet.sign(&mut psbt.clone(), SignOptions::default()),
+        Ok(true)
+    );
+    assert_matches!(
+        wallet.sign(
+            &mut psbt,
+            SignOptions {
+                try_finalize: false,
+                ..Default::default()
+            }
+        ),
+        Err(SignerError::UserCanceled)
+    );
+
+    // fatal errors stop the signing process right away
+    log.lock().unwrap().clear();
+    wallet.add_signer(
+        KeychainKind::External,
+        SignerOrdering(100),
+        mock("fatal", || Err(SignerError::InvalidSighash)),
+    );
+    let mut psbt = unsigned_psbt;
+    assert_matches!(
+        wallet.sign(&mut psbt, SignOptions::default()),
+        Err(SignerError::InvalidSighash)
+    );
+    assert!(!log.lock().unwrap().iter().any(|(name, _)| *name == "after"));
+}
+
+#[test]
+fn test_sign_per_input_sighash() {
+    let sighash = EcdsaSighashType::SinglePlusAnyoneCanPay;
+
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .ordering(bdk_wallet::wallet::tx_builder::TxOrdering::Untouched);
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.inputs.len(), 2);
+    psbt.inputs[1].sighash_type = Some(sighash.into());
+
+    assert_matches!(
+        wallet.sign(&mut psbt, SignOptions::default()),
+        Err(SignerError::NonStandardSighash { input_index: 1, sighash: s }) if s == sighash.into()
+    );
+
+    let finalized = wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                allow_all_sighashes: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(
+        *extracted.input[0].witness.to_vec()[0].last().unwrap(),
+        EcdsaSighashType::All.to_u32() as u8
+    );
+    assert_eq!(
+        *extracted.input[1].witness.to_vec()[0].last().unwrap(),
+        sighash.to_u32() as u8
+    );
+}
+
+#[test]
+fn test_unused_address() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let change_desc = get_test_wpkh();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).expect("wallet");
+
+    // `list_unused_addresses` should be empty if we haven't revealed any
+    assert!(wallet
+        .list_unused_addresses(KeychainKind::External)
+        .next()
+        .is_none());
+
+    assert_eq!(
+        wallet
+            .next_unused_address(KeychainKind::External)
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+    assert_eq!(
+        wallet
+            .list_unused_addresses(KeychainKind::External)
+            .next()
+            .unwrap()
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+}
+
+#[test]
+fn test_next_unused_address() {
+    let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let change = get_test_wpkh();
+    let mut wallet = Wallet::new(descriptor, change, Network::Testnet).expect("wallet");
+    assert_eq!(wallet.derivation_index(KeychainKind::External), None);
+
+    assert_eq!(
+        wallet
+            .next_unused_address(KeychainKind::External)
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+    // calling next_unused again gives same address
+    assert_eq!(
+        wallet
+            .next_unused_address(KeychainKind::External)
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+
+    // test mark used / unused
+    assert!(wallet.mark_used(KeychainKind::External, 0));
+    let next_unused_addr = wallet.next_unused_address(KeychainKind::External);
+    assert_eq!(next_unused_addr.index, 1);
+
+    assert!(wallet.unmark_used(KeychainKind::External, 0));
+    let next_unused_addr = wallet.next_unused_address(KeychainKind::External);
+    assert_eq!(next_unused_addr.index, 0);
+
+    // use the above address
+    receive_output_in_latest_block(&mut wallet, 25_000);
+
+    assert_eq!(
+        wallet
+            .next_unused_address(KeychainKind::External)
+            .to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(1));
+
+    // trying to mark index 0 unused should return false
+    assert!(!wallet.unmark_used(KeychainKind::External, 0));
+}
+
+/// The derivation index of the change of a new transaction sending 25_000 sats
+fn build_change_index(wallet: &mut Wallet) -> Option<u32> {
+    let addr = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let (psbt, change) = builder.finish_with_change().unwrap();
+    let change = change.expect("the transaction must have change");
+    if let Some(index) = change.derivation_index {
+        assert_eq!(
+            psbt.unsigned_tx.output[change.index].script_pubkey,
+            wallet
+                .peek_address(KeychainKind::Internal, index)
+                .script_pubkey()
+        );
+    }
+    change.derivation_index
+}
+
+#[test]
+fn test_change_address_policy_rotate_within() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    assert_eq!(
+        wallet.change_address_policy(),
+        ChangeAddressPolicy::FreshAlways
+    );
+    let _ = wallet.take_staged();
+
+    wallet
+        .set_change_address_policy(ChangeAddressPolicy::RotateWithin(3))
+        .unwrap();
+    let indices = (0..7)
+        .map(|_| build_change_index(&mut wallet))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        indices,
+        [0, 1, 2, 0, 1, 2, 0].map(Some),
+        "the change rotates deterministically"
+    );
+    assert_eq!(wallet.derivation_index(KeychainKind::Internal), Some(2));
+
+    // the policy and the position of the rotation are persisted
+    let changeset = wallet.take_staged().expect("staged changes");
+    assert_eq!(
+        changeset.change_address_policy,
+        Some(ChangeAddressPolicy::RotateWithin(3))
+    );
+    assert_eq!(changeset.change_rotation, Some(1));
+    let mut loaded = Wallet::load_from_changeset(wallet.snapshot()).unwrap();
+    assert_eq!(
+        loaded.change_address_policy(),
+        ChangeAddressPolicy::RotateWithin(3)
+    );
+    assert_eq!(build_change_index(&mut loaded), Some(1));
+
+    // the estimate uses the same address without moving the rotation
+    let addr = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked();
+    let mut builder = loaded.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let estimate = builder.estimate().unwrap();
+    assert_eq!(estimate.change.and_then(|c| c.derivation_index), Some(2));
+    assert_eq!(build_change_index(&mut loaded), Some(2));
+    assert_eq!(loaded.derivation_index(KeychainKind::Internal), Some(2));
+
+    // a policy for a single transaction
+    let mut builder = loaded.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .change_address_policy(ChangeAddressPolicy::Fixed(1))
+        .unwrap();
+    let (_, change) = builder.finish_with_change().unwrap();
+    assert_eq!(change.and_then(|c| c.derivation_index), Some(1));
+    assert_eq!(build_change_index(&mut loaded), Some(0));
+}
+
+#[test]
+fn test_change_address_policy_fresh_always() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    let first = build_change_index(&mut wallet).expect("change address");
+    let indices = (0..3)
+        .map(|_| build_change_index(&mut wallet))
+        .collect::<Vec<_>>();
+    assert_eq!(indices, [first + 1, first + 2, first + 3].map(Some));
+    assert_eq!(
+        wallet.derivation_index(KeychainKind::Internal),
+        Some(first + 3)
+    );
+
+    // the change set with `drain_to_change` has no derivation index
+    let addr = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .drain_to_change(addr.script_pubkey());
+    let (_, change) = builder.finish_with_change().unwrap();
+    assert_eq!(change.map(|c| c.derivation_index), Some(None));
+}
+
+#[test]
+fn test_change_address_policy_fixed() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    wallet
+        .set_change_address_policy(ChangeAddressPolicy::Fixed(5))
+        .unwrap();
+    for _ in 0..3 {
+        assert_eq!(build_change_index(&mut wallet), Some(5));
+    }
+    assert_eq!(wallet.derivation_index(KeychainKind::Internal), Some(5));
+
+    assert_eq!(
+        wallet.set_change_address_policy(ChangeAddressPolicy::RotateWithin(0)),
+        Err(ChangeAddressPolicyError::EmptyRotation)
+    );
+    assert_eq!(
+        wallet.set_change_address_policy(ChangeAddressPolicy::Fixed(1 << 31)),
+        Err(ChangeAddressPolicyError::HardenedIndex(1 << 31))
+    );
+    // the addresses already revealed don't grow the gap
+    wallet.set_reveal_guard(3);
+    assert_eq!(
+        wallet.set_change_address_policy(ChangeAddressPolicy::RotateWithin(6)),
+        Ok(())
+    );
+    assert_eq!(
+        wallet.set_change_address_policy(ChangeAddressPolicy::Fixed(6)),
+        Err(ChangeAddressPolicyError::RevealGuard(
+            RevealGuardError::GapLimit {
+                keychain: KeychainKind::Internal,
+                gap: 7,
+                max: 3,
+            }
+        ))
+    );
+    assert_eq!(
+        wallet.change_address_policy(),
+        ChangeAddressPolicy::RotateWithin(6)
+    );
+}
+
+#[test]
+fn test_reveal_guard() {
+    let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let mut wallet = Wallet::new(descriptor, get_test_wpkh(), Network::Testnet).expect("wallet");
+    assert_eq!(wallet.unused_gap(KeychainKind::External), 0);
+    wallet.set_reveal_guard(3);
+
+    for index in 0..3 {
+        let address = wallet.try_reveal_next_address(KeychainKind::External);
+        assert_eq!(address.map(|info| info.index), Ok(index));
+    }
+    assert_eq!(wallet.unused_gap(KeychainKind::External), 3);
+    assert_eq!(
+        wallet.try_reveal_next_address(KeychainKind::External),
+        Err(RevealGuardError::GapLimit {
+            keychain: KeychainKind::External,
+            gap: 3,
+            max: 3,
+        })
+    );
+    // an unused address is returned without revealing a new one
+    assert_eq!(
+        wallet
+            .try_next_unused_address(KeychainKind::External)
+            .map(|info| info.index),
+        Ok(0)
+    );
+    // marking addresses used doesn't fund them, so there is nothing left to give out
+    for index in 0..3 {
+        wallet.mark_used(KeychainKind::External, index);
+    }
+    assert!(wallet
+        .try_next_unused_address(KeychainKind::External)
+        .is_err());
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(2));
+
+    // funding the address at index 1 leaves a single unused address after it
+    let tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .peek_address(KeychainKind::External, 1)
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_eq!(wallet.unused_gap(KeychainKind::External), 1);
+    assert_eq!(
+        wallet
+            .try_next_unused_address(KeychainKind::External)
+            .map(|info| info.index),
+        Ok(3)
+    );
+    assert_eq!(
+        wallet
+            .try_reveal_next_address(KeychainKind::External)
+            .map(|info| info.index),
+        Ok(4)
+    );
+    assert!(wallet
+        .try_reveal_next_address(KeychainKind::External)
+        .is_err());
+
+    // the infallible methods ignore the guard
+    assert_eq!(wallet.reveal_next_address(KeychainKind::External).index, 5);
+    assert_eq!(wallet.unused_gap(KeychainKind::External), 4);
+    wallet.clear_reveal_guard();
+    assert_eq!(
+        wallet
+            .try_reveal_next_address(KeychainKind::External)
+            .map(|info| info.index),
+        Ok(6)
+    );
+}
+
+#[test]
+fn test_mark_used_persists() -> anyhow::Result<()> {
+    let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.sqlite");
+    let mut db = bdk_sqlite::Store::new(Connection::open(&file_path)?)?;
+
+    let unused_indexes = |wallet: &Wallet| {
+        wallet
+            .list_unused_addresses(KeychainKind::External)
+            .map(|info| info.index)
+            .collect::<Vec<_>>()
+    };
+
+    {
+        let mut wallet = Wallet::new(descriptor, get_test_wpkh(), Network::Testnet)?;
+        // marking an index beyond the last revealed one reveals it
+        assert!(wallet.mark_used(KeychainKind::External, 3));
+        assert_eq!(wallet.derivation_index(KeychainKind::External), Some(3));
+        assert_eq!(unused_indexes(&wallet), [0, 1, 2]);
+
+        assert!(wallet.mark_used(KeychainKind::External, 0));
+        assert!(!wallet.mark_used(KeychainKind::External, 0));
+        assert_eq!(wallet.next_unused_address(KeychainKind::External).index, 1);
+        db.write(&wallet.take_staged().expect("changeset"))?;
+
+        assert!(wallet.unmark_used(KeychainKind::External, 3));
+        db.write(&wallet.take_staged().expect("changeset"))?;
+    }
+
+    let wallet = Wallet::load_from_changeset(db.read()?.expect("changeset"))?;
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(3));
+    assert_eq!(unused_indexes(&wallet), [1, 2, 3]);
+
+    Ok(())
+}
+
+#[test]
+fn test_peek_address_at_index() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let change_desc = get_test_wpkh();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).unwrap();
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 1).to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 0).to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 2).to_string(),
+        "tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2"
+    );
+
+    // current new address is not affected
+    assert_eq!(
+        wallet
+            .reveal_next_address(KeychainKind::External)
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+
+    assert_eq!(
+        wallet
+            .reveal_next_address(KeychainKind::External)
+            .to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+}
+
+#[test]
+fn test_peek_address_at_index_not_derivable() {
+    let wallet = Wallet::new("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1)",
+                                 get_test_wpkh(), Network::Testnet).unwrap();
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 1).to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 0).to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 2).to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+}
+
+#[test]
+fn test_list_addresses() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let mut wallet = Wallet::new(desc, get_test_wpkh(), Network::Testnet).unwrap();
+
+    let addresses = wallet
+        .list_addresses(KeychainKind::External, 0..3)
+        .map(|info| (info.index, info.to_string()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        addresses,
+        [
+            (0, "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a".to_string()),
+            (1, "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7".to_string()),
+            (2, "tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2".to_string()),
+        ]
+    );
+    // beyond the lookahead
+    let far = wallet
+        .list_addresses(KeychainKind::External, 1_000..=1_001)
+        .collect::<Vec<_>>();
+    assert_eq!(far.len(), 2);
+    assert_eq!(far[1], wallet.peek_address(KeychainKind::External, 1_001));
+
+    // nothing was revealed
+    assert_eq!(wallet.derivation_index(KeychainKind::External), None,);
+    assert_eq!(wallet.reveal_next_address(KeychainKind::External).index, 0);
+
+    let wallet = Wallet::new("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1)",
+                                 get_test_wpkh(), Network::Testnet).unwrap();
+    assert_eq!(
+        wallet.list_addresses(KeychainKind::External, 0..5).count(),
+        1
+    );
+    assert_eq!(
+        wallet.list_addresses(KeychainKind::External, 1..5).count(),
+        0
+    );
+}
+
+#[test]
+fn test_find_address_index() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let mut wallet = Wallet::new(desc, get_test_wpkh(), Network::Testnet).unwrap();
+    let revealed = wallet.reveal_next_address(KeychainKind::External);
+    assert_eq!(
+        wallet.find_address_index(&revealed),
+        Some((KeychainKind::External, revealed.index))
+    );
+
+    // lookahead addresses are found too
+    let lookahead = wallet.peek_address(KeychainKind::External, 10);
+    assert_eq!(
+        wallet.find_address_index(&lookahead),
+        Some((KeychainKind::External, 10))
+    );
+
+    let beyond_lookahead = wallet.peek_address(KeychainKind::External, 1_000);
+    assert_eq!(wallet.find_address_index(&beyond_lookahead), None);
+
+    let foreign = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    assert_eq!(wallet.find_address_index(&foreign), None);
+}
+
+#[test]
+fn test_returns_index_and_address() {
+    let mut wallet = Wallet::new("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)",
+                                 get_test_wpkh(), Network::Testnet).unwrap();
+
+    // new index 0
+    assert_eq!(
+        wallet.reveal_next_address(KeychainKind::External),
+        AddressInfo {
+            index: 0,
+            address: Address::from_str("tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+            revision: 0,
+        }
+    );
+
+    // new index 1
+    assert_eq!(
+        wallet.reveal_next_address(KeychainKind::External),
+        AddressInfo {
+            index: 1,
+            address: Address::from_str("tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+            revision: 0,
+        }
+    );
+
+    // peek index 25
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 25),
+        AddressInfo {
+            index: 25,
+            address: Address::from_str("tb1qsp7qu0knx3sl6536dzs0703u2w2ag6ppl9d0c2")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+            revision: 0,
+        }
+    );
+
+    // new index 2
+    assert_eq!(
+        wallet.reveal_next_address(KeychainKind::External),
+        AddressInfo {
+            index: 2,
+            address: Address::from_str("tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+            revision: 0,
+        }
+    );
+}
+
+#[test]
+fn test_sending_to_bip350_bech32m_address() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(45_000));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_get_address() {
+    use bdk_wallet::descriptor::template::Bip84;
+    let key = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
+    let wallet = Wallet::new(
+        Bip84(key, KeychainKind::External),
+        Bip84(key, KeychainKind::Internal),
+        Network::Regtest,
+    )
+    .unwrap();
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 0),
+        AddressInfo {
+            index: 0,
+            address: Address::from_str("bcrt1qrhgaqu0zvf5q2d0gwwz04w0dh0cuehhqvzpp4w")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+            revision: 0,
+        }
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::Internal, 0),
+        AddressInfo {
+            index: 0,
+            address: Address::from_str("bcrt1q0ue3s5y935tw7v3gmnh36c5zzsaw4n9c9smq79")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::Internal,
+            revision: 0,
+        }
+    );
+}
+
+#[test]
+fn test_reveal_addresses() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Signet).unwrap();
+    let keychain = KeychainKind::External;
+
+    let last_revealed_addr = wallet.reveal_addresses_to(keychain, 9).last().unwrap();
+    assert_eq!(wallet.derivation_index(keychain), Some(9));
+
+    let unused_addrs = wallet.list_unused_addresses(keychain).collect::<Vec<_>>();
+    assert_eq!(unused_addrs.len(), 10);
+    assert_eq!(unused_addrs.last().unwrap(), &last_revealed_addr);
+
+    // revealing to an already revealed index returns nothing
+    let mut already_revealed = wallet.reveal_addresses_to(keychain, 9);
+    assert!(already_revealed.next().is_none());
+}
+
+#[test]
+fn test_get_address_no_reuse() {
+    use bdk_wallet::descriptor::template::Bip84;
+    use std::collections::HashSet;
+
+    let key = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
+    let mut wallet = Wallet::new(
+        Bip84(key, KeychainKind::External),
+        Bip84(key, KeychainKind::Internal),
+        Network::Regtest,
+    )
+    .unwrap();
+
+    let mut used_set = HashSet::new();
+
+    (0..3).for_each(|_| {
+        let external_addr = wallet.reveal_next_address(KeychainKind::External).address;
+        assert!(used_set.insert(external_addr));
+
+        let internal_addr = wallet.reveal_next_address(KeychainKind::Internal).address;
+        assert!(used_set.insert(internal_addr));
+    });
+}
+
+#[test]
+fn test_taproot_psbt_populate_tap_key_origins() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    let addr = wallet.reveal_next_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(
+        psbt.inputs[0]
+            .tap_key_origins
+            .clone()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![(
+            from_str!("0841db1dbaf949dbbda893e01a18f2cca9179cf8ea2d8e667857690502b06483"),
+            (vec![], (from_str!("f6a5cb8b"), from_str!("m/0/0")))
+        )],
+        "Wrong input tap_key_origins"
+    );
+    assert_eq!(
+        psbt.outputs[0]
+            .tap_key_origins
+            .clone()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![(
+            from_str!("9187c1e80002d19ddde9c5c7f5394e9a063cee8695867b58815af0562695ca21"),
+            (vec![], (from_str!("f6a5cb8b"), from_str!("m/0/1")))
+        )],
+        "Wrong output tap_key_origins"
+    );
+}
+
+#[test]
+fn test_taproot_psbt_populate_tap_key_origins_repeated_key() {
+    let (mut wallet, _) =
+        get_funded_wallet_with_change(get_test_tr_repeated_key(), get_test_tr_single_sig());
+    let addr = wallet.reveal_next_address(KeychainKind::External);
+
+    let path = vec![("rn4nre9c".to_string(), vec![0])]
+        .into_iter()
+        .collect();
+
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .policy_path(path, KeychainKind::External);
+    let psbt = builder.finish().unwrap();
+
+    let mut input_key_origins = psbt.inputs[0]
+        .tap_key_origins
+        .clone()
+        .into_iter()
+        .collect::<Vec<_>>();
+    input_key_origins.sort();
+
+    assert_eq!(
+        input_key_origins,
+        vec![
+            (
+                from_str!("2b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3"),
+                (
+                    vec![
+                        from_str!(
+                            "858ad7a7d7f270e2c490c4d6ba00c499e46b18fdd59ea3c2c47d20347110271e"
+                        ),
+                        from_str!(
+                            "f6e927ad4492c051fe325894a4f5f14538333b55a35f099876be42009ec8f903"
+                        ),
+                    ],
+                    (FromStr::from_str("ece52657").unwrap(), vec![].into())
+                )
+            ),
+            (
+                from_str!("b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55"),
+                (
+                    vec![],
+                    (FromStr::from_str("871fd295").unwrap(), vec![].into())
+                )
+            )
+        ],
+        "Wrong input tap_key_origins"
+    );
+
+    let mut output_key_origins = psbt.outputs[0]
+        .tap_key_origins
+        .clone()
+        .into_iter()
+        .collect::<Vec<_>>();
+    output_key_origins.sort();
+
+    assert_eq!(
+        input_key_origins, output_key_origins,
+        "Wrong output tap_key_origins"
+    );
+}
+
+#[test]
+fn test_taproot_psbt_input_tap_tree() {
+    use bitcoin::hex::FromHex;
+    use bitcoin::taproot;
+
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(
+        psbt.inputs[0].tap_merkle_root,
+        Some(
+            TapNodeHash::from_str(
+                "61f81509635053e52d9d1217545916167394490da2287aca4693606e43851986"
+            )
+            .unwrap()
+        ),
+    );
+    assert_eq!(
+        psbt.inputs[0].tap_scripts.clone().into_iter().collect::<Vec<_>>(),
+        vec![
+            (taproot::ControlBlock::decode(&Vec::<u8>::from_hex("c0b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55b7ef769a745e625ed4b9a4982a4dc08274c59187e73e6f07171108f455081cb2").unwrap()).unwrap(), (ScriptBuf::from_hex("208aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642ac").unwrap(), taproot::LeafVersion::TapScript)),
+            (taproot::ControlBlock::decode(&Vec::<u8>::from_hex("c0b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55b9a515f7be31a70186e3c5937ee4a70cc4b4e1efe876c1d38e408222ffc64834").unwrap()).unwrap(), (ScriptBuf::from_hex("2051494dc22e24a32fe9dcfbd7e85faf345fa1df296fb49d156e859ef345201295ac").unwrap(), taproot::LeafVersion::TapScript)),
+        ],
+    );
+    assert_eq!(
+        psbt.inputs[0].tap_internal_key,
+        Some(from_str!(
+            "b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55"
+        ))
+    );
+
+    // Since we are creating an output to the same address as the input, assert that the
+    // internal_key is the same
+    assert_eq!(
+        psbt.inputs[0].tap_internal_key,
+        psbt.outputs[0].tap_internal_key
+    );
+
+    let tap_tree: bitcoin::taproot::TapTree = serde_json::from_str(r#"[1,{"Script":["2051494dc22e24a32fe9dcfbd7e85faf345fa1df296fb49d156e859ef345201295ac",192]},1,{"Script":["208aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642ac",192]}]"#).unwrap();
+    assert_eq!(psbt.outputs[0].tap_tree, Some(tap_tree));
+}
+
+#[test]
+fn test_taproot_sign_missing_witness_utxo() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    let witness_utxo = psbt.inputs[0].witness_utxo.take();
+
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+    assert_matches!(
+        result,
+        Err(SignerError::MissingWitnessUtxo),
+        "Signing should have failed with the correct error because the witness_utxo is missing"
+    );
+
+    // restore the witness_utxo
+    psbt.inputs[0].witness_utxo = witness_utxo;
+
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+
+    assert_matches!(
+        result,
+        Ok(true),
+        "Should finalize the input since we can produce signatures"
+    );
+}
+
+#[test]
+fn test_taproot_sign_using_non_witness_utxo() {
+    let (mut wallet, prev_txid) = get_funded_wallet(get_test_tr_single_sig());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    psbt.inputs[0].witness_utxo = None;
+    psbt.inputs[0].non_witness_utxo =
+        Some(wallet.get_tx(prev_txid).unwrap().tx_node.as_ref().clone());
+    assert!(
+        psbt.inputs[0].non_witness_utxo.is_some(),
+        "Previous tx should be present in the database"
+    );
+
+    let result = wallet.sign(&mut psbt, Default::default());
+    assert!(result.is_ok(), "Signing should have worked");
+    assert!(
+        result.unwrap(),
+        "Should finalize the input since we can produce signatures"
+    );
+}
+
+#[test]
+fn test_taproot_foreign_utxo() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) = get_funded_wallet(get_test_tr_single_sig());
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let utxo = wallet2.list_unspent().next().unwrap();
+    let psbt_input = wallet2.get_psbt_input(utxo.clone(), None, false).unwrap();
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+
+    assert!(
+        psbt_input.non_witness_utxo.is_none(),
+        "`non_witness_utxo` should never be populated for taproot"
+    );
+
+    let mut builder = wallet1.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .add_foreign_utxo(
+            utxo.outpoint,
+            psbt_input,
+            foreign_utxo_satisfaction.to_wu() as usize,
+        )
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet1.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    wallet1.insert_txout(utxo.outpoint, utxo.txout);
+    let fee = check_fee!(wallet1, psbt);
+
+    assert_eq!(
+        sent_received.0 - sent_received.1,
+        Amount::from_sat(10_000) + fee.unwrap_or(Amount::ZERO),
+        "we should have only net spent ~10_000"
+    );
+
+    assert!(
+        psbt.unsigned_tx
+            .input
+            .iter()
+            .any(|input| input.previous_output == utxo.outpoint),
+        "foreign_utxo should be in there"
+    );
+}
+
+fn test_spend_from_wallet(mut wallet: Wallet) {
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    assert!(
+        wallet.sign(&mut psbt, Default::default()).unwrap(),
+        "Unable to finalize tx"
+    );
+}
+
+//     #[test]
+//     fn test_taproot_key_spend() {
+//         let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+//         test_spend_from_wallet(wallet);
+
+//         let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+//         test_spend_from_wallet(wallet);
+//     }
+
+#[test]
+fn test_taproot_no_key_spend() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    assert!(
+        wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    sign_with_tap_internal_key: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        "Unable to finalize tx"
+    );
+
+    assert!(psbt.inputs.iter().all(|i| i.tap_key_sig.is_none()));
+}
+
+#[test]
+fn test_taproot_script_spend() {
+    let (wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+    test_spend_from_wallet(wallet);
+
+    let (wallet, _) = get_funded_wallet(get_test_tr_with_taptree_xprv());
+    test_spend_from_wallet(wallet);
+}
+
+#[test]
+fn test_taproot_script_spend_sign_all_leaves() {
+    use bdk_wallet::signer::TapLeavesOptions;
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    assert!(
+        wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    tap_leaves_options: TapLeavesOptions::All,
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        "Unable to finalize tx"
+    );
+
+    assert!(psbt
+        .inputs
+        .iter()
+        .all(|i| i.tap_script_sigs.len() == i.tap_scripts.len()));
+}
+
+#[test]
+fn test_taproot_script_spend_sign_include_some_leaves() {
+    use bdk_wallet::signer::TapLeavesOptions;
+    use bitcoin::taproot::TapLeafHash;
+
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+    let mut script_leaves: Vec<_> = psbt.inputs[0]
+        .tap_scripts
+        .clone()
+        .values()
+        .map(|(script, version)| TapLeafHash::from_script(script, *version))
+        .collect();
+    let included_script_leaves = vec![script_leaves.pop().unwrap()];
+    let excluded_script_leaves = script_leaves;
+
+    assert!(
+        wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    tap_leaves_options: TapLeavesOptions::Include(included_script_leaves.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        "Unable to finalize tx"
+    );
+
+    assert!(psbt.inputs[0]
+        .tap_script_sigs
+        .iter()
+        .all(|s| included_script_leaves.contains(&s.0 .1)
+            && !excluded_script_leaves.contains(&s.0 .1)));
+}
+
+#[test]
+fn test_taproot_script_spend_sign_exclude_some_leaves() {
+    use bdk_wallet::signer::TapLeavesOptions;
+    use bitcoin::taproot::TapLeafHash;
+
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+    let mut script_leaves: Vec<_> = psbt.inputs[0]
+        .tap_scripts
+        .clone()
+        .values()
+        .map(|(script, version)| TapLeafHash::from_script(script, *version))
+        .collect();
+    let included_script_leaves = [script_leaves.pop().unwrap()];
+    let excluded_script_leaves = script_leaves;
+
+    assert!(
+        wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    tap_leaves_options: TapLeavesOptions::Exclude(excluded_script_leaves.clone()),
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        "Unable to finalize tx"
+    );
+
+    assert!(psbt.inputs[0]
+        .tap_script_sigs
+        .iter()
+        .all(|s| included_script_leaves.contains(&s.0 .1)
+            && !excluded_script_leaves.contains(&s.0 .1)));
+}
+
+#[test]
+fn test_taproot_script_spend_sign_no_leaves() {
+    use bdk_wallet::signer::TapLeavesOptions;
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                tap_leaves_options: TapLeavesOptions::None,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert!(psbt.inputs.iter().all(|i| i.tap_script_sigs.is_empty()));
+}
+
+#[test]
+fn test_taproot_script_spend_multi_a_two_wallets() {
+    // tr(internal, multi_a(2, A, B)) where each wallet only holds one of the leaf keys
+    let desc = |a: &str, b: &str, keychain: u32| {
+        format!("tr(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/{keychain}/*,multi_a(2,{a}/{keychain}/*,{b}/{keychain}/*))")
+    };
+    let a_priv = "tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN";
+    let a_pub = "tpubD6NzVbkrYhZ4WgCeJid2Zds24zATB58r1q1qTLMuApUxZUxzETADNTeP6SvZKSsXs4qhvFAC21GFjXHwgxAcDtZqzzj8JMpsFDgqyjSJHGa";
+    let b_priv = "tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L";
+    let b_pub = "tpubD6NzVbkrYhZ4XS88FM9UegpoPq8MHbNzz5Y8imrsHPVV5Uyhfqe3skHVAaziMyehys4CPCzsB8KQZuYEbmJJ3NQgnhSkfDuAqkW2PGyewpB";
+    let (mut wallet_a, _) =
+        get_funded_wallet_with_change(&desc(a_priv, b_pub, 0), &desc(a_priv, b_pub, 1));
+    let (wallet_b, _) =
+        get_funded_wallet_with_change(&desc(a_pub, b_priv, 0), &desc(a_pub, b_priv, 1));
+    assert_eq!(
+        wallet_a.peek_address(KeychainKind::External, 0),
+        wallet_b.peek_address(KeychainKind::External, 0)
+    );
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet_a.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet_a.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(!finalized, "one signature isn't enough");
+    assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 1);
+    assert!(psbt.inputs[0].tap_key_sig.is_none());
+
+    let finalized = wallet_b.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(finalized, "both leaf keys signed");
+    assert!(psbt.inputs[0].final_script_witness.is_some());
+}
+
+const COSIGNER_A_PRIV: &str = "tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN";
+const COSIGNER_A_PUB: &str = "tpubD6NzVbkrYhZ4WgCeJid2Zds24zATB58r1q1qTLMuApUxZUxzETADNTeP6SvZKSsXs4qhvFAC21GFjXHwgxAcDtZqzzj8JMpsFDgqyjSJHGa";
+const COSIGNER_B_PRIV: &str = "tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L";
+const COSIGNER_B_PUB: &str = "tpubD6NzVbkrYhZ4XS88FM9UegpoPq8MHbNzz5Y8imrsHPVV5Uyhfqe3skHVAaziMyehys4CPCzsB8KQZuYEbmJJ3NQgnhSkfDuAqkW2PGyewpB";
+
+/// Build the wallets of two cosigners, each one holding one of the keys of `desc`
+fn get_cosigner_wallets(desc: impl Fn(&str, &str, u32) -> String) -> (Wallet, Wallet) {
+    let (wallet_a, _) = get_funded_wallet_with_change(
+        &desc(COSIGNER_A_PRIV, COSIGNER_B_PUB, 0),
+        &desc(COSIGNER_A_PRIV, COSIGNER_B_PUB, 1),
+    );
+    let (wallet_b, _) = get_funded_wallet_with_change(
+        &desc(COSIGNER_A_PUB, COSIGNER_B_PRIV, 0),
+        &desc(COSIGNER_A_PUB, COSIGNER_B_PRIV, 1),
+    );
+    (wallet_a, wallet_b)
+}
+
+fn cosigner_sign(wallet: &Wallet, psbt: &psbt::Psbt) -> psbt::Psbt {
+    let mut psbt = psbt.clone();
+    let finalized = wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                try_finalize: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(!finalized);
+    psbt
+}
+
+#[test]
+fn test_combine_psbts_multisig() {
+    let (mut wallet_a, wallet_b) = get_cosigner_wallets(|a, b, keychain| {
+        format!("wsh(multi(2,{a}/{keychain}/*,{b}/{keychain}/*))")
+    });
+    let addr = wallet_a.next_unused_address(KeychainKind::External);
+    let mut builder = wallet_a.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let psbt = builder.finish().unwrap();
+
+    let psbt_a = cosigner_sign(&wallet_a, &psbt);
+    let psbt_b = cosigner_sign(&wallet_b, &psbt);
+
+    let mut base = psbt.clone();
+    let report = wallet_a.combine_psbts(&mut base, [psbt_a.clone()]).unwrap();
+    assert_eq!(
+        report.inputs,
+        [Some(InputSignatures {
+            present: 1,
+            required: 2
+        })]
+    );
+    assert!(!report.is_complete());
+    assert!(!wallet_a
+        .finalize_psbt(&mut base.clone(), SignOptions::default())
+        .unwrap());
+
+    let report = wallet_a.combine_psbts(&mut base, [psbt_a, psbt_b]).unwrap();
+    assert_eq!(
+        report.inputs,
+        [Some(InputSignatures {
+            present: 2,
+            required: 2
+        })]
+    );
+    assert!(report.is_complete());
+    assert_eq!(base.inputs[0].partial_sigs.len(), 2);
+    assert!(wallet_a
+        .finalize_psbt(&mut base, SignOptions::default())
+        .unwrap());
+}
+
+#[test]
+fn test_combine_psbts_taproot_script_sigs() {
+    let (mut wallet_a, wallet_b) = get_cosigner_wallets(|a, b, keychain| {
+        format!("tr(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/{keychain}/*,multi_a(2,{a}/{keychain}/*,{b}/{keychain}/*))")
+    });
+    let addr = wallet_a.next_unused_address(KeychainKind::External);
+    let mut builder = wallet_a.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    let psbt_a = cosigner_sign(&wallet_a, &psbt);
+    let psbt_b = cosigner_sign(&wallet_b, &psbt);
+
+    let report = wallet_b.combine_psbts(&mut psbt, [psbt_a, psbt_b]).unwrap();
+    assert!(report.is_complete());
+    assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 2);
+    assert!(wallet_b
+        .finalize_psbt(&mut psbt, SignOptions::default())
+        .unwrap());
+}
+
+#[test]
+fn test_combine_psbts_invalid_signature() {
+    let (mut wallet_a, wallet_b) = get_cosigner_wallets(|a, b, keychain| {
+        format!("wsh(multi(2,{a}/{keychain}/*,{b}/{keychain}/*))")
+    });
+    let addr = wallet_a.next_unused_address(KeychainKind::External);
+    let mut builder = wallet_a.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let psbt = builder.finish().unwrap();
+
+    let psbt_a = cosigner_sign(&wallet_a, &psbt);
+    let mut psbt_b = cosigner_sign(&wallet_b, &psbt);
+    // replace the signature of cosigner B with the one of cosigner A
+    let sig_a = *psbt_a.inputs[0].partial_sigs.values().next().unwrap();
+    psbt_b.inputs[0]
+        .partial_sigs
+        .values_mut()
+        .for_each(|sig| *sig = sig_a);
+
+    let mut base = psbt_a.clone();
+    assert_matches!(
+        wallet_a.combine_psbts(&mut base, [psbt_b]),
+        Err(CombineError::InvalidSignature { input_index: 0 })
+    );
+    assert_eq!(base, psbt_a, "the base psbt is left untouched");
+}
+
+#[test]
+fn test_combine_psbts_different_transaction() {
+    let (mut wallet_a, wallet_b) = get_cosigner_wallets(|a, b, keychain| {
+        format!("wsh(multi(2,{a}/{keychain}/*,{b}/{keychain}/*))")
+    });
+    let addr = wallet_a.next_unused_address(KeychainKind::External);
+    let mut builder = wallet_a.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+    let mut builder = wallet_a.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(20_000));
+    let other = cosigner_sign(&wallet_b, &builder.finish().unwrap());
+
+    assert_matches!(
+        wallet_a.combine_psbts(&mut psbt, [other.clone()]),
+        Err(CombineError::DifferentTransaction { expected, found })
+            if expected == psbt.unsigned_tx.compute_txid()
+                && found == other.unsigned_tx.compute_txid()
+    );
+}
+
+#[test]
+fn test_taproot_script_spend_same_master_key() {
+    // the same master key is used for both the internal key and the leaf key
+    let (mut wallet, _) = get_funded_wallet("tr(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/1/*,pk(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/0/*))");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                sign_with_tap_internal_key: false,
+                try_finalize: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(!finalized);
+    assert!(psbt.inputs[0].tap_key_sig.is_none());
+    assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 1);
+
+    let finalized = wallet
+        .finalize_psbt(&mut psbt, SignOptions::default())
+        .unwrap();
+    assert!(finalized, "the script path can be finalized");
+}
+
+#[test]
+fn test_taproot_sign_derive_index_from_psbt() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    // re-create the wallet with an empty db
+    let wallet_empty = Wallet::new(
+        get_test_tr_single_sig_xprv(),
+        get_test_tr_single_sig(),
+        Network::Regtest,
+    )
+    .unwrap();
+
+    // signing with an empty db means that we will only look at the psbt to infer the
+    // derivation index
+    assert!(
+        wallet_empty.sign(&mut psbt, Default::default()).unwrap(),
+        "Unable to finalize tx"
+    );
+}
+
+#[test]
+fn test_taproot_sign_explicit_sighash_all() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .sighash(TapSighashType::All.into())
+        .drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let result = wallet.sign(&mut psbt, Default::default());
+    assert!(
+        result.is_ok(),
+        "Signing should work because SIGHASH_ALL is safe"
+    )
+}
+
+#[test]
+fn test_taproot_sign_non_default_sighash() {
+    let sighash = TapSighashType::NonePlusAnyoneCanPay;
+
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .sighash(sighash.into())
+        .drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let witness_utxo = psbt.inputs[0].witness_utxo.take();
+
+    let result = wallet.sign(&mut psbt, Default::default());
+    assert!(
+        result.is_err(),
+        "Signing should have failed because the TX uses non-standard sighashes"
+    );
+    assert_matches!(
+        result,
+        Err(SignerError::NonStandardSighash { input_index: 0, sighash: s }) if s == sighash.into(),
+        "Signing failed with the wrong error type"
+    );
+
+    // try again after opting-in
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+    assert!(
+        result.is_err(),
+        "Signing should have failed because the witness_utxo is missing"
+    );
+    assert_matches!(
+        result,
+        Err(SignerError::MissingWitnessUtxo),
+        "Signing failed with the wrong error type"
+    );
+
+    // restore the witness_utxo
+    psbt.inputs[0].witness_utxo = witness_utxo;
+
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+
+    assert!(result.is_ok(), "Signing should have worked");
+    assert!(
+        result.unwrap(),
+        "Should finalize the input since we can produce signatures"
+    );
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(
+        *extracted.input[0].witness.to_vec()[0].last().unwrap(),
+        sighash as u8,
+        "The signature should have been made with the right sighash"
+    );
+}
+
+#[test]
+fn test_spend_coinbase() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest).unwrap();
+
+    let confirmation_height = 5;
+    wallet
+        .insert_checkpoint(BlockId {
+            height: confirmation_height,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    let coinbase_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    wallet
+        .insert_tx(
+            coinbase_tx,
+            ConfirmationTime::Confirmed {
+                height: confirmation_height,
+                time: 30_000,
+            },
+        )
+        .unwrap();
+
+    // the spending transaction is included at best in the block after the current height
+    let not_yet_mature_time = confirmation_height + COINBASE_MATURITY - 2;
+    let maturity_time = confirmation_height + COINBASE_MATURITY - 1;
+
+    let balance = wallet.balance();
+    assert_eq!(
+        balance,
+        Balance {
+            immature: Amount::from_sat(25_000),
+            trusted_pending: Amount::ZERO,
+            untrusted_pending: Amount::ZERO,
+            confirmed: Amount::ZERO
+        }
+    );
+
+    // We try to create a transaction, only to notice that all
+    // our funds are unspendable
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), balance.immature / 2)
+        .current_height(confirmation_height);
+    assert!(matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds {
+                needed: _,
+                available: Amount::ZERO
+            }
+        ))
+    ));
+
+    // Still unspendable...
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), balance.immature / 2)
+        .current_height(not_yet_mature_time);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds {
+                needed: _,
+                available: Amount::ZERO
+            }
+        ))
+    );
+
+    // ...even when selected manually
+    let utxo = wallet.list_unspent().next().unwrap();
+    assert!(utxo.is_coinbase);
+    assert_eq!(
+        utxo.mature_at_height(),
+        Some(confirmation_height + COINBASE_MATURITY)
+    );
+    assert!(!utxo.is_mature(not_yet_mature_time));
+    assert!(utxo.is_mature(maturity_time));
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), balance.immature / 2)
+        .add_utxo(utxo.outpoint)
+        .unwrap()
+        .current_height(not_yet_mature_time);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::ImmatureCoinbase {
+            outpoint,
+            mature_at_height,
+        }) if outpoint == utxo.outpoint && mature_at_height == confirmation_height + COINBASE_MATURITY
+    );
+
+    wallet
+        .insert_checkpoint(BlockId {
+            height: maturity_time,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    let balance = wallet.balance();
+    assert_eq!(
+        balance,
+        Balance {
+            immature: Amount::ZERO,
+            trusted_pending: Amount::ZERO,
+            untrusted_pending: Amount::ZERO,
+            confirmed: Amount::from_sat(25_000)
+        }
+    );
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), balance.confirmed / 2)
+        .current_height(maturity_time);
+    builder.finish().unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), balance.confirmed / 2)
+        .add_utxo(utxo.outpoint)
+        .unwrap()
+        .manually_selected_only()
+        .current_height(maturity_time);
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_spend_coinbase_regtest() -> anyhow::Result<()> {
+    use bdk_testenv::bitcoincore_rpc::RpcApi;
+    use bdk_testenv::TestEnv;
+
+    let env = TestEnv::new()?;
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest)?;
+    let address = wallet.reveal_next_address(KeychainKind::External).address;
+    let hash = env.mine_blocks(1, Some(address.clone()))?[0];
+    let height = env.rpc_client().get_block_count()? as u32;
+    let coinbase_tx = env.rpc_client().get_block(&hash)?.txdata.remove(0);
+    wallet.insert_checkpoint(BlockId { height, hash })?;
+    wallet.insert_tx(coinbase_tx, ConfirmationTime::Confirmed { height, time: 0 })?;
+    let utxo = wallet.list_unspent().next().expect("the coinbase output");
+    assert_eq!(utxo.mature_at_height(), Some(height + COINBASE_MATURITY));
+
+    // the coinbase output with 99, 100 and 101 confirmations
+    env.mine_blocks(COINBASE_MATURITY as usize - 2, None)?;
+    for confirmations in [99, 100, 101] {
+        let tip = env.rpc_client().get_block_count()? as u32;
+        assert_eq!(tip + 1 - height, confirmations);
+        wallet.insert_checkpoint(BlockId {
+            height: tip,
+            hash: env.rpc_client().get_block_hash(tip as u64)?,
+        })?;
+
+        let mut builder = wallet.build_tx();
+        builder
+            .add_utxo(utxo.outpoint)?
+            .drain_to(address.script_pubkey())
+            .nlocktime(absolute::LockTime::ZERO);
+        let result = builder.finish();
+        assert_eq!(result.is_ok(), utxo.is_mature(tip));
+
+        // bitcoind agrees with the wallet: ignore the wallet maturity to build the transaction
+        let mut builder = wallet.build_tx();
+        builder
+            .add_utxo(utxo.outpoint)?
+            .drain_to(address.script_pubkey())
+            .nlocktime(absolute::LockTime::ZERO)
+            .current_height(height + COINBASE_MATURITY);
+        let mut psbt = builder.finish()?;
+        assert!(wallet.sign(&mut psbt, SignOptions::default())?);
+        let tx = psbt.extract_tx()?;
+        let accepted = env.rpc_client().test_mempool_accept(&[&tx])?;
+        assert_eq!(accepted[0].allowed, confirmations >= 100);
+        if let Err(e) = result {
+            assert_matches!(e, CreateTxError::ImmatureCoinbase { outpoint, .. } if outpoint == utxo.outpoint);
+        }
+        wallet.cancel_tx(&tx);
+        env.mine_blocks(1, None)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_allow_dust_limit() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
+
+    let addr = wallet.next_unused_address(KeychainKind::External);
+
+    let mut builder = wallet.build_tx();
+
+    builder.add_recipient(addr.script_pubkey(), Amount::ZERO);
+
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::OutputBelowDustLimit(0))
+    );
+
+    let mut builder = wallet.build_tx();
+
+    builder
+        .allow_dust(true)
+        .add_recipient(addr.script_pubkey(), Amount::ZERO);
+
+    assert!(builder.finish().is_ok());
+}
+
+#[test]
+fn test_dust_relay_feerate() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    // 294 sats at the default 3 sat/vB, 98 sats at 1 sat/vB
+    assert_eq!(
+        dust_value(&addr.script_pubkey(), DEFAULT_DUST_RELAY_FEERATE),
+        Amount::from_sat(294)
+    );
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(200));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::OutputBelowDustLimit(0))
+    );
+
+    let mut builder = wallet.build_tx();
+    builder
+        .dust_relay_feerate(FeeRate::from_sat_per_vb_u32(1))
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(200));
+    assert!(builder.finish().is_ok());
+
+    let mut builder = wallet.build_tx();
+    builder.dust_relay_feerate(FeeRate::from_sat_per_vb_u32(1));
+    let errors = match builder.add_recipients(vec![
+        (addr.as_unchecked().clone(), Amount::from_sat(97)),
+        (addr.as_unchecked().clone(), Amount::from_sat(98)),
+    ]) {
+        Err(CreateTxError::InvalidRecipients(errors)) => errors,
+        _ => panic!("expected invalid recipients"),
+    };
+    assert_eq!(
+        errors,
+        vec![(
+            0,
+            RecipientError::BelowDustLimit {
+                amount: Amount::from_sat(97),
+                dust_limit: Amount::from_sat(98),
+            }
+        )]
+    );
+
+    // OP_RETURN outputs are never dust
+    let op_return = ScriptBuf::new_op_return([0xaa; 4]);
+    assert_eq!(
+        dust_value(&op_return, DEFAULT_DUST_RELAY_FEERATE),
+        Amount::ZERO
+    );
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .add_recipient(op_return, Amount::ZERO);
+    assert!(builder.finish().is_ok());
+}
+
+#[test]
+fn test_p2tr_change_dust_threshold() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let change_script = wallet
+        .peek_address(KeychainKind::Internal, 0)
+        .script_pubkey();
+    assert_eq!(
+        dust_value(&change_script, DEFAULT_DUST_RELAY_FEERATE),
+        Amount::from_sat(330)
+    );
+    let fee = Amount::from_sat(1_000);
+
+    // a change of exactly the dust limit is created
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(50_000 - 1_000 - 330))
+        .fee_absolute(fee);
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.output.len(), 2);
+    let change = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .find(|txout| txout.script_pubkey.is_p2tr())
+        .unwrap();
+    assert_eq!(change.value, Amount::from_sat(330));
+    assert_eq!(check_fee!(wallet, psbt), Some(fee));
+
+    // one sat less is added to the fee
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(50_000 - 1_000 - 329))
+        .fee_absolute(fee);
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(check_fee!(wallet, psbt), Some(fee + Amount::from_sat(329)));
+
+    // unless the dust relay feerate is lowered
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(50_000 - 1_000 - 329))
+        .fee_absolute(fee)
+        .dust_relay_feerate(FeeRate::from_sat_per_vb_u32(1));
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.output.len(), 2);
+    assert_eq!(check_fee!(wallet, psbt), Some(fee));
+}
+
+#[test]
+fn test_add_recipients_reports_invalid() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.peek_address(KeychainKind::External, 0).address;
+    let mainnet_addr = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+    let recipients = vec![
+        (addr.as_unchecked().clone(), Amount::from_sat(10_000)),
+        (mainnet_addr.clone(), Amount::from_sat(10_000)),
+        (addr.as_unchecked().clone(), Amount::from_sat(100)),
+        (addr.as_unchecked().clone(), Amount::from_sat(20_000)),
+    ];
+
+    let mut builder = wallet.build_tx();
+    let errors = match builder.add_recipients(recipients.clone()) {
+        Err(CreateTxError::InvalidRecipients(errors)) => errors,
+        _ => panic!("expected invalid recipients"),
+    };
+    assert_eq!(
+        errors,
+        vec![
+            (
+                1,
+                RecipientError::WrongNetwork {
+                    address: mainnet_addr,
+                    expected: Network::Regtest,
+                }
+            ),
+            (
+                2,
+                RecipientError::BelowDustLimit {
+                    amount: Amount::from_sat(100),
+                    dust_limit: addr.script_pubkey().minimal_non_dust(),
+                }
+            ),
+        ]
+    );
+    // nothing was added
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::InvalidParams(errors)) if errors == vec![ParamError::NoRecipients]
+    );
+
+    let mut builder = wallet.build_tx();
+    builder
+        .skip_invalid_recipients(true)
+        .add_recipients(recipients)
+        .unwrap();
+    assert_eq!(
+        builder
+            .skipped_recipients()
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    let psbt = builder.finish().unwrap();
+    let mut values = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|txout| txout.script_pubkey == addr.script_pubkey())
+        .map(|txout| txout.value.to_sat())
+        .collect::<Vec<_>>();
+    values.sort_unstable();
+    assert_eq!(values, vec![10_000, 20_000]);
+}
+
+#[test]
+fn test_merge_duplicate_recipients() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+    let addr = wallet.peek_address(KeychainKind::External, 0).address;
+    let other = wallet.peek_address(KeychainKind::External, 1).address;
+    let data = PushBytesBuf::try_from(vec![0]).unwrap();
+
+    let mut builder = wallet.build_tx();
+    builder
+        .ordering(bdk_wallet::wallet::tx_builder::TxOrdering::Untouched)
+        .merge_duplicate_recipients(true)
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .add_recipient(other.script_pubkey(), Amount::from_sat(5_000))
+        .add_data(&data)
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(2_000))
+        .add_data(&data);
+    let psbt = builder.finish().unwrap();
+    let outputs = &psbt.unsigned_tx.output;
+
+    // two payments, two OP_RETURNs and the change
+    assert_eq!(outputs.len(), 5);
+    assert_eq!(outputs[0].script_pubkey, addr.script_pubkey());
+    assert_eq!(outputs[0].value, Amount::from_sat(12_000));
+    assert_eq!(outputs[1].script_pubkey, other.script_pubkey());
+    assert!(outputs[2].script_pubkey.is_op_return());
+    assert!(outputs[3].script_pubkey.is_op_return());
+}
+
+#[test]
+fn test_add_recipients_large_batch() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let recipients = (0..200u8)
+        .map(|i| {
+            let script = ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([i; 20]));
+            let addr = Address::from_script(&script, Network::Regtest).unwrap();
+            (addr.as_unchecked().clone(), Amount::from_sat(100))
+        })
+        .collect::<Vec<_>>();
+    let fee_rate = FeeRate::from_sat_per_vb_u32(2);
+
+    let mut builder = wallet.build_tx();
+    builder
+        .allow_dust(true)
+        .add_recipients(recipients)
+        .unwrap()
+        .fee_rate(fee_rate);
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.output.len(), 201);
+
+    let fee = check_fee!(wallet, psbt);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate, @add_signature);
+}
+
+#[test]
+fn test_fee_rate_sign_no_grinding_high_r() {
+    // Our goal is to obtain a transaction with a signature with high-R (71 bytes
+    // instead of 70). We then check that our fee rate and fee calculation is
+    // alright.
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
+    let mut builder = wallet.build_tx();
+    let mut data = PushBytesBuf::try_from(vec![0]).unwrap();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_rate(fee_rate)
+        .add_data(&data);
+    let mut psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    let (op_return_vout, _) = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_n, i)| i.script_pubkey.is_op_return())
+        .unwrap();
+
+    let mut sig_len: usize = 0;
+    // We try to sign many different times until we find a longer signature (71 bytes)
+    while sig_len < 71 {
+        // Changing the OP_RETURN data will make the signature change (but not the fee, until
+        // data[0] is small enough)
+        data.as_mut_bytes()[0] += 1;
+        psbt.unsigned_tx.output[op_return_vout].script_pubkey = ScriptBuf::new_op_return(&data);
+        // Clearing the previous signature
+        psbt.inputs[0].partial_sigs.clear();
+        // Signing
+        wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    try_finalize: false,
+                    allow_grinding: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        // We only have one key in the partial_sigs map, this is a trick to retrieve it
+        let key = psbt.inputs[0].partial_sigs.keys().next().unwrap();
+        sig_len = psbt.inputs[0].partial_sigs[key]
+            .signature
+            .serialize_der()
+            .len();
+    }
+    // Actually finalizing the transaction...
+    wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                allow_grinding: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    // ...and checking that everything is fine
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate);
+}
+
+#[test]
+fn test_fee_rate_sign_grinding_low_r() {
+    // Our goal is to obtain a transaction with a signature with low-R (70 bytes)
+    // by setting the `allow_grinding` signing option as true.
+    // We then check that our fee rate and fee calculation is alright and that our
+    // signature is 70 bytes.
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_rate(fee_rate);
+    let mut psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                try_finalize: false,
+                allow_grinding: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let key = psbt.inputs[0].partial_sigs.keys().next().unwrap();
+    let sig_len = psbt.inputs[0].partial_sigs[key]
+        .signature
+        .serialize_der()
+        .len();
+    assert_eq!(sig_len, 70);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate);
+}
+
+#[test]
+fn test_taproot_load_descriptor_duplicated_keys() {
+    // Added after issue https://github.com/bitcoindevkit/bdk/issues/760
+    //
+    // Having the same key in multiple taproot leaves is safe and should be accepted by BDK
+
+    let (wallet, _) = get_funded_wallet(get_test_tr_dup_keys());
+    let addr = wallet.peek_address(KeychainKind::External, 0);
+
+    assert_eq!(
+        addr.to_string(),
+        "bcrt1pvysh4nmh85ysrkpwtrr8q8gdadhgdejpy6f9v424a8v9htjxjhyqw9c5s5"
+    );
+}
+
+#[test]
+/// The wallet should re-use previously allocated change addresses when the tx using them is cancelled
+fn test_tx_cancellation() {
+    macro_rules! new_tx {
+        ($wallet:expr) => {{
+            let addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
+                .unwrap()
+                .assume_checked();
+            let mut builder = $wallet.build_tx();
+            builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
+
+            let psbt = builder.finish().unwrap();
+
+            psbt
+        }};
+    }
+
+    let (mut wallet, _) =
+        get_funded_wallet_with_change(get_test_wpkh(), get_test_tr_single_sig_xprv());
+
+    let psbt1 = new_tx!(wallet);
+    let change_derivation_1 = psbt1
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_1, (KeychainKind::Internal, 0));
+
+    let psbt2 = new_tx!(wallet);
+
+    let change_derivation_2 = psbt2
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_2, (KeychainKind::Internal, 1));
+
+    wallet.cancel_tx(&psbt1.extract_tx().expect("failed to extract tx"));
+
+    let psbt3 = new_tx!(wallet);
+    let change_derivation_3 = psbt3
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_3, (KeychainKind::Internal, 0));
+
+    let psbt3 = new_tx!(wallet);
+    let change_derivation_3 = psbt3
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_3, (KeychainKind::Internal, 2));
+
+    wallet.cancel_tx(&psbt3.extract_tx().expect("failed to extract tx"));
+
+    let psbt3 = new_tx!(wallet);
+    let change_derivation_4 = psbt3
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_4, (KeychainKind::Internal, 2));
+}
+
+#[test]
+fn test_thread_safety() {
+    fn thread_safe<T: Send + Sync>() {}
+    thread_safe::<Wallet>(); // compiles only if true
+}
+
+/// Returns the account-level watch-only descriptors for `desc(key)` (with the key origin), and
+/// the matching descriptors holding the master private key
+fn get_watch_only_and_signing_descriptors(
+    desc: impl Fn(&str) -> String,
+) -> ((String, String), (String, String)) {
+    use bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+
+    let secp = Secp256k1::new();
+    let xprv = Xpriv::from_str(COSIGNER_A_PRIV).unwrap();
+    let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+    let account_xpub = Xpub::from_priv(&secp, &xprv.derive_priv(&secp, &path).unwrap());
+    let fingerprint = xprv.fingerprint(&secp);
+
+    let watch_only = |branch: u32| {
+        desc(&format!(
+            "[{fingerprint}/84'/1'/0']{account_xpub}/{branch}/*"
+        ))
+    };
+    let signing = |branch: u32| desc(&format!("{COSIGNER_A_PRIV}/84'/1'/0'/{branch}/*"));
+    ((watch_only(0), watch_only(1)), (signing(0), signing(1)))
+}
+
+#[test]
+fn test_watch_only_wallet_offline_signing() {
+    for desc in [
+        (|k: &str| format!("wpkh({k})")) as fn(&str) -> String,
+        |k: &str| format!("tr({k})"),
+        |k: &str| format!("pkh({k})"),
+    ] {
+        let ((watch_ext, watch_int), (sign_ext, sign_int)) =
+            get_watch_only_and_signing_descriptors(desc);
+        let (mut watch_only, _) = get_funded_wallet_with_change(&watch_ext, &watch_int);
+        assert!(watch_only.is_watch_only());
+
+        let addr = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+            .unwrap()
+            .assume_checked();
+        let mut builder = watch_only.build_tx();
+        builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+        let mut psbt = builder.finish().unwrap();
+
+        let input = &psbt.inputs[0];
+        assert!(!input.bip32_derivation.is_empty() || !input.tap_key_origins.is_empty());
+        if watch_ext.starts_with("pkh") {
+            assert!(input.witness_utxo.is_none());
+            assert!(input.non_witness_utxo.is_some());
+        } else {
+            assert!(input.witness_utxo.is_some());
+        }
+
+        assert_matches!(
+            watch_only.sign(&mut psbt, SignOptions::default()),
+            Err(SignerError::WatchOnly)
+        );
+
+        // the keys are on a separate device, which doesn't know about the wallet's history
+        let signer = Wallet::new(&sign_ext, &sign_int, Network::Regtest).unwrap();
+        assert!(!signer.is_watch_only());
+        let finalized = signer
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    try_finalize: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(!finalized);
+
+        let finalized = watch_only
+            .finalize_psbt(&mut psbt, SignOptions::default())
+            .unwrap();
+        assert!(finalized);
+        assert!(psbt.extract_tx().is_ok());
+    }
+}
+
+#[test]
+fn test_create_single() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let mut wallet = Wallet::create_single(desc, Network::Testnet).unwrap();
+    assert_eq!(wallet.keychains().count(), 1);
+    assert_eq!(
+        wallet.public_descriptor(KeychainKind::Internal),
+        wallet.public_descriptor(KeychainKind::External)
+    );
+
+    // the internal keychain is the external one
+    let addr = wallet.next_unused_address(KeychainKind::Internal);
+    assert_eq!(addr.keychain, KeychainKind::External);
+    assert_eq!(addr.index, 0);
+    assert_eq!(
+        addr.to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+    assert_eq!(wallet.reveal_next_address(KeychainKind::Internal).index, 1);
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(1));
+    assert_eq!(wallet.derivation_index(KeychainKind::Internal), Some(1));
+
+    // change goes back to the external keychain
+    receive_output(
+        &mut wallet,
+        50_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
+    );
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let psbt = builder.finish().unwrap();
+    let change_derivations = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .collect::<Vec<_>>();
+    assert_eq!(change_derivations, [(KeychainKind::External, 1)]);
+
+    // there's no change to trust
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let change = tx
+        .output
+        .iter()
+        .find(|txout| wallet.is_mine(&txout.script_pubkey))
+        .unwrap()
+        .value;
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 1 })
+        .unwrap();
+    let balance = wallet.balance();
+    assert_eq!(balance.trusted_pending, Amount::ZERO);
+    assert_eq!(balance.untrusted_pending, change);
+}
+
+#[test]
+fn test_create_single_persists() -> anyhow::Result<()> {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.sqlite");
+    let mut db = bdk_sqlite::Store::new(Connection::open(&file_path)?)?;
+
+    {
+        let mut wallet = Wallet::create_single(desc, Network::Testnet)?;
+        wallet
+            .reveal_addresses_to(KeychainKind::Internal, 2)
+            .for_each(drop);
+        db.write(&wallet.take_staged().expect("changeset"))?;
+    }
+
+    let changeset = db.read()?.expect("changeset");
+    assert_eq!(changeset.indexed_tx_graph.indexer.keychains_added.len(), 1);
+    let wallet = Wallet::load_from_changeset(changeset.clone())?;
+    assert_eq!(wallet.keychains().count(), 1);
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(2));
+
+    // loading it with a change descriptor doesn't invent one
+    let err = Wallet::new_or_load(desc, get_test_wpkh(), Some(changeset), Network::Testnet)
+        .expect_err("the wallet has no change descriptor");
+    assert_matches!(
+        err,
+        bdk_wallet::wallet::NewOrLoadError::LoadedDescriptorDoesNotMatch {
+            got: None,
+            keychain: KeychainKind::Internal,
+        }
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_create_single_static_address() -> anyhow::Result<()> {
+    let mut wallet = Wallet::create_single(get_test_wpkh(), Network::Regtest)?;
+    assert!(!wallet
+        .public_descriptor(KeychainKind::External)
+        .has_wildcard());
+
+    // the single address is revealed idempotently
+    let addr = wallet.reveal_next_address(KeychainKind::External);
+    assert_eq!(addr.index, 0);
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        assert_eq!(wallet.reveal_next_address(keychain).address, addr.address);
+        assert_eq!(wallet.next_unused_address(keychain).index, 0);
+    }
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+
+    // the full scan requests its only script pubkey
+    let mut request = wallet.start_full_scan();
+    assert_eq!(request.spks_by_keychain.len(), 1);
+    let spks = request
+        .spks_by_keychain
+        .remove(&KeychainKind::External)
+        .expect("external keychain")
+        .collect::<Vec<_>>();
+    assert_eq!(spks, [(0, addr.script_pubkey())]);
+
+    receive_output_in_latest_block(&mut wallet, 20_000);
+    receive_output_in_latest_block(&mut wallet, 30_000);
+    assert_eq!(wallet.balance().total(), Amount::from_sat(50_000));
+    assert_eq!(wallet.list_unspent().count(), 2);
+
+    // there's no address to send the change to
+    let recipient = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(recipient.clone(), Amount::from_sat(25_000));
+    assert_matches!(builder.finish(), Err(CreateTxError::StaticChangeAddress));
+
+    // unless it's sent elsewhere
+    let change_spk = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(recipient.clone(), Amount::from_sat(25_000))
+        .drain_to_change(change_spk.clone());
+    let psbt = builder.finish()?;
+    assert!(psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .any(|txout| txout.script_pubkey == change_spk));
+
+    // or there's none, sweeping the address
+    let mut builder = wallet.build_tx();
+    builder.drain_wallet().drain_to(recipient.clone());
+    let mut psbt = builder.finish()?;
+    assert_eq!(psbt.unsigned_tx.input.len(), 2);
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(psbt.unsigned_tx.output[0].script_pubkey, recipient);
+    assert!(wallet.sign(&mut psbt, SignOptions::default())?);
+
+    // persistence round-trips it
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let mut db = bdk_sqlite::Store::new(Connection::open(temp_dir.path().join("store.sqlite"))?)?;
+    db.write(&wallet.take_staged().expect("changeset"))?;
+    let mut loaded = Wallet::load_from_changeset(db.read()?.expect("changeset"))?;
+    assert_eq!(loaded.keychains().count(), 1);
+    assert_eq!(
+        loaded.reveal_next_address(KeychainKind::Internal).address,
+        addr.address
+    );
+    assert_eq!(loaded.balance(), wallet.balance());
+    assert!(loaded.staged().is_none());
+
+    Ok(())
+}
+
+/// A watch-only wallet of a static address receives twice and sweeps it, the PSBT being signed
+/// by another wallet holding the key.
+#[test]
+fn test_create_single_static_address_regtest() -> anyhow::Result<()> {
+    use bdk_testenv::bitcoincore_rpc::RpcApi;
+    use bdk_testenv::TestEnv;
+
+    let env = TestEnv::new()?;
+    env.mine_blocks(101, None)?;
+    let mut signer = Wallet::create_single(get_test_wpkh(), Network::Regtest)?;
+    let descriptor = signer.public_descriptor_string(KeychainKind::External);
+    let mut watcher = Wallet::create_single(descriptor.as_str(), Network::Regtest)?;
+    let address = watcher.reveal_next_address(KeychainKind::External).address;
+    assert_eq!(
+        signer.reveal_next_address(KeychainKind::External).address,
+        address
+    );
+
+    let txids = [20_000, 30_000]
+        .iter()
+        .map(|sats| env.send(&address, Amount::from_sat(*sats)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let hash = env.mine_blocks(1, None)?[0];
+    let height = env.rpc_client().get_block_count()? as u32;
+    watcher.insert_checkpoint(BlockId { height, hash })?;
+    for txid in &txids {
+        watcher.insert_tx(
+            env.rpc_client().get_raw_transaction(txid, None)?,
+            ConfirmationTime::Confirmed { height, time: 0 },
+        )?;
+    }
+    assert_eq!(watcher.balance().confirmed, Amount::from_sat(50_000));
+
+    // the change of a payment has nowhere to go
+    let recipient = env
+        .rpc_client()
+        .get_new_address(None, None)?
+        .assume_checked();
+    let mut builder = watcher.build_tx();
+    builder.add_recipient(recipient.script_pubkey(), Amount::from_sat(10_000));
+    assert_matches!(builder.finish(), Err(CreateTxError::StaticChangeAddress));
+
+    // the sweep is signed by the wallet with the key, and accepted by the node
+    let mut builder = watcher.build_tx();
+    builder
+        .drain_wallet()
+        .drain_to(recipient.script_pubkey())
+        .fee_rate(FeeRate::from_sat_per_vb(2).unwrap());
+    let mut psbt = builder.finish()?;
+    assert_matches!(
+        watcher.sign(&mut psbt, SignOptions::default()),
+        Err(SignerError::WatchOnly)
+    );
+    assert!(signer.sign(&mut psbt, SignOptions::default())?);
+    let tx = psbt.extract_tx()?;
+    let txid = env.rpc_client().send_raw_transaction(&tx)?;
+    let hash = env.mine_blocks(1, None)?[0];
+    assert!(env
+        .rpc_client()
+        .get_block(&hash)?
+        .txdata
+        .iter()
+        .any(|tx| tx.compute_txid() == txid));
+
+    watcher.insert_checkpoint(BlockId {
+        height: height + 1,
+        hash,
+    })?;
+    watcher.insert_tx(
+        tx,
+        ConfirmationTime::Confirmed {
+            height: height + 1,
+            time: 0,
+        },
+    )?;
+    assert_eq!(watcher.balance().total(), Amount::ZERO);
+    assert_eq!(watcher.transactions().count(), 3);
+    Ok(())
+}
+
+/// In-memory persister, failing the next `fail_next` writes
+#[derive(Default)]
+struct MemoryPersister {
+    changeset: ChangeSet,
+    fail_next: usize,
+}
+
+impl MemoryPersister {
+    fn write(&mut self, changeset: &ChangeSet) -> Result<(), &'static str> {
+        if self.fail_next > 0 {
+            self.fail_next -= 1;
+            return Err("storage unavailable");
+        }
+        self.changeset.append(changeset.clone());
+        Ok(())
+    }
+}
+
+impl WalletPersister for MemoryPersister {
+    type Error = &'static str;
+
+    fn initialize(&mut self) -> Result<Option<ChangeSet>, Self::Error> {
+        Ok(Some(self.changeset.clone()).filter(|changeset| !changeset.is_empty()))
+    }
+
+    fn persist(&mut self, changeset: &ChangeSet) -> Result<(), Self::Error> {
+        self.write(changeset)
+    }
+}
+
+/// Async in-memory persister, yielding to the executor in the middle of every write
+#[derive(Default)]
+struct AsyncMemoryPersister(MemoryPersister);
+
+impl AsyncWalletPersister for AsyncMemoryPersister {
+    type Error = &'static str;
+
+    fn initialize<'a>(&'a mut self) -> FutureResult<'a, Option<ChangeSet>, Self::Error>
+    where
+        Self: 'a,
+    {
+        Box::pin(async move { WalletPersister::initialize(&mut self.0) })
+    }
+
+    fn persist<'a>(&'a mut self, changeset: &'a ChangeSet) -> FutureResult<'a, (), Self::Error>
+    where
+        Self: 'a,
+    {
+        Box::pin(async move {
+            YieldNow(false).await;
+            self.0.write(changeset)
+        })
+    }
+}
+
+/// Future returning `Pending` the first time it's polled
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            Poll::Pending
+        }
+    }
+}
+
+fn noop_context() -> Context<'static> {
+    struct NoopWaker;
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+    let waker = Box::leak(Box::new(Waker::from(Arc::new(NoopWaker))));
+    Context::from_waker(waker)
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut cx = noop_context();
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[test]
+fn test_persist() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut persister = MemoryPersister {
+        fail_next: 1,
+        ..Default::default()
+    };
+    let changeset = WalletPersister::initialize(&mut persister).unwrap();
+    assert!(changeset.is_none());
+    let mut wallet = Wallet::new_or_load(desc, change_desc, changeset, Network::Testnet).unwrap();
+    let staged = wallet
+        .staged()
+        .cloned()
+        .expect("new wallets have staged changes");
+
+    // the changes are kept when the persister fails
+    assert_eq!(wallet.persist(&mut persister), Err("storage unavailable"));
+    assert_eq!(wallet.staged(), Some(&staged));
+
+    assert_eq!(wallet.persist(&mut persister), Ok(true));
+    assert!(wallet.staged().is_none());
+    assert_eq!(wallet.persist(&mut persister), Ok(false));
+    assert_eq!(persister.changeset, staged);
+}
+
+#[test]
+fn test_persist_async() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let mut persister = AsyncMemoryPersister(MemoryPersister {
+        fail_next: 1,
+        ..Default::default()
+    });
+    let changeset = block_on(persister.initialize()).unwrap();
+    let mut wallet =
+        Wallet::new_or_load(desc, get_test_wpkh(), changeset, Network::Testnet).unwrap();
+    wallet
+        .reveal_addresses_to(KeychainKind::External, 2)
+        .for_each(drop);
+    let staged = wallet.staged().cloned().unwrap();
+
+    // the persister fails after yielding
+    assert_eq!(
+        block_on(wallet.persist_async(&mut persister)),
+        Err("storage unavailable")
+    );
+    assert_eq!(wallet.staged(), Some(&staged));
+
+    // dropping the future while it's writing keeps the changes too
+    {
+        let mut future = Box::pin(wallet.persist_async(&mut persister));
+        assert!(future.as_mut().poll(&mut noop_context()).is_pending());
+    }
+    assert_eq!(wallet.staged(), Some(&staged));
+
+    assert_eq!(block_on(wallet.persist_async(&mut persister)), Ok(true));
+    assert!(wallet.staged().is_none());
+    assert_eq!(block_on(wallet.persist_async(&mut persister)), Ok(false));
+
+    let changeset = block_on(persister.initialize()).unwrap();
+    let wallet = Wallet::new_or_load(desc, get_test_wpkh(), changeset, Network::Testnet).unwrap();
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(2));
+}
+
+#[test]
+fn test_persist_async_sync_persister() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut persister = SyncPersister(MemoryPersister {
+        fail_next: 1,
+        ..Default::default()
+    });
+    let changeset = block_on(persister.initialize()).unwrap();
+    let mut wallet = Wallet::new_or_load(desc, change_desc, changeset, Network::Testnet).unwrap();
+    let staged = wallet.staged().cloned().unwrap();
+
+    assert_eq!(
+        block_on(wallet.persist_async(&mut persister)),
+        Err("storage unavailable")
+    );
+    assert_eq!(wallet.staged(), Some(&staged));
+    assert_eq!(block_on(wallet.persist_async(&mut persister)), Ok(true));
+    assert_eq!(persister.0.changeset, staged);
+}
+
+#[test]
+fn test_discard_staged() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, get_test_wpkh());
+    let unconfirmed_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .peek_address(KeychainKind::External, 0)
+                .script_pubkey(),
+            value: Amount::from_sat(5_000),
+        }],
+    };
+    let unconfirmed_txid = unconfirmed_tx.compute_txid();
+    wallet
+        .insert_tx(
+            unconfirmed_tx,
+            ConfirmationTime::Unconfirmed { last_seen: 100 },
+        )
+        .unwrap();
+    let mut persister = MemoryPersister::default();
+    assert_eq!(wallet.persist(&mut persister), Ok(true));
+
+    let balance = wallet.balance();
+    let tip = wallet.latest_checkpoint().block_id();
+    let graph = wallet.tx_graph().initial_changeset();
+
+    // preview a sync finding a new transaction, confirmed in a new block, to a new address
+    let new_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .peek_address(KeychainKind::External, 5)
+                .script_pubkey(),
+            value: Amount::from_sat(10_000),
+        }],
+    };
+    let new_txid = new_tx.compute_txid();
+    let new_block = BlockId {
+        height: 3_000,
+        hash: BlockHash::from_byte_array([1; 32]),
+    };
+    let mut update_graph = TxGraph::default();
+    let _ = update_graph.insert_tx(new_tx);
+    let _ = update_graph.insert_anchor(
+        new_txid,
+        ConfirmationTimeHeightAnchor {
+            anchor_block: new_block,
+            confirmation_height: 3_000,
+            confirmation_time: 300,
+        },
+    );
+    let _ = update_graph.insert_seen_at(unconfirmed_txid, 200);
+    wallet
+        .apply_update(Update {
+            last_active_indices: [(KeychainKind::External, 5)].into(),
+            graph: update_graph,
+            chain: Some(wallet.latest_checkpoint().push(new_block).unwrap()),
+        })
+        .unwrap();
+    assert_eq!(
+        wallet.balance().confirmed,
+        balance.confirmed + Amount::from_sat(10_000)
+    );
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(5));
+
+    // only the newer last seen time of the known transaction can't be reverted
+    let not_reverted = wallet.discard_staged();
+    assert!(wallet.staged().is_none());
+    let mut expected = ChangeSet::default();
+    expected.indexed_tx_graph.graph.last_seen = [(unconfirmed_txid, 200)].into();
+    assert_eq!(not_reverted, expected);
+
+    assert_eq!(wallet.balance(), balance);
+    assert_eq!(wallet.latest_checkpoint().block_id(), tip);
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+    assert!(wallet.get_tx(new_txid).is_none());
+    let mut graph_seen_again = graph.clone();
+    graph_seen_again.last_seen.insert(unconfirmed_txid, 200);
+    assert_eq!(wallet.tx_graph().initial_changeset(), graph_seen_again);
+
+    // the wallet keeps working after discarding
+    assert_eq!(wallet.reveal_next_address(KeychainKind::External).index, 1);
+
+    // reloading from persistence is like never having applied the update
+    let persisted = WalletPersister::initialize(&mut persister)
+        .unwrap()
+        .unwrap();
+    let mut loaded = Wallet::load_from_changeset(persisted).unwrap();
+    assert_eq!(loaded.balance(), balance);
+    assert_eq!(loaded.latest_checkpoint().block_id(), tip);
+    assert_eq!(loaded.derivation_index(KeychainKind::External), Some(0));
+    assert_eq!(loaded.tx_graph().initial_changeset(), graph);
+    assert!(loaded.discard_staged().is_empty());
+}
+
+#[test]
+fn test_discard_staged_new_wallet() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let mut wallet = Wallet::new(desc, get_test_wpkh(), Network::Testnet).unwrap();
+    let created = wallet.staged().cloned().unwrap();
+    let _ = wallet.reveal_next_address(KeychainKind::External);
+
+    // the wallet can't forget its own network, descriptors and genesis block
+    assert_eq!(wallet.discard_staged(), created);
+    assert_eq!(wallet.derivation_index(KeychainKind::External), None);
+    assert_eq!(wallet.network(), Network::Testnet);
+}
+
+#[test]
+fn test_labels() {
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    let address = wallet.peek_address(KeychainKind::External, 0);
+    let tx_ref = LabelRef::Tx(txid);
+    let addr_ref = LabelRef::Addr(address.script_pubkey());
+    wallet.set_label(tx_ref.clone(), "payment".to_string());
+    wallet.set_label(addr_ref.clone(), "alice".to_string());
+    wallet.set_label(tx_ref.clone(), "rent".to_string());
+    assert_eq!(wallet.label(&tx_ref), Some("rent"));
+    assert_eq!(
+        wallet.labels().collect::<Vec<_>>(),
+        [(&tx_ref, "rent"), (&addr_ref, "alice")]
+    );
+
+    // labels are persisted
+    let changeset = wallet.take_staged().unwrap();
+    let mut loaded = Wallet::load_from_changeset(changeset.clone()).unwrap();
+    assert_eq!(
+        loaded.labels().collect::<Vec<_>>(),
+        wallet.labels().collect::<Vec<_>>()
+    );
+
+    assert_eq!(loaded.remove_label(&addr_ref), Some("alice".to_string()));
+    assert_eq!(loaded.remove_label(&addr_ref), None);
+    let mut changeset = changeset;
+    changeset.append(loaded.take_staged().unwrap());
+    let loaded = Wallet::load_from_changeset(changeset).unwrap();
+    assert_eq!(loaded.labels().collect::<Vec<_>>(), [(&tx_ref, "rent")]);
+}
+
+/// A wallet persisted before labels existed, see [`test_load_wallet_from_format_v0_store`], has
+/// no labels and persists the labels set after it is loaded.
+#[test]
+fn test_labels_of_wallet_from_format_v0_store() -> anyhow::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let file_path = temp_dir.path().join("store.db");
+    std::fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/wallet_format_v0.dat"),
+        &file_path,
+    )?;
+
+    let mut db = bdk_file_store::Store::<ChangeSet>::open(DB_MAGIC, &file_path)?;
+    let changeset = db.aggregate_changesets()?.expect("persisted changes");
+    assert!(changeset.labels.is_empty());
+    let mut wallet = Wallet::load_from_changeset(changeset)?;
+    assert_eq!(wallet.labels().count(), 0);
+
+    let txid = wallet
+        .transactions()
+        .find(|tx| !tx.chain_position.is_confirmed())
+        .expect("unconfirmed transaction")
+        .tx_node
+        .txid;
+    let tx_ref = LabelRef::Tx(txid);
+    wallet.set_label(tx_ref.clone(), "pending".to_string());
+    db.append_changeset(&wallet.take_staged().expect("staged changes"))?;
+    drop(db);
+
+    let changeset = bdk_file_store::Store::<ChangeSet>::open(DB_MAGIC, &file_path)?
+        .aggregate_changesets()?
+        .expect("persisted changes");
+    let wallet = Wallet::load_from_changeset(changeset)?;
+    assert_eq!(wallet.labels().collect::<Vec<_>>(), [(&tx_ref, "pending")]);
+    Ok(())
+}
+
+#[test]
+fn test_import_sparrow_labels() {
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    let address = wallet.peek_address(KeychainKind::External, 0).address;
+    let origin = "wpkh([41d6f9a6/84'/1'/0'])";
+    // as exported by Sparrow, with the optional origin and spendable fields
+    let sparrow_export = format!(
+        r#"{{"type":"tx","ref":"{txid}","label":"Coffee","origin":"{origin}"}}
+{{"type":"addr","ref":"{address}","label":"Receive 0"}}
+{{"type":"output","ref":"{txid}:0","label":"Change","spendable":true}}
+{{"type":"input","ref":"{txid}:0","label":"Funding"}}
+{{"type":"xpub","ref":"tpubDDnGNapGEY6AZAdQbfRJgMg9fvz8pUBrLwvyvUqEgcUfgzM6zc2eVK4vY9x9L5n4PToZsD4ZQcDzRQeQRCbqCfMYVbbMMf8KEZFQfEbNt2N","label":"Testnet account"}}
+{{"type":"output","ref":"{txid}:1","label":"Not ours"}}
+
+{{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","label":"Unknown"}}
+{{"type":"addr","ref":"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c","label":"Mainnet"}}
+{{"type":"tx","ref":"{txid}"}}
+"#
+    );
+
+    let stats = wallet.import_labels(sparrow_export.as_bytes()).unwrap();
+    assert_eq!(stats.imported, 5);
+    assert_eq!(
+        stats
+            .skipped
+            .iter()
+            .map(|skipped| (skipped.line, skipped.reason))
+            .collect::<Vec<_>>(),
+        [
+            (5, SkipReason::UnsupportedType),
+            (8, SkipReason::UnknownRef),
+            (9, SkipReason::InvalidRef),
+            (10, SkipReason::MissingLabel),
+        ]
+    );
+    assert_eq!(wallet.label(&LabelRef::Tx(txid)), Some("Coffee"));
+    assert_eq!(
+        wallet.label(&LabelRef::Addr(address.script_pubkey())),
+        Some("Receive 0")
+    );
+    assert_eq!(
+        wallet.label(&LabelRef::Output(OutPoint::new(txid, 0))),
+        Some("Change")
+    );
+    assert_eq!(
+        wallet.label(&LabelRef::Input(OutPoint::new(txid, 0))),
+        Some("Funding")
+    );
+    // the wallet's graph knows the foreign output of its own transaction
+    assert_eq!(
+        wallet.label(&LabelRef::Output(OutPoint::new(txid, 1))),
+        Some("Not ours")
+    );
+
+    // an invalid line fails the import
+    let err = wallet
+        .import_labels(&b"{\"type\":\"tx\",\"label\":\"no ref\"}\n"[..])
+        .unwrap_err();
+    assert_matches!(err, LabelError::Json { line: 1, .. });
+}
+
+#[test]
+fn test_export_import_labels() {
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    let address = wallet.peek_address(KeychainKind::External, 0);
+    wallet.set_label(LabelRef::Tx(txid), "payment \"with\" quotes".to_string());
+    wallet.set_label(LabelRef::Addr(address.script_pubkey()), "alice".to_string());
+    wallet.set_label(
+        LabelRef::Output(OutPoint::new(txid, 0)),
+        "change".to_string(),
+    );
+    wallet.set_label(
+        LabelRef::Input(OutPoint::new(txid, 0)),
+        "funding".to_string(),
+    );
+
+    let mut exported = Vec::new();
+    wallet.export_labels(&mut exported).unwrap();
+    assert_eq!(
+        String::from_utf8(exported.clone()).unwrap().lines().count(),
+        4
+    );
+
+    // importing into a fresh copy of the wallet is lossless
+    let (mut copy, _) = get_funded_wallet_wpkh();
+    let stats = copy.import_labels(exported.as_slice()).unwrap();
+    assert_eq!(stats.imported, 4);
+    assert!(stats.skipped.is_empty());
+    assert_eq!(
+        copy.labels().collect::<Vec<_>>(),
+        wallet.labels().collect::<Vec<_>>()
+    );
+
+    let mut reexported = Vec::new();
+    copy.export_labels(&mut reexported).unwrap();
+    assert_eq!(reexported, exported);
+}
+
+#[cfg(feature = "bip21")]
+#[test]
+fn test_receive_uri() {
+    use bdk_wallet::wallet::bip21::Bip21Uri;
+
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let uri = wallet.receive_uri(Some(Amount::from_sat(150_000)), Some("Alice & Bob".into()));
+    let address = wallet.peek_address(KeychainKind::External, 0).address;
+    assert_eq!(
+        uri.to_string(),
+        format!("bitcoin:{}?amount=0.0015&label=Alice%20%26%20Bob", address)
+    );
+    assert_eq!(uri.to_string().parse::<Bip21Uri>().unwrap(), uri);
+    assert!(wallet.staged().is_some());
+}
+
+#[cfg(feature = "bip21")]
+#[test]
+fn test_pay_to_uri() {
+    use bdk_wallet::wallet::bip21::Bip21Uri;
+    use bdk_wallet::wallet::tx_builder::AddUriRecipientError;
+
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let uri: Bip21Uri =
+        "BITCOIN:BCRT1Q3QTZE4YS45TGDVGUJ66ZRK4FU6HQ3A3V9PFLY5?amount=0.0002&label=Caf%C3%A9"
+            .parse()
+            .unwrap();
+    assert_eq!(uri.label.as_deref(), Some("Café"));
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient_from_uri(&uri).unwrap();
+    let psbt = builder.finish().unwrap();
+    let address = uri.address.clone().assume_checked();
+    assert!(psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .any(|txout| txout.script_pubkey == address.script_pubkey()
+            && txout.value == Amount::from_sat(20_000)));
+
+    // the address must be for the wallet's network
+    let testnet_uri: Bip21Uri = "bitcoin:tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a?amount=0.0002"
+        .parse()
+        .unwrap();
+    let mut builder = wallet.build_tx();
+    assert_matches!(
+        builder.add_recipient_from_uri(&testnet_uri),
+        Err(AddUriRecipientError::WrongNetwork {
+            expected: Network::Regtest,
+            ..
+        })
+    );
+
+    // and there must be an amount to pay
+    let mut uri = uri;
+    uri.amount = None;
+    assert_matches!(
+        builder.add_recipient_from_uri(&uri),
+        Err(AddUriRecipientError::MissingAmount)
+    );
+}
+
+/// Build, sign and extract a transaction paying 25_000 sats from `wallet`
+fn signed_tx(wallet: &mut Wallet) -> Transaction {
+    let addr = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    psbt.extract_tx().expect("failed to extract tx")
+}
+
+#[test]
+fn test_verify_tx() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let tx = signed_tx(&mut wallet);
+
+    let report = wallet.verify_tx(&tx, &VerifyOptions::default()).unwrap();
+    assert_eq!(report.fee, wallet.calculate_fee(&tx).unwrap());
+    assert_eq!(report.fee_rate, wallet.calculate_fee_rate(&tx).unwrap());
+    assert_eq!(report.weight, tx.weight());
+
+    // the fee bounds
+    let options = VerifyOptions {
+        max_fee: Some(report.fee),
+        max_fee_rate: Some(report.fee_rate),
+        min_fee_rate: Some(report.fee_rate),
+        ..Default::default()
+    };
+    assert_eq!(wallet.verify_tx(&tx, &options).unwrap(), report);
+    let options = VerifyOptions {
+        max_fee: Some(report.fee - Amount::from_sat(1)),
+        ..Default::default()
+    };
+    assert_matches!(
+        wallet.verify_tx(&tx, &options),
+        Err(VerifyError::FeeTooHigh { fee, .. }) if fee == report.fee
+    );
+    let options = VerifyOptions {
+        max_fee_rate: Some(FeeRate::BROADCAST_MIN),
+        ..Default::default()
+    };
+    assert_matches!(
+        wallet.verify_tx(&tx, &options),
+        Err(VerifyError::FeeRateTooHigh { .. })
+    );
+    let options = VerifyOptions {
+        min_fee_rate: Some(FeeRate::from_sat_per_vb_u32(10)),
+        ..Default::default()
+    };
+    assert_matches!(
+        wallet.verify_tx(&tx, &options),
+        Err(VerifyError::FeeRateTooLow { .. })
+    );
+
+    // the transaction is still valid once applied to the wallet
+    wallet.apply_unconfirmed_txs([(&tx, 100)]);
+    assert_eq!(
+        wallet.verify_tx(&tx, &VerifyOptions::default()).unwrap(),
+        report
+    );
+}
+
+#[test]
+fn test_verify_tx_op_return() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let data = PushBytesBuf::try_from(b"bdk".to_vec()).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_data(&data);
+    let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    assert!(tx
+        .output
+        .iter()
+        .any(|txout| txout.script_pubkey.is_op_return() && txout.value == Amount::ZERO));
+
+    assert!(wallet.verify_tx(&tx, &VerifyOptions::default()).is_ok());
+}
+
+#[test]
+fn test_verify_broken_tx() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let tx = signed_tx(&mut wallet);
+    let options = VerifyOptions::default();
+
+    let mut dust = tx.clone();
+    dust.output[0].value = Amount::from_sat(100);
+    assert_matches!(
+        wallet.verify_tx(&dust, &options),
+        Err(VerifyError::DustOutput { index: 0, value }) if value == Amount::from_sat(100)
+    );
+
+    let mut overspending = tx.clone();
+    overspending.output[0].value = Amount::from_sat(100_000);
+    assert_matches!(
+        wallet.verify_tx(&overspending, &options),
+        Err(VerifyError::OutputsExceedInputs { .. })
+    );
+
+    let mut duplicate = tx.clone();
+    duplicate.input.push(tx.input[0].clone());
+    assert_matches!(
+        wallet.verify_tx(&duplicate, &options),
+        Err(VerifyError::DuplicateInput(outpoint)) if outpoint == tx.input[0].previous_output
+    );
+
+    let mut unsigned = tx.clone();
+    unsigned.input[0].witness.clear();
+    assert_matches!(
+        wallet.verify_tx(&unsigned, &options),
+        Err(VerifyError::NotFinalized(0))
+    );
+
+    // a foreign input must come with its previous output
+    let foreign_outpoint = OutPoint::new(Txid::all_zeros(), 0);
+    let mut foreign = tx.clone();
+    foreign.input.push(TxIn {
+        previous_output: foreign_outpoint,
+        ..Default::default()
+    });
+    assert_matches!(
+        wallet.verify_tx(&foreign, &options),
+        Err(VerifyError::UnknownInput { index: 1, outpoint }) if outpoint == foreign_outpoint
+    );
+    let options_with_prevout = VerifyOptions {
+        foreign_prevouts: [(
+            foreign_outpoint,
+            TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new(),
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+    assert_matches!(
+        wallet.verify_tx(&foreign, &options_with_prevout),
+        Err(VerifyError::NotFinalized(1))
+    );
+
+    // a double spend of a UTXO already spent by a wallet transaction
+    let mut double_spend = tx.clone();
+    double_spend.output[0].value -= Amount::from_sat(1_000);
+    wallet.apply_unconfirmed_txs([(&tx, 100)]);
+    assert_matches!(
+        wallet.verify_tx(&double_spend, &options),
+        Err(VerifyError::SpentInput { index: 0, spent_by, .. }) if spent_by == tx.compute_txid()
+    );
+}
+
+#[test]
+fn test_verify_tx_timelock() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
+    let tx = signed_tx(&mut wallet);
+    assert_eq!(tx.input[0].sequence, Sequence(6));
+    assert!(wallet.verify_tx(&tx, &VerifyOptions::default()).is_ok());
+
+    let mut early = tx.clone();
+    early.input[0].sequence = Sequence(3);
+    assert_matches!(
+        wallet.verify_tx(&early, &VerifyOptions::default()),
+        Err(VerifyError::UnsatisfiedInput {
+            index: 0,
+            error: miniscript::interpreter::Error::RelativeLockTimeNotMet(_),
+        })
+    );
+}
+
+#[cfg(feature = "verify")]
+#[test]
+fn test_verify_tx_script() {
+    let (mut wallet, _) = get_funded_wallet(get_test_wpkh());
+    let mut tx = signed_tx(&mut wallet);
+    // the signature doesn't commit to the new output value
+    tx.output[0].value -= Amount::from_sat(1_000);
+    assert_matches!(
+        wallet.verify_tx(&tx, &VerifyOptions::default()),
+        Err(VerifyError::Script { index: 0, .. })
+    );
+}
+
+/// A block of `height` on top of `prev_blockhash` with a coinbase and, every 10 blocks, a
+/// transaction paying `amount` to `script_pubkey`. `fork` tells apart blocks of the same height.
+fn test_block(
+    prev_blockhash: BlockHash,
+    height: u32,
+    fork: u8,
+    script_pubkey: ScriptBuf,
+    amount: Amount,
+) -> bitcoin::Block {
+    let coinbase = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::from_bytes([&height.to_le_bytes()[..], &[fork]].concat()),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+    let mut txdata = vec![coinbase];
+    if height % 10 == 0 {
+        txdata.push(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(txdata[0].compute_txid(), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: amount,
+                script_pubkey,
+            }],
+        });
+    }
+    bitcoin::Block {
+        header: bitcoin::block::Header {
+            version: bitcoin::block::Version::ONE,
+            prev_blockhash,
+            merkle_root: bitcoin::TxMerkleNode::all_zeros(),
+            time: height,
+            bits: bitcoin::CompactTarget::from_consensus(0x207fffff),
+            nonce: fork as u32,
+        },
+        txdata,
+    }
+}
+
+/// `count` blocks on top of `base`, paying to the external addresses of `wallet`
+fn test_blocks(wallet: &Wallet, base: BlockId, count: u32, fork: u8) -> Vec<(u32, bitcoin::Block)> {
+    let mut prev_blockhash = base.hash;
+    (base.height + 1..=base.height + count)
+        .map(|height| {
+            let script_pubkey = wallet
+                .peek_address(KeychainKind::External, height % 5)
+                .script_pubkey();
+            let amount = Amount::from_sat(1_000 * height as u64 + fork as u64);
+            let block = test_block(prev_blockhash, height, fork, script_pubkey, amount);
+            prev_blockhash = block.block_hash();
+            (height, block)
+        })
+        .collect()
+}
+
+#[test]
+fn test_apply_blocks() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut per_block = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let mut batched = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let genesis = per_block.local_chain().tip().block_id();
+
+    // 200 blocks, then a reorg of the last 100
+    let blocks = test_blocks(&per_block, genesis, 200, 0);
+    let fork_base = BlockId {
+        height: 100,
+        hash: blocks[99].1.block_hash(),
+    };
+    let fork_blocks = test_blocks(&per_block, fork_base, 100, 1);
+
+    for (height, block) in blocks.iter().chain(&fork_blocks) {
+        per_block.apply_block(block, *height).unwrap();
+    }
+    for batch in blocks.chunks(50).chain(fork_blocks.chunks(50)) {
+        let (first_height, first_block) = &batch[0];
+        let connected_to = BlockId {
+            height: first_height - 1,
+            hash: first_block.header.prev_blockhash,
+        };
+        batched
+            .apply_blocks(batch.iter().map(|(h, b)| (*h, b)), connected_to)
+            .unwrap();
+    }
+
+    assert_eq!(
+        batched.local_chain().tip().block_id(),
+        BlockId {
+            height: 200,
+            hash: fork_blocks[99].1.block_hash(),
+        }
+    );
+    assert_eq!(
+        batched.local_chain().tip().block_id(),
+        per_block.local_chain().tip().block_id()
+    );
+    assert_eq!(batched.balance(), per_block.balance());
+    let txs = |wallet: &Wallet| {
+        wallet
+            .transactions()
+            .map(|tx| (tx.tx_node.txid, tx.chain_position.cloned()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    assert_eq!(txs(&batched), txs(&per_block));
+    // 10 payments below the fork and 10 on the fork are confirmed, the 10 payments of the stale
+    // blocks are not
+    let confirmed = txs(&batched)
+        .values()
+        .filter(|pos| pos.is_confirmed())
+        .count();
+    assert_eq!(confirmed, 20);
+    let expected: u64 = (1..=20).map(|i| 10_000 * i + u64::from(i > 10)).sum();
+    assert_eq!(batched.balance().confirmed, Amount::from_sat(expected));
+    assert_eq!(batched.take_staged(), per_block.take_staged());
+}
+
+#[test]
+fn test_apply_blocks_errors() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let genesis = wallet.local_chain().tip().block_id();
+    let blocks = test_blocks(&wallet, genesis, 20, 0);
+    let staged = wallet.staged().cloned();
+
+    // a gap in the batch
+    let res = wallet.apply_blocks(
+        blocks[..5]
+            .iter()
+            .chain(&blocks[6..10])
+            .map(|(h, b)| (*h, b)),
+        genesis,
+    );
+    let err = res.unwrap_err();
+    assert_matches!(
+        err,
+        ApplyBlocksError::NotConsecutive {
+            height: 7,
+            prev_height: 5,
+            ..
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "block {} at height 7 doesn't build on the previous block {} at height 5",
+            blocks[6].1.block_hash(),
+            blocks[4].1.block_hash()
+        )
+    );
+
+    // the first block doesn't connect to the wallet's chain
+    let connected_to = BlockId {
+        height: 10,
+        hash: blocks[9].1.block_hash(),
+    };
+    let res = wallet.apply_blocks(blocks[10..].iter().map(|(h, b)| (*h, b)), connected_to);
+    assert_matches!(
+        res,
+        Err(ApplyBlocksError::CannotConnect { height: 11, hash, .. }) if hash == blocks[10].1.block_hash()
+    );
+
+    // the wallet is left untouched
+    assert_eq!(wallet.local_chain().tip().block_id(), genesis);
+    assert_eq!(wallet.staged(), staged.as_ref());
+    assert_eq!(wallet.transactions().count(), 0);
+}
+
+#[test]
+fn test_apply_mempool() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let script_pubkey = wallet
+        .next_unused_address(KeychainKind::External)
+        .script_pubkey();
+    let payment = |value: u64| Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: script_pubkey.clone(),
+        }],
+    };
+    let first = payment(10_000);
+    let replacement = payment(9_000);
+
+    assert_eq!(wallet.apply_mempool(vec![(first.clone(), 100)]), vec![]);
+    assert!(wallet.get_tx(first.compute_txid()).is_some());
+
+    let evicted = wallet.apply_mempool(vec![(replacement.clone(), 200)]);
+    assert_eq!(evicted, vec![first.compute_txid()]);
+    assert!(wallet.get_tx(first.compute_txid()).is_none());
+    assert!(wallet.get_tx(replacement.compute_txid()).is_some());
+}
+
+#[test]
+fn test_evicted_tx_leaves_balance() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let balance = wallet.balance();
+    let received = receive_output(
+        &mut wallet,
+        30_000,
+        ConfirmationTime::Unconfirmed { last_seen: 100 },
+    );
+    assert_eq!(
+        wallet.balance().untrusted_pending,
+        balance.untrusted_pending + Amount::from_sat(30_000)
+    );
+
+    // the unconfirmed transactions are part of the sync request
+    let request = wallet.start_sync_with_revealed_spks();
+    assert_eq!(request.txids.collect::<Vec<_>>(), vec![received.txid]);
+
+    // the chain source reports the transaction missing from the mempool
+    let mut update_graph = TxGraph::default();
+    let _ = update_graph.insert_evicted_at(received.txid, 200);
+    wallet
+        .apply_update(Update {
+            graph: update_graph,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(wallet.balance(), balance);
+    assert!(wallet.get_tx(received.txid).is_none());
+    assert_eq!(
+        wallet
+            .staged()
+            .map(|staged| staged.indexed_tx_graph.graph.last_evicted.clone()),
+        Some([(received.txid, 200)].into())
+    );
+}
+
+#[test]
+fn test_list_unspent_detailed() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let tip_height = wallet.latest_checkpoint().height();
+    let received = receive_output(
+        &mut wallet,
+        30_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
+    );
+    wallet.set_label(LabelRef::Output(received), "from Alice".to_string());
+
+    let utxos = wallet.list_unspent_detailed().by_value_desc();
+    assert_eq!(utxos.len(), 2);
+    // the change of the funding payment, confirmed at height 2000
+    let change = &utxos[0];
+    assert_eq!(change.utxo.txout.value, Amount::from_sat(50_000));
+    assert_eq!(change.confirmations, tip_height - 2000 + 1);
+    assert!(change.is_change);
+    assert_eq!(change.label, None);
+    assert_eq!(
+        change.address.as_ref().map(Address::script_pubkey),
+        Some(change.utxo.txout.script_pubkey.clone())
+    );
+    // the mempool output has no confirmations
+    let mempool = &utxos[1];
+    assert_eq!(mempool.utxo.outpoint, received);
+    assert_eq!(mempool.confirmations, 0);
+    assert!(!mempool.is_change);
+    assert_eq!(mempool.label.as_deref(), Some("from Alice"));
+
+    // the label of the address is used if the output has none
+    let address = change.address.clone().unwrap();
+    wallet.set_label(
+        LabelRef::Addr(address.script_pubkey()),
+        "change".to_string(),
+    );
+    let utxos = wallet.list_unspent_detailed().min_confirmations(1);
+    assert_eq!(utxos.len(), 1);
+    assert_eq!(utxos[0].label.as_deref(), Some("change"));
+    assert!(wallet
+        .list_unspent_detailed()
+        .keychain(KeychainKind::Internal)
+        .is_empty());
+}
+
+#[test]
+fn test_deduplicate_recipients() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let payee = ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([1; 20]));
+    let window = TimeOrHeightWindow::Blocks(6);
+    let build_payout = |wallet: &mut Wallet, deduplicate: bool| {
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(payee.clone(), Amount::from_sat(10_000))
+            .enable_rbf();
+        if deduplicate {
+            builder.deduplicate_recipients(window);
+        }
+        builder.finish()
+    };
+
+    // the payout is recorded before its broadcast, then the service crashes
+    let tx = build_payout(&mut wallet, true).unwrap().unsigned_tx;
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_eq!(
+        wallet
+            .unbroadcast_transactions()
+            .map(|tx| tx.compute_txid())
+            .collect::<Vec<_>>(),
+        [txid]
+    );
+    assert_eq!(
+        wallet.find_payments_to(&payee, Some(Amount::from_sat(10_000)), window),
+        [txid]
+    );
+    assert_eq!(wallet.find_payments_to(&payee, None, window), [txid]);
+    assert!(wallet
+        .find_payments_to(&payee, Some(Amount::from_sat(9_000)), window)
+        .is_empty());
+
+    // rebuilding the payout after the crash is blocked
+    assert_matches!(
+        build_payout(&mut wallet, true),
+        Err(CreateTxError::PossibleDuplicatePayment { txids }) if txids == [txid]
+    );
+    // unless the payment is meant to be repeated
+    build_payout(&mut wallet, false).unwrap();
+    // a fee bump is not a duplicate of the transaction it replaces
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_rate(FeeRate::from_sat_per_vb_u32(5))
+        .deduplicate_recipients(window);
+    builder.finish().unwrap();
+
+    // the confirmed payments are only found within the window
+    wallet
+        .insert_tx(
+            tx,
+            ConfirmationTime::Confirmed {
+                height: 2_000,
+                time: 200,
+            },
+        )
+        .unwrap();
+    assert_eq!(wallet.unbroadcast_transactions().count(), 0);
+    assert_eq!(wallet.find_payments_to(&payee, None, window), [txid]);
+    assert_eq!(
+        wallet.find_payments_to(&payee, None, TimeOrHeightWindow::Since(200)),
+        [txid]
+    );
+    assert!(wallet
+        .find_payments_to(&payee, None, TimeOrHeightWindow::Since(201))
+        .is_empty());
+    wallet
+        .insert_checkpoint(BlockId {
+            height: 2_006,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    assert!(wallet.find_payments_to(&payee, None, window).is_empty());
+    build_payout(&mut wallet, true).unwrap();
+}
+
+#[test]
+fn test_unbroadcast_payment_replaced_by_confirmed_tx() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let payee = ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([1; 20]));
+    let window = TimeOrHeightWindow::Blocks(6);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(payee.clone(), Amount::from_sat(10_000));
+    let tx = builder.finish().unwrap().unsigned_tx;
+    let txid = tx.compute_txid();
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // a confirmed transaction spending the same inputs means the payment never happens
+    let mut conflict = tx;
+    let payment = conflict
+        .output
+        .iter_mut()
+        .find(|txout| txout.script_pubkey == payee)
+        .unwrap();
+    payment.value = Amount::from_sat(9_000);
+    let conflict_txid = conflict.compute_txid();
+    wallet
+        .insert_tx(
+            conflict,
+            ConfirmationTime::Confirmed {
+                height: 2_000,
+                time: 200,
+            },
+        )
+        .unwrap();
+    assert_eq!(wallet.unbroadcast_transactions().count(), 1);
+    assert!(wallet
+        .find_payments_to(&payee, Some(Amount::from_sat(10_000)), window)
+        .is_empty());
+    assert_eq!(
+        wallet.find_payments_to(&payee, None, window),
+        [conflict_txid]
+    );
+    assert_ne!(conflict_txid, txid);
+}
+
+#[test]
+fn test_health_report() {
+    let (descriptor, _) = get_test_tr_single_sig_xprv_with_change_desc();
+    // external index 0 received the funding output and the output it spent
+    let (mut wallet, _) = get_funded_wallet_with_change(descriptor, get_test_wpkh());
+    let change_address = wallet.next_unused_address(KeychainKind::Internal);
+    let dust_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: change_address.script_pubkey(),
+            value: Amount::from_sat(150),
+        }],
+    };
+    wallet
+        .insert_tx(
+            dust_tx,
+            ConfirmationTime::Confirmed {
+                height: 2_000,
+                time: 200,
+            },
+        )
+        .unwrap();
+    receive_output(
+        &mut wallet,
+        5_000,
+        ConfirmationTime::Unconfirmed { last_seen: 300 },
+    );
+    let oldest = receive_output(
+        &mut wallet,
+        20_000,
+        ConfirmationTime::Unconfirmed { last_seen: 100 },
+    );
+    let _ = wallet.reveal_addresses_to(KeychainKind::External, 5);
+
+    let report = wallet.health_report(FeeRate::from_sat_per_vb_u32(10));
+    assert_eq!(report.feerate, FeeRate::from_sat_per_vb_u32(10));
+    assert_eq!(
+        report.script_types,
+        [
+            (
+                ScriptType::P2wpkh,
+                UtxoStats {
+                    count: 1,
+                    value: Amount::from_sat(150),
+                },
+            ),
+            (
+                ScriptType::P2tr,
+                UtxoStats {
+                    count: 3,
+                    value: Amount::from_sat(75_000),
+                },
+            ),
+        ]
+        .into()
+    );
+    assert_eq!(
+        report
+            .size_buckets
+            .iter()
+            .map(|bucket| (bucket.min_value.to_sat(), bucket.stats.count))
+            .collect::<Vec<_>>(),
+        [
+            (0, 1),
+            (1_000, 1),
+            (10_000, 2),
+            (100_000, 0),
+            (1_000_000, 0),
+            (10_000_000, 0)
+        ]
+    );
+    assert_eq!(report.size_buckets[2].stats.value, Amount::from_sat(70_000));
+    assert_eq!(
+        report.size_buckets[0].max_value,
+        Some(Amount::from_sat(1_000))
+    );
+    assert_eq!(report.size_buckets[5].max_value, None);
+    // spending the dust output costs more than its value at 10 sat/vb
+    assert_eq!(
+        report.uneconomical,
+        UtxoStats {
+            count: 1,
+            value: Amount::from_sat(150),
+        }
+    );
+    assert_eq!(
+        report.reused_scripts,
+        [ReusedScript {
+            keychain: KeychainKind::External,
+            index: 0,
+            outputs: 2,
+        }]
+    );
+    assert_eq!(
+        report.gaps[&KeychainKind::External],
+        GapStatus {
+            last_revealed_index: Some(5),
+            last_used_index: Some(2),
+            unused_revealed: 3,
+            gap_limit: 25,
+        }
+    );
+    assert_eq!(report.gaps[&KeychainKind::Internal].unused_revealed, 0);
+    assert_eq!(
+        report.oldest_unconfirmed,
+        Some(UnconfirmedTx {
+            txid: oldest.txid,
+            last_seen: 100,
+        })
+    );
+
+    // the uneconomical outputs agree with coin selection
+    let report = wallet.health_report(FeeRate::from_sat_per_vb_u32(1));
+    assert_eq!(report.uneconomical, UtxoStats::default());
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["script_types"]["p2tr"]["count"], 3);
+    assert_eq!(
+        serde_json::from_value::<HealthReport>(json).unwrap(),
+        report
+    );
+}
+
+#[test]
+fn test_list_unspent_detailed_reorg() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let genesis = wallet.local_chain().tip().block_id();
+    let blocks = test_blocks(&wallet, genesis, 30, 0);
+    for (height, block) in &blocks {
+        wallet.apply_block(block, *height).unwrap();
+    }
+    let confirmations = |wallet: &Wallet| {
+        wallet
+            .list_unspent_detailed()
+            .into_iter()
+            .map(|utxo| (utxo.utxo.txout.value.to_sat(), utxo.confirmations))
+            .collect::<BTreeMap<_, _>>()
+    };
+    // payments are made in blocks 10, 20 and 30
+    assert_eq!(
+        confirmations(&wallet),
+        [(10_000, 21), (20_000, 11), (30_000, 1)].into()
+    );
+
+    // replace the blocks after 15 with a longer chain
+    let fork_base = BlockId {
+        height: 15,
+        hash: blocks[14].1.block_hash(),
+    };
+    for (height, block) in test_blocks(&wallet, fork_base, 20, 1) {
+        wallet.apply_block(&block, height).unwrap();
+    }
+    // the payments of the stale blocks are back to the mempool
+    assert_eq!(
+        confirmations(&wallet),
+        [
+            (10_000, 26),
+            (20_000, 0),
+            (20_001, 16),
+            (30_000, 0),
+            (30_001, 6)
+        ]
+        .into()
+    );
+    let utxos = wallet.list_unspent_detailed();
+    assert!(utxos.iter().all(|utxo| !utxo.is_change));
+}
+
+#[test]
+fn test_birthday() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    assert_eq!(wallet.birthday(), None);
+    wallet.set_birthday(BlockTimeOrHeight::Height(100));
+
+    // the birthday is persisted
+    let changeset = wallet.take_staged().unwrap();
+    assert_eq!(changeset.birthday, Some(BlockTimeOrHeight::Height(100)));
+    let mut wallet = Wallet::load_from_changeset(changeset).unwrap();
+    assert_eq!(wallet.birthday(), Some(BlockTimeOrHeight::Height(100)));
+    let report = wallet.health_report(FeeRate::from_sat_per_vb_u32(1));
+    assert_eq!(report.birthday, Some(BlockTimeOrHeight::Height(100)));
+
+    // the birthday is only a hint: rescanning from genesis still finds the payments of the
+    // blocks 10, 20 and 30, before it
+    let genesis = wallet.local_chain().tip().block_id();
+    for (height, block) in test_blocks(&wallet, genesis, 30, 0) {
+        wallet.apply_block(&block, height).unwrap();
+    }
+    assert_eq!(wallet.balance().confirmed, Amount::from_sat(60_000));
+}
+
+#[test]
+fn test_apply_block_with_events_reorg() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let genesis = wallet.local_chain().tip().block_id();
+    let blocks = test_blocks(&wallet, genesis, 20, 0);
+    let block_id = |height: u32, block: &bitcoin::Block| BlockId {
+        height,
+        hash: block.block_hash(),
+    };
+    let payment = |block: &bitcoin::Block| block.txdata[1].compute_txid();
+
+    let mut balance = Balance::default();
+    for (height, block) in &blocks {
+        let events = wallet.apply_block_with_events(block, *height).unwrap();
+        if height % 10 != 0 {
+            assert_eq!(events, vec![]);
+            continue;
+        }
+        let mut expected = vec![];
+        if *height == 10 {
+            expected.push(WalletEvent::AddressRevealed {
+                keychain: KeychainKind::External,
+                index: 0,
+                address: wallet.peek_address(KeychainKind::External, 0).address,
+            });
+        }
+        let new_balance = wallet.balance();
+        assert_eq!(
+            new_balance.confirmed,
+            balance.confirmed + Amount::from_sat(1_000 * *height as u64)
+        );
+        expected.push(WalletEvent::TxConfirmed {
+            txid: payment(block),
+            block_id: block_id(*height, block),
+        });
+        expected.push(WalletEvent::BalanceChanged {
+            old: balance,
+            new: new_balance.clone(),
+        });
+        assert_eq!(events, expected);
+        balance = new_balance;
+    }
+
+    // replace the blocks after 15, the payment of block 20 is back to the mempool
+    let fork_base = block_id(15, &blocks[14].1);
+    let fork_blocks = test_blocks(&wallet, fork_base, 10, 1);
+    let (height, block) = &fork_blocks[0];
+    let events = wallet.apply_block_with_events(block, *height).unwrap();
+    let unconfirmed = wallet.balance();
+    assert_eq!(unconfirmed.confirmed, Amount::from_sat(10_000));
+    assert_eq!(unconfirmed.untrusted_pending, Amount::from_sat(20_000));
+    assert_eq!(
+        events,
+        vec![
+            WalletEvent::TxUnconfirmed {
+                txid: payment(&blocks[19].1),
+                block_id: block_id(20, &blocks[19].1),
+            },
+            WalletEvent::BalanceChanged {
+                old: balance,
+                new: unconfirmed.clone(),
+            },
+        ]
+    );
+
+    // the payment of the new block 20 is confirmed
+    for (height, block) in &fork_blocks[1..4] {
+        assert_eq!(
+            wallet.apply_block_with_events(block, *height).unwrap(),
+            vec![]
+        );
+    }
+    let (height, block) = &fork_blocks[4];
+    let events = wallet.apply_block_with_events(block, *height).unwrap();
+    assert_eq!(
+        events,
+        vec![
+            WalletEvent::TxConfirmed {
+                txid: payment(block),
+                block_id: block_id(20, block),
+            },
+            WalletEvent::BalanceChanged {
+                old: unconfirmed,
+                new: wallet.balance(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_apply_update_with_events() {
+    let (mut wallet, funding_txid) = get_funded_wallet(get_test_tr_single_sig_xprv());
+    let funding = OutPoint::new(funding_txid, 0);
+    let script_pubkey = wallet
+        .peek_address(KeychainKind::External, 5)
+        .script_pubkey();
+    let payment = |previous_output: OutPoint, value: u64| Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: script_pubkey.clone(),
+        }],
+    };
+    // an incoming payment, and one that is replaced
+    let first = payment(OutPoint::new(Txid::from_byte_array([1; 32]), 0), 10_000);
+    let second = payment(OutPoint::new(Txid::from_byte_array([2; 32]), 0), 20_000);
+    let replacement = payment(OutPoint::new(Txid::from_byte_array([2; 32]), 0), 19_000);
+
+    let mut graph = TxGraph::default();
+    let _ = graph.insert_tx(first.clone());
+    let _ = graph.insert_tx(second.clone());
+    let _ = graph.insert_seen_at(first.compute_txid(), 100);
+    let _ = graph.insert_seen_at(second.compute_txid(), 100);
+    let balance = wallet.balance();
+    let events = wallet
+        .apply_update_with_events(Update {
+            last_active_indices: [(KeychainKind::External, 5)].into(),
+            graph,
+            ..Default::default()
+        })
+        .unwrap();
+    let mut txids = [first.compute_txid(), second.compute_txid()];
+    txids.sort();
+    let mut expected = (1..=5)
+        .map(|index| WalletEvent::AddressRevealed {
+            keychain: KeychainKind::External,
+            index,
+            address: wallet.peek_address(KeychainKind::External, index).address,
+        })
+        .collect::<Vec<_>>();
+    expected.extend(txids.iter().map(|&txid| WalletEvent::TxSeen { txid }));
+    expected.push(WalletEvent::BalanceChanged {
+        old: balance,
+        new: wallet.balance(),
+    });
+    assert_eq!(events, expected);
+
+    // the second payment is replaced, the first leaves the mempool, and the transaction funding
+    // the wallet doesn't change
+    let balance = wallet.balance();
+    let mut graph = TxGraph::default();
+    let _ = graph.insert_tx(replacement.clone());
+    let _ = graph.insert_seen_at(replacement.compute_txid(), 200);
+    let _ = graph.insert_evicted_at(first.compute_txid(), 200);
+    let events = wallet
+        .apply_update_with_events(Update {
+            graph,
+            ..Default::default()
+        })
+        .unwrap();
+    let mut expected = vec![
+        WalletEvent::TxEvicted {
+            txid: first.compute_txid(),
+        },
+        WalletEvent::TxReplaced {
+            txid: second.compute_txid(),
+            replaced_by: vec![replacement.compute_txid()],
+        },
+        WalletEvent::TxSeen {
+            txid: replacement.compute_txid(),
+        },
+    ];
+    expected.sort_by_key(|event| match event {
+        WalletEvent::TxEvicted { txid }
+        | WalletEvent::TxReplaced { txid, .. }
+        | WalletEvent::TxSeen { txid } => *txid,
+        _ => unreachable!(),
+    });
+    expected.push(WalletEvent::BalanceChanged {
+        old: balance,
+        new: wallet.balance(),
+    });
+    assert_eq!(events, expected);
+    assert!(wallet.get_tx(funding.txid).is_some());
+
+    // nothing changes
+    assert_eq!(
+        wallet.apply_update_with_events(Update::default()).unwrap(),
+        vec![]
+    );
+}
+
+#[test]
+fn test_subscribe() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+    let all = wallet.subscribe();
+    let bounded = wallet.subscribe_with_capacity(2);
+    assert_eq!(
+        all.capacity(),
+        bdk_wallet::wallet::DEFAULT_SUBSCRIPTION_CAPACITY
+    );
+    let script_pubkey = wallet
+        .peek_address(KeychainKind::External, 2)
+        .script_pubkey();
+    let payment = |n: u8, value: u64| Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([n; 32]), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: script_pubkey.clone(),
+        }],
+    };
+
+    // a sync update with an incoming payment
+    let first = payment(1, 10_000);
+    let mut graph = TxGraph::default();
+    let _ = graph.insert_tx(first.clone());
+    let _ = graph.insert_seen_at(first.compute_txid(), 100);
+    let balance = wallet.balance();
+    wallet
+        .apply_update(Update {
+            last_active_indices: [(KeychainKind::External, 2)].into(),
+            graph,
+            ..Default::default()
+        })
+        .unwrap();
+    let mut expected = (1..=2)
+        .map(|index| WalletEvent::AddressRevealed {
+            keychain: KeychainKind::External,
+            index,
+            address: wallet.peek_address(KeychainKind::External, index).address,
+        })
+        .collect::<Vec<_>>();
+    expected.push(WalletEvent::TxSeen {
+        txid: first.compute_txid(),
+    });
+    expected.push(WalletEvent::BalanceChanged {
+        old: balance,
+        new: wallet.balance(),
+    });
+
+    // then a mempool transaction, whose events are also returned to the caller
+    let second = payment(2, 20_000);
+    let balance = wallet.balance();
+    wallet.apply_unconfirmed_txs([(&second, 200)]);
+    let update_events = wallet.apply_update_with_events(Update::default()).unwrap();
+    assert!(update_events.is_empty());
+    expected.push(WalletEvent::TxSeen {
+        txid: second.compute_txid(),
+    });
+    expected.push(WalletEvent::BalanceChanged {
+        old: balance,
+        new: wallet.balance(),
+    });
+
+    // the subscriptions see the same events in the order they were applied, the bounded one
+    // only the last ones
+    assert_eq!(all.poll_events(), expected);
+    assert_eq!(all.dropped_events(), 0);
+    assert!(all.poll_events().is_empty());
+    let polled = std::thread::spawn(move || (bounded.poll_events(), bounded))
+        .join()
+        .unwrap();
+    assert_eq!(polled.0, expected[expected.len() - 2..]);
+    assert_eq!(polled.1.dropped_events(), expected.len() as u64 - 2);
+
+    // a dropped subscription is forgotten, the others still see the events
+    drop(polled);
+    let (block, height) = (
+        bitcoin::Block {
+            header: bitcoin::block::Header {
+                version: bitcoin::block::Version::ONE,
+                prev_blockhash: wallet.latest_checkpoint().hash(),
+                merkle_root: bitcoin::TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: bitcoin::CompactTarget::from_consensus(0),
+                nonce: 0,
+            },
+            txdata: vec![second.clone()],
+        },
+        wallet.latest_checkpoint().height() + 1,
+    );
+    let balance = wallet.balance();
+    wallet.apply_block(&block, height).unwrap();
+    assert_eq!(
+        all.poll_events(),
+        vec![
+            WalletEvent::TxConfirmed {
+                txid: second.compute_txid(),
+                block_id: BlockId {
+                    height,
+                    hash: block.block_hash(),
+                },
+            },
+            WalletEvent::BalanceChanged {
+                old: balance,
+                new: wallet.balance(),
+            },
+        ]
+    );
+    assert!(!all.is_closed());
+    drop(wallet);
+    assert!(all.is_closed());
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_subscription_stream() {
+    use futures::StreamExt;
+
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+    let mut subscription = wallet.subscribe();
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: wallet
+                .peek_address(KeychainKind::External, 0)
+                .script_pubkey(),
+        }],
+    };
+    let waiting = std::thread::spawn(move || {
+        futures::executor::block_on(async {
+            let mut events = vec![];
+            while let Some(event) = subscription.next().await {
+                events.push(event);
+            }
+            events
+        })
+    });
+    wallet.apply_unconfirmed_txs([(&tx, 100)]);
+    drop(wallet);
+    let events = waiting.join().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        events[0],
+        WalletEvent::TxSeen {
+            txid: tx.compute_txid()
+        }
+    );
+    assert!(matches!(events[1], WalletEvent::BalanceChanged { .. }));
+}
+
+/// Apply a random operation to `wallet`: reveal or mark addresses, receive or spend outputs,
+/// extend or reorg the chain, set or remove labels.
+fn random_wallet_operation(wallet: &mut Wallet, rng: &mut StdRng, fork: &mut u8) {
+    match rng.gen_range(0..8) {
+        0 => {
+            let keychain = if rng.gen() {
+                KeychainKind::External
+            } else {
+                KeychainKind::Internal
+            };
+            let _ = wallet.reveal_next_address(keychain);
+        }
+        1 => {
+            let index = rng.gen_range(0..5);
+            if rng.gen() {
+                let _ = wallet.mark_used(KeychainKind::External, index);
+            } else {
+                let _ = wallet.unmark_used(KeychainKind::External, index);
+            }
+        }
+        2 => {
+            let last_seen = rng.gen_range(0..1_000);
+            let _ = receive_output(
+                wallet,
+                rng.gen_range(1_000..50_000),
+                ConfirmationTime::Unconfirmed { last_seen },
+            );
+        }
+        3 => {
+            let recipient = wallet
+                .peek_address(KeychainKind::External, 100)
+                .script_pubkey();
+            let mut builder = wallet.build_tx();
+            builder
+                .add_recipient(recipient, Amount::from_sat(rng.gen_range(1_000..20_000)))
+                .fee_rate(FeeRate::from_sat_per_vb_u32(2));
+            if let Ok(psbt) = builder.finish() {
+                let last_seen = rng.gen_range(0..1_000);
+                wallet
+                    .insert_tx(
+                        psbt.unsigned_tx,
+                        ConfirmationTime::Unconfirmed { last_seen },
+                    )
+                    .unwrap();
+            }
+        }
+        4 => {
+            let tip = wallet.latest_checkpoint().block_id();
+            for (height, block) in test_blocks(wallet, tip, rng.gen_range(1..15), 0) {
+                wallet.apply_block(&block, height).unwrap();
+            }
+        }
+        5 => {
+            // replace up to the 2 last blocks
+            let base = wallet
+                .latest_checkpoint()
+                .iter()
+                .take(rng.gen_range(1..=3))
+                .last()
+                .unwrap()
+                .block_id();
+            *fork += 1;
+            for (height, block) in test_blocks(wallet, base, rng.gen_range(1..5), *fork) {
+                wallet.apply_block(&block, height).unwrap();
+            }
+        }
+        6 => {
+            let txids = wallet
+                .transactions()
+                .map(|tx| tx.tx_node.txid)
+                .collect::<Vec<_>>();
+            if !txids.is_empty() {
+                let txid = txids[rng.gen_range(0..txids.len())];
+                wallet.set_label(LabelRef::Tx(txid), format!("label {}", rng.gen::<u8>()));
+            }
+        }
+        _ => {
+            let label_refs = wallet
+                .labels()
+                .map(|(label_ref, _)| label_ref.clone())
+                .collect::<Vec<_>>();
+            if !label_refs.is_empty() {
+                let label_ref = &label_refs[rng.gen_range(0..label_refs.len())];
+                let _ = wallet.remove_label(label_ref);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_snapshot_matches_history() -> anyhow::Result<()> {
+    fn run<Db, New, Read, Write, Compact>(
+        filename: &str,
+        new: New,
+        read: Read,
+        write: Write,
+        compact_to: Compact,
+    ) -> anyhow::Result<()>
+    where
+        New: Fn(&Path) -> anyhow::Result<Db>,
+        Read: Fn(&mut Db) -> anyhow::Result<Option<ChangeSet>>,
+        Write: Fn(&mut Db, &ChangeSet) -> anyhow::Result<()>,
+        Compact: Fn(&mut Db, &ChangeSet) -> anyhow::Result<()>,
+    {
+        for seed in 0..8 {
+            let temp_dir = tempfile::tempdir().expect("must create tempdir");
+            let file_path = temp_dir.path().join(filename);
+            let mut db = new(&file_path)?;
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut fork = 0;
+
+            let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+            let mut wallet = Wallet::new(desc, change_desc, Network::Regtest)?;
+            for _ in 0..40 {
+                random_wallet_operation(&mut wallet, &mut rng, &mut fork);
+                if rng.gen_bool(0.5) {
+                    if let Some(changeset) = wallet.take_staged() {
+                        write(&mut db, &changeset)?;
+                    }
+                }
+            }
+            if let Some(changeset) = wallet.take_staged() {
+                write(&mut db, &changeset)?;
+            }
+            let snapshot = wallet.snapshot();
+
+            let history = read(&mut db)?.expect("must have history");
+            let from_history = Wallet::load_from_changeset(history)?;
+            compact_to(&mut db, &snapshot)?;
+            let compacted = read(&mut db)?.expect("must have snapshot");
+            let mut from_snapshot = Wallet::load_from_changeset(compacted)?;
+
+            for loaded in [&from_history, &from_snapshot] {
+                assert_eq!(loaded.snapshot(), snapshot, "seed {}", seed);
+                assert_eq!(loaded.balance(), wallet.balance());
+                assert_eq!(
+                    loaded.list_unspent().collect::<Vec<_>>(),
+                    wallet.list_unspent().collect::<Vec<_>>()
+                );
+                assert_eq!(
+                    loaded.spk_index().last_revealed_indices(),
+                    wallet.spk_index().last_revealed_indices()
+                );
+                assert_eq!(
+                    loaded
+                        .list_unused_addresses(KeychainKind::External)
+                        .collect::<Vec<_>>(),
+                    wallet
+                        .list_unused_addresses(KeychainKind::External)
+                        .collect::<Vec<_>>()
+                );
+            }
+
+            // the store keeps working after the compaction
+            let _ = from_snapshot.reveal_next_address(KeychainKind::Internal);
+            let changeset = from_snapshot.take_staged().expect("revealed an address");
+            write(&mut db, &changeset)?;
+            let reloaded = Wallet::load_from_changeset(read(&mut db)?.expect("must have data"))?;
+            assert_eq!(reloaded.snapshot(), from_snapshot.snapshot());
+        }
+        Ok(())
+    }
+
+    run(
+        "store.db",
+        |path| Ok(bdk_file_store::Store::create_new(DB_MAGIC, path)?),
+        |db| Ok(bdk_file_store::Store::aggregate_changesets(db)?),
+        |db, changeset| Ok(bdk_file_store::Store::append_changeset(db, changeset)?),
+        |db, snapshot| Ok(bdk_file_store::Store::compact_to(db, snapshot)?),
+    )?;
+    run(
+        "store.sqlite",
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+        |db| Ok(bdk_sqlite::Store::read(db)?),
+        |db, changeset| Ok(bdk_sqlite::Store::write(db, changeset)?),
+        |db, snapshot| Ok(bdk_sqlite::Store::compact_to(db, snapshot)?),
+    )?;
+
+    Ok(())
+}
+
+#[test]
+fn test_opportunistic_consolidation() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let tiny = [1_000, 2_000, 3_000, 4_000]
+        .iter()
+        .map(|&value| receive_output_in_latest_block(&mut wallet, value))
+        .collect::<Vec<_>>();
+    let addr = Address::from_str("bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm")
+        .unwrap()
+        .assume_checked();
+    let fee_rate = FeeRate::from_sat_per_vb_u32(5);
+
+    // without it the largest UTXO is enough
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
+        .fee_rate(fee_rate);
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 1);
+    let change_without = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .find(|txout| wallet.is_mine(&txout.script_pubkey))
+        .unwrap()
+        .value;
+
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
+        .fee_rate(fee_rate)
+        .opportunistic_consolidation(2, Weight::from_wu(10_000));
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 3);
+    assert_eq!(
+        psbt.unsigned_tx
+            .input
+            .iter()
+            .filter(|txin| tiny.contains(&txin.previous_output))
+            .count(),
+        2
     );
+    let change_with = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .find(|txout| wallet.is_mine(&txout.script_pubkey))
+        .unwrap()
+        .value;
+    assert!(change_with > change_without);
+    let fee = psbt.fee().unwrap();
+    assert_fee_rate!(psbt, fee, fee_rate, @add_signature);
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(
-        *extracted.input[0].witness.to_vec()[0].last().unwrap(),
-        sighash.to_u32() as u8,
-        "The signature should have been made with the right sighash"
-    );
+    // the weight cap leaves room for a single P2WPKH input
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
+        .fee_rate(fee_rate)
+        .opportunistic_consolidation(4, Weight::from_wu(300));
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 2);
+    let fee = psbt.fee().unwrap();
+    assert_fee_rate!(psbt, fee, fee_rate, @add_signature);
 }
 
 #[test]
-fn test_unused_address() {
-    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
-    let change_desc = get_test_wpkh();
-    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).expect("wallet");
-
-    // `list_unused_addresses` should be empty if we haven't revealed any
-    assert!(wallet
-        .list_unused_addresses(KeychainKind::External)
-        .next()
-        .is_none());
+fn test_opportunistic_consolidation_high_feerate() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    for value in [1_000, 2_000, 3_000, 4_000] {
+        receive_output_in_latest_block(&mut wallet, value);
+    }
+    let addr = Address::from_str("bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm")
+        .unwrap()
+        .assume_checked();
 
+    // spending any of the small UTXOs costs more than its value
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
+        .fee_rate(FeeRate::from_sat_per_vb_u32(100))
+        .opportunistic_consolidation(4, Weight::from_wu(10_000));
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 1);
     assert_eq!(
-        wallet
-            .next_unused_address(KeychainKind::External)
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-    );
-    assert_eq!(
-        wallet
-            .list_unused_addresses(KeychainKind::External)
-            .next()
-            .unwrap()
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+        psbt.inputs[0].witness_utxo.as_ref().unwrap().value,
+        Amount::from_sat(50_000)
     );
 }
 
 #[test]
-fn test_next_unused_address() {
-    let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
-    let change = get_test_wpkh();
-    let mut wallet = Wallet::new(descriptor, change, Network::Testnet).expect("wallet");
-    assert_eq!(wallet.derivation_index(KeychainKind::External), None);
-
-    assert_eq!(
-        wallet
-            .next_unused_address(KeychainKind::External)
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-    );
-    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
-    // calling next_unused again gives same address
-    assert_eq!(
-        wallet
-            .next_unused_address(KeychainKind::External)
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+fn test_broadcast_journal() -> anyhow::Result<()> {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet)?;
+    let txid = Txid::from_byte_array([1; 32]);
+    let other_txid = Txid::from_byte_array([2; 32]);
+    wallet.record_broadcast(
+        txid,
+        "esplora",
+        BroadcastOutcome::Rejected("missing-inputs".to_string()),
+        1_000,
     );
-    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
-
-    // test mark used / unused
-    assert!(wallet.mark_used(KeychainKind::External, 0));
-    let next_unused_addr = wallet.next_unused_address(KeychainKind::External);
-    assert_eq!(next_unused_addr.index, 1);
-
-    assert!(wallet.unmark_used(KeychainKind::External, 0));
-    let next_unused_addr = wallet.next_unused_address(KeychainKind::External);
-    assert_eq!(next_unused_addr.index, 0);
-
-    // use the above address
-    receive_output_in_latest_block(&mut wallet, 25_000);
+    wallet.record_broadcast(other_txid, "esplora", BroadcastOutcome::Accepted, 1_500);
+    wallet.record_broadcast(txid, "electrum", BroadcastOutcome::Accepted, 2_000);
 
+    let history = |wallet: &Wallet| {
+        wallet
+            .broadcast_history(txid)
+            .map(|record| {
+                (
+                    record.backend.clone(),
+                    record.outcome.clone(),
+                    record.timestamp,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let expected = vec![
+        (
+            "esplora".to_string(),
+            BroadcastOutcome::Rejected("missing-inputs".to_string()),
+            1_000,
+        ),
+        ("electrum".to_string(), BroadcastOutcome::Accepted, 2_000),
+    ];
+    assert_eq!(history(&wallet), expected);
     assert_eq!(
         wallet
-            .next_unused_address(KeychainKind::External)
-            .to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+            .all_broadcasts(1_200..)
+            .map(|record| record.txid)
+            .collect::<Vec<_>>(),
+        [other_txid, txid]
     );
-    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(1));
 
-    // trying to mark index 0 unused should return false
-    assert!(!wallet.unmark_used(KeychainKind::External, 0));
+    // the journal is persisted, also after a compaction
+    let temp_dir = tempfile::tempdir()?;
+    let mut db = bdk_sqlite::Store::new(Connection::open(temp_dir.path().join("db.sqlite"))?)?;
+    db.write(&wallet.take_staged().expect("recorded broadcasts"))?;
+    let loaded = Wallet::load_from_changeset(db.read()?.expect("must have data"))?;
+    assert_eq!(history(&loaded), expected);
+    assert_eq!(loaded.all_broadcasts(..).count(), 3);
+
+    db.compact_to(&loaded.snapshot())?;
+    let compacted = Wallet::load_from_changeset(db.read()?.expect("must have data"))?;
+    assert_eq!(history(&compacted), expected);
+    assert_eq!(compacted.snapshot(), loaded.snapshot());
+
+    // discarding the staged changes doesn't forget a broadcast
+    let mut wallet = compacted;
+    wallet.record_broadcast(txid, "electrum", BroadcastOutcome::Accepted, 3_000);
+    let not_reverted = wallet.discard_staged();
+    assert_eq!(not_reverted.broadcasts.len(), 1);
+    assert_eq!(wallet.broadcast_history(txid).count(), 3);
+    Ok(())
 }
 
 #[test]
-fn test_peek_address_at_index() {
-    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
-    let change_desc = get_test_wpkh();
-    let mut wallet = Wallet::new(desc, change_desc, Network::Testnet).unwrap();
+fn test_broadcast_with() {
+    use bdk_chain::spk_client::{BackendError, BroadcastBackend, BroadcastError};
+    use std::cell::Cell;
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 1).to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
-    );
+    /// Rejects the first broadcast for a low fee, then accepts the transactions
+    struct Backend(Cell<bool>);
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 0).to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-    );
+    impl BroadcastBackend for Backend {
+        fn name(&self) -> &str {
+            "mock"
+        }
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 2).to_string(),
-        "tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2"
-    );
+        fn broadcast(&self, tx: &Transaction) -> Result<Txid, BroadcastError<BackendError>> {
+            if self.0.replace(true) {
+                Ok(tx.compute_txid())
+            } else {
+                Err(BroadcastError::MinRelayFeeNotMet)
+            }
+        }
+    }
 
-    // current new address is not affected
-    assert_eq!(
-        wallet
-            .reveal_next_address(KeychainKind::External)
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
+    let mut psbt = builder.finish().unwrap();
+    wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    let tx = psbt.extract_tx().unwrap();
+    let txid = tx.compute_txid();
+
+    let backend = Backend(Cell::new(false));
+    assert_matches!(
+        wallet.broadcast_with(&backend, &tx),
+        Err(BroadcastError::MinRelayFeeNotMet)
     );
+    assert_eq!(wallet.broadcast_with(&backend, &tx).unwrap(), txid);
 
+    let history = wallet.broadcast_history(txid).collect::<Vec<_>>();
+    assert_eq!(history.len(), 2);
+    assert!(history.iter().all(|record| record.backend == "mock"));
     assert_eq!(
-        wallet
-            .reveal_next_address(KeychainKind::External)
-            .to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+        history[0].outcome,
+        BroadcastOutcome::Rejected("the fee of the transaction is too low".to_string())
     );
+    assert_eq!(history[1].outcome, BroadcastOutcome::Accepted);
+    assert!(history[0].timestamp <= history[1].timestamp);
+}
+
+/// Sign the PSBTs of a chain built by [`Wallet::build_chain`] and extract their transactions
+fn sign_chain(wallet: &Wallet, psbts: Vec<psbt::Psbt>) -> Vec<Transaction> {
+    psbts
+        .into_iter()
+        .map(|mut psbt| {
+            assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+            psbt.extract_tx().expect("failed to extract tx")
+        })
+        .collect()
 }
 
 #[test]
-fn test_peek_address_at_index_not_derivable() {
-    let wallet = Wallet::new("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1)",
-                                 get_test_wpkh(), Network::Testnet).unwrap();
+fn test_build_chain() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let recipient = |n: u8| ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([n; 20]));
+    let specs = (0..3)
+        .map(|n| TxBuilderSpec::new(vec![(recipient(n), Amount::from_sat(5_000))]))
+        .collect::<Vec<_>>();
+    let psbts = wallet.build_chain(specs).unwrap();
+    assert_eq!(psbts.len(), 3);
+
+    // each transaction spends the change of its parent, and only that
+    for pair in psbts.windows(2) {
+        let parent = &pair[0].unsigned_tx;
+        let child = &pair[1].unsigned_tx;
+        assert_eq!(child.input.len(), 1);
+        let spent = child.input[0].previous_output;
+        assert_eq!(spent.txid, parent.compute_txid());
+        assert!(wallet.is_mine(&parent.output[spent.vout as usize].script_pubkey));
+    }
+    // the inputs are reserved, a new transaction can't select them
+    let reserved = psbts[0].unsigned_tx.input[0].previous_output;
+    assert!(wallet.is_reserved(reserved));
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 1).to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
-    );
+    let txs = sign_chain(&wallet, psbts);
+    for (tx, exp) in txs.iter().zip([5_000, 5_000, 5_000]) {
+        assert!(tx
+            .output
+            .iter()
+            .any(|txout| txout.value == Amount::from_sat(exp)
+                && !wallet.is_mine(&txout.script_pubkey)));
+    }
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 0).to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
-    );
+    // the chain is broadcast parents first, whatever the order it's given in
+    use bdk_chain::spk_client::{BackendError, BroadcastBackend, BroadcastError};
+    use std::cell::RefCell;
+
+    /// Records the broadcast transactions, rejecting the one at index `reject`
+    struct Backend {
+        broadcast: RefCell<Vec<Txid>>,
+        reject: Option<usize>,
+    }
+
+    impl BroadcastBackend for Backend {
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        fn broadcast(&self, tx: &Transaction) -> Result<Txid, BroadcastError<BackendError>> {
+            let mut broadcast = self.broadcast.borrow_mut();
+            if self.reject == Some(broadcast.len()) {
+                return Err(BroadcastError::MinRelayFeeNotMet);
+            }
+            broadcast.push(tx.compute_txid());
+            Ok(tx.compute_txid())
+        }
+    }
 
+    let txids = txs.iter().map(|tx| tx.compute_txid()).collect::<Vec<_>>();
+    let backend = Backend {
+        broadcast: RefCell::new(vec![]),
+        reject: None,
+    };
+    let shuffled = [txs[2].clone(), txs[0].clone(), txs[1].clone()];
     assert_eq!(
-        wallet.peek_address(KeychainKind::External, 2).to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+        wallet.broadcast_package(&backend, &shuffled).unwrap(),
+        txids
     );
+    assert_eq!(*backend.broadcast.borrow(), txids);
+    assert!(txids
+        .iter()
+        .all(|&txid| wallet.broadcast_history(txid).count() == 1));
 }
 
 #[test]
-fn test_returns_index_and_address() {
-    let mut wallet = Wallet::new("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)",
-                                 get_test_wpkh(), Network::Testnet).unwrap();
+fn test_broadcast_package_aborts() {
+    use bdk_chain::spk_client::{BackendError, BroadcastBackend, BroadcastError};
 
-    // new index 0
-    assert_eq!(
-        wallet.reveal_next_address(KeychainKind::External),
-        AddressInfo {
-            index: 0,
-            address: Address::from_str("tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
+    /// Rejects the second transaction
+    struct Backend;
+
+    impl BroadcastBackend for Backend {
+        fn name(&self) -> &str {
+            "mock"
         }
-    );
 
-    // new index 1
-    assert_eq!(
-        wallet.reveal_next_address(KeychainKind::External),
-        AddressInfo {
-            index: 1,
-            address: Address::from_str("tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
+        fn broadcast(&self, tx: &Transaction) -> Result<Txid, BroadcastError<BackendError>> {
+            Ok(tx.compute_txid())
         }
-    );
 
-    // peek index 25
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 25),
-        AddressInfo {
-            index: 25,
-            address: Address::from_str("tb1qsp7qu0knx3sl6536dzs0703u2w2ag6ppl9d0c2")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
+        fn broadcast_package(
+            &self,
+            txs: &[Transaction],
+        ) -> Vec<Result<Txid, BroadcastError<BackendError>>> {
+            vec![
+                Ok(txs[0].compute_txid()),
+                Err(BroadcastError::MinRelayFeeNotMet),
+            ]
         }
-    );
+    }
 
-    // new index 2
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let specs = vec![TxBuilderSpec::new(vec![(addr.script_pubkey(), Amount::from_sat(5_000))]); 3];
+    let psbts = wallet.build_chain(specs).unwrap();
+    let txs = sign_chain(&wallet, psbts);
+    let txids = txs.iter().map(|tx| tx.compute_txid()).collect::<Vec<_>>();
+    let inputs = |i: usize| {
+        txs[i]
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>()
+    };
+
+    let err = wallet.broadcast_package(&Backend, &txs).unwrap_err();
+    assert_eq!(err.txid, txids[1]);
+    assert_matches!(err.error, BroadcastError::MinRelayFeeNotMet);
+    assert_eq!(err.broadcast, vec![txids[0]]);
+    // the reservations of the abandoned transactions are released
+    assert!(inputs(0).into_iter().all(|op| wallet.is_reserved(op)));
+    assert!(!inputs(1)
+        .into_iter()
+        .chain(inputs(2))
+        .any(|op| wallet.is_reserved(op)));
+    assert_eq!(wallet.broadcast_history(txids[0]).count(), 1);
     assert_eq!(
-        wallet.reveal_next_address(KeychainKind::External),
-        AddressInfo {
-            index: 2,
-            address: Address::from_str("tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
-        }
+        wallet.broadcast_history(txids[1]).next().unwrap().outcome,
+        BroadcastOutcome::Rejected("the fee of the transaction is too low".to_string())
     );
+    assert_eq!(wallet.broadcast_history(txids[2]).count(), 0);
 }
 
 #[test]
-fn test_sending_to_bip350_bech32m_address() {
+fn test_build_chain_errors() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(45_000));
-    builder.finish().unwrap();
-}
+    let addr = wallet.next_unused_address(KeychainKind::External);
+    let spec = |sats| TxBuilderSpec::new(vec![(addr.script_pubkey(), Amount::from_sat(sats))]);
 
-#[test]
-fn test_get_address() {
-    use bdk_wallet::descriptor::template::Bip84;
-    let key = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
-    let wallet = Wallet::new(
-        Bip84(key, KeychainKind::External),
-        Bip84(key, KeychainKind::Internal),
-        Network::Regtest,
-    )
-    .unwrap();
+    // the last transaction can't be funded, the reservations of the others are released
+    assert_matches!(
+        wallet.build_chain(vec![spec(5_000), spec(5_000), spec(100_000_000)]),
+        Err(BuildChainError::CreateTx {
+            index: 2,
+            error: CreateTxError::CoinSelection(_)
+        })
+    );
+    assert_eq!(wallet.reserved_outpoints().count(), 0);
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 0),
-        AddressInfo {
-            index: 0,
-            address: Address::from_str("bcrt1qrhgaqu0zvf5q2d0gwwz04w0dh0cuehhqvzpp4w")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
-        }
+    // spending about the whole balance leaves no change to spend
+    let all = wallet.balance().total() - Amount::from_sat(200);
+    assert_matches!(
+        wallet.build_chain(vec![spec(all.to_sat()), spec(5_000)]),
+        Err(BuildChainError::NoParentChange { index: 1 })
     );
+    assert_eq!(wallet.reserved_outpoints().count(), 0);
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::Internal, 0),
-        AddressInfo {
-            index: 0,
-            address: Address::from_str("bcrt1q0ue3s5y935tw7v3gmnh36c5zzsaw4n9c9smq79")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::Internal,
-        }
+    // the txid of a parent with a legacy input changes once signed
+    let (mut wallet, _) =
+        get_funded_wallet("pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
+    assert_matches!(
+        wallet.build_chain(vec![spec(5_000), spec(5_000)]),
+        Err(BuildChainError::MalleableTxid { index: 0 })
+    );
+    assert_eq!(wallet.reserved_outpoints().count(), 0);
+    // an independent transaction is fine, but can't select the input reserved by the first one
+    let mut independent = spec(5_000);
+    independent.spend_parent_change = false;
+    assert_matches!(
+        wallet.build_chain(vec![spec(5_000), independent]),
+        Err(BuildChainError::CreateTx {
+            index: 1,
+            error: CreateTxError::CoinSelection(_)
+        })
     );
+    assert_eq!(wallet.reserved_outpoints().count(), 0);
 }
 
 #[test]
-fn test_reveal_addresses() {
-    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
-    let mut wallet = Wallet::new(desc, change_desc, Network::Signet).unwrap();
-    let keychain = KeychainKind::External;
+fn test_build_chain_regtest() -> anyhow::Result<()> {
+    use bdk_chain::spk_client::{BackendError, BroadcastBackend, BroadcastError};
+    use bdk_testenv::bitcoincore_rpc::RpcApi;
+    use bdk_testenv::TestEnv;
 
-    let last_revealed_addr = wallet.reveal_addresses_to(keychain, 9).last().unwrap();
-    assert_eq!(wallet.derivation_index(keychain), Some(9));
+    /// Broadcasts through the RPC interface of bitcoind
+    struct Rpc<'a, C>(&'a C);
 
-    let unused_addrs = wallet.list_unused_addresses(keychain).collect::<Vec<_>>();
-    assert_eq!(unused_addrs.len(), 10);
-    assert_eq!(unused_addrs.last().unwrap(), &last_revealed_addr);
+    impl<C: RpcApi> BroadcastBackend for Rpc<'_, C> {
+        fn name(&self) -> &str {
+            "bitcoind"
+        }
 
-    // revealing to an already revealed index returns nothing
-    let mut already_revealed = wallet.reveal_addresses_to(keychain, 9);
-    assert!(already_revealed.next().is_none());
+        fn broadcast(&self, tx: &Transaction) -> Result<Txid, BroadcastError<BackendError>> {
+            self.0
+                .send_raw_transaction(tx)
+                .map_err(|err| BroadcastError::Request(err.into()))
+        }
+    }
+
+    let env = TestEnv::new()?;
+    env.mine_blocks(101, None)?;
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest)?;
+    let address = wallet.reveal_next_address(KeychainKind::External).address;
+    let txid = env.send(&address, Amount::from_sat(1_000_000))?;
+    let hash = env.mine_blocks(1, None)?[0];
+    let height = env.rpc_client().get_block_count()? as u32;
+    wallet.insert_checkpoint(BlockId { height, hash })?;
+    wallet.insert_tx(
+        env.rpc_client().get_raw_transaction(&txid, None)?,
+        ConfirmationTime::Confirmed { height, time: 0 },
+    )?;
+
+    // three payouts, each spending the change of the previous one
+    let specs = (0..3)
+        .map(|_| {
+            let recipient = env
+                .rpc_client()
+                .get_new_address(None, None)?
+                .assume_checked();
+            Ok(
+                TxBuilderSpec::new(vec![(recipient.script_pubkey(), Amount::from_sat(100_000))])
+                    .fee_rate(FeeRate::from_sat_per_vb(2).unwrap()),
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let psbts = wallet.build_chain(specs)?;
+    let txs = sign_chain(&wallet, psbts);
+    let txids = wallet.broadcast_package(&Rpc(env.rpc_client()), &txs)?;
+    assert_eq!(
+        txids,
+        txs.iter().map(|tx| tx.compute_txid()).collect::<Vec<_>>()
+    );
+
+    // they are all confirmed in the same block
+    let hash = env.mine_blocks(1, None)?[0];
+    let block = env.rpc_client().get_block(&hash)?;
+    let confirmed = block
+        .txdata
+        .iter()
+        .map(|tx| tx.compute_txid())
+        .collect::<Vec<_>>();
+    assert!(txids.iter().all(|txid| confirmed.contains(txid)));
+    Ok(())
 }
 
 #[test]
-fn test_get_address_no_reuse() {
-    use bdk_wallet::descriptor::template::Bip84;
-    use std::collections::HashSet;
+fn test_build_cpfp_regtest() -> anyhow::Result<()> {
+    use bdk_testenv::bitcoincore_rpc::RpcApi;
+    use bdk_testenv::TestEnv;
 
-    let key = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
-    let mut wallet = Wallet::new(
-        Bip84(key, KeychainKind::External),
-        Bip84(key, KeychainKind::Internal),
-        Network::Regtest,
-    )
-    .unwrap();
+    let env = TestEnv::new()?;
+    env.mine_blocks(101, None)?;
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest)?;
+    let address = wallet.reveal_next_address(KeychainKind::External).address;
+    let txid = env.send(&address, Amount::from_sat(1_000_000))?;
+    let hash = env.mine_blocks(1, None)?[0];
+    let height = env.rpc_client().get_block_count()? as u32;
+    wallet.insert_checkpoint(BlockId { height, hash })?;
+    wallet.insert_tx(
+        env.rpc_client().get_raw_transaction(&txid, None)?,
+        ConfirmationTime::Confirmed { height, time: 0 },
+    )?;
 
-    let mut used_set = HashSet::new();
+    // a parent spending the change of its own unconfirmed parent, both at 1 sat/vb
+    let specs = (0..2)
+        .map(|_| {
+            let recipient = env
+                .rpc_client()
+                .get_new_address(None, None)?
+                .assume_checked();
+            Ok(
+                TxBuilderSpec::new(vec![(recipient.script_pubkey(), Amount::from_sat(100_000))])
+                    .fee_rate(FeeRate::from_sat_per_vb(1).unwrap()),
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let psbts = wallet.build_chain(specs)?;
+    let ancestors = sign_chain(&wallet, psbts);
+    for tx in &ancestors {
+        env.rpc_client().send_raw_transaction(tx)?;
+        wallet.insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })?;
+    }
 
-    (0..3).for_each(|_| {
-        let external_addr = wallet.reveal_next_address(KeychainKind::External).address;
-        assert!(used_set.insert(external_addr));
+    let target_feerate = FeeRate::from_sat_per_vb(10).unwrap();
+    let mut psbt = wallet
+        .build_cpfp(ancestors[1].compute_txid(), target_feerate)?
+        .finish()?;
+    assert!(wallet.sign(&mut psbt, SignOptions::default())?);
+    let child = psbt.extract_tx()?;
+    let child_txid = env.rpc_client().send_raw_transaction(&child)?;
+
+    // the mempool counts the whole chain as the ancestors of the child
+    let entry = env.rpc_client().get_mempool_entry(&child_txid)?;
+    assert_eq!(entry.ancestor_count, 3);
+    let package_feerate = entry.fees.ancestor / Weight::from_vb_unchecked(entry.ancestor_size);
+    assert!(
+        package_feerate >= target_feerate,
+        "package feerate {} below the target",
+        package_feerate.to_sat_per_kwu()
+    );
+    Ok(())
+}
 
-        let internal_addr = wallet.reveal_next_address(KeychainKind::Internal).address;
-        assert!(used_set.insert(internal_addr));
-    });
+/// The canonical transactions of `wallet` with their position in the chain, by txid
+fn canonical_txs(
+    wallet: &Wallet,
+) -> BTreeMap<Txid, bdk_chain::ChainPosition<ConfirmationTimeHeightAnchor>> {
+    wallet
+        .transactions()
+        .map(|c| (c.tx_node.txid, c.chain_position.cloned()))
+        .collect()
 }
 
 #[test]
-fn test_taproot_psbt_populate_tap_key_origins() {
-    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
-    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
-    let addr = wallet.reveal_next_address(KeychainKind::External);
+fn test_update_bundle_sync() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut phone = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let mut desktop = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let genesis = phone.local_chain().tip().block_id();
 
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
+    // the desktop is 10 blocks ahead
+    for (height, block) in test_blocks(&phone, genesis, 30, 0) {
+        if height <= 20 {
+            phone.apply_block(&block, height).unwrap();
+        }
+        desktop.apply_block(&block, height).unwrap();
+    }
+
+    // both saw the same unconfirmed transaction, the desktop later
+    let shared = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: phone
+                .peek_address(KeychainKind::External, 3)
+                .script_pubkey(),
+            value: Amount::from_sat(7_000),
+        }],
+    };
+    phone
+        .insert_tx(
+            shared.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 50 },
+        )
+        .unwrap();
+    desktop
+        .insert_tx(
+            shared.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 80 },
+        )
+        .unwrap();
+    let shared = shared.compute_txid();
 
+    // and each received a payment of its own
+    let on_phone = receive_output(
+        &mut phone,
+        5_000,
+        ConfirmationTime::Unconfirmed { last_seen: 100 },
+    );
+    let _ = desktop.reveal_addresses_to(KeychainKind::External, 5);
+    let on_desktop = receive_output(
+        &mut desktop,
+        6_000,
+        ConfirmationTime::Confirmed {
+            height: 25,
+            time: 0,
+        },
+    );
+    phone.set_label(LabelRef::Tx(on_phone.txid), "from the phone".to_string());
+    desktop.set_label(
+        LabelRef::Tx(on_desktop.txid),
+        "from the desktop".to_string(),
+    );
+    desktop.set_birthday(BlockTimeOrHeight::Height(5));
+    assert_ne!(phone.sync_marker(), desktop.sync_marker());
+
+    // the phone asks for what it's missing, over a transport of its own
+    let bundle = desktop.export_update_since(Some(&phone.sync_marker()));
+    // only the blocks above the tip of the phone, and those the transactions are anchored in
+    let anchor_heights = bundle
+        .graph
+        .anchors
+        .iter()
+        .map(|(anchor, _)| anchor.anchor_block.height)
+        .filter(|&height| height < 20)
+        .collect::<BTreeSet<_>>();
+    assert!(!anchor_heights.is_empty());
     assert_eq!(
-        psbt.inputs[0]
-            .tap_key_origins
-            .clone()
-            .into_iter()
-            .collect::<Vec<_>>(),
-        vec![(
-            from_str!("0841db1dbaf949dbbda893e01a18f2cca9179cf8ea2d8e667857690502b06483"),
-            (vec![], (from_str!("f6a5cb8b"), from_str!("m/0/0")))
-        )],
-        "Wrong input tap_key_origins"
+        bundle.chain.keys().copied().collect::<BTreeSet<_>>(),
+        anchor_heights.into_iter().chain(20..=30).collect()
     );
+    let bundle: WalletUpdateBundle =
+        serde_json::from_str(&serde_json::to_string(&bundle).unwrap()).unwrap();
+    let report = phone.apply_update_bundle(bundle).unwrap();
     assert_eq!(
-        psbt.outputs[0]
-            .tap_key_origins
-            .clone()
-            .into_iter()
-            .collect::<Vec<_>>(),
-        vec![(
-            from_str!("9187c1e80002d19ddde9c5c7f5394e9a063cee8695867b58815af0562695ca21"),
-            (vec![], (from_str!("f6a5cb8b"), from_str!("m/0/1")))
-        )],
-        "Wrong output tap_key_origins"
+        report.chain.keys().copied().collect::<Vec<_>>(),
+        (21..=30).collect::<Vec<_>>()
+    );
+    assert!(!report.ignored_chain);
+    assert!(report.new_txs.contains(&on_desktop.txid));
+    assert!(!report.new_txs.contains(&shared));
+    assert!(report.updated_txs.contains(&shared));
+    assert_eq!(report.revealed.get(&KeychainKind::External), Some(&5));
+    assert_eq!(report.new_labels, vec![LabelRef::Tx(on_desktop.txid)]);
+    assert!(report.conflicting_labels.is_empty());
+    assert!(report.birthday);
+    assert!(phone.staged().is_some());
+
+    // and answers with its own state
+    let bundle = phone.export_update_since(Some(&desktop.sync_marker()));
+    let report = desktop.apply_update_bundle(bundle).unwrap();
+    assert!(report.chain.is_empty());
+    assert_eq!(
+        report.new_txs.iter().collect::<Vec<_>>(),
+        vec![&on_phone.txid]
+    );
+    assert_eq!(report.new_labels, vec![LabelRef::Tx(on_phone.txid)]);
+    assert!(report.revealed.is_empty());
+
+    // both end with the same state
+    assert_eq!(phone.sync_marker(), desktop.sync_marker());
+    assert_eq!(canonical_txs(&phone), canonical_txs(&desktop));
+    assert_eq!(phone.balance(), desktop.balance());
+    assert_eq!(
+        phone.local_chain().tip().block_id(),
+        desktop.local_chain().tip().block_id()
     );
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        assert_eq!(
+            phone.derivation_index(keychain),
+            desktop.derivation_index(keychain)
+        );
+    }
+    let last_seen = phone
+        .tx_graph()
+        .full_txs()
+        .find(|tx| tx.txid == shared)
+        .unwrap()
+        .last_seen_unconfirmed;
+    assert_eq!(last_seen, 80);
+    assert_eq!(phone.birthday(), desktop.birthday());
+
+    // applying a bundle again changes nothing
+    let bundle = desktop.export_update_since(Some(&phone.sync_marker()));
+    assert!(bundle.graph.txs.is_empty());
+    assert!(phone.apply_update_bundle(bundle).unwrap().is_empty());
+    let bundle = desktop.export_update_since(None);
+    assert!(phone.apply_update_bundle(bundle).unwrap().is_empty());
+
+    // a label set differently on both devices is kept
+    phone.set_label(LabelRef::Tx(shared), "phone".to_string());
+    desktop.set_label(LabelRef::Tx(shared), "desktop".to_string());
+    let report = phone
+        .apply_update_bundle(desktop.export_update_since(None))
+        .unwrap();
+    assert_eq!(report.conflicting_labels, vec![LabelRef::Tx(shared)]);
+    assert_eq!(phone.label(&LabelRef::Tx(shared)), Some("phone"));
 }
 
 #[test]
-fn test_taproot_psbt_populate_tap_key_origins_repeated_key() {
-    let (mut wallet, _) =
-        get_funded_wallet_with_change(get_test_tr_repeated_key(), get_test_tr_single_sig());
-    let addr = wallet.reveal_next_address(KeychainKind::External);
-
-    let path = vec![("rn4nre9c".to_string(), vec![0])]
-        .into_iter()
-        .collect();
+fn test_update_bundle_chain_conflict() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut phone = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let mut desktop = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let genesis = phone.local_chain().tip().block_id();
 
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .policy_path(path, KeychainKind::External);
-    let psbt = builder.finish().unwrap();
-
-    let mut input_key_origins = psbt.inputs[0]
-        .tap_key_origins
-        .clone()
-        .into_iter()
-        .collect::<Vec<_>>();
-    input_key_origins.sort();
+    // the chains fork after block 10, the one of the desktop is longer
+    let blocks = test_blocks(&phone, genesis, 10, 0);
+    let fork_base = BlockId {
+        height: 10,
+        hash: blocks[9].1.block_hash(),
+    };
+    for (height, block) in &blocks {
+        phone.apply_block(block, *height).unwrap();
+        desktop.apply_block(block, *height).unwrap();
+    }
+    for (height, block) in test_blocks(&phone, fork_base, 5, 1) {
+        phone.apply_block(&block, height).unwrap();
+    }
+    let longer = test_blocks(&phone, fork_base, 8, 2);
+    for (height, block) in &longer {
+        desktop.apply_block(block, *height).unwrap();
+    }
 
+    // the shorter chain is ignored
+    let report = desktop
+        .apply_update_bundle(phone.export_update_since(Some(&desktop.sync_marker())))
+        .unwrap();
+    assert!(report.ignored_chain);
+    assert!(report.chain.is_empty());
+    // the longer chain wins
+    let report = phone
+        .apply_update_bundle(desktop.export_update_since(Some(&phone.sync_marker())))
+        .unwrap();
+    assert!(!report.ignored_chain);
     assert_eq!(
-        input_key_origins,
-        vec![
-            (
-                from_str!("2b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3"),
-                (
-                    vec![
-                        from_str!(
-                            "858ad7a7d7f270e2c490c4d6ba00c499e46b18fdd59ea3c2c47d20347110271e"
-                        ),
-                        from_str!(
-                            "f6e927ad4492c051fe325894a4f5f14538333b55a35f099876be42009ec8f903"
-                        ),
-                    ],
-                    (FromStr::from_str("ece52657").unwrap(), vec![].into())
-                )
-            ),
-            (
-                from_str!("b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55"),
-                (
-                    vec![],
-                    (FromStr::from_str("871fd295").unwrap(), vec![].into())
-                )
-            )
-        ],
-        "Wrong input tap_key_origins"
+        phone.local_chain().tip().block_id(),
+        desktop.local_chain().tip().block_id()
     );
+    assert_eq!(report.chain.get(&11), Some(&Some(longer[0].1.block_hash())));
 
-    let mut output_key_origins = psbt.outputs[0]
-        .tap_key_origins
-        .clone()
-        .into_iter()
-        .collect::<Vec<_>>();
-    output_key_origins.sort();
-
-    assert_eq!(
-        input_key_origins, output_key_origins,
-        "Wrong output tap_key_origins"
+    // bundles of other wallets are rejected
+    let other = Wallet::new(get_test_wpkh(), get_test_tr_single_sig(), Network::Regtest).unwrap();
+    assert_matches!(
+        phone.apply_update_bundle(other.export_update_since(None)),
+        Err(ApplyBundleError::DescriptorMismatch(KeychainKind::External))
+    );
+    let testnet = Wallet::new(descriptor, change_descriptor, Network::Testnet).unwrap();
+    assert_matches!(
+        phone.apply_update_bundle(testnet.export_update_since(None)),
+        Err(ApplyBundleError::NetworkMismatch {
+            expected: Network::Regtest,
+            found: Network::Testnet,
+        })
     );
 }
 
-#[test]
-fn test_taproot_psbt_input_tap_tree() {
-    use bitcoin::hex::FromHex;
-    use bitcoin::taproot;
+/// A chain source which counts the script pubkeys and txids requested, and returns the
+/// transactions paying its script pubkeys
+struct CountingBackend {
+    txs: BTreeMap<ScriptBuf, Transaction>,
+    requests: std::cell::RefCell<Vec<usize>>,
+}
 
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
-    let addr = wallet.next_unused_address(KeychainKind::External);
+impl bdk_chain::spk_client::SyncBackend<KeychainKind> for CountingBackend {
+    fn full_scan(
+        &self,
+        _request: bdk_chain::spk_client::FullScanRequest<KeychainKind>,
+        _options: bdk_chain::spk_client::BackendOptions,
+    ) -> Result<
+        bdk_chain::spk_client::FullScanResult<KeychainKind>,
+        bdk_chain::spk_client::BackendError,
+    > {
+        unimplemented!("the scheduler only syncs")
+    }
 
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
+    fn sync(
+        &self,
+        request: bdk_chain::spk_client::SyncRequest,
+        _options: bdk_chain::spk_client::BackendOptions,
+    ) -> Result<bdk_chain::spk_client::SyncResult, bdk_chain::spk_client::BackendError> {
+        let mut graph_update = TxGraph::default();
+        let mut count = request.txids.len();
+        for spk in request.spks {
+            count += 1;
+            if let Some(tx) = self.txs.get(&spk) {
+                let _ = graph_update.insert_tx(tx.clone());
+                let _ = graph_update.insert_seen_at(tx.compute_txid(), 6_000);
+            }
+        }
+        self.requests.borrow_mut().push(count);
+        Ok(bdk_chain::spk_client::SyncResult {
+            graph_update,
+            chain_update: request.chain_tip,
+        })
+    }
+}
 
+#[test]
+fn test_sync_scheduler_many_keychains() {
+    let mut wallet =
+        Wallet::new(get_test_wpkh(), get_test_tr_single_sig(), Network::Regtest).unwrap();
+    let mut accounts = vec![];
+    for account in 0..20 {
+        let descriptor = get_test_tr_single_sig_xprv().replace("/*)", &format!("/{}/*)", account));
+        accounts.push(
+            wallet
+                .add_keychain(format!("account{}", account), descriptor.as_str())
+                .unwrap(),
+        );
+    }
+    let keychains = wallet.keychains().map(|(k, _)| *k).collect::<Vec<_>>();
+    assert_eq!(keychains.len(), 22);
+    for keychain in &keychains {
+        let _ = wallet.reveal_addresses_to(*keychain, 2).count();
+    }
+
+    // three accounts were recently active, one of them with an unconfirmed transaction
+    let pay = |wallet: &Wallet, keychain: KeychainKind, index: u32| Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::from_consensus(index),
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet.peek_address(keychain, index).script_pubkey(),
+            value: Amount::from_sat(10_000),
+        }],
+    };
+    let _ = wallet
+        .insert_checkpoint(BlockId {
+            height: 1,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    for (account, time) in [(5, 4_000), (12, 3_000)] {
+        let tx = pay(&wallet, accounts[account], 0);
+        wallet
+            .insert_tx(tx, ConfirmationTime::Confirmed { height: 1, time })
+            .unwrap();
+    }
+    let unconfirmed = pay(&wallet, accounts[17], 1);
+    let unconfirmed_txid = unconfirmed.compute_txid();
+    wallet
+        .insert_tx(
+            unconfirmed,
+            ConfirmationTime::Unconfirmed { last_seen: 5_000 },
+        )
+        .unwrap();
+    // the others were synced before, the internal keychain a long time ago
+    for keychain in &keychains {
+        if *keychain != KeychainKind::Internal {
+            wallet.record_keychain_synced(*keychain, 2_000);
+        }
+    }
+    wallet.record_keychain_synced(KeychainKind::Internal, 1_000);
+    wallet.record_keychain_synced(KeychainKind::Internal, 500);
     assert_eq!(
-        psbt.inputs[0].tap_merkle_root,
-        Some(
-            TapNodeHash::from_str(
-                "61f81509635053e52d9d1217545916167394490da2287aca4693606e43851986"
-            )
-            .unwrap()
-        ),
-    );
-    assert_eq!(
-        psbt.inputs[0].tap_scripts.clone().into_iter().collect::<Vec<_>>(),
-        vec![
-            (taproot::ControlBlock::decode(&Vec::<u8>::from_hex("c0b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55b7ef769a745e625ed4b9a4982a4dc08274c59187e73e6f07171108f455081cb2").unwrap()).unwrap(), (ScriptBuf::from_hex("208aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642ac").unwrap(), taproot::LeafVersion::TapScript)),
-            (taproot::ControlBlock::decode(&Vec::<u8>::from_hex("c0b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55b9a515f7be31a70186e3c5937ee4a70cc4b4e1efe876c1d38e408222ffc64834").unwrap()).unwrap(), (ScriptBuf::from_hex("2051494dc22e24a32fe9dcfbd7e85faf345fa1df296fb49d156e859ef345201295ac").unwrap(), taproot::LeafVersion::TapScript)),
-        ],
-    );
-    assert_eq!(
-        psbt.inputs[0].tap_internal_key,
-        Some(from_str!(
-            "b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55"
-        ))
+        wallet.keychain_last_synced(KeychainKind::Internal),
+        Some(1_000)
     );
 
-    // Since we are creating an output to the same address as the input, assert that the
-    // internal_key is the same
+    let budget = RequestBudget {
+        max_requests: 10,
+        window_secs: 60,
+    };
+    let scheduler = wallet.sync_scheduler(budget);
+    let pending = scheduler.pending();
+    assert_eq!(pending[..3], [accounts[17], accounts[5], accounts[12]]);
+
+    // the backend finds a new transaction of an account which wasn't active
+    let found = pay(&wallet, accounts[8], 2);
+    let backend = CountingBackend {
+        txs: [(
+            wallet.peek_address(accounts[8], 2).script_pubkey(),
+            found.clone(),
+        )]
+        .into(),
+        requests: Default::default(),
+    };
+    let mut scheduler = scheduler;
+    let mut now = 10_000;
+    let mut windows = vec![];
+    while !scheduler.is_done() {
+        let mut synced = vec![];
+        while let Some((keychain, result)) = scheduler
+            .sync_next(
+                &backend,
+                wallet.latest_checkpoint(),
+                Default::default(),
+                now,
+            )
+            .unwrap()
+        {
+            synced.push(keychain);
+            wallet.apply_update(result).unwrap();
+        }
+        let spent = backend.requests.borrow_mut().drain(..).sum::<usize>();
+        assert!(spent <= budget.max_requests);
+        windows.push(synced);
+        now += budget.window_secs;
+    }
+    // the active accounts are synced within the first window: 3 script pubkeys each and the
+    // unconfirmed transaction
+    assert_eq!(windows[0], vec![accounts[17], accounts[5], accounts[12]]);
+    // 22 keychains of 3 script pubkeys and a txid
+    assert_eq!(windows.len(), 7);
+    // then the stalest keychain
+    assert_eq!(windows[1][0], KeychainKind::Internal);
+    assert!(wallet.get_tx(found.compute_txid()).is_some());
+    assert!(wallet.get_tx(unconfirmed_txid).is_some());
+
+    for (keychain, at) in scheduler.last_synced() {
+        wallet.record_keychain_synced(*keychain, *at);
+    }
+    assert_eq!(wallet.keychain_last_synced(accounts[17]), Some(10_000));
     assert_eq!(
-        psbt.inputs[0].tap_internal_key,
-        psbt.outputs[0].tap_internal_key
+        wallet.keychain_last_synced(KeychainKind::Internal),
+        Some(10_060)
     );
 
-    let tap_tree: bitcoin::taproot::TapTree = serde_json::from_str(r#"[1,{"Script":["2051494dc22e24a32fe9dcfbd7e85faf345fa1df296fb49d156e859ef345201295ac",192]},1,{"Script":["208aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642ac",192]}]"#).unwrap();
-    assert_eq!(psbt.outputs[0].tap_tree, Some(tap_tree));
+    // the sync times are persisted, the next run starts with the active accounts then the
+    // stalest ones
+    let changeset = wallet.take_staged().unwrap();
+    assert_eq!(changeset.last_synced.len(), 22);
+    let loaded = Wallet::load_from_changeset(changeset).unwrap();
+    for keychain in &keychains {
+        assert_eq!(
+            loaded.keychain_last_synced(*keychain),
+            wallet.keychain_last_synced(*keychain)
+        );
+    }
+    let pending = loaded.sync_scheduler(budget).pending();
+    assert_eq!(
+        pending[..4],
+        [accounts[8], accounts[17], accounts[5], accounts[12]]
+    );
+    assert_eq!(pending.len(), 22);
 }
 
 #[test]
-fn test_taproot_sign_missing_witness_utxo() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
-    let witness_utxo = psbt.inputs[0].witness_utxo.take();
-
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
-            ..Default::default()
-        },
-    );
-    assert_matches!(
-        result,
-        Err(SignerError::MissingWitnessUtxo),
-        "Signing should have failed with the correct error because the witness_utxo is missing"
-    );
+fn test_reserve_inputs_concurrent_drains() {
+    use std::sync::Mutex;
 
-    // restore the witness_utxo
-    psbt.inputs[0].witness_utxo = witness_utxo;
+    let mut wallet = Wallet::new(
+        get_test_wpkh(),
+        get_test_tr_single_sig_xprv(),
+        Network::Regtest,
+    )
+    .unwrap();
+    let _ = wallet
+        .insert_checkpoint(BlockId {
+            height: 1,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    for _ in 0..6 {
+        let _ = receive_output(
+            &mut wallet,
+            20_000,
+            ConfirmationTime::Confirmed { height: 1, time: 0 },
+        );
+    }
+    let drain_spk = wallet
+        .peek_address(KeychainKind::External, 100)
+        .script_pubkey();
+    let wallet = Arc::new(Mutex::new(wallet));
+
+    let threads = (0..2u64)
+        .map(|seed| {
+            let wallet = Arc::clone(&wallet);
+            let drain_spk = drain_spk.clone();
+            std::thread::spawn(move || {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut built = vec![];
+                for attempt in 0..30 {
+                    let mut wallet = wallet.lock().unwrap();
+                    let unspent = wallet
+                        .list_unspent()
+                        .map(|utxo| utxo.outpoint)
+                        .collect::<Vec<_>>();
+                    let mut builder = wallet.build_tx();
+                    builder.drain_to(drain_spk.clone()).reserve_inputs(true);
+                    if attempt % 3 == 0 {
+                        builder.drain_wallet();
+                    } else {
+                        // two outpoints, which may be reserved already
+                        let picked = (0..2)
+                            .map(|_| unspent[rng.gen_range(0..unspent.len())])
+                            .collect::<Vec<_>>();
+                        builder.add_utxos(&picked).unwrap().manually_selected_only();
+                    }
+                    match builder.finish() {
+                        Ok(psbt) => built.push(
+                            psbt.unsigned_tx
+                                .input
+                                .iter()
+                                .map(|txin| txin.previous_output)
+                                .collect::<BTreeSet<_>>(),
+                        ),
+                        Err(CreateTxError::InputsReserved(outpoints)) => {
+                            assert!(outpoints.iter().all(|op| wallet.is_reserved(*op)))
+                        }
+                        Err(CreateTxError::CoinSelection(_)) => {}
+                        Err(e) => panic!("unexpected error: {}", e),
+                    }
+                    drop(wallet);
+                    std::thread::yield_now();
+                }
+                built
+            })
+        })
+        .collect::<Vec<_>>();
+    let built = threads
+        .into_iter()
+        .flat_map(|thread| thread.join().unwrap())
+        .collect::<Vec<_>>();
 
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
-            ..Default::default()
-        },
+    // no outpoint is spent by two transactions
+    assert!(!built.is_empty());
+    let mut spent = BTreeSet::new();
+    for inputs in &built {
+        for outpoint in inputs {
+            assert!(spent.insert(*outpoint), "{} spent twice", outpoint);
+        }
+    }
+    let mut wallet = Arc::try_unwrap(wallet).unwrap().into_inner().unwrap();
+    assert_eq!(
+        wallet
+            .reserved_outpoints()
+            .map(|(outpoint, _)| outpoint)
+            .collect::<BTreeSet<_>>(),
+        spent
     );
 
-    assert_matches!(
-        result,
-        Ok(true),
-        "Should finalize the input since we can produce signatures"
+    // canceling a transaction, or recording its failed broadcast, releases its inputs
+    let txids = wallet
+        .reserved_outpoints()
+        .map(|(_, txid)| txid)
+        .collect::<BTreeSet<_>>();
+    let released = *txids.iter().next().unwrap();
+    wallet.record_broadcast(
+        released,
+        "test",
+        BroadcastOutcome::Rejected("missing-inputs".to_string()),
+        0,
     );
+    assert!(wallet
+        .reserved_outpoints()
+        .all(|(_, txid)| txid != released));
+    wallet.release_expired_reservations(u64::MAX);
+    assert_eq!(wallet.reserved_outpoints().count(), 0);
 }
 
 #[test]
-fn test_taproot_sign_using_non_witness_utxo() {
-    let (mut wallet, prev_txid) = get_funded_wallet(get_test_tr_single_sig());
+fn test_reserve_inputs() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
+    let utxo = wallet.list_unspent().next().unwrap().outpoint;
+
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .reserve_inputs(true);
+    let psbt = builder.finish().unwrap();
+    assert!(wallet.is_reserved(utxo));
 
-    psbt.inputs[0].witness_utxo = None;
-    psbt.inputs[0].non_witness_utxo =
-        Some(wallet.get_tx(prev_txid).unwrap().tx_node.as_ref().clone());
-    assert!(
-        psbt.inputs[0].non_witness_utxo.is_some(),
-        "Previous tx should be present in the database"
+    // the reserved outpoint isn't selected, nor can it be added
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    assert_matches!(builder.finish(), Err(CreateTxError::CoinSelection(_)));
+    let mut builder = wallet.build_tx();
+    builder
+        .add_utxo(utxo)
+        .unwrap()
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .reserve_inputs(true);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::InputsReserved(outpoints)) if outpoints == vec![utxo]
     );
 
-    let result = wallet.sign(&mut psbt, Default::default());
-    assert!(result.is_ok(), "Signing should have worked");
-    assert!(
-        result.unwrap(),
-        "Should finalize the input since we can produce signatures"
-    );
+    // canceling the transaction releases it
+    wallet.cancel_tx(&psbt.unsigned_tx);
+    assert!(!wallet.is_reserved(utxo));
+
+    // an expired reservation is released when the next transaction is built
+    wallet.set_reservation_ttl(0);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .reserve_inputs(true);
+    let _ = builder.finish().unwrap();
+    assert!(wallet.is_reserved(utxo));
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    assert!(builder.finish().is_ok());
+    assert!(!wallet.is_reserved(utxo));
+
+    // so is the reservation of a transaction applied to the wallet
+    wallet.set_reservation_ttl(DEFAULT_RESERVATION_TTL);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .reserve_inputs(true);
+    let tx = builder.finish().unwrap().unsigned_tx;
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(1_000));
+    let _ = builder.finish();
+    assert!(!wallet.is_reserved(utxo));
 }
 
 #[test]
-fn test_taproot_foreign_utxo() {
-    let (mut wallet1, _) = get_funded_wallet_wpkh();
-    let (wallet2, _) = get_funded_wallet(get_test_tr_single_sig());
+fn test_tiered_fetcher_fallback() {
+    use bdk_chain::spk_client::BackendError;
+    use bdk_chain::tiered_fetch::{BlockTxSource, BlockTxs, TieredFetcher};
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let utxo = wallet2.list_unspent().next().unwrap();
-    let psbt_input = wallet2.get_psbt_input(utxo.clone(), None, false).unwrap();
-    let foreign_utxo_satisfaction = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+    struct MockTier {
+        name: &'static str,
+        /// The lowest height it can serve, like a pruned node
+        pruned_below: u32,
+        blocks: BTreeMap<u32, BlockTxs>,
+    }
 
-    assert!(
-        psbt_input.non_witness_utxo.is_none(),
-        "`non_witness_utxo` should never be populated for taproot"
-    );
+    impl BlockTxSource for MockTier {
+        fn name(&self) -> &str {
+            self.name
+        }
 
-    let mut builder = wallet1.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
-        .add_foreign_utxo(
-            utxo.outpoint,
-            psbt_input,
-            foreign_utxo_satisfaction.to_wu() as usize,
+        fn block_txs(
+            &self,
+            height: u32,
+            _spks: &BTreeSet<ScriptBuf>,
+        ) -> Result<BlockTxs, BackendError> {
+            if height < self.pruned_below {
+                return Err(format!("block {} is pruned", height).into());
+            }
+            Ok(self.blocks[&height].clone())
+        }
+    }
+
+    let new_wallet = || {
+        let (desc, change_desc) = get_test_wpkh_with_change_desc();
+        let mut wallet = Wallet::new(desc, change_desc, Network::Regtest).unwrap();
+        let _ = wallet.reveal_next_address(KeychainKind::External);
+        wallet
+    };
+    let mut node_wallet = new_wallet();
+    let mut esplora_wallet = new_wallet();
+
+    let receive = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey: node_wallet
+                .peek_address(KeychainKind::External, 0)
+                .script_pubkey(),
+        }],
+    };
+    let unrelated = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 1),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new(),
+        }],
+        ..receive.clone()
+    };
+    let spend = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(receive.compute_txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![
+            TxOut {
+                value: Amount::from_sat(20_000),
+                script_pubkey: ScriptBuf::new(),
+            },
+            TxOut {
+                value: Amount::from_sat(29_000),
+                script_pubkey: node_wallet
+                    .peek_address(KeychainKind::Internal, 0)
+                    .script_pubkey(),
+            },
+        ],
+        ..receive.clone()
+    };
+    let block = |height: u32, txs: Vec<Transaction>| {
+        let block = BlockId {
+            height,
+            hash: BlockHash::hash(&height.to_le_bytes()),
+        };
+        (
+            height,
+            BlockTxs {
+                block,
+                time: u64::from(height) * 600,
+                txs,
+            },
         )
-        .unwrap();
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet1.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    wallet1.insert_txout(utxo.outpoint, utxo.txout);
-    let fee = check_fee!(wallet1, psbt);
+    };
+    let blocks = BTreeMap::from([
+        block(100, vec![unrelated, receive.clone()]),
+        block(200, vec![spend.clone()]),
+    ]);
+    let node = MockTier {
+        name: "bitcoind",
+        pruned_below: 150,
+        blocks: blocks.clone(),
+    };
+    let esplora = MockTier {
+        name: "esplora",
+        pruned_below: 0,
+        blocks,
+    };
+
+    let mut served_by = vec![];
+    for (wallet, tiers) in [
+        (&mut node_wallet, vec![&node, &esplora]),
+        (&mut esplora_wallet, vec![&esplora]),
+    ] {
+        let mut fetcher = TieredFetcher::new();
+        for tier in tiers {
+            fetcher = fetcher.with_tier(tier);
+        }
+        fetcher.add_spks(wallet.spk_index().inner().all_spks().values().cloned());
+        let update = fetcher.fetch([100, 200]).unwrap();
+        let chain = wallet
+            .latest_checkpoint()
+            .extend(
+                update
+                    .blocks
+                    .iter()
+                    .map(|(&height, &hash)| BlockId { height, hash }),
+            )
+            .unwrap();
+        wallet
+            .apply_update(Update {
+                graph: update.graph_update,
+                chain: Some(chain),
+                ..Default::default()
+            })
+            .unwrap();
+        served_by.push((update.served_by, update.failures.len()));
+    }
 
     assert_eq!(
-        sent_received.0 - sent_received.1,
-        Amount::from_sat(10_000) + fee.unwrap_or(Amount::ZERO),
-        "we should have only net spent ~10_000"
+        served_by,
+        vec![
+            (
+                BTreeMap::from([(100, "esplora".into()), (200, "bitcoind".into())]),
+                1
+            ),
+            (
+                BTreeMap::from([(100, "esplora".into()), (200, "esplora".into())]),
+                0
+            ),
+        ]
     );
-
-    assert!(
-        psbt.unsigned_tx
-            .input
-            .iter()
-            .any(|input| input.previous_output == utxo.outpoint),
-        "foreign_utxo should be in there"
+    assert_eq!(node_wallet.balance().confirmed, Amount::from_sat(29_000));
+    assert_eq!(node_wallet.balance(), esplora_wallet.balance());
+    assert_eq!(node_wallet.staged(), esplora_wallet.staged());
+    let txids = |wallet: &Wallet| {
+        wallet
+            .transactions()
+            .map(|tx| tx.tx_node.txid)
+            .collect::<BTreeSet<_>>()
+    };
+    assert_eq!(
+        txids(&node_wallet),
+        BTreeSet::from([receive.compute_txid(), spend.compute_txid()])
     );
+    assert_eq!(txids(&node_wallet), txids(&esplora_wallet));
 }
 
-fn test_spend_from_wallet(mut wallet: Wallet) {
-    let addr = wallet.next_unused_address(KeychainKind::External);
+#[test]
+fn test_state_fingerprint_insertion_order() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest).unwrap();
+    let mut other = Wallet::new(desc, change_desc, Network::Regtest).unwrap();
+    assert_eq!(wallet.state_fingerprint(), other.state_fingerprint());
 
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
+    let address = wallet.reveal_next_address(KeychainKind::External);
+    let change_address = wallet.reveal_next_address(KeychainKind::Internal);
+    let tx = |value: u64, script_pubkey: ScriptBuf| Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey,
+            value: Amount::from_sat(value),
+        }],
+    };
+    let confirmed = tx(10_000, address.script_pubkey());
+    let unconfirmed = tx(20_000, change_address.script_pubkey());
+    let blocks = [50, 100].map(|height| BlockId {
+        height,
+        hash: BlockHash::hash(&[height as u8]),
+    });
 
-    assert!(
-        wallet.sign(&mut psbt, Default::default()).unwrap(),
-        "Unable to finalize tx"
+    for block in blocks {
+        wallet.insert_checkpoint(block).unwrap();
+    }
+    wallet
+        .insert_tx(
+            confirmed.clone(),
+            ConfirmationTime::Confirmed {
+                height: 50,
+                time: 1_000,
+            },
+        )
+        .unwrap();
+    wallet
+        .insert_tx(
+            unconfirmed.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 10 },
+        )
+        .unwrap();
+
+    // the same data the other way around, the last seen time doesn't count
+    other
+        .insert_tx(unconfirmed, ConfirmationTime::Unconfirmed { last_seen: 20 })
+        .unwrap();
+    other.reveal_next_address(KeychainKind::Internal);
+    other.reveal_next_address(KeychainKind::External);
+    for block in blocks.into_iter().rev() {
+        other.insert_checkpoint(block).unwrap();
+    }
+    other
+        .insert_tx(
+            confirmed,
+            ConfirmationTime::Confirmed {
+                height: 50,
+                time: 1_000,
+            },
+        )
+        .unwrap();
+
+    assert_eq!(wallet.state_fingerprint(), other.state_fingerprint());
+    assert_eq!(FingerprintMismatch::between(&wallet, &other), None);
+    // the preimage is fixed by its version, on every platform
+    assert_eq!(
+        wallet.state_fingerprint_components().version,
+        bdk_wallet::wallet::STATE_FINGERPRINT_VERSION
+    );
+    assert_eq!(
+        bitcoin::hex::DisplayHex::to_lower_hex_string(&wallet.state_fingerprint()[..]),
+        "0dbe8b53389dcb179807917430bc011b9724c9aed8ce3c6a13c08d4125033cad"
     );
 }
 
-//     #[test]
-//     fn test_taproot_key_spend() {
-//         let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
-//         test_spend_from_wallet(wallet);
+#[test]
+fn test_state_fingerprint_persistence() -> anyhow::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let file_path = temp_dir.path().join("store.db");
+    let mut db = bdk_file_store::Store::<ChangeSet>::create_new(DB_MAGIC, &file_path)?;
 
-//         let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
-//         test_spend_from_wallet(wallet);
-//     }
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    db.append_changeset(&wallet.take_staged().expect("staged changes"))?;
+    for _ in 0..3 {
+        wallet.reveal_next_address(KeychainKind::External);
+        receive_output_in_latest_block(&mut wallet, 10_000);
+        let tip = wallet.latest_checkpoint().height();
+        wallet.insert_checkpoint(BlockId {
+            height: tip + 1,
+            hash: BlockHash::hash(&tip.to_le_bytes()),
+        })?;
+        db.append_changeset(&wallet.take_staged().expect("staged changes"))?;
+    }
+    let fingerprint = wallet.state_fingerprint();
+
+    let load = |db: &mut bdk_file_store::Store<ChangeSet>| -> anyhow::Result<Wallet> {
+        let changeset = db.aggregate_changesets()?.expect("persisted changes");
+        Ok(Wallet::load_from_changeset(changeset)?)
+    };
+    assert_eq!(load(&mut db)?.state_fingerprint(), fingerprint);
+    db.compact()?;
+    assert_eq!(load(&mut db)?.state_fingerprint(), fingerprint);
+    db.compact_to(&wallet.snapshot())?;
+    assert_eq!(load(&mut db)?.state_fingerprint(), fingerprint);
+    drop(db);
+    let mut db = bdk_file_store::Store::<ChangeSet>::open(DB_MAGIC, &file_path)?;
+    assert_eq!(load(&mut db)?.state_fingerprint(), fingerprint);
+    Ok(())
+}
 
 #[test]
-fn test_taproot_no_key_spend() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
-    let addr = wallet.next_unused_address(KeychainKind::External);
+fn test_state_fingerprint_mismatch() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let changeset = wallet.snapshot();
+    let mut other = Wallet::load_from_changeset(changeset).unwrap();
 
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
+    // the remote fingerprint is sent over the wire
+    let remote = |wallet: &Wallet| -> StateFingerprint {
+        let json = serde_json::to_string(&wallet.state_fingerprint_components()).unwrap();
+        serde_json::from_str(&json).unwrap()
+    };
+    assert_eq!(
+        wallet.state_fingerprint_components().diff(&remote(&other)),
+        None
+    );
 
-    assert!(
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    sign_with_tap_internal_key: false,
-                    ..Default::default()
-                },
-            )
-            .unwrap(),
-        "Unable to finalize tx"
+    other.reveal_next_address(KeychainKind::Internal);
+    assert_eq!(
+        FingerprintMismatch::between(&wallet, &other),
+        Some(FingerprintMismatch::Components(vec![
+            FingerprintComponent::RevealedIndices
+        ]))
     );
+    wallet.reveal_next_address(KeychainKind::Internal);
+    assert_eq!(FingerprintMismatch::between(&wallet, &other), None);
 
-    assert!(psbt.inputs.iter().all(|i| i.tap_key_sig.is_none()));
-}
+    let tip = wallet.latest_checkpoint().height();
+    other
+        .insert_checkpoint(BlockId {
+            height: tip + 1,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    assert_eq!(
+        wallet.state_fingerprint_components().diff(&remote(&other)),
+        Some(FingerprintMismatch::Components(vec![
+            FingerprintComponent::ChainTip
+        ]))
+    );
 
-#[test]
-fn test_taproot_script_spend() {
-    let (wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
-    test_spend_from_wallet(wallet);
+    receive_output_in_latest_block(&mut other, 10_000);
+    let mismatch = FingerprintMismatch::between(&wallet, &other);
+    assert_matches!(
+        &mismatch,
+        Some(FingerprintMismatch::Components(components))
+            if components.starts_with(&[FingerprintComponent::Transactions, FingerprintComponent::Utxos])
+    );
+    assert_ne!(wallet.state_fingerprint(), other.state_fingerprint());
 
-    let (wallet, _) = get_funded_wallet(get_test_tr_with_taptree_xprv());
-    test_spend_from_wallet(wallet);
+    let mut remote = remote(&wallet);
+    remote.version += 1;
+    assert_eq!(
+        wallet.state_fingerprint_components().diff(&remote),
+        Some(FingerprintMismatch::Version {
+            local: 1,
+            remote: 2
+        })
+    );
 }
 
 #[test]
-fn test_taproot_script_spend_sign_all_leaves() {
-    use bdk_wallet::signer::TapLeavesOptions;
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+fn test_check_feerate_overestimated_taptree() {
+    // the weight is estimated from the worst case, the largest script spend of the tree, but the
+    // input is spent with the internal key
+    let descriptor = "tr(cNJmN3fH9DDbDt131fQNkVakkpzawJBSeybCUNmP1BovpmGQ45xG,{multi_a(3,b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55,8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642,f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16),{multi_a(2,b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55,8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642,f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16),pk(f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16)}})";
+    let (mut wallet, _) = get_funded_wallet(descriptor);
     let addr = wallet.next_unused_address(KeychainKind::External);
-
+    let target = FeeRate::from_sat_per_vb(5).unwrap();
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
+        .fee_rate(target);
+    let (mut psbt, fee_target) = builder.finish_with_fee_target().unwrap();
+    assert_eq!(fee_target.fee_rate, target);
+    assert!(fee_target.change.is_some());
+    assert_eq!(fee_target.fee, psbt.fee_amount().unwrap());
 
-    assert!(
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    tap_leaves_options: TapLeavesOptions::All,
-                    ..Default::default()
-                },
-            )
-            .unwrap(),
-        "Unable to finalize tx"
+    assert_matches!(
+        wallet.check_feerate(&psbt, target),
+        Err(FeeRateCheckError::NotFinalized(inputs)) if inputs == vec![0]
     );
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+
+    let drift = wallet.check_feerate(&psbt, target).unwrap();
+    let tx = psbt.clone().extract_tx().unwrap();
+    assert_eq!(drift.fee, fee_target.fee);
+    assert_eq!(drift.weight, tx.weight());
+    assert!(drift.weight < fee_target.estimated_weight);
+    assert!(drift.drift() > SignedAmount::ZERO);
+    assert!(!drift.is_below_target());
+    assert!(!drift.is_below_min_relay());
+    assert_eq!(drift.shortfall(), Amount::ZERO);
+
+    // the signed transaction gives the same report, once its prevouts are in the graph
+    assert_eq!(wallet.check_feerate(&tx, target).unwrap(), drift);
+    // against a higher target, the drift is negative
+    let higher = FeeRate::from_sat_per_vb(50).unwrap();
+    let drift = wallet.check_feerate(&tx, higher).unwrap();
+    assert!(drift.drift() < SignedAmount::ZERO);
+    assert!(drift.is_below_target());
+    assert!(drift.shortfall() > Amount::ZERO);
+    // the fee missing to reach the target
+    assert_eq!(-drift.drift(), drift.shortfall().to_signed().unwrap());
+}
+
+#[test]
+fn test_sign_fee_adjustment() {
+    let descriptor = "tr(cNJmN3fH9DDbDt131fQNkVakkpzawJBSeybCUNmP1BovpmGQ45xG,{multi_a(3,b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55,8aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642,f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16),pk(f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16)})";
+    let (mut wallet, _) = get_funded_wallet(descriptor);
+    let recipient = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
 
-    assert!(psbt
-        .inputs
-        .iter()
-        .all(|i| i.tap_script_sigs.len() == i.tap_scripts.len()));
+    // an absolute fee below the minimum relay fee rate is topped up from the change
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(recipient.clone(), Amount::from_sat(20_000))
+        .fee_absolute(Amount::from_sat(50));
+    let (mut psbt, fee_target) = builder.finish_with_fee_target().unwrap();
+    let original = psbt.unsigned_tx.clone();
+    let change = fee_target.change.unwrap();
+    let options = SignOptions {
+        fee_adjustment: fee_target.adjustment(Amount::from_sat(1_000)),
+        ..Default::default()
+    };
+    assert!(wallet.sign(&mut psbt, options).unwrap());
+    let drift = wallet.check_feerate(&psbt, FeeRate::BROADCAST_MIN).unwrap();
+    assert!(!drift.is_below_min_relay());
+    assert_eq!(drift.shortfall(), Amount::ZERO);
+    assert_eq!(
+        drift.fee,
+        Amount::from_sat(drift.weight.to_vbytes_ceil()),
+        "the change is only reduced by the missing fee"
+    );
+    let tx = psbt.extract_tx().unwrap();
+    for (index, (txout, original)) in tx.output.iter().zip(&original.output).enumerate() {
+        if index == change.index {
+            assert_eq!(txout.value, change.value - (drift.fee - fee_target.fee));
+        } else {
+            assert_eq!(txout, original, "the recipient outputs are untouched");
+        }
+    }
+    // the input was signed again over the reduced change
+    let prevouts = [wallet.get_utxo(tx.input[0].previous_output).unwrap().txout];
+    let mut cache = bitcoin::sighash::SighashCache::new(&tx);
+    let sighash = cache
+        .taproot_key_spend_signature_hash(
+            0,
+            &bitcoin::sighash::Prevouts::All(&prevouts),
+            TapSighashType::Default,
+        )
+        .unwrap();
+    let signature =
+        bitcoin::secp256k1::schnorr::Signature::from_slice(&tx.input[0].witness[0]).unwrap();
+    let output_key = bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(
+        bitcoin::XOnlyPublicKey::from_slice(&prevouts[0].script_pubkey.as_bytes()[2..]).unwrap(),
+    );
+    Secp256k1::verification_only()
+        .verify_schnorr(
+            &signature,
+            &bitcoin::secp256k1::Message::from_digest(sighash.to_byte_array()),
+            &output_key.to_x_only_public_key(),
+        )
+        .expect("valid signature");
+
+    // a target above the achieved fee rate, within the bound
+    let target = FeeRate::from_sat_per_vb(20).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(recipient.clone(), Amount::from_sat(20_000))
+        .fee_rate(FeeRate::from_sat_per_vb(2).unwrap());
+    let (mut psbt, fee_target) = builder.finish_with_fee_target().unwrap();
+    let adjustment = FeeAdjustment {
+        target,
+        ..fee_target.adjustment(Amount::from_sat(10_000)).unwrap()
+    };
+    let options = SignOptions {
+        fee_adjustment: Some(adjustment),
+        ..Default::default()
+    };
+    assert!(wallet.sign(&mut psbt, options).unwrap());
+    let drift = wallet.check_feerate(&psbt, target).unwrap();
+    assert_eq!(drift.shortfall(), Amount::ZERO);
+    assert!(drift.fee > fee_target.fee);
+
+    // beyond the bound, the outputs are left untouched
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(recipient.clone(), Amount::from_sat(20_000))
+        .fee_rate(FeeRate::from_sat_per_vb(2).unwrap());
+    let (mut psbt, fee_target) = builder.finish_with_fee_target().unwrap();
+    let unsigned_tx = psbt.unsigned_tx.clone();
+    let adjustment = FeeAdjustment {
+        target,
+        ..fee_target.adjustment(Amount::from_sat(10)).unwrap()
+    };
+    let options = SignOptions {
+        fee_adjustment: Some(adjustment),
+        ..Default::default()
+    };
+    assert!(wallet.sign(&mut psbt, options).unwrap());
+    assert_eq!(psbt.unsigned_tx, unsigned_tx);
+    assert!(wallet
+        .check_feerate(&psbt, target)
+        .unwrap()
+        .is_below_target());
+
+    // the recipient outputs can't be reduced
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(recipient, Amount::from_sat(20_000))
+        .fee_absolute(Amount::from_sat(50));
+    let (mut psbt, fee_target) = builder.finish_with_fee_target().unwrap();
+    let recipient_index = 1 - fee_target.change.unwrap().index;
+    let options = SignOptions {
+        fee_adjustment: Some(FeeAdjustment {
+            change_index: recipient_index,
+            ..fee_target.adjustment(Amount::from_sat(1_000)).unwrap()
+        }),
+        ..Default::default()
+    };
+    assert_matches!(
+        wallet.sign(&mut psbt, options),
+        Err(SignerError::InvalidChangeOutput(index)) if index == recipient_index
+    );
 }
 
 #[test]
-fn test_taproot_script_spend_sign_include_some_leaves() {
-    use bdk_wallet::signer::TapLeavesOptions;
-    use bitcoin::taproot::TapLeafHash;
-
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+fn test_fee_limits_build() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
+    wallet.set_fee_limits(FeeLimits {
+        max_fee: Some(Amount::from_sat(4_000)),
+        max_fee_rate: Some(FeeRate::from_sat_per_vb(20).unwrap()),
+    });
 
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
-    let mut script_leaves: Vec<_> = psbt.inputs[0]
-        .tap_scripts
-        .clone()
-        .values()
-        .map(|(script, version)| TapLeafHash::from_script(script, *version))
-        .collect();
-    let included_script_leaves = vec![script_leaves.pop().unwrap()];
-    let excluded_script_leaves = script_leaves;
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .fee_absolute(Amount::from_sat(5_000));
+    assert_matches!(builder.estimate(), Err(CreateTxError::FeeAboveLimit { .. }));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::FeeAboveLimit { fee, max })
+            if fee == Amount::from_sat(5_000) && max == Amount::from_sat(4_000)
+    );
 
-    assert!(
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    tap_leaves_options: TapLeavesOptions::Include(included_script_leaves.clone()),
-                    ..Default::default()
-                },
-            )
-            .unwrap(),
-        "Unable to finalize tx"
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .fee_rate(FeeRate::from_sat_per_vb(25).unwrap());
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::FeeRateAboveLimit { fee_rate, max })
+            if fee_rate > max && max == FeeRate::from_sat_per_vb(20).unwrap()
     );
 
-    assert!(psbt.inputs[0]
-        .tap_script_sigs
-        .iter()
-        .all(|s| included_script_leaves.contains(&s.0 .1)
-            && !excluded_script_leaves.contains(&s.0 .1)));
+    // looser limits only apply with the acknowledgment
+    let looser = FeeLimits {
+        max_fee: Some(Amount::from_sat(6_000)),
+        max_fee_rate: None,
+    };
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .fee_absolute(Amount::from_sat(5_000))
+        .fee_limits(looser);
+    assert_matches!(
+        builder.clone().finish(),
+        Err(CreateTxError::FeeAboveLimit { max, .. }) if max == Amount::from_sat(4_000)
+    );
+    builder.acknowledge_fee_limits();
+    let psbt = builder.finish().unwrap();
+    assert_eq!(psbt.fee().unwrap(), Amount::from_sat(5_000));
+
+    // tighter limits apply without it
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .fee_absolute(Amount::from_sat(1_500))
+        .fee_limits(FeeLimits {
+            max_fee: Some(Amount::from_sat(1_000)),
+            max_fee_rate: None,
+        });
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::FeeAboveLimit { max, .. }) if max == Amount::from_sat(1_000)
+    );
 }
 
 #[test]
-fn test_taproot_script_spend_sign_exclude_some_leaves() {
-    use bdk_wallet::signer::TapLeavesOptions;
-    use bitcoin::taproot::TapLeafHash;
-
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+fn test_fee_limits_sign() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External);
-
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
-    let mut script_leaves: Vec<_> = psbt.inputs[0]
-        .tap_scripts
-        .clone()
-        .values()
-        .map(|(script, version)| TapLeafHash::from_script(script, *version))
-        .collect();
-    let included_script_leaves = [script_leaves.pop().unwrap()];
-    let excluded_script_leaves = script_leaves;
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .fee_absolute(Amount::from_sat(3_000));
+    let psbt = builder.finish().unwrap();
 
-    assert!(
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    tap_leaves_options: TapLeavesOptions::Exclude(excluded_script_leaves.clone()),
-                    ..Default::default()
-                },
-            )
-            .unwrap(),
-        "Unable to finalize tx"
+    // the limits of the wallet apply when signing
+    wallet.set_fee_limits(FeeLimits {
+        max_fee: Some(Amount::from_sat(2_000)),
+        max_fee_rate: None,
+    });
+    let mut signed = psbt.clone();
+    assert_matches!(
+        wallet.sign(&mut signed, SignOptions::default()),
+        Err(SignerError::FeeAboveLimit { fee, max })
+            if fee == Amount::from_sat(3_000) && max == Amount::from_sat(2_000)
     );
+    assert_eq!(signed, psbt, "the PSBT is left untouched");
 
-    assert!(psbt.inputs[0]
-        .tap_script_sigs
-        .iter()
-        .all(|s| included_script_leaves.contains(&s.0 .1)
-            && !excluded_script_leaves.contains(&s.0 .1)));
+    // and so do the tighter ones of the options
+    wallet.set_fee_limits(FeeLimits::default());
+    let options = SignOptions {
+        max_fee_rate: Some(FeeRate::from_sat_per_vb(10).unwrap()),
+        ..Default::default()
+    };
+    assert_matches!(
+        wallet.sign(&mut signed, options),
+        Err(SignerError::FeeRateAboveLimit { fee_rate, max })
+            if fee_rate > max && max == FeeRate::from_sat_per_vb(10).unwrap()
+    );
+    assert_eq!(signed, psbt);
+    let options = SignOptions {
+        max_fee: Some(Amount::from_sat(3_000)),
+        max_fee_rate: Some(FeeRate::from_sat_per_vb(50).unwrap()),
+        ..Default::default()
+    };
+    assert!(wallet.sign(&mut signed, options).unwrap());
 }
 
 #[test]
-fn test_taproot_script_spend_sign_no_leaves() {
-    use bdk_wallet::signer::TapLeavesOptions;
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
-    let addr = wallet.next_unused_address(KeychainKind::External);
-
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+fn test_fee_limits_unknown_prevouts() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+    let addr = wallet1.next_unused_address(KeychainKind::External);
+    let utxo = wallet2.list_unspent().next().unwrap();
+    let satisfaction_weight = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+    let mut builder = wallet1.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(
+            utxo.outpoint,
+            psbt::Input {
+                witness_utxo: Some(utxo.txout.clone()),
+                ..Default::default()
+            },
+            satisfaction_weight.to_wu() as usize,
+        )
+        .unwrap();
     let mut psbt = builder.finish().unwrap();
+    let foreign = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|txin| txin.previous_output == utxo.outpoint)
+        .unwrap();
+    // an externally provided PSBT, without the previous output of the foreign input
+    psbt.inputs[foreign].witness_utxo = None;
+    // only the input of the wallet is signed
+    let options = SignOptions {
+        max_fee: Some(Amount::from_sat(10_000)),
+        inputs: Some([1 - foreign].into()),
+        trust_witness_utxo: true,
+        ..Default::default()
+    };
 
-    wallet
-        .sign(
-            &mut psbt,
+    let unchanged = psbt.clone();
+    assert_matches!(
+        wallet1.sign(&mut psbt, options.clone()),
+        Err(SignerError::UnknownPrevouts(inputs)) if inputs == vec![foreign]
+    );
+    assert_eq!(psbt, unchanged);
+
+    // without a limit there is nothing to check
+    let mut unlimited = psbt.clone();
+    let details = wallet1
+        .sign_with_details(
+            &mut unlimited,
             SignOptions {
-                tap_leaves_options: TapLeavesOptions::None,
-                ..Default::default()
+                max_fee: None,
+                ..options.clone()
             },
         )
         .unwrap();
+    assert!(!details.finalized);
 
-    assert!(psbt.inputs.iter().all(|i| i.tap_script_sigs.is_empty()));
+    let options = SignOptions {
+        allow_unknown_prevouts: true,
+        ..options
+    };
+    let details = wallet1.sign_with_details(&mut psbt, options).unwrap();
+    assert!(!details.finalized);
+    assert_eq!(details.signed_inputs, [1 - foreign].into());
 }
 
 #[test]
-fn test_taproot_sign_derive_index_from_psbt() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
-
+fn test_apply_unconfirmed_txs_conflicts() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let balance = wallet.balance().total();
     let addr = wallet.next_unused_address(KeychainKind::External);
+    let foreign_outpoint = OutPoint::new(Txid::from_byte_array([0x42; 32]), 0);
+    let spend = |value: u64, script_pubkey: ScriptBuf| Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: foreign_outpoint,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey,
+        }],
+    };
+    let elsewhere = ScriptBuf::new_op_return([0x01; 4]);
+    let receive = spend(10_000, addr.script_pubkey());
+    let older_double_spend = spend(9_000, elsewhere.clone());
+    let double_spend = spend(8_000, elsewhere.clone());
+    let unrelated = {
+        let mut tx = spend(1_000, elsewhere);
+        tx.input[0].previous_output.vout = 1;
+        tx
+    };
 
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
+    let applied = wallet.apply_unconfirmed_txs([(&receive, 100), (&unrelated, 100)]);
+    assert_eq!(
+        applied,
+        vec![
+            TxApplied {
+                txid: receive.compute_txid(),
+                relevant: true,
+                conflicts: vec![],
+                canonical: true,
+                balance_delta: SignedAmount::from_sat(10_000),
+            },
+            TxApplied {
+                txid: unrelated.compute_txid(),
+                relevant: false,
+                conflicts: vec![],
+                canonical: false,
+                balance_delta: SignedAmount::ZERO,
+            },
+        ]
+    );
+    assert_eq!(wallet.balance().total(), balance + Amount::from_sat(10_000));
+    // the unrelated transaction neither bloats the graph nor the changeset
+    assert!(wallet.get_tx(unrelated.compute_txid()).is_none());
+    assert!(!wallet
+        .staged()
+        .unwrap()
+        .indexed_tx_graph
+        .graph
+        .txs
+        .iter()
+        .any(|tx| tx.compute_txid() == unrelated.compute_txid()));
 
-    // re-create the wallet with an empty db
-    let wallet_empty = Wallet::new(
-        get_test_tr_single_sig_xprv(),
-        get_test_tr_single_sig(),
-        Network::Regtest,
-    )
-    .unwrap();
+    // a double spend seen before the receive doesn't replace it
+    let applied = wallet.apply_unconfirmed_txs([(&older_double_spend, 50)]);
+    assert_eq!(
+        applied,
+        vec![TxApplied {
+            txid: older_double_spend.compute_txid(),
+            relevant: true,
+            conflicts: vec![receive.compute_txid()],
+            canonical: false,
+            balance_delta: SignedAmount::ZERO,
+        }]
+    );
 
-    // signing with an empty db means that we will only look at the psbt to infer the
-    // derivation index
-    assert!(
-        wallet_empty.sign(&mut psbt, Default::default()).unwrap(),
-        "Unable to finalize tx"
+    // a double spend seen after the receive replaces it
+    let applied = wallet.apply_unconfirmed_txs([(&double_spend, 200)]);
+    let mut conflicts = vec![receive.compute_txid(), older_double_spend.compute_txid()];
+    conflicts.sort();
+    assert_eq!(
+        applied,
+        vec![TxApplied {
+            txid: double_spend.compute_txid(),
+            relevant: true,
+            conflicts,
+            canonical: true,
+            balance_delta: SignedAmount::from_sat(-10_000),
+        }]
     );
+    assert_eq!(wallet.balance().total(), balance);
+    assert!(!wallet
+        .transactions()
+        .any(|tx| tx.tx_node.txid == receive.compute_txid()));
 }
 
 #[test]
-fn test_taproot_sign_explicit_sighash_all() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+fn test_sign_arbitrary_derivations() {
+    use bitcoin::bip32::{DerivationPath, Xpriv};
+    use bitcoin::secp256k1::Secp256k1;
+
+    let tprv = "tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L";
+    let (mut wallet, _) = get_funded_wallet(&format!("wpkh({}/84'/1'/0'/0/*)", tprv));
+
+    // a key of the same master key, in another account
+    let secp = Secp256k1::new();
+    let master = Xpriv::from_str(tprv).unwrap();
+    let path = DerivationPath::from_str("m/84'/1'/7'/0/0").unwrap();
+    let other_path = DerivationPath::from_str("m/84'/1'/7'/0/1").unwrap();
+    let public_key = |path: &DerivationPath| {
+        bitcoin::PublicKey::new(
+            master
+                .derive_priv(&secp, path)
+                .unwrap()
+                .to_priv()
+                .inner
+                .public_key(&secp),
+        )
+    };
+    let funding = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            value: Amount::from_sat(30_000),
+            script_pubkey: ScriptBuf::new_p2wpkh(&public_key(&path).wpubkey_hash().unwrap()),
+        }],
+    };
+
     let addr = wallet.next_unused_address(KeychainKind::External);
     let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .sighash(TapSighashType::All.into())
-        .drain_wallet();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(20_000));
     let mut psbt = builder.finish().unwrap();
+    psbt.unsigned_tx.input.push(TxIn {
+        previous_output: OutPoint::new(funding.compute_txid(), 0),
+        ..Default::default()
+    });
+    psbt.inputs.push(psbt::Input {
+        witness_utxo: Some(funding.output[0].clone()),
+        non_witness_utxo: Some(funding.clone()),
+        bip32_derivation: [(
+            public_key(&path).inner,
+            (master.fingerprint(&secp), path.clone()),
+        )]
+        .into(),
+        ..Default::default()
+    });
+    let foreign = psbt.inputs.len() - 1;
+    let mine = BTreeSet::from_iter(0..foreign);
 
-    let result = wallet.sign(&mut psbt, Default::default());
-    assert!(
-        result.is_ok(),
-        "Signing should work because SIGHASH_ALL is safe"
-    )
+    // by default the key at another path is ignored
+    let mut unsigned = psbt.clone();
+    let details = wallet
+        .sign_with_details(&mut unsigned, SignOptions::default())
+        .unwrap();
+    assert_eq!(details.signed_inputs, mine);
+    assert!(details.arbitrary_derivation_inputs.is_empty());
+    assert!(unsigned.inputs[foreign].partial_sigs.is_empty());
+
+    let sign_options = SignOptions {
+        sign_arbitrary_derivations: true,
+        ..Default::default()
+    };
+    let mut signed = psbt.clone();
+    let details = wallet
+        .sign_with_details(&mut signed, sign_options.clone())
+        .unwrap();
+    let mut all = mine.clone();
+    all.insert(foreign);
+    assert_eq!(details.signed_inputs, all);
+    assert_eq!(details.arbitrary_derivation_inputs, [foreign].into());
+    assert!(signed.inputs[foreign]
+        .partial_sigs
+        .contains_key(&public_key(&path)));
+    // the input can't be finalized with the wallet's descriptors
+    assert!(!details.finalized);
+    assert!(signed.inputs[0].final_script_witness.is_some());
+
+    // the derived key must be the one of the entry
+    let mut mismatched = psbt;
+    mismatched.inputs[foreign].bip32_derivation = [(
+        public_key(&other_path).inner,
+        (master.fingerprint(&secp), path),
+    )]
+    .into();
+    let details = wallet
+        .sign_with_details(&mut mismatched, sign_options)
+        .unwrap();
+    assert_eq!(details.signed_inputs, mine);
+    assert!(details
+        .signer_errors
+        .iter()
+        .any(|(_, err)| matches!(err, SignerError::InvalidKey)));
+    assert!(mismatched.inputs[foreign].partial_sigs.is_empty());
 }
 
 #[test]
-fn test_taproot_sign_non_default_sighash() {
-    let sighash = TapSighashType::NonePlusAnyoneCanPay;
+fn test_bump_candidates() {
+    let mempool_min_fee = FeeRate::from_sat_per_vb(1).unwrap();
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    // the confirmed transactions can't be bumped
+    assert_eq!(wallet.bump_candidates(mempool_min_fee), vec![]);
 
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
-    let addr = wallet.next_unused_address(KeychainKind::External);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .sighash(sighash.into())
-        .drain_wallet();
-    let mut psbt = builder.finish().unwrap();
-
-    let witness_utxo = psbt.inputs[0].witness_utxo.take();
-
-    let result = wallet.sign(&mut psbt, Default::default());
-    assert!(
-        result.is_err(),
-        "Signing should have failed because the TX uses non-standard sighashes"
-    );
-    assert_matches!(
-        result,
-        Err(SignerError::NonStandardSighash),
-        "Signing failed with the wrong error type"
-    );
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(FeeRate::from_sat_per_vb(2).unwrap())
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.compute_txid();
+    let fee = wallet.calculate_fee(&tx).unwrap();
+    let fee_rate = wallet.calculate_fee_rate(&tx).unwrap();
+    let weight = tx.weight();
+    let change = tx
+        .output
+        .iter()
+        .position(|txout| wallet.is_mine(&txout.script_pubkey))
+        .unwrap();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
 
-    // try again after opting-in
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
-            ..Default::default()
-        },
-    );
-    assert!(
-        result.is_err(),
-        "Signing should have failed because the witness_utxo is missing"
-    );
-    assert_matches!(
-        result,
-        Err(SignerError::MissingWitnessUtxo),
-        "Signing failed with the wrong error type"
+    let candidates = wallet.bump_candidates(mempool_min_fee);
+    let extra = (fee + FeeRate::BROADCAST_MIN * weight)
+        .max(FeeRate::from_sat_per_kwu(fee_rate.to_sat_per_kwu() + 250) * weight)
+        - fee;
+    assert_eq!(
+        candidates,
+        vec![BumpCandidate {
+            txid,
+            signals_rbf: true,
+            all_inputs_ours: true,
+            cpfp_outputs: vec![OutPoint::new(txid, change as u32)],
+            fee: Some(fee),
+            package_fee_rate: Some(fee_rate),
+            min_replacement_extra_fee: Some(extra),
+        }]
     );
+    assert!(candidates[0].can_rbf());
+    assert!(candidates[0].can_cpfp());
 
-    // restore the witness_utxo
-    psbt.inputs[0].witness_utxo = witness_utxo;
-
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
-            ..Default::default()
-        },
+    // a full mempool raises the fee of the replacement
+    let full_mempool = FeeRate::from_sat_per_vb(100).unwrap();
+    assert_eq!(
+        wallet.bump_candidates(full_mempool)[0].min_replacement_extra_fee,
+        Some(full_mempool * weight - fee)
     );
 
-    assert!(result.is_ok(), "Signing should have worked");
-    assert!(
-        result.unwrap(),
-        "Should finalize the input since we can produce signatures"
+    // the fee bump at the minimum fee rate of the replacement is built
+    let min_rate = FeeRate::from_sat_per_kwu(
+        ((fee + extra).to_sat() * 1000 + weight.to_wu() - 1) / weight.to_wu(),
     );
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(min_rate);
+    assert!(builder.finish().is_ok());
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    // the package of a child includes its unconfirmed parent
+    let child = {
+        let builder = wallet
+            .build_cpfp(txid, FeeRate::from_sat_per_vb(10).unwrap())
+            .unwrap();
+        builder.finish().unwrap().extract_tx().unwrap()
+    };
+    let child_txid = child.compute_txid();
+    let child_fee = wallet.calculate_fee(&child).unwrap();
+    let child_weight = child.weight();
+    wallet
+        .insert_tx(child, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    let candidates = wallet.bump_candidates(mempool_min_fee);
+    let parent = candidates.iter().find(|c| c.txid == txid).unwrap();
+    assert!(parent.cpfp_outputs.is_empty());
+    assert!(!parent.can_cpfp());
+    let child = candidates.iter().find(|c| c.txid == child_txid).unwrap();
     assert_eq!(
-        *extracted.input[0].witness.to_vec()[0].last().unwrap(),
-        sighash as u8,
-        "The signature should have been made with the right sighash"
+        child.package_fee_rate,
+        Some((fee + child_fee) / (weight + child_weight))
     );
+    assert!(child.can_cpfp());
 }
 
 #[test]
-fn test_spend_coinbase() {
-    let (desc, change_desc) = get_test_wpkh_with_change_desc();
-    let mut wallet = Wallet::new(desc, change_desc, Network::Regtest).unwrap();
+fn test_tx_package_fee_rate() {
+    use bdk_chain::spk_client::{CpfpInfo, MempoolTx};
 
-    let confirmation_height = 5;
-    wallet
-        .insert_checkpoint(BlockId {
-            height: confirmation_height,
-            hash: BlockHash::all_zeros(),
-        })
-        .unwrap();
-    let coinbase_tx = Transaction {
-        version: transaction::Version::ONE,
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    // an incoming payment spending an output of an unconfirmed transaction of the sender
+    let parent_outpoint = OutPoint::new(Txid::from_byte_array([7; 32]), 0);
+    let tx = Transaction {
+        version: transaction::Version::TWO,
         lock_time: absolute::LockTime::ZERO,
         input: vec![TxIn {
-            previous_output: OutPoint::null(),
+            previous_output: parent_outpoint,
             ..Default::default()
         }],
         output: vec![TxOut {
             script_pubkey: wallet
                 .next_unused_address(KeychainKind::External)
                 .script_pubkey(),
-            value: Amount::from_sat(25_000),
+            value: Amount::from_sat(40_000),
         }],
     };
+    let txid = tx.compute_txid();
+    let weight = tx.weight();
     wallet
-        .insert_tx(
-            coinbase_tx,
-            ConfirmationTime::Confirmed {
-                height: confirmation_height,
-                time: 30_000,
-            },
-        )
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
+    let cpfp_info = CpfpInfo {
+        ancestors: vec![MempoolTx {
+            txid: parent_outpoint.txid,
+            fee: Amount::from_sat(300),
+            weight: Weight::from_wu(800),
+        }],
+        ..Default::default()
+    };
 
-    let not_yet_mature_time = confirmation_height + COINBASE_MATURITY - 1;
-    let maturity_time = confirmation_height + COINBASE_MATURITY;
+    // the fee of the payment is unknown
+    assert_eq!(wallet.tx_package_fee_rate(txid, None), None);
+    assert_eq!(wallet.tx_package_fee_rate(txid, Some(&cpfp_info)), None);
 
-    let balance = wallet.balance();
+    // with the previous output of the payment, as the chain source reports it
+    wallet.insert_txout(
+        parent_outpoint,
+        TxOut {
+            script_pubkey: ScriptBuf::new(),
+            value: Amount::from_sat(41_000),
+        },
+    );
+    let fee = Amount::from_sat(1_000);
+    // the parent isn't in the graph, the package is the transaction alone
+    assert_eq!(wallet.tx_package_fee_rate(txid, None), Some(fee / weight));
     assert_eq!(
-        balance,
-        Balance {
-            immature: Amount::from_sat(25_000),
-            trusted_pending: Amount::ZERO,
-            untrusted_pending: Amount::ZERO,
-            confirmed: Amount::ZERO
-        }
+        wallet.tx_package_fee_rate(txid, Some(&cpfp_info)),
+        Some((fee + Amount::from_sat(300)) / (weight + Weight::from_wu(800)))
     );
 
-    // We try to create a transaction, only to notice that all
-    // our funds are unspendable
+    // a transaction unknown to the wallet
+    let unknown = Txid::from_byte_array([8; 32]);
+    assert_eq!(wallet.tx_package_fee_rate(unknown, Some(&cpfp_info)), None);
+}
+
+#[test]
+fn test_bump_candidates_not_replaceable() {
+    let mempool_min_fee = FeeRate::from_sat_per_vb(1).unwrap();
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), balance.immature / 2)
-        .current_height(confirmation_height);
-    assert!(matches!(
-        builder.finish(),
-        Err(CreateTxError::CoinSelection(
-            coin_selection::Error::InsufficientFunds {
-                needed: _,
-                available: 0
-            }
-        ))
-    ));
 
-    // Still unspendable...
+    // a transaction without RBF signal
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), balance.immature / 2)
-        .current_height(not_yet_mature_time);
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let tx = builder.finish().unwrap().extract_tx().unwrap();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    let candidate = &wallet.bump_candidates(mempool_min_fee)[0];
+    assert!(!candidate.signals_rbf);
+    assert!(candidate.all_inputs_ours);
+    assert!(!candidate.can_rbf());
+    assert!(candidate.can_cpfp());
     assert_matches!(
-        builder.finish(),
-        Err(CreateTxError::CoinSelection(
-            coin_selection::Error::InsufficientFunds {
-                needed: _,
-                available: 0
-            }
-        ))
+        wallet.build_fee_bump(candidate.txid),
+        Err(BuildFeeBumpError::IrreplaceableTransaction(_))
     );
 
+    // a transaction with an input of another wallet
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+    let utxo = wallet2.list_unspent().next().unwrap();
+    let satisfaction_weight = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(
+            utxo.outpoint,
+            psbt::Input {
+                witness_utxo: Some(utxo.txout.clone()),
+                ..Default::default()
+            },
+            satisfaction_weight.to_wu() as usize,
+        )
+        .unwrap()
+        .enable_rbf();
+    let tx = builder.finish().unwrap().extract_tx().unwrap();
+    wallet.insert_txout(utxo.outpoint, utxo.txout);
     wallet
-        .insert_checkpoint(BlockId {
-            height: maturity_time,
-            hash: BlockHash::all_zeros(),
-        })
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
-    let balance = wallet.balance();
-    assert_eq!(
-        balance,
-        Balance {
-            immature: Amount::ZERO,
-            trusted_pending: Amount::ZERO,
-            untrusted_pending: Amount::ZERO,
-            confirmed: Amount::from_sat(25_000)
-        }
-    );
+    let candidate = &wallet.bump_candidates(mempool_min_fee)[0];
+    assert!(candidate.signals_rbf);
+    assert!(!candidate.all_inputs_ours);
+    assert!(candidate.fee.is_some());
+    assert!(!candidate.can_rbf());
+    assert!(candidate.can_cpfp());
+
+    // a transaction without change
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), balance.confirmed / 2)
-        .current_height(maturity_time);
-    builder.finish().unwrap();
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .enable_rbf();
+    let tx = builder.finish().unwrap().extract_tx().unwrap();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    let candidate = &wallet.bump_candidates(mempool_min_fee)[0];
+    assert!(candidate.cpfp_outputs.is_empty());
+    assert!(!candidate.can_cpfp());
+    assert!(candidate.can_rbf());
+    assert_matches!(
+        wallet.build_cpfp(candidate.txid, mempool_min_fee),
+        Err(BuildCpfpError::NoSpendableOutput(_))
+    );
+}
+
+/// Insert a transaction paying `value` to `address`, its input made unique by `nonce`
+fn pay_invoice(
+    wallet: &mut Wallet,
+    address: &Address,
+    value: u64,
+    nonce: u32,
+    position: ConfirmationTime,
+) -> Txid {
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), nonce),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            script_pubkey: address.script_pubkey(),
+            value: Amount::from_sat(value),
+        }],
+    };
+    let txid = tx.compute_txid();
+    wallet.insert_tx(tx, position).unwrap();
+    txid
 }
 
-#[test]
-fn test_allow_dust_limit() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
-
-    let addr = wallet.next_unused_address(KeychainKind::External);
+fn insert_blocks_to(wallet: &mut Wallet, to: u32) {
+    for height in wallet.latest_checkpoint().height() + 1..=to {
+        wallet
+            .insert_checkpoint(BlockId {
+                height,
+                hash: BlockHash::from_byte_array([height as u8; 32]),
+            })
+            .unwrap();
+    }
+}
 
-    let mut builder = wallet.build_tx();
+fn sorted_txids<const N: usize>(mut txids: [Txid; N]) -> Vec<Txid> {
+    txids.sort();
+    txids.to_vec()
+}
 
-    builder.add_recipient(addr.script_pubkey(), Amount::ZERO);
+#[test]
+fn test_invoice_partial_payments_and_settlement() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    insert_blocks_to(&mut wallet, 10);
+    let invoice =
+        wallet.create_invoice(Amount::from_sat(50_000), BlockTimeOrHeight::Height(100), 2);
+    assert_eq!(invoice.id, 0);
+    assert_eq!(wallet.invoice(0), Some(invoice.clone()));
+    assert_eq!(wallet.invoice(1), None);
+    assert_eq!(wallet.invoice_status(1, 0), None);
 
-    assert_matches!(
-        builder.finish(),
-        Err(CreateTxError::OutputBelowDustLimit(0))
+    // the address of the invoice isn't given out again
+    assert_eq!(
+        invoice.address,
+        wallet.peek_address(KeychainKind::External, 0).address
+    );
+    assert_ne!(
+        wallet.next_unused_address(KeychainKind::External).address,
+        invoice.address
+    );
+    let second = wallet.create_invoice(Amount::from_sat(10_000), BlockTimeOrHeight::Height(100), 0);
+    assert_eq!(second.id, 1);
+    assert_ne!(second.address, invoice.address);
+
+    let status = wallet.invoice_status(0, 0).unwrap();
+    assert_eq!(status.state, InvoiceState::Unpaid);
+    assert_eq!(status.received, Amount::ZERO);
+    assert!(status.txids.is_empty());
+
+    // two partial payments are summed
+    let unconfirmed = ConfirmationTime::Unconfirmed { last_seen: 1 };
+    let first = pay_invoice(&mut wallet, &invoice.address, 20_000, 0, unconfirmed);
+    let status = wallet.invoice_status(0, 0).unwrap();
+    assert_eq!(status.state, InvoiceState::PartiallyPaid);
+    assert_eq!(status.received, Amount::from_sat(20_000));
+    let confirmed_at = |height| ConfirmationTime::Confirmed { height, time: 0 };
+    let second_payment = pay_invoice(&mut wallet, &invoice.address, 35_000, 1, confirmed_at(10));
+    let status = wallet.invoice_status(0, 0).unwrap();
+    assert_eq!(status.state, InvoiceState::PaidUnconfirmed);
+    assert_eq!(status.received, Amount::from_sat(55_000));
+    assert_eq!(status.overpayment, Amount::from_sat(5_000));
+    assert_eq!(status.txids, sorted_txids([first, second_payment]));
+
+    // settled once both payments have 2 confirmations
+    insert_blocks_to(&mut wallet, 12);
+    let first_tx = wallet.get_tx(first).unwrap().tx_node.tx.as_ref().clone();
+    wallet.insert_tx(first_tx, confirmed_at(12)).unwrap();
+    assert_eq!(
+        wallet.invoice_status(0, 0).unwrap().state,
+        InvoiceState::PaidUnconfirmed
+    );
+    insert_blocks_to(&mut wallet, 13);
+    let status = wallet.invoice_status(0, 0).unwrap();
+    assert_eq!(status.state, InvoiceState::Settled);
+    assert_eq!(status.received, Amount::from_sat(55_000));
+    assert_eq!(status.late_received, Amount::ZERO);
+
+    // without confirmations required, an unconfirmed payment settles the invoice
+    pay_invoice(&mut wallet, &second.address, 10_000, 2, unconfirmed);
+    assert_eq!(
+        wallet.invoice_status(1, 0).unwrap().state,
+        InvoiceState::Settled
     );
 
-    let mut builder = wallet.build_tx();
+    assert_eq!(wallet.list_invoices(InvoiceFilter::Open, 0), vec![]);
+    assert_eq!(
+        wallet
+            .list_invoices(InvoiceFilter::State(InvoiceState::Settled), 0)
+            .into_iter()
+            .map(|(invoice, _)| invoice.id)
+            .collect::<Vec<_>>(),
+        vec![0, 1]
+    );
 
-    builder
-        .allow_dust(true)
-        .add_recipient(addr.script_pubkey(), Amount::ZERO);
+    // a settled invoice stays settled once expired, but an unconfirmed payment becomes late
+    insert_blocks_to(&mut wallet, 100);
+    assert_eq!(
+        wallet.invoice_status(0, 0).unwrap().state,
+        InvoiceState::Settled
+    );
+    let status = wallet.invoice_status(1, 0).unwrap();
+    assert_eq!(status.state, InvoiceState::Expired);
+    assert_eq!(status.received, Amount::ZERO);
+    assert_eq!(status.late_received, Amount::from_sat(10_000));
+}
+
+#[test]
+fn test_invoice_expiry() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    insert_blocks_to(&mut wallet, 11);
+    let by_height =
+        wallet.create_invoice(Amount::from_sat(30_000), BlockTimeOrHeight::Height(12), 1);
+    let by_time = wallet.create_invoice(
+        Amount::from_sat(30_000),
+        BlockTimeOrHeight::Time(1_700_000_000),
+        1,
+    );
 
-    assert!(builder.finish().is_ok());
+    // a partial payment before the expiry, then the rest in the block of the expiry
+    let confirmed = |height, time| ConfirmationTime::Confirmed { height, time };
+    let on_time = pay_invoice(
+        &mut wallet,
+        &by_height.address,
+        10_000,
+        0,
+        confirmed(11, 1_699_999_000),
+    );
+    assert_eq!(
+        wallet.invoice_status(by_height.id, 0).unwrap().state,
+        InvoiceState::PartiallyPaid
+    );
+    insert_blocks_to(&mut wallet, 12);
+    let late = pay_invoice(
+        &mut wallet,
+        &by_height.address,
+        20_000,
+        1,
+        confirmed(12, 1_699_999_600),
+    );
+    let status = wallet.invoice_status(by_height.id, 0).unwrap();
+    assert_eq!(status.state, InvoiceState::Expired);
+    assert_eq!(status.received, Amount::from_sat(10_000));
+    assert_eq!(status.txids, vec![on_time]);
+    assert_eq!(status.late_received, Amount::from_sat(20_000));
+    assert_eq!(status.late_txids, vec![late]);
+
+    // the time expiry is checked against `now` and the block time of the payments
+    assert_eq!(
+        wallet
+            .invoice_status(by_time.id, 1_699_999_999)
+            .unwrap()
+            .state,
+        InvoiceState::Unpaid
+    );
+    assert_eq!(
+        wallet
+            .invoice_status(by_time.id, 1_700_000_000)
+            .unwrap()
+            .state,
+        InvoiceState::Expired
+    );
+    assert_eq!(
+        wallet.list_invoices(InvoiceFilter::Open, 1_699_999_999)[0].0,
+        by_time
+    );
+    pay_invoice(
+        &mut wallet,
+        &by_time.address,
+        30_000,
+        2,
+        confirmed(12, 1_699_999_600),
+    );
+    let status = wallet.invoice_status(by_time.id, 1_800_000_000).unwrap();
+    assert_eq!(status.state, InvoiceState::Settled);
+    assert_eq!(status.overpayment, Amount::ZERO);
+    assert_eq!(wallet.list_invoices(InvoiceFilter::All, 0).len(), 2);
 }
 
 #[test]
-fn test_fee_rate_sign_no_grinding_high_r() {
-    // Our goal is to obtain a transaction with a signature with high-R (71 bytes
-    // instead of 70). We then check that our fee rate and fee calculation is
-    // alright.
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External);
-    let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
-    let mut builder = wallet.build_tx();
-    let mut data = PushBytesBuf::try_from(vec![0]).unwrap();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_rate(fee_rate)
-        .add_data(&data);
-    let mut psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
-    let (op_return_vout, _) = psbt
-        .unsigned_tx
-        .output
-        .iter()
-        .enumerate()
-        .find(|(_n, i)| i.script_pubkey.is_op_return())
-        .unwrap();
+fn test_invoices_persisted() {
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new(descriptor, change_descriptor, Network::Regtest).unwrap();
+    let invoice = wallet.create_invoice(
+        Amount::from_sat(30_000),
+        BlockTimeOrHeight::Time(1_700_000_000),
+        3,
+    );
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let conn = Connection::open(temp_dir.path().join("store.sqlite")).unwrap();
+    let mut db = SqlitePersister(bdk_sqlite::Store::new(conn).unwrap());
+    assert!(wallet.persist(&mut db).unwrap());
+
+    let changeset = WalletPersister::initialize(&mut db).unwrap().unwrap();
+    let mut loaded = Wallet::load_from_changeset(changeset.clone()).unwrap();
+    assert_eq!(loaded.invoice(invoice.id), Some(invoice.clone()));
+    assert_eq!(loaded.snapshot().invoices, changeset.invoices);
+    // the ids and the addresses carry on after the loaded invoices
+    let next = loaded.create_invoice(Amount::from_sat(1_000), BlockTimeOrHeight::Height(10), 0);
+    assert_eq!(next.id, 1);
+    assert_ne!(next.address, invoice.address);
+    assert_ne!(
+        loaded.next_unused_address(KeychainKind::External).address,
+        invoice.address
+    );
 
-    let mut sig_len: usize = 0;
-    // We try to sign many different times until we find a longer signature (71 bytes)
-    while sig_len < 71 {
-        // Changing the OP_RETURN data will make the signature change (but not the fee, until
-        // data[0] is small enough)
-        data.as_mut_bytes()[0] += 1;
-        psbt.unsigned_tx.output[op_return_vout].script_pubkey = ScriptBuf::new_op_return(&data);
-        // Clearing the previous signature
-        psbt.inputs[0].partial_sigs.clear();
-        // Signing
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    try_finalize: false,
-                    allow_grinding: false,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-        // We only have one key in the partial_sigs map, this is a trick to retrieve it
-        let key = psbt.inputs[0].partial_sigs.keys().next().unwrap();
-        sig_len = psbt.inputs[0].partial_sigs[key]
-            .signature
-            .serialize_der()
-            .len();
-    }
-    // Actually finalizing the transaction...
-    wallet
-        .sign(
-            &mut psbt,
-            SignOptions {
-                allow_grinding: false,
-                ..Default::default()
-            },
-        )
-        .unwrap();
-    // ...and checking that everything is fine
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate);
+    // an invoice created since the staged changes were taken is discarded with its address
+    let mut kv = KvPersister::new(MemoryKvStore::default());
+    WalletPersister::persist(&mut kv, &loaded.snapshot()).unwrap();
+    loaded.take_staged();
+    let discarded =
+        loaded.create_invoice(Amount::from_sat(2_000), BlockTimeOrHeight::Height(10), 0);
+    assert!(loaded.discard_staged().invoices.is_empty());
+    assert_eq!(loaded.invoice(discarded.id), None);
+    let changeset = WalletPersister::initialize(&mut kv).unwrap().unwrap();
+    let loaded = Wallet::load_from_changeset(changeset).unwrap();
+    assert_eq!(
+        loaded
+            .list_invoices(InvoiceFilter::All, 0)
+            .into_iter()
+            .map(|(invoice, _)| invoice)
+            .collect::<Vec<_>>(),
+        vec![invoice, next]
+    );
 }
 
-#[test]
-fn test_fee_rate_sign_grinding_low_r() {
-    // Our goal is to obtain a transaction with a signature with low-R (70 bytes)
-    // by setting the `allow_grinding` signing option as true.
-    // We then check that our fee rate and fee calculation is alright and that our
-    // signature is 70 bytes.
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+/// Build, sign and extract a transaction spending all the coins of the funded `wallet`, choosing
+/// the items at `policy_path` of the root of its policy if the wallet has several spending paths
+fn signed_drain_tx(wallet: &mut Wallet, policy_path: Option<Vec<usize>>) -> Transaction {
     let addr = wallet.next_unused_address(KeychainKind::External);
-    let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
+    let root = wallet.policies(KeychainKind::External).unwrap().unwrap();
     let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_rate(fee_rate);
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    if let Some(path) = policy_path {
+        builder.policy_path([(root.id, path)].into(), KeychainKind::External);
+    }
     let mut psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
-
-    wallet
+    let finalized = wallet
         .sign(
             &mut psbt,
             SignOptions {
-                try_finalize: false,
-                allow_grinding: true,
+                assume_height: Some(100_000),
                 ..Default::default()
             },
         )
         .unwrap();
-
-    let key = psbt.inputs[0].partial_sigs.keys().next().unwrap();
-    let sig_len = psbt.inputs[0].partial_sigs[key]
-        .signature
-        .serialize_der()
-        .len();
-    assert_eq!(sig_len, 70);
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate);
+    assert!(finalized);
+    psbt.extract_tx().unwrap()
 }
 
 #[test]
-fn test_taproot_load_descriptor_duplicated_keys() {
-    // Added after issue https://github.com/bitcoindevkit/bdk/issues/760
-    //
-    // Having the same key in multiple taproot leaves is safe and should be accepted by BDK
-
-    let (wallet, _) = get_funded_wallet(get_test_tr_dup_keys());
-    let addr = wallet.peek_address(KeychainKind::External, 0);
+fn test_input_weights_breakdown() {
+    let parts = |weights: &InputWeightBreakdown| {
+        weights.signatures
+            + weights.public_keys
+            + weights.scripts
+            + weights.control_blocks
+            + weights.other
+            + weights.overhead
+    };
 
+    // wpkh: a signature and a public key
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let tx = signed_drain_tx(&mut wallet, None);
+    let weights = input_weights(&tx);
+    assert_eq!(weights.len(), 1);
+    let wpkh = weights[0];
+    assert_eq!(wpkh.weight, tx.input[0].segwit_weight());
+    assert_eq!(parts(&wpkh), wpkh.weight);
+    assert!((71..=73).contains(&wpkh.signatures.to_wu()));
+    assert_eq!(wpkh.public_keys, Weight::from_wu(33));
+    assert_eq!(wpkh.scripts, Weight::ZERO);
+    // outpoint, empty script sig, sequence, element count and length prefixes
+    assert_eq!(wpkh.overhead, Weight::from_wu(41 * 4 + 3));
+    let analyzed = wallet.input_weights(&tx);
+    assert_eq!(analyzed[0].breakdown, wpkh);
+    assert_eq!(analyzed[0].keychain, Some(KeychainKind::External));
+    assert_eq!(analyzed[0].spend_path, Some(SpendPath::KeySpend));
+
+    // tr key spend: a 64 bytes signature
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let tx = signed_drain_tx(&mut wallet, None);
+    let analyzed = wallet.input_weights(&tx);
+    let key_spend = analyzed[0].breakdown;
+    assert_eq!(key_spend.signatures, Weight::from_wu(64));
+    assert_eq!(key_spend.public_keys + key_spend.scripts, Weight::ZERO);
+    assert_eq!(key_spend.satisfaction_weight, Weight::from_wu(65));
+    assert_eq!(analyzed[0].spend_path, Some(SpendPath::KeySpend));
+    assert_eq!(input_weights(&tx)[0], key_spend);
+
+    // tr script spend: a signature, the `pk()` leaf and a control block with one sibling
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+    let tx = signed_drain_tx(&mut wallet, None);
+    let analyzed = wallet.input_weights(&tx);
+    let script_spend = analyzed[0].breakdown;
+    assert_eq!(script_spend.signatures, Weight::from_wu(64));
+    assert_eq!(script_spend.scripts, Weight::from_wu(34));
+    assert_eq!(script_spend.control_blocks, Weight::from_wu(65));
+    assert_eq!(parts(&script_spend), script_spend.weight);
+    let leaf_script = Script::from_bytes(&tx.input[0].witness[1]);
     assert_eq!(
-        addr.to_string(),
-        "bcrt1pvysh4nmh85ysrkpwtrr8q8gdadhgdejpy6f9v424a8v9htjxjhyqw9c5s5"
+        analyzed[0].spend_path,
+        Some(SpendPath::ScriptSpend {
+            leaf_hash: Some(TapLeafHash::from_script(
+                leaf_script,
+                LeafVersion::TapScript
+            ))
+        })
     );
-}
-
-#[test]
-/// The wallet should re-use previously allocated change addresses when the tx using them is cancelled
-fn test_tx_cancellation() {
-    macro_rules! new_tx {
-        ($wallet:expr) => {{
-            let addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
-                .unwrap()
-                .assume_checked();
-            let mut builder = $wallet.build_tx();
-            builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
+    assert_eq!(input_weights(&tx)[0], script_spend);
 
-            let psbt = builder.finish().unwrap();
+    // the report is serializable
+    let json = serde_json::to_string(&analyzed).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Vec<WalletInputWeight>>(&json).unwrap(),
+        analyzed
+    );
 
-            psbt
-        }};
+    // an input the wallet doesn't know is only split
+    let mut foreign = tx.clone();
+    foreign.input[0].previous_output = OutPoint::new(Txid::all_zeros(), 0);
+    let analyzed = wallet.input_weights(&foreign);
+    assert_eq!(analyzed[0].breakdown, script_spend);
+    assert_eq!(analyzed[0].keychain, None);
+    assert_eq!(analyzed[0].spend_path, None);
+    assert_eq!(analyzed[0].estimated_satisfaction_weight, None);
+}
+
+#[test]
+fn test_satisfaction_weight_estimates() {
+    // the descriptors of the tests, and a legacy and a nested segwit ones
+    let descriptors = [
+        (get_test_wpkh(), None),
+        (get_test_single_sig_csv(), None),
+        (get_test_a_or_b_plus_csv(), Some(vec![0])),
+        (get_test_a_or_b_plus_csv(), Some(vec![1])),
+        (get_test_single_sig_cltv(), None),
+        (get_test_tr_single_sig(), None),
+        (get_test_tr_with_taptree(), None),
+        (get_test_tr_with_taptree_both_priv(), Some(vec![0])),
+        (get_test_tr_with_taptree_both_priv(), Some(vec![1])),
+        (get_test_tr_repeated_key(), Some(vec![0])),
+        (get_test_tr_repeated_key(), Some(vec![1])),
+        (get_test_tr_single_sig_xprv(), None),
+        (get_test_tr_with_taptree_xprv(), None),
+        (
+            "pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)",
+            None,
+        ),
+        (
+            "sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))",
+            None,
+        ),
+        ("sh(wsh(multi(1,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))", None),
+    ];
+    for (descriptor, policy_path) in descriptors {
+        let (mut wallet, _) = get_funded_wallet(descriptor);
+        // past the relative timelocks of the descriptors
+        insert_blocks_to(&mut wallet, 3_000);
+        let tx = signed_drain_tx(&mut wallet, policy_path);
+        for input in wallet.input_weights(&tx) {
+            assert_eq!(
+                input.keychain,
+                Some(KeychainKind::External),
+                "{}",
+                descriptor
+            );
+            let estimate = input.estimated_satisfaction_weight.unwrap();
+            assert!(
+                input.breakdown.satisfaction_weight <= estimate,
+                "{}: {} > {}",
+                descriptor,
+                input.breakdown.satisfaction_weight,
+                estimate
+            );
+        }
     }
-
-    let (mut wallet, _) =
-        get_funded_wallet_with_change(get_test_wpkh(), get_test_tr_single_sig_xprv());
-
-    let psbt1 = new_tx!(wallet);
-    let change_derivation_1 = psbt1
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
-        .unwrap();
-    assert_eq!(change_derivation_1, (KeychainKind::Internal, 0));
-
-    let psbt2 = new_tx!(wallet);
-
-    let change_derivation_2 = psbt2
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
-        .unwrap();
-    assert_eq!(change_derivation_2, (KeychainKind::Internal, 1));
-
-    wallet.cancel_tx(&psbt1.extract_tx().expect("failed to extract tx"));
-
-    let psbt3 = new_tx!(wallet);
-    let change_derivation_3 = psbt3
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
-        .unwrap();
-    assert_eq!(change_derivation_3, (KeychainKind::Internal, 0));
-
-    let psbt3 = new_tx!(wallet);
-    let change_derivation_3 = psbt3
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
-        .unwrap();
-    assert_eq!(change_derivation_3, (KeychainKind::Internal, 2));
-
-    wallet.cancel_tx(&psbt3.extract_tx().expect("failed to extract tx"));
-
-    let psbt3 = new_tx!(wallet);
-    let change_derivation_4 = psbt3
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
-        .unwrap();
-    assert_eq!(change_derivation_4, (KeychainKind::Internal, 2));
-}
-
-#[test]
-fn test_thread_safety() {
-    fn thread_safe<T: Send + Sync>() {}
-    thread_safe::<Wallet>(); // compiles only if true
 }
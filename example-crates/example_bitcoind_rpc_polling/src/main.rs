@@ -9,10 +9,10 @@ use std::{
 
 use bdk_bitcoind_rpc::{
     bitcoincore_rpc::{Auth, Client, RpcApi},
-    Emitter,
+    Emitter, MempoolEvent,
 };
 use bdk_chain::{
-    bitcoin::{constants::genesis_block, Block, Transaction},
+    bitcoin::{constants::genesis_block, Block},
     indexed_tx_graph, keychain,
     local_chain::{self, LocalChain},
     Append, ConfirmationTimeHeightAnchor, IndexedTxGraph,
@@ -43,7 +43,7 @@ type ChangeSet = (
 #[derive(Debug)]
 enum Emission {
     Block(bdk_bitcoind_rpc::BlockEvent<Block>),
-    Mempool(Vec<(Transaction, u64)>),
+    Mempool(MempoolEvent),
     Tip(u32),
 }
 
@@ -228,10 +228,17 @@ fn main() -> anyhow::Result<()> {
                 }
             }
 
-            let mempool_txs = emitter.mempool()?;
-            let graph_changeset = graph.lock().unwrap().batch_insert_relevant_unconfirmed(
-                mempool_txs.iter().map(|(tx, time)| (tx, *time)),
-            );
+            let mempool_event = emitter.mempool()?;
+            let graph_changeset = {
+                let mut graph = graph.lock().unwrap();
+                let mut graph_changeset = graph.batch_insert_relevant_unconfirmed(
+                    mempool_event.new_txs.iter().map(|(tx, time)| (tx, *time)),
+                );
+                for (txid, evicted_at) in mempool_event.evicted_ats() {
+                    graph_changeset.append(graph.insert_evicted_at(txid, evicted_at));
+                }
+                graph_changeset
+            };
             {
                 let db = &mut *db.lock().unwrap();
                 db_stage.append((local_chain::ChangeSet::default(), graph_changeset));
@@ -309,10 +316,13 @@ fn main() -> anyhow::Result<()> {
                             graph.apply_block_relevant(&block_emission.block, height);
                         (chain_changeset, graph_changeset)
                     }
-                    Emission::Mempool(mempool_txs) => {
-                        let graph_changeset = graph.batch_insert_relevant_unconfirmed(
-                            mempool_txs.iter().map(|(tx, time)| (tx, *time)),
+                    Emission::Mempool(mempool_event) => {
+                        let mut graph_changeset = graph.batch_insert_relevant_unconfirmed(
+                            mempool_event.new_txs.iter().map(|(tx, time)| (tx, *time)),
                         );
+                        for (txid, evicted_at) in mempool_event.evicted_ats() {
+                            graph_changeset.append(graph.insert_evicted_at(txid, evicted_at));
+                        }
                         (local_chain::ChangeSet::default(), graph_changeset)
                     }
                     Emission::Tip(h) => {
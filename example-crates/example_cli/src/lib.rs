@@ -291,7 +291,8 @@ where
             .find(|(k, _)| *k == &internal_keychain)
             .expect("must exist")
             .1
-            .dust_value(),
+            .dust_value()
+            .to_sat(),
         ..CoinSelectorOpt::fund_outputs(
             &outputs,
             &change_output,
@@ -98,6 +98,19 @@ pub struct ScanOptions {
     /// Max number of concurrent esplora server requests.
     #[clap(long, default_value = "1")]
     pub parallel_requests: usize,
+    /// Fetch and check the header of every block confirming a transaction of the update.
+    #[clap(long)]
+    pub verify_anchors: bool,
+}
+
+impl ScanOptions {
+    fn to_esplora(&self) -> bdk_esplora::ScanOptions {
+        bdk_esplora::ScanOptions {
+            parallel_requests: self.parallel_requests,
+            verify_anchors: self.verify_anchors,
+            ..Default::default()
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -190,7 +203,7 @@ fn main() -> anyhow::Result<()> {
             // represents the last active spk derivation indices of keychains
             // (`keychain_indices_update`).
             let mut update = client
-                .full_scan(request, *stop_gap, scan_options.parallel_requests)
+                .full_scan(request, *stop_gap, scan_options.to_esplora())
                 .context("scanning for transactions")?;
 
             // We want to keep track of the latest time a transaction was seen unconfirmed.
@@ -344,7 +357,7 @@ fn main() -> anyhow::Result<()> {
                         eprintln!(" [ {:>6.2}% ]", (visited * 100) as f32 / total_ops as f32)
                     }
                 });
-            let mut update = client.sync(request, scan_options.parallel_requests)?;
+            let mut update = client.sync(request, scan_options.to_esplora())?;
 
             // Update last seen unconfirmed
             let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
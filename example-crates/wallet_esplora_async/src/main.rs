@@ -1,6 +1,6 @@
 use std::{collections::BTreeSet, io::Write, str::FromStr};
 
-use bdk_esplora::{esplora_client, EsploraAsyncExt};
+use bdk_esplora::{esplora_client, EsploraAsyncExt, ScanOptions};
 use bdk_wallet::{
     bitcoin::{Address, Amount, Network, Script},
     KeychainKind, SignOptions, Wallet,
@@ -73,7 +73,14 @@ async fn main() -> Result<(), anyhow::Error> {
         );
 
     let mut update = client
-        .full_scan(request, STOP_GAP, PARALLEL_REQUESTS)
+        .full_scan(
+            request,
+            STOP_GAP,
+            ScanOptions {
+                parallel_requests: PARALLEL_REQUESTS,
+                ..Default::default()
+            },
+        )
         .await?;
     let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
     let _ = update.graph_update.update_last_seen_unconfirmed(now);
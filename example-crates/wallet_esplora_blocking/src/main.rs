@@ -5,7 +5,7 @@ const PARALLEL_REQUESTS: usize = 1;
 
 use std::{collections::BTreeSet, io::Write, str::FromStr};
 
-use bdk_esplora::{esplora_client, EsploraExt};
+use bdk_esplora::{esplora_client, EsploraExt, ScanOptions};
 use bdk_file_store::Store;
 use bdk_wallet::{
     bitcoin::{Address, Amount, Network},
@@ -51,7 +51,14 @@ fn main() -> Result<(), anyhow::Error> {
         }
     });
 
-    let mut update = client.full_scan(request, STOP_GAP, PARALLEL_REQUESTS)?;
+    let mut update = client.full_scan(
+        request,
+        STOP_GAP,
+        ScanOptions {
+            parallel_requests: PARALLEL_REQUESTS,
+            ..Default::default()
+        },
+    )?;
     let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
     let _ = update.graph_update.update_last_seen_unconfirmed(now);
 
@@ -1,11 +1,12 @@
 use bdk_bitcoind_rpc::{
+    birthday_height,
     bitcoincore_rpc::{Auth, Client, RpcApi},
-    Emitter,
+    Emitter, MempoolEvent,
 };
 use bdk_file_store::Store;
 use bdk_wallet::{
-    bitcoin::{Block, Network, Transaction},
-    wallet::Wallet,
+    bitcoin::{Block, Network},
+    wallet::{BlockTimeOrHeight, Wallet},
 };
 use clap::{self, Parser};
 use std::{path::PathBuf, sync::mpsc::sync_channel, thread::spawn, time::Instant};
@@ -26,7 +27,7 @@ pub struct Args {
     /// Wallet change descriptor
     #[clap(env = "CHANGE_DESCRIPTOR")]
     pub change_descriptor: String,
-    /// Earliest block height to start sync from
+    /// Earliest block height to start sync from, kept as the wallet's birthday
     #[clap(env = "START_HEIGHT", long, default_value = "481824")]
     pub start_height: u32,
     /// Bitcoin network to connect to
@@ -73,7 +74,7 @@ impl Args {
 enum Emission {
     SigTerm,
     Block(bdk_bitcoind_rpc::BlockEvent<Block>),
-    Mempool(Vec<(Transaction, u64)>),
+    Mempool(MempoolEvent),
 }
 
 fn main() -> anyhow::Result<()> {
@@ -122,9 +123,20 @@ fn main() -> anyhow::Result<()> {
             .expect("failed to send sigterm")
     });
 
+    // the wallet's birthday is the start height it was first synced with
+    let birthday = match wallet.birthday() {
+        Some(birthday) => birthday,
+        None => {
+            let birthday = BlockTimeOrHeight::Height(args.start_height);
+            wallet.set_birthday(birthday);
+            birthday
+        }
+    };
+    let start_height = birthday_height(&rpc_client, birthday)?;
+
     let emitter_tip = wallet_tip.clone();
     spawn(move || -> Result<(), anyhow::Error> {
-        let mut emitter = Emitter::new(&rpc_client, emitter_tip, args.start_height);
+        let mut emitter = Emitter::new(&rpc_client, emitter_tip, start_height);
         while let Some(emission) = emitter.next_block()? {
             sender.send(Emission::Block(emission))?;
         }
@@ -157,7 +169,12 @@ fn main() -> anyhow::Result<()> {
             }
             Emission::Mempool(mempool_emission) => {
                 let start_apply_mempool = Instant::now();
-                wallet.apply_unconfirmed_txs(mempool_emission.iter().map(|(tx, time)| (tx, *time)));
+                wallet.apply_unconfirmed_txs(
+                    mempool_emission
+                        .new_txs
+                        .iter()
+                        .map(|(tx, time)| (tx, *time)),
+                );
                 if let Some(changeset) = wallet.take_staged() {
                     db.append_changeset(&changeset)?;
                 }
@@ -14,6 +14,10 @@ pub struct WeightedValue {
     pub input_count: usize,
     /// Whether this [`WeightedValue`] contains at least one segwit spend.
     pub is_segwit: bool,
+    /// Height of the block confirming the UTXO(s), `None` if unconfirmed or unknown.
+    ///
+    /// Only used to order the candidates, see [`SelectionPreset`].
+    pub confirmation_height: Option<u32>,
 }
 
 impl WeightedValue {
@@ -28,9 +32,16 @@ impl WeightedValue {
             weight,
             input_count: 1,
             is_segwit,
+            confirmation_height: None,
         }
     }
 
+    /// Set the height of the block confirming this input candidate.
+    pub fn with_confirmation_height(mut self, height: u32) -> Self {
+        self.confirmation_height = Some(height);
+        self
+    }
+
     /// Effective value of this input candidate: `actual_value - input_weight * feerate (sats/wu)`.
     pub fn effective_value(&self, effective_feerate: f32) -> i64 {
         // We prefer undershooting the candidate's effective value (so we over-estimate the fee of a
@@ -65,6 +76,9 @@ pub struct CoinSelectorOpt {
 
     /// Minimum value allowed for a drain (change) output.
     pub min_drain_value: u64,
+
+    /// The maximum weight of the transaction, `None` if unlimited.
+    pub max_weight: Option<u32>,
 }
 
 impl CoinSelectorOpt {
@@ -86,6 +100,7 @@ impl CoinSelectorOpt {
             drain_weight,
             spend_drain_weight,
             min_drain_value,
+            max_weight: None,
         }
     }
 
@@ -301,6 +316,20 @@ impl<'a> CoinSelector<'a> {
             (weight_without_drain as f32 * self.opts.target_feerate).ceil() as u64;
         let fee_with_drain = (weight_with_drain as f32 * self.opts.target_feerate).ceil() as u64;
 
+        if let Some(max_weight) = self.opts.max_weight {
+            if weight_without_drain > max_weight {
+                return Err(SelectionError {
+                    selected: self.selected_absolute_value(),
+                    missing: 0,
+                    constraint: SelectionConstraint::MaxWeight,
+                });
+            }
+        }
+        let drain_fits = self
+            .opts
+            .max_weight
+            .map_or(true, |max_weight| weight_with_drain <= max_weight);
+
         let inputs_minus_outputs = {
             let target_value = self.opts.target_value.unwrap_or(0);
             let selected = self.selected_absolute_value();
@@ -391,7 +420,8 @@ impl<'a> CoinSelector<'a> {
         }
 
         // with drain
-        if fee_with_drain >= self.opts.min_absolute_fee
+        if drain_fits
+            && fee_with_drain >= self.opts.min_absolute_fee
             && inputs_minus_outputs >= fee_with_drain + self.opts.min_drain_value
         {
             excess_strategies.insert(
@@ -454,6 +484,8 @@ pub enum SelectionConstraint {
     MinAbsoluteFee,
     /// Min drain value is not met
     MinDrainValue,
+    /// The selection is heavier than the max weight
+    MaxWeight,
 }
 
 impl core::fmt::Display for SelectionConstraint {
@@ -463,6 +495,7 @@ impl core::fmt::Display for SelectionConstraint {
             SelectionConstraint::TargetFee => core::write!(f, "target_fee"),
             SelectionConstraint::MinAbsoluteFee => core::write!(f, "min_absolute_fee"),
             SelectionConstraint::MinDrainValue => core::write!(f, "min_drain_value"),
+            SelectionConstraint::MaxWeight => core::write!(f, "max_weight"),
         }
     }
 }
@@ -541,6 +574,7 @@ mod test {
                 weight: 100,
                 input_count: 1,
                 is_segwit: false,
+                confirmation_height: None,
             })
             .collect::<super::Vec<_>>();
 
@@ -554,6 +588,7 @@ mod test {
             drain_weight: 10,
             spend_drain_weight: 10,
             min_drain_value: 10,
+            max_weight: None,
         };
 
         for (index, v) in candidates.iter().enumerate() {
@@ -581,6 +616,7 @@ mod test {
                 weight: 166,
                 input_count: 1,
                 is_segwit: false,
+                confirmation_height: None,
             })
             .collect::<super::Vec<_>>();
 
@@ -594,6 +630,7 @@ mod test {
             drain_weight: 100,
             spend_drain_weight: 66,
             min_drain_value: 1000,
+            max_weight: None,
         };
 
         let selection = CoinSelector::new(&candidates, &opts)
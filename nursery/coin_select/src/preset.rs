@@ -0,0 +1,197 @@
+use super::*;
+use core::cmp::{Ordering, Reverse};
+
+/// A fixed order in which [`CoinSelector::select_by_preset`] consumes the candidates.
+///
+/// Ties are broken by the index of the candidates, so candidates sorted by outpoint are consumed
+/// in a deterministic order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelectionPreset {
+    /// Confirmed candidates by confirmation height ascending, then the unconfirmed ones.
+    OldestFirst,
+    /// Unconfirmed candidates, then the confirmed ones by confirmation height descending.
+    NewestFirst,
+    /// Candidates by value descending.
+    LargestFirst,
+}
+
+impl SelectionPreset {
+    /// The order in which the preset consumes `a` and `b`.
+    pub fn cmp(&self, a: &WeightedValue, b: &WeightedValue) -> Ordering {
+        match self {
+            SelectionPreset::OldestFirst => {
+                let key =
+                    |wv: &WeightedValue| (wv.confirmation_height.is_none(), wv.confirmation_height);
+                key(a).cmp(&key(b))
+            }
+            SelectionPreset::NewestFirst => {
+                let key = |wv: &WeightedValue| {
+                    (
+                        wv.confirmation_height.is_some(),
+                        Reverse(wv.confirmation_height),
+                    )
+                };
+                key(a).cmp(&key(b))
+            }
+            SelectionPreset::LargestFirst => b.value.cmp(&a.value),
+        }
+    }
+}
+
+impl<'a> CoinSelector<'a> {
+    /// Select unselected candidates in the order of `preset` until the selection is finished.
+    ///
+    /// With a [`CoinSelectorOpt::max_weight`], the candidates which would make the selection
+    /// heavier than the max weight are skipped.
+    pub fn select_by_preset(
+        &mut self,
+        preset: SelectionPreset,
+    ) -> Result<Selection, SelectionError> {
+        let mut order = self.unselected_indexes().collect::<Vec<_>>();
+        // stable, so that ties are broken by index
+        order.sort_by(|&a, &b| preset.cmp(&self.candidates[a], &self.candidates[b]));
+
+        let mut selection = self.finish();
+        for index in order {
+            if selection.is_ok() {
+                break;
+            }
+            self.select(index);
+            if let Some(max_weight) = self.opts.max_weight {
+                if self.current_weight() > max_weight {
+                    self.deselect(index);
+                    continue;
+                }
+            }
+            selection = self.finish();
+        }
+        selection
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn candidate(value: u64, weight: u32, height: Option<u32>) -> WeightedValue {
+        WeightedValue {
+            value,
+            weight,
+            input_count: 1,
+            is_segwit: true,
+            confirmation_height: height,
+        }
+    }
+
+    fn opts(target_value: u64) -> CoinSelectorOpt {
+        CoinSelectorOpt {
+            target_value: Some(target_value),
+            max_extra_target: 0,
+            target_feerate: 0.25,
+            long_term_feerate: None,
+            min_absolute_fee: 0,
+            base_weight: 200,
+            drain_weight: 124,
+            spend_drain_weight: 272,
+            min_drain_value: 500,
+            max_weight: None,
+        }
+    }
+
+    fn selected(selection: &Selection) -> Vec<usize> {
+        selection.selected.iter().copied().collect()
+    }
+
+    #[test]
+    fn presets_order_candidates() {
+        let candidates = vec![
+            candidate(10_000, 272, Some(30)),
+            candidate(30_000, 272, None),
+            candidate(20_000, 272, Some(10)),
+            candidate(40_000, 272, Some(20)),
+        ];
+        let opts = opts(15_000);
+
+        let selection = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::OldestFirst)
+            .unwrap();
+        assert_eq!(selected(&selection), [2]);
+
+        let selection = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::NewestFirst)
+            .unwrap();
+        assert_eq!(selected(&selection), [1]);
+
+        let opts = self::opts(55_000);
+        let selection = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::LargestFirst)
+            .unwrap();
+        assert_eq!(selected(&selection), [1, 3]);
+
+        // the selection respects the feerate target
+        let (_, strategy) = selection.best_strategy();
+        assert!(strategy.feerate() >= opts.target_feerate);
+    }
+
+    #[test]
+    fn ties_are_broken_by_index() {
+        let candidates = (0..6)
+            .map(|i| candidate(10_000, 272, Some(if i % 2 == 0 { 5 } else { 7 })))
+            .collect::<Vec<_>>();
+        let opts = opts(15_000);
+
+        let selection = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::OldestFirst)
+            .unwrap();
+        assert_eq!(selected(&selection), [0, 2]);
+
+        let selection = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::NewestFirst)
+            .unwrap();
+        assert_eq!(selected(&selection), [1, 3]);
+
+        let selection = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::LargestFirst)
+            .unwrap();
+        assert_eq!(selected(&selection), [0, 1]);
+    }
+
+    #[test]
+    fn preset_respects_max_weight() {
+        let candidates = vec![
+            // the oldest candidate is too heavy to fit
+            candidate(50_000, 2_000, Some(1)),
+            candidate(10_000, 272, Some(2)),
+            candidate(10_000, 272, Some(3)),
+            candidate(10_000, 272, Some(4)),
+        ];
+        let mut opts = opts(15_000);
+        opts.max_weight = Some(1_200);
+
+        let selection = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::OldestFirst)
+            .unwrap();
+        assert_eq!(selected(&selection), [1, 2]);
+        let (_, strategy) = selection.best_strategy();
+        assert!(strategy.weight <= 1_200);
+
+        // no change output when it doesn't fit
+        opts.max_weight = Some(200 + 2 * 272 + 2 + 100);
+        let selection = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::OldestFirst)
+            .unwrap();
+        assert_eq!(selected(&selection), [1, 2]);
+        assert!(selection
+            .excess_strategies
+            .values()
+            .all(|strategy| strategy.drain_value.is_none()));
+
+        // not enough value fits
+        opts.max_weight = Some(700);
+        let err = CoinSelector::new(&candidates, &opts)
+            .select_by_preset(SelectionPreset::OldestFirst)
+            .unwrap_err();
+        assert!(err.to_string().contains("unsatisfied_constraint=TargetFee"));
+    }
+}
@@ -35,6 +35,7 @@ pub(crate) fn varint_len(v: usize) -> usize {
 }
 
 mod plan_impls;
+pub mod plan_vectors;
 mod requirements;
 mod template;
 pub use requirements::*;
@@ -55,6 +56,10 @@ enum Target {
     Legacy,
     Segwitv0 {
         script_code: ScriptBuf,
+        /// The witness script of a wsh descriptor, pushed last on the witness
+        witness_script: Option<ScriptBuf>,
+        /// The redeem script of a sh-wrapped descriptor, pushed in the script sig
+        redeem_script: Option<ScriptBuf>,
     },
     Segwitv1 {
         tr: Tr<DefiniteDescriptorKey>,
@@ -104,7 +109,7 @@ where
 {
     /// The expected satisfaction weight for the plan if it is completed.
     pub fn expected_weight(&self) -> usize {
-        let script_sig_size = match self.target {
+        let script_sig_size = match &self.target {
             Target::Legacy => unimplemented!(), // self
             // .template
             // .iter()
@@ -113,14 +118,22 @@ where
             //     size + push_opcode_size(size)
             // })
             // .sum()
+            Target::Segwitv0 {
+                redeem_script: Some(redeem_script),
+                ..
+            } => {
+                let script_sig_len = redeem_script_sig(redeem_script).len();
+                varint_len(script_sig_len) + script_sig_len
+            }
             Target::Segwitv0 { .. } | Target::Segwitv1 { .. } => 1,
         };
         let witness_elem_sizes: Option<Vec<usize>> = match &self.target {
             Target::Legacy => None,
-            Target::Segwitv0 { .. } => Some(
+            Target::Segwitv0 { witness_script, .. } => Some(
                 self.template
                     .iter()
                     .map(|step| step.expected_size())
+                    .chain(witness_script.as_ref().map(|script| script.len()))
                     .collect(),
             ),
             Target::Segwitv1 { tr, tr_plan } => {
@@ -176,13 +189,19 @@ where
                 TemplateItem::Sign(key) => {
                     !auth_data.schnorr_sigs.contains_key(&key.descriptor_key)
                 }
+                TemplateItem::SignEcdsa(key) => {
+                    !auth_data.ecdsa_sigs.contains_key(&key.descriptor_key)
+                }
                 TemplateItem::Hash160(image) => !auth_data.hash160_preimages.contains_key(image),
                 TemplateItem::Hash256(image) => !auth_data.hash256_preimages.contains_key(image),
                 TemplateItem::Sha256(image) => !auth_data.sha256_preimages.contains_key(image),
                 TemplateItem::Ripemd160(image) => {
                     !auth_data.ripemd160_preimages.contains_key(image)
                 }
-                TemplateItem::Pk { .. } | TemplateItem::One | TemplateItem::Zero => false,
+                TemplateItem::Pk { .. }
+                | TemplateItem::PkEcdsa { .. }
+                | TemplateItem::One
+                | TemplateItem::Zero => false,
             })
             .collect::<Vec<_>>();
 
@@ -193,7 +212,19 @@ where
                 .flat_map(|step| step.to_witness_stack(&auth_data))
                 .collect::<Vec<_>>();
             match &self.target {
-                Target::Segwitv0 { .. } => todo!(),
+                Target::Segwitv0 {
+                    witness_script,
+                    redeem_script,
+                    ..
+                } => {
+                    if let Some(witness_script) = witness_script {
+                        witness.push(witness_script.clone().into_bytes());
+                    }
+                    PlanState::Complete {
+                        final_script_sig: redeem_script.as_ref().map(redeem_script_sig),
+                        final_script_witness: Some(Witness::from(witness)),
+                    }
+                }
                 Target::Legacy => todo!(),
                 Target::Segwitv1 {
                     tr_plan: TrSpend::KeySpend,
@@ -230,8 +261,11 @@ where
                 Target::Legacy => {
                     todo!()
                 }
-                Target::Segwitv0 { .. } => {
-                    todo!()
+                Target::Segwitv0 { script_code, .. } => {
+                    requirements.signatures = RequiredSignatures::Segwitv0 {
+                        script_code: script_code.clone(),
+                        keys: vec![],
+                    };
                 }
                 Target::Segwitv1 { tr, tr_plan } => {
                     let spend_info = tr.spend_info();
@@ -261,7 +295,7 @@ where
 
             let required_signatures = match requirements.signatures {
                 RequiredSignatures::Legacy { .. } => todo!(),
-                RequiredSignatures::Segwitv0 { .. } => todo!(),
+                RequiredSignatures::Segwitv0 { ref mut keys, .. } => keys,
                 RequiredSignatures::TapKey { .. } => return PlanState::Incomplete(requirements),
                 RequiredSignatures::TapScript {
                     plan_keys: ref mut keys,
@@ -271,7 +305,7 @@ where
 
             for step in unsatisfied_items {
                 match step {
-                    TemplateItem::Sign(plan_key) => {
+                    TemplateItem::Sign(plan_key) | TemplateItem::SignEcdsa(plan_key) => {
                         required_signatures.push(plan_key.clone());
                     }
                     TemplateItem::Hash160(image) => {
@@ -286,8 +320,10 @@ where
                     TemplateItem::Ripemd160(image) => {
                         requirements.ripemd160_images.insert(image.clone());
                     }
-                    TemplateItem::Pk { .. } | TemplateItem::One | TemplateItem::Zero => { /* no requirements */
-                    }
+                    TemplateItem::Pk { .. }
+                    | TemplateItem::PkEcdsa { .. }
+                    | TemplateItem::One
+                    | TemplateItem::Zero => { /* no requirements */ }
                 }
             }
 
@@ -324,6 +360,15 @@ where
     }
 }
 
+/// The script sig of a sh-wrapped segwit descriptor, which pushes the `redeem_script`
+fn redeem_script_sig(redeem_script: &ScriptBuf) -> ScriptBuf {
+    let redeem_script = bitcoin::script::PushBytesBuf::try_from(redeem_script.to_bytes())
+        .expect("a segwit redeem script is small");
+    bitcoin::script::Builder::new()
+        .push_slice(redeem_script)
+        .into_script()
+}
+
 /// The returned value from [`Plan::try_complete`].
 pub enum PlanState<Ak> {
     /// The plan is complete
@@ -433,9 +478,9 @@ where
     match desc {
         Descriptor::Bare(_) => todo!(),
         Descriptor::Pkh(_) => todo!(),
-        Descriptor::Wpkh(_) => todo!(),
-        Descriptor::Sh(_) => todo!(),
-        Descriptor::Wsh(_) => todo!(),
+        Descriptor::Wpkh(wpkh) => crate::plan_impls::plan_satisfaction_wpkh(wpkh, assets),
+        Descriptor::Sh(sh) => crate::plan_impls::plan_satisfaction_sh(sh, assets),
+        Descriptor::Wsh(wsh) => crate::plan_impls::plan_satisfaction_wsh(wsh, assets),
         Descriptor::Tr(tr) => crate::plan_impls::plan_satisfaction_tr(tr, assets),
     }
 }
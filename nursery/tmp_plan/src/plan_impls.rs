@@ -1,6 +1,6 @@
 use bdk_chain::{bitcoin, miniscript};
 use bitcoin::locktime::absolute;
-use miniscript::Terminal;
+use miniscript::{SigType, Terminal};
 
 use super::*;
 
@@ -78,28 +78,82 @@ impl<Ak> TermPlan<Ak> {
 //     }
 // }
 
-// impl crate::descriptor::Wpkh<DefiniteDescriptorKey> {
-//     pub(crate) fn plan_satisfaction<Ak>(&self, assets: &Assets<Ak>) -> Option<Plan<Ak>>
-//     where
-//         Ak: CanDerive + Clone,
-//     {
-//         let (asset_key, derivation_hint) = assets.keys.iter().find_map(|asset_key| {
-//             let derivation_hint = asset_key.can_derive(self.as_inner())?;
-//             Some((asset_key, derivation_hint))
-//         })?;
+pub(crate) fn plan_satisfaction_wpkh<Ak>(
+    wpkh: &miniscript::descriptor::Wpkh<DefiniteDescriptorKey>,
+    assets: &Assets<Ak>,
+) -> Option<Plan<Ak>>
+where
+    Ak: CanDerive + Clone,
+{
+    let key = wpkh.as_inner();
+    let (asset_key, derivation_hint) = assets
+        .keys
+        .iter()
+        .find_map(|asset_key| Some((asset_key, asset_key.can_derive(key)?)))?;
 
-//         Some(Plan {
-//             template: vec![TemplateItem::Sign(PlanKey {
-//                 asset_key: asset_key.clone(),
-//                 descriptor_key: self.as_inner().clone(),
-//                 derivation_hint,
-//             })],
-//             target: Target::Segwitv0,
-//             set_locktime: None,
-//             set_sequence: None,
-//         })
-//     }
-// }
+    Some(Plan {
+        template: vec![
+            TemplateItem::SignEcdsa(PlanKey {
+                asset_key: asset_key.clone(),
+                derivation_hint,
+                descriptor_key: key.clone(),
+            }),
+            TemplateItem::PkEcdsa { key: key.clone() },
+        ],
+        target: Target::Segwitv0 {
+            script_code: wpkh.ecdsa_sighash_script_code(),
+            witness_script: None,
+            redeem_script: None,
+        },
+        set_locktime: None,
+        set_sequence: None,
+    })
+}
+
+pub(crate) fn plan_satisfaction_wsh<Ak>(
+    wsh: &miniscript::descriptor::Wsh<DefiniteDescriptorKey>,
+    assets: &Assets<Ak>,
+) -> Option<Plan<Ak>>
+where
+    Ak: CanDerive + Clone,
+{
+    let plan = match wsh.as_inner() {
+        miniscript::descriptor::WshInner::Ms(ms) => plan_steps(&ms.node, assets)?,
+        miniscript::descriptor::WshInner::SortedMulti(_) => todo!(),
+    };
+
+    Some(Plan {
+        template: plan.template,
+        target: Target::Segwitv0 {
+            script_code: wsh.ecdsa_sighash_script_code(),
+            witness_script: Some(wsh.inner_script()),
+            redeem_script: None,
+        },
+        set_locktime: plan.min_locktime,
+        set_sequence: plan.min_sequence,
+    })
+}
+
+/// Only the sh-wrapped segwit descriptors are supported
+pub(crate) fn plan_satisfaction_sh<Ak>(
+    sh: &miniscript::descriptor::Sh<DefiniteDescriptorKey>,
+    assets: &Assets<Ak>,
+) -> Option<Plan<Ak>>
+where
+    Ak: CanDerive + Clone,
+{
+    use miniscript::descriptor::ShInner;
+
+    let (mut plan, inner_script_pubkey) = match sh.as_inner() {
+        ShInner::Wpkh(wpkh) => (plan_satisfaction_wpkh(wpkh, assets)?, wpkh.script_pubkey()),
+        ShInner::Wsh(wsh) => (plan_satisfaction_wsh(wsh, assets)?, wsh.script_pubkey()),
+        ShInner::SortedMulti(_) | ShInner::Ms(_) => todo!(),
+    };
+    if let Target::Segwitv0 { redeem_script, .. } = &mut plan.target {
+        *redeem_script = Some(inner_script_pubkey);
+    }
+    Some(plan)
+}
 
 pub(crate) fn plan_satisfaction_tr<Ak>(
     tr: &miniscript::descriptor::Tr<DefiniteDescriptorKey>,
@@ -152,6 +206,20 @@ where
     })
 }
 
+/// The plan key of the first asset key which can derive `key`
+fn plan_key<Ak: Clone + CanDerive>(
+    key: &DefiniteDescriptorKey,
+    assets: &Assets<Ak>,
+) -> Option<PlanKey<Ak>> {
+    assets.keys.iter().find_map(|asset_key| {
+        Some(PlanKey {
+            asset_key: asset_key.clone(),
+            derivation_hint: asset_key.can_derive(key)?,
+            descriptor_key: key.clone(),
+        })
+    })
+}
+
 #[derive(Debug)]
 struct TermPlan<Ak> {
     pub min_locktime: Option<absolute::LockTime>,
@@ -186,29 +254,24 @@ fn plan_steps<Ak: Clone + CanDerive, Ctx: ScriptContext>(
         Terminal::True => Some(TermPlan::new(vec![])),
         Terminal::False => return None,
         Terminal::PkH(key) => {
-            let (asset_key, derivation_hint) = assets
-                .keys
-                .iter()
-                .find_map(|asset_key| Some((asset_key, asset_key.can_derive(key)?)))?;
-            Some(TermPlan::new(vec![
-                TemplateItem::Sign(PlanKey {
-                    asset_key: asset_key.clone(),
-                    derivation_hint,
-                    descriptor_key: key.clone(),
-                }),
-                TemplateItem::Pk { key: key.clone() },
-            ]))
+            let plan_key = plan_key(key, assets)?;
+            Some(TermPlan::new(match Ctx::sig_type() {
+                SigType::Ecdsa => vec![
+                    TemplateItem::SignEcdsa(plan_key),
+                    TemplateItem::PkEcdsa { key: key.clone() },
+                ],
+                SigType::Schnorr => vec![
+                    TemplateItem::Sign(plan_key),
+                    TemplateItem::Pk { key: key.clone() },
+                ],
+            }))
         }
         Terminal::PkK(key) => {
-            let (asset_key, derivation_hint) = assets
-                .keys
-                .iter()
-                .find_map(|asset_key| Some((asset_key, asset_key.can_derive(key)?)))?;
-            Some(TermPlan::new(vec![TemplateItem::Sign(PlanKey {
-                asset_key: asset_key.clone(),
-                derivation_hint,
-                descriptor_key: key.clone(),
-            })]))
+            let plan_key = plan_key(key, assets)?;
+            Some(TermPlan::new(vec![match Ctx::sig_type() {
+                SigType::Ecdsa => TemplateItem::SignEcdsa(plan_key),
+                SigType::Schnorr => TemplateItem::Sign(plan_key),
+            }]))
         }
         Terminal::RawPkH(_pk_hash) => {
             /* TODO */
@@ -217,13 +280,8 @@ fn plan_steps<Ak: Clone + CanDerive, Ctx: ScriptContext>(
         Terminal::After(locktime) => {
             let max_locktime = assets.max_locktime?;
             let locktime = absolute::LockTime::from(*locktime);
-            let (height, time) = match max_locktime {
-                absolute::LockTime::Blocks(height) => {
-                    (height, absolute::Time::from_consensus(0).unwrap())
-                }
-                absolute::LockTime::Seconds(seconds) => (absolute::Height::ZERO, seconds),
-            };
-            if max_locktime.is_satisfied_by(height, time) {
+            // a transaction with the max locktime must satisfy the locktime of the descriptor
+            if locktime.is_implied_by(max_locktime) {
                 Some(TermPlan {
                     min_locktime: Some(locktime),
                     ..Default::default()
@@ -292,7 +350,8 @@ fn plan_steps<Ak: Clone + CanDerive, Ctx: ScriptContext>(
         Terminal::AndV(l, r) | Terminal::AndB(l, r) => {
             let lhs = plan_steps(&l.node, assets)?;
             let rhs = plan_steps(&r.node, assets)?;
-            lhs.combine(rhs)
+            // the left script runs first, its satisfaction goes on top of the stack
+            rhs.combine(lhs)
         }
         Terminal::AndOr(_, _, _) => todo!(),
         Terminal::OrB(_, _) => todo!(),
@@ -319,7 +378,21 @@ fn plan_steps<Ak: Clone + CanDerive, Ctx: ScriptContext>(
             }
         }
         Terminal::Thresh(_) => todo!(),
-        Terminal::Multi(_) => todo!(),
+        Terminal::Multi(thresh) => {
+            // the signatures of the first keys we can sign for, in the order of the keys, after
+            // the dummy element consumed by OP_CHECKMULTISIG
+            let plan_keys = thresh
+                .iter()
+                .filter_map(|key| plan_key(key, assets))
+                .take(thresh.k())
+                .collect::<Vec<_>>();
+            if plan_keys.len() < thresh.k() {
+                return None;
+            }
+            let mut template = vec![TemplateItem::Zero];
+            template.extend(plan_keys.into_iter().map(TemplateItem::SignEcdsa));
+            Some(TermPlan::new(template))
+        }
         Terminal::MultiA(_) => todo!(),
     }
 }
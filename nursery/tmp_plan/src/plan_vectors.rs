@@ -0,0 +1,513 @@
+//! Conformance vectors of the planning module.
+//!
+//! Each [`PlanVector`] is a descriptor with the assets of the planner and the outcome expected
+//! from planning, signing and finalizing a spend of one of its outputs. Other planners can check
+//! their behavior against the same vectors.
+//!
+//! The secret keys of the descriptors are the test keys of [`TEST_KEYS`]: the planner has the
+//! keys written as WIF or `tprv` in the descriptor, the ones written as public keys belong to
+//! someone else. The spent output and the spending transaction are built with
+//! [`PlanVector::prevout`] and [`PlanVector::spending_tx`]. Signatures are deterministic: ECDSA
+//! signatures use RFC6979 nonces (without grinding for a low R) with `SIGHASH_ALL`, Schnorr
+//! signatures use no auxiliary randomness with `SIGHASH_DEFAULT`.
+//!
+//! All hex strings are lowercase. The public keys of Taproot descriptors are x-only.
+
+use bdk_chain::{bitcoin, miniscript};
+use bitcoin::{
+    absolute, hashes::Hash, transaction, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Txid, Witness,
+};
+use miniscript::{Descriptor, DescriptorPublicKey};
+
+/// The test keys of the vectors: the WIF of the secret key `[i; 32]` and its public key, for `i`
+/// from 1 to 4
+pub const TEST_KEYS: [(&str, &str); 4] = [
+    (
+        "cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9",
+        "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+    ),
+    (
+        "cMec2DGaTXkYJYfi7x3ZGjRXkeqmAvYAoWzMAcWj5fdLaqudWsNi",
+        "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766",
+    ),
+    (
+        "cMgZD2qsGReP1UvGbNQ7moL6PZFgzsuPFV3St8sGwpNxED4hqkEM",
+        "02531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337",
+    ),
+    (
+        "cMiWPrRA5KYDiRAq4nkgGsEf2TfcpqGbhT6YbfDpoy8ZsaAHiDeo",
+        "03462779ad4aad39514614751a71085f2f10e1c7a593e4e030efb5b8721ce55b0b",
+    ),
+];
+
+/// The sha256 pre-image of the hash locks of the vectors, 32 bytes of `0x33`
+pub const PREIMAGE: &str = "3333333333333333333333333333333333333333333333333333333333333333";
+
+/// The value of the output spent by the vectors, in satoshis
+pub const PREVOUT_VALUE: u64 = 100_000;
+
+/// The fee of the spending transaction, in satoshis
+pub const FEE: u64 = 1_000;
+
+/// A descriptor, the assets of the planner and the expected outcome
+#[derive(Debug, Clone, Copy)]
+pub struct PlanVector {
+    /// The name of the vector
+    pub name: &'static str,
+    /// The descriptor, with the secret keys of the planner
+    pub descriptor: &'static str,
+    /// The derivation index of the spent output, for descriptors with wildcards
+    pub derivation_index: u32,
+    /// Whether the planner has [`PREIMAGE`]
+    pub has_preimage: bool,
+    /// The age of the spent output, in blocks, if the planner may use relative timelocks
+    pub txo_age: Option<u16>,
+    /// The max block height of the spending transaction's locktime, if the planner may use
+    /// absolute timelocks
+    pub max_locktime: Option<u32>,
+    /// The expected plan, `None` if the descriptor can't be satisfied with the assets
+    pub expected: Option<ExpectedPlan>,
+}
+
+/// The expected outcome of a [`PlanVector`]
+#[derive(Debug, Clone, Copy)]
+pub struct ExpectedPlan {
+    /// The expected satisfaction weight of the plan, in witness units
+    pub satisfaction_weight: usize,
+    /// The witness version of the descriptor
+    pub witness_version: u8,
+    /// The hex script of the leaf spent with a Taproot script path, `None` otherwise
+    pub tap_leaf: Option<&'static str>,
+    /// The locktime height required by the plan
+    pub required_locktime: Option<u32>,
+    /// The input sequence required by the plan
+    pub required_sequence: Option<u32>,
+    /// The hex public keys which must sign, in the order of the plan
+    pub signing_keys: &'static [&'static str],
+    /// The hex sha256 images whose pre-images are required
+    pub sha256_images: &'static [&'static str],
+    /// The hex final script sig of the input
+    pub script_sig: &'static str,
+    /// The hex elements of the final witness of the input
+    pub witness: &'static [&'static str],
+}
+
+impl PlanVector {
+    /// The descriptor at the derivation index of the vector, with its key map
+    pub fn descriptor(
+        &self,
+    ) -> (
+        Descriptor<miniscript::DefiniteDescriptorKey>,
+        miniscript::descriptor::KeyMap,
+    ) {
+        let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+        let (descriptor, keymap) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, self.descriptor)
+                .expect("the descriptors of the vectors are valid");
+        let descriptor = descriptor
+            .at_derivation_index(self.derivation_index)
+            .expect("the derivation indices of the vectors are valid");
+        (descriptor, keymap)
+    }
+
+    /// The spent output: [`PREVOUT_VALUE`] to the script pubkey of the descriptor
+    pub fn prevout(&self) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(PREVOUT_VALUE),
+            script_pubkey: self.descriptor().0.script_pubkey(),
+        }
+    }
+
+    /// The version 2 transaction spending the output `[0x42; 32]:0` of [`prevout`] back to the
+    /// same script pubkey, less [`FEE`]
+    ///
+    /// The input has the `sequence`, or 0xfffffffd, and the transaction has the `locktime`, or
+    /// zero.
+    ///
+    /// [`prevout`]: Self::prevout
+    pub fn spending_tx(
+        &self,
+        sequence: Option<Sequence>,
+        locktime: Option<absolute::LockTime>,
+    ) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: locktime.unwrap_or(absolute::LockTime::ZERO),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([0x42; 32]), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: sequence.unwrap_or(Sequence::ENABLE_RBF_NO_LOCKTIME),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(PREVOUT_VALUE - FEE),
+                script_pubkey: self.prevout().script_pubkey,
+            }],
+        }
+    }
+}
+
+/// The vectors
+pub const VECTORS: &[PlanVector] = &[
+    // a single key
+    PlanVector {
+        name: "wpkh",
+        descriptor: "wpkh(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9)",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 113,
+            witness_version: 0,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "3044022028fa4af7d10c86302c965553321b7800f8f6669aade2998ad195e6dec78e0e31022070e77c20d1776a77a725da0e1dcda36206e701e4a77a4cbaa1aa5df3c80a93e501",
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+            ],
+        }),
+    },
+    // a key derived from an xprv
+    PlanVector {
+        name: "wpkh_xprv",
+        descriptor: "wpkh(tprv8ZgxMBicQKsPdDArR4xSAECuVxeX1jwwSXR4ApKbkYgZiziDc4LdBy2WvJeGDfUSE4UT4hHhbgEwbdq8ajjUHiKDegkwrNU6V55CxcxonVN/0/*)",
+        derivation_index: 7,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 113,
+            witness_version: 0,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "03ce6cb00d4026823036fb098f7186827e36fb43ad28d01c42c05cc0037c9d3f61",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "30450221008d564298376b97831b65f817ae87dfe76bb23add291a4df18073afc44b470ee9022028e14625f133b067994d4a1a70c37a456f67a20965545695de4d9f43b079010401",
+                "03ce6cb00d4026823036fb098f7186827e36fb43ad28d01c42c05cc0037c9d3f61",
+            ],
+        }),
+    },
+    // nested in P2SH
+    PlanVector {
+        name: "sh_wpkh",
+        descriptor: "sh(wpkh(cMec2DGaTXkYJYfi7x3ZGjRXkeqmAvYAoWzMAcWj5fdLaqudWsNi))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 205,
+            witness_version: 0,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766",
+            ],
+            sha256_images: &[],
+            script_sig: "160014ebc0ee0b2ab9e8277a600c251475e22a3241a1c1",
+            witness: &[
+                "304402202fa406d56afb2afd0ef521eca68cfc436f07cec69925fe6adc12332a0d28d556022065ea6d7ae155b7fd0eb2a1b7955f66eb623878a72e7d5eb01970bb3a8d55462e01",
+                "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766",
+            ],
+        }),
+    },
+    // signed by the two keys of the planner, in the order of the keys
+    PlanVector {
+        name: "wsh_multi_2_of_3",
+        descriptor: "wsh(multi(2,cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9,024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766,cMgZD2qsGReP1UvGbNQ7moL6PZFgzsuPFV3St8sGwpNxED4hqkEM))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 260,
+            witness_version: 0,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+                "02531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "",
+                "30450221008fb123040f681ddd0e6dd0b8806ae904faf2708d15f62cd35cacbaaf3da0e1ca022007d00980e88afa255f4930072694b2db5b2f40f1f622ff24ef08246cb7c78d6901",
+                "3045022100d88b0d4dc78e131693c228fe5237c187f7c1631c132f0cda5492a3a3bd19862602205c827fc9aa7ad35771f9e96bc5210ad80e8964c053d61a08c1b9af7c339544ed01",
+                "5221031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f21024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d07662102531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe33753ae",
+            ],
+        }),
+    },
+    // the planner has a single key of the two required
+    PlanVector {
+        name: "wsh_multi_missing_key",
+        descriptor: "wsh(multi(2,cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9,024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766,02531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: None,
+    },
+    // the pre-image is on top of the signature
+    PlanVector {
+        name: "wsh_hash_lock",
+        descriptor: "wsh(and_v(v:sha256(deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a),pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9)))",
+        derivation_index: 0,
+        has_preimage: true,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 187,
+            witness_version: 0,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+            ],
+            sha256_images: &[
+                "deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a",
+            ],
+            script_sig: "",
+            witness: &[
+                "304402200ef228badbe552cd9d7a19188b6fb232fe81f92bf6400629c0b418e5dd2b9f69022063069d19cf2258d308a32edfa7f0a2faaf5680adfee1a690b55b1394c698e43101",
+                "3333333333333333333333333333333333333333333333333333333333333333",
+                "82012088a820deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a8821031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078fac",
+            ],
+        }),
+    },
+    // the planner doesn't have the pre-image
+    PlanVector {
+        name: "wsh_hash_lock_missing_preimage",
+        descriptor: "wsh(and_v(v:sha256(deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a),pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9)))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: None,
+    },
+    // the input sequence is the relative timelock
+    PlanVector {
+        name: "wsh_relative_timelock",
+        descriptor: "wsh(and_v(v:pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9),older(144)))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: Some(144),
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 119,
+            witness_version: 0,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: Some(144),
+            signing_keys: &[
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "3044022008311e99160a4aab59a2f80794a0a2ed853099b08293bafd15deea3f5500cfd202201c554eb798c2728e054b76d8bb3996fde27a07d136bdb6fe69e6738830859de501",
+                "21031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078fad029000b2",
+            ],
+        }),
+    },
+    // the output is too young
+    PlanVector {
+        name: "wsh_relative_timelock_not_mature",
+        descriptor: "wsh(and_v(v:pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9),older(144)))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: Some(143),
+        max_locktime: None,
+        expected: None,
+    },
+    // the locktime is the absolute timelock
+    PlanVector {
+        name: "wsh_absolute_timelock",
+        descriptor: "wsh(and_v(v:pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9),after(500000)))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: Some(500000),
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 120,
+            witness_version: 0,
+            tap_leaf: None,
+            required_locktime: Some(500000),
+            required_sequence: None,
+            signing_keys: &[
+                "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "30440220332242a00617e3e9283f5cab06778239b9146958b4c4b369dd50f524a1b25d6c02207e5bf4286eeae72a887784703df2db05ebd53a2170c2fb1c2e1a514171e4f1b801",
+                "21031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078fad0320a107b1",
+            ],
+        }),
+    },
+    // the locktime can't be reached
+    PlanVector {
+        name: "wsh_absolute_timelock_not_reached",
+        descriptor: "wsh(and_v(v:pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9),after(500000)))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: Some(499999),
+        expected: None,
+    },
+    // the only satisfiable branch is chosen
+    PlanVector {
+        name: "wsh_or_i",
+        descriptor: "wsh(or_i(and_v(v:pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9),older(144)),pk(cMgZD2qsGReP1UvGbNQ7moL6PZFgzsuPFV3St8sGwpNxED4hqkEM)))",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 158,
+            witness_version: 0,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "02531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "30450221009b9c340ebfa954ffc24eef59df2bd3f8e6cfed6bef7249771690307c2cf0ad6a022027fb7580aa247652e602694f6ac3085a673a80909f4ba11b7f9c4ede10cd891e01",
+                "",
+                "6321031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078fad029000b2672102531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337ac68",
+            ],
+        }),
+    },
+    // a key spend without script tree
+    PlanVector {
+        name: "tr_key_spend",
+        descriptor: "tr(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9)",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 70,
+            witness_version: 1,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "1b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "5af4fe94bd8f4aa5082e277381a616750561a725a459455ff088ab935b86e5e8ca45d4cff4191ba4c6a99d4053ec5f8eb694046c2c817393437f93bd0afb4522",
+            ],
+        }),
+    },
+    // the key spend is preferred over the leaves
+    PlanVector {
+        name: "tr_key_spend_with_tree",
+        descriptor: "tr(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9,{pk(4d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766),pk(cMgZD2qsGReP1UvGbNQ7moL6PZFgzsuPFV3St8sGwpNxED4hqkEM)})",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 70,
+            witness_version: 1,
+            tap_leaf: None,
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "1b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "f6eb3d37c22981bb5c8b706f4b887a81489b8a8782ba4c1b176cf8d760bdd7f585e473b2a1de9dc0d0fb9283d04714fe69c58f55978a401edba2be216834e4b0",
+            ],
+        }),
+    },
+    // the smallest satisfiable leaf is chosen
+    PlanVector {
+        name: "tr_leaf_smallest",
+        descriptor: "tr(462779ad4aad39514614751a71085f2f10e1c7a593e4e030efb5b8721ce55b0b,{pk(4d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766),{and_v(v:pk(cMgZD2qsGReP1UvGbNQ7moL6PZFgzsuPFV3St8sGwpNxED4hqkEM),older(144)),and_v(v:sha256(deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a),pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9))}})",
+        derivation_index: 0,
+        has_preimage: true,
+        txo_age: Some(144),
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 207,
+            witness_version: 1,
+            tap_leaf: Some("20531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337ad029000b2"),
+            required_locktime: None,
+            required_sequence: Some(144),
+            signing_keys: &[
+                "531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337",
+            ],
+            sha256_images: &[],
+            script_sig: "",
+            witness: &[
+                "4eeb26df5caf8d0c592f32cf44f7ddee05046b96db30ae0b2133d30f05a6721eba19d41bc2e9f24b64ec02ee6ded6757a90dbc938020d925952bc505ba852798",
+                "20531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe337ad029000b2",
+                "c0462779ad4aad39514614751a71085f2f10e1c7a593e4e030efb5b8721ce55b0b48a58877fdbcb8183f7cec654f75c565aefd6a1ac5b10f812fd61fa9ab2f08b9f187614054c95f166583937b7f5fd07edfdc4b304234612600954adb5f2de5b5",
+            ],
+        }),
+    },
+    // the relative timelock can't be used, the hash lock leaf is the only choice
+    PlanVector {
+        name: "tr_leaf_hash_lock",
+        descriptor: "tr(462779ad4aad39514614751a71085f2f10e1c7a593e4e030efb5b8721ce55b0b,{pk(4d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766),{and_v(v:pk(cMgZD2qsGReP1UvGbNQ7moL6PZFgzsuPFV3St8sGwpNxED4hqkEM),older(144)),and_v(v:sha256(deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a),pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9))}})",
+        derivation_index: 0,
+        has_preimage: true,
+        txo_age: None,
+        max_locktime: None,
+        expected: Some(ExpectedPlan {
+            satisfaction_weight: 275,
+            witness_version: 1,
+            tap_leaf: Some("82012088a820deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a88201b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078fac"),
+            required_locktime: None,
+            required_sequence: None,
+            signing_keys: &[
+                "1b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+            ],
+            sha256_images: &[
+                "deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a",
+            ],
+            script_sig: "",
+            witness: &[
+                "0f8bb2d8e6ebd222156a7827bdc8c9c002dd880d630a8db965b99eaa2a9165c6a1fb18b1f303d75cd2394a17ede3eabc5df58cc031dc42abe8c8293b42116ca2",
+                "3333333333333333333333333333333333333333333333333333333333333333",
+                "82012088a820deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a88201b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078fac",
+                "c0462779ad4aad39514614751a71085f2f10e1c7a593e4e030efb5b8721ce55b0bb8081a66489f68e9e4b6cb558fb71dcdab159734014218a355a3a95aafab54d4f187614054c95f166583937b7f5fd07edfdc4b304234612600954adb5f2de5b5",
+            ],
+        }),
+    },
+    // no leaf can be satisfied
+    PlanVector {
+        name: "tr_no_leaf",
+        descriptor: "tr(462779ad4aad39514614751a71085f2f10e1c7a593e4e030efb5b8721ce55b0b,{pk(4d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766),{and_v(v:pk(cMgZD2qsGReP1UvGbNQ7moL6PZFgzsuPFV3St8sGwpNxED4hqkEM),older(144)),and_v(v:sha256(deb0e38ced1e41de6f92e70e80c418d2d356afaaa99e26f5939dbc7d3ef4772a),pk(cMceqPhHedrhbcR9eXgzmfWy7kRqLyAxMYwFT6ABDWsiwUp9Nsq9))}})",
+        derivation_index: 0,
+        has_preimage: false,
+        txo_age: None,
+        max_locktime: None,
+        expected: None,
+    },
+];
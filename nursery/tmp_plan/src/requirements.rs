@@ -68,7 +68,12 @@ pub enum RequiredSignatures<Ak> {
     /// Legacy ECDSA signatures are required
     Legacy { keys: Vec<PlanKey<Ak>> },
     /// Segwitv0 ECDSA signatures are required
-    Segwitv0 { keys: Vec<PlanKey<Ak>> },
+    Segwitv0 {
+        /// The script code of the signature hash
+        script_code: ScriptBuf,
+        /// The keys that require signatures
+        keys: Vec<PlanKey<Ak>>,
+    },
     /// A Taproot key spend signature is required
     TapKey {
         /// the internal key
@@ -88,8 +93,17 @@ pub enum RequiredSignatures<Ak> {
 #[derive(Clone, Debug)]
 pub enum SigningError {
     SigHashP2wpkh(sighash::P2wpkhError),
+    SigHashSegwitv0(bitcoin::transaction::InputsIndexError),
     SigHashTaproot(sighash::TaprootError),
     DerivationError(bip32::Error),
+    /// The previous output of the input being signed wasn't provided
+    MissingPrevout(usize),
+}
+
+impl From<bitcoin::transaction::InputsIndexError> for SigningError {
+    fn from(v: bitcoin::transaction::InputsIndexError) -> Self {
+        Self::SigHashSegwitv0(v)
+    }
 }
 
 impl From<sighash::TaprootError> for SigningError {
@@ -108,8 +122,12 @@ impl core::fmt::Display for SigningError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             SigningError::SigHashP2wpkh(e) => e.fmt(f),
+            SigningError::SigHashSegwitv0(e) => e.fmt(f),
             SigningError::SigHashTaproot(e) => e.fmt(f),
             SigningError::DerivationError(e) => e.fmt(f),
+            SigningError::MissingPrevout(input_index) => {
+                write!(f, "missing the previous output of input {}", input_index)
+            }
         }
     }
 }
@@ -136,7 +154,57 @@ impl RequiredSignatures<DescriptorPublicKey> {
         secp: &Secp256k1<impl Signing + Verification>,
     ) -> Result<bool, SigningError> {
         match self {
-            RequiredSignatures::Legacy { .. } | RequiredSignatures::Segwitv0 { .. } => todo!(),
+            RequiredSignatures::Legacy { .. } => todo!(),
+            RequiredSignatures::Segwitv0 { script_code, keys } => {
+                let sighash_type = _ecdsa_sighashty.unwrap_or(EcdsaSighashType::All);
+                let value = match prevouts {
+                    Prevouts::All(prevouts) => {
+                        prevouts.get(input_index).map(|txout| txout.borrow().value)
+                    }
+                    Prevouts::One(index, txout) if *index == input_index => {
+                        Some(txout.borrow().value)
+                    }
+                    Prevouts::One(..) => None,
+                }
+                .ok_or(SigningError::MissingPrevout(input_index))?;
+                let sighash = sighash_cache.p2wsh_signature_hash(
+                    input_index,
+                    script_code,
+                    value,
+                    sighash_type,
+                )?;
+
+                let mut modified = false;
+                for plan_key in keys {
+                    if let Some(secret_key) = keymap.get(&plan_key.asset_key) {
+                        let secret_key = match secret_key {
+                            DescriptorSecretKey::Single(single) => single.key.inner,
+                            DescriptorSecretKey::XPrv(xprv) => {
+                                xprv.xkey
+                                    .derive_priv(&secp, &plan_key.derivation_hint)?
+                                    .private_key
+                            }
+                            DescriptorSecretKey::MultiXPrv(_) => {
+                                // This crate will be replaced by
+                                // https://github.com/rust-bitcoin/rust-miniscript/pull/481 anyways
+                                todo!();
+                            }
+                        };
+                        let msg = Message::from_digest(sighash.to_byte_array());
+                        let signature = secp.sign_ecdsa(&msg, &secret_key);
+                        let bitcoin_sig = ecdsa::Signature {
+                            signature,
+                            sighash_type,
+                        };
+
+                        auth_data
+                            .ecdsa_sigs
+                            .insert(plan_key.descriptor_key.clone(), bitcoin_sig);
+                        modified = true;
+                    }
+                }
+                Ok(modified)
+            }
             RequiredSignatures::TapKey {
                 plan_key,
                 merkle_root,
@@ -10,7 +10,9 @@ use crate::{hash256, varint_len, DefiniteDescriptorKey};
 #[derive(Clone, Debug)]
 pub(crate) enum TemplateItem<Ak> {
     Sign(PlanKey<Ak>),
+    SignEcdsa(PlanKey<Ak>),
     Pk { key: DefiniteDescriptorKey },
+    PkEcdsa { key: DefiniteDescriptorKey },
     One,
     Zero,
     Sha256(sha256::Hash),
@@ -36,7 +38,10 @@ impl<Ak> TemplateItem<Ak> {
     pub fn expected_size(&self) -> usize {
         match self {
             TemplateItem::Sign { .. } => 64, /* size of sig TODO: take into consideration sighash flag */
+            // DER encoded with the sighash flag, at most
+            TemplateItem::SignEcdsa { .. } => 73,
             TemplateItem::Pk { .. } => 32,
+            TemplateItem::PkEcdsa { .. } => 33,
             TemplateItem::One => varint_len(1),
             TemplateItem::Zero => 0, /* zero means an empty witness element */
             // I'm not sure if it should be 32 here (it's a 20 byte hash) but that's what other
@@ -56,6 +61,13 @@ impl<Ak> TemplateItem<Ak> {
                     .unwrap()
                     .to_vec()]
             }
+            TemplateItem::SignEcdsa(plan_key) => {
+                vec![auth_data
+                    .ecdsa_sigs
+                    .get(&plan_key.descriptor_key)
+                    .unwrap()
+                    .to_vec()]
+            }
             TemplateItem::One => vec![vec![1]],
             TemplateItem::Zero => vec![vec![]],
             TemplateItem::Sha256(image) => {
@@ -71,6 +83,7 @@ impl<Ak> TemplateItem<Ak> {
                 vec![auth_data.hash256_preimages.get(image).unwrap().to_vec()]
             }
             TemplateItem::Pk { key } => vec![key.to_public_key().to_bytes()],
+            TemplateItem::PkEcdsa { key } => vec![key.to_public_key().to_bytes()],
         }
     }
 }
@@ -0,0 +1,187 @@
+use bdk_chain::bitcoin::{
+    self, absolute,
+    consensus::encode::serialize,
+    hashes::{hex::FromHex, sha256, Hash},
+    secp256k1::Secp256k1,
+    sighash::{Prevouts, SighashCache},
+    Sequence, WitnessVersion,
+};
+use bdk_chain::miniscript::{interpreter::Interpreter, ToPublicKey};
+use bdk_tmp_plan::plan_vectors::{PlanVector, PREIMAGE, VECTORS};
+use bdk_tmp_plan::{
+    plan_satisfaction, Assets, PlanState, RequiredSignatures, SatisfactionMaterial,
+};
+
+/// The outcome of planning, signing and finalizing the spend of a vector
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    satisfaction_weight: usize,
+    witness_version: u8,
+    tap_leaf: Option<String>,
+    required_locktime: Option<u32>,
+    required_sequence: Option<u32>,
+    signing_keys: Vec<String>,
+    sha256_images: Vec<String>,
+    script_sig: String,
+    witness: Vec<String>,
+}
+
+fn run(vector: &PlanVector) -> Option<Outcome> {
+    let secp = Secp256k1::new();
+    let (descriptor, keymap) = vector.descriptor();
+    let preimage = Vec::<u8>::from_hex(PREIMAGE).unwrap();
+    let image = sha256::Hash::hash(&preimage);
+    let assets = Assets {
+        keys: keymap.keys().cloned().collect(),
+        txo_age: vector.txo_age.map(Sequence::from_height),
+        max_locktime: vector
+            .max_locktime
+            .map(|height| absolute::LockTime::from_height(height).unwrap()),
+        sha256: if vector.has_preimage {
+            vec![image]
+        } else {
+            vec![]
+        },
+        ..Default::default()
+    };
+
+    let plan = plan_satisfaction(&descriptor, &assets)?;
+    let requirements = plan.requirements();
+    let signing_keys = match &requirements.signatures {
+        RequiredSignatures::Legacy { keys } | RequiredSignatures::Segwitv0 { keys, .. } => keys
+            .iter()
+            .map(|key| key.descriptor_key.to_public_key().to_string())
+            .collect(),
+        RequiredSignatures::TapKey { plan_key, .. } => {
+            vec![plan_key.descriptor_key.to_x_only_pubkey().to_string()]
+        }
+        RequiredSignatures::TapScript { plan_keys, .. } => plan_keys
+            .iter()
+            .map(|key| key.descriptor_key.to_x_only_pubkey().to_string())
+            .collect(),
+    };
+    let mut sha256_images = requirements
+        .sha256_images
+        .iter()
+        .map(|image| image.to_string())
+        .collect::<Vec<_>>();
+    sha256_images.sort();
+
+    let prevout = vector.prevout();
+    let mut tx = vector.spending_tx(plan.required_sequence(), plan.required_locktime());
+    let mut auth_data = SatisfactionMaterial::default();
+    if requirements.sha256_images.contains(&image) {
+        auth_data.sha256_preimages.insert(image, preimage);
+    }
+    let signed = requirements
+        .signatures
+        .sign_with_keymap(
+            0,
+            &keymap,
+            &Prevouts::All(core::slice::from_ref(&prevout)),
+            None,
+            None,
+            &mut SighashCache::new(&tx),
+            &mut auth_data,
+            &secp,
+        )
+        .expect("must sign");
+    assert!(signed, "{}: nothing signed", vector.name);
+    let (script_sig, witness) = match plan.try_complete(&auth_data) {
+        PlanState::Complete {
+            final_script_sig,
+            final_script_witness,
+        } => (
+            final_script_sig.unwrap_or_default(),
+            final_script_witness.unwrap_or_default(),
+        ),
+        PlanState::Incomplete(_) => panic!("{}: the plan must be complete", vector.name),
+    };
+    tx.input[0].script_sig = script_sig.clone();
+    tx.input[0].witness = witness.clone();
+
+    // the spend must be valid, signatures included
+    let txin = &tx.input[0];
+    let interpreter = Interpreter::from_txdata(
+        &prevout.script_pubkey,
+        &txin.script_sig,
+        &txin.witness,
+        txin.sequence,
+        tx.lock_time,
+    )
+    .expect("must interpret the spend");
+    let prevouts = Prevouts::All(&[prevout]);
+    for constraint in interpreter.iter(&secp, &tx, 0, &prevouts) {
+        if let Err(err) = constraint {
+            panic!("{}: invalid spend: {}", vector.name, err);
+        }
+    }
+
+    // the plan doesn't underestimate the weight of the satisfaction
+    let satisfaction_weight = plan.expected_weight();
+    let actual_weight = serialize(&script_sig).len() * 4 + witness.size();
+    assert!(
+        actual_weight <= satisfaction_weight,
+        "{}: the satisfaction weighs {} > {}",
+        vector.name,
+        actual_weight,
+        satisfaction_weight
+    );
+
+    let witness_version = plan.witness_version().map(WitnessVersion::to_num);
+    let tap_leaf = match witness_version {
+        Some(1) if witness.len() > 1 => Some(bitcoin::hex::DisplayHex::to_lower_hex_string(
+            &witness[witness.len() - 2],
+        )),
+        _ => None,
+    };
+    Some(Outcome {
+        satisfaction_weight,
+        witness_version: witness_version.expect("only segwit descriptors are planned"),
+        tap_leaf,
+        required_locktime: plan
+            .required_locktime()
+            .map(|locktime| locktime.to_consensus_u32()),
+        required_sequence: plan
+            .required_sequence()
+            .map(|sequence| sequence.to_consensus_u32()),
+        signing_keys,
+        sha256_images,
+        script_sig: script_sig.to_hex_string(),
+        witness: witness
+            .iter()
+            .map(bitcoin::hex::DisplayHex::to_lower_hex_string)
+            .collect(),
+    })
+}
+
+#[test]
+fn plan_vectors() {
+    assert!(!VECTORS.is_empty());
+    for vector in VECTORS {
+        let expected = vector.expected.map(|expected| Outcome {
+            satisfaction_weight: expected.satisfaction_weight,
+            witness_version: expected.witness_version,
+            tap_leaf: expected.tap_leaf.map(str::to_string),
+            required_locktime: expected.required_locktime,
+            required_sequence: expected.required_sequence,
+            signing_keys: expected
+                .signing_keys
+                .iter()
+                .map(|key| key.to_string())
+                .collect(),
+            sha256_images: expected
+                .sha256_images
+                .iter()
+                .map(|image| image.to_string())
+                .collect(),
+            script_sig: expected.script_sig.to_string(),
+            witness: expected
+                .witness
+                .iter()
+                .map(|elem| elem.to_string())
+                .collect(),
+        });
+        assert_eq!(run(vector), expected, "vector {}", vector.name);
+    }
+}